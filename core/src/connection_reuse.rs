@@ -232,11 +232,6 @@ where
             addr,
         })
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.shared.lock().transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T, D, M> MuxedTransport for ConnectionReuse<T, D, M>
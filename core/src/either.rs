@@ -20,7 +20,8 @@
 
 use futures::prelude::*;
 use muxing::StreamMuxer;
-use std::io::{Error as IoError, Read, Write};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::time::Duration;
 use tokio_io::{AsyncRead, AsyncWrite};
 use Multiaddr;
 
@@ -126,7 +127,7 @@ where
             (EitherOutput::Second(ref inner), EitherOutbound::B(ref mut substream)) => {
                 inner.poll_outbound(substream).map(|p| p.map(|o| o.map(EitherOutput::Second)))
             },
-            _ => panic!("Wrong API usage")
+            _ => Err(wrong_muxer_error()),
         }
     }
 
@@ -135,13 +136,13 @@ where
             EitherOutput::First(ref inner) => {
                 match substream {
                     EitherOutbound::A(substream) => inner.destroy_outbound(substream),
-                    _ => panic!("Wrong API usage")
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
                 }
             },
             EitherOutput::Second(ref inner) => {
                 match substream {
                     EitherOutbound::B(substream) => inner.destroy_outbound(substream),
-                    _ => panic!("Wrong API usage")
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
                 }
             },
         }
@@ -155,7 +156,7 @@ where
             (EitherOutput::Second(ref inner), EitherOutput::Second(ref mut substream)) => {
                 inner.read_substream(substream, buf)
             },
-            _ => panic!("Wrong API usage")
+            _ => Err(wrong_muxer_error()),
         }
     }
 
@@ -167,7 +168,7 @@ where
             (EitherOutput::Second(ref inner), EitherOutput::Second(ref mut substream)) => {
                 inner.write_substream(substream, buf)
             },
-            _ => panic!("Wrong API usage")
+            _ => Err(wrong_muxer_error()),
         }
     }
 
@@ -179,7 +180,7 @@ where
             (EitherOutput::Second(ref inner), EitherOutput::Second(ref mut substream)) => {
                 inner.flush_substream(substream)
             },
-            _ => panic!("Wrong API usage")
+            _ => Err(wrong_muxer_error()),
         }
     }
 
@@ -191,7 +192,7 @@ where
             (EitherOutput::Second(ref inner), EitherOutput::Second(ref mut substream)) => {
                 inner.shutdown_substream(substream)
             },
-            _ => panic!("Wrong API usage")
+            _ => Err(wrong_muxer_error()),
         }
     }
 
@@ -200,13 +201,13 @@ where
             EitherOutput::First(ref inner) => {
                 match substream {
                     EitherOutput::First(substream) => inner.destroy_substream(substream),
-                    _ => panic!("Wrong API usage")
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
                 }
             },
             EitherOutput::Second(ref inner) => {
                 match substream {
                     EitherOutput::Second(substream) => inner.destroy_substream(substream),
-                    _ => panic!("Wrong API usage")
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
                 }
             },
         }
@@ -225,6 +226,24 @@ where
             EitherOutput::Second(ref inner) => inner.close_outbound(),
         }
     }
+
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        match *self {
+            EitherOutput::First(ref inner) => inner.take_round_trip_time(),
+            EitherOutput::Second(ref inner) => inner.take_round_trip_time(),
+        }
+    }
+}
+
+/// Error returned when a substream that belongs to one variant of an `EitherOutput` is passed
+/// to the other variant.
+///
+/// This can only happen if the substreams of multiple muxers are mixed up, which is a bug in the
+/// calling code. We return an error instead of panicking so that the connection gets closed
+/// without bringing down the rest of the process.
+pub(crate) fn wrong_muxer_error() -> IoError {
+    error!("Substream used with the wrong muxer");
+    IoError::new(IoErrorKind::Other, "substream used with the wrong muxer")
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -285,3 +304,55 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{EitherOutbound, EitherOutput};
+    use futures::prelude::*;
+    use muxing::StreamMuxer;
+    use std::io::Error as IoError;
+
+    // Muxer whose substreams are all immediately available and empty.
+    struct DummyMuxer;
+    impl StreamMuxer for DummyMuxer {
+        type Substream = ();
+        type OutboundSubstream = ();
+        fn poll_inbound(&self) -> Poll<Option<()>, IoError> { Ok(Async::Ready(Some(()))) }
+        fn open_outbound(&self) -> () { () }
+        fn poll_outbound(&self, _: &mut ()) -> Poll<Option<()>, IoError> {
+            Ok(Async::Ready(Some(())))
+        }
+        fn destroy_outbound(&self, _: ()) {}
+        fn read_substream(&self, _: &mut (), _: &mut [u8]) -> Result<usize, IoError> { Ok(0) }
+        fn write_substream(&self, _: &mut (), buf: &[u8]) -> Result<usize, IoError> {
+            Ok(buf.len())
+        }
+        fn flush_substream(&self, _: &mut ()) -> Result<(), IoError> { Ok(()) }
+        fn shutdown_substream(&self, _: &mut ()) -> Poll<(), IoError> { Ok(Async::Ready(())) }
+        fn destroy_substream(&self, _: ()) {}
+        fn close_inbound(&self) {}
+        fn close_outbound(&self) {}
+    }
+
+    #[test]
+    fn wrong_substream_returns_error() {
+        let first: EitherOutput<DummyMuxer, DummyMuxer> = EitherOutput::First(DummyMuxer);
+        let second: EitherOutput<DummyMuxer, DummyMuxer> = EitherOutput::Second(DummyMuxer);
+
+        let mut substream = match first.poll_inbound() {
+            Ok(Async::Ready(Some(s))) => s,
+            _ => panic!()
+        };
+        assert!(first.read_substream(&mut substream, &mut [0; 8]).is_ok());
+        assert!(second.read_substream(&mut substream, &mut [0; 8]).is_err());
+        assert!(second.write_substream(&mut substream, b"hello").is_err());
+        assert!(second.flush_substream(&mut substream).is_err());
+        assert!(second.shutdown_substream(&mut substream).is_err());
+        second.destroy_substream(substream);
+
+        let mut outbound = first.open_outbound();
+        match outbound { EitherOutbound::A(_) => (), _ => panic!() }
+        assert!(second.poll_outbound(&mut outbound).is_err());
+        second.destroy_outbound(outbound);
+    }
+}
@@ -0,0 +1,597 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! N-ary versions of the types of the `either` module and of the `or` combinators.
+//!
+//! Combining more than two transports with `or_transport`, or more than two upgrades with
+//! `upgrade::or`, nests the `Either` types into each other. The types of this module instead have
+//! one variant per transport or upgrade, for up to 16 of them.
+//!
+//! Use `transport::or_all` and `upgrade::or_all` with a tuple in order to build them.
+//!
+//! ```ignore
+//! let transport = transport::or_all((tcp, websocket, memory));
+//! let upgrade = upgrade::or_all((proto_a, proto_b, proto_c, proto_d));
+//! ```
+//!
+//! The variants of the enums are named after the position of the element in the tuple: `A` for
+//! the first one, `B` for the second one, and so on.
+
+use bytes::Bytes;
+use either::wrong_muxer_error;
+use futures::{future, prelude::*};
+use muxing::StreamMuxer;
+use std::io::{Error as IoError, Read, Write};
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{MuxedTransport, OrTransport, Transport};
+use upgrade::{ConnectionUpgrade, Endpoint, OrUpgrade};
+use Multiaddr;
+
+/// Implemented on tuples of upgrades. See `upgrade::or_all`.
+pub trait IntoOrUpgrade {
+    /// Upgrade that chooses between the elements of the tuple.
+    type Upgrade;
+
+    /// Turns the tuple into an upgrade.
+    fn into_or_upgrade(self) -> Self::Upgrade;
+}
+
+/// Implemented on tuples of transports. See `transport::or_all`.
+pub trait IntoOrTransport {
+    /// Transport that tries the elements of the tuple one by one.
+    type Transport;
+
+    /// Turns the tuple into a transport.
+    fn into_or_transport(self) -> Self::Transport;
+}
+
+impl<A, B> IntoOrUpgrade for (A, B) {
+    type Upgrade = OrUpgrade<A, B>;
+
+    #[inline]
+    fn into_or_upgrade(self) -> Self::Upgrade {
+        ::upgrade::or(self.0, self.1)
+    }
+}
+
+impl<A, B> IntoOrTransport for (A, B) {
+    type Transport = OrTransport<A, B>;
+
+    #[inline]
+    fn into_or_transport(self) -> Self::Transport {
+        OrTransport::new(self.0, self.1)
+    }
+}
+
+macro_rules! either_n {
+    ($output:ident, $outbound:ident, $future:ident, $listen:ident, $id:ident, $names:ident,
+     $upgrade:ident, $upgrade_future:ident, $transport:ident;
+     $(($v:ident, $f:ident, $out:ident)),+) => {
+        /// Implements `AsyncRead`, `AsyncWrite` and `StreamMuxer`, and dispatches all method
+        /// calls to the variant it contains.
+        #[derive(Debug, Copy, Clone)]
+        pub enum $output<$($v),+> {
+            $($v($v),)+
+        }
+
+        impl<$($v),+> AsyncRead for $output<$($v),+>
+        where
+            $($v: AsyncRead),+
+        {
+            #[inline]
+            unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+                match *self {
+                    $($output::$v(ref inner) => inner.prepare_uninitialized_buffer(buf),)+
+                }
+            }
+        }
+
+        impl<$($v),+> Read for $output<$($v),+>
+        where
+            $($v: Read),+
+        {
+            #[inline]
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+                match *self {
+                    $($output::$v(ref mut inner) => inner.read(buf),)+
+                }
+            }
+        }
+
+        impl<$($v),+> AsyncWrite for $output<$($v),+>
+        where
+            $($v: AsyncWrite),+
+        {
+            #[inline]
+            fn shutdown(&mut self) -> Poll<(), IoError> {
+                match *self {
+                    $($output::$v(ref mut inner) => inner.shutdown(),)+
+                }
+            }
+        }
+
+        impl<$($v),+> Write for $output<$($v),+>
+        where
+            $($v: Write),+
+        {
+            #[inline]
+            fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+                match *self {
+                    $($output::$v(ref mut inner) => inner.write(buf),)+
+                }
+            }
+
+            #[inline]
+            fn flush(&mut self) -> Result<(), IoError> {
+                match *self {
+                    $($output::$v(ref mut inner) => inner.flush(),)+
+                }
+            }
+        }
+
+        impl<$($v),+> StreamMuxer for $output<$($v),+>
+        where
+            $($v: StreamMuxer),+
+        {
+            type Substream = $output<$($v::Substream),+>;
+            type OutboundSubstream = $outbound<$($v),+>;
+
+            fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
+                match *self {
+                    $($output::$v(ref inner) => {
+                        inner.poll_inbound().map(|p| p.map(|o| o.map($output::$v)))
+                    },)+
+                }
+            }
+
+            fn open_outbound(&self) -> Self::OutboundSubstream {
+                match *self {
+                    $($output::$v(ref inner) => $outbound::$v(inner.open_outbound()),)+
+                }
+            }
+
+            fn poll_outbound(
+                &self,
+                substream: &mut Self::OutboundSubstream,
+            ) -> Poll<Option<Self::Substream>, IoError> {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), &mut $outbound::$v(ref mut substream)) => {
+                        inner.poll_outbound(substream).map(|p| p.map(|o| o.map($output::$v)))
+                    },)+
+                    _ => Err(wrong_muxer_error()),
+                }
+            }
+
+            fn destroy_outbound(&self, substream: Self::OutboundSubstream) {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), $outbound::$v(substream)) => {
+                        inner.destroy_outbound(substream)
+                    },)+
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
+                }
+            }
+
+            fn read_substream(
+                &self,
+                substream: &mut Self::Substream,
+                buf: &mut [u8],
+            ) -> Result<usize, IoError> {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), &mut $output::$v(ref mut substream)) => {
+                        inner.read_substream(substream, buf)
+                    },)+
+                    _ => Err(wrong_muxer_error()),
+                }
+            }
+
+            fn write_substream(
+                &self,
+                substream: &mut Self::Substream,
+                buf: &[u8],
+            ) -> Result<usize, IoError> {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), &mut $output::$v(ref mut substream)) => {
+                        inner.write_substream(substream, buf)
+                    },)+
+                    _ => Err(wrong_muxer_error()),
+                }
+            }
+
+            fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), &mut $output::$v(ref mut substream)) => {
+                        inner.flush_substream(substream)
+                    },)+
+                    _ => Err(wrong_muxer_error()),
+                }
+            }
+
+            fn shutdown_substream(&self, substream: &mut Self::Substream) -> Poll<(), IoError> {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), &mut $output::$v(ref mut substream)) => {
+                        inner.shutdown_substream(substream)
+                    },)+
+                    _ => Err(wrong_muxer_error()),
+                }
+            }
+
+            fn destroy_substream(&self, substream: Self::Substream) {
+                match (self, substream) {
+                    $((&$output::$v(ref inner), $output::$v(substream)) => {
+                        inner.destroy_substream(substream)
+                    },)+
+                    _ => error!("Tried to destroy a substream with the wrong muxer"),
+                }
+            }
+
+            fn close_inbound(&self) {
+                match *self {
+                    $($output::$v(ref inner) => inner.close_inbound(),)+
+                }
+            }
+
+            fn close_outbound(&self) {
+                match *self {
+                    $($output::$v(ref inner) => inner.close_outbound(),)+
+                }
+            }
+
+            fn take_round_trip_time(&self) -> Option<Duration> {
+                match *self {
+                    $($output::$v(ref inner) => inner.take_round_trip_time(),)+
+                }
+            }
+        }
+
+        /// Outbound substream of the `StreamMuxer` implementation of the output.
+        #[derive(Debug, Copy, Clone)]
+        #[must_use = "futures do nothing unless polled"]
+        pub enum $outbound<$($v: StreamMuxer),+> {
+            $($v($v::OutboundSubstream),)+
+        }
+
+        /// Implements `Future` and dispatches all method calls to the variant it contains.
+        #[derive(Debug, Copy, Clone)]
+        #[must_use = "futures do nothing unless polled"]
+        pub enum $future<$($v),+> {
+            $($v($v),)+
+        }
+
+        impl<$($v),+> Future for $future<$($v),+>
+        where
+            $($v: Future<Error = IoError>),+
+        {
+            type Item = $output<$($v::Item),+>;
+            type Error = IoError;
+
+            #[inline]
+            fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+                match *self {
+                    $($future::$v(ref mut inner) => inner.poll().map(|v| v.map($output::$v)),)+
+                }
+            }
+        }
+
+        /// Implements `Stream` and dispatches all method calls to the variant it contains.
+        #[derive(Debug, Copy, Clone)]
+        #[must_use = "futures do nothing unless polled"]
+        pub enum $listen<$($v),+> {
+            $($v($v),)+
+        }
+
+        impl<$($v, $out),+> Stream for $listen<$($v),+>
+        where
+            $($v: Stream<Item = ($out, Multiaddr), Error = IoError>),+
+        {
+            type Item = ($future<$($out),+>, Multiaddr);
+            type Error = IoError;
+
+            #[inline]
+            fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+                match *self {
+                    $($listen::$v(ref mut inner) => {
+                        inner.poll().map(|i| i.map(|v| v.map(|(o, addr)| ($future::$v(o), addr))))
+                    },)+
+                }
+            }
+        }
+
+        /// Identifier of the protocol negotiated by the upgrade.
+        #[derive(Debug, Copy, Clone)]
+        pub enum $id<$($v),+> {
+            $($v($v),)+
+        }
+
+        /// Iterator over the protocol names of all the upgrades.
+        #[derive(Debug, Copy, Clone)]
+        pub struct $names<$($v),+> {
+            $($f: $v,)+
+        }
+
+        impl<$($v, $out),+> Iterator for $names<$($v),+>
+        where
+            $($v: Iterator<Item = (Bytes, $out)>),+
+        {
+            type Item = (Bytes, $id<$($out),+>);
+
+            #[inline]
+            fn next(&mut self) -> Option<Self::Item> {
+                $(
+                    if let Some((name, id)) = self.$f.next() {
+                        return Some((name, $id::$v(id)));
+                    }
+                )+
+                None
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                let mut min = 0usize;
+                let mut max = Some(0usize);
+                $(
+                    let (elem_min, elem_max) = self.$f.size_hint();
+                    min = min.saturating_add(elem_min);
+                    max = match (max, elem_max) {
+                        (Some(max), Some(elem_max)) => max.checked_add(elem_max),
+                        _ => None,
+                    };
+                )+
+                (min, max)
+            }
+        }
+
+        /// Upgrade that chooses between multiple upgrades. The earlier upgrades have priority.
+        #[derive(Debug, Copy, Clone)]
+        pub struct $upgrade<$($v),+> {
+            $($f: $v,)+
+        }
+
+        impl<TSocket, TOut, $($v),+> ConnectionUpgrade<TSocket> for $upgrade<$($v),+>
+        where
+            TSocket: AsyncRead + AsyncWrite,
+            $($v: ConnectionUpgrade<TSocket, Output = TOut>),+
+        {
+            type NamesIter = $names<$($v::NamesIter),+>;
+            type UpgradeIdentifier = $id<$($v::UpgradeIdentifier),+>;
+
+            #[inline]
+            fn protocol_names(&self) -> Self::NamesIter {
+                $names {
+                    $($f: self.$f.protocol_names(),)+
+                }
+            }
+
+            type Output = TOut;
+            type Future = $upgrade_future<$($v::Future),+>;
+
+            #[inline]
+            fn upgrade(
+                self,
+                socket: TSocket,
+                id: Self::UpgradeIdentifier,
+                ty: Endpoint,
+                remote_addr: &Multiaddr,
+            ) -> Self::Future {
+                match id {
+                    $($id::$v(id) => {
+                        $upgrade_future::$v(self.$f.upgrade(socket, id, ty, remote_addr))
+                    },)+
+                }
+            }
+        }
+
+        impl<$($v),+> IntoOrUpgrade for ($($v,)+) {
+            type Upgrade = $upgrade<$($v),+>;
+
+            #[inline]
+            fn into_or_upgrade(self) -> Self::Upgrade {
+                let ($($f,)+) = self;
+                $upgrade { $($f,)+ }
+            }
+        }
+
+        /// Future of the upgrade. Dispatches to the upgrade that has been negotiated.
+        #[must_use = "futures do nothing unless polled"]
+        pub enum $upgrade_future<$($v),+> {
+            $($v($v),)+
+        }
+
+        impl<TOut, $($v),+> Future for $upgrade_future<$($v),+>
+        where
+            $($v: Future<Item = TOut, Error = IoError>),+
+        {
+            type Item = TOut;
+            type Error = IoError;
+
+            #[inline]
+            fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+                match *self {
+                    $($upgrade_future::$v(ref mut inner) => inner.poll(),)+
+                }
+            }
+        }
+
+        /// Transport that tries multiple transports one by one, and uses the first one that
+        /// accepts the multiaddress.
+        #[derive(Debug, Copy, Clone)]
+        pub struct $transport<$($v),+> {
+            $($f: $v,)+
+        }
+
+        impl<$($v),+> Transport for $transport<$($v),+>
+        where
+            $($v: Transport),+
+        {
+            type Output = $output<$($v::Output),+>;
+            type Listener = $listen<$($v::Listener),+>;
+            type ListenerUpgrade = $future<$($v::ListenerUpgrade),+>;
+            type Dial = $future<$($v::Dial),+>;
+
+            fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+                let $transport { $($f,)+ } = self;
+                $(
+                    let ($f, addr) = match $f.listen_on(addr) {
+                        Ok((listener, addr)) => return Ok(($listen::$v(listener), addr)),
+                        Err(err) => err,
+                    };
+                )+
+                Err(($transport { $($f,)+ }, addr))
+            }
+
+            fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+                let $transport { $($f,)+ } = self;
+                $(
+                    let ($f, addr) = match $f.dial(addr) {
+                        Ok(dial) => return Ok($future::$v(dial)),
+                        Err(err) => err,
+                    };
+                )+
+                Err(($transport { $($f,)+ }, addr))
+            }
+        }
+
+        impl<$($v),+> MuxedTransport for $transport<$($v),+>
+        where
+            $($v: MuxedTransport,
+              $v::Incoming: Send + 'static,
+              $v::IncomingUpgrade: Send + 'static,
+              $v::Output: 'static,)+
+        {
+            type Incoming = Box<Future<Item = (Self::IncomingUpgrade, Multiaddr), Error = IoError> + Send>;
+            type IncomingUpgrade = Box<Future<Item = $output<$($v::Output),+>, Error = IoError> + Send>;
+
+            fn next_incoming(self) -> Self::Incoming {
+                let mut incoming = Vec::new();
+                $(
+                    let next = self.$f.next_incoming().map(|(out, addr)| {
+                        let fut = out.map($output::$v);
+                        (Box::new(fut) as Box<Future<Item = _, Error = _> + Send>, addr)
+                    });
+                    incoming.push(Box::new(next) as Box<Future<Item = _, Error = _> + Send>);
+                )+
+                let future = future::select_all(incoming)
+                    .map(|(i, _, _)| i)
+                    .map_err(|(e, _, _)| e);
+                Box::new(future) as Box<_>
+            }
+        }
+
+        impl<$($v),+> IntoOrTransport for ($($v,)+) {
+            type Transport = $transport<$($v),+>;
+
+            #[inline]
+            fn into_or_transport(self) -> Self::Transport {
+                let ($($f,)+) = self;
+                $transport { $($f,)+ }
+            }
+        }
+    };
+}
+
+either_n!(EitherOutput3, EitherOutbound3, EitherFuture3, EitherListenStream3,
+          EitherUpgradeIdentifier3, NamesIterChain3, OrUpgrade3, OrUpgradeFuture3, OrTransport3;
+          (A, a, AOut), (B, b, BOut), (C, c, COut));
+either_n!(EitherOutput4, EitherOutbound4, EitherFuture4, EitherListenStream4,
+          EitherUpgradeIdentifier4, NamesIterChain4, OrUpgrade4, OrUpgradeFuture4, OrTransport4;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut));
+either_n!(EitherOutput5, EitherOutbound5, EitherFuture5, EitherListenStream5,
+          EitherUpgradeIdentifier5, NamesIterChain5, OrUpgrade5, OrUpgradeFuture5, OrTransport5;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut));
+either_n!(EitherOutput6, EitherOutbound6, EitherFuture6, EitherListenStream6,
+          EitherUpgradeIdentifier6, NamesIterChain6, OrUpgrade6, OrUpgradeFuture6, OrTransport6;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut));
+either_n!(EitherOutput7, EitherOutbound7, EitherFuture7, EitherListenStream7,
+          EitherUpgradeIdentifier7, NamesIterChain7, OrUpgrade7, OrUpgradeFuture7, OrTransport7;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut));
+either_n!(EitherOutput8, EitherOutbound8, EitherFuture8, EitherListenStream8,
+          EitherUpgradeIdentifier8, NamesIterChain8, OrUpgrade8, OrUpgradeFuture8, OrTransport8;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut));
+either_n!(EitherOutput9, EitherOutbound9, EitherFuture9, EitherListenStream9,
+          EitherUpgradeIdentifier9, NamesIterChain9, OrUpgrade9, OrUpgradeFuture9, OrTransport9;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut));
+either_n!(EitherOutput10, EitherOutbound10, EitherFuture10, EitherListenStream10,
+          EitherUpgradeIdentifier10, NamesIterChain10, OrUpgrade10, OrUpgradeFuture10, OrTransport10;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut));
+either_n!(EitherOutput11, EitherOutbound11, EitherFuture11, EitherListenStream11,
+          EitherUpgradeIdentifier11, NamesIterChain11, OrUpgrade11, OrUpgradeFuture11, OrTransport11;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut));
+either_n!(EitherOutput12, EitherOutbound12, EitherFuture12, EitherListenStream12,
+          EitherUpgradeIdentifier12, NamesIterChain12, OrUpgrade12, OrUpgradeFuture12, OrTransport12;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut), (L, l, LOut));
+either_n!(EitherOutput13, EitherOutbound13, EitherFuture13, EitherListenStream13,
+          EitherUpgradeIdentifier13, NamesIterChain13, OrUpgrade13, OrUpgradeFuture13, OrTransport13;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut), (L, l, LOut),
+          (M, m, MOut));
+either_n!(EitherOutput14, EitherOutbound14, EitherFuture14, EitherListenStream14,
+          EitherUpgradeIdentifier14, NamesIterChain14, OrUpgrade14, OrUpgradeFuture14, OrTransport14;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut), (L, l, LOut),
+          (M, m, MOut), (N, n, NOut));
+either_n!(EitherOutput15, EitherOutbound15, EitherFuture15, EitherListenStream15,
+          EitherUpgradeIdentifier15, NamesIterChain15, OrUpgrade15, OrUpgradeFuture15, OrTransport15;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut), (L, l, LOut),
+          (M, m, MOut), (N, n, NOut), (O, o, OOut));
+either_n!(EitherOutput16, EitherOutbound16, EitherFuture16, EitherListenStream16,
+          EitherUpgradeIdentifier16, NamesIterChain16, OrUpgrade16, OrUpgradeFuture16, OrTransport16;
+          (A, a, AOut), (B, b, BOut), (C, c, COut), (D, d, DOut), (E, e, EOut), (F, f, FOut),
+          (G, g, GOut), (H, h, HOut), (I, i, IOut), (J, j, JOut), (K, k, KOut), (L, l, LOut),
+          (M, m, MOut), (N, n, NOut), (O, o, OOut), (P, p, POut));
+
+#[cfg(test)]
+mod tests {
+    use super::{EitherFuture3, EitherUpgradeIdentifier3};
+    use std::io::Cursor;
+    use transport::{self, memory, DeniedTransport, Transport};
+    use upgrade::{self, ConnectionUpgrade, PlainTextConfig};
+
+    #[test]
+    fn upgrade_names_in_order() {
+        let upgrade = upgrade::or_all((PlainTextConfig, PlainTextConfig, PlainTextConfig));
+        let ids = ConnectionUpgrade::<Cursor<Vec<u8>>>::protocol_names(&upgrade)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        match ids[0] { EitherUpgradeIdentifier3::A(()) => (), _ => panic!() }
+        match ids[1] { EitherUpgradeIdentifier3::B(()) => (), _ => panic!() }
+        match ids[2] { EitherUpgradeIdentifier3::C(()) => (), _ => panic!() }
+    }
+
+    #[test]
+    fn transport_tries_in_order() {
+        let (dialer, _listener) = memory::connector();
+        let transport = transport::or_all((DeniedTransport, DeniedTransport, dialer));
+        match transport.dial("/memory".parse().unwrap()) {
+            Ok(EitherFuture3::C(_)) => (),
+            _ => panic!(),
+        }
+
+        let transport = transport::or_all((DeniedTransport, DeniedTransport, DeniedTransport));
+        assert!(transport.listen_on("/memory".parse().unwrap()).is_err());
+    }
+}
@@ -211,6 +211,11 @@ extern crate bytes;
 extern crate fnv;
 #[macro_use]
 extern crate futures;
+#[cfg(feature = "std-future")]
+extern crate futures03;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate get_if_addrs;
+extern crate iovec;
 #[macro_use]
 extern crate log;
 extern crate multihash;
@@ -219,14 +224,17 @@ extern crate parking_lot;
 extern crate protobuf;
 #[macro_use]
 extern crate quick_error;
+extern crate rand;
 extern crate rw_stream_sink;
 extern crate smallvec;
+#[cfg(not(feature = "wasm"))]
 extern crate tokio_executor;
 extern crate tokio_io;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate tokio_threadpool;
 extern crate void;
+extern crate wasm_timer;
 
-#[cfg(test)]
-extern crate rand;
 #[cfg(test)]
 extern crate tokio;
 #[cfg(test)]
@@ -246,8 +254,13 @@ mod public_key;
 mod unique;
 
 pub mod either;
+pub mod either_n;
 pub mod muxing;
 pub mod nodes;
+pub mod resource_manager;
+pub mod rtt;
+pub mod runtime;
+pub mod span;
 pub mod swarm;
 pub mod transport;
 pub mod upgrade;
@@ -257,7 +270,8 @@ pub use self::multiaddr::Multiaddr;
 pub use self::muxing::StreamMuxer;
 pub use self::peer_id::PeerId;
 pub use self::public_key::PublicKey;
-pub use self::swarm::{swarm, SwarmController, SwarmEvents};
+pub use self::runtime::Runtime;
+pub use self::swarm::{swarm, DialOpts, SwarmController, SwarmEvents};
 pub use self::transport::{MuxedTransport, Transport};
 pub use self::unique::{UniqueConnec, UniqueConnecFuture, UniqueConnecState};
 pub use self::upgrade::{ConnectionUpgrade, Endpoint};
@@ -18,14 +18,22 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use bytes::Buf;
 use fnv::FnvHashMap;
 use futures::{future, prelude::*};
+use iovec::IoVec;
 use parking_lot::Mutex;
-use std::io::{Error as IoError, Read, Write};
+use smallvec::SmallVec;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
 use std::ops::Deref;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio_io::{AsyncRead, AsyncWrite};
 
+/// Maximum number of buffers passed at once to `StreamMuxer::write_substream_vectored` by
+/// `SubstreamRef::write_buf`.
+const MAX_IOVECS: usize = 16;
+
 /// Implemented on objects that can open and manage substreams.
 pub trait StreamMuxer {
     /// Type of the object that represents the raw substream where data can be read and written.
@@ -93,6 +101,39 @@ pub trait StreamMuxer {
         buf: &[u8],
     ) -> Result<usize, IoError>;
 
+    /// Reads data from a substream into multiple buffers, which are filled in order. The
+    /// behaviour is otherwise the same as `read_substream`.
+    ///
+    /// The default implementation reads into the first non-empty buffer.
+    #[inline]
+    fn read_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, IoError> {
+        match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.read_substream(substream, buf),
+            None => Ok(0),
+        }
+    }
+
+    /// Writes the content of multiple buffers, in order, to a substream. The behaviour is
+    /// otherwise the same as `write_substream`.
+    ///
+    /// Muxers should override this method if they can send the buffers without concatenating
+    /// them first. The default implementation writes the first non-empty buffer.
+    #[inline]
+    fn write_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &[&[u8]],
+    ) -> Result<usize, IoError> {
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.write_substream(substream, buf),
+            None => Ok(0),
+        }
+    }
+
     /// Flushes a substream. The behaviour is the same as `std::io::Write::flush`.
     ///
     /// If `WouldBlock` is returned, then the current task will be notified once the substream
@@ -120,6 +161,31 @@ pub trait StreamMuxer {
     /// If supported, sends a hint to the remote that we may no longer open any further outbound
     /// substream. Calling `poll_outbound` afterwards may or may not produce `None`.
     fn close_outbound(&self);
+
+    /// Returns a round-trip time measured by the muxer itself since the last call, for example
+    /// with the keep-alive frames of the multiplexing protocol. Each measurement is only returned
+    /// once.
+    ///
+    /// These measurements don't depend on the protocols that run on the substreams. The default
+    /// implementation never measures anything.
+    #[inline]
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Turns this `StreamMuxer` into a `StreamMuxerBox`, which erases its type.
+    ///
+    /// This makes it possible to store muxers of different types in the same place, or to avoid
+    /// naming complex muxer types.
+    #[inline]
+    fn boxed(self) -> StreamMuxerBox
+    where
+        Self: Sized + Send + Sync + 'static,
+        Self::OutboundSubstream: Send,
+        Self::Substream: Send,
+    {
+        StreamMuxerBox::new(self)
+    }
 }
 
 /// Polls for an inbound from the muxer but wraps the output in an object that
@@ -304,6 +370,27 @@ where
         self.muxer
             .shutdown_substream(self.substream.as_mut().expect("substream was empty"))
     }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, IoError> {
+        if !buf.has_remaining() {
+            return Ok(Async::Ready(0));
+        }
+
+        let written = {
+            let mut iovecs: [&IoVec; MAX_IOVECS] = Default::default();
+            let num = buf.bytes_vec(&mut iovecs);
+            let bufs = iovecs[..num].iter().map(|iovec| &***iovec).collect::<SmallVec<[&[u8]; MAX_IOVECS]>>();
+            let substream = self.substream.as_mut().expect("substream was empty");
+            match self.muxer.write_substream_vectored(substream, &bufs) {
+                Ok(written) => written,
+                Err(ref err) if err.kind() == IoErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(err) => return Err(err),
+            }
+        };
+
+        buf.advance(written);
+        Ok(Async::Ready(written))
+    }
 }
 
 impl<P> Drop for SubstreamRef<P>
@@ -318,7 +405,9 @@ where
     }
 }
 
-/// Abstract `StreamMuxer`.
+/// Abstract `StreamMuxer`. See `StreamMuxer::boxed`.
+///
+/// The substreams of the inner muxer are stored internally, and are referred to by an identifier.
 pub struct StreamMuxerBox {
     inner: Box<StreamMuxer<Substream = usize, OutboundSubstream = usize> + Send + Sync>,
 }
@@ -391,6 +480,24 @@ impl StreamMuxer for StreamMuxerBox {
         self.inner.write_substream(substream, buf)
     }
 
+    #[inline]
+    fn read_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, IoError> {
+        self.inner.read_substream_vectored(substream, bufs)
+    }
+
+    #[inline]
+    fn write_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &[&[u8]],
+    ) -> Result<usize, IoError> {
+        self.inner.write_substream_vectored(substream, bufs)
+    }
+
     #[inline]
     fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
         self.inner.flush_substream(substream)
@@ -415,6 +522,11 @@ impl StreamMuxer for StreamMuxerBox {
     fn close_outbound(&self) {
         self.inner.close_outbound()
     }
+
+    #[inline]
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        self.inner.take_round_trip_time()
+    }
 }
 
 struct Wrap<T> where T: StreamMuxer {
@@ -492,6 +604,26 @@ impl<T> StreamMuxer for Wrap<T> where T: StreamMuxer {
         self.inner.write_substream(list.get_mut(substream).unwrap(), buf)
     }
 
+    #[inline]
+    fn read_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, IoError> {
+        let mut list = self.substreams.lock();
+        self.inner.read_substream_vectored(list.get_mut(substream).unwrap(), bufs)
+    }
+
+    #[inline]
+    fn write_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &[&[u8]],
+    ) -> Result<usize, IoError> {
+        let mut list = self.substreams.lock();
+        self.inner.write_substream_vectored(list.get_mut(substream).unwrap(), bufs)
+    }
+
     #[inline]
     fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
         let mut list = self.substreams.lock();
@@ -519,4 +651,67 @@ impl<T> StreamMuxer for Wrap<T> where T: StreamMuxer {
     fn close_outbound(&self) {
         self.inner.close_outbound()
     }
+
+    #[inline]
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        self.inner.take_round_trip_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use muxing::{StreamMuxer, StreamMuxerBox};
+    use std::io::Error as IoError;
+
+    // Muxer whose substreams are all immediately available, and produce the number of the
+    // substream when read.
+    struct CountingMuxer;
+    impl StreamMuxer for CountingMuxer {
+        type Substream = u8;
+        type OutboundSubstream = u8;
+        fn poll_inbound(&self) -> Poll<Option<u8>, IoError> { Ok(Async::Ready(Some(1))) }
+        fn open_outbound(&self) -> u8 { 2 }
+        fn poll_outbound(&self, s: &mut u8) -> Poll<Option<u8>, IoError> {
+            Ok(Async::Ready(Some(*s)))
+        }
+        fn destroy_outbound(&self, _: u8) {}
+        fn read_substream(&self, s: &mut u8, buf: &mut [u8]) -> Result<usize, IoError> {
+            buf[0] = *s;
+            Ok(1)
+        }
+        fn write_substream(&self, _: &mut u8, buf: &[u8]) -> Result<usize, IoError> {
+            Ok(buf.len())
+        }
+        fn flush_substream(&self, _: &mut u8) -> Result<(), IoError> { Ok(()) }
+        fn shutdown_substream(&self, _: &mut u8) -> Poll<(), IoError> { Ok(Async::Ready(())) }
+        fn destroy_substream(&self, _: u8) {}
+        fn close_inbound(&self) {}
+        fn close_outbound(&self) {}
+    }
+
+    #[test]
+    fn boxed_muxer_dispatches_to_substreams() {
+        let muxer: StreamMuxerBox = CountingMuxer.boxed();
+        let mut buf = [0; 1];
+
+        let mut inbound = match muxer.poll_inbound() {
+            Ok(Async::Ready(Some(s))) => s,
+            _ => panic!(),
+        };
+        let mut outbound = muxer.open_outbound();
+        let mut outbound_substream = match muxer.poll_outbound(&mut outbound) {
+            Ok(Async::Ready(Some(s))) => s,
+            _ => panic!(),
+        };
+        muxer.destroy_outbound(outbound);
+
+        assert_eq!(muxer.read_substream(&mut inbound, &mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 1);
+        assert_eq!(muxer.read_substream(&mut outbound_substream, &mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 2);
+
+        muxer.destroy_substream(inbound);
+        muxer.destroy_substream(outbound_substream);
+    }
 }
@@ -0,0 +1,359 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Allow and deny lists for the connections of a swarm.
+//!
+//! An `AddressFilter` is a set of rules that decide whether the swarm is allowed to dial an
+//! address, and whether it keeps the connections it receives. A rule matches either a range of
+//! IP addresses in CIDR notation, all the multiaddresses that start with a certain prefix, or a
+//! specific peer.
+//!
+//! A connection is rejected if any of the deny rules matches its address or its peer. Otherwise,
+//! if there are allow rules, the connection must also match them: its address must match one of
+//! the allow rules about addresses, if there is any, and its peer must match one of the allow
+//! rules about peers, if there is any. Keeping the two kinds of rules separate lets us check an
+//! address before we know which peer is behind it.
+//!
+//! # Example
+//!
+//! ```
+//! use libp2p_core::nodes::address_filter::{AddressFilter, FilterRule};
+//!
+//! let filter = AddressFilter::new()
+//!     .allow(FilterRule::Cidr("10.0.0.0/8".parse().unwrap()))
+//!     .deny(FilterRule::Prefix("/ip4/10.0.0.1".parse().unwrap()));
+//!
+//! assert!(filter.check(&"/ip4/10.1.2.3/tcp/4001".parse().unwrap(), None).is_ok());
+//! assert!(filter.check(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap(), None).is_err());
+//! assert!(filter.check(&"/ip4/192.168.0.1/tcp/4001".parse().unwrap(), None).is_err());
+//! ```
+
+use multiaddr::Protocol;
+use std::{error, fmt};
+use std::net::IpAddr;
+use std::str::FromStr;
+use {Multiaddr, PeerId};
+
+/// Set of rules that decide which connections a swarm accepts.
+#[derive(Debug, Clone, Default)]
+pub struct AddressFilter {
+    /// Rules that let connections through.
+    allow: Vec<FilterRule>,
+    /// Rules that reject connections. Take precedence over `allow`.
+    deny: Vec<FilterRule>,
+}
+
+impl AddressFilter {
+    /// Creates a filter without any rule, which accepts all the connections.
+    #[inline]
+    pub fn new() -> AddressFilter {
+        Default::default()
+    }
+
+    /// Adds a rule to the allow list.
+    #[inline]
+    pub fn allow(mut self, rule: FilterRule) -> Self {
+        self.allow.push(rule);
+        self
+    }
+
+    /// Adds a rule to the deny list.
+    #[inline]
+    pub fn deny(mut self, rule: FilterRule) -> Self {
+        self.deny.push(rule);
+        self
+    }
+
+    /// Returns the rules of the allow list.
+    #[inline]
+    pub fn allow_list(&self) -> &[FilterRule] {
+        &self.allow
+    }
+
+    /// Returns the rules of the deny list.
+    #[inline]
+    pub fn deny_list(&self) -> &[FilterRule] {
+        &self.deny
+    }
+
+    /// Returns true if the filter has no rule.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Checks a connection to or from the given address, and optionally with the given peer.
+    ///
+    /// If the address ends with `/p2p/<peer>`, the peer is extracted from it. The rules about
+    /// peers are ignored if the peer is unknown.
+    pub fn check(&self, address: &Multiaddr, peer_id: Option<&PeerId>) -> Result<(), FilterRejection> {
+        let from_addr = address.split_p2p()
+            .and_then(|(_, hash)| PeerId::from_multihash(hash).ok());
+        let peer_id = peer_id.or(from_addr.as_ref());
+
+        if let Some(rule) = self.deny.iter().find(|r| r.matches(address, peer_id)) {
+            return Err(FilterRejection::Denied(rule.clone()));
+        }
+
+        let mut address_rules = self.allow.iter().filter(|r| !r.is_peer()).peekable();
+        if address_rules.peek().is_some() && !address_rules.any(|r| r.matches(address, None)) {
+            return Err(FilterRejection::NotAllowed);
+        }
+
+        if let Some(peer_id) = peer_id {
+            let mut peer_rules = self.allow.iter().filter(|r| r.is_peer()).peekable();
+            if peer_rules.peek().is_some() && !peer_rules.any(|r| r.matches(address, Some(peer_id))) {
+                return Err(FilterRejection::NotAllowed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a connection to or from the given peer, whose address isn't known.
+    pub fn check_peer(&self, peer_id: &PeerId) -> Result<(), FilterRejection> {
+        if let Some(rule) = self.deny.iter().find(|r| r.matches_peer(peer_id)) {
+            return Err(FilterRejection::Denied(rule.clone()));
+        }
+
+        let mut peer_rules = self.allow.iter().filter(|r| r.is_peer()).peekable();
+        if peer_rules.peek().is_some() && !peer_rules.any(|r| r.matches_peer(peer_id)) {
+            return Err(FilterRejection::NotAllowed);
+        }
+
+        Ok(())
+    }
+}
+
+/// Rule of an `AddressFilter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterRule {
+    /// Matches the addresses whose IP address is in the range.
+    Cidr(IpCidr),
+    /// Matches the addresses that start with the given components.
+    Prefix(Multiaddr),
+    /// Matches the connections with the given peer.
+    Peer(PeerId),
+}
+
+impl FilterRule {
+    /// Returns true if the rule is about peers rather than addresses.
+    #[inline]
+    fn is_peer(&self) -> bool {
+        match *self {
+            FilterRule::Peer(_) => true,
+            FilterRule::Cidr(_) | FilterRule::Prefix(_) => false,
+        }
+    }
+
+    /// Returns true if the rule matches the address or the peer.
+    fn matches(&self, address: &Multiaddr, peer_id: Option<&PeerId>) -> bool {
+        match *self {
+            FilterRule::Cidr(ref cidr) => {
+                address.iter().any(|p| match p {
+                    Protocol::Ip4(ip) => cidr.contains(&IpAddr::V4(ip)),
+                    Protocol::Ip6(ip) => cidr.contains(&IpAddr::V6(ip)),
+                    _ => false,
+                })
+            },
+            FilterRule::Prefix(ref prefix) => {
+                let mut components = address.iter();
+                prefix.iter().all(|p| components.next() == Some(p))
+            },
+            FilterRule::Peer(ref peer) => peer_id == Some(peer),
+        }
+    }
+
+    /// Returns true if the rule matches the peer.
+    #[inline]
+    fn matches_peer(&self, peer_id: &PeerId) -> bool {
+        match *self {
+            FilterRule::Peer(ref peer) => peer == peer_id,
+            FilterRule::Cidr(_) | FilterRule::Prefix(_) => false,
+        }
+    }
+}
+
+/// Reason why an `AddressFilter` rejected a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterRejection {
+    /// The connection matches a rule of the deny list.
+    Denied(FilterRule),
+    /// There are allow rules, and the connection doesn't match any of them.
+    NotAllowed,
+}
+
+impl fmt::Display for FilterRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterRejection::Denied(ref rule) => write!(f, "denied by rule {:?}", rule),
+            FilterRejection::NotAllowed => write!(f, "not in the allow list"),
+        }
+    }
+}
+
+impl error::Error for FilterRejection {}
+
+/// Range of IP addresses, such as `192.168.0.0/16` or `fe80::/10`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    /// First address of the range.
+    addr: IpAddr,
+    /// Number of leading bits that are fixed.
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// Builds a range from an address and the number of leading bits that are fixed. The other
+    /// bits of the address are ignored.
+    ///
+    /// Returns `None` if `prefix_len` is larger than the number of bits of the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<IpCidr> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return None;
+        }
+        Some(IpCidr { addr, prefix_len })
+    }
+
+    /// Returns the address the range was built with.
+    #[inline]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the number of leading bits that are fixed.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns true if the address is in the range. An IPv4 address is never in an IPv6 range,
+    /// and vice versa.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, *addr) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                prefix_matches(&range.octets(), &addr.octets(), self.prefix_len)
+            },
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                prefix_matches(&range.octets(), &addr.octets(), self.prefix_len)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpCidr {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<IpCidr, ParseCidrError> {
+        let mut parts = s.splitn(2, '/');
+        let addr = parts.next()
+            .and_then(|a| a.parse::<IpAddr>().ok())
+            .ok_or(ParseCidrError)?;
+        let prefix_len = match parts.next() {
+            Some(len) => len.parse::<u8>().map_err(|_| ParseCidrError)?,
+            None => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+        };
+        IpCidr::new(addr, prefix_len).ok_or(ParseCidrError)
+    }
+}
+
+/// Error when parsing an `IpCidr`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseCidrError;
+
+impl fmt::Display for ParseCidrError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid CIDR range")
+    }
+}
+
+impl error::Error for ParseCidrError {}
+
+/// Returns true if the first `len` bits of `a` and `b` are equal.
+fn prefix_matches(a: &[u8], b: &[u8], len: u8) -> bool {
+    let full = usize::from(len / 8);
+    if a[..full] != b[..full] {
+        return false;
+    }
+    let rest = len % 8;
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest);
+    a[full] & mask == b[full] & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressFilter, FilterRejection, FilterRule, IpCidr};
+    use PublicKey;
+
+    #[test]
+    fn cidr_contains() {
+        let cidr: IpCidr = "192.168.0.0/17".parse().unwrap();
+        assert!(cidr.contains(&"192.168.127.1".parse().unwrap()));
+        assert!(!cidr.contains(&"192.168.128.1".parse().unwrap()));
+        assert!(!cidr.contains(&"::1".parse().unwrap()));
+
+        let cidr: IpCidr = "fe80::/10".parse().unwrap();
+        assert!(cidr.contains(&"fe80::1".parse().unwrap()));
+        assert!(!cidr.contains(&"fec0::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("0.0.0.0/0".parse::<IpCidr>().unwrap().contains(&"8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn peer_rules() {
+        let allowed = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let other = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+        let filter = AddressFilter::new()
+            .allow(FilterRule::Peer(allowed.clone()))
+            .deny(FilterRule::Cidr("127.0.0.0/8".parse().unwrap()));
+
+        let addr = "/ip4/1.2.3.4/tcp/80".parse().unwrap();
+        assert!(filter.check(&addr, None).is_ok());
+        assert!(filter.check(&addr, Some(&allowed)).is_ok());
+        assert_eq!(filter.check(&addr, Some(&other)), Err(FilterRejection::NotAllowed));
+        assert!(filter.check_peer(&other).is_err());
+
+        let local = "/ip4/127.0.0.1/tcp/80".parse().unwrap();
+        match filter.check(&local, Some(&allowed)) {
+            Err(FilterRejection::Denied(FilterRule::Cidr(_))) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
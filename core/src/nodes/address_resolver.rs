@@ -0,0 +1,145 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Resolution of the addresses of a peer.
+//!
+//! `Swarm::dial_peer` dials a peer by its `PeerId` alone. In order to do so, the swarm asks an
+//! `AddressResolver` for the addresses of this peer. The resolver first returns the addresses
+//! that are already known, for example the ones stored in a peer store. If there is none, or if
+//! all of them fail, the resolver can optionally start a lookup on the network, for example a
+//! Kademlia `FIND_NODE` query.
+//!
+//! The `FnResolver` struct builds a resolver out of two closures, which is convenient to plug an
+//! existing peer store and DHT together.
+
+use futures::prelude::*;
+use std::fmt;
+use std::io::Error as IoError;
+use {Multiaddr, PeerId};
+
+/// Future that looks for the addresses of a peer on the network.
+pub type AddressLookup = Box<Future<Item = Vec<Multiaddr>, Error = IoError> + Send>;
+
+/// Provides the addresses of peers.
+pub trait AddressResolver {
+    /// Returns the addresses of the given peer that are already known, by order of preference.
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr>;
+
+    /// Starts looking for the addresses of the given peer on the network. Called if there is no
+    /// known address or if all of them have failed.
+    ///
+    /// Returns `None` if the resolver can't perform lookups, which is the default.
+    #[inline]
+    fn lookup(&mut self, _peer_id: &PeerId) -> Option<AddressLookup> {
+        None
+    }
+}
+
+impl<'a, T> AddressResolver for &'a mut T
+where
+    T: AddressResolver + ?Sized,
+{
+    #[inline]
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        (**self).known_addresses(peer_id)
+    }
+
+    #[inline]
+    fn lookup(&mut self, peer_id: &PeerId) -> Option<AddressLookup> {
+        (**self).lookup(peer_id)
+    }
+}
+
+impl<T> AddressResolver for Box<T>
+where
+    T: AddressResolver + ?Sized,
+{
+    #[inline]
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        (**self).known_addresses(peer_id)
+    }
+
+    #[inline]
+    fn lookup(&mut self, peer_id: &PeerId) -> Option<AddressLookup> {
+        (**self).lookup(peer_id)
+    }
+}
+
+/// `AddressResolver` built from closures.
+#[derive(Clone)]
+pub struct FnResolver<TKnown, TLookup> {
+    known: TKnown,
+    lookup: Option<TLookup>,
+}
+
+impl<TKnown> FnResolver<TKnown, fn(&PeerId) -> AddressLookup>
+where
+    TKnown: FnMut(&PeerId) -> Vec<Multiaddr>,
+{
+    /// Builds a resolver that only returns the addresses produced by `known`, and never performs
+    /// lookups.
+    #[inline]
+    pub fn new(known: TKnown) -> Self {
+        FnResolver {
+            known,
+            lookup: None,
+        }
+    }
+}
+
+impl<TKnown, TLookup> FnResolver<TKnown, TLookup>
+where
+    TKnown: FnMut(&PeerId) -> Vec<Multiaddr>,
+    TLookup: FnMut(&PeerId) -> AddressLookup,
+{
+    /// Builds a resolver that returns the addresses produced by `known`, and calls `lookup` if
+    /// they are not enough.
+    #[inline]
+    pub fn with_lookup(known: TKnown, lookup: TLookup) -> Self {
+        FnResolver {
+            known,
+            lookup: Some(lookup),
+        }
+    }
+}
+
+impl<TKnown, TLookup> AddressResolver for FnResolver<TKnown, TLookup>
+where
+    TKnown: FnMut(&PeerId) -> Vec<Multiaddr>,
+    TLookup: FnMut(&PeerId) -> AddressLookup,
+{
+    #[inline]
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        (self.known)(peer_id)
+    }
+
+    #[inline]
+    fn lookup(&mut self, peer_id: &PeerId) -> Option<AddressLookup> {
+        self.lookup.as_mut().map(|lookup| lookup(peer_id))
+    }
+}
+
+impl<TKnown, TLookup> fmt::Debug for FnResolver<TKnown, TLookup> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("FnResolver")
+            .field("lookup", &self.lookup.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Translation of the addresses we listen on into addresses that remotes can dial.
+//!
+//! The addresses we listen on are often not reachable from the outside, for example because we
+//! listen on `0.0.0.0` or because we are behind a NAT. The `AddressTranslator` combines several
+//! sources of information in order to compute the addresses that should be advertised instead:
+//!
+//! - The addresses we listen on.
+//! - The addresses that remotes observe us as, for example as reported by the identify protocol.
+//! - The port mappings that have been opened on the gateway, for example with UPnP or NAT-PMP.
+//!
+//! The translation itself is performed by a list of `TranslationStrategy`s. The default ones are
+//! `PortMappings` and `ObservedIpWithListenPort`. Use `AddressTranslator::with_strategies` in
+//! order to customize them.
+
+use multiaddr::Protocol;
+use std::mem;
+use Multiaddr;
+
+/// Default maximum number of observed addresses that are kept.
+pub const DEFAULT_OBSERVED_ADDRS_LIMIT: usize = 16;
+
+/// An address a remote observes us as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObservedAddress {
+    /// The address.
+    pub addr: Multiaddr,
+    /// Number of times the address has been reported.
+    pub reports: u32,
+}
+
+/// A port mapping opened on the gateway, for example with UPnP or NAT-PMP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    /// The local address the mapping points to, for example `/ip4/192.168.1.2/tcp/4001`. An
+    /// unspecified IP address matches all the addresses we listen on with the same port.
+    pub internal: Multiaddr,
+    /// The address on the gateway, for example `/ip4/80.81.82.83/tcp/4001`.
+    pub external: Multiaddr,
+}
+
+/// Information passed to a `TranslationStrategy`.
+#[derive(Debug, Copy, Clone)]
+pub struct TranslationInput<'a> {
+    /// Addresses we are listening on.
+    pub listen_addrs: &'a [Multiaddr],
+    /// Addresses that remotes observe us as.
+    pub observed_addrs: &'a [ObservedAddress],
+    /// Port mappings that have been opened on the gateway.
+    pub port_mappings: &'a [PortMapping],
+}
+
+/// Way to compute addresses through which we can be reached.
+pub trait TranslationStrategy {
+    /// Returns the addresses that should be advertised, according to the given sources.
+    fn translate(&self, input: &TranslationInput) -> Vec<Multiaddr>;
+}
+
+/// Advertises the external address of each port mapping that points to an address we listen
+/// on.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PortMappings;
+
+impl TranslationStrategy for PortMappings {
+    fn translate(&self, input: &TranslationInput) -> Vec<Multiaddr> {
+        input.port_mappings
+            .iter()
+            .filter(|mapping| input.listen_addrs.iter().any(|l| mapping_matches(&mapping.internal, l)))
+            .map(|mapping| mapping.external.clone())
+            .collect()
+    }
+}
+
+/// Replaces the IP address of the addresses we listen on with the IP address remotes observe us
+/// as, and keeps the rest of the listen address.
+///
+/// For example, if we listen on `/ip4/0.0.0.0/tcp/3000/ws` and are observed as
+/// `/ip4/80.81.82.83/tcp/29601/ws`, then `/ip4/80.81.82.83/tcp/3000/ws` is advertised. This
+/// assumes that the NAT preserves the port of the connections, which is the most common case.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ObservedIpWithListenPort;
+
+impl TranslationStrategy for ObservedIpWithListenPort {
+    fn translate(&self, input: &TranslationInput) -> Vec<Multiaddr> {
+        let mut out = Vec::new();
+        for observed in input.observed_addrs {
+            for listen in input.listen_addrs {
+                if let Some(addr) = replace_ip(listen, &observed.addr) {
+                    out.push(addr);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Advertises the observed addresses as they are.
+///
+/// This is only useful if the connections we open reuse the port we listen on, or if the NAT is
+/// known to keep the same mapping for all the connections.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ObservedAddrs;
+
+impl TranslationStrategy for ObservedAddrs {
+    fn translate(&self, input: &TranslationInput) -> Vec<Multiaddr> {
+        input.observed_addrs.iter().map(|observed| observed.addr.clone()).collect()
+    }
+}
+
+/// Keeps track of the sources of addresses and translates them with a list of strategies.
+pub struct AddressTranslator {
+    /// The strategies, in order of priority.
+    strategies: Vec<Box<TranslationStrategy + Send>>,
+    /// Addresses that remotes observe us as. Always sorted by decreasing number of reports, and
+    /// addresses with the same number of reports from the least to the most recently reported.
+    observed: Vec<ObservedAddress>,
+    /// Maximum number of entries in `observed`.
+    observed_limit: usize,
+    /// Port mappings opened on the gateway.
+    port_mappings: Vec<PortMapping>,
+}
+
+impl AddressTranslator {
+    /// Creates a translator that uses the `PortMappings` and `ObservedIpWithListenPort`
+    /// strategies.
+    #[inline]
+    pub fn new() -> AddressTranslator {
+        AddressTranslator::with_strategies(vec![
+            Box::new(PortMappings),
+            Box::new(ObservedIpWithListenPort),
+        ])
+    }
+
+    /// Creates a translator that uses the given strategies, in order of priority.
+    pub fn with_strategies(strategies: Vec<Box<TranslationStrategy + Send>>) -> AddressTranslator {
+        AddressTranslator {
+            strategies,
+            observed: Vec::new(),
+            observed_limit: DEFAULT_OBSERVED_ADDRS_LIMIT,
+            port_mappings: Vec::new(),
+        }
+    }
+
+    /// Replaces the strategies. The sources that have been recorded are kept.
+    #[inline]
+    pub fn set_strategies(&mut self, strategies: Vec<Box<TranslationStrategy + Send>>) {
+        self.strategies = strategies;
+    }
+
+    /// Records an address a remote observes us as, and returns the addresses derived from this
+    /// observation alone.
+    ///
+    /// If `DEFAULT_OBSERVED_ADDRS_LIMIT` addresses are already known, a new address replaces the
+    /// one with the fewest reports, and among those the one that was reported the longest ago.
+    /// The new address can then be promoted if it keeps being reported.
+    pub fn report_observed(&mut self, listen_addrs: &[Multiaddr], addr: Multiaddr) -> Vec<Multiaddr> {
+        let observed = match self.observed.iter().position(|o| o.addr == addr) {
+            Some(pos) => {
+                let mut observed = self.observed.remove(pos);
+                observed.reports = observed.reports.saturating_add(1);
+                observed
+            },
+            None => {
+                if self.observed.len() >= self.observed_limit {
+                    let fewest = match self.observed.last() {
+                        Some(last) => last.reports,
+                        None => return Vec::new(),
+                    };
+                    let oldest = self.observed.iter().position(|o| o.reports == fewest)
+                        .expect("the last entry has this number of reports ; qed");
+                    self.observed.remove(oldest);
+                }
+                ObservedAddress { addr, reports: 1 }
+            },
+        };
+
+        let pos = self.observed.iter().position(|o| o.reports < observed.reports)
+            .unwrap_or(self.observed.len());
+        self.observed.insert(pos, observed.clone());
+
+        self.run(&TranslationInput {
+            listen_addrs,
+            observed_addrs: &[observed],
+            port_mappings: &[],
+        })
+    }
+
+    /// Records a port mapping, and returns the addresses derived from this mapping alone.
+    ///
+    /// Replaces the existing mapping with the same internal address, if any.
+    pub fn add_port_mapping(&mut self, listen_addrs: &[Multiaddr], mapping: PortMapping) -> Vec<Multiaddr> {
+        self.port_mappings.retain(|m| m.internal != mapping.internal);
+        self.port_mappings.push(mapping.clone());
+
+        self.run(&TranslationInput {
+            listen_addrs,
+            observed_addrs: &[],
+            port_mappings: &[mapping],
+        })
+    }
+
+    /// Removes the port mapping with the given internal address. Returns the addresses that were
+    /// derived from it and that are no longer derived from the remaining sources.
+    pub fn remove_port_mapping(&mut self, listen_addrs: &[Multiaddr], internal: &Multiaddr) -> Vec<Multiaddr> {
+        let mapping = match self.port_mappings.iter().position(|m| &m.internal == internal) {
+            Some(pos) => self.port_mappings.remove(pos),
+            None => return Vec::new(),
+        };
+
+        let remaining = self.translate(listen_addrs);
+        self.run(&TranslationInput {
+            listen_addrs,
+            observed_addrs: &[],
+            port_mappings: &[mapping],
+        })
+        .into_iter()
+        .filter(|addr| !remaining.contains(addr))
+        .collect()
+    }
+
+    /// Returns the addresses derived from all the recorded sources, in order of priority and
+    /// without duplicates.
+    #[inline]
+    pub fn translate(&self, listen_addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+        self.run(&TranslationInput {
+            listen_addrs,
+            observed_addrs: &self.observed,
+            port_mappings: &self.port_mappings,
+        })
+    }
+
+    /// Returns the observed addresses, by decreasing number of reports.
+    #[inline]
+    pub fn observed_addresses(&self) -> &[ObservedAddress] {
+        &self.observed
+    }
+
+    /// Returns the port mappings.
+    #[inline]
+    pub fn port_mappings(&self) -> &[PortMapping] {
+        &self.port_mappings
+    }
+
+    /// Runs all the strategies on the input and removes the duplicates.
+    fn run(&self, input: &TranslationInput) -> Vec<Multiaddr> {
+        let mut out: Vec<Multiaddr> = Vec::new();
+        for strategy in &self.strategies {
+            for addr in strategy.translate(input) {
+                if !out.contains(&addr) {
+                    out.push(addr);
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Default for AddressTranslator {
+    #[inline]
+    fn default() -> Self {
+        AddressTranslator::new()
+    }
+}
+
+/// Returns `listen` with its IP address replaced with the one of `observed`, if both start with
+/// an IP address and the rest of their protocols are the same.
+fn replace_ip(listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+    let mut listen_iter = listen.iter();
+    let mut observed_iter = observed.iter();
+
+    match listen_iter.next()? {
+        Protocol::Ip4(_) | Protocol::Ip6(_) => (),
+        _ => return None,
+    }
+    let observed_ip = match observed_iter.next()? {
+        ip @ Protocol::Ip4(_) | ip @ Protocol::Ip6(_) => ip,
+        _ => return None,
+    };
+
+    let rest = listen_iter.collect::<Vec<_>>();
+    let observed_rest = observed_iter.collect::<Vec<_>>();
+    if rest.is_empty() || rest.len() != observed_rest.len() {
+        return None;
+    }
+    // The values (such as the ports) are allowed to differ, but not the protocols.
+    if rest.iter().zip(observed_rest.iter()).any(|(a, b)| mem::discriminant(a) != mem::discriminant(b)) {
+        return None;
+    }
+
+    Some(Some(observed_ip).into_iter().chain(rest).collect())
+}
+
+/// Returns true if a port mapping whose internal address is `internal` applies to `listen`.
+fn mapping_matches(internal: &Multiaddr, listen: &Multiaddr) -> bool {
+    if internal == listen {
+        return true;
+    }
+
+    let mut internal_iter = internal.iter();
+    let mut listen_iter = listen.iter();
+    let unspecified = match (internal_iter.next(), listen_iter.next()) {
+        (Some(Protocol::Ip4(a)), Some(Protocol::Ip4(_))) => a.is_unspecified(),
+        (Some(Protocol::Ip6(a)), Some(Protocol::Ip6(_))) => a.is_unspecified(),
+        _ => false,
+    };
+    unspecified && internal_iter.eq(listen_iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn observed_ip_with_listen_port() {
+        let mut translator = AddressTranslator::new();
+        let listen = vec![addr("/ip4/0.0.0.0/tcp/10000"), addr("/ip4/0.0.0.0/tcp/10001/ws")];
+
+        let out = translator.report_observed(&listen, addr("/ip4/80.81.82.83/tcp/25000"));
+        assert_eq!(out, vec![addr("/ip4/80.81.82.83/tcp/10000")]);
+        let out = translator.report_observed(&listen, addr("/ip4/80.81.82.83/tcp/25001/ws"));
+        assert_eq!(out, vec![addr("/ip4/80.81.82.83/tcp/10001/ws")]);
+        assert!(translator.report_observed(&listen, addr("/ip4/80.81.82.83/udp/25000")).is_empty());
+    }
+
+    #[test]
+    fn combines_sources() {
+        let mut translator = AddressTranslator::new();
+        let listen = vec![addr("/ip4/192.168.1.2/tcp/4001")];
+
+        translator.report_observed(&listen, addr("/ip4/80.81.82.83/tcp/31000"));
+        let mapping = PortMapping {
+            internal: addr("/ip4/0.0.0.0/tcp/4001"),
+            external: addr("/ip4/80.81.82.83/tcp/5001"),
+        };
+        assert_eq!(translator.add_port_mapping(&listen, mapping), vec![addr("/ip4/80.81.82.83/tcp/5001")]);
+
+        // Port mappings have priority.
+        assert_eq!(translator.translate(&listen), vec![
+            addr("/ip4/80.81.82.83/tcp/5001"),
+            addr("/ip4/80.81.82.83/tcp/4001"),
+        ]);
+
+        let removed = translator.remove_port_mapping(&listen, &addr("/ip4/0.0.0.0/tcp/4001"));
+        assert_eq!(removed, vec![addr("/ip4/80.81.82.83/tcp/5001")]);
+        assert_eq!(translator.translate(&listen), vec![addr("/ip4/80.81.82.83/tcp/4001")]);
+    }
+
+    #[test]
+    fn observed_limit() {
+        let mut translator = AddressTranslator::new();
+        for port in 0 .. DEFAULT_OBSERVED_ADDRS_LIMIT + 1 {
+            translator.report_observed(&[], addr(&format!("/ip4/1.2.3.4/tcp/{}", port)));
+        }
+        translator.report_observed(&[], addr("/ip4/1.2.3.4/tcp/5"));
+        assert_eq!(translator.observed_addresses().len(), DEFAULT_OBSERVED_ADDRS_LIMIT);
+        assert_eq!(translator.observed_addresses()[0].addr, addr("/ip4/1.2.3.4/tcp/5"));
+        assert_eq!(translator.observed_addresses()[0].reports, 2);
+    }
+
+    #[test]
+    fn new_address_replaces_oldest_when_full() {
+        let mut translator = AddressTranslator::new();
+        let listen = vec![addr("/ip4/0.0.0.0/tcp/4001")];
+        let observed = |n: usize| addr(&format!("/ip4/1.2.3.{}/tcp/{}", n, 30000 + n));
+        for n in 0 .. DEFAULT_OBSERVED_ADDRS_LIMIT {
+            translator.report_observed(&listen, observed(n));
+        }
+        // Reported twice, so that it isn't the next one to be replaced.
+        translator.report_observed(&listen, observed(0));
+
+        let out = translator.report_observed(&listen, addr("/ip4/5.6.7.8/tcp/30100"));
+        assert_eq!(out, vec![addr("/ip4/5.6.7.8/tcp/4001")]);
+        let kept = translator.observed_addresses().iter().map(|o| o.addr.clone()).collect::<Vec<_>>();
+        assert_eq!(kept.len(), DEFAULT_OBSERVED_ADDRS_LIMIT);
+        assert!(kept.contains(&observed(0)));
+        assert!(!kept.contains(&observed(1)));
+        assert_eq!(kept.last(), Some(&addr("/ip4/5.6.7.8/tcp/30100")));
+        assert!(translator.translate(&listen).contains(&addr("/ip4/5.6.7.8/tcp/4001")));
+
+        // Reporting the new address again promotes it above the addresses reported once.
+        translator.report_observed(&listen, addr("/ip4/5.6.7.8/tcp/30100"));
+        assert_eq!(translator.observed_addresses()[1].addr, addr("/ip4/5.6.7.8/tcp/30100"));
+        assert_eq!(translator.observed_addresses()[1].reports, 2);
+    }
+}
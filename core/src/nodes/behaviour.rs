@@ -0,0 +1,594 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Composition of protocols on top of the `Swarm`.
+//!
+//! A `NetworkBehaviour` describes the behaviour of the local node on the network for a given
+//! protocol. The state that is specific to a connection is held by a `ConnectionHandler`, which
+//! the behaviour provides for each connection. The behaviour receives the events produced by the
+//! handlers of all the connections, can send events to them, and decides which actions to
+//! perform (dialing, reporting events). A `BehaviourSwarm` drives a `NetworkBehaviour` and a
+//! `Swarm` together.
+//!
+//! When polled, a behaviour receives a `PollParameters` that gives access to the addresses we
+//! listen on and to our external addresses, which it can advertise to remotes.
+//!
+//! Multiple behaviours can be composed into one by grouping them in a struct and using the
+//! `#[derive(NetworkBehaviour)]` macro of the `libp2p-core-derive` crate. The handlers of all the
+//! fields are then combined with `ConnectionHandler::select`, and the events of each handler are
+//! dispatched to the field that created it. The events generated by the fields are passed to the
+//! `NetworkBehaviourEventProcess` implementations of the struct.
+
+use futures::prelude::*;
+use muxing::StreamMuxer;
+use nodes::address_filter::FilterRejection;
+use nodes::connection_handler::{ConnectionHandler, NodeHandlerWrapper};
+use nodes::external_addrs::{AddressRecord, ExternalAddresses};
+use nodes::handled_node::NodeHandler;
+use nodes::node::Substream;
+use nodes::raw_swarm::{HandlerFactory, Peer};
+use nodes::swarm::{ConnectedPoint, DialError, Swarm, SwarmEvent};
+use runtime::Runtime;
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use void::Void;
+use wasm_timer::Instant;
+use {Multiaddr, PeerId, Transport};
+
+/// Behaviour of the local node on the network for a protocol.
+pub trait NetworkBehaviour {
+    /// Handler for the connections of this behaviour.
+    type ConnectionHandler: ConnectionHandler;
+    /// Event generated by the behaviour and returned by the swarm.
+    type OutEvent;
+
+    /// Builds the handler that processes the connections.
+    ///
+    /// > **Note**: This method is called once, when the `BehaviourSwarm` is created, and the
+    /// >           handler is then cloned for each new connection.
+    fn new_handler(&self) -> Self::ConnectionHandler;
+
+    /// Indicates the behaviour that we connected to a node.
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint);
+
+    /// Indicates the behaviour that we disconnected from a node.
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint);
+
+    /// Indicates the behaviour that an address couldn't be dialed. `peer_id` is the peer we
+    /// wanted to reach, if known.
+    ///
+    /// Called for every failed dialing attempt, and for the addresses of the `DialAddress` and
+    /// `DialPeer` actions of the behaviour that the swarm refused to dial.
+    ///
+    /// Does nothing by default.
+    #[inline]
+    fn inject_dial_failure(
+        &mut self,
+        _peer_id: Option<&PeerId>,
+        _address: &Multiaddr,
+        _reason: DialFailureReason,
+    ) {
+    }
+
+    /// Indicates the behaviour that the handler of the connection to a node has produced an
+    /// event.
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        event: <Self::ConnectionHandler as ConnectionHandler>::OutEvent,
+    );
+
+    /// Polls for things that the swarm should do.
+    ///
+    /// Should behave like `Stream::poll()`, and register the current task when returning
+    /// `NotReady`.
+    fn poll(
+        &mut self,
+        params: &mut PollParameters,
+    ) -> Async<NetworkBehaviourAction<<Self::ConnectionHandler as ConnectionHandler>::InEvent, Self::OutEvent>>;
+}
+
+/// Reason why an address couldn't be dialed. Passed to `NetworkBehaviour::inject_dial_failure`.
+#[derive(Debug, Copy, Clone)]
+pub enum DialFailureReason<'a> {
+    /// The address has been dialed, and the attempt failed.
+    Error(&'a DialError),
+    /// The address hasn't been dialed because the previous attempts to dial it failed recently.
+    /// It can be dialed again after the given instant.
+    BackedOff(Instant),
+    /// The address or the peer is rejected by the `AddressFilter` of the swarm.
+    Filtered(&'a FilterRejection),
+    /// The peer is banned.
+    Banned,
+    /// The address isn't supported by the transport.
+    Unsupported,
+}
+
+/// Information about the swarm that is passed to `NetworkBehaviour::poll`.
+pub struct PollParameters<'a> {
+    /// Addresses we listen on.
+    listened_addrs: Vec<Multiaddr>,
+    /// Addresses through which we can be reached from the outside.
+    external_addrs: &'a ExternalAddresses,
+}
+
+impl<'a> PollParameters<'a> {
+    /// Builds a new `PollParameters`.
+    #[inline]
+    pub fn new(listened_addrs: Vec<Multiaddr>, external_addrs: &'a ExternalAddresses) -> Self {
+        PollParameters {
+            listened_addrs,
+            external_addrs,
+        }
+    }
+
+    /// Returns the addresses we listen on.
+    #[inline]
+    pub fn listened_addresses(&self) -> slice::Iter<Multiaddr> {
+        self.listened_addrs.iter()
+    }
+
+    /// Returns the external addresses, by decreasing score.
+    #[inline]
+    pub fn external_addresses(&self) -> slice::Iter<AddressRecord> {
+        self.external_addrs.iter()
+    }
+
+    /// Returns the addresses that should be advertised to remotes: the external addresses by
+    /// decreasing score, followed by the addresses we listen on.
+    #[inline]
+    pub fn advertised_addresses(&self) -> Vec<Multiaddr> {
+        self.external_addrs.advertised(&self.listened_addrs)
+    }
+}
+
+/// Action that a `NetworkBehaviour` asks the swarm to perform.
+#[derive(Debug, Clone)]
+pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
+    /// Generate an event that is returned by the swarm.
+    GenerateEvent(TOutEvent),
+
+    /// Dial the given address, without knowing the peer ID we are going to obtain.
+    ///
+    /// If the swarm refuses to dial the address, for example because dialing it recently failed,
+    /// `inject_dial_failure` is called.
+    DialAddress {
+        /// The address to dial.
+        address: Multiaddr,
+    },
+
+    /// Dial the given peer, trying the given addresses one after the other. Has no effect if
+    /// we are already connected or connecting to this peer.
+    ///
+    /// The addresses that the swarm refuses to dial are skipped and passed to
+    /// `inject_dial_failure`.
+    DialPeer {
+        /// The peer to dial.
+        peer_id: PeerId,
+        /// Addresses to try, in order.
+        addresses: Vec<Multiaddr>,
+    },
+
+    /// Send an event to the handler of the connection to a node.
+    ///
+    /// Has no effect if we are not connected to this node.
+    SendEvent {
+        /// The node to send the event to.
+        peer_id: PeerId,
+        /// Event to pass to the handler.
+        event: TInEvent,
+    },
+
+    /// Report that a remote observes us on the given address. The address is translated with the
+    /// `AddressTranslator` of the swarm, and the resulting addresses are added to the external
+    /// addresses, or their score is increased if they are already known.
+    ReportObservedAddr {
+        /// The observed address.
+        address: Multiaddr,
+    },
+}
+
+impl<TInEvent, TOutEvent> NetworkBehaviourAction<TInEvent, TOutEvent> {
+    /// If this is `SendEvent`, maps the event to something else.
+    #[inline]
+    pub fn map_in_event<F, T>(self, map: F) -> NetworkBehaviourAction<T, TOutEvent>
+    where F: FnOnce(TInEvent) -> T
+    {
+        match self {
+            NetworkBehaviourAction::GenerateEvent(event) => {
+                NetworkBehaviourAction::GenerateEvent(event)
+            },
+            NetworkBehaviourAction::DialAddress { address } => {
+                NetworkBehaviourAction::DialAddress { address }
+            },
+            NetworkBehaviourAction::DialPeer { peer_id, addresses } => {
+                NetworkBehaviourAction::DialPeer { peer_id, addresses }
+            },
+            NetworkBehaviourAction::SendEvent { peer_id, event } => {
+                NetworkBehaviourAction::SendEvent { peer_id, event: map(event) }
+            },
+            NetworkBehaviourAction::ReportObservedAddr { address } => {
+                NetworkBehaviourAction::ReportObservedAddr { address }
+            },
+        }
+    }
+
+    /// If this is `GenerateEvent`, maps the event to something else.
+    #[inline]
+    pub fn map_out_event<F, T>(self, map: F) -> NetworkBehaviourAction<TInEvent, T>
+    where F: FnOnce(TOutEvent) -> T
+    {
+        match self {
+            NetworkBehaviourAction::GenerateEvent(event) => {
+                NetworkBehaviourAction::GenerateEvent(map(event))
+            },
+            NetworkBehaviourAction::DialAddress { address } => {
+                NetworkBehaviourAction::DialAddress { address }
+            },
+            NetworkBehaviourAction::DialPeer { peer_id, addresses } => {
+                NetworkBehaviourAction::DialPeer { peer_id, addresses }
+            },
+            NetworkBehaviourAction::SendEvent { peer_id, event } => {
+                NetworkBehaviourAction::SendEvent { peer_id, event }
+            },
+            NetworkBehaviourAction::ReportObservedAddr { address } => {
+                NetworkBehaviourAction::ReportObservedAddr { address }
+            },
+        }
+    }
+}
+
+/// Implemented on a struct that groups multiple behaviours, in order to process the events that
+/// one of them generates.
+///
+/// When using `#[derive(NetworkBehaviour)]`, the struct must implement this trait for the
+/// `OutEvent` of each of its fields.
+pub trait NetworkBehaviourEventProcess<TEvent> {
+    /// Called when one of the fields of the struct has generated an event.
+    fn inject_event(&mut self, event: TEvent);
+}
+
+/// Builds a `NodeHandlerWrapper` for each new node, by cloning a prototype handler.
+pub struct BehaviourHandlerBuilder<THandler> {
+    prototype: THandler,
+}
+
+impl<THandler> HandlerFactory for BehaviourHandlerBuilder<THandler>
+where
+    THandler: ConnectionHandler + Clone,
+{
+    type Handler = NodeHandlerWrapper<THandler>;
+
+    #[inline]
+    fn new_handler(&self, endpoint: ConnectedPoint) -> Self::Handler {
+        let remote_addr = match endpoint {
+            ConnectedPoint::Dialer { address } => address,
+            ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr,
+        };
+
+        self.prototype.clone().into_node_handler(remote_addr)
+    }
+}
+
+/// Input event of the handlers of a behaviour.
+type InEvent<TBehaviour> =
+    <<TBehaviour as NetworkBehaviour>::ConnectionHandler as ConnectionHandler>::InEvent;
+/// Output event of the handlers of a behaviour.
+type OutEvent<TBehaviour> =
+    <<TBehaviour as NetworkBehaviour>::ConnectionHandler as ConnectionHandler>::OutEvent;
+
+/// Drives a `Swarm` and a `NetworkBehaviour` together.
+///
+/// Implements `Stream` and produces the events of the `Swarm`, plus the events generated by the
+/// behaviour as `SwarmEvent::Behaviour`. The events produced by the nodes are passed to the
+/// behaviour and `SwarmEvent::NodeEvent` is never produced.
+///
+/// Dereferences to the `Swarm`, which can be used to listen or to dial.
+pub struct BehaviourSwarm<TTransport, TBehaviour>
+where
+    TTransport: Transport,
+    TBehaviour: NetworkBehaviour,
+{
+    /// The swarm that handles the nodes.
+    swarm: Swarm<
+        TTransport,
+        InEvent<TBehaviour>,
+        OutEvent<TBehaviour>,
+        BehaviourHandlerBuilder<TBehaviour::ConnectionHandler>,
+    >,
+
+    /// The behaviour that drives the swarm.
+    behaviour: TBehaviour,
+}
+
+impl<TTransport, TMuxer, TBehaviour> BehaviourSwarm<TTransport, TBehaviour>
+where
+    TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+    TTransport::Dial: Send + 'static,
+    TTransport::ListenerUpgrade: Send + 'static,
+    TMuxer: StreamMuxer + Send + Sync + 'static,
+    TMuxer::OutboundSubstream: Send,
+    TMuxer::Substream: Send,
+    TBehaviour: NetworkBehaviour,
+    TBehaviour::ConnectionHandler: ConnectionHandler<Substream = Substream<TMuxer>> + Clone,
+    InEvent<TBehaviour>: Send + 'static,
+    OutEvent<TBehaviour>: Send + 'static,
+    NodeHandlerWrapper<TBehaviour::ConnectionHandler>: NodeHandler<
+        Substream<TMuxer>,
+        InEvent = InEvent<TBehaviour>,
+        OutEvent = OutEvent<TBehaviour>,
+        OutboundOpenInfo = u64,
+    > + Send + 'static,
+{
+    /// Builds a new `BehaviourSwarm`.
+    #[inline]
+    pub fn new(transport: TTransport, behaviour: TBehaviour) -> Self {
+        BehaviourSwarm::with_runtime(transport, behaviour, Runtime::default())
+    }
+
+    /// Builds a new `BehaviourSwarm` that runs on the given `Runtime`.
+    pub fn with_runtime(transport: TTransport, behaviour: TBehaviour, runtime: Runtime) -> Self {
+        let handler_build = BehaviourHandlerBuilder {
+            prototype: behaviour.new_handler(),
+        };
+
+        BehaviourSwarm {
+            swarm: Swarm::with_runtime(transport, handler_build, runtime),
+            behaviour,
+        }
+    }
+
+    /// Returns a reference to the behaviour.
+    #[inline]
+    pub fn behaviour(&self) -> &TBehaviour {
+        &self.behaviour
+    }
+
+    /// Returns a mutable reference to the behaviour.
+    #[inline]
+    pub fn behaviour_mut(&mut self) -> &mut TBehaviour {
+        &mut self.behaviour
+    }
+
+    /// Provides an API similar to `Stream`, except that it cannot error.
+    pub fn poll(&mut self) -> Async<Option<SwarmEvent<TBehaviour::OutEvent>>> {
+        let _entered = self.swarm.span().map(|span| span.enter());
+        loop {
+            let mut swarm_not_ready = false;
+
+            match self.swarm.poll() {
+                Async::NotReady => swarm_not_ready = true,
+                Async::Ready(None) => return Async::Ready(None),
+                Async::Ready(Some(event)) => {
+                    let event = match event {
+                        SwarmEvent::NodeEvent { peer_id, event, .. } => {
+                            self.behaviour.inject_node_event(peer_id, event);
+                            continue;
+                        },
+                        SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint } => {
+                            self.behaviour.inject_connected(peer_id.clone(), endpoint.clone());
+                            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint }
+                        },
+                        SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause } => {
+                            self.behaviour.inject_disconnected(&peer_id, endpoint.clone());
+                            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause }
+                        },
+                        SwarmEvent::NewListenAddr { listener_id, listen_addr } => {
+                            SwarmEvent::NewListenAddr { listener_id, listen_addr }
+                        },
+                        SwarmEvent::ExpiredListenAddr { listener_id, listen_addr } => {
+                            SwarmEvent::ExpiredListenAddr { listener_id, listen_addr }
+                        },
+                        SwarmEvent::ListenerClosed { listener_id, listen_addr, result } => {
+                            SwarmEvent::ListenerClosed { listener_id, listen_addr, result }
+                        },
+                        SwarmEvent::IncomingConnection { listener_id, connection_id, listen_addr, send_back_addr } => {
+                            SwarmEvent::IncomingConnection { listener_id, connection_id, listen_addr, send_back_addr }
+                        },
+                        SwarmEvent::IncomingConnectionError { listener_id, connection_id, listen_addr, send_back_addr, error } => {
+                            SwarmEvent::IncomingConnectionError { listener_id, connection_id, listen_addr, send_back_addr, error }
+                        },
+                        SwarmEvent::DialFailure { peer_id, connection_id, multiaddr, remain_addrs_attempt, error } => {
+                            let reason = DialFailureReason::Error(&error);
+                            self.behaviour.inject_dial_failure(peer_id.as_ref(), &multiaddr, reason);
+                            SwarmEvent::DialFailure { peer_id, connection_id, multiaddr, remain_addrs_attempt, error }
+                        },
+                        SwarmEvent::PeerBanned { peer_id, until } => {
+                            SwarmEvent::PeerBanned { peer_id, until }
+                        },
+                        SwarmEvent::PeerUnbanned { peer_id } => {
+                            SwarmEvent::PeerUnbanned { peer_id }
+                        },
+                        SwarmEvent::BannedPeerRejected { peer_id, connection_id, endpoint } => {
+                            SwarmEvent::BannedPeerRejected { peer_id, connection_id, endpoint }
+                        },
+                        SwarmEvent::ConnectionFiltered { peer_id, connection_id, endpoint, reason } => {
+                            SwarmEvent::ConnectionFiltered { peer_id, connection_id, endpoint, reason }
+                        },
+                        SwarmEvent::DialBackedOff { peer_id, address, until } => {
+                            SwarmEvent::DialBackedOff { peer_id, address, until }
+                        },
+                        SwarmEvent::DuplicateConnectionRejected { peer_id, connection_id, endpoint } => {
+                            SwarmEvent::DuplicateConnectionRejected { peer_id, connection_id, endpoint }
+                        },
+                        SwarmEvent::NewExternalAddress { address } => {
+                            SwarmEvent::NewExternalAddress { address }
+                        },
+                        SwarmEvent::ExpiredExternalAddress { address } => {
+                            SwarmEvent::ExpiredExternalAddress { address }
+                        },
+                        SwarmEvent::PeerUnreachable { peer_id, errors } => {
+                            SwarmEvent::PeerUnreachable { peer_id, errors }
+                        },
+                        SwarmEvent::Behaviour(_) => {
+                            unreachable!("The Swarm never produces Behaviour events")
+                        },
+                    };
+
+                    return Async::Ready(Some(event));
+                },
+            }
+
+            let action = {
+                let listened_addrs = self.swarm.listeners().cloned().collect();
+                let mut params = PollParameters::new(listened_addrs, self.swarm.external_addrs());
+                self.behaviour.poll(&mut params)
+            };
+
+            match action {
+                Async::NotReady if swarm_not_ready => return Async::NotReady,
+                Async::NotReady => (),
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
+                    return Async::Ready(Some(SwarmEvent::Behaviour(event)));
+                },
+                Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
+                    if self.swarm.is_closing() {
+                        debug!("Behaviour asked to dial {} while shutting down", address);
+                    } else if let Some(refusal) = self.refusal(None, &address) {
+                        debug!("Behaviour asked to dial {}, which is refused: {:?}", address, refusal);
+                        self.behaviour.inject_dial_failure(None, &address, refusal.as_reason());
+                    } else if let Err(address) = self.swarm.dial(address) {
+                        debug!("Behaviour asked to dial unsupported address {}", address);
+                        self.behaviour.inject_dial_failure(None, &address, DialFailureReason::Unsupported);
+                    }
+                },
+                Async::Ready(NetworkBehaviourAction::DialPeer { peer_id, addresses }) => {
+                    if addresses.is_empty() {
+                        debug!("Behaviour asked to dial {:?} without any address", peer_id);
+                    } else if self.swarm.is_closing() {
+                        debug!("Behaviour asked to dial {:?} while shutting down", peer_id);
+                    } else {
+                        let mut to_dial = Vec::with_capacity(addresses.len());
+                        for address in addresses {
+                            match self.refusal(Some(&peer_id), &address) {
+                                Some(refusal) => {
+                                    debug!("Not dialing {} for {:?}: {:?}", address, peer_id, refusal);
+                                    let reason = refusal.as_reason();
+                                    self.behaviour.inject_dial_failure(Some(&peer_id), &address, reason);
+                                },
+                                None => to_dial.push(address),
+                            }
+                        }
+                        if !to_dial.is_empty() {
+                            if let Peer::NotConnected(peer) = self.swarm.peer(peer_id) {
+                                let _ = peer.connect_iter(to_dial);
+                            }
+                        }
+                    }
+                },
+                Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event }) => {
+                    if let Some(mut peer) = self.swarm.peer(peer_id).as_connected() {
+                        peer.send_event(event);
+                    }
+                },
+                Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
+                    self.swarm.report_observed_address(address);
+                },
+            }
+        }
+    }
+
+    /// Returns why the swarm refuses to dial `address`, if it does.
+    fn refusal(&self, peer_id: Option<&PeerId>, address: &Multiaddr) -> Option<Refusal> {
+        if peer_id.map(|peer_id| self.swarm.is_banned(peer_id)).unwrap_or(false) {
+            return Some(Refusal::Banned);
+        }
+        if let Err(reason) = self.swarm.address_filter().check(address, peer_id) {
+            return Some(Refusal::Filtered(reason));
+        }
+        self.swarm.dial_backoff_until(address).map(Refusal::BackedOff)
+    }
+}
+
+/// Reason why `BehaviourSwarm` refuses to dial an address that its behaviour asked for.
+#[derive(Debug)]
+enum Refusal {
+    BackedOff(Instant),
+    Filtered(FilterRejection),
+    Banned,
+}
+
+impl Refusal {
+    /// Builds the reason passed to `inject_dial_failure`.
+    fn as_reason(&self) -> DialFailureReason {
+        match *self {
+            Refusal::BackedOff(until) => DialFailureReason::BackedOff(until),
+            Refusal::Filtered(ref rejection) => DialFailureReason::Filtered(rejection),
+            Refusal::Banned => DialFailureReason::Banned,
+        }
+    }
+}
+
+impl<TTransport, TBehaviour> Deref for BehaviourSwarm<TTransport, TBehaviour>
+where
+    TTransport: Transport,
+    TBehaviour: NetworkBehaviour,
+{
+    type Target = Swarm<
+        TTransport,
+        InEvent<TBehaviour>,
+        OutEvent<TBehaviour>,
+        BehaviourHandlerBuilder<TBehaviour::ConnectionHandler>,
+    >;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.swarm
+    }
+}
+
+impl<TTransport, TBehaviour> DerefMut for BehaviourSwarm<TTransport, TBehaviour>
+where
+    TTransport: Transport,
+    TBehaviour: NetworkBehaviour,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.swarm
+    }
+}
+
+impl<TTransport, TMuxer, TBehaviour> Stream for BehaviourSwarm<TTransport, TBehaviour>
+where
+    TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+    TTransport::Dial: Send + 'static,
+    TTransport::ListenerUpgrade: Send + 'static,
+    TMuxer: StreamMuxer + Send + Sync + 'static,
+    TMuxer::OutboundSubstream: Send,
+    TMuxer::Substream: Send,
+    TBehaviour: NetworkBehaviour,
+    TBehaviour::ConnectionHandler: ConnectionHandler<Substream = Substream<TMuxer>> + Clone,
+    InEvent<TBehaviour>: Send + 'static,
+    OutEvent<TBehaviour>: Send + 'static,
+    NodeHandlerWrapper<TBehaviour::ConnectionHandler>: NodeHandler<
+        Substream<TMuxer>,
+        InEvent = InEvent<TBehaviour>,
+        OutEvent = OutEvent<TBehaviour>,
+        OutboundOpenInfo = u64,
+    > + Send + 'static,
+{
+    type Item = SwarmEvent<TBehaviour::OutEvent>;
+    type Error = Void; // TODO: use `!` once stable
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        Ok(self.poll())
+    }
+}
@@ -21,10 +21,14 @@
 use fnv::FnvHashMap;
 use futures::prelude::*;
 use muxing::StreamMuxer;
-use nodes::node::Substream;
+use nodes::node::{OutboundLimits, Substream};
 use nodes::handled_node_tasks::{HandledNodesEvent, HandledNodesTasks};
 use nodes::handled_node_tasks::{Task as HandledNodesTask, TaskId};
 use nodes::handled_node::NodeHandler;
+use nodes::outbound_scheduler::SchedulerConfig;
+use nodes::substream_stats::SubstreamStats;
+use runtime::Runtime;
+use span::Span;
 use std::{collections::hash_map::Entry, fmt, mem};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use PeerId;
@@ -82,12 +86,16 @@ pub enum CollectionEvent<'a, TInEvent:'a , TOutEvent: 'a> {
     NodeClosed {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection.
+        connection_id: ConnectionId,
     },
 
     /// A connection to a node has errored.
     NodeError {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection.
+        connection_id: ConnectionId,
         /// The error that happened.
         error: IoError,
     },
@@ -104,6 +112,8 @@ pub enum CollectionEvent<'a, TInEvent:'a , TOutEvent: 'a> {
     NodeEvent {
         /// Identifier of the node.
         peer_id: PeerId,
+        /// Identifier of the connection that produced the event.
+        connection_id: ConnectionId,
         /// The produced event.
         event: TOutEvent,
     },
@@ -119,14 +129,16 @@ where TOutEvent: fmt::Debug
                 .field(inner)
                 .finish()
             },
-            CollectionEvent::NodeClosed { ref peer_id } => {
+            CollectionEvent::NodeClosed { ref peer_id, ref connection_id } => {
                 f.debug_struct("CollectionEvent::NodeClosed")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .finish()
             },
-            CollectionEvent::NodeError { ref peer_id, ref error } => {
+            CollectionEvent::NodeError { ref peer_id, ref connection_id, ref error } => {
                 f.debug_struct("CollectionEvent::NodeError")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .field("error", error)
                 .finish()
             },
@@ -136,9 +148,10 @@ where TOutEvent: fmt::Debug
                 .field("error", error)
                 .finish()
             },
-            CollectionEvent::NodeEvent { ref peer_id, ref event } => {
+            CollectionEvent::NodeEvent { ref peer_id, ref connection_id, ref event } => {
                 f.debug_struct("CollectionEvent::NodeEvent")
                 .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
                 .field("event", event)
                 .finish()
             },
@@ -170,6 +183,12 @@ impl<'a, TInEvent, TOutEvent> CollectionReachEvent<'a, TInEvent, TOutEvent> {
         ReachAttemptId(self.id)
     }
 
+    /// Returns the id the connection keeps if it is accepted.
+    #[inline]
+    pub fn connection_id(&self) -> ConnectionId {
+        ConnectionId(self.id)
+    }
+
     /// Returns `true` if accepting this reached node would replace an existing connection to that
     /// node.
     #[inline]
@@ -250,17 +269,72 @@ pub enum CollectionNodeAccept {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ReachAttemptId(TaskId);
 
+impl ReachAttemptId {
+    /// Returns the id of the connection this attempt opens, if it succeeds.
+    #[inline]
+    pub fn connection_id(&self) -> ConnectionId {
+        ConnectionId(self.0)
+    }
+}
+
+/// Identifier of a connection, from the moment it starts being negotiated until it is closed.
+///
+/// Ids are never reused, which makes it possible to tell apart multiple connections to the same
+/// peer, or the successive connections to a peer we reconnect to.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConnectionId(TaskId);
+
+impl fmt::Display for ConnectionId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
 impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
     /// Creates a new empty collection.
     #[inline]
     pub fn new() -> Self {
+        CollectionStream::with_runtime(Runtime::default())
+    }
+
+    /// Creates a new empty collection whose node tasks are spawned on the given `Runtime`.
+    #[inline]
+    pub fn with_runtime(runtime: Runtime) -> Self {
         CollectionStream {
-            inner: HandledNodesTasks::new(),
+            inner: HandledNodesTasks::with_runtime(runtime),
             nodes: Default::default(),
             tasks: Default::default(),
         }
     }
 
+    /// Sets the parent of the spans of the node tasks added afterwards. See
+    /// `HandledNodesTasks::set_span`.
+    #[inline]
+    pub fn set_span(&mut self, span: Span) {
+        self.inner.set_span(span)
+    }
+
+    /// Returns the span passed to `set_span`, if any.
+    #[inline]
+    pub fn span(&self) -> Option<&Span> {
+        self.inner.span()
+    }
+
+    /// Sets the configuration of the outbound scheduler of the nodes added afterwards. See
+    /// `HandledNodesTasks::set_scheduler_config`.
+    #[inline]
+    pub fn set_scheduler_config(&mut self, config: Option<SchedulerConfig>) {
+        self.inner.set_scheduler_config(config)
+    }
+
+    /// Sets the limits on the outbound substreams being opened by the nodes added afterwards. See
+    /// `HandledNodesTasks::set_outbound_limits`.
+    #[inline]
+    pub fn set_outbound_limits(&mut self, limits: OutboundLimits) {
+        self.inner.set_outbound_limits(limits)
+    }
+
     /// Adds to the collection a future that tries to reach a remote.
     ///
     /// This method spawns a task dedicated to resolving this future and processing the node's
@@ -315,6 +389,18 @@ impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
         self.inner.broadcast_event(event)
     }
 
+    /// Asks all the connected nodes to shut down gracefully.
+    ///
+    /// The nodes stay in the collection until they have finished closing, at which point a
+    /// `NodeClosed` or a `NodeError` event is generated. Reach attempts are not affected.
+    pub fn start_shutdown_all(&mut self) {
+        for &task_id in self.nodes.values() {
+            if let Some(mut task) = self.inner.task(task_id) {
+                task.start_shutdown();
+            }
+        }
+    }
+
     /// Grants access to an object that allows controlling a peer of the collection.
     ///
     /// Returns `None` if we don't have a connection to this peer.
@@ -351,6 +437,13 @@ impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
         self.nodes.keys()
     }
 
+    /// Returns a snapshot of the substreams of the connection to the given peer, or `None` if we
+    /// are not connected to it.
+    pub fn substream_stats(&self, id: &PeerId) -> Option<SubstreamStats> {
+        let task_id = self.nodes.get(id)?;
+        self.inner.substream_tracker(*task_id).map(|tracker| tracker.stats())
+    }
+
     /// Provides an API similar to `Stream`, except that it cannot error.
     ///
     /// > **Note**: we use a regular `poll` method instead of implementing `Stream` in order to
@@ -383,6 +476,7 @@ impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
                         debug_assert_eq!(_node_task_id, Some(id));
                         Async::Ready(Some(CollectionEvent::NodeClosed {
                             peer_id,
+                            connection_id: ConnectionId(id),
                         }))
                     },
                     (Some(TaskState::Connected(peer_id)), Err(err)) => {
@@ -390,6 +484,7 @@ impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
                         debug_assert_eq!(_node_task_id, Some(id));
                         Async::Ready(Some(CollectionEvent::NodeError {
                             peer_id,
+                            connection_id: ConnectionId(id),
                             error: err,
                         }))
                     },
@@ -419,6 +514,7 @@ impl<TInEvent, TOutEvent> CollectionStream<TInEvent, TOutEvent> {
 
                 Async::Ready(Some(CollectionEvent::NodeEvent {
                     peer_id,
+                    connection_id: ConnectionId(id),
                     event,
                 }))
             }
@@ -435,12 +531,33 @@ pub struct PeerMut<'a, TInEvent: 'a> {
 }
 
 impl<'a, TInEvent> PeerMut<'a, TInEvent> {
+    /// Returns the id of the connection to this node.
+    #[inline]
+    pub fn connection_id(&self) -> ConnectionId {
+        ConnectionId(self.inner.id())
+    }
+
+    /// Returns a snapshot of the substreams of the connection to this node.
+    #[inline]
+    pub fn substream_stats(&self) -> SubstreamStats {
+        self.inner.substream_tracker().stats()
+    }
+
     /// Sends an event to the given node.
     #[inline]
     pub fn send_event(&mut self, event: TInEvent) {
         self.inner.send_event(event)
     }
 
+    /// Asks the node to shut down gracefully.
+    ///
+    /// Contrary to `close()`, the node stays in the collection until it has finished closing, at
+    /// which point a `NodeClosed` or a `NodeError` event is generated.
+    #[inline]
+    pub fn start_shutdown(&mut self) {
+        self.inner.start_shutdown()
+    }
+
     /// Closes the connections to this node.
     ///
     /// No further event will be generated for this node.
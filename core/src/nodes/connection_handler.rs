@@ -0,0 +1,765 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Per-connection protocol handling.
+//!
+//! A `ConnectionHandler` holds the state of one or more protocols on a single connection. It
+//! describes the protocols it accepts on inbound substreams, asks for outbound substreams to be
+//! opened when it needs them, and receives the substreams once the protocol has been negotiated.
+//! It also indicates how long the connection should be kept alive.
+//!
+//! The bookkeeping of the substreams being opened and negotiated is done by the
+//! `NodeHandlerWrapper`, which turns a `ConnectionHandler` into a `NodeHandler` that can be used
+//! by the swarm. Outbound substreams are only opened when the handler requests them.
+//!
+//! Two handlers can be combined with `ConnectionHandler::select`.
+
+use bytes::Bytes;
+use either::EitherOutput;
+use futures::prelude::*;
+use nodes::handled_node::{NodeHandler, NodeHandlerEndpoint, NodeHandlerEvent};
+use nodes::node::OpenSubstreamError;
+use nodes::outbound_scheduler::PriorityClass;
+use nodes::substream_stats::SubstreamHandle;
+use std::cmp::Ordering;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::marker::PhantomData;
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::{self, apply::UpgradeApplyFuture, ConnectionUpgrade, DeniedConnectionUpgrade};
+use upgrade::{Endpoint, SelectUpgrade};
+use void::Void;
+use wasm_timer::{Delay, Instant};
+use Multiaddr;
+
+/// Handler for the protocols of a single connection.
+///
+/// > **Note**: When implementing the various methods, don't forget that you have to register the
+/// >           task that was the latest to poll and notify it.
+pub trait ConnectionHandler {
+    /// Custom event that can be received from the outside.
+    type InEvent;
+    /// Custom event that can be produced by the handler and that will be returned to the outside.
+    type OutEvent;
+    /// The type of the substreams of the connection.
+    type Substream: AsyncRead + AsyncWrite;
+    /// The upgrade that is negotiated on the substreams.
+    type Protocol: ConnectionUpgrade<Self::Substream> + Clone;
+    /// Information about an outbound substream, passed back once the substream is negotiated or
+    /// has failed to open.
+    type OutboundOpenInfo;
+
+    /// Returns the upgrade to negotiate on the inbound substreams.
+    ///
+    /// This method is called every time a new inbound substream is opened by the remote.
+    fn listen_protocol(&self) -> Self::Protocol;
+
+    /// Injects the output of a successful negotiation on a substream.
+    fn inject_fully_negotiated(
+        &mut self,
+        protocol: <Self::Protocol as ConnectionUpgrade<Self::Substream>>::Output,
+        endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>,
+    );
+
+    /// Injects an event coming from the outside in the handler.
+    fn inject_event(&mut self, event: Self::InEvent);
+
+    /// Indicates the handler that an outbound substream we requested failed to open or failed
+    /// to negotiate.
+    ///
+    /// If the substream couldn't be opened, the error wraps an `OpenSubstreamError`.
+    fn inject_dial_upgrade_error(&mut self, info: Self::OutboundOpenInfo, error: &IoError);
+
+    /// Indicates the handler that the inbound part of the muxer has been closed, and that
+    /// therefore no more inbound substream will be produced.
+    fn inject_inbound_closed(&mut self);
+
+    /// Indicates the handler that the remote proposed a protocol on an inbound substream and
+    /// that we answered that it is not available, because it isn't part of `listen_protocol`.
+    ///
+    /// Does nothing by default.
+    #[inline]
+    fn inject_inbound_protocol_rejected(&mut self, _protocol: &Bytes) {
+    }
+
+    /// Returns the priority class of the substreams on which the given protocol has been
+    /// negotiated, or `None` to let the `SchedulerConfig` of the swarm decide. Only matters if
+    /// the swarm has an outbound scheduler.
+    ///
+    /// Returns `None` by default.
+    #[inline]
+    fn substream_priority(&self, _protocol: &Bytes) -> Option<PriorityClass> {
+        None
+    }
+
+    /// Returns until when the connection should be kept alive.
+    ///
+    /// The connection is closed once no substream is being negotiated and all the handlers agree
+    /// that it is no longer needed.
+    fn connection_keep_alive(&self) -> KeepAlive;
+
+    /// Indicates the handler that it should shut down. After that, it is expected that `poll()`
+    /// returns `Ready(None)` as soon as possible.
+    fn shutdown(&mut self);
+
+    /// Should behave like `Stream::poll()`. Should close if no more event can be produced and the
+    /// connection should be closed.
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, Self::OutboundOpenInfo, Self::OutEvent>>, IoError>;
+
+    /// Builds an implementation of `ConnectionHandler` that handles both this protocol and the
+    /// other one together.
+    #[inline]
+    fn select<TOther>(self, other: TOther) -> ConnectionHandlerSelect<Self, TOther>
+    where
+        Self: Sized,
+    {
+        ConnectionHandlerSelect::new(self, other)
+    }
+
+    /// Wraps around `self` to turn it into a `NodeHandler`. `remote_addr` is the address of the
+    /// remote, which is passed to the upgrades.
+    #[inline]
+    fn into_node_handler(self, remote_addr: Multiaddr) -> NodeHandlerWrapper<Self>
+    where
+        Self: Sized,
+    {
+        NodeHandlerWrapper::new(self, remote_addr)
+    }
+}
+
+/// Event produced by a `ConnectionHandler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionHandlerEvent<TUpgrade, TOutboundOpenInfo, TCustom> {
+    /// Require a new outbound substream to be opened with the remote, and the given upgrade to
+    /// be negotiated on it.
+    OutboundSubstreamRequest {
+        /// The upgrade to negotiate on the substream.
+        upgrade: TUpgrade,
+        /// User data passed back once the substream is negotiated or has failed to open.
+        info: TOutboundOpenInfo,
+    },
+
+    /// Other event.
+    Custom(TCustom),
+}
+
+impl<TUpgrade, TOutboundOpenInfo, TCustom> ConnectionHandlerEvent<TUpgrade, TOutboundOpenInfo, TCustom> {
+    /// If this is `OutboundSubstreamRequest`, maps the upgrade and the info to something else.
+    #[inline]
+    pub fn map_outbound_open_info<F, TNewUpgrade, TNewInfo>(self, map: F)
+        -> ConnectionHandlerEvent<TNewUpgrade, TNewInfo, TCustom>
+    where F: FnOnce(TUpgrade, TOutboundOpenInfo) -> (TNewUpgrade, TNewInfo)
+    {
+        match self {
+            ConnectionHandlerEvent::OutboundSubstreamRequest { upgrade, info } => {
+                let (upgrade, info) = map(upgrade, info);
+                ConnectionHandlerEvent::OutboundSubstreamRequest { upgrade, info }
+            },
+            ConnectionHandlerEvent::Custom(val) => ConnectionHandlerEvent::Custom(val),
+        }
+    }
+
+    /// If this is `Custom`, maps the content to something else.
+    #[inline]
+    pub fn map_custom<F, I>(self, map: F) -> ConnectionHandlerEvent<TUpgrade, TOutboundOpenInfo, I>
+    where F: FnOnce(TCustom) -> I
+    {
+        match self {
+            ConnectionHandlerEvent::OutboundSubstreamRequest { upgrade, info } => {
+                ConnectionHandlerEvent::OutboundSubstreamRequest { upgrade, info }
+            },
+            ConnectionHandlerEvent::Custom(val) => ConnectionHandlerEvent::Custom(map(val)),
+        }
+    }
+}
+
+/// How long a connection should be kept alive.
+///
+/// Values are ordered from the shortest to the longest: `Now < Until(_) < Forever`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeepAlive {
+    /// The connection is no longer needed.
+    Now,
+    /// The connection is needed until the given instant.
+    Until(Instant),
+    /// The connection is needed for as long as possible.
+    Forever,
+}
+
+impl KeepAlive {
+    /// Returns true for `Forever`.
+    #[inline]
+    pub fn is_forever(&self) -> bool {
+        *self == KeepAlive::Forever
+    }
+}
+
+impl PartialOrd for KeepAlive {
+    #[inline]
+    fn partial_cmp(&self, other: &KeepAlive) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KeepAlive {
+    fn cmp(&self, other: &KeepAlive) -> Ordering {
+        match (*self, *other) {
+            (KeepAlive::Now, KeepAlive::Now) => Ordering::Equal,
+            (KeepAlive::Now, _) => Ordering::Less,
+            (_, KeepAlive::Now) => Ordering::Greater,
+            (KeepAlive::Until(a), KeepAlive::Until(b)) => a.cmp(b),
+            (KeepAlive::Until(_), KeepAlive::Forever) => Ordering::Less,
+            (KeepAlive::Forever, KeepAlive::Until(_)) => Ordering::Greater,
+            (KeepAlive::Forever, KeepAlive::Forever) => Ordering::Equal,
+        }
+    }
+}
+
+/// Wraps around a `ConnectionHandler` and implements `NodeHandler`.
+///
+/// Takes care of opening the substreams requested by the handler, of negotiating the protocols,
+/// and of closing the connection when the handler no longer needs it.
+pub struct NodeHandlerWrapper<THandler>
+where
+    THandler: ConnectionHandler,
+{
+    /// The underlying handler.
+    handler: THandler,
+    /// Address of the remote, passed to the upgrades.
+    remote_addr: Multiaddr,
+    /// Inbound substreams being negotiated, with their handle in the tracker of the node if any.
+    negotiating_in: Vec<(Option<SubstreamHandle>, UpgradeApplyFuture<THandler::Substream, THandler::Protocol>)>,
+    /// Outbound substreams being negotiated, with their handle in the tracker of the node if any.
+    negotiating_out: Vec<(THandler::OutboundOpenInfo, Option<SubstreamHandle>, UpgradeApplyFuture<THandler::Substream, THandler::Protocol>)>,
+    /// Outbound substreams that have been requested to the node but are not open yet.
+    queued_dial_upgrades: Vec<(u64, THandler::Protocol, THandler::OutboundOpenInfo)>,
+    /// Identifier to use for the next outbound substream request.
+    unique_dial_upgrade_id: u64,
+    /// If the handler asked for the connection to be kept alive until some point in time,
+    /// contains this instant and the timer that fires at that moment.
+    connection_shutdown: Option<(Instant, Delay)>,
+}
+
+impl<THandler> NodeHandlerWrapper<THandler>
+where
+    THandler: ConnectionHandler,
+{
+    /// Builds a new `NodeHandlerWrapper`.
+    #[inline]
+    pub fn new(handler: THandler, remote_addr: Multiaddr) -> Self {
+        NodeHandlerWrapper {
+            handler,
+            remote_addr,
+            negotiating_in: Vec::new(),
+            negotiating_out: Vec::new(),
+            queued_dial_upgrades: Vec::new(),
+            unique_dial_upgrade_id: 0,
+            connection_shutdown: None,
+        }
+    }
+
+    /// Returns a reference to the underlying handler.
+    #[inline]
+    pub fn handler(&self) -> &THandler {
+        &self.handler
+    }
+
+    /// Returns a mutable reference to the underlying handler.
+    #[inline]
+    pub fn handler_mut(&mut self) -> &mut THandler {
+        &mut self.handler
+    }
+
+    /// Starts negotiating the protocol of a new substream.
+    fn start_negotiation(&mut self, substream: THandler::Substream, handle: Option<SubstreamHandle>,
+                         endpoint: NodeHandlerEndpoint<u64>) {
+        match endpoint {
+            NodeHandlerEndpoint::Listener => {
+                let protocol = self.handler.listen_protocol();
+                let future = upgrade::apply(substream, protocol, Endpoint::Listener, &self.remote_addr);
+                self.negotiating_in.push((handle, future));
+            },
+            NodeHandlerEndpoint::Dialer(id) => {
+                let pos = match self.queued_dial_upgrades.iter().position(|&(i, _, _)| i == id) {
+                    Some(pos) => pos,
+                    None => {
+                        debug!("Received an outbound substream that we didn't request");
+                        return;
+                    },
+                };
+
+                let (_, protocol, info) = self.queued_dial_upgrades.remove(pos);
+                let future = upgrade::apply(substream, protocol, Endpoint::Dialer, &self.remote_addr);
+                self.negotiating_out.push((info, handle, future));
+            },
+        }
+    }
+
+    /// Reports the protocol negotiated on a substream to its tracker, along with the priority
+    /// class requested by the handler if any.
+    fn report_protocol(&self, handle: SubstreamHandle, protocol: &Bytes) {
+        handle.set_protocol(protocol.clone());
+        if let Some(class) = self.handler.substream_priority(protocol) {
+            handle.set_priority(class);
+        }
+    }
+
+    /// Reports to the handler that the outbound substream requested with `id` couldn't be opened.
+    fn fail_dial_upgrade(&mut self, id: u64, err: IoError) {
+        let pos = match self.queued_dial_upgrades.iter().position(|&(i, _, _)| i == id) {
+            Some(pos) => pos,
+            None => return,
+        };
+
+        let (_, _, info) = self.queued_dial_upgrades.remove(pos);
+        self.handler.inject_dial_upgrade_error(info, &err);
+    }
+
+    /// Returns true if the handler no longer needs the connection.
+    fn keep_alive_expired(&mut self) -> bool {
+        if !self.negotiating_in.is_empty() || !self.negotiating_out.is_empty()
+            || !self.queued_dial_upgrades.is_empty()
+        {
+            self.connection_shutdown = None;
+            return false;
+        }
+
+        match self.handler.connection_keep_alive() {
+            KeepAlive::Forever => {
+                self.connection_shutdown = None;
+                false
+            },
+            KeepAlive::Now => true,
+            KeepAlive::Until(when) => {
+                let timer_outdated = match self.connection_shutdown {
+                    Some((instant, _)) => instant != when,
+                    None => true,
+                };
+                if timer_outdated {
+                    self.connection_shutdown = Some((when, Delay::new(when)));
+                }
+
+                match self.connection_shutdown {
+                    Some((_, ref mut delay)) => match delay.poll() {
+                        Ok(Async::Ready(())) => true,
+                        Ok(Async::NotReady) => false,
+                        Err(err) => {
+                            debug!("Keep-alive timer errored: {:?}", err);
+                            true
+                        },
+                    },
+                    None => unreachable!("connection_shutdown was set above ; qed"),
+                }
+            },
+        }
+    }
+}
+
+impl<THandler> NodeHandler<THandler::Substream> for NodeHandlerWrapper<THandler>
+where
+    THandler: ConnectionHandler,
+    <THandler::Protocol as ConnectionUpgrade<THandler::Substream>>::NamesIter: Clone,
+{
+    type InEvent = THandler::InEvent;
+    type OutEvent = THandler::OutEvent;
+    type OutboundOpenInfo = u64;
+
+    #[inline]
+    fn inject_substream(&mut self, substream: THandler::Substream, endpoint: NodeHandlerEndpoint<u64>) {
+        self.start_negotiation(substream, None, endpoint)
+    }
+
+    #[inline]
+    fn inject_tracked_substream(&mut self, substream: THandler::Substream, handle: SubstreamHandle,
+                                endpoint: NodeHandlerEndpoint<u64>) {
+        self.start_negotiation(substream, Some(handle), endpoint)
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+        self.handler.inject_inbound_closed();
+    }
+
+    #[inline]
+    fn inject_outbound_closed(&mut self, id: u64) {
+        let err = IoError::new(IoErrorKind::ConnectionAborted, OpenSubstreamError::Closed);
+        self.fail_dial_upgrade(id, err);
+    }
+
+    fn inject_outbound_failed(&mut self, id: u64, error: OpenSubstreamError) {
+        let kind = match error {
+            OpenSubstreamError::Closed => IoErrorKind::ConnectionAborted,
+            OpenSubstreamError::TooManyPending => IoErrorKind::Other,
+            OpenSubstreamError::Timeout => IoErrorKind::TimedOut,
+        };
+        self.fail_dial_upgrade(id, IoError::new(kind, error));
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: Self::InEvent) {
+        self.handler.inject_event(event);
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.handler.shutdown();
+    }
+
+    fn poll(&mut self) -> Poll<Option<NodeHandlerEvent<u64, Self::OutEvent>>, IoError> {
+        for n in (0 .. self.negotiating_in.len()).rev() {
+            let (handle, mut negotiating) = self.negotiating_in.swap_remove(n);
+            let polled = negotiating.poll();
+            for protocol in negotiating.take_rejected_protocols() {
+                self.handler.inject_inbound_protocol_rejected(&protocol);
+            }
+            let handle = match (handle, negotiating.negotiated_protocol()) {
+                (Some(handle), Some(protocol)) => {
+                    self.report_protocol(handle, protocol);
+                    None
+                },
+                (handle, _) => handle,
+            };
+            match polled {
+                Ok(Async::Ready(output)) => {
+                    self.handler.inject_fully_negotiated(output, NodeHandlerEndpoint::Listener);
+                },
+                Ok(Async::NotReady) => self.negotiating_in.push((handle, negotiating)),
+                Err(err) => {
+                    debug!("Error while negotiating an inbound substream with {}: {:?}",
+                        self.remote_addr, err);
+                },
+            }
+        }
+
+        for n in (0 .. self.negotiating_out.len()).rev() {
+            let (info, handle, mut negotiating) = self.negotiating_out.swap_remove(n);
+            let polled = negotiating.poll();
+            let handle = match (handle, negotiating.negotiated_protocol()) {
+                (Some(handle), Some(protocol)) => {
+                    self.report_protocol(handle, protocol);
+                    None
+                },
+                (handle, _) => handle,
+            };
+            match polled {
+                Ok(Async::Ready(output)) => {
+                    self.handler.inject_fully_negotiated(output, NodeHandlerEndpoint::Dialer(info));
+                },
+                Ok(Async::NotReady) => self.negotiating_out.push((info, handle, negotiating)),
+                Err(err) => self.handler.inject_dial_upgrade_error(info, &err),
+            }
+        }
+
+        match self.handler.poll()? {
+            Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest { upgrade, info })) => {
+                let id = self.unique_dial_upgrade_id;
+                self.unique_dial_upgrade_id += 1;
+                self.queued_dial_upgrades.push((id, upgrade, info));
+                return Ok(Async::Ready(Some(NodeHandlerEvent::OutboundSubstreamRequest(id))));
+            },
+            Async::Ready(Some(ConnectionHandlerEvent::Custom(event))) => {
+                return Ok(Async::Ready(Some(NodeHandlerEvent::Custom(event))));
+            },
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => (),
+        }
+
+        if self.keep_alive_expired() {
+            debug!("Closing the connection to {} because it is no longer needed", self.remote_addr);
+            return Ok(Async::Ready(None));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// Implementation of `ConnectionHandler` that combines two handlers into one.
+///
+/// The events and the outbound substreams of each handler are wrapped in an `EitherOutput`, and
+/// the protocols of both handlers are accepted on the inbound substreams.
+#[derive(Debug, Clone)]
+pub struct ConnectionHandlerSelect<TProto1, TProto2> {
+    /// The first handler, and whether it has finished.
+    proto1: TProto1,
+    proto1_finished: bool,
+    /// The second handler, and whether it has finished.
+    proto2: TProto2,
+    proto2_finished: bool,
+}
+
+impl<TProto1, TProto2> ConnectionHandlerSelect<TProto1, TProto2> {
+    /// Builds a `ConnectionHandlerSelect`.
+    #[inline]
+    pub fn new(proto1: TProto1, proto2: TProto2) -> Self {
+        ConnectionHandlerSelect {
+            proto1,
+            proto1_finished: false,
+            proto2,
+            proto2_finished: false,
+        }
+    }
+}
+
+impl<TSubstream, TProto1, TProto2> ConnectionHandler for ConnectionHandlerSelect<TProto1, TProto2>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+    TProto1: ConnectionHandler<Substream = TSubstream>,
+    TProto2: ConnectionHandler<Substream = TSubstream>,
+{
+    type InEvent = EitherOutput<TProto1::InEvent, TProto2::InEvent>;
+    type OutEvent = EitherOutput<TProto1::OutEvent, TProto2::OutEvent>;
+    type Substream = TSubstream;
+    type Protocol = SelectUpgrade<TProto1::Protocol, TProto2::Protocol>;
+    type OutboundOpenInfo = EitherOutput<TProto1::OutboundOpenInfo, TProto2::OutboundOpenInfo>;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        upgrade::select(self.proto1.listen_protocol(), self.proto2.listen_protocol())
+    }
+
+    fn inject_fully_negotiated(
+        &mut self,
+        protocol: <Self::Protocol as ConnectionUpgrade<TSubstream>>::Output,
+        endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>,
+    ) {
+        match (protocol, endpoint) {
+            (EitherOutput::First(protocol), NodeHandlerEndpoint::Dialer(EitherOutput::First(info))) => {
+                self.proto1.inject_fully_negotiated(protocol, NodeHandlerEndpoint::Dialer(info))
+            },
+            (EitherOutput::Second(protocol), NodeHandlerEndpoint::Dialer(EitherOutput::Second(info))) => {
+                self.proto2.inject_fully_negotiated(protocol, NodeHandlerEndpoint::Dialer(info))
+            },
+            (EitherOutput::First(protocol), NodeHandlerEndpoint::Listener) => {
+                self.proto1.inject_fully_negotiated(protocol, NodeHandlerEndpoint::Listener)
+            },
+            (EitherOutput::Second(protocol), NodeHandlerEndpoint::Listener) => {
+                self.proto2.inject_fully_negotiated(protocol, NodeHandlerEndpoint::Listener)
+            },
+            _ => unreachable!("An outbound substream with a First info is always upgraded with \
+                               SelectUpgrade::first, and one with a Second info with \
+                               SelectUpgrade::second ; qed"),
+        }
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: Self::InEvent) {
+        match event {
+            EitherOutput::First(event) => self.proto1.inject_event(event),
+            EitherOutput::Second(event) => self.proto2.inject_event(event),
+        }
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(&mut self, info: Self::OutboundOpenInfo, error: &IoError) {
+        match info {
+            EitherOutput::First(info) => self.proto1.inject_dial_upgrade_error(info, error),
+            EitherOutput::Second(info) => self.proto2.inject_dial_upgrade_error(info, error),
+        }
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+        self.proto1.inject_inbound_closed();
+        self.proto2.inject_inbound_closed();
+    }
+
+    #[inline]
+    fn inject_inbound_protocol_rejected(&mut self, protocol: &Bytes) {
+        self.proto1.inject_inbound_protocol_rejected(protocol);
+        self.proto2.inject_inbound_protocol_rejected(protocol);
+    }
+
+    #[inline]
+    fn substream_priority(&self, protocol: &Bytes) -> Option<PriorityClass> {
+        self.proto1.substream_priority(protocol)
+            .or_else(|| self.proto2.substream_priority(protocol))
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        let keep_alive1 = if self.proto1_finished { KeepAlive::Now } else { self.proto1.connection_keep_alive() };
+        let keep_alive2 = if self.proto2_finished { KeepAlive::Now } else { self.proto2.connection_keep_alive() };
+        keep_alive1.max(keep_alive2)
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.proto1.shutdown();
+        self.proto2.shutdown();
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, Self::OutboundOpenInfo, Self::OutEvent>>, IoError> {
+        if !self.proto1_finished {
+            match self.proto1.poll()? {
+                Async::Ready(Some(event)) => {
+                    let event = event
+                        .map_custom(EitherOutput::First)
+                        .map_outbound_open_info(|upgrade, info| {
+                            (SelectUpgrade::first(upgrade), EitherOutput::First(info))
+                        });
+                    return Ok(Async::Ready(Some(event)));
+                },
+                Async::Ready(None) => self.proto1_finished = true,
+                Async::NotReady => (),
+            }
+        }
+
+        if !self.proto2_finished {
+            match self.proto2.poll()? {
+                Async::Ready(Some(event)) => {
+                    let event = event
+                        .map_custom(EitherOutput::Second)
+                        .map_outbound_open_info(|upgrade, info| {
+                            (SelectUpgrade::second(upgrade), EitherOutput::Second(info))
+                        });
+                    return Ok(Async::Ready(Some(event)));
+                },
+                Async::Ready(None) => self.proto2_finished = true,
+                Async::NotReady => (),
+            }
+        }
+
+        if self.proto1_finished && self.proto2_finished {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Implementation of `ConnectionHandler` that doesn't handle anything and doesn't need the
+/// connection to be kept alive.
+pub struct DummyConnectionHandler<TSubstream> {
+    shutting_down: bool,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> Default for DummyConnectionHandler<TSubstream> {
+    #[inline]
+    fn default() -> Self {
+        DummyConnectionHandler {
+            shutting_down: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> Clone for DummyConnectionHandler<TSubstream> {
+    #[inline]
+    fn clone(&self) -> Self {
+        DummyConnectionHandler {
+            shutting_down: self.shutting_down,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> ConnectionHandler for DummyConnectionHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type InEvent = Void;
+    type OutEvent = Void;
+    type Substream = TSubstream;
+    type Protocol = DeniedConnectionUpgrade;
+    type OutboundOpenInfo = Void;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        DeniedConnectionUpgrade
+    }
+
+    #[inline]
+    fn inject_fully_negotiated(&mut self, _: (), _: NodeHandlerEndpoint<Void>) {
+    }
+
+    #[inline]
+    fn inject_event(&mut self, _: Void) {
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(&mut self, _: Void, _: &IoError) {
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        KeepAlive::Now
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, Void, Void>>, IoError> {
+        if self.shutting_down {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn keep_alive_ordering() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+        assert!(KeepAlive::Now < KeepAlive::Until(now));
+        assert!(KeepAlive::Until(now) < KeepAlive::Until(later));
+        assert!(KeepAlive::Until(later) < KeepAlive::Forever);
+        assert_eq!(KeepAlive::Now.max(KeepAlive::Forever), KeepAlive::Forever);
+    }
+
+    #[test]
+    fn dummy_closes_connection() {
+        let handler = DummyConnectionHandler::<Cursor<Vec<u8>>>::default();
+        let mut wrapper = handler.into_node_handler("/ip4/127.0.0.1/tcp/1234".parse().unwrap());
+        match wrapper.poll() {
+            Ok(Async::Ready(None)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn select_finishes_when_both_finish() {
+        let mut select = DummyConnectionHandler::<Cursor<Vec<u8>>>::default()
+            .select(DummyConnectionHandler::default());
+        assert_eq!(select.connection_keep_alive(), KeepAlive::Now);
+        match select.poll() {
+            Ok(Async::NotReady) => (),
+            _ => panic!(),
+        }
+        select.shutdown();
+        match select.poll() {
+            Ok(Async::Ready(None)) => (),
+            _ => panic!(),
+        }
+    }
+}
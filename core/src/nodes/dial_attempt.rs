@@ -0,0 +1,347 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Progress and cancellation of the dialing attempts of a swarm.
+//!
+//! Dialing an address with `Swarm::dial` or a peer with `PeerNotConnected::connect` returns a
+//! `DialAttempt`. This handle reports the progress of the attempt address by address, as a
+//! `Stream` of `DialAttemptEvent`s or with `addresses`, and can be used to cancel the attempt.
+//!
+//! Cancelling an attempt drops the future that is connecting to the current address, which
+//! aborts the underlying connection and the negotiation of its upgrades, and the addresses that
+//! haven't been tried yet are abandoned. The swarm then reports a dialing error of kind
+//! `Interrupted` for the current address, without waiting for a timeout.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate libp2p_core;
+//! use libp2p_core::nodes::dial_attempt::{AddressStatus, DialAttempt};
+//!
+//! # fn main() {
+//! let attempt = DialAttempt::new(vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()]);
+//! assert_eq!(attempt.addresses()[0].1, AddressStatus::Pending);
+//!
+//! attempt.cancel();
+//! assert!(attempt.is_cancelled());
+//! assert_eq!(attempt.addresses()[0].1, AddressStatus::Cancelled);
+//! # }
+//! ```
+
+use futures::{prelude::*, task::{self, Task}};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use void::Void;
+use {Multiaddr, PeerId};
+
+/// Handle to an attempt to dial one or more addresses.
+///
+/// Cloning a `DialAttempt` produces a handle to the same attempt. The events are only produced
+/// once, to whichever clone polls them first.
+#[derive(Debug, Clone)]
+pub struct DialAttempt {
+    inner: Arc<Mutex<DialAttemptInner>>,
+}
+
+#[derive(Debug)]
+struct DialAttemptInner {
+    /// The addresses of the attempt, in the order in which they are tried.
+    addresses: Vec<(Multiaddr, AddressStatus)>,
+    /// Events not yet produced by the stream.
+    events: VecDeque<DialAttemptEvent>,
+    /// True if the attempt has been cancelled.
+    cancelled: bool,
+    /// True if the attempt is over, either successfully or not.
+    finished: bool,
+    /// Task polling the future that dials the current address.
+    dial_task: Option<Task>,
+    /// Task polling the stream of events.
+    events_task: Option<Task>,
+}
+
+/// Status of an address of a `DialAttempt`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressStatus {
+    /// The address hasn't been tried yet.
+    Pending,
+    /// The address is being dialed.
+    Dialing,
+    /// Dialing the address has failed.
+    Failed,
+    /// We are connected through this address.
+    Connected,
+    /// The attempt has been cancelled before the address could be reached.
+    Cancelled,
+}
+
+/// Event produced by a `DialAttempt`.
+#[derive(Debug)]
+pub enum DialAttemptEvent {
+    /// Started dialing an address.
+    Dialing {
+        /// The address being dialed.
+        address: Multiaddr,
+    },
+    /// Dialing an address has failed. The next address, if any, is tried next.
+    AddressFailed {
+        /// The address that failed.
+        address: Multiaddr,
+        /// The error that happened.
+        error: IoError,
+    },
+    /// We are connected to the peer.
+    Connected {
+        /// The address we are connected through.
+        address: Multiaddr,
+        /// The identity of the peer.
+        peer_id: PeerId,
+    },
+    /// The attempt has been cancelled.
+    Cancelled,
+}
+
+impl DialAttempt {
+    /// Creates the handle of an attempt to dial the given addresses one after the other.
+    pub fn new<I>(addresses: I) -> DialAttempt
+    where I: IntoIterator<Item = Multiaddr>
+    {
+        DialAttempt {
+            inner: Arc::new(Mutex::new(DialAttemptInner {
+                addresses: addresses.into_iter().map(|a| (a, AddressStatus::Pending)).collect(),
+                events: VecDeque::new(),
+                cancelled: false,
+                finished: false,
+                dial_task: None,
+                events_task: None,
+            })),
+        }
+    }
+
+    /// Creates the handle of an attempt that is already over, for example because we are already
+    /// connected to the peer. It doesn't produce any event.
+    pub fn finished() -> DialAttempt {
+        let attempt = DialAttempt::new(Vec::new());
+        attempt.inner.lock().finished = true;
+        attempt
+    }
+
+    /// Returns the addresses of the attempt and their status.
+    pub fn addresses(&self) -> Vec<(Multiaddr, AddressStatus)> {
+        self.inner.lock().addresses.clone()
+    }
+
+    /// Returns true if the attempt is over, either successfully, because all the addresses have
+    /// failed, or because it has been cancelled.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.inner.lock().finished
+    }
+
+    /// Returns true if the attempt has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().cancelled
+    }
+
+    /// Cancels the attempt. Does nothing if it is already over.
+    ///
+    /// > **Note**: If the connection to the current address is already established but hasn't
+    /// >           been processed by the swarm yet, it is kept.
+    pub fn cancel(&self) {
+        let mut inner = self.inner.lock();
+        if inner.finished {
+            return;
+        }
+
+        inner.cancelled = true;
+        inner.finished = true;
+        for (_, status) in inner.addresses.iter_mut() {
+            if *status == AddressStatus::Pending || *status == AddressStatus::Dialing {
+                *status = AddressStatus::Cancelled;
+            }
+        }
+        inner.push_event(DialAttemptEvent::Cancelled);
+        if let Some(task) = inner.dial_task.take() {
+            task.notify();
+        }
+    }
+
+    /// Adds an address to try if the others fail.
+    pub(crate) fn append(&self, address: Multiaddr) {
+        let mut inner = self.inner.lock();
+        if !inner.finished {
+            inner.addresses.push((address, AddressStatus::Pending));
+        }
+    }
+
+    /// Marks an address as being dialed, and wraps the future that dials it so that it is
+    /// interrupted if the attempt is cancelled.
+    pub(crate) fn dialing<F>(&self, address: &Multiaddr, future: F) -> CancellableDial<F> {
+        let mut inner = self.inner.lock();
+        if !inner.cancelled {
+            inner.set_status(address, AddressStatus::Dialing);
+            inner.push_event(DialAttemptEvent::Dialing { address: address.clone() });
+        }
+
+        CancellableDial {
+            inner: future,
+            attempt: self.clone(),
+        }
+    }
+
+    /// Reports that dialing an address has failed. `last` is true if there is no other address
+    /// to try.
+    pub(crate) fn address_failed(&self, address: &Multiaddr, error: &IoError, last: bool) {
+        let mut inner = self.inner.lock();
+        if inner.cancelled {
+            return;
+        }
+
+        inner.set_status(address, AddressStatus::Failed);
+        inner.push_event(DialAttemptEvent::AddressFailed {
+            address: address.clone(),
+            error: IoError::new(error.kind(), error.to_string()),
+        });
+        if last {
+            inner.finished = true;
+            inner.notify_events();
+        }
+    }
+
+    /// Reports that we are connected to the peer through an address.
+    pub(crate) fn connected(&self, address: &Multiaddr, peer_id: &PeerId) {
+        let mut inner = self.inner.lock();
+        inner.set_status(address, AddressStatus::Connected);
+        inner.push_event(DialAttemptEvent::Connected {
+            address: address.clone(),
+            peer_id: peer_id.clone(),
+        });
+        inner.finished = true;
+    }
+}
+
+impl DialAttemptInner {
+    /// Sets the status of an address, unless it is already final.
+    fn set_status(&mut self, address: &Multiaddr, status: AddressStatus) {
+        let entry = self.addresses.iter_mut()
+            .find(|(a, s)| a == address && (*s == AddressStatus::Pending || *s == AddressStatus::Dialing));
+        if let Some((_, s)) = entry {
+            *s = status;
+        }
+    }
+
+    /// Adds an event to produce and notifies the task polling them.
+    fn push_event(&mut self, event: DialAttemptEvent) {
+        self.events.push_back(event);
+        self.notify_events();
+    }
+
+    /// Notifies the task polling the events.
+    fn notify_events(&mut self) {
+        if let Some(task) = self.events_task.take() {
+            task.notify();
+        }
+    }
+}
+
+impl Stream for DialAttempt {
+    type Item = DialAttemptEvent;
+    type Error = Void;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.inner.lock();
+        if let Some(event) = inner.events.pop_front() {
+            return Ok(Async::Ready(Some(event)));
+        }
+
+        if inner.finished {
+            Ok(Async::Ready(None))
+        } else {
+            inner.events_task = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Future that dials an address, interrupted if its `DialAttempt` is cancelled.
+#[must_use = "futures do nothing unless polled"]
+pub struct CancellableDial<F> {
+    inner: F,
+    attempt: DialAttempt,
+}
+
+impl<F> Future for CancellableDial<F>
+where F: Future<Error = IoError>
+{
+    type Item = F::Item;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let mut inner = self.attempt.inner.lock();
+            if inner.cancelled {
+                return Err(IoError::new(IoErrorKind::Interrupted, "dial attempt cancelled"));
+            }
+            inner.dial_task = Some(task::current());
+        }
+
+        self.inner.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use super::{AddressStatus, DialAttempt, DialAttemptEvent};
+    use Multiaddr;
+
+    #[test]
+    fn cancel_interrupts_dial() {
+        let first: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let second: Multiaddr = "/ip4/1.2.3.4/tcp/6".parse().unwrap();
+        let attempt = DialAttempt::new(vec![first.clone(), second.clone()]);
+
+        let mut dial = attempt.dialing(&first, future::empty::<(), IoError>());
+        future::lazy(|| {
+            assert!(dial.poll().unwrap().is_not_ready());
+            attempt.cancel();
+            let err = dial.poll().unwrap_err();
+            assert_eq!(err.kind(), IoErrorKind::Interrupted);
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+
+        assert_eq!(attempt.addresses(), vec![
+            (first.clone(), AddressStatus::Cancelled),
+            (second, AddressStatus::Cancelled),
+        ]);
+
+        let events = attempt.collect().wait().unwrap();
+        match &events[..] {
+            [DialAttemptEvent::Dialing { address }, DialAttemptEvent::Cancelled] => {
+                assert_eq!(address, &first)
+            },
+            _ => panic!("unexpected events: {:?}", events),
+        }
+    }
+}
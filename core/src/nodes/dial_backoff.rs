@@ -0,0 +1,285 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Back-off for addresses that recently failed to be dialed.
+//!
+//! Dialing an address that is unreachable is expensive: the attempt usually only fails after a
+//! timeout, and protocols such as Kademlia tend to try the same addresses over and over again.
+//! The `DialBackoff` struct remembers the addresses whose dialing failed, and tells until when
+//! they shouldn't be dialed again. The delay doubles after each consecutive failure, up to a
+//! maximum, and is forgotten once an address has stayed quiet for long enough or, optionally,
+//! once a connection to it succeeds.
+
+use fnv::FnvHashMap;
+use std::cmp;
+use std::time::Duration;
+use wasm_timer::Instant;
+use Multiaddr;
+
+/// Configuration of a `DialBackoff`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DialBackoffConfig {
+    /// Delay after the first failure.
+    initial: Duration,
+    /// Maximum delay, whatever the number of failures.
+    max: Duration,
+    /// If true, successfully connecting to an address forgets its failures.
+    reset_on_success: bool,
+}
+
+impl DialBackoffConfig {
+    /// Sets the delay before an address can be dialed again after its first failure. Each
+    /// consecutive failure doubles the delay. Defaults to 1 second.
+    #[inline]
+    pub fn with_initial_backoff(mut self, initial: Duration) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    /// Sets the maximum delay before an address can be dialed again. Defaults to 5 minutes.
+    ///
+    /// Also determines how long an address must go without failing before its previous
+    /// failures are forgotten.
+    #[inline]
+    pub fn with_max_backoff(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// If true, the failures of an address are forgotten as soon as we successfully connect to
+    /// it. Otherwise, they are only forgotten after a period of inactivity. Defaults to `true`.
+    #[inline]
+    pub fn with_reset_on_success(mut self, reset: bool) -> Self {
+        self.reset_on_success = reset;
+        self
+    }
+}
+
+impl Default for DialBackoffConfig {
+    #[inline]
+    fn default() -> DialBackoffConfig {
+        DialBackoffConfig {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5 * 60),
+            reset_on_success: true,
+        }
+    }
+}
+
+/// Failures of a single address.
+#[derive(Debug, Copy, Clone)]
+struct FailureRecord {
+    /// Number of consecutive failures.
+    failures: u32,
+    /// The address shouldn't be dialed before this instant.
+    retry_after: Instant,
+}
+
+/// Collection of addresses that recently failed to be dialed.
+#[derive(Debug, Clone)]
+pub struct DialBackoff {
+    /// Failures of each address.
+    records: FnvHashMap<Multiaddr, FailureRecord>,
+    /// The configuration.
+    config: DialBackoffConfig,
+}
+
+impl DialBackoff {
+    /// Creates an empty collection with the default configuration.
+    #[inline]
+    pub fn new() -> DialBackoff {
+        DialBackoff::with_config(DialBackoffConfig::default())
+    }
+
+    /// Creates an empty collection with the given configuration.
+    #[inline]
+    pub fn with_config(config: DialBackoffConfig) -> DialBackoff {
+        DialBackoff {
+            records: FnvHashMap::default(),
+            config,
+        }
+    }
+
+    /// Returns the configuration.
+    #[inline]
+    pub fn config(&self) -> &DialBackoffConfig {
+        &self.config
+    }
+
+    /// Changes the configuration. Only affects the failures that happen afterwards.
+    #[inline]
+    pub fn set_config(&mut self, config: DialBackoffConfig) {
+        self.config = config;
+    }
+
+    /// Records that dialing `addr` has failed at `now`. Returns the instant before which the
+    /// address shouldn't be dialed again.
+    pub fn record_failure(&mut self, addr: &Multiaddr, now: Instant) -> Instant {
+        let failures = match self.records.get(addr) {
+            Some(record) if !self.is_stale(record, now) => record.failures.saturating_add(1),
+            _ => 1,
+        };
+
+        let retry_after = now + self.delay(failures);
+        self.records.insert(addr.clone(), FailureRecord { failures, retry_after });
+        retry_after
+    }
+
+    /// Records that we successfully connected to `addr`. Forgets its failures if the
+    /// configuration says so.
+    #[inline]
+    pub fn record_success(&mut self, addr: &Multiaddr) {
+        if self.config.reset_on_success {
+            self.records.remove(addr);
+        }
+    }
+
+    /// Returns the instant before which `addr` shouldn't be dialed, or `None` if it can be
+    /// dialed at `now`.
+    #[inline]
+    pub fn backed_off_until(&self, addr: &Multiaddr, now: Instant) -> Option<Instant> {
+        self.records
+            .get(addr)
+            .map(|record| record.retry_after)
+            .filter(|retry_after| *retry_after > now)
+    }
+
+    /// Returns the number of consecutive failures of `addr` that are still remembered.
+    #[inline]
+    pub fn failures(&self, addr: &Multiaddr) -> u32 {
+        self.records.get(addr).map(|record| record.failures).unwrap_or(0)
+    }
+
+    /// Forgets the failures of the given address. Returns false if none were remembered.
+    #[inline]
+    pub fn reset(&mut self, addr: &Multiaddr) -> bool {
+        self.records.remove(addr).is_some()
+    }
+
+    /// Forgets the failures of the addresses that haven't failed for longer than the maximum
+    /// back-off.
+    pub fn remove_stale(&mut self, now: Instant) {
+        let max = self.config.max;
+        self.records.retain(|_, record| record.retry_after + max > now);
+    }
+
+    /// Returns the number of addresses whose failures are remembered.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if no failure is remembered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the delay to apply after the given number of consecutive failures.
+    fn delay(&self, failures: u32) -> Duration {
+        let shift = cmp::min(failures.saturating_sub(1), 31);
+        self.config.initial
+            .checked_mul(1 << shift)
+            .map(|delay| cmp::min(delay, self.config.max))
+            .unwrap_or(self.config.max)
+    }
+
+    /// Returns true if the record should be forgotten.
+    #[inline]
+    fn is_stale(&self, record: &FailureRecord, now: Instant) -> bool {
+        record.retry_after + self.config.max <= now
+    }
+}
+
+impl Default for DialBackoff {
+    #[inline]
+    fn default() -> DialBackoff {
+        DialBackoff::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> Multiaddr {
+        format!("/ip4/1.2.3.4/tcp/{}", port).parse().unwrap()
+    }
+
+    fn config() -> DialBackoffConfig {
+        DialBackoffConfig::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(10))
+    }
+
+    #[test]
+    fn exponential_up_to_max() {
+        let mut backoff = DialBackoff::with_config(config());
+        let now = Instant::now();
+        let delays = (0..6)
+            .map(|_| backoff.record_failure(&addr(1), now) - now)
+            .collect::<Vec<_>>();
+        let expected = [1, 2, 4, 8, 10, 10].iter()
+            .map(|s| Duration::from_secs(*s))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, expected);
+        assert_eq!(backoff.failures(&addr(1)), 6);
+
+        assert!(backoff.backed_off_until(&addr(1), now).is_some());
+        assert!(backoff.backed_off_until(&addr(1), now + Duration::from_secs(10)).is_none());
+        assert!(backoff.backed_off_until(&addr(2), now).is_none());
+    }
+
+    #[test]
+    fn reset_on_success() {
+        let now = Instant::now();
+
+        let mut backoff = DialBackoff::with_config(config());
+        backoff.record_failure(&addr(1), now);
+        backoff.record_success(&addr(1));
+        assert!(backoff.backed_off_until(&addr(1), now).is_none());
+        assert!(backoff.is_empty());
+
+        let mut backoff = DialBackoff::with_config(config().with_reset_on_success(false));
+        backoff.record_failure(&addr(1), now);
+        backoff.record_success(&addr(1));
+        assert!(backoff.backed_off_until(&addr(1), now).is_some());
+    }
+
+    #[test]
+    fn stale_failures_forgotten() {
+        let mut backoff = DialBackoff::with_config(config());
+        let now = Instant::now();
+        backoff.record_failure(&addr(1), now);
+        backoff.record_failure(&addr(1), now);
+        backoff.record_failure(&addr(2), now);
+
+        // The address 1 is backed off for 2 seconds, so its record expires after 12 seconds.
+        let later = now + Duration::from_secs(11);
+        backoff.remove_stale(later);
+        assert_eq!(backoff.len(), 1);
+        assert_eq!(backoff.failures(&addr(1)), 2);
+
+        // A failure after a long quiet period starts again from the initial delay.
+        let much_later = now + Duration::from_secs(60);
+        assert_eq!(backoff.record_failure(&addr(1), much_later) - much_later, Duration::from_secs(1));
+    }
+}
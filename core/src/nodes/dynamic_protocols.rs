@@ -0,0 +1,308 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Inbound protocols that can be enabled and disabled while the swarm is running.
+//!
+//! The protocols supported by a `NetworkBehaviour` are normally fixed when the behaviour is
+//! built. `DynamicProtocols` is a behaviour whose list of protocols can be modified at any time
+//! with `add_protocol` and `remove_protocol`. Each protocol is associated with a closure that
+//! receives the substreams on which the remote has negotiated this protocol, along with the id of
+//! the remote.
+//!
+//! The modifications apply to all the connections, including the ones that are already open.
+//! Substreams whose negotiation is already in progress are not affected.
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::{future, prelude::*};
+use nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use nodes::handled_node::NodeHandlerEndpoint;
+use nodes::swarm::ConnectedPoint;
+use span::Span;
+use std::collections::VecDeque;
+use std::{fmt, iter, io::Error as IoError};
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::{ConnectionUpgrade, Endpoint, ProtocolRegistry};
+use void::{self, Void};
+use {Multiaddr, PeerId};
+
+/// Closure that processes the substreams of a protocol.
+type SubstreamHandler<TSubstream> = Box<FnMut(PeerId, TSubstream) + Send>;
+
+/// `NetworkBehaviour` whose inbound protocols can be registered and removed at runtime.
+pub struct DynamicProtocols<TSubstream> {
+    /// Protocols accepted on inbound substreams. Shared with the handlers of all the connections.
+    registry: ProtocolRegistry<TSubstream, (Bytes, TSubstream)>,
+    /// Closure to call for each protocol.
+    handlers: FnvHashMap<Bytes, SubstreamHandler<TSubstream>>,
+}
+
+impl<TSubstream> DynamicProtocols<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// Creates a new `DynamicProtocols` that doesn't support any protocol.
+    #[inline]
+    pub fn new() -> Self {
+        DynamicProtocols {
+            registry: ProtocolRegistry::new(),
+            handlers: FnvHashMap::default(),
+        }
+    }
+
+    /// Starts accepting the given protocol on inbound substreams. Each substream on which the
+    /// protocol has been negotiated is passed to `handler`, along with the id of the remote.
+    ///
+    /// If the protocol was already registered, its handler is replaced.
+    pub fn add_protocol<N, F>(&mut self, name: N, handler: F)
+    where
+        N: Into<Bytes>,
+        F: FnMut(PeerId, TSubstream) + Send + 'static,
+    {
+        let name = name.into();
+        self.registry.register(NamedSubstream { name: name.clone() });
+        self.handlers.insert(name, Box::new(handler));
+    }
+
+    /// Stops accepting the given protocol. Returns false if it wasn't registered.
+    pub fn remove_protocol(&mut self, name: &[u8]) -> bool {
+        self.handlers.remove(name);
+        self.registry.unregister(name)
+    }
+
+    /// Returns the list of protocols that are currently accepted.
+    #[inline]
+    pub fn protocols(&self) -> Vec<Bytes> {
+        self.registry.protocols()
+    }
+}
+
+impl<TSubstream> Default for DynamicProtocols<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    #[inline]
+    fn default() -> Self {
+        DynamicProtocols::new()
+    }
+}
+
+impl<TSubstream> fmt::Debug for DynamicProtocols<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("DynamicProtocols")
+            .field("protocols", &self.registry)
+            .finish()
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for DynamicProtocols<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type ConnectionHandler = DynamicProtocolsHandler<TSubstream>;
+    type OutEvent = Void;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        DynamicProtocolsHandler {
+            registry: self.registry.clone(),
+            pending: VecDeque::new(),
+            shutting_down: false,
+        }
+    }
+
+    #[inline]
+    fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {
+    }
+
+    #[inline]
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, (name, substream): (Bytes, TSubstream)) {
+        match self.handlers.get_mut(&name) {
+            Some(handler) => {
+                let span = Span::child_of_current()
+                    .with_field("remote", peer_id.to_base58())
+                    .with_field("protocol", String::from_utf8_lossy(&name));
+                span.in_scope(|| handler(peer_id, substream))
+            },
+            None => debug!("Dropping substream of removed protocol {:?}", name),
+        }
+    }
+
+    #[inline]
+    fn poll(&mut self, _: &mut PollParameters) -> Async<NetworkBehaviourAction<Void, Void>> {
+        Async::NotReady
+    }
+}
+
+/// Connection handler of `DynamicProtocols`.
+pub struct DynamicProtocolsHandler<TSubstream> {
+    /// Protocols accepted on inbound substreams.
+    registry: ProtocolRegistry<TSubstream, (Bytes, TSubstream)>,
+    /// Substreams that have been negotiated and must be passed to the behaviour.
+    pending: VecDeque<(Bytes, TSubstream)>,
+    /// True if `shutdown()` has been called.
+    shutting_down: bool,
+}
+
+impl<TSubstream> Clone for DynamicProtocolsHandler<TSubstream> {
+    #[inline]
+    fn clone(&self) -> Self {
+        DynamicProtocolsHandler {
+            registry: self.registry.clone(),
+            pending: VecDeque::new(),
+            shutting_down: self.shutting_down,
+        }
+    }
+}
+
+impl<TSubstream> ConnectionHandler for DynamicProtocolsHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type InEvent = Void;
+    type OutEvent = (Bytes, TSubstream);
+    type Substream = TSubstream;
+    type Protocol = ProtocolRegistry<TSubstream, (Bytes, TSubstream)>;
+    type OutboundOpenInfo = Void;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        self.registry.clone()
+    }
+
+    fn inject_fully_negotiated(&mut self, protocol: (Bytes, TSubstream), endpoint: NodeHandlerEndpoint<Void>) {
+        match endpoint {
+            NodeHandlerEndpoint::Listener => self.pending.push_back(protocol),
+            NodeHandlerEndpoint::Dialer(info) => void::unreachable(info),
+        }
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: Void) {
+        void::unreachable(event)
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(&mut self, info: Void, _: &IoError) {
+        void::unreachable(info)
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        // We only accept substreams opened by the remote ; we don't need the connection except
+        // to deliver the substreams that have already been negotiated.
+        if self.pending.is_empty() {
+            KeepAlive::Now
+        } else {
+            KeepAlive::Forever
+        }
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, Void, Self::OutEvent>>, IoError> {
+        if let Some(substream) = self.pending.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(substream))));
+        }
+
+        if self.shutting_down {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Upgrade registered in the `ProtocolRegistry` for each protocol. Passes the substream through,
+/// along with the name of the protocol.
+#[derive(Debug, Clone)]
+struct NamedSubstream {
+    name: Bytes,
+}
+
+impl<C> ConnectionUpgrade<C> for NamedSubstream
+where
+    C: AsyncRead + AsyncWrite,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((self.name.clone(), ()))
+    }
+
+    type Output = (Bytes, C);
+    type Future = future::FutureResult<(Bytes, C), IoError>;
+
+    #[inline]
+    fn upgrade(self, socket: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        future::ok((self.name, socket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use PublicKey;
+
+    #[test]
+    fn protocols_added_at_runtime() {
+        let mut behaviour = DynamicProtocols::<Cursor<Vec<u8>>>::new();
+        let mut handler = behaviour.new_handler();
+        assert_eq!(handler.listen_protocol().protocol_names().count(), 0);
+
+        // Protocols added after the handler has been created are visible to it.
+        let received = Arc::new(AtomicUsize::new(0));
+        let received2 = received.clone();
+        behaviour.add_protocol("/app/1.0.0", move |_, _| {
+            received2.fetch_add(1, Ordering::SeqCst);
+        });
+        let names = handler.listen_protocol().protocol_names().map(|(n, _)| n).collect::<Vec<_>>();
+        assert_eq!(names, vec![Bytes::from("/app/1.0.0")]);
+
+        handler.inject_fully_negotiated((Bytes::from("/app/1.0.0"), Cursor::new(Vec::new())),
+                                        NodeHandlerEndpoint::Listener);
+        let event = match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(event)))) => event,
+            _ => panic!(),
+        };
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        behaviour.inject_node_event(peer_id, event);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        assert!(behaviour.remove_protocol(b"/app/1.0.0"));
+        assert_eq!(handler.listen_protocol().protocol_names().count(), 0);
+    }
+}
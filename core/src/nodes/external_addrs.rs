@@ -0,0 +1,243 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Addresses through which the local node can be reached from the outside.
+//!
+//! The addresses we listen on are not necessarily reachable by remotes, for example because we
+//! are behind a NAT or only reachable through a relay. The `ExternalAddresses` struct holds the
+//! addresses that are known to be reachable, each with a score that indicates how confident we
+//! are about it. These addresses are advertised before the listen addresses.
+
+use std::slice;
+use Multiaddr;
+
+/// Default maximum number of external addresses that are kept.
+pub const DEFAULT_EXTERNAL_ADDRS_LIMIT: usize = 16;
+
+/// Confidence in an external address.
+///
+/// Values are ordered from the lowest to the highest confidence. An `Infinite` score is used for
+/// addresses that are known to be correct, for example because they have been configured by the
+/// user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AddressScore {
+    /// The address has been confirmed this number of times.
+    Finite(u32),
+    /// The address is known to be correct.
+    Infinite,
+}
+
+impl AddressScore {
+    /// Adds two scores together. Saturates instead of overflowing.
+    #[inline]
+    pub fn add(self, other: AddressScore) -> AddressScore {
+        match (self, other) {
+            (AddressScore::Finite(a), AddressScore::Finite(b)) => {
+                AddressScore::Finite(a.saturating_add(b))
+            },
+            _ => AddressScore::Infinite,
+        }
+    }
+}
+
+/// An external address with its score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+    /// The address.
+    pub addr: Multiaddr,
+    /// Confidence in the address.
+    pub score: AddressScore,
+}
+
+/// Outcome of `ExternalAddresses::add`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddAddressResult {
+    /// The address wasn't known and has been inserted. Contains the addresses with the lowest
+    /// score that have been evicted in order to stay within the limit.
+    Inserted {
+        /// Addresses that have been removed.
+        expired: Vec<Multiaddr>,
+    },
+    /// The address was already known and its score has been increased.
+    Updated,
+}
+
+/// Collection of external addresses, ordered by decreasing score.
+#[derive(Debug, Clone)]
+pub struct ExternalAddresses {
+    /// The addresses. Always sorted by decreasing score.
+    addrs: Vec<AddressRecord>,
+    /// Maximum number of entries in `addrs`.
+    limit: usize,
+}
+
+impl ExternalAddresses {
+    /// Creates an empty collection that keeps at most `DEFAULT_EXTERNAL_ADDRS_LIMIT` addresses.
+    #[inline]
+    pub fn new() -> ExternalAddresses {
+        ExternalAddresses::with_limit(DEFAULT_EXTERNAL_ADDRS_LIMIT)
+    }
+
+    /// Creates an empty collection that keeps at most `limit` addresses.
+    ///
+    /// A limit of `0` is treated like a limit of `1`.
+    #[inline]
+    pub fn with_limit(limit: usize) -> ExternalAddresses {
+        ExternalAddresses {
+            addrs: Vec::new(),
+            limit: if limit == 0 { 1 } else { limit },
+        }
+    }
+
+    /// Adds an address, or adds `score` to its current score if it is already known.
+    ///
+    /// If the limit is exceeded, the addresses with the lowest score are evicted. Among addresses
+    /// with the same score, the oldest ones are kept.
+    pub fn add(&mut self, addr: Multiaddr, score: AddressScore) -> AddAddressResult {
+        let result = match self.addrs.iter().position(|record| record.addr == addr) {
+            Some(pos) => {
+                let record = &mut self.addrs[pos];
+                record.score = record.score.add(score);
+                AddAddressResult::Updated
+            },
+            None => {
+                self.addrs.push(AddressRecord { addr, score });
+                AddAddressResult::Inserted { expired: Vec::new() }
+            },
+        };
+
+        // Stable sort, so that older addresses stay in front of newer ones with the same score.
+        self.addrs.sort_by(|a, b| b.score.cmp(&a.score));
+
+        match result {
+            AddAddressResult::Inserted { .. } => {
+                let expired = if self.addrs.len() > self.limit {
+                    self.addrs.split_off(self.limit).into_iter().map(|r| r.addr).collect()
+                } else {
+                    Vec::new()
+                };
+                AddAddressResult::Inserted { expired }
+            },
+            AddAddressResult::Updated => AddAddressResult::Updated,
+        }
+    }
+
+    /// Removes an address. Returns false if the address wasn't known.
+    pub fn remove(&mut self, addr: &Multiaddr) -> bool {
+        match self.addrs.iter().position(|record| &record.addr == addr) {
+            Some(pos) => {
+                self.addrs.remove(pos);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the score of an address, if it is known.
+    #[inline]
+    pub fn score(&self, addr: &Multiaddr) -> Option<AddressScore> {
+        self.addrs.iter().find(|record| &record.addr == addr).map(|record| record.score)
+    }
+
+    /// Returns the number of addresses.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Returns true if there is no address.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    /// Returns the addresses, by decreasing score.
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<AddressRecord> {
+        self.addrs.iter()
+    }
+
+    /// Returns the addresses that should be advertised to the rest of the network: the external
+    /// addresses by decreasing score, followed by the listen addresses that aren't already
+    /// included.
+    pub fn advertised<'a, I>(&self, listened: I) -> Vec<Multiaddr>
+    where
+        I: IntoIterator<Item = &'a Multiaddr>,
+    {
+        let mut out: Vec<Multiaddr> = self.addrs.iter().map(|record| record.addr.clone()).collect();
+        for addr in listened {
+            if !out.iter().any(|a| a == addr) {
+                out.push(addr.clone());
+            }
+        }
+        out
+    }
+}
+
+impl Default for ExternalAddresses {
+    #[inline]
+    fn default() -> Self {
+        ExternalAddresses::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> Multiaddr {
+        format!("/ip4/1.2.3.4/tcp/{}", port).parse().unwrap()
+    }
+
+    #[test]
+    fn ordered_by_score() {
+        let mut addrs = ExternalAddresses::new();
+        addrs.add(addr(1), AddressScore::Finite(1));
+        addrs.add(addr(2), AddressScore::Finite(1));
+        addrs.add(addr(3), AddressScore::Infinite);
+        assert_eq!(addrs.add(addr(2), AddressScore::Finite(3)), AddAddressResult::Updated);
+
+        let order = addrs.iter().map(|r| r.addr.clone()).collect::<Vec<_>>();
+        assert_eq!(order, vec![addr(3), addr(2), addr(1)]);
+        assert_eq!(addrs.score(&addr(2)), Some(AddressScore::Finite(4)));
+    }
+
+    #[test]
+    fn lowest_score_evicted() {
+        let mut addrs = ExternalAddresses::with_limit(2);
+        addrs.add(addr(1), AddressScore::Finite(5));
+        addrs.add(addr(2), AddressScore::Finite(1));
+        match addrs.add(addr(3), AddressScore::Finite(2)) {
+            AddAddressResult::Inserted { expired } => assert_eq!(expired, vec![addr(2)]),
+            _ => panic!(),
+        }
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs.remove(&addr(1)));
+        assert!(!addrs.remove(&addr(1)));
+    }
+
+    #[test]
+    fn advertised_addresses() {
+        let mut addrs = ExternalAddresses::new();
+        addrs.add(addr(1), AddressScore::Finite(1));
+        let listened = vec![addr(1), addr(7)];
+        assert_eq!(addrs.advertised(&listened), vec![addr(1), addr(7)]);
+    }
+}
@@ -19,8 +19,11 @@
 // DEALINGS IN THE SOFTWARE.
 
 use muxing::StreamMuxer;
-use nodes::node::{NodeEvent, NodeStream, Substream};
+use nodes::node::{NodeEvent, NodeStream, OpenSubstreamError, OutboundLimits, Substream};
+use nodes::substream_stats::{SubstreamHandle, SubstreamTracker};
 use futures::prelude::*;
+use std::any::Any;
+use std::{error, fmt};
 use std::io::Error as IoError;
 
 /// Handler for the substreams of a node.
@@ -44,6 +47,16 @@ pub trait NodeHandler<TSubstream> {
     /// The handler is responsible for upgrading the substream to whatever protocol it wants.
     fn inject_substream(&mut self, substream: TSubstream, endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>);
 
+    /// Same as `inject_substream`, but also passes a handle to the substream in the
+    /// `SubstreamTracker` of the node, which can be used to report the protocol negotiated on it.
+    ///
+    /// Calls `inject_substream` by default.
+    #[inline]
+    fn inject_tracked_substream(&mut self, substream: TSubstream, _handle: SubstreamHandle,
+                                endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>) {
+        self.inject_substream(substream, endpoint)
+    }
+
     /// Indicates the handler that the inbound part of the muxer has been closed, and that
     /// therefore no more inbound substream will be produced.
     fn inject_inbound_closed(&mut self);
@@ -52,6 +65,16 @@ pub trait NodeHandler<TSubstream> {
     /// part of the muxer has been closed.
     fn inject_outbound_closed(&mut self, user_data: Self::OutboundOpenInfo);
 
+    /// Indicates the handler that an outbound substream failed to open because of the
+    /// `OutboundLimits` of the node, either because too many substreams were already being opened
+    /// or because the remote didn't accept it in time.
+    ///
+    /// Calls `inject_outbound_closed` by default.
+    #[inline]
+    fn inject_outbound_failed(&mut self, user_data: Self::OutboundOpenInfo, _error: OpenSubstreamError) {
+        self.inject_outbound_closed(user_data)
+    }
+
     /// Injects an event coming from the outside in the handler.
     fn inject_event(&mut self, event: Self::InEvent);
 
@@ -113,6 +136,52 @@ impl<TOutboundOpenInfo, TCustom> NodeHandlerEvent<TOutboundOpenInfo, TCustom> {
     }
 }
 
+/// Error reported when the handler of a node panics while being polled.
+///
+/// The task that drives the node catches the panic and closes the connection, without affecting
+/// the other connections. The panic is then reported as an `IoError` of kind `Other` that wraps
+/// a `HandlerPanicked`. Use `HandlerPanicked::from_io_error` in order to recognize it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerPanicked {
+    /// Message of the panic.
+    message: String,
+}
+
+impl HandlerPanicked {
+    /// Builds a `HandlerPanicked` from the payload returned by `std::panic::catch_unwind`.
+    pub(crate) fn from_payload(payload: Box<Any + Send>) -> HandlerPanicked {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            (*message).to_owned()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic payload".to_owned()
+        };
+
+        HandlerPanicked { message }
+    }
+
+    /// Returns the `HandlerPanicked` wrapped in `error`, if any.
+    #[inline]
+    pub fn from_io_error(error: &IoError) -> Option<&HandlerPanicked> {
+        error.get_ref().and_then(|err| err.downcast_ref::<HandlerPanicked>())
+    }
+
+    /// Returns the message of the panic.
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for HandlerPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the handler of the node panicked: {}", self.message)
+    }
+}
+
+impl error::Error for HandlerPanicked {}
+
 /// A node combined with an implementation of `NodeHandler`.
 // TODO: impl Debug
 pub struct HandledNode<TMuxer, THandler>
@@ -140,6 +209,24 @@ where
         }
     }
 
+    /// Same as `new`, but the substreams are accounted in the given `SubstreamTracker`.
+    #[inline]
+    pub fn with_tracker(muxer: TMuxer, handler: THandler, tracker: SubstreamTracker) -> Self {
+        HandledNode {
+            node: Some(NodeStream::with_tracker(muxer, tracker)),
+            handler,
+        }
+    }
+
+    /// Sets the limits on the outbound substreams being opened. See
+    /// `NodeStream::set_outbound_limits`.
+    #[inline]
+    pub fn set_outbound_limits(&mut self, limits: OutboundLimits) {
+        if let Some(node) = self.node.as_mut() {
+            node.set_outbound_limits(limits);
+        }
+    }
+
     /// Injects an event to the handler.
     #[inline]
     pub fn inject_event(&mut self, event: THandler::InEvent) {
@@ -201,11 +288,13 @@ where
                         break;
                     },
                     Ok(Async::Ready(Some(NodeEvent::InboundSubstream { substream }))) => {
-                        self.handler.inject_substream(substream, NodeHandlerEndpoint::Listener);
+                        let handle = substream.handle().clone();
+                        self.handler.inject_tracked_substream(substream, handle, NodeHandlerEndpoint::Listener);
                     },
                     Ok(Async::Ready(Some(NodeEvent::OutboundSubstream { user_data, substream }))) => {
+                        let handle = substream.handle().clone();
                         let endpoint = NodeHandlerEndpoint::Dialer(user_data);
-                        self.handler.inject_substream(substream, endpoint);
+                        self.handler.inject_tracked_substream(substream, handle, endpoint);
                     },
                     Ok(Async::Ready(None)) => {
                         // Breaking from the loop without putting back the node.
@@ -215,6 +304,9 @@ where
                     Ok(Async::Ready(Some(NodeEvent::OutboundClosed { user_data }))) => {
                         self.handler.inject_outbound_closed(user_data);
                     },
+                    Ok(Async::Ready(Some(NodeEvent::OutboundFailed { user_data, error }))) => {
+                        self.handler.inject_outbound_failed(user_data, error);
+                    },
                     Ok(Async::Ready(Some(NodeEvent::InboundClosed))) => {
                         self.handler.inject_inbound_closed();
                     },
@@ -233,7 +325,12 @@ where
                     if let Some(node) = self.node.as_mut() {
                         match node.open_substream(user_data) {
                             Ok(()) => (),
-                            Err(user_data) => self.handler.inject_outbound_closed(user_data),
+                            Err((user_data, OpenSubstreamError::Closed)) => {
+                                self.handler.inject_outbound_closed(user_data)
+                            },
+                            Err((user_data, error)) => {
+                                self.handler.inject_outbound_failed(user_data, error)
+                            },
                         }
                     } else {
                         self.handler.inject_outbound_closed(user_data);
@@ -258,8 +355,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::task;
+    use futures::{future, task};
     use muxing::StreamMuxer;
+    use runtime::{ManualTimer, Runtime};
+    use std::time::Duration;
     use tokio::runtime::current_thread;
 
     // TODO: move somewhere? this could be useful as a dummy
@@ -340,4 +439,70 @@ mod tests {
 
         current_thread::Runtime::new().unwrap().block_on(handled.for_each(|_| Ok(()))).unwrap();
     }
+
+    #[test]
+    fn outbound_limits_enforced() {
+        // Muxer whose remote never accepts nor refuses our substreams.
+        struct PendingMuxer;
+        impl StreamMuxer for PendingMuxer {
+            type Substream = ();
+            type OutboundSubstream = ();
+            fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+            fn open_outbound(&self) -> Self::OutboundSubstream { () }
+            fn poll_outbound(&self, _: &mut Self::OutboundSubstream) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+            fn destroy_outbound(&self, _: Self::OutboundSubstream) {}
+            fn read_substream(&self, _: &mut Self::Substream, _: &mut [u8]) -> Result<usize, IoError> { panic!() }
+            fn write_substream(&self, _: &mut Self::Substream, _: &[u8]) -> Result<usize, IoError> { panic!() }
+            fn flush_substream(&self, _: &mut Self::Substream) -> Result<(), IoError> { panic!() }
+            fn shutdown_substream(&self, _: &mut Self::Substream) -> Poll<(), IoError> { panic!() }
+            fn destroy_substream(&self, _: Self::Substream) { panic!() }
+            fn close_inbound(&self) {}
+            fn close_outbound(&self) {}
+        }
+
+        struct Handler {
+            requests: usize,
+            failures: Vec<OpenSubstreamError>,
+        }
+        impl<T> NodeHandler<T> for Handler {
+            type InEvent = ();
+            type OutEvent = ();
+            type OutboundOpenInfo = ();
+            fn inject_substream(&mut self, _: T, _: NodeHandlerEndpoint<()>) { panic!() }
+            fn inject_inbound_closed(&mut self) {}
+            fn inject_outbound_closed(&mut self, _: ()) { panic!() }
+            fn inject_outbound_failed(&mut self, _: (), error: OpenSubstreamError) {
+                self.failures.push(error);
+            }
+            fn inject_event(&mut self, _: Self::InEvent) { panic!() }
+            fn shutdown(&mut self) {}
+            fn poll(&mut self) -> Poll<Option<NodeHandlerEvent<(), ()>>, IoError> {
+                if self.requests == 0 {
+                    return Ok(Async::NotReady);
+                }
+                self.requests -= 1;
+                Ok(Async::Ready(Some(NodeHandlerEvent::OutboundSubstreamRequest(()))))
+            }
+        }
+
+        let timer = ManualTimer::new();
+        let tracker = SubstreamTracker::new(Runtime::deterministic(timer.clone()));
+        let handler = Handler { requests: 2, failures: Vec::new() };
+        let mut handled = HandledNode::with_tracker(PendingMuxer, handler, tracker);
+        handled.set_outbound_limits(OutboundLimits::unlimited()
+            .with_max_pending(1)
+            .with_open_timeout(Duration::from_secs(5)));
+
+        future::poll_fn(|| {
+            // The second request exceeds the number of pending attempts.
+            assert!(handled.poll().unwrap().is_not_ready());
+            assert_eq!(handled.handler.failures, vec![OpenSubstreamError::TooManyPending]);
+
+            timer.advance(Duration::from_secs(5));
+            assert!(handled.poll().unwrap().is_not_ready());
+            assert_eq!(handled.handler.failures,
+                       vec![OpenSubstreamError::TooManyPending, OpenSubstreamError::Timeout]);
+            Ok::<_, ()>(Async::Ready(()))
+        }).wait().unwrap();
+    }
 }
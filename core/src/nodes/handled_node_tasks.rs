@@ -21,13 +21,17 @@
 use fnv::FnvHashMap;
 use futures::{prelude::*, stream, sync::mpsc, task};
 use muxing::StreamMuxer;
-use nodes::node::Substream;
-use nodes::handled_node::{HandledNode, NodeHandler};
+use nodes::node::{OutboundLimits, Substream};
+use nodes::handled_node::{HandledNode, HandlerPanicked, NodeHandler};
+use nodes::outbound_scheduler::{OutboundScheduler, SchedulerConfig};
+use nodes::substream_stats::SubstreamTracker;
+use runtime::Runtime;
+use span::Span;
 use smallvec::SmallVec;
 use std::collections::hash_map::{Entry, OccupiedEntry};
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::panic::{self, AssertUnwindSafe};
 use std::{fmt, mem};
-use tokio_executor;
 use void::Void;
 use PeerId;
 
@@ -48,14 +52,20 @@ use PeerId;
 // state of the `HandledNodesTasks` and allowed to process in parallel. This is why there is no
 // "substream closed" event being reported, as it could potentially create confusions and race
 // conditions in the user's code. See similar comments in the documentation of `NodeStream`.
+//
+// Each task catches the panics that happen while polling its node, so that a bug in a protocol
+// handler only closes the faulty connection. The panic is reported as a `TaskClosed` event whose
+// error wraps a `HandlerPanicked`. This also applies to the tasks that the runtime couldn't spawn
+// and that are polled as part of `poll()`, which is the case for deterministic runs.
 
 /// Implementation of `Stream` that handles a collection of nodes.
 // TODO: implement Debug
 pub struct HandledNodesTasks<TInEvent, TOutEvent> {
-    /// For each active task, a sender allowing to transmit messages. Closing the sender interrupts
-    /// the task. It is possible that we receive messages from tasks that used to be in this list
-    /// but no longer are, in which case we should ignore them.
-    tasks: FnvHashMap<TaskId, mpsc::UnboundedSender<TInEvent>>,
+    /// For each active task, a sender allowing to transmit messages and the tracker of the
+    /// substreams of the node. Closing the sender interrupts the task. It is possible that we
+    /// receive messages from tasks that used to be in this list but no longer are, in which case
+    /// we should ignore them.
+    tasks: FnvHashMap<TaskId, (mpsc::UnboundedSender<ExtToInMessage<TInEvent>>, SubstreamTracker)>,
     /// Identifier for the next task to spawn.
     next_task_id: TaskId,
 
@@ -64,6 +74,16 @@ pub struct HandledNodesTasks<TInEvent, TOutEvent> {
     to_spawn: SmallVec<[Box<Future<Item = (), Error = ()> + Send>; 8]>,
     /// Task to notify when an element is added to `to_spawn`.
     to_notify: Option<task::Task>,
+    /// Runtime used to spawn the node tasks.
+    runtime: Runtime,
+    /// Node tasks that the runtime couldn't spawn. They are polled as part of `poll()` instead.
+    local_tasks: stream::FuturesUnordered<Box<Future<Item = (), Error = ()> + Send>>,
+    /// Parent of the spans of the node tasks, if any.
+    span: Option<Span>,
+    /// Configuration of the outbound scheduler of each node, if any.
+    scheduler_config: Option<SchedulerConfig>,
+    /// Limits on the outbound substreams being opened by each node.
+    outbound_limits: OutboundLimits,
 
     /// Sender to emit events to the outside. Meant to be cloned and sent to tasks.
     events_tx: mpsc::UnboundedSender<(InToExtMessage<TOutEvent>, TaskId)>,
@@ -113,10 +133,22 @@ pub enum HandledNodesEvent<TOutEvent> {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(usize);
 
+impl fmt::Display for TaskId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
-    /// Creates a new empty collection.
+    /// Creates a new empty collection that spawns its tasks on the default `Runtime`.
     #[inline]
     pub fn new() -> Self {
+        HandledNodesTasks::with_runtime(Runtime::default())
+    }
+
+    /// Creates a new empty collection that spawns its tasks on the given `Runtime`.
+    pub fn with_runtime(runtime: Runtime) -> Self {
         let (events_tx, events_rx) = mpsc::unbounded();
 
         HandledNodesTasks {
@@ -124,11 +156,43 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
             next_task_id: TaskId(0),
             to_spawn: SmallVec::new(),
             to_notify: None,
+            runtime,
+            local_tasks: stream::FuturesUnordered::new(),
+            span: None,
+            scheduler_config: None,
+            outbound_limits: OutboundLimits::default(),
             events_tx,
             events_rx,
         }
     }
 
+    /// Sets the parent of the spans of the tasks added afterwards. Each task enters a child span
+    /// with a `conn` field, plus a `remote` field once the node has been reached.
+    #[inline]
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+
+    /// Returns the span passed to `set_span`, if any.
+    #[inline]
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    /// Sets the configuration of the `OutboundScheduler` of the nodes added afterwards. If
+    /// `None`, which is the default, the substreams of the nodes write without scheduling.
+    #[inline]
+    pub fn set_scheduler_config(&mut self, config: Option<SchedulerConfig>) {
+        self.scheduler_config = config;
+    }
+
+    /// Sets the limits on the outbound substreams being opened by the nodes added afterwards.
+    /// Defaults to `OutboundLimits::default()`.
+    #[inline]
+    pub fn set_outbound_limits(&mut self, limits: OutboundLimits) {
+        self.outbound_limits = limits;
+    }
+
     /// Adds to the collection a future that tries to reach a node.
     ///
     /// This method spawns a task dedicated to resolving this future and processing the node's
@@ -148,17 +212,30 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
         self.next_task_id.0 += 1;
 
         let (tx, rx) = mpsc::unbounded();
-        self.tasks.insert(task_id, tx);
+        let tracker = match self.scheduler_config {
+            Some(ref config) => {
+                let scheduler = OutboundScheduler::new(config.clone());
+                SubstreamTracker::with_scheduler(self.runtime.clone(), scheduler)
+            },
+            None => SubstreamTracker::new(self.runtime.clone()),
+        };
+        self.tasks.insert(task_id, (tx, tracker.clone()));
+
+        let span = self.span.as_ref().map(|s| s.child()).unwrap_or_else(Span::root)
+            .with_field("conn", task_id.0);
 
         let task = Box::new(NodeTask {
             inner: NodeTaskInner::Future {
                 future,
                 handler,
+                tracker,
+                outbound_limits: self.outbound_limits,
                 events_buffer: Vec::new(),
             },
             events_tx: self.events_tx.clone(),
             in_events_rx: rx.fuse(),
             id: task_id,
+            span,
         });
 
         self.to_spawn.push(task);
@@ -175,11 +252,11 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
     pub fn broadcast_event(&mut self, event: &TInEvent)
     where TInEvent: Clone,
     {
-        for sender in self.tasks.values() {
+        for (sender, _) in self.tasks.values() {
             // Note: it is possible that sending an event fails if the background task has already
             // finished, but the local state hasn't reflected that yet becaues it hasn't been
             // polled. This is not an error situation.
-            let _ = sender.unbounded_send(event.clone());
+            let _ = sender.unbounded_send(ExtToInMessage::HandlerEvent(event.clone()));
         }
     }
 
@@ -194,6 +271,12 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
         }
     }
 
+    /// Returns the tracker of the substreams of a task, or `None` if the task id is invalid.
+    #[inline]
+    pub fn substream_tracker(&self, id: TaskId) -> Option<&SubstreamTracker> {
+        self.tasks.get(&id).map(|(_, tracker)| tracker)
+    }
+
     /// Returns a list of all the active tasks.
     #[inline]
     pub fn tasks<'a>(&'a self) -> impl Iterator<Item = TaskId> + 'a {
@@ -203,7 +286,18 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
     /// Provides an API similar to `Stream`, except that it cannot error.
     pub fn poll(&mut self) -> Async<Option<HandledNodesEvent<TOutEvent>>> {
         for to_spawn in self.to_spawn.drain() {
-            tokio_executor::spawn(to_spawn);
+            if let Err(to_spawn) = self.runtime.spawn(to_spawn) {
+                self.local_tasks.push(to_spawn);
+            }
+        }
+
+        // Drive the tasks that couldn't be spawned. `FuturesUnordered` notifies the current task
+        // when one of them can make progress.
+        loop {
+            match self.local_tasks.poll() {
+                Ok(Async::Ready(Some(()))) | Err(()) => (),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+            }
         }
 
         loop {
@@ -253,7 +347,7 @@ impl<TInEvent, TOutEvent> HandledNodesTasks<TInEvent, TOutEvent> {
 
 /// Access to a task in the collection.
 pub struct Task<'a, TInEvent: 'a> {
-    inner: OccupiedEntry<'a, TaskId, mpsc::UnboundedSender<TInEvent>>,
+    inner: OccupiedEntry<'a, TaskId, (mpsc::UnboundedSender<ExtToInMessage<TInEvent>>, SubstreamTracker)>,
 }
 
 impl<'a, TInEvent> Task<'a, TInEvent> {
@@ -263,7 +357,23 @@ impl<'a, TInEvent> Task<'a, TInEvent> {
         // It is possible that the sender is closed if the background task has already finished
         // but the local state hasn't been updated yet because we haven't been polled in the
         // meanwhile.
-        let _ = self.inner.get_mut().unbounded_send(event);
+        let _ = self.inner.get_mut().0.unbounded_send(ExtToInMessage::HandlerEvent(event));
+    }
+
+    /// Asks the node to shut down gracefully.
+    ///
+    /// Contrary to `close()`, the task keeps being tracked, and a `TaskClosed` event will be
+    /// generated once the node has finished closing. If the task is still trying to reach its
+    /// node, the attempt is aborted.
+    #[inline]
+    pub fn start_shutdown(&mut self) {
+        let _ = self.inner.get_mut().0.unbounded_send(ExtToInMessage::Shutdown);
+    }
+
+    /// Returns the tracker of the substreams of the node.
+    #[inline]
+    pub fn substream_tracker(&self) -> &SubstreamTracker {
+        &self.inner.get().1
     }
 
     /// Returns the task id.
@@ -298,6 +408,15 @@ impl<TInEvent, TOutEvent> Stream for HandledNodesTasks<TInEvent, TOutEvent> {
     }
 }
 
+/// Message to transmit from the public API to a task.
+#[derive(Debug)]
+enum ExtToInMessage<TInEvent> {
+    /// An event to inject into the handler of the node.
+    HandlerEvent(TInEvent),
+    /// The node must start shutting down gracefully.
+    Shutdown,
+}
+
 /// Message to transmit from a task to the public API.
 #[derive(Debug)]
 enum InToExtMessage<TOutEvent> {
@@ -319,11 +438,13 @@ where
     /// Sender to transmit events to the outside.
     events_tx: mpsc::UnboundedSender<(InToExtMessage<TOutEvent>, TaskId)>,
     /// Receiving end for events sent from the main `HandledNodesTasks`.
-    in_events_rx: stream::Fuse<mpsc::UnboundedReceiver<TInEvent>>,
+    in_events_rx: stream::Fuse<mpsc::UnboundedReceiver<ExtToInMessage<TInEvent>>>,
     /// Inner state of the `NodeTask`.
     inner: NodeTaskInner<TFut, TMuxer, THandler, TInEvent>,
     /// Identifier of the attempt.
     id: TaskId,
+    /// Span entered whenever the task is polled.
+    span: Span,
 }
 
 enum NodeTaskInner<TFut, TMuxer, THandler, TInEvent>
@@ -337,6 +458,10 @@ where
         future: TFut,
         /// The handler that will be used to build the `HandledNode`.
         handler: THandler,
+        /// Tracker of the substreams of the `HandledNode`.
+        tracker: SubstreamTracker,
+        /// Limits on the outbound substreams of the `HandledNode`.
+        outbound_limits: OutboundLimits,
         /// While we are dialing the future, we need to buffer the events received on
         /// `in_events_rx` so that they get delivered once dialing succeeds. We can't simply leave
         /// events in `in_events_rx` because we have to detect if it gets closed.
@@ -361,15 +486,50 @@ where
     type Error = ();
 
     fn poll(&mut self) -> Poll<(), ()> {
+        // A panic in the handler or in the muxer must only tear down this node, and not the
+        // executor or the `HandledNodesTasks` that polls the task. The node and its handler are
+        // dropped while unwinding, and `inner` is left as `Poisoned`.
+        match panic::catch_unwind(AssertUnwindSafe(|| self.poll_inner())) {
+            Ok(result) => result,
+            Err(payload) => {
+                let panicked = HandlerPanicked::from_payload(payload);
+                error!("Node task {} panicked: {}", self.id, panicked.message());
+                let error = IoError::new(IoErrorKind::Other, panicked);
+                let event = InToExtMessage::TaskClosed(Err(error));
+                let _ = self.events_tx.unbounded_send((event, self.id));
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+impl<TFut, TMuxer, THandler, TInEvent, TOutEvent> NodeTask<TFut, TMuxer, THandler, TInEvent, TOutEvent>
+where
+    TMuxer: StreamMuxer,
+    TFut: Future<Item = (PeerId, TMuxer), Error = IoError>,
+    THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent>,
+{
+    /// Drives the node. Called by `poll`, which catches the panics.
+    fn poll_inner(&mut self) -> Poll<(), ()> {
+        let _entered = self.span.enter();
         loop {
             match mem::replace(&mut self.inner, NodeTaskInner::Poisoned) {
                 // First possibility: we are still trying to reach a node.
-                NodeTaskInner::Future { mut future, handler, mut events_buffer } => {
+                NodeTaskInner::Future { mut future, handler, tracker, outbound_limits, mut events_buffer } => {
                     // If self.in_events_rx is closed, we stop the task.
                     loop {
                         match self.in_events_rx.poll() {
                             Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
-                            Ok(Async::Ready(Some(event))) => events_buffer.push(event),
+                            Ok(Async::Ready(Some(ExtToInMessage::HandlerEvent(event)))) => {
+                                events_buffer.push(event)
+                            },
+                            Ok(Async::Ready(Some(ExtToInMessage::Shutdown))) => {
+                                let error = IoError::new(IoErrorKind::ConnectionAborted,
+                                                         "shut down before reaching the node");
+                                let event = InToExtMessage::TaskClosed(Err(error));
+                                let _ = self.events_tx.unbounded_send((event, self.id));
+                                return Ok(Async::Ready(()));
+                            },
                             Ok(Async::NotReady) => break,
                             Err(_) => unreachable!("An UnboundedReceiver never errors"),
                         }
@@ -378,8 +538,10 @@ where
                     // Check whether dialing succeeded.
                     match future.poll() {
                         Ok(Async::Ready((peer_id, muxer))) => {
+                            self.span.record("remote", peer_id.to_base58());
                             let event = InToExtMessage::NodeReached(peer_id);
-                            let mut node = HandledNode::new(muxer, handler);
+                            let mut node = HandledNode::with_tracker(muxer, handler, tracker);
+                            node.set_outbound_limits(outbound_limits);
                             for event in events_buffer {
                                 node.inject_event(event);
                             }
@@ -389,7 +551,7 @@ where
                             self.inner = NodeTaskInner::Node(node);
                         }
                         Ok(Async::NotReady) => {
-                            self.inner = NodeTaskInner::Future { future, handler, events_buffer };
+                            self.inner = NodeTaskInner::Future { future, handler, tracker, outbound_limits, events_buffer };
                             return Ok(Async::NotReady);
                         },
                         Err(err) => {
@@ -408,9 +570,14 @@ where
                         loop {
                             match self.in_events_rx.poll() {
                                 Ok(Async::NotReady) => break,
-                                Ok(Async::Ready(Some(event))) => {
+                                Ok(Async::Ready(Some(ExtToInMessage::HandlerEvent(event)))) => {
                                     node.inject_event(event);
                                 },
+                                Ok(Async::Ready(Some(ExtToInMessage::Shutdown))) => {
+                                    // Graceful shutdown requested by the external API ; we keep
+                                    // processing the node until it has finished closing.
+                                    node.shutdown();
+                                },
                                 Ok(Async::Ready(None)) => {
                                     // Node closed by the external API ; start shutdown process.
                                     node.shutdown();
@@ -455,3 +622,71 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use nodes::handled_node::{NodeHandlerEndpoint, NodeHandlerEvent};
+    use runtime::{ManualTimer, Runtime};
+    use tokio::runtime::current_thread;
+    use PublicKey;
+
+    struct PendingMuxer;
+    impl StreamMuxer for PendingMuxer {
+        type Substream = ();
+        type OutboundSubstream = ();
+        fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+        fn open_outbound(&self) -> Self::OutboundSubstream { () }
+        fn poll_outbound(&self, _: &mut Self::OutboundSubstream) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+        fn destroy_outbound(&self, _: Self::OutboundSubstream) {}
+        fn read_substream(&self, _: &mut Self::Substream, _: &mut [u8]) -> Result<usize, IoError> { panic!() }
+        fn write_substream(&self, _: &mut Self::Substream, _: &[u8]) -> Result<usize, IoError> { panic!() }
+        fn flush_substream(&self, _: &mut Self::Substream) -> Result<(), IoError> { panic!() }
+        fn shutdown_substream(&self, _: &mut Self::Substream) -> Poll<(), IoError> { panic!() }
+        fn destroy_substream(&self, _: Self::Substream) { panic!() }
+        fn close_inbound(&self) {}
+        fn close_outbound(&self) {}
+    }
+
+    struct PanickingHandler;
+    impl<T> NodeHandler<T> for PanickingHandler {
+        type InEvent = ();
+        type OutEvent = ();
+        type OutboundOpenInfo = ();
+        fn inject_substream(&mut self, _: T, _: NodeHandlerEndpoint<()>) {}
+        fn inject_inbound_closed(&mut self) {}
+        fn inject_outbound_closed(&mut self, _: ()) {}
+        fn inject_event(&mut self, _: ()) {}
+        fn shutdown(&mut self) {}
+        fn poll(&mut self) -> Poll<Option<NodeHandlerEvent<(), ()>>, IoError> {
+            panic!("handler bug")
+        }
+    }
+
+    #[test]
+    fn panicking_handler_only_closes_its_task() {
+        let runtime = Runtime::deterministic(ManualTimer::new());
+        let mut tasks = HandledNodesTasks::<(), ()>::with_runtime(runtime);
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let id = tasks.add_reach_attempt(future::ok::<_, IoError>((peer_id, PendingMuxer)), PanickingHandler);
+
+        let closed = future::poll_fn(move || -> Poll<_, ()> {
+            loop {
+                match tasks.poll() {
+                    Async::Ready(Some(HandledNodesEvent::TaskClosed { id, result })) => {
+                        return Ok(Async::Ready((id, result)));
+                    },
+                    Async::Ready(Some(_)) => (),
+                    Async::Ready(None) | Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+        });
+
+        let (closed_id, result) = current_thread::Runtime::new().unwrap().block_on(closed).unwrap();
+        assert_eq!(closed_id, id);
+        let error = result.unwrap_err();
+        let panicked = HandlerPanicked::from_io_error(&error).unwrap();
+        assert_eq!(panicked.message(), "handler bug");
+    }
+}
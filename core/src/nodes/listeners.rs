@@ -19,12 +19,34 @@
 // DEALINGS IN THE SOFTWARE.
 
 use futures::{prelude::*, task};
+#[cfg(not(target_arch = "wasm32"))]
+use get_if_addrs;
+use multiaddr::Protocol;
+use runtime::{Delay, Runtime};
+use std::collections::VecDeque;
 use std::fmt;
+use std::io::Error as IoError;
+use std::iter;
+use std::net::IpAddr;
+use std::time::Duration;
 use void::Void;
 use {Multiaddr, Transport};
 
+/// Interval between two checks of the network interfaces, if we listen on an unspecified IP
+/// address.
+const INTERFACES_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Implementation of `Stream` that handles listeners.
 ///
+/// Listeners can be added and removed at any time. Each of them is identified by a
+/// `ListenerId`, and the stream reports when a listen address becomes active or expires.
+///
+/// A listener whose address starts with an unspecified IP address (`0.0.0.0` or `::`) accepts
+/// connections on all the network interfaces, but its address can't be dialed by remotes.
+/// Instead of this address, the stream reports the address of each network interface of the
+/// same IP version as a distinct listen address, and periodically checks the interfaces in
+/// order to report the addresses that appear or disappear.
+///
 /// The stream cannot produce errors.
 pub struct ListenersStream<TTrans>
 where
@@ -34,19 +56,48 @@ where
     transport: TTrans,
     /// All the active listeners.
     listeners: Vec<Listener<TTrans>>,
+    /// Id to assign to the next listener.
+    next_id: ListenerId,
+    /// Events that have been generated but not returned yet.
+    pending_events: VecDeque<ListenersEvent<TTrans>>,
+    /// Timer for the next check of the network interfaces. `None` if no listener listens on an
+    /// unspecified IP address.
+    interfaces_refresh: Option<Delay>,
+    /// Runtime providing the timer for `interfaces_refresh`.
+    runtime: Runtime,
     /// Task to notify when we add a new listener to `listeners`, so that we start polling.
     to_notify: Option<task::Task>,
 }
 
+/// Identifier of a listener of a `ListenersStream`.
+///
+/// Ids are never reused, even after the listener has been removed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ListenerId(u64);
+
+impl fmt::Display for ListenerId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// A single active listener.
 struct Listener<TTrans>
 where
     TTrans: Transport,
 {
+    /// Identifier of the listener.
+    id: ListenerId,
     /// The object that actually listens.
     listener: TTrans::Listener,
     /// Address it is listening on.
     address: Multiaddr,
+    /// True if `address` starts with an unspecified IP address.
+    unspecified: bool,
+    /// Addresses that have been reported for this listener. Equal to `address` unless
+    /// `unspecified` is true, in which case they are the addresses of the network interfaces.
+    reported: Vec<Multiaddr>,
 }
 
 /// Event that can happen on the `ListenersStream`.
@@ -54,8 +105,27 @@ pub enum ListenersEvent<TTrans>
 where
     TTrans: Transport,
 {
+    /// A listener has started listening on an address.
+    NewAddress {
+        /// Id of the listener.
+        listener_id: ListenerId,
+        /// The address the listener is listening on.
+        listen_addr: Multiaddr,
+    },
+
+    /// A listener is no longer listening on an address, because it has closed or it has been
+    /// removed.
+    AddressExpired {
+        /// Id of the listener.
+        listener_id: ListenerId,
+        /// The address the listener was listening on.
+        listen_addr: Multiaddr,
+    },
+
     /// A connection is incoming on one of the listeners.
     Incoming {
+        /// Id of the listener which received the connection.
+        listener_id: ListenerId,
         /// The produced upgrade.
         upgrade: TTrans::ListenerUpgrade,
         /// Address of the listener which received the connection.
@@ -64,8 +134,11 @@ where
         send_back_addr: Multiaddr,
     },
 
-    /// A listener has closed, either gracefully or with an error.
+    /// A listener has closed, either gracefully or with an error, or it has been removed with
+    /// `remove_listener`. Always preceded with an `AddressExpired` event.
     Closed {
+        /// Id of the listener which closed.
+        listener_id: ListenerId,
         /// Address of the listener which closed.
         listen_addr: Multiaddr,
         /// The listener that closed.
@@ -85,6 +158,10 @@ where
         ListenersStream {
             transport,
             listeners: Vec::new(),
+            next_id: ListenerId(0),
+            pending_events: VecDeque::new(),
+            interfaces_refresh: None,
+            runtime: Runtime::default(),
             to_notify: None,
         }
     }
@@ -96,14 +173,41 @@ where
         ListenersStream {
             transport,
             listeners: Vec::with_capacity(capacity),
+            next_id: ListenerId(0),
+            pending_events: VecDeque::new(),
+            interfaces_refresh: None,
+            runtime: Runtime::default(),
             to_notify: None,
         }
     }
 
+    /// Same as `new`, but uses the timer of the given `Runtime`.
+    #[inline]
+    pub fn with_runtime(transport: TTrans, runtime: Runtime) -> Self {
+        ListenersStream {
+            runtime,
+            ..ListenersStream::new(transport)
+        }
+    }
+
     /// Start listening on a multiaddress.
     ///
     /// Returns an error if the transport doesn't support the given multiaddress.
+    #[inline]
     pub fn listen_on(&mut self, addr: Multiaddr) -> Result<Multiaddr, Multiaddr>
+    where
+        TTrans: Clone,
+    {
+        self.add_listener(addr).map(|(_, addr)| addr)
+    }
+
+    /// Start listening on a multiaddress. Returns the id of the new listener and the address it
+    /// is actually listening on, which can differ from `addr`, for example if `addr` contains
+    /// the port `0`. A `NewAddress` event is produced, or one for each network interface if the
+    /// address contains an unspecified IP address.
+    ///
+    /// Returns an error if the transport doesn't support the given multiaddress.
+    pub fn add_listener(&mut self, addr: Multiaddr) -> Result<(ListenerId, Multiaddr), Multiaddr>
     where
         TTrans: Clone,
     {
@@ -113,16 +217,61 @@ where
             .listen_on(addr)
             .map_err(|(_, addr)| addr)?;
 
+        let id = self.next_id;
+        self.next_id = ListenerId(id.0 + 1);
+
+        let (unspecified, reported) = match interface_addrs(&new_addr) {
+            None => (false, vec![new_addr.clone()]),
+            Some(Ok(addrs)) => (true, addrs),
+            Some(Err(err)) => {
+                debug!("Failed to list the network interfaces: {:?}", err);
+                (true, Vec::new())
+            },
+        };
+
+        for listen_addr in reported.iter().cloned() {
+            self.pending_events.push_back(ListenersEvent::NewAddress { listener_id: id, listen_addr });
+        }
+        if unspecified && self.interfaces_refresh.is_none() {
+            self.interfaces_refresh = Some(self.runtime.delay_for(INTERFACES_REFRESH_INTERVAL));
+        }
+
         self.listeners.push(Listener {
+            id,
             listener,
             address: new_addr.clone(),
+            unspecified,
+            reported,
         });
 
         if let Some(task) = self.to_notify.take() {
             task.notify();
         }
 
-        Ok(new_addr)
+        Ok((id, new_addr))
+    }
+
+    /// Stops the given listener and returns the address it was listening on, or `None` if there
+    /// is no such listener. An `AddressExpired` and a `Closed` event are produced.
+    pub fn remove_listener(&mut self, id: ListenerId) -> Option<Multiaddr> {
+        let pos = self.listeners.iter().position(|l| l.id == id)?;
+        let listener = self.listeners.remove(pos);
+
+        for listen_addr in listener.reported {
+            self.pending_events.push_back(ListenersEvent::AddressExpired { listener_id: id, listen_addr });
+        }
+        self.pending_events.push_back(ListenersEvent::Closed {
+            listener_id: id,
+            listen_addr: listener.address.clone(),
+            listener: listener.listener,
+            result: Ok(()),
+        });
+
+        if let Some(task) = self.to_notify.take() {
+            task.notify();
+        }
+
+        Some(listener.address)
     }
 
     /// Returns the transport passed when building this object.
@@ -131,14 +280,106 @@ where
         &self.transport
     }
 
+    /// Returns the runtime passed when building this object.
+    #[inline]
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
     /// Returns an iterator that produces the list of addresses we're listening on.
+    ///
+    /// The listeners on an unspecified IP address produce the addresses of the network
+    /// interfaces instead.
     #[inline]
     pub fn listeners(&self) -> impl Iterator<Item = &Multiaddr> {
-        self.listeners.iter().map(|l| &l.address)
+        self.listeners.iter().flat_map(|l| l.reported.iter())
+    }
+
+    /// Returns an iterator that produces the id of each listener and the addresses it is
+    /// listening on, similar to `listeners`.
+    #[inline]
+    pub fn listeners_with_id(&self) -> impl Iterator<Item = (ListenerId, &Multiaddr)> {
+        self.listeners
+            .iter()
+            .flat_map(|l| l.reported.iter().map(move |addr| (l.id, addr)))
+    }
+
+    /// Returns the address the given listener is bound to, or `None` if there is no such
+    /// listener. Contrary to `listeners`, the unspecified IP addresses are not replaced.
+    #[inline]
+    pub fn listen_addr(&self, id: ListenerId) -> Option<&Multiaddr> {
+        self.listeners.iter().find(|l| l.id == id).map(|l| &l.address)
+    }
+
+    /// Stops all the listeners and returns their ids and the addresses they were listening on.
+    ///
+    /// No event is generated for the listeners that are closed this way, and the `NewAddress`
+    /// events that haven't been returned yet are discarded.
+    pub fn close_all(&mut self) -> Vec<(ListenerId, Multiaddr)> {
+        self.pending_events.retain(|event| match event {
+            ListenersEvent::NewAddress { .. } => false,
+            _ => true,
+        });
+        self.interfaces_refresh = None;
+        self.listeners.drain(..).map(|l| (l.id, l.address)).collect()
+    }
+
+    /// Lists the network interfaces and produces events for the addresses of the listeners on
+    /// an unspecified IP address that have appeared or disappeared.
+    fn refresh_interfaces(&mut self) {
+        for listener in self.listeners.iter_mut().filter(|l| l.unspecified) {
+            let addrs = match interface_addrs(&listener.address) {
+                Some(Ok(addrs)) => addrs,
+                Some(Err(err)) => {
+                    debug!("Failed to list the network interfaces: {:?}", err);
+                    continue;
+                },
+                None => continue,
+            };
+
+            for listen_addr in listener.reported.iter().filter(|a| !addrs.contains(a)) {
+                debug!("Listen address {} has expired", listen_addr);
+                self.pending_events.push_back(ListenersEvent::AddressExpired {
+                    listener_id: listener.id,
+                    listen_addr: listen_addr.clone(),
+                });
+            }
+            for listen_addr in addrs.iter().filter(|a| !listener.reported.contains(a)) {
+                debug!("New listen address {}", listen_addr);
+                self.pending_events.push_back(ListenersEvent::NewAddress {
+                    listener_id: listener.id,
+                    listen_addr: listen_addr.clone(),
+                });
+            }
+            listener.reported = addrs;
+        }
     }
 
     /// Provides an API similar to `Stream`, except that it cannot error.
     pub fn poll(&mut self) -> Async<Option<ListenersEvent<TTrans>>> {
+        while let Some(mut refresh) = self.interfaces_refresh.take() {
+            match refresh.poll() {
+                Ok(Async::NotReady) => {
+                    self.interfaces_refresh = Some(refresh);
+                    break;
+                },
+                Ok(Async::Ready(())) => {
+                    self.refresh_interfaces();
+                    if self.listeners.iter().any(|l| l.unspecified) {
+                        // The new delay is polled at the next iteration, so that we get notified.
+                        self.interfaces_refresh = Some(self.runtime.delay_for(INTERFACES_REFRESH_INTERVAL));
+                    }
+                },
+                Err(err) => {
+                    debug!("Error in the network interfaces timer: {:?}", err);
+                },
+            }
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Async::Ready(Some(event));
+        }
+
         // We remove each element from `listeners` one by one and add them back.
         for n in (0..self.listeners.len()).rev() {
             let mut listener = self.listeners.swap_remove(n);
@@ -147,27 +388,21 @@ where
                     self.listeners.push(listener);
                 }
                 Ok(Async::Ready(Some((upgrade, send_back_addr)))) => {
+                    let listener_id = listener.id;
                     let listen_addr = listener.address.clone();
                     self.listeners.push(listener);
                     return Async::Ready(Some(ListenersEvent::Incoming {
+                        listener_id,
                         upgrade,
                         listen_addr,
                         send_back_addr,
                     }));
                 }
                 Ok(Async::Ready(None)) => {
-                    return Async::Ready(Some(ListenersEvent::Closed {
-                        listen_addr: listener.address,
-                        listener: listener.listener,
-                        result: Ok(()),
-                    }));
+                    return self.listener_closed(listener, Ok(()));
                 }
                 Err(err) => {
-                    return Async::Ready(Some(ListenersEvent::Closed {
-                        listen_addr: listener.address,
-                        listener: listener.listener,
-                        result: Err(err),
-                    }));
+                    return self.listener_closed(listener, Err(err));
                 }
             }
         }
@@ -176,6 +411,72 @@ where
         self.to_notify = Some(task::current());
         Async::NotReady
     }
+
+    /// Queues the `Closed` event of a listener that has closed by itself, and returns the
+    /// `AddressExpired` event that precedes it.
+    fn listener_closed(
+        &mut self,
+        listener: Listener<TTrans>,
+        result: Result<(), <TTrans::Listener as Stream>::Error>,
+    ) -> Async<Option<ListenersEvent<TTrans>>> {
+        for listen_addr in listener.reported {
+            self.pending_events.push_back(ListenersEvent::AddressExpired {
+                listener_id: listener.id,
+                listen_addr,
+            });
+        }
+        self.pending_events.push_back(ListenersEvent::Closed {
+            listener_id: listener.id,
+            listen_addr: listener.address,
+            listener: listener.listener,
+            result,
+        });
+        let event = self.pending_events.pop_front().expect("we just pushed an element");
+        Async::Ready(Some(event))
+    }
+}
+
+/// If `addr` starts with an unspecified IP address, returns `addr` with this IP address
+/// replaced with the address of each network interface of the same IP version. Returns `None`
+/// if `addr` doesn't start with an unspecified IP address.
+#[cfg(not(target_arch = "wasm32"))]
+fn interface_addrs(addr: &Multiaddr) -> Option<Result<Vec<Multiaddr>, IoError>> {
+    let ipv4 = match addr.iter().next()? {
+        Protocol::Ip4(ip) => if ip.is_unspecified() { true } else { return None },
+        Protocol::Ip6(ip) => if ip.is_unspecified() { false } else { return None },
+        _ => return None,
+    };
+
+    let interfaces = match get_if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => return Some(Err(err)),
+    };
+
+    let addrs = interfaces
+        .into_iter()
+        .filter_map(|interface| match interface.ip() {
+            IpAddr::V4(ip) if ipv4 => Some(Protocol::Ip4(ip)),
+            // Link-local addresses can't be dialed without knowing the interface.
+            IpAddr::V6(ip) if !ipv4 && (ip.segments()[0] & 0xffc0) != 0xfe80 => {
+                Some(Protocol::Ip6(ip))
+            },
+            _ => None,
+        })
+        .map(|ip| iter::once(ip).chain(addr.iter().skip(1)).collect::<Multiaddr>())
+        .fold(Vec::new(), |mut addrs, addr| {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+            addrs
+        });
+
+    Some(Ok(addrs))
+}
+
+/// Network interfaces can't be listed on WASM, where we can't listen anyway.
+#[cfg(target_arch = "wasm32")]
+fn interface_addrs(_: &Multiaddr) -> Option<Result<Vec<Multiaddr>, IoError>> {
+    None
 }
 
 impl<TTrans> Stream for ListenersStream<TTrans>
@@ -210,18 +511,39 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
+            ListenersEvent::NewAddress {
+                ref listener_id,
+                ref listen_addr,
+            } => f
+                .debug_struct("ListenersEvent::NewAddress")
+                .field("listener_id", listener_id)
+                .field("listen_addr", listen_addr)
+                .finish(),
+            ListenersEvent::AddressExpired {
+                ref listener_id,
+                ref listen_addr,
+            } => f
+                .debug_struct("ListenersEvent::AddressExpired")
+                .field("listener_id", listener_id)
+                .field("listen_addr", listen_addr)
+                .finish(),
             ListenersEvent::Incoming {
-                ref listen_addr, ..
+                ref listener_id,
+                ref listen_addr,
+                ..
             } => f
                 .debug_struct("ListenersEvent::Incoming")
+                .field("listener_id", listener_id)
                 .field("listen_addr", listen_addr)
                 .finish(),
             ListenersEvent::Closed {
+                ref listener_id,
                 ref listen_addr,
                 ref result,
                 ..
             } => f
                 .debug_struct("ListenersEvent::Closed")
+                .field("listener_id", listener_id)
                 .field("listen_addr", listen_addr)
                 .field("result", result)
                 .finish(),
@@ -248,9 +570,19 @@ mod tests {
         let future = listeners
             .into_future()
             .map_err(|(err, _)| err)
+            .and_then(|(event, listeners)| {
+                match event {
+                    Some(ListenersEvent::NewAddress { listen_addr, .. }) => {
+                        assert_eq!(listen_addr, "/memory".parse().unwrap());
+                    },
+                    _ => panic!()
+                }
+                listeners.into_future().map_err(|(err, _)| err)
+            })
             .and_then(|(event, _)| {
                 match event {
-                    Some(ListenersEvent::Incoming { listen_addr, upgrade, send_back_addr }) => {
+                    Some(ListenersEvent::Incoming { listener_id, listen_addr, upgrade, send_back_addr }) => {
+                        assert_eq!(listener_id, ListenerId(0));
                         assert_eq!(listen_addr, "/memory".parse().unwrap());
                         assert_eq!(send_back_addr, "/memory".parse().unwrap());
                         upgrade.map(|_| ()).map_err(|_| panic!())
@@ -264,4 +596,59 @@ mod tests {
         let mut runtime = Runtime::new().unwrap();
         runtime.block_on(future).unwrap();
     }
+
+    #[test]
+    fn close_all_listeners() {
+        let (_tx, rx) = transport::connector();
+
+        let mut listeners = ListenersStream::new(rx);
+        listeners.listen_on("/memory".parse().unwrap()).unwrap();
+        assert_eq!(listeners.listeners().count(), 1);
+
+        let closed = listeners.close_all();
+        assert_eq!(closed, vec![(ListenerId(0), "/memory".parse().unwrap())]);
+        assert_eq!(listeners.listeners().count(), 0);
+    }
+
+    #[test]
+    fn unspecified_address_replaced_with_interfaces() {
+        let mut listeners = ListenersStream::new(libp2p_tcp_transport::TcpConfig::new());
+        let (id, bound) = listeners.add_listener("/ip4/0.0.0.0/tcp/0".parse().unwrap()).unwrap();
+        assert!(bound.to_string().starts_with("/ip4/0.0.0.0/tcp/"));
+        assert_eq!(listeners.listen_addr(id), Some(&bound));
+
+        let port = bound.to_string().rsplit('/').next().unwrap().to_owned();
+        let loopback: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+        let reported = listeners.listeners().cloned().collect::<Vec<_>>();
+        assert!(reported.contains(&loopback));
+        assert!(!reported.contains(&bound));
+        assert!(reported.iter().all(|a| a.to_string().starts_with("/ip4/")));
+    }
+
+    #[test]
+    fn remove_listener() {
+        let (_tx, rx) = transport::connector();
+
+        let mut listeners = ListenersStream::new(rx);
+        let (id1, _) = listeners.add_listener("/memory".parse().unwrap()).unwrap();
+        let (id2, _) = listeners.add_listener("/memory".parse().unwrap()).unwrap();
+        assert_ne!(id1, id2);
+
+        assert_eq!(listeners.remove_listener(id1), Some("/memory".parse().unwrap()));
+        assert_eq!(listeners.remove_listener(id1), None);
+        assert_eq!(listeners.listeners_with_id().map(|(id, _)| id).collect::<Vec<_>>(), vec![id2]);
+
+        let events = listeners.take(4).collect().wait().unwrap();
+        match (&events[0], &events[1], &events[2], &events[3]) {
+            (
+                ListenersEvent::NewAddress { listener_id: a, .. },
+                ListenersEvent::NewAddress { listener_id: b, .. },
+                ListenersEvent::AddressExpired { listener_id: c, .. },
+                ListenersEvent::Closed { listener_id: d, result: Ok(()), .. },
+            ) => {
+                assert_eq!((*a, *b, *c, *d), (id1, id2, id1, id1));
+            },
+            _ => panic!(),
+        }
+    }
 }
@@ -20,8 +20,21 @@
 
 mod handled_node_tasks;
 
+pub mod address_filter;
+pub mod address_resolver;
+pub mod address_translation;
+pub mod behaviour;
 pub mod collection;
+pub mod connection_handler;
+pub mod dial_attempt;
+pub mod dial_backoff;
+pub mod dynamic_protocols;
+pub mod external_addrs;
 pub mod handled_node;
 pub mod listeners;
 pub mod node;
+pub mod outbound_scheduler;
+pub mod protocol_filter;
+pub mod raw_swarm;
+pub mod substream_stats;
 pub mod swarm;
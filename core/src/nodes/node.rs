@@ -20,10 +20,14 @@
 
 use futures::{prelude::*, task};
 use muxing;
+use nodes::substream_stats::{SubstreamTracker, TrackedSubstream};
+use runtime::{Delay, Runtime};
 use smallvec::SmallVec;
-use std::fmt;
+use std::{error, fmt};
 use std::io::Error as IoError;
 use std::sync::Arc;
+use std::time::Duration;
+use upgrade::Endpoint;
 
 // Implementor notes
 // =================
@@ -59,15 +63,20 @@ where
     inbound_finished: bool,
     /// If true, the outbound side of the muxer has closed earlier.
     outbound_finished: bool,
-    /// List of substreams we are currently opening.
-    outbound_substreams: SmallVec<[(TUserData, TMuxer::OutboundSubstream); 8]>,
+    /// List of substreams we are currently opening, with the moment when the attempt times out.
+    outbound_substreams: SmallVec<[(TUserData, TMuxer::OutboundSubstream, Option<Delay>); 8]>,
+    /// Limits on the substreams we are opening.
+    outbound_limits: OutboundLimits,
     /// Task to notify when a new element is added to `outbound_substreams`, so that we can start
     /// polling it.
     to_notify: Option<task::Task>,
+    /// Keeps track of the substreams that have been produced.
+    tracker: SubstreamTracker,
 }
 
-/// A successfully opened substream.
-pub type Substream<TMuxer> = muxing::SubstreamRef<Arc<TMuxer>>;
+/// A successfully opened substream. It is removed from the `SubstreamTracker` of the node once
+/// dropped.
+pub type Substream<TMuxer> = TrackedSubstream<muxing::SubstreamRef<Arc<TMuxer>>>;
 
 /// Event that can happen on the `NodeStream`.
 pub enum NodeEvent<TMuxer, TUserData>
@@ -95,6 +104,15 @@ where
         user_data: TUserData,
     },
 
+    /// An outbound substream couldn't be opened because the remote didn't accept it before the
+    /// timeout of the `OutboundLimits`. The error is always `OpenSubstreamError::Timeout`.
+    OutboundFailed {
+        /// User data that has been passed to the `open_substream` method.
+        user_data: TUserData,
+        /// Why the substream couldn't be opened.
+        error: OpenSubstreamError,
+    },
+
     /// The inbound side of the muxer has been closed. No more inbound substreams will be produced.
     InboundClosed,
 }
@@ -103,6 +121,91 @@ where
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct OutboundSubstreamId(usize);
 
+/// Limits on the outbound substreams that a `NodeStream` is opening.
+///
+/// Opening a substream requires the cooperation of the remote. Without limits, a remote that
+/// never accepts nor refuses our substreams makes the attempts pile up for as long as the
+/// connection is alive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutboundLimits {
+    /// Time after which an attempt that is still pending fails.
+    open_timeout: Option<Duration>,
+    /// Maximum number of attempts pending at the same time.
+    max_pending: Option<usize>,
+}
+
+impl OutboundLimits {
+    /// Builds limits that don't restrict anything.
+    #[inline]
+    pub fn unlimited() -> OutboundLimits {
+        OutboundLimits {
+            open_timeout: None,
+            max_pending: None,
+        }
+    }
+
+    /// Makes the attempts that are still pending after `timeout` fail with
+    /// `OpenSubstreamError::Timeout`.
+    #[inline]
+    pub fn with_open_timeout(mut self, timeout: Duration) -> Self {
+        self.open_timeout = Some(timeout);
+        self
+    }
+
+    /// Makes the attempts started while `max` attempts are already pending fail with
+    /// `OpenSubstreamError::TooManyPending`.
+    #[inline]
+    pub fn with_max_pending(mut self, max: usize) -> Self {
+        self.max_pending = Some(max);
+        self
+    }
+
+    /// Returns the time after which a pending attempt fails, if any.
+    #[inline]
+    pub fn open_timeout(&self) -> Option<Duration> {
+        self.open_timeout
+    }
+
+    /// Returns the maximum number of attempts pending at the same time, if any.
+    #[inline]
+    pub fn max_pending(&self) -> Option<usize> {
+        self.max_pending
+    }
+}
+
+impl Default for OutboundLimits {
+    /// Attempts time out after 10 seconds, and at most 128 of them can be pending.
+    #[inline]
+    fn default() -> OutboundLimits {
+        OutboundLimits::unlimited()
+            .with_open_timeout(Duration::from_secs(10))
+            .with_max_pending(128)
+    }
+}
+
+/// Reason why an outbound substream couldn't be opened.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpenSubstreamError {
+    /// The outbound side of the muxer is closed.
+    Closed,
+    /// The maximum number of pending attempts of the `OutboundLimits` has been reached.
+    TooManyPending,
+    /// The remote didn't accept the substream before the timeout of the `OutboundLimits`.
+    Timeout,
+}
+
+impl fmt::Display for OpenSubstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenSubstreamError::Closed => write!(f, "outbound part of the muxer is closed"),
+            OpenSubstreamError::TooManyPending => write!(f, "too many outbound substreams pending"),
+            OpenSubstreamError::Timeout => write!(f, "timeout while opening an outbound substream"),
+        }
+    }
+}
+
+impl error::Error for OpenSubstreamError {}
+
 impl<TMuxer, TUserData> NodeStream<TMuxer, TUserData>
 where
     TMuxer: muxing::StreamMuxer,
@@ -110,29 +213,72 @@ where
     /// Creates a new node events stream.
     #[inline]
     pub fn new(muxer: TMuxer) -> Self {
+        NodeStream::with_tracker(muxer, SubstreamTracker::new(Runtime::default()))
+    }
+
+    /// Same as `new`, but the substreams are accounted in the given `SubstreamTracker`.
+    #[inline]
+    pub fn with_tracker(muxer: TMuxer, tracker: SubstreamTracker) -> Self {
         NodeStream {
             muxer: Arc::new(muxer),
             inbound_finished: false,
             outbound_finished: false,
             outbound_substreams: SmallVec::new(),
+            outbound_limits: OutboundLimits::default(),
             to_notify: None,
+            tracker,
         }
     }
 
+    /// Returns the tracker of the substreams produced by this node.
+    #[inline]
+    pub fn tracker(&self) -> &SubstreamTracker {
+        &self.tracker
+    }
+
+    /// Returns the limits on the outbound substreams being opened.
+    #[inline]
+    pub fn outbound_limits(&self) -> &OutboundLimits {
+        &self.outbound_limits
+    }
+
+    /// Sets the limits on the outbound substreams being opened. The timeout only applies to the
+    /// attempts started afterwards.
+    #[inline]
+    pub fn set_outbound_limits(&mut self, limits: OutboundLimits) {
+        self.outbound_limits = limits;
+    }
+
+    /// Returns the number of outbound substreams being opened.
+    #[inline]
+    pub fn num_pending_outbound(&self) -> usize {
+        self.outbound_substreams.len()
+    }
+
     /// Starts the process of opening a new outbound substream.
     ///
-    /// Returns an error if the outbound side of the muxer is closed.
+    /// Returns an error, along with the user data, if the outbound side of the muxer is closed or
+    /// if the maximum number of pending attempts has been reached.
     ///
     /// After calling this method, polling the stream should eventually produce either an
-    /// `OutboundSubstream` event or an `OutboundClosed` event containing the user data that has
-    /// been passed to this method.
-    pub fn open_substream(&mut self, user_data: TUserData) -> Result<(), TUserData> {
+    /// `OutboundSubstream` event, or an `OutboundClosed` or `OutboundFailed` event containing the
+    /// user data that has been passed to this method.
+    pub fn open_substream(&mut self, user_data: TUserData) -> Result<(), (TUserData, OpenSubstreamError)> {
         if self.outbound_finished {
-            return Err(user_data);
+            return Err((user_data, OpenSubstreamError::Closed));
+        }
+
+        if let Some(max) = self.outbound_limits.max_pending {
+            if self.outbound_substreams.len() >= max {
+                debug!("Refused outbound substream ; {} attempts already pending", max);
+                return Err((user_data, OpenSubstreamError::TooManyPending));
+            }
         }
 
+        let timeout = self.outbound_limits.open_timeout
+            .map(|timeout| self.tracker.runtime().delay_for(timeout));
         let raw = self.muxer.open_outbound();
-        self.outbound_substreams.push((user_data, raw));
+        self.outbound_substreams.push((user_data, raw, timeout));
 
         if let Some(task) = self.to_notify.take() {
             task.notify();
@@ -159,12 +305,17 @@ where
     }
 
     /// Destroys the node stream and returns all the pending outbound substreams.
+    ///
+    /// If the muxer supports it, the remote is notified that we no longer accept or open
+    /// substreams.
     pub fn close(mut self) -> Vec<TUserData> {
         let mut out = Vec::with_capacity(self.outbound_substreams.len());
-        for (user_data, outbound) in self.outbound_substreams.drain() {
+        for (user_data, outbound, _) in self.outbound_substreams.drain() {
             out.push(user_data);
             self.muxer.destroy_outbound(outbound);
         }
+        self.muxer.close_inbound();
+        self.muxer.close_outbound();
         out
     }
 }
@@ -182,6 +333,7 @@ where
             match self.muxer.poll_inbound() {
                 Ok(Async::Ready(Some(substream))) => {
                     let substream = muxing::substream_from_ref(self.muxer.clone(), substream);
+                    let substream = self.tracker.track(substream, Endpoint::Listener);
                     return Ok(Async::Ready(Some(NodeEvent::InboundSubstream {
                         substream,
                     })));
@@ -198,10 +350,11 @@ where
         // Polling outbound substreams.
         // We remove each element from `outbound_substreams` one by one and add them back.
         for n in (0..self.outbound_substreams.len()).rev() {
-            let (user_data, mut outbound) = self.outbound_substreams.swap_remove(n);
+            let (user_data, mut outbound, mut timeout) = self.outbound_substreams.swap_remove(n);
             match self.muxer.poll_outbound(&mut outbound) {
                 Ok(Async::Ready(Some(substream))) => {
                     let substream = muxing::substream_from_ref(self.muxer.clone(), substream);
+                    let substream = self.tracker.track(substream, Endpoint::Dialer);
                     self.muxer.destroy_outbound(outbound);
                     return Ok(Async::Ready(Some(NodeEvent::OutboundSubstream {
                         user_data,
@@ -214,7 +367,28 @@ where
                     return Ok(Async::Ready(Some(NodeEvent::OutboundClosed { user_data })));
                 }
                 Ok(Async::NotReady) => {
-                    self.outbound_substreams.push((user_data, outbound));
+                    let expired = match timeout {
+                        Some(ref mut timeout) => match timeout.poll() {
+                            Ok(Async::Ready(())) => true,
+                            Ok(Async::NotReady) => false,
+                            Err(err) => {
+                                self.muxer.destroy_outbound(outbound);
+                                return Err(err);
+                            }
+                        },
+                        None => false,
+                    };
+
+                    if expired {
+                        debug!("Timeout while opening outbound substream");
+                        self.muxer.destroy_outbound(outbound);
+                        return Ok(Async::Ready(Some(NodeEvent::OutboundFailed {
+                            user_data,
+                            error: OpenSubstreamError::Timeout,
+                        })));
+                    }
+
+                    self.outbound_substreams.push((user_data, outbound, timeout));
                 }
                 Err(err) => {
                     self.muxer.destroy_outbound(outbound);
@@ -255,7 +429,7 @@ where
         // The substreams that were produced will continue to work, as the muxer is held in an Arc.
         // However we will no longer process any further inbound or outbound substream, and we
         // therefore close everything.
-        for (_, outbound) in self.outbound_substreams.drain() {
+        for (_, outbound, _) in self.outbound_substreams.drain() {
             self.muxer.destroy_outbound(outbound);
         }
         if !self.inbound_finished {
@@ -0,0 +1,413 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Sharing of the outbound bandwidth of a connection between the protocols.
+//!
+//! All the substreams of a connection are written to the same underlying socket. When the
+//! socket can't accept more data, the substreams that are waiting for it race each other, and a
+//! protocol that writes a lot of data, such as a file transfer, can starve the protocols that
+//! only send small control messages, such as ping or gossip.
+//!
+//! An `OutboundScheduler` prevents this. Each substream of the connection belongs to a
+//! `PriorityClass`, and when the substreams of several classes are waiting to write, the
+//! bandwidth is shared between the classes with a deficit round-robin: every round, each class
+//! that is waiting is allowed to write `quantum` bytes multiplied by its weight. When only one
+//! class is waiting, its substreams write without restriction.
+//!
+//! The class of a substream is determined from the protocol negotiated on it, either by the
+//! `ConnectionHandler` with `substream_priority`, or by the prefixes of protocol names passed to
+//! `SchedulerConfig::with_protocol_class`. Substreams whose protocol isn't known have the
+//! default class of the configuration.
+//!
+//! The scheduler is enabled on the connections of a swarm with `set_scheduler_config`.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate libp2p_core;
+//! use libp2p_core::nodes::outbound_scheduler::{PriorityClass, SchedulerConfig};
+//!
+//! # fn main() {
+//! let config = SchedulerConfig::new()
+//!     .with_protocol_class("/ipfs/ping/", PriorityClass::Control)
+//!     .with_protocol_class("/meshsub/", PriorityClass::Control)
+//!     .with_protocol_class("/ipfs/kad/", PriorityClass::High)
+//!     .with_protocol_class("/transfer/", PriorityClass::Bulk);
+//! assert_eq!(config.weight(PriorityClass::Control), 8);
+//! # }
+//! ```
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::task::{self, Task};
+use nodes::substream_stats::SubstreamId;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Class of a substream, which determines its share of the outbound bandwidth of the connection.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PriorityClass {
+    /// Small messages that must not be delayed, such as pings and gossip control messages.
+    Control,
+    /// Latency-sensitive requests, such as DHT queries.
+    High,
+    /// Everything else.
+    Normal,
+    /// Large transfers, which should only use the bandwidth that the other classes don't need.
+    Bulk,
+}
+
+impl PriorityClass {
+    /// All the classes, from the highest to the lowest.
+    pub const ALL: [PriorityClass; 4] = [
+        PriorityClass::Control,
+        PriorityClass::High,
+        PriorityClass::Normal,
+        PriorityClass::Bulk,
+    ];
+
+    /// Returns the position of the class in `ALL`.
+    #[inline]
+    fn index(self) -> usize {
+        match self {
+            PriorityClass::Control => 0,
+            PriorityClass::High => 1,
+            PriorityClass::Normal => 2,
+            PriorityClass::Bulk => 3,
+        }
+    }
+}
+
+impl Default for PriorityClass {
+    #[inline]
+    fn default() -> Self {
+        PriorityClass::Normal
+    }
+}
+
+/// Configuration of an `OutboundScheduler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// Number of bytes a class of weight 1 can write per round.
+    quantum: usize,
+    /// Weight of each class, indexed by `PriorityClass::index`.
+    weights: [usize; 4],
+    /// Class of the substreams whose protocol starts with the given prefix. The first match wins.
+    protocol_classes: Vec<(Bytes, PriorityClass)>,
+    /// Class of the substreams that don't match any prefix.
+    default_class: PriorityClass,
+}
+
+impl SchedulerConfig {
+    /// Builds the default configuration.
+    #[inline]
+    pub fn new() -> SchedulerConfig {
+        Default::default()
+    }
+
+    /// Sets the number of bytes a class of weight 1 can write per round. Defaults to 4096.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `quantum` is zero.
+    #[inline]
+    pub fn with_quantum(mut self, quantum: usize) -> Self {
+        assert_ne!(quantum, 0, "the quantum of a scheduler can't be zero");
+        self.quantum = quantum;
+        self
+    }
+
+    /// Sets the weight of a class. The bandwidth of the connection is shared between the classes
+    /// that are waiting proportionally to their weights. Defaults to 8 for `Control`, 4 for
+    /// `High`, 2 for `Normal` and 1 for `Bulk`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `weight` is zero.
+    #[inline]
+    pub fn with_weight(mut self, class: PriorityClass, weight: usize) -> Self {
+        assert_ne!(weight, 0, "the weight of a priority class can't be zero");
+        self.weights[class.index()] = weight;
+        self
+    }
+
+    /// Assigns the substreams whose protocol name starts with `prefix` to `class`. The prefixes
+    /// are tried in the order in which they have been added.
+    #[inline]
+    pub fn with_protocol_class<P>(mut self, prefix: P, class: PriorityClass) -> Self
+    where P: Into<Bytes>
+    {
+        self.protocol_classes.push((prefix.into(), class));
+        self
+    }
+
+    /// Sets the class of the substreams that don't match any prefix. Defaults to `Normal`.
+    #[inline]
+    pub fn with_default_class(mut self, class: PriorityClass) -> Self {
+        self.default_class = class;
+        self
+    }
+
+    /// Returns the weight of a class.
+    #[inline]
+    pub fn weight(&self, class: PriorityClass) -> usize {
+        self.weights[class.index()]
+    }
+
+    /// Returns the class of the substreams that have negotiated the given protocol.
+    pub fn class_of(&self, protocol: &[u8]) -> PriorityClass {
+        self.protocol_classes.iter()
+            .find(|(prefix, _)| protocol.starts_with(prefix))
+            .map(|(_, class)| *class)
+            .unwrap_or(self.default_class)
+    }
+}
+
+impl Default for SchedulerConfig {
+    #[inline]
+    fn default() -> Self {
+        SchedulerConfig {
+            quantum: 4096,
+            weights: [8, 4, 2, 1],
+            protocol_classes: Vec::new(),
+            default_class: PriorityClass::Normal,
+        }
+    }
+}
+
+/// Shares the outbound bandwidth of a connection between its substreams.
+///
+/// Cloning an `OutboundScheduler` produces a handle to the same scheduler.
+#[derive(Debug, Clone)]
+pub struct OutboundScheduler {
+    inner: Arc<Mutex<SchedulerInner>>,
+}
+
+#[derive(Debug)]
+struct SchedulerInner {
+    config: SchedulerConfig,
+    /// Class of the substreams that don't have the default class.
+    classes: FnvHashMap<SubstreamId, PriorityClass>,
+    /// State of each class, indexed by `PriorityClass::index`.
+    queues: [ClassQueue; 4],
+}
+
+#[derive(Debug, Default)]
+struct ClassQueue {
+    /// Number of bytes the class can still write in the current round.
+    deficit: usize,
+    /// Substreams of the class that are waiting to write.
+    waiting: FnvHashMap<SubstreamId, Waiting>,
+}
+
+#[derive(Debug)]
+struct Waiting {
+    /// Task to notify when the substream can write.
+    task: Task,
+    /// If true, the substream has been told to wait by the scheduler and must be notified by
+    /// it. Otherwise, it is waiting for the underlying socket, which will notify it.
+    yielded: bool,
+}
+
+impl OutboundScheduler {
+    /// Creates a new scheduler.
+    pub fn new(config: SchedulerConfig) -> OutboundScheduler {
+        OutboundScheduler {
+            inner: Arc::new(Mutex::new(SchedulerInner {
+                config,
+                classes: FnvHashMap::default(),
+                queues: Default::default(),
+            })),
+        }
+    }
+
+    /// Returns the class of a substream.
+    pub fn class(&self, id: SubstreamId) -> PriorityClass {
+        let inner = self.inner.lock();
+        inner.class(id)
+    }
+
+    /// Sets the class of a substream.
+    pub fn set_class(&self, id: SubstreamId, class: PriorityClass) {
+        let mut inner = self.inner.lock();
+        let previous = inner.class(id);
+        if previous == class {
+            return;
+        }
+
+        if let Some(waiting) = inner.queues[previous.index()].waiting.remove(&id) {
+            inner.queues[class.index()].waiting.insert(id, waiting);
+        }
+        inner.classes.insert(id, class);
+    }
+
+    /// Sets the class of a substream from the protocol negotiated on it.
+    pub fn set_protocol(&self, id: SubstreamId, protocol: &[u8]) {
+        let class = self.inner.lock().config.class_of(protocol);
+        self.set_class(id, class);
+    }
+
+    /// Called before writing `len` bytes on a substream. Returns the number of bytes the
+    /// substream is allowed to write, or `None` if it has to wait, in which case the current
+    /// task will be notified.
+    ///
+    /// After writing, `wrote` or `write_blocked` must be called.
+    pub(crate) fn poll_write(&self, id: SubstreamId, len: usize) -> Option<usize> {
+        if len == 0 {
+            return Some(0);
+        }
+
+        let mut inner = self.inner.lock();
+        let class = inner.class(id).index();
+
+        let contenders = (0 .. 4)
+            .filter(|&c| c == class || inner.queues[c].waiting.keys().any(|w| *w != id))
+            .collect::<Vec<_>>();
+        if contenders.len() == 1 {
+            return Some(len);
+        }
+
+        if inner.queues[class].deficit == 0 &&
+            contenders.iter().all(|&c| inner.queues[c].deficit == 0)
+        {
+            // Start a new round.
+            let quantum = inner.config.quantum;
+            for &c in &contenders {
+                inner.queues[c].deficit += quantum * inner.config.weights[c];
+            }
+            inner.notify_yielded(None);
+        }
+
+        let deficit = inner.queues[class].deficit;
+        if deficit == 0 {
+            inner.queues[class].waiting.insert(id, Waiting { task: task::current(), yielded: true });
+            return None;
+        }
+
+        Some(if len < deficit { len } else { deficit })
+    }
+
+    /// Called after a substream has written `len` bytes.
+    pub(crate) fn wrote(&self, id: SubstreamId, len: usize) {
+        let mut inner = self.inner.lock();
+        let class = inner.class(id).index();
+        inner.queues[class].waiting.remove(&id);
+        inner.queues[class].deficit = inner.queues[class].deficit.saturating_sub(len);
+        // The substreams that have yielded may be able to write now.
+        inner.notify_yielded(Some(class));
+    }
+
+    /// Called after the underlying socket refused to accept data from a substream, in which
+    /// case the current task will be notified by the socket.
+    pub(crate) fn write_blocked(&self, id: SubstreamId) {
+        let mut inner = self.inner.lock();
+        let class = inner.class(id).index();
+        inner.queues[class].waiting.insert(id, Waiting { task: task::current(), yielded: false });
+    }
+
+    /// Removes a substream from the scheduler.
+    pub(crate) fn remove(&self, id: SubstreamId) {
+        let mut inner = self.inner.lock();
+        let class = inner.class(id).index();
+        inner.classes.remove(&id);
+        if inner.queues[class].waiting.remove(&id).is_some() {
+            inner.notify_yielded(Some(class));
+        }
+    }
+}
+
+impl SchedulerInner {
+    /// Returns the class of a substream.
+    #[inline]
+    fn class(&self, id: SubstreamId) -> PriorityClass {
+        self.classes.get(&id).cloned().unwrap_or(self.config.default_class)
+    }
+
+    /// Notifies and removes the substreams that have been told to wait by the scheduler, except
+    /// the ones of the class of index `except`.
+    fn notify_yielded(&mut self, except: Option<usize>) {
+        for (c, queue) in self.queues.iter_mut().enumerate() {
+            if Some(c) == except {
+                continue;
+            }
+
+            queue.waiting.retain(|_, waiting| {
+                if waiting.yielded {
+                    waiting.task.notify();
+                }
+                !waiting.yielded
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use nodes::substream_stats::SubstreamTracker;
+    use runtime::Runtime;
+    use super::{OutboundScheduler, PriorityClass, SchedulerConfig};
+    use upgrade::Endpoint;
+
+    #[test]
+    fn protocol_classes() {
+        let config = SchedulerConfig::new()
+            .with_protocol_class("/ipfs/ping/", PriorityClass::Control)
+            .with_protocol_class("/ipfs/", PriorityClass::High)
+            .with_default_class(PriorityClass::Bulk);
+        assert_eq!(config.class_of(b"/ipfs/ping/1.0.0"), PriorityClass::Control);
+        assert_eq!(config.class_of(b"/ipfs/kad/1.0.0"), PriorityClass::High);
+        assert_eq!(config.class_of(b"/transfer/1.0.0"), PriorityClass::Bulk);
+    }
+
+    #[test]
+    fn bandwidth_shared_by_weight() {
+        let tracker = SubstreamTracker::new(Runtime::default());
+        let control = tracker.track((), Endpoint::Dialer).handle().id();
+        let bulk = tracker.track((), Endpoint::Dialer).handle().id();
+
+        let scheduler = OutboundScheduler::new(SchedulerConfig::new().with_quantum(100));
+        scheduler.set_class(control, PriorityClass::Control);
+        scheduler.set_class(bulk, PriorityClass::Bulk);
+
+        future::lazy(|| {
+            // Without contention, everything can be written.
+            assert_eq!(scheduler.poll_write(bulk, 10_000), Some(10_000));
+
+            // The socket is full and both substreams are waiting for it.
+            scheduler.write_blocked(bulk);
+            scheduler.write_blocked(control);
+
+            // A round gives 800 bytes to the control substream and 100 bytes to the bulk one.
+            assert_eq!(scheduler.poll_write(bulk, 10_000), Some(100));
+            scheduler.wrote(bulk, 100);
+            scheduler.write_blocked(bulk);
+            assert_eq!(scheduler.poll_write(bulk, 10_000), None);
+            assert_eq!(scheduler.poll_write(control, 10_000), Some(800));
+            scheduler.wrote(control, 800);
+            scheduler.write_blocked(control);
+
+            // Once both classes have used their share, a new round starts.
+            assert_eq!(scheduler.poll_write(bulk, 10_000), Some(100));
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,552 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Restricting the inbound protocols that each remote is allowed to negotiate.
+//!
+//! A `ProtocolPolicy` associates each peer with a `ProtocolRule`, either directly or through a
+//! named class of peers, and falls back to a default rule for the other peers.
+//!
+//! `ProtocolFilter` wraps around a `NetworkBehaviour` and enforces a `ProtocolPolicy` on the
+//! inbound substreams. The protocols that a remote isn't allowed to use are removed from the list
+//! of protocols of its inbound substreams, so that multistream-select answers that they are not
+//! available. Each time a remote proposes one of these protocols, a
+//! `ProtocolFilterEvent::Denied` is generated.
+//!
+//! > **Note**: The rule of a peer is sent to the handler of its connection when the behaviour is
+//! >           notified of the connection. Inbound substreams opened before that are filtered
+//! >           with the default rule, which should therefore be the most restrictive one.
+
+use bytes::Bytes;
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::prelude::*;
+use nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use nodes::handled_node::NodeHandlerEndpoint;
+use nodes::outbound_scheduler::PriorityClass;
+use nodes::swarm::ConnectedPoint;
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::{protocol_name_matches, ConnectionUpgrade, Endpoint};
+use {Multiaddr, PeerId};
+
+/// Which inbound protocols a remote is allowed to negotiate.
+///
+/// The names are compared with the names returned by `ConnectionUpgrade::protocol_names`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolRule {
+    /// All the protocols are allowed.
+    AllowAll,
+    /// Only the given protocols are allowed.
+    AllowOnly(Vec<Bytes>),
+    /// All the protocols are allowed except the given ones.
+    Deny(Vec<Bytes>),
+}
+
+impl ProtocolRule {
+    /// Returns true if the rule allows the given protocol.
+    pub fn allows(&self, protocol: &[u8]) -> bool {
+        match *self {
+            ProtocolRule::AllowAll => true,
+            ProtocolRule::AllowOnly(ref list) => list.iter().any(|p| &p[..] == protocol),
+            ProtocolRule::Deny(ref list) => !list.iter().any(|p| &p[..] == protocol),
+        }
+    }
+}
+
+impl Default for ProtocolRule {
+    #[inline]
+    fn default() -> Self {
+        ProtocolRule::AllowAll
+    }
+}
+
+/// Associates peers with the `ProtocolRule` that applies to them.
+///
+/// The rule of a peer is, by order of priority, the rule set for this peer with `set_peer_rule`,
+/// the rule of the class the peer has been assigned to, or the default rule.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolPolicy {
+    /// Rule of the peers that don't have a more specific one.
+    default: ProtocolRule,
+    /// Rules of the classes.
+    classes: FnvHashMap<String, ProtocolRule>,
+    /// Class of each peer.
+    peer_classes: FnvHashMap<PeerId, String>,
+    /// Rules specific to a peer.
+    peers: FnvHashMap<PeerId, ProtocolRule>,
+}
+
+impl ProtocolPolicy {
+    /// Builds a policy that applies the given rule to every peer.
+    #[inline]
+    pub fn new(default: ProtocolRule) -> ProtocolPolicy {
+        ProtocolPolicy {
+            default,
+            classes: FnvHashMap::default(),
+            peer_classes: FnvHashMap::default(),
+            peers: FnvHashMap::default(),
+        }
+    }
+
+    /// Returns the rule of the peers that don't have a more specific one.
+    #[inline]
+    pub fn default_rule(&self) -> &ProtocolRule {
+        &self.default
+    }
+
+    /// Sets the rule of the peers that don't have a more specific one.
+    #[inline]
+    pub fn set_default_rule(&mut self, rule: ProtocolRule) {
+        self.default = rule;
+    }
+
+    /// Sets the rule of a peer, which takes precedence over the rule of its class.
+    #[inline]
+    pub fn set_peer_rule(&mut self, peer_id: PeerId, rule: ProtocolRule) {
+        self.peers.insert(peer_id, rule);
+    }
+
+    /// Removes the rule of a peer. Returns false if it didn't have one.
+    #[inline]
+    pub fn remove_peer_rule(&mut self, peer_id: &PeerId) -> bool {
+        self.peers.remove(peer_id).is_some()
+    }
+
+    /// Sets the rule of the peers of a class.
+    #[inline]
+    pub fn set_class_rule<C>(&mut self, class: C, rule: ProtocolRule)
+    where
+        C: Into<String>,
+    {
+        self.classes.insert(class.into(), rule);
+    }
+
+    /// Assigns a peer to a class, replacing its previous class.
+    ///
+    /// The peers of a class that doesn't have a rule get the default rule.
+    #[inline]
+    pub fn assign_class<C>(&mut self, peer_id: PeerId, class: C)
+    where
+        C: Into<String>,
+    {
+        self.peer_classes.insert(peer_id, class.into());
+    }
+
+    /// Removes a peer from its class. Returns false if it wasn't assigned to any.
+    #[inline]
+    pub fn unassign_class(&mut self, peer_id: &PeerId) -> bool {
+        self.peer_classes.remove(peer_id).is_some()
+    }
+
+    /// Returns the rule that applies to the given peer.
+    pub fn rule_for(&self, peer_id: &PeerId) -> &ProtocolRule {
+        if let Some(rule) = self.peers.get(peer_id) {
+            return rule;
+        }
+
+        self.peer_classes
+            .get(peer_id)
+            .and_then(|class| self.classes.get(class))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Event generated by a `ProtocolFilter`.
+#[derive(Debug, Clone)]
+pub enum ProtocolFilterEvent<TEvent> {
+    /// A remote proposed a protocol that it isn't allowed to use on an inbound substream, and we
+    /// answered that the protocol is not available.
+    Denied {
+        /// The remote.
+        peer_id: PeerId,
+        /// Name of the protocol, as proposed by the remote.
+        protocol: Bytes,
+    },
+    /// Event generated by the underlying behaviour.
+    Inner(TEvent),
+}
+
+/// `NetworkBehaviour` that wraps around another one and restricts the protocols that each remote
+/// may negotiate on inbound substreams. See the module-level documentation.
+pub struct ProtocolFilter<TBehaviour>
+where
+    TBehaviour: NetworkBehaviour,
+{
+    /// The underlying behaviour.
+    inner: TBehaviour,
+    /// The rules to enforce.
+    policy: ProtocolPolicy,
+    /// Peers we are connected to.
+    connected: FnvHashSet<PeerId>,
+    /// Actions to return from `poll`.
+    queued_actions: VecDeque<NetworkBehaviourAction<
+        ProtocolFilterHandlerIn<<TBehaviour::ConnectionHandler as ConnectionHandler>::InEvent>,
+        ProtocolFilterEvent<TBehaviour::OutEvent>,
+    >>,
+}
+
+impl<TBehaviour> ProtocolFilter<TBehaviour>
+where
+    TBehaviour: NetworkBehaviour,
+{
+    /// Wraps around `inner` and enforces `policy` on the inbound substreams.
+    #[inline]
+    pub fn new(inner: TBehaviour, policy: ProtocolPolicy) -> Self {
+        ProtocolFilter {
+            inner,
+            policy,
+            connected: FnvHashSet::default(),
+            queued_actions: VecDeque::new(),
+        }
+    }
+
+    /// Returns the underlying behaviour.
+    #[inline]
+    pub fn inner(&self) -> &TBehaviour {
+        &self.inner
+    }
+
+    /// Returns the underlying behaviour.
+    #[inline]
+    pub fn inner_mut(&mut self) -> &mut TBehaviour {
+        &mut self.inner
+    }
+
+    /// Returns the policy that is enforced.
+    #[inline]
+    pub fn policy(&self) -> &ProtocolPolicy {
+        &self.policy
+    }
+
+    /// Modifies the policy. The new rules are applied to the connections that are already open,
+    /// except for the substreams whose negotiation is in progress.
+    pub fn update_policy<F>(&mut self, update: F)
+    where
+        F: FnOnce(&mut ProtocolPolicy),
+    {
+        update(&mut self.policy);
+        for peer_id in self.connected.iter() {
+            self.queued_actions.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer_id.clone(),
+                event: ProtocolFilterHandlerIn::SetRule(self.policy.rule_for(peer_id).clone()),
+            });
+        }
+    }
+}
+
+impl<TBehaviour> NetworkBehaviour for ProtocolFilter<TBehaviour>
+where
+    TBehaviour: NetworkBehaviour,
+{
+    type ConnectionHandler = ProtocolFilterHandler<TBehaviour::ConnectionHandler>;
+    type OutEvent = ProtocolFilterEvent<TBehaviour::OutEvent>;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        ProtocolFilterHandler::new(self.inner.new_handler(), self.policy.default_rule().clone())
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        self.queued_actions.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id: peer_id.clone(),
+            event: ProtocolFilterHandlerIn::SetRule(self.policy.rule_for(&peer_id).clone()),
+        });
+        self.connected.insert(peer_id.clone());
+        self.inner.inject_connected(peer_id, endpoint);
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+        self.connected.remove(peer_id);
+        self.inner.inject_disconnected(peer_id, endpoint);
+    }
+
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        event: ProtocolFilterHandlerEvent<<TBehaviour::ConnectionHandler as ConnectionHandler>::OutEvent>,
+    ) {
+        match event {
+            ProtocolFilterHandlerEvent::Denied(protocol) => {
+                debug!("Denied protocol {:?} to {:?}", protocol, peer_id);
+                let event = ProtocolFilterEvent::Denied { peer_id, protocol };
+                self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(event));
+            },
+            ProtocolFilterHandlerEvent::Inner(event) => self.inner.inject_node_event(peer_id, event),
+        }
+    }
+
+    fn poll(
+        &mut self,
+        params: &mut PollParameters,
+    ) -> Async<NetworkBehaviourAction<<Self::ConnectionHandler as ConnectionHandler>::InEvent, Self::OutEvent>> {
+        if let Some(action) = self.queued_actions.pop_front() {
+            return Async::Ready(action);
+        }
+
+        match self.inner.poll(params) {
+            Async::Ready(action) => {
+                let action = action
+                    .map_in_event(ProtocolFilterHandlerIn::Inner)
+                    .map_out_event(ProtocolFilterEvent::Inner);
+                Async::Ready(action)
+            },
+            Async::NotReady => Async::NotReady,
+        }
+    }
+}
+
+/// Event sent to a `ProtocolFilterHandler`.
+#[derive(Debug, Clone)]
+pub enum ProtocolFilterHandlerIn<TEvent> {
+    /// Replaces the rule applied to the inbound substreams.
+    SetRule(ProtocolRule),
+    /// Event for the underlying handler.
+    Inner(TEvent),
+}
+
+/// Event produced by a `ProtocolFilterHandler`.
+#[derive(Debug, Clone)]
+pub enum ProtocolFilterHandlerEvent<TEvent> {
+    /// The remote proposed a protocol that the rule doesn't allow.
+    Denied(Bytes),
+    /// Event produced by the underlying handler.
+    Inner(TEvent),
+}
+
+/// Connection handler of `ProtocolFilter`. Wraps around another handler and removes the
+/// protocols that the rule doesn't allow from its inbound protocols.
+#[derive(Debug, Clone)]
+pub struct ProtocolFilterHandler<THandler> {
+    /// The underlying handler.
+    inner: THandler,
+    /// Rule applied to the inbound substreams.
+    rule: Arc<ProtocolRule>,
+    /// Denied protocols to report.
+    denied: VecDeque<Bytes>,
+}
+
+impl<THandler> ProtocolFilterHandler<THandler> {
+    /// Wraps around `inner` and applies `rule` to the inbound substreams.
+    #[inline]
+    pub fn new(inner: THandler, rule: ProtocolRule) -> Self {
+        ProtocolFilterHandler {
+            inner,
+            rule: Arc::new(rule),
+            denied: VecDeque::new(),
+        }
+    }
+}
+
+impl<THandler> ConnectionHandler for ProtocolFilterHandler<THandler>
+where
+    THandler: ConnectionHandler,
+{
+    type InEvent = ProtocolFilterHandlerIn<THandler::InEvent>;
+    type OutEvent = ProtocolFilterHandlerEvent<THandler::OutEvent>;
+    type Substream = THandler::Substream;
+    type Protocol = FilteredUpgrade<THandler::Protocol>;
+    type OutboundOpenInfo = THandler::OutboundOpenInfo;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        FilteredUpgrade {
+            inner: self.inner.listen_protocol(),
+            rule: Some(self.rule.clone()),
+        }
+    }
+
+    #[inline]
+    fn inject_fully_negotiated(
+        &mut self,
+        protocol: <Self::Protocol as ConnectionUpgrade<Self::Substream>>::Output,
+        endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>,
+    ) {
+        self.inner.inject_fully_negotiated(protocol, endpoint)
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: Self::InEvent) {
+        match event {
+            ProtocolFilterHandlerIn::SetRule(rule) => self.rule = Arc::new(rule),
+            ProtocolFilterHandlerIn::Inner(event) => self.inner.inject_event(event),
+        }
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(&mut self, info: Self::OutboundOpenInfo, error: &IoError) {
+        self.inner.inject_dial_upgrade_error(info, error)
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+        self.inner.inject_inbound_closed()
+    }
+
+    fn inject_inbound_protocol_rejected(&mut self, protocol: &Bytes) {
+        // The protocol is denied, rather than unsupported, if the underlying handler supports it.
+        let denied = self.inner
+            .listen_protocol()
+            .protocol_names()
+            .any(|(name, _)| protocol_name_matches(protocol, &name) && !self.rule.allows(&name));
+        if denied {
+            self.denied.push_back(protocol.clone());
+        }
+
+        self.inner.inject_inbound_protocol_rejected(protocol)
+    }
+
+    #[inline]
+    fn substream_priority(&self, protocol: &Bytes) -> Option<PriorityClass> {
+        self.inner.substream_priority(protocol)
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.inner.connection_keep_alive()
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, Self::OutboundOpenInfo, Self::OutEvent>>, IoError> {
+        if let Some(protocol) = self.denied.pop_front() {
+            let event = ProtocolFilterHandlerEvent::Denied(protocol);
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(event))));
+        }
+
+        let event = try_ready!(self.inner.poll()).map(|event| {
+            event
+                .map_custom(ProtocolFilterHandlerEvent::Inner)
+                .map_outbound_open_info(|inner, info| (FilteredUpgrade { inner, rule: None }, info))
+        });
+        Ok(Async::Ready(event))
+    }
+}
+
+/// Wraps around an upgrade and removes the protocols that a `ProtocolRule` doesn't allow from its
+/// protocol names.
+#[derive(Debug, Clone)]
+pub struct FilteredUpgrade<TUpgrade> {
+    /// The underlying upgrade.
+    inner: TUpgrade,
+    /// The rule to apply. `None` for the outbound substreams, which aren't filtered.
+    rule: Option<Arc<ProtocolRule>>,
+}
+
+impl<C, TUpgrade> ConnectionUpgrade<C> for FilteredUpgrade<TUpgrade>
+where
+    TUpgrade: ConnectionUpgrade<C>,
+    C: AsyncRead + AsyncWrite,
+{
+    type NamesIter = FilteredNames<TUpgrade::NamesIter>;
+    type UpgradeIdentifier = TUpgrade::UpgradeIdentifier;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        FilteredNames {
+            inner: self.inner.protocol_names(),
+            rule: self.rule.clone(),
+        }
+    }
+
+    type Output = TUpgrade::Output;
+    type Future = TUpgrade::Future;
+
+    #[inline]
+    fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint, remote_addr: &Multiaddr) -> Self::Future {
+        self.inner.upgrade(socket, id, ty, remote_addr)
+    }
+}
+
+/// Iterator returned by `FilteredUpgrade::protocol_names`.
+#[derive(Debug, Clone)]
+pub struct FilteredNames<TInner> {
+    inner: TInner,
+    rule: Option<Arc<ProtocolRule>>,
+}
+
+impl<TInner, TId> Iterator for FilteredNames<TInner>
+where
+    TInner: Iterator<Item = (Bytes, TId)>,
+{
+    type Item = (Bytes, TId);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (name, id) = self.inner.next()?;
+            match self.rule {
+                Some(ref rule) if !rule.allows(&name) => continue,
+                _ => return Some((name, id)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nodes::dynamic_protocols::DynamicProtocols;
+    use std::io::Cursor;
+    use PublicKey;
+
+    #[test]
+    fn rule_priority() {
+        let peer = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let mut policy = ProtocolPolicy::new(ProtocolRule::AllowOnly(vec!["/a".into()]));
+        assert!(!policy.rule_for(&peer).allows(b"/b"));
+
+        policy.set_class_rule("trusted", ProtocolRule::AllowAll);
+        policy.assign_class(peer.clone(), "trusted");
+        assert!(policy.rule_for(&peer).allows(b"/b"));
+
+        policy.set_peer_rule(peer.clone(), ProtocolRule::Deny(vec!["/b".into()]));
+        assert!(!policy.rule_for(&peer).allows(b"/b"));
+        assert!(policy.rule_for(&peer).allows(b"/a"));
+    }
+
+    #[test]
+    fn denied_protocols_hidden_and_reported() {
+        let mut inner = DynamicProtocols::<Cursor<Vec<u8>>>::new();
+        inner.add_protocol("/a", |_, _| ());
+        inner.add_protocol("/b", |_, _| ());
+        let behaviour = ProtocolFilter::new(inner, ProtocolPolicy::new(ProtocolRule::Deny(vec!["/b".into()])));
+
+        let mut handler = behaviour.new_handler();
+        let names = handler.listen_protocol().protocol_names().map(|(n, _)| n).collect::<Vec<_>>();
+        assert_eq!(names, vec![Bytes::from("/a")]);
+
+        // Unsupported protocols are not reported as denied.
+        handler.inject_inbound_protocol_rejected(&Bytes::from("/c"));
+        handler.inject_inbound_protocol_rejected(&Bytes::from("/b"));
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(ProtocolFilterHandlerEvent::Denied(p))))) => {
+                assert_eq!(p, Bytes::from("/b"))
+            },
+            _ => panic!(),
+        }
+
+        handler.inject_event(ProtocolFilterHandlerIn::SetRule(ProtocolRule::AllowAll));
+        assert_eq!(handler.listen_protocol().protocol_names().count(), 2);
+    }
+}
@@ -0,0 +1,457 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Accounting of the substreams of the connections.
+//!
+//! Every connection of a swarm has a `SubstreamTracker`, which keeps track of the substreams
+//! that are open on it: whether they have been opened by us or by the remote, which protocol has
+//! been negotiated on them, and since when they are open. Once a substream is dropped, it is
+//! removed from the tracker and its lifetime is added to the totals of the connection.
+//!
+//! The substreams produced by a `NodeStream` are wrapped in a `TrackedSubstream`, which is
+//! transparent for the protocols. The protocols negotiated on the substreams are reported by the
+//! `NodeHandlerWrapper`.
+//!
+//! This makes it possible to find the substreams that are leaked by a protocol, for example in a
+//! long-running simulation, by looking at the substreams that stay open for a long time.
+//!
+//! A tracker can also have an `OutboundScheduler`, through which the substreams write. See the
+//! `outbound_scheduler` module.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate bytes;
+//! # extern crate libp2p_core;
+//! use bytes::Bytes;
+//! use libp2p_core::Endpoint;
+//! use libp2p_core::nodes::substream_stats::SubstreamTracker;
+//! use libp2p_core::runtime::Runtime;
+//! use std::io::Cursor;
+//!
+//! # fn main() {
+//! let tracker = SubstreamTracker::new(Runtime::default());
+//! let substream = tracker.track(Cursor::new(Vec::<u8>::new()), Endpoint::Dialer);
+//! substream.handle().set_protocol(Bytes::from("/ipfs/ping/1.0.0"));
+//!
+//! let stats = tracker.stats();
+//! assert_eq!(stats.open_outbound(), 1);
+//! assert_eq!(stats.open_by_protocol().get(&Bytes::from("/ipfs/ping/1.0.0")), Some(&1));
+//!
+//! drop(substream);
+//! assert_eq!(tracker.stats().closed.outbound, 1);
+//! # }
+//! ```
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use nodes::outbound_scheduler::{OutboundScheduler, PriorityClass};
+use parking_lot::Mutex;
+use runtime::Runtime;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::Endpoint;
+use wasm_timer::Instant;
+
+/// Keeps track of the substreams of a connection.
+///
+/// Cloning a `SubstreamTracker` produces a handle to the same tracker.
+#[derive(Debug, Clone)]
+pub struct SubstreamTracker {
+    inner: Arc<Mutex<TrackerInner>>,
+}
+
+#[derive(Debug)]
+struct TrackerInner {
+    /// Used to know when the substreams are opened and closed.
+    runtime: Runtime,
+    /// Id to assign to the next substream.
+    next_id: u64,
+    /// Substreams that are currently open.
+    open: FnvHashMap<SubstreamId, SubstreamInfo>,
+    /// Totals about the substreams that have been closed.
+    closed: ClosedSubstreams,
+    /// Scheduler that the substreams write through, if any.
+    scheduler: Option<OutboundScheduler>,
+}
+
+/// Identifier of a substream within a `SubstreamTracker`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubstreamId(u64);
+
+/// Information about an open substream.
+#[derive(Debug, Clone)]
+pub struct SubstreamInfo {
+    id: SubstreamId,
+    endpoint: Endpoint,
+    protocol: Option<Bytes>,
+    opened_at: Instant,
+}
+
+impl SubstreamInfo {
+    /// Returns the id of the substream.
+    #[inline]
+    pub fn id(&self) -> SubstreamId {
+        self.id
+    }
+
+    /// Returns `Dialer` if we opened the substream, and `Listener` if the remote opened it.
+    #[inline]
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint
+    }
+
+    /// Returns the protocol negotiated on the substream, or `None` if the negotiation hasn't
+    /// finished yet.
+    #[inline]
+    pub fn protocol(&self) -> Option<&Bytes> {
+        self.protocol.as_ref()
+    }
+
+    /// Returns when the substream has been opened.
+    #[inline]
+    pub fn opened_at(&self) -> Instant {
+        self.opened_at
+    }
+
+    /// Returns for how long the substream has been open at `now`.
+    #[inline]
+    pub fn lifetime(&self, now: Instant) -> Duration {
+        if now > self.opened_at { now - self.opened_at } else { Duration::new(0, 0) }
+    }
+}
+
+/// Totals about the substreams of a connection that have been closed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClosedSubstreams {
+    /// Number of closed substreams that had been opened by the remote.
+    pub inbound: u64,
+    /// Number of closed substreams that we had opened.
+    pub outbound: u64,
+    /// Sum of the lifetimes of the closed substreams.
+    pub total_lifetime: Duration,
+    /// Lifetime of the closed substream that stayed open the longest.
+    pub longest_lifetime: Duration,
+}
+
+/// Snapshot of the substreams of a connection. Obtained with `SubstreamTracker::stats`.
+#[derive(Debug, Clone)]
+pub struct SubstreamStats {
+    /// When the snapshot has been taken.
+    pub now: Instant,
+    /// The substreams that are open, sorted by id.
+    pub open: Vec<SubstreamInfo>,
+    /// Totals about the substreams that have been closed.
+    pub closed: ClosedSubstreams,
+}
+
+impl SubstreamStats {
+    /// Returns the number of open substreams that have been opened by the remote.
+    pub fn open_inbound(&self) -> usize {
+        self.open.iter().filter(|s| s.endpoint == Endpoint::Listener).count()
+    }
+
+    /// Returns the number of open substreams that we opened.
+    pub fn open_outbound(&self) -> usize {
+        self.open.iter().filter(|s| s.endpoint == Endpoint::Dialer).count()
+    }
+
+    /// Returns the number of open substreams for each negotiated protocol. The substreams whose
+    /// negotiation hasn't finished are not counted.
+    pub fn open_by_protocol(&self) -> FnvHashMap<Bytes, usize> {
+        let mut out = FnvHashMap::default();
+        for protocol in self.open.iter().filter_map(|s| s.protocol.clone()) {
+            *out.entry(protocol).or_insert(0) += 1;
+        }
+        out
+    }
+
+    /// Returns the open substreams that have been open for at least `min`, which are likely to
+    /// have been leaked.
+    pub fn older_than(&self, min: Duration) -> impl Iterator<Item = &SubstreamInfo> {
+        let now = self.now;
+        self.open.iter().filter(move |s| s.lifetime(now) >= min)
+    }
+}
+
+impl SubstreamTracker {
+    /// Creates a new tracker. The times of the substreams are obtained from `runtime`.
+    pub fn new(runtime: Runtime) -> SubstreamTracker {
+        SubstreamTracker::with_scheduler_opt(runtime, None)
+    }
+
+    /// Same as `new`, but the substreams write through the given scheduler.
+    pub fn with_scheduler(runtime: Runtime, scheduler: OutboundScheduler) -> SubstreamTracker {
+        SubstreamTracker::with_scheduler_opt(runtime, Some(scheduler))
+    }
+
+    fn with_scheduler_opt(runtime: Runtime, scheduler: Option<OutboundScheduler>) -> SubstreamTracker {
+        SubstreamTracker {
+            inner: Arc::new(Mutex::new(TrackerInner {
+                runtime,
+                next_id: 0,
+                open: FnvHashMap::default(),
+                closed: ClosedSubstreams::default(),
+                scheduler,
+            })),
+        }
+    }
+
+    /// Returns the runtime that the times of the substreams are obtained from.
+    #[inline]
+    pub fn runtime(&self) -> Runtime {
+        self.inner.lock().runtime.clone()
+    }
+
+    /// Returns the scheduler that the substreams write through, if any.
+    #[inline]
+    pub fn scheduler(&self) -> Option<OutboundScheduler> {
+        self.inner.lock().scheduler.clone()
+    }
+
+    /// Starts tracking a substream. The substream is considered closed once the returned
+    /// `TrackedSubstream` is dropped.
+    pub fn track<S>(&self, substream: S, endpoint: Endpoint) -> TrackedSubstream<S> {
+        let (id, scheduler) = {
+            let mut inner = self.inner.lock();
+            let id = SubstreamId(inner.next_id);
+            inner.next_id += 1;
+            let opened_at = inner.runtime.now();
+            inner.open.insert(id, SubstreamInfo { id, endpoint, protocol: None, opened_at });
+            (id, inner.scheduler.clone())
+        };
+
+        TrackedSubstream {
+            inner: substream,
+            handle: SubstreamHandle { tracker: self.clone(), id },
+            scheduler,
+        }
+    }
+
+    /// Returns the number of open substreams.
+    #[inline]
+    pub fn num_open(&self) -> usize {
+        self.inner.lock().open.len()
+    }
+
+    /// Returns a snapshot of the substreams of the connection.
+    pub fn stats(&self) -> SubstreamStats {
+        let inner = self.inner.lock();
+        let mut open = inner.open.values().cloned().collect::<Vec<_>>();
+        open.sort_by_key(|s| s.id);
+        SubstreamStats {
+            now: inner.runtime.now(),
+            open,
+            closed: inner.closed.clone(),
+        }
+    }
+
+    /// Removes a substream and adds it to the totals.
+    fn close(&self, id: SubstreamId) {
+        let mut inner = self.inner.lock();
+        let info = match inner.open.remove(&id) {
+            Some(info) => info,
+            None => return,
+        };
+
+        let lifetime = info.lifetime(inner.runtime.now());
+        match info.endpoint {
+            Endpoint::Listener => inner.closed.inbound += 1,
+            Endpoint::Dialer => inner.closed.outbound += 1,
+        }
+        inner.closed.total_lifetime += lifetime;
+        if lifetime > inner.closed.longest_lifetime {
+            inner.closed.longest_lifetime = lifetime;
+        }
+    }
+}
+
+/// Handle to a substream of a `SubstreamTracker`, used to report the protocol that has been
+/// negotiated on it.
+#[derive(Debug, Clone)]
+pub struct SubstreamHandle {
+    tracker: SubstreamTracker,
+    id: SubstreamId,
+}
+
+impl SubstreamHandle {
+    /// Returns the id of the substream.
+    #[inline]
+    pub fn id(&self) -> SubstreamId {
+        self.id
+    }
+
+    /// Records the protocol that has been negotiated on the substream. If the tracker has a
+    /// scheduler, the priority class of the substream is updated according to the protocol.
+    pub fn set_protocol(&self, protocol: Bytes) {
+        let scheduler = {
+            let mut inner = self.tracker.inner.lock();
+            if let Some(info) = inner.open.get_mut(&self.id) {
+                info.protocol = Some(protocol.clone());
+            }
+            inner.scheduler.clone()
+        };
+
+        if let Some(scheduler) = scheduler {
+            scheduler.set_protocol(self.id, &protocol);
+        }
+    }
+
+    /// Sets the priority class of the substream, overriding the one determined from its
+    /// protocol. Has no effect if the tracker doesn't have a scheduler.
+    pub fn set_priority(&self, class: PriorityClass) {
+        if let Some(scheduler) = self.tracker.scheduler() {
+            scheduler.set_class(self.id, class);
+        }
+    }
+}
+
+/// Substream that is removed from its `SubstreamTracker` when dropped.
+///
+/// Reading is passed through to the underlying substream. Writing is passed through as well,
+/// after having been allowed by the scheduler of the tracker if there is one.
+#[derive(Debug)]
+pub struct TrackedSubstream<S> {
+    inner: S,
+    handle: SubstreamHandle,
+    /// Scheduler of the tracker, if any.
+    scheduler: Option<OutboundScheduler>,
+}
+
+impl<S> TrackedSubstream<S> {
+    /// Returns a handle to the substream in its tracker.
+    #[inline]
+    pub fn handle(&self) -> &SubstreamHandle {
+        &self.handle
+    }
+
+    /// Returns a reference to the underlying substream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying substream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S> Drop for TrackedSubstream<S> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(ref scheduler) = self.scheduler {
+            scheduler.remove(self.handle.id);
+        }
+        self.handle.tracker.close(self.handle.id);
+    }
+}
+
+impl<S: Read> Read for TrackedSubstream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for TrackedSubstream<S> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<S: Write> Write for TrackedSubstream<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let scheduler = match self.scheduler {
+            Some(ref scheduler) => scheduler,
+            None => return self.inner.write(buf),
+        };
+
+        let len = match scheduler.poll_write(self.handle.id, buf.len()) {
+            Some(len) => len,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+
+        match self.inner.write(&buf[..len]) {
+            Ok(written) => {
+                scheduler.wrote(self.handle.id, written);
+                Ok(written)
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                scheduler.write_blocked(self.handle.id);
+                Err(io::ErrorKind::WouldBlock.into())
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for TrackedSubstream<S> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use runtime::{ManualTimer, Runtime};
+    use std::time::Duration;
+    use super::SubstreamTracker;
+    use upgrade::Endpoint;
+
+    #[test]
+    fn substreams_accounted() {
+        let timer = ManualTimer::new();
+        let tracker = SubstreamTracker::new(Runtime::deterministic(timer.clone()));
+
+        let inbound = tracker.track((), Endpoint::Listener);
+        let outbound = tracker.track((), Endpoint::Dialer);
+        inbound.handle().set_protocol(Bytes::from("/ping/1.0.0"));
+        timer.advance(Duration::from_secs(5));
+
+        let stats = tracker.stats();
+        assert_eq!((stats.open_inbound(), stats.open_outbound()), (1, 1));
+        assert_eq!(stats.open_by_protocol().get(&Bytes::from("/ping/1.0.0")), Some(&1));
+        assert_eq!(stats.older_than(Duration::from_secs(5)).count(), 2);
+
+        drop(inbound);
+        timer.advance(Duration::from_secs(5));
+        drop(outbound);
+
+        let stats = tracker.stats();
+        assert!(stats.open.is_empty());
+        assert_eq!((stats.closed.inbound, stats.closed.outbound), (1, 1));
+        assert_eq!(stats.closed.total_lifetime, Duration::from_secs(15));
+        assert_eq!(stats.closed.longest_lifetime, Duration::from_secs(10));
+    }
+}
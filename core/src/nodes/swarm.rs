@@ -18,87 +18,192 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+//! High-level swarm that reports the lifecycle of the connections as typed events.
+//!
+//! The `Swarm` wraps around a `RawSwarm` and dereferences to it, which means that all the methods
+//! of the `RawSwarm` (such as `listen_on`, `dial` or `peer`) can be called on it. The difference
+//! is in the events that are produced: the events of the `RawSwarm` mirror its internal state
+//! machine, while the `Swarm` produces a uniform stream of `SwarmEvent`s that describes when
+//! connections are established or closed, why they were closed, and why dialing failed.
+//!
+//! Each connection is identified by a `ConnectionId`, assigned as soon as the connection starts
+//! being dialed or negotiated and never reused. All the events related to a connection contain
+//! its id, which makes it possible to tell apart multiple connections to the same peer, for
+//! example when a connection is replaced, or the successive connections to a peer we reconnect
+//! to. Connection ids are also the value of the `conn` field of the connection spans.
+//!
+//! Listeners can be added and removed at any time with `add_listener` and `remove_listener`.
+//! The `Swarm` reports the addresses it starts or stops listening on with the `NewListenAddr` and
+//! `ExpiredListenAddr` events, and the listeners that close, for example because of an error,
+//! with the `ListenerClosed` event.
+//!
+//! The `Swarm` also lets you disconnect from peers with `disconnect_peer`, and ban them with
+//! `ban_peer`. While a peer is banned, we refuse to dial it, and any connection to it is closed
+//! as soon as the id of the remote is known, whether it was opened by us or by the remote.
+//!
+//! Finally, the `Swarm` keeps track of the addresses through which we can be reached from the
+//! outside, which can differ from the addresses we listen on if we are behind a NAT or a relay.
+//! See `add_external_address` and `advertised_addresses`. The addresses reported by remotes with
+//! `report_observed_address` and the port mappings added with `add_port_mapping` are turned into
+//! external addresses by an `AddressTranslator`. See the `address_translation` module.
+//!
+//! The connections can be restricted with an `AddressFilter`, passed to `set_address_filter`,
+//! which allows or denies IP ranges, address prefixes and peers. Dialing a filtered address is
+//! refused, and the connections that are established with a filtered address or peer are closed
+//! immediately. Both produce a `ConnectionFiltered` event. In order to drop the incoming
+//! connections before they are even negotiated, also use `Transport::filter`. See the
+//! `address_filter` module.
+//!
+//! Addresses that fail to be dialed are put in back-off: they aren't dialed again before a delay
+//! that grows exponentially with the number of consecutive failures. While an address is backed
+//! off, `dial` gives it back and produces a `DialBackedOff` event, and `dial_peer` skips it. Use
+//! `reset_dial_backoff` to dial it anyway. See the `dial_backoff` module.
+//!
+//! `dial` returns a `DialAttempt`, which reports the progress of the attempt address by address
+//! and can cancel it, for example when the network is known to be partitioned. See the
+//! `dial_attempt` module.
+//!
+//! Peers can be dialed by their `PeerId` alone with `dial_peer`. The addresses of the peer are
+//! then obtained from the `AddressResolver` passed to `set_address_resolver`, which typically
+//! queries a peer store and falls back to a DHT lookup. See the `address_resolver` module.
+//!
+//! The background tasks of the connections are spawned, and the timers are created, through the
+//! `Runtime` passed to `with_runtime`. By default, the tasks are spawned on the current tokio
+//! executor and the system clock is used.
+//!
+//! Each connection runs in its own task, which catches the panics of the protocol handlers. A
+//! panic only closes the faulty connection, and is reported as a `ConnectionClosed` event with
+//! `CloseCause::HandlerPanicked`.
+//!
+//! The substreams opened on each connection are accounted for. Use `substream_stats` to get the
+//! number of open inbound and outbound substreams of a connection, the protocol negotiated on
+//! each of them and their lifetimes. See the `substream_stats` module.
+//!
+//! With `set_scheduler_config`, the outbound bandwidth of each connection is shared between the
+//! protocols according to priority classes, so that bulk transfers don't starve the control
+//! messages of the other protocols. See the `outbound_scheduler` module.
+//!
+//! The outbound substreams that a connection is opening are bounded by `set_outbound_limits`. By
+//! default, an attempt fails after 10 seconds, and at most 128 attempts can be pending at the
+//! same time. This prevents a remote that never accepts our substreams from making them pile up.
+//!
+//! The `Swarm` can be shut down gracefully with `start_close` or `close`. The listeners are
+//! stopped, and the connections are drained: the protocols get a chance to finish their work and
+//! the remotes are notified, until all the connections are closed or a deadline is reached.
+
 use fnv::FnvHashMap;
-use futures::{prelude::*, future};
+use futures::prelude::*;
 use muxing::StreamMuxer;
-use nodes::collection::{
-    CollectionEvent, CollectionNodeAccept, CollectionReachEvent, CollectionStream, PeerMut as CollecPeerMut, ReachAttemptId,
-};
-use nodes::handled_node::NodeHandler;
-use nodes::listeners::{ListenersEvent, ListenersStream};
+use nodes::address_filter::{AddressFilter, FilterRejection};
+use nodes::address_resolver::{AddressLookup, AddressResolver};
+use nodes::address_translation::{AddressTranslator, PortMapping, TranslationStrategy};
+use nodes::dial_attempt::DialAttempt;
+use nodes::dial_backoff::{DialBackoff, DialBackoffConfig};
+use nodes::external_addrs::{AddAddressResult, AddressRecord, AddressScore, ExternalAddresses};
+use nodes::handled_node::{HandlerPanicked, NodeHandler};
 use nodes::node::Substream;
-use std::collections::hash_map::{Entry, OccupiedEntry};
+use nodes::raw_swarm::{HandlerFactory, Peer, RawSwarm, RawSwarmEvent};
+use runtime::{Delay, Runtime};
+use std::collections::VecDeque;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 use void::Void;
-use {Endpoint, Multiaddr, PeerId, Transport};
+use wasm_timer::Instant;
+use {Multiaddr, PeerId, Transport};
+
+pub use nodes::raw_swarm::{ConnectedPoint, ConnectionId, DuplicateConnectionPolicy, ListenerId};
 
-/// Implementation of `Stream` that handles the nodes.
+/// Implementation of `Stream` that handles the nodes and produces typed events.
 pub struct Swarm<TTrans, TInEvent, TOutEvent, THandlerBuild>
 where
     TTrans: Transport,
 {
-    /// Listeners for incoming connections.
-    listeners: ListenersStream<TTrans>,
+    /// The underlying swarm.
+    raw: RawSwarm<TTrans, TInEvent, TOutEvent, THandlerBuild>,
 
-    /// The nodes currently active.
-    active_nodes: CollectionStream<TInEvent, TOutEvent>,
+    /// Events that have been generated but not returned yet. A single event of the raw swarm can
+    /// be translated into multiple events.
+    pending_events: VecDeque<SwarmEvent<TOutEvent>>,
 
-    /// The reach attempts of the swarm.
-    /// This needs to be a separate struct in order to handle multiple mutable borrows issues.
-    reach_attempts: ReachAttempts,
+    /// Peers that are banned, with the instant when the ban expires. `None` means that the ban
+    /// never expires.
+    banned_peers: FnvHashMap<PeerId, Option<Instant>>,
 
-    /// Object that builds new handlers.
-    handler_build: THandlerBuild,
-}
+    /// Rules that decide which connections are accepted.
+    address_filter: AddressFilter,
 
-struct ReachAttempts {
-    /// Attempts to reach a peer.
-    out_reach_attempts: FnvHashMap<PeerId, OutReachAttempt>,
+    /// Addresses through which we can be reached from the outside.
+    external_addrs: ExternalAddresses,
 
-    /// Reach attempts for incoming connections, and outgoing connections for which we don't know
-    /// the peer ID.
-    other_reach_attempts: Vec<(ReachAttemptId, ConnectedPoint)>,
+    /// Computes the external addresses from the observed addresses and the port mappings.
+    address_translator: AddressTranslator,
 
-    /// For each peer ID we're connected to, contains the endpoint we're connected to.
-    connected_endpoints: FnvHashMap<PeerId, ConnectedPoint>,
-}
+    /// Addresses that recently failed to be dialed.
+    dial_backoff: DialBackoff,
 
-/// Attempt to reach a peer.
-#[derive(Debug, Clone)]
-struct OutReachAttempt {
-    /// Identifier for the reach attempt.
-    id: ReachAttemptId,
-    /// Multiaddr currently being attempted.
-    cur_attempted: Multiaddr,
-    /// Multiaddresses to attempt if the current one fails.
-    next_attempts: Vec<Multiaddr>,
+    /// Provides the addresses of the peers dialed with `dial_peer`.
+    address_resolver: Option<Box<AddressResolver + Send>>,
+
+    /// Peers that are being dialed because of `dial_peer`.
+    peer_dials: FnvHashMap<PeerId, PeerDial>,
+
+    /// True if `start_close` has been called.
+    closing: bool,
+
+    /// If we are closing, the deadline after which the remaining connections are closed
+    /// abruptly. Set back to `None` once the deadline has been reached.
+    close_deadline: Option<Delay>,
 }
 
 /// Event that can happen on the `Swarm`.
-pub enum SwarmEvent<TTrans, TOutEvent>
-where
-    TTrans: Transport,
-{
-    /// One of the listeners gracefully closed.
+#[derive(Debug)]
+pub enum SwarmEvent<TOutEvent> {
+    /// One of the listeners has started listening on an address.
+    NewListenAddr {
+        /// Id of the listener.
+        listener_id: ListenerId,
+        /// The new address.
+        listen_addr: Multiaddr,
+    },
+
+    /// One of the listeners is no longer listening on an address.
+    ExpiredListenAddr {
+        /// Id of the listener.
+        listener_id: ListenerId,
+        /// The address that expired.
+        listen_addr: Multiaddr,
+    },
+
+    /// One of the listeners has closed, either gracefully, because of an error, or because it
+    /// has been removed with `remove_listener`.
     ListenerClosed {
+        /// Id of the listener which closed.
+        listener_id: ListenerId,
         /// Address of the listener which closed.
         listen_addr: Multiaddr,
-        /// The listener which closed.
-        listener: TTrans::Listener,
-        /// The error that happened. `Ok` if gracefully closed.
-        result: Result<(), <TTrans::Listener as Stream>::Error>,
+        /// `Ok` if the listener closed gracefully, otherwise the error that happened.
+        result: Result<(), IoError>,
     },
 
-    /// A new connection arrived on a listener.
+    /// A new connection arrived on a listener and is being negotiated.
     IncomingConnection {
+        /// Id of the listener which received the connection.
+        listener_id: ListenerId,
+        /// Id of the connection. Kept once the connection is established.
+        connection_id: ConnectionId,
         /// Address of the listener which received the connection.
         listen_addr: Multiaddr,
         /// Address used to send back data to the incoming connection.
         send_back_addr: Multiaddr,
     },
 
-    /// An error happened when negotiating a new connection.
+    /// An error happened when negotiating an incoming connection.
     IncomingConnectionError {
+        /// Id of the listener which received the connection.
+        listener_id: ListenerId,
+        /// Id of the connection.
+        connection_id: ConnectionId,
         /// Address of the listener which received the connection.
         listen_addr: Multiaddr,
         /// Address used to send back data to the incoming connection.
@@ -107,161 +212,240 @@ where
         error: IoError,
     },
 
-    /// A new connection to a peer has been opened.
-    Connected {
+    /// A connection to a peer has been established.
+    ConnectionEstablished {
         /// Id of the peer.
         peer_id: PeerId,
-        /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
-        /// we opened.
+        /// Id of the connection.
+        connection_id: ConnectionId,
+        /// If `Listener`, then we received the connection. If `Dialer`, then it's a connection
+        /// that we opened.
         endpoint: ConnectedPoint,
     },
 
-    /// A connection to a peer has been replaced with a new one.
-    Replaced {
+    /// A connection to a peer has been closed.
+    ConnectionClosed {
         /// Id of the peer.
         peer_id: PeerId,
-        /// Endpoint we used to be connected to.
-        closed_endpoint: ConnectedPoint,
-        /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
-        /// we opened.
+        /// Id of the connection.
+        connection_id: ConnectionId,
+        /// Endpoint of the connection that has been closed.
         endpoint: ConnectedPoint,
+        /// Why the connection has been closed.
+        cause: CloseCause,
     },
 
-    /// A connection to a node has been closed.
-    ///
-    /// This happens once both the inbound and outbound channels are closed, and no more outbound
-    /// substream attempt is pending.
-    NodeClosed {
-        /// Identifier of the node.
-        peer_id: PeerId,
-        /// Endpoint we were connected to.
-        endpoint: ConnectedPoint,
+    /// Failed to reach an address that we were dialing.
+    DialFailure {
+        /// Id of the peer we were trying to dial, if known.
+        peer_id: Option<PeerId>,
+        /// Id of the connection attempt that failed.
+        connection_id: ConnectionId,
+        /// The multiaddr we failed to reach.
+        multiaddr: Multiaddr,
+        /// Number of multiaddresses that still need to be attempted for this peer. If this is
+        /// zero, then we have definitely failed to reach the peer.
+        remain_addrs_attempt: usize,
+        /// The error that happened.
+        error: DialError,
     },
 
-    /// The muxer of a node has produced an error.
-    NodeError {
-        /// Identifier of the node.
+    /// A node produced a custom event.
+    ///
+    /// > **Note**: Never produced by a `BehaviourSwarm`, which passes the events of the nodes to
+    /// >           its behaviour instead.
+    NodeEvent {
+        /// Id of the node that produced the event.
         peer_id: PeerId,
-        /// Endpoint we were connected to.
-        endpoint: ConnectedPoint,
-        /// The error that happened.
-        error: IoError,
+        /// Id of the connection that produced the event.
+        connection_id: ConnectionId,
+        /// Event that was produced by the node.
+        event: TOutEvent,
     },
 
-    /// Failed to reach a peer that we were trying to dial.
-    DialError {
-        /// Returns the number of multiaddresses that still need to be attempted. If this is
-        /// non-zero, then there's still a chance we can connect to this node. If this is zero,
-        /// then we have definitely failed.
-        remain_addrs_attempt: usize,
+    /// The behaviour of a `BehaviourSwarm` generated an event. Never produced by a `Swarm`.
+    Behaviour(TOutEvent),
 
-        /// Id of the peer we were trying to dial.
+    /// A peer has been banned with `ban_peer`.
+    PeerBanned {
+        /// Id of the peer.
         peer_id: PeerId,
-
-        /// The multiaddr we failed to reach.
-        multiaddr: Multiaddr,
-
-        /// The error that happened.
-        error: IoError,
+        /// When the ban expires, or `None` if it never expires.
+        until: Option<Instant>,
     },
 
-    /// Failed to reach a peer that we were trying to dial.
-    UnknownPeerDialError {
-        /// The multiaddr we failed to reach.
-        multiaddr: Multiaddr,
-        /// The error that happened.
-        error: IoError,
+    /// A peer is no longer banned, either because the ban has expired or because `unban_peer`
+    /// has been called.
+    PeerUnbanned {
+        /// Id of the peer.
+        peer_id: PeerId,
     },
 
-    /// When dialing a peer, we successfully connected to a remote whose peer id doesn't match
-    /// what we expected.
-    PublicKeyMismatch {
-        /// Id of the peer we were expecting.
-        expected_peer_id: PeerId,
-
-        /// Id of the peer we actually obtained.
-        actual_peer_id: PeerId,
+    /// A connection to a banned peer has been opened, and has been closed as soon as the id of
+    /// the peer was known, before being passed to a handler.
+    ///
+    /// > **Note**: No `ConnectionEstablished` event is produced for this connection.
+    BannedPeerRejected {
+        /// Id of the peer.
+        peer_id: PeerId,
+        /// Id of the connection that has been rejected.
+        connection_id: ConnectionId,
+        /// Endpoint of the connection that has been rejected.
+        endpoint: ConnectedPoint,
+    },
 
-        /// The multiaddr we failed to reach.
-        multiaddr: Multiaddr,
+    /// A connection has been refused because of the `AddressFilter` of the swarm.
+    ///
+    /// Either we refused to dial a filtered address, in which case the endpoint is `Dialer`, or
+    /// a connection with a filtered address or peer has been established and has been closed
+    /// immediately.
+    ///
+    /// > **Note**: No `ConnectionEstablished` event is produced for this connection.
+    ConnectionFiltered {
+        /// Id of the peer, if known.
+        peer_id: Option<PeerId>,
+        /// Id of the connection that has been refused, or `None` if we refused to dial.
+        connection_id: Option<ConnectionId>,
+        /// Endpoint of the connection that has been refused.
+        endpoint: ConnectedPoint,
+        /// Why the connection has been refused.
+        reason: FilterRejection,
+    },
 
-        /// Returns the number of multiaddresses that still need to be attempted in order to reach
-        /// `expected_peer_id`. If this is non-zero, then there's still a chance we can connect to
-        /// this node. If this is zero, then we have definitely failed.
-        remain_addrs_attempt: usize,
+    /// We refused to dial an address because previous attempts to dial it have failed recently.
+    /// See the `dial_backoff` module.
+    DialBackedOff {
+        /// Id of the peer we wanted to reach, if known.
+        peer_id: Option<PeerId>,
+        /// The address that hasn't been dialed.
+        address: Multiaddr,
+        /// Instant after which the address can be dialed again.
+        until: Instant,
     },
 
-    /// A node produced a custom event.
-    NodeEvent {
-        /// Id of the node that produced the event.
+    /// A connection to a peer we're already connected to has been established, and has been
+    /// closed immediately because of the `DuplicateConnectionPolicy` of the swarm. The existing
+    /// connection is kept.
+    ///
+    /// > **Note**: No `ConnectionEstablished` event is produced for this connection.
+    DuplicateConnectionRejected {
+        /// Id of the peer.
         peer_id: PeerId,
-        /// Event that was produced by the node.
-        event: TOutEvent,
+        /// Id of the connection that has been rejected.
+        connection_id: ConnectionId,
+        /// Endpoint of the connection that has been rejected.
+        endpoint: ConnectedPoint,
     },
-}
 
-/// How we connected to a node.
-#[derive(Debug, Clone)]
-pub enum ConnectedPoint {
-    /// We dialed the node.
-    Dialer {
-        /// Multiaddress that was successfully dialed.
+    /// A new external address has been added with `add_external_address`.
+    NewExternalAddress {
+        /// The address.
         address: Multiaddr,
     },
-    /// We received the node.
-    Listener {
-        /// Address of the listener that received the connection.
-        listen_addr: Multiaddr,
-        /// Address to send back data to the remote.
-        send_back_addr: Multiaddr,
+
+    /// An external address has been removed, either with `remove_external_address` or because
+    /// it had the lowest score when the limit of external addresses was reached.
+    ExpiredExternalAddress {
+        /// The address.
+        address: Multiaddr,
+    },
+
+    /// A peer dialed with `dial_peer` couldn't be reached through any of its addresses, including
+    /// the ones found by the lookup on the network, if any.
+    ///
+    /// > **Note**: A `DialFailure` event has already been produced for each of the addresses.
+    PeerUnreachable {
+        /// Id of the peer.
+        peer_id: PeerId,
+        /// The addresses that have been tried, with the error that happened for each of them.
+        /// Empty if no address could be found.
+        errors: Vec<(Multiaddr, DialError)>,
     },
 }
 
-impl From<ConnectedPoint> for Endpoint {
-    #[inline]
-    fn from(endpoint: ConnectedPoint) -> Endpoint {
-        match endpoint {
-            ConnectedPoint::Dialer { .. } => Endpoint::Dialer,
-            ConnectedPoint::Listener { .. } => Endpoint::Listener,
-        }
-    }
+/// Reason why a connection has been closed.
+#[derive(Debug)]
+pub enum CloseCause {
+    /// Both the inbound and outbound channels have been closed, and no more outbound substream
+    /// was pending.
+    Graceful,
+    /// The muxer of the connection has produced an error.
+    Error(IoError),
+    /// The handler of the connection panicked. Contains the message of the panic.
+    ///
+    /// Only this connection is affected by the panic; the other connections and the swarm keep
+    /// running.
+    HandlerPanicked(String),
+    /// The connection has been replaced with a new connection to the same peer.
+    Replaced,
+    /// The peer has been banned with `ban_peer`.
+    Banned,
+    /// The connection has been closed with `disconnect_peer`.
+    Disconnected,
+    /// The swarm is shutting down, and the connection didn't finish closing gracefully before
+    /// the deadline.
+    ShutdownDeadline,
 }
 
-impl ConnectedPoint {
-    /// Returns true if we are `Dialer`.
-    #[inline]
-    pub fn is_dialer(&self) -> bool {
-        match *self {
-            ConnectedPoint::Dialer { .. } => true,
-            ConnectedPoint::Listener { .. } => false,
-        }
-    }
+/// Reason why dialing an address has failed.
+#[derive(Debug)]
+pub enum DialError {
+    /// The transport failed to reach the address or to negotiate the connection.
+    Transport(IoError),
+    /// We successfully connected to a remote whose peer id doesn't match what we expected.
+    PeerIdMismatch {
+        /// Id of the peer we actually obtained.
+        actual_peer_id: PeerId,
+    },
+}
 
-    /// Returns true if we are `Listener`.
-    #[inline]
-    pub fn is_listener(&self) -> bool {
+impl DialError {
+    /// Builds a copy of this error. `IoError` isn't `Clone`, so the copy of a `Transport` error
+    /// only keeps its kind and its message.
+    fn duplicate(&self) -> DialError {
         match *self {
-            ConnectedPoint::Dialer { .. } => false,
-            ConnectedPoint::Listener { .. } => true,
+            DialError::Transport(ref err) => {
+                DialError::Transport(IoError::new(err.kind(), err.to_string()))
+            },
+            DialError::PeerIdMismatch { ref actual_peer_id } => {
+                DialError::PeerIdMismatch { actual_peer_id: actual_peer_id.clone() }
+            },
         }
     }
 }
 
-/// Trait for structures that can create new factories.
-pub trait HandlerFactory {
-    /// The generated handler.
-    type Handler;
-
-    /// Creates a new handler.
-    fn new_handler(&self, endpoint: ConnectedPoint) -> Self::Handler;
+/// Reason why `dial_peer` couldn't start dialing a peer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DialPeerError {
+    /// The peer is banned.
+    Banned,
+    /// The peer is rejected by the `AddressFilter`.
+    Filtered,
+    /// No `AddressResolver` has been set with `set_address_resolver`.
+    NoResolver,
+    /// The resolver doesn't know any address of the peer that isn't in back-off, and can't look
+    /// for the peer on the network.
+    NoAddresses,
 }
 
-impl<T, THandler> HandlerFactory for T where T: Fn(ConnectedPoint) -> THandler {
-    type Handler = THandler;
+/// State of an attempt to reach a peer started with `dial_peer`.
+struct PeerDial {
+    /// Addresses that have been tried, with the error that happened for each of them.
+    errors: Vec<(Multiaddr, DialError)>,
+    /// Lookup of the addresses of the peer on the network, if one is in progress.
+    lookup: Option<AddressLookup>,
+    /// True if a lookup has been started. At most one lookup is performed for each attempt.
+    lookup_started: bool,
+}
 
+impl PeerDial {
     #[inline]
-    fn new_handler(&self, endpoint: ConnectedPoint) -> THandler {
-        (*self)(endpoint)
+    fn new() -> PeerDial {
+        PeerDial {
+            errors: Vec::new(),
+            lookup: None,
+            lookup_started: false,
+        }
     }
 }
 
@@ -279,859 +463,846 @@ where
     pub fn new(transport: TTrans) -> Swarm<TTrans, TInEvent, TOutEvent, fn(ConnectedPoint) -> THandler>
     where THandler: Default,
     {
-        // TODO: with_capacity?
         Swarm {
-            listeners: ListenersStream::new(transport),
-            active_nodes: CollectionStream::new(),
-            reach_attempts: ReachAttempts {
-                out_reach_attempts: Default::default(),
-                other_reach_attempts: Vec::new(),
-                connected_endpoints: Default::default(),
-            },
-            handler_build: |_| Default::default(),
+            raw: RawSwarm::new(transport),
+            pending_events: VecDeque::new(),
+            banned_peers: FnvHashMap::default(),
+            address_filter: AddressFilter::new(),
+            external_addrs: ExternalAddresses::new(),
+            address_translator: AddressTranslator::new(),
+            dial_backoff: DialBackoff::new(),
+            address_resolver: None,
+            peer_dials: FnvHashMap::default(),
+            closing: false,
+            close_deadline: None,
         }
     }
 
     /// Same as `new`, but lets you specify a way to build a node handler.
     #[inline]
     pub fn with_handler_builder(transport: TTrans, handler_build: THandlerBuild) -> Self {
-        // TODO: with_capacity?
-        Swarm {
-            listeners: ListenersStream::new(transport),
-            active_nodes: CollectionStream::new(),
-            reach_attempts: ReachAttempts {
-                out_reach_attempts: Default::default(),
-                other_reach_attempts: Vec::new(),
-                connected_endpoints: Default::default(),
-            },
-            handler_build,
-        }
-    }
-
-    /// Returns the transport passed when building this object.
-    #[inline]
-    pub fn transport(&self) -> &TTrans {
-        self.listeners.transport()
+        Swarm::with_runtime(transport, handler_build, Runtime::default())
     }
 
-    /// Start listening on the given multiaddress.
+    /// Same as `with_handler_builder`, but the node tasks are spawned and the timers are created
+    /// with the given `Runtime`. See the `runtime` module.
     #[inline]
-    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<Multiaddr, Multiaddr> {
-        self.listeners.listen_on(addr)
+    pub fn with_runtime(transport: TTrans, handler_build: THandlerBuild, runtime: Runtime) -> Self {
+        Swarm {
+            raw: RawSwarm::with_runtime(transport, handler_build, runtime),
+            pending_events: VecDeque::new(),
+            banned_peers: FnvHashMap::default(),
+            address_filter: AddressFilter::new(),
+            external_addrs: ExternalAddresses::new(),
+            address_translator: AddressTranslator::new(),
+            dial_backoff: DialBackoff::new(),
+            address_resolver: None,
+            peer_dials: FnvHashMap::default(),
+            closing: false,
+            close_deadline: None,
+        }
     }
 
-    /// Returns an iterator that produces the list of addresses we're listening on.
+    /// Sets the `AddressResolver` that `dial_peer` uses to find the addresses of the peers.
     #[inline]
-    pub fn listeners(&self) -> impl Iterator<Item = &Multiaddr> {
-        self.listeners.listeners()
+    pub fn set_address_resolver<TResolver>(&mut self, resolver: TResolver)
+    where
+        TResolver: AddressResolver + Send + 'static,
+    {
+        self.address_resolver = Some(Box::new(resolver));
     }
 
-    /// Call this function in order to know which address remotes should dial in order to access
-    /// your local node.
+    /// Dials an address, unless it is rejected by the `AddressFilter` or is backed off.
     ///
-    /// `observed_addr` should be an address a remote observes you as, which can be obtained for
-    /// example with the identify protocol.
+    /// Same as `RawSwarm::dial`, except that a filtered address produces a `ConnectionFiltered`
+    /// event and is given back, and an address that recently failed to be dialed produces a
+    /// `DialBackedOff` event and is given back.
     ///
-    /// For each listener, calls `nat_traversal` with the observed address and returns the outcome.
-    #[inline]
-    pub fn nat_traversal<'a>(
-        &'a self,
-        observed_addr: &'a Multiaddr,
-    ) -> impl Iterator<Item = Multiaddr> + 'a
-        where TMuxer: 'a,
-              THandler: 'a,
-    {
-        self.listeners()
-            .flat_map(move |server| self.transport().nat_traversal(server, observed_addr))
-    }
-
-    /// Dials a multiaddress without knowing the peer ID we're going to obtain.
-    pub fn dial(&mut self, addr: Multiaddr) -> Result<(), Multiaddr>
+    /// If the address ends with `/p2p/<peer id>` and the peer is banned, it is given back as well.
+    /// Otherwise, the connection is rejected with a `BannedPeerRejected` event if it turns out to
+    /// reach a banned peer.
+    ///
+    /// The returned `DialAttempt` reports the progress of the attempt and can be used to cancel
+    /// it. See the `dial_attempt` module.
+    pub fn dial(&mut self, addr: Multiaddr) -> Result<DialAttempt, Multiaddr>
     where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
         TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
+        TMuxer: Send + Sync + 'static,
         TMuxer::OutboundSubstream: Send,
         TMuxer::Substream: Send,
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
     {
-        let future = match self.transport().clone().dial(addr.clone()) {
-            Ok(fut) => fut,
-            Err((_, addr)) => return Err(addr),
-        };
-
-        let endpoint = ConnectedPoint::Dialer { address: addr.clone() };
-
-        let reach_id = self.active_nodes.add_reach_attempt(future, self.handler_build.new_handler(endpoint));
-        self.reach_attempts.other_reach_attempts
-            .push((reach_id, ConnectedPoint::Dialer { address: addr }));
-        Ok(())
-    }
-
-    /// Returns the number of incoming connections that are currently in the process of being
-    /// negotiated.
-    ///
-    /// We don't know anything about these connections yet, so all we can do is know how many of
-    /// them we have.
-    // TODO: thats's not true as we should be able to know their multiaddress, but that requires
-    // a lot of API changes
-    #[inline]
-    pub fn num_incoming_negotiated(&self) -> usize {
-        self.reach_attempts.other_reach_attempts
-            .iter()
-            .filter(|&(_, endpoint)| endpoint.is_listener())
-            .count()
-    }
-
-    /// Returns true if the swarm is connected or attempting to connect to any node.
-    pub fn has_connections_or_pending(&self) -> bool {
-        !self.reach_attempts.out_reach_attempts.is_empty() ||
-            !self.reach_attempts.other_reach_attempts.is_empty() ||
-            !self.reach_attempts.connected_endpoints.is_empty()
-    }
-
-    /// Sends an event to all nodes.
-    #[inline]
-    pub fn broadcast_event(&mut self, event: &TInEvent)
-    where TInEvent: Clone,
-    {
-        self.active_nodes.broadcast_event(event)
-    }
-
-    /// Grants access to a struct that represents a peer.
-    #[inline]
-    pub fn peer(&mut self, peer_id: PeerId) -> Peer<TTrans, TInEvent, TOutEvent, THandlerBuild> {
-        // TODO: we do `peer_mut(...).is_some()` followed with `peer_mut(...).unwrap()`, otherwise
-        // the borrow checker yells at us.
-
-        if self.active_nodes.peer_mut(&peer_id).is_some() {
-            debug_assert!(!self.reach_attempts.out_reach_attempts.contains_key(&peer_id));
-            return Peer::Connected(PeerConnected {
-                peer: self
-                    .active_nodes
-                    .peer_mut(&peer_id)
-                    .expect("we checked for Some just above"),
-                peer_id,
-                connected_endpoints: &mut self.reach_attempts.connected_endpoints,
+        if let Err(reason) = self.address_filter.check(&addr, None) {
+            debug!("Not dialing {}: {}", addr, reason);
+            self.pending_events.push_back(SwarmEvent::ConnectionFiltered {
+                peer_id: None,
+                connection_id: None,
+                endpoint: ConnectedPoint::Dialer { address: addr.clone() },
+                reason,
             });
+            return Err(addr);
         }
 
-        if self.reach_attempts.out_reach_attempts.get_mut(&peer_id).is_some() {
-            debug_assert!(!self.reach_attempts.connected_endpoints.contains_key(&peer_id));
-            return Peer::PendingConnect(PeerPendingConnect {
-                attempt: match self.reach_attempts.out_reach_attempts.entry(peer_id.clone()) {
-                    Entry::Occupied(e) => e,
-                    Entry::Vacant(_) => panic!("we checked for Some just above"),
-                },
-                active_nodes: &mut self.active_nodes,
+        if let Some(until) = self.dial_backoff.backed_off_until(&addr, self.raw.runtime().now()) {
+            debug!("Not dialing {}: backed off until {:?}", addr, until);
+            self.pending_events.push_back(SwarmEvent::DialBackedOff {
+                peer_id: None,
+                address: addr.clone(),
+                until,
             });
+            return Err(addr);
         }
 
-        debug_assert!(!self.reach_attempts.connected_endpoints.contains_key(&peer_id));
-        Peer::NotConnected(PeerNotConnected {
-            nodes: self,
-            peer_id,
-        })
+        self.raw.dial(addr)
     }
 
-    /// Starts dialing out a multiaddress. `rest` is the list of multiaddresses to attempt if
-    /// `first` fails.
+    /// Dials a peer by its `PeerId`.
     ///
-    /// It is a logic error to call this method if we already have an outgoing attempt to the
-    /// given peer.
-    fn start_dial_out(&mut self, peer_id: PeerId, first: Multiaddr, rest: Vec<Multiaddr>)
-    where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-        TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
-        TMuxer::OutboundSubstream: Send,
-        TMuxer::Substream: Send,
-        TInEvent: Send + 'static,
-        TOutEvent: Send + 'static,
-    {
-        let endpoint = ConnectedPoint::Dialer { address: first.clone() };
-        let reach_id = match self.transport().clone().dial(first.clone()) {
-            Ok(fut) => {
-                self.active_nodes.add_reach_attempt(fut, self.handler_build.new_handler(endpoint))
-            },
-            Err((_, addr)) => {
-                let msg = format!("unsupported multiaddr {}", addr);
-                let fut = future::err(IoError::new(IoErrorKind::Other, msg));
-                self.active_nodes.add_reach_attempt(fut, self.handler_build.new_handler(endpoint))
-            },
-        };
-
-        let former = self.reach_attempts.out_reach_attempts.insert(
-            peer_id,
-            OutReachAttempt {
-                id: reach_id,
-                cur_attempted: first,
-                next_attempts: rest,
-            },
-        );
-
-        debug_assert!(former.is_none());
-    }
-
-    /// Provides an API similar to `Stream`, except that it cannot error.
-    pub fn poll(&mut self) -> Async<Option<SwarmEvent<TTrans, TOutEvent>>>
+    /// The known addresses of the peer that aren't in back-off are obtained from the
+    /// `AddressResolver` and are tried one by one. If there is none, or if they all fail, the
+    /// resolver is asked to look for the peer on the network and the addresses it finds are tried
+    /// as well. If the peer can't be reached, a `PeerUnreachable` event is produced with the
+    /// errors of all the addresses.
+    ///
+    /// Does nothing if we are already connected to this peer. If we are already dialing it, the
+    /// outcome of the existing attempt is reported.
+    pub fn dial_peer(&mut self, peer_id: PeerId) -> Result<(), DialPeerError>
     where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
         TTrans::Dial: Send + 'static,
-        TTrans::ListenerUpgrade: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
+        TMuxer: Send + Sync + 'static,
         TMuxer::OutboundSubstream: Send,
         TMuxer::Substream: Send,
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
-        THandlerBuild: HandlerFactory<Handler = THandler>,
-        THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent> + Send + 'static,
-        THandler::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
     {
-        // Start by polling the listeners for events.
-        match self.listeners.poll() {
-            Async::NotReady => (),
-            Async::Ready(Some(ListenersEvent::Incoming {
-                upgrade,
-                listen_addr,
-                send_back_addr,
-            })) => {
-                let endpoint = ConnectedPoint::Listener {
-                    listen_addr: listen_addr.clone(),
-                    send_back_addr: send_back_addr.clone(),
-                };
+        if self.is_banned(&peer_id) {
+            return Err(DialPeerError::Banned);
+        }
 
-                let id = self.active_nodes.add_reach_attempt(upgrade, self.handler_build.new_handler(endpoint));
-                self.reach_attempts.other_reach_attempts.push((
-                    id,
-                    ConnectedPoint::Listener {
-                        listen_addr: listen_addr.clone(),
-                        send_back_addr: send_back_addr.clone(),
-                    },
-                ));
-                return Async::Ready(Some(SwarmEvent::IncomingConnection {
-                    listen_addr,
-                    send_back_addr,
-                }));
-            }
-            Async::Ready(Some(ListenersEvent::Closed {
-                listen_addr,
-                listener,
-                result,
-            })) => {
-                return Async::Ready(Some(SwarmEvent::ListenerClosed {
-                    listen_addr,
-                    listener,
-                    result,
-                }));
-            }
-            Async::Ready(None) => unreachable!("The listeners stream never finishes"),
+        if let Err(reason) = self.address_filter.check_peer(&peer_id) {
+            debug!("Not dialing {:?}: {}", peer_id, reason);
+            return Err(DialPeerError::Filtered);
         }
 
-        // Poll the existing nodes.
-        loop {
-            let (action, out_event);
-            match self.active_nodes.poll() {
-                Async::NotReady => break,
-                Async::Ready(Some(CollectionEvent::NodeReached(reach_event))) => {
-                    let (a, e) = handle_node_reached(&mut self.reach_attempts, reach_event);
-                    action = a;
-                    out_event = e;
-                }
-                Async::Ready(Some(CollectionEvent::ReachError { id, error })) => {
-                    let (a, e) = handle_reach_error(&mut self.reach_attempts, id, error);
-                    action = a;
-                    out_event = e;
-                }
-                Async::Ready(Some(CollectionEvent::NodeError {
-                    peer_id,
-                    error,
-                })) => {
-                    let endpoint = self.reach_attempts.connected_endpoints.remove(&peer_id)
-                        .expect("we insert in connected_endpoints whenever we receive a \
-                                 connection ; NodeError is only ever received for nodes that \
-                                 are connected ; therefore we always have an entry for this peer \
-                                 ; qed");
-                    debug_assert!(!self.reach_attempts.out_reach_attempts.contains_key(&peer_id));
-                    action = Default::default();
-                    out_event = SwarmEvent::NodeError {
-                        peer_id,
-                        endpoint,
-                        error,
-                    };
-                }
-                Async::Ready(Some(CollectionEvent::NodeClosed { peer_id })) => {
-                    let endpoint = self.reach_attempts.connected_endpoints.remove(&peer_id)
-                        .expect("we insert in connected_endpoints whenever we receive a \
-                                 connection ; NodeClosed is only ever received for nodes that \
-                                 are connected ; therefore we always have an entry for this peer \
-                                 ; qed");
-                    debug_assert!(!self.reach_attempts.out_reach_attempts.contains_key(&peer_id));
-                    action = Default::default();
-                    out_event = SwarmEvent::NodeClosed { peer_id, endpoint };
-                }
-                Async::Ready(Some(CollectionEvent::NodeEvent { peer_id, event })) => {
-                    action = Default::default();
-                    out_event = SwarmEvent::NodeEvent { peer_id, event };
-                }
-                Async::Ready(None) => unreachable!("CollectionStream never ends"),
-            };
+        match self.raw.peer(peer_id.clone()) {
+            Peer::Connected(_) => return Ok(()),
+            Peer::PendingConnect(_) => {
+                self.peer_dials.entry(peer_id).or_insert_with(PeerDial::new);
+                return Ok(());
+            },
+            Peer::NotConnected(_) => (),
+        }
 
-            if let Some((peer_id, first, rest)) = action.start_dial_out {
-                self.start_dial_out(peer_id, first, rest);
-            }
+        if self.peer_dials.contains_key(&peer_id) {
+            // A lookup is in progress.
+            return Ok(());
+        }
 
-            if let Some(interrupt) = action.interrupt {
-                // TODO: improve proof or remove ; this is too complicated right now
-                self.active_nodes
-                    .interrupt(interrupt)
-                    .expect("interrupt is guaranteed to be gathered from `out_reach_attempts` ;
-                             we insert in out_reach_attempts only when we call \
-                             active_nodes.add_reach_attempt, and we remove only when we call \
-                             interrupt or when a reach attempt succeeds or errors ; therefore the \
-                             out_reach_attempts should always be in sync with the actual \
-                             attempts ; qed");
-            }
+        let known = match self.address_resolver {
+            Some(ref mut resolver) => resolver.known_addresses(&peer_id),
+            None => return Err(DialPeerError::NoResolver),
+        };
 
-            return Async::Ready(Some(out_event));
-        }
+        self.dial_peer_addresses(peer_id, PeerDial::new(), known)
+            .map_err(|_| DialPeerError::NoAddresses)
+    }
 
-        Async::NotReady
+    /// Closes the connection to the given peer, or interrupts the attempt to connect to it.
+    ///
+    /// Produces a `ConnectionClosed` event with `CloseCause::Disconnected` if we were connected.
+    /// Returns true if we were connected or connecting to this peer.
+    pub fn disconnect_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.close_peer(peer_id, CloseCause::Disconnected)
     }
-}
 
-/// Internal struct indicating an action to perform of the swarm.
-#[derive(Debug, Default)]
-#[must_use]
-struct ActionItem {
-    start_dial_out: Option<(PeerId, Multiaddr, Vec<Multiaddr>)>,
-    interrupt: Option<ReachAttemptId>,
-}
+    /// Bans the given peer for the given duration, or forever if `None`.
+    ///
+    /// The connection to the peer, if any, is closed and a `ConnectionClosed` event with
+    /// `CloseCause::Banned` is produced. While the peer is banned, `dial_peer` returns
+    /// `DialPeerError::Banned`, `dial` and `peer().connect()` refuse to dial it, and connections
+    /// to it are closed as soon as its id is known and produce a `BannedPeerRejected` event.
+    ///
+    /// Banning a peer that is already banned replaces the duration of the ban.
+    pub fn ban_peer(&mut self, peer_id: PeerId, duration: Option<Duration>) {
+        let now = self.raw.runtime().now();
+        let until = duration.map(|duration| now + duration);
+        self.banned_peers.insert(peer_id.clone(), until);
+        self.raw.deny_peer(peer_id.clone(), true);
+        self.close_peer(&peer_id, CloseCause::Banned);
+        self.pending_events.push_back(SwarmEvent::PeerBanned { peer_id, until });
+    }
 
-/// Handles a node reached event from the collection.
-///
-/// Returns an event to return from the stream.
-///
-/// > **Note**: The event **must** have been produced by the collection of nodes, otherwise
-/// >           panics will likely happen.
-fn handle_node_reached<TTrans, TMuxer, TInEvent, TOutEvent>(
-    reach_attempts: &mut ReachAttempts,
-    event: CollectionReachEvent<TInEvent, TOutEvent>
-) -> (ActionItem, SwarmEvent<TTrans, TOutEvent>)
-where
-    TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-    TTrans::Dial: Send + 'static,
-    TMuxer: StreamMuxer + Send + Sync + 'static,
-    TMuxer::OutboundSubstream: Send,
-    TMuxer::Substream: Send,
-    TInEvent: Send + 'static,
-    TOutEvent: Send + 'static,
-{
-    // We first start looking in the incoming attempts. While this makes the code less optimal,
-    // it also makes the logic easier.
-    if let Some(in_pos) = reach_attempts
-        .other_reach_attempts
-        .iter()
-        .position(|i| i.0 == event.reach_attempt_id())
-    {
-        let (_, endpoint) = reach_attempts.other_reach_attempts.swap_remove(in_pos);
-
-        // Clear the known multiaddress for this peer.
-        let closed_endpoint = reach_attempts.connected_endpoints.insert(event.peer_id().clone(), endpoint.clone());
-        // Cancel any outgoing attempt to this peer.
-        let action = if let Some(attempt) = reach_attempts.out_reach_attempts.remove(&event.peer_id()) {
-            debug_assert_ne!(attempt.id, event.reach_attempt_id());
-            ActionItem {
-                interrupt: Some(attempt.id),
-                .. Default::default()
-            }
+    /// Lifts the ban of the given peer. Returns false if the peer wasn't banned.
+    pub fn unban_peer(&mut self, peer_id: &PeerId) -> bool {
+        let was_banned = self.is_banned(peer_id);
+        self.banned_peers.remove(peer_id);
+        self.raw.deny_peer(peer_id.clone(), false);
+        if was_banned {
+            self.pending_events.push_back(SwarmEvent::PeerUnbanned { peer_id: peer_id.clone() });
+            true
         } else {
-            ActionItem::default()
-        };
+            false
+        }
+    }
 
-        let (outcome, peer_id) = event.accept();
-        if let Some(closed_endpoint) = closed_endpoint {
-            debug_assert_eq!(outcome, CollectionNodeAccept::ReplacedExisting);
-            return (action, SwarmEvent::Replaced {
-                peer_id,
-                endpoint,
-                closed_endpoint,
-            });
-        } else {
-            debug_assert_eq!(outcome, CollectionNodeAccept::NewEntry);
-            return (action, SwarmEvent::Connected { peer_id, endpoint });
+    /// Returns true if the given peer is currently banned.
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        match self.banned_peers.get(peer_id) {
+            Some(&Some(until)) => until > self.raw.runtime().now(),
+            Some(&None) => true,
+            None => false,
         }
     }
 
-    // Otherwise, try for outgoing attempts.
-    let is_outgoing_and_ok = if let Some(attempt) = reach_attempts.out_reach_attempts.get(event.peer_id()) {
-        attempt.id == event.reach_attempt_id()
-    } else {
-        false
-    };
+    /// Returns the list of peers that are currently banned.
+    pub fn banned_peers(&self) -> impl Iterator<Item = &PeerId> {
+        let now = self.raw.runtime().now();
+        self.banned_peers
+            .iter()
+            .filter(move |&(_, until)| until.map(|until| until > now).unwrap_or(true))
+            .map(|(peer_id, _)| peer_id)
+    }
 
-    // We only remove the attempt from `out_reach_attempts` if it both matches the reach id
-    // and the expected peer id.
-    if is_outgoing_and_ok {
-        let attempt = reach_attempts.out_reach_attempts.remove(event.peer_id())
-            .expect("is_outgoing_and_ok is true only if reach_attempts.out_reach_attempts.get(event.peer_id()) \
-                        returned Some");
+    /// Returns the rules that decide which connections are accepted.
+    #[inline]
+    pub fn address_filter(&self) -> &AddressFilter {
+        &self.address_filter
+    }
 
-        let endpoint = ConnectedPoint::Dialer {
-            address: attempt.cur_attempted,
-        };
-        let closed_endpoint = reach_attempts.connected_endpoints
-            .insert(event.peer_id().clone(), endpoint.clone());
-
-        let (outcome, peer_id) = event.accept();
-        if let Some(closed_endpoint) = closed_endpoint {
-            debug_assert_eq!(outcome, CollectionNodeAccept::ReplacedExisting);
-            return (Default::default(), SwarmEvent::Replaced {
-                peer_id,
-                endpoint,
-                closed_endpoint,
-            });
-        } else {
-            debug_assert_eq!(outcome, CollectionNodeAccept::NewEntry);
-            return (Default::default(), SwarmEvent::Connected { peer_id, endpoint });
-        }
-    }
-
-    // If in neither, check outgoing reach attempts again as we may have a public
-    // key mismatch.
-    let expected_peer_id = reach_attempts
-        .out_reach_attempts
-        .iter()
-        .find(|(_, a)| a.id == event.reach_attempt_id())
-        .map(|(p, _)| p.clone());
-    if let Some(expected_peer_id) = expected_peer_id {
-        debug_assert_ne!(&expected_peer_id, event.peer_id());
-        let attempt = reach_attempts.out_reach_attempts.remove(&expected_peer_id)
-            .expect("expected_peer_id is a key that is grabbed from out_reach_attempts");
-
-        let num_remain = attempt.next_attempts.len();
-        let failed_addr = attempt.cur_attempted.clone();
-
-        let peer_id = event.deny();
-
-        let action = if !attempt.next_attempts.is_empty() {
-            let mut attempt = attempt;
-            let next = attempt.next_attempts.remove(0);
-            ActionItem {
-                start_dial_out: Some((expected_peer_id.clone(), next, attempt.next_attempts)),
-                .. Default::default()
-            }
-        } else {
-            Default::default()
-        };
+    /// Replaces the rules that decide which connections are accepted.
+    ///
+    /// > **Note**: The existing connections aren't checked against the new rules. Use
+    /// >           `disconnect_peer` to close them if needed.
+    #[inline]
+    pub fn set_address_filter(&mut self, filter: AddressFilter) {
+        self.address_filter = filter;
+    }
 
-        return (action, SwarmEvent::PublicKeyMismatch {
-            remain_addrs_attempt: num_remain,
-            expected_peer_id,
-            actual_peer_id: peer_id,
-            multiaddr: failed_addr,
-        });
-    }
-
-    // We didn't find any entry in neither the outgoing connections not ingoing connections.
-    // TODO: improve proof or remove ; this is too complicated right now
-    panic!("The API of collection guarantees that the id sent back in NodeReached (which is where \
-            we call handle_node_reached) is one that was passed to add_reach_attempt. Whenever we \
-            call add_reach_attempt, we also insert at the same time an entry either in \
-            out_reach_attempts or in other_reach_attempts. It is therefore guaranteed that we \
-            find back this ID in either of these two sets");
-}
+    /// Returns the instant before which the given address shouldn't be dialed because previous
+    /// attempts to dial it have failed, or `None` if it can be dialed right now.
+    #[inline]
+    pub fn dial_backoff_until(&self, address: &Multiaddr) -> Option<Instant> {
+        self.dial_backoff.backed_off_until(address, self.raw.runtime().now())
+    }
 
-/// Handles a reach error event from the collection.
-///
-/// Optionally returns an event to return from the stream.
-///
-/// > **Note**: The event **must** have been produced by the collection of nodes, otherwise
-/// >           panics will likely happen.
-fn handle_reach_error<TTrans, TOutEvent>(
-    reach_attempts: &mut ReachAttempts,
-    reach_id: ReachAttemptId,
-    error: IoError,
-) -> (ActionItem, SwarmEvent<TTrans, TOutEvent>)
-where TTrans: Transport
-{
-    // Search for the attempt in `out_reach_attempts`.
-    // TODO: could be more optimal than iterating over everything
-    let out_reach_peer_id = reach_attempts
-        .out_reach_attempts
-        .iter()
-        .find(|(_, a)| a.id == reach_id)
-        .map(|(p, _)| p.clone());
-    if let Some(peer_id) = out_reach_peer_id {
-        let mut attempt = reach_attempts.out_reach_attempts.remove(&peer_id)
-            .expect("out_reach_peer_id is a key that is grabbed from out_reach_attempts");
-
-        let num_remain = attempt.next_attempts.len();
-        let failed_addr = attempt.cur_attempted.clone();
-
-        let action = if !attempt.next_attempts.is_empty() {
-            let mut attempt = attempt;
-            let next_attempt = attempt.next_attempts.remove(0);
-            ActionItem {
-                start_dial_out: Some((peer_id.clone(), next_attempt, attempt.next_attempts)),
-                .. Default::default()
-            }
-        } else {
-            Default::default()
-        };
+    /// Forgets the dialing failures of the given address. Returns false if there wasn't any.
+    #[inline]
+    pub fn reset_dial_backoff(&mut self, address: &Multiaddr) -> bool {
+        self.dial_backoff.reset(address)
+    }
 
-        return (action, SwarmEvent::DialError {
-            remain_addrs_attempt: num_remain,
-            peer_id,
-            multiaddr: failed_addr,
-            error,
-        });
+    /// Returns the configuration of the dial back-off.
+    #[inline]
+    pub fn dial_backoff_config(&self) -> &DialBackoffConfig {
+        self.dial_backoff.config()
     }
 
-    // If this is not an outgoing reach attempt, check the incoming reach attempts.
-    if let Some(in_pos) = reach_attempts
-        .other_reach_attempts
-        .iter()
-        .position(|i| i.0 == reach_id)
-    {
-        let (_, endpoint) = reach_attempts.other_reach_attempts.swap_remove(in_pos);
-        match endpoint {
-            ConnectedPoint::Dialer { address } => {
-                return (Default::default(), SwarmEvent::UnknownPeerDialError {
-                    multiaddr: address,
-                    error,
-                });
+    /// Changes the configuration of the dial back-off. Only affects the failures that happen
+    /// afterwards.
+    #[inline]
+    pub fn set_dial_backoff_config(&mut self, config: DialBackoffConfig) {
+        self.dial_backoff.set_config(config);
+    }
+
+    /// Adds an address through which we can be reached from the outside, or increases its score
+    /// if it is already known.
+    ///
+    /// Use `AddressScore::Infinite` for addresses that are known to be correct, and a finite score
+    /// for addresses that have been reported by remotes. Produces a `NewExternalAddress` event if
+    /// the address wasn't known, and an `ExpiredExternalAddress` event for each address that has
+    /// been evicted in order to stay within the limit.
+    pub fn add_external_address(&mut self, address: Multiaddr, score: AddressScore) -> AddAddressResult {
+        let result = self.external_addrs.add(address.clone(), score);
+        if let AddAddressResult::Inserted { ref expired } = result {
+            // If the new address has immediately been evicted, it is neither new nor expired.
+            for expired in expired.iter().filter(|a| **a != address) {
+                let address = expired.clone();
+                self.pending_events.push_back(SwarmEvent::ExpiredExternalAddress { address });
             }
-            ConnectedPoint::Listener { listen_addr, send_back_addr } => {
-                return (Default::default(), SwarmEvent::IncomingConnectionError { listen_addr, send_back_addr, error });
+            if !expired.contains(&address) {
+                self.pending_events.push_back(SwarmEvent::NewExternalAddress { address });
             }
         }
+        result
     }
 
-    // The id was neither in the outbound list nor the inbound list.
-    // TODO: improve proof or remove ; this is too complicated right now
-    panic!("The API of collection guarantees that the id sent back in ReachError events \
-            (which is where we call handle_reach_error) is one that was passed to \
-            add_reach_attempt. Whenever we call add_reach_attempt, we also insert \
-            at the same time an entry either in out_reach_attempts or in \
-            other_reach_attempts. It is therefore guaranteed that we find back this ID in \
-            either of these two sets");
-}
+    /// Removes an external address. Produces an `ExpiredExternalAddress` event and returns true
+    /// if the address was known.
+    pub fn remove_external_address(&mut self, address: &Multiaddr) -> bool {
+        if self.external_addrs.remove(address) {
+            let address = address.clone();
+            self.pending_events.push_back(SwarmEvent::ExpiredExternalAddress { address });
+            true
+        } else {
+            false
+        }
+    }
 
-/// State of a peer in the system.
-pub enum Peer<'a, TTrans: 'a, TInEvent: 'a, TOutEvent: 'a, THandlerBuild: 'a>
-where
-    TTrans: Transport,
-{
-    /// We are connected to this peer.
-    Connected(PeerConnected<'a, TInEvent>),
+    /// Returns the external addresses, by decreasing score.
+    #[inline]
+    pub fn external_addresses(&self) -> impl Iterator<Item = &AddressRecord> {
+        self.external_addrs.iter()
+    }
 
-    /// We are currently attempting to connect to this peer.
-    PendingConnect(PeerPendingConnect<'a, TInEvent, TOutEvent>),
+    /// Reports an address a remote observes us as, for example as obtained with the identify
+    /// protocol.
+    ///
+    /// The address is translated with the `AddressTranslator`, and each resulting address is
+    /// added with `add_external_address` and a score of `AddressScore::Finite(1)`.
+    pub fn report_observed_address(&mut self, address: Multiaddr) {
+        let listen_addrs = self.raw.listeners().cloned().collect::<Vec<_>>();
+        for addr in self.address_translator.report_observed(&listen_addrs, address) {
+            self.add_external_address(addr, AddressScore::Finite(1));
+        }
+    }
 
-    /// We are not connected to this peer at all.
+    /// Adds a port mapping that has been opened on the gateway, for example with UPnP or
+    /// NAT-PMP. Replaces the existing mapping with the same internal address, if any.
     ///
-    /// > **Note**: It is however possible that a pending incoming connection is being negotiated
-    /// > and will connect to this peer, but we don't know it yet.
-    NotConnected(PeerNotConnected<'a, TTrans, TInEvent, TOutEvent, THandlerBuild>),
-}
+    /// The resulting addresses are added with `add_external_address` and a score of
+    /// `AddressScore::Infinite`.
+    ///
+    /// > **Note**: The mapping is only translated against the addresses we are listening on at
+    /// >           the time of the call.
+    pub fn add_port_mapping(&mut self, internal: Multiaddr, external: Multiaddr) {
+        let listen_addrs = self.raw.listeners().cloned().collect::<Vec<_>>();
+        let mapping = PortMapping { internal, external };
+        for addr in self.address_translator.add_port_mapping(&listen_addrs, mapping) {
+            self.add_external_address(addr, AddressScore::Infinite);
+        }
+    }
 
-// TODO: add other similar methods that wrap to the ones of `PeerNotConnected`
-impl<'a, TTrans, TMuxer, TInEvent, TOutEvent, THandler, THandlerBuild>
-    Peer<'a, TTrans, TInEvent, TOutEvent, THandlerBuild>
-where
-    TTrans: Transport<Output = (PeerId, TMuxer)>,
-    TMuxer: StreamMuxer,
-    THandlerBuild: HandlerFactory<Handler = THandler>,
-    THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent> + Send + 'static,
-    THandler::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
-{
-    /// If we are connected, returns the `PeerConnected`.
-    #[inline]
-    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent>> {
-        match self {
-            Peer::Connected(peer) => Some(peer),
-            _ => None,
+    /// Removes the port mapping with the given internal address, and the external addresses
+    /// that were only derived from it.
+    pub fn remove_port_mapping(&mut self, internal: &Multiaddr) {
+        let listen_addrs = self.raw.listeners().cloned().collect::<Vec<_>>();
+        for addr in self.address_translator.remove_port_mapping(&listen_addrs, internal) {
+            self.remove_external_address(&addr);
         }
     }
 
-    /// If a connection is pending, returns the `PeerPendingConnect`.
+    /// Replaces the strategies used to translate the observed addresses and the port mappings.
+    /// Only affects the addresses reported afterwards.
     #[inline]
-    pub fn as_pending_connect(self) -> Option<PeerPendingConnect<'a, TInEvent, TOutEvent>> {
-        match self {
-            Peer::PendingConnect(peer) => Some(peer),
-            _ => None,
-        }
+    pub fn set_translation_strategies(&mut self, strategies: Vec<Box<TranslationStrategy + Send>>) {
+        self.address_translator.set_strategies(strategies);
     }
 
-    /// If we are not connected, returns the `PeerNotConnected`.
+    /// Returns the `AddressTranslator`, which holds the observed addresses and the port mappings.
     #[inline]
-    pub fn as_not_connected(self) -> Option<PeerNotConnected<'a, TTrans, TInEvent, TOutEvent, THandlerBuild>> {
-        match self {
-            Peer::NotConnected(peer) => Some(peer),
-            _ => None,
-        }
+    pub fn address_translator(&self) -> &AddressTranslator {
+        &self.address_translator
     }
 
-    /// If we're not connected, opens a new connection to this peer using the given multiaddr.
+    /// Returns the addresses that should be advertised to the rest of the network, for example
+    /// through identify or Kademlia: the external addresses by decreasing score, followed by the
+    /// addresses we listen on.
     #[inline]
-    pub fn or_connect(
-        self,
-        addr: Multiaddr,
-    ) -> Result<PeerPotentialConnect<'a, TInEvent, TOutEvent>, Self>
-    where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-        TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
+    pub fn advertised_addresses(&self) -> Vec<Multiaddr> {
+        self.external_addrs.advertised(self.raw.listeners())
+    }
+
+    /// Starts shutting down the swarm gracefully.
+    ///
+    /// All the listeners are stopped and produce a `ListenerClosed` event, the pending dialing
+    /// attempts are interrupted, and all the connections are asked to close. The handlers are
+    /// notified with `shutdown()` and get a chance to flush their outbound queues, and the remotes
+    /// are notified if the muxer supports it. Each connection produces a `ConnectionClosed` event
+    /// once it has finished closing. The connections that are still open once `timeout` has
+    /// elapsed are closed abruptly, with `CloseCause::ShutdownDeadline`.
+    ///
+    /// The stream ends once all the connections have been closed.
+    ///
+    /// > **Note**: Calling this method multiple times does nothing. The timeout is measured with
+    /// >           the timer of the swarm's `Runtime`.
+    pub fn start_close(&mut self, timeout: Duration) {
+        if self.closing {
+            return;
+        }
+
+        self.closing = true;
+        for (listener_id, listen_addr) in self.raw.start_shutdown() {
+            self.pending_events.push_back(SwarmEvent::ListenerClosed {
+                listener_id,
+                listen_addr,
+                result: Ok(()),
+            });
+        }
+        self.close_deadline = Some(self.raw.runtime().delay_for(timeout));
+    }
+
+    /// Shuts down the swarm gracefully and returns a future that resolves once all the
+    /// connections have been closed. See `start_close`.
+    ///
+    /// The events produced while the connections are being drained are discarded. Use
+    /// `start_close` and keep polling the swarm if you are interested in them.
+    #[inline]
+    pub fn close(mut self, timeout: Duration) -> SwarmClose<TTrans, TInEvent, TOutEvent, THandlerBuild> {
+        self.start_close(timeout);
+        SwarmClose { swarm: self }
+    }
+
+    /// Returns true if `start_close` has been called.
+    #[inline]
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Returns the collection of external addresses.
+    #[inline]
+    pub(crate) fn external_addrs(&self) -> &ExternalAddresses {
+        &self.external_addrs
+    }
+
+    /// Closes the connection or the connection attempt to a peer. If we were connected, pushes
+    /// a `ConnectionClosed` event with the given cause.
+    fn close_peer(&mut self, peer_id: &PeerId, cause: CloseCause) -> bool {
+        self.peer_dials.remove(peer_id);
+        match self.raw.peer(peer_id.clone()) {
+            Peer::Connected(peer) => {
+                let connection_id = peer.connection_id();
+                let endpoint = peer.endpoint().clone();
+                peer.close();
+                self.pending_events.push_back(SwarmEvent::ConnectionClosed {
+                    peer_id: peer_id.clone(),
+                    connection_id,
+                    endpoint,
+                    cause,
+                });
+                true
+            },
+            Peer::PendingConnect(peer) => {
+                peer.interrupt();
+                true
+            },
+            Peer::NotConnected(_) => false,
+        }
+    }
+
+    /// Dials the given addresses as part of a `dial_peer` attempt, ignoring the ones that have
+    /// already been tried and the ones in back-off. If there is no address left, starts a lookup
+    /// if none has been started yet.
+    ///
+    /// Gives back `dial` if there is nothing left to try.
+    fn dial_peer_addresses(&mut self, peer_id: PeerId, mut dial: PeerDial, addrs: Vec<Multiaddr>)
+        -> Result<(), PeerDial>
+    where
+        TTrans::Dial: Send + 'static,
+        TMuxer: Send + Sync + 'static,
         TMuxer::OutboundSubstream: Send,
         TMuxer::Substream: Send,
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
     {
-        self.or_connect_with(move |_| addr)
+        let now = self.raw.runtime().now();
+        let mut to_dial: Vec<Multiaddr> = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            if to_dial.contains(&addr) || dial.errors.iter().any(|&(ref a, _)| *a == addr) {
+                continue;
+            }
+            if let Some(until) = self.dial_backoff.backed_off_until(&addr, now) {
+                trace!("Not dialing {} for {:?} because of back-off", addr, peer_id);
+                self.pending_events.push_back(SwarmEvent::DialBackedOff {
+                    peer_id: Some(peer_id.clone()),
+                    address: addr,
+                    until,
+                });
+                continue;
+            }
+            if let Err(reason) = self.address_filter.check(&addr, Some(&peer_id)) {
+                debug!("Not dialing {} for {:?}: {}", addr, peer_id, reason);
+                self.pending_events.push_back(SwarmEvent::ConnectionFiltered {
+                    peer_id: Some(peer_id.clone()),
+                    connection_id: None,
+                    endpoint: ConnectedPoint::Dialer { address: addr },
+                    reason,
+                });
+                continue;
+            }
+            to_dial.push(addr);
+        }
+
+        if !to_dial.is_empty() {
+            if let Peer::NotConnected(peer) = self.raw.peer(peer_id.clone()) {
+                let _ = peer.connect_iter(to_dial);
+            }
+            self.peer_dials.insert(peer_id, dial);
+            return Ok(());
+        }
+
+        if self.start_lookup(&peer_id, &mut dial) {
+            self.peer_dials.insert(peer_id, dial);
+            return Ok(());
+        }
+
+        Err(dial)
     }
 
-    /// If we're not connected, calls the function passed as parameter and opens a new connection
-    /// using the returned address.
-    #[inline]
-    pub fn or_connect_with<TFn>(
-        self,
-        addr: TFn,
-    ) -> Result<PeerPotentialConnect<'a, TInEvent, TOutEvent>, Self>
+    /// Starts looking for the addresses of the peer on the network, unless a lookup has already
+    /// been started for this attempt. Returns false if no lookup has been started.
+    fn start_lookup(&mut self, peer_id: &PeerId, dial: &mut PeerDial) -> bool {
+        if dial.lookup_started {
+            return false;
+        }
+
+        dial.lookup_started = true;
+        dial.lookup = self.address_resolver.as_mut().and_then(|r| r.lookup(peer_id));
+        if dial.lookup.is_some() {
+            debug!("Looking for the addresses of {:?} on the network", peer_id);
+        }
+        dial.lookup.is_some()
+    }
+
+    /// Polls the address lookups in progress, and dials the addresses they produce.
+    fn poll_lookups(&mut self)
     where
-        TFn: FnOnce(&PeerId) -> Multiaddr,
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
         TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
+        TMuxer: Send + Sync + 'static,
         TMuxer::OutboundSubstream: Send,
         TMuxer::Substream: Send,
         TInEvent: Send + 'static,
         TOutEvent: Send + 'static,
     {
-        match self {
-            Peer::Connected(peer) => Ok(PeerPotentialConnect::Connected(peer)),
-            Peer::PendingConnect(peer) => Ok(PeerPotentialConnect::PendingConnect(peer)),
-            Peer::NotConnected(peer) => {
-                let addr = addr(&peer.peer_id);
-                match peer.connect(addr) {
-                    Ok(peer) => Ok(PeerPotentialConnect::PendingConnect(peer)),
-                    Err(peer) => Err(Peer::NotConnected(peer)),
-                }
+        let mut finished = Vec::new();
+        for (peer_id, dial) in self.peer_dials.iter_mut() {
+            let addrs = match dial.lookup.as_mut().map(|lookup| lookup.poll()) {
+                None | Some(Ok(Async::NotReady)) => continue,
+                Some(Ok(Async::Ready(addrs))) => addrs,
+                Some(Err(err)) => {
+                    debug!("Lookup of the addresses of {:?} failed: {:?}", peer_id, err);
+                    Vec::new()
+                },
+            };
+            dial.lookup = None;
+            finished.push((peer_id.clone(), addrs));
+        }
+
+        for (peer_id, addrs) in finished {
+            let dial = self.peer_dials.remove(&peer_id)
+                .expect("finished only contains entries of peer_dials ; qed");
+            if let Err(dial) = self.dial_peer_addresses(peer_id.clone(), dial, addrs) {
+                debug!("Failed to reach {:?} through {} address(es)", peer_id, dial.errors.len());
+                self.pending_events.push_back(SwarmEvent::PeerUnreachable {
+                    peer_id,
+                    errors: dial.errors,
+                });
             }
         }
     }
-}
 
-/// Peer we are potentially going to connect to.
-pub enum PeerPotentialConnect<'a, TInEvent: 'a, TOutEvent: 'a> {
-    /// We are connected to this peer.
-    Connected(PeerConnected<'a, TInEvent>),
+    /// Records a dialing failure in the `dial_peer` attempt to this peer, if any. If it was the
+    /// last address, starts a lookup or reports that the peer is unreachable.
+    fn inject_peer_dial_failure(&mut self, peer_id: PeerId, multiaddr: Multiaddr, remain_addrs_attempt: usize, error: DialError) {
+        // If the attempt has been cancelled, the peer isn't looked up on the network.
+        let cancelled = match error {
+            DialError::Transport(ref err) => err.kind() == IoErrorKind::Interrupted,
+            DialError::PeerIdMismatch { .. } => false,
+        };
+
+        let finished = match self.peer_dials.get_mut(&peer_id) {
+            Some(dial) => {
+                dial.errors.push((multiaddr, error));
+                remain_addrs_attempt == 0 && dial.lookup.is_none()
+            },
+            None => return,
+        };
 
-    /// We are currently attempting to connect to this peer.
-    PendingConnect(PeerPendingConnect<'a, TInEvent, TOutEvent>),
-}
+        if !finished {
+            return;
+        }
 
-impl<'a, TInEvent, TOutEvent> PeerPotentialConnect<'a, TInEvent, TOutEvent> {
-    /// Closes the connection or the connection attempt.
-    ///
-    /// If the connection was active, returns the list of outbound substream openings that were
-    /// closed in the process.
-    // TODO: consider returning a `PeerNotConnected`
-    #[inline]
-    pub fn close(self) {
-        match self {
-            PeerPotentialConnect::Connected(peer) => peer.close(),
-            PeerPotentialConnect::PendingConnect(peer) => peer.interrupt(),
+        let mut dial = self.peer_dials.remove(&peer_id)
+            .expect("get_mut returned Some just above ; qed");
+        if !cancelled && self.start_lookup(&peer_id, &mut dial) {
+            self.peer_dials.insert(peer_id, dial);
+        } else {
+            debug!("Failed to reach {:?} through {} address(es)", peer_id, dial.errors.len());
+            self.pending_events.push_back(SwarmEvent::PeerUnreachable { peer_id, errors: dial.errors });
         }
     }
 
-    /// If we are connected, returns the `PeerConnected`.
-    #[inline]
-    pub fn as_connected(self) -> Option<PeerConnected<'a, TInEvent>> {
-        match self {
-            PeerPotentialConnect::Connected(peer) => Some(peer),
-            _ => None,
+    /// Closes abruptly all the connections that haven't finished closing gracefully.
+    fn force_close_all(&mut self) {
+        // Interrupts the dialing attempts that have been started after `start_close`.
+        let _ = self.raw.start_shutdown();
+
+        let remaining = self.raw.connected_peers().cloned().collect::<Vec<_>>();
+        for peer_id in remaining {
+            debug!("Connection to {:?} didn't close before the deadline", peer_id);
+            self.close_peer(&peer_id, CloseCause::ShutdownDeadline);
         }
     }
 
-    /// If a connection is pending, returns the `PeerPendingConnect`.
-    #[inline]
-    pub fn as_pending_connect(self) -> Option<PeerPendingConnect<'a, TInEvent, TOutEvent>> {
-        match self {
-            PeerPotentialConnect::PendingConnect(peer) => Some(peer),
-            _ => None,
+    /// Removes the bans that have expired and produces the corresponding events.
+    fn expire_bans(&mut self) {
+        let now = self.raw.runtime().now();
+        let expired = self.banned_peers
+            .iter()
+            .filter(|&(_, until)| until.map(|until| until <= now).unwrap_or(false))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect::<Vec<_>>();
+
+        for peer_id in expired {
+            self.banned_peers.remove(&peer_id);
+            self.raw.deny_peer(peer_id.clone(), false);
+            self.pending_events.push_back(SwarmEvent::PeerUnbanned { peer_id });
         }
     }
-}
 
-/// Access to a peer we are connected to.
-pub struct PeerConnected<'a, TInEvent: 'a> {
-    peer: CollecPeerMut<'a, TInEvent>,
-    /// Reference to the `connected_endpoints` field of the parent.
-    connected_endpoints: &'a mut FnvHashMap<PeerId, ConnectedPoint>,
-    peer_id: PeerId,
-}
+    /// Provides an API similar to `Stream`, except that it cannot error.
+    pub fn poll(&mut self) -> Async<Option<SwarmEvent<TOutEvent>>>
+    where
+        TTrans::Dial: Send + 'static,
+        TTrans::ListenerUpgrade: Send + 'static,
+        TMuxer: Send + Sync + 'static,
+        TMuxer::OutboundSubstream: Send,
+        TMuxer::Substream: Send,
+        TInEvent: Send + 'static,
+        TOutEvent: Send + 'static,
+    {
+        let _entered = self.raw.span().map(|span| span.enter());
 
-impl<'a, TInEvent> PeerConnected<'a, TInEvent> {
-    /// Closes the connection to this node.
-    ///
-    /// No `NodeClosed` message will be generated for this node.
-    // TODO: consider returning a `PeerNotConnected` ; however this makes all the borrows things
-    // much more annoying to deal with
-    pub fn close(self) {
-        self.connected_endpoints.remove(&self.peer_id);
-        self.peer.close()
-    }
+        if !self.banned_peers.is_empty() {
+            self.expire_bans();
+        }
 
-    /// Returns the endpoint we are connected to the remote through.
-    #[inline]
-    pub fn endpoint(&self) -> &ConnectedPoint {
-        self.connected_endpoints.get(&self.peer_id)
-            .expect("we insert in connected_endpoints whenever we receive a connection ; \
-                     a PeerConnected can only ever be created for nodes that are connected ; \
-                     therefore we always have an entry for this peer ; qed")
-    }
+        if !self.dial_backoff.is_empty() {
+            self.dial_backoff.remove_stale(self.raw.runtime().now());
+        }
 
-    /// Sends an event to the node.
-    #[inline]
-    pub fn send_event(&mut self, event: TInEvent) {
-        self.peer.send_event(event)
-    }
-}
+        if !self.peer_dials.is_empty() {
+            self.poll_lookups();
+        }
 
-/// Access to a peer we are attempting to connect to.
-pub struct PeerPendingConnect<'a, TInEvent: 'a, TOutEvent: 'a> {
-    attempt: OccupiedEntry<'a, PeerId, OutReachAttempt>,
-    active_nodes: &'a mut CollectionStream<TInEvent, TOutEvent>,
-}
+        loop {
+            if let Some(event) = self.pending_events.pop_front() {
+                return Async::Ready(Some(event));
+            }
 
-impl<'a, TInEvent, TOutEvent> PeerPendingConnect<'a, TInEvent, TOutEvent> {
-    /// Interrupt this connection attempt.
-    // TODO: consider returning a PeerNotConnected ; however that is really pain in terms of
-    // borrows
-    #[inline]
-    pub fn interrupt(self) {
-        let attempt = self.attempt.remove();
-        if let Err(_) = self.active_nodes.interrupt(attempt.id) {
-            // TODO: improve proof or remove ; this is too complicated right now
-            panic!("We retreived this attempt.id from out_reach_attempts. We insert in \
-                    out_reach_attempts only at the same time as we call add_reach_attempt. \
-                    Whenever we receive a NodeReached, NodeReplaced or ReachError event, which \
-                    invalidate the attempt.id, we also remove the corresponding entry in \
-                    out_reach_attempts.");
+            if self.closing {
+                if let Some(mut deadline) = self.close_deadline.take() {
+                    match deadline.poll() {
+                        Ok(Async::NotReady) => self.close_deadline = Some(deadline),
+                        Ok(Async::Ready(())) => {
+                            self.force_close_all();
+                            continue;
+                        },
+                        Err(err) => {
+                            debug!("Error in the shutdown deadline timer: {:?}", err);
+                            self.force_close_all();
+                            continue;
+                        },
+                    }
+                }
+
+                if !self.raw.has_connections_or_pending() {
+                    return Async::Ready(None);
+                }
+            }
+
+            match self.raw.poll() {
+                Async::NotReady => return Async::NotReady,
+                Async::Ready(None) => return Async::Ready(None),
+                Async::Ready(Some(event)) => self.inject_raw_event(event),
+            }
         }
     }
 
-    /// Returns the multiaddress we're currently trying to dial.
-    #[inline]
-    pub fn attempted_multiaddr(&self) -> &Multiaddr {
-        &self.attempt.get().cur_attempted
-    }
+    /// Translates an event of the raw swarm and pushes the result to `pending_events`.
+    fn inject_raw_event(&mut self, event: RawSwarmEvent<TTrans, TOutEvent>) {
+        match event {
+            RawSwarmEvent::Connected { endpoint: ConnectedPoint::Dialer { ref address }, .. } |
+            RawSwarmEvent::Replaced { endpoint: ConnectedPoint::Dialer { ref address }, .. } => {
+                self.dial_backoff.record_success(address);
+            }
+            // Cancelled attempts say nothing about the address.
+            RawSwarmEvent::DialError { ref error, .. } |
+            RawSwarmEvent::UnknownPeerDialError { ref error, .. }
+                if error.kind() == IoErrorKind::Interrupted => {}
+            RawSwarmEvent::DialError { ref multiaddr, .. } |
+            RawSwarmEvent::UnknownPeerDialError { ref multiaddr, .. } |
+            RawSwarmEvent::PublicKeyMismatch { ref multiaddr, .. } => {
+                let until = self.dial_backoff.record_failure(multiaddr, self.raw.runtime().now());
+                trace!("Backing off from dialing {} until {:?}", multiaddr, until);
+            }
+            _ => {}
+        }
 
-    /// Returns a list of the multiaddresses we're going to try if the current dialing fails.
-    #[inline]
-    pub fn pending_multiaddrs(&self) -> impl Iterator<Item = &Multiaddr> {
-        self.attempt.get().next_attempts.iter()
+        match event {
+            RawSwarmEvent::Connected { ref peer_id, .. } |
+            RawSwarmEvent::Replaced { ref peer_id, .. } |
+            RawSwarmEvent::DuplicateRejected { ref peer_id, .. } |
+            RawSwarmEvent::DeniedRejected { ref peer_id, .. } => {
+                self.peer_dials.remove(peer_id);
+            }
+            _ => {}
+        }
+
+        let event = match event {
+            RawSwarmEvent::NewListenAddr { listener_id, listen_addr } => {
+                SwarmEvent::NewListenAddr { listener_id, listen_addr }
+            }
+            RawSwarmEvent::ExpiredListenAddr { listener_id, listen_addr } => {
+                SwarmEvent::ExpiredListenAddr { listener_id, listen_addr }
+            }
+            RawSwarmEvent::ListenerClosed { listener_id, listen_addr, result, .. } => {
+                if let Err(ref err) = result {
+                    debug!("Listener on {} closed with an error: {:?}", listen_addr, err);
+                }
+                SwarmEvent::ListenerClosed { listener_id, listen_addr, result }
+            }
+            RawSwarmEvent::IncomingConnection { listener_id, connection_id, listen_addr, send_back_addr } => {
+                SwarmEvent::IncomingConnection { listener_id, connection_id, listen_addr, send_back_addr }
+            }
+            RawSwarmEvent::IncomingConnectionError { listener_id, connection_id, listen_addr, send_back_addr, error } => {
+                SwarmEvent::IncomingConnectionError { listener_id, connection_id, listen_addr, send_back_addr, error }
+            }
+            RawSwarmEvent::Connected { peer_id, connection_id, endpoint } => {
+                self.accept_connection(peer_id, connection_id, endpoint)
+            }
+            RawSwarmEvent::Replaced { peer_id, closed_connection_id, closed_endpoint, connection_id, endpoint } => {
+                self.pending_events.push_back(SwarmEvent::ConnectionClosed {
+                    peer_id: peer_id.clone(),
+                    connection_id: closed_connection_id,
+                    endpoint: closed_endpoint,
+                    cause: CloseCause::Replaced,
+                });
+                self.accept_connection(peer_id, connection_id, endpoint)
+            }
+            RawSwarmEvent::DuplicateRejected { peer_id, connection_id, endpoint } => {
+                SwarmEvent::DuplicateConnectionRejected { peer_id, connection_id, endpoint }
+            }
+            RawSwarmEvent::DeniedRejected { peer_id, connection_id, endpoint } => {
+                debug!("Rejected connection to banned peer {:?}", peer_id);
+                SwarmEvent::BannedPeerRejected { peer_id, connection_id, endpoint }
+            }
+            RawSwarmEvent::NodeClosed { peer_id, connection_id, endpoint } => {
+                SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause: CloseCause::Graceful }
+            }
+            RawSwarmEvent::NodeError { peer_id, connection_id, endpoint, error } => {
+                let panic_message = HandlerPanicked::from_io_error(&error)
+                    .map(|panicked| panicked.message().to_owned());
+                let cause = match panic_message {
+                    Some(message) => CloseCause::HandlerPanicked(message),
+                    None => CloseCause::Error(error),
+                };
+                SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause }
+            }
+            RawSwarmEvent::DialError { remain_addrs_attempt, peer_id, connection_id, multiaddr, error } => {
+                SwarmEvent::DialFailure {
+                    peer_id: Some(peer_id),
+                    connection_id,
+                    multiaddr,
+                    remain_addrs_attempt,
+                    error: DialError::Transport(error),
+                }
+            }
+            RawSwarmEvent::UnknownPeerDialError { connection_id, multiaddr, error } => {
+                SwarmEvent::DialFailure {
+                    peer_id: None,
+                    connection_id,
+                    multiaddr,
+                    remain_addrs_attempt: 0,
+                    error: DialError::Transport(error),
+                }
+            }
+            RawSwarmEvent::PublicKeyMismatch {
+                expected_peer_id,
+                actual_peer_id,
+                connection_id,
+                multiaddr,
+                remain_addrs_attempt,
+            } => {
+                SwarmEvent::DialFailure {
+                    peer_id: Some(expected_peer_id),
+                    connection_id,
+                    multiaddr,
+                    remain_addrs_attempt,
+                    error: DialError::PeerIdMismatch { actual_peer_id },
+                }
+            }
+            RawSwarmEvent::NodeEvent { peer_id, connection_id, event } => {
+                SwarmEvent::NodeEvent { peer_id, connection_id, event }
+            }
+        };
+
+        let peer_dial_failure = match event {
+            SwarmEvent::DialFailure { peer_id: Some(ref peer_id), ref multiaddr, remain_addrs_attempt, ref error }
+                if self.peer_dials.contains_key(peer_id) =>
+            {
+                Some((peer_id.clone(), multiaddr.clone(), remain_addrs_attempt, error.duplicate()))
+            },
+            _ => None,
+        };
+
+        self.pending_events.push_back(event);
+
+        if let Some((peer_id, multiaddr, remain_addrs_attempt, error)) = peer_dial_failure {
+            self.inject_peer_dial_failure(peer_id, multiaddr, remain_addrs_attempt, error);
+        }
     }
 
-    /// Adds a new multiaddr to attempt if the current dialing fails.
+    /// Checks a connection that has just been established against the `AddressFilter`, closes it
+    /// if it is refused, and returns the event to produce.
     ///
-    /// Doesn't do anything if that multiaddress is already in the queue.
-    pub fn append_multiaddr_attempt(&mut self, addr: Multiaddr) {
-        if self.attempt.get().next_attempts.iter().any(|a| a == &addr) {
-            return;
+    /// > **Note**: The connections to banned peers are rejected by the raw swarm before reaching
+    /// >           this point.
+    fn accept_connection(&mut self, peer_id: PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint)
+        -> SwarmEvent<TOutEvent>
+    {
+        if let Err(reason) = self.check_connection(&peer_id, &endpoint) {
+            self.reject_filtered(&peer_id, &reason);
+            SwarmEvent::ConnectionFiltered {
+                peer_id: Some(peer_id),
+                connection_id: Some(connection_id),
+                endpoint,
+                reason,
+            }
+        } else {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint }
         }
+    }
+
+    /// Checks a connection that has just been established against the `AddressFilter`.
+    fn check_connection(&self, peer_id: &PeerId, endpoint: &ConnectedPoint) -> Result<(), FilterRejection> {
+        let address = match *endpoint {
+            ConnectedPoint::Dialer { ref address } => address,
+            ConnectedPoint::Listener { ref send_back_addr, .. } => send_back_addr,
+        };
+        self.address_filter.check(address, Some(peer_id))
+    }
 
-        self.attempt.get_mut().next_attempts.push(addr);
+    /// Closes the connection that has just been established with a filtered address or peer.
+    fn reject_filtered(&mut self, peer_id: &PeerId, reason: &FilterRejection) {
+        debug!("Rejecting connection to {:?}: {}", peer_id, reason);
+        if let Some(peer) = self.raw.peer(peer_id.clone()).as_connected() {
+            peer.close();
+        }
     }
 }
 
-/// Access to a peer we're not connected to.
-pub struct PeerNotConnected<'a, TTrans: 'a, TInEvent: 'a, TOutEvent: 'a, THandlerBuild: 'a>
+impl<TTrans, TInEvent, TOutEvent, THandlerBuild> Deref
+    for Swarm<TTrans, TInEvent, TOutEvent, THandlerBuild>
 where
     TTrans: Transport,
 {
-    peer_id: PeerId,
-    nodes: &'a mut Swarm<TTrans, TInEvent, TOutEvent, THandlerBuild>,
-}
+    type Target = RawSwarm<TTrans, TInEvent, TOutEvent, THandlerBuild>;
 
-impl<'a, TTrans, TInEvent, TOutEvent, TMuxer, THandler, THandlerBuild>
-    PeerNotConnected<'a, TTrans, TInEvent, TOutEvent, THandlerBuild>
-where
-    TTrans: Transport<Output = (PeerId, TMuxer)>,
-    TMuxer: StreamMuxer,
-    THandlerBuild: HandlerFactory<Handler = THandler>,
-    THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent> + Send + 'static,
-    THandler::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
-{
-    /// Attempts a new connection to this node using the given multiaddress.
     #[inline]
-    pub fn connect(self, addr: Multiaddr) -> Result<PeerPendingConnect<'a, TInEvent, TOutEvent>, Self>
-    where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-        TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
-        TMuxer::OutboundSubstream: Send,
-        TMuxer::Substream: Send,
-        TInEvent: Send + 'static,
-        TOutEvent: Send + 'static,
-    {
-        self.connect_inner(addr, Vec::new())
+    fn deref(&self) -> &Self::Target {
+        &self.raw
     }
+}
 
-    /// Attempts a new connection to this node using the given multiaddresses.
-    ///
-    /// The multiaddresses passes as parameter will be tried one by one.
-    ///
-    /// If the iterator is empty, TODO: what to do? at the moment we unwrap
+impl<TTrans, TInEvent, TOutEvent, THandlerBuild> DerefMut
+    for Swarm<TTrans, TInEvent, TOutEvent, THandlerBuild>
+where
+    TTrans: Transport,
+{
     #[inline]
-    pub fn connect_iter<TIter>(
-        self,
-        addrs: TIter,
-    ) -> Result<PeerPendingConnect<'a, TInEvent, TOutEvent>, Self>
-    where
-        TIter: IntoIterator<Item = Multiaddr>,
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-        TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
-        TMuxer::OutboundSubstream: Send,
-        TMuxer::Substream: Send,
-        TInEvent: Send + 'static,
-        TOutEvent: Send + 'static,
-    {
-        let mut addrs = addrs.into_iter();
-        let first = addrs.next().unwrap(); // TODO: bad
-        let rest = addrs.collect();
-        self.connect_inner(first, rest)
-    }
-
-    /// Inner implementation of `connect`.
-    fn connect_inner(
-        self,
-        first: Multiaddr,
-        rest: Vec<Multiaddr>,
-    ) -> Result<PeerPendingConnect<'a, TInEvent, TOutEvent>, Self>
-    where
-        TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
-        TTrans::Dial: Send + 'static,
-        TMuxer: StreamMuxer + Send + Sync + 'static,
-        TMuxer::OutboundSubstream: Send,
-        TMuxer::Substream: Send,
-        TInEvent: Send + 'static,
-        TOutEvent: Send + 'static,
-    {
-        self.nodes.start_dial_out(self.peer_id.clone(), first, rest);
-
-        Ok(PeerPendingConnect {
-            attempt: match self.nodes.reach_attempts.out_reach_attempts.entry(self.peer_id) {
-                Entry::Occupied(e) => e,
-                Entry::Vacant(_) => {
-                    panic!("We called out_reach_attempts.insert with this peer id just above")
-                },
-            },
-            active_nodes: &mut self.nodes.active_nodes,
-        })
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.raw
     }
 }
 
@@ -1150,7 +1321,7 @@ where
     THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent> + Send + 'static,
     THandler::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
 {
-    type Item = SwarmEvent<TTrans, TOutEvent>;
+    type Item = SwarmEvent<TOutEvent>;
     type Error = Void; // TODO: use `!` once stable
 
     #[inline]
@@ -1158,3 +1329,697 @@ where
         Ok(self.poll())
     }
 }
+
+/// Future that shuts down a `Swarm` gracefully. Produced by `Swarm::close`.
+#[must_use = "futures do nothing unless polled"]
+pub struct SwarmClose<TTrans, TInEvent, TOutEvent, THandlerBuild>
+where
+    TTrans: Transport,
+{
+    /// The swarm being shut down.
+    swarm: Swarm<TTrans, TInEvent, TOutEvent, THandlerBuild>,
+}
+
+impl<TTrans, TMuxer, TInEvent, TOutEvent, THandler, THandlerBuild> Future for
+    SwarmClose<TTrans, TInEvent, TOutEvent, THandlerBuild>
+where
+    TTrans: Transport<Output = (PeerId, TMuxer)> + Clone,
+    TTrans::Dial: Send + 'static,
+    TTrans::ListenerUpgrade: Send + 'static,
+    TMuxer: StreamMuxer + Send + Sync + 'static,
+    TMuxer::OutboundSubstream: Send,
+    TMuxer::Substream: Send,
+    TInEvent: Send + 'static,
+    TOutEvent: Send + 'static,
+    THandlerBuild: HandlerFactory<Handler = THandler>,
+    THandler: NodeHandler<Substream<TMuxer>, InEvent = TInEvent, OutEvent = TOutEvent> + Send + 'static,
+    THandler::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+{
+    type Item = ();
+    type Error = Void; // TODO: use `!` once stable
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        loop {
+            match self.swarm.poll() {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::Ready(Some(_)) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, sync::mpsc};
+    use nodes::handled_node::{NodeHandlerEndpoint, NodeHandlerEvent};
+    use parking_lot::Mutex;
+    use runtime::ManualTimer;
+    use std::sync::Arc;
+    use PublicKey;
+
+    /// Muxer that never opens any substream.
+    struct PendingMuxer;
+    impl StreamMuxer for PendingMuxer {
+        type Substream = ();
+        type OutboundSubstream = ();
+        fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+        fn open_outbound(&self) -> Self::OutboundSubstream { () }
+        fn poll_outbound(&self, _: &mut Self::OutboundSubstream) -> Poll<Option<Self::Substream>, IoError> { Ok(Async::NotReady) }
+        fn destroy_outbound(&self, _: Self::OutboundSubstream) {}
+        fn read_substream(&self, _: &mut Self::Substream, _: &mut [u8]) -> Result<usize, IoError> { panic!() }
+        fn write_substream(&self, _: &mut Self::Substream, _: &[u8]) -> Result<usize, IoError> { panic!() }
+        fn flush_substream(&self, _: &mut Self::Substream) -> Result<(), IoError> { panic!() }
+        fn shutdown_substream(&self, _: &mut Self::Substream) -> Poll<(), IoError> { panic!() }
+        fn destroy_substream(&self, _: Self::Substream) { panic!() }
+        fn close_inbound(&self) {}
+        fn close_outbound(&self) {}
+    }
+
+    /// What a `TestHandler` should do.
+    #[derive(Debug, Clone)]
+    enum Command {
+        /// Close the connection gracefully.
+        Close,
+        /// Close the connection with an error.
+        Fail,
+        /// Panic.
+        Panic,
+    }
+
+    /// Handler that keeps the connection open until it receives a `Command`. Ignores `shutdown`.
+    #[derive(Default)]
+    struct TestHandler {
+        command: Option<Command>,
+    }
+
+    impl<T> NodeHandler<T> for TestHandler {
+        type InEvent = Command;
+        type OutEvent = ();
+        type OutboundOpenInfo = ();
+        fn inject_substream(&mut self, _: T, _: NodeHandlerEndpoint<()>) {}
+        fn inject_inbound_closed(&mut self) {}
+        fn inject_outbound_closed(&mut self, _: ()) {}
+        fn inject_event(&mut self, command: Command) { self.command = Some(command); }
+        fn shutdown(&mut self) {}
+        fn poll(&mut self) -> Poll<Option<NodeHandlerEvent<(), ()>>, IoError> {
+            match self.command {
+                None => Ok(Async::NotReady),
+                Some(Command::Close) => Ok(Async::Ready(None)),
+                Some(Command::Fail) => Err(IoError::new(IoErrorKind::Other, "handler failed")),
+                Some(Command::Panic) => panic!("handler bug"),
+            }
+        }
+    }
+
+    /// In-memory transport. Dialing an address reaches the peer registered with `add_peer`, and
+    /// incoming connections are simulated with `incoming`.
+    #[derive(Clone, Default)]
+    struct TestTransport {
+        /// Peer reached when dialing each address. The other addresses are unreachable.
+        peers: Arc<Mutex<FnvHashMap<Multiaddr, PeerId>>>,
+        /// For each address we listen on, sender of the incoming connections.
+        listeners: Arc<Mutex<FnvHashMap<Multiaddr, mpsc::UnboundedSender<(PeerId, Multiaddr)>>>>,
+    }
+
+    impl TestTransport {
+        fn add_peer(&self, addr: &Multiaddr, peer_id: &PeerId) {
+            self.peers.lock().insert(addr.clone(), peer_id.clone());
+        }
+
+        fn incoming(&self, listen_addr: &Multiaddr, peer_id: &PeerId, send_back_addr: &Multiaddr) {
+            let listeners = self.listeners.lock();
+            let sender = listeners.get(listen_addr).expect("not listening on this address");
+            sender.unbounded_send((peer_id.clone(), send_back_addr.clone())).unwrap();
+        }
+    }
+
+    impl Transport for TestTransport {
+        type Output = (PeerId, PendingMuxer);
+        type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+        type ListenerUpgrade = future::FutureResult<Self::Output, IoError>;
+        type Dial = future::FutureResult<Self::Output, IoError>;
+
+        fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+            let (tx, rx) = mpsc::unbounded();
+            self.listeners.lock().insert(addr.clone(), tx);
+            let stream = rx
+                .map(|(peer_id, send_back_addr)| (future::ok((peer_id, PendingMuxer)), send_back_addr))
+                .map_err(|()| -> IoError { unreachable!() });
+            Ok((Box::new(stream), addr))
+        }
+
+        fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+            Ok(match self.peers.lock().get(&addr) {
+                Some(peer_id) => future::ok((peer_id.clone(), PendingMuxer)),
+                None => future::err(IoErrorKind::ConnectionRefused.into()),
+            })
+        }
+    }
+
+    type TestSwarm = Swarm<TestTransport, Command, (), fn(ConnectedPoint) -> TestHandler>;
+
+    fn new_swarm(transport: &TestTransport, timer: &ManualTimer) -> TestSwarm {
+        let handler_build: fn(ConnectedPoint) -> TestHandler = |_| TestHandler::default();
+        let runtime = Runtime::deterministic(timer.clone());
+        Swarm::with_runtime(transport.clone(), handler_build, runtime)
+    }
+
+    fn peer(n: u8) -> PeerId {
+        PublicKey::Ed25519(vec![n; 32]).into_peer_id()
+    }
+
+    fn addr(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
+    /// Waits for the next event of the swarm.
+    fn next_event(swarm: &mut TestSwarm) -> SwarmEvent<()> {
+        future::poll_fn(|| -> Poll<_, ()> {
+            match swarm.poll() {
+                Async::Ready(Some(event)) => Ok(Async::Ready(event)),
+                Async::Ready(None) => panic!("the swarm has ended"),
+                Async::NotReady => Ok(Async::NotReady),
+            }
+        }).wait().unwrap()
+    }
+
+    /// Polls the swarm once, and returns the event it produced, if any.
+    fn poll_once(swarm: &mut TestSwarm) -> Option<SwarmEvent<()>> {
+        future::poll_fn(|| -> Poll<_, ()> {
+            match swarm.poll() {
+                Async::Ready(event) => Ok(Async::Ready(event)),
+                Async::NotReady => Ok(Async::Ready(None)),
+            }
+        }).wait().unwrap()
+    }
+
+    /// Dials `addr` and waits for the connection to `peer_id` to be established.
+    fn connect(swarm: &mut TestSwarm, addr: &Multiaddr, peer_id: &PeerId) -> ConnectionId {
+        swarm.dial(addr.clone()).unwrap();
+        match next_event(swarm) {
+            SwarmEvent::ConnectionEstablished { peer_id: ref p, connection_id, .. } if p == peer_id => {
+                connection_id
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn ban_closes_connection_and_refuses_dials() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        let connection_id = connect(&mut swarm, &addr(1), &remote);
+        swarm.ban_peer(remote.clone(), None);
+        assert!(swarm.is_banned(&remote));
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionClosed { peer_id, connection_id: id, cause: CloseCause::Banned, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(id, connection_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerBanned { peer_id, until: None } => assert_eq!(peer_id, remote),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let with_peer_id: Multiaddr = format!("{}/p2p/{}", addr(1), remote.to_base58()).parse().unwrap();
+        assert!(swarm.dial(with_peer_id).is_err());
+        assert!(swarm.peer(remote.clone()).as_not_connected().unwrap().connect(addr(1)).is_err());
+        assert_eq!(swarm.dial_peer(remote.clone()), Err(DialPeerError::Banned));
+        assert!(poll_once(&mut swarm).is_none());
+        assert!(swarm.peer(remote).as_not_connected().is_some());
+    }
+
+    #[test]
+    fn banned_peer_rejected_once_identified() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+        swarm.listen_on(addr(10)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::NewListenAddr { .. } => (),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        swarm.ban_peer(remote.clone(), None);
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerBanned { .. } => (),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        // Inbound connection.
+        transport.incoming(&addr(10), &remote, &addr(20));
+        let incoming_id = match next_event(&mut swarm) {
+            SwarmEvent::IncomingConnection { connection_id, .. } => connection_id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        match next_event(&mut swarm) {
+            SwarmEvent::BannedPeerRejected { peer_id, connection_id, endpoint: ConnectedPoint::Listener { .. } } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, incoming_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(swarm.peer(remote.clone()).as_not_connected().is_some());
+
+        // Outbound connection to an address whose peer isn't known in advance.
+        swarm.dial(addr(1)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::BannedPeerRejected { peer_id, endpoint: ConnectedPoint::Dialer { address }, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(address, addr(1));
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(swarm.peer(remote).as_not_connected().is_some());
+    }
+
+    #[test]
+    fn ban_expires() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        swarm.ban_peer(remote.clone(), Some(Duration::from_secs(10)));
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerBanned { peer_id, until: Some(_) } => assert_eq!(peer_id, remote),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        timer.advance(Duration::from_secs(5));
+        assert!(swarm.is_banned(&remote));
+        assert!(poll_once(&mut swarm).is_none());
+
+        timer.advance(Duration::from_secs(5));
+        assert!(!swarm.is_banned(&remote));
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerUnbanned { peer_id } => assert_eq!(peer_id, remote),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert_eq!(swarm.banned_peers().count(), 0);
+        connect(&mut swarm, &addr(1), &remote);
+    }
+
+    #[test]
+    fn unban_allows_connections_again() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        swarm.ban_peer(remote.clone(), None);
+        assert_eq!(swarm.banned_peers().collect::<Vec<_>>(), vec![&remote]);
+        assert!(swarm.unban_peer(&remote));
+        assert!(!swarm.unban_peer(&remote));
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerBanned { .. } => (),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        match next_event(&mut swarm) {
+            SwarmEvent::PeerUnbanned { peer_id } => assert_eq!(peer_id, remote),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        connect(&mut swarm, &addr(1), &remote);
+    }
+
+    #[test]
+    fn disconnect_peer_closes_connection() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        let connection_id = connect(&mut swarm, &addr(1), &remote);
+        assert!(swarm.disconnect_peer(&remote));
+        assert!(!swarm.disconnect_peer(&remote));
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionClosed { peer_id, connection_id: id, cause: CloseCause::Disconnected, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(id, connection_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(swarm.peer(remote.clone()).as_not_connected().is_some());
+        assert!(!swarm.is_banned(&remote));
+
+        // Disconnecting doesn't prevent reconnecting.
+        let new_id = connect(&mut swarm, &addr(1), &remote);
+        assert_ne!(new_id, connection_id);
+    }
+
+    /// Sends a command to the handler of the connection to `peer_id`, and returns the cause of
+    /// the closing of the connection.
+    fn close_with(swarm: &mut TestSwarm, peer_id: &PeerId, command: Command) -> CloseCause {
+        let connection_id = swarm.peer(peer_id.clone()).as_connected().unwrap().connection_id();
+        swarm.peer(peer_id.clone()).as_connected().unwrap().send_event(command);
+        match next_event(swarm) {
+            SwarmEvent::ConnectionClosed { peer_id: ref p, connection_id: id, endpoint, cause } => {
+                assert_eq!(p, peer_id);
+                assert_eq!(id, connection_id);
+                assert_eq!(endpoint, ConnectedPoint::Dialer { address: addr(1) });
+                cause
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn connection_established_when_dialing() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        swarm.dial(addr(1)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(endpoint, ConnectedPoint::Dialer { address: addr(1) });
+                let peer = swarm.peer(remote).as_connected().unwrap();
+                assert_eq!(peer.connection_id(), connection_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn incoming_connection_then_established() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+
+        let (listener_id, _) = swarm.add_listener(addr(10)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::NewListenAddr { listener_id: id, listen_addr } => {
+                assert_eq!(id, listener_id);
+                assert_eq!(listen_addr, addr(10));
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        transport.incoming(&addr(10), &remote, &addr(20));
+        let incoming_id = match next_event(&mut swarm) {
+            SwarmEvent::IncomingConnection { listener_id: id, connection_id, listen_addr, send_back_addr } => {
+                assert_eq!(id, listener_id);
+                assert_eq!(listen_addr, addr(10));
+                assert_eq!(send_back_addr, addr(20));
+                connection_id
+            },
+            event => panic!("unexpected event: {:?}", event),
+        };
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, incoming_id);
+                assert_eq!(endpoint, ConnectedPoint::Listener { listen_addr: addr(10), send_back_addr: addr(20) });
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn connection_closed_causes() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+
+        connect(&mut swarm, &addr(1), &remote);
+        match close_with(&mut swarm, &remote, Command::Close) {
+            CloseCause::Graceful => (),
+            cause => panic!("unexpected cause: {:?}", cause),
+        }
+
+        connect(&mut swarm, &addr(1), &remote);
+        match close_with(&mut swarm, &remote, Command::Fail) {
+            CloseCause::Error(err) => assert_eq!(err.to_string(), "handler failed"),
+            cause => panic!("unexpected cause: {:?}", cause),
+        }
+
+        connect(&mut swarm, &addr(1), &remote);
+        match close_with(&mut swarm, &remote, Command::Panic) {
+            CloseCause::HandlerPanicked(message) => assert_eq!(message, "handler bug"),
+            cause => panic!("unexpected cause: {:?}", cause),
+        }
+
+        // The swarm keeps working after the panic.
+        connect(&mut swarm, &addr(1), &remote);
+    }
+
+    #[test]
+    fn connection_replaced() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        swarm.listen_on(addr(10)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::NewListenAddr { .. } => (),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        transport.incoming(&addr(10), &remote, &addr(20));
+        let _ = next_event(&mut swarm);
+        let first = match next_event(&mut swarm) {
+            SwarmEvent::ConnectionEstablished { connection_id, .. } => connection_id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+
+        transport.incoming(&addr(10), &remote, &addr(21));
+        let second = match next_event(&mut swarm) {
+            SwarmEvent::IncomingConnection { connection_id, .. } => connection_id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause: CloseCause::Replaced } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, first);
+                assert_eq!(endpoint, ConnectedPoint::Listener { listen_addr: addr(10), send_back_addr: addr(20) });
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, second);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn connection_closed_at_shutdown_deadline() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        transport.add_peer(&addr(1), &remote);
+        let connection_id = connect(&mut swarm, &addr(1), &remote);
+
+        // `TestHandler` ignores `shutdown`, so the connection only closes at the deadline.
+        swarm.start_close(Duration::from_secs(10));
+        assert!(poll_once(&mut swarm).is_none());
+        timer.advance(Duration::from_secs(10));
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionClosed { peer_id, connection_id: id, cause: CloseCause::ShutdownDeadline, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(id, connection_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(poll_once(&mut swarm).is_none());
+    }
+
+    #[test]
+    fn dial_failure_reports_address_and_peer() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+        let other = peer(2);
+        transport.add_peer(&addr(2), &other);
+
+        // Unreachable address, unknown peer.
+        swarm.dial(addr(1)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::DialFailure { peer_id: None, multiaddr, remain_addrs_attempt: 0, error: DialError::Transport(err), .. } => {
+                assert_eq!(multiaddr, addr(1));
+                assert_eq!(err.kind(), IoErrorKind::ConnectionRefused);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        // Known peer, the first address is unreachable and the second one reaches another peer.
+        swarm.peer(remote.clone()).as_not_connected().unwrap()
+            .connect_iter(vec![addr(1), addr(2)]).unwrap();
+        let first_id = match next_event(&mut swarm) {
+            SwarmEvent::DialFailure { peer_id: Some(peer_id), connection_id, multiaddr, remain_addrs_attempt: 1, error: DialError::Transport(_) } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(multiaddr, addr(1));
+                connection_id
+            },
+            event => panic!("unexpected event: {:?}", event),
+        };
+        match next_event(&mut swarm) {
+            SwarmEvent::DialFailure { peer_id: Some(peer_id), connection_id, multiaddr, remain_addrs_attempt: 0, error: DialError::PeerIdMismatch { actual_peer_id } } => {
+                assert_eq!(peer_id, remote);
+                assert_ne!(connection_id, first_id);
+                assert_eq!(multiaddr, addr(2));
+                assert_eq!(actual_peer_id, other);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(swarm.peer(remote).as_not_connected().is_some());
+    }
+
+    #[test]
+    fn backed_off_address_not_dialed() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        swarm.set_dial_backoff_config(DialBackoffConfig::default()
+            .with_initial_backoff(Duration::from_secs(5)));
+
+        swarm.dial(addr(1)).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::DialFailure { ref multiaddr, .. } if *multiaddr == addr(1) => {},
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        // The peer is now reachable, but the address is still backed off.
+        transport.add_peer(&addr(1), &peer(1));
+        assert_eq!(swarm.dial(addr(1)).err(), Some(addr(1)));
+        let until = match next_event(&mut swarm) {
+            SwarmEvent::DialBackedOff { peer_id: None, address, until } => {
+                assert_eq!(address, addr(1));
+                until
+            },
+            event => panic!("unexpected event: {:?}", event),
+        };
+        assert_eq!(swarm.dial_backoff_until(&addr(1)), Some(until));
+        assert!(poll_once(&mut swarm).is_none());
+
+        timer.advance(Duration::from_secs(5));
+        assert!(swarm.dial_backoff_until(&addr(1)).is_none());
+        connect(&mut swarm, &addr(1), &peer(1));
+    }
+
+    #[test]
+    fn connection_and_listener_ids() {
+        let transport = TestTransport::default();
+        let timer = ManualTimer::new();
+        let mut swarm = new_swarm(&transport, &timer);
+        let remote = peer(1);
+
+        let (first_listener, _) = swarm.add_listener(addr(10)).unwrap();
+        let (second_listener, _) = swarm.add_listener(addr(11)).unwrap();
+        assert_ne!(first_listener, second_listener);
+        for &(expected_id, ref expected_addr) in &[(first_listener, addr(10)), (second_listener, addr(11))] {
+            match next_event(&mut swarm) {
+                SwarmEvent::NewListenAddr { listener_id, listen_addr } => {
+                    assert_eq!(listener_id, expected_id);
+                    assert_eq!(listen_addr, *expected_addr);
+                },
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+
+        // Each connection is reported with the id of the listener that received it, and keeps
+        // its id once established.
+        let mut connection_ids = Vec::new();
+        for &(listener, ref listen_addr) in &[(second_listener, addr(11)), (first_listener, addr(10))] {
+            transport.incoming(listen_addr, &remote, &addr(20));
+            let incoming_id = match next_event(&mut swarm) {
+                SwarmEvent::IncomingConnection { listener_id, connection_id, .. } => {
+                    assert_eq!(listener_id, listener);
+                    connection_id
+                },
+                event => panic!("unexpected event: {:?}", event),
+            };
+            assert!(!connection_ids.contains(&incoming_id));
+
+            // The second connection replaces the first one.
+            if let Some(&previous_id) = connection_ids.last() {
+                match next_event(&mut swarm) {
+                    SwarmEvent::ConnectionClosed { connection_id, cause: CloseCause::Replaced, .. } => {
+                        assert_eq!(connection_id, previous_id);
+                    },
+                    event => panic!("unexpected event: {:?}", event),
+                }
+            }
+
+            match next_event(&mut swarm) {
+                SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint } => {
+                    assert_eq!(peer_id, remote);
+                    assert_eq!(connection_id, incoming_id);
+                    assert_eq!(endpoint, ConnectedPoint::Listener { listen_addr: listen_addr.clone(), send_back_addr: addr(20) });
+                },
+                event => panic!("unexpected event: {:?}", event),
+            }
+            connection_ids.push(incoming_id);
+        }
+
+        // A rejected duplicate is reported with its own id, and the existing connection is kept.
+        swarm.set_duplicate_connection_policy(DuplicateConnectionPolicy::KeepExisting);
+        transport.incoming(&addr(10), &remote, &addr(21));
+        let rejected_id = match next_event(&mut swarm) {
+            SwarmEvent::IncomingConnection { listener_id, connection_id, .. } => {
+                assert_eq!(listener_id, first_listener);
+                connection_id
+            },
+            event => panic!("unexpected event: {:?}", event),
+        };
+        assert!(!connection_ids.contains(&rejected_id));
+        match next_event(&mut swarm) {
+            SwarmEvent::DuplicateConnectionRejected { peer_id, connection_id, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, rejected_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let current_id = connection_ids[1];
+        assert_eq!(swarm.peer(remote.clone()).as_connected().unwrap().connection_id(), current_id);
+        swarm.disconnect_peer(&remote);
+        match next_event(&mut swarm) {
+            SwarmEvent::ConnectionClosed { connection_id, cause: CloseCause::Disconnected, .. } => {
+                assert_eq!(connection_id, current_id);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        // Removing a listener is reported with its id.
+        swarm.remove_listener(first_listener).unwrap();
+        match next_event(&mut swarm) {
+            SwarmEvent::ExpiredListenAddr { listener_id, listen_addr } => {
+                assert_eq!(listener_id, first_listener);
+                assert_eq!(listen_addr, addr(10));
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        match next_event(&mut swarm) {
+            SwarmEvent::ListenerClosed { listener_id, result: Ok(()), .. } => {
+                assert_eq!(listener_id, first_listener);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert_eq!(swarm.listeners_with_id().map(|(id, _)| id).collect::<Vec<_>>(), vec![second_listener]);
+    }
+}
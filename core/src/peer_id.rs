@@ -25,13 +25,19 @@ use PublicKey;
 
 /// Identifier of a peer of the network.
 ///
-/// The data is a multihash of the public key of the peer.
+/// The data is a multihash of the protobuf encoding of the public key of the peer. Keys whose
+/// encoding is short enough are embedded with the identity multihash, as defined in the libp2p
+/// specifications, and can be extracted with `public_key()`. Other keys are hashed with
+/// SHA2-256.
 // TODO: maybe keep things in decoded version?
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct PeerId {
     multihash: multihash::Multihash,
 }
 
+/// Maximum length of the protobuf encoding of a public key for it to be embedded in the `PeerId`.
+const MAX_INLINE_KEY_LENGTH: usize = 42;
+
 impl fmt::Debug for PeerId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "PeerId({})", self.to_base58())
@@ -40,12 +46,36 @@ impl fmt::Debug for PeerId {
 
 impl PeerId {
     /// Builds a `PeerId` from a public key.
+    ///
+    /// Ed25519 and compressed secp256k1 keys are embedded in the `PeerId` with the identity
+    /// multihash. Other keys are hashed with SHA2-256.
     #[inline]
     pub fn from_public_key(public_key: PublicKey) -> PeerId {
         let protobuf = public_key.into_protobuf_encoding();
-        let multihash = multihash::encode(multihash::Hash::SHA2256, &protobuf)
-            .expect("sha2-256 is always supported");
-        PeerId { multihash }
+        let hash = if protobuf.len() <= MAX_INLINE_KEY_LENGTH {
+            multihash::Hash::Identity
+        } else {
+            multihash::Hash::SHA2256
+        };
+        PeerId::from_public_key_with_hash(&protobuf, hash)
+            .expect("identity and sha2-256 are always supported, and the length was checked")
+    }
+
+    /// Builds a `PeerId` from a public key with the given multihash algorithm.
+    ///
+    /// Returns `None` if the algorithm isn't valid for a `PeerId`, or if the key is too long to
+    /// be embedded with the identity multihash. Use `from_public_key` to apply the algorithm
+    /// that the libp2p specifications require.
+    pub fn from_public_key_hashed(public_key: PublicKey, hash: multihash::Hash) -> Option<PeerId> {
+        PeerId::from_public_key_with_hash(&public_key.into_protobuf_encoding(), hash)
+    }
+
+    fn from_public_key_with_hash(protobuf: &[u8], hash: multihash::Hash) -> Option<PeerId> {
+        if !is_valid_hash(hash) {
+            return None;
+        }
+        let multihash = multihash::encode(hash, protobuf).ok()?;
+        Some(PeerId { multihash })
     }
 
     /// Checks whether `data` is a valid `PeerId`. If so, returns the `PeerId`. If not, returns
@@ -54,7 +84,7 @@ impl PeerId {
     pub fn from_bytes(data: Vec<u8>) -> Result<PeerId, Vec<u8>> {
         match multihash::Multihash::from_bytes(data) {
             Ok(multihash) => {
-                if multihash.algorithm() == multihash::Hash::SHA2256 {
+                if is_valid_hash(multihash.algorithm()) {
                     Ok(PeerId { multihash })
                 } else {
                     Err(multihash.into_bytes())
@@ -68,13 +98,23 @@ impl PeerId {
     /// returns back the data as an error.
     #[inline]
     pub fn from_multihash(data: multihash::Multihash) -> Result<PeerId, multihash::Multihash> {
-        if data.algorithm() == multihash::Hash::SHA2256 {
+        if is_valid_hash(data.algorithm()) {
             Ok(PeerId { multihash: data })
         } else {
             Err(data)
         }
     }
 
+    /// Returns the public key embedded in this `PeerId`, if any.
+    ///
+    /// Returns `None` if the `PeerId` contains the hash of the public key.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        if self.multihash.algorithm() != multihash::Hash::Identity {
+            return None;
+        }
+        PublicKey::from_protobuf_encoding(self.multihash.digest()).ok()
+    }
+
     /// Returns a raw bytes representation of this `PeerId`.
     ///
     /// Note that this is not the same as the public key of the peer.
@@ -112,10 +152,17 @@ impl PeerId {
         match multihash::encode(alg, &public_key.clone().into_protobuf_encoding()) {
             Ok(compare) => Some(compare == self.multihash),
             Err(multihash::EncodeError::UnsupportedType) => None,
+            Err(multihash::EncodeError::UnsupportedInputLength) => Some(false),
         }
     }
 }
 
+/// Returns true if `hash` can be used in a `PeerId`.
+#[inline]
+fn is_valid_hash(hash: multihash::Hash) -> bool {
+    hash == multihash::Hash::SHA2256 || hash == multihash::Hash::Identity
+}
+
 impl From<PublicKey> for PeerId {
     #[inline]
     fn from(key: PublicKey) -> PeerId {
@@ -156,6 +203,7 @@ impl FromStr for PeerId {
 
 #[cfg(test)]
 mod tests {
+    use multihash;
     use rand::random;
     use {PeerId, PublicKey};
 
@@ -166,6 +214,30 @@ mod tests {
         assert_eq!(peer_id.is_public_key(&key), Some(true));
     }
 
+    #[test]
+    fn small_keys_are_embedded() {
+        let key = PublicKey::Ed25519((0 .. 32).map(|_| -> u8 { random() }).collect());
+        let peer_id = PeerId::from_public_key(key.clone());
+        assert_eq!(peer_id.is_public_key(&key), Some(true));
+        assert_eq!(peer_id.public_key(), Some(key.clone()));
+
+        let second = PeerId::from_bytes(peer_id.clone().into_bytes()).unwrap();
+        assert_eq!(peer_id, second);
+
+        let hashed = PeerId::from_public_key_hashed(key.clone(), multihash::Hash::SHA2256).unwrap();
+        assert_ne!(hashed, peer_id);
+        assert_eq!(hashed.public_key(), None);
+        assert_eq!(hashed.is_public_key(&key), Some(true));
+    }
+
+    #[test]
+    fn large_keys_are_hashed() {
+        let key = PublicKey::Rsa((0 .. 2048).map(|_| -> u8 { random() }).collect());
+        let peer_id = PeerId::from_public_key(key.clone());
+        assert_eq!(peer_id.public_key(), None);
+        assert!(PeerId::from_public_key_hashed(key, multihash::Hash::Identity).is_none());
+    }
+
     #[test]
     fn peer_id_into_bytes_then_from_bytes() {
         let peer_id = PublicKey::Rsa((0 .. 2048).map(|_| -> u8 { random() }).collect()).into_peer_id();
@@ -28,14 +28,19 @@ use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 pub enum PublicKey {
     /// DER format.
     Rsa(Vec<u8>),
-    /// Format = ???
-    // TODO: ^
+    /// The 32 bytes of the public key, as defined in RFC 8032.
     Ed25519(Vec<u8>),
-    /// Format = ???
-    // TODO: ^
+    /// The 33 bytes of the compressed public key, or the 65 bytes of the uncompressed public key.
     Secp256k1(Vec<u8>),
 }
 
+/// Length of an Ed25519 public key.
+const ED25519_KEY_LEN: usize = 32;
+/// Length of a compressed secp256k1 public key.
+const SECP256K1_COMPRESSED_KEY_LEN: usize = 33;
+/// Length of an uncompressed secp256k1 public key.
+const SECP256K1_UNCOMPRESSED_KEY_LEN: usize = 65;
+
 impl PublicKey {
     /// Encodes the public key as a protobuf message.
     ///
@@ -65,8 +70,8 @@ impl PublicKey {
 
     /// Decodes the public key from a protobuf message.
     ///
-    /// Used at various locations in the wire protocol of libp2p.
-    #[inline]
+    /// Used at various locations in the wire protocol of libp2p. Produces an error if the key
+    /// doesn't have the format of its type.
     pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<PublicKey, IoError> {
         let mut pubkey = protobuf::parse_from_bytes::<keys_proto::PublicKey>(bytes)
             .map_err(|err| {
@@ -74,7 +79,7 @@ impl PublicKey {
                 IoError::new(IoErrorKind::InvalidData, err)
             })?;
 
-        Ok(match pubkey.get_Type() {
+        let key = match pubkey.get_Type() {
             keys_proto::KeyType::RSA => {
                 PublicKey::Rsa(pubkey.take_Data())
             },
@@ -84,7 +89,33 @@ impl PublicKey {
             keys_proto::KeyType::Secp256k1 => {
                 PublicKey::Secp256k1(pubkey.take_Data())
             },
-        })
+        };
+
+        if !key.has_valid_format() {
+            debug!("public key in protobuf encoding has an invalid format");
+            return Err(IoError::new(IoErrorKind::InvalidData, "invalid public key format"));
+        }
+
+        Ok(key)
+    }
+
+    /// Returns true if the key has the format expected for its type.
+    ///
+    /// Only the length and the prefix of the key are checked. This doesn't guarantee that the
+    /// key is a valid point of the curve.
+    pub fn has_valid_format(&self) -> bool {
+        match *self {
+            PublicKey::Rsa(ref data) => !data.is_empty(),
+            PublicKey::Ed25519(ref data) => data.len() == ED25519_KEY_LEN,
+            PublicKey::Secp256k1(ref data) => {
+                match (data.len(), data.first()) {
+                    (SECP256K1_COMPRESSED_KEY_LEN, Some(&0x02)) => true,
+                    (SECP256K1_COMPRESSED_KEY_LEN, Some(&0x03)) => true,
+                    (SECP256K1_UNCOMPRESSED_KEY_LEN, Some(&0x04)) => true,
+                    _ => false,
+                }
+            },
+        }
     }
 
     /// Builds a `PeerId` corresponding to the public key of the node.
@@ -105,4 +136,32 @@ mod tests {
         let second = PublicKey::from_protobuf_encoding(&key.clone().into_protobuf_encoding()).unwrap();
         assert_eq!(key, second);
     }
+
+    #[test]
+    fn ed25519_and_secp256k1_into_protobuf_then_back() {
+        let keys = vec![
+            PublicKey::Ed25519((0 .. 32).map(|_| -> u8 { random() }).collect()),
+            PublicKey::Secp256k1(Some(2).into_iter().chain((0 .. 32).map(|_| -> u8 { random() })).collect()),
+            PublicKey::Secp256k1(Some(4).into_iter().chain((0 .. 64).map(|_| -> u8 { random() })).collect()),
+        ];
+
+        for key in keys {
+            let second = PublicKey::from_protobuf_encoding(&key.clone().into_protobuf_encoding()).unwrap();
+            assert_eq!(key, second);
+        }
+    }
+
+    #[test]
+    fn invalid_key_format_refused() {
+        let keys = vec![
+            PublicKey::Ed25519(vec![1; 31]),
+            PublicKey::Secp256k1(vec![2; 32]),
+            PublicKey::Secp256k1(vec![4; 33]),
+        ];
+
+        for key in keys {
+            assert!(!key.has_valid_format());
+            assert!(PublicKey::from_protobuf_encoding(&key.into_protobuf_encoding()).is_err());
+        }
+    }
 }
@@ -0,0 +1,640 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Limits on the resources used by the local node.
+//!
+//! The `ResourceManager` keeps track of the connections, substreams and memory in use, globally
+//! and for each peer, and denies the allocations that would exceed the configured
+//! `ResourceLimits` with a `ResourceError`.
+//!
+//! Each allocation is represented by a permit, which releases the resources it holds when it is
+//! dropped:
+//!
+//! - A `ConnectionPermit` is obtained with `ResourceManager::reserve_connection` before a
+//!   connection is opened or accepted. Once the identity of the remote is known, it must be
+//!   assigned with `ConnectionPermit::set_peer`, which enforces the per-peer limits.
+//! - A `SubstreamPermit` is obtained from the `ConnectionPermit` for each substream.
+//! - A `MemoryPermit` is obtained with `ResourceManager::reserve_memory` by the protocols that
+//!   buffer data, and can grow or shrink along with the buffer. The mplex and yamux muxers
+//!   reserve their receive buffers this way when configured with a `ResourceManager`, and so
+//!   does the bitswap handler for the blocks it receives.
+//!
+//! Wrapping a transport in a `transport::ResourceLimitedTransport` takes care of the connection
+//! and substream permits automatically.
+//!
+//! The `ResourceManager` can be cloned cheaply, and all the clones share the same state.
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use PeerId;
+
+/// Limits enforced by a `ResourceManager`. All the limits are disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    max_connections: Option<usize>,
+    max_connections_per_peer: Option<usize>,
+    max_substreams_per_connection: Option<usize>,
+    max_substreams_per_peer: Option<usize>,
+    max_memory: Option<usize>,
+    max_memory_per_protocol: Option<usize>,
+}
+
+impl ResourceLimits {
+    /// Builds a `ResourceLimits` where all the limits are disabled.
+    #[inline]
+    pub fn new() -> ResourceLimits {
+        Default::default()
+    }
+
+    /// Sets the maximum number of simultaneous connections, including the pending ones.
+    #[inline]
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of simultaneous connections to the same peer.
+    #[inline]
+    pub fn with_max_connections_per_peer(mut self, max: usize) -> Self {
+        self.max_connections_per_peer = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of simultaneous substreams on a single connection.
+    #[inline]
+    pub fn with_max_substreams_per_connection(mut self, max: usize) -> Self {
+        self.max_substreams_per_connection = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of simultaneous substreams with the same peer, over all the
+    /// connections to it.
+    #[inline]
+    pub fn with_max_substreams_per_peer(mut self, max: usize) -> Self {
+        self.max_substreams_per_peer = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of bytes that can be reserved with `reserve_memory`.
+    #[inline]
+    pub fn with_max_memory(mut self, max: usize) -> Self {
+        self.max_memory = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of bytes that can be reserved with `reserve_memory` for a single
+    /// protocol.
+    #[inline]
+    pub fn with_max_memory_per_protocol(mut self, max: usize) -> Self {
+        self.max_memory_per_protocol = Some(max);
+        self
+    }
+
+    /// Returns the maximum number of simultaneous connections, if any.
+    #[inline]
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Returns the maximum number of simultaneous connections to the same peer, if any.
+    #[inline]
+    pub fn max_connections_per_peer(&self) -> Option<usize> {
+        self.max_connections_per_peer
+    }
+
+    /// Returns the maximum number of simultaneous substreams on a connection, if any.
+    #[inline]
+    pub fn max_substreams_per_connection(&self) -> Option<usize> {
+        self.max_substreams_per_connection
+    }
+
+    /// Returns the maximum number of simultaneous substreams with the same peer, if any.
+    #[inline]
+    pub fn max_substreams_per_peer(&self) -> Option<usize> {
+        self.max_substreams_per_peer
+    }
+
+    /// Returns the maximum number of bytes that can be reserved, if any.
+    #[inline]
+    pub fn max_memory(&self) -> Option<usize> {
+        self.max_memory
+    }
+
+    /// Returns the maximum number of bytes that can be reserved for a protocol, if any.
+    #[inline]
+    pub fn max_memory_per_protocol(&self) -> Option<usize> {
+        self.max_memory_per_protocol
+    }
+}
+
+quick_error! {
+    /// Error returned when an allocation is denied by a `ResourceManager`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ResourceError {
+        /// The maximum number of connections has been reached.
+        ConnectionLimit { limit: usize } {
+            display("reached the limit of {} connections", limit)
+        }
+        /// The maximum number of connections to this peer has been reached.
+        PeerConnectionLimit { limit: usize } {
+            display("reached the limit of {} connections to the peer", limit)
+        }
+        /// The maximum number of substreams on this connection has been reached.
+        SubstreamLimit { limit: usize } {
+            display("reached the limit of {} substreams on the connection", limit)
+        }
+        /// The maximum number of substreams with this peer has been reached.
+        PeerSubstreamLimit { limit: usize } {
+            display("reached the limit of {} substreams with the peer", limit)
+        }
+        /// Reserving memory would exceed the global limit.
+        MemoryLimit { requested: usize, limit: usize } {
+            display("reserving {} bytes would exceed the memory limit of {} bytes", requested, limit)
+        }
+        /// Reserving memory would exceed the limit of the protocol.
+        ProtocolMemoryLimit { requested: usize, limit: usize } {
+            display("reserving {} bytes would exceed the protocol memory limit of {} bytes", requested, limit)
+        }
+    }
+}
+
+impl From<ResourceError> for IoError {
+    #[inline]
+    fn from(err: ResourceError) -> IoError {
+        IoError::new(IoErrorKind::Other, err)
+    }
+}
+
+/// Snapshot of the resources in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceStats {
+    /// Number of connections, including the ones whose peer is unknown.
+    pub connections: usize,
+    /// Number of substreams.
+    pub substreams: usize,
+    /// Number of peers we have at least one connection with.
+    pub peers: usize,
+    /// Number of bytes reserved with `reserve_memory`.
+    pub memory: usize,
+}
+
+/// Keeps track of the resources in use and enforces `ResourceLimits`.
+#[derive(Debug, Clone)]
+pub struct ResourceManager {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    limits: ResourceLimits,
+    /// Connections, indexed by the id of their `ConnectionPermit`.
+    connections: FnvHashMap<u64, ConnectionState>,
+    /// Id of the next `ConnectionPermit`.
+    next_connection_id: u64,
+    /// Number of connections whose permit is still alive.
+    open_connections: usize,
+    /// Total number of substreams.
+    substreams: usize,
+    /// Resources in use for each peer. Entries are removed when they become empty.
+    peers: FnvHashMap<PeerId, PeerState>,
+    /// Total number of reserved bytes.
+    memory: usize,
+    /// Number of reserved bytes for each protocol. Entries are removed when they become zero.
+    protocols_memory: FnvHashMap<Bytes, usize>,
+}
+
+#[derive(Debug)]
+struct ConnectionState {
+    /// Peer at the other side of the connection, if known.
+    peer: Option<PeerId>,
+    /// Number of substreams on this connection.
+    substreams: usize,
+    /// False if the `ConnectionPermit` has been dropped. The entry is kept until all the
+    /// substreams have been released.
+    open: bool,
+}
+
+#[derive(Debug, Default)]
+struct PeerState {
+    connections: usize,
+    substreams: usize,
+}
+
+impl ResourceManager {
+    /// Builds a new `ResourceManager` enforcing the given limits.
+    pub fn new(limits: ResourceLimits) -> ResourceManager {
+        ResourceManager {
+            state: Arc::new(Mutex::new(State {
+                limits,
+                connections: Default::default(),
+                next_connection_id: 0,
+                open_connections: 0,
+                substreams: 0,
+                peers: Default::default(),
+                memory: 0,
+                protocols_memory: Default::default(),
+            })),
+        }
+    }
+
+    /// Returns the limits being enforced.
+    #[inline]
+    pub fn limits(&self) -> ResourceLimits {
+        self.state.lock().limits.clone()
+    }
+
+    /// Changes the limits being enforced.
+    ///
+    /// The resources that are already allocated are kept, even if they exceed the new limits.
+    #[inline]
+    pub fn set_limits(&self, limits: ResourceLimits) {
+        self.state.lock().limits = limits;
+    }
+
+    /// Returns a snapshot of the resources in use.
+    pub fn stats(&self) -> ResourceStats {
+        let state = self.state.lock();
+        ResourceStats {
+            connections: state.open_connections,
+            substreams: state.substreams,
+            peers: state.peers.values().filter(|peer| peer.connections != 0).count(),
+            memory: state.memory,
+        }
+    }
+
+    /// Returns the number of connections to the given peer.
+    #[inline]
+    pub fn peer_connections(&self, peer_id: &PeerId) -> usize {
+        self.state.lock().peers.get(peer_id).map(|peer| peer.connections).unwrap_or(0)
+    }
+
+    /// Returns the number of substreams with the given peer.
+    #[inline]
+    pub fn peer_substreams(&self, peer_id: &PeerId) -> usize {
+        self.state.lock().peers.get(peer_id).map(|peer| peer.substreams).unwrap_or(0)
+    }
+
+    /// Returns the number of bytes reserved for the given protocol.
+    #[inline]
+    pub fn protocol_memory(&self, protocol: &[u8]) -> usize {
+        self.state.lock().protocols_memory.get(protocol).cloned().unwrap_or(0)
+    }
+
+    /// Reserves a new connection, whose peer isn't known yet.
+    pub fn reserve_connection(&self) -> Result<ConnectionPermit, ResourceError> {
+        let mut state = self.state.lock();
+        if let Some(limit) = state.limits.max_connections {
+            if state.open_connections >= limit {
+                return Err(ResourceError::ConnectionLimit { limit });
+            }
+        }
+
+        let id = state.next_connection_id;
+        state.next_connection_id += 1;
+        state.open_connections += 1;
+        state.connections.insert(id, ConnectionState {
+            peer: None,
+            substreams: 0,
+            open: true,
+        });
+
+        Ok(ConnectionPermit {
+            manager: self.clone(),
+            id,
+        })
+    }
+
+    /// Reserves `size` bytes of memory for the given protocol.
+    pub fn reserve_memory(&self, protocol: Bytes, size: usize) -> Result<MemoryPermit, ResourceError> {
+        let mut permit = MemoryPermit {
+            manager: self.clone(),
+            protocol,
+            size: 0,
+        };
+        permit.grow(size)?;
+        Ok(permit)
+    }
+}
+
+impl Default for ResourceManager {
+    #[inline]
+    fn default() -> Self {
+        ResourceManager::new(ResourceLimits::new())
+    }
+}
+
+impl State {
+    /// Releases a substream of the given connection.
+    fn release_substream(&mut self, connection_id: u64) {
+        self.substreams -= 1;
+        let remove = {
+            let connection = self.connections.get_mut(&connection_id)
+                .expect("connection entries are kept while they have substreams");
+            connection.substreams -= 1;
+            if let Some(ref peer_id) = connection.peer {
+                release_peer(&mut self.peers, peer_id, 0, 1);
+            }
+            !connection.open && connection.substreams == 0
+        };
+
+        if remove {
+            self.connections.remove(&connection_id);
+        }
+    }
+}
+
+/// Decrements the counters of a peer, and removes its entry if it becomes empty.
+fn release_peer(peers: &mut FnvHashMap<PeerId, PeerState>, peer_id: &PeerId, connections: usize, substreams: usize) {
+    let remove = match peers.get_mut(peer_id) {
+        Some(peer) => {
+            peer.connections -= connections;
+            peer.substreams -= substreams;
+            peer.connections == 0 && peer.substreams == 0
+        },
+        None => false,
+    };
+
+    if remove {
+        peers.remove(peer_id);
+    }
+}
+
+/// Permit for a connection. The connection is released when the permit is dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    manager: ResourceManager,
+    id: u64,
+}
+
+impl ConnectionPermit {
+    /// Assigns the peer at the other side of the connection, and checks the limit of connections
+    /// per peer. The substreams that have already been reserved are transferred to the peer.
+    ///
+    /// Assigning the same peer multiple times does nothing.
+    pub fn set_peer(&mut self, peer_id: PeerId) -> Result<(), ResourceError> {
+        let mut state = self.manager.state.lock();
+        let state = &mut *state;
+        let connection = state.connections.get_mut(&self.id).expect("the permit is alive");
+
+        if connection.peer.as_ref() == Some(&peer_id) {
+            return Ok(());
+        }
+
+        if let Some(limit) = state.limits.max_connections_per_peer {
+            let current = state.peers.get(&peer_id).map(|peer| peer.connections).unwrap_or(0);
+            if current >= limit {
+                return Err(ResourceError::PeerConnectionLimit { limit });
+            }
+        }
+
+        if let Some(ref previous) = connection.peer {
+            release_peer(&mut state.peers, previous, 1, connection.substreams);
+        }
+
+        let peer = state.peers.entry(peer_id.clone()).or_insert_with(Default::default);
+        peer.connections += 1;
+        peer.substreams += connection.substreams;
+        connection.peer = Some(peer_id);
+        Ok(())
+    }
+
+    /// Returns the peer at the other side of the connection, if it has been assigned.
+    #[inline]
+    pub fn peer(&self) -> Option<PeerId> {
+        let state = self.manager.state.lock();
+        state.connections.get(&self.id).and_then(|connection| connection.peer.clone())
+    }
+
+    /// Reserves a substream on this connection.
+    pub fn reserve_substream(&self) -> Result<SubstreamPermit, ResourceError> {
+        let mut state = self.manager.state.lock();
+        let state = &mut *state;
+        let connection = state.connections.get_mut(&self.id).expect("the permit is alive");
+
+        if let Some(limit) = state.limits.max_substreams_per_connection {
+            if connection.substreams >= limit {
+                return Err(ResourceError::SubstreamLimit { limit });
+            }
+        }
+
+        if let Some(ref peer_id) = connection.peer {
+            let peer = state.peers.get_mut(peer_id).expect("peers with a connection have an entry");
+            if let Some(limit) = state.limits.max_substreams_per_peer {
+                if peer.substreams >= limit {
+                    return Err(ResourceError::PeerSubstreamLimit { limit });
+                }
+            }
+            peer.substreams += 1;
+        }
+
+        connection.substreams += 1;
+        state.substreams += 1;
+
+        Ok(SubstreamPermit {
+            manager: self.manager.clone(),
+            connection_id: self.id,
+        })
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut state = self.manager.state.lock();
+        let state = &mut *state;
+        state.open_connections -= 1;
+
+        let remove = {
+            let connection = state.connections.get_mut(&self.id).expect("the permit is alive");
+            connection.open = false;
+            if let Some(ref peer_id) = connection.peer {
+                release_peer(&mut state.peers, peer_id, 1, 0);
+            }
+            connection.substreams == 0
+        };
+
+        if remove {
+            state.connections.remove(&self.id);
+        }
+    }
+}
+
+/// Permit for a substream. The substream is released when the permit is dropped.
+#[derive(Debug)]
+pub struct SubstreamPermit {
+    manager: ResourceManager,
+    connection_id: u64,
+}
+
+impl Drop for SubstreamPermit {
+    #[inline]
+    fn drop(&mut self) {
+        self.manager.state.lock().release_substream(self.connection_id);
+    }
+}
+
+/// Permit for memory reserved by a protocol. The memory is released when the permit is dropped.
+#[derive(Debug)]
+pub struct MemoryPermit {
+    manager: ResourceManager,
+    protocol: Bytes,
+    size: usize,
+}
+
+impl MemoryPermit {
+    /// Returns the number of bytes held by this permit.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reserves `additional` more bytes. On error, the size of the permit is unchanged.
+    pub fn grow(&mut self, additional: usize) -> Result<(), ResourceError> {
+        let mut state = self.manager.state.lock();
+        let state = &mut *state;
+
+        if let Some(limit) = state.limits.max_memory {
+            if state.memory.saturating_add(additional) > limit {
+                return Err(ResourceError::MemoryLimit { requested: additional, limit });
+            }
+        }
+
+        let protocol_memory = state.protocols_memory.get(&self.protocol).cloned().unwrap_or(0);
+        if let Some(limit) = state.limits.max_memory_per_protocol {
+            if protocol_memory.saturating_add(additional) > limit {
+                return Err(ResourceError::ProtocolMemoryLimit { requested: additional, limit });
+            }
+        }
+
+        if additional != 0 {
+            state.memory += additional;
+            state.protocols_memory.insert(self.protocol.clone(), protocol_memory + additional);
+            self.size += additional;
+        }
+        Ok(())
+    }
+
+    /// Releases `amount` bytes, or all of them if `amount` is larger than the size of the permit.
+    pub fn shrink(&mut self, amount: usize) {
+        let amount = if amount > self.size { self.size } else { amount };
+        if amount == 0 {
+            return;
+        }
+
+        let mut state = self.manager.state.lock();
+        state.memory -= amount;
+        let remove = match state.protocols_memory.get_mut(&self.protocol) {
+            Some(memory) => {
+                *memory -= amount;
+                *memory == 0
+            },
+            None => false,
+        };
+        if remove {
+            state.protocols_memory.remove(&self.protocol);
+        }
+        self.size -= amount;
+    }
+}
+
+impl Drop for MemoryPermit {
+    #[inline]
+    fn drop(&mut self) {
+        let size = self.size;
+        self.shrink(size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PublicKey;
+
+    fn peer_id(n: u8) -> PeerId {
+        PublicKey::Rsa((0..32).map(|_| n).collect()).into_peer_id()
+    }
+
+    #[test]
+    fn connection_limits() {
+        let manager = ResourceManager::new(ResourceLimits::new()
+            .with_max_connections(3)
+            .with_max_connections_per_peer(1));
+
+        let mut first = manager.reserve_connection().unwrap();
+        let mut second = manager.reserve_connection().unwrap();
+        first.set_peer(peer_id(1)).unwrap();
+        assert_eq!(second.set_peer(peer_id(1)).unwrap_err(), ResourceError::PeerConnectionLimit { limit: 1 });
+        second.set_peer(peer_id(2)).unwrap();
+
+        let third = manager.reserve_connection().unwrap();
+        assert_eq!(manager.reserve_connection().unwrap_err(), ResourceError::ConnectionLimit { limit: 3 });
+        drop(third);
+        drop(first);
+        assert_eq!(manager.peer_connections(&peer_id(1)), 0);
+        assert_eq!(manager.stats().connections, 1);
+    }
+
+    #[test]
+    fn substream_limits() {
+        let manager = ResourceManager::new(ResourceLimits::new()
+            .with_max_substreams_per_connection(2)
+            .with_max_substreams_per_peer(3));
+
+        let mut first = manager.reserve_connection().unwrap();
+        let mut second = manager.reserve_connection().unwrap();
+        let s1 = first.reserve_substream().unwrap();
+        let _s2 = first.reserve_substream().unwrap();
+        assert_eq!(first.reserve_substream().unwrap_err(), ResourceError::SubstreamLimit { limit: 2 });
+
+        // The substreams reserved before the peer is known are accounted to it.
+        first.set_peer(peer_id(1)).unwrap();
+        second.set_peer(peer_id(1)).unwrap();
+        let _s3 = second.reserve_substream().unwrap();
+        assert_eq!(second.reserve_substream().unwrap_err(), ResourceError::PeerSubstreamLimit { limit: 3 });
+
+        drop(s1);
+        drop(first);
+        assert_eq!(manager.peer_substreams(&peer_id(1)), 2);
+        assert_eq!(manager.stats().substreams, 2);
+    }
+
+    #[test]
+    fn memory_limits() {
+        let manager = ResourceManager::new(ResourceLimits::new()
+            .with_max_memory(100)
+            .with_max_memory_per_protocol(60));
+
+        let mut a = manager.reserve_memory(Bytes::from("/a"), 50).unwrap();
+        assert_eq!(a.grow(20).unwrap_err(), ResourceError::ProtocolMemoryLimit { requested: 20, limit: 60 });
+        let b = manager.reserve_memory(Bytes::from("/b"), 50).unwrap();
+        assert_eq!(manager.reserve_memory(Bytes::from("/c"), 1).unwrap_err(), ResourceError::MemoryLimit { requested: 1, limit: 100 });
+
+        a.shrink(30);
+        assert_eq!(a.size(), 20);
+        assert_eq!(manager.protocol_memory(b"/a"), 20);
+        drop(b);
+        drop(a);
+        assert_eq!(manager.stats().memory, 0);
+        assert_eq!(manager.protocol_memory(b"/a"), 0);
+    }
+}
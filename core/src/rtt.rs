@@ -0,0 +1,176 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Estimation of the round-trip time of a connection.
+//!
+//! The `RttEstimator` follows the algorithm of RFC 6298: it maintains a smoothed round-trip time
+//! and its variance, which are updated every time a new sample is reported. Samples can come from
+//! any protocol that measures round-trips, such as the ping protocol or acknowledgements sent by
+//! a multiplexer.
+//!
+//! Since the producers of samples usually run on a different task than the code that reads the
+//! estimate, the estimator is normally accessed through a `SharedRtt`, which can be cloned and
+//! passed around.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Smoothed estimate of the round-trip time of a connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RttEstimator {
+    /// Smoothed round-trip time. `None` if no sample has been reported yet.
+    smoothed: Option<Duration>,
+    /// Variance of the round-trip time.
+    variance: Duration,
+    /// Lowest sample ever reported.
+    min: Option<Duration>,
+    /// Most recent sample.
+    latest: Option<Duration>,
+    /// Number of samples reported so far.
+    samples: u64,
+}
+
+impl RttEstimator {
+    /// Creates an estimator without any sample.
+    #[inline]
+    pub fn new() -> RttEstimator {
+        RttEstimator::default()
+    }
+
+    /// Reports a new round-trip time measurement.
+    pub fn add_sample(&mut self, rtt: Duration) {
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variance = rtt / 2;
+            },
+            Some(smoothed) => {
+                let diff = if smoothed > rtt { smoothed - rtt } else { rtt - smoothed };
+                // RTTVAR <- 3/4 * RTTVAR + 1/4 * |SRTT - R|
+                self.variance = (self.variance * 3 + diff) / 4;
+                // SRTT <- 7/8 * SRTT + 1/8 * R
+                self.smoothed = Some((smoothed * 7 + rtt) / 8);
+            },
+        }
+
+        self.min = Some(self.min.map_or(rtt, |min| if rtt < min { rtt } else { min }));
+        self.latest = Some(rtt);
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// Returns the smoothed round-trip time, or `None` if no sample has been reported.
+    #[inline]
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed
+    }
+
+    /// Returns the variance of the round-trip time. Zero if no sample has been reported.
+    #[inline]
+    pub fn variance(&self) -> Duration {
+        self.variance
+    }
+
+    /// Returns the lowest round-trip time that has been reported.
+    #[inline]
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Returns the most recent round-trip time that has been reported.
+    #[inline]
+    pub fn latest(&self) -> Option<Duration> {
+        self.latest
+    }
+
+    /// Returns the number of samples that have been reported.
+    #[inline]
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+}
+
+/// Handle to an `RttEstimator` shared between multiple owners.
+///
+/// Cloning a `SharedRtt` is cheap, and all the clones report to and read from the same estimator.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRtt {
+    inner: Arc<Mutex<RttEstimator>>,
+}
+
+impl SharedRtt {
+    /// Creates a handle to a new estimator without any sample.
+    #[inline]
+    pub fn new() -> SharedRtt {
+        SharedRtt::default()
+    }
+
+    /// Reports a new round-trip time measurement.
+    #[inline]
+    pub fn add_sample(&self, rtt: Duration) {
+        self.inner.lock().add_sample(rtt)
+    }
+
+    /// Returns the smoothed round-trip time, or `None` if no sample has been reported.
+    #[inline]
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.inner.lock().smoothed()
+    }
+
+    /// Returns a copy of the current state of the estimator.
+    #[inline]
+    pub fn snapshot(&self) -> RttEstimator {
+        self.inner.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!(rtt.smoothed(), None);
+        rtt.add_sample(Duration::from_millis(100));
+        assert_eq!(rtt.smoothed(), Some(Duration::from_millis(100)));
+        assert_eq!(rtt.variance(), Duration::from_millis(50));
+        assert_eq!(rtt.samples(), 1);
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut rtt = RttEstimator::new();
+        rtt.add_sample(Duration::from_millis(100));
+        rtt.add_sample(Duration::from_millis(180));
+        assert_eq!(rtt.smoothed(), Some(Duration::from_millis(110)));
+        assert_eq!(rtt.variance(), Duration::from_millis(57) + Duration::from_micros(500));
+        assert_eq!(rtt.min(), Some(Duration::from_millis(100)));
+        assert_eq!(rtt.latest(), Some(Duration::from_millis(180)));
+    }
+
+    #[test]
+    fn shared_between_clones() {
+        let rtt = SharedRtt::new();
+        rtt.clone().add_sample(Duration::from_millis(20));
+        assert_eq!(rtt.smoothed(), Some(Duration::from_millis(20)));
+        assert_eq!(rtt.snapshot().samples(), 1);
+    }
+}
@@ -0,0 +1,530 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Abstraction over the asynchronous runtime.
+//!
+//! The swarm needs to spawn background tasks (one for each connection) and to wait until a
+//! certain point in time (closing deadlines, refreshes of the network interfaces, ...). Instead
+//! of hard-coding a specific runtime, it uses the `Executor` and `Timer` traits, which are grouped
+//! together in a `Runtime` and passed to the swarm.
+//!
+//! The default `Runtime` spawns on the tokio executor of the current thread and uses the system
+//! clock. Other runtimes can be supported by implementing the traits. On platforms without a tokio
+//! executor, such as WASM, enable the `wasm` feature: the tasks are then polled by the swarm
+//! itself.
+//!
+//! A `Runtime` also holds a `RandomSource`. The protocols that make random decisions, such as the
+//! IDs looked up by Kademlia or the remotes picked by gossipsub, accept a `RandomSource` and
+//! should be given the one of the runtime instead of using `rand::thread_rng`.
+//!
+//! For tests and simulations, `Runtime::deterministic` builds a runtime that never spawns tasks in
+//! the background, whose time only advances when `ManualTimer::advance` is called, and whose
+//! `RandomSource` is seeded. Running a swarm on such a runtime is entirely deterministic.
+//!
+//! The executor of a runtime can be replaced with `with_executor`. `ThreadPoolExecutor` drives
+//! the tasks on a dedicated pool of threads of a configurable size, while `LocalExecutor` never
+//! spawns anything, so that all the tasks are polled by the thread that polls the swarm.
+
+use futures::{prelude::*, task};
+use parking_lot::Mutex;
+use rand::{FromEntropy, Rng, RngCore, SeedableRng};
+use rand::prng::XorShiftRng;
+use rand::rngs::StdRng;
+use std::fmt;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use std::time::Duration;
+use wasm_timer::Instant;
+#[cfg(not(feature = "wasm"))]
+use tokio_executor::{self, Executor as TokioExecutor};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_threadpool::{Builder as ThreadPoolBuilder, ThreadPool};
+
+/// Future that can be spawned on an `Executor`.
+pub type BoxTask = Box<Future<Item = (), Error = ()> + Send>;
+
+/// Future produced by a `Timer`, that resolves once a point in time has been reached.
+pub type Delay = Box<Future<Item = (), Error = IoError> + Send>;
+
+/// Spawns futures in the background.
+pub trait Executor {
+    /// Spawns a future so that it runs in the background.
+    ///
+    /// If the executor can't spawn the future, it must return it back. The caller is then
+    /// responsible for polling it.
+    fn spawn(&self, future: BoxTask) -> Result<(), BoxTask>;
+}
+
+/// Provides the current time and futures that wait for a point in time.
+pub trait Timer {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Builds a future that resolves when `deadline` is reached.
+    ///
+    /// > **Note**: Like any future, the returned `Delay` only notifies the current task after
+    /// >           it has been polled once.
+    fn delay(&self, deadline: Instant) -> Delay;
+}
+
+/// Groups together an `Executor`, a `Timer` and a `RandomSource`.
+///
+/// Cloning a `Runtime` is cheap, and the clones share the same executor, timer and random source.
+#[derive(Clone)]
+pub struct Runtime {
+    executor: Arc<Executor + Send + Sync>,
+    timer: Arc<Timer + Send + Sync>,
+    random: RandomSource,
+}
+
+impl Runtime {
+    /// Builds a `Runtime` from an executor and a timer. The random source is seeded from the
+    /// entropy of the system.
+    #[inline]
+    pub fn new<E, T>(executor: E, timer: T) -> Runtime
+    where
+        E: Executor + Send + Sync + 'static,
+        T: Timer + Send + Sync + 'static,
+    {
+        Runtime {
+            executor: Arc::new(executor),
+            timer: Arc::new(timer),
+            random: RandomSource::from_entropy(),
+        }
+    }
+
+    /// Builds a deterministic `Runtime`. No task is ever spawned in the background, the time is
+    /// controlled by `timer`, and the random source is seeded with `0`. Use `with_random_source`
+    /// in order to pick another seed.
+    #[inline]
+    pub fn deterministic(timer: ManualTimer) -> Runtime {
+        Runtime::new(LocalExecutor, timer).with_random_source(RandomSource::seeded(0))
+    }
+
+    /// Replaces the executor of the runtime, keeping its timer and its random source.
+    #[inline]
+    pub fn with_executor<E>(mut self, executor: E) -> Runtime
+    where
+        E: Executor + Send + Sync + 'static,
+    {
+        self.executor = Arc::new(executor);
+        self
+    }
+
+    /// Replaces the random source of the runtime.
+    #[inline]
+    pub fn with_random_source(mut self, random: RandomSource) -> Runtime {
+        self.random = random;
+        self
+    }
+
+    /// Returns the random source of the runtime. Clone it in order to pass it to the protocols.
+    #[inline]
+    pub fn random_source(&self) -> &RandomSource {
+        &self.random
+    }
+
+    /// Spawns a future. See `Executor::spawn`.
+    #[inline]
+    pub fn spawn(&self, future: BoxTask) -> Result<(), BoxTask> {
+        self.executor.spawn(future)
+    }
+
+    /// Returns the current time. See `Timer::now`.
+    #[inline]
+    pub fn now(&self) -> Instant {
+        self.timer.now()
+    }
+
+    /// Builds a future that resolves at `deadline`. See `Timer::delay`.
+    #[inline]
+    pub fn delay(&self, deadline: Instant) -> Delay {
+        self.timer.delay(deadline)
+    }
+
+    /// Builds a future that resolves after `duration` has elapsed.
+    #[inline]
+    pub fn delay_for(&self, duration: Duration) -> Delay {
+        let deadline = self.now() + duration;
+        self.delay(deadline)
+    }
+}
+
+impl Default for Runtime {
+    #[inline]
+    fn default() -> Self {
+        Runtime::new(DefaultExecutor, SystemTimer)
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Runtime").finish()
+    }
+}
+
+/// Shared source of random numbers.
+///
+/// Cloning a `RandomSource` is cheap, and the clones draw from the same generator. Protocols
+/// should draw their random numbers from a `RandomSource` that is passed to them, so that seeding
+/// it with `seeded` makes their decisions reproducible.
+///
+/// Implements the `RngCore` trait of `rand`, but the methods can also be called on a shared
+/// reference.
+#[derive(Clone)]
+pub struct RandomSource {
+    inner: Arc<Mutex<Box<RngCore + Send>>>,
+}
+
+impl RandomSource {
+    /// Builds a `RandomSource` that draws from the given generator.
+    #[inline]
+    pub fn new<R>(rng: R) -> RandomSource
+    where
+        R: RngCore + Send + 'static,
+    {
+        RandomSource {
+            inner: Arc::new(Mutex::new(Box::new(rng))),
+        }
+    }
+
+    /// Builds a `RandomSource` seeded from the entropy of the system.
+    #[inline]
+    pub fn from_entropy() -> RandomSource {
+        RandomSource::new(StdRng::from_entropy())
+    }
+
+    /// Builds a `RandomSource` that always produces the same numbers for the same seed.
+    pub fn seeded(seed: u64) -> RandomSource {
+        let mut bytes = [0; 16];
+        for (n, byte) in bytes.iter_mut().enumerate() {
+            *byte = (seed >> (8 * (n % 8))) as u8;
+        }
+        RandomSource::new(XorShiftRng::from_seed(bytes))
+    }
+
+    /// Returns a random `u32`.
+    #[inline]
+    pub fn next_u32(&self) -> u32 {
+        self.inner.lock().next_u32()
+    }
+
+    /// Returns a random `u64`.
+    #[inline]
+    pub fn next_u64(&self) -> u64 {
+        self.inner.lock().next_u64()
+    }
+
+    /// Fills `dest` with random bytes.
+    #[inline]
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        self.inner.lock().fill_bytes(dest)
+    }
+
+    /// Shuffles the elements of `values` in place.
+    #[inline]
+    pub fn shuffle<T>(&self, values: &mut [T]) {
+        self.inner.lock().shuffle(values)
+    }
+}
+
+impl Default for RandomSource {
+    #[inline]
+    fn default() -> Self {
+        RandomSource::from_entropy()
+    }
+}
+
+impl RngCore for RandomSource {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        RandomSource::next_u32(self)
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        RandomSource::next_u64(self)
+    }
+
+    #[inline]
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RandomSource::fill_bytes(self, dest)
+    }
+
+    #[inline]
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), ::rand::Error> {
+        self.inner.lock().try_fill_bytes(dest)
+    }
+}
+
+impl fmt::Debug for RandomSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RandomSource").finish()
+    }
+}
+
+/// Executor that spawns on the tokio executor of the current context.
+///
+/// With the `wasm` feature, where there is no such executor, the future is always given back and
+/// polled by the swarm.
+///
+/// # Panic
+///
+/// Without the `wasm` feature, panics if there is no tokio executor in the current context. Use
+/// `LocalExecutor` in order to poll the tasks from the swarm instead.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DefaultExecutor;
+
+impl Executor for DefaultExecutor {
+    #[cfg(not(feature = "wasm"))]
+    fn spawn(&self, future: BoxTask) -> Result<(), BoxTask> {
+        let mut executor = tokio_executor::DefaultExecutor::current();
+        if let Err(err) = executor.status() {
+            panic!("No tokio executor to spawn a task on ({:?}) ; run the swarm within a tokio \
+                    runtime, enable the `wasm` feature or use a `LocalExecutor`", err);
+        }
+
+        if let Err(err) = executor.spawn(future) {
+            error!("Failed to spawn a task: {:?}", err);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "wasm")]
+    #[inline]
+    fn spawn(&self, future: BoxTask) -> Result<(), BoxTask> {
+        Err(future)
+    }
+}
+
+/// Executor that never spawns anything, so that the futures are always polled by the caller.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LocalExecutor;
+
+impl Executor for LocalExecutor {
+    #[inline]
+    fn spawn(&self, future: BoxTask) -> Result<(), BoxTask> {
+        Err(future)
+    }
+}
+
+/// Executor that spawns the futures on a dedicated pool of threads.
+///
+/// The threads are stopped once the `ThreadPoolExecutor` and all the `Runtime`s that hold it
+/// are dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ThreadPoolExecutor {
+    pool: ThreadPool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ThreadPoolExecutor {
+    /// Builds a pool of `threads` threads.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `threads` is 0.
+    pub fn new(threads: usize) -> ThreadPoolExecutor {
+        assert!(threads > 0, "a thread pool needs at least one thread");
+        let pool = ThreadPoolBuilder::new()
+            .pool_size(threads)
+            .name_prefix("libp2p-node-")
+            .build();
+        ThreadPoolExecutor { pool }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Executor for ThreadPoolExecutor {
+    #[inline]
+    fn spawn(&self, future: BoxTask) -> Result<(), BoxTask> {
+        self.pool.spawn(future);
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl fmt::Debug for ThreadPoolExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ThreadPoolExecutor").finish()
+    }
+}
+
+/// Timer based on the system clock.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemTimer;
+
+impl Timer for SystemTimer {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn delay(&self, deadline: Instant) -> Delay {
+        Box::new(::wasm_timer::Delay::new(deadline))
+    }
+}
+
+/// Timer whose time only advances when `advance` is called.
+///
+/// Cloning a `ManualTimer` is cheap, and all the clones share the same clock.
+#[derive(Debug, Clone)]
+pub struct ManualTimer {
+    inner: Arc<Mutex<ManualTimerInner>>,
+}
+
+#[derive(Debug)]
+struct ManualTimerInner {
+    /// Current time of the clock.
+    now: Instant,
+    /// Tasks waiting for the time to advance, with their deadline.
+    waiting: Vec<(Instant, task::Task)>,
+}
+
+impl ManualTimer {
+    /// Builds a new `ManualTimer`. Its clock starts at the current system time.
+    #[inline]
+    pub fn new() -> ManualTimer {
+        ManualTimer {
+            inner: Arc::new(Mutex::new(ManualTimerInner {
+                now: Instant::now(),
+                waiting: Vec::new(),
+            })),
+        }
+    }
+
+    /// Advances the clock by `duration`, and wakes up the delays that have expired.
+    pub fn advance(&self, duration: Duration) {
+        let expired = {
+            let mut inner = self.inner.lock();
+            inner.now += duration;
+            let now = inner.now;
+            let (expired, waiting) = inner.waiting.drain(..).partition(|&(deadline, _)| deadline <= now);
+            inner.waiting = waiting;
+            expired
+        };
+
+        // We notify after releasing the lock, in case the tasks run on this thread.
+        for (_, task) in expired {
+            task.notify();
+        }
+    }
+}
+
+impl Default for ManualTimer {
+    #[inline]
+    fn default() -> Self {
+        ManualTimer::new()
+    }
+}
+
+impl Timer for ManualTimer {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.inner.lock().now
+    }
+
+    #[inline]
+    fn delay(&self, deadline: Instant) -> Delay {
+        Box::new(ManualDelay {
+            timer: self.inner.clone(),
+            deadline,
+        })
+    }
+}
+
+/// Future returned by `ManualTimer::delay`.
+struct ManualDelay {
+    timer: Arc<Mutex<ManualTimerInner>>,
+    deadline: Instant,
+}
+
+impl Future for ManualDelay {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<(), IoError> {
+        let mut inner = self.timer.lock();
+        if inner.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+
+        inner.waiting.push((self.deadline, task::current()));
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn manual_timer() {
+        let timer = ManualTimer::new();
+        let runtime = Runtime::deterministic(timer.clone());
+        let start = runtime.now();
+        let mut delay = runtime.delay_for(Duration::from_secs(5));
+
+        future::poll_fn(|| {
+            assert!(delay.poll().unwrap().is_not_ready());
+            timer.advance(Duration::from_secs(4));
+            assert!(delay.poll().unwrap().is_not_ready());
+            timer.advance(Duration::from_secs(1));
+            assert!(delay.poll().unwrap().is_ready());
+            Ok::<_, ()>(Async::Ready(()))
+        }).wait().unwrap();
+
+        assert_eq!(runtime.now(), start + Duration::from_secs(5));
+    }
+
+    #[test]
+    fn thread_pool_executor() {
+        let runtime = Runtime::deterministic(ManualTimer::new())
+            .with_executor(ThreadPoolExecutor::new(2));
+        let (tx, rx) = ::futures::sync::oneshot::channel();
+        let task = future::lazy(move || tx.send(5).map_err(|_| ()));
+        assert!(runtime.spawn(Box::new(task)).is_ok());
+        assert_eq!(rx.wait().unwrap(), 5);
+    }
+
+    #[test]
+    fn seeded_random_source() {
+        let a = Runtime::deterministic(ManualTimer::new());
+        let b = Runtime::deterministic(ManualTimer::new());
+        let mut values_a = (0 .. 16).collect::<Vec<u32>>();
+        let mut values_b = values_a.clone();
+        a.random_source().shuffle(&mut values_a);
+        b.random_source().shuffle(&mut values_b);
+        assert_eq!(values_a, values_b);
+        assert_eq!(a.random_source().next_u64(), b.random_source().next_u64());
+        assert_ne!(RandomSource::seeded(1).next_u64(), RandomSource::seeded(2).next_u64());
+    }
+
+    #[test]
+    fn local_executor_returns_future() {
+        let runtime = Runtime::deterministic(ManualTimer::new());
+        let task: BoxTask = Box::new(future::ok(()));
+        assert!(runtime.spawn(task).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Attribution of the log lines to a node, a connection and a protocol.
+//!
+//! When several nodes run in the same process, for example in a simulation, their log lines are
+//! interleaved and it is impossible to tell which node produced which line. A `Span` is a set of
+//! named fields, such as the id of the local node, the id of the remote or the id of the
+//! connection, that is *entered* while some code runs. Spans form a tree: the fields of a child
+//! span are added to the fields of its parent.
+//!
+//! The `SpanLogger` wraps around any implementation of `log::Log` and prefixes each line with the
+//! fields of the span entered on the current thread, if any. The existing `log` macros therefore
+//! don't need to be modified.
+//!
+//! The `RawSwarm` enters the span passed to `set_span` whenever it is polled, and the task of
+//! each connection enters a child span with a `conn` field, plus a `remote` field once the remote
+//! is known. The behaviours are polled by the swarm and the connection handlers by the connection
+//! tasks, so their log lines are attributed as well. Other futures can be attributed with
+//! `Instrument::instrument`.
+//!
+//! The field names used in this crate are `local`, `remote`, `conn` and `protocol`.
+//!
+//! # Example
+//!
+//! ```
+//! use libp2p_core::span::Span;
+//!
+//! let node = Span::root().with_field("local", "node-1");
+//! let connection = node.child().with_field("conn", 3);
+//!
+//! let _entered = connection.enter();
+//! // Any line logged here is prefixed with `[local=node-1 conn=3]` by the `SpanLogger`.
+//! assert_eq!(Span::current().unwrap().to_string(), "local=node-1 conn=3");
+//! ```
+
+use futures::prelude::*;
+use log::{self, Log, Metadata, Record, SetLoggerError};
+use parking_lot::RwLock;
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+thread_local! {
+    /// Stack of the spans entered on the current thread.
+    static CURRENT: RefCell<Vec<Span>> = RefCell::new(Vec::new());
+}
+
+/// Set of fields that attributes the log lines produced while it is entered.
+///
+/// Cloning a `Span` produces a handle to the same span.
+#[derive(Clone)]
+pub struct Span {
+    inner: Arc<SpanInner>,
+}
+
+struct SpanInner {
+    /// Span whose fields come before ours.
+    parent: Option<Span>,
+    /// Fields of the span, in the order they were first recorded.
+    fields: RwLock<Vec<(&'static str, String)>>,
+}
+
+impl Span {
+    /// Creates a span without parent and without fields.
+    #[inline]
+    pub fn root() -> Span {
+        Span::with_parent(None)
+    }
+
+    /// Creates a span without fields whose parent is `self`.
+    #[inline]
+    pub fn child(&self) -> Span {
+        Span::with_parent(Some(self.clone()))
+    }
+
+    /// Creates a child of the span entered on the current thread, or a root span if none is.
+    #[inline]
+    pub fn child_of_current() -> Span {
+        Span::with_parent(Span::current())
+    }
+
+    fn with_parent(parent: Option<Span>) -> Span {
+        Span {
+            inner: Arc::new(SpanInner {
+                parent,
+                fields: RwLock::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Records a field and returns the span.
+    #[inline]
+    pub fn with_field<V>(self, name: &'static str, value: V) -> Span
+    where
+        V: fmt::Display,
+    {
+        self.record(name, value);
+        self
+    }
+
+    /// Records a field, or replaces its value if this span already has it. Affects all the
+    /// handles to this span, including the ones that are currently entered.
+    pub fn record<V>(&self, name: &'static str, value: V)
+    where
+        V: fmt::Display,
+    {
+        let value = value.to_string();
+        let mut fields = self.inner.fields.write();
+        if let Some(field) = fields.iter_mut().find(|f| f.0 == name) {
+            field.1 = value;
+            return;
+        }
+        fields.push((name, value));
+    }
+
+    /// Returns the value of a field of this span or, if it doesn't have it, of its closest
+    /// parent that does.
+    pub fn field(&self, name: &str) -> Option<String> {
+        let value = self.inner.fields.read()
+            .iter()
+            .find(|f| f.0 == name)
+            .map(|f| f.1.clone());
+        match (value, &self.inner.parent) {
+            (Some(value), _) => Some(value),
+            (None, Some(parent)) => parent.field(name),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns true if neither this span nor its parents have any field.
+    pub fn is_empty(&self) -> bool {
+        self.inner.fields.read().is_empty() &&
+            self.inner.parent.as_ref().map(|p| p.is_empty()).unwrap_or(true)
+    }
+
+    /// Enters the span on the current thread until the returned guard is dropped.
+    pub fn enter(&self) -> Entered {
+        CURRENT.with(|current| current.borrow_mut().push(self.clone()));
+        Entered { marker: PhantomData }
+    }
+
+    /// Runs `f` with the span entered.
+    #[inline]
+    pub fn in_scope<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _entered = self.enter();
+        f()
+    }
+
+    /// Returns the span most recently entered on the current thread, if any.
+    pub fn current() -> Option<Span> {
+        CURRENT.with(|current| current.borrow().last().cloned())
+    }
+
+    /// Writes the fields of the parents, then ours. Returns true if anything has been written.
+    fn write_fields(&self, f: &mut fmt::Formatter) -> Result<bool, fmt::Error> {
+        let mut written = match self.inner.parent {
+            Some(ref parent) => parent.write_fields(f)?,
+            None => false,
+        };
+        for &(name, ref value) in self.inner.fields.read().iter() {
+            if written {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+            written = true;
+        }
+        Ok(written)
+    }
+}
+
+impl fmt::Display for Span {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_fields(f).map(|_| ())
+    }
+}
+
+impl fmt::Debug for Span {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Span({})", self)
+    }
+}
+
+/// Guard returned by `Span::enter`. Exits the span when dropped.
+///
+/// > **Note**: The spans must be exited in the reverse order they were entered, on the thread
+/// >           they were entered on, which is why this type isn't `Send`.
+pub struct Entered {
+    marker: PhantomData<*const ()>,
+}
+
+impl Drop for Entered {
+    #[inline]
+    fn drop(&mut self) {
+        CURRENT.with(|current| { current.borrow_mut().pop(); });
+    }
+}
+
+/// Extension trait that attaches a span to a `Future` or a `Stream`.
+pub trait Instrument: Sized {
+    /// Wraps around `self` so that the span is entered whenever it is polled.
+    #[inline]
+    fn instrument(self, span: Span) -> Instrumented<Self> {
+        Instrumented { inner: self, span }
+    }
+}
+
+impl<T> Instrument for T {}
+
+/// Future or stream that enters a span whenever it is polled. See `Instrument::instrument`.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Instrumented<T> {
+    inner: T,
+    span: Span,
+}
+
+impl<T> Instrumented<T> {
+    /// Returns the span of this object.
+    #[inline]
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl<T> Future for Instrumented<T>
+where
+    T: Future,
+{
+    type Item = T::Item;
+    type Error = T::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let _entered = self.span.enter();
+        self.inner.poll()
+    }
+}
+
+impl<T> Stream for Instrumented<T>
+where
+    T: Stream,
+{
+    type Item = T::Item;
+    type Error = T::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let _entered = self.span.enter();
+        self.inner.poll()
+    }
+}
+
+/// Implementation of `log::Log` that prefixes the lines with the fields of the current span,
+/// then passes them to another logger.
+#[derive(Debug)]
+pub struct SpanLogger<L> {
+    inner: L,
+}
+
+impl<L> SpanLogger<L> {
+    /// Wraps around a logger.
+    #[inline]
+    pub fn new(inner: L) -> SpanLogger<L> {
+        SpanLogger { inner }
+    }
+
+    /// Installs this logger as the global logger of the `log` crate, with the given maximum
+    /// level.
+    ///
+    /// Fails if a global logger has already been installed.
+    pub fn init(self, max_level: log::LevelFilter) -> Result<(), SetLoggerError>
+    where
+        L: Log + 'static,
+    {
+        log::set_logger(Box::leak(Box::new(self)))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl<L> Log for SpanLogger<L>
+where
+    L: Log,
+{
+    #[inline]
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let span = match Span::current() {
+            Some(ref span) if !span.is_empty() => span.clone(),
+            _ => return self.inner.log(record),
+        };
+
+        self.inner.log(&Record::builder()
+            .args(format_args!("[{}] {}", span, record.args()))
+            .metadata(record.metadata().clone())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line())
+            .build());
+    }
+
+    #[inline]
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use super::{Instrument, Span};
+
+    #[test]
+    fn nested_spans() {
+        assert!(Span::current().is_none());
+
+        let node = Span::root().with_field("local", "a");
+        let conn = node.child().with_field("conn", 1);
+        {
+            let _node = node.enter();
+            let _conn = conn.enter();
+            conn.record("remote", "b");
+            assert_eq!(Span::current().unwrap().to_string(), "local=a conn=1 remote=b");
+            assert_eq!(conn.field("local"), Some("a".to_owned()));
+        }
+
+        assert!(Span::current().is_none());
+    }
+
+    #[test]
+    fn instrumented_future() {
+        let span = Span::root().with_field("local", "a");
+        let future = future::lazy(|| {
+            Ok::<_, ()>(Span::current().map(|s| s.to_string()))
+        });
+        assert_eq!(future.instrument(span).wait(), Ok(Some("local=a".to_owned())));
+        assert!(Span::current().is_none());
+    }
+}
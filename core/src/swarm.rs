@@ -26,8 +26,14 @@ use parking_lot::Mutex;
 use std::fmt;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::sync::Arc;
+use std::time::Duration;
+use wasm_timer::{Delay, Instant};
 use {Multiaddr, MuxedTransport, Transport};
 
+/// Delay between two consecutive connection attempts of a multi-address dial, as recommended by
+/// RFC 8305.
+const DEFAULT_DIAL_STAGGER_MS: u64 = 250;
+
 /// Creates a swarm.
 ///
 /// Requires an upgraded transport, and a function or closure that will turn the upgrade into a
@@ -35,6 +41,10 @@ use {Multiaddr, MuxedTransport, Transport};
 ///
 /// Produces a `SwarmController` and an implementation of `Future`. The controller can be used to
 /// control, and the `Future` must be driven to completion in order for things to work.
+///
+/// > **Note**: The handler is called with the connection but isn't told when the connection
+/// >           closes or why dialing failed. See `nodes::swarm::Swarm` for a swarm that tracks
+/// >           the connected peers and reports their lifecycle as typed events.
 pub fn swarm<T, H, F>(
     transport: T,
     handler: H,
@@ -145,58 +155,105 @@ where
 
         match transport.dial(multiaddr.clone()) {
             Ok(dial) => {
-                let (tx, rx) = oneshot::channel();
-                let mut then = Some(move |val| {
-                    let _ = tx.send(then(val));
-                });
-                // Unfortunately the `Box<FnOnce(_)>` type is still unusable in Rust right now,
-                // so we use a `Box<FnMut(_)>` instead and panic if it is called multiple times.
-                let mut then = Box::new(move |val: Result<(), IoError>| {
-                    let then = then.take().expect("The Boxed FnMut should only be called once");
-                    then(val);
-                }) as Box<FnMut(_) + Send>;
-
-                let dial = dial.then(|result| {
-                    match result {
-                        Ok(output) => {
-                            Ok((output.into(), then))
-                        }
-                        Err(err) => {
-                            debug!("Error in dialer upgrade: {:?}", err);
-                            let err_clone = IoError::new(err.kind(), err.to_string());
-                            then(Err(err));
-                            Err(err_clone)
-                        }
-                    }
-                });
+                let addrs = vec![multiaddr.clone()];
+                Ok(self.push_dialer(addrs, dial.map(move |out| (out, multiaddr)), then))
+            }
+            Err((_, multiaddr)) => Err(multiaddr),
+        }
+    }
 
-                let mut shared = self.shared.lock();
-                shared.dialers.push((multiaddr, Box::new(dial) as Box<_>));
-                if let Some(task) = shared.task_to_notify.take() {
-                    task.notify();
-                }
+    /// Asks the swarm to dial a node that is reachable through several multiaddresses.
+    ///
+    /// The addresses are dialed in order, with a delay between each attempt, and the first
+    /// connection that succeeds is kept while the other attempts are cancelled. An attempt is
+    /// started early if all the ongoing ones have failed. See `DialOpts` for more information.
+    ///
+    /// The connection is then sent to the handler that was passed when calling `swarm`, along
+    /// with the address that was successfully dialed. Returns back the `DialOpts` if it doesn't
+    /// contain any address.
+    pub fn dial_with_opts<Du>(&self, opts: DialOpts, transport: Du)
+        -> Result<impl Future<Item = (), Error = IoError>, DialOpts>
+    where
+        Du: Transport + Clone + Send + 'static, // TODO: 'static :-/
+        Du::Dial: Send,
+        Du::Output: Into<T::Output>,
+    {
+        if opts.addrs.is_empty() {
+            return Err(opts);
+        }
 
-                Ok(rx.then(|result| {
-                    match result {
-                        Ok(Ok(())) => Ok(()),
-                        Ok(Err(err)) => Err(err),
-                        Err(_) => Err(IoError::new(IoErrorKind::ConnectionAborted,
-                            "dial cancelled the swarm future has been destroyed")),
-                    }
-                }))
+        trace!("Swarm dialing {:?}", opts.addrs);
+        let addrs = opts.addrs.clone();
+        let dial = HappyEyeballs {
+            transport,
+            remaining: opts.addrs.into_iter(),
+            attempts: Vec::new(),
+            stagger: opts.stagger,
+            next_attempt: None,
+            last_error: None,
+        };
+        Ok(self.push_dialer(addrs, dial, |v| v))
+    }
+
+    // Adds a dialing future to the list of dialers of the swarm. The future must produce the
+    // connection and the address that was successfully dialed.
+    fn push_dialer<Fut, TOut, TThen>(&self, addrs: Vec<Multiaddr>, dial: Fut, then: TThen)
+        -> impl Future<Item = (), Error = IoError>
+    where
+        Fut: Future<Item = (TOut, Multiaddr), Error = IoError> + Send + 'static,
+        TOut: Into<T::Output>,
+        TThen: FnOnce(Result<(), IoError>) -> Result<(), IoError> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let mut then = Some(move |val| {
+            let _ = tx.send(then(val));
+        });
+        // Unfortunately the `Box<FnOnce(_)>` type is still unusable in Rust right now,
+        // so we use a `Box<FnMut(_)>` instead and panic if it is called multiple times.
+        let mut then = Box::new(move |val: Result<(), IoError>| {
+            let then = then.take().expect("The Boxed FnMut should only be called once");
+            then(val);
+        }) as Box<FnMut(_) + Send>;
+
+        let dial = dial.then(|result| {
+            match result {
+                Ok((output, addr)) => {
+                    Ok((output.into(), addr, then))
+                }
+                Err(err) => {
+                    debug!("Error in dialer upgrade: {:?}", err);
+                    let err_clone = IoError::new(err.kind(), err.to_string());
+                    then(Err(err));
+                    Err(err_clone)
+                }
             }
-            Err((_, multiaddr)) => Err(multiaddr),
+        });
+
+        let mut shared = self.shared.lock();
+        shared.dialers.push((addrs, Box::new(dial) as Box<_>));
+        if let Some(task) = shared.task_to_notify.take() {
+            task.notify();
         }
+
+        rx.then(|result| {
+            match result {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(err)) => Err(err),
+                Err(_) => Err(IoError::new(IoErrorKind::ConnectionAborted,
+                    "dial cancelled the swarm future has been destroyed")),
+            }
+        })
     }
 
-    /// Interrupts all dialing attempts to a specific multiaddress.
+    /// Interrupts all dialing attempts to a specific multiaddress, including the multi-address
+    /// dials that contain this multiaddress.
     ///
     /// Has no effect if the dialing attempt has already succeeded, in which case it will be
     /// dispatched to the handler.
     pub fn interrupt_dial(&self, multiaddr: &Multiaddr) {
         let mut shared = self.shared.lock();
         shared.dialers.retain(|dialer| {
-            &dialer.0 != multiaddr
+            !dialer.0.contains(multiaddr)
         });
     }
 
@@ -222,6 +279,157 @@ where
     }
 }
 
+/// Options for dialing a node that is reachable through several multiaddresses.
+///
+/// The addresses are dialed in the order in which they are passed, in the style of the "Happy
+/// Eyeballs" algorithm (RFC 8305): a new connection attempt is started every `stagger`, without
+/// interrupting the previous ones, until one of them succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialOpts {
+    addrs: Vec<Multiaddr>,
+    stagger: Duration,
+}
+
+impl DialOpts {
+    /// Creates the options for dialing the given addresses, with the default delay of 250ms
+    /// between two attempts.
+    pub fn new<I>(addrs: I) -> DialOpts
+    where
+        I: IntoIterator<Item = Multiaddr>,
+    {
+        DialOpts {
+            addrs: addrs.into_iter().collect(),
+            stagger: Duration::from_millis(DEFAULT_DIAL_STAGGER_MS),
+        }
+    }
+
+    /// Sets the delay between the start of two consecutive connection attempts.
+    ///
+    /// A delay of zero dials all the addresses at once.
+    #[inline]
+    pub fn with_stagger(mut self, stagger: Duration) -> DialOpts {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Returns the addresses to dial, in order.
+    #[inline]
+    pub fn addrs(&self) -> &[Multiaddr] {
+        &self.addrs
+    }
+
+    /// Returns the delay between the start of two consecutive connection attempts.
+    #[inline]
+    pub fn stagger(&self) -> Duration {
+        self.stagger
+    }
+}
+
+/// Future that races the connection attempts to multiple addresses.
+struct HappyEyeballs<Du>
+where
+    Du: Transport,
+{
+    /// Transport used to dial.
+    transport: Du,
+    /// Addresses that haven't been dialed yet.
+    remaining: ::std::vec::IntoIter<Multiaddr>,
+    /// Ongoing connection attempts.
+    attempts: Vec<(Multiaddr, Du::Dial)>,
+    /// Delay between two attempts.
+    stagger: Duration,
+    /// Fires when the next attempt must be started. `None` if no attempt has been started yet.
+    next_attempt: Option<Delay>,
+    /// Error produced by the last attempt that failed.
+    last_error: Option<IoError>,
+}
+
+impl<Du> HappyEyeballs<Du>
+where
+    Du: Transport + Clone,
+{
+    // Starts dialing the next address. Returns false if there is no address left.
+    fn start_next_attempt(&mut self) -> bool {
+        while let Some(addr) = self.remaining.next() {
+            self.next_attempt = Some(Delay::new(Instant::now() + self.stagger));
+            match self.transport.clone().dial(addr.clone()) {
+                Ok(dial) => {
+                    trace!("Starting connection attempt to {}", addr);
+                    self.attempts.push((addr, dial));
+                    return true;
+                }
+                Err((_, addr)) => {
+                    debug!("Address not supported by the transport: {}", addr);
+                    self.last_error = Some(IoError::new(IoErrorKind::Other,
+                        "multiaddr not supported"));
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl<Du> Future for HappyEyeballs<Du>
+where
+    Du: Transport + Clone,
+{
+    type Item = (Du::Output, Multiaddr);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            // Start a new attempt if the delay has elapsed, or if nothing is in progress.
+            let start_next = if self.attempts.is_empty() {
+                true
+            } else {
+                match self.next_attempt {
+                    None => true,
+                    Some(ref mut delay) => match delay.poll() {
+                        Ok(Async::Ready(())) => true,
+                        Ok(Async::NotReady) => false,
+                        Err(err) => {
+                            debug!("Timer error while dialing, starting next attempt: {:?}", err);
+                            true
+                        }
+                    },
+                }
+            };
+
+            let started = start_next && self.start_next_attempt();
+
+            for n in (0 .. self.attempts.len()).rev() {
+                match self.attempts[n].1.poll() {
+                    Ok(Async::Ready(output)) => {
+                        let (addr, _) = self.attempts.swap_remove(n);
+                        debug!("Successfully dialed {}, cancelling {} other attempt(s)", addr,
+                            self.attempts.len());
+                        return Ok(Async::Ready((output, addr)));
+                    }
+                    Ok(Async::NotReady) => (),
+                    Err(err) => {
+                        let (addr, _) = self.attempts.swap_remove(n);
+                        debug!("Connection attempt to {} failed: {:?}", addr, err);
+                        self.last_error = Some(err);
+                    }
+                }
+            }
+
+            if self.attempts.is_empty() && self.remaining.len() == 0 {
+                return Err(self.last_error.take().unwrap_or_else(|| {
+                    IoError::new(IoErrorKind::Other, "no address to dial")
+                }));
+            }
+
+            // If we started a new attempt or if all the attempts have failed, loop again so that
+            // the new attempt and the new delay get polled.
+            if !started && !self.attempts.is_empty() {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
 /// Future that must be driven to completion in order for the swarm to work.
 #[must_use = "futures do nothing unless polled"]
 pub struct SwarmEvents<T, F, H>
@@ -326,9 +534,9 @@ where
         // We remove each element from `shared.dialers` one by one and add them back only
         // if relevant.
         for n in (0 .. shared.dialers.len()).rev() {
-            let (client_addr, mut dialer) = shared.dialers.swap_remove(n);
+            let (mut client_addrs, mut dialer) = shared.dialers.swap_remove(n);
             match dialer.poll() {
-                Ok(Async::Ready((output, mut notifier))) => {
+                Ok(Async::Ready((output, client_addr, mut notifier))) => {
                     trace!("Successfully upgraded dialed connection");
                     // TODO: unlock mutex before calling handler, in order to avoid deadlocks if
                     // the user does something stupid
@@ -337,12 +545,12 @@ where
                 }
                 Err(error) => {
                     return Ok(Async::Ready(Some(SwarmEvent::DialFailed {
-                        client_addr,
+                        client_addr: client_addrs.swap_remove(0),
                         error,
                     })));
                 },
                 Ok(Async::NotReady) => {
-                    shared.dialers.push((client_addr, dialer));
+                    shared.dialers.push((client_addrs, dialer));
                 },
             }
         }
@@ -401,10 +609,19 @@ struct Shared<T, F> where T: MuxedTransport + 'static {
     listeners_upgrade:
         Vec<(Box<Future<Item = T::Output, Error = IoError> + Send>, Multiaddr)>,
 
-    /// Futures that dial a remote address.
+    /// Futures that dial a remote address. They produce the connection and the address that was
+    /// successfully dialed.
     ///
-    /// Contains the address we dial, so that we can cancel it if necessary.
-    dialers: Vec<(Multiaddr, Box<Future<Item = (T::Output, Box<FnMut(Result<(), IoError>) + Send>), Error = IoError> + Send>)>,
+    /// Contains the addresses we dial, so that we can cancel them if necessary.
+    dialers: Vec<(
+        Vec<Multiaddr>,
+        Box<
+            Future<
+                Item = (T::Output, Multiaddr, Box<FnMut(Result<(), IoError>) + Send>),
+                Error = IoError,
+            > + Send,
+        >,
+    )>,
 
     /// List of futures produced by the swarm closure. Must be processed to the end.
     to_process: Vec<F>,
@@ -439,7 +656,8 @@ pub enum SwarmEvent<F> {
 
     /// Failed to dial a remote address.
     DialFailed {
-        /// Address we were trying to dial.
+        /// Address we were trying to dial. For the dials started with `dial_with_opts`, this is
+        /// the first address of the list.
         client_addr: Multiaddr,
         /// Error that happened.
         error: IoError,
@@ -466,9 +684,11 @@ mod tests {
     use rand;
     use transport::{self, DeniedTransport, Transport};
     use std::io::Error as IoError;
-    use std::sync::{atomic, Arc};
-    use swarm;
+    use std::sync::{atomic, Arc, Mutex};
+    use std::time::Duration;
+    use swarm::{swarm, DialOpts};
     use tokio::runtime::current_thread;
+    use Multiaddr;
 
     #[test]
     fn transport_error_propagation_listen() {
@@ -552,4 +772,63 @@ mod tests {
             .map_err(|(err, _)| err);
         current_thread::Runtime::new().unwrap().block_on(future).unwrap();
     }
+
+    #[test]
+    fn dial_with_opts_empty() {
+        let (swarm_ctrl, _swarm_future) = swarm(DeniedTransport, |_, _| future::empty());
+        assert!(swarm_ctrl.dial_with_opts(DialOpts::new(Vec::new()), DeniedTransport).is_err());
+    }
+
+    #[test]
+    fn dial_with_opts_falls_back() {
+        // Transport whose dials hang forever, except for `/memory`.
+        #[derive(Clone)]
+        struct HangExceptMemory(transport::memory::Dialer);
+        impl Transport for HangExceptMemory {
+            type Output = <transport::memory::Dialer as Transport>::Output;
+            type Listener = <transport::memory::Dialer as Transport>::Listener;
+            type ListenerUpgrade = <transport::memory::Dialer as Transport>::ListenerUpgrade;
+            type Dial = <transport::memory::Dialer as Transport>::Dial;
+
+            fn listen_on(self, addr: Multiaddr)
+                -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)>
+            {
+                Err((self, addr))
+            }
+
+            fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+                if addr == "/memory".parse::<Multiaddr>().unwrap() {
+                    self.0.dial(addr).map_err(|(dialer, addr)| (HangExceptMemory(dialer), addr))
+                } else {
+                    Ok(Box::new(future::empty()))
+                }
+            }
+        }
+
+        let (tx, rx) = transport::connector();
+
+        let (swarm_ctrl1, swarm_future1) = swarm(rx.with_dummy_muxing(), |_, _| future::empty());
+        swarm_ctrl1.listen_on("/memory".parse().unwrap()).unwrap();
+
+        let dialed_addr = Arc::new(Mutex::new(None));
+        let dialed_addr2 = dialed_addr.clone();
+        let (swarm_ctrl2, swarm_future2) = swarm(tx.clone().with_dummy_muxing(), move |_, addr| {
+            *dialed_addr2.lock().unwrap() = Some(addr);
+            future::empty()
+        });
+
+        let opts = DialOpts::new(vec![
+            "/ip4/127.0.0.1/tcp/10000".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/10001".parse().unwrap(),
+            "/memory".parse().unwrap(),
+        ]).with_stagger(Duration::from_millis(10));
+        let dial_success = swarm_ctrl2.dial_with_opts(opts, HangExceptMemory(tx)).unwrap();
+        let future = swarm_future2.for_each(|_| Ok(()))
+            .select(swarm_future1.for_each(|_| Ok(()))).map(|_| ()).map_err(|(err, _)| err)
+            .select(dial_success).map(|_| ()).map_err(|(err, _)| err);
+
+        current_thread::Runtime::new().unwrap().block_on(future).unwrap();
+        let dialed_addr = dialed_addr.lock().unwrap().take();
+        assert_eq!(dialed_addr, Some("/memory".parse().unwrap()));
+    }
 }
@@ -108,11 +108,6 @@ where
 
         Ok(Box::new(future))
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T, C, F, O> MuxedTransport for AndThen<T, C>
@@ -63,7 +63,6 @@ pub type IncomingUpgrade<O> = Box<Future<Item = O, Error = IoError> + Send>;
 trait Abstract<O> {
     fn listen_on(&self, addr: Multiaddr) -> Result<(Listener<O>, Multiaddr), Multiaddr>;
     fn dial(&self, addr: Multiaddr) -> Result<Dial<O>, Multiaddr>;
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr>;
 }
 
 impl<T, O> Abstract<O> for T
@@ -87,11 +86,6 @@ where
             .map_err(|(_, addr)| addr)?;
         Ok(Box::new(fut) as Box<_>)
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        Transport::nat_traversal(self, server, observed)
-    }
 }
 
 trait AbstractMuxed<O>: Abstract<O> {
@@ -156,11 +150,6 @@ impl<O> Transport for Boxed<O> {
             Err(addr) => Err((self, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.inner.nat_traversal(server, observed)
-    }
 }
 
 /// See the `Transport::boxed_muxed` method.
@@ -204,11 +193,6 @@ impl<O> Transport for BoxedMuxed<O> {
             Err(addr) => Err((self, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.inner.nat_traversal(server, observed)
-    }
 }
 
 impl<O> MuxedTransport for BoxedMuxed<O> {
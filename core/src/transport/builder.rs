@@ -0,0 +1,548 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Builder for a transport that authenticates then multiplexes its connections.
+//!
+//! Calling `Transport::upgrade()` returns a `Builder`. The security layer must then be passed to
+//! `authenticate`, and only after that can the multiplexing layer be passed to `multiplex`. The
+//! resulting transport produces `(PeerId, Connection)` tuples, which can for example be passed
+//! to `into_connection_reuse()` or to a swarm.
+//!
+//! ```ignore
+//! let transport = TcpConfig::new()
+//!     .upgrade()
+//!     .authenticate(SecioConfig::new(keypair))
+//!     .multiplex(mplex::MplexConfig::new())
+//!     .into_connection_reuse();
+//! ```
+//!
+//! Contrary to chaining `with_upgrade` calls, it is not possible to forget the security layer
+//! or to apply it after the multiplexing.
+//!
+//! The `Connection` implements `StreamMuxer` and gives access to a `ConnectionInfo` describing
+//! the connection, such as the protocols that have been negotiated. Other protocols can later be
+//! negotiated on top of an existing `Connection` with `Connection::re_upgrade`.
+//!
+//! The `ConnectionInfo` also records when each step of the handshake has finished, and the
+//! `Connection` holds a `SharedRtt` that other protocols, such as ping, can feed with round-trip
+//! time measurements. The measurements made by the multiplexer itself, such as the keep-alive of
+//! mplex, are reported to it automatically.
+
+use bytes::Bytes;
+use futures::{future, prelude::*};
+use multiaddr::Multiaddr;
+use muxing::{self, StreamMuxer, SubstreamRef};
+use rtt::SharedRtt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{Transport, UpgradedNode};
+use upgrade::{self, ConnectionUpgrade, Endpoint};
+use wasm_timer::Instant;
+use PeerId;
+
+/// Implemented on the output of security upgrades, such as `secio`. Gives access to the identity
+/// of the remote, which has been authenticated during the upgrade.
+pub trait AuthenticatedOutput {
+    /// Stream on which the data is sent and received after the upgrade.
+    type Stream: AsyncRead + AsyncWrite;
+
+    /// Splits the output between the identity of the remote and the stream.
+    fn into_authenticated(self) -> (PeerId, Self::Stream);
+}
+
+/// See the `Transport::upgrade` method.
+#[derive(Debug, Clone)]
+pub struct Builder<T> {
+    transport: T,
+}
+
+impl<T> Builder<T>
+where
+    T: Transport,
+    T::Output: AsyncRead + AsyncWrite,
+{
+    /// Creates a new builder around the given transport.
+    #[inline]
+    pub fn new(transport: T) -> Builder<T> {
+        Builder { transport }
+    }
+
+    /// Sets the security upgrade to apply first on all the connections.
+    #[inline]
+    pub fn authenticate<S>(self, security: S) -> Authenticated<T, S>
+    where
+        S: ConnectionUpgrade<T::Output>,
+        S::Output: AuthenticatedOutput,
+    {
+        Authenticated {
+            transport: self.transport,
+            security,
+        }
+    }
+}
+
+/// Builder whose security upgrade has been set. See `Builder::authenticate`.
+#[derive(Debug, Clone)]
+pub struct Authenticated<T, S> {
+    transport: T,
+    security: S,
+}
+
+impl<T, S> Authenticated<T, S>
+where
+    T: Transport,
+    T::Output: AsyncRead + AsyncWrite,
+    S: ConnectionUpgrade<T::Output>,
+    S::Output: AuthenticatedOutput,
+{
+    /// Sets the multiplexing upgrade to apply on the connections once they have been
+    /// authenticated, and returns the final transport.
+    #[inline]
+    pub fn multiplex<M>(self, muxer: M) -> Multiplexed<T, AuthenticateThenMultiplex<S, M>>
+    where
+        M: ConnectionUpgrade<<S::Output as AuthenticatedOutput>::Stream>,
+        M::Output: StreamMuxer,
+    {
+        let upgrade = AuthenticateThenMultiplex {
+            security: self.security,
+            muxer,
+        };
+
+        Multiplexed {
+            inner: UpgradedNode::new(self.transport, upgrade),
+        }
+    }
+}
+
+/// Information about a connection opened by a transport built with `Transport::upgrade`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Identity of the remote, as authenticated by the security layer.
+    pub peer_id: PeerId,
+    /// Name of the negotiated security protocol, for example `/secio/1.0.0`.
+    pub security_protocol: Bytes,
+    /// Name of the negotiated multiplexing protocol, for example `/mplex/6.7.0`.
+    pub muxer_protocol: Bytes,
+    /// Whether we dialed or received the connection.
+    pub endpoint: Endpoint,
+    /// Address we were listening on when the connection has been received.
+    ///
+    /// Always `None` if we dialed, as transports don't report the local address of the
+    /// connections they open.
+    pub local_addr: Option<Multiaddr>,
+    /// Address of the remote.
+    pub remote_addr: Multiaddr,
+    /// Moments when the steps of the handshake have finished.
+    pub timings: ConnectionTimings,
+}
+
+/// Moments when the steps of the handshake of a connection have finished.
+///
+/// The handshake starts once the security protocol has been negotiated on the raw socket.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectionTimings {
+    /// When the security handshake has started.
+    pub handshake_start: Instant,
+    /// When the remote has been authenticated.
+    pub security_established: Instant,
+    /// When the multiplexing protocol has been negotiated. The connection is usable from this
+    /// point on.
+    pub established: Instant,
+}
+
+impl ConnectionTimings {
+    /// Returns the time it took to authenticate the remote.
+    #[inline]
+    pub fn security_duration(&self) -> Duration {
+        self.security_established - self.handshake_start
+    }
+
+    /// Returns the time it took to negotiate the multiplexing protocol, once authenticated.
+    #[inline]
+    pub fn muxer_duration(&self) -> Duration {
+        self.established - self.security_established
+    }
+
+    /// Returns the total duration of the handshake.
+    #[inline]
+    pub fn handshake_duration(&self) -> Duration {
+        self.established - self.handshake_start
+    }
+}
+
+/// Multiplexed connection produced by a transport built with `Transport::upgrade`.
+///
+/// Implements `StreamMuxer` by delegating to the negotiated multiplexer.
+#[derive(Debug)]
+pub struct Connection<M> {
+    muxer: M,
+    info: ConnectionInfo,
+    rtt: SharedRtt,
+}
+
+impl<M> Connection<M> {
+    /// Returns the information about this connection.
+    #[inline]
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Returns the estimator of the round-trip time of this connection.
+    ///
+    /// The connection reports the measurements of the multiplexer, if it makes any (see
+    /// `StreamMuxer::take_round_trip_time`). The returned handle can also be cloned and passed to
+    /// the protocols that measure round-trips, such as ping, so that they report their samples
+    /// with `SharedRtt::add_sample`.
+    #[inline]
+    pub fn rtt(&self) -> &SharedRtt {
+        &self.rtt
+    }
+
+    /// Reports a round-trip time measurement for this connection.
+    #[inline]
+    pub fn record_rtt(&self, rtt: Duration) {
+        self.rtt.add_sample(rtt)
+    }
+
+    /// Destroys the `Connection` and returns the multiplexer.
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.muxer
+    }
+}
+
+impl<TMuxer> Connection<TMuxer>
+where
+    TMuxer: StreamMuxer + Send + Sync + 'static,
+    TMuxer::Substream: Send,
+    TMuxer::OutboundSubstream: Send,
+{
+    /// Negotiates a new security and multiplexing protocol on a substream of this connection.
+    ///
+    /// This makes it possible to migrate a connection to other protocols without closing it, for
+    /// example after both sides have exchanged their capabilities. If `endpoint` is `Dialer`, a
+    /// new substream is opened. If `endpoint` is `Listener`, the next inbound substream is used.
+    /// The remote must therefore call this method as well, with the opposite `endpoint`.
+    ///
+    /// The produced `Connection` keeps the addresses of this one. The future produces an error if
+    /// the remote authenticates with a different `PeerId` than the one of this connection.
+    ///
+    /// > **Note**: The new connection is tunneled through a substream of this one, which is kept
+    /// >           alive for as long as the new connection is alive.
+    pub fn re_upgrade<S, M, TOut>(
+        connection: Arc<Connection<TMuxer>>,
+        endpoint: Endpoint,
+        security: S,
+        muxer: M,
+    ) -> Box<Future<Item = (PeerId, Connection<TOut>), Error = IoError> + Send>
+    where
+        AuthenticateThenMultiplex<S, M>: ConnectionUpgrade<
+            SubstreamRef<Arc<Connection<TMuxer>>>,
+            Output = (PeerId, Connection<TOut>),
+        > + Send + 'static,
+        <AuthenticateThenMultiplex<S, M> as ConnectionUpgrade<SubstreamRef<Arc<Connection<TMuxer>>>>>::NamesIter: Clone + Send,
+        <AuthenticateThenMultiplex<S, M> as ConnectionUpgrade<SubstreamRef<Arc<Connection<TMuxer>>>>>::UpgradeIdentifier: Send,
+        <AuthenticateThenMultiplex<S, M> as ConnectionUpgrade<SubstreamRef<Arc<Connection<TMuxer>>>>>::Future: Send,
+        TOut: Send + 'static,
+    {
+        let previous_info = connection.info.clone();
+        // The new connection goes through the same network path, so keep the estimate.
+        let rtt = connection.rtt.clone();
+        let remote_addr = previous_info.remote_addr.clone();
+        let upgrade = AuthenticateThenMultiplex { security, muxer };
+
+        let substream = match endpoint {
+            Endpoint::Dialer => future::Either::A(muxing::outbound_from_ref_and_wrap(connection)),
+            Endpoint::Listener => future::Either::B(muxing::inbound_from_ref_and_wrap(connection)),
+        };
+
+        let future = substream
+            .and_then(|substream| {
+                substream.ok_or_else(|| {
+                    IoError::new(IoErrorKind::BrokenPipe, "connection closed before re-upgrade")
+                })
+            })
+            .and_then(move |substream| upgrade::apply(substream, upgrade, endpoint, &remote_addr))
+            .and_then(move |(peer_id, mut new_connection)| {
+                if peer_id != previous_info.peer_id {
+                    debug!("Remote authenticated as {:?} instead of {:?} during re-upgrade",
+                           peer_id, previous_info.peer_id);
+                    return Err(IoError::new(IoErrorKind::PermissionDenied,
+                                            "remote changed its identity during re-upgrade"));
+                }
+                new_connection.info.local_addr = previous_info.local_addr;
+                new_connection.rtt = rtt;
+                Ok((peer_id, new_connection))
+            });
+
+        Box::new(future)
+    }
+}
+
+impl<M> StreamMuxer for Connection<M>
+where
+    M: StreamMuxer,
+{
+    type Substream = M::Substream;
+    type OutboundSubstream = M::OutboundSubstream;
+
+    #[inline]
+    fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
+        let result = self.muxer.poll_inbound();
+        // The muxer measures round-trips while it's being polled.
+        self.take_round_trip_time();
+        result
+    }
+
+    #[inline]
+    fn open_outbound(&self) -> Self::OutboundSubstream {
+        self.muxer.open_outbound()
+    }
+
+    #[inline]
+    fn poll_outbound(
+        &self,
+        substream: &mut Self::OutboundSubstream,
+    ) -> Poll<Option<Self::Substream>, IoError> {
+        self.muxer.poll_outbound(substream)
+    }
+
+    #[inline]
+    fn destroy_outbound(&self, substream: Self::OutboundSubstream) {
+        self.muxer.destroy_outbound(substream)
+    }
+
+    #[inline]
+    fn read_substream(
+        &self,
+        substream: &mut Self::Substream,
+        buf: &mut [u8],
+    ) -> Result<usize, IoError> {
+        self.muxer.read_substream(substream, buf)
+    }
+
+    #[inline]
+    fn write_substream(
+        &self,
+        substream: &mut Self::Substream,
+        buf: &[u8],
+    ) -> Result<usize, IoError> {
+        self.muxer.write_substream(substream, buf)
+    }
+
+    #[inline]
+    fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
+        self.muxer.flush_substream(substream)
+    }
+
+    #[inline]
+    fn shutdown_substream(&self, substream: &mut Self::Substream) -> Poll<(), IoError> {
+        self.muxer.shutdown_substream(substream)
+    }
+
+    #[inline]
+    fn destroy_substream(&self, substream: Self::Substream) {
+        self.muxer.destroy_substream(substream)
+    }
+
+    #[inline]
+    fn close_inbound(&self) {
+        self.muxer.close_inbound()
+    }
+
+    #[inline]
+    fn close_outbound(&self) {
+        self.muxer.close_outbound()
+    }
+
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        let rtt = self.muxer.take_round_trip_time();
+        if let Some(rtt) = rtt {
+            self.rtt.add_sample(rtt);
+        }
+        rtt
+    }
+}
+
+/// Transport produced by `Authenticated::multiplex`.
+#[derive(Debug, Clone)]
+pub struct Multiplexed<T, U> {
+    inner: UpgradedNode<T, U>,
+}
+
+impl<T, U, M> Transport for Multiplexed<T, U>
+where
+    UpgradedNode<T, U>: Transport<Output = (PeerId, Connection<M>)>,
+    <UpgradedNode<T, U> as Transport>::Listener: Send + 'static,
+    <UpgradedNode<T, U> as Transport>::ListenerUpgrade: Send + 'static,
+    M: 'static,
+{
+    type Output = (PeerId, Connection<M>);
+    type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+    type ListenerUpgrade = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+    type Dial = <UpgradedNode<T, U> as Transport>::Dial;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let (listener, listen_addr) = match Transport::listen_on(self.inner, addr) {
+            Ok(val) => val,
+            Err((inner, addr)) => return Err((Multiplexed { inner }, addr)),
+        };
+
+        let local_addr = listen_addr.clone();
+        let listener = listener.map(move |(upgrade, remote_addr)| {
+            let local_addr = local_addr.clone();
+            let upgrade = upgrade.map(move |(peer_id, mut connection)| {
+                connection.info.local_addr = Some(local_addr);
+                (peer_id, connection)
+            });
+            (Box::new(upgrade) as Box<_>, remote_addr)
+        });
+
+        Ok((Box::new(listener), listen_addr))
+    }
+
+    #[inline]
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        Transport::dial(self.inner, addr)
+            .map_err(|(inner, addr)| (Multiplexed { inner }, addr))
+    }
+}
+
+/// Upgrade that applies a security upgrade, then a multiplexing upgrade on the authenticated
+/// stream. Produces the `PeerId` of the remote and the multiplexer.
+///
+/// See `Authenticated::multiplex`.
+#[derive(Debug, Clone)]
+pub struct AuthenticateThenMultiplex<S, M> {
+    security: S,
+    muxer: M,
+}
+
+impl<C, S, M> ConnectionUpgrade<C> for AuthenticateThenMultiplex<S, M>
+where
+    C: AsyncRead + AsyncWrite,
+    S: ConnectionUpgrade<C>,
+    S::Output: AuthenticatedOutput,
+    S::Future: Send + 'static,
+    <S::Output as AuthenticatedOutput>::Stream: Send + 'static,
+    M: ConnectionUpgrade<<S::Output as AuthenticatedOutput>::Stream> + Send + 'static,
+    M::NamesIter: Clone + Send + 'static,
+    M::UpgradeIdentifier: Send + 'static,
+    M::Future: Send + 'static,
+    M::Output: StreamMuxer + Send + 'static,
+{
+    type NamesIter = WithNameIter<S::NamesIter, S::UpgradeIdentifier>;
+    type UpgradeIdentifier = (Bytes, S::UpgradeIdentifier);
+    type Output = (PeerId, Connection<M::Output>);
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        self.security.protocol_names().map(with_name as fn(_) -> _)
+    }
+
+    fn upgrade(
+        self,
+        socket: C,
+        (security_protocol, id): Self::UpgradeIdentifier,
+        endpoint: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        let muxer = WithName(self.muxer);
+        let remote_addr = remote_addr.clone();
+
+        let handshake_start = Instant::now();
+
+        let future = self.security
+            .upgrade(socket, id, endpoint, &remote_addr)
+            .and_then(move |output| {
+                let security_established = Instant::now();
+                let (peer_id, stream) = output.into_authenticated();
+                upgrade::apply(stream, muxer, endpoint, &remote_addr)
+                    .map(move |(muxer_protocol, muxer)| {
+                        let timings = ConnectionTimings {
+                            handshake_start,
+                            security_established,
+                            established: Instant::now(),
+                        };
+                        trace!("Connection to {:?} established in {:?}", peer_id,
+                               timings.handshake_duration());
+                        let info = ConnectionInfo {
+                            peer_id: peer_id.clone(),
+                            security_protocol,
+                            muxer_protocol,
+                            endpoint,
+                            local_addr: None,
+                            remote_addr,
+                            timings,
+                        };
+                        (peer_id, Connection { muxer, info, rtt: SharedRtt::new() })
+                    })
+            });
+
+        Box::new(future)
+    }
+}
+
+/// Iterator of protocol names whose identifiers also contain the name of the protocol.
+pub type WithNameIter<I, Id> = iter::Map<I, fn((Bytes, Id)) -> (Bytes, (Bytes, Id))>;
+
+/// Adds the name of the protocol to its identifier, so that the name is known when upgrading.
+#[inline]
+fn with_name<Id>((name, id): (Bytes, Id)) -> (Bytes, (Bytes, Id)) {
+    (name.clone(), (name, id))
+}
+
+/// Wraps around an upgrade and adds the name of the negotiated protocol to its output.
+#[derive(Debug, Clone)]
+struct WithName<U>(U);
+
+impl<C, U> ConnectionUpgrade<C> for WithName<U>
+where
+    U: ConnectionUpgrade<C>,
+    U::Future: Send + 'static,
+{
+    type NamesIter = WithNameIter<U::NamesIter, U::UpgradeIdentifier>;
+    type UpgradeIdentifier = (Bytes, U::UpgradeIdentifier);
+    type Output = (Bytes, U::Output);
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        self.0.protocol_names().map(with_name as fn(_) -> _)
+    }
+
+    #[inline]
+    fn upgrade(
+        self,
+        socket: C,
+        (name, id): Self::UpgradeIdentifier,
+        endpoint: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        let future = self.0
+            .upgrade(socket, id, endpoint, remote_addr)
+            .map(move |output| (name, output));
+        Box::new(future)
+    }
+}
@@ -19,11 +19,24 @@
 // DEALINGS IN THE SOFTWARE.
 
 use either::{EitherListenStream, EitherOutput, EitherFuture};
+use either_n::IntoOrTransport;
 use futures::prelude::*;
 use multiaddr::Multiaddr;
 use std::io::Error as IoError;
 use transport::{MuxedTransport, Transport};
 
+/// Builds a new `Transport` that tries all the transports of a tuple one by one.
+///
+/// The transports that come first in the tuple have priority. Contrary to nesting calls to
+/// `or_transport`, the output has one variant per transport. See the `either_n` module.
+#[inline]
+pub fn or_all<T>(transports: T) -> T::Transport
+where
+    T: IntoOrTransport,
+{
+    transports.into_or_transport()
+}
+
 /// Struct returned by `or_transport()`.
 #[derive(Debug, Copy, Clone)]
 pub struct OrTransport<A, B>(A, B);
@@ -67,16 +80,6 @@ where
             Err((second, addr)) => Err((OrTransport(first, second), addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        let first = self.0.nat_traversal(server, observed);
-        if let Some(first) = first {
-            return Some(first);
-        }
-
-        self.1.nat_traversal(server, observed)
-    }
 }
 
 impl<A, B> MuxedTransport for OrTransport<A, B>
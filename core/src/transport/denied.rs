@@ -45,11 +45,6 @@ impl Transport for DeniedTransport {
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
         Err((DeniedTransport, addr))
     }
-
-    #[inline]
-    fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
-        None
-    }
 }
 
 impl MuxedTransport for DeniedTransport {
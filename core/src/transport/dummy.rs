@@ -78,9 +78,4 @@ where
             .dial(addr)
             .map_err(|(inner, addr)| (DummyMuxing { inner }, addr))
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.inner.nat_traversal(server, observed)
-    }
 }
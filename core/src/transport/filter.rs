@@ -0,0 +1,230 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::prelude::*;
+use multiaddr::Multiaddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use transport::{MuxedTransport, Transport};
+use Endpoint;
+
+/// See `Transport::filter`.
+pub struct Filter<T, F> {
+    transport: T,
+    filter: Arc<F>,
+}
+
+impl<T, F> Filter<T, F> {
+    /// Internal function that builds a `Filter`.
+    #[inline]
+    pub(crate) fn new(transport: T, filter: F) -> Filter<T, F> {
+        Filter { transport, filter: Arc::new(filter) }
+    }
+}
+
+impl<T, F> Clone for Filter<T, F>
+where
+    T: Clone,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Filter {
+            transport: self.transport.clone(),
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+impl<T, F> Transport for Filter<T, F>
+where
+    T: Transport,
+    F: Fn(&Multiaddr, Endpoint) -> bool,
+{
+    type Output = T::Output;
+    type Listener = FilterListener<T::Listener, F>;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = FilterDial<T::Dial>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let filter = self.filter;
+        match self.transport.listen_on(addr) {
+            Ok((inner, listen_addr)) => {
+                let listener = FilterListener { inner, filter };
+                Ok((listener, listen_addr))
+            },
+            Err((transport, addr)) => Err((Filter { transport, filter }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        if !(self.filter)(&addr, Endpoint::Dialer) {
+            debug!("Refusing to dial filtered address {}", addr);
+            return Ok(FilterDial::Denied(Some(addr)));
+        }
+
+        let filter = self.filter;
+        match self.transport.dial(addr) {
+            Ok(future) => Ok(FilterDial::Allowed(future)),
+            Err((transport, addr)) => Err((Filter { transport, filter }, addr)),
+        }
+    }
+}
+
+impl<T, F> MuxedTransport for Filter<T, F>
+where
+    T: MuxedTransport,
+    F: Fn(&Multiaddr, Endpoint) -> bool,
+{
+    type Incoming = FilterIncoming<T::Incoming, F>;
+    type IncomingUpgrade = FilterDial<T::IncomingUpgrade>;
+
+    #[inline]
+    fn next_incoming(self) -> Self::Incoming {
+        FilterIncoming {
+            inner: self.transport.next_incoming(),
+            filter: self.filter,
+        }
+    }
+}
+
+/// Listener for `Filter`. Drops the incoming connections whose address is rejected by the
+/// filter.
+pub struct FilterListener<L, F> {
+    inner: L,
+    filter: Arc<F>,
+}
+
+impl<L, U, F> Stream for FilterListener<L, F>
+where
+    L: Stream<Item = (U, Multiaddr), Error = IoError>,
+    F: Fn(&Multiaddr, Endpoint) -> bool,
+{
+    type Item = (U, Multiaddr);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match try_ready!(self.inner.poll()) {
+                Some((upgrade, addr)) => {
+                    if (self.filter)(&addr, Endpoint::Listener) {
+                        return Ok(Async::Ready(Some((upgrade, addr))));
+                    }
+                    debug!("Dropping incoming connection from filtered address {}", addr);
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+/// Dialing future for `Filter`. Also used for the incoming substreams of a muxed `Filter`.
+#[must_use = "futures do nothing unless polled"]
+pub enum FilterDial<F> {
+    /// The address has been accepted by the filter.
+    Allowed(F),
+    /// The address has been rejected by the filter. Produces an error when polled.
+    Denied(Option<Multiaddr>),
+}
+
+impl<F> Future for FilterDial<F>
+where
+    F: Future<Error = IoError>,
+{
+    type Item = F::Item;
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            FilterDial::Allowed(ref mut inner) => inner.poll(),
+            FilterDial::Denied(ref mut addr) => {
+                let addr = addr.take().expect("poll() called again after error");
+                let msg = format!("address {} rejected by the transport filter", addr);
+                Err(IoError::new(IoErrorKind::PermissionDenied, msg))
+            },
+        }
+    }
+}
+
+/// Incoming future for `Filter`.
+///
+/// The incoming substreams of a muxed transport can't be dropped without waiting for the next
+/// one, so the ones whose address is rejected produce an error instead.
+#[must_use = "futures do nothing unless polled"]
+pub struct FilterIncoming<I, F> {
+    inner: I,
+    filter: Arc<F>,
+}
+
+impl<I, U, F> Future for FilterIncoming<I, F>
+where
+    I: Future<Item = (U, Multiaddr), Error = IoError>,
+    F: Fn(&Multiaddr, Endpoint) -> bool,
+{
+    type Item = (FilterDial<U>, Multiaddr);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (upgrade, addr) = try_ready!(self.inner.poll());
+        let upgrade = if (self.filter)(&addr, Endpoint::Listener) {
+            FilterDial::Allowed(upgrade)
+        } else {
+            debug!("Rejecting incoming substream from filtered address {}", addr);
+            FilterDial::Denied(Some(addr.clone()))
+        };
+        Ok(Async::Ready((upgrade, addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use std::io::ErrorKind;
+    use transport::{self, Transport};
+    use Multiaddr;
+
+    #[test]
+    fn dial_rejected() {
+        let (tx, _rx) = transport::connector();
+        let denied: Multiaddr = "/memory".parse().unwrap();
+        let transport = tx.filter(move |addr, _| *addr != denied);
+
+        let err = transport.dial("/memory".parse().unwrap())
+            .unwrap_or_else(|_| panic!("the address is supported"))
+            .wait()
+            .err()
+            .expect("dialing a rejected address must fail");
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn incoming_rejected() {
+        let (tx, rx) = transport::connector();
+        let rx = rx.filter(|_, _| false);
+        let (listener, _) = rx.listen_on("/memory".parse().unwrap())
+            .unwrap_or_else(|_| panic!("the address is supported"));
+
+        let _dial = tx.dial("/memory".parse().unwrap())
+            .unwrap_or_else(|_| panic!("the address is supported"))
+            .wait()
+            .unwrap();
+        assert!(listener.wait().next().is_none());
+    }
+}
@@ -70,11 +70,6 @@ where
             Err((transport, addr)) => Err((Interruptible { transport, rx: self.rx }, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T> MuxedTransport for Interruptible<T>
@@ -83,11 +83,6 @@ where
             Err((transport, addr)) => Err((Map { transport, map }, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T, F, D> MuxedTransport for Map<T, F>
@@ -68,11 +68,6 @@ where
             Err((transport, addr)) => Err((MapErr { transport, map }, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T, F> MuxedTransport for MapErr<T, F>
@@ -67,11 +67,6 @@ where
             Err((transport, addr)) => Err((MapErrDial { transport, map }, addr)),
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(server, observed)
-    }
 }
 
 impl<T, F> MuxedTransport for MapErrDial<T, F>
@@ -73,14 +73,6 @@ impl<T: IntoBuf + Send + 'static> Transport for Dialer<T> {
             .map_err(|_| io::ErrorKind::ConnectionRefused.into());
         Ok(Box::new(future))
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        if server == observed {
-            Some(server.clone())
-        } else {
-            None
-        }
-    }
 }
 
 /// Receiving end of the memory transport.
@@ -116,15 +108,6 @@ impl<T: IntoBuf + Send + 'static> Transport for Listener<T> {
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
         Err((self, addr))
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        if server == observed {
-            Some(server.clone())
-        } else {
-            None
-        }
-    }
 }
 
 /// Returns `true` if and only if the address is `/memory`.
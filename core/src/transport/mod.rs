@@ -28,6 +28,9 @@
 //! Thanks to the `Transport::or_transport`, `Transport::with_upgrade` and
 //! `UpgradedNode::or_upgrade` methods, you can combine multiple transports and/or upgrades
 //! together in a complex chain of protocols negotiation.
+//!
+//! The `Transport::upgrade` method is the recommended way to add a security and a multiplexing
+//! layer to a transport, as it guarantees that both are applied and in the right order.
 
 use connection_reuse::ConnectionReuse;
 use futures::prelude::*;
@@ -39,23 +42,30 @@ use upgrade::{ConnectionUpgrade, Endpoint};
 
 pub mod and_then;
 pub mod boxed;
+pub mod builder;
 pub mod choice;
 pub mod denied;
 pub mod dummy;
+pub mod filter;
 pub mod interruptible;
 pub mod map;
 pub mod map_err;
 pub mod map_err_dial;
 pub mod memory;
 pub mod muxed;
+pub mod resource_limit;
+#[cfg(feature = "std-future")]
+pub mod std_future;
 pub mod upgrade;
 
 pub use self::boxed::BoxedMuxed;
-pub use self::choice::OrTransport;
+pub use self::builder::{AuthenticatedOutput, Connection, ConnectionInfo, ConnectionTimings};
+pub use self::choice::{or_all, OrTransport};
 pub use self::denied::DeniedTransport;
 pub use self::dummy::DummyMuxing;
 pub use self::memory::connector;
 pub use self::muxed::MuxedTransport;
+pub use self::resource_limit::ResourceLimitedTransport;
 pub use self::upgrade::UpgradedNode;
 
 /// A transport is an object that can be used to produce connections by listening or dialing a
@@ -107,19 +117,12 @@ pub trait Transport {
     where
         Self: Sized;
 
-    /// Takes a multiaddress we're listening on (`server`), and tries to convert it to an
-    /// externally-visible multiaddress. In order to do so, we pass an `observed` address which
-    /// a remote node observes for one of our dialers.
-    ///
-    /// For example, if `server` is `/ip4/0.0.0.0/tcp/3000` and `observed` is
-    /// `/ip4/80.81.82.83/tcp/29601`, then we should return `/ip4/80.81.82.83/tcp/3000`. Each
-    /// implementation of `Transport` is only responsible for handling the protocols it supports.
-    ///
-    /// Returns `None` if nothing can be determined. This happens if this trait implementation
-    /// doesn't recognize the protocols, or if `server` and `observed` are related.
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr>;
-
     /// Turns this `Transport` into an abstract boxed transport.
+    ///
+    /// This makes it possible to store a transport in a struct without naming its type, which
+    /// can become very long after combining multiple transports and upgrades. If the output
+    /// contains a muxer, consider using `map` and `StreamMuxer::boxed` in order to erase the
+    /// type of the muxer as well.
     #[inline]
     fn boxed(self) -> boxed::Boxed<Self::Output>
     where Self: Sized + Clone + Send + Sync + 'static,
@@ -177,6 +180,21 @@ pub trait Transport {
         map_err_dial::MapErrDial::new(self, map_err)
     }
 
+    /// Only lets through the connections whose address is accepted by `filter`.
+    ///
+    /// The filter is called with the address to dial, or with the address of the remote for the
+    /// incoming connections. Dialing a rejected address produces a future that fails with an
+    /// error of kind `PermissionDenied`, and incoming connections from a rejected address are
+    /// dropped before being upgraded.
+    #[inline]
+    fn filter<F>(self, filter: F) -> filter::Filter<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Multiaddr, Endpoint) -> bool,
+    {
+        filter::Filter::new(self, filter)
+    }
+
     /// Builds a new struct that implements `Transport` that contains both `self` and `other`.
     ///
     /// The returned object will redirect its calls to `self`, except that if `listen_on` or `dial`
@@ -204,6 +222,18 @@ pub trait Transport {
         UpgradedNode::new(self, upgrade)
     }
 
+    /// Starts building a transport that authenticates then multiplexes all its connections.
+    ///
+    /// See the documentation of the `builder` module.
+    #[inline]
+    fn upgrade(self) -> builder::Builder<Self>
+    where
+        Self: Sized,
+        Self::Output: AsyncRead + AsyncWrite,
+    {
+        builder::Builder::new(self)
+    }
+
     /// Wraps this transport inside an upgrade. Whenever a connection that uses this transport
     /// is established, it is wrapped inside the upgrade.
     ///
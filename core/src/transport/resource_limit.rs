@@ -0,0 +1,286 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Transport wrapper that enforces the limits of a `ResourceManager`.
+//!
+//! See `ResourceLimitedTransport`.
+
+use futures::prelude::*;
+use muxing::StreamMuxer;
+use resource_manager::{ConnectionPermit, ResourceError, ResourceManager, SubstreamPermit};
+use std::io::Error as IoError;
+use std::time::Duration;
+use transport::Transport;
+use {Multiaddr, PeerId};
+
+/// Wraps around a `Transport` whose output is a peer id and a muxer, and accounts the connections
+/// and the substreams in a `ResourceManager`.
+///
+/// A connection permit is reserved before each dialing attempt and for each incoming connection.
+/// If the limit is reached, the dialing attempt fails or the incoming connection is dropped, with
+/// an error containing the `ResourceError`. Once the connection is established, the permit is
+/// assigned to the remote peer. Each substream of the muxer then needs a substream permit:
+/// inbound substreams that exceed the limits are immediately closed, and outbound substreams
+/// produce an error.
+#[derive(Debug, Clone)]
+pub struct ResourceLimitedTransport<T> {
+    inner: T,
+    manager: ResourceManager,
+}
+
+impl<T> ResourceLimitedTransport<T> {
+    /// Wraps around a transport.
+    #[inline]
+    pub fn new(inner: T, manager: ResourceManager) -> Self {
+        ResourceLimitedTransport { inner, manager }
+    }
+
+    /// Returns the resource manager.
+    #[inline]
+    pub fn manager(&self) -> &ResourceManager {
+        &self.manager
+    }
+}
+
+impl<T, TMuxer> Transport for ResourceLimitedTransport<T>
+where
+    T: Transport<Output = (PeerId, TMuxer)>,
+    TMuxer: StreamMuxer,
+{
+    type Output = (PeerId, ResourceLimitedMuxer<TMuxer>);
+    type Listener = ResourceLimitedListener<T::Listener>;
+    type ListenerUpgrade = ResourceLimitedUpgrade<T::ListenerUpgrade>;
+    type Dial = ResourceLimitedUpgrade<T::Dial>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let manager = self.manager;
+        match self.inner.listen_on(addr) {
+            Ok((inner, addr)) => Ok((ResourceLimitedListener { inner, manager }, addr)),
+            Err((inner, addr)) => Err((ResourceLimitedTransport { inner, manager }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let permit = match self.manager.reserve_connection() {
+            Ok(permit) => permit,
+            Err(err) => {
+                debug!("Denied dialing {}: {}", addr, err);
+                return Ok(ResourceLimitedUpgrade { inner: Err(Some(err)) });
+            },
+        };
+
+        let manager = self.manager;
+        match self.inner.dial(addr) {
+            Ok(inner) => Ok(ResourceLimitedUpgrade { inner: Ok((inner, Some(permit))) }),
+            Err((inner, addr)) => Err((ResourceLimitedTransport { inner, manager }, addr)),
+        }
+    }
+}
+
+/// Listener of a `ResourceLimitedTransport`.
+pub struct ResourceLimitedListener<TListener> {
+    inner: TListener,
+    manager: ResourceManager,
+}
+
+impl<TListener, TUpgrade> Stream for ResourceLimitedListener<TListener>
+where
+    TListener: Stream<Item = (TUpgrade, Multiaddr)>,
+{
+    type Item = (ResourceLimitedUpgrade<TUpgrade>, Multiaddr);
+    type Error = TListener::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let (upgrade, addr) = match try_ready!(self.inner.poll()) {
+            Some(incoming) => incoming,
+            None => return Ok(Async::Ready(None)),
+        };
+
+        let inner = match self.manager.reserve_connection() {
+            Ok(permit) => Ok((upgrade, Some(permit))),
+            Err(err) => {
+                debug!("Denied incoming connection from {}: {}", addr, err);
+                Err(Some(err))
+            },
+        };
+
+        Ok(Async::Ready(Some((ResourceLimitedUpgrade { inner }, addr))))
+    }
+}
+
+/// Future that establishes a connection of a `ResourceLimitedTransport`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ResourceLimitedUpgrade<TFut> {
+    /// The inner future and the permit of the connection, or the reason why the connection has
+    /// been denied.
+    inner: Result<(TFut, Option<ConnectionPermit>), Option<ResourceError>>,
+}
+
+impl<TFut, TMuxer> Future for ResourceLimitedUpgrade<TFut>
+where
+    TFut: Future<Item = (PeerId, TMuxer), Error = IoError>,
+{
+    type Item = (PeerId, ResourceLimitedMuxer<TMuxer>);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (future, permit) = match self.inner {
+            Ok((ref mut future, ref mut permit)) => (future, permit),
+            Err(ref mut err) => return Err(err.take().expect("future polled after completion").into()),
+        };
+
+        let (peer_id, muxer) = try_ready!(future.poll());
+        let mut permit = permit.take().expect("future polled after completion");
+        if let Err(err) = permit.set_peer(peer_id.clone()) {
+            debug!("Denied connection to {:?}: {}", peer_id, err);
+            return Err(err.into());
+        }
+
+        Ok(Async::Ready((peer_id, ResourceLimitedMuxer { inner: muxer, permit })))
+    }
+}
+
+/// Wraps around a `StreamMuxer` and reserves a substream permit for each of its substreams.
+pub struct ResourceLimitedMuxer<TMuxer> {
+    inner: TMuxer,
+    permit: ConnectionPermit,
+}
+
+/// Substream of a `ResourceLimitedMuxer`.
+pub struct ResourceLimitedSubstream<TSubstream> {
+    inner: TSubstream,
+    _permit: SubstreamPermit,
+}
+
+impl<TMuxer> StreamMuxer for ResourceLimitedMuxer<TMuxer>
+where
+    TMuxer: StreamMuxer,
+{
+    type Substream = ResourceLimitedSubstream<TMuxer::Substream>;
+    type OutboundSubstream = TMuxer::OutboundSubstream;
+
+    fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
+        loop {
+            let inner = match try_ready!(self.inner.poll_inbound()) {
+                Some(substream) => substream,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            match self.permit.reserve_substream() {
+                Ok(permit) => {
+                    return Ok(Async::Ready(Some(ResourceLimitedSubstream { inner, _permit: permit })));
+                },
+                Err(err) => {
+                    debug!("Closing inbound substream: {}", err);
+                    self.inner.destroy_substream(inner);
+                },
+            }
+        }
+    }
+
+    #[inline]
+    fn open_outbound(&self) -> Self::OutboundSubstream {
+        self.inner.open_outbound()
+    }
+
+    fn poll_outbound(
+        &self,
+        substream: &mut Self::OutboundSubstream,
+    ) -> Poll<Option<Self::Substream>, IoError> {
+        let inner = match try_ready!(self.inner.poll_outbound(substream)) {
+            Some(substream) => substream,
+            None => return Ok(Async::Ready(None)),
+        };
+
+        match self.permit.reserve_substream() {
+            Ok(permit) => Ok(Async::Ready(Some(ResourceLimitedSubstream { inner, _permit: permit }))),
+            Err(err) => {
+                self.inner.destroy_substream(inner);
+                Err(err.into())
+            },
+        }
+    }
+
+    #[inline]
+    fn destroy_outbound(&self, substream: Self::OutboundSubstream) {
+        self.inner.destroy_outbound(substream)
+    }
+
+    #[inline]
+    fn read_substream(&self, substream: &mut Self::Substream, buf: &mut [u8]) -> Result<usize, IoError> {
+        self.inner.read_substream(&mut substream.inner, buf)
+    }
+
+    #[inline]
+    fn write_substream(&self, substream: &mut Self::Substream, buf: &[u8]) -> Result<usize, IoError> {
+        self.inner.write_substream(&mut substream.inner, buf)
+    }
+
+    #[inline]
+    fn read_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, IoError> {
+        self.inner.read_substream_vectored(&mut substream.inner, bufs)
+    }
+
+    #[inline]
+    fn write_substream_vectored(
+        &self,
+        substream: &mut Self::Substream,
+        bufs: &[&[u8]],
+    ) -> Result<usize, IoError> {
+        self.inner.write_substream_vectored(&mut substream.inner, bufs)
+    }
+
+    #[inline]
+    fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
+        self.inner.flush_substream(&mut substream.inner)
+    }
+
+    #[inline]
+    fn shutdown_substream(&self, substream: &mut Self::Substream) -> Poll<(), IoError> {
+        self.inner.shutdown_substream(&mut substream.inner)
+    }
+
+    #[inline]
+    fn destroy_substream(&self, substream: Self::Substream) {
+        // The permit is released once the substream has been destroyed.
+        let ResourceLimitedSubstream { inner, _permit } = substream;
+        self.inner.destroy_substream(inner);
+    }
+
+    #[inline]
+    fn close_inbound(&self) {
+        self.inner.close_inbound()
+    }
+
+    #[inline]
+    fn close_outbound(&self) {
+        self.inner.close_outbound()
+    }
+
+    #[inline]
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        self.inner.take_round_trip_time()
+    }
+}
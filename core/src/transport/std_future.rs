@@ -0,0 +1,115 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Interoperability between transports and `std::future::Future`.
+//!
+//! This module is only available with the `std-future` feature.
+//!
+//! The `TransportStdExt` trait lets code written with `async`/`await` dial and listen with any
+//! `Transport`, and `from_std` lets a `Transport` implementation return a `std::future::Future`
+//! (for example an `async` block) as its `Dial` or `ListenerUpgrade`.
+//!
+//! These adapters are not a port to `std::future`. The `Transport` and `StreamMuxer` traits, the
+//! combinators of the `transport` module and every implementation in this repository are still
+//! defined in terms of futures 0.1, and porting them remains to be done.
+//!
+//! > **Note**: Porting the traits requires all the transports, muxers and protocols, and the
+//! >           tokio version they rely on, to be ported at the same time.
+
+use futures03::compat::{Compat, Compat01As03};
+use futures03::stream::{MapOk, TryStreamExt};
+use futures03::TryFuture;
+use multiaddr::Multiaddr;
+use std::pin::Pin;
+use transport::Transport;
+
+/// Extension trait for `Transport` that produces `std::future` types.
+pub trait TransportStdExt: Transport + Sized {
+    /// Same as `Transport::dial`, but returns a `std::future::Future`.
+    #[inline]
+    fn dial_std(self, addr: Multiaddr) -> Result<Compat01As03<Self::Dial>, (Self, Multiaddr)> {
+        self.dial(addr).map(Compat01As03::new)
+    }
+
+    /// Same as `Transport::listen_on`, but the listener is a futures 0.3 `Stream`, and the
+    /// upgrade of each incoming connection is a `std::future::Future`.
+    #[inline]
+    fn listen_on_std(self, addr: Multiaddr)
+        -> Result<(StdListener<Self>, Multiaddr), (Self, Multiaddr)>
+    {
+        self.listen_on(addr).map(|(listener, addr)| {
+            let listener = Compat01As03::new(listener)
+                .map_ok(upgrade_to_std as fn(_) -> _);
+            (listener, addr)
+        })
+    }
+}
+
+/// Listener returned by `TransportStdExt::listen_on_std`.
+pub type StdListener<T> = MapOk<
+    Compat01As03<<T as Transport>::Listener>,
+    fn((<T as Transport>::ListenerUpgrade, Multiaddr))
+        -> (Compat01As03<<T as Transport>::ListenerUpgrade>, Multiaddr),
+>;
+
+// Turns the upgrade of an incoming connection into a `std::future::Future`.
+fn upgrade_to_std<U>((upgrade, addr): (U, Multiaddr)) -> (Compat01As03<U>, Multiaddr) {
+    (Compat01As03::new(upgrade), addr)
+}
+
+impl<T> TransportStdExt for T where T: Transport {}
+
+/// Turns a `std::future::Future` that produces a `Result` into a futures 0.1 `Future`, so that
+/// it can be used as the `Dial` or the `ListenerUpgrade` of a `Transport`.
+///
+/// > **Note**: The returned future must be polled from within a futures 0.1 task.
+#[inline]
+pub fn from_std<F>(future: F) -> Compat<Pin<Box<F>>>
+where
+    F: TryFuture,
+{
+    Compat::new(Box::pin(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future};
+    use futures03::executor::block_on;
+    use futures03::future as future03;
+    use super::*;
+    use transport::memory;
+
+    #[test]
+    fn std_future_roundtrip() {
+        let value = from_std(future03::ready(Ok::<_, ()>(5u32))).wait();
+        assert_eq!(value, Ok(5));
+
+        let future = Compat01As03::new(future::ok::<_, ()>(12u32));
+        assert_eq!(block_on(future), Ok(12));
+    }
+
+    #[test]
+    fn dial_std_unsupported_address() {
+        let (tx, _rx) = memory::connector();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+        assert!(tx.dial_std(addr).is_err());
+    }
+}
@@ -221,11 +221,6 @@ where
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
         self.dial(addr)
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.transports.nat_traversal(server, observed)
-    }
 }
 
 impl<T, C> MuxedTransport for UpgradedNode<T, C>
@@ -20,10 +20,12 @@
 
 use bytes::Bytes;
 use futures::{prelude::*, future::Either};
-use multistream_select::{self, DialerSelectFuture, ListenerSelectFuture};
+use multistream_select::{self, DialerSelectFuture, DialerSelectLazy, ListenerSelectFuture};
+use multistream_select::Negotiated;
 use std::{io::{Error as IoError, ErrorKind as IoErrorKind}, mem};
 use tokio_io::{AsyncRead, AsyncWrite};
 use upgrade::{ConnectionUpgrade, Endpoint};
+use upgrade::version::protocol_name_matches;
 use Multiaddr;
 
 /// Applies a connection upgrade on a socket.
@@ -36,13 +38,24 @@ where
     U::NamesIter: Clone, // TODO: not elegant
     C: AsyncRead + AsyncWrite,
 {
+    debug!("Starting protocol negotiation");
+    let iter = ProtocolNames(NamedProtocolNames(upgrade.protocol_names()));
+    let future = NegotiationFuture {
+        inner: match e {
+            Endpoint::Listener => Either::A(multistream_select::listener_select_proto(conn, iter)),
+            Endpoint::Dialer => Either::B(multistream_select::dialer_select_proto(conn, iter)),
+        }
+    };
+
     UpgradeApplyFuture {
         inner: UpgradeApplyState::Init {
-            future: negotiate(conn, &upgrade, e),
+            future,
             upgrade,
             endpoint: e,
             remote: remote.clone()
-        }
+        },
+        rejected: Vec::new(),
+        protocol: None,
     }
 }
 
@@ -52,7 +65,31 @@ where
     U: ConnectionUpgrade<C>,
     C: AsyncRead + AsyncWrite
 {
-    inner: UpgradeApplyState<C, U>
+    inner: UpgradeApplyState<C, U>,
+    /// Protocols proposed by the remote that we rejected, not taken yet.
+    rejected: Vec<Bytes>,
+    /// Name of the protocol that has been negotiated, if negotiation has finished.
+    protocol: Option<Bytes>,
+}
+
+impl<C, U> UpgradeApplyFuture<C, U>
+where
+    U: ConnectionUpgrade<C>,
+    C: AsyncRead + AsyncWrite
+{
+    /// Returns the names of the protocols that the remote proposed and that we answered as not
+    /// available since the last call to this method. Always empty on the dialer side.
+    #[inline]
+    pub fn take_rejected_protocols(&mut self) -> Vec<Bytes> {
+        mem::replace(&mut self.rejected, Vec::new())
+    }
+
+    /// Returns the name of the protocol that has been negotiated with the remote, or `None` if
+    /// the negotiation hasn't finished yet.
+    #[inline]
+    pub fn negotiated_protocol(&self) -> Option<&Bytes> {
+        self.protocol.as_ref()
+    }
 }
 
 enum UpgradeApplyState<C, U>
@@ -61,7 +98,7 @@ where
     C: AsyncRead + AsyncWrite
 {
     Init {
-        future: NegotiationFuture<C, ProtocolNames<U::NamesIter>, U::UpgradeIdentifier>,
+        future: NegotiationFuture<C, ProtocolNames<NamedProtocolNames<U::NamesIter>>, (Bytes, U::UpgradeIdentifier)>,
         upgrade: U,
         endpoint: Endpoint,
         remote: Multiaddr
@@ -85,13 +122,16 @@ where
         loop {
             match mem::replace(&mut self.inner, UpgradeApplyState::Undefined) {
                 UpgradeApplyState::Init { mut future, upgrade, endpoint, remote } => {
-                    let (upgrade_id, connection) = match future.poll()? {
+                    let polled = future.poll();
+                    self.rejected.extend(future.take_rejected_protocols());
+                    let ((name, upgrade_id), connection) = match polled? {
                         Async::Ready(x) => x,
                         Async::NotReady => {
                             self.inner = UpgradeApplyState::Init { future, upgrade, endpoint, remote };
                             return Ok(Async::NotReady)
                         }
                     };
+                    self.protocol = Some(name);
                     self.inner = UpgradeApplyState::Upgrade {
                         future: upgrade.upgrade(connection, upgrade_id, endpoint, &remote)
                     };
@@ -119,6 +159,139 @@ where
     }
 }
 
+/// How the dialer negotiates the protocol to use with the remote.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NegotiationMode {
+    /// Wait for the listener to accept a protocol before using the connection. Several protocols
+    /// can be attempted one after the other.
+    Standard,
+    /// Propose the first protocol of the upgrade optimistically and start using the connection
+    /// immediately, which saves a round trip. If the listener doesn't support this protocol, the
+    /// error is produced when reading from the connection.
+    ///
+    /// Has no effect on the listener side, which handles lazy dialers transparently.
+    Lazy,
+}
+
+impl Default for NegotiationMode {
+    #[inline]
+    fn default() -> Self {
+        NegotiationMode::Standard
+    }
+}
+
+/// Same as `apply`, but lets you choose the `NegotiationMode`.
+///
+/// The upgrade is applied on a `Negotiated` wrapper around the socket, which processes the answer
+/// of the listener if the protocol has been proposed lazily, and is transparent otherwise.
+#[inline]
+pub fn apply_with_mode<C, U>(conn: C, upgrade: U, e: Endpoint, remote: &Multiaddr, mode: NegotiationMode)
+    -> UpgradeApplyWithModeFuture<C, U>
+where
+    U: ConnectionUpgrade<Negotiated<C>>,
+    U::NamesIter: Clone, // TODO: not elegant
+    C: AsyncRead + AsyncWrite,
+{
+    let future = match (mode, e) {
+        (NegotiationMode::Lazy, Endpoint::Dialer) => {
+            debug!("Starting lazy protocol negotiation");
+            let iter = ProtocolNames(upgrade.protocol_names());
+            Either::B(multistream_select::dialer_select_proto_lazy(conn, iter))
+        },
+        _ => Either::A(negotiate::<_, Negotiated<C>, _>(conn, &upgrade, e)),
+    };
+
+    UpgradeApplyWithModeFuture {
+        inner: UpgradeApplyWithModeState::Init {
+            future,
+            upgrade,
+            endpoint: e,
+            remote: remote.clone()
+        }
+    }
+}
+
+/// Future, returned from `apply_with_mode` which performs a connection upgrade.
+pub struct UpgradeApplyWithModeFuture<C, U>
+where
+    U: ConnectionUpgrade<Negotiated<C>>,
+    C: AsyncRead + AsyncWrite
+{
+    inner: UpgradeApplyWithModeState<C, U>
+}
+
+enum UpgradeApplyWithModeState<C, U>
+where
+    U: ConnectionUpgrade<Negotiated<C>>,
+    C: AsyncRead + AsyncWrite
+{
+    Init {
+        future: Either<
+            NegotiationFuture<C, ProtocolNames<U::NamesIter>, U::UpgradeIdentifier>,
+            DialerSelectLazy<C, U::UpgradeIdentifier>
+        >,
+        upgrade: U,
+        endpoint: Endpoint,
+        remote: Multiaddr
+    },
+    Upgrade {
+        future: U::Future
+    },
+    Undefined
+}
+
+impl<C, U> Future for UpgradeApplyWithModeFuture<C, U>
+where
+    U: ConnectionUpgrade<Negotiated<C>>,
+    U::NamesIter: Clone,
+    C: AsyncRead + AsyncWrite
+{
+    type Item = U::Output;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(&mut self.inner, UpgradeApplyWithModeState::Undefined) {
+                UpgradeApplyWithModeState::Init { mut future, upgrade, endpoint, remote } => {
+                    let polled = match future {
+                        Either::A(ref mut future) => future.poll()
+                            .map(|a| a.map(|(id, conn)| (id, Negotiated::completed(conn)))),
+                        Either::B(ref mut future) => future.poll()
+                            .map_err(|err| IoError::new(IoErrorKind::Other, err)),
+                    };
+                    let (upgrade_id, connection) = match polled? {
+                        Async::Ready(x) => x,
+                        Async::NotReady => {
+                            self.inner = UpgradeApplyWithModeState::Init { future, upgrade, endpoint, remote };
+                            return Ok(Async::NotReady)
+                        }
+                    };
+                    self.inner = UpgradeApplyWithModeState::Upgrade {
+                        future: upgrade.upgrade(connection, upgrade_id, endpoint, &remote)
+                    };
+                }
+                UpgradeApplyWithModeState::Upgrade { mut future } => {
+                    match future.poll() {
+                        Ok(Async::NotReady) => {
+                            self.inner = UpgradeApplyWithModeState::Upgrade { future };
+                            return Ok(Async::NotReady)
+                        }
+                        Ok(Async::Ready(x)) => {
+                            debug!("Successfully applied negotiated protocol");
+                            return Ok(Async::Ready(x))
+                        }
+                        Err(e) => {
+                            debug!("Failed to apply negotiated protocol: {:?}", e);
+                            return Err(e)
+                        }
+                    }
+                }
+                UpgradeApplyWithModeState::Undefined =>
+                    panic!("UpgradeApplyWithModeState::poll called after completion")
+            }
+        }
+    }
+}
 
 /// Negotiates a protocol on a stream.
 ///
@@ -149,6 +322,18 @@ pub struct NegotiationFuture<R: AsyncRead + AsyncWrite, I, P> {
     inner: Either<ListenerSelectFuture<R, I, P>, DialerSelectFuture<R, I, P>>
 }
 
+impl<R: AsyncRead + AsyncWrite, I, P> NegotiationFuture<R, I, P> {
+    /// Returns the names of the protocols that the remote proposed and that we answered as not
+    /// available since the last call to this method. Always empty on the dialer side.
+    #[inline]
+    pub fn take_rejected_protocols(&mut self) -> Vec<Bytes> {
+        match self.inner {
+            Either::A(ref mut listener) => listener.take_rejected_protocols(),
+            Either::B(_) => Vec::new(),
+        }
+    }
+}
+
 impl<R, I, M, P> Future for NegotiationFuture<R, I, P>
 where
     R: AsyncRead + AsyncWrite,
@@ -174,7 +359,8 @@ where
     }
 }
 
-/// Iterator adapter which adds equality matching predicates to items.
+/// Iterator adapter which adds matching predicates to items. Names are matched with
+/// `protocol_name_matches`, which supports version requirements.
 /// Used in `NegotiationFuture`.
 #[derive(Clone)]
 pub struct ProtocolNames<I>(I);
@@ -186,7 +372,7 @@ where
     type Item = (Bytes, fn(&Bytes, &Bytes) -> bool, Id);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let f = <Bytes as PartialEq>::eq as fn(&Bytes, &Bytes) -> bool;
+        let f = names_match as fn(&Bytes, &Bytes) -> bool;
         self.0.next().map(|(b, id)| (b, f, id))
     }
 
@@ -195,4 +381,28 @@ where
     }
 }
 
+/// Iterator adapter which keeps a copy of the protocol name alongside the upgrade identifier,
+/// so that the name of the negotiated protocol can be retrieved.
+/// Used in `UpgradeApplyFuture`.
+#[derive(Clone)]
+struct NamedProtocolNames<I>(I);
+
+impl<I, Id> Iterator for NamedProtocolNames<I>
+where
+    I: Iterator<Item=(Bytes, Id)>
+{
+    type Item = (Bytes, (Bytes, Id));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(b, id)| (b.clone(), (b, id)))
+    }
 
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// Matching predicate used by `ProtocolNames`.
+fn names_match(remote: &Bytes, local: &Bytes) -> bool {
+    protocol_name_matches(remote, local)
+}
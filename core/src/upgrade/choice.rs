@@ -19,6 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use bytes::Bytes;
+use either_n::IntoOrUpgrade;
 use futures::future;
 use tokio_io::{AsyncRead, AsyncWrite};
 use upgrade::{ConnectionUpgrade, Endpoint};
@@ -33,6 +34,19 @@ pub fn or<A, B>(me: A, other: B) -> OrUpgrade<A, B> {
     OrUpgrade(me, other)
 }
 
+/// Builds a new `ConnectionUpgrade` that chooses between all the upgrades of a tuple.
+///
+/// The upgrades that come first in the tuple have priority. Contrary to nesting calls to `or`,
+/// the identifier of the negotiated protocol has one variant per upgrade. See the `either_n`
+/// module.
+#[inline]
+pub fn or_all<T>(upgrades: T) -> T::Upgrade
+where
+    T: IntoOrUpgrade,
+{
+    upgrades.into_or_upgrade()
+}
+
 /// See `upgrade::or`.
 #[derive(Debug, Copy, Clone)]
 pub struct OrUpgrade<A, B>(A, B);
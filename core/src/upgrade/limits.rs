@@ -0,0 +1,103 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Limits on the size of the messages that are read from the remotes.
+//!
+//! Most protocols exchange messages prefixed with their length. A remote that announces a
+//! gigantic length can make us allocate as much memory, unless the reading side enforces a
+//! maximum. The `MessageLimits` struct holds the maximum size of the messages for all the
+//! protocols, with optional overrides for specific protocols, and is passed to the configuration
+//! of the upgrades with their `with_message_limits` method.
+//!
+//! # Example
+//!
+//! ```
+//! use libp2p_core::upgrade::MessageLimits;
+//!
+//! let limits = MessageLimits::new()
+//!     .with_default_max(64 * 1024)
+//!     .with_protocol_max("/meshsub/1.0.0", 1024 * 1024);
+//!
+//! assert_eq!(limits.max_for(b"/ipfs/id/1.0.0"), 64 * 1024);
+//! assert_eq!(limits.max_for(b"/meshsub/1.0.0"), 1024 * 1024);
+//! ```
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+
+/// Maximum size of a message for the protocols that don't have a more specific limit. 1 MiB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Maximum sizes of the messages read from the remotes, globally and per protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Limit for the protocols that aren't in `protocols`.
+    default: usize,
+    /// Limits of specific protocols, indexed by protocol name.
+    protocols: FnvHashMap<Bytes, usize>,
+}
+
+impl MessageLimits {
+    /// Creates limits of `DEFAULT_MAX_MESSAGE_SIZE` for all the protocols.
+    #[inline]
+    pub fn new() -> MessageLimits {
+        MessageLimits {
+            default: DEFAULT_MAX_MESSAGE_SIZE,
+            protocols: FnvHashMap::default(),
+        }
+    }
+
+    /// Sets the limit of the protocols that don't have a specific limit.
+    #[inline]
+    pub fn with_default_max(mut self, max: usize) -> Self {
+        self.default = max;
+        self
+    }
+
+    /// Sets the limit of a specific protocol, identified by its name as negotiated with
+    /// multistream-select.
+    #[inline]
+    pub fn with_protocol_max<N>(mut self, protocol: N, max: usize) -> Self
+    where
+        N: Into<Bytes>,
+    {
+        self.protocols.insert(protocol.into(), max);
+        self
+    }
+
+    /// Returns the limit of the protocols that don't have a specific limit.
+    #[inline]
+    pub fn default_max(&self) -> usize {
+        self.default
+    }
+
+    /// Returns the maximum size of a message of the given protocol.
+    #[inline]
+    pub fn max_for(&self, protocol: &[u8]) -> usize {
+        self.protocols.get(protocol).cloned().unwrap_or(self.default)
+    }
+}
+
+impl Default for MessageLimits {
+    #[inline]
+    fn default() -> Self {
+        MessageLimits::new()
+    }
+}
@@ -21,17 +21,27 @@
 pub mod apply;
 pub mod choice;
 pub mod denied;
+pub mod limits;
 pub mod loop_upg;
 pub mod map;
 pub mod plaintext;
+pub mod registry;
+pub mod select;
 pub mod toggleable;
 pub mod traits;
+pub mod transfer;
+pub mod version;
 
-pub use self::apply::{apply, negotiate};
-pub use self::choice::{or, OrUpgrade};
+pub use self::apply::{apply, apply_with_mode, negotiate, NegotiationMode};
+pub use self::choice::{or, or_all, OrUpgrade};
 pub use self::denied::DeniedConnectionUpgrade;
+pub use self::limits::{MessageLimits, DEFAULT_MAX_MESSAGE_SIZE};
 pub use self::loop_upg::{loop_upg, Loop};
 pub use self::map::map;
 pub use self::plaintext::PlainTextConfig;
+pub use self::registry::ProtocolRegistry;
+pub use self::select::{select, SelectUpgrade};
 pub use self::toggleable::toggleable;
 pub use self::traits::{ConnectionUpgrade, Endpoint};
+pub use self::transfer::{read_one, read_respond, write_one, ReadOneError};
+pub use self::version::protocol_name_matches;
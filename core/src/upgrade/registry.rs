@@ -0,0 +1,181 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use futures::prelude::*;
+use parking_lot::Mutex;
+use std::{fmt, io::Error as IoError, sync::Arc, vec::IntoIter as VecIntoIter};
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::{ConnectionUpgrade, Endpoint};
+use Multiaddr;
+
+/// Type-erased upgrade stored in a `ProtocolRegistry`.
+type UpgradeFn<C, O> = Fn(C, Endpoint, &Multiaddr) -> Box<Future<Item = O, Error = IoError> + Send> + Send + Sync;
+
+/// `ConnectionUpgrade` whose list of supported protocols can be modified at runtime.
+///
+/// Contrary to the other upgrades, whose protocol names are fixed when they are built, upgrades
+/// can be registered or removed from a `ProtocolRegistry` at any time, including while it is in
+/// use by a transport. Cloning a `ProtocolRegistry` produces a handle to the same list. Can be
+/// combined with other upgrades with `upgrade::or`.
+///
+/// Each negotiation uses the list of protocols as it is when the negotiation starts.
+pub struct ProtocolRegistry<C, O> {
+    protocols: Arc<Mutex<Vec<(Bytes, Arc<UpgradeFn<C, O>>)>>>,
+}
+
+impl<C, O> ProtocolRegistry<C, O> {
+    /// Creates a new empty registry.
+    #[inline]
+    pub fn new() -> Self {
+        ProtocolRegistry {
+            protocols: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Registers all the protocols of an upgrade. If a protocol name is already registered, it is
+    /// replaced.
+    pub fn register<U>(&self, upgrade: U)
+    where
+        U: ConnectionUpgrade<C, Output = O> + Clone + Send + Sync + 'static,
+        U::UpgradeIdentifier: Clone + Send + Sync + 'static,
+        U::Future: Send + 'static,
+        C: 'static,
+        O: 'static,
+    {
+        let mut protocols = self.protocols.lock();
+        for (name, id) in upgrade.protocol_names() {
+            let upgrade = upgrade.clone();
+            let upgrade_fn = move |socket: C, endpoint: Endpoint, remote_addr: &Multiaddr| {
+                let future = upgrade.clone().upgrade(socket, id.clone(), endpoint, remote_addr);
+                Box::new(future) as Box<Future<Item = _, Error = _> + Send>
+            };
+
+            protocols.retain(|&(ref n, _)| n != &name);
+            protocols.push((name, Arc::new(upgrade_fn)));
+        }
+    }
+
+    /// Removes a protocol. Returns false if it wasn't registered.
+    ///
+    /// Negotiations that are already in progress are not affected.
+    pub fn unregister(&self, name: &[u8]) -> bool {
+        let mut protocols = self.protocols.lock();
+        let len_before = protocols.len();
+        protocols.retain(|&(ref n, _)| &n[..] != name);
+        protocols.len() != len_before
+    }
+
+    /// Returns the names of the protocols that are currently registered.
+    pub fn protocols(&self) -> Vec<Bytes> {
+        self.protocols.lock().iter().map(|&(ref name, _)| name.clone()).collect()
+    }
+}
+
+impl<C, O> Default for ProtocolRegistry<C, O> {
+    #[inline]
+    fn default() -> Self {
+        ProtocolRegistry::new()
+    }
+}
+
+impl<C, O> Clone for ProtocolRegistry<C, O> {
+    #[inline]
+    fn clone(&self) -> Self {
+        ProtocolRegistry {
+            protocols: self.protocols.clone(),
+        }
+    }
+}
+
+impl<C, O> fmt::Debug for ProtocolRegistry<C, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_list().entries(self.protocols().iter()).finish()
+    }
+}
+
+impl<C, O> ConnectionUpgrade<C> for ProtocolRegistry<C, O>
+where
+    C: AsyncRead + AsyncWrite,
+{
+    type NamesIter = VecIntoIter<(Bytes, RegisteredUpgrade<C, O>)>;
+    type UpgradeIdentifier = RegisteredUpgrade<C, O>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        self.protocols
+            .lock()
+            .iter()
+            .map(|&(ref name, ref upgrade)| (name.clone(), RegisteredUpgrade(upgrade.clone())))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    type Output = O;
+    type Future = Box<Future<Item = O, Error = IoError> + Send>;
+
+    #[inline]
+    fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint, remote_addr: &Multiaddr) -> Self::Future {
+        (id.0)(socket, ty, remote_addr)
+    }
+}
+
+/// Identifier of a protocol of a `ProtocolRegistry`.
+pub struct RegisteredUpgrade<C, O>(Arc<UpgradeFn<C, O>>);
+
+impl<C, O> Clone for RegisteredUpgrade<C, O> {
+    #[inline]
+    fn clone(&self) -> Self {
+        RegisteredUpgrade(self.0.clone())
+    }
+}
+
+impl<C, O> fmt::Debug for RegisteredUpgrade<C, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_tuple("RegisteredUpgrade").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProtocolRegistry;
+    use bytes::Bytes;
+    use std::io::Cursor;
+    use upgrade::{ConnectionUpgrade, PlainTextConfig};
+
+    #[test]
+    fn register_and_unregister() {
+        let registry = ProtocolRegistry::<Cursor<Vec<u8>>, _>::new();
+        let handle = registry.clone();
+        assert!(registry.protocol_names().next().is_none());
+
+        handle.register(PlainTextConfig);
+        assert_eq!(registry.protocols(), vec![Bytes::from("/plaintext/1.0.0")]);
+
+        // Registering the same protocol twice doesn't duplicate it.
+        handle.register(PlainTextConfig);
+        assert_eq!(registry.protocol_names().count(), 1);
+
+        assert!(handle.unregister(b"/plaintext/1.0.0"));
+        assert!(!handle.unregister(b"/plaintext/1.0.0"));
+        assert!(registry.protocols().is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bytes::Bytes;
+use either::EitherOutput;
+use futures::{future, prelude::*};
+use tokio_io::{AsyncRead, AsyncWrite};
+use upgrade::choice::EitherUpgradeIdentifier;
+use upgrade::{ConnectionUpgrade, Endpoint};
+use Multiaddr;
+
+/// Builds a new `ConnectionUpgrade` that supports the protocols of both `A` and `B`.
+///
+/// Contrary to `upgrade::or`, `A` and `B` can have different outputs. The output of the upgrade
+/// is `EitherOutput::First` if the protocol of `A` was negotiated, and `EitherOutput::Second`
+/// otherwise.
+#[inline]
+pub fn select<A, B>(first: A, second: B) -> SelectUpgrade<A, B> {
+    SelectUpgrade {
+        first: Some(first),
+        second: Some(second),
+    }
+}
+
+/// See `upgrade::select`.
+///
+/// A `SelectUpgrade` can also contain only one of the two upgrades, in which case only the
+/// protocols of this upgrade are supported. This is useful in order to open a substream for a
+/// specific protocol while keeping the same type as the upgrade that supports all of them.
+#[derive(Debug, Copy, Clone)]
+pub struct SelectUpgrade<A, B> {
+    first: Option<A>,
+    second: Option<B>,
+}
+
+impl<A, B> SelectUpgrade<A, B> {
+    /// Builds a `SelectUpgrade` that only supports the protocols of `A`.
+    #[inline]
+    pub fn first(first: A) -> SelectUpgrade<A, B> {
+        SelectUpgrade {
+            first: Some(first),
+            second: None,
+        }
+    }
+
+    /// Builds a `SelectUpgrade` that only supports the protocols of `B`.
+    #[inline]
+    pub fn second(second: B) -> SelectUpgrade<A, B> {
+        SelectUpgrade {
+            first: None,
+            second: Some(second),
+        }
+    }
+}
+
+impl<C, A, B> ConnectionUpgrade<C> for SelectUpgrade<A, B>
+where
+    C: AsyncRead + AsyncWrite,
+    A: ConnectionUpgrade<C>,
+    B: ConnectionUpgrade<C>,
+{
+    type NamesIter = SelectNamesIter<A::NamesIter, B::NamesIter>;
+    type UpgradeIdentifier = EitherUpgradeIdentifier<A::UpgradeIdentifier, B::UpgradeIdentifier>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        SelectNamesIter {
+            first: self.first.as_ref().map(|u| u.protocol_names()),
+            second: self.second.as_ref().map(|u| u.protocol_names()),
+        }
+    }
+
+    type Output = EitherOutput<A::Output, B::Output>;
+    type Future = future::Either<
+        future::Map<A::Future, fn(A::Output) -> Self::Output>,
+        future::Map<B::Future, fn(B::Output) -> Self::Output>,
+    >;
+
+    #[inline]
+    fn upgrade(
+        self,
+        socket: C,
+        id: Self::UpgradeIdentifier,
+        ty: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        match (id, self.first, self.second) {
+            (EitherUpgradeIdentifier::First(id), Some(first), _) => {
+                let map: fn(_) -> _ = EitherOutput::First;
+                future::Either::A(first.upgrade(socket, id, ty, remote_addr).map(map))
+            }
+            (EitherUpgradeIdentifier::Second(id), _, Some(second)) => {
+                let map: fn(_) -> _ = EitherOutput::Second;
+                future::Either::B(second.upgrade(socket, id, ty, remote_addr).map(map))
+            }
+            _ => panic!("The identifier passed to upgrade() is always one that was produced by \
+                         protocol_names(), which only produces identifiers for the upgrades \
+                         that are present ; qed"),
+        }
+    }
+}
+
+/// Iterator returned by the `protocol_names` method of `SelectUpgrade`.
+#[derive(Debug, Copy, Clone)]
+pub struct SelectNamesIter<A, B> {
+    first: Option<A>,
+    second: Option<B>,
+}
+
+impl<A, B, AId, BId> Iterator for SelectNamesIter<A, B>
+where
+    A: Iterator<Item = (Bytes, AId)>,
+    B: Iterator<Item = (Bytes, BId)>,
+{
+    type Item = (Bytes, EitherUpgradeIdentifier<AId, BId>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ref mut first) = self.first {
+            if let Some((name, id)) = first.next() {
+                return Some((name, EitherUpgradeIdentifier::First(id)));
+            }
+        }
+        if let Some(ref mut second) = self.second {
+            if let Some((name, id)) = second.next() {
+                return Some((name, EitherUpgradeIdentifier::Second(id)));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use std::io::Cursor;
+    use upgrade::{self, ConnectionUpgrade, DeniedConnectionUpgrade, PlainTextConfig};
+
+    fn names<U: ConnectionUpgrade<Cursor<Vec<u8>>>>(upgrade: &U) -> Vec<Bytes> {
+        upgrade.protocol_names().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn protocol_names() {
+        let both = upgrade::select(PlainTextConfig, DeniedConnectionUpgrade);
+        assert_eq!(names(&both), names(&PlainTextConfig));
+
+        let first = upgrade::SelectUpgrade::<_, PlainTextConfig>::first(PlainTextConfig);
+        assert_eq!(names(&first), names(&PlainTextConfig));
+
+        let second = upgrade::SelectUpgrade::<PlainTextConfig, _>::second(DeniedConnectionUpgrade);
+        assert!(names(&second).is_empty());
+
+        let both = upgrade::select(PlainTextConfig, PlainTextConfig);
+        assert_eq!(names(&both).len(), 2 * names(&PlainTextConfig).len());
+    }
+}
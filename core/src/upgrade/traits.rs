@@ -62,6 +62,9 @@ pub trait ConnectionUpgrade<C> {
     type UpgradeIdentifier;
 
     /// Returns the name of the protocols to advertise to the remote.
+    ///
+    /// The version of a name can be a requirement such as `/myproto/1.x`, in which case it
+    /// matches all the compatible versions proposed by the remote. See the `version` module.
     fn protocol_names(&self) -> Self::NamesIter;
 
     /// Type of the stream that has been upgraded. Generally wraps around `C` and `Self`.
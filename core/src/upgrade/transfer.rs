@@ -0,0 +1,366 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Helpers for exchanging length-prefixed messages over a substream.
+//!
+//! Each message is prefixed with its length encoded as an unsigned varint. The reading side
+//! is given a maximum size and produces an error as soon as the remote announces a message
+//! larger than this limit, without allocating any buffer for it.
+
+use futures::prelude::*;
+use std::{mem, io::Error as IoError, io::ErrorKind as IoErrorKind};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Maximum number of bytes of the length prefix. Enough for a 64-bits value.
+const MAX_PREFIX_LEN: usize = 10;
+
+/// Sends a message on the socket, prefixed with its length, then flushes the socket.
+///
+/// The future produces the socket once the message has been sent.
+pub fn write_one<TSocket, TData>(socket: TSocket, data: TData) -> WriteOne<TSocket, TData>
+where
+    TSocket: AsyncWrite,
+    TData: AsRef<[u8]>,
+{
+    let mut prefix = [0; MAX_PREFIX_LEN];
+    let prefix_len = encode_len(data.as_ref().len(), &mut prefix);
+    WriteOne {
+        inner: WriteOneInner::WriteLen { socket, prefix, prefix_len, written: 0, data },
+    }
+}
+
+/// Future produced by `write_one`.
+#[must_use = "futures do nothing unless polled"]
+pub struct WriteOne<TSocket, TData> {
+    inner: WriteOneInner<TSocket, TData>,
+}
+
+enum WriteOneInner<TSocket, TData> {
+    /// Writing the length prefix.
+    WriteLen {
+        socket: TSocket,
+        prefix: [u8; MAX_PREFIX_LEN],
+        prefix_len: usize,
+        written: usize,
+        data: TData,
+    },
+    /// Writing the message.
+    WriteData {
+        socket: TSocket,
+        data: TData,
+        written: usize,
+    },
+    /// Flushing the socket.
+    Flush {
+        socket: TSocket,
+    },
+    /// Temporary state while transitioning between two states, or after the future finished.
+    Poisoned,
+}
+
+impl<TSocket, TData> Future for WriteOne<TSocket, TData>
+where
+    TSocket: AsyncWrite,
+    TData: AsRef<[u8]>,
+{
+    type Item = TSocket;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(&mut self.inner, WriteOneInner::Poisoned) {
+                WriteOneInner::WriteLen { mut socket, prefix, prefix_len, mut written, data } => {
+                    if written == prefix_len {
+                        self.inner = WriteOneInner::WriteData { socket, data, written: 0 };
+                        continue;
+                    }
+                    match socket.poll_write(&prefix[written .. prefix_len])? {
+                        Async::Ready(0) => return Err(IoErrorKind::WriteZero.into()),
+                        Async::Ready(n) => written += n,
+                        Async::NotReady => {
+                            self.inner = WriteOneInner::WriteLen { socket, prefix, prefix_len, written, data };
+                            return Ok(Async::NotReady);
+                        },
+                    }
+                    self.inner = WriteOneInner::WriteLen { socket, prefix, prefix_len, written, data };
+                },
+                WriteOneInner::WriteData { mut socket, data, mut written } => {
+                    if written == data.as_ref().len() {
+                        self.inner = WriteOneInner::Flush { socket };
+                        continue;
+                    }
+                    match socket.poll_write(&data.as_ref()[written ..])? {
+                        Async::Ready(0) => return Err(IoErrorKind::WriteZero.into()),
+                        Async::Ready(n) => written += n,
+                        Async::NotReady => {
+                            self.inner = WriteOneInner::WriteData { socket, data, written };
+                            return Ok(Async::NotReady);
+                        },
+                    }
+                    self.inner = WriteOneInner::WriteData { socket, data, written };
+                },
+                WriteOneInner::Flush { mut socket } => {
+                    match socket.poll_flush()? {
+                        Async::Ready(()) => return Ok(Async::Ready(socket)),
+                        Async::NotReady => {
+                            self.inner = WriteOneInner::Flush { socket };
+                            return Ok(Async::NotReady);
+                        },
+                    }
+                },
+                WriteOneInner::Poisoned => panic!("WriteOne polled after completion"),
+            }
+        }
+    }
+}
+
+/// Reads a message from the socket that has been sent with `write_one`.
+///
+/// The future produces the socket and the message. If the remote announces a message larger than
+/// `max_size` bytes, the future produces a `ReadOneError::TooLarge` error before reading the
+/// message.
+pub fn read_one<TSocket>(socket: TSocket, max_size: usize) -> ReadOne<TSocket>
+where
+    TSocket: AsyncRead,
+{
+    ReadOne {
+        inner: ReadOneInner::ReadLen { socket, len: 0, shift: 0 },
+        max_size,
+    }
+}
+
+/// Future produced by `read_one`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ReadOne<TSocket> {
+    inner: ReadOneInner<TSocket>,
+    max_size: usize,
+}
+
+enum ReadOneInner<TSocket> {
+    /// Reading the length prefix, one byte at a time.
+    ReadLen {
+        socket: TSocket,
+        len: u64,
+        shift: u32,
+    },
+    /// Reading the message.
+    ReadData {
+        socket: TSocket,
+        data: Vec<u8>,
+        read: usize,
+    },
+    /// Temporary state while transitioning between two states, or after the future finished.
+    Poisoned,
+}
+
+impl<TSocket> Future for ReadOne<TSocket>
+where
+    TSocket: AsyncRead,
+{
+    type Item = (TSocket, Vec<u8>);
+    type Error = ReadOneError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(&mut self.inner, ReadOneInner::Poisoned) {
+                ReadOneInner::ReadLen { mut socket, mut len, mut shift } => {
+                    let mut byte = [0];
+                    match socket.poll_read(&mut byte)? {
+                        Async::Ready(0) => return Err(ReadOneError::Io(IoErrorKind::UnexpectedEof.into())),
+                        Async::Ready(_) => (),
+                        Async::NotReady => {
+                            self.inner = ReadOneInner::ReadLen { socket, len, shift };
+                            return Ok(Async::NotReady);
+                        },
+                    }
+
+                    if shift > 63 || (shift == 63 && byte[0] & 0x7f > 1) {
+                        return Err(ReadOneError::Io(IoError::new(IoErrorKind::InvalidData, "length prefix overflow")));
+                    }
+                    len |= u64::from(byte[0] & 0x7f) << shift;
+                    shift += 7;
+
+                    // Check the size as soon as possible, so that a remote can't make us read a
+                    // long prefix either.
+                    if len > self.max_size as u64 {
+                        return Err(ReadOneError::TooLarge { requested: len, max: self.max_size });
+                    }
+
+                    if byte[0] & 0x80 == 0 {
+                        let data = vec![0; len as usize];
+                        self.inner = ReadOneInner::ReadData { socket, data, read: 0 };
+                    } else {
+                        self.inner = ReadOneInner::ReadLen { socket, len, shift };
+                    }
+                },
+                ReadOneInner::ReadData { mut socket, mut data, mut read } => {
+                    if read == data.len() {
+                        return Ok(Async::Ready((socket, data)));
+                    }
+                    match socket.poll_read(&mut data[read ..])? {
+                        Async::Ready(0) => return Err(ReadOneError::Io(IoErrorKind::UnexpectedEof.into())),
+                        Async::Ready(n) => read += n,
+                        Async::NotReady => {
+                            self.inner = ReadOneInner::ReadData { socket, data, read };
+                            return Ok(Async::NotReady);
+                        },
+                    }
+                    self.inner = ReadOneInner::ReadData { socket, data, read };
+                },
+                ReadOneInner::Poisoned => panic!("ReadOne polled after completion"),
+            }
+        }
+    }
+}
+
+quick_error! {
+    /// Error while reading a message with `read_one`.
+    #[derive(Debug)]
+    pub enum ReadOneError {
+        /// Error on the socket.
+        Io(err: IoError) {
+            display("I/O error: {}", err)
+            cause(err)
+            from()
+        }
+        /// The remote announced a message larger than the maximum size.
+        TooLarge { requested: u64, max: usize } {
+            display("message of {} bytes is larger than the maximum of {} bytes", requested, max)
+        }
+    }
+}
+
+impl From<ReadOneError> for IoError {
+    #[inline]
+    fn from(err: ReadOneError) -> IoError {
+        match err {
+            ReadOneError::Io(err) => err,
+            err @ ReadOneError::TooLarge { .. } => IoError::new(IoErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Reads a message from the socket with `read_one`, then passes the socket, the message and
+/// `param` to `then`.
+///
+/// This is typically used by the listening side of a protocol to read a request and build the
+/// future that sends the response.
+pub fn read_respond<TSocket, TParam, TThen, TOut, TErr>(socket: TSocket, max_size: usize, param: TParam, then: TThen)
+    -> ReadRespond<TSocket, TParam, TThen>
+where
+    TSocket: AsyncRead,
+    TThen: FnOnce(TSocket, Vec<u8>, TParam) -> Result<TOut, TErr>,
+    TErr: From<ReadOneError>,
+{
+    ReadRespond {
+        inner: read_one(socket, max_size),
+        then: Some((param, then)),
+    }
+}
+
+/// Future produced by `read_respond`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ReadRespond<TSocket, TParam, TThen> {
+    inner: ReadOne<TSocket>,
+    then: Option<(TParam, TThen)>,
+}
+
+impl<TSocket, TParam, TThen, TOut, TErr> Future for ReadRespond<TSocket, TParam, TThen>
+where
+    TSocket: AsyncRead,
+    TThen: FnOnce(TSocket, Vec<u8>, TParam) -> Result<TOut, TErr>,
+    TErr: From<ReadOneError>,
+{
+    type Item = TOut;
+    type Error = TErr;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (socket, data) = try_ready!(self.inner.poll());
+        let (param, then) = self.then.take().expect("ReadRespond polled after completion");
+        then(socket, data, param).map(Async::Ready)
+    }
+}
+
+/// Encodes `len` as an unsigned varint in `buf`. Returns the number of bytes written.
+fn encode_len(len: usize, buf: &mut [u8; MAX_PREFIX_LEN]) -> usize {
+    let mut len = len as u64;
+    let mut n = 0;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf[n] = byte;
+            return n + 1;
+        }
+        buf[n] = byte | 0x80;
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn write_then_read() {
+        let message = vec![7u8; 300];
+        let socket = write_one(Cursor::new(Vec::new()), message.clone()).wait().unwrap();
+        let written = socket.into_inner();
+        assert_eq!(&written[..2], &[0xac, 0x02]);
+
+        let (_, read) = read_one(Cursor::new(written), 300).wait().unwrap();
+        assert_eq!(read, message);
+    }
+
+    #[test]
+    fn too_large() {
+        let socket = write_one(Cursor::new(Vec::new()), vec![0u8; 301]).wait().unwrap();
+        match read_one(Cursor::new(socket.into_inner()), 300).wait() {
+            Err(ReadOneError::TooLarge { requested: 301, max: 300 }) => (),
+            _ => panic!(),
+        }
+
+        // A huge length is refused without reading the rest of the prefix.
+        let prefix = vec![0xff; MAX_PREFIX_LEN];
+        match read_one(Cursor::new(prefix), 1024).wait() {
+            Err(ReadOneError::TooLarge { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        match read_one(Cursor::new(vec![5, 1, 2]), 1024).wait() {
+            Err(ReadOneError::Io(ref err)) if err.kind() == IoErrorKind::UnexpectedEof => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn respond() {
+        let socket = write_one(Cursor::new(Vec::new()), b"ping".to_vec()).wait().unwrap();
+        let out = read_respond(Cursor::new(socket.into_inner()), 16, 3, |_, data, param| {
+            Ok::<_, ReadOneError>(data.len() + param)
+        }).wait().unwrap();
+        assert_eq!(out, 7);
+    }
+}
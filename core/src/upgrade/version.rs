@@ -0,0 +1,105 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Matching of protocol names that contain a version requirement.
+//!
+//! The last component of a protocol name is usually its version, such as in `/myproto/1.2.0`.
+//! When an upgrade declares a protocol name whose version contains a wildcard, such as
+//! `/myproto/1.x`, `/myproto/1.2.*` or `/myproto/*`, the name matches all the protocol names
+//! with the same prefix and a compatible version. For example `/myproto/1.x` matches
+//! `/myproto/1.0.0` and `/myproto/1.4`, but not `/myproto/2.0.0`.
+//!
+//! Names without any wildcard only match names that are strictly identical.
+
+/// Returns true if the two protocol names match, either because they are identical or because
+/// one of them contains a version requirement that the other one satisfies.
+///
+/// This is the function used by `upgrade::negotiate` to compare the names proposed by the remote
+/// with ours.
+pub fn protocol_name_matches(a: &[u8], b: &[u8]) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let (a_prefix, a_version) = split_version(a);
+    let (b_prefix, b_version) = split_version(b);
+    if a_prefix != b_prefix {
+        return false;
+    }
+
+    let a_comps = a_version.split(|c| *c == b'.').collect::<Vec<_>>();
+    let b_comps = b_version.split(|c| *c == b'.').collect::<Vec<_>>();
+    if !a_comps.iter().chain(b_comps.iter()).any(|c| is_wildcard(c)) {
+        return false;
+    }
+
+    for n in 0 .. a_comps.len().max(b_comps.len()) {
+        // Missing components are treated as zeroes, so that `1.x` matches `1`.
+        let a_comp = a_comps.get(n).cloned().unwrap_or(&b"0"[..]);
+        let b_comp = b_comps.get(n).cloned().unwrap_or(&b"0"[..]);
+        if is_wildcard(a_comp) || is_wildcard(b_comp) {
+            return true;
+        }
+        if a_comp != b_comp {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Splits a protocol name into the part before the last `/` (included) and the version.
+fn split_version(name: &[u8]) -> (&[u8], &[u8]) {
+    match name.iter().rposition(|c| *c == b'/') {
+        Some(pos) => (&name[.. pos + 1], &name[pos + 1 ..]),
+        None => (&name[.. 0], name),
+    }
+}
+
+/// Returns true if a version component matches anything.
+#[inline]
+fn is_wildcard(comp: &[u8]) -> bool {
+    comp == b"x" || comp == b"X" || comp == b"*"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol_name_matches;
+
+    #[test]
+    fn exact_names() {
+        assert!(protocol_name_matches(b"/myproto/1.0.0", b"/myproto/1.0.0"));
+        assert!(!protocol_name_matches(b"/myproto/1.0.0", b"/myproto/1.0.1"));
+        assert!(!protocol_name_matches(b"/myproto/1.0", b"/myproto/1.0.0"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(protocol_name_matches(b"/myproto/1.x", b"/myproto/1.0.0"));
+        assert!(protocol_name_matches(b"/myproto/1.4", b"/myproto/1.x"));
+        assert!(protocol_name_matches(b"/myproto/1.2.*", b"/myproto/1.2.7"));
+        assert!(protocol_name_matches(b"/myproto/*", b"/myproto/3.1.4"));
+        assert!(protocol_name_matches(b"/myproto/1.x", b"/myproto/1"));
+        assert!(!protocol_name_matches(b"/myproto/1.x", b"/myproto/2.0.0"));
+        assert!(!protocol_name_matches(b"/myproto/1.2.x", b"/myproto/1.3.0"));
+        assert!(!protocol_name_matches(b"/myproto/1.x", b"/otherproto/1.0.0"));
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Feeds arbitrary data to a mplex connection, as if it had been sent by the remote, and reads
+//! all the substreams that it opens.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_core;
+extern crate libp2p_mplex;
+extern crate tokio_io;
+
+use futures::{future, prelude::*};
+use libp2p_core::{ConnectionUpgrade, Endpoint, StreamMuxer};
+use std::io::{self, Cursor, Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Socket that reads the fuzzer input and discards everything that is written.
+struct FuzzSocket<'a>(Cursor<&'a [u8]>);
+
+impl<'a> Read for FuzzSocket<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<'a> AsyncRead for FuzzSocket<'a> {}
+
+impl<'a> Write for FuzzSocket<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> AsyncWrite for FuzzSocket<'a> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let addr: libp2p_core::Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    let socket = FuzzSocket(Cursor::new(data));
+    let muxer = match libp2p_mplex::MplexConfig::new()
+        .upgrade(socket, (), Endpoint::Listener, &addr)
+        .wait()
+    {
+        Ok(muxer) => muxer,
+        Err(_) => return,
+    };
+
+    let _ = future::poll_fn(|| -> Poll<(), io::Error> {
+        let mut buf = [0; 256];
+        while let Async::Ready(Some(mut substream)) = muxer.poll_inbound()? {
+            loop {
+                match muxer.read_substream(&mut substream, &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => (),
+                }
+            }
+            muxer.destroy_substream(substream);
+        }
+        Ok(Async::Ready(()))
+    }).wait();
+});
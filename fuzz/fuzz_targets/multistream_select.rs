@@ -0,0 +1,80 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Feeds arbitrary data to both sides of a multistream-select negotiation, as if it had been sent
+//! by the remote.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate futures;
+extern crate multistream_select;
+extern crate tokio_io;
+
+use bytes::Bytes;
+use futures::prelude::*;
+use multistream_select::{dialer_select_proto, listener_select_proto};
+use std::io::{self, Cursor, Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Socket that reads the fuzzer input and discards everything that is written.
+struct FuzzSocket<'a>(Cursor<&'a [u8]>);
+
+impl<'a> Read for FuzzSocket<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<'a> AsyncRead for FuzzSocket<'a> {}
+
+impl<'a> Write for FuzzSocket<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> AsyncWrite for FuzzSocket<'a> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+fn protocols() -> impl Iterator<Item = (Bytes, fn(&Bytes, &Bytes) -> bool, u32)> + Clone {
+    let eq: fn(&Bytes, &Bytes) -> bool = |a, b| a == b;
+    vec![
+        (Bytes::from("/proto/1"), eq, 1),
+        (Bytes::from("/proto/2"), eq, 2),
+        (Bytes::from("/proto/3"), eq, 3),
+        (Bytes::from("/proto/4"), eq, 4),
+    ].into_iter()
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = listener_select_proto(FuzzSocket(Cursor::new(data)), protocols()).wait();
+    let _ = dialer_select_proto(FuzzSocket(Cursor::new(data)), protocols()).wait();
+    let _ = dialer_select_proto(FuzzSocket(Cursor::new(data)), protocols().take(1)).wait();
+});
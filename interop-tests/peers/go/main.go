@@ -0,0 +1,133 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// Remote peer of the interoperability tests, built with go-libp2p. See the documentation of the
+// `interop-tests` crate for the protocol it follows.
+package main
+
+import (
+	"bufio"
+	"context"
+	"flag"
+	"fmt"
+	"os"
+	"strings"
+	"time"
+
+	libp2p "github.com/libp2p/go-libp2p"
+	dht "github.com/libp2p/go-libp2p-kad-dht"
+	peer "github.com/libp2p/go-libp2p-peer"
+	pstore "github.com/libp2p/go-libp2p-peerstore"
+	secio "github.com/libp2p/go-libp2p-secio"
+	ping "github.com/libp2p/go-libp2p/p2p/protocol/ping"
+	ma "github.com/multiformats/go-multiaddr"
+	mplex "github.com/whyrusleeping/go-smux-multiplex"
+	yamux "github.com/whyrusleeping/go-smux-yamux"
+)
+
+const commandTimeout = 20 * time.Second
+
+func main() {
+	listen := flag.String("listen", "/ip4/127.0.0.1/tcp/0", "address to listen on")
+	muxer := flag.String("muxer", "mplex", "multiplexing protocol, mplex or yamux")
+	flag.Parse()
+
+	var muxerOpt libp2p.Option
+	switch *muxer {
+	case "mplex":
+		muxerOpt = libp2p.Muxer("/mplex/6.7.0", mplex.DefaultTransport)
+	case "yamux":
+		muxerOpt = libp2p.Muxer("/yamux/1.0.0", yamux.DefaultTransport)
+	default:
+		fmt.Fprintf(os.Stderr, "unknown muxer: %s\n", *muxer)
+		os.Exit(2)
+	}
+
+	ctx := context.Background()
+	host, err := libp2p.New(ctx,
+		libp2p.ListenAddrStrings(*listen),
+		libp2p.Security(secio.ID, secio.New),
+		muxerOpt,
+	)
+	if err != nil {
+		fmt.Fprintf(os.Stderr, "failed to start: %s\n", err)
+		os.Exit(1)
+	}
+	pinger := ping.NewPingService(host)
+	if _, err := dht.New(ctx, host); err != nil {
+		fmt.Fprintf(os.Stderr, "failed to start the DHT: %s\n", err)
+		os.Exit(1)
+	}
+
+	fmt.Printf("%s/p2p/%s\n", host.Addrs()[0], host.ID().Pretty())
+
+	// Commands are read until the standard input is closed.
+	stdin := bufio.NewScanner(os.Stdin)
+	for stdin.Scan() {
+		fields := strings.Fields(stdin.Text())
+		if len(fields) != 2 || fields[0] != "ping" {
+			fmt.Printf("error unknown command\n")
+			continue
+		}
+		if err := pingPeer(ctx, host, pinger, fields[1]); err != nil {
+			fmt.Printf("error %s\n", err)
+		} else {
+			fmt.Printf("ok\n")
+		}
+	}
+}
+
+// Dials the peer at the given `<multiaddr>/p2p/<peer id>` address and pings it.
+func pingPeer(ctx context.Context, host interface {
+	Connect(context.Context, pstore.PeerInfo) error
+}, pinger *ping.PingService, target string) error {
+	ctx, cancel := context.WithTimeout(ctx, commandTimeout)
+	defer cancel()
+
+	pos := strings.LastIndex(target, "/p2p/")
+	if pos < 0 {
+		return fmt.Errorf("missing peer id in %s", target)
+	}
+	addr, err := ma.NewMultiaddr(target[:pos])
+	if err != nil {
+		return err
+	}
+	id, err := peer.IDB58Decode(target[pos+len("/p2p/"):])
+	if err != nil {
+		return err
+	}
+
+	if err := host.Connect(ctx, pstore.PeerInfo{ID: id, Addrs: []ma.Multiaddr{addr}}); err != nil {
+		return err
+	}
+	rtts, err := pinger.Ping(ctx, id)
+	if err != nil {
+		return err
+	}
+	select {
+	case _, ok := <-rtts:
+		if !ok {
+			return fmt.Errorf("ping failed")
+		}
+		return nil
+	case <-ctx.Done():
+		return ctx.Err()
+	}
+}
@@ -0,0 +1,216 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Starts and controls the remote peers.
+
+use libp2p::{Multiaddr, PeerId};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Environment variable that configures the driver.
+const DRIVER_VAR: &str = "LIBP2P_INTEROP_PEER";
+
+/// Maximum time to wait for a line from a remote peer, in seconds.
+const LINE_TIMEOUT_SECS: u64 = 30;
+
+/// Multiplexing protocol used by a remote peer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Muxer {
+    Mplex,
+    Yamux,
+}
+
+impl Muxer {
+    /// Returns the name of the protocol on the command line of the remote peers.
+    fn name(&self) -> &'static str {
+        match *self {
+            Muxer::Mplex => "mplex",
+            Muxer::Yamux => "yamux",
+        }
+    }
+}
+
+/// Way to start the remote peers.
+#[derive(Debug, Clone)]
+pub enum Driver {
+    /// Runs an executable.
+    Exec(String),
+    /// Runs a Docker image.
+    Docker(String),
+}
+
+impl Driver {
+    /// Builds the driver configured by the environment, if any.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the configuration is invalid.
+    pub fn from_env() -> Option<Driver> {
+        let config = env::var(DRIVER_VAR).ok()?;
+        if config.starts_with("exec:") {
+            Some(Driver::Exec(config["exec:".len()..].to_owned()))
+        } else if config.starts_with("docker:") {
+            Some(Driver::Docker(config["docker:".len()..].to_owned()))
+        } else {
+            panic!("invalid value for {}: {:?}", DRIVER_VAR, config)
+        }
+    }
+
+    /// Starts a remote peer that uses the given multiplexing protocol, and waits for it to
+    /// listen.
+    pub fn spawn(&self, muxer: Muxer) -> Result<RemotePeer, io::Error> {
+        let mut command = match *self {
+            Driver::Exec(ref path) => Command::new(path),
+            Driver::Docker(ref image) => {
+                let mut command = Command::new("docker");
+                command.args(&["run", "--rm", "-i", "--network", "host", image]);
+                command
+            },
+        };
+        command.args(&["--listen", "/ip4/127.0.0.1/tcp/0", "--muxer", muxer.name()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+
+        let mut child = command.spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        // The standard output is read from a background thread, so that we can give up on a
+        // remote peer that doesn't answer.
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (addr, peer_id) = match next_line(&lines).and_then(|line| parse_peer_addr(&line)) {
+            Ok(addr) => addr,
+            Err(err) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err);
+            },
+        };
+
+        Ok(RemotePeer {
+            child,
+            stdin,
+            lines,
+            addr,
+            peer_id,
+        })
+    }
+}
+
+/// Starts a remote peer with the driver configured by the environment. Returns `None` if no
+/// driver is configured, in which case the test should return immediately.
+///
+/// # Panic
+///
+/// Panics if the remote peer fails to start.
+pub fn remote_peer(muxer: Muxer) -> Option<RemotePeer> {
+    let driver = match Driver::from_env() {
+        Some(driver) => driver,
+        None => {
+            println!("{} is not set; skipping the interoperability test", DRIVER_VAR);
+            return None;
+        },
+    };
+    Some(driver.spawn(muxer).expect("failed to start the remote peer"))
+}
+
+/// Remote peer that runs in a child process. The process is killed when the `RemotePeer` is
+/// destroyed.
+pub struct RemotePeer {
+    child: Child,
+    stdin: ChildStdin,
+    lines: mpsc::Receiver<Result<String, io::Error>>,
+    addr: Multiaddr,
+    peer_id: PeerId,
+}
+
+impl RemotePeer {
+    /// Returns the address the remote peer listens on.
+    #[inline]
+    pub fn addr(&self) -> &Multiaddr {
+        &self.addr
+    }
+
+    /// Returns the identity of the remote peer.
+    #[inline]
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    /// Asks the remote peer to dial the given peer and ping it.
+    pub fn ping(&mut self, addr: &Multiaddr, peer_id: &PeerId) -> Result<(), io::Error> {
+        self.command(&format!("ping {}/p2p/{}", addr, peer_id.to_base58()))
+    }
+
+    /// Sends a command to the remote peer and waits for its answer.
+    fn command(&mut self, command: &str) -> Result<(), io::Error> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()?;
+        let answer = next_line(&self.lines)?;
+        if answer == "ok" {
+            Ok(())
+        } else if answer.starts_with("error ") {
+            Err(io::Error::new(io::ErrorKind::Other, &answer["error ".len()..]))
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               format!("unexpected answer from the remote peer: {:?}", answer)))
+        }
+    }
+}
+
+impl Drop for RemotePeer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Returns the next line printed by a remote peer.
+fn next_line(lines: &mpsc::Receiver<Result<String, io::Error>>) -> Result<String, io::Error> {
+    match lines.recv_timeout(Duration::from_secs(LINE_TIMEOUT_SECS)) {
+        Ok(line) => line,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Splits an address of the form `<multiaddr>/p2p/<peer id>`.
+fn parse_peer_addr(line: &str) -> Result<(Multiaddr, PeerId), io::Error> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                                    format!("invalid address of the remote peer: {:?}", line));
+    let pos = line.rfind("/p2p/").or_else(|| line.rfind("/ipfs/")).ok_or_else(invalid)?;
+    let addr = line[..pos].trim().parse().map_err(|_| invalid())?;
+    let peer_id = line[pos..].splitn(3, '/').nth(2).ok_or_else(invalid)?
+        .trim().parse().map_err(|_| invalid())?;
+    Ok((addr, peer_id))
+}
@@ -0,0 +1,53 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance of the identify protocol.
+
+use driver::{self, Muxer};
+use libp2p::Transport;
+use libp2p::identify::{IdentifyOutput, IdentifyProtocolConfig};
+use tokio_current_thread;
+use transport;
+
+#[test]
+fn identify_remote() {
+    let peer = match driver::remote_peer(Muxer::Mplex) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let transport = transport::build_transport(transport::local_key(), Muxer::Mplex)
+        .into_connection_reuse()
+        .map(|(_, substream), _| substream)
+        .with_upgrade(IdentifyProtocolConfig::new());
+    let dial = transport.dial(peer.addr().clone())
+        .unwrap_or_else(|_| panic!("unsupported address"));
+
+    match tokio_current_thread::block_on_all(dial).expect("identify failed") {
+        IdentifyOutput::RemoteInfo { info, observed_addr } => {
+            assert_eq!(&info.public_key.into_peer_id(), peer.peer_id());
+            assert!(info.protocols.iter().any(|p| p == "/ipfs/id/1.0.0"));
+            assert!(info.protocols.iter().any(|p| p == "/ipfs/ping/1.0.0"));
+            assert!(!info.listen_addrs.is_empty());
+            assert!(observed_addr.to_string().starts_with("/ip4/127.0.0.1/tcp/"));
+        },
+        IdentifyOutput::Sender { .. } => panic!("the dialer received a sender"),
+    }
+}
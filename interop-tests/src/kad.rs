@@ -0,0 +1,61 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance of the Kademlia protocol.
+
+use driver::{self, Muxer};
+use futures::prelude::*;
+use libp2p::Transport;
+use libp2p::kad::{KadConnecConfig, KadPeer};
+use std::io;
+use tokio_current_thread;
+use transport;
+
+#[test]
+fn find_node() {
+    let peer = match driver::remote_peer(Muxer::Mplex) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let key = transport::local_key();
+    let local_id = key.to_public_key().into_peer_id();
+    let transport = transport::build_transport(key, Muxer::Mplex)
+        .into_connection_reuse()
+        .map(|(_, substream), _| substream)
+        .with_upgrade(KadConnecConfig::new());
+    let dial = transport.dial(peer.addr().clone())
+        .unwrap_or_else(|_| panic!("unsupported address"))
+        .and_then(move |(controller, requests)| {
+            // The stream of requests has to be polled in order for the responses to be received.
+            let requests = requests.for_each(|_| Ok(()))
+                .and_then(|()| -> Result<Vec<KadPeer>, io::Error> {
+                    Err(io::ErrorKind::UnexpectedEof.into())
+                });
+            controller.find_node(&local_id)
+                .select(requests)
+                .map(|(peers, _)| peers)
+                .map_err(|(err, _)| err)
+        });
+
+    let peers = tokio_current_thread::block_on_all(dial).expect("FIND_NODE failed");
+    // The remote doesn't know any other node, and doesn't return itself.
+    assert!(peers.iter().all(|p| &p.node_id != peer.peer_id()));
+}
@@ -0,0 +1,69 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance tests of this crate against other implementations of libp2p.
+//!
+//! Each test starts a remote peer written with another implementation, such as go-libp2p or
+//! js-libp2p, then connects to it and checks that a protocol behaves as expected on both sides.
+//! The suites cover secio, mplex, yamux, identify, ping and Kademlia.
+//!
+//! The remote peer is started by a driver, configured with the `LIBP2P_INTEROP_PEER`
+//! environment variable:
+//!
+//! - `exec:<path>` runs the executable at the given path.
+//! - `docker:<image>` runs the given Docker image, with the host network.
+//!
+//! If the variable isn't set, the tests do nothing. The `peers/go` directory contains a
+//! go-libp2p peer and its Dockerfile:
+//!
+//! ```ignore
+//! docker build -t libp2p-interop-go interop-tests/peers/go
+//! LIBP2P_INTEROP_PEER=docker:libp2p-interop-go cargo test -p interop-tests
+//! ```
+//!
+//! # Remote peers
+//!
+//! A remote peer receives the following arguments:
+//!
+//! - `--listen <multiaddr>`: the address to listen on, with an IPv4 address and a TCP port.
+//! - `--muxer <name>`: the multiplexing protocol to use, either `mplex` or `yamux`.
+//!
+//! The peer uses secio for encryption and supports identify, ping and Kademlia. Once it is
+//! listening, it prints its address followed by `/p2p/<peer id>` on a line of its standard
+//! output. It then reads commands from its standard input, one per line, and answers each of
+//! them on a line of its standard output, with either `ok` or `error <message>`:
+//!
+//! - `ping <multiaddr>/p2p/<peer id>`: dials the given peer and pings it.
+//!
+//! The peer exits when its standard input is closed.
+
+#![cfg(test)]
+
+extern crate futures;
+extern crate libp2p;
+extern crate tokio_current_thread;
+
+mod driver;
+mod identify;
+mod kad;
+mod muxer;
+mod ping;
+mod secio;
+mod transport;
@@ -0,0 +1,78 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance of the multiplexing protocols.
+
+use driver::{self, Muxer};
+use futures::{future, prelude::*};
+use libp2p::Transport;
+use libp2p::ping::{Ping, PingOutput};
+use tokio_current_thread;
+use transport;
+
+/// Number of substreams to open at the same time.
+const SUBSTREAMS: u32 = 10;
+
+/// Opens a substream, then many substreams at the same time over the same connection, and pings
+/// the remote on each of them.
+fn concurrent_substreams(muxer: Muxer) {
+    let peer = match driver::remote_peer(muxer) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let transport = transport::build_transport(transport::local_key(), muxer)
+        .into_connection_reuse()
+        .map(|(_, substream), _| substream)
+        .with_upgrade(Ping::<u32>::default());
+    let addr = peer.addr().clone();
+    let ping = move |n| {
+        transport.clone().dial(addr.clone())
+            .unwrap_or_else(|_| panic!("unsupported address"))
+            .and_then(move |out| match out {
+                PingOutput::Pinger(mut pinger) => {
+                    pinger.ping(n);
+                    pinger.into_future().map(|(pong, _)| pong).map_err(|(err, _)| err)
+                },
+                PingOutput::Ponger(_) => panic!("the dialer received a ponger"),
+            })
+    };
+
+    // The first substream opens the connection, which the other ones reuse.
+    let first = ping(0);
+    let future = first.and_then(move |first| {
+        future::join_all((1..SUBSTREAMS).map(ping).collect::<Vec<_>>())
+            .map(move |rest| (first, rest))
+    });
+
+    let (first, rest) = tokio_current_thread::block_on_all(future).expect("ping failed");
+    assert_eq!(first, Some(0));
+    assert_eq!(rest, (1..SUBSTREAMS).map(Some).collect::<Vec<_>>());
+}
+
+#[test]
+fn mplex() {
+    concurrent_substreams(Muxer::Mplex);
+}
+
+#[test]
+fn yamux() {
+    concurrent_substreams(Muxer::Yamux);
+}
@@ -0,0 +1,95 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance of the ping protocol.
+
+use driver::{self, Muxer};
+use futures::{prelude::*, sync::oneshot};
+use libp2p::Transport;
+use libp2p::core::swarm;
+use libp2p::ping::{Ping, PingOutput};
+use std::{io, thread};
+use tokio_current_thread;
+use transport;
+
+/// Number of pings to send.
+const PINGS: u32 = 5;
+
+#[test]
+fn ping_remote() {
+    let peer = match driver::remote_peer(Muxer::Mplex) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let transport = transport::build_transport(transport::local_key(), Muxer::Mplex)
+        .into_connection_reuse()
+        .map(|(_, substream), _| substream)
+        .with_upgrade(Ping::<u32>::default());
+    let dial = transport.dial(peer.addr().clone())
+        .unwrap_or_else(|_| panic!("unsupported address"))
+        .and_then(|out| match out {
+            PingOutput::Pinger(mut pinger) => {
+                for n in 0..PINGS {
+                    pinger.ping(n);
+                }
+                pinger.take(u64::from(PINGS)).collect()
+            },
+            PingOutput::Ponger(_) => panic!("the dialer received a ponger"),
+        });
+
+    let pongs = tokio_current_thread::block_on_all(dial).expect("ping failed");
+    assert_eq!(pongs, (0..PINGS).collect::<Vec<_>>());
+}
+
+#[test]
+fn ping_from_remote() {
+    let mut peer = match driver::remote_peer(Muxer::Mplex) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let key = transport::local_key();
+    let local_id = key.to_public_key().into_peer_id();
+    let transport = transport::build_transport(key, Muxer::Mplex)
+        .into_connection_reuse()
+        .map(|(_, substream), _| substream)
+        .with_upgrade(Ping::<()>::default());
+    let (controller, swarm_future) = swarm(transport, |out, _| match out {
+        PingOutput::Ponger(ponger) => ponger,
+        PingOutput::Pinger(_) => panic!("the listener received a pinger"),
+    });
+    let addr = controller.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap_or_else(|_| panic!("unsupported address"));
+
+    // Waiting for the answer of the remote blocks, while the swarm has to keep running in order
+    // to answer the pings.
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = tx.send(peer.ping(&addr, &local_id));
+    });
+    let swarm_future = swarm_future.for_each(|_| Ok(()))
+        .then(|_| -> Result<io::Result<()>, ()> { Err(()) });
+    let result = tokio_current_thread::block_on_all(rx.map_err(|_| ()).select(swarm_future))
+        .map(|(result, _)| result)
+        .map_err(|_| ())
+        .expect("the swarm stopped before the remote finished pinging");
+    result.expect("the remote failed to ping");
+}
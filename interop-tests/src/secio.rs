@@ -0,0 +1,55 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conformance of the secio handshake.
+
+use driver::{self, Muxer};
+use futures::prelude::*;
+use libp2p::Transport;
+use libp2p::secio::{Cipher, SecioConfig};
+use libp2p::tcp::TcpConfig;
+use tokio_current_thread;
+use transport;
+
+/// Performs a secio handshake with a remote peer, proposing only the given ciphers.
+fn handshake(ciphers: &[Cipher]) {
+    let peer = match driver::remote_peer(Muxer::Mplex) {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let transport = TcpConfig::new()
+        .with_upgrade(SecioConfig::new(transport::local_key()).ciphers(ciphers));
+    let dial = transport.dial(peer.addr().clone())
+        .unwrap_or_else(|_| panic!("unsupported address"))
+        .map(|out| out.remote_key.into_peer_id());
+    let remote_id = tokio_current_thread::block_on_all(dial).expect("secio handshake failed");
+    assert_eq!(&remote_id, peer.peer_id());
+}
+
+#[test]
+fn aes_128() {
+    handshake(&[Cipher::Aes128]);
+}
+
+#[test]
+fn aes_256() {
+    handshake(&[Cipher::Aes256]);
+}
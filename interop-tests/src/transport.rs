@@ -0,0 +1,64 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Transports used to connect to the remote peers.
+
+use driver::Muxer;
+use libp2p::{PeerId, Transport, mplex, yamux};
+use libp2p::core::{either, upgrade, transport::boxed::Boxed};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox};
+use libp2p::secio::{SecioConfig, SecioKeyPair};
+use libp2p::tcp::TcpConfig;
+use libp2p::transport_timeout::TransportTimeout;
+use std::time::Duration;
+
+/// Maximum time to establish a connection with a remote peer, in seconds.
+const CONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// Generates a new identity for the local node.
+pub fn local_key() -> SecioKeyPair {
+    SecioKeyPair::ed25519_generated().expect("failed to generate an ed25519 key")
+}
+
+/// Builds a transport that encrypts the connections with secio and multiplexes them with the
+/// given protocol. Produces the identity of the remote and the multiplexer.
+///
+/// The other multiplexing protocol isn't proposed, so that the negotiation fails if the remote
+/// doesn't support the requested one.
+pub fn build_transport(key: SecioKeyPair, muxer: Muxer) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let base = TcpConfig::new()
+        .with_upgrade(SecioConfig::new(key))
+        .and_then(move |out, endpoint, client_addr| {
+            let mut mplex = upgrade::toggleable(
+                upgrade::map(mplex::MplexConfig::new(), either::EitherOutput::First));
+            let mut yamux = upgrade::toggleable(
+                upgrade::map(yamux::Config::default(), either::EitherOutput::Second));
+            match muxer {
+                Muxer::Mplex => yamux.disable(),
+                Muxer::Yamux => mplex.disable(),
+            }
+            let peer_id = out.remote_key.into_peer_id();
+            let upgrade = upgrade::map(upgrade::or(mplex, yamux), move |muxer| (peer_id, muxer));
+            upgrade::apply(out.stream, upgrade, endpoint, client_addr)
+        })
+        .map(|(id, muxer), _| (id, muxer.boxed()));
+
+    TransportTimeout::new(base, Duration::from_secs(CONNECT_TIMEOUT_SECS)).boxed()
+}
@@ -0,0 +1,86 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Control API of a running node.
+//!
+//! The `ControlServer` accepts TCP connections from orchestration tools, and lets them drive the
+//! node with JSON-RPC 2.0 requests, one per line. This makes it possible to mix real nodes and
+//! simulated ones in the same experiment, and to control all of them from the same scripts.
+//!
+//! The server doesn't know anything about the node. It implements `Stream` and produces a
+//! `ControlRequest` for each request of a client, and it is up to the user to perform the
+//! operation and to answer with the `ControlResponder` of the request.
+//!
+//! | Method      | Parameters                                   | Request      |
+//! |-------------|----------------------------------------------|--------------|
+//! | `peers`     |                                              | `ListPeers`  |
+//! | `dial`      | `address`                                    | `Dial`       |
+//! | `ban`       | `peer_id`, `duration_secs` (optional)        | `Ban`        |
+//! | `unban`     | `peer_id`                                    | `Unban`      |
+//! | `dht_query` | `key`                                        | `DhtQuery`   |
+//! | `publish`   | `topic`, `data`                              | `Publish`    |
+//! | `metrics`   |                                              | `Metrics`    |
+//!
+//! The parameters are passed by name, and are all strings except `duration_secs`.
+//!
+//! > **Note**: Only JSON-RPC is supported. There is no gRPC transport.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate libp2p_control;
+//!
+//! use futures::prelude::*;
+//! use libp2p_control::{ControlRequest, ControlServer};
+//!
+//! # fn main() {
+//! let server = ControlServer::bind(&"127.0.0.1:5001".parse().unwrap()).unwrap();
+//! let serve = server.for_each(|request| {
+//!     match request {
+//!         // Answer with the peers of the swarm.
+//!         ControlRequest::ListPeers { responder } => responder.peers(None),
+//!         // Call `swarm.dial(address)`.
+//!         ControlRequest::Dial { responder, .. } => responder.ok(),
+//!         other => other.into_responder().error("not supported by this node"),
+//!     }
+//!     Ok(())
+//! });
+//! # drop(serve);
+//! # }
+//! ```
+
+extern crate futures;
+extern crate libp2p_core as swarm;
+extern crate libp2p_metrics;
+#[macro_use]
+extern crate log;
+extern crate multiaddr;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio_codec;
+extern crate tokio_tcp;
+
+mod request;
+mod server;
+
+pub use self::request::{ControlError, ControlRequest, ControlResponder};
+pub use self::server::ControlServer;
@@ -0,0 +1,333 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Requests received by the control server, and how to answer them.
+
+use futures::sync::oneshot;
+use libp2p_metrics::Registry;
+use multiaddr::Multiaddr;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::Duration;
+use swarm::PeerId;
+
+/// Request received from a client of the control server.
+///
+/// Each request contains a `ControlResponder` that must be used to answer it. If the responder
+/// is dropped, the client receives an internal error.
+#[derive(Debug)]
+pub enum ControlRequest {
+    /// List the peers we are connected to. Answer with `ControlResponder::peers`.
+    ListPeers {
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Dial an address.
+    Dial {
+        /// The address to dial.
+        address: Multiaddr,
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Ban a peer, see `Swarm::ban_peer`.
+    Ban {
+        /// The peer to ban.
+        peer_id: PeerId,
+        /// How long to ban the peer for. `None` means forever.
+        duration: Option<Duration>,
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Lift the ban of a peer, see `Swarm::unban_peer`.
+    Unban {
+        /// The peer to unban.
+        peer_id: PeerId,
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Query the DHT for a key. The meaning of the key, and whether the result is a value or a
+    /// list of peers, depends on the DHT in use.
+    DhtQuery {
+        /// The key to look for.
+        key: Vec<u8>,
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Publish a message on a pubsub topic.
+    Publish {
+        /// Name of the topic.
+        topic: String,
+        /// Content of the message.
+        data: Vec<u8>,
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+
+    /// Dump the metrics of the node. Answer with `ControlResponder::metrics`.
+    Metrics {
+        /// Object to use to respond to the request.
+        responder: ControlResponder,
+    },
+}
+
+impl ControlRequest {
+    /// Builds a request from the method and the parameters of a JSON-RPC request.
+    pub(crate) fn from_rpc(method: &str, params: &Map<String, Value>, responder: ControlResponder)
+        -> Result<ControlRequest, (ControlError, ControlResponder)>
+    {
+        macro_rules! try_param {
+            ($expr:expr) => {
+                match $expr {
+                    Ok(value) => value,
+                    Err(err) => return Err((err, responder)),
+                }
+            };
+        }
+
+        let request = match method {
+            "peers" => ControlRequest::ListPeers { responder },
+            "dial" => {
+                let address = try_param!(string_param(params, "address"));
+                let address = try_param!(address.parse::<Multiaddr>()
+                    .map_err(|_| ControlError::invalid_params(format!("invalid multiaddr {:?}", address))));
+                ControlRequest::Dial { address, responder }
+            },
+            "ban" => {
+                let peer_id = try_param!(peer_id_param(params));
+                let duration = match params.get("duration_secs") {
+                    None | Some(&Value::Null) => None,
+                    Some(value) => Some(Duration::from_secs(try_param!(value.as_u64()
+                        .ok_or_else(|| ControlError::invalid_params("`duration_secs` must be a non-negative integer"))))),
+                };
+                ControlRequest::Ban { peer_id, duration, responder }
+            },
+            "unban" => {
+                let peer_id = try_param!(peer_id_param(params));
+                ControlRequest::Unban { peer_id, responder }
+            },
+            "dht_query" => {
+                let key = try_param!(string_param(params, "key")).as_bytes().to_vec();
+                ControlRequest::DhtQuery { key, responder }
+            },
+            "publish" => {
+                let topic = try_param!(string_param(params, "topic")).to_owned();
+                let data = try_param!(string_param(params, "data")).as_bytes().to_vec();
+                ControlRequest::Publish { topic, data, responder }
+            },
+            "metrics" => ControlRequest::Metrics { responder },
+            other => {
+                let err = ControlError::new(ControlError::METHOD_NOT_FOUND, format!("unknown method {:?}", other));
+                return Err((err, responder));
+            },
+        };
+
+        Ok(request)
+    }
+
+    /// Returns the name of the JSON-RPC method of the request.
+    pub fn method(&self) -> &'static str {
+        match *self {
+            ControlRequest::ListPeers { .. } => "peers",
+            ControlRequest::Dial { .. } => "dial",
+            ControlRequest::Ban { .. } => "ban",
+            ControlRequest::Unban { .. } => "unban",
+            ControlRequest::DhtQuery { .. } => "dht_query",
+            ControlRequest::Publish { .. } => "publish",
+            ControlRequest::Metrics { .. } => "metrics",
+        }
+    }
+
+    /// Returns the responder of the request, for example to answer that it isn't supported.
+    pub fn into_responder(self) -> ControlResponder {
+        match self {
+            ControlRequest::ListPeers { responder } => responder,
+            ControlRequest::Dial { responder, .. } => responder,
+            ControlRequest::Ban { responder, .. } => responder,
+            ControlRequest::Unban { responder, .. } => responder,
+            ControlRequest::DhtQuery { responder, .. } => responder,
+            ControlRequest::Publish { responder, .. } => responder,
+            ControlRequest::Metrics { responder } => responder,
+        }
+    }
+}
+
+/// Returns the string parameter `name`.
+fn string_param<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a str, ControlError> {
+    params.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ControlError::invalid_params(format!("missing string parameter `{}`", name)))
+}
+
+/// Returns the `peer_id` parameter.
+fn peer_id_param(params: &Map<String, Value>) -> Result<PeerId, ControlError> {
+    let peer_id = string_param(params, "peer_id")?;
+    peer_id.parse()
+        .map_err(|_| ControlError::invalid_params(format!("invalid peer id {:?}", peer_id)))
+}
+
+/// Object used to answer a `ControlRequest`.
+pub struct ControlResponder {
+    inner: oneshot::Sender<Result<Value, ControlError>>,
+}
+
+impl ControlResponder {
+    /// Builds a responder, and the receiver of its answer.
+    pub(crate) fn new() -> (ControlResponder, oneshot::Receiver<Result<Value, ControlError>>) {
+        let (tx, rx) = oneshot::channel();
+        (ControlResponder { inner: tx }, rx)
+    }
+
+    /// Answers the request with the `result` member of the JSON-RPC response, or with an error.
+    ///
+    /// Does nothing if the client has disconnected.
+    #[inline]
+    pub fn respond(self, result: Result<Value, ControlError>) {
+        let _ = self.inner.send(result);
+    }
+
+    /// Answers that the request succeeded, without any result.
+    #[inline]
+    pub fn ok(self) {
+        self.respond(Ok(Value::Null))
+    }
+
+    /// Answers that the request failed.
+    #[inline]
+    pub fn error<S: Into<String>>(self, message: S) {
+        self.respond(Err(ControlError::new(ControlError::REQUEST_FAILED, message)))
+    }
+
+    /// Answers a `ListPeers` request with a list of peers.
+    pub fn peers<'a, I>(self, peers: I)
+    where I: IntoIterator<Item = &'a PeerId>
+    {
+        let peers = peers.into_iter().map(|peer| Value::String(peer.to_base58())).collect();
+        self.respond(Ok(Value::Array(peers)))
+    }
+
+    /// Answers a `Metrics` request with the content of a registry, in the Prometheus text
+    /// exposition format.
+    #[inline]
+    pub fn metrics(self, registry: &Registry) {
+        self.respond(Ok(Value::String(registry.encode())))
+    }
+}
+
+impl fmt::Debug for ControlResponder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ControlResponder").finish()
+    }
+}
+
+/// Error sent back to a client, as the `error` member of a JSON-RPC response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlError {
+    /// Code of the error.
+    pub code: i64,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl ControlError {
+    /// The request is not valid JSON.
+    pub const PARSE_ERROR: i64 = -32700;
+    /// The request is not a valid JSON-RPC request.
+    pub const INVALID_REQUEST: i64 = -32600;
+    /// The method doesn't exist.
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    /// The parameters of the method are invalid.
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The responder was dropped without answering.
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// The node couldn't perform the request. Used by `ControlResponder::error`.
+    pub const REQUEST_FAILED: i64 = -32000;
+
+    /// Builds an error.
+    #[inline]
+    pub fn new<S: Into<String>>(code: i64, message: S) -> ControlError {
+        ControlError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Builds an `INVALID_PARAMS` error.
+    #[inline]
+    fn invalid_params<S: Into<String>>(message: S) -> ControlError {
+        ControlError::new(ControlError::INVALID_PARAMS, message)
+    }
+
+    /// Serializes the error as the `error` member of a JSON-RPC response.
+    pub(crate) fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControlError, ControlRequest, ControlResponder};
+    use serde_json::Value;
+    use std::time::Duration;
+    use swarm::PublicKey;
+
+    fn parse(method: &str, params: Value) -> Result<ControlRequest, ControlError> {
+        let (responder, _rx) = ControlResponder::new();
+        let params = params.as_object().unwrap().clone();
+        ControlRequest::from_rpc(method, &params, responder).map_err(|(err, _)| err)
+    }
+
+    #[test]
+    fn parse_requests() {
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+
+        match parse("ban", json!({ "peer_id": peer_id.to_base58(), "duration_secs": 60 })) {
+            Ok(ControlRequest::Ban { peer_id: banned, duration, .. }) => {
+                assert_eq!(banned, peer_id);
+                assert_eq!(duration, Some(Duration::from_secs(60)));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match parse("publish", json!({ "topic": "chat", "data": "hello" })) {
+            Ok(ControlRequest::Publish { topic, data, .. }) => {
+                assert_eq!(topic, "chat");
+                assert_eq!(data, b"hello".to_vec());
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert_eq!(parse("dial", json!({ "address": "not a multiaddr" })).unwrap_err().code,
+                   ControlError::INVALID_PARAMS);
+        assert_eq!(parse("ban", json!({})).unwrap_err().code, ControlError::INVALID_PARAMS);
+        assert_eq!(parse("reboot", json!({})).unwrap_err().code, ControlError::METHOD_NOT_FOUND);
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! TCP server of the control API.
+
+use futures::{prelude::*, stream::FuturesUnordered, sync::oneshot};
+use request::{ControlError, ControlRequest, ControlResponder};
+use serde_json::{self, Value};
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+use tokio_codec::{Framed, LinesCodec};
+use tokio_tcp::{Incoming, TcpListener, TcpStream};
+
+/// Maximum length of a request, in bytes. Longer lines close the connection.
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// Server of the control API.
+///
+/// Implements `Stream` and produces the requests of all the clients. The server must be polled
+/// for the responses to be sent, even after all the requests have been produced.
+pub struct ControlServer {
+    /// Accepts the clients.
+    incoming: Incoming,
+    /// Address the server listens on.
+    local_addr: SocketAddr,
+    /// Connected clients.
+    clients: Vec<Client>,
+}
+
+/// Connection of a client.
+struct Client {
+    /// The socket, framed as lines.
+    socket: Framed<TcpStream, LinesCodec>,
+    /// Responses to the requests produced by the server, completed by the user.
+    pending: FuturesUnordered<PendingResponse>,
+    /// Responses ready to be written to the socket.
+    to_send: VecDeque<String>,
+    /// False once the client has stopped sending requests.
+    reading: bool,
+}
+
+impl ControlServer {
+    /// Starts listening on `addr`.
+    ///
+    /// > **Note**: Anyone that can connect to the server can control the node. Make sure to
+    /// >           listen on an address that only the orchestration tools can reach, such as
+    /// >           `127.0.0.1`.
+    pub fn bind(addr: &SocketAddr) -> Result<ControlServer, IoError> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        debug!("Control server listening on {}", local_addr);
+        Ok(ControlServer {
+            incoming: listener.incoming(),
+            local_addr,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Returns the address the server listens on. Useful when binding to port 0.
+    #[inline]
+    pub fn local_addr(&self) -> &SocketAddr {
+        &self.local_addr
+    }
+
+    /// Returns the number of connected clients.
+    #[inline]
+    pub fn num_clients(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+impl Stream for ControlServer {
+    type Item = ControlRequest;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.incoming.poll() {
+                Ok(Async::Ready(Some(socket))) => {
+                    if let Ok(addr) = socket.peer_addr() {
+                        debug!("New control client {}", addr);
+                    }
+                    self.clients.push(Client {
+                        socket: Framed::new(socket, LinesCodec::new_with_max_length(MAX_REQUEST_LEN)),
+                        pending: FuturesUnordered::new(),
+                        to_send: VecDeque::new(),
+                        reading: true,
+                    });
+                },
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(err) => {
+                    // Errors of `accept` concern a single connection, such as running out of file
+                    // descriptors. The listener is still usable.
+                    debug!("Error while accepting a control client: {:?}", err);
+                    break;
+                },
+            }
+        }
+
+        let mut request = None;
+        for n in (0 .. self.clients.len()).rev() {
+            let mut client = self.clients.swap_remove(n);
+            match client.poll(request.is_none()) {
+                Ok(Some(new_request)) => {
+                    request = Some(new_request);
+                    self.clients.push(client);
+                },
+                Ok(None) if client.is_finished() => debug!("Control client closed"),
+                Ok(None) => self.clients.push(client),
+                Err(err) => debug!("Control client closed with an error: {:?}", err),
+            }
+        }
+
+        match request {
+            Some(request) => Ok(Async::Ready(Some(request))),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Client {
+    /// Reads the next request if `read` is true, and writes the responses that are ready.
+    fn poll(&mut self, read: bool) -> Result<Option<ControlRequest>, IoError> {
+        let mut request = None;
+        while read && self.reading {
+            let line = match self.socket.poll()? {
+                Async::Ready(Some(line)) => line,
+                Async::Ready(None) => {
+                    self.reading = false;
+                    break;
+                },
+                Async::NotReady => break,
+            };
+
+            match parse_request(&line) {
+                Ok(None) => (),
+                Ok(Some((id, new_request, response))) => {
+                    self.pending.push(PendingResponse { id, inner: response });
+                    request = Some(new_request);
+                    break;
+                },
+                Err((id, err)) => self.to_send.push_back(response_line(id, Err(err))),
+            }
+        }
+
+        loop {
+            match self.pending.poll() {
+                Ok(Async::Ready(Some(line))) => self.to_send.push_back(line),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(()) => unreachable!("PendingResponse never errors"),
+            }
+        }
+
+        while let Some(line) = self.to_send.pop_front() {
+            if let AsyncSink::NotReady(line) = self.socket.start_send(line)? {
+                self.to_send.push_front(line);
+                break;
+            }
+        }
+        self.socket.poll_complete()?;
+
+        Ok(request)
+    }
+
+    /// Returns true if the client has stopped sending requests and all the responses have been
+    /// sent.
+    fn is_finished(&self) -> bool {
+        !self.reading && self.pending.is_empty() && self.to_send.is_empty()
+    }
+}
+
+/// Parses a line sent by a client. Returns `None` for empty lines.
+fn parse_request(line: &str)
+    -> Result<Option<(Value, ControlRequest, oneshot::Receiver<Result<Value, ControlError>>)>, (Value, ControlError)>
+{
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let request: Value = serde_json::from_str(line)
+        .map_err(|err| (Value::Null, ControlError::new(ControlError::PARSE_ERROR, err.to_string())))?;
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let invalid = |message: &str| (id.clone(), ControlError::new(ControlError::INVALID_REQUEST, message));
+
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid("expected \"jsonrpc\": \"2.0\""));
+    }
+    let method = request.get("method").and_then(Value::as_str).ok_or_else(|| invalid("missing method"))?;
+    let params = match request.get("params") {
+        Some(&Value::Object(ref params)) => params.clone(),
+        None => Default::default(),
+        Some(_) => return Err(invalid("params must be an object")),
+    };
+
+    let (responder, response) = ControlResponder::new();
+    match ControlRequest::from_rpc(method, &params, responder) {
+        Ok(request) => Ok(Some((id, request, response))),
+        Err((err, _)) => Err((id, err)),
+    }
+}
+
+/// Serializes a JSON-RPC response.
+fn response_line(id: Value, result: Result<Value, ControlError>) -> String {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() }),
+    };
+    response.to_string()
+}
+
+/// Response to a request, waiting for the user to call the responder.
+struct PendingResponse {
+    /// Identifier of the request.
+    id: Value,
+    /// Receives the answer of the user.
+    inner: oneshot::Receiver<Result<Value, ControlError>>,
+}
+
+impl Future for PendingResponse {
+    type Item = String;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<String, ()> {
+        let result = match self.inner.poll() {
+            Ok(Async::Ready(result)) => result,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(oneshot::Canceled) => Err(ControlError::new(ControlError::INTERNAL_ERROR,
+                                                            "the request was dropped without an answer")),
+        };
+        Ok(Async::Ready(response_line(self.id.take(), result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tokio_current_thread;
+
+    use futures::{future, prelude::*};
+    use request::ControlRequest;
+    use serde_json::{self, Value};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use super::ControlServer;
+    use swarm::PublicKey;
+
+    #[test]
+    fn serves_requests() {
+        let mut server = ControlServer::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = *server.local_addr();
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+
+        let client = thread::spawn(move || {
+            let mut socket = TcpStream::connect(addr).unwrap();
+            socket.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"peers\"}\n").unwrap();
+            socket.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"reboot\"}\n").unwrap();
+            socket.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"dial\",\"params\":{\"address\":\"/ip4/1.2.3.4/tcp/5\"}}\n").unwrap();
+
+            // Dropping the socket once the responses are read closes the connection.
+            let mut responses = BufReader::new(socket).lines()
+                .take(3)
+                .map(|line| serde_json::from_str::<Value>(&line.unwrap()).unwrap())
+                .collect::<Vec<_>>();
+            responses.sort_by_key(|response| response["id"].as_u64());
+            responses
+        });
+
+        let listed_peer = peer_id.clone();
+        let mut answered = 0;
+        let serve = future::poll_fn(move || -> Poll<(), ()> {
+            while let Async::Ready(Some(request)) = server.poll().unwrap() {
+                match request {
+                    ControlRequest::ListPeers { responder } => responder.peers(Some(&listed_peer)),
+                    ControlRequest::Dial { address, responder } => {
+                        assert_eq!(address, "/ip4/1.2.3.4/tcp/5".parse().unwrap());
+                        responder.error("unreachable");
+                    },
+                    other => panic!("unexpected {:?}", other),
+                }
+                answered += 1;
+            }
+
+            if answered == 2 && server.num_clients() == 0 {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        });
+
+        tokio_current_thread::block_on_all(serve).unwrap();
+        let responses = client.join().unwrap();
+        assert_eq!(responses[0]["result"], json!([peer_id.to_base58()]));
+        assert_eq!(responses[1]["error"]["code"], json!(-32601));
+        assert_eq!(responses[2]["error"]["message"], json!("unreachable"));
+    }
+}
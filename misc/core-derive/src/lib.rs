@@ -0,0 +1,330 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Provides `#[derive(NetworkBehaviour)]`, which implements the `NetworkBehaviour` trait of
+//! `libp2p-core` on a struct by combining the behaviours of its fields.
+//!
+//! The connection handlers of all the fields are combined with `ConnectionHandler::select`, and
+//! the events produced by a handler are passed to the field that created it. The actions of the
+//! fields are returned by the generated `poll`, except for the events they generate, which are
+//! passed to the `NetworkBehaviourEventProcess` implementation of the struct for the `OutEvent`
+//! of the field.
+//!
+//! The following attributes are supported:
+//!
+//! - `#[behaviour(out_event = "Type")]` on the struct sets the `OutEvent` of the generated
+//!   implementation. Defaults to `()`.
+//! - `#[behaviour(poll_method = "name")]` on the struct makes the generated `poll` call
+//!   `self.name()` first. This method must have the signature
+//!   `fn name<TInEvent>(&mut self) -> Async<NetworkBehaviourAction<TInEvent, OutEvent>>`, and is
+//!   the only way for the struct to generate events.
+//! - `#[behaviour(ignore)]` on a field excludes it from the composition.
+//!
+//! > **Note**: The generated code refers to the `libp2p` facade crate, which must therefore be
+//! >           a dependency of the crate that uses the derive.
+
+#![recursion_limit = "256"]
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+use proc_macro2::TokenStream;
+use syn::{Attribute, Data, DataStruct, DeriveInput, Field, Ident, Lit, Meta, NestedMeta, Type};
+
+/// Generates the implementation of `NetworkBehaviour`.
+#[proc_macro_derive(NetworkBehaviour, attributes(behaviour))]
+pub fn derive_network_behaviour(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let result = match ast.data {
+        Data::Struct(ref data) => build_struct(&ast, data),
+        Data::Enum(_) | Data::Union(_) => Err(syn::Error::new(
+            ast.ident.span(),
+            "#[derive(NetworkBehaviour)] is only supported on structs",
+        )),
+    };
+
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Returns the content of the `#[behaviour(...)]` attributes.
+fn behaviour_attrs(attrs: &[Attribute]) -> syn::Result<Vec<Meta>> {
+    let mut out = Vec::new();
+
+    for attr in attrs {
+        let meta = match attr.parse_meta() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+
+        if let Meta::List(list) = meta {
+            if list.ident != "behaviour" {
+                continue;
+            }
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(meta) => out.push(meta),
+                    NestedMeta::Literal(lit) => {
+                        return Err(syn::Error::new_spanned(lit, "expected an attribute"));
+                    },
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Returns true if the field has the `#[behaviour(ignore)]` attribute.
+fn is_ignored(field: &Field) -> syn::Result<bool> {
+    let mut ignored = false;
+
+    for meta in behaviour_attrs(&field.attrs)? {
+        match meta {
+            Meta::Word(ref word) if word == "ignore" => ignored = true,
+            other => {
+                return Err(syn::Error::new_spanned(other, "unknown behaviour attribute on field"));
+            },
+        }
+    }
+
+    Ok(ignored)
+}
+
+/// Wraps `inner`, which corresponds to the field at position `index` out of `num`, in the
+/// nesting of `EitherOutput` used by the combined handler.
+fn wrap_nested(index: usize, num: usize, inner: TokenStream) -> TokenStream {
+    let either_output = quote!{ ::libp2p::core::either::EitherOutput };
+
+    let mut out = if index == num - 1 {
+        inner
+    } else {
+        quote!{ #either_output::First(#inner) }
+    };
+
+    for _ in 0 .. index {
+        out = quote!{ #either_output::Second(#out) };
+    }
+
+    out
+}
+
+/// Generates the implementation for a struct.
+fn build_struct(ast: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+
+    let nb = quote!{ ::libp2p::core::nodes::behaviour::NetworkBehaviour };
+    let nbep = quote!{ ::libp2p::core::nodes::behaviour::NetworkBehaviourEventProcess };
+    let action = quote!{ ::libp2p::core::nodes::behaviour::NetworkBehaviourAction };
+    let handler = quote!{ ::libp2p::core::nodes::connection_handler::ConnectionHandler };
+    let handler_select = quote!{ ::libp2p::core::nodes::connection_handler::ConnectionHandlerSelect };
+    let connected_point = quote!{ ::libp2p::core::nodes::swarm::ConnectedPoint };
+    let poll_params = quote!{ ::libp2p::core::nodes::behaviour::PollParameters };
+    let dial_failure_reason = quote!{ ::libp2p::core::nodes::behaviour::DialFailureReason };
+    let multiaddr = quote!{ ::libp2p::core::Multiaddr };
+    let peer_id = quote!{ ::libp2p::core::PeerId };
+    let async_ = quote!{ ::libp2p::futures::Async };
+
+    // Attributes of the struct.
+    let mut out_event: Type = parse_quote!{ () };
+    let mut poll_method: Option<Ident> = None;
+    for meta in behaviour_attrs(&ast.attrs)? {
+        match meta {
+            Meta::NameValue(ref m) if m.ident == "out_event" => match m.lit {
+                Lit::Str(ref s) => out_event = s.parse()?,
+                ref other => return Err(syn::Error::new_spanned(other, "expected a string")),
+            },
+            Meta::NameValue(ref m) if m.ident == "poll_method" => match m.lit {
+                Lit::Str(ref s) => poll_method = Some(s.parse()?),
+                ref other => return Err(syn::Error::new_spanned(other, "expected a string")),
+            },
+            other => {
+                return Err(syn::Error::new_spanned(other, "unknown behaviour attribute"));
+            },
+        }
+    }
+
+    // Fields that take part in the composition, with the tokens that access them.
+    let mut fields: Vec<(TokenStream, &Type)> = Vec::new();
+    for (index, field) in data.fields.iter().enumerate() {
+        if is_ignored(field)? {
+            continue;
+        }
+
+        let access = match field.ident {
+            Some(ref ident) => quote!{ #ident },
+            None => {
+                let index = syn::Index::from(index);
+                quote!{ #index }
+            },
+        };
+
+        fields.push((access, &field.ty));
+    }
+
+    if fields.is_empty() {
+        return Err(syn::Error::new(name.span(),
+            "#[derive(NetworkBehaviour)] requires at least one field that is not ignored"));
+    }
+    let num = fields.len();
+
+    // Generics of the impl block.
+    let (impl_generics, ty_generics, _) = ast.generics.split_for_impl();
+
+    // The combined handler type and the expression that builds it.
+    let (handler_ty, handler_expr) = {
+        let mut ty: Option<TokenStream> = None;
+        let mut expr: Option<TokenStream> = None;
+        for &(ref access, field_ty) in fields.iter().rev() {
+            let this_ty = quote!{ <#field_ty as #nb>::ConnectionHandler };
+            let this_expr = quote!{ #nb::new_handler(&self.#access) };
+            ty = Some(match ty {
+                Some(ty) => quote!{ #handler_select<#this_ty, #ty> },
+                None => this_ty,
+            });
+            expr = Some(match expr {
+                Some(expr) => quote!{ #handler::select(#this_expr, #expr) },
+                None => this_expr,
+            });
+        }
+        (ty.expect("fields is not empty ; qed"), expr.expect("fields is not empty ; qed"))
+    };
+
+    let mut where_clause: Vec<TokenStream> = ast.generics.where_clause.as_ref()
+        .map(|clause| clause.predicates.iter().map(|pred| quote!{ #pred }).collect())
+        .unwrap_or_default();
+    for &(_, ty) in &fields {
+        where_clause.push(quote!{ #ty: #nb });
+        where_clause.push(quote!{ Self: #nbep<<#ty as #nb>::OutEvent> });
+    }
+    where_clause.push(quote!{ #handler_ty: #handler });
+
+    let inject_connected = fields.iter().map(|&(ref access, _)| {
+        quote!{ #nb::inject_connected(&mut self.#access, peer_id.clone(), endpoint.clone()); }
+    });
+
+    let inject_disconnected = fields.iter().map(|&(ref access, _)| {
+        quote!{ #nb::inject_disconnected(&mut self.#access, peer_id, endpoint.clone()); }
+    });
+
+    let inject_dial_failure = fields.iter().map(|&(ref access, _)| {
+        quote!{ #nb::inject_dial_failure(&mut self.#access, peer_id, address, reason); }
+    });
+
+    let inject_node_event = fields.iter().enumerate().map(|(index, &(ref access, _))| {
+        let pattern = wrap_nested(index, num, quote!{ event });
+        quote!{ #pattern => #nb::inject_node_event(&mut self.#access, peer_id, event), }
+    });
+
+    let poll_fields = fields.iter().enumerate().map(|(index, &(ref access, _))| {
+        let wrapped = wrap_nested(index, num, quote!{ event });
+        quote!{
+            loop {
+                match #nb::poll(&mut self.#access, params) {
+                    #async_::Ready(#action::GenerateEvent(event)) => {
+                        #nbep::inject_event(self, event);
+                    },
+                    #async_::Ready(#action::DialAddress { address }) => {
+                        return #async_::Ready(#action::DialAddress { address });
+                    },
+                    #async_::Ready(#action::DialPeer { peer_id, addresses }) => {
+                        return #async_::Ready(#action::DialPeer { peer_id, addresses });
+                    },
+                    #async_::Ready(#action::SendEvent { peer_id, event }) => {
+                        return #async_::Ready(#action::SendEvent { peer_id, event: #wrapped });
+                    },
+                    #async_::Ready(#action::ReportObservedAddr { address }) => {
+                        return #async_::Ready(#action::ReportObservedAddr { address });
+                    },
+                    #async_::NotReady => break,
+                }
+            }
+        }
+    });
+
+    let poll_method = poll_method.map(|method| {
+        quote!{
+            if let #async_::Ready(action) = self.#method() {
+                return #async_::Ready(action);
+            }
+        }
+    });
+
+    Ok(quote!{
+        impl #impl_generics #nb for #name #ty_generics
+        where #(#where_clause,)*
+        {
+            type ConnectionHandler = #handler_ty;
+            type OutEvent = #out_event;
+
+            #[inline]
+            fn new_handler(&self) -> Self::ConnectionHandler {
+                #handler_expr
+            }
+
+            #[inline]
+            fn inject_connected(&mut self, peer_id: #peer_id, endpoint: #connected_point) {
+                #(#inject_connected)*
+            }
+
+            #[inline]
+            fn inject_disconnected(&mut self, peer_id: &#peer_id, endpoint: #connected_point) {
+                #(#inject_disconnected)*
+            }
+
+            #[inline]
+            fn inject_dial_failure(
+                &mut self,
+                peer_id: Option<&#peer_id>,
+                address: &#multiaddr,
+                reason: #dial_failure_reason,
+            ) {
+                #(#inject_dial_failure)*
+            }
+
+            #[inline]
+            fn inject_node_event(
+                &mut self,
+                peer_id: #peer_id,
+                event: <Self::ConnectionHandler as #handler>::OutEvent,
+            ) {
+                match event {
+                    #(#inject_node_event)*
+                }
+            }
+
+            fn poll(
+                &mut self,
+                params: &mut #poll_params,
+            ) -> #async_<#action<<Self::ConnectionHandler as #handler>::InEvent, Self::OutEvent>> {
+                #poll_method
+                #(#poll_fields)*
+                #async_::NotReady
+            }
+        }
+    })
+}
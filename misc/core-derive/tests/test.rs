@@ -0,0 +1,303 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+extern crate libp2p;
+#[macro_use]
+extern crate libp2p_core_derive;
+extern crate void;
+
+use libp2p::core::either::EitherOutput;
+use libp2p::core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction};
+use libp2p::core::nodes::behaviour::{NetworkBehaviourEventProcess, PollParameters};
+use libp2p::core::nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use libp2p::core::nodes::external_addrs::ExternalAddresses;
+use libp2p::core::nodes::handled_node::NodeHandlerEndpoint;
+use libp2p::core::nodes::swarm::ConnectedPoint;
+use libp2p::core::upgrade::DeniedConnectionUpgrade;
+use libp2p::core::{PeerId, PublicKey};
+use libp2p::futures::{Async, Poll};
+use libp2p::tokio_io::{AsyncRead, AsyncWrite};
+use std::io::{Cursor, Error as IoError};
+use std::marker::PhantomData;
+use void::Void;
+
+/// Handler that doesn't do anything.
+struct CounterHandler<TSubstream>(PhantomData<TSubstream>);
+
+impl<TSubstream> Clone for CounterHandler<TSubstream> {
+    fn clone(&self) -> Self {
+        CounterHandler(PhantomData)
+    }
+}
+
+impl<TSubstream> ConnectionHandler for CounterHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type InEvent = u32;
+    type OutEvent = u32;
+    type Substream = TSubstream;
+    type Protocol = DeniedConnectionUpgrade;
+    type OutboundOpenInfo = Void;
+
+    fn listen_protocol(&self) -> DeniedConnectionUpgrade {
+        DeniedConnectionUpgrade
+    }
+
+    fn inject_fully_negotiated(&mut self, _: (), _: NodeHandlerEndpoint<Void>) {
+    }
+
+    fn inject_event(&mut self, _: u32) {
+    }
+
+    fn inject_dial_upgrade_error(&mut self, _: Void, _: &IoError) {
+    }
+
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        KeepAlive::Forever
+    }
+
+    fn shutdown(&mut self) {
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<DeniedConnectionUpgrade, Void, u32>>, IoError> {
+        Ok(Async::NotReady)
+    }
+}
+
+/// Behaviour that counts what is injected in it.
+struct Counter<TSubstream> {
+    connected: usize,
+    node_events: Vec<u32>,
+    pending: Vec<NetworkBehaviourAction<u32, u32>>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> Counter<TSubstream> {
+    fn new() -> Self {
+        Counter {
+            connected: 0,
+            node_events: Vec::new(),
+            pending: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Counter<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ConnectionHandler = CounterHandler<TSubstream>;
+    type OutEvent = u32;
+
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        CounterHandler(PhantomData)
+    }
+
+    fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {
+        self.connected += 1;
+    }
+
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {
+        self.connected -= 1;
+    }
+
+    fn inject_node_event(&mut self, _: PeerId, event: u32) {
+        self.node_events.push(event);
+    }
+
+    fn poll(&mut self, _: &mut PollParameters) -> Async<NetworkBehaviourAction<u32, u32>> {
+        if self.pending.is_empty() {
+            Async::NotReady
+        } else {
+            Async::Ready(self.pending.remove(0))
+        }
+    }
+}
+
+type Substream = Cursor<Vec<u8>>;
+
+#[derive(NetworkBehaviour)]
+struct Composite {
+    first: Counter<Substream>,
+    second: Counter<Substream>,
+    third: Counter<Substream>,
+    #[behaviour(ignore)]
+    received: Vec<u32>,
+}
+
+impl Composite {
+    fn new() -> Self {
+        Composite {
+            first: Counter::new(),
+            second: Counter::new(),
+            third: Counter::new(),
+            received: Vec::new(),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<u32> for Composite {
+    fn inject_event(&mut self, event: u32) {
+        self.received.push(event);
+    }
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "String", poll_method = "poll_inner")]
+struct WithEvents {
+    counter: Counter<Substream>,
+    #[behaviour(ignore)]
+    pending: Vec<String>,
+}
+
+impl WithEvents {
+    fn new() -> Self {
+        WithEvents {
+            counter: Counter::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn poll_inner<TInEvent>(&mut self) -> Async<NetworkBehaviourAction<TInEvent, String>> {
+        if self.pending.is_empty() {
+            Async::NotReady
+        } else {
+            Async::Ready(NetworkBehaviourAction::GenerateEvent(self.pending.remove(0)))
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<u32> for WithEvents {
+    fn inject_event(&mut self, event: u32) {
+        self.pending.push(event.to_string());
+    }
+}
+
+#[derive(NetworkBehaviour)]
+struct Generic<TSubstream> {
+    first: Counter<TSubstream>,
+    second: Counter<TSubstream>,
+}
+
+impl<TSubstream> NetworkBehaviourEventProcess<u32> for Generic<TSubstream> {
+    fn inject_event(&mut self, _: u32) {
+    }
+}
+
+fn peer_id() -> PeerId {
+    PeerId::from_public_key(PublicKey::Rsa(vec![1, 2, 3, 4]))
+}
+
+fn poll<T: NetworkBehaviour>(behaviour: &mut T)
+    -> Async<NetworkBehaviourAction<<T::ConnectionHandler as ConnectionHandler>::InEvent, T::OutEvent>>
+{
+    let external_addrs = ExternalAddresses::new();
+    let mut params = PollParameters::new(Vec::new(), &external_addrs);
+    behaviour.poll(&mut params)
+}
+
+fn endpoint() -> ConnectedPoint {
+    ConnectedPoint::Dialer { address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap() }
+}
+
+#[test]
+fn connections_are_reported_to_all_fields() {
+    let mut composite = Composite::new();
+    composite.inject_connected(peer_id(), endpoint());
+    assert_eq!(composite.first.connected, 1);
+    assert_eq!(composite.second.connected, 1);
+    assert_eq!(composite.third.connected, 1);
+
+    composite.inject_disconnected(&peer_id(), endpoint());
+    assert_eq!(composite.first.connected, 0);
+    assert_eq!(composite.third.connected, 0);
+}
+
+#[test]
+fn node_events_are_routed_to_the_right_field() {
+    let mut composite = Composite::new();
+    composite.inject_node_event(peer_id(), EitherOutput::Second(EitherOutput::Second(3)));
+    composite.inject_node_event(peer_id(), EitherOutput::First(1));
+    composite.inject_node_event(peer_id(), EitherOutput::Second(EitherOutput::First(2)));
+    assert_eq!(composite.first.node_events, vec![1]);
+    assert_eq!(composite.second.node_events, vec![2]);
+    assert_eq!(composite.third.node_events, vec![3]);
+}
+
+#[test]
+fn generated_events_are_processed() {
+    let mut composite = Composite::new();
+    composite.second.pending.push(NetworkBehaviourAction::GenerateEvent(5));
+    composite.third.pending.push(NetworkBehaviourAction::GenerateEvent(6));
+    match poll(&mut composite) {
+        Async::NotReady => (),
+        _ => panic!(),
+    }
+    assert_eq!(composite.received, vec![5, 6]);
+
+    let mut with_events = WithEvents::new();
+    with_events.counter.pending.push(NetworkBehaviourAction::GenerateEvent(12));
+    match poll(&mut with_events) {
+        Async::NotReady => (),
+        _ => panic!(),
+    }
+    match poll(&mut with_events) {
+        Async::Ready(NetworkBehaviourAction::GenerateEvent(ref event)) if event == "12" => (),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn sent_events_are_wrapped() {
+    let mut composite = Composite::new();
+    composite.second.pending.push(NetworkBehaviourAction::SendEvent {
+        peer_id: peer_id(),
+        event: 8,
+    });
+
+    match poll(&mut composite) {
+        Async::Ready(NetworkBehaviourAction::SendEvent { event, .. }) => {
+            match event {
+                EitherOutput::Second(EitherOutput::First(8)) => (),
+                _ => panic!(),
+            }
+        },
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn generic_struct() {
+    let generic: Generic<Substream> = Generic {
+        first: Counter::new(),
+        second: Counter::new(),
+    };
+    let mut handler = generic.new_handler();
+    assert_eq!(handler.connection_keep_alive(), KeepAlive::Forever);
+    match handler.poll() {
+        Ok(Async::NotReady) => (),
+        _ => panic!(),
+    }
+}
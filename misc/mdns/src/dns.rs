@@ -0,0 +1,266 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains methods that handle the DNS encoding and decoding capabilities not available in the
+//! `dns_parser` library.
+
+use data_encoding;
+use libp2p_core::PeerId;
+use multiaddr::Multiaddr;
+use rand;
+use std::{error, fmt, time::Duration};
+use {META_QUERY_SERVICE, SERVICE_NAME};
+
+/// Builds the binary representation of a DNS query to send on the network.
+pub fn build_query() -> Vec<u8> {
+    let mut out = Vec::with_capacity(33);
+
+    // Program-generated transaction ID; unused by our implementation.
+    append_u16(&mut out, rand::random());
+
+    // 0x0 flag for a regular query.
+    append_u16(&mut out, 0x0);
+
+    // Number of questions.
+    append_u16(&mut out, 0x1);
+
+    // Number of answers, authorities, and additionals.
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, 0x0);
+
+    // Our single question.
+    // The name.
+    append_qname(&mut out, SERVICE_NAME);
+
+    // Flags.
+    append_u16(&mut out, 0x0c);
+    append_u16(&mut out, 0x01);
+
+    // Since the output is constant, we reserve the right amount ahead of time.
+    // If this assert fails, adjust the capacity of `out` in the source code.
+    debug_assert_eq!(out.capacity(), out.len());
+    out
+}
+
+/// Builds the response to the DNS query.
+///
+/// If there are more than 2^16-1 addresses, ignores the rest.
+pub fn build_query_response<I>(
+    id: u16,
+    peer_id: PeerId,
+    addresses: I,
+    ttl: Duration,
+) -> Result<Vec<u8>, MdnsResponseError>
+where
+    I: IntoIterator<Item = Multiaddr>,
+{
+    // Ceiling of the TTL, in seconds.
+    let ttl = duration_to_secs(ttl);
+
+    // Add a limit to 2^16-1 addresses, as the protocol limits to this number.
+    let addresses = addresses.into_iter().take(65535).collect::<Vec<_>>();
+
+    let peer_id_base32 = data_encoding::BASE32_DNSCURVE.encode(&peer_id.into_bytes());
+    if peer_id_base32.len() > 63 {
+        return Err(MdnsResponseError::PeerIdTooLong);
+    }
+
+    // The name of the peer, as `<peer id>._p2p._udp.local`.
+    let mut peer_name = peer_id_base32.into_bytes();
+    peer_name.push(b'.');
+    peer_name.extend_from_slice(SERVICE_NAME);
+
+    let mut out = Vec::with_capacity(512);
+
+    // Program-generated transaction ID; must be the same as the one of the query.
+    append_u16(&mut out, id);
+    // 0x8400 flag for an authoritative response.
+    append_u16(&mut out, 0x8400);
+    // Number of questions, answers, authorities, and additionals.
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, 0x1);
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, addresses.len() as u16);
+
+    // Our single answer, which points to the name of the peer.
+    append_qname(&mut out, SERVICE_NAME);
+    append_u16(&mut out, 0x000c); // PTR record
+    append_u16(&mut out, 0x0001); // IN class
+    append_u32(&mut out, ttl);
+    {
+        let mut rdata = Vec::with_capacity(peer_name.len() + 2);
+        append_qname(&mut rdata, &peer_name);
+        append_u16(&mut out, rdata.len() as u16);
+        out.extend_from_slice(&rdata);
+    }
+
+    // The additional records, one TXT record per address.
+    for addr in addresses {
+        let txt = format!("dnsaddr={}", addr);
+        if txt.len() > 255 {
+            return Err(MdnsResponseError::TxtRecordTooLong);
+        }
+
+        append_qname(&mut out, &peer_name);
+        append_u16(&mut out, 0x0010); // TXT record
+        append_u16(&mut out, 0x8001); // IN class, with the cache-flush bit
+        append_u32(&mut out, ttl);
+        append_u16(&mut out, txt.len() as u16 + 1);
+        out.push(txt.len() as u8);
+        out.extend_from_slice(txt.as_bytes());
+    }
+
+    Ok(out)
+}
+
+/// Builds the response to the DNS query asking for the list of services.
+pub fn build_service_discovery_response(id: u16, ttl: Duration) -> Vec<u8> {
+    let ttl = duration_to_secs(ttl);
+
+    let mut out = Vec::with_capacity(69);
+
+    append_u16(&mut out, id);
+    append_u16(&mut out, 0x8400);
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, 0x1);
+    append_u16(&mut out, 0x0);
+    append_u16(&mut out, 0x0);
+
+    // Our single answer, which points to our service name.
+    append_qname(&mut out, META_QUERY_SERVICE);
+    append_u16(&mut out, 0x000c); // PTR record
+    append_u16(&mut out, 0x0001); // IN class
+    append_u32(&mut out, ttl);
+    {
+        let mut rdata = Vec::with_capacity(SERVICE_NAME.len() + 2);
+        append_qname(&mut rdata, SERVICE_NAME);
+        append_u16(&mut out, rdata.len() as u16);
+        out.extend_from_slice(&rdata);
+    }
+
+    debug_assert_eq!(out.capacity(), out.len());
+    out
+}
+
+// Returns the number of seconds of a `Duration`, rounded up.
+fn duration_to_secs(duration: Duration) -> u32 {
+    let secs = duration
+        .as_secs()
+        .saturating_add(if duration.subsec_nanos() > 0 { 1 } else { 0 });
+    if secs > u64::from(u32::max_value()) {
+        u32::max_value()
+    } else {
+        secs as u32
+    }
+}
+
+// Appends a big-endian u32 to `out`.
+fn append_u32(out: &mut Vec<u8>, value: u32) {
+    out.push(((value >> 24) & 0xff) as u8);
+    out.push(((value >> 16) & 0xff) as u8);
+    out.push(((value >> 8) & 0xff) as u8);
+    out.push((value & 0xff) as u8);
+}
+
+// Appends a big-endian u16 to `out`.
+fn append_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(((value >> 8) & 0xff) as u8);
+    out.push((value & 0xff) as u8);
+}
+
+// Appends a `QNAME` (as defined by RFC1035) to the `Vec`.
+//
+// # Panic
+//
+// Panics if `name` has a zero-length component or a component that is too long.
+// This is fine considering that this function is not public and is only called in a controlled
+// environment.
+fn append_qname(out: &mut Vec<u8>, name: &[u8]) {
+    debug_assert!(name.is_ascii());
+
+    for element in name.split(|&c| c == b'.') {
+        assert!(element.len() < 64, "Service name has a label too long");
+        assert_ne!(element.len(), 0, "Service name contains zero length label");
+        out.push(element.len() as u8);
+        for chr in element.iter() {
+            out.push(*chr);
+        }
+    }
+
+    out.push(0);
+}
+
+/// Error that can happen when producing a DNS response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MdnsResponseError {
+    /// The encoded peer ID is longer than a DNS label.
+    PeerIdTooLong,
+    /// One of the addresses is too long to fit in a TXT record.
+    TxtRecordTooLong,
+}
+
+impl fmt::Display for MdnsResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MdnsResponseError::PeerIdTooLong => {
+                write!(f, "The encoded peer ID is too long to fit in a DNS label")
+            }
+            MdnsResponseError::TxtRecordTooLong => {
+                write!(f, "One of the addresses is too long to fit in a DNS TXT record")
+            }
+        }
+    }
+}
+
+impl error::Error for MdnsResponseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns_parser::Packet;
+    use libp2p_core::PublicKey;
+
+    #[test]
+    fn build_query_correct() {
+        let query = build_query();
+        assert!(Packet::parse(&query).is_ok());
+    }
+
+    #[test]
+    fn build_query_response_correct() {
+        let my_peer_id = PublicKey::Rsa(vec![1, 2, 3, 4]).into_peer_id();
+        let addr1 = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+        let addr2 = "/ip6/::1/udp/10000".parse().unwrap();
+        let query = build_query_response(
+            0xf8f8,
+            my_peer_id,
+            vec![addr1, addr2].into_iter(),
+            Duration::from_secs(60),
+        ).unwrap();
+        assert!(Packet::parse(&query).is_ok());
+    }
+
+    #[test]
+    fn build_service_discovery_response_correct() {
+        let query = build_service_discovery_response(0x1234, Duration::from_secs(120));
+        assert!(Packet::parse(&query).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! mDNS is a protocol defined by [RFC 6762](https://tools.ietf.org/html/rfc6762) that allows
+//! querying nodes that correspond to a certain domain name.
+//!
+//! In the context of libp2p, the mDNS protocol is used to discover other nodes on the local
+//! network that support libp2p.
+//!
+//! # Usage
+//!
+//! This crate provides the `MdnsService` struct, which sends queries on the local network and
+//! produces the queries and responses sent by other nodes, and the higher-level `MdnsDiscovery`
+//! stream which automatically answers the queries of other nodes with the addresses of the local
+//! node, and produces the `(PeerId, Multiaddr)` pairs of the nodes it discovers.
+//!
+//! These pairs are candidates for dialing, and can be passed for example to
+//! `SwarmController::dial`.
+//!
+//! > **Note**: Both must be polled from within a tokio runtime.
+
+extern crate data_encoding;
+extern crate dns_parser;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate multiaddr;
+extern crate net2;
+extern crate rand;
+extern crate tokio_reactor;
+extern crate tokio_timer;
+extern crate tokio_udp;
+
+pub use self::dns::MdnsResponseError;
+pub use self::service::{MdnsPacket, MdnsPeer, MdnsQuery, MdnsResponse, MdnsService};
+pub use self::service::MdnsServiceDiscovery;
+
+use futures::{Async, Poll, Stream};
+use libp2p_core::PeerId;
+use multiaddr::Multiaddr;
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::time::Duration;
+
+mod dns;
+mod service;
+
+/// Hardcoded name of the mDNS service. Part of the mDNS libp2p specifications.
+const SERVICE_NAME: &'static [u8] = b"_p2p._udp.local";
+/// Hardcoded name of the service used for DNS-SD.
+const META_QUERY_SERVICE: &'static [u8] = b"_services._dns-sd._udp.local";
+
+/// Discovers the libp2p nodes of the local network, and advertises the local node to them.
+///
+/// Implements `Stream` and produces the `PeerId` and the addresses of each node that is
+/// discovered. The same pair can be produced multiple times, as nodes periodically query the
+/// network.
+pub struct MdnsDiscovery {
+    /// The underlying service.
+    service: MdnsService,
+    /// Identity of the local node, reported to the other nodes.
+    local_peer_id: PeerId,
+    /// Addresses of the local node, reported to the other nodes.
+    listen_addresses: Vec<Multiaddr>,
+    /// Duration during which the other nodes should consider our addresses valid.
+    ttl: Duration,
+    /// Discovered nodes that haven't been produced yet.
+    discovered: VecDeque<(PeerId, Multiaddr)>,
+}
+
+impl MdnsDiscovery {
+    /// Starts discovering nodes on the local network, and advertises the given identity and
+    /// addresses to them.
+    pub fn new(local_peer_id: PeerId, listen_addresses: Vec<Multiaddr>) -> Result<MdnsDiscovery, IoError> {
+        Ok(MdnsDiscovery {
+            service: MdnsService::new()?,
+            local_peer_id: local_peer_id,
+            listen_addresses: listen_addresses,
+            ttl: Duration::from_secs(5 * 60),
+            discovered: VecDeque::new(),
+        })
+    }
+
+    /// Changes the addresses that are advertised to the other nodes, for example after we start
+    /// listening on a new address.
+    #[inline]
+    pub fn set_listen_addresses(&mut self, listen_addresses: Vec<Multiaddr>) {
+        self.listen_addresses = listen_addresses;
+    }
+
+    /// Changes the duration during which the other nodes should consider our addresses valid.
+    /// The default is 5 minutes.
+    #[inline]
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+}
+
+impl Stream for MdnsDiscovery {
+    type Item = (PeerId, Multiaddr);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(discovered) = self.discovered.pop_front() {
+                return Ok(Async::Ready(Some(discovered)));
+            }
+
+            match self.service.poll() {
+                Async::Ready(MdnsPacket::Query(query)) => {
+                    trace!("Answering mDNS query from {}", query.remote_addr());
+                    let result = self.service.respond(
+                        &query,
+                        self.local_peer_id.clone(),
+                        self.listen_addresses.clone(),
+                        self.ttl,
+                    );
+                    if let Err(err) = result {
+                        warn!("Failed to build mDNS response: {}", err);
+                    }
+                }
+                Async::Ready(MdnsPacket::ServiceDiscovery(query)) => {
+                    self.service.respond_service_discovery(&query, self.ttl);
+                }
+                Async::Ready(MdnsPacket::Response(response)) => {
+                    for peer in response.discovered_peers() {
+                        if peer.id() == &self.local_peer_id {
+                            continue;
+                        }
+
+                        debug!("Discovered {:?} through mDNS", peer.id());
+                        for addr in peer.addresses() {
+                            self.discovered.push_back((peer.id().clone(), addr.clone()));
+                        }
+                    }
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
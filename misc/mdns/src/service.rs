@@ -0,0 +1,477 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use data_encoding;
+use dns::{self, MdnsResponseError};
+use dns_parser::{Packet, RData};
+use futures::{Async, Stream};
+use libp2p_core::PeerId;
+use multiaddr::{Multiaddr, Protocol};
+use net2;
+use std::{fmt, io, net::Ipv4Addr, net::SocketAddr, str, time::Duration, time::Instant};
+use tokio_reactor::Handle;
+use tokio_timer::Interval;
+use tokio_udp::UdpSocket;
+use {META_QUERY_SERVICE, SERVICE_NAME};
+
+/// A running service that discovers libp2p peers and responds to other libp2p peers' queries on
+/// the local network.
+///
+/// # Usage
+///
+/// In order to use mDNS to discover peers on the local network, use the `MdnsService`. This is
+/// done by creating a `MdnsService` then polling it in the same way as you would poll a stream.
+///
+/// Polling the `MdnsService` can produce either an `MdnsQuery`, corresponding to an mDNS query
+/// received by another node on the local network, or an `MdnsResponse` corresponding to a
+/// response to a query previously emitted locally. The `MdnsService` will automatically produce
+/// queries, which means that you will receive responses automatically.
+///
+/// When you receive an `MdnsQuery`, you should call `respond` with the `PeerId` and the addresses
+/// of the local node.
+///
+/// For most purposes, the higher-level `MdnsDiscovery` does all of this automatically.
+pub struct MdnsService {
+    /// Main socket for listening.
+    socket: UdpSocket,
+    /// Socket for sending queries on the network.
+    query_socket: UdpSocket,
+    /// Interval for sending queries.
+    query_interval: Interval,
+    /// Whether we send queries on the network at all.
+    silent: bool,
+    /// Buffer used for receiving data from the main socket.
+    recv_buffer: [u8; 2048],
+    /// Buffers pending to send on the main socket.
+    send_buffers: Vec<Vec<u8>>,
+    /// Buffers pending to send on the query socket.
+    query_send_buffers: Vec<Vec<u8>>,
+}
+
+impl MdnsService {
+    /// Starts a new mDNS service.
+    #[inline]
+    pub fn new() -> io::Result<MdnsService> {
+        Self::new_inner(false)
+    }
+
+    /// Same as `new`, but we don't send automatically send queries on the network.
+    #[inline]
+    pub fn silent() -> io::Result<MdnsService> {
+        Self::new_inner(true)
+    }
+
+    /// Starts a new mDNS service.
+    fn new_inner(silent: bool) -> io::Result<MdnsService> {
+        let socket = {
+            #[cfg(unix)]
+            fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
+                net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
+                Ok(())
+            }
+            #[cfg(not(unix))]
+            fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> { Ok(()) }
+            let builder = net2::UdpBuilder::new_v4()?;
+            builder.reuse_address(true)?;
+            platform_specific(&builder)?;
+            builder.bind(("0.0.0.0", 5353))?
+        };
+
+        let socket = UdpSocket::from_std(socket, &Handle::default())?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        // TODO: correct interfaces?
+        socket.join_multicast_v4(&From::from([224, 0, 0, 251]), &Ipv4Addr::UNSPECIFIED)?;
+
+        Ok(MdnsService {
+            socket: socket,
+            // Given that we pass an IP address to bind, which does not need to be resolved, we can
+            // use std::net::UdpSocket::bind, instead of its async counterpart from tokio.
+            query_socket: UdpSocket::from_std(
+                ::std::net::UdpSocket::bind((Ipv4Addr::from([0u8, 0, 0, 0]), 0u16))?,
+                &Handle::default(),
+            )?,
+            query_interval: Interval::new(Instant::now(), Duration::from_secs(20)),
+            silent: silent,
+            recv_buffer: [0; 2048],
+            send_buffers: Vec::new(),
+            query_send_buffers: Vec::new(),
+        })
+    }
+
+    /// Queues a response to a query received on the network.
+    ///
+    /// `peer_id` and `addresses` should be the ones of the local node, and `ttl` the duration
+    /// during which the remotes should consider the addresses valid.
+    pub fn respond<I>(
+        &mut self,
+        query: &MdnsQuery,
+        peer_id: PeerId,
+        addresses: I,
+        ttl: Duration,
+    ) -> Result<(), MdnsResponseError>
+    where
+        I: IntoIterator<Item = Multiaddr>,
+    {
+        let response = dns::build_query_response(query.query_id, peer_id, addresses, ttl)?;
+        self.send_buffers.push(response);
+        Ok(())
+    }
+
+    /// Queues a response to a query for the list of the services of the node.
+    pub fn respond_service_discovery(&mut self, query: &MdnsServiceDiscovery, ttl: Duration) {
+        let response = dns::build_service_discovery_response(query.query_id, ttl);
+        self.send_buffers.push(response);
+    }
+
+    /// Polls the service for packets.
+    pub fn poll(&mut self) -> Async<MdnsPacket> {
+        // Send a query if it's time to do so.
+        if !self.silent {
+            loop {
+                match self.query_interval.poll() {
+                    Ok(Async::Ready(_)) => self.query_send_buffers.push(dns::build_query()),
+                    Ok(Async::NotReady) => break,
+                    Err(err) => {
+                        warn!("Error in the mDNS query timer: {:?}", err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Flush the send buffers of the main socket.
+        while !self.send_buffers.is_empty() {
+            let to_send = self.send_buffers.remove(0);
+            match self.socket.poll_send_to(&to_send, &From::from(([224, 0, 0, 251], 5353))) {
+                Ok(Async::Ready(bytes_written)) => {
+                    debug_assert_eq!(bytes_written, to_send.len());
+                }
+                Ok(Async::NotReady) => {
+                    self.send_buffers.insert(0, to_send);
+                    break;
+                }
+                Err(err) => {
+                    // Errors are non-fatal because they can happen for example if we lose
+                    // connection to the network.
+                    debug!("Failed to send mDNS response ; err = {:?}", err);
+                }
+            }
+        }
+
+        // Flush the query send buffer.
+        while !self.query_send_buffers.is_empty() {
+            let to_send = self.query_send_buffers.remove(0);
+            match self.query_socket.poll_send_to(&to_send, &From::from(([224, 0, 0, 251], 5353))) {
+                Ok(Async::Ready(bytes_written)) => {
+                    debug_assert_eq!(bytes_written, to_send.len());
+                }
+                Ok(Async::NotReady) => {
+                    self.query_send_buffers.insert(0, to_send);
+                    break;
+                }
+                Err(err) => {
+                    debug!("Failed to send mDNS query ; err = {:?}", err);
+                }
+            }
+        }
+
+        // Check for any incoming packet. Packets that fail to parse are ignored.
+        loop {
+            match self.socket.poll_recv_from(&mut self.recv_buffer) {
+                Ok(Async::Ready((len, from))) => {
+                    if let Some(packet) = MdnsPacket::from_bytes(&self.recv_buffer[..len], from) {
+                        return Async::Ready(packet);
+                    }
+                }
+                Ok(Async::NotReady) => break,
+                Err(err) => {
+                    debug!("Error while receiving mDNS packet ; err = {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        Async::NotReady
+    }
+}
+
+impl fmt::Debug for MdnsService {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MdnsService")
+            .field("silent", &self.silent)
+            .finish()
+    }
+}
+
+/// A valid mDNS packet received by the service.
+#[derive(Debug, Clone)]
+pub enum MdnsPacket {
+    /// A query made by a remote.
+    Query(MdnsQuery),
+    /// A response sent by a remote in response to one of our queries.
+    Response(MdnsResponse),
+    /// A request for service discovery.
+    ServiceDiscovery(MdnsServiceDiscovery),
+}
+
+impl MdnsPacket {
+    // Parses a packet received on the network. Returns `None` if the packet is invalid or
+    // unrelated to libp2p.
+    fn from_bytes(buf: &[u8], from: SocketAddr) -> Option<MdnsPacket> {
+        let packet = match Packet::parse(buf) {
+            Ok(packet) => packet,
+            Err(err) => {
+                debug!("Parsing mDNS packet failed ; err = {:?}", err);
+                return None;
+            }
+        };
+
+        if packet.header.query {
+            if packet
+                .questions
+                .iter()
+                .any(|q| q.qname.to_string().as_bytes() == SERVICE_NAME)
+            {
+                return Some(MdnsPacket::Query(MdnsQuery {
+                    from: from,
+                    query_id: packet.header.id,
+                }));
+            }
+
+            if packet
+                .questions
+                .iter()
+                .any(|q| q.qname.to_string().as_bytes() == META_QUERY_SERVICE)
+            {
+                return Some(MdnsPacket::ServiceDiscovery(MdnsServiceDiscovery {
+                    from: from,
+                    query_id: packet.header.id,
+                }));
+            }
+
+            return None;
+        }
+
+        let peers = packet
+            .answers
+            .iter()
+            .filter(|record| record.name.to_string().as_bytes() == SERVICE_NAME)
+            .filter_map(|record| {
+                let peer_name = match record.data {
+                    RData::PTR(ref ptr) => ptr.0.to_string(),
+                    _ => return None,
+                };
+
+                let peer_id = {
+                    let encoded = peer_name.split('.').next()?;
+                    let bytes = data_encoding::BASE32_DNSCURVE.decode(encoded.as_bytes()).ok()?;
+                    PeerId::from_bytes(bytes).ok()?
+                };
+
+                let addresses = packet
+                    .additional
+                    .iter()
+                    .filter(|add| add.name.to_string() == peer_name)
+                    .flat_map(|add| match add.data {
+                        RData::TXT(ref txt) => txt
+                            .iter()
+                            .filter_map(|txt| parse_txt_address(txt, &peer_id))
+                            .collect(),
+                        _ => Vec::new(),
+                    })
+                    .collect();
+
+                Some(MdnsPeer {
+                    id: peer_id,
+                    addresses: addresses,
+                    ttl: Duration::from_secs(u64::from(record.ttl)),
+                })
+            })
+            .collect();
+
+        Some(MdnsPacket::Response(MdnsResponse { from: from, peers: peers }))
+    }
+}
+
+// Parses a `dnsaddr=<multiaddr>` TXT entry. If the address ends with `/p2p/<peer id>`, the peer
+// ID must match `peer_id` and is removed from the address.
+fn parse_txt_address(txt: &[u8], peer_id: &PeerId) -> Option<Multiaddr> {
+    let txt = str::from_utf8(txt).ok()?;
+    if !txt.starts_with("dnsaddr=") {
+        return None;
+    }
+
+    let mut addr: Multiaddr = txt["dnsaddr=".len()..].parse().ok()?;
+    let strip_peer_id = match addr.iter().last() {
+        Some(Protocol::P2p(ref hash)) if hash.as_bytes() == peer_id.as_bytes() => true,
+        Some(Protocol::P2p(_)) => return None,
+        _ => false,
+    };
+    if strip_peer_id {
+        addr.pop();
+    }
+
+    Some(addr)
+}
+
+/// A received mDNS query.
+#[derive(Debug, Clone)]
+pub struct MdnsQuery {
+    /// Sender of the query.
+    from: SocketAddr,
+    /// Id of the received DNS query. We need to pass this ID back in the results.
+    query_id: u16,
+}
+
+impl MdnsQuery {
+    /// Source address of the packet.
+    #[inline]
+    pub fn remote_addr(&self) -> &SocketAddr {
+        &self.from
+    }
+}
+
+/// A received mDNS service discovery query.
+#[derive(Debug, Clone)]
+pub struct MdnsServiceDiscovery {
+    /// Sender of the query.
+    from: SocketAddr,
+    /// Id of the received DNS query. We need to pass this ID back in the results.
+    query_id: u16,
+}
+
+impl MdnsServiceDiscovery {
+    /// Source address of the packet.
+    #[inline]
+    pub fn remote_addr(&self) -> &SocketAddr {
+        &self.from
+    }
+}
+
+/// A received mDNS response.
+#[derive(Debug, Clone)]
+pub struct MdnsResponse {
+    /// Sender of the response.
+    from: SocketAddr,
+    /// Peers contained in the response.
+    peers: Vec<MdnsPeer>,
+}
+
+impl MdnsResponse {
+    /// Returns the list of peers that have been reported in this packet.
+    #[inline]
+    pub fn discovered_peers(&self) -> &[MdnsPeer] {
+        &self.peers
+    }
+
+    /// Source address of the packet.
+    #[inline]
+    pub fn remote_addr(&self) -> &SocketAddr {
+        &self.from
+    }
+}
+
+/// A peer discovered by the service.
+#[derive(Debug, Clone)]
+pub struct MdnsPeer {
+    /// Id of the peer.
+    id: PeerId,
+    /// Addresses the peer is listening on.
+    addresses: Vec<Multiaddr>,
+    /// Duration during which the addresses are valid.
+    ttl: Duration,
+}
+
+impl MdnsPeer {
+    /// Returns the id of the peer.
+    #[inline]
+    pub fn id(&self) -> &PeerId {
+        &self.id
+    }
+
+    /// Returns the list of addresses the peer says it is listening on.
+    #[inline]
+    pub fn addresses(&self) -> &[Multiaddr] {
+        &self.addresses
+    }
+
+    /// Returns the duration during which the addresses are valid.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MdnsPacket, parse_txt_address};
+    use dns;
+    use libp2p_core::PublicKey;
+    use multiaddr::{Multiaddr, Protocol};
+    use std::time::Duration;
+
+    #[test]
+    fn response_roundtrip() {
+        let peer_id = PublicKey::Rsa(vec![1, 2, 3, 4]).into_peer_id();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+        let response = dns::build_query_response(
+            0x1234,
+            peer_id.clone(),
+            vec![addr.clone()],
+            Duration::from_secs(60),
+        ).unwrap();
+
+        match MdnsPacket::from_bytes(&response, ([127, 0, 0, 1], 5353).into()) {
+            Some(MdnsPacket::Response(response)) => {
+                let peers = response.discovered_peers();
+                assert_eq!(peers.len(), 1);
+                assert_eq!(peers[0].id(), &peer_id);
+                assert_eq!(peers[0].addresses(), &[addr][..]);
+                assert_eq!(peers[0].ttl(), Duration::from_secs(60));
+            }
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[test]
+    fn query_detected() {
+        let query = dns::build_query();
+        match MdnsPacket::from_bytes(&query, ([127, 0, 0, 1], 5353).into()) {
+            Some(MdnsPacket::Query(_)) => (),
+            _ => panic!("expected a query"),
+        }
+    }
+
+    #[test]
+    fn txt_peer_id_stripped() {
+        let peer_id = PublicKey::Rsa(vec![1, 2, 3, 4]).into_peer_id();
+        let other = PublicKey::Rsa(vec![5, 6, 7, 8]).into_peer_id();
+
+        let mut addr: Multiaddr = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+        let expected = addr.clone();
+        addr.append(Protocol::P2p(peer_id.clone().into()));
+        let txt = format!("dnsaddr={}", addr);
+
+        assert_eq!(parse_txt_address(txt.as_bytes(), &peer_id), Some(expected));
+        assert_eq!(parse_txt_address(txt.as_bytes(), &other), None);
+        assert_eq!(parse_txt_address(b"foo=bar", &peer_id), None);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Metrics of a libp2p node.
+//!
+//! This crate provides counters, gauges and histograms grouped in a `Registry`, which can be
+//! exported in the Prometheus text exposition format with `Registry::encode`. Serving the result
+//! over HTTP, or pulling the individual values, is left to the user.
+//!
+//! The `Metrics` struct registers the metrics of a node. They are filled by wrapping the
+//! transport with a `MetricsTransport` and the upgrades with a `MetricsUpgrade`. The metrics of
+//! the Kademlia queries and of the pubsub messages are filled by the user, with
+//! `Metrics::observe_kad_query`, `Metrics::observe_kad_lookup`, `Metrics::observe_kad_buckets`
+//! and `Metrics::observe_pubsub_message`.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_core;
+//! extern crate libp2p_metrics;
+//!
+//! use libp2p_metrics::{Metrics, MetricsTransport, Registry};
+//!
+//! # fn main() {
+//! let registry = Registry::new();
+//! let metrics = Metrics::new(&registry);
+//! let (dialer, _listener) = libp2p_core::transport::connector();
+//! let transport = MetricsTransport::new(dialer, metrics.clone());
+//! # drop(transport);
+//! println!("{}", registry.encode());
+//! # }
+//! ```
+
+extern crate bytes;
+extern crate fnv;
+#[macro_use]
+extern crate futures;
+extern crate libp2p_core as swarm;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate tokio_io;
+
+pub mod metrics;
+pub mod registry;
+pub mod transport;
+pub mod upgrade;
+
+pub use self::metrics::Metrics;
+pub use self::registry::{Counter, Family, Gauge, Histogram, Registry};
+pub use self::transport::{Metered, MetricsTransport};
+pub use self::upgrade::MetricsUpgrade;
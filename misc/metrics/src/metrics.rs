@@ -0,0 +1,136 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use registry::{Counter, Family, Gauge, Histogram, Registry};
+use std::time::Duration;
+
+/// Metrics of a libp2p node, registered in a `Registry`.
+///
+/// Cloning a `Metrics` produces a handle to the same metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Number of dial attempts.
+    pub dials: Counter,
+    /// Number of dial attempts that failed.
+    pub dial_failures: Counter,
+    /// Number of incoming connections that failed to be established.
+    pub incoming_failures: Counter,
+    /// Number of connections established, labelled with their direction (`inbound` or
+    /// `outbound`).
+    pub connections_established: Family<Counter>,
+    /// Number of connections currently open.
+    pub connections_open: Gauge,
+    /// Time it takes for a dial to succeed, including the upgrades applied to the transport
+    /// that is measured.
+    pub dial_duration: Histogram,
+    /// Number of substreams negotiated, labelled with the protocol and the endpoint (`dialer`
+    /// or `listener`).
+    pub substreams: Family<Counter>,
+    /// Number of substreams whose upgrade failed after negotiation, labelled with the protocol.
+    pub upgrade_failures: Family<Counter>,
+    /// Duration of the Kademlia queries.
+    pub kad_query_duration: Histogram,
+    /// Number of hops between the local node and the farthest peer that answered a Kademlia
+    /// query.
+    pub kad_query_hops: Histogram,
+    /// Number of peers contacted by a Kademlia query.
+    pub kad_query_peers_contacted: Histogram,
+    /// Number of peers in each bucket of the Kademlia routing table, labelled with the index of
+    /// the bucket.
+    pub kad_bucket_entries: Family<Gauge>,
+    /// Number of pubsub messages received, labelled with the protocol that received them.
+    pub pubsub_messages: Family<Counter>,
+    /// Time between the publication of a pubsub message and its reception.
+    pub pubsub_propagation: Histogram,
+}
+
+impl Metrics {
+    /// Registers all the metrics in the given registry.
+    pub fn new(registry: &Registry) -> Metrics {
+        Metrics {
+            dials: registry.counter("libp2p_dials_total", "Number of dial attempts."),
+            dial_failures: registry.counter("libp2p_dial_failures_total",
+                "Number of dial attempts that failed."),
+            incoming_failures: registry.counter("libp2p_incoming_failures_total",
+                "Number of incoming connections that failed to be established."),
+            connections_established: registry.counter_family(
+                "libp2p_connections_established_total",
+                "Number of connections established.",
+                &["direction"]),
+            connections_open: registry.gauge("libp2p_connections_open",
+                "Number of connections currently open."),
+            dial_duration: registry.histogram("libp2p_dial_duration_seconds",
+                "Time it takes for a dial to succeed."),
+            substreams: registry.counter_family("libp2p_substreams_total",
+                "Number of substreams negotiated.",
+                &["protocol", "endpoint"]),
+            upgrade_failures: registry.counter_family("libp2p_upgrade_failures_total",
+                "Number of upgrades that failed after negotiation.",
+                &["protocol"]),
+            kad_query_duration: registry.histogram("libp2p_kad_query_duration_seconds",
+                "Duration of the Kademlia queries."),
+            kad_query_hops: registry.histogram_with_buckets("libp2p_kad_query_hops",
+                "Number of hops of the farthest peer that answered a Kademlia query.",
+                &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0, 20.0]),
+            kad_query_peers_contacted: registry.histogram_with_buckets(
+                "libp2p_kad_query_peers_contacted",
+                "Number of peers contacted by a Kademlia query.",
+                &[1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0]),
+            kad_bucket_entries: registry.gauge_family("libp2p_kad_bucket_entries",
+                "Number of peers in each bucket of the Kademlia routing table.",
+                &["bucket"]),
+            pubsub_messages: registry.counter_family("libp2p_pubsub_messages_total",
+                "Number of pubsub messages received.",
+                &["protocol"]),
+            pubsub_propagation: registry.histogram("libp2p_pubsub_propagation_seconds",
+                "Time between the publication of a pubsub message and its reception."),
+        }
+    }
+
+    /// Records the duration of a Kademlia query.
+    #[inline]
+    pub fn observe_kad_query(&self, duration: Duration) {
+        self.kad_query_duration.observe_duration(duration);
+    }
+
+    /// Records the number of hops and of peers contacted by a Kademlia query.
+    #[inline]
+    pub fn observe_kad_lookup(&self, hops: usize, peers_contacted: usize) {
+        self.kad_query_hops.observe(hops as f64);
+        self.kad_query_peers_contacted.observe(peers_contacted as f64);
+    }
+
+    /// Updates the occupancy of the buckets of the Kademlia routing table, as returned by
+    /// `KadSystem::bucket_occupancy`.
+    pub fn observe_kad_buckets(&self, occupancy: &[usize]) {
+        for (index, &entries) in occupancy.iter().enumerate() {
+            self.kad_bucket_entries.with_labels(&[&index.to_string()]).set(entries as isize);
+        }
+    }
+
+    /// Records the reception of a pubsub message, with the time elapsed since its publication
+    /// if known.
+    pub fn observe_pubsub_message(&self, protocol: &str, propagation: Option<Duration>) {
+        self.pubsub_messages.with_labels(&[protocol]).inc();
+        if let Some(propagation) = propagation {
+            self.pubsub_propagation.observe_duration(propagation);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Default buckets of a histogram, in seconds. Suitable for network latencies.
+pub const DEFAULT_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Metric whose value only goes up.
+///
+/// Cloning a `Counter` produces a handle to the same value.
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+    value: Arc<AtomicUsize>,
+}
+
+impl Counter {
+    /// Increments the counter by one.
+    #[inline]
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    /// Increments the counter by the given value.
+    #[inline]
+    pub fn inc_by(&self, value: usize) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of the counter.
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Metric whose value can go up and down.
+///
+/// Cloning a `Gauge` produces a handle to the same value.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge {
+    value: Arc<AtomicIsize>,
+}
+
+impl Gauge {
+    /// Increments the gauge by one.
+    #[inline]
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrements the gauge by one.
+    #[inline]
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Sets the value of the gauge.
+    #[inline]
+    pub fn set(&self, value: isize) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of the gauge.
+    #[inline]
+    pub fn get(&self) -> isize {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Metric that samples observations and counts them in buckets.
+///
+/// Cloning a `Histogram` produces a handle to the same buckets.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    inner: Arc<Mutex<HistogramInner>>,
+}
+
+#[derive(Debug)]
+struct HistogramInner {
+    /// Upper bounds of the buckets, sorted.
+    bounds: Vec<f64>,
+    /// Number of observations less than or equal to each bound.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Creates a histogram with the given bucket upper bounds.
+    pub fn new(bounds: &[f64]) -> Histogram {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(|a, b| a.partial_cmp(b).expect("histogram bounds can't be NaN"));
+        let num_bounds = bounds.len();
+        Histogram {
+            inner: Arc::new(Mutex::new(HistogramInner {
+                bounds: bounds,
+                counts: vec![0; num_bounds],
+                sum: 0.0,
+                count: 0,
+            })),
+        }
+    }
+
+    /// Records an observation.
+    pub fn observe(&self, value: f64) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        inner.sum += value;
+        inner.count += 1;
+        for (bound, count) in inner.bounds.iter().zip(inner.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Records a duration, in seconds.
+    #[inline]
+    pub fn observe_duration(&self, duration: Duration) {
+        let secs = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0;
+        self.observe(secs)
+    }
+
+    /// Returns the number of observations.
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.inner.lock().count
+    }
+
+    /// Returns the sum of all the observations.
+    #[inline]
+    pub fn sum(&self) -> f64 {
+        self.inner.lock().sum
+    }
+}
+
+impl Default for Histogram {
+    #[inline]
+    fn default() -> Histogram {
+        Histogram::new(DEFAULT_BUCKETS)
+    }
+}
+
+/// Collection of metrics of the same type, distinguished by the values of their labels.
+///
+/// Cloning a `Family` produces a handle to the same collection.
+#[derive(Debug, Clone)]
+pub struct Family<M> {
+    labels: Arc<Vec<String>>,
+    metrics: Arc<Mutex<FnvHashMap<Vec<String>, M>>>,
+    new_metric: fn() -> M,
+}
+
+impl<M: Clone> Family<M> {
+    /// Returns the metric with the given label values, creating it if necessary.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the number of values doesn't match the number of labels of the family.
+    pub fn with_labels(&self, values: &[&str]) -> M {
+        assert_eq!(values.len(), self.labels.len(), "wrong number of label values");
+        let key = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        self.metrics.lock().entry(key).or_insert_with(self.new_metric).clone()
+    }
+
+    /// Returns the label values and metrics of the family, sorted by label values.
+    fn sorted(&self) -> Vec<(Vec<String>, M)> {
+        let mut list = self.metrics.lock()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+}
+
+/// Metric registered in a `Registry`.
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+    CounterFamily(Family<Counter>),
+    GaugeFamily(Family<Gauge>),
+    HistogramFamily(Family<Histogram>),
+}
+
+/// Collection of metrics that can be exported all at once.
+///
+/// Cloning a `Registry` produces a handle to the same collection.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    metrics: Arc<Mutex<Vec<(String, String, Metric)>>>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    #[inline]
+    pub fn new() -> Registry {
+        Default::default()
+    }
+
+    /// Registers a new counter.
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        let counter = Counter::default();
+        self.register(name, help, Metric::Counter(counter.clone()));
+        counter
+    }
+
+    /// Registers a new gauge.
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        let gauge = Gauge::default();
+        self.register(name, help, Metric::Gauge(gauge.clone()));
+        gauge
+    }
+
+    /// Registers a new histogram with the default buckets.
+    pub fn histogram(&self, name: &str, help: &str) -> Histogram {
+        let histogram = Histogram::default();
+        self.register(name, help, Metric::Histogram(histogram.clone()));
+        histogram
+    }
+
+    /// Registers a new histogram with the given bucket upper bounds.
+    pub fn histogram_with_buckets(&self, name: &str, help: &str, bounds: &[f64]) -> Histogram {
+        let histogram = Histogram::new(bounds);
+        self.register(name, help, Metric::Histogram(histogram.clone()));
+        histogram
+    }
+
+    /// Registers a new family of counters with the given labels.
+    pub fn counter_family(&self, name: &str, help: &str, labels: &[&str]) -> Family<Counter> {
+        let family = new_family(labels, Counter::default);
+        self.register(name, help, Metric::CounterFamily(family.clone()));
+        family
+    }
+
+    /// Registers a new family of gauges with the given labels.
+    pub fn gauge_family(&self, name: &str, help: &str, labels: &[&str]) -> Family<Gauge> {
+        let family = new_family(labels, Gauge::default);
+        self.register(name, help, Metric::GaugeFamily(family.clone()));
+        family
+    }
+
+    /// Registers a new family of histograms with the default buckets and the given labels.
+    pub fn histogram_family(&self, name: &str, help: &str, labels: &[&str]) -> Family<Histogram> {
+        let family = new_family(labels, Histogram::default);
+        self.register(name, help, Metric::HistogramFamily(family.clone()));
+        family
+    }
+
+    fn register(&self, name: &str, help: &str, metric: Metric) {
+        self.metrics.lock().push((name.to_owned(), help.to_owned(), metric));
+    }
+
+    /// Encodes all the metrics of the registry in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        for &(ref name, ref help, ref metric) in self.metrics.lock().iter() {
+            let ty = match *metric {
+                Metric::Counter(_) | Metric::CounterFamily(_) => "counter",
+                Metric::Gauge(_) | Metric::GaugeFamily(_) => "gauge",
+                Metric::Histogram(_) | Metric::HistogramFamily(_) => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, ty);
+
+            match *metric {
+                Metric::Counter(ref c) => { let _ = writeln!(out, "{} {}", name, c.get()); },
+                Metric::Gauge(ref g) => { let _ = writeln!(out, "{} {}", name, g.get()); },
+                Metric::Histogram(ref h) => encode_histogram(&mut out, name, &[], h),
+                Metric::CounterFamily(ref f) => {
+                    for (values, c) in f.sorted() {
+                        let labels = format_labels(&f.labels, &values);
+                        let _ = writeln!(out, "{}{{{}}} {}", name, labels, c.get());
+                    }
+                },
+                Metric::GaugeFamily(ref f) => {
+                    for (values, g) in f.sorted() {
+                        let labels = format_labels(&f.labels, &values);
+                        let _ = writeln!(out, "{}{{{}}} {}", name, labels, g.get());
+                    }
+                },
+                Metric::HistogramFamily(ref f) => {
+                    for (values, h) in f.sorted() {
+                        let labels = f.labels.iter().cloned().zip(values).collect::<Vec<_>>();
+                        encode_histogram(&mut out, name, &labels, &h);
+                    }
+                },
+            }
+        }
+        out
+    }
+}
+
+fn new_family<M>(labels: &[&str], new_metric: fn() -> M) -> Family<M> {
+    Family {
+        labels: Arc::new(labels.iter().map(|l| l.to_string()).collect()),
+        metrics: Arc::new(Mutex::new(FnvHashMap::default())),
+        new_metric: new_metric,
+    }
+}
+
+/// Formats labels as `name="value",name2="value2"`.
+fn format_labels(names: &[String], values: &[String]) -> String {
+    names.iter()
+        .zip(values)
+        .map(|(n, v)| format!("{}=\"{}\"", n, escape_label(v)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Escapes a label value according to the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn encode_histogram(out: &mut String, name: &str, labels: &[(String, String)], h: &Histogram) {
+    let inner = h.inner.lock();
+    let names = labels.iter().map(|l| l.0.clone()).collect::<Vec<_>>();
+    let values = labels.iter().map(|l| l.1.clone()).collect::<Vec<_>>();
+    let base = format_labels(&names, &values);
+    let prefix = if base.is_empty() { String::new() } else { format!("{},", base) };
+
+    for (bound, count) in inner.bounds.iter().zip(inner.counts.iter()) {
+        let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, prefix, bound, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, prefix, inner.count);
+    let suffix = if base.is_empty() { String::new() } else { format!("{{{}}}", base) };
+    let _ = writeln!(out, "{}_sum{} {}", name, suffix, inner.sum);
+    let _ = writeln!(out, "{}_count{} {}", name, suffix, inner.count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+
+    #[test]
+    fn encode_text_format() {
+        let registry = Registry::new();
+        let dials = registry.counter("libp2p_dials_total", "Number of dials.");
+        let open = registry.gauge("libp2p_connections", "Open connections.");
+        let per_proto = registry.counter_family("libp2p_substreams_total", "Substreams.",
+                                                &["protocol"]);
+        dials.inc_by(3);
+        open.inc();
+        per_proto.with_labels(&["/ipfs/ping/1.0.0"]).inc();
+
+        let text = registry.encode();
+        assert!(text.contains("# TYPE libp2p_dials_total counter\nlibp2p_dials_total 3\n"));
+        assert!(text.contains("libp2p_connections 1\n"));
+        assert!(text.contains("libp2p_substreams_total{protocol=\"/ipfs/ping/1.0.0\"} 1\n"));
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let registry = Registry::new();
+        let latency = registry.histogram("latency_seconds", "Latency.");
+        latency.observe(0.02);
+        latency.observe(3.0);
+        let text = registry.encode();
+        assert!(text.contains("latency_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("latency_seconds_count 2\n"));
+    }
+}
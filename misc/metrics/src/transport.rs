@@ -0,0 +1,262 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::prelude::*;
+use metrics::Metrics;
+use multiaddr::Multiaddr;
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+use swarm::Transport;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Wraps around a `Transport` and records the dials and the connections in `Metrics`.
+///
+/// > **Note**: The dial duration includes the upgrades that have been applied to the inner
+/// >           transport. Wrap around the raw transport in order to measure the time it takes
+/// >           to establish the connection alone.
+#[derive(Debug, Clone)]
+pub struct MetricsTransport<T> {
+    inner: T,
+    metrics: Metrics,
+}
+
+impl<T> MetricsTransport<T> {
+    /// Wraps around a transport.
+    #[inline]
+    pub fn new(inner: T, metrics: Metrics) -> MetricsTransport<T> {
+        MetricsTransport {
+            inner: inner,
+            metrics: metrics,
+        }
+    }
+}
+
+impl<T> Transport for MetricsTransport<T>
+where T: Transport,
+{
+    type Output = Metered<T::Output>;
+    type Listener = MetricsListener<T::Listener>;
+    type ListenerUpgrade = MetricsFuture<T::ListenerUpgrade>;
+    type Dial = MetricsFuture<T::Dial>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let metrics = self.metrics;
+        match self.inner.listen_on(addr) {
+            Ok((listener, addr)) => {
+                let listener = MetricsListener {
+                    inner: listener,
+                    metrics: metrics,
+                };
+                Ok((listener, addr))
+            },
+            Err((inner, addr)) => Err((MetricsTransport { inner: inner, metrics: metrics }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let metrics = self.metrics;
+        match self.inner.dial(addr) {
+            Ok(dial) => {
+                metrics.dials.inc();
+                Ok(MetricsFuture {
+                    inner: dial,
+                    metrics: metrics,
+                    start: Instant::now(),
+                    outbound: true,
+                })
+            },
+            Err((inner, addr)) => Err((MetricsTransport { inner: inner, metrics: metrics }, addr)),
+        }
+    }
+}
+
+/// Listener of a `MetricsTransport`.
+pub struct MetricsListener<L> {
+    inner: L,
+    metrics: Metrics,
+}
+
+impl<L, U> Stream for MetricsListener<L>
+where L: Stream<Item = (U, Multiaddr), Error = io::Error>,
+      U: Future<Error = io::Error>,
+{
+    type Item = (MetricsFuture<U>, Multiaddr);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.inner.poll()) {
+            Some((upgrade, addr)) => {
+                let upgrade = MetricsFuture {
+                    inner: upgrade,
+                    metrics: self.metrics.clone(),
+                    start: Instant::now(),
+                    outbound: false,
+                };
+                Ok(Async::Ready(Some((upgrade, addr))))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// Future that establishes a connection and records the outcome.
+pub struct MetricsFuture<F> {
+    inner: F,
+    metrics: Metrics,
+    start: Instant,
+    /// True for dials, false for incoming connections.
+    outbound: bool,
+}
+
+impl<F> Future for MetricsFuture<F>
+where F: Future<Error = io::Error>,
+{
+    type Item = Metered<F::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let output = match self.inner.poll() {
+            Ok(Async::Ready(output)) => output,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => {
+                if self.outbound {
+                    self.metrics.dial_failures.inc();
+                } else {
+                    self.metrics.incoming_failures.inc();
+                }
+                return Err(err);
+            },
+        };
+
+        let direction = if self.outbound { "outbound" } else { "inbound" };
+        self.metrics.connections_established.with_labels(&[direction]).inc();
+        if self.outbound {
+            self.metrics.dial_duration.observe_duration(self.start.elapsed());
+        }
+        self.metrics.connections_open.inc();
+
+        Ok(Async::Ready(Metered {
+            inner: output,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+/// Connection established through a `MetricsTransport`. Counts as open until it is dropped.
+///
+/// Dereferences to the underlying connection, and implements `AsyncRead` and `AsyncWrite` if
+/// the underlying connection does.
+pub struct Metered<T> {
+    inner: T,
+    metrics: Metrics,
+}
+
+impl<T> Deref for Metered<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Metered<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for Metered<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.metrics.connections_open.dec();
+    }
+}
+
+impl<T: Read> Read for Metered<T> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Metered<T> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: Write> Write for Metered<T> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Metered<T> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use metrics::Metrics;
+    use registry::Registry;
+    use swarm::{Transport, transport::memory};
+    use super::MetricsTransport;
+
+    #[test]
+    fn counts_connections() {
+        let metrics = Metrics::new(&Registry::new());
+        let (dialer, listener) = memory::connector();
+        let dialer = MetricsTransport::new(dialer, metrics.clone());
+        let listener = MetricsTransport::new(listener, metrics.clone());
+
+        let (listener, addr) = listener.listen_on("/memory".parse().unwrap()).ok().unwrap();
+        let outbound = dialer.clone().dial(addr.clone()).ok().unwrap().wait().unwrap();
+        let (incoming, _) = listener.into_future().map_err(|(err, _)| err).wait().unwrap();
+        let inbound = incoming.unwrap().0.wait().unwrap();
+
+        assert_eq!(metrics.dials.get(), 1);
+        assert_eq!(metrics.connections_established.with_labels(&["outbound"]).get(), 1);
+        assert_eq!(metrics.connections_established.with_labels(&["inbound"]).get(), 1);
+        assert_eq!(metrics.connections_open.get(), 2);
+        assert_eq!(metrics.dial_duration.count(), 1);
+
+        drop(outbound);
+        drop(inbound);
+        assert_eq!(metrics.connections_open.get(), 0);
+
+        // The listener has been dropped, so the dial fails.
+        assert!(dialer.dial(addr).ok().unwrap().wait().is_err());
+        assert_eq!(metrics.dial_failures.get(), 1);
+    }
+}
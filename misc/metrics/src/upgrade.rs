@@ -0,0 +1,111 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bytes::Bytes;
+use futures::prelude::*;
+use metrics::Metrics;
+use multiaddr::Multiaddr;
+use std::{io, iter};
+use swarm::upgrade::{ConnectionUpgrade, Endpoint};
+
+/// Wraps around a `ConnectionUpgrade` and records in `Metrics` the protocols that are
+/// negotiated.
+#[derive(Debug, Clone)]
+pub struct MetricsUpgrade<U> {
+    inner: U,
+    metrics: Metrics,
+}
+
+impl<U> MetricsUpgrade<U> {
+    /// Wraps around an upgrade.
+    #[inline]
+    pub fn new(inner: U, metrics: Metrics) -> MetricsUpgrade<U> {
+        MetricsUpgrade {
+            inner: inner,
+            metrics: metrics,
+        }
+    }
+}
+
+impl<C, U> ConnectionUpgrade<C> for MetricsUpgrade<U>
+where U: ConnectionUpgrade<C>,
+      U::Future: Send + 'static,
+      U::Output: 'static,
+{
+    type NamesIter = iter::Map<U::NamesIter, fn((Bytes, U::UpgradeIdentifier))
+        -> (Bytes, (U::UpgradeIdentifier, Bytes))>;
+    type UpgradeIdentifier = (U::UpgradeIdentifier, Bytes);
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        fn with_name<I>((name, id): (Bytes, I)) -> (Bytes, (I, Bytes)) {
+            (name.clone(), (id, name))
+        }
+        self.inner.protocol_names().map(with_name::<U::UpgradeIdentifier> as fn(_) -> _)
+    }
+
+    type Output = U::Output;
+    type Future = Box<Future<Item = U::Output, Error = io::Error> + Send>;
+
+    fn upgrade(self, socket: C, (id, name): Self::UpgradeIdentifier, ty: Endpoint,
+               remote_addr: &Multiaddr) -> Self::Future
+    {
+        let protocol = String::from_utf8_lossy(&name).into_owned();
+        let endpoint = match ty {
+            Endpoint::Dialer => "dialer",
+            Endpoint::Listener => "listener",
+        };
+        self.metrics.substreams.with_labels(&[&protocol, endpoint]).inc();
+
+        let failures = self.metrics.upgrade_failures.with_labels(&[&protocol]);
+        let future = self.inner.upgrade(socket, id, ty, remote_addr)
+            .map_err(move |err| {
+                failures.inc();
+                err
+            });
+        Box::new(future)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use metrics::Metrics;
+    use registry::Registry;
+    use std::io::Cursor;
+    use swarm::upgrade::{self, ConnectionUpgrade, Endpoint};
+    use super::MetricsUpgrade;
+
+    #[test]
+    fn counts_substreams() {
+        let metrics = Metrics::new(&Registry::new());
+        let upgrade = MetricsUpgrade::new(upgrade::PlainTextConfig, metrics.clone());
+        let (name, id) = ConnectionUpgrade::<Cursor<Vec<u8>>>::protocol_names(&upgrade)
+            .next().unwrap();
+        assert_eq!(&name[..], &(id.1)[..]);
+
+        let addr = "/memory".parse().unwrap();
+        upgrade.upgrade(Cursor::new(Vec::new()), id, Endpoint::Dialer, &addr).wait().unwrap();
+
+        let protocol = String::from_utf8(name.to_vec()).unwrap();
+        assert_eq!(metrics.substreams.with_labels(&[&protocol, "dialer"]).get(), 1);
+        assert_eq!(metrics.upgrade_failures.with_labels(&[&protocol]).get(), 0);
+    }
+}
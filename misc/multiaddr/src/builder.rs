@@ -0,0 +1,117 @@
+use multihash::Multihash;
+use std::{borrow::Cow, net::{IpAddr, Ipv4Addr, Ipv6Addr}};
+use {Multiaddr, Protocol};
+
+/// Builds a `Multiaddr` component by component, without going through its string
+/// representation.
+///
+/// # Examples
+///
+/// ```
+/// use multiaddr::{Multiaddr, Protocol};
+///
+/// let address = Multiaddr::builder()
+///     .dns4("example.com")
+///     .tcp(443)
+///     .with(Protocol::Wss)
+///     .build();
+///
+/// assert_eq!(address, "/dns4/example.com/tcp/443/wss".parse().unwrap());
+/// assert_eq!(address.iter_components().nth(1), Some(Protocol::Tcp(443)));
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct MultiaddrBuilder {
+    components: Vec<Protocol<'static>>,
+}
+
+impl MultiaddrBuilder {
+    /// Creates a builder for an empty multiaddr.
+    #[inline]
+    pub fn new() -> MultiaddrBuilder {
+        Default::default()
+    }
+
+    /// Appends an arbitrary component.
+    #[inline]
+    pub fn with(mut self, protocol: Protocol) -> MultiaddrBuilder {
+        self.components.push(protocol.acquire());
+        self
+    }
+
+    /// Appends an `/ip4` or an `/ip6` component, depending on the address.
+    #[inline]
+    pub fn ip(self, addr: IpAddr) -> MultiaddrBuilder {
+        match addr {
+            IpAddr::V4(addr) => self.ip4(addr),
+            IpAddr::V6(addr) => self.ip6(addr),
+        }
+    }
+
+    /// Appends an `/ip4` component.
+    #[inline]
+    pub fn ip4<A: Into<Ipv4Addr>>(self, addr: A) -> MultiaddrBuilder {
+        self.with(Protocol::Ip4(addr.into()))
+    }
+
+    /// Appends an `/ip6` component.
+    #[inline]
+    pub fn ip6<A: Into<Ipv6Addr>>(self, addr: A) -> MultiaddrBuilder {
+        self.with(Protocol::Ip6(addr.into()))
+    }
+
+    /// Appends a `/dns4` component.
+    #[inline]
+    pub fn dns4<S: Into<String>>(self, name: S) -> MultiaddrBuilder {
+        self.with(Protocol::Dns4(Cow::Owned(name.into())))
+    }
+
+    /// Appends a `/dns6` component.
+    #[inline]
+    pub fn dns6<S: Into<String>>(self, name: S) -> MultiaddrBuilder {
+        self.with(Protocol::Dns6(Cow::Owned(name.into())))
+    }
+
+    /// Appends a `/tcp` component.
+    #[inline]
+    pub fn tcp(self, port: u16) -> MultiaddrBuilder {
+        self.with(Protocol::Tcp(port))
+    }
+
+    /// Appends a `/udp` component.
+    #[inline]
+    pub fn udp(self, port: u16) -> MultiaddrBuilder {
+        self.with(Protocol::Udp(port))
+    }
+
+    /// Appends a `/ws` component.
+    #[inline]
+    pub fn ws(self) -> MultiaddrBuilder {
+        self.with(Protocol::Ws)
+    }
+
+    /// Appends a `/wss` component.
+    #[inline]
+    pub fn wss(self) -> MultiaddrBuilder {
+        self.with(Protocol::Wss)
+    }
+
+    /// Appends a `/p2p-circuit` component.
+    #[inline]
+    pub fn p2p_circuit(self) -> MultiaddrBuilder {
+        self.with(Protocol::P2pCircuit)
+    }
+
+    /// Appends a `/p2p` component, which contains the identity of a peer. Accepts a `PeerId` as
+    /// well as a raw multihash.
+    #[inline]
+    pub fn p2p<H: Into<Multihash>>(self, peer: H) -> MultiaddrBuilder {
+        self.with(Protocol::P2p(peer.into()))
+    }
+
+    /// Builds the multiaddr.
+    #[inline]
+    pub fn build(self) -> Multiaddr {
+        self.components.into_iter().collect()
+    }
+}
@@ -9,6 +9,7 @@ extern crate serde;
 extern crate unsigned_varint;
 pub extern crate multihash;
 
+mod builder;
 mod protocol;
 mod errors;
 
@@ -26,6 +27,8 @@ use std::{
     result::Result as StdResult,
     str::FromStr
 };
+use multihash::Multihash;
+pub use builder::MultiaddrBuilder;
 pub use errors::{Result, Error};
 pub use protocol::Protocol;
 
@@ -116,6 +119,23 @@ impl fmt::Display for Multiaddr {
 }
 
 impl Multiaddr {
+    /// Starts building a multiaddr component by component.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiaddr::Multiaddr;
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let address = Multiaddr::builder().ip4(Ipv4Addr::new(127, 0, 0, 1)).tcp(4001).build();
+    /// assert_eq!(address, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+    /// ```
+    ///
+    #[inline]
+    pub fn builder() -> MultiaddrBuilder {
+        MultiaddrBuilder::new()
+    }
+
     /// Returns the raw bytes representation of the multiaddr.
     #[inline]
     pub fn into_bytes(self) -> Vec<u8> {
@@ -261,6 +281,40 @@ impl Multiaddr {
         Iter(&self.bytes)
     }
 
+    /// Returns the components of this multiaddress. Identical to `iter`, and provided for
+    /// symmetry with the builder.
+    #[inline]
+    pub fn iter_components(&self) -> Iter {
+        self.iter()
+    }
+
+    /// If the multiaddr ends with a `/p2p` component, returns the multiaddr without this component
+    /// and the multihash it contains.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiaddr::Multiaddr;
+    ///
+    /// let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC"
+    ///     .parse().unwrap();
+    /// let (transport_addr, peer) = address.split_p2p().unwrap();
+    /// assert_eq!(transport_addr, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+    /// assert_eq!(Multiaddr::builder().p2p(peer).build(),
+    ///            "/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC".parse().unwrap());
+    ///
+    /// let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+    /// assert!(address.split_p2p().is_none());
+    /// ```
+    ///
+    pub fn split_p2p(&self) -> Option<(Multiaddr, Multihash)> {
+        let mut components = self.iter().collect::<Vec<_>>();
+        match components.pop() {
+            Some(Protocol::P2p(hash)) => Some((components.into_iter().collect(), hash)),
+            _ => None,
+        }
+    }
+
     /// Pops the last `Protocol` of this multiaddr, or `None` if the multiaddr is empty.
     /// ```
     /// use multiaddr::{Multiaddr, Protocol};
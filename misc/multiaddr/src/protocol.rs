@@ -15,6 +15,7 @@ use {Result, Error};
 const DCCP: u32 = 33;
 const DNS4: u32 = 54;
 const DNS6: u32 = 55;
+const DNSADDR: u32 = 56;
 const HTTP: u32 = 480;
 const HTTPS: u32 = 443;
 const IP4: u32 = 4;
@@ -42,6 +43,7 @@ pub enum Protocol<'a> {
     Dccp(u16),
     Dns4(Cow<'a, str>),
     Dns6(Cow<'a, str>),
+    Dnsaddr(Cow<'a, str>),
     Http,
     Https,
     Ip4(Ipv4Addr),
@@ -104,6 +106,10 @@ impl<'a> Protocol<'a> {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::Dns6(Cow::Borrowed(s)))
             }
+            "dnsaddr" => {
+                let s = iter.next().ok_or(Error::InvalidProtocolString)?;
+                Ok(Protocol::Dnsaddr(Cow::Borrowed(s)))
+            }
             "sctp" => {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::Sctp(s.parse()?))
@@ -114,7 +120,8 @@ impl<'a> Protocol<'a> {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::Unix(Cow::Borrowed(s)))
             }
-            "p2p" => {
+            // `ipfs` is the former name of `p2p`.
+            "p2p" | "ipfs" => {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 let decoded = bs58::decode(s).into_vec()?;
                 Ok(Protocol::P2p(Multihash::from_bytes(decoded)?))
@@ -161,6 +168,11 @@ impl<'a> Protocol<'a> {
                 let (data, rest) = split_at(n, input)?;
                 Ok((Protocol::Dns6(Cow::Borrowed(str::from_utf8(data)?)), rest))
             }
+            DNSADDR => {
+                let (n, input) = decode::usize(input)?;
+                let (data, rest) = split_at(n, input)?;
+                Ok((Protocol::Dnsaddr(Cow::Borrowed(str::from_utf8(data)?)), rest))
+            }
             HTTP => Ok((Protocol::Http, input)),
             HTTPS => Ok((Protocol::Https, input)),
             IP4 => {
@@ -273,6 +285,12 @@ impl<'a> Protocol<'a> {
                 w.write_all(encode::usize(bytes.len(), &mut encode::usize_buffer()))?;
                 w.write_all(&bytes)?
             }
+            Protocol::Dnsaddr(s) => {
+                w.write_all(encode::u32(DNSADDR, &mut buf))?;
+                let bytes = s.as_bytes();
+                w.write_all(encode::usize(bytes.len(), &mut encode::usize_buffer()))?;
+                w.write_all(&bytes)?
+            }
             Protocol::Unix(s) => {
                 w.write_all(encode::u32(UNIX, &mut buf))?;
                 let bytes = s.as_bytes();
@@ -309,6 +327,7 @@ impl<'a> Protocol<'a> {
             Dccp(a) => Dccp(a),
             Dns4(cow) => Dns4(Cow::Owned(cow.into_owned())),
             Dns6(cow) => Dns6(Cow::Owned(cow.into_owned())),
+            Dnsaddr(cow) => Dnsaddr(Cow::Owned(cow.into_owned())),
             Http => Http,
             Https => Https,
             Ip4(a) => Ip4(a),
@@ -340,6 +359,7 @@ impl<'a> fmt::Display for Protocol<'a> {
             Dccp(port) => write!(f, "/dccp/{}", port),
             Dns4(s) => write!(f, "/dns4/{}", s),
             Dns6(s) => write!(f, "/dns6/{}", s),
+            Dnsaddr(s) => write!(f, "/dnsaddr/{}", s),
             Http => f.write_str("/http"),
             Https => f.write_str("/https"),
             Ip4(addr) => write!(f, "/ip4/{}", addr),
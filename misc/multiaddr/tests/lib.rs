@@ -59,7 +59,7 @@ struct Proto(Protocol<'static>);
 impl Arbitrary for Proto {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
         use Protocol::*;
-        match g.gen_range(0, 22) { // TODO: Add Protocol::Quic
+        match g.gen_range(0, 23) { // TODO: Add Protocol::Quic
              0 => Proto(Dccp(g.gen())),
              1 => Proto(Dns4(Cow::Owned(SubString::arbitrary(g).0))),
              2 => Proto(Dns6(Cow::Owned(SubString::arbitrary(g).0))),
@@ -83,6 +83,7 @@ impl Arbitrary for Proto {
             19 => Proto(Utp),
             20 => Proto(Ws),
             21 => Proto(Wss),
+            22 => Proto(Dnsaddr(Cow::Owned(SubString::arbitrary(g).0))),
              _ => panic!("outside range")
         }
     }
@@ -186,6 +187,9 @@ fn construct_success() {
     ma_valid("/ip4/127.0.0.1/tcp/9090/p2p-circuit/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC",
              "047F000001062382A202A503221220D52EBB89D85B02A284948203A62FF28389C57C9F42BEEC4EC20DB76A68911C0B",
              vec![Ip4(local.clone()), Tcp(9090), P2pCircuit, P2p(multihash("QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC"))]);
+    ma_valid("/dnsaddr/bootstrap.libp2p.io",
+             "3813626F6F7473747261702E6C69627032702E696F",
+             vec![Dnsaddr(Cow::Borrowed("bootstrap.libp2p.io"))]);
 }
 
 #[test]
@@ -252,6 +256,33 @@ fn to_multiaddr() {
                "/ip6/2601:9:4f81:9700:803e:ca65:66e8:c21/tcp/1234".parse::<Multiaddr>().unwrap());
 }
 
+#[test]
+fn legacy_ipfs_component() {
+    let id = "QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC";
+    let legacy = format!("/ip4/127.0.0.1/tcp/4001/ipfs/{}", id).parse::<Multiaddr>().unwrap();
+    let current = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", id).parse::<Multiaddr>().unwrap();
+    assert_eq!(legacy, current);
+    assert_eq!(legacy.to_string(), format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", id));
+}
+
+#[test]
+fn builder_matches_parsing() {
+    let peer = multihash("QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC");
+    let built = Multiaddr::builder()
+        .ip6(Ipv6Addr::new(0x2601, 0x9, 0x4f81, 0x9700, 0x803e, 0xca65, 0x66e8, 0xc21))
+        .tcp(1234)
+        .ws()
+        .p2p(peer.clone())
+        .build();
+    let parsed = "/ip6/2601:9:4f81:9700:803e:ca65:66e8:c21/tcp/1234/ws/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC"
+        .parse::<Multiaddr>().unwrap();
+    assert_eq!(built, parsed);
+
+    let (stripped, hash) = built.split_p2p().unwrap();
+    assert_eq!(hash, peer);
+    assert_eq!(stripped.iter_components().last(), Some(Protocol::Ws));
+}
+
 #[test]
 fn from_bytes_fail() {
     let bytes = vec![1, 2, 3, 4];
@@ -5,6 +5,8 @@ use std::{fmt, error};
 pub enum EncodeError {
     /// The requested hash algorithm isn't supported by this library.
     UnsupportedType,
+    /// The input is too long to be embedded in an identity multihash.
+    UnsupportedInputLength,
 }
 
 impl fmt::Display for EncodeError {
@@ -12,6 +14,7 @@ impl fmt::Display for EncodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             EncodeError::UnsupportedType => write!(f, "This type is not supported yet"),
+            EncodeError::UnsupportedInputLength => write!(f, "Input is too long for this type"),
         }
     }
 }
@@ -4,6 +4,8 @@
 /// Not all hash types are supported by this library.
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
 pub enum Hash {
+    /// Identity (the digest is the input itself, variable size)
+    Identity,
     /// SHA-1 (20-byte hash size)
     SHA1,
     /// SHA-256 (32-byte hash size)
@@ -36,6 +38,7 @@ impl Hash {
     /// Get the corresponding hash code.
     pub fn code(&self) -> u8 {
         match *self {
+            Hash::Identity => 0x00,
             Hash::SHA1 => 0x11,
             Hash::SHA2256 => 0x12,
             Hash::SHA2512 => 0x13,
@@ -53,8 +56,11 @@ impl Hash {
     }
 
     /// Get the hash length in bytes.
+    ///
+    /// Returns 0 for `Identity`, whose length depends on the input.
     pub fn size(&self) -> u8 {
         match *self {
+            Hash::Identity => 0,
             Hash::SHA1 => 20,
             Hash::SHA2256 => 32,
             Hash::SHA2512 => 64,
@@ -74,6 +80,7 @@ impl Hash {
     /// Returns the algorithm corresponding to a code, or `None` if no algorith is matching.
     pub fn from_code(code: u8) -> Option<Hash> {
         Some(match code {
+            0x00 => Hash::Identity,
             0x11 => Hash::SHA1,
             0x12 => Hash::SHA2256,
             0x13 => Hash::SHA2512,
@@ -74,6 +74,19 @@ macro_rules! match_encoder {
 /// ```
 ///
 pub fn encode(hash: Hash, input: &[u8]) -> Result<Multihash, EncodeError> {
+    if hash == Hash::Identity {
+        // TODO: the length is a varint, but we only support single-byte lengths ; see the
+        // comment in `MultihashRef::from_slice`
+        if input.len() >= 128 {
+            return Err(EncodeError::UnsupportedInputLength);
+        }
+        let mut output = Vec::with_capacity(2 + input.len());
+        output.push(hash.code());
+        output.push(input.len() as u8);
+        output.extend_from_slice(input);
+        return Ok(Multihash { bytes: output });
+    }
+
     let size = hash.size();
     let mut output = Vec::new();
     output.resize(2 + size as usize, 0);
@@ -164,7 +177,7 @@ pub struct MultihashRef<'a> {
 impl<'a> MultihashRef<'a> {
     /// Verifies whether `bytes` contains a valid multihash, and if so returns a `MultihashRef`.
     pub fn from_slice(input: &'a [u8]) -> Result<MultihashRef<'a>, DecodeError> {
-        if input.is_empty() {
+        if input.len() < 2 {
             return Err(DecodeError::BadInputLength);
         }
 
@@ -179,7 +192,11 @@ impl<'a> MultihashRef<'a> {
         }
 
         let alg = Hash::from_code(code).ok_or(DecodeError::UnknownCode)?;
-        let hash_len = alg.size() as usize;
+        let hash_len = if alg == Hash::Identity {
+            input[1] as usize
+        } else {
+            alg.size() as usize
+        };
 
         // length of input should be exactly hash_len + 2
         if input.len() != hash_len + 2 {
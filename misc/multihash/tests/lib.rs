@@ -29,6 +29,7 @@ macro_rules! assert_encode {
 #[test]
 fn multihash_encode() {
     assert_encode! {
+        Identity, b"hello world", "000b68656c6c6f20776f726c64";
         SHA1, b"beep boop", "11147c8357577f51d4f0a8d393aa1aaafb28863d9421";
         SHA2256, b"helloworld", "1220936a185caaa266bb9cbe981e9e05cb78cd732b0b3280eb944412bb6f8f8f07af";
         SHA2256, b"beep boop", "122090ea688e275d580567325032492b597bc77221c62493e76330b85ddda191ef7c";
@@ -60,6 +61,7 @@ macro_rules! assert_decode {
 #[test]
 fn assert_decode() {
     assert_decode! {
+        Identity, "000b68656c6c6f20776f726c64";
         SHA1, "11147c8357577f51d4f0a8d393aa1aaafb28863d9421";
         SHA2256, "1220936a185caaa266bb9cbe981e9e05cb78cd732b0b3280eb944412bb6f8f8f07af";
         SHA2256, "122090ea688e275d580567325032492b597bc77221c62493e76330b85ddda191ef7c";
@@ -92,11 +94,18 @@ macro_rules! assert_roundtrip {
 #[test]
 fn assert_roundtrip() {
     assert_roundtrip!(
-        SHA1, SHA2256, SHA2512, SHA3224, SHA3256, SHA3384, SHA3512,
+        Identity, SHA1, SHA2256, SHA2512, SHA3224, SHA3256, SHA3384, SHA3512,
         Keccak224, Keccak256, Keccak384, Keccak512
     );
 }
 
+#[test]
+fn identity_length() {
+    assert_eq!(encode(Hash::Identity, &[0; 128]), Err(EncodeError::UnsupportedInputLength));
+    assert_eq!(MultihashRef::from_slice(&[0x00, 0x02, 0xff]), Err(DecodeError::BadInputLength));
+    assert_eq!(encode(Hash::Identity, b"").unwrap().digest(), b"");
+}
+
 #[test]
 fn hash_types() {
     assert_eq!(Hash::SHA2256.size(), 32);
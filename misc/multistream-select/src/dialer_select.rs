@@ -22,8 +22,10 @@
 //! `multistream-select` for the dialer.
 
 use bytes::Bytes;
-use futures::{future::Either, prelude::*, sink, stream::StreamFuture};
+use futures::{future::{self, Either, FutureResult}, prelude::*, sink, stream::StreamFuture};
+use negotiated::Negotiated;
 use protocol::{Dialer, DialerFuture, DialerToListenerMessage, ListenerToDialerMessage};
+use protocol::MultistreamSelectError;
 use std::mem;
 use tokio_io::{AsyncRead, AsyncWrite};
 use ProtocolChoiceError;
@@ -59,6 +61,37 @@ where
     }
 }
 
+/// Future, returned by `dialer_select_proto_lazy`, which resolves immediately.
+pub type DialerSelectLazy<R, P> = FutureResult<(P, Negotiated<R>), ProtocolChoiceError>;
+
+/// Helps selecting a protocol amongst the ones supported, without waiting for the remote.
+///
+/// Same as `dialer_select_proto`, except that the first protocol produced by the iterator is
+/// proposed optimistically, which is known as the "V1-lazy" mode of `multistream-select`. The
+/// future resolves immediately, and the proposal is sent together with the first bytes written to
+/// the returned `Negotiated` socket. This saves a round trip, at the cost of never trying any
+/// other protocol.
+///
+/// If the remote doesn't support the protocol, the error is produced when reading from the
+/// socket.
+pub fn dialer_select_proto_lazy<R, I, M, P>(inner: R, mut protocols: I) -> DialerSelectLazy<R, P>
+where
+    R: AsyncRead + AsyncWrite,
+    I: Iterator<Item=(Bytes, M, P)>,
+{
+    let (proto_name, _, proto_value) = match protocols.next() {
+        Some(protocol) => protocol,
+        None => return future::err(ProtocolChoiceError::NoProtocolFound),
+    };
+
+    if !proto_name.starts_with(b"/") {
+        return future::err(MultistreamSelectError::WrongProtocolName.into());
+    }
+
+    trace!("lazily proposing {:?}", proto_name);
+    future::ok((proto_value, Negotiated::propose(inner, proto_name)))
+}
+
 
 /// Iterator, which ignores match predicates of the iterator it wraps.
 pub struct IgnoreMatchFn<I>(I);
@@ -91,6 +91,15 @@ impl<I, S> LengthDelimitedFramedRead<I, S> {
         assert_eq!(self.internal_buffer_pos, 0);
         self.inner
     }
+
+    /// Grants mutable access to the underlying socket.
+    ///
+    /// Reading from the socket directly while a frame is being decoded will corrupt the stream of
+    /// frames.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
 }
 
 impl<I, S> Stream for LengthDelimitedFramedRead<I, S>
@@ -136,7 +145,15 @@ where
                     if (*self.internal_buffer.last().unwrap_or(&0) & 0x80) == 0 {
                         // End of length prefix. Most of the time we will switch to reading data,
                         // but we need to handle a few corner cases first.
-                        let frame_len = decode_length_prefix(&self.internal_buffer);
+                        let frame_len = match decode_length_prefix(&self.internal_buffer) {
+                            Some(len) => len,
+                            None => {
+                                return Err(IoError::new(
+                                    IoErrorKind::InvalidData,
+                                    "invalid frame length prefix",
+                                ));
+                            }
+                        };
 
                         if frame_len >= 1 {
                             self.state = State::ReadingData { frame_len };
@@ -218,19 +235,25 @@ where
     }
 }
 
-fn decode_length_prefix(buf: &[u8]) -> u16 {
-    debug_assert!(buf.len() <= 2);
+/// Decodes the varint length prefix of a frame. Returns `None` if the prefix is longer than two
+/// bytes or isn't minimally encoded.
+fn decode_length_prefix(buf: &[u8]) -> Option<u16> {
+    if buf.is_empty() || buf.len() > 2 {
+        return None;
+    }
 
-    let mut sum = 0u16;
+    // A trailing zero byte means that the same value could have been encoded with fewer bytes.
+    if buf.len() > 1 && buf[buf.len() - 1] == 0 {
+        return None;
+    }
 
+    // Two bytes of 7 bits each always fit in a `u16`.
+    let mut sum = 0u16;
     for &byte in buf.iter().rev() {
-        let byte = byte & 0x7f;
-        sum <<= 7;
-        debug_assert!(sum.checked_add(u16::from(byte)).is_some());
-        sum += u16::from(byte);
+        sum = (sum << 7) | u16::from(byte & 0x7f);
     }
 
-    sum
+    Some(sum)
 }
 
 #[cfg(test)]
@@ -293,6 +316,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn non_minimal_length_prefix() {
+        let data = vec![0x85, 0x00, 9, 8, 7, 6, 5];
+        let framed = LengthDelimitedFramedRead::<Vec<u8>, _>::new(Cursor::new(data));
+
+        match framed.collect().wait() {
+            Err(io_err) => assert_eq!(io_err.kind(), ErrorKind::InvalidData),
+            _ => panic!(),
+        }
+    }
+
     #[test]
     fn empty_frames() {
         let data = vec![0, 0, 6, 9, 8, 7, 6, 5, 4, 0, 3, 9, 8, 7];
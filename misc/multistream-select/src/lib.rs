@@ -37,6 +37,10 @@
 //! supports, or suggest a protocol. If a protocol is suggested, the listener can either accept (by
 //! answering with the same protocol name) or refuse the choice (by answering "not available").
 //!
+//! If the dialer only wants to use one protocol, it can also propose it optimistically with
+//! `dialer_select_proto_lazy` and start sending data immediately, without waiting for the listener
+//! to accept. This saves a round trip. The listener doesn't need to do anything special.
+//!
 //! ## Examples
 //!
 //! For a dialer:
@@ -125,10 +129,13 @@ mod dialer_select;
 mod error;
 mod length_delimited;
 mod listener_select;
+mod negotiated;
 mod tests;
 
 pub mod protocol;
 
-pub use self::dialer_select::{dialer_select_proto, DialerSelectFuture};
+pub use self::dialer_select::{dialer_select_proto, dialer_select_proto_lazy};
+pub use self::dialer_select::{DialerSelectFuture, DialerSelectLazy};
 pub use self::error::ProtocolChoiceError;
 pub use self::listener_select::{listener_select_proto, ListenerSelectFuture};
+pub use self::negotiated::Negotiated;
@@ -49,13 +49,26 @@ where
     M: FnMut(&Bytes, &Bytes) -> bool,
 {
     ListenerSelectFuture {
-        inner: ListenerSelectState::AwaitListener { listener_fut: Listener::new(inner), protocols }
+        inner: ListenerSelectState::AwaitListener { listener_fut: Listener::new(inner), protocols },
+        rejected: Vec::new(),
     }
 }
 
 /// Future, returned by `listener_select_proto` which selects a protocol among the ones supported.
 pub struct ListenerSelectFuture<R: AsyncRead + AsyncWrite, I, P> {
-    inner: ListenerSelectState<R, I, P>
+    inner: ListenerSelectState<R, I, P>,
+    /// Names proposed by the dialer that we answered as not available, and that haven't been
+    /// taken with `take_rejected_protocols` yet.
+    rejected: Vec<Bytes>,
+}
+
+impl<R: AsyncRead + AsyncWrite, I, P> ListenerSelectFuture<R, I, P> {
+    /// Returns the names of the protocols that the dialer proposed and that we answered as not
+    /// available since the last call to this method.
+    #[inline]
+    pub fn take_rejected_protocols(&mut self) -> Vec<Bytes> {
+        mem::replace(&mut self.rejected, Vec::new())
+    }
 }
 
 enum ListenerSelectState<R: AsyncRead + AsyncWrite, I, P> {
@@ -131,6 +144,9 @@ where
                                 }
                             }
                             trace!("requested: {:?}, response: {:?}", name, send_back);
+                            if outcome.is_none() {
+                                self.rejected.push(name);
+                            }
                             let sender = listener.send(send_back);
                             self.inner = ListenerSelectState::Outgoing { sender, protocols, outcome }
                         }
@@ -0,0 +1,227 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Contains the `Negotiated` wrapper, which is the socket produced by the lazy negotiation mode
+//! of the dialer.
+
+use bytes::Bytes;
+use futures::{Async, Poll, Stream};
+use length_delimited::LengthDelimitedFramedRead;
+use protocol::MULTISTREAM_PROTOCOL_WITH_LF;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+use unsigned_varint::encode;
+use ProtocolChoiceError;
+
+/// Maximum number of bytes of application data that we buffer before the protocol proposal has
+/// been written to the socket.
+const MAX_PENDING_WRITE: usize = 8 * 1024;
+
+/// Socket on which a protocol has been proposed optimistically, without waiting for the remote to
+/// accept it.
+///
+/// Writing to a `Negotiated` sends the proposal together with the first bytes of data. Reading
+/// from it first processes the answer of the remote. If the remote refused the protocol, reading
+/// produces an error of kind `InvalidData` whose inner error is a `ProtocolChoiceError`.
+///
+/// > **Note**: If the application never reads from the socket, a refusal from the remote will go
+/// >           unnoticed.
+pub struct Negotiated<R> {
+    /// The socket, wrapped so that we can decode the answer of the listener without ever reading
+    /// past it.
+    inner: LengthDelimitedFramedRead<Bytes, R>,
+    /// Data that must be written to the socket before anything else. Contains the handshake and
+    /// the protocol proposal, and possibly the first bytes of application data.
+    pending_write: Vec<u8>,
+    /// State of the negotiation.
+    state: State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// We are waiting for the handshake of the listener.
+    AwaitingHandshake { protocol: Bytes },
+    /// We are waiting for the listener to accept the protocol.
+    AwaitingAck { protocol: Bytes },
+    /// The protocol has been accepted ; the socket is used transparently.
+    Completed,
+}
+
+impl<R> Negotiated<R>
+where
+    R: AsyncRead + AsyncWrite,
+{
+    /// Wraps around a socket on which no negotiation has happened yet, and prepares the handshake
+    /// and the proposal of `protocol`.
+    pub(crate) fn propose(inner: R, protocol: Bytes) -> Negotiated<R> {
+        let mut pending_write = Vec::with_capacity(MULTISTREAM_PROTOCOL_WITH_LF.len() + protocol.len() + 4);
+        let mut len_buf = encode::usize_buffer();
+        pending_write.extend_from_slice(encode::usize(MULTISTREAM_PROTOCOL_WITH_LF.len(), &mut len_buf));
+        pending_write.extend_from_slice(MULTISTREAM_PROTOCOL_WITH_LF);
+        pending_write.extend_from_slice(encode::usize(protocol.len() + 1, &mut len_buf));
+        pending_write.extend_from_slice(&protocol);
+        pending_write.push(b'\n');
+
+        Negotiated {
+            inner: LengthDelimitedFramedRead::new(inner),
+            pending_write,
+            state: State::AwaitingHandshake { protocol },
+        }
+    }
+
+    /// Wraps around a socket on which a protocol has already been negotiated.
+    #[inline]
+    pub fn completed(inner: R) -> Negotiated<R> {
+        Negotiated {
+            inner: LengthDelimitedFramedRead::new(inner),
+            pending_write: Vec::new(),
+            state: State::Completed,
+        }
+    }
+
+    /// Returns true if the remote has accepted the protocol.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.state == State::Completed
+    }
+
+    /// Writes the content of `pending_write` to the socket.
+    fn flush_pending(&mut self) -> Result<(), IoError> {
+        while !self.pending_write.is_empty() {
+            let written = self.inner.get_mut().write(&self.pending_write)?;
+            if written == 0 {
+                return Err(IoError::new(IoErrorKind::WriteZero, "failed to write the protocol proposal"));
+            }
+            self.pending_write.drain(..written);
+        }
+        Ok(())
+    }
+
+    /// Processes the answer of the listener until the protocol has been accepted.
+    fn poll_answer(&mut self) -> Poll<(), IoError> {
+        loop {
+            let protocol = match self.state {
+                State::Completed => return Ok(Async::Ready(())),
+                State::AwaitingHandshake { ref protocol } |
+                State::AwaitingAck { ref protocol } => protocol.clone(),
+            };
+
+            let frame = match self.inner.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => {
+                    return Err(IoError::new(IoErrorKind::UnexpectedEof, "remote closed during negotiation"));
+                },
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            self.state = match self.state {
+                State::AwaitingHandshake { .. } if frame == MULTISTREAM_PROTOCOL_WITH_LF => {
+                    State::AwaitingAck { protocol }
+                },
+                State::AwaitingAck { .. } if frame.len() == protocol.len() + 1 &&
+                    frame.starts_with(&protocol) && frame.ends_with(b"\n") => {
+                    trace!("lazily negotiated {:?}", protocol);
+                    State::Completed
+                },
+                State::AwaitingAck { .. } if frame == b"na\n"[..] => {
+                    debug!("remote refused lazily proposed {:?}", protocol);
+                    let err = ProtocolChoiceError::NoProtocolFound;
+                    return Err(IoError::new(IoErrorKind::InvalidData, err));
+                },
+                _ => {
+                    let err = ProtocolChoiceError::UnexpectedMessage;
+                    return Err(IoError::new(IoErrorKind::InvalidData, err));
+                },
+            };
+        }
+    }
+}
+
+impl<R> Read for Negotiated<R>
+where
+    R: AsyncRead + AsyncWrite,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        // The remote can't answer before it has received our proposal.
+        match self.flush_pending() {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => (),
+            Err(err) => return Err(err),
+        }
+
+        match self.poll_answer()? {
+            Async::Ready(()) => self.inner.get_mut().read(buf),
+            Async::NotReady => Err(IoErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<R> AsyncRead for Negotiated<R>
+where
+    R: AsyncRead + AsyncWrite,
+{
+}
+
+impl<R> Write for Negotiated<R>
+where
+    R: AsyncRead + AsyncWrite,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        if self.pending_write.is_empty() {
+            return self.inner.get_mut().write(buf);
+        }
+
+        // We append the data to the proposal, so that both are sent together.
+        let len = cmp::min(buf.len(), MAX_PENDING_WRITE.saturating_sub(self.pending_write.len()));
+        self.pending_write.extend_from_slice(&buf[..len]);
+        match self.flush_pending() {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock && len != 0 => (),
+            Err(err) => return Err(err),
+        }
+
+        if len == 0 && !buf.is_empty() {
+            // The buffer was full, but we managed to flush it.
+            self.inner.get_mut().write(buf)
+        } else {
+            Ok(len)
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.flush_pending()?;
+        self.inner.get_mut().flush()
+    }
+}
+
+impl<R> AsyncWrite for Negotiated<R>
+where
+    R: AsyncRead + AsyncWrite,
+{
+    fn shutdown(&mut self) -> Poll<(), IoError> {
+        match self.flush_pending() {
+            Ok(()) => self.inner.get_mut().shutdown(),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(err) => Err(err),
+        }
+    }
+}
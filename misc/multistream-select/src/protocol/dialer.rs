@@ -32,6 +32,8 @@ use tokio_io::codec::length_delimited::FramedWrite as LengthDelimitedFramedWrite
 use tokio_io::{AsyncRead, AsyncWrite};
 use unsigned_varint::decode;
 
+/// Maximum number of protocols that we accept in a list sent by the listener.
+const MAX_PROTOCOLS_IN_LIST: usize = 1000;
 
 /// Wraps around a `AsyncRead+AsyncWrite`. Assumes that we're on the dialer's side. Produces and
 /// accepts messages.
@@ -149,7 +151,7 @@ where
             } else {
                 // A varint number of protocols
                 let (num_protocols, mut remaining) = decode::usize(&frame)?;
-                if num_protocols > 1000 { // TODO: configurable limit
+                if num_protocols > MAX_PROTOCOLS_IN_LIST { // TODO: configurable limit
                     return Err(MultistreamSelectError::VarintParseError("too many protocols".into()))
                 }
                 let mut out = Vec::with_capacity(num_protocols);
@@ -31,8 +31,9 @@ use dialer_select::{dialer_select_proto_parallel, dialer_select_proto_serial};
 use futures::Future;
 use futures::{Sink, Stream};
 use protocol::{Dialer, DialerToListenerMessage, Listener, ListenerToDialerMessage};
+use tokio_io::io::{read_exact, write_all};
 use ProtocolChoiceError;
-use {dialer_select_proto, listener_select_proto};
+use {dialer_select_proto, dialer_select_proto_lazy, listener_select_proto};
 
 #[test]
 fn negotiate_with_self_succeeds() {
@@ -205,3 +206,86 @@ fn select_proto_serial() {
     assert_eq!(dialer_chosen, 3);
     assert_eq!(listener_chosen, 1);
 }
+
+#[test]
+fn select_proto_lazy() {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+
+    let server = listener
+        .incoming()
+        .into_future()
+        .map(|s| s.0.unwrap())
+        .map_err(|(e, _)| e.into())
+        .and_then(move |connec| {
+            let protos = vec![
+                (Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 0),
+                (Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 1),
+            ].into_iter();
+            listener_select_proto(connec, protos)
+        })
+        .and_then(|(proto, connec)| {
+            read_exact(connec, [0; 5])
+                .and_then(|(connec, buf)| {
+                    assert_eq!(&buf, b"hello");
+                    write_all(connec, b"world")
+                })
+                .map(move |_| proto)
+                .map_err(ProtocolChoiceError::from)
+        });
+
+    let client = TcpStream::connect(&listener_addr)
+        .from_err()
+        .and_then(move |connec| {
+            let protos = vec![(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 3)].into_iter();
+            dialer_select_proto_lazy(connec, protos)
+        })
+        .and_then(|(proto, connec)| {
+            // Data is sent before the listener has accepted the protocol.
+            write_all(connec, b"hello")
+                .and_then(|(connec, _)| read_exact(connec, [0; 5]))
+                .map(move |(connec, buf)| {
+                    assert_eq!(&buf, b"world");
+                    assert!(connec.is_completed());
+                    proto
+                })
+                .map_err(ProtocolChoiceError::from)
+        });
+
+    let (dialer_chosen, listener_chosen) =
+        tokio_current_thread::block_on_all(client.join(server)).unwrap();
+    assert_eq!(dialer_chosen, 3);
+    assert_eq!(listener_chosen, 1);
+}
+
+#[test]
+fn select_proto_lazy_refused() {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+
+    let server = listener
+        .incoming()
+        .into_future()
+        .map(|s| s.0.unwrap())
+        .map_err(|(e, _)| e.into())
+        .and_then(move |connec| {
+            let protos = vec![(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 0)].into_iter();
+            listener_select_proto(connec, protos).map(|r| r.0)
+        });
+
+    let client = TcpStream::connect(&listener_addr)
+        .from_err()
+        .and_then(move |connec| {
+            let protos = vec![(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 1)].into_iter();
+            dialer_select_proto_lazy(connec, protos)
+        })
+        .and_then(|(_, connec)| {
+            write_all(connec, b"hello")
+                .and_then(|(connec, _)| read_exact(connec, [0; 5]))
+                .map(|_| ())
+                .map_err(ProtocolChoiceError::from)
+        });
+
+    // The dialer fails when reading the refusal, which makes the join fail.
+    assert!(tokio_current_thread::block_on_all(client.join(server)).is_err());
+}
@@ -0,0 +1,325 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Commands of the debug node.
+//!
+//! The same commands can be entered as lines in the interactive prompt, or sent as JSON-RPC 2.0
+//! requests, one per line, when the node runs with `--json-rpc`.
+//!
+//! | Prompt                      | JSON-RPC method and parameters       |
+//! |-----------------------------|--------------------------------------|
+//! | `dial <multiaddr>`          | `dial`, `[multiaddr]`                |
+//! | `ping <multiaddr>`          | `ping`, `[multiaddr]`                |
+//! | `peers`                     | `peers`, `[]`                        |
+//! | `subscribe <topic>`         | `subscribe`, `[topic]`               |
+//! | `publish <topic> <message>` | `publish`, `[topic, message]`        |
+//! | `dht put <key> <value>`     | `dht_put`, `[key, value]`            |
+//! | `dht get <key>`             | `dht_get`, `[key]`                   |
+//! | `help`                      |                                      |
+//! | `quit`                      | `quit`, `[]`                         |
+
+use libp2p::Multiaddr;
+use serde_json::{self, Value};
+
+/// Command sent to the debug node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Connects to a remote and opens the floodsub and Kademlia substreams.
+    Dial(Multiaddr),
+    /// Opens a ping substream to a remote and measures the round-trip time.
+    Ping(Multiaddr),
+    /// Lists the addresses of the remotes we have substreams with.
+    Peers,
+    /// Subscribes to a floodsub topic.
+    Subscribe(String),
+    /// Publishes a message on a floodsub topic.
+    Publish {
+        /// Name of the topic.
+        topic: String,
+        /// Content of the message.
+        data: Vec<u8>,
+    },
+    /// Stores a record locally, where it is served to the remotes that ask for it.
+    DhtPut {
+        /// Key of the record.
+        key: Vec<u8>,
+        /// Value of the record.
+        value: Vec<u8>,
+    },
+    /// Asks the connected remotes for the value of a record.
+    DhtGet {
+        /// Key of the record.
+        key: Vec<u8>,
+    },
+    /// Prints the list of commands.
+    Help,
+    /// Stops the node.
+    Quit,
+}
+
+/// Request read in JSON-RPC mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcRequest {
+    /// Identifier of the request, to copy in the response. `None` for notifications.
+    pub id: Option<Value>,
+    /// The command, or the error to send back if the request is invalid.
+    pub command: Result<Command, RpcError>,
+}
+
+/// Error of a JSON-RPC request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    /// Code of the error, as defined by the JSON-RPC specification.
+    pub code: i64,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl RpcError {
+    /// The request is not valid JSON.
+    pub const PARSE_ERROR: i64 = -32700;
+    /// The request is not a valid JSON-RPC request.
+    pub const INVALID_REQUEST: i64 = -32600;
+    /// The method doesn't exist.
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    /// The parameters of the method are invalid.
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The command failed.
+    pub const COMMAND_FAILED: i64 = -32000;
+
+    /// Builds an error.
+    #[inline]
+    pub fn new<S: Into<String>>(code: i64, message: S) -> RpcError {
+        RpcError {
+            code: code,
+            message: message.into(),
+        }
+    }
+
+    /// Serializes the error as the `error` member of a response.
+    pub fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl Command {
+    /// Parses a line of the interactive prompt.
+    ///
+    /// Returns `Ok(None)` if the line is empty.
+    pub fn parse_line(line: &str) -> Result<Option<Command>, String> {
+        let line = line.trim();
+        let (name, rest) = split_word(line);
+        let command = match name {
+            "" => return Ok(None),
+            "dial" => Command::Dial(parse_multiaddr(rest)?),
+            "ping" => Command::Ping(parse_multiaddr(rest)?),
+            "peers" => Command::Peers,
+            "subscribe" => Command::Subscribe(non_empty(rest, "topic")?.to_owned()),
+            "publish" => {
+                let (topic, data) = split_word(rest);
+                Command::Publish {
+                    topic: non_empty(topic, "topic")?.to_owned(),
+                    data: data.as_bytes().to_vec(),
+                }
+            },
+            "dht" => {
+                let (verb, rest) = split_word(rest);
+                let (key, value) = split_word(rest);
+                let key = non_empty(key, "key")?.as_bytes().to_vec();
+                match verb {
+                    "put" => Command::DhtPut { key: key, value: value.as_bytes().to_vec() },
+                    "get" => Command::DhtGet { key: key },
+                    _ => return Err("expected `dht put <key> <value>` or `dht get <key>`".to_owned()),
+                }
+            },
+            "help" => Command::Help,
+            "quit" | "exit" => Command::Quit,
+            other => return Err(format!("unknown command `{}`, type `help` for the list", other)),
+        };
+
+        Ok(Some(command))
+    }
+
+    /// Parses a line received in JSON-RPC mode.
+    ///
+    /// Returns `None` if the line is empty.
+    pub fn parse_rpc(line: &str) -> Option<RpcRequest> {
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => return Some(RpcRequest {
+                id: Some(Value::Null),
+                command: Err(RpcError::new(RpcError::PARSE_ERROR, err.to_string())),
+            }),
+        };
+
+        let id = request.get("id").cloned();
+        let command = Command::from_rpc(&request);
+        Some(RpcRequest { id: id, command: command })
+    }
+
+    /// Builds a command from a decoded JSON-RPC request.
+    fn from_rpc(request: &Value) -> Result<Command, RpcError> {
+        if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(RpcError::new(RpcError::INVALID_REQUEST, "expected `\"jsonrpc\": \"2.0\"`"));
+        }
+
+        let method = request.get("method")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::new(RpcError::INVALID_REQUEST, "missing method"))?;
+        let params = match request.get("params") {
+            None => Vec::new(),
+            Some(&Value::Array(ref params)) => params.iter()
+                .map(|param| param.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, "parameters must be strings"))?,
+            Some(_) => return Err(RpcError::new(RpcError::INVALID_PARAMS, "parameters must be an array")),
+        };
+
+        let expect = |count: usize| -> Result<(), RpcError> {
+            if params.len() == count {
+                Ok(())
+            } else {
+                Err(RpcError::new(RpcError::INVALID_PARAMS,
+                    format!("`{}` expects {} parameter(s), got {}", method, count, params.len())))
+            }
+        };
+        let invalid = |message: String| RpcError::new(RpcError::INVALID_PARAMS, message);
+
+        match method {
+            "dial" => {
+                expect(1)?;
+                parse_multiaddr(&params[0]).map(Command::Dial).map_err(invalid)
+            },
+            "ping" => {
+                expect(1)?;
+                parse_multiaddr(&params[0]).map(Command::Ping).map_err(invalid)
+            },
+            "peers" => expect(0).map(|()| Command::Peers),
+            "subscribe" => {
+                expect(1)?;
+                Ok(Command::Subscribe(params[0].clone()))
+            },
+            "publish" => {
+                expect(2)?;
+                Ok(Command::Publish { topic: params[0].clone(), data: params[1].as_bytes().to_vec() })
+            },
+            "dht_put" => {
+                expect(2)?;
+                Ok(Command::DhtPut {
+                    key: params[0].as_bytes().to_vec(),
+                    value: params[1].as_bytes().to_vec(),
+                })
+            },
+            "dht_get" => {
+                expect(1)?;
+                Ok(Command::DhtGet { key: params[0].as_bytes().to_vec() })
+            },
+            "quit" => expect(0).map(|()| Command::Quit),
+            other => Err(RpcError::new(RpcError::METHOD_NOT_FOUND, format!("unknown method `{}`", other))),
+        }
+    }
+}
+
+/// Text printed by the `help` command.
+pub const HELP: &str = "\
+dial <multiaddr>            connect to a remote
+ping <multiaddr>            measure the round-trip time to a remote
+peers                       list the remotes we have substreams with
+subscribe <topic>           subscribe to a floodsub topic
+publish <topic> <message>   publish a message on a floodsub topic
+dht put <key> <value>       store a record and serve it to the remotes
+dht get <key>               ask the connected remotes for a record
+quit                        stop the node";
+
+/// Splits `text` after its first word. Returns the word and the rest of the text, trimmed.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_left();
+    match text.find(char::is_whitespace) {
+        Some(pos) => (&text[..pos], text[pos..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Returns an error mentioning `what` if `text` is empty.
+fn non_empty<'a>(text: &'a str, what: &str) -> Result<&'a str, String> {
+    if text.is_empty() {
+        Err(format!("missing {}", what))
+    } else {
+        Ok(text)
+    }
+}
+
+/// Parses a multiaddress, with a readable error.
+fn parse_multiaddr(text: &str) -> Result<Multiaddr, String> {
+    non_empty(text, "multiaddr")?
+        .parse()
+        .map_err(|err| format!("invalid multiaddr `{}`: {:?}", text, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, RpcError};
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(Command::parse_line("   "), Ok(None));
+        assert_eq!(
+            Command::parse_line("dial /ip4/127.0.0.1/tcp/4001"),
+            Ok(Some(Command::Dial("/ip4/127.0.0.1/tcp/4001".parse().unwrap())))
+        );
+        assert_eq!(
+            Command::parse_line("publish chat hello  world"),
+            Ok(Some(Command::Publish { topic: "chat".to_owned(), data: b"hello  world".to_vec() }))
+        );
+        assert_eq!(
+            Command::parse_line("dht put foo bar"),
+            Ok(Some(Command::DhtPut { key: b"foo".to_vec(), value: b"bar".to_vec() }))
+        );
+        assert!(Command::parse_line("dht get").is_err());
+        assert!(Command::parse_line("ping not-an-addr").is_err());
+        assert!(Command::parse_line("frobnicate").is_err());
+    }
+
+    #[test]
+    fn parse_rpc() {
+        let request = Command::parse_rpc(r#"{"jsonrpc":"2.0","id":7,"method":"dht_get","params":["foo"]}"#)
+            .unwrap();
+        assert_eq!(request.id, Some(json!(7)));
+        assert_eq!(request.command, Ok(Command::DhtGet { key: b"foo".to_vec() }));
+
+        let request = Command::parse_rpc(r#"{"jsonrpc":"2.0","id":8,"method":"ping","params":[]}"#)
+            .unwrap();
+        assert_eq!(request.command.unwrap_err().code, RpcError::INVALID_PARAMS);
+
+        let request = Command::parse_rpc(r#"{"jsonrpc":"2.0","id":9,"method":"reboot"}"#).unwrap();
+        assert_eq!(request.command.unwrap_err().code, RpcError::METHOD_NOT_FOUND);
+
+        let request = Command::parse_rpc("{").unwrap();
+        assert_eq!(request.id, Some(json!(null)));
+        assert_eq!(request.command.unwrap_err().code, RpcError::PARSE_ERROR);
+
+        assert!(Command::parse_rpc("").is_none());
+    }
+}
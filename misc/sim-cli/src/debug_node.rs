@@ -0,0 +1,453 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Interactive debug node of the `node` subcommand.
+//!
+//! The node listens over TCP with secio and mplex, and supports ping, floodsub and Kademlia on
+//! each connection. It is driven by the commands of the `command` module, read from the
+//! standard input either as prompt lines or as JSON-RPC requests. Messages received on the
+//! subscribed topics are printed as they arrive, or sent as `message` notifications in JSON-RPC
+//! mode.
+//!
+//! > **Note**: `dht get` sends a single `GET_VALUE` request to each connected remote and doesn't
+//! >           follow the closer peers they return, and `dht put` only stores the record locally,
+//! >           where the remotes can fetch it. The Kademlia implementation doesn't support
+//! >           `PUT_VALUE` yet.
+
+use command::{Command, RpcError, HELP};
+use futures::{future, prelude::*, sync::mpsc};
+use libp2p::core::upgrade;
+use libp2p::floodsub::{FloodSubController, FloodSubFuture, FloodSubUpgrade, TopicBuilder};
+use libp2p::kad::{KadConnecConfig, KadConnecController, KadIncomingRequest};
+use libp2p::mplex::MplexConfig;
+use libp2p::ping::{Ping, PingOutput};
+use libp2p::secio::{SecioConfig, SecioKeyPair, SecioOutput};
+use libp2p::tcp::TcpConfig;
+use libp2p::tokio_io::{AsyncRead, AsyncWrite};
+use libp2p::{swarm, Multiaddr, Transport};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{error, iter, thread};
+use tokio_current_thread;
+
+/// Output of the protocols negotiated on the substreams of the debug node.
+enum DebugOutput {
+    /// We sent a ping. Produces the round-trip time once the pong arrives.
+    Pinger(Box<Future<Item = Duration, Error = IoError> + Send>),
+    /// A remote is pinging us. Must be driven in order to answer.
+    Ponger(Box<Future<Item = (), Error = IoError> + Send>),
+    /// Floodsub substream.
+    FloodSub(FloodSubFuture),
+    /// Kademlia substream. The stream produces the requests of the remote.
+    Kad(KadConnecController, Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send>),
+}
+
+/// Turns the output of the ping protocol into a `DebugOutput`, and sends the ping if we dialed.
+fn ping_output<S>(output: PingOutput<S, ()>) -> DebugOutput
+where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    match output {
+        PingOutput::Pinger(mut pinger) => {
+            let started = Instant::now();
+            pinger.ping(());
+            let pong = pinger.into_future()
+                .map_err(|(err, _)| err)
+                .and_then(move |(pong, _)| match pong {
+                    Some(()) => Ok(started.elapsed()),
+                    None => Err(IoError::new(IoErrorKind::UnexpectedEof, "substream closed before the pong")),
+                });
+            DebugOutput::Pinger(Box::new(pong))
+        },
+        PingOutput::Ponger(ponger) => DebugOutput::Ponger(Box::new(ponger)),
+    }
+}
+
+/// Turns the output of the Kademlia protocol into a `DebugOutput`.
+fn kad_output(
+    (controller, requests): (KadConnecController, Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send>)
+) -> DebugOutput {
+    DebugOutput::Kad(controller, requests)
+}
+
+/// State shared between the swarm and the commands.
+#[derive(Default)]
+struct State {
+    /// Number of open floodsub and Kademlia substreams, by remote address.
+    peers: HashMap<Multiaddr, usize>,
+    /// Kademlia controllers, by remote address.
+    kad: HashMap<Multiaddr, KadConnecController>,
+    /// Records stored with `dht put`.
+    records: HashMap<Vec<u8>, Vec<u8>>,
+    /// Identifiers of the `ping` requests waiting for a ping substream, by remote address.
+    pings: HashMap<Multiaddr, VecDeque<Option<Value>>>,
+}
+
+impl State {
+    /// Records that a substream with `addr` has closed.
+    fn substream_closed(&mut self, addr: &Multiaddr) {
+        let remaining = match self.peers.get_mut(addr) {
+            Some(count) => { *count -= 1; *count }
+            None => return,
+        };
+        if remaining == 0 {
+            self.peers.remove(addr);
+        }
+    }
+}
+
+/// Where the results of the commands are written.
+#[derive(Debug, Copy, Clone)]
+struct Console {
+    /// True to write JSON-RPC responses and notifications instead of text.
+    json_rpc: bool,
+}
+
+impl Console {
+    /// Answers the command whose identifier is `id`. `text` is printed instead of `result` in
+    /// prompt mode, unless empty.
+    fn reply(&self, id: Option<Value>, result: Result<Value, RpcError>, text: String) {
+        if self.json_rpc {
+            // Notifications don't get a response.
+            let id = match id {
+                Some(id) => id,
+                None => return,
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() }),
+            };
+            println!("{}", response);
+        } else {
+            match result {
+                Ok(_) if text.is_empty() => (),
+                Ok(_) => println!("{}", text),
+                Err(err) => println!("error: {}", err.message),
+            }
+        }
+    }
+
+    /// Reports an event that isn't the answer to a command.
+    fn notify(&self, method: &str, params: Value, text: String) {
+        if self.json_rpc {
+            println!("{}", json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+        } else {
+            println!("{}", text);
+        }
+    }
+}
+
+/// Runs a debug node listening on `listen` until the standard input is closed or the `quit`
+/// command is received.
+pub fn run(listen: Multiaddr, json_rpc: bool) -> Result<(), Box<error::Error>> {
+    let console = Console { json_rpc: json_rpc };
+    let state = Arc::new(Mutex::new(State::default()));
+
+    let key = SecioKeyPair::ed25519_generated()
+        .map_err(|err| IoError::new(IoErrorKind::Other, err.to_string()))?;
+    let local_peer_id = key.to_peer_id();
+
+    let transport = TcpConfig::new()
+        .with_upgrade(SecioConfig::new(key))
+        .map(|out: SecioOutput<_>, _| out.stream)
+        .with_upgrade(MplexConfig::new())
+        .map(|val, _| ((), val))
+        .into_connection_reuse()
+        .map(|((), val), _| val);
+
+    let (floodsub_upgrade, floodsub_rx) = FloodSubUpgrade::new(local_peer_id.clone());
+    let floodsub = FloodSubController::new(&floodsub_upgrade);
+
+    let ping_upgrade = upgrade::map(Ping::default(), ping_output);
+    let floodsub_upgrade = upgrade::map(floodsub_upgrade, DebugOutput::FloodSub);
+    let kad_upgrade = upgrade::map(KadConnecConfig::new(), kad_output);
+    let listen_upgrade = upgrade::or(ping_upgrade, upgrade::or(floodsub_upgrade.clone(), kad_upgrade.clone()));
+
+    let (swarm_controller, swarm_events) = swarm(
+        transport.clone().with_upgrade(listen_upgrade),
+        {
+            let state = state.clone();
+            move |output, addr: Multiaddr| -> Box<Future<Item = (), Error = IoError>> {
+                handle_substream(&state, console, output, addr)
+            }
+        },
+    );
+
+    let listen_addr = swarm_controller.listen_on(listen.clone())
+        .map_err(|addr| IoError::new(IoErrorKind::Other, format!("can't listen on {}", addr)))?;
+    console.notify(
+        "listening",
+        json!({ "peer_id": local_peer_id.to_base58(), "address": listen_addr.to_string() }),
+        format!("Local peer id: {}\nListening on {}", local_peer_id.to_base58(), listen_addr),
+    );
+
+    let messages = floodsub_rx.for_each(move |message| {
+        let topics = message.topics.into_iter().map(|topic| topic.into_string()).collect::<Vec<_>>();
+        let data = String::from_utf8_lossy(&message.data).into_owned();
+        console.notify(
+            "message",
+            json!({ "source": message.source.to_string(), "topics": topics, "data": data }),
+            format!("< [{}] {}", topics.join(","), data),
+        );
+        Ok(())
+    });
+
+    let commands = read_lines().and_then(move |line| {
+        let (id, command) = if json_rpc {
+            match Command::parse_rpc(&line) {
+                Some(request) => (request.id, request.command),
+                None => return Ok(true),
+            }
+        } else {
+            match Command::parse_line(&line) {
+                Ok(Some(command)) => (Some(Value::Null), Ok(command)),
+                Ok(None) => return Ok(true),
+                Err(err) => (Some(Value::Null), Err(RpcError::new(RpcError::INVALID_PARAMS, err))),
+            }
+        };
+
+        let command = match command {
+            Ok(command) => command,
+            Err(err) => {
+                console.reply(id, Err(err), String::new());
+                return Ok(true);
+            },
+        };
+
+        match command {
+            Command::Dial(addr) => {
+                let floodsub_dial = swarm_controller.dial(addr.clone(), transport.clone().with_upgrade(floodsub_upgrade.clone()));
+                let kad_dial = swarm_controller.dial(addr.clone(), transport.clone().with_upgrade(kad_upgrade.clone()));
+                match (floodsub_dial, kad_dial) {
+                    (Ok(floodsub_dial), Ok(kad_dial)) => {
+                        tokio_current_thread::spawn(floodsub_dial.join(kad_dial).then(move |result| {
+                            match result {
+                                Ok(_) => console.reply(id, Ok(Value::Null), format!("Connected to {}", addr)),
+                                Err(err) => console.reply(id, Err(command_failed(&addr, err)), String::new()),
+                            }
+                            Ok::<_, ()>(())
+                        }));
+                    },
+                    _ => console.reply(id, Err(unsupported(&addr)), String::new()),
+                }
+            },
+
+            Command::Ping(addr) => {
+                let ping_upgrade = upgrade::map(Ping::default(), ping_output);
+                match swarm_controller.dial(addr.clone(), transport.clone().with_upgrade(ping_upgrade)) {
+                    Ok(dial) => {
+                        state.lock().unwrap().pings.entry(addr.clone()).or_insert_with(VecDeque::new).push_back(id);
+                        let state = state.clone();
+                        tokio_current_thread::spawn(dial.or_else(move |err| {
+                            // The dial failed before the handler could take the identifier.
+                            let id = state.lock().unwrap().pings.get_mut(&addr).and_then(VecDeque::pop_front);
+                            if let Some(id) = id {
+                                console.reply(id, Err(command_failed(&addr, err)), String::new());
+                            }
+                            Ok::<_, ()>(())
+                        }));
+                    },
+                    Err(addr) => console.reply(id, Err(unsupported(&addr)), String::new()),
+                }
+            },
+
+            Command::Peers => {
+                let peers = state.lock().unwrap().peers.keys().map(|addr| addr.to_string()).collect::<Vec<_>>();
+                let text = if peers.is_empty() { "No peer".to_owned() } else { peers.join("\n") };
+                console.reply(id, Ok(json!(peers)), text);
+            },
+
+            Command::Subscribe(topic) => {
+                floodsub.subscribe(&TopicBuilder::new(topic.clone()).build());
+                console.reply(id, Ok(Value::Null), format!("Subscribed to {}", topic));
+            },
+
+            Command::Publish { topic, data } => {
+                floodsub.publish(&TopicBuilder::new(topic).build(), data);
+                console.reply(id, Ok(Value::Null), String::new());
+            },
+
+            Command::DhtPut { key, value } => {
+                state.lock().unwrap().records.insert(key, value);
+                console.reply(id, Ok(Value::Null), "Stored".to_owned());
+            },
+
+            Command::DhtGet { key } => {
+                let (local, controllers) = {
+                    let state = state.lock().unwrap();
+                    (state.records.get(&key).cloned(), state.kad.values().cloned().collect::<Vec<_>>())
+                };
+
+                if let Some(value) = local {
+                    reply_record(console, id, Some(value));
+                } else if controllers.is_empty() {
+                    let err = RpcError::new(RpcError::COMMAND_FAILED, "not connected to any Kademlia peer");
+                    console.reply(id, Err(err), String::new());
+                } else {
+                    let queries = controllers.into_iter()
+                        .map(|controller| controller.get_value(&key).then(|result| {
+                            Ok::<_, ()>(result.ok().and_then(|(record, _)| record))
+                        }))
+                        .collect::<Vec<_>>();
+                    tokio_current_thread::spawn(future::join_all(queries).map(move |records| {
+                        reply_record(console, id, records.into_iter().filter_map(|r| r).next());
+                    }));
+                }
+            },
+
+            Command::Help => console.reply(id, Ok(Value::Null), HELP.to_owned()),
+
+            Command::Quit => {
+                console.reply(id, Ok(Value::Null), String::new());
+                return Ok(false);
+            },
+        }
+
+        Ok(true)
+    });
+
+    let commands = commands
+        .take_while(|keep_going| Ok(*keep_going))
+        .for_each(|_| Ok(()))
+        .map_err(|()| IoError::new(IoErrorKind::Other, "reading the standard input failed"));
+
+    let node = swarm_events
+        .for_each(|_| Ok(()))
+        .select(messages)
+        .map(|_| ())
+        .map_err(|(err, _)| err)
+        .select(commands)
+        .map(|_| ())
+        .map_err(|(err, _)| err);
+
+    tokio_current_thread::block_on_all(node)?;
+    Ok(())
+}
+
+/// Handles a substream negotiated by the swarm, and returns the future that drives it.
+fn handle_substream(state: &Arc<Mutex<State>>, console: Console, output: DebugOutput, addr: Multiaddr)
+    -> Box<Future<Item = (), Error = IoError>>
+{
+    match output {
+        DebugOutput::Pinger(pong) => {
+            let id = match state.lock().unwrap().pings.get_mut(&addr).and_then(VecDeque::pop_front) {
+                Some(id) => id,
+                None => return Box::new(future::ok(())),
+            };
+            Box::new(pong.then(move |result| {
+                match result {
+                    Ok(rtt) => {
+                        let rtt_ms = rtt.as_secs() * 1000 + u64::from(rtt.subsec_nanos() / 1_000_000);
+                        console.reply(id, Ok(json!({ "rtt_ms": rtt_ms })),
+                                      format!("Pong from {} in {} ms", addr, rtt_ms));
+                    },
+                    Err(err) => console.reply(id, Err(command_failed(&addr, err)), String::new()),
+                }
+                Ok(())
+            }))
+        },
+
+        DebugOutput::Ponger(ponger) => ponger,
+
+        DebugOutput::FloodSub(future) => {
+            *state.lock().unwrap().peers.entry(addr.clone()).or_insert(0) += 1;
+            let state = state.clone();
+            Box::new(future.then(move |result| {
+                state.lock().unwrap().substream_closed(&addr);
+                result
+            }))
+        },
+
+        DebugOutput::Kad(controller, requests) => {
+            {
+                let mut state = state.lock().unwrap();
+                *state.peers.entry(addr.clone()).or_insert(0) += 1;
+                state.kad.insert(addr.clone(), controller);
+            }
+
+            let records_state = state.clone();
+            let answer = requests.for_each(move |request| {
+                match request {
+                    KadIncomingRequest::GetValue { key, responder } => {
+                        let record = records_state.lock().unwrap().records.get(&key).cloned();
+                        responder.respond(record, iter::empty());
+                    },
+                    KadIncomingRequest::FindNode { responder, .. } => responder.respond(iter::empty()),
+                    KadIncomingRequest::GetProviders { responder, .. } =>
+                        responder.respond(iter::empty(), iter::empty()),
+                    KadIncomingRequest::PutValue { key, value } => {
+                        records_state.lock().unwrap().records.insert(key, value);
+                    },
+                    KadIncomingRequest::AddProvider { .. } => (),
+                    KadIncomingRequest::PingPong => (),
+                }
+                Ok(())
+            });
+
+            let state = state.clone();
+            Box::new(answer.then(move |result| {
+                let mut state = state.lock().unwrap();
+                state.kad.remove(&addr);
+                state.substream_closed(&addr);
+                result
+            }))
+        },
+    }
+}
+
+/// Answers a `dht get` command.
+fn reply_record(console: Console, id: Option<Value>, record: Option<Vec<u8>>) {
+    match record {
+        Some(value) => {
+            let value = String::from_utf8_lossy(&value).into_owned();
+            console.reply(id, Ok(json!(value)), value.clone());
+        },
+        None => console.reply(id, Ok(Value::Null), "Not found".to_owned()),
+    }
+}
+
+/// Error returned when a command about `addr` fails.
+fn command_failed(addr: &Multiaddr, err: IoError) -> RpcError {
+    RpcError::new(RpcError::COMMAND_FAILED, format!("{}: {}", addr, err))
+}
+
+/// Error returned when `addr` isn't supported by the transport.
+fn unsupported(addr: &Multiaddr) -> RpcError {
+    RpcError::new(RpcError::INVALID_PARAMS, format!("unsupported multiaddr {}", addr))
+}
+
+/// Reads the lines of the standard input from a background thread, since reading the standard
+/// input blocks.
+fn read_lines() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if tx.unbounded_send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
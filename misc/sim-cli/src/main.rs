@@ -0,0 +1,149 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Command-line tool for the simulator.
+//!
+//! - `libp2p-sim run <scenario.toml>` runs a scenario file against the simulator and prints the
+//!   statistics of the nodes, as JSON or CSV. See the `scenario_file` module for the format of
+//!   the file.
+//! - `libp2p-sim node` starts a debug node on the real network, driven by commands read from the
+//!   standard input: `dial`, `ping`, `dht get`, `dht put`, `subscribe` and `publish`. With
+//!   `--json-rpc`, each line must instead be a JSON-RPC 2.0 request. See the `command` module
+//!   for the list of commands.
+
+extern crate env_logger;
+extern crate futures;
+extern crate libp2p;
+extern crate rand;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate structopt;
+extern crate tokio_current_thread;
+extern crate toml;
+
+mod command;
+mod debug_node;
+mod scenario_file;
+
+use libp2p::Multiaddr;
+use scenario_file::ScenarioFile;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Error as IoError};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "libp2p-sim", about = "Runs simulation scenarios and debug nodes")]
+enum Options {
+    #[structopt(name = "run")]
+    /// Run a scenario file against the simulator and print the statistics.
+    Run(RunOpts),
+    #[structopt(name = "node")]
+    /// Start an interactive debug node.
+    Node(NodeOpts),
+}
+
+#[derive(Debug, StructOpt)]
+struct RunOpts {
+    /// Path to the scenario file.
+    #[structopt(parse(from_os_str))]
+    scenario: PathBuf,
+    /// Format of the statistics: `json` or `csv`.
+    #[structopt(short = "f", long = "format", default_value = "json")]
+    format: StatsFormat,
+    /// Write the statistics to this file instead of the standard output.
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct NodeOpts {
+    /// Address to listen on.
+    #[structopt(short = "l", long = "listen", default_value = "/ip4/0.0.0.0/tcp/0", parse(try_from_str))]
+    listen: Multiaddr,
+    /// Read JSON-RPC 2.0 requests instead of commands, and write JSON-RPC responses.
+    #[structopt(long = "json-rpc")]
+    json_rpc: bool,
+}
+
+/// Format of the statistics printed by `run`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StatsFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StatsFormat, String> {
+        match s {
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            other => Err(format!("unknown format `{}`, expected `json` or `csv`", other)),
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let result = match Options::from_args() {
+        Options::Run(opts) => run(opts),
+        Options::Node(opts) => debug_node::run(opts.listen, opts.json_rpc),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Implementation of the `run` subcommand.
+fn run(opts: RunOpts) -> Result<(), Box<Error>> {
+    let scenario = ScenarioFile::from_path(&opts.scenario)
+        .map_err(|err| IoError::new(err.kind(), format!("{}: {}", opts.scenario.display(), err)))?;
+    let stats = scenario.run()?;
+
+    match opts.output {
+        Some(path) => {
+            let file = File::create(path)?;
+            match opts.format {
+                StatsFormat::Json => stats.write_json(file)?,
+                StatsFormat::Csv => stats.write_csv(file)?,
+            }
+        },
+        None => {
+            let stdout = io::stdout();
+            let stdout = stdout.lock();
+            match opts.format {
+                StatsFormat::Json => stats.write_json(stdout)?,
+                StatsFormat::Csv => stats.write_csv(stdout)?,
+            }
+        },
+    }
+
+    Ok(())
+}
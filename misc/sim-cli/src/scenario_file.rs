@@ -0,0 +1,423 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Scenario files of the `run` subcommand.
+//!
+//! A scenario file is a TOML document that describes the simulated network, the faults to inject
+//! and how long to run. Each node runs a swarm built with `SwarmBuilder::sim_plaintext_mplex`
+//! and the identify behaviour, listens on the simulated network, and dials its neighbours in the
+//! topology at the start of the simulation.
+//!
+//! The following statistics are recorded for each node: `connections_established`,
+//! `connections_closed`, `dial_failures` and `peers_identified`.
+//!
+//! # Example
+//!
+//! ```toml
+//! seed = 42
+//! nodes = 20
+//! duration_secs = 120
+//! # Optional. Node configuration whose resource limits apply to every node. Relative paths are
+//! # resolved from the directory of the scenario file.
+//! node_config = "node.toml"
+//!
+//! # `clique`, `ring`, `random` (with `p`), `small-world` (with `k` and `beta`) or
+//! # `scale-free` (with `m`).
+//! [topology]
+//! kind = "small-world"
+//! k = 2
+//! beta = 0.1
+//!
+//! [link]
+//! latency_ms = 50
+//! jitter_ms = 10
+//! loss = 0.01
+//!
+//! # `partition` separates `nodes` from the rest of the network, `heal` removes all the
+//! # partitions, `stop`, `crash` and `restart` apply to each node of `nodes`.
+//! [[events]]
+//! at_secs = 30
+//! action = "partition"
+//! nodes = [0, 1, 2]
+//!
+//! [[events]]
+//! at_secs = 60
+//! action = "heal"
+//! ```
+//!
+//! > **Note**: A restarted node doesn't dial its neighbours again. It gets reconnected when its
+//! >           neighbours dial it, which they don't do on their own either.
+
+use futures::{future, prelude::*};
+use libp2p::core::PublicKey;
+use libp2p::core::nodes::swarm::SwarmEvent;
+use libp2p::core::resource_manager::ResourceLimits;
+use libp2p::identify::{Identify, IdentifyEvent};
+use libp2p::node_config::NodeConfigLoader;
+use libp2p::sim::{LinkModel, LinkParams, Scenario, SimNetwork, SimNodeConfig, Stats, StopMode, Topology};
+use libp2p::{Multiaddr, SwarmBuilder};
+use rand::{SeedableRng, prng::XorShiftRng};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use tokio_current_thread::CurrentThread;
+use toml::{self, value::Table, Value};
+
+/// Simulation described by a scenario file.
+#[derive(Debug, Clone)]
+pub struct ScenarioFile {
+    /// Seed of the random generator used to build the topology.
+    seed: u64,
+    /// Number of nodes of the network.
+    nodes: usize,
+    /// Virtual time to run the simulation for.
+    duration: Duration,
+    /// How the nodes are connected.
+    topology: Topology,
+    /// Characteristics of every link.
+    link: LinkParams,
+    /// Resource limits of every node.
+    limits: Option<ResourceLimits>,
+    /// Faults to inject, in the order of the file.
+    events: Vec<Event>,
+}
+
+/// Fault injected at some point of the simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Virtual time of the fault.
+    pub at: Duration,
+    /// What happens.
+    pub action: Action,
+}
+
+/// Fault of an `Event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Separates the nodes from the rest of the network.
+    Partition(Vec<usize>),
+    /// Removes all the partitions.
+    Heal,
+    /// Stops each of the nodes.
+    Stop(Vec<usize>, StopMode),
+    /// Brings each of the nodes back online.
+    Restart(Vec<usize>),
+}
+
+impl ScenarioFile {
+    /// Reads and parses a scenario file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ScenarioFile, IoError> {
+        let path = path.as_ref();
+        let document = fs::read_to_string(path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        ScenarioFile::parse(&document, base_dir)
+    }
+
+    /// Parses a scenario file. `base_dir` is the directory relative paths are resolved from.
+    pub fn parse(document: &str, base_dir: &Path) -> Result<ScenarioFile, IoError> {
+        let root: Value = toml::from_str(document).map_err(|err| invalid(err.to_string()))?;
+        let root = table(&root, "the scenario")?;
+
+        let limits = match root.get("node_config") {
+            Some(path) => {
+                let path = path.as_str().ok_or_else(|| invalid("`node_config` must be a string"))?;
+                let config = NodeConfigLoader::new()
+                    .add_file(base_dir.join(path))
+                    .and_then(|loader| loader.load())
+                    .map_err(|err| invalid(err.to_string()))?;
+                Some(config.limits().clone())
+            },
+            None => None,
+        };
+
+        let link = match root.get("link") {
+            Some(link) => parse_link(table(link, "`link`")?)?,
+            None => LinkParams::with_latency(Duration::new(0, 0)),
+        };
+
+        let events = match root.get("events") {
+            Some(&Value::Array(ref events)) => events.iter()
+                .map(|event| parse_event(table(event, "each of `events`")?))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(_) => return Err(invalid("`events` must be an array of tables")),
+            None => Vec::new(),
+        };
+
+        Ok(ScenarioFile {
+            seed: opt_integer(root, "seed")?.unwrap_or(0),
+            nodes: opt_integer(root, "nodes")?.ok_or_else(|| invalid("missing `nodes`"))? as usize,
+            duration: Duration::from_secs(opt_integer(root, "duration_secs")?
+                .ok_or_else(|| invalid("missing `duration_secs`"))?),
+            topology: parse_topology(table(root.get("topology")
+                .ok_or_else(|| invalid("missing `topology`"))?, "`topology`")?)?,
+            link: link,
+            limits: limits,
+            events: events,
+        })
+    }
+
+    /// Returns the faults to inject.
+    #[inline]
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Runs the simulation to the end and returns the statistics.
+    pub fn run(&self) -> Result<Stats, IoError> {
+        let network = SimNetwork::new();
+        network.set_link_model(LinkModel::Uniform(self.link));
+
+        let limits = self.limits.clone();
+        let nodes = network.spawn_nodes(self.nodes, |_, config: SimNodeConfig| match limits {
+            Some(ref limits) => config.with_limits(limits.clone()),
+            None => config,
+        });
+
+        let mut executor = CurrentThread::new();
+        let mut swarms = Vec::with_capacity(nodes.len());
+        let mut addresses = Vec::with_capacity(nodes.len());
+        for node in &nodes {
+            let public_key: PublicKey = node.public_key().clone();
+            let behaviour = Identify::new(public_key.clone(), "/libp2p-sim/1.0.0".to_owned(),
+                                          format!("libp2p-sim/node-{}", node.index()));
+            let mut swarm = SwarmBuilder::sim_plaintext_mplex(node.transport().clone(), public_key)
+                .with_runtime(node.runtime().clone())
+                .build(behaviour);
+            let addr: Multiaddr = swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr"))
+                .map_err(|addr| invalid(format!("simulated node can't listen on {}", addr)))?;
+            addresses.push(addr);
+            swarms.push(swarm);
+        }
+
+        let mut rng = XorShiftRng::from_seed(seed_bytes(self.seed));
+        for (a, b) in self.topology.edges(nodes.len(), &mut rng) {
+            if swarms[b].dial(addresses[a].clone()).is_err() {
+                network.stats().increment(nodes[b].index(), "dial_failures", 1);
+            }
+        }
+
+        for (node, mut swarm) in nodes.iter().zip(swarms) {
+            let index = node.index();
+            let stats = network.stats().clone();
+            executor.spawn(future::poll_fn(move || -> Poll<(), ()> {
+                loop {
+                    let name = match swarm.poll() {
+                        Async::Ready(Some(SwarmEvent::ConnectionEstablished { .. })) =>
+                            "connections_established",
+                        Async::Ready(Some(SwarmEvent::ConnectionClosed { .. })) =>
+                            "connections_closed",
+                        Async::Ready(Some(SwarmEvent::DialFailure { .. })) => "dial_failures",
+                        Async::Ready(Some(SwarmEvent::Behaviour(IdentifyEvent::Identified { .. }))) =>
+                            "peers_identified",
+                        Async::Ready(Some(_)) => continue,
+                        Async::Ready(None) => return Ok(Async::Ready(())),
+                        Async::NotReady => return Ok(Async::NotReady),
+                    };
+                    stats.increment(index, name, 1);
+                }
+            }));
+        }
+
+        let mut scenario = Scenario::new().until(self.duration);
+        for event in self.events() {
+            let action = event.action.clone();
+            scenario = scenario.at(event.at, move |network: &mut SimNetwork, _| apply(network, action));
+        }
+
+        let mut context = network.clone();
+        scenario.run(network.clock(), &mut executor, &mut context);
+        Ok(network.stats().clone())
+    }
+}
+
+/// Injects a fault in the network.
+fn apply(network: &SimNetwork, action: Action) {
+    match action {
+        Action::Partition(group) => {
+            let members = group.iter().cloned().collect::<BTreeSet<_>>();
+            let rest = (0 .. network.num_nodes()).filter(|n| !members.contains(n)).collect::<Vec<_>>();
+            network.partition(group, rest);
+        },
+        Action::Heal => network.heal(),
+        Action::Stop(nodes, mode) => for node in nodes {
+            network.stop_node(node, mode);
+        },
+        Action::Restart(nodes) => for node in nodes {
+            network.restart_node(node);
+        },
+    }
+}
+
+/// Expands a seed into the seed of a `XorShiftRng`, which must not be all zeroes.
+fn seed_bytes(seed: u64) -> [u8; 16] {
+    let mut bytes = [0x5a; 16];
+    for (n, byte) in bytes.iter_mut().take(8).enumerate() {
+        *byte = (seed >> (8 * n)) as u8;
+    }
+    bytes
+}
+
+/// Parses the `topology` table.
+fn parse_topology(topology: &Table) -> Result<Topology, IoError> {
+    let kind = topology.get("kind")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("`topology.kind` must be a string"))?;
+    let required_integer = |key: &str| -> Result<usize, IoError> {
+        opt_integer(topology, key)?
+            .map(|value| value as usize)
+            .ok_or_else(|| invalid(format!("topology `{}` requires `{}`", kind, key)))
+    };
+    let required_probability = |key: &str| -> Result<f64, IoError> {
+        let value = topology.get(key)
+            .and_then(Value::as_float)
+            .ok_or_else(|| invalid(format!("topology `{}` requires `{}` as a float", kind, key)))?;
+        if value >= 0.0 && value <= 1.0 {
+            Ok(value)
+        } else {
+            Err(invalid(format!("`topology.{}` must be between 0.0 and 1.0", key)))
+        }
+    };
+
+    match kind {
+        "clique" => Ok(Topology::Clique),
+        "ring" => Ok(Topology::Ring),
+        "random" => Ok(Topology::Random { p: required_probability("p")? }),
+        "small-world" => Ok(Topology::SmallWorld {
+            k: required_integer("k")?,
+            beta: required_probability("beta")?,
+        }),
+        "scale-free" => Ok(Topology::ScaleFree { m: required_integer("m")? }),
+        other => Err(invalid(format!("unknown topology `{}`", other))),
+    }
+}
+
+/// Parses the `link` table.
+fn parse_link(link: &Table) -> Result<LinkParams, IoError> {
+    let mut params = LinkParams::with_latency(
+        Duration::from_millis(opt_integer(link, "latency_ms")?.unwrap_or(0)));
+    params.jitter = Duration::from_millis(opt_integer(link, "jitter_ms")?.unwrap_or(0));
+    params.loss = match link.get("loss") {
+        Some(loss) => {
+            let loss = loss.as_float().ok_or_else(|| invalid("`link.loss` must be a float"))?;
+            if loss < 0.0 || loss >= 1.0 {
+                return Err(invalid("`link.loss` must be at least 0.0 and less than 1.0"));
+            }
+            loss
+        },
+        None => 0.0,
+    };
+    Ok(params)
+}
+
+/// Parses an element of `events`.
+fn parse_event(event: &Table) -> Result<Event, IoError> {
+    let at = Duration::from_secs(opt_integer(event, "at_secs")?
+        .ok_or_else(|| invalid("each event requires `at_secs`"))?);
+    let nodes = || -> Result<Vec<usize>, IoError> {
+        match event.get("nodes") {
+            Some(&Value::Array(ref nodes)) => nodes.iter()
+                .map(|node| node.as_integer()
+                    .and_then(|node| if node >= 0 { Some(node as usize) } else { None })
+                    .ok_or_else(|| invalid("`nodes` must contain node indices")))
+                .collect(),
+            _ => Err(invalid("this event requires `nodes`, an array of node indices")),
+        }
+    };
+
+    let action = match event.get("action").and_then(Value::as_str) {
+        Some("partition") => Action::Partition(nodes()?),
+        Some("heal") => Action::Heal,
+        Some("stop") => Action::Stop(nodes()?, StopMode::Graceful),
+        Some("crash") => Action::Stop(nodes()?, StopMode::Crash),
+        Some("restart") => Action::Restart(nodes()?),
+        Some(other) => return Err(invalid(format!("unknown action `{}`", other))),
+        None => return Err(invalid("each event requires `action`, a string")),
+    };
+
+    Ok(Event { at: at, action: action })
+}
+
+/// Returns `value` as a table, or an error mentioning `what`.
+fn table<'a>(value: &'a Value, what: &str) -> Result<&'a Table, IoError> {
+    value.as_table().ok_or_else(|| invalid(format!("{} must be a table", what)))
+}
+
+/// Returns the non-negative integer `key` of `table`, if present.
+fn opt_integer(table: &Table, key: &str) -> Result<Option<u64>, IoError> {
+    match table.get(key) {
+        Some(value) => value.as_integer()
+            .and_then(|value| if value >= 0 { Some(Some(value as u64)) } else { None })
+            .ok_or_else(|| invalid(format!("`{}` must be a non-negative integer", key))),
+        None => Ok(None),
+    }
+}
+
+/// Builds the error returned for an invalid scenario file.
+fn invalid<S: Into<String>>(message: S) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, ScenarioFile};
+    use libp2p::sim::StopMode;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_and_run() {
+        let scenario = ScenarioFile::parse(r#"
+            seed = 3
+            nodes = 4
+            duration_secs = 20
+
+            [topology]
+            kind = "ring"
+
+            [link]
+            latency_ms = 20
+
+            [[events]]
+            at_secs = 10
+            action = "crash"
+            nodes = [3]
+        "#, Path::new(".")).unwrap();
+
+        assert_eq!(scenario.events()[0].at, Duration::from_secs(10));
+        assert_eq!(scenario.events()[0].action, Action::Stop(vec![3], StopMode::Crash));
+
+        let stats = scenario.run().unwrap();
+        // A ring of four nodes has four edges, and each connection is seen by both ends.
+        assert_eq!(stats.total("connections_established"), 8);
+        assert_eq!(stats.total("peers_identified"), 8);
+    }
+
+    #[test]
+    fn invalid_scenarios() {
+        let parse = |document: &str| ScenarioFile::parse(document, Path::new("."));
+        assert!(parse("nodes = 4\nduration_secs = 1\n[topology]\nkind = \"torus\"").is_err());
+        assert!(parse("nodes = 4\nduration_secs = 1").is_err());
+        assert!(parse("nodes = 4\nduration_secs = 1\n[topology]\nkind = \"random\"\np = 2.0").is_err());
+        assert!(parse("nodes = -1\nduration_secs = 1\n[topology]\nkind = \"ring\"").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Compares the muxers on a simulated link.
+//!
+//! Each benchmark opens a connection between two nodes of a `SimNetwork`, opens a number of
+//! substreams from the dialer and sends a fixed amount of data on each of them. The link has a
+//! configurable round-trip time, and the muxers are configured with the frame size being
+//! benchmarked.
+//!
+//! The clock of the simulation is virtual, so the time measured by the `processing` benchmarks
+//! is the processing time of the muxers. The `virtual` benchmarks report instead the virtual
+//! time it took to complete the transfer, which is what a deployment on a link with the same
+//! round-trip time would observe.
+//!
+//! Run with `cargo bench -p libp2p-sim --bench muxers`.
+
+#[macro_use]
+extern crate criterion;
+extern crate futures;
+extern crate libp2p_core;
+extern crate libp2p_mplex;
+extern crate libp2p_sim;
+extern crate libp2p_yamux;
+extern crate tokio_current_thread;
+extern crate tokio_io;
+
+use criterion::Criterion;
+use futures::{future, prelude::*};
+use libp2p_core::{muxing, ConnectionUpgrade, StreamMuxer, Transport};
+use libp2p_sim::{LinkModel, LinkParams, Scenario, SimConnection, SimNetwork};
+use std::cell::Cell;
+use std::fmt;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_current_thread::CurrentThread;
+
+/// Amount of data sent on each substream.
+const BYTES_PER_SUBSTREAM: usize = 256 * 1024;
+
+/// Configuration of a benchmark.
+#[derive(Copy, Clone)]
+struct Setup {
+    /// Size of the frames the muxer splits the data into.
+    frame_size: usize,
+    /// Number of substreams opened by the dialer.
+    substreams: usize,
+    /// Round-trip time of the link between the two nodes.
+    rtt: Duration,
+}
+
+impl fmt::Debug for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame={}B substreams={} rtt={}ms", self.frame_size, self.substreams,
+            self.rtt.as_secs() * 1000 + u64::from(self.rtt.subsec_nanos() / 1_000_000))
+    }
+}
+
+/// All the configurations that are benchmarked.
+fn setups() -> Vec<Setup> {
+    let mut setups = Vec::new();
+    for &frame_size in &[1024, 16 * 1024, 64 * 1024] {
+        for &substreams in &[1, 16, 128] {
+            for &rtt in &[0, 20, 200] {
+                setups.push(Setup {
+                    frame_size,
+                    substreams,
+                    rtt: Duration::from_millis(rtt),
+                });
+            }
+        }
+    }
+    setups
+}
+
+/// Sends `BYTES_PER_SUBSTREAM` bytes on each of the substreams of `setup` through the given
+/// upgrade. Returns the virtual time it took for the listener to receive everything.
+fn transfer<U>(upgrade: U, setup: &Setup) -> Duration
+where
+    U: ConnectionUpgrade<SimConnection> + Clone + Send + 'static,
+    U::NamesIter: Clone + Send,
+    U::Future: Send,
+    U::UpgradeIdentifier: Send,
+    U::Output: StreamMuxer + 'static,
+{
+    let network = SimNetwork::new();
+    network.set_link_model(LinkModel::Uniform(LinkParams::with_latency(setup.rtt / 2)));
+    let listener = network.add_node().with_upgrade(upgrade.clone());
+    let dialer = network.add_node().with_upgrade(upgrade);
+
+    let (listener, addr) = listener.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .unwrap_or_else(|_| panic!("unsupported address"));
+    let dial = dialer.dial(addr).unwrap_or_else(|_| panic!("unsupported address"));
+
+    let mut executor = CurrentThread::new();
+    let finished = Rc::new(Cell::new(None));
+
+    let clock = network.clock().clone();
+    let expected = setup.substreams;
+    let finished2 = finished.clone();
+    executor.spawn(listener
+        .into_future()
+        .map_err(|(err, _)| err)
+        .and_then(|(incoming, _)| incoming.expect("the listener is never closed").0)
+        .and_then(move |muxer| receive_all(Arc::new(muxer), expected))
+        .map(move |()| finished2.set(Some(clock.now())))
+        .map_err(|err| panic!("listener error: {:?}", err)));
+
+    let setup = *setup;
+    executor.spawn(dial
+        .and_then(move |muxer| {
+            let muxer = Arc::new(muxer);
+            let senders = (0 .. setup.substreams)
+                .map(|_| send(muxer.clone(), setup.frame_size))
+                .collect::<Vec<_>>();
+            future::join_all(senders).join(drive(muxer)).map(|_| ())
+        })
+        .map_err(|err| panic!("dialer error: {:?}", err)));
+
+    Scenario::new().run(network.clock(), &mut executor, &mut ());
+    finished.get().expect("the transfer didn't finish")
+}
+
+/// Opens a substream and sends `BYTES_PER_SUBSTREAM` bytes on it, in writes of `frame_size`
+/// bytes.
+fn send<M>(muxer: Arc<M>, frame_size: usize) -> impl Future<Item = (), Error = IoError>
+where M: StreamMuxer + 'static
+{
+    muxing::outbound_from_ref_and_wrap(muxer)
+        .map(|substream| substream.expect("the outbound side is never closed"))
+        .and_then(move |substream| {
+            future::loop_fn((substream, BYTES_PER_SUBSTREAM), move |(substream, remaining)| {
+                let len = remaining.min(frame_size);
+                tokio_io::io::write_all(substream, vec![0; len])
+                    .map(move |(substream, _)| {
+                        if remaining == len {
+                            future::Loop::Break(substream)
+                        } else {
+                            future::Loop::Continue((substream, remaining - len))
+                        }
+                    })
+            })
+        })
+        .and_then(|substream| tokio_io::io::shutdown(substream))
+        .map(|_| ())
+}
+
+/// Keeps processing the incoming data of the connection until the remote closes it.
+fn drive<M>(muxer: Arc<M>) -> impl Future<Item = (), Error = IoError>
+where M: StreamMuxer
+{
+    future::poll_fn(move || {
+        loop {
+            match muxer.poll_inbound() {
+                Ok(Async::Ready(Some(substream))) => muxer.destroy_substream(substream),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The remote closing the connection once it has received everything is the
+                // expected way for the transfer to finish.
+                Err(_) => return Ok(Async::Ready(())),
+            }
+        }
+    })
+}
+
+/// Accepts `expected` substreams and reads each of them until the end.
+fn receive_all<M>(muxer: Arc<M>, expected: usize) -> impl Future<Item = (), Error = IoError>
+where M: StreamMuxer + 'static
+{
+    let mut accepted = 0;
+    let mut readers = Vec::new();
+    future::poll_fn(move || {
+        while accepted < expected {
+            match muxer.poll_inbound()? {
+                Async::Ready(Some(substream)) => {
+                    let substream = muxing::substream_from_ref(muxer.clone(), substream);
+                    readers.push(tokio_io::io::read_to_end(substream, Vec::new()));
+                    accepted += 1;
+                },
+                Async::Ready(None) => panic!("the inbound side is never closed"),
+                Async::NotReady => break,
+            }
+        }
+
+        for n in (0 .. readers.len()).rev() {
+            if let Async::Ready((_, data)) = readers[n].poll()? {
+                assert_eq!(data.len(), BYTES_PER_SUBSTREAM);
+                readers.swap_remove(n);
+            }
+        }
+
+        if accepted == expected && readers.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    })
+}
+
+fn mplex(setup: &Setup) -> libp2p_mplex::MplexConfig {
+    // Mplex buffers the frames of all the substreams in the same buffer, which must be large
+    // enough for the frames of the substreams that are being read.
+    let frames = setup.substreams * (BYTES_PER_SUBSTREAM / setup.frame_size + 1);
+    let mut config = libp2p_mplex::MplexConfig::new();
+    config.split_send_size(setup.frame_size)
+        .max_substreams(setup.substreams.max(128))
+        .max_buffer_len(frames.max(4096));
+    config
+}
+
+fn yamux(setup: &Setup) -> libp2p_yamux::Config {
+    let mut config = libp2p_yamux::Config::default();
+    config.split_send_size(setup.frame_size)
+        .max_substreams(setup.substreams.max(128));
+    config
+}
+
+fn bench_muxers(c: &mut Criterion) {
+    for setup in setups() {
+        c.bench_function(&format!("mplex processing {:?}", setup), move |b| {
+            b.iter(|| transfer(mplex(&setup), &setup))
+        });
+        c.bench_function(&format!("mplex virtual {:?}", setup), move |b| {
+            b.iter_custom(|iters| (0 .. iters).map(|_| transfer(mplex(&setup), &setup)).sum())
+        });
+        c.bench_function(&format!("yamux processing {:?}", setup), move |b| {
+            b.iter(|| transfer(yamux(&setup), &setup))
+        });
+        c.bench_function(&format!("yamux virtual {:?}", setup), move |b| {
+            b.iter_custom(|iters| (0 .. iters).map(|_| transfer(yamux(&setup), &setup)).sum())
+        });
+    }
+}
+
+criterion_group!{
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_muxers
+}
+criterion_main!(benches);
@@ -0,0 +1,678 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Building blocks for nodes that misbehave on purpose.
+//!
+//! Security evaluations need nodes that don't follow the protocols. This module provides:
+//!
+//! - An `Adversary`, whose `wrap` method wraps around the upgrade of a protocol. The messages
+//!   that the node sends over the substreams of this protocol are then dropped, duplicated or
+//!   corrupted according to the `MessagePolicy` configured for the protocol.
+//! - An `IdentifyLie`, that alters the information that the node sends through identify. It is
+//!   applied with `Identify::with_info_rewriter`.
+//! - A `BogusDht`, that answers the Kademlia requests of the remotes with bogus peers and
+//!   records.
+//!
+//! All the random decisions are drawn from a `RandomSource`, which makes the experiments
+//! reproducible when the source is seeded.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_core;
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_core::runtime::RandomSource;
+//! use libp2p_sim::{Adversary, MessagePolicy};
+//!
+//! # fn main() {
+//! let adversary = Adversary::new(RandomSource::seeded(7));
+//! // Corrupt a tenth of the Kademlia messages and don't forward any pubsub message.
+//! adversary.set_policy("/ipfs/kad/", MessagePolicy::new().with_corrupt_probability(0.1));
+//! adversary.refuse_pubsub_forwarding();
+//! // `adversary.wrap(upgrade)` is then used instead of `upgrade` by the adversarial node.
+//! # }
+//! ```
+//!
+//! > **Note**: Messages are delimited by assuming that the protocol prefixes them with their
+//! >           length encoded as a varint, which is the case of most of the libp2p protocols. If
+//! >           the data written on a substream doesn't follow this framing, the policy is applied
+//! >           to each write instead.
+
+use bytes::Bytes;
+use futures::prelude::*;
+use identify::IdentifyInfo;
+use kad::{KadConnectionType, KadIncomingRequest, KadPeer};
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::{io::{self, Read, Write}, mem};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use swarm::{ConnectionUpgrade, Endpoint, PeerId, PublicKey};
+use swarm::runtime::RandomSource;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::socket_to_multiaddr;
+use unsigned_varint::decode;
+
+/// Maximum number of bytes waiting to be written to the underlying stream before `write` stops
+/// accepting data.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+/// Maximum length of a message. Longer length prefixes are assumed not to be length prefixes.
+const MAX_MESSAGE_LEN: u64 = 16 * 1024 * 1024;
+
+/// Prefixes of the names of the pubsub protocols.
+const PUBSUB_PROTOCOLS: &[&str] = &["/floodsub/", "/meshsub/"];
+
+/// What to do with the outgoing messages of a protocol.
+///
+/// The probabilities are independent of each other: a message can be both corrupted and
+/// duplicated. A dropped message is neither corrupted nor duplicated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessagePolicy {
+    /// Probability that a message is not sent.
+    pub(crate) drop_probability: f64,
+    /// Probability that a message is sent twice.
+    pub(crate) duplicate_probability: f64,
+    /// Probability that a byte of a message is altered.
+    pub(crate) corrupt_probability: f64,
+}
+
+impl MessagePolicy {
+    /// Creates a policy that leaves the messages untouched.
+    #[inline]
+    pub fn new() -> MessagePolicy {
+        MessagePolicy {
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            corrupt_probability: 0.0,
+        }
+    }
+
+    /// Creates a policy that drops every message.
+    #[inline]
+    pub fn drop_all() -> MessagePolicy {
+        MessagePolicy::new().with_drop_probability(1.0)
+    }
+
+    /// Sets the probability that a message is not sent.
+    #[inline]
+    pub fn with_drop_probability(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Sets the probability that a message is sent twice.
+    #[inline]
+    pub fn with_duplicate_probability(mut self, probability: f64) -> Self {
+        self.duplicate_probability = probability;
+        self
+    }
+
+    /// Sets the probability that a byte of a message is altered.
+    #[inline]
+    pub fn with_corrupt_probability(mut self, probability: f64) -> Self {
+        self.corrupt_probability = probability;
+        self
+    }
+}
+
+impl Default for MessagePolicy {
+    #[inline]
+    fn default() -> Self {
+        MessagePolicy::new()
+    }
+}
+
+/// Number of messages that an `Adversary` has tampered with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TamperStats {
+    /// Number of messages that haven't been sent.
+    pub dropped: u64,
+    /// Number of messages that have been sent twice.
+    pub duplicated: u64,
+    /// Number of messages that have been altered.
+    pub corrupted: u64,
+}
+
+/// Tampers with the messages of the protocols of a node.
+///
+/// Cloning an `Adversary` produces a handle to the same policies, so that they can be changed
+/// while the simulation runs.
+#[derive(Clone)]
+pub struct Adversary {
+    inner: Arc<Mutex<AdversaryInner>>,
+    random: RandomSource,
+}
+
+struct AdversaryInner {
+    /// Policies, with the prefix of the protocol names they apply to.
+    policies: Vec<(String, MessagePolicy)>,
+    stats: TamperStats,
+}
+
+impl Adversary {
+    /// Creates an adversary that doesn't tamper with any protocol yet.
+    pub fn new(random: RandomSource) -> Adversary {
+        Adversary {
+            inner: Arc::new(Mutex::new(AdversaryInner {
+                policies: Vec::new(),
+                stats: TamperStats::default(),
+            })),
+            random,
+        }
+    }
+
+    /// Applies `policy` to the protocols whose name starts with `protocol`, for example
+    /// `/ipfs/kad/` or `/ipfs/id/1.0.0`. Replaces the previous policy for the same prefix.
+    ///
+    /// If several prefixes match a protocol, the longest one wins. The policy applies to the
+    /// substreams that are opened after this call.
+    pub fn set_policy(&self, protocol: &str, policy: MessagePolicy) {
+        let mut inner = self.inner.lock();
+        inner.policies.retain(|&(ref p, _)| p != protocol);
+        inner.policies.push((protocol.to_owned(), policy));
+    }
+
+    /// Stops tampering with the protocols whose name starts with `protocol`.
+    pub fn remove_policy(&self, protocol: &str) {
+        self.inner.lock().policies.retain(|&(ref p, _)| p != protocol);
+    }
+
+    /// Drops all the messages of the floodsub and gossipsub protocols, so that the node never
+    /// forwards any pubsub message.
+    pub fn refuse_pubsub_forwarding(&self) {
+        for protocol in PUBSUB_PROTOCOLS {
+            self.set_policy(protocol, MessagePolicy::drop_all());
+        }
+    }
+
+    /// Returns the policy that applies to the protocol with the given name, if any.
+    pub fn policy(&self, protocol: &[u8]) -> Option<MessagePolicy> {
+        self.inner.lock().policies.iter()
+            .filter(|&&(ref prefix, _)| protocol.starts_with(prefix.as_bytes()))
+            .max_by_key(|&&(ref prefix, _)| prefix.len())
+            .map(|&(_, policy)| policy)
+    }
+
+    /// Returns the number of messages that have been tampered with so far.
+    #[inline]
+    pub fn stats(&self) -> TamperStats {
+        self.inner.lock().stats
+    }
+
+    /// Wraps around the upgrade of one or more protocols. The messages sent over the substreams
+    /// negotiated through the returned upgrade are tampered with according to the policies.
+    #[inline]
+    pub fn wrap<U>(&self, upgrade: U) -> AdversarialUpgrade<U> {
+        AdversarialUpgrade {
+            inner: upgrade,
+            adversary: self.clone(),
+        }
+    }
+
+    /// Returns true with the given probability.
+    fn draw(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        f64::from(self.random.next_u32()) / f64::from(u32::max_value()) < probability
+    }
+
+    /// Applies `policy` to `message`, and appends the result to `out`.
+    fn tamper(&self, policy: &MessagePolicy, message: &[u8], out: &mut Vec<u8>) {
+        if self.draw(policy.drop_probability) {
+            self.inner.lock().stats.dropped += 1;
+            return;
+        }
+
+        let start = out.len();
+        out.extend_from_slice(message);
+        if self.draw(policy.corrupt_probability) {
+            // Alter the payload rather than the length prefix, so that the remote receives a
+            // well-framed but invalid message.
+            let header = decode::u64(message)
+                .map(|(_, rest)| message.len() - rest.len())
+                .unwrap_or(0);
+            let payload = &mut out[start + header..];
+            if !payload.is_empty() {
+                let pos = self.random.next_u32() as usize % payload.len();
+                payload[pos] ^= 1 + (self.random.next_u32() % 255) as u8;
+                self.inner.lock().stats.corrupted += 1;
+            }
+        }
+        if self.draw(policy.duplicate_probability) {
+            let copy = out[start..].to_vec();
+            out.extend_from_slice(&copy);
+            self.inner.lock().stats.duplicated += 1;
+        }
+    }
+}
+
+/// Upgrade that tampers with the messages sent by the upgrade it wraps. See `Adversary::wrap`.
+#[derive(Clone)]
+pub struct AdversarialUpgrade<U> {
+    inner: U,
+    adversary: Adversary,
+}
+
+impl<C, U> ConnectionUpgrade<C> for AdversarialUpgrade<U>
+where
+    C: AsyncRead + AsyncWrite,
+    U: ConnectionUpgrade<TamperedStream<C>>,
+{
+    type NamesIter = AdversarialNames<U::NamesIter>;
+    type UpgradeIdentifier = (Bytes, U::UpgradeIdentifier);
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        AdversarialNames {
+            inner: self.inner.protocol_names(),
+        }
+    }
+
+    type Output = U::Output;
+    type Future = U::Future;
+
+    fn upgrade(
+        self,
+        socket: C,
+        (name, id): Self::UpgradeIdentifier,
+        ty: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        let policy = self.adversary.policy(&name);
+        let socket = TamperedStream::new(socket, policy, self.adversary);
+        self.inner.upgrade(socket, id, ty, remote_addr)
+    }
+}
+
+/// Protocol names of an `AdversarialUpgrade`. Remembers the name of each protocol in order to
+/// find its policy once it has been negotiated.
+#[derive(Debug, Clone)]
+pub struct AdversarialNames<I> {
+    inner: I,
+}
+
+impl<I, T> Iterator for AdversarialNames<I>
+where I: Iterator<Item = (Bytes, T)>
+{
+    type Item = (Bytes, (Bytes, T));
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(name, id)| (name.clone(), (name, id)))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Stream whose outgoing messages are tampered with by an `Adversary`. Incoming data is left
+/// untouched.
+pub struct TamperedStream<S> {
+    inner: S,
+    /// Policy of the protocol of the stream. If `None`, the data is passed through.
+    policy: Option<MessagePolicy>,
+    adversary: Adversary,
+    /// Data that doesn't form a complete message yet.
+    pending: Vec<u8>,
+    /// Data waiting to be written to `inner`.
+    out: Vec<u8>,
+    /// Position in `out` of the next byte to write.
+    out_pos: usize,
+    /// True if the data doesn't follow the varint framing, in which case the policy is applied
+    /// to each write.
+    unframed: bool,
+}
+
+impl<S> TamperedStream<S> {
+    fn new(inner: S, policy: Option<MessagePolicy>, adversary: Adversary) -> TamperedStream<S> {
+        TamperedStream {
+            inner,
+            policy,
+            adversary,
+            pending: Vec::new(),
+            out: Vec::new(),
+            out_pos: 0,
+            unframed: false,
+        }
+    }
+
+    /// Moves the complete messages of `pending` to `out`, applying the policy.
+    fn process(&mut self) {
+        let policy = match self.policy {
+            Some(ref policy) => *policy,
+            None => {
+                self.out.extend_from_slice(&self.pending);
+                self.pending.clear();
+                return;
+            },
+        };
+
+        let mut consumed = 0;
+        while consumed < self.pending.len() {
+            let data = &self.pending[consumed..];
+            let len = if self.unframed {
+                data.len()
+            } else {
+                match decode::u64(data) {
+                    Ok((len, rest)) if (len as usize) <= rest.len() => {
+                        data.len() - rest.len() + len as usize
+                    },
+                    Ok((len, _)) if len <= MAX_MESSAGE_LEN => break,
+                    // A varint can't be longer than 10 bytes.
+                    Err(_) if data.len() < 10 => break,
+                    _ => {
+                        debug!("Data doesn't follow the varint framing; tampering with writes");
+                        self.unframed = true;
+                        data.len()
+                    },
+                }
+            };
+
+            self.adversary.tamper(&policy, &data[..len], &mut self.out);
+            consumed += len;
+        }
+        self.pending.drain(..consumed);
+    }
+}
+
+impl<S: Write> TamperedStream<S> {
+    /// Writes `out` to the underlying stream.
+    fn write_out(&mut self) -> io::Result<()> {
+        while self.out_pos < self.out.len() {
+            match self.inner.write(&self.out[self.out_pos..])? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => self.out_pos += n,
+            }
+        }
+        self.out.clear();
+        self.out_pos = 0;
+        Ok(())
+    }
+}
+
+impl<S: Read> Read for TamperedStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for TamperedStream<S> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<S: Write> Write for TamperedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.out.len() - self.out_pos >= MAX_BUFFERED {
+            self.write_out()?;
+        }
+
+        self.pending.extend_from_slice(buf);
+        self.process();
+        match self.write_out() {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) => return Err(err),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for TamperedStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        // An incomplete message is sent as it is.
+        let pending = mem::replace(&mut self.pending, Vec::new());
+        self.out.extend_from_slice(&pending);
+        match self.write_out() {
+            Ok(()) => self.inner.shutdown(),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Lies that a node tells through identify.
+///
+/// The fields that aren't set are sent truthfully.
+#[derive(Debug, Clone, Default)]
+pub struct IdentifyLie {
+    public_key: Option<PublicKey>,
+    agent_version: Option<String>,
+    listen_addrs: Option<Vec<Multiaddr>>,
+    protocols: Option<Vec<String>>,
+    observed_addr: Option<Multiaddr>,
+}
+
+impl IdentifyLie {
+    /// Creates a lie that doesn't alter anything yet.
+    #[inline]
+    pub fn new() -> IdentifyLie {
+        IdentifyLie::default()
+    }
+
+    /// Sends the given public key instead of ours.
+    #[inline]
+    pub fn with_public_key(mut self, public_key: PublicKey) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Sends the given agent version instead of ours.
+    #[inline]
+    pub fn with_agent_version(mut self, agent_version: String) -> Self {
+        self.agent_version = Some(agent_version);
+        self
+    }
+
+    /// Advertises the given listen addresses instead of ours, for example in order to direct
+    /// the remotes towards a victim.
+    #[inline]
+    pub fn with_listen_addrs(mut self, listen_addrs: Vec<Multiaddr>) -> Self {
+        self.listen_addrs = Some(listen_addrs);
+        self
+    }
+
+    /// Advertises the given protocols instead of the ones we support.
+    #[inline]
+    pub fn with_protocols(mut self, protocols: Vec<String>) -> Self {
+        self.protocols = Some(protocols);
+        self
+    }
+
+    /// Reports the given address as the address we observe for the remote.
+    #[inline]
+    pub fn with_observed_addr(mut self, observed_addr: Multiaddr) -> Self {
+        self.observed_addr = Some(observed_addr);
+        self
+    }
+
+    /// Applies the lie to the information sent to `remote`. Can be passed to
+    /// `Identify::with_info_rewriter` through a closure.
+    pub fn apply(&self, _remote: &PeerId, info: &mut IdentifyInfo, observed_addr: &mut Multiaddr) {
+        if let Some(ref public_key) = self.public_key {
+            info.public_key = public_key.clone();
+        }
+        if let Some(ref agent_version) = self.agent_version {
+            info.agent_version = agent_version.clone();
+        }
+        if let Some(ref listen_addrs) = self.listen_addrs {
+            info.listen_addrs = listen_addrs.clone();
+        }
+        if let Some(ref protocols) = self.protocols {
+            info.protocols = protocols.clone();
+        }
+        if let Some(ref addr) = self.observed_addr {
+            *observed_addr = addr.clone();
+        }
+    }
+}
+
+/// Answers the Kademlia requests of the remotes with bogus peers and records.
+///
+/// The bogus peers have random identities and addresses in the public range of the simulated
+/// network that don't belong to any node, so that the remotes waste their time dialing them.
+#[derive(Clone)]
+pub struct BogusDht {
+    random: RandomSource,
+    num_peers: usize,
+    record_len: usize,
+}
+
+impl BogusDht {
+    /// Creates a `BogusDht` that returns 20 peers and records of 32 bytes.
+    #[inline]
+    pub fn new(random: RandomSource) -> BogusDht {
+        BogusDht {
+            random,
+            num_peers: 20,
+            record_len: 32,
+        }
+    }
+
+    /// Sets the number of peers returned in each answer.
+    #[inline]
+    pub fn with_num_peers(mut self, num_peers: usize) -> Self {
+        self.num_peers = num_peers;
+        self
+    }
+
+    /// Sets the length of the bogus records.
+    #[inline]
+    pub fn with_record_len(mut self, record_len: usize) -> Self {
+        self.record_len = record_len;
+        self
+    }
+
+    /// Generates a list of bogus peers, which claim to be connected.
+    pub fn bogus_peers(&self) -> Vec<KadPeer> {
+        (0 .. self.num_peers)
+            .map(|_| {
+                let mut key = vec![0; 32];
+                self.random.fill_bytes(&mut key);
+                let ip = self.random.next_u32();
+                // Addresses at the end of `11.0.0.0/8`, where the network doesn't allocate nodes.
+                let ip = Ipv4Addr::new(11, 255, (ip >> 8) as u8, ip as u8);
+                let port = 1024 + (self.random.next_u32() % 60000) as u16;
+                KadPeer {
+                    node_id: PeerId::from_public_key(PublicKey::Ed25519(key)),
+                    multiaddrs: vec![socket_to_multiaddr(SocketAddrV4::new(ip, port))],
+                    connection_ty: KadConnectionType::Connected,
+                }
+            })
+            .collect()
+    }
+
+    /// Generates a bogus record.
+    pub fn bogus_record(&self) -> Vec<u8> {
+        let mut record = vec![0; self.record_len];
+        self.random.fill_bytes(&mut record);
+        record
+    }
+
+    /// Answers `request` with bogus data. The records and providers sent by the remote are
+    /// discarded.
+    pub fn respond(&self, request: KadIncomingRequest) {
+        match request {
+            KadIncomingRequest::FindNode { responder, .. } => {
+                responder.respond(self.bogus_peers())
+            },
+            KadIncomingRequest::GetValue { responder, .. } => {
+                responder.respond(Some(self.bogus_record()), self.bogus_peers())
+            },
+            KadIncomingRequest::GetProviders { responder, .. } => {
+                responder.respond(self.bogus_peers(), self.bogus_peers())
+            },
+            KadIncomingRequest::PutValue { .. } | KadIncomingRequest::AddProvider { .. } => (),
+            KadIncomingRequest::PingPong => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use swarm::runtime::RandomSource;
+    use super::{Adversary, BogusDht, MessagePolicy, TamperStats, TamperedStream};
+
+    fn stream(adversary: &Adversary, protocol: &[u8]) -> TamperedStream<Cursor<Vec<u8>>> {
+        let policy = adversary.policy(protocol);
+        TamperedStream::new(Cursor::new(Vec::new()), policy, adversary.clone())
+    }
+
+    #[test]
+    fn policies_apply_per_protocol() {
+        let adversary = Adversary::new(RandomSource::seeded(0));
+        adversary.set_policy("/ipfs/", MessagePolicy::new().with_duplicate_probability(1.0));
+        adversary.set_policy("/ipfs/kad/", MessagePolicy::drop_all());
+
+        // Messages are written in pieces, in order to check the framing.
+        let mut dropped = stream(&adversary, b"/ipfs/kad/1.0.0");
+        dropped.write_all(&[3, 1]).unwrap();
+        dropped.write_all(&[2, 3]).unwrap();
+        assert!(dropped.inner.get_ref().is_empty());
+
+        let mut duplicated = stream(&adversary, b"/ipfs/id/1.0.0");
+        duplicated.write_all(&[2, 1, 2, 1]).unwrap();
+        duplicated.write_all(&[9]).unwrap();
+        assert_eq!(duplicated.inner.get_ref(), &vec![2, 1, 2, 2, 1, 2, 1, 9, 1, 9]);
+
+        let mut untouched = stream(&adversary, b"/floodsub/1.0.0");
+        untouched.write_all(&[1, 5]).unwrap();
+        assert_eq!(untouched.inner.get_ref(), &vec![1, 5]);
+
+        assert_eq!(adversary.stats(), TamperStats { dropped: 1, duplicated: 2, corrupted: 0 });
+    }
+
+    #[test]
+    fn corruption_keeps_framing() {
+        let adversary = Adversary::new(RandomSource::seeded(0));
+        adversary.set_policy("/ipfs/kad/", MessagePolicy::new().with_corrupt_probability(1.0));
+        let mut corrupted = stream(&adversary, b"/ipfs/kad/1.0.0");
+        corrupted.write_all(&[3, 0, 0, 0]).unwrap();
+        let written = corrupted.inner.get_ref();
+        assert_eq!(written.len(), 4);
+        assert_eq!(written[0], 3);
+        assert_ne!(&written[1..], &[0, 0, 0]);
+        assert_eq!(adversary.stats().corrupted, 1);
+    }
+
+    #[test]
+    fn bogus_peers_are_reproducible() {
+        let a = BogusDht::new(RandomSource::seeded(3)).with_num_peers(5);
+        let b = BogusDht::new(RandomSource::seeded(3)).with_num_peers(5);
+        let peers = a.bogus_peers();
+        assert_eq!(peers.len(), 5);
+        assert_eq!(peers.iter().map(|p| p.node_id.clone()).collect::<Vec<_>>(),
+                   b.bogus_peers().into_iter().map(|p| p.node_id).collect::<Vec<_>>());
+    }
+}
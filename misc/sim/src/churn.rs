@@ -0,0 +1,295 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Churn of the nodes of a simulation.
+//!
+//! The `ChurnController` regularly stops random nodes of a `SimNetwork` and restarts them after
+//! a while. It is meant to be called periodically, for example with `Scenario::every`.
+//!
+//! Stopping and restarting a node only affects the network. The controller returns a list of
+//! `ChurnEvent`s, and it is the responsibility of the caller to shut down the swarm of the
+//! nodes that are stopped and to create a new one for the nodes that are restarted, with the
+//! same identity or a new one as indicated by the event.
+
+use network::{SimNetwork, StopMode};
+use rand::Rng;
+use std::time::Duration;
+
+/// Configuration of a `ChurnController`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChurnConfig {
+    initial_rate: f64,
+    final_rate: f64,
+    ramp: Duration,
+    crash_probability: f64,
+    new_identity_probability: f64,
+    downtime: Duration,
+}
+
+impl ChurnConfig {
+    /// Builds a configuration where `rate` is the fraction of the online nodes that leave the
+    /// network every second.
+    ///
+    /// By default, nodes leave gracefully, come back after 30 seconds, and keep their identity.
+    pub fn new(rate: f64) -> ChurnConfig {
+        ChurnConfig {
+            initial_rate: rate,
+            final_rate: rate,
+            ramp: Duration::new(0, 0),
+            crash_probability: 0.0,
+            new_identity_probability: 0.0,
+            downtime: Duration::from_secs(30),
+        }
+    }
+
+    /// Makes the churn rate increase or decrease linearly from the initial rate to `final_rate`
+    /// during `duration`, starting from the first call to `ChurnController::tick`.
+    #[inline]
+    pub fn with_ramp(mut self, final_rate: f64, duration: Duration) -> ChurnConfig {
+        self.final_rate = final_rate;
+        self.ramp = duration;
+        self
+    }
+
+    /// Sets the probability for a node to crash instead of leaving gracefully.
+    #[inline]
+    pub fn with_crash_probability(mut self, probability: f64) -> ChurnConfig {
+        self.crash_probability = probability;
+        self
+    }
+
+    /// Sets the probability for a node to come back with a new identity.
+    #[inline]
+    pub fn with_new_identity_probability(mut self, probability: f64) -> ChurnConfig {
+        self.new_identity_probability = probability;
+        self
+    }
+
+    /// Sets how long a node stays offline before being restarted.
+    #[inline]
+    pub fn with_downtime(mut self, downtime: Duration) -> ChurnConfig {
+        self.downtime = downtime;
+        self
+    }
+
+    /// Returns the churn rate at the given time since the start of the churn.
+    fn rate_at(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.ramp {
+            return self.final_rate;
+        }
+        let progress = duration_secs(elapsed) / duration_secs(self.ramp);
+        self.initial_rate + (self.final_rate - self.initial_rate) * progress
+    }
+}
+
+/// Change of state of a node decided by a `ChurnController`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChurnEvent {
+    /// The node has been stopped. Its swarm must be shut down.
+    Stopped {
+        /// Index of the node in the network.
+        node: usize,
+        /// How the node has been stopped.
+        mode: StopMode,
+    },
+    /// The node has been restarted. A new swarm must be created for it.
+    Restarted {
+        /// Index of the node in the network.
+        node: usize,
+        /// If true, the node must use a new identity. Otherwise it must reuse its previous one.
+        new_identity: bool,
+    },
+}
+
+/// Stops and restarts nodes of a network according to a `ChurnConfig`.
+pub struct ChurnController {
+    config: ChurnConfig,
+    /// Nodes that are subject to churn.
+    nodes: Vec<usize>,
+    /// Time of the first call to `tick`.
+    start: Option<Duration>,
+    /// Time of the last call to `tick`.
+    last_tick: Option<Duration>,
+    /// Nodes that have been stopped, with the time when they must be restarted.
+    stopped: Vec<(usize, Duration)>,
+    /// Fractional number of nodes that should have left but haven't yet.
+    carry: f64,
+}
+
+impl ChurnController {
+    /// Builds a controller that applies churn to the given nodes.
+    pub fn new<I>(config: ChurnConfig, nodes: I) -> ChurnController
+    where I: IntoIterator<Item = usize>
+    {
+        ChurnController {
+            config: config,
+            nodes: nodes.into_iter().collect(),
+            start: None,
+            last_tick: None,
+            stopped: Vec::new(),
+            carry: 0.0,
+        }
+    }
+
+    /// Stops and restarts nodes according to the time elapsed since the last call.
+    pub fn tick<R: Rng>(&mut self, network: &SimNetwork, rng: &mut R) -> Vec<ChurnEvent> {
+        let now = network.clock().now();
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now - self.last_tick.unwrap_or(now);
+        self.last_tick = Some(now);
+
+        let mut events = self.restart_due(network, now, rng);
+
+        let online = self.online_nodes(network);
+        let rate = self.config.rate_at(now - start);
+        let expected = rate * duration_secs(elapsed) * online.len() as f64 + self.carry;
+        let num = (expected.floor() as usize).min(online.len());
+        self.carry = expected - num as f64;
+
+        events.extend(self.stop_random(network, online, num, rng));
+        events
+    }
+
+    /// Immediately stops a fraction of the online nodes, chosen randomly.
+    pub fn stop_fraction<R: Rng>(&mut self, network: &SimNetwork, fraction: f64, rng: &mut R)
+        -> Vec<ChurnEvent>
+    {
+        let online = self.online_nodes(network);
+        let num = ((online.len() as f64 * fraction).round() as usize).min(online.len());
+        self.stop_random(network, online, num, rng)
+    }
+
+    /// Returns the nodes subject to churn that are currently online.
+    fn online_nodes(&self, network: &SimNetwork) -> Vec<usize> {
+        self.nodes.iter()
+            .cloned()
+            .filter(|&node| network.is_online(node))
+            .collect()
+    }
+
+    /// Restarts the nodes whose downtime is over.
+    fn restart_due<R: Rng>(&mut self, network: &SimNetwork, now: Duration, rng: &mut R)
+        -> Vec<ChurnEvent>
+    {
+        let mut events = Vec::new();
+        let new_identity_probability = self.config.new_identity_probability;
+        self.stopped.retain(|&(node, restart_at)| {
+            if restart_at > now {
+                return true;
+            }
+            network.restart_node(node);
+            events.push(ChurnEvent::Restarted {
+                node: node,
+                new_identity: rng.gen::<f64>() < new_identity_probability,
+            });
+            false
+        });
+        events
+    }
+
+    /// Stops `num` nodes randomly chosen in `candidates`.
+    fn stop_random<R: Rng>(&mut self, network: &SimNetwork, mut candidates: Vec<usize>,
+                           num: usize, rng: &mut R) -> Vec<ChurnEvent>
+    {
+        let restart_at = network.clock().now() + self.config.downtime;
+        let mut events = Vec::with_capacity(num);
+        for _ in 0..num {
+            let node = candidates.swap_remove(rng.gen_range(0, candidates.len()));
+            let mode = if rng.gen::<f64>() < self.config.crash_probability {
+                StopMode::Crash
+            } else {
+                StopMode::Graceful
+            };
+            network.stop_node(node, mode);
+            self.stopped.push((node, restart_at));
+            events.push(ChurnEvent::Stopped { node: node, mode: mode });
+        }
+        events
+    }
+}
+
+/// Converts a `Duration` into a number of seconds.
+fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use network::SimNetwork;
+    use rand::{SeedableRng, prng::XorShiftRng};
+    use std::time::Duration;
+    use super::{ChurnConfig, ChurnController, ChurnEvent};
+
+    #[test]
+    fn stops_and_restarts() {
+        let network = SimNetwork::new();
+        for _ in 0..8 {
+            network.add_node();
+        }
+        let mut rng = XorShiftRng::from_seed([3; 16]);
+        let config = ChurnConfig::new(0.25).with_downtime(Duration::from_secs(5));
+        let mut churn = ChurnController::new(config, 0..8);
+
+        assert!(churn.tick(&network, &mut rng).is_empty());
+        network.clock().advance(Duration::from_secs(1));
+        let mut stopped = churn.tick(&network, &mut rng).into_iter()
+            .map(|ev| match ev {
+                ChurnEvent::Stopped { node, .. } => node,
+                ev => panic!("unexpected event {:?}", ev),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stopped.len(), 2);
+        assert!(stopped.iter().all(|&node| !network.is_online(node)));
+
+        network.clock().advance(Duration::from_secs(5));
+        let mut restarted = churn.tick(&network, &mut rng).into_iter()
+            .filter_map(|ev| match ev {
+                ChurnEvent::Restarted { node, new_identity } => {
+                    assert!(!new_identity);
+                    Some(node)
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        stopped.sort();
+        restarted.sort();
+        assert_eq!(stopped, restarted);
+    }
+
+    #[test]
+    fn ramp() {
+        let config = ChurnConfig::new(0.0).with_ramp(1.0, Duration::from_secs(10));
+        assert_eq!(config.rate_at(Duration::from_secs(0)), 0.0);
+        assert_eq!(config.rate_at(Duration::from_secs(5)), 0.5);
+        assert_eq!(config.rate_at(Duration::from_secs(20)), 1.0);
+    }
+
+    #[test]
+    fn stop_fraction() {
+        let network = SimNetwork::new();
+        for _ in 0..50 {
+            network.add_node();
+        }
+        let mut rng = XorShiftRng::from_seed([3; 16]);
+        let mut churn = ChurnController::new(ChurnConfig::new(0.0), 0..50);
+        assert_eq!(churn.stop_fraction(&network, 0.1, &mut rng).len(), 5);
+        assert_eq!((0..50).filter(|&n| network.is_online(n)).count(), 45);
+    }
+}
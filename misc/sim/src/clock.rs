@@ -0,0 +1,214 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{prelude::*, task};
+use parking_lot::Mutex;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use swarm::runtime::{Delay, Timer};
+use wasm_timer::Instant;
+
+/// Virtual clock of a simulation.
+///
+/// The time of the simulation doesn't advance on its own, but only when `advance` or
+/// `advance_to` is called. This makes the simulations deterministic and independent from the
+/// speed of the machine.
+///
+/// Cloning a `SimClock` produces a handle to the same clock.
+#[derive(Debug, Clone, Default)]
+pub struct SimClock {
+    inner: Arc<Mutex<ClockInner>>,
+}
+
+#[derive(Debug, Default)]
+struct ClockInner {
+    /// Time elapsed since the start of the simulation.
+    now: Duration,
+    /// Tasks waiting for the clock to reach a certain time.
+    timers: Vec<(Duration, task::Task)>,
+}
+
+impl SimClock {
+    /// Creates a new clock at time zero.
+    #[inline]
+    pub fn new() -> SimClock {
+        Default::default()
+    }
+
+    /// Returns the time elapsed since the start of the simulation.
+    #[inline]
+    pub fn now(&self) -> Duration {
+        self.inner.lock().now
+    }
+
+    /// Advances the time of the simulation.
+    #[inline]
+    pub fn advance(&self, duration: Duration) {
+        let now = self.now();
+        self.advance_to(now + duration);
+    }
+
+    /// Advances the time of the simulation to the given time. Does nothing if the clock is
+    /// already past this time.
+    pub fn advance_to(&self, time: Duration) {
+        let mut inner = self.inner.lock();
+        if time <= inner.now {
+            return;
+        }
+
+        inner.now = time;
+        let mut n = 0;
+        while n < inner.timers.len() {
+            if inner.timers[n].0 <= time {
+                inner.timers.swap_remove(n).1.notify();
+            } else {
+                n += 1;
+            }
+        }
+    }
+
+    /// Returns the earliest time a `SimDelay` is waiting for, if any.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.inner.lock().timers.iter().map(|t| t.0).min()
+    }
+
+    /// Returns a future that is resolved once the clock reaches the given time.
+    #[inline]
+    pub fn delay_until(&self, deadline: Duration) -> SimDelay {
+        SimDelay {
+            clock: self.clone(),
+            deadline: deadline,
+        }
+    }
+
+    /// Returns a future that is resolved once the given duration has elapsed on the clock.
+    #[inline]
+    pub fn delay(&self, duration: Duration) -> SimDelay {
+        let now = self.now();
+        self.delay_until(now + duration)
+    }
+
+    /// Returns an implementation of the `Timer` trait of `libp2p-core` that follows this clock,
+    /// so that swarms can be run on the time of the simulation.
+    #[inline]
+    pub fn timer(&self) -> SimTimer {
+        SimTimer {
+            clock: self.clone(),
+            epoch: Instant::now(),
+        }
+    }
+}
+
+/// Implementation of `Timer` based on a `SimClock`. See `SimClock::timer`.
+///
+/// The time zero of the clock corresponds to the `Instant` when the timer has been created.
+#[derive(Debug, Clone)]
+pub struct SimTimer {
+    clock: SimClock,
+    epoch: Instant,
+}
+
+impl Timer for SimTimer {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.epoch + self.clock.now()
+    }
+
+    fn delay(&self, deadline: Instant) -> Delay {
+        let deadline = if deadline > self.epoch {
+            deadline - self.epoch
+        } else {
+            Duration::new(0, 0)
+        };
+
+        let delay = self.clock.delay_until(deadline)
+            .map_err(|()| io::Error::new(io::ErrorKind::Other, "simulated delay failed"));
+        Box::new(delay)
+    }
+}
+
+/// Future that is resolved once a `SimClock` reaches a certain time.
+#[derive(Debug, Clone)]
+pub struct SimDelay {
+    clock: SimClock,
+    deadline: Duration,
+}
+
+impl SimDelay {
+    /// Returns the time at which the future is resolved.
+    #[inline]
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl Future for SimDelay {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let mut inner = self.clock.inner.lock();
+        if inner.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+        inner.timers.push((self.deadline, task::current()));
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use std::time::Duration;
+    use super::SimClock;
+    use swarm::runtime::Timer;
+
+    #[test]
+    fn delay_resolves_when_advanced() {
+        let clock = SimClock::new();
+        let mut delay = clock.delay(Duration::from_secs(5));
+        future::lazy(move || {
+            assert!(delay.poll().unwrap().is_not_ready());
+            assert_eq!(clock.next_deadline(), Some(Duration::from_secs(5)));
+            clock.advance(Duration::from_secs(4));
+            assert!(delay.poll().unwrap().is_not_ready());
+            clock.advance(Duration::from_secs(1));
+            assert!(delay.poll().unwrap().is_ready());
+            assert_eq!(clock.now(), Duration::from_secs(5));
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn timer_follows_clock() {
+        let clock = SimClock::new();
+        let timer = clock.timer();
+        let start = timer.now();
+        let mut delay = timer.delay(start + Duration::from_secs(3));
+        future::lazy(move || {
+            assert!(delay.poll().unwrap().is_not_ready());
+            clock.advance(Duration::from_secs(3));
+            assert!(delay.poll().unwrap().is_ready());
+            assert_eq!(timer.now(), start + Duration::from_secs(3));
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bytes::Bytes;
+use clock::SimClock;
+use futures::{prelude::*, sync::mpsc, task};
+use link::LinkParams;
+use parking_lot::Mutex;
+use pcap::Capture;
+use rand::{Rng, prng::XorShiftRng};
+use rw_stream_sink::RwStreamSink;
+use std::{io, sync::Arc, time::Duration};
+
+/// An established connection between two nodes of the simulated network.
+///
+/// Implements `AsyncRead` and `AsyncWrite`.
+pub type SimConnection = RwStreamSink<SimChannel>;
+
+/// State of a connection, controlled by the network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LinkState {
+    /// Data flows normally.
+    Open,
+    /// The connection has been closed gracefully. The data in flight is still delivered, then
+    /// reading returns EOF.
+    Closed,
+    /// The connection has been reset. All operations return an error.
+    Reset,
+    /// The remote has disappeared without closing the connection. Writing succeeds but data is
+    /// discarded, and reading never produces anything.
+    Blackholed,
+}
+
+/// Shared state of the two ends of a connection, used by the network to terminate it.
+#[derive(Debug)]
+pub(crate) struct Link {
+    state: LinkState,
+    /// Tasks to wake up when the state changes.
+    tasks: Vec<task::Task>,
+}
+
+impl Default for Link {
+    fn default() -> Link {
+        Link {
+            state: LinkState::Open,
+            tasks: Vec::new(),
+        }
+    }
+}
+
+impl Link {
+    /// Changes the state of the connection and wakes up the tasks that use it.
+    pub fn set_state(&mut self, state: LinkState) {
+        self.state = state;
+        for task in self.tasks.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+/// Computes the time at which the data sent in one direction of a connection is delivered.
+pub(crate) struct Transmitter {
+    clock: SimClock,
+    params: LinkParams,
+    rng: Arc<Mutex<XorShiftRng>>,
+    /// Delivery time of the last chunk of data, in order to preserve ordering.
+    last_delivery: Duration,
+}
+
+impl Transmitter {
+    /// Builds a `Transmitter` for a direction of a connection with the given parameters.
+    pub fn new(clock: SimClock, params: LinkParams, rng: Arc<Mutex<XorShiftRng>>) -> Transmitter {
+        Transmitter {
+            clock: clock,
+            params: params,
+            rng: rng,
+            last_delivery: Duration::new(0, 0),
+        }
+    }
+
+    /// Returns the time at which a chunk of data sent now is delivered.
+    fn next_delivery(&mut self) -> Duration {
+        let mut delivery = self.clock.now() + self.params.latency;
+
+        if self.params.jitter != Duration::new(0, 0) || self.params.loss > 0.0 {
+            let mut rng = self.rng.lock();
+            if self.params.jitter != Duration::new(0, 0) {
+                let jitter_nanos = self.params.jitter.as_secs() * 1_000_000_000 +
+                    u64::from(self.params.jitter.subsec_nanos());
+                delivery += Duration::from_nanos(rng.gen_range(0, jitter_nanos + 1));
+            }
+            // Each loss delays the chunk by a retransmission timeout. We put an upper bound on
+            // the number of retransmissions so that a loss of 1.0 doesn't loop forever.
+            let mut retransmissions = 0;
+            while retransmissions < 16 && rng.gen::<f64>() < self.params.loss {
+                delivery += self.params.retransmission_timeout();
+                retransmissions += 1;
+            }
+        }
+
+        self.last_delivery = self.last_delivery.max(delivery);
+        self.last_delivery
+    }
+}
+
+/// Builds a pair of connected channels. `a_to_b` determines the delivery of the data written on
+/// the first channel, and `b_to_a` the delivery of the data written on the second channel.
+///
+/// The `mappings` are tokens that are kept alive for as long as any of the two channels is
+/// alive. They are used to keep NAT mappings active while the connection is open.
+pub(crate) fn channel_pair(link: Arc<Mutex<Link>>, mappings: Vec<Arc<()>>,
+                           a_to_b: Transmitter, b_to_a: Transmitter)
+    -> (SimChannel, SimChannel)
+{
+    let (a_tx, a_rx) = mpsc::unbounded();
+    let (b_tx, b_rx) = mpsc::unbounded();
+    let a = SimChannel {
+        clock: b_to_a.clock.clone(),
+        incoming: a_rx,
+        pending: None,
+        outgoing: b_tx,
+        transmitter: a_to_b,
+        link: link.clone(),
+        _mappings: mappings.clone(),
+        capture: None,
+    };
+    let b = SimChannel {
+        clock: a.clock.clone(),
+        incoming: b_rx,
+        pending: None,
+        outgoing: a_tx,
+        transmitter: b_to_a,
+        link: link,
+        _mappings: mappings,
+        capture: None,
+    };
+    (a, b)
+}
+
+/// End of a connection whose other end is in another shard of a distributed simulation. The
+/// data is carried between the shards by the gateway of the network.
+pub(crate) struct RemoteEnd {
+    /// Sends the data received from the other shard, with its delivery time.
+    pub to_local: mpsc::UnboundedSender<(Duration, Bytes)>,
+    /// Receives the data written by the local node, with its delivery time.
+    pub from_local: mpsc::UnboundedReceiver<(Duration, Bytes)>,
+    pub link: Arc<Mutex<Link>>,
+}
+
+/// Builds a channel whose remote end is in another shard. `transmitter` determines the delivery
+/// of the data written on the channel. The delivery of the data received is determined by the
+/// other shard.
+pub(crate) fn remote_pair(link: Arc<Mutex<Link>>, mappings: Vec<Arc<()>>,
+                          transmitter: Transmitter) -> (SimChannel, RemoteEnd)
+{
+    let (to_local, incoming) = mpsc::unbounded();
+    let (outgoing, from_local) = mpsc::unbounded();
+    let channel = SimChannel {
+        clock: transmitter.clock.clone(),
+        incoming: incoming,
+        pending: None,
+        outgoing: outgoing,
+        transmitter: transmitter,
+        link: link.clone(),
+        _mappings: mappings,
+        capture: None,
+    };
+    let remote = RemoteEnd {
+        to_local: to_local,
+        from_local: from_local,
+        link: link,
+    };
+    (channel, remote)
+}
+
+/// One end of a connection of the simulated network.
+///
+/// Implements `Sink` and `Stream`.
+pub struct SimChannel {
+    clock: SimClock,
+    /// Data sent by the remote, with the time at which it is delivered.
+    incoming: mpsc::UnboundedReceiver<(Duration, Bytes)>,
+    /// Data received from `incoming` whose delivery time hasn't been reached yet.
+    pending: Option<(Duration, Bytes)>,
+    outgoing: mpsc::UnboundedSender<(Duration, Bytes)>,
+    transmitter: Transmitter,
+    link: Arc<Mutex<Link>>,
+    _mappings: Vec<Arc<()>>,
+    /// Captures the data sent on the channel, if the network exports its traffic.
+    capture: Option<Capture>,
+}
+
+impl SimChannel {
+    /// Captures the data sent on the channel from now on.
+    #[inline]
+    pub(crate) fn set_capture(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+}
+
+impl Stream for SimChannel {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let state = self.link.lock().state;
+        match state {
+            LinkState::Reset => return Err(io::ErrorKind::ConnectionReset.into()),
+            LinkState::Blackholed => return Ok(Async::NotReady),
+            LinkState::Open | LinkState::Closed => (),
+        }
+
+        let next = match self.pending.take() {
+            Some(pending) => Ok(Async::Ready(Some(pending))),
+            None => self.incoming.poll(),
+        };
+
+        match next {
+            Ok(Async::Ready(Some((delivery, data)))) => {
+                if delivery <= self.clock.now() {
+                    return Ok(Async::Ready(Some(data)));
+                }
+                // Registers the current task to be woken up when the clock reaches the
+                // delivery time.
+                let mut delay = self.clock.delay_until(delivery);
+                let _ = delay.poll();
+                self.pending = Some((delivery, data));
+                Ok(Async::NotReady)
+            },
+            Ok(Async::NotReady) => {
+                let mut link = self.link.lock();
+                match link.state {
+                    LinkState::Open => (),
+                    LinkState::Closed => return Ok(Async::Ready(None)),
+                    LinkState::Reset => return Err(io::ErrorKind::ConnectionReset.into()),
+                    LinkState::Blackholed => return Ok(Async::NotReady),
+                }
+                if !link.tasks.iter().any(|t| t.will_notify_current()) {
+                    link.tasks.push(task::current());
+                }
+                Ok(Async::NotReady)
+            },
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Err(()) => Err(io::ErrorKind::ConnectionReset.into()),
+        }
+    }
+}
+
+impl Sink for SimChannel {
+    type SinkItem = Bytes;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let state = self.link.lock().state;
+        match state {
+            LinkState::Open => (),
+            LinkState::Closed => return Err(io::ErrorKind::BrokenPipe.into()),
+            LinkState::Reset => return Err(io::ErrorKind::ConnectionReset.into()),
+            LinkState::Blackholed => return Ok(AsyncSink::Ready),
+        }
+        if let Some(ref mut capture) = self.capture {
+            capture.sent(&item);
+        }
+        let delivery = self.transmitter.next_delivery();
+        match self.outgoing.start_send((delivery, item)) {
+            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+            Ok(AsyncSink::NotReady((_, item))) => Ok(AsyncSink::NotReady(item)),
+            Err(_) => Err(io::ErrorKind::ConnectionReset.into()),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        let state = self.link.lock().state;
+        match state {
+            LinkState::Open => (),
+            LinkState::Closed => return Err(io::ErrorKind::BrokenPipe.into()),
+            LinkState::Reset => return Err(io::ErrorKind::ConnectionReset.into()),
+            LinkState::Blackholed => return Ok(Async::Ready(())),
+        }
+        self.outgoing.poll_complete().map_err(|_| io::ErrorKind::ConnectionReset.into())
+    }
+
+    #[inline]
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.outgoing.close().map_err(|_| io::ErrorKind::ConnectionReset.into())
+    }
+}
+
+impl Into<SimConnection> for SimChannel {
+    #[inline]
+    fn into(self) -> SimConnection {
+        RwStreamSink::new(self)
+    }
+}
@@ -0,0 +1,712 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Simulations sharded across several processes or hosts.
+//!
+//! A single process can simulate a few tens of thousands of nodes. Larger simulations are split
+//! into *shards*, each of which is a `SimNetwork` created with `SimNetwork::for_shard` and driven
+//! by an `Agent`, typically in its own process. The agents connect to a `Coordinator` over TCP.
+//!
+//! The nodes of a shard communicate with each other in memory, as in a regular simulation. When
+//! a node dials an address that belongs to another shard, the connection is carried by the
+//! coordinator: the data written by the node is sent to the coordinator along with the virtual
+//! time at which it must be delivered, and forwarded to the shard of the destination.
+//!
+//! The virtual clocks of the shards are kept in sync with barriers. Each agent runs its shard
+//! until it is idle, then reports to the coordinator the next time at which something happens
+//! in its shard, along with the data sent to other shards. The coordinator then lets all the
+//! shards advance to the earliest of these times. A simulation is therefore as deterministic as
+//! a single-process one, regardless of the speed of the machines.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate libp2p_sim;
+//! extern crate tokio_current_thread;
+//!
+//! use libp2p_sim::{LinkParams, Scenario, SimNetwork};
+//! use libp2p_sim::distributed::{Agent, Coordinator};
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! // In the coordinator process.
+//! let coordinator = Coordinator::bind("127.0.0.1:7000", 2).unwrap();
+//! # let _ = coordinator;
+//!
+//! // In the process of each shard.
+//! let network = SimNetwork::for_shard(0, LinkParams::with_latency(Duration::from_millis(50)));
+//! let _node = network.add_node();
+//! let mut executor = tokio_current_thread::CurrentThread::new();
+//! Agent::connect("127.0.0.1:7000", &network).unwrap()
+//!     .run(Scenario::new().until(Duration::from_secs(60)), &mut executor, &mut ())
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! > **Note**: In a shard, `SimNetwork::partition` designates the nodes by their global index
+//! >           (see `global_index_of`), which makes it possible to block the connection attempts
+//! >           towards the nodes of other shards. Each shard must declare the partitions that
+//! >           concern its nodes, and connections between shards that are already open aren't
+//! >           severed. Connections between shards have the link parameters passed to
+//! >           `SimNetwork::for_shard`, and aren't reported by `SimNetwork::events`.
+
+use bytes::Bytes;
+use connection::{LinkState, RemoteEnd};
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use link::LinkParams;
+use network::SimNetwork;
+use scenario::{self, Scenario};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tokio_current_thread::CurrentThread;
+use trace::{duration_nanos, invalid_data, read_bytes, read_u64, write_bytes, write_u64};
+
+/// Number of nodes that each shard can contain. The addresses of the nodes of shard `n` start at
+/// index `n * SHARD_CAPACITY` of the address space of the simulated network.
+pub const SHARD_CAPACITY: usize = 1 << 18;
+
+/// Maximum number of shards.
+pub const MAX_SHARDS: u32 = 63;
+
+/// Maximum size of a message exchanged with the coordinator.
+const MAX_FRAME_LEN: u64 = 256 * 1024 * 1024;
+
+/// Returns the shard that the public address `ip` belongs to.
+pub fn shard_of(ip: &Ipv4Addr) -> Option<u32> {
+    global_index_of(ip).map(|n| (n / SHARD_CAPACITY) as u32)
+}
+
+/// Returns the index across all the shards of the node whose public address is `ip`. The node
+/// `index` of shard `shard` has the global index `SHARD_CAPACITY * shard + index`.
+pub fn global_index_of(ip: &Ipv4Addr) -> Option<usize> {
+    let octets = ip.octets();
+    if octets[0] != 11 {
+        return None;
+    }
+    let n = (usize::from(octets[1]) << 16) | (usize::from(octets[2]) << 8) | usize::from(octets[3]);
+    if n == 0 {
+        return None;
+    }
+    Some(n - 1)
+}
+
+/// Identifies a connection between two shards: the shard of the dialer and a number allocated
+/// by this shard.
+pub(crate) type ConnId = (u32, u64);
+
+/// Data exchanged between two shards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Envelope {
+    /// Shard the envelope is addressed to.
+    pub to: u32,
+    pub conn: ConnId,
+    pub kind: EnvelopeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EnvelopeKind {
+    /// A node of the shard of the dialer opens a connection.
+    Connect {
+        /// Address of the dialer as seen by the destination.
+        observed: SocketAddrV4,
+        destination: SocketAddrV4,
+    },
+    /// Data written on the connection, and the time at which it must be delivered.
+    Data {
+        delivery: Duration,
+        data: Bytes,
+    },
+    /// The connection has been closed by the sender.
+    Close,
+    /// The connection has been refused or reset.
+    Reset,
+}
+
+/// Carries the connections between the nodes of a shard and the nodes of other shards.
+pub(crate) struct Gateway {
+    /// Index of the shard.
+    pub shard: u32,
+    /// Parameters of the connections with other shards.
+    pub link: LinkParams,
+    /// Number to allocate to the next connection opened by a node of this shard.
+    next_conn: u64,
+    /// Open connections, with the shard of the other end.
+    connections: FnvHashMap<ConnId, (u32, RemoteEnd)>,
+    /// Envelopes to send with the next call to `drain`.
+    outgoing: Vec<Envelope>,
+}
+
+impl Gateway {
+    /// Creates the gateway of the given shard.
+    pub fn new(shard: u32, link: LinkParams) -> Gateway {
+        assert!(shard < MAX_SHARDS, "a simulation can't have more than {} shards", MAX_SHARDS);
+        Gateway {
+            shard,
+            link,
+            next_conn: 0,
+            connections: FnvHashMap::default(),
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// Registers a connection opened by a local node towards another shard.
+    pub fn open(&mut self, to: u32, observed: SocketAddrV4, destination: SocketAddrV4,
+                end: RemoteEnd)
+    {
+        let conn = (self.shard, self.next_conn);
+        self.next_conn += 1;
+        self.connections.insert(conn, (to, end));
+        self.outgoing.push(Envelope {
+            to,
+            conn,
+            kind: EnvelopeKind::Connect { observed, destination },
+        });
+    }
+
+    /// Registers a connection opened by a node of another shard towards a local node, or
+    /// refuses it if `end` is `None`.
+    pub fn accept(&mut self, conn: ConnId, end: Option<RemoteEnd>) {
+        match end {
+            Some(end) => {
+                self.connections.insert(conn, (conn.0, end));
+            },
+            None => self.outgoing.push(Envelope { to: conn.0, conn, kind: EnvelopeKind::Reset }),
+        }
+    }
+
+    /// Handles an envelope other than `Connect`.
+    pub fn deliver(&mut self, envelope: Envelope) {
+        match envelope.kind {
+            EnvelopeKind::Connect { .. } => {
+                unreachable!("connections are opened by the network, not by the gateway")
+            },
+            EnvelopeKind::Data { delivery, data } => {
+                if let Some(&(_, ref end)) = self.connections.get(&envelope.conn) {
+                    // An error means that the local node has dropped the connection, in which
+                    // case the data is discarded like on a regular connection.
+                    let _ = end.to_local.unbounded_send((delivery, data));
+                }
+            },
+            EnvelopeKind::Close => {
+                // Dropping the sender makes the local end read EOF once the data in flight has
+                // been delivered.
+                self.connections.remove(&envelope.conn);
+            },
+            EnvelopeKind::Reset => {
+                if let Some((_, end)) = self.connections.remove(&envelope.conn) {
+                    end.link.lock().set_state(LinkState::Reset);
+                }
+            },
+        }
+    }
+
+    /// Collects the data written by the local nodes since the last call.
+    ///
+    /// Must be called from within a task.
+    pub fn drain(&mut self) -> Vec<Envelope> {
+        let mut envelopes = Vec::new();
+        let mut closed = Vec::new();
+        for (&conn, &mut (to, ref mut end)) in self.connections.iter_mut() {
+            loop {
+                match end.from_local.poll() {
+                    Ok(Async::Ready(Some((delivery, data)))) => {
+                        envelopes.push(Envelope { to, conn, kind: EnvelopeKind::Data { delivery, data } });
+                    },
+                    Ok(Async::NotReady) => break,
+                    Ok(Async::Ready(None)) | Err(()) => {
+                        envelopes.push(Envelope { to, conn, kind: EnvelopeKind::Close });
+                        closed.push(conn);
+                        break;
+                    },
+                }
+            }
+        }
+        // Sort the envelopes so that the order doesn't depend on the hash map. The sort is
+        // stable, which preserves the order of the data of each connection.
+        envelopes.sort_by_key(|e| e.conn);
+        for conn in closed {
+            self.connections.remove(&conn);
+        }
+        let mut out = mem::replace(&mut self.outgoing, Vec::new());
+        out.extend(envelopes);
+        out
+    }
+}
+
+/// Message from an agent to the coordinator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AgentMessage {
+    /// First message of an agent.
+    Hello { shard: u32 },
+    /// The shard is idle. Contains the next time at which something happens in the shard, if
+    /// anything, and the data sent to other shards.
+    Idle { next: Option<Duration>, envelopes: Vec<Envelope> },
+}
+
+/// Message from the coordinator to an agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CoordinatorMessage {
+    /// Deliver the envelopes, then advance the clock to the given time.
+    Advance { until: Duration, envelopes: Vec<Envelope> },
+    /// The simulation is over.
+    Finish,
+}
+
+/// Drives the virtual time of the shards of a distributed simulation and carries the data
+/// exchanged between them.
+pub struct Coordinator {
+    listener: TcpListener,
+    num_shards: u32,
+}
+
+impl Coordinator {
+    /// Listens for the agents of `num_shards` shards, numbered from 0 to `num_shards - 1`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, num_shards: u32) -> Result<Coordinator, io::Error> {
+        assert!(num_shards <= MAX_SHARDS, "a simulation can't have more than {} shards", MAX_SHARDS);
+        Ok(Coordinator {
+            listener: TcpListener::bind(addr)?,
+            num_shards,
+        })
+    }
+
+    /// Returns the address the coordinator listens on.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddrV4, io::Error> {
+        match self.listener.local_addr()? {
+            ::std::net::SocketAddr::V4(addr) => Ok(addr),
+            ::std::net::SocketAddr::V6(_) => Err(invalid_data("the coordinator must listen on IPv4")),
+        }
+    }
+
+    /// Waits for all the agents to connect, then runs the simulation until no shard has
+    /// anything left to do, or until the virtual time would go past `end`. Returns the virtual
+    /// time at which the simulation stopped.
+    pub fn run(self, end: Option<Duration>) -> Result<Duration, io::Error> {
+        let mut agents: Vec<Option<TcpStream>> = (0 .. self.num_shards).map(|_| None).collect();
+        while agents.iter().any(Option::is_none) {
+            let (mut stream, addr) = self.listener.accept()?;
+            stream.set_nodelay(true)?;
+            match read_frame(&mut stream).and_then(|f| decode_agent_message(&f))? {
+                AgentMessage::Hello { shard } if shard < self.num_shards
+                    && agents[shard as usize].is_none() =>
+                {
+                    debug!("Agent of shard {} connected from {}", shard, addr);
+                    agents[shard as usize] = Some(stream);
+                },
+                msg => return Err(invalid_data(&format!("unexpected message from {}: {:?}", addr, msg))),
+            }
+        }
+        let mut agents = agents.into_iter().map(|a| a.expect("all the agents are connected")).collect::<Vec<_>>();
+
+        let mut time = Duration::new(0, 0);
+        loop {
+            let mut next: Option<Duration> = None;
+            let mut outbox: Vec<Vec<Envelope>> = (0 .. self.num_shards).map(|_| Vec::new()).collect();
+            for agent in agents.iter_mut() {
+                let (shard_next, envelopes) = match decode_agent_message(&read_frame(agent)?)? {
+                    AgentMessage::Idle { next, envelopes } => (next, envelopes),
+                    msg => return Err(invalid_data(&format!("unexpected message: {:?}", msg))),
+                };
+                next = min_time(next, shard_next);
+                for envelope in envelopes {
+                    // Envelopes have to be delivered before the delivery time of their data, and
+                    // immediately for the other kinds.
+                    let delivery = match envelope.kind {
+                        EnvelopeKind::Data { delivery, .. } => delivery.max(time),
+                        _ => time,
+                    };
+                    next = min_time(next, Some(delivery));
+                    if envelope.to < self.num_shards {
+                        outbox[envelope.to as usize].push(envelope);
+                    } else if envelope.conn.0 < self.num_shards {
+                        debug!("Connection towards unknown shard {}", envelope.to);
+                        outbox[envelope.conn.0 as usize].push(Envelope {
+                            to: envelope.conn.0,
+                            conn: envelope.conn,
+                            kind: EnvelopeKind::Reset,
+                        });
+                    }
+                }
+            }
+
+            let next = match next {
+                Some(next) if end.map(|end| next <= end).unwrap_or(true) => next.max(time),
+                _ => break,
+            };
+            time = next;
+            trace!("Advancing all the shards to {:?}", time);
+            for (agent, envelopes) in agents.iter_mut().zip(outbox) {
+                let msg = CoordinatorMessage::Advance { until: time, envelopes };
+                write_frame(agent, &encode_coordinator_message(&msg))?;
+            }
+        }
+
+        for agent in agents.iter_mut() {
+            write_frame(agent, &encode_coordinator_message(&CoordinatorMessage::Finish))?;
+        }
+        Ok(time)
+    }
+}
+
+/// Runs a shard of a distributed simulation, as instructed by the `Coordinator`.
+pub struct Agent {
+    stream: TcpStream,
+    network: SimNetwork,
+    shard: u32,
+}
+
+impl Agent {
+    /// Connects to the coordinator. `network` must have been created with
+    /// `SimNetwork::for_shard`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `network` isn't a shard of a distributed simulation.
+    pub fn connect<A: ToSocketAddrs>(coordinator: A, network: &SimNetwork)
+        -> Result<Agent, io::Error>
+    {
+        let shard = network.shard().expect("the network must be created with SimNetwork::for_shard");
+        let mut stream = TcpStream::connect(coordinator)?;
+        stream.set_nodelay(true)?;
+        write_frame(&mut stream, &encode_agent_message(&AgentMessage::Hello { shard }))?;
+        Ok(Agent {
+            stream,
+            network: network.clone(),
+            shard,
+        })
+    }
+
+    /// Returns the index of the shard.
+    #[inline]
+    pub fn shard(&self) -> u32 {
+        self.shard
+    }
+
+    /// Runs the shard until the coordinator ends the simulation. The actions of `scenario` are
+    /// executed when the virtual time of the simulation reaches them.
+    pub fn run<C: 'static>(mut self, mut scenario: Scenario<C>, executor: &mut CurrentThread,
+                           context: &mut C) -> Result<(), io::Error>
+    {
+        let clock = self.network.clock().clone();
+        loop {
+            scenario::run_until_idle(executor);
+            let envelopes = self.network.drain_remote();
+            let next = scenario.next_step(&clock);
+            let msg = AgentMessage::Idle { next, envelopes };
+            write_frame(&mut self.stream, &encode_agent_message(&msg))?;
+
+            match decode_coordinator_message(&read_frame(&mut self.stream)?)? {
+                CoordinatorMessage::Finish => break,
+                CoordinatorMessage::Advance { until, envelopes } => {
+                    for envelope in envelopes {
+                        self.network.deliver_remote(envelope);
+                    }
+                    scenario::run_until_idle(executor);
+                    clock.advance_to(until);
+                    scenario.run_due(&clock, executor, context);
+                },
+            }
+        }
+
+        debug!("Shard {} finished at {:?}", self.shard, clock.now());
+        Ok(())
+    }
+}
+
+/// Returns the earliest of two optional times.
+fn min_time(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), io::Error> {
+    let mut out = Vec::with_capacity(frame.len() + 10);
+    write_bytes(&mut out, frame);
+    writer.write_all(&out)?;
+    writer.flush()
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut len = 0u64;
+    for n in 0 .. 10 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        len |= u64::from(byte[0] & 0x7f) << (7 * n);
+        if byte[0] & 0x80 == 0 {
+            if len > MAX_FRAME_LEN {
+                return Err(invalid_data("frame too long"));
+            }
+            let mut frame = vec![0; len as usize];
+            reader.read_exact(&mut frame)?;
+            return Ok(frame);
+        }
+    }
+    Err(invalid_data("invalid frame length"))
+}
+
+fn encode_agent_message(msg: &AgentMessage) -> Vec<u8> {
+    let mut out = Vec::new();
+    match *msg {
+        AgentMessage::Hello { shard } => {
+            out.push(0);
+            write_u64(&mut out, u64::from(shard));
+        },
+        AgentMessage::Idle { next, ref envelopes } => {
+            out.push(1);
+            match next {
+                Some(next) => {
+                    out.push(1);
+                    write_u64(&mut out, duration_nanos(next));
+                },
+                None => out.push(0),
+            }
+            write_envelopes(&mut out, envelopes);
+        },
+    }
+    out
+}
+
+fn decode_agent_message(data: &[u8]) -> Result<AgentMessage, io::Error> {
+    let (&tag, data) = data.split_first().ok_or_else(|| invalid_data("empty message"))?;
+    let (msg, rest) = match tag {
+        0 => {
+            let (shard, rest) = read_u64(data)?;
+            (AgentMessage::Hello { shard: shard as u32 }, rest)
+        },
+        1 => {
+            let (&has_next, data) = data.split_first().ok_or_else(|| invalid_data("truncated"))?;
+            let (next, data) = if has_next != 0 {
+                let (next, rest) = read_u64(data)?;
+                (Some(Duration::from_nanos(next)), rest)
+            } else {
+                (None, data)
+            };
+            let (envelopes, rest) = read_envelopes(data)?;
+            (AgentMessage::Idle { next, envelopes }, rest)
+        },
+        _ => return Err(invalid_data("unknown message")),
+    };
+    if !rest.is_empty() {
+        return Err(invalid_data("trailing data after message"));
+    }
+    Ok(msg)
+}
+
+fn encode_coordinator_message(msg: &CoordinatorMessage) -> Vec<u8> {
+    let mut out = Vec::new();
+    match *msg {
+        CoordinatorMessage::Advance { until, ref envelopes } => {
+            out.push(0);
+            write_u64(&mut out, duration_nanos(until));
+            write_envelopes(&mut out, envelopes);
+        },
+        CoordinatorMessage::Finish => out.push(1),
+    }
+    out
+}
+
+fn decode_coordinator_message(data: &[u8]) -> Result<CoordinatorMessage, io::Error> {
+    let (&tag, data) = data.split_first().ok_or_else(|| invalid_data("empty message"))?;
+    let (msg, rest) = match tag {
+        0 => {
+            let (until, data) = read_u64(data)?;
+            let (envelopes, rest) = read_envelopes(data)?;
+            (CoordinatorMessage::Advance { until: Duration::from_nanos(until), envelopes }, rest)
+        },
+        1 => (CoordinatorMessage::Finish, data),
+        _ => return Err(invalid_data("unknown message")),
+    };
+    if !rest.is_empty() {
+        return Err(invalid_data("trailing data after message"));
+    }
+    Ok(msg)
+}
+
+fn write_envelopes(out: &mut Vec<u8>, envelopes: &[Envelope]) {
+    write_u64(out, envelopes.len() as u64);
+    for envelope in envelopes {
+        write_u64(out, u64::from(envelope.to));
+        write_u64(out, u64::from(envelope.conn.0));
+        write_u64(out, envelope.conn.1);
+        match envelope.kind {
+            EnvelopeKind::Connect { observed, destination } => {
+                out.push(0);
+                write_socket_addr(out, observed);
+                write_socket_addr(out, destination);
+            },
+            EnvelopeKind::Data { delivery, ref data } => {
+                out.push(1);
+                write_u64(out, duration_nanos(delivery));
+                write_bytes(out, data);
+            },
+            EnvelopeKind::Close => out.push(2),
+            EnvelopeKind::Reset => out.push(3),
+        }
+    }
+}
+
+fn read_envelopes(data: &[u8]) -> Result<(Vec<Envelope>, &[u8]), io::Error> {
+    let (len, mut data) = read_u64(data)?;
+    let mut envelopes = Vec::new();
+    for _ in 0 .. len {
+        let (to, rest) = read_u64(data)?;
+        let (dialer, rest) = read_u64(rest)?;
+        let (conn, rest) = read_u64(rest)?;
+        let (&tag, rest) = rest.split_first().ok_or_else(|| invalid_data("truncated envelope"))?;
+        let (kind, rest) = match tag {
+            0 => {
+                let (observed, rest) = read_socket_addr(rest)?;
+                let (destination, rest) = read_socket_addr(rest)?;
+                (EnvelopeKind::Connect { observed, destination }, rest)
+            },
+            1 => {
+                let (delivery, rest) = read_u64(rest)?;
+                let (bytes, rest) = read_bytes(rest)?;
+                let kind = EnvelopeKind::Data {
+                    delivery: Duration::from_nanos(delivery),
+                    data: Bytes::from(bytes),
+                };
+                (kind, rest)
+            },
+            2 => (EnvelopeKind::Close, rest),
+            3 => (EnvelopeKind::Reset, rest),
+            _ => return Err(invalid_data("unknown envelope")),
+        };
+        envelopes.push(Envelope { to: to as u32, conn: (dialer as u32, conn), kind });
+        data = rest;
+    }
+    Ok((envelopes, data))
+}
+
+fn write_socket_addr(out: &mut Vec<u8>, addr: SocketAddrV4) {
+    write_u64(out, u64::from(u32::from(*addr.ip())));
+    write_u64(out, u64::from(addr.port()));
+}
+
+fn read_socket_addr(data: &[u8]) -> Result<(SocketAddrV4, &[u8]), io::Error> {
+    let (ip, rest) = read_u64(data)?;
+    let (port, rest) = read_u64(rest)?;
+    Ok((SocketAddrV4::new(Ipv4Addr::from(ip as u32), port as u16), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use link::LinkParams;
+    use network::SimNetwork;
+    use scenario::Scenario;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use super::{Agent, Coordinator, Envelope, EnvelopeKind, read_envelopes, shard_of};
+    use super::write_envelopes;
+    use swarm::Transport;
+    use tokio_current_thread::CurrentThread;
+    use tokio_io;
+
+    #[test]
+    fn envelopes_roundtrip() {
+        let envelopes = vec![
+            Envelope {
+                to: 1,
+                conn: (0, 5),
+                kind: EnvelopeKind::Connect {
+                    observed: "11.0.0.1:10000".parse().unwrap(),
+                    destination: "11.4.0.1:10000".parse().unwrap(),
+                },
+            },
+            Envelope {
+                to: 0,
+                conn: (0, 5),
+                kind: EnvelopeKind::Data { delivery: Duration::from_millis(3), data: "hi".into() },
+            },
+            Envelope { to: 1, conn: (0, 5), kind: EnvelopeKind::Close },
+        ];
+        let mut out = Vec::new();
+        write_envelopes(&mut out, &envelopes);
+        let (decoded, rest) = read_envelopes(&out).unwrap();
+        assert_eq!(decoded, envelopes);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn nodes_get_addresses_of_their_shard() {
+        let network = SimNetwork::for_shard(3, LinkParams::with_latency(Duration::new(0, 0)));
+        let node = network.add_node();
+        assert_eq!(shard_of(&node.public_ip()), Some(3));
+        assert_eq!(shard_of(&SimNetwork::new().add_node().public_ip()), Some(0));
+    }
+
+    #[test]
+    fn connection_between_shards() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", 2).unwrap();
+        let addr = coordinator.local_addr().unwrap();
+        let coordinator = thread::spawn(move || coordinator.run(None).unwrap());
+        let latency = LinkParams::with_latency(Duration::from_millis(50));
+        let (tx, rx) = mpsc::channel();
+
+        // Shard 0 has a node that listens and records when it receives the data.
+        let listener = thread::spawn(move || {
+            let network = SimNetwork::for_shard(0, latency);
+            let node = network.add_node();
+            let (listener, listen_addr) = node.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+                .ok().unwrap();
+            tx.send(listen_addr).unwrap();
+
+            let clock = network.clock().clone();
+            let mut executor = CurrentThread::new();
+            executor.spawn(listener.into_future()
+                .map_err(|(err, _)| err)
+                .and_then(|(incoming, _)| incoming.unwrap().0)
+                .and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]))
+                .map(move |(_, buf)| {
+                    assert_eq!(&buf, b"hello");
+                    assert_eq!(clock.now(), Duration::from_millis(150));
+                })
+                .map_err(|err| panic!("{:?}", err)));
+            let agent = Agent::connect(addr, &network).unwrap();
+            agent.run(Scenario::new().until(Duration::from_secs(1)), &mut executor, &mut ()).unwrap();
+        });
+
+        // Shard 1 has a node that dials it.
+        let listen_addr = rx.recv().unwrap();
+        let dialer = thread::spawn(move || {
+            let network = SimNetwork::for_shard(1, latency);
+            let node = network.add_node();
+            let mut executor = CurrentThread::new();
+            executor.spawn(node.dial(listen_addr).ok().unwrap()
+                .and_then(|socket| tokio_io::io::write_all(socket, b"hello"))
+                .map(|_| ())
+                .map_err(|err| panic!("{:?}", err)));
+            let agent = Agent::connect(addr, &network).unwrap();
+            agent.run(Scenario::new().until(Duration::from_secs(1)), &mut executor, &mut ()).unwrap();
+        });
+
+        listener.join().unwrap();
+        dialer.join().unwrap();
+        assert_eq!(coordinator.join().unwrap(), Duration::from_secs(1));
+    }
+}
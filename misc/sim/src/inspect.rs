@@ -0,0 +1,416 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Inspection of the state of a simulation.
+//!
+//! A simulation can be paused with `Scenario::run_until`. While it is paused, a `Snapshot`
+//! records the state of the network (the time, the partitions, the open connections and which
+//! nodes are online) and the state of each node, as reported by an implementation of the
+//! `Inspect` trait: its connections, its routing table, its pubsub meshes and its pending dials.
+//!
+//! Snapshots can be serialized with `to_bytes` in order to be compared between runs or to restart
+//! a long experiment later. `Snapshot::restore_network` applies the network part of a snapshot to
+//! a new network, and the state of the nodes can be used to rebuild their protocols.
+//!
+//! # Format
+//!
+//! The binary format starts with the magic bytes `SIMSNAPS` followed by a version byte, then the
+//! time of the snapshot in nanoseconds, the partitions, the connections and the nodes. Integers
+//! are encoded as varints, and lists and byte strings are prefixed with their length.
+
+use multiaddr::Multiaddr;
+use network::{SimNetwork, StopMode};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use swarm::PeerId;
+use trace::{duration_nanos, invalid_data, read_bytes, read_list, read_u64};
+use trace::{write_bytes, write_list, write_u64};
+
+/// Magic bytes at the start of a serialized snapshot.
+const MAGIC: &[u8] = b"SIMSNAPS";
+/// Version of the binary format.
+const VERSION: u8 = 1;
+
+/// State of a node, as reported by `Inspect::inspect`.
+///
+/// The fields that don't apply to the protocols of the node are left empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeState {
+    /// Peers the node is connected to.
+    pub connections: Vec<PeerId>,
+    /// Addresses that are being dialed.
+    pub pending_dials: Vec<Multiaddr>,
+    /// Peers in the routing table, for example the k-buckets of Kademlia.
+    pub routing_table: Vec<PeerId>,
+    /// Members of the pubsub mesh of each topic. Members are identified by their peer ID or
+    /// their address, depending on the protocol.
+    pub meshes: BTreeMap<String, Vec<String>>,
+    /// Any other information, indexed by name.
+    pub custom: BTreeMap<String, String>,
+}
+
+/// Uniform way to report the state of a node.
+///
+/// Implemented on closures that take a `&mut NodeState`.
+pub trait Inspect {
+    /// Fills `state` with the current state of the node.
+    fn inspect(&self, state: &mut NodeState);
+}
+
+impl<F> Inspect for F
+where
+    F: Fn(&mut NodeState),
+{
+    #[inline]
+    fn inspect(&self, state: &mut NodeState) {
+        self(state)
+    }
+}
+
+/// State of a node in a `Snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSnapshot {
+    /// Index of the node in the network.
+    pub node: usize,
+    /// False if the node was stopped.
+    pub online: bool,
+    /// State reported by the node, if it has been inspected.
+    pub state: Option<NodeState>,
+}
+
+/// State of a simulation at a given time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Time of the virtual clock.
+    pub time: Duration,
+    /// Pairs of groups of nodes that couldn't reach each other.
+    pub partitions: Vec<(Vec<usize>, Vec<usize>)>,
+    /// Open connections, as pairs of the index of the dialer and of the listener.
+    pub connections: Vec<(usize, usize)>,
+    /// State of each node, by index.
+    pub nodes: Vec<NodeSnapshot>,
+}
+
+impl Snapshot {
+    /// Records the state of the network. The nodes still have to be inspected with `inspect`.
+    pub fn new(network: &SimNetwork) -> Snapshot {
+        let nodes = (0 .. network.num_nodes())
+            .map(|node| NodeSnapshot {
+                node,
+                online: network.is_online(node),
+                state: None,
+            })
+            .collect();
+
+        Snapshot {
+            time: network.clock().now(),
+            partitions: network.partitions(),
+            connections: network.connections(),
+            nodes,
+        }
+    }
+
+    /// Records the state of a node, as reported by `target`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `node` is out of range.
+    pub fn inspect<T>(&mut self, node: usize, target: &T) -> &NodeState
+    where
+        T: Inspect + ?Sized,
+    {
+        let mut state = NodeState::default();
+        target.inspect(&mut state);
+        let snapshot = &mut self.nodes[node];
+        snapshot.state = Some(state);
+        snapshot.state.as_ref().expect("we just set the state")
+    }
+
+    /// Returns the state of a node, if it has been inspected.
+    #[inline]
+    pub fn node(&self, node: usize) -> Option<&NodeState> {
+        self.nodes.get(node).and_then(|n| n.state.as_ref())
+    }
+
+    /// Applies the network part of the snapshot to `network`, which must contain at least as
+    /// many nodes as the snapshot.
+    ///
+    /// The clock is advanced to the time of the snapshot, the partitions are restored and the
+    /// nodes that were stopped are stopped. The connections aren't restored: they are the result
+    /// of the behaviour of the nodes.
+    pub fn restore_network(&self, network: &SimNetwork) {
+        network.clock().advance_to(self.time);
+        for &(ref group_a, ref group_b) in &self.partitions {
+            network.partition(group_a.iter().cloned(), group_b.iter().cloned());
+        }
+        for node in self.nodes.iter().filter(|n| !n.online) {
+            network.stop_node(node.node, StopMode::Graceful);
+        }
+    }
+
+    /// Serializes the snapshot.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        write_u64(&mut out, duration_nanos(self.time));
+
+        write_u64(&mut out, self.partitions.len() as u64);
+        for &(ref group_a, ref group_b) in &self.partitions {
+            write_list(&mut out, group_a);
+            write_list(&mut out, group_b);
+        }
+
+        write_u64(&mut out, self.connections.len() as u64);
+        for &(dialer, listener) in &self.connections {
+            write_u64(&mut out, dialer as u64);
+            write_u64(&mut out, listener as u64);
+        }
+
+        write_u64(&mut out, self.nodes.len() as u64);
+        for node in &self.nodes {
+            write_u64(&mut out, node.node as u64);
+            out.push(if node.online { 1 } else { 0 });
+            match node.state {
+                None => out.push(0),
+                Some(ref state) => {
+                    out.push(1);
+                    write_state(&mut out, state);
+                },
+            }
+        }
+
+        out
+    }
+
+    /// Deserializes a snapshot produced by `to_bytes`.
+    pub fn from_bytes(mut data: &[u8]) -> Result<Snapshot, io::Error> {
+        if !data.starts_with(MAGIC) {
+            return Err(invalid_data("missing magic bytes"));
+        }
+        data = &data[MAGIC.len()..];
+        if data.first() != Some(&VERSION) {
+            return Err(invalid_data("unsupported version"));
+        }
+        data = &data[1..];
+
+        let (time, rest) = read_u64(data)?;
+        data = rest;
+
+        let (num_partitions, rest) = read_u64(data)?;
+        data = rest;
+        let mut partitions = Vec::new();
+        for _ in 0 .. num_partitions {
+            let (group_a, rest) = read_list(data)?;
+            let (group_b, rest) = read_list(rest)?;
+            data = rest;
+            partitions.push((group_a, group_b));
+        }
+
+        let (num_connections, rest) = read_u64(data)?;
+        data = rest;
+        let mut connections = Vec::new();
+        for _ in 0 .. num_connections {
+            let (dialer, rest) = read_u64(data)?;
+            let (listener, rest) = read_u64(rest)?;
+            data = rest;
+            connections.push((dialer as usize, listener as usize));
+        }
+
+        let (num_nodes, rest) = read_u64(data)?;
+        data = rest;
+        let mut nodes = Vec::new();
+        for _ in 0 .. num_nodes {
+            let (node, rest) = read_u64(data)?;
+            let (flags, rest) = read_byte_pair(rest)?;
+            data = rest;
+            let state = if flags.1 {
+                let (state, rest) = read_state(data)?;
+                data = rest;
+                Some(state)
+            } else {
+                None
+            };
+            nodes.push(NodeSnapshot { node: node as usize, online: flags.0, state });
+        }
+
+        if !data.is_empty() {
+            return Err(invalid_data("trailing data after the snapshot"));
+        }
+
+        Ok(Snapshot {
+            time: Duration::from_nanos(time),
+            partitions,
+            connections,
+            nodes,
+        })
+    }
+
+    /// Writes the serialized snapshot.
+    #[inline]
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Reads a serialized snapshot.
+    #[inline]
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Snapshot, io::Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Snapshot::from_bytes(&data)
+    }
+}
+
+fn write_state(out: &mut Vec<u8>, state: &NodeState) {
+    write_u64(out, state.connections.len() as u64);
+    for peer in &state.connections {
+        write_bytes(out, peer.as_bytes());
+    }
+    write_u64(out, state.pending_dials.len() as u64);
+    for addr in &state.pending_dials {
+        write_bytes(out, &addr.to_bytes());
+    }
+    write_u64(out, state.routing_table.len() as u64);
+    for peer in &state.routing_table {
+        write_bytes(out, peer.as_bytes());
+    }
+    write_u64(out, state.meshes.len() as u64);
+    for (topic, members) in &state.meshes {
+        write_bytes(out, topic.as_bytes());
+        write_u64(out, members.len() as u64);
+        for member in members {
+            write_bytes(out, member.as_bytes());
+        }
+    }
+    write_u64(out, state.custom.len() as u64);
+    for (key, value) in &state.custom {
+        write_bytes(out, key.as_bytes());
+        write_bytes(out, value.as_bytes());
+    }
+}
+
+fn read_state(data: &[u8]) -> Result<(NodeState, &[u8]), io::Error> {
+    let mut state = NodeState::default();
+
+    let (connections, mut data) = read_u64(data)?;
+    for _ in 0 .. connections {
+        let (peer, rest) = read_peer_id(data)?;
+        state.connections.push(peer);
+        data = rest;
+    }
+    let (pending_dials, rest) = read_u64(data)?;
+    data = rest;
+    for _ in 0 .. pending_dials {
+        let (addr, rest) = read_bytes(data)?;
+        let addr = Multiaddr::from_bytes(addr.to_vec())
+            .map_err(|_| invalid_data("invalid multiaddress"))?;
+        state.pending_dials.push(addr);
+        data = rest;
+    }
+    let (routing_table, rest) = read_u64(data)?;
+    data = rest;
+    for _ in 0 .. routing_table {
+        let (peer, rest) = read_peer_id(data)?;
+        state.routing_table.push(peer);
+        data = rest;
+    }
+    let (meshes, rest) = read_u64(data)?;
+    data = rest;
+    for _ in 0 .. meshes {
+        let (topic, rest) = read_string(data)?;
+        let (num_members, rest) = read_u64(rest)?;
+        data = rest;
+        let mut members = Vec::new();
+        for _ in 0 .. num_members {
+            let (member, rest) = read_string(data)?;
+            members.push(member);
+            data = rest;
+        }
+        state.meshes.insert(topic, members);
+    }
+    let (custom, rest) = read_u64(data)?;
+    data = rest;
+    for _ in 0 .. custom {
+        let (key, rest) = read_string(data)?;
+        let (value, rest) = read_string(rest)?;
+        state.custom.insert(key, value);
+        data = rest;
+    }
+
+    Ok((state, data))
+}
+
+fn read_byte_pair(data: &[u8]) -> Result<((bool, bool), &[u8]), io::Error> {
+    if data.len() < 2 {
+        return Err(invalid_data("truncated node"));
+    }
+    Ok(((data[0] != 0, data[1] != 0), &data[2..]))
+}
+
+fn read_peer_id(data: &[u8]) -> Result<(PeerId, &[u8]), io::Error> {
+    let (bytes, rest) = read_bytes(data)?;
+    let peer = PeerId::from_bytes(bytes.to_vec()).map_err(|_| invalid_data("invalid peer ID"))?;
+    Ok((peer, rest))
+}
+
+fn read_string(data: &[u8]) -> Result<(String, &[u8]), io::Error> {
+    let (bytes, rest) = read_bytes(data)?;
+    let string = String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("invalid UTF-8"))?;
+    Ok((string, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use network::{SimNetwork, StopMode};
+    use node::SimNodeConfig;
+    use super::{NodeState, Snapshot};
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let network = SimNetwork::new();
+        let nodes = network.spawn_nodes(3, |_, config: SimNodeConfig| config);
+        network.partition(vec![0], vec![1, 2]);
+        network.stop_node(2, StopMode::Crash);
+
+        let mut snapshot = Snapshot::new(&network);
+        let peer = nodes[1].peer_id().clone();
+        snapshot.inspect(0, &move |state: &mut NodeState| {
+            state.routing_table.push(peer.clone());
+            state.pending_dials.push("/ip4/11.0.0.2/tcp/10000".parse().unwrap());
+            state.meshes.insert("topic".to_owned(), vec!["a".to_owned(), "b".to_owned()]);
+            state.custom.insert("mode".to_owned(), "server".to_owned());
+        });
+
+        assert_eq!(snapshot.node(0).unwrap().routing_table, vec![nodes[1].peer_id().clone()]);
+        assert!(snapshot.node(1).is_none());
+        assert!(!snapshot.nodes[2].online);
+
+        let decoded = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert!(Snapshot::from_bytes(b"SIMSNAPS").is_err());
+
+        let restored = SimNetwork::new();
+        restored.spawn_nodes(3, |_, config: SimNodeConfig| config);
+        decoded.restore_network(&restored);
+        assert_eq!(restored.partitions(), vec![(vec![0], vec![1, 2])]);
+        assert!(!restored.is_online(2));
+    }
+}
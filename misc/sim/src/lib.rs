@@ -0,0 +1,129 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Simulation of a network of libp2p nodes.
+//!
+//! This crate provides a `SimNetwork`, which contains nodes that communicate with each other
+//! through memory channels, and a `SimTransport` for each of these nodes. The transport
+//! behaves like the TCP transport: nodes are reached through `/ip4/<ip>/tcp/<port>`
+//! multiaddresses, which makes it possible to use the simulation with the same upgrades and
+//! protocols as a real network.
+//!
+//! Contrary to the memory transport of `libp2p-core`, the simulated network can model the NATs
+//! that nodes are placed behind. See the `nat` module for the available NAT models.
+//!
+//! Experiments that involve many nodes should spawn them with `SimNetwork::spawn_nodes`, which
+//! gives each of them an identity, a `Runtime` that follows the clock of the simulation and a
+//! resource budget. See the `node` module.
+//!
+//! A simulation can be paused with `Scenario::run_until` and its state recorded in a `Snapshot`.
+//! See the `inspect` module.
+//!
+//! The results of an experiment are recorded in the `Stats` of the network and exported to CSV or
+//! JSON. See the `stats` module.
+//!
+//! Byzantine nodes are built with the tools of the `adversary` module, which tamper with the
+//! messages of specific protocols, lie in identify or answer DHT requests with bogus records.
+//! The `sybil` module generates identities in targeted regions of the DHT keyspace and schedules
+//! eclipse attacks.
+//!
+//! With the `null-crypto` feature, the `null_crypto` module provides a security upgrade that
+//! authenticates the nodes without encrypting their connections, which makes large simulations
+//! much faster.
+//!
+//! Simulations that don't fit in a single process are sharded across several processes or hosts
+//! with the `distributed` module.
+//!
+//! The traffic of the network can be exported to pcapng files and inspected with Wireshark. See
+//! the `pcap` module.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_core;
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_core::Transport;
+//! use libp2p_sim::{NatConfig, NatType, SimNetwork};
+//!
+//! # fn main() {
+//! let network = SimNetwork::new();
+//! let server = network.add_node();
+//! let client = network.add_node_behind_nat(NatConfig::new(NatType::Symmetric));
+//!
+//! let (_listener, addr) = server.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+//!     .unwrap_or_else(|_| panic!("unsupported address"));
+//! let _dial = client.dial(addr).unwrap_or_else(|_| panic!("unsupported address"));
+//! # }
+//! ```
+
+extern crate bytes;
+extern crate fnv;
+extern crate futures;
+extern crate libp2p_core as swarm;
+extern crate libp2p_identify as identify;
+extern crate libp2p_kad as kad;
+#[macro_use]
+extern crate log;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate rand;
+extern crate rw_stream_sink;
+extern crate tokio_current_thread;
+extern crate tokio_io;
+extern crate unsigned_varint;
+extern crate wasm_timer;
+
+pub mod adversary;
+pub mod churn;
+pub mod clock;
+pub mod connection;
+pub mod distributed;
+pub mod inspect;
+pub mod link;
+pub mod nat;
+pub mod pcap;
+pub mod network;
+pub mod node;
+#[cfg(feature = "null-crypto")]
+pub mod null_crypto;
+pub mod scenario;
+pub mod stats;
+pub mod sybil;
+pub mod topology;
+pub mod trace;
+pub mod transport;
+
+pub use self::adversary::{Adversary, BogusDht, IdentifyLie, MessagePolicy, TamperStats};
+pub use self::churn::{ChurnConfig, ChurnController, ChurnEvent};
+pub use self::clock::{SimClock, SimDelay, SimTimer};
+pub use self::connection::SimConnection;
+pub use self::distributed::{Agent, Coordinator};
+pub use self::inspect::{Inspect, NodeSnapshot, NodeState, Snapshot};
+pub use self::link::{LinkModel, LinkParams};
+pub use self::nat::{NatConfig, NatType};
+pub use self::pcap::PcapWriter;
+pub use self::network::{NetworkEvent, SimNetwork, StopMode};
+pub use self::node::{SimNode, SimNodeConfig};
+pub use self::scenario::Scenario;
+pub use self::stats::{Stats, Summary};
+pub use self::topology::{SimTopology, Topology};
+pub use self::trace::{Trace, TraceEvent, Tracer};
+pub use self::transport::{SimListener, SimTransport};
@@ -0,0 +1,146 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Characteristics of the links between the nodes of a simulation.
+//!
+//! The `LinkModel` of a `SimNetwork` determines the `LinkParams` of each connection when it is
+//! opened. The parameters of a direction of a connection are obtained by passing the index of
+//! the sending node and the index of the receiving node to the model.
+
+use std::sync::Arc;
+use std::time::Duration;
+use swarm::PeerId;
+
+/// Characteristics of one direction of a link.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LinkParams {
+    /// Time it takes for data to go from one end to the other.
+    pub latency: Duration,
+    /// Maximum additional latency. Each chunk of data gets a random additional latency between
+    /// zero and this value. Since connections are reliable and ordered, a chunk is never
+    /// delivered before the previous ones.
+    pub jitter: Duration,
+    /// Probability for a chunk of data to be lost. Since connections are reliable, a lost chunk
+    /// is retransmitted, which delays it and all the following ones.
+    pub loss: f64,
+}
+
+impl LinkParams {
+    /// Builds the parameters of a link with the given latency, no jitter and no loss.
+    #[inline]
+    pub fn with_latency(latency: Duration) -> LinkParams {
+        LinkParams {
+            latency: latency,
+            jitter: Duration::new(0, 0),
+            loss: 0.0,
+        }
+    }
+
+    /// Returns the delay before a lost chunk of data is retransmitted.
+    pub(crate) fn retransmission_timeout(&self) -> Duration {
+        (self.latency * 2).max(Duration::from_millis(200))
+    }
+}
+
+impl Default for LinkParams {
+    #[inline]
+    fn default() -> LinkParams {
+        LinkParams::with_latency(Duration::new(0, 0))
+    }
+}
+
+/// Determines the characteristics of the links between the nodes of a simulation.
+#[derive(Clone)]
+pub enum LinkModel {
+    /// All the links have the same parameters.
+    Uniform(LinkParams),
+    /// Matrix indexed by the sending node, then by the receiving node. Links that are out of
+    /// the matrix use the default parameters.
+    Matrix(Vec<Vec<LinkParams>>),
+    /// Function called with the index of the sending node and the index of the receiving node.
+    Callback(Arc<Fn(usize, usize) -> LinkParams + Send + Sync>),
+    /// Function called with the `PeerId` of the sending node and the `PeerId` of the receiving
+    /// node, as set with `SimNetwork::set_peer_id`. Links where one of the two nodes doesn't
+    /// have a `PeerId` use the default parameters.
+    PeerCallback(Arc<Fn(&PeerId, &PeerId) -> LinkParams + Send + Sync>),
+}
+
+impl LinkModel {
+    /// Builds a `LinkModel::Callback`.
+    #[inline]
+    pub fn from_fn<F>(f: F) -> LinkModel
+    where F: Fn(usize, usize) -> LinkParams + Send + Sync + 'static
+    {
+        LinkModel::Callback(Arc::new(f))
+    }
+
+    /// Builds a `LinkModel::PeerCallback`.
+    #[inline]
+    pub fn from_peer_fn<F>(f: F) -> LinkModel
+    where F: Fn(&PeerId, &PeerId) -> LinkParams + Send + Sync + 'static
+    {
+        LinkModel::PeerCallback(Arc::new(f))
+    }
+
+    /// Returns the parameters of the link from `from` to `to`.
+    pub(crate) fn params(&self, from: (usize, Option<&PeerId>), to: (usize, Option<&PeerId>))
+        -> LinkParams
+    {
+        match *self {
+            LinkModel::Uniform(params) => params,
+            LinkModel::Matrix(ref matrix) => {
+                matrix.get(from.0)
+                    .and_then(|row| row.get(to.0))
+                    .cloned()
+                    .unwrap_or_default()
+            },
+            LinkModel::Callback(ref f) => f(from.0, to.0),
+            LinkModel::PeerCallback(ref f) => {
+                match (from.1, to.1) {
+                    (Some(from), Some(to)) => f(from, to),
+                    _ => LinkParams::default(),
+                }
+            },
+        }
+    }
+}
+
+impl Default for LinkModel {
+    #[inline]
+    fn default() -> LinkModel {
+        LinkModel::Uniform(LinkParams::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{LinkModel, LinkParams};
+
+    #[test]
+    fn matrix_lookup() {
+        let fast = LinkParams::with_latency(Duration::from_millis(10));
+        let slow = LinkParams::with_latency(Duration::from_millis(150));
+        let model = LinkModel::Matrix(vec![vec![fast, slow], vec![slow, fast]]);
+        assert_eq!(model.params((0, None), (1, None)), slow);
+        assert_eq!(model.params((1, None), (1, None)), fast);
+        assert_eq!(model.params((2, None), (0, None)), LinkParams::default());
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! NAT models of the simulated network.
+//!
+//! A node of the simulated network can be placed behind a NAT by passing a `NatConfig` to
+//! `SimNetwork::add_node_behind_nat`. The NAT then rewrites the source address of the
+//! connections that the node opens, and filters the connections that other nodes try to open
+//! towards its public address.
+
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default number of seconds after which an unused mapping is removed from the NAT.
+pub const DEFAULT_MAPPING_TIMEOUT_SECS: u64 = 120;
+
+/// Behaviour of a NAT, following the classification of RFC 3489.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NatType {
+    /// All the connections from the same internal port are mapped to the same external port,
+    /// and any remote can connect to the external port once the mapping exists.
+    FullCone,
+    /// Same as `FullCone`, except that a remote can only connect to the external port if the
+    /// node has previously opened a connection to the IP address of this remote.
+    RestrictedCone,
+    /// Same as `RestrictedCone`, except that the remote must also connect from the port the node
+    /// has previously opened a connection to.
+    PortRestrictedCone,
+    /// Each destination gets its own mapping, and only this destination can connect to the
+    /// external port of the mapping.
+    Symmetric,
+}
+
+/// Configuration of the NAT in front of a node of the simulated network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatConfig {
+    nat_type: NatType,
+    mapping_timeout: Duration,
+}
+
+impl NatConfig {
+    /// Builds a new configuration for the given type of NAT.
+    #[inline]
+    pub fn new(nat_type: NatType) -> NatConfig {
+        NatConfig {
+            nat_type: nat_type,
+            mapping_timeout: Duration::from_secs(DEFAULT_MAPPING_TIMEOUT_SECS),
+        }
+    }
+
+    /// Sets the duration after which a mapping that isn't used by any connection is removed.
+    #[inline]
+    pub fn with_mapping_timeout(mut self, timeout: Duration) -> NatConfig {
+        self.mapping_timeout = timeout;
+        self
+    }
+
+    /// Returns the type of NAT.
+    #[inline]
+    pub fn nat_type(&self) -> NatType {
+        self.nat_type
+    }
+
+    /// Returns the duration after which an unused mapping is removed.
+    #[inline]
+    pub fn mapping_timeout(&self) -> Duration {
+        self.mapping_timeout
+    }
+}
+
+/// State of a NAT of the simulated network.
+#[derive(Debug)]
+pub(crate) struct Nat {
+    config: NatConfig,
+    mappings: Vec<Mapping>,
+    next_port: u16,
+}
+
+/// Mapping between an internal port and an external port.
+#[derive(Debug)]
+struct Mapping {
+    internal_port: u16,
+    external_port: u16,
+    /// Destinations the node has connected to through this mapping.
+    destinations: Vec<SocketAddrV4>,
+    /// Virtual time when the mapping was last used.
+    last_used: Duration,
+    /// Token that is cloned into the connections that use this mapping. The mapping never
+    /// expires as long as a clone is alive.
+    active: Arc<()>,
+}
+
+impl Mapping {
+    /// Returns true if the mapping is still valid at the given time.
+    fn is_alive(&self, now: Duration, timeout: Duration) -> bool {
+        Arc::strong_count(&self.active) > 1 || now < self.last_used + timeout
+    }
+}
+
+impl Nat {
+    /// Builds a NAT with no mapping.
+    pub fn new(config: NatConfig) -> Nat {
+        Nat {
+            config: config,
+            mappings: Vec::new(),
+            next_port: 30000,
+        }
+    }
+
+    /// Returns the configuration of the NAT.
+    #[inline]
+    pub fn config(&self) -> &NatConfig {
+        &self.config
+    }
+
+    /// Processes a connection from `internal_port` towards `destination`.
+    ///
+    /// Returns the external port the connection goes out from, and a token that keeps the
+    /// mapping alive.
+    pub fn outbound(&mut self, internal_port: u16, destination: SocketAddrV4, now: Duration)
+        -> (u16, Arc<()>)
+    {
+        self.expire(now);
+
+        let symmetric = self.config.nat_type == NatType::Symmetric;
+        let existing = self.mappings.iter().position(|m| {
+            m.internal_port == internal_port &&
+                (!symmetric || m.destinations.first() == Some(&destination))
+        });
+
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                let external_port = self.allocate_port(internal_port);
+                self.mappings.push(Mapping {
+                    internal_port: internal_port,
+                    external_port: external_port,
+                    destinations: Vec::new(),
+                    last_used: now,
+                    active: Arc::new(()),
+                });
+                self.mappings.len() - 1
+            },
+        };
+
+        let mapping = &mut self.mappings[index];
+        if !mapping.destinations.contains(&destination) {
+            mapping.destinations.push(destination);
+        }
+        mapping.last_used = now;
+        (mapping.external_port, mapping.active.clone())
+    }
+
+    /// Processes a connection from `source` towards `external_port`.
+    ///
+    /// Returns the internal port the connection must be forwarded to and a token that keeps the
+    /// mapping alive, or `None` if the NAT drops the connection.
+    pub fn inbound(&mut self, external_port: u16, source: SocketAddrV4, now: Duration)
+        -> Option<(u16, Arc<()>)>
+    {
+        self.expire(now);
+
+        let nat_type = self.config.nat_type;
+        let mapping = self.mappings.iter_mut().find(|m| {
+            if m.external_port != external_port {
+                return false;
+            }
+            match nat_type {
+                NatType::FullCone => true,
+                NatType::RestrictedCone => m.destinations.iter().any(|d| d.ip() == source.ip()),
+                NatType::PortRestrictedCone | NatType::Symmetric => m.destinations.contains(&source),
+            }
+        });
+
+        mapping.map(|mapping| {
+            mapping.last_used = now;
+            (mapping.internal_port, mapping.active.clone())
+        })
+    }
+
+    /// Returns the number of mappings currently valid.
+    pub fn num_mappings(&mut self, now: Duration) -> usize {
+        self.expire(now);
+        self.mappings.len()
+    }
+
+    /// Removes the mappings that have expired.
+    fn expire(&mut self, now: Duration) {
+        let timeout = self.config.mapping_timeout;
+        self.mappings.retain(|m| m.is_alive(now, timeout));
+    }
+
+    /// Picks an external port for a new mapping. Tries to preserve the internal port if it is
+    /// available.
+    fn allocate_port(&mut self, internal_port: u16) -> u16 {
+        if !self.is_port_used(internal_port) {
+            return internal_port;
+        }
+
+        loop {
+            let port = self.next_port;
+            self.next_port = self.next_port.checked_add(1).unwrap_or(30000);
+            if !self.is_port_used(port) {
+                return port;
+            }
+        }
+    }
+
+    /// Returns true if a mapping uses the given external port.
+    fn is_port_used(&self, port: u16) -> bool {
+        self.mappings.iter().any(|m| m.external_port == port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Nat, NatConfig, NatType};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+
+    fn addr(a: u8, port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(11, 0, 0, a), port)
+    }
+
+    #[test]
+    fn full_cone_accepts_anyone() {
+        let mut nat = Nat::new(NatConfig::new(NatType::FullCone));
+        assert!(nat.inbound(5000, addr(1, 1000), Duration::from_secs(0)).is_none());
+        let (port, _token) = nat.outbound(5000, addr(1, 1000), Duration::from_secs(0));
+        assert_eq!(port, 5000);
+        let (internal, _) = nat.inbound(port, addr(2, 2000), Duration::from_secs(1)).unwrap();
+        assert_eq!(internal, 5000);
+    }
+
+    #[test]
+    fn restricted_cone_filters_by_ip() {
+        let mut nat = Nat::new(NatConfig::new(NatType::RestrictedCone));
+        let (port, _token) = nat.outbound(5000, addr(1, 1000), Duration::from_secs(0));
+        assert!(nat.inbound(port, addr(1, 3000), Duration::from_secs(1)).is_some());
+        assert!(nat.inbound(port, addr(2, 1000), Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn port_restricted_cone_filters_by_ip_and_port() {
+        let mut nat = Nat::new(NatConfig::new(NatType::PortRestrictedCone));
+        let (port, _token) = nat.outbound(5000, addr(1, 1000), Duration::from_secs(0));
+        assert!(nat.inbound(port, addr(1, 1000), Duration::from_secs(1)).is_some());
+        assert!(nat.inbound(port, addr(1, 3000), Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn symmetric_uses_one_port_per_destination() {
+        let mut nat = Nat::new(NatConfig::new(NatType::Symmetric));
+        let (port1, _t1) = nat.outbound(5000, addr(1, 1000), Duration::from_secs(0));
+        let (port2, _t2) = nat.outbound(5000, addr(2, 1000), Duration::from_secs(0));
+        assert_ne!(port1, port2);
+        assert_eq!(nat.outbound(5000, addr(1, 1000), Duration::from_secs(0)).0, port1);
+        assert!(nat.inbound(port1, addr(1, 1000), Duration::from_secs(1)).is_some());
+        assert!(nat.inbound(port1, addr(2, 1000), Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn mappings_expire() {
+        let config = NatConfig::new(NatType::FullCone)
+            .with_mapping_timeout(Duration::from_secs(10));
+        let mut nat = Nat::new(config);
+        let (port, token) = nat.outbound(5000, addr(1, 1000), Duration::from_secs(0));
+        assert!(nat.inbound(port, addr(2, 1000), Duration::from_secs(20)).is_some());
+        drop(token);
+        assert_eq!(nat.num_mappings(Duration::from_secs(25)), 1);
+        assert_eq!(nat.num_mappings(Duration::from_secs(30)), 0);
+        assert!(nat.inbound(port, addr(2, 1000), Duration::from_secs(30)).is_none());
+    }
+}
@@ -0,0 +1,972 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use clock::SimClock;
+use connection::{self, Link, LinkState, RemoteEnd, SimConnection, Transmitter};
+use distributed::{self, Envelope, EnvelopeKind, Gateway, SHARD_CAPACITY};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{future, prelude::*, sync::mpsc};
+use link::{LinkModel, LinkParams};
+use multiaddr::Multiaddr;
+use nat::{Nat, NatConfig};
+use node::{self, HostInfo, SimNode, SimNodeConfig};
+use parking_lot::Mutex;
+use pcap::{Capture, PcapWriter};
+use rand::{SeedableRng, prng::XorShiftRng};
+use stats::Stats;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use swarm::PeerId;
+use swarm::resource_manager::ResourceManager;
+use swarm::runtime::{LocalExecutor, RandomSource, Runtime};
+use trace::{TraceEvent, Tracer};
+use transport::{self, SimTransport};
+
+/// First port that is allocated when listening on port 0.
+const FIRST_LISTEN_PORT: u16 = 10000;
+/// First port that is allocated when dialing without listening.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+/// Default number of seconds after which a connection attempt towards an unreachable node fails.
+pub const DEFAULT_DIAL_TIMEOUT_SECS: u64 = 20;
+
+/// Event that happens on a simulated network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// Traffic between two groups of nodes has been blocked.
+    Partitioned {
+        /// Nodes on the first side of the partition.
+        group_a: Vec<usize>,
+        /// Nodes on the second side of the partition.
+        group_b: Vec<usize>,
+    },
+    /// All the partitions have been removed.
+    Healed,
+    /// A node has been stopped.
+    NodeStopped {
+        /// Index of the node.
+        node: usize,
+        /// How the node has been stopped.
+        mode: StopMode,
+    },
+    /// A node that was stopped has been started again.
+    NodeRestarted {
+        /// Index of the node.
+        node: usize,
+    },
+    /// A connection has been opened.
+    ConnectionOpened {
+        /// Index of the node that opened the connection.
+        dialer: usize,
+        /// Index of the node that accepted the connection.
+        listener: usize,
+    },
+    /// A connection that was open has been terminated by the network, either because of a
+    /// partition or because one of its nodes has been stopped.
+    ConnectionSevered {
+        /// Index of the node that opened the connection.
+        dialer: usize,
+        /// Index of the node that accepted the connection.
+        listener: usize,
+    },
+}
+
+/// How a node is stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StopMode {
+    /// The node closes its connections before leaving. The remotes receive the data in flight
+    /// followed by EOF.
+    Graceful,
+    /// The node disappears without closing its connections. The remotes don't notice anything,
+    /// and the data they send is lost.
+    Crash,
+}
+
+/// Simulated network of nodes connected to each other through memory channels.
+///
+/// Each node gets its own IPv4 address and is reached through `/ip4/<ip>/tcp/<port>`
+/// multiaddresses. Nodes that are directly on the network have an address in `11.0.0.0/8`.
+/// Nodes that are behind a NAT have a private address in `10.0.0.0/8`, and are reached through
+/// the public address of their NAT, in `11.0.0.0/8`.
+///
+/// Cloning a `SimNetwork` produces a handle to the same network.
+#[derive(Clone)]
+pub struct SimNetwork {
+    clock: SimClock,
+    stats: Stats,
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+struct NetworkInner {
+    /// List of nodes. The index of a node in this list is its identifier.
+    nodes: Vec<Node>,
+    /// Listeners, indexed by the local address of the node they belong to, with their
+    /// identifier.
+    listeners: FnvHashMap<SocketAddrV4, (u64, mpsc::UnboundedSender<(SimConnection, Multiaddr)>)>,
+    /// Identifier to assign to the next listener.
+    next_listener_id: u64,
+    /// Connections that have been opened and may still be alive.
+    connections: Vec<ConnectionInfo>,
+    /// Pairs of groups of nodes that can't reach each other, designated by their global index.
+    partitions: Vec<(FnvHashSet<usize>, FnvHashSet<usize>)>,
+    /// Time after which a connection attempt towards an unreachable node fails.
+    dial_timeout: Duration,
+    /// Senders of the streams returned by `events()`.
+    events: Vec<mpsc::UnboundedSender<NetworkEvent>>,
+    /// Model that gives the characteristics of new connections.
+    link_model: LinkModel,
+    /// Random number generator used for jitter and losses.
+    rng: Arc<Mutex<XorShiftRng>>,
+    /// Tracer that records the events, if any.
+    tracer: Option<Tracer>,
+    /// Writer that exports the traffic of the connections, if any.
+    pcap: Option<PcapWriter>,
+    /// Carries the connections with other shards, if the network is a shard of a distributed
+    /// simulation.
+    gateway: Option<Gateway>,
+}
+
+/// Information about a connection, in order to sever it.
+struct ConnectionInfo {
+    dialer: usize,
+    listener: usize,
+    link: Weak<Mutex<Link>>,
+}
+
+impl NetworkInner {
+    /// Returns the parameters of the link from `from` to `to`.
+    fn link_params(&self, from: usize, to: usize) -> LinkParams {
+        self.link_model.params((from, self.nodes[from].peer_id.as_ref()),
+                               (to, self.nodes[to].peer_id.as_ref()))
+    }
+
+    /// Returns the index of a node of this network across all the shards of a distributed
+    /// simulation. Equal to `node` if the network isn't a shard.
+    fn global_index(&self, node: usize) -> usize {
+        self.gateway.as_ref().map(|g| g.shard as usize * SHARD_CAPACITY).unwrap_or(0) + node
+    }
+
+    /// Returns true if traffic between the nodes with the global indices `a` and `b` is blocked
+    /// by a partition.
+    fn is_partitioned(&self, a: usize, b: usize) -> bool {
+        self.partitions.iter().any(|&(ref group_a, ref group_b)| {
+            (group_a.contains(&a) && group_b.contains(&b)) ||
+                (group_a.contains(&b) && group_b.contains(&a))
+        })
+    }
+
+    /// Opens a connection from `node` towards a node of another shard, if the network is a shard
+    /// of a distributed simulation and if `destination` belongs to another shard.
+    fn connect_remote(&mut self, clock: &SimClock, node: usize, observed: SocketAddrV4,
+                      destination: SocketAddrV4, mappings: Vec<Arc<()>>)
+        -> Result<(SimConnection, Duration), (io::Error, Duration)>
+    {
+        let refused = || (io::Error::from(io::ErrorKind::ConnectionRefused), Duration::new(0, 0));
+        let shard = match self.gateway {
+            Some(ref gateway) => gateway.shard,
+            None => return Err(refused()),
+        };
+        let to = match distributed::shard_of(destination.ip()) {
+            Some(to) if to != shard => to,
+            _ => return Err(refused()),
+        };
+        let target_node = distributed::global_index_of(destination.ip())
+            .expect("shard_of succeeded just above");
+        if self.is_partitioned(self.global_index(node), target_node) {
+            debug!("Connection from {} to {} blocked by a partition", observed, destination);
+            return Err((io::Error::from(io::ErrorKind::TimedOut), self.dial_timeout));
+        }
+
+        let rng = self.rng.clone();
+        let gateway = self.gateway.as_mut().expect("checked that the gateway exists above");
+
+        let link = Arc::new(Mutex::new(Link::default()));
+        let transmitter = Transmitter::new(clock.clone(), gateway.link, rng);
+        let (mut channel, end) = connection::remote_pair(link, mappings, transmitter);
+        if let Some(ref pcap) = self.pcap {
+            channel.set_capture(Capture::new(pcap.open_flow(observed, destination), true));
+        }
+        gateway.open(to, observed, destination, end);
+        debug!("Connecting to {} in shard {} as {}", destination, to, observed);
+        Ok((channel.into(), gateway.link.latency * 2))
+    }
+
+    /// Accepts a connection from a node of another shard. Returns `None` if the connection is
+    /// refused.
+    fn accept_remote(&mut self, clock: &SimClock, observed: SocketAddrV4,
+                     destination: SocketAddrV4) -> Option<RemoteEnd>
+    {
+        let now = clock.now();
+        let target_node = self.nodes.iter().position(|n| n.public_ip == *destination.ip())?;
+        if let Some(dialer) = distributed::global_index_of(observed.ip()) {
+            if self.is_partitioned(dialer, self.global_index(target_node)) {
+                debug!("Connection from {} to {} blocked by a partition", observed, destination);
+                return None;
+            }
+        }
+        let mut mappings = Vec::new();
+        let target = {
+            let target = &mut self.nodes[target_node];
+            if !target.online {
+                return None;
+            }
+            match target.nat {
+                Some(ref mut nat) => {
+                    let (port, token) = nat.inbound(destination.port(), observed, now)?;
+                    mappings.push(token);
+                    SocketAddrV4::new(target.local_ip, port)
+                },
+                None => destination,
+            }
+        };
+
+        let link_params = self.gateway.as_ref()?.link;
+        let link = Arc::new(Mutex::new(Link::default()));
+        let transmitter = Transmitter::new(clock.clone(), link_params, self.rng.clone());
+        let (mut channel, end) = connection::remote_pair(link, mappings, transmitter);
+        if let Some(ref pcap) = self.pcap {
+            channel.set_capture(Capture::new(pcap.open_flow(observed, destination), false));
+        }
+        self.listeners.get(&target)?
+            .1.unbounded_send((channel.into(), transport::socket_to_multiaddr(observed)))
+            .ok()?;
+        debug!("Node {} accepted a connection from {} in another shard", target_node, observed);
+        Some(end)
+    }
+
+    /// Sends an event to all the streams returned by `events()`.
+    fn emit(&mut self, event: NetworkEvent) {
+        if let Some(ref tracer) = self.tracer {
+            tracer.record(TraceEvent::Network(event.clone()));
+        }
+        self.events.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Terminates all the connections for which `filter` returns true by putting them in the
+    /// given state, and emits the corresponding events.
+    fn terminate_where<F>(&mut self, state: LinkState, mut filter: F)
+    where F: FnMut(usize, usize) -> bool
+    {
+        let mut severed = Vec::new();
+        self.connections.retain(|info| {
+            let link = match info.link.upgrade() {
+                Some(link) => link,
+                None => return false,
+            };
+            if !filter(info.dialer, info.listener) {
+                return true;
+            }
+            link.lock().set_state(state);
+            severed.push((info.dialer, info.listener));
+            false
+        });
+
+        for (dialer, listener) in severed {
+            debug!("Connection from node {} to node {} severed", dialer, listener);
+            self.emit(NetworkEvent::ConnectionSevered { dialer: dialer, listener: listener });
+        }
+    }
+}
+
+struct Node {
+    /// Address of the node on its local network.
+    local_ip: Ipv4Addr,
+    /// Address of the node as seen by the rest of the network. Equal to `local_ip` if the node
+    /// isn't behind a NAT.
+    public_ip: Ipv4Addr,
+    /// NAT in front of the node, if any.
+    nat: Option<Nat>,
+    /// False if the node has been stopped.
+    online: bool,
+    /// Identity of the node, as set with `set_peer_id`.
+    peer_id: Option<PeerId>,
+    /// Identity, runtime and resources of the node, if it has been spawned with `spawn_node`.
+    host: Option<HostInfo>,
+    /// Next port to try when listening on port 0.
+    next_listen_port: u16,
+    /// Next port to use when dialing without a listener.
+    next_ephemeral_port: u16,
+}
+
+impl SimNetwork {
+    /// Creates a new empty network.
+    pub fn new() -> SimNetwork {
+        SimNetwork::with_clock(SimClock::new())
+    }
+
+    /// Creates a new empty network that uses the given clock.
+    pub fn with_clock(clock: SimClock) -> SimNetwork {
+        SimNetwork {
+            stats: Stats::new(clock.clone()),
+            clock: clock,
+            inner: Arc::new(Mutex::new(NetworkInner {
+                nodes: Vec::new(),
+                listeners: Default::default(),
+                next_listener_id: 0,
+                connections: Vec::new(),
+                partitions: Vec::new(),
+                dial_timeout: Duration::from_secs(DEFAULT_DIAL_TIMEOUT_SECS),
+                events: Vec::new(),
+                link_model: LinkModel::default(),
+                rng: Arc::new(Mutex::new(XorShiftRng::from_seed([0x5a; 16]))),
+                tracer: None,
+                pcap: None,
+                gateway: None,
+            })),
+        }
+    }
+
+    /// Creates a new empty network that is the shard `shard` of a distributed simulation. The
+    /// connections with the nodes of other shards have the parameters `link` in both
+    /// directions. See the `distributed` module.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `shard` is greater than or equal to `distributed::MAX_SHARDS`.
+    pub fn for_shard(shard: u32, link: LinkParams) -> SimNetwork {
+        let network = SimNetwork::new();
+        network.inner.lock().gateway = Some(Gateway::new(shard, link));
+        network
+    }
+
+    /// Returns the index of the shard, if the network is a shard of a distributed simulation.
+    #[inline]
+    pub fn shard(&self) -> Option<u32> {
+        self.inner.lock().gateway.as_ref().map(|g| g.shard)
+    }
+
+    /// Returns the clock of the simulation.
+    #[inline]
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// Returns the statistics of the simulation, where protocols and harnesses record their
+    /// results.
+    #[inline]
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Adds a node that is directly reachable by every other node of the network, and returns
+    /// the transport to use for this node.
+    pub fn add_node(&self) -> SimTransport {
+        self.add_node_inner(None)
+    }
+
+    /// Adds a node that is placed behind a NAT, and returns the transport to use for this node.
+    pub fn add_node_behind_nat(&self, config: NatConfig) -> SimTransport {
+        self.add_node_inner(Some(Nat::new(config)))
+    }
+
+    fn add_node_inner(&self, nat: Option<Nat>) -> SimTransport {
+        let mut inner = self.inner.lock();
+        let index = inner.nodes.len();
+        // The nodes of each shard get addresses in a different range.
+        assert!(index < SHARD_CAPACITY || inner.gateway.is_none(), "too many nodes in the shard");
+        let global_index = inner.global_index(index);
+        let public_ip = node_ip(11, global_index);
+        let local_ip = if nat.is_some() { node_ip(10, global_index) } else { public_ip };
+        inner.nodes.push(Node {
+            local_ip: local_ip,
+            public_ip: public_ip,
+            nat: nat,
+            online: true,
+            peer_id: None,
+            host: None,
+            next_listen_port: FIRST_LISTEN_PORT,
+            next_ephemeral_port: FIRST_EPHEMERAL_PORT,
+        });
+        transport::new(self.clone(), index)
+    }
+
+    /// Adds a node according to the given configuration, and returns its handle.
+    ///
+    /// Contrary to `add_node`, the node is given an identity, which is also registered with
+    /// `set_peer_id`, a `Runtime` that follows the clock of the network, and a resource budget.
+    pub fn spawn_node(&self, config: SimNodeConfig) -> SimNode {
+        let transport = self.add_node_inner(config.nat.map(Nat::new));
+        let index = transport.node_index();
+
+        let public_key = config.public_key.unwrap_or_else(|| node::default_public_key(index));
+        let random = RandomSource::seeded(config.random_seed.unwrap_or(index as u64));
+        let host = HostInfo {
+            public_key: public_key.clone(),
+            runtime: Runtime::new(LocalExecutor, self.clock.timer()).with_random_source(random),
+            resources: ResourceManager::new(config.limits),
+        };
+
+        {
+            let mut inner = self.inner.lock();
+            inner.nodes[index].peer_id = Some(public_key.into_peer_id());
+            inner.nodes[index].host = Some(host.clone());
+        }
+
+        node::new(transport, host)
+    }
+
+    /// Adds `count` nodes and returns their handles.
+    ///
+    /// `configure` is called for each node with the position of the node in the batch and a
+    /// default `SimNodeConfig`, and returns the configuration of the node. This makes it
+    /// possible to spawn heterogeneous nodes.
+    pub fn spawn_nodes<F>(&self, count: usize, mut configure: F) -> Vec<SimNode>
+    where F: FnMut(usize, SimNodeConfig) -> SimNodeConfig
+    {
+        (0 .. count)
+            .map(|n| {
+                let config = configure(n, SimNodeConfig::new());
+                self.spawn_node(config)
+            })
+            .collect()
+    }
+
+    /// Returns the handle of a node that has been spawned with `spawn_node`.
+    ///
+    /// Returns `None` if there is no such node, or if it has been added with `add_node` or
+    /// `add_node_behind_nat`.
+    pub fn node(&self, index: usize) -> Option<SimNode> {
+        let host = self.inner.lock().nodes.get(index)?.host.clone()?;
+        Some(node::new(transport::new(self.clone(), index), host))
+    }
+
+    /// Returns the number of nodes in the network.
+    pub fn num_nodes(&self) -> usize {
+        self.inner.lock().nodes.len()
+    }
+
+    /// Sets the model that determines the latency, jitter and loss of the connections.
+    ///
+    /// The model is only applied to the connections that are opened afterwards.
+    pub fn set_link_model(&self, model: LinkModel) {
+        self.inner.lock().link_model = model;
+    }
+
+    /// Sets the time after which a connection attempt towards a node that is stopped, behind a
+    /// partition or behind a NAT that drops the attempt fails. Defaults to
+    /// `DEFAULT_DIAL_TIMEOUT_SECS` seconds.
+    ///
+    /// The timeout is only applied to the connection attempts that start afterwards.
+    pub fn set_dial_timeout(&self, timeout: Duration) {
+        self.inner.lock().dial_timeout = timeout;
+    }
+
+    /// Returns the time after which a connection attempt towards an unreachable node fails.
+    pub fn dial_timeout(&self) -> Duration {
+        self.inner.lock().dial_timeout
+    }
+
+    /// Associates a `PeerId` to a node, for `LinkModel::PeerCallback`.
+    pub fn set_peer_id(&self, node: usize, peer_id: PeerId) {
+        self.inner.lock().nodes[node].peer_id = Some(peer_id);
+    }
+
+    /// Returns the parameters of the link from `from` to `to` according to the current model.
+    pub fn link_params(&self, from: usize, to: usize) -> LinkParams {
+        let inner = self.inner.lock();
+        inner.link_params(from, to)
+    }
+
+    /// Sets the tracer that records the events happening on the network, or removes it.
+    pub fn set_tracer(&self, tracer: Option<Tracer>) {
+        self.inner.lock().tracer = tracer;
+    }
+
+    /// Sets the writer that exports the traffic of the connections opened from now on, or
+    /// removes it.
+    ///
+    /// The data is captured as it is written by the nodes, which means after encryption. Use
+    /// `PcapWriter::capture` in order to capture the plaintext traffic of a node.
+    pub fn set_pcap(&self, pcap: Option<PcapWriter>) {
+        self.inner.lock().pcap = pcap;
+    }
+
+    /// Returns a stream of the events happening on the network from now on.
+    pub fn events(&self) -> mpsc::UnboundedReceiver<NetworkEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.inner.lock().events.push(tx);
+        rx
+    }
+
+    /// Blocks all traffic between the nodes of `group_a` and the nodes of `group_b`.
+    ///
+    /// The open connections between the two groups are severed, and new connections between
+    /// them fail after the dial timeout, until `heal` is called. Nodes are designated by their
+    /// index in the network. In a shard of a distributed simulation, nodes are designated by
+    /// their index across all the shards, see `distributed::global_index_of`.
+    pub fn partition<A, B>(&self, group_a: A, group_b: B)
+    where A: IntoIterator<Item = usize>,
+          B: IntoIterator<Item = usize>,
+    {
+        let group_a = group_a.into_iter().collect::<FnvHashSet<_>>();
+        let group_b = group_b.into_iter().collect::<FnvHashSet<_>>();
+
+        let mut inner = self.inner.lock();
+        let mut event_a = group_a.iter().cloned().collect::<Vec<_>>();
+        let mut event_b = group_b.iter().cloned().collect::<Vec<_>>();
+        event_a.sort();
+        event_b.sort();
+        inner.emit(NetworkEvent::Partitioned { group_a: event_a, group_b: event_b });
+
+        let first = inner.global_index(0);
+        inner.terminate_where(LinkState::Reset, |dialer, listener| {
+            let (dialer, listener) = (first + dialer, first + listener);
+            (group_a.contains(&dialer) && group_b.contains(&listener)) ||
+                (group_a.contains(&listener) && group_b.contains(&dialer))
+        });
+        inner.partitions.push((group_a, group_b));
+    }
+
+    /// Returns the pairs of groups of nodes that can't reach each other, as passed to
+    /// `partition`. Each group is sorted.
+    pub fn partitions(&self) -> Vec<(Vec<usize>, Vec<usize>)> {
+        let sorted = |group: &FnvHashSet<usize>| {
+            let mut group = group.iter().cloned().collect::<Vec<_>>();
+            group.sort();
+            group
+        };
+        self.inner.lock().partitions.iter().map(|&(ref a, ref b)| (sorted(a), sorted(b))).collect()
+    }
+
+    /// Returns the connections that are open, as pairs of the index of the node that opened
+    /// the connection and of the index of the node that accepted it.
+    pub fn connections(&self) -> Vec<(usize, usize)> {
+        self.inner.lock().connections
+            .iter()
+            .filter(|info| info.link.upgrade().is_some())
+            .map(|info| (info.dialer, info.listener))
+            .collect()
+    }
+
+    /// Removes all the partitions created with `partition`.
+    ///
+    /// Connections that have been severed aren't restored, but new connections can be opened.
+    pub fn heal(&self) {
+        let mut inner = self.inner.lock();
+        inner.partitions.clear();
+        inner.emit(NetworkEvent::Healed);
+    }
+
+    /// Stops a node.
+    ///
+    /// The listeners of the node are closed and its connections are terminated according to
+    /// `mode`. Until `restart_node` is called, the node can neither listen nor dial, and
+    /// connection attempts towards it fail after the dial timeout.
+    ///
+    /// Does nothing if the node is already stopped.
+    pub fn stop_node(&self, node: usize, mode: StopMode) {
+        let mut inner = self.inner.lock();
+        if !inner.nodes[node].online {
+            return;
+        }
+
+        debug!("Stopping node {} ({:?})", node, mode);
+        inner.nodes[node].online = false;
+        let local_ip = inner.nodes[node].local_ip;
+        inner.listeners.retain(|addr, _| *addr.ip() != local_ip);
+        inner.emit(NetworkEvent::NodeStopped { node: node, mode: mode });
+
+        let state = match mode {
+            StopMode::Graceful => LinkState::Closed,
+            StopMode::Crash => LinkState::Blackholed,
+        };
+        inner.terminate_where(state, |dialer, listener| dialer == node || listener == node);
+    }
+
+    /// Starts again a node that has been stopped with `stop_node`. The node keeps its IP
+    /// address, but has to listen again.
+    ///
+    /// Does nothing if the node isn't stopped.
+    pub fn restart_node(&self, node: usize) {
+        let mut inner = self.inner.lock();
+        if inner.nodes[node].online {
+            return;
+        }
+
+        debug!("Restarting node {}", node);
+        inner.nodes[node].online = true;
+        inner.emit(NetworkEvent::NodeRestarted { node: node });
+    }
+
+    /// Returns true if the node hasn't been stopped.
+    pub fn is_online(&self, node: usize) -> bool {
+        self.inner.lock().nodes[node].online
+    }
+
+    /// Returns the local and public IP addresses of a node.
+    pub(crate) fn node_ips(&self, node: usize) -> (Ipv4Addr, Ipv4Addr) {
+        let inner = self.inner.lock();
+        (inner.nodes[node].local_ip, inner.nodes[node].public_ip)
+    }
+
+    /// Registers a listener for a node. Port 0 allocates a free port.
+    ///
+    /// Returns the receiving side of the listener, the actual address that is listened on and
+    /// the identifier of the listener, or an error if the address isn't valid for this node, is
+    /// already in use, or if the node is stopped.
+    pub(crate) fn listen(&self, node: usize, addr: SocketAddrV4)
+        -> Result<(mpsc::UnboundedReceiver<(SimConnection, Multiaddr)>, SocketAddrV4, u64), ()>
+    {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+
+        let local_ip = inner.nodes[node].local_ip;
+        if *addr.ip() != local_ip && !addr.ip().is_unspecified() {
+            return Err(());
+        }
+        if !inner.nodes[node].online {
+            return Err(());
+        }
+
+        let port = if addr.port() == 0 {
+            let node = &mut inner.nodes[node];
+            loop {
+                let port = node.next_listen_port;
+                node.next_listen_port = node.next_listen_port.wrapping_add(1).max(FIRST_LISTEN_PORT);
+                if !inner.listeners.contains_key(&SocketAddrV4::new(local_ip, port)) {
+                    break port;
+                }
+            }
+        } else {
+            addr.port()
+        };
+
+        let addr = SocketAddrV4::new(local_ip, port);
+        if inner.listeners.contains_key(&addr) {
+            return Err(());
+        }
+
+        let (tx, rx) = mpsc::unbounded();
+        let id = inner.next_listener_id;
+        inner.next_listener_id += 1;
+        inner.listeners.insert(addr, (id, tx));
+        debug!("Node {} listening on {}", node, addr);
+        Ok((rx, addr, id))
+    }
+
+    /// Unregisters a listener previously registered with `listen`. Does nothing if the listener
+    /// has already been removed, for example because its node has been stopped.
+    pub(crate) fn unlisten(&self, addr: &SocketAddrV4, id: u64) {
+        let mut inner = self.inner.lock();
+        if inner.listeners.get(addr).map(|l| l.0 == id).unwrap_or(false) {
+            inner.listeners.remove(addr);
+        }
+    }
+
+    /// Collects the data sent to other shards since the last call.
+    pub(crate) fn drain_remote(&self) -> Vec<Envelope> {
+        let mut inner = self.inner.lock();
+        match inner.gateway {
+            // Polling the connections requires a task.
+            Some(ref mut gateway) => future::lazy(|| Ok::<_, ()>(gateway.drain())).wait()
+                .expect("the future always succeeds"),
+            None => Vec::new(),
+        }
+    }
+
+    /// Handles data received from another shard.
+    pub(crate) fn deliver_remote(&self, envelope: Envelope) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        match envelope.kind {
+            EnvelopeKind::Connect { observed, destination } => {
+                let end = inner.accept_remote(&self.clock, observed, destination);
+                if let Some(ref mut gateway) = inner.gateway {
+                    gateway.accept(envelope.conn, end);
+                }
+            },
+            _ => {
+                if let Some(ref mut gateway) = inner.gateway {
+                    gateway.deliver(envelope);
+                }
+            },
+        }
+    }
+
+    /// Opens a connection from a node to the given address.
+    ///
+    /// The connection goes through the NAT of the dialing node, if any, then through the NAT
+    /// of the destination, if any.
+    ///
+    /// Returns the connection and the time the dialer has to wait before using it, which
+    /// corresponds to the round trip of the handshake. On error, returns the time the dialer
+    /// has to wait before noticing the error, which is the dial timeout if the destination is
+    /// unreachable.
+    pub(crate) fn connect(&self, node: usize, destination: SocketAddrV4)
+        -> Result<(SimConnection, Duration), (io::Error, Duration)>
+    {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        let dial_timeout = inner.dial_timeout;
+        let refused = || (io::Error::from(io::ErrorKind::ConnectionRefused), Duration::new(0, 0));
+        let timed_out = || (io::Error::from(io::ErrorKind::TimedOut), dial_timeout);
+
+        if !inner.nodes[node].online {
+            return Err((io::Error::from(io::ErrorKind::NotConnected), Duration::new(0, 0)));
+        }
+
+        // We reuse the port of a listener as source port, if any, in order to make it possible
+        // to traverse NATs with simultaneous connections.
+        let local_ip = inner.nodes[node].local_ip;
+        let listen_port = inner.listeners.keys()
+            .filter(|addr| *addr.ip() == local_ip)
+            .map(|addr| addr.port())
+            .min();
+        let source_port = match listen_port {
+            Some(port) => port,
+            None => {
+                let node = &mut inner.nodes[node];
+                let port = node.next_ephemeral_port;
+                node.next_ephemeral_port = node.next_ephemeral_port.wrapping_add(1)
+                    .max(FIRST_EPHEMERAL_PORT);
+                port
+            },
+        };
+
+        let mut mappings = Vec::new();
+
+        // Address of the dialer as seen by the destination.
+        let observed = {
+            let node = &mut inner.nodes[node];
+            match node.nat {
+                Some(ref mut nat) => {
+                    let (port, token) = nat.outbound(source_port, destination, now);
+                    mappings.push(token);
+                    SocketAddrV4::new(node.public_ip, port)
+                },
+                None => SocketAddrV4::new(node.local_ip, source_port),
+            }
+        };
+
+        let target_node = match inner.nodes.iter().position(|n| n.public_ip == *destination.ip()) {
+            Some(target_node) => target_node,
+            None => return inner.connect_remote(&self.clock, node, observed, destination, mappings),
+        };
+        if !inner.nodes[target_node].online {
+            debug!("Connection from {} to {} towards a stopped node", observed, destination);
+            return Err(timed_out());
+        }
+        if inner.is_partitioned(inner.global_index(node), inner.global_index(target_node)) {
+            debug!("Connection from {} to {} blocked by a partition", observed, destination);
+            return Err(timed_out());
+        }
+
+        // Local address of the listener.
+        let target = {
+            let target = &mut inner.nodes[target_node];
+            match target.nat {
+                Some(ref mut nat) => {
+                    match nat.inbound(destination.port(), observed, now) {
+                        Some((port, token)) => {
+                            mappings.push(token);
+                            SocketAddrV4::new(target.local_ip, port)
+                        },
+                        None => {
+                            debug!("Connection from {} to {} dropped by NAT", observed, destination);
+                            return Err(timed_out());
+                        },
+                    }
+                },
+                None => destination,
+            }
+        };
+
+        let to_listener = inner.link_params(node, target_node);
+        let to_dialer = inner.link_params(target_node, node);
+        let handshake = to_listener.latency + to_dialer.latency;
+
+        let link = Arc::new(Mutex::new(Link::default()));
+        let (mut dialer_end, mut listener_end) = connection::channel_pair(
+            link.clone(),
+            mappings,
+            Transmitter::new(self.clock.clone(), to_listener, inner.rng.clone()),
+            Transmitter::new(self.clock.clone(), to_dialer, inner.rng.clone())
+        );
+        if let Some(ref pcap) = inner.pcap {
+            // The connection is captured as seen from the public network.
+            let flow = pcap.open_flow(observed, destination);
+            dialer_end.set_capture(Capture::new(flow.clone(), true));
+            listener_end.set_capture(Capture::new(flow, false));
+        }
+        inner.listeners.get(&target)
+            .ok_or_else(refused)?
+            .1.unbounded_send((listener_end.into(), transport::socket_to_multiaddr(observed)))
+            .map_err(|_| refused())?;
+
+        // Clean up the connections that have been closed, so that the list doesn't grow forever.
+        inner.connections.retain(|info| info.link.upgrade().is_some());
+        inner.connections.push(ConnectionInfo {
+            dialer: node,
+            listener: target_node,
+            link: Arc::downgrade(&link),
+        });
+        debug!("Node {} connected to {} as {}", node, destination, observed);
+        inner.emit(NetworkEvent::ConnectionOpened { dialer: node, listener: target_node });
+        Ok((dialer_end.into(), handshake))
+    }
+}
+
+/// Builds the IP address of the node with the given index.
+fn node_ip(prefix: u8, index: usize) -> Ipv4Addr {
+    let n = index + 1;
+    Ipv4Addr::new(prefix, (n >> 16) as u8, (n >> 8) as u8, n as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use distributed::SHARD_CAPACITY;
+    use futures::{future, prelude::*};
+    use link::LinkParams;
+    use multiaddr::Multiaddr;
+    use std::io::{self, Read};
+    use std::time::Duration;
+    use nat::{NatConfig, NatType};
+    use node::SimNodeConfig;
+    use swarm::Transport;
+    use swarm::resource_manager::ResourceLimits;
+    use super::{NetworkEvent, SimNetwork, StopMode, DEFAULT_DIAL_TIMEOUT_SECS};
+    use transport::SimTransport;
+
+    /// Dials `addr` and checks that the attempt fails with `TimedOut` once the dial timeout has
+    /// elapsed on the clock of `network`, and not before.
+    fn assert_dial_times_out(network: &SimNetwork, transport: SimTransport, addr: Multiaddr) {
+        let mut dial = transport.dial(addr).ok().unwrap();
+        let clock = network.clock().clone();
+        let timeout = network.dial_timeout();
+        future::lazy(move || {
+            assert!(dial.poll().unwrap().is_not_ready());
+            clock.advance(timeout - Duration::from_millis(1));
+            assert!(dial.poll().unwrap().is_not_ready());
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(dial.poll().err().unwrap().kind(), io::ErrorKind::TimedOut);
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn partition_and_heal() {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node();
+        let events = network.events();
+
+        let (listener, addr) = a.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).ok().unwrap();
+        let dialer_conn = b.clone().dial(addr.clone()).ok().unwrap().wait().unwrap();
+        let (incoming, listener) = listener.into_future().map_err(|(err, _)| err).wait().unwrap();
+        let mut listener_conn = incoming.unwrap().0.wait().unwrap();
+
+        network.partition(vec![0], vec![1]);
+        let err = listener_conn.read(&mut [0; 16]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        drop(dialer_conn);
+
+        assert_dial_times_out(&network, b.clone(), addr.clone());
+
+        network.heal();
+        assert!(b.dial(addr).ok().unwrap().wait().is_ok());
+        drop(listener);
+
+        let events = events.take(5).collect().wait().unwrap();
+        assert_eq!(events, vec![
+            NetworkEvent::ConnectionOpened { dialer: 1, listener: 0 },
+            NetworkEvent::Partitioned { group_a: vec![0], group_b: vec![1] },
+            NetworkEvent::ConnectionSevered { dialer: 1, listener: 0 },
+            NetworkEvent::Healed,
+            NetworkEvent::ConnectionOpened { dialer: 1, listener: 0 },
+        ]);
+    }
+
+    #[test]
+    fn stop_and_restart_node() {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node();
+
+        let (listener, addr) = a.clone().listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+            .ok().unwrap();
+        let mut dialer_conn = b.clone().dial(addr.clone()).ok().unwrap().wait().unwrap();
+        let (incoming, listener) = listener.into_future().map_err(|(err, _)| err).wait().unwrap();
+        let _listener_conn = incoming.unwrap().0.wait().unwrap();
+
+        network.stop_node(0, StopMode::Graceful);
+        assert_eq!(dialer_conn.read(&mut [0; 16]).unwrap(), 0);
+        assert!(listener.collect().wait().unwrap().is_empty());
+
+        assert_dial_times_out(&network, b.clone(), addr.clone());
+
+        network.restart_node(0);
+        let (_listener, addr) = a.listen_on(addr).ok().unwrap();
+        assert!(b.dial(addr).ok().unwrap().wait().is_ok());
+    }
+
+    #[test]
+    fn dial_timeout_is_configurable() {
+        let network = SimNetwork::new();
+        assert_eq!(network.dial_timeout(), Duration::from_secs(DEFAULT_DIAL_TIMEOUT_SECS));
+        network.set_dial_timeout(Duration::from_secs(3));
+        let a = network.add_node();
+        let b = network.add_node();
+        let (_listener, addr) = a.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).ok().unwrap();
+        network.stop_node(0, StopMode::Crash);
+
+        assert_dial_times_out(&network, b, addr);
+        assert_eq!(network.clock().now(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn partition_applies_to_other_shards() {
+        let network = SimNetwork::for_shard(1, LinkParams::default());
+        let node = network.add_node();
+        let remote: Multiaddr = "/ip4/11.0.0.1/tcp/1000".parse().unwrap();
+
+        // The node 0 of shard 0 is partitioned from the node 0 of this shard.
+        network.partition(vec![SHARD_CAPACITY], vec![0]);
+        assert_dial_times_out(&network, node.clone(), remote.clone());
+
+        network.heal();
+        assert!(node.dial(remote).ok().unwrap().wait().is_ok());
+    }
+
+    #[test]
+    fn spawn_heterogeneous_nodes() {
+        let network = SimNetwork::new();
+        network.add_node();
+        let nodes = network.spawn_nodes(4, |n, config: SimNodeConfig| {
+            if n == 0 {
+                config.with_nat(NatConfig::new(NatType::FullCone))
+                    .with_limits(ResourceLimits::new().with_max_connections(1))
+            } else {
+                config
+            }
+        });
+
+        assert!(network.node(0).is_none());
+        assert_eq!(nodes[0].index(), 1);
+        assert_ne!(nodes[0].public_ip(), nodes[0].transport().local_ip());
+        assert_eq!(nodes[0].resources().limits().max_connections(), Some(1));
+        assert_eq!(nodes[1].public_ip(), nodes[1].transport().local_ip());
+        assert_ne!(nodes[1].peer_id(), nodes[2].peer_id());
+        assert_eq!(network.node(3).unwrap().peer_id(), nodes[2].peer_id());
+        assert_ne!(nodes[1].runtime().random_source().next_u64(),
+                   nodes[2].runtime().random_source().next_u64());
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Simulated nodes.
+//!
+//! A `SimNode` bundles everything a node of the simulation needs in order to run a swarm: its
+//! identity, its transport, a `Runtime` whose time is the one of the simulation, and a
+//! `ResourceManager` that enforces its resource budget.
+//!
+//! Nodes are spawned with `SimNetwork::spawn_node` or `SimNetwork::spawn_nodes`, according to a
+//! `SimNodeConfig`. Passing a closure to `spawn_nodes` makes it possible to give different
+//! configurations to the nodes, for example in order to put some of them behind a NAT.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_sim::{NatConfig, NatType, SimNetwork, SimNodeConfig};
+//!
+//! # fn main() {
+//! let network = SimNetwork::new();
+//! // One node out of ten is behind a NAT.
+//! let nodes = network.spawn_nodes(500, |n, config: SimNodeConfig| {
+//!     if n % 10 == 0 {
+//!         config.with_nat(NatConfig::new(NatType::Symmetric))
+//!     } else {
+//!         config
+//!     }
+//! });
+//! assert_eq!(nodes.len(), 500);
+//! assert_eq!(network.node(42).unwrap().peer_id(), nodes[42].peer_id());
+//! # }
+//! ```
+
+use clock::SimClock;
+use nat::NatConfig;
+use network::SimNetwork;
+use std::net::Ipv4Addr;
+use swarm::resource_manager::{ResourceLimits, ResourceManager};
+use swarm::runtime::Runtime;
+use swarm::{PeerId, PublicKey};
+use transport::SimTransport;
+
+/// Configuration of a node spawned with `SimNetwork::spawn_node`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimNodeConfig {
+    pub(crate) nat: Option<NatConfig>,
+    pub(crate) public_key: Option<PublicKey>,
+    pub(crate) limits: ResourceLimits,
+    pub(crate) random_seed: Option<u64>,
+}
+
+impl SimNodeConfig {
+    /// Builds the configuration of a node that is directly reachable, whose identity and random
+    /// seed are derived from its index, and that has no resource limit.
+    #[inline]
+    pub fn new() -> SimNodeConfig {
+        SimNodeConfig {
+            nat: None,
+            public_key: None,
+            limits: ResourceLimits::new(),
+            random_seed: None,
+        }
+    }
+
+    /// Places the node behind a NAT.
+    #[inline]
+    pub fn with_nat(mut self, nat: NatConfig) -> Self {
+        self.nat = Some(nat);
+        self
+    }
+
+    /// Sets the public key of the node, and therefore its `PeerId`.
+    ///
+    /// > **Note**: By default the key is derived from the index of the node. Such a key has no
+    /// >           private counterpart, which is fine as long as the protocols don't need to sign
+    /// >           anything.
+    #[inline]
+    pub fn with_public_key(mut self, public_key: PublicKey) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Sets the resource budget of the node.
+    #[inline]
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the seed of the `RandomSource` of the node's `Runtime`. By default, the index of the
+    /// node is used.
+    #[inline]
+    pub fn with_random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+}
+
+impl Default for SimNodeConfig {
+    #[inline]
+    fn default() -> Self {
+        SimNodeConfig::new()
+    }
+}
+
+/// Information about a node spawned with `SimNetwork::spawn_node`, kept by the network.
+#[derive(Clone)]
+pub(crate) struct HostInfo {
+    pub(crate) public_key: PublicKey,
+    pub(crate) runtime: Runtime,
+    pub(crate) resources: ResourceManager,
+}
+
+/// Node of a simulated network.
+///
+/// Cloning a `SimNode` produces a handle to the same node.
+#[derive(Clone)]
+pub struct SimNode {
+    transport: SimTransport,
+    peer_id: PeerId,
+    host: HostInfo,
+}
+
+/// Builds the handle of a node.
+pub(crate) fn new(transport: SimTransport, host: HostInfo) -> SimNode {
+    SimNode {
+        peer_id: host.public_key.clone().into_peer_id(),
+        transport,
+        host,
+    }
+}
+
+/// Returns the public key derived from the index of a node.
+pub(crate) fn default_public_key(index: usize) -> PublicKey {
+    let mut key = vec![0x5a; 32];
+    for (n, byte) in key.iter_mut().take(8).enumerate() {
+        *byte = (index as u64 >> (8 * n)) as u8;
+    }
+    PublicKey::Ed25519(key)
+}
+
+impl SimNode {
+    /// Returns the index of the node in the network.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.transport.node_index()
+    }
+
+    /// Returns the `PeerId` of the node.
+    #[inline]
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    /// Returns the public key of the node.
+    #[inline]
+    pub fn public_key(&self) -> &PublicKey {
+        &self.host.public_key
+    }
+
+    /// Returns the transport of the node. Clone it in order to build the swarm.
+    #[inline]
+    pub fn transport(&self) -> &SimTransport {
+        &self.transport
+    }
+
+    /// Returns the network the node belongs to.
+    #[inline]
+    pub fn network(&self) -> &SimNetwork {
+        self.transport.network()
+    }
+
+    /// Returns the clock of the simulation.
+    #[inline]
+    pub fn clock(&self) -> &SimClock {
+        self.transport.network().clock()
+    }
+
+    /// Returns the `Runtime` to pass to the swarm of the node. Its tasks are never spawned in
+    /// the background, its time is the one of the simulation, and its `RandomSource` is seeded.
+    #[inline]
+    pub fn runtime(&self) -> &Runtime {
+        &self.host.runtime
+    }
+
+    /// Returns the `ResourceManager` that enforces the budget of the node. Wrap the transport of
+    /// the node in a `ResourceLimitedTransport` with this manager in order to apply it.
+    #[inline]
+    pub fn resources(&self) -> &ResourceManager {
+        &self.host.resources
+    }
+
+    /// Returns the IP address of the node as seen by the rest of the network.
+    #[inline]
+    pub fn public_ip(&self) -> Ipv4Addr {
+        self.transport.public_ip()
+    }
+
+    /// Returns true if the node hasn't been stopped.
+    #[inline]
+    pub fn is_online(&self) -> bool {
+        self.network().is_online(self.index())
+    }
+}
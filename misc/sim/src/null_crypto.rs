@@ -0,0 +1,279 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Authentication without encryption, in order to speed up large simulations.
+//!
+//! In runs with thousands of nodes, most of the CPU time is spent encrypting data whose
+//! confidentiality doesn't matter. `NullCryptoConfig` is an upgrade that can replace `secio` in
+//! a simulation: the two nodes exchange and authenticate their public keys, which gives the same
+//! `PeerId`s and the same verification results as a real handshake, but the data is then sent in
+//! clear.
+//!
+//! Instead of signatures, a node proves that it owns a public key by hashing a secret token that
+//! the `NullCryptoKeyring` of the simulation assigned to the key. A node that claims a key it
+//! didn't register fails the handshake, as it would with `secio`.
+//!
+//! This module is only available with the `null-crypto` feature.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_core;
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_core::{PublicKey, runtime::RandomSource};
+//! use libp2p_sim::null_crypto::NullCryptoKeyring;
+//!
+//! # fn main() {
+//! let keyring = NullCryptoKeyring::new(RandomSource::seeded(0));
+//! let config = keyring.register(PublicKey::Ed25519(vec![1; 32]));
+//! // `config` is then used as the security upgrade of the node, instead of `SecioConfig`.
+//! # let _ = config;
+//! # }
+//! ```
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::{io, iter, sync::Arc};
+use swarm::{ConnectionUpgrade, Endpoint, PeerId, PublicKey};
+use swarm::runtime::RandomSource;
+use swarm::transport::AuthenticatedOutput;
+use tokio_io::{AsyncRead, AsyncWrite, io as tio};
+
+/// Maximum length of an encoded public key.
+const MAX_KEY_LEN: usize = 4096;
+
+/// Assigns a secret token to the public key of each node of the simulation.
+///
+/// Cloning a `NullCryptoKeyring` produces a handle to the same tokens.
+#[derive(Clone)]
+pub struct NullCryptoKeyring {
+    tokens: Arc<Mutex<FnvHashMap<Vec<u8>, u64>>>,
+    random: RandomSource,
+}
+
+impl NullCryptoKeyring {
+    /// Creates an empty keyring. The tokens are drawn from `random`.
+    pub fn new(random: RandomSource) -> NullCryptoKeyring {
+        NullCryptoKeyring {
+            tokens: Arc::new(Mutex::new(FnvHashMap::default())),
+            random,
+        }
+    }
+
+    /// Registers a public key, and returns the upgrade for the node that owns it. Registering
+    /// the same key again returns an upgrade with the same token.
+    pub fn register(&self, public_key: PublicKey) -> NullCryptoConfig {
+        let encoded = public_key.clone().into_protobuf_encoding();
+        let random = &self.random;
+        let token = *self.tokens.lock().entry(encoded).or_insert_with(|| random.next_u64());
+        NullCryptoConfig {
+            public_key,
+            token,
+            keyring: self.clone(),
+        }
+    }
+
+    /// Returns the token of an encoded public key, if it has been registered.
+    fn token(&self, encoded_key: &[u8]) -> Option<u64> {
+        self.tokens.lock().get(encoded_key).cloned()
+    }
+}
+
+/// Upgrade that authenticates the remote without encrypting the connection.
+///
+/// See the module-level documentation.
+#[derive(Clone)]
+pub struct NullCryptoConfig {
+    public_key: PublicKey,
+    token: u64,
+    keyring: NullCryptoKeyring,
+}
+
+impl NullCryptoConfig {
+    /// Returns the public key that this upgrade authenticates as.
+    #[inline]
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+}
+
+/// Output of the `NullCryptoConfig` upgrade.
+pub struct NullCryptoOutput<S> {
+    /// The connection, unencrypted.
+    pub stream: S,
+    /// The public key of the remote.
+    pub remote_key: PublicKey,
+}
+
+impl<S> AuthenticatedOutput for NullCryptoOutput<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Stream = S;
+
+    #[inline]
+    fn into_authenticated(self) -> (PeerId, Self::Stream) {
+        (self.remote_key.into_peer_id(), self.stream)
+    }
+}
+
+impl<S> ConnectionUpgrade<S> for NullCryptoConfig
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once(("/sim/null-crypto/1.0.0".into(), ()))
+    }
+
+    type Output = NullCryptoOutput<S>;
+    type Future = Box<Future<Item = Self::Output, Error = io::Error> + Send>;
+
+    fn upgrade(self, socket: S, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        let local_nonce = self.keyring.random.next_u64();
+        let local_key = self.public_key.clone().into_protobuf_encoding();
+        let mut hello = Vec::with_capacity(local_key.len() + 12);
+        hello.extend_from_slice(&to_be_bytes(local_key.len() as u64)[4..]);
+        hello.extend_from_slice(&local_key);
+        hello.extend_from_slice(&to_be_bytes(local_nonce));
+
+        let future = tio::write_all(socket, hello)
+            .and_then(|(socket, _)| tio::read_exact(socket, [0; 4]))
+            .and_then(|(socket, len)| {
+                let len = from_be_bytes(&len) as usize;
+                if len > MAX_KEY_LEN {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "public key too long"));
+                }
+                Ok(tio::read_exact(socket, vec![0; len + 8]))
+            })
+            .flatten()
+            .and_then(move |(socket, remote)| {
+                let (remote_key, remote_nonce) = remote.split_at(remote.len() - 8);
+                let remote_nonce = from_be_bytes(remote_nonce);
+                let proof = proof(self.token, remote_nonce);
+                let remote_key = remote_key.to_vec();
+                tio::write_all(socket, to_be_bytes(proof))
+                    .and_then(|(socket, _)| tio::read_exact(socket, [0; 8]))
+                    .map(move |(socket, remote_proof)| (socket, remote_key, remote_proof, self))
+            })
+            .and_then(move |(socket, remote_key, remote_proof, config)| {
+                let expected = config.keyring.token(&remote_key)
+                    .map(|token| proof(token, local_nonce));
+                if expected != Some(from_be_bytes(&remote_proof)) {
+                    debug!("Remote failed to prove the ownership of its public key");
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                              "remote failed to authenticate"));
+                }
+                let remote_key = PublicKey::from_protobuf_encoding(&remote_key)?;
+                Ok(NullCryptoOutput {
+                    stream: socket,
+                    remote_key,
+                })
+            });
+
+        Box::new(future)
+    }
+}
+
+/// Proof that a node knows `token`, bound to the nonce chosen by the remote.
+fn proof(token: u64, nonce: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u64(token);
+    hasher.write_u64(nonce);
+    hasher.finish()
+}
+
+/// Encodes a `u64` in big endian.
+fn to_be_bytes(value: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (n, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (56 - 8 * n)) as u8;
+    }
+    bytes
+}
+
+/// Decodes a big-endian integer of at most 8 bytes.
+fn from_be_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use network::SimNetwork;
+    use swarm::{ConnectionUpgrade, Endpoint, PublicKey, Transport};
+    use swarm::runtime::RandomSource;
+    use super::{NullCryptoConfig, NullCryptoKeyring};
+    use tokio_current_thread;
+
+    /// Connects two nodes and upgrades the connection on both sides. Returns the key
+    /// authenticated by the listener, or its error.
+    fn handshake(listener: NullCryptoConfig, dialer: NullCryptoConfig)
+        -> Result<PublicKey, ::std::io::Error>
+    {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node();
+        let (incoming, addr) = a.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).ok().unwrap();
+
+        let addr2 = addr.clone();
+        let dial = b.dial(addr.clone()).ok().unwrap()
+            .and_then(move |socket| dialer.upgrade(socket, (), Endpoint::Dialer, &addr2))
+            .then(|_| Ok(()));
+        let listen = incoming.into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(incoming, _)| incoming.unwrap().0)
+            .and_then(move |socket| listener.upgrade(socket, (), Endpoint::Listener, &addr))
+            .map(|output| output.remote_key);
+
+        tokio_current_thread::block_on_all(dial.join(listen).map(|(_, key)| key))
+    }
+
+    #[test]
+    fn authenticates_registered_keys() {
+        let keyring = NullCryptoKeyring::new(RandomSource::seeded(0));
+        let a = keyring.register(PublicKey::Ed25519(vec![1; 32]));
+        let b = keyring.register(PublicKey::Ed25519(vec![2; 32]));
+        assert_eq!(handshake(a, b).unwrap(), PublicKey::Ed25519(vec![2; 32]));
+    }
+
+    #[test]
+    fn rejects_impostor() {
+        let keyring = NullCryptoKeyring::new(RandomSource::seeded(0));
+        let a = keyring.register(PublicKey::Ed25519(vec![1; 32]));
+        keyring.register(PublicKey::Ed25519(vec![2; 32]));
+        // Claims the key of `b` without knowing its token.
+        let impostor = NullCryptoConfig {
+            public_key: PublicKey::Ed25519(vec![2; 32]),
+            token: 0,
+            keyring: keyring.clone(),
+        };
+        assert!(handshake(a, impostor).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Export of the traffic of the simulated network in the pcapng format.
+//!
+//! The traffic can be captured at two levels:
+//!
+//! - With `SimNetwork::set_pcap`, all the data sent over the connections of the network is
+//!   captured as it is written on the wire, which means after encryption if the nodes use an
+//!   encryption upgrade.
+//! - With `PcapWriter::capture`, the data sent and received over the substreams negotiated by
+//!   an upgrade is captured. Wrapping the upgrades that are applied after encryption captures
+//!   the plaintext traffic of a node.
+//!
+//! Each connection is exported as a TCP connection between the public addresses of the nodes,
+//! with a synthetic handshake, so that Wireshark reassembles the streams and applies its
+//! dissectors. The timestamps are the times of the virtual clock.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_sim::{PcapWriter, SimNetwork};
+//!
+//! # fn main() {
+//! let network = SimNetwork::new();
+//! let pcap = PcapWriter::create("simulation.pcapng", network.clock().clone()).unwrap();
+//! network.set_pcap(Some(pcap));
+//! # }
+//! ```
+
+use clock::SimClock;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use std::sync::Arc;
+use swarm::{ConnectionUpgrade, Endpoint};
+use tokio_io::{AsyncRead, AsyncWrite};
+use trace::duration_nanos;
+use transport::multiaddr_to_socketaddr;
+
+
+/// Link type of packets that start with an IP header.
+const LINKTYPE_RAW: u16 = 101;
+
+/// Maximum payload of a captured TCP segment, so that the IP packet fits in 64 KiB.
+const MAX_SEGMENT: usize = 65535 - 40;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Writes captured traffic to a pcapng file.
+///
+/// Cloning a `PcapWriter` produces a handle to the same file.
+#[derive(Clone)]
+pub struct PcapWriter {
+    inner: Arc<Mutex<PcapInner>>,
+}
+
+struct PcapInner {
+    out: Box<Write + Send>,
+    clock: SimClock,
+    /// Connections being captured.
+    flows: FnvHashMap<u64, FlowState>,
+    /// Identifier of the next connection.
+    next_flow: u64,
+    /// Identification field of the next IP packet.
+    next_ip_id: u16,
+}
+
+/// State of a TCP connection being captured.
+struct FlowState {
+    /// Address of the node that opened the connection.
+    initiator: SocketAddrV4,
+    /// Address of the node that accepted the connection.
+    responder: SocketAddrV4,
+    /// Next sequence number of the initiator.
+    initiator_seq: u32,
+    /// Next sequence number of the responder.
+    responder_seq: u32,
+}
+
+impl PcapWriter {
+    /// Creates a writer that writes to `out`, with timestamps taken from `clock`. Writes the
+    /// headers of the file immediately.
+    pub fn new<W>(out: W, clock: SimClock) -> Result<PcapWriter, io::Error>
+    where W: Write + Send + 'static
+    {
+        let mut out: Box<Write + Send> = Box::new(out);
+
+        // Section header block, version 1.0, without options and with an unknown length.
+        let mut shb = Vec::with_capacity(16);
+        put_le(&mut shb, 0x1a2b_3c4d, 4);
+        put_le(&mut shb, 1, 2);
+        put_le(&mut shb, 0, 2);
+        shb.extend_from_slice(&[0xff; 8]);
+        write_block(&mut out, 0x0a0d_0d0a, &shb)?;
+
+        // Interface description block. Without options, the timestamps are in microseconds.
+        let mut idb = Vec::with_capacity(8);
+        put_le(&mut idb, u32::from(LINKTYPE_RAW), 2);
+        put_le(&mut idb, 0, 2);
+        put_le(&mut idb, 0, 4);
+        write_block(&mut out, 1, &idb)?;
+
+        Ok(PcapWriter {
+            inner: Arc::new(Mutex::new(PcapInner {
+                out,
+                clock,
+                flows: FnvHashMap::default(),
+                next_flow: 0,
+                next_ip_id: 0,
+            })),
+        })
+    }
+
+    /// Creates a writer that writes to a new file at `path`.
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P, clock: SimClock) -> Result<PcapWriter, io::Error> {
+        PcapWriter::new(BufWriter::new(File::create(path)?), clock)
+    }
+
+    /// Writes the buffered packets to the underlying writer.
+    #[inline]
+    pub fn flush(&self) -> Result<(), io::Error> {
+        self.inner.lock().out.flush()
+    }
+
+    /// Wraps around an upgrade in order to capture the data of the substreams it negotiates.
+    /// `local_addr` is the address the captured connections have on the side of the node that
+    /// uses the upgrade.
+    ///
+    /// Applying the upgrade to an encrypted connection captures its plaintext.
+    ///
+    /// > **Note**: Both the data sent and received are captured. Wrapping the upgrades of the
+    /// >           two ends of a connection therefore captures its data twice.
+    #[inline]
+    pub fn capture<U>(&self, local_addr: SocketAddrV4, upgrade: U) -> PcapUpgrade<U> {
+        PcapUpgrade {
+            inner: upgrade,
+            writer: self.clone(),
+            local_addr,
+        }
+    }
+
+    /// Starts capturing a connection from `initiator` to `responder`, and writes the packets of
+    /// the TCP handshake. The connection is forgotten once the returned `Flow` is destroyed.
+    pub(crate) fn open_flow(&self, initiator: SocketAddrV4, responder: SocketAddrV4)
+        -> Arc<Flow>
+    {
+        let mut inner = self.inner.lock();
+        let id = inner.next_flow;
+        inner.next_flow += 1;
+        inner.flows.insert(id, FlowState {
+            initiator,
+            responder,
+            initiator_seq: 0,
+            responder_seq: 0,
+        });
+        inner.segment(id, true, TCP_SYN, &[]);
+        inner.segment(id, false, TCP_SYN | TCP_ACK, &[]);
+        inner.segment(id, true, TCP_ACK, &[]);
+
+        Arc::new(Flow {
+            writer: self.clone(),
+            id,
+        })
+    }
+}
+
+impl PcapInner {
+    /// Writes a TCP segment of the given connection, and updates its sequence numbers.
+    fn segment(&mut self, flow: u64, from_initiator: bool, flags: u8, payload: &[u8]) {
+        let ip_id = self.next_ip_id;
+        let packet = {
+            let flow = match self.flows.get_mut(&flow) {
+                Some(flow) => flow,
+                None => return,
+            };
+            let (src, dst, seq, ack) = if from_initiator {
+                (flow.initiator, flow.responder, flow.initiator_seq, flow.responder_seq)
+            } else {
+                (flow.responder, flow.initiator, flow.responder_seq, flow.initiator_seq)
+            };
+            // SYN and FIN consume one sequence number.
+            let len = payload.len() as u32 + if flags & (TCP_SYN | TCP_FIN) != 0 { 1 } else { 0 };
+            if from_initiator {
+                flow.initiator_seq = seq.wrapping_add(len);
+            } else {
+                flow.responder_seq = seq.wrapping_add(len);
+            }
+            let ack = if flags & TCP_ACK != 0 { ack } else { 0 };
+            build_packet(src, dst, seq, ack, flags, ip_id, payload)
+        };
+        self.next_ip_id = ip_id.wrapping_add(1);
+
+        // Enhanced packet block, on the only interface of the file.
+        let micros = duration_nanos(self.clock.now()) / 1000;
+        let mut epb = Vec::with_capacity(packet.len() + 20);
+        put_le(&mut epb, 0, 4);
+        put_le(&mut epb, (micros >> 32) as u32, 4);
+        put_le(&mut epb, micros as u32, 4);
+        put_le(&mut epb, packet.len() as u32, 4);
+        put_le(&mut epb, packet.len() as u32, 4);
+        epb.extend_from_slice(&packet);
+        if let Err(err) = write_block(&mut self.out, 6, &epb) {
+            debug!("Failed to write a captured packet: {:?}", err);
+        }
+    }
+}
+
+/// Connection being captured. Removed from its `PcapWriter` when destroyed.
+pub(crate) struct Flow {
+    writer: PcapWriter,
+    id: u64,
+}
+
+impl Drop for Flow {
+    fn drop(&mut self) {
+        self.writer.inner.lock().flows.remove(&self.id);
+    }
+}
+
+/// Captures the traffic of one end of a connection.
+pub(crate) struct Capture {
+    flow: Arc<Flow>,
+    /// True if this end opened the connection.
+    initiator: bool,
+    /// True if the FIN of this end has been written.
+    closed: bool,
+    /// True if the FIN of the remote has been written.
+    remote_closed: bool,
+}
+
+impl Capture {
+    /// Captures the end of `flow` that opened the connection if `initiator` is true, or the end
+    /// that accepted it otherwise.
+    #[inline]
+    pub fn new(flow: Arc<Flow>, initiator: bool) -> Capture {
+        Capture {
+            flow,
+            initiator,
+            closed: false,
+            remote_closed: false,
+        }
+    }
+
+    /// Captures data sent by this end.
+    pub fn sent(&mut self, data: &[u8]) {
+        self.data(self.initiator, data)
+    }
+
+    /// Captures data received by this end.
+    pub fn received(&mut self, data: &[u8]) {
+        let from_initiator = !self.initiator;
+        self.data(from_initiator, data)
+    }
+
+    /// Captures the closing of the sending direction of this end.
+    pub fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.fin(self.initiator);
+        }
+    }
+
+    /// Captures the closing of the sending direction of the remote.
+    pub fn remote_close(&mut self) {
+        if !self.remote_closed {
+            self.remote_closed = true;
+            let from_initiator = !self.initiator;
+            self.fin(from_initiator);
+        }
+    }
+
+    fn data(&self, from_initiator: bool, data: &[u8]) {
+        let mut inner = self.flow.writer.inner.lock();
+        for chunk in data.chunks(MAX_SEGMENT) {
+            inner.segment(self.flow.id, from_initiator, TCP_PSH | TCP_ACK, chunk);
+        }
+    }
+
+    fn fin(&self, from_initiator: bool) {
+        let mut inner = self.flow.writer.inner.lock();
+        inner.segment(self.flow.id, from_initiator, TCP_FIN | TCP_ACK, &[]);
+    }
+}
+
+impl Drop for Capture {
+    #[inline]
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Upgrade that captures the data of the substreams negotiated by another upgrade. See
+/// `PcapWriter::capture`.
+#[derive(Clone)]
+pub struct PcapUpgrade<U> {
+    inner: U,
+    writer: PcapWriter,
+    local_addr: SocketAddrV4,
+}
+
+impl<C, U> ConnectionUpgrade<C> for PcapUpgrade<U>
+where
+    C: AsyncRead + AsyncWrite,
+    U: ConnectionUpgrade<PcapStream<C>>,
+{
+    type NamesIter = U::NamesIter;
+    type UpgradeIdentifier = U::UpgradeIdentifier;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        self.inner.protocol_names()
+    }
+
+    type Output = U::Output;
+    type Future = U::Future;
+
+    fn upgrade(
+        self,
+        socket: C,
+        id: Self::UpgradeIdentifier,
+        ty: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        // Addresses that aren't IPv4 can't be represented in the capture.
+        let remote = multiaddr_to_socketaddr(remote_addr)
+            .unwrap_or_else(|()| SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0));
+        let capture = match ty {
+            Endpoint::Dialer => Capture::new(self.writer.open_flow(self.local_addr, remote), true),
+            Endpoint::Listener => Capture::new(self.writer.open_flow(remote, self.local_addr), false),
+        };
+        let socket = PcapStream {
+            inner: socket,
+            capture,
+        };
+        self.inner.upgrade(socket, id, ty, remote_addr)
+    }
+}
+
+/// Stream whose traffic is captured by a `PcapWriter`.
+pub struct PcapStream<S> {
+    inner: S,
+    capture: Capture,
+}
+
+impl<S: Read> Read for PcapStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.capture.remote_close();
+        } else {
+            self.capture.received(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for PcapStream<S> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<S: Write> Write for PcapStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.capture.sent(&buf[..n]);
+        Ok(n)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for PcapStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.inner.shutdown()? {
+            Async::Ready(()) => {
+                self.capture.close();
+                Ok(Async::Ready(()))
+            },
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Writes a pcapng block, padding its body to a multiple of 4 bytes.
+fn write_block<W: Write + ?Sized>(out: &mut W, ty: u32, body: &[u8]) -> Result<(), io::Error> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    let mut block = Vec::with_capacity(len as usize);
+    put_le(&mut block, ty, 4);
+    put_le(&mut block, len, 4);
+    block.extend_from_slice(body);
+    block.extend_from_slice(&[0; 3][..padding]);
+    put_le(&mut block, len, 4);
+    out.write_all(&block)
+}
+
+/// Builds an IPv4 packet that contains a TCP segment.
+fn build_packet(src: SocketAddrV4, dst: SocketAddrV4, seq: u32, ack: u32, flags: u8, ip_id: u16,
+                payload: &[u8]) -> Vec<u8>
+{
+    let total_len = 40 + payload.len();
+    let mut packet = Vec::with_capacity(total_len);
+
+    // IPv4 header with the "don't fragment" flag, a TTL of 64 and the TCP protocol.
+    packet.extend_from_slice(&[0x45, 0]);
+    put_be(&mut packet, total_len as u32, 2);
+    put_be(&mut packet, u32::from(ip_id), 2);
+    packet.extend_from_slice(&[0x40, 0, 64, 6, 0, 0]);
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dst.ip().octets());
+    let ip_checksum = checksum(0, &packet[..20]);
+    packet[10] = (ip_checksum >> 8) as u8;
+    packet[11] = ip_checksum as u8;
+
+    // TCP header without options, with the maximal window.
+    put_be(&mut packet, u32::from(src.port()), 2);
+    put_be(&mut packet, u32::from(dst.port()), 2);
+    put_be(&mut packet, seq, 4);
+    put_be(&mut packet, ack, 4);
+    packet.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    packet.extend_from_slice(payload);
+
+    // The TCP checksum also covers a pseudo-header made of the addresses, the protocol and the
+    // length of the segment.
+    let mut pseudo = Vec::with_capacity(12);
+    pseudo.extend_from_slice(&src.ip().octets());
+    pseudo.extend_from_slice(&dst.ip().octets());
+    pseudo.extend_from_slice(&[0, 6]);
+    put_be(&mut pseudo, (20 + payload.len()) as u32, 2);
+    let tcp_checksum = checksum(sum_words(0, &pseudo), &packet[20..]);
+    packet[36] = (tcp_checksum >> 8) as u8;
+    packet[37] = tcp_checksum as u8;
+
+    packet
+}
+
+/// Adds the big-endian 16-bit words of `data` to `sum`. An odd last byte is padded with zero.
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    for word in data.chunks(2) {
+        let low = if word.len() == 2 { word[1] } else { 0 };
+        sum += u32::from(word[0]) << 8 | u32::from(low);
+    }
+    sum
+}
+
+/// Computes the internet checksum of `data`, starting from the partial sum `initial`.
+fn checksum(initial: u32, data: &[u8]) -> u16 {
+    let mut sum = sum_words(initial, data);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Appends the `len` lowest bytes of `value` to `out`, in little endian.
+fn put_le(out: &mut Vec<u8>, value: u32, len: usize) {
+    for n in 0..len {
+        out.push((value >> (8 * n)) as u8);
+    }
+}
+
+/// Appends the `len` lowest bytes of `value` to `out`, in big endian.
+fn put_be(out: &mut Vec<u8>, value: u32, len: usize) {
+    for n in (0..len).rev() {
+        out.push((value >> (8 * n)) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use network::SimNetwork;
+    use parking_lot::Mutex;
+    use std::io::{self, Write};
+    use std::sync::Arc;
+    use super::{checksum, PcapWriter};
+    use swarm::Transport;
+
+    /// Writer whose content can be inspected by the test.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns the type and body of the blocks of a pcapng file.
+    fn blocks(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let read_u32 = |data: &[u8]| {
+            u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2]) << 16 |
+                u32::from(data[3]) << 24
+        };
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let len = read_u32(&data[4..]) as usize;
+            assert_eq!(read_u32(&data[len - 4..]) as usize, len);
+            blocks.push((read_u32(data), data[8..len - 4].to_vec()));
+            data = &data[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn captures_connection() {
+        let network = SimNetwork::new();
+        let buffer = SharedBuffer::default();
+        network.set_pcap(Some(PcapWriter::new(buffer.clone(), network.clock().clone()).unwrap()));
+        let a = network.add_node();
+        let b = network.add_node();
+
+        let (listener, addr) = a.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).ok().unwrap();
+        let mut dialer_conn = b.dial(addr).ok().unwrap().wait().unwrap();
+        let (incoming, _listener) = listener.into_future().map_err(|(err, _)| err).wait().unwrap();
+        let listener_conn = incoming.unwrap().0.wait().unwrap();
+        dialer_conn.write_all(b"hello world").unwrap();
+        drop(dialer_conn);
+        drop(listener_conn);
+
+        let blocks = blocks(&buffer.0.lock());
+        assert_eq!(blocks[0].0, 0x0a0d_0d0a);
+        assert_eq!(&blocks[0].1[..4], &[0x4d, 0x3c, 0x2b, 0x1a]);
+        assert_eq!(blocks[1].0, 1);
+        // Handshake, data, then the FIN of each end.
+        let packets = blocks[2..].iter().map(|b| &b.1[20..]).collect::<Vec<_>>();
+        assert_eq!(packets.len(), 6);
+        let data = packets[3];
+        assert_eq!(data[33], 0x18);
+        assert_eq!(&data[40..51], b"hello world");
+        assert_eq!(checksum(0, &data[..20]), 0);
+        assert_eq!(packets[4][33] & 0x01, 0x01);
+        assert_eq!(packets[5][33] & 0x01, 0x01);
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Scripted simulations.
+//!
+//! A `Scenario` is a list of actions, each associated to a time of the virtual clock. Running a
+//! scenario executes all the futures of the simulation until there is nothing left to do, then
+//! advances the clock to the next action or to the next `SimDelay`, and so on.
+//!
+//! Since the clock only advances when the simulation is idle, a run is entirely deterministic
+//! and the real time it takes doesn't influence its outcome.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_sim;
+//! extern crate tokio_current_thread;
+//!
+//! use libp2p_sim::{Scenario, SimNetwork};
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! let network = SimNetwork::new();
+//! let mut executor = tokio_current_thread::CurrentThread::new();
+//! let mut events = Vec::new();
+//!
+//! Scenario::new()
+//!     .at(Duration::from_secs(10), |events: &mut Vec<u32>, _| events.push(1))
+//!     .at(Duration::from_secs(30), |events: &mut Vec<u32>, _| events.push(2))
+//!     .run(network.clock(), &mut executor, &mut events);
+//!
+//! assert_eq!(events, vec![1, 2]);
+//! assert_eq!(network.clock().now(), Duration::from_secs(30));
+//! # }
+//! ```
+
+use clock::SimClock;
+use std::time::Duration;
+use tokio_current_thread::CurrentThread;
+
+/// Action of a scenario. Receives the context of the scenario and the executor the simulation
+/// runs on, in order to spawn new futures.
+pub type ScenarioAction<C> = Box<FnMut(&mut C, &mut CurrentThread) + Send>;
+
+/// List of actions to execute at specific times of the virtual clock.
+pub struct Scenario<C> {
+    /// Actions, sorted by time. Actions with the same time are kept in insertion order.
+    events: Vec<(Duration, ScenarioAction<C>)>,
+    /// Time until which to run the simulation, even if there is no action left.
+    end: Option<Duration>,
+}
+
+impl<C: 'static> Scenario<C> {
+    /// Creates an empty scenario.
+    #[inline]
+    pub fn new() -> Scenario<C> {
+        Scenario {
+            events: Vec::new(),
+            end: None,
+        }
+    }
+
+    /// Adds an action to execute when the clock reaches `time`.
+    pub fn at<F>(mut self, time: Duration, action: F) -> Scenario<C>
+    where F: FnOnce(&mut C, &mut CurrentThread) + Send + 'static
+    {
+        let mut action = Some(action);
+        let action = move |context: &mut C, executor: &mut CurrentThread| {
+            if let Some(action) = action.take() {
+                action(context, executor)
+            }
+        };
+        self.insert(time, Box::new(action));
+        self
+    }
+
+    /// Adds an action to execute every `period`, starting at `start` and until `end` included.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `period` is zero.
+    pub fn every<F>(mut self, start: Duration, period: Duration, end: Duration, action: F)
+        -> Scenario<C>
+    where F: FnMut(&mut C, &mut CurrentThread) + Clone + Send + 'static
+    {
+        assert_ne!(period, Duration::new(0, 0), "the period of a scenario action can't be zero");
+        let mut time = start;
+        while time <= end {
+            self.insert(time, Box::new(action.clone()));
+            time += period;
+        }
+        self
+    }
+
+    /// Keeps running the simulation until the clock reaches `end`, even if all the actions have
+    /// been executed.
+    #[inline]
+    pub fn until(mut self, end: Duration) -> Scenario<C> {
+        self.end = Some(end);
+        self
+    }
+
+    /// Runs the scenario to completion.
+    ///
+    /// Returns once all the actions have been executed, the clock has reached the time passed
+    /// to `until`, and there is no future ready to make progress.
+    #[inline]
+    pub fn run(self, clock: &SimClock, executor: &mut CurrentThread, context: &mut C) {
+        self.run_inner(None, clock, executor, context);
+    }
+
+    /// Runs the scenario until the clock reaches `pause`, and returns the rest of the scenario.
+    ///
+    /// The actions and timers scheduled at `pause` or before are executed, then the clock is
+    /// advanced to `pause` and the futures are run until none of them can make progress. The
+    /// simulation can then be inspected, for example with a `Snapshot`, and resumed by running
+    /// the returned scenario.
+    ///
+    /// > **Note**: If the scenario finishes before `pause`, the clock isn't advanced and an empty
+    /// >           scenario is returned.
+    #[inline]
+    pub fn run_until(self, pause: Duration, clock: &SimClock, executor: &mut CurrentThread,
+                     context: &mut C) -> Scenario<C>
+    {
+        self.run_inner(Some(pause), clock, executor, context)
+    }
+
+    /// Implementation of `run` and `run_until`.
+    fn run_inner(self, pause: Option<Duration>, clock: &SimClock, executor: &mut CurrentThread,
+                 context: &mut C) -> Scenario<C>
+    {
+        let end = self.end;
+        let mut events = self.events.into_iter().peekable();
+
+        loop {
+            run_until_idle(executor);
+
+            let next = match next_step(events.peek().map(|e| e.0), end, clock) {
+                Some(next) => next,
+                None => break,
+            };
+
+            if let Some(pause) = pause {
+                if next > pause {
+                    debug!("Pausing scenario at {:?}", pause);
+                    clock.advance_to(pause);
+                    break;
+                }
+            }
+
+            clock.advance_to(next);
+            while events.peek().map(|e| e.0 <= clock.now()).unwrap_or(false) {
+                let (time, mut action) = events.next().expect("we just peeked an element");
+                debug!("Running scenario action scheduled at {:?}", time);
+                action(context, executor);
+            }
+        }
+
+        run_until_idle(executor);
+        Scenario {
+            events: events.collect(),
+            end: end,
+        }
+    }
+
+    /// Returns the time the simulation has to be advanced to in order to make progress, or `None`
+    /// if the scenario is finished. Used when the clock is driven by something else than `run`.
+    pub(crate) fn next_step(&self, clock: &SimClock) -> Option<Duration> {
+        next_step(self.events.first().map(|e| e.0), self.end, clock)
+    }
+
+    /// Runs the actions scheduled at the current time of the clock or before.
+    pub(crate) fn run_due(&mut self, clock: &SimClock, executor: &mut CurrentThread,
+                          context: &mut C)
+    {
+        let due = self.events.iter().take_while(|e| e.0 <= clock.now()).count();
+        for (time, mut action) in self.events.drain(..due) {
+            debug!("Running scenario action scheduled at {:?}", time);
+            action(context, executor);
+        }
+    }
+
+    /// Inserts an action after all the actions scheduled at the same time or before.
+    fn insert(&mut self, time: Duration, action: ScenarioAction<C>) {
+        let pos = self.events.iter().position(|e| e.0 > time).unwrap_or(self.events.len());
+        self.events.insert(pos, (time, action));
+    }
+}
+
+impl<C: 'static> Default for Scenario<C> {
+    #[inline]
+    fn default() -> Self {
+        Scenario::new()
+    }
+}
+
+/// Returns the time of the next action or timer, given the time of the next action and the end
+/// of the scenario.
+fn next_step(next_event: Option<Duration>, end: Option<Duration>, clock: &SimClock)
+    -> Option<Duration>
+{
+    // Timers are only taken into account if they are before the end of the scenario.
+    let next_timer = clock.next_deadline().and_then(|t| {
+        if end.map(|end| t <= end).unwrap_or(next_event.is_some()) {
+            Some(t)
+        } else {
+            None
+        }
+    });
+    match (next_event, next_timer, end) {
+        (Some(e), Some(t), _) => Some(e.min(t)),
+        (Some(e), None, _) => Some(e),
+        (None, Some(t), _) => Some(t),
+        (None, None, Some(end)) if clock.now() < end => Some(end),
+        (None, None, _) => None,
+    }
+}
+
+/// Polls the futures of the executor until none of them can make progress.
+pub(crate) fn run_until_idle(executor: &mut CurrentThread) {
+    loop {
+        let turn = executor.turn(Some(Duration::new(0, 0)))
+            .expect("the executor isn't used from within a future");
+        if !turn.has_polled() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::SimClock;
+    use futures::prelude::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use super::Scenario;
+    use tokio_current_thread::CurrentThread;
+
+    #[test]
+    fn actions_run_in_order() {
+        let clock = SimClock::new();
+        let mut executor = CurrentThread::new();
+        let mut log = Vec::new();
+
+        let clock2 = clock.clone();
+        Scenario::new()
+            .at(Duration::from_secs(20), |log: &mut Vec<_>, _| log.push("b"))
+            .at(Duration::from_secs(10), |log: &mut Vec<_>, _| log.push("a"))
+            .at(Duration::from_secs(20), move |log: &mut Vec<_>, _| {
+                assert_eq!(clock2.now(), Duration::from_secs(20));
+                log.push("c")
+            })
+            .run(&clock, &mut executor, &mut log);
+
+        assert_eq!(log, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn timers_fire_between_actions() {
+        let clock = SimClock::new();
+        let mut executor = CurrentThread::new();
+        let fired = Arc::new(Mutex::new(Vec::new()));
+
+        let clock2 = clock.clone();
+        let fired2 = fired.clone();
+        Scenario::new()
+            .at(Duration::from_secs(1), move |_: &mut (), executor: &mut CurrentThread| {
+                let clock3 = clock2.clone();
+                executor.spawn(clock2.delay(Duration::from_secs(5)).map(move |_| {
+                    fired2.lock().push(clock3.now());
+                }));
+            })
+            .every(Duration::from_secs(0), Duration::from_secs(2), Duration::from_secs(4), |_, _| {})
+            .until(Duration::from_secs(10))
+            .run(&clock, &mut executor, &mut ());
+
+        assert_eq!(*fired.lock(), vec![Duration::from_secs(6)]);
+        assert_eq!(clock.now(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let clock = SimClock::new();
+        let mut executor = CurrentThread::new();
+        let mut log = Vec::new();
+
+        let rest = Scenario::new()
+            .at(Duration::from_secs(10), |log: &mut Vec<_>, _| log.push("a"))
+            .at(Duration::from_secs(30), |log: &mut Vec<_>, _| log.push("b"))
+            .run_until(Duration::from_secs(20), &clock, &mut executor, &mut log);
+
+        assert_eq!(log, vec!["a"]);
+        assert_eq!(clock.now(), Duration::from_secs(20));
+        rest.run(&clock, &mut executor, &mut log);
+        assert_eq!(log, vec!["a", "b"]);
+    }
+}
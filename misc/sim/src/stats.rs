@@ -0,0 +1,373 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Collection of the results of an experiment.
+//!
+//! The `Stats` of a `SimNetwork` record named counters and time series for each node. Protocols
+//! and test harnesses record values while the simulation runs, and the results are aggregated
+//! and exported to CSV or JSON once the scenario has finished, instead of each experiment
+//! writing and parsing its own logs.
+//!
+//! The samples of a time series are stamped with the time of the virtual clock.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_sim::SimNetwork;
+//!
+//! # fn main() {
+//! let network = SimNetwork::new();
+//! let stats = network.stats();
+//! stats.increment(0, "messages_sent", 1);
+//! stats.record(0, "peers", 4.0);
+//! stats.record(1, "peers", 6.0);
+//!
+//! assert_eq!(stats.counter(0, "messages_sent"), 1);
+//! assert_eq!(stats.summary("peers").unwrap().mean, 5.0);
+//! let _csv = stats.to_csv();
+//! # }
+//! ```
+//!
+//! # Formats
+//!
+//! The CSV output has a header line followed by one line per counter and one line per sample,
+//! with the columns `kind,node,name,time,value`. The `kind` is either `counter` or `sample`, and
+//! the time is in seconds. Counters have an empty time.
+//!
+//! The JSON output is an object with a `counters` list, a `series` list and a `summaries` object
+//! that gives the aggregate of each time series over all the nodes.
+
+use clock::SimClock;
+use kad::KadQueryStats;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Named counters and time series recorded for each node of a simulation.
+///
+/// Cloning a `Stats` produces a handle to the same values.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    clock: SimClock,
+    inner: Arc<Mutex<StatsInner>>,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    /// Counters, indexed by node and name.
+    counters: BTreeMap<(usize, String), u64>,
+    /// Samples of the time series, indexed by node and name.
+    series: BTreeMap<(usize, String), Vec<(Duration, f64)>>,
+}
+
+/// Aggregate of the samples of a time series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    /// Number of samples.
+    pub count: usize,
+    /// Smallest sample.
+    pub min: f64,
+    /// Largest sample.
+    pub max: f64,
+    /// Average of the samples.
+    pub mean: f64,
+}
+
+impl Summary {
+    /// Builds the summary of a list of samples. Returns `None` if the list is empty.
+    fn from_samples<I>(samples: I) -> Option<Summary>
+    where I: IntoIterator<Item = f64>
+    {
+        let mut summary: Option<Summary> = None;
+        let mut sum = 0.0;
+        for value in samples {
+            sum += value;
+            summary = Some(match summary {
+                None => Summary { count: 1, min: value, max: value, mean: 0.0 },
+                Some(s) => Summary {
+                    count: s.count + 1,
+                    min: s.min.min(value),
+                    max: s.max.max(value),
+                    mean: 0.0,
+                },
+            });
+        }
+        summary.map(|s| Summary { mean: sum / s.count as f64, ..s })
+    }
+}
+
+impl Stats {
+    /// Creates an empty collection whose samples are stamped with the time of `clock`.
+    pub fn new(clock: SimClock) -> Stats {
+        Stats {
+            clock: clock,
+            inner: Arc::new(Mutex::new(StatsInner::default())),
+        }
+    }
+
+    /// Adds `value` to the counter `name` of `node`.
+    pub fn increment(&self, node: usize, name: &str, value: u64) {
+        *self.inner.lock().counters.entry((node, name.to_owned())).or_insert(0) += value;
+    }
+
+    /// Returns the value of the counter `name` of `node`. Counters that have never been
+    /// incremented are zero.
+    pub fn counter(&self, node: usize, name: &str) -> u64 {
+        self.inner.lock().counters.get(&(node, name.to_owned())).cloned().unwrap_or(0)
+    }
+
+    /// Returns the sum of the counter `name` over all the nodes.
+    pub fn total(&self, name: &str) -> u64 {
+        self.inner.lock().counters.iter()
+            .filter(|&(&(_, ref n), _)| n == name)
+            .map(|(_, v)| *v)
+            .sum()
+    }
+
+    /// Adds a sample to the time series `name` of `node`, at the current time of the clock.
+    pub fn record(&self, node: usize, name: &str, value: f64) {
+        let now = self.clock.now();
+        self.inner.lock().series.entry((node, name.to_owned())).or_insert_with(Vec::new)
+            .push((now, value));
+    }
+
+    /// Records the occupancy of the routing table of `node`, as returned by
+    /// `KadSystem::bucket_occupancy`.
+    ///
+    /// The total number of peers goes to the `kad_routing_table_size` series, and the number of
+    /// peers of each non-empty bucket `i` to the `kad_bucket_<i>` series.
+    pub fn record_kad_buckets(&self, node: usize, occupancy: &[usize]) {
+        self.record(node, "kad_routing_table_size", occupancy.iter().sum::<usize>() as f64);
+        for (index, &entries) in occupancy.iter().enumerate().filter(|&(_, &e)| e != 0) {
+            self.record(node, &format!("kad_bucket_{}", index), entries as f64);
+        }
+    }
+
+    /// Records the statistics of a Kademlia query performed by `node`.
+    ///
+    /// Increments the `kad_queries` and `kad_query_failures` counters, and adds a sample to the
+    /// `kad_query_hops` and `kad_query_peers_contacted` series.
+    pub fn record_kad_query(&self, node: usize, query: &KadQueryStats) {
+        self.increment(node, "kad_queries", 1);
+        self.increment(node, "kad_query_failures", query.failures as u64);
+        self.record(node, "kad_query_hops", query.max_hops as f64);
+        self.record(node, "kad_query_peers_contacted", query.peers_contacted as f64);
+    }
+
+    /// Returns the samples of the time series `name` of `node`, in the order they were recorded.
+    pub fn series(&self, node: usize, name: &str) -> Vec<(Duration, f64)> {
+        self.inner.lock().series.get(&(node, name.to_owned())).cloned().unwrap_or_default()
+    }
+
+    /// Returns the aggregate of the samples of the time series `name` over all the nodes, or
+    /// `None` if no sample has been recorded.
+    pub fn summary(&self, name: &str) -> Option<Summary> {
+        let inner = self.inner.lock();
+        let samples = inner.series.iter()
+            .filter(|&(&(_, ref n), _)| n == name)
+            .flat_map(|(_, samples)| samples.iter().map(|&(_, v)| v));
+        Summary::from_samples(samples)
+    }
+
+    /// Removes all the recorded values.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.counters.clear();
+        inner.series.clear();
+    }
+
+    /// Exports the recorded values in CSV. See the module-level documentation for the format.
+    pub fn to_csv(&self) -> String {
+        let inner = self.inner.lock();
+        let mut out = String::from("kind,node,name,time,value\n");
+        for (&(node, ref name), value) in &inner.counters {
+            let _ = writeln!(out, "counter,{},{},,{}", node, csv_escape(name), value);
+        }
+        for (&(node, ref name), samples) in &inner.series {
+            for &(time, value) in samples {
+                let _ = writeln!(out, "sample,{},{},{},{}", node, csv_escape(name),
+                                 secs(time), value);
+            }
+        }
+        out
+    }
+
+    /// Exports the recorded values in JSON. See the module-level documentation for the format.
+    pub fn to_json(&self) -> String {
+        let inner = self.inner.lock();
+        let mut out = String::from("{\"counters\":[");
+        for (n, (&(node, ref name), value)) in inner.counters.iter().enumerate() {
+            if n != 0 { out.push(','); }
+            let _ = write!(out, "{{\"node\":{},\"name\":{},\"value\":{}}}", node,
+                           json_string(name), value);
+        }
+
+        out.push_str("],\"series\":[");
+        for (n, (&(node, ref name), samples)) in inner.series.iter().enumerate() {
+            if n != 0 { out.push(','); }
+            let _ = write!(out, "{{\"node\":{},\"name\":{},\"samples\":[", node,
+                           json_string(name));
+            for (m, &(time, value)) in samples.iter().enumerate() {
+                if m != 0 { out.push(','); }
+                let _ = write!(out, "[{},{}]", secs(time), json_number(value));
+            }
+            out.push_str("]}");
+        }
+
+        out.push_str("],\"summaries\":{");
+        let mut names = inner.series.keys().map(|&(_, ref name)| name).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        for (n, name) in names.into_iter().enumerate() {
+            let samples = inner.series.iter()
+                .filter(|&(&(_, ref other), _)| other == name)
+                .flat_map(|(_, samples)| samples.iter().map(|&(_, v)| v));
+            let summary = Summary::from_samples(samples)
+                .expect("time series are only created when a sample is recorded");
+            if n != 0 { out.push(','); }
+            let _ = write!(out, "{}:{{\"count\":{},\"min\":{},\"max\":{},\"mean\":{}}}",
+                           json_string(name), summary.count, json_number(summary.min),
+                           json_number(summary.max), json_number(summary.mean));
+        }
+        out.push_str("}}");
+        out
+    }
+
+    /// Writes the CSV export of the recorded values.
+    #[inline]
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_all(self.to_csv().as_bytes())
+    }
+
+    /// Writes the JSON export of the recorded values.
+    #[inline]
+    pub fn write_json<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+}
+
+/// Converts a duration into seconds.
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Quotes a CSV field if necessary.
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Encodes a JSON string.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Encodes a JSON number. JSON doesn't support infinities and NaN, which are encoded as `null`.
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{}", value)
+    } else {
+        "null".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::SimClock;
+    use kad::KadQueryStats;
+    use std::time::Duration;
+    use super::Stats;
+
+    #[test]
+    fn counters_and_series() {
+        let clock = SimClock::new();
+        let stats = Stats::new(clock.clone());
+        stats.increment(0, "dials", 2);
+        stats.increment(1, "dials", 3);
+        stats.record(0, "latency", 1.0);
+        clock.advance(Duration::from_millis(500));
+        stats.record(0, "latency", 3.0);
+
+        assert_eq!(stats.counter(0, "dials"), 2);
+        assert_eq!(stats.counter(2, "dials"), 0);
+        assert_eq!(stats.total("dials"), 5);
+        assert_eq!(stats.series(0, "latency"),
+                   vec![(Duration::new(0, 0), 1.0), (Duration::from_millis(500), 3.0)]);
+        let summary = stats.summary("latency").unwrap();
+        assert_eq!((summary.count, summary.min, summary.max, summary.mean), (2, 1.0, 3.0, 2.0));
+        assert!(stats.summary("unknown").is_none());
+    }
+
+    #[test]
+    fn kad_metrics() {
+        let stats = Stats::new(SimClock::new());
+        stats.record_kad_buckets(0, &[0, 2, 0, 5]);
+        stats.record_kad_query(0, &KadQueryStats {
+            peers_contacted: 6,
+            failures: 1,
+            max_hops: 3,
+            ..KadQueryStats::default()
+        });
+
+        assert_eq!(stats.series(0, "kad_routing_table_size")[0].1, 7.0);
+        assert_eq!(stats.series(0, "kad_bucket_3")[0].1, 5.0);
+        assert!(stats.series(0, "kad_bucket_0").is_empty());
+        assert_eq!(stats.counter(0, "kad_queries"), 1);
+        assert_eq!(stats.counter(0, "kad_query_failures"), 1);
+        assert_eq!(stats.series(0, "kad_query_hops")[0].1, 3.0);
+        assert_eq!(stats.series(0, "kad_query_peers_contacted")[0].1, 6.0);
+    }
+
+    #[test]
+    fn export() {
+        let clock = SimClock::new();
+        let stats = Stats::new(clock.clone());
+        stats.increment(1, "dials", 1);
+        clock.advance(Duration::from_millis(1500));
+        stats.record(0, "peers, total", 4.0);
+
+        assert_eq!(stats.to_csv(), "kind,node,name,time,value\n\
+                                    counter,1,dials,,1\n\
+                                    sample,0,\"peers, total\",1.5,4\n");
+        assert_eq!(stats.to_json(),
+                   "{\"counters\":[{\"node\":1,\"name\":\"dials\",\"value\":1}],\
+                    \"series\":[{\"node\":0,\"name\":\"peers, total\",\"samples\":[[1.5,4]]}],\
+                    \"summaries\":{\"peers, total\":{\"count\":1,\"min\":4,\"max\":4,\"mean\":4}}}");
+    }
+}
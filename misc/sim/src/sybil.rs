@@ -0,0 +1,264 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Sybil identities and eclipse attacks on the DHT.
+//!
+//! An eclipse attack surrounds a victim with identities controlled by the attacker, so that the
+//! victim's view of a region of the Kademlia keyspace only contains these identities. The
+//! `SybilGenerator` produces identities whose distance to a target shares a given number of
+//! leading zero bits, and an `EclipseAttack` inserts them into the routing tables of the victims
+//! at a controlled rate during a `Scenario`.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate libp2p_core;
+//! extern crate libp2p_sim;
+//!
+//! use libp2p_core::{PeerId, PublicKey, runtime::RandomSource};
+//! use libp2p_sim::sybil::{EclipseAttack, SybilGenerator};
+//! use libp2p_sim::Scenario;
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! let victim = PeerId::from_public_key(PublicKey::Ed25519(vec![1; 32]));
+//! let sybils = SybilGenerator::new(RandomSource::seeded(0)).generate(&victim, 4, 8);
+//!
+//! let scenario = EclipseAttack::new(sybils.iter().map(|s| s.peer_id.clone()).collect())
+//!     .with_rate(2, Duration::from_secs(10))
+//!     .schedule(Scenario::new(), Duration::from_secs(60), |inserted: &mut Vec<PeerId>, sybil| {
+//!         // Typically `kad_system.update_kbuckets(sybil.clone())`.
+//!         inserted.push(sybil.clone());
+//!     });
+//! # let _ = scenario;
+//! # }
+//! ```
+
+use kad::{KadSystem, KBucketsPeerId};
+use parking_lot::Mutex;
+use scenario::Scenario;
+use std::sync::Arc;
+use std::time::Duration;
+use swarm::{PeerId, PublicKey};
+use swarm::runtime::RandomSource;
+
+/// Number of closest peers considered by `eclipsed_fraction`. Matches the size of a k-bucket.
+const CLOSEST_PEERS: usize = 20;
+
+/// Identity generated by a `SybilGenerator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sybil {
+    /// Public key of the identity. Can be passed to `SimNodeConfig::with_public_key` in order to
+    /// spawn a node with this identity.
+    pub public_key: PublicKey,
+    /// Peer ID derived from the public key.
+    pub peer_id: PeerId,
+}
+
+/// Generates identities in targeted regions of the Kademlia keyspace.
+///
+/// > **Note**: The identities are found by trial and error. Generating an identity whose
+/// >           distance to the target has `n` leading zero bits takes `2^n` attempts on average.
+/// >           The public keys are random bytes and have no corresponding private key, which is
+/// >           enough as long as the simulation doesn't use an encryption upgrade.
+#[derive(Clone)]
+pub struct SybilGenerator {
+    random: RandomSource,
+    max_attempts: u64,
+}
+
+impl SybilGenerator {
+    /// Creates a generator that draws the keys from `random`. Gives up after a million
+    /// attempts.
+    #[inline]
+    pub fn new(random: RandomSource) -> SybilGenerator {
+        SybilGenerator {
+            random,
+            max_attempts: 1_000_000,
+        }
+    }
+
+    /// Sets the maximum number of keys to try in a call to `generate`.
+    #[inline]
+    pub fn with_max_attempts(mut self, max_attempts: u64) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Generates a random identity, anywhere in the keyspace.
+    pub fn random_identity(&self) -> Sybil {
+        let mut key = vec![0; 32];
+        self.random.fill_bytes(&mut key);
+        let public_key = PublicKey::Ed25519(key);
+        Sybil {
+            peer_id: PeerId::from_public_key(public_key.clone()),
+            public_key,
+        }
+    }
+
+    /// Generates `count` identities whose XOR distance to `target` has at least `prefix_bits`
+    /// leading zero bits, in other words that share the first `prefix_bits` bits of the
+    /// Kademlia key of `target`.
+    ///
+    /// Returns fewer identities if the maximum number of attempts is reached.
+    pub fn generate(&self, target: &PeerId, prefix_bits: u32, count: usize) -> Vec<Sybil> {
+        let mut sybils = Vec::with_capacity(count);
+        let mut attempts = 0;
+        while sybils.len() < count && attempts < self.max_attempts {
+            attempts += 1;
+            let sybil = self.random_identity();
+            if common_prefix_len(target, &sybil.peer_id) >= prefix_bits {
+                sybils.push(sybil);
+            }
+        }
+
+        if sybils.len() < count {
+            debug!("Generated only {} out of {} sybils with a prefix of {} bits",
+                   sybils.len(), count, prefix_bits);
+        }
+        sybils
+    }
+}
+
+/// Returns the number of leading bits that the Kademlia keys of `a` and `b` have in common.
+#[inline]
+pub fn common_prefix_len(a: &PeerId, b: &PeerId) -> u32 {
+    <PeerId as KBucketsPeerId>::leading_zeros(a.distance_with(b))
+}
+
+/// Returns the proportion of the 20 peers closest to the victim in its own
+/// routing table that are sybils. An eclipse is complete when this reaches `1.0`.
+pub fn eclipsed_fraction(victim: &KadSystem, sybils: &[PeerId]) -> f64 {
+    let closest = victim.known_closest_peers(victim.local_peer_id())
+        .filter(|peer| peer != victim.local_peer_id())
+        .take(CLOSEST_PEERS)
+        .collect::<Vec<_>>();
+    if closest.is_empty() {
+        return 0.0;
+    }
+    let num_sybils = closest.iter().filter(|peer| sybils.contains(peer)).count();
+    num_sybils as f64 / closest.len() as f64
+}
+
+/// Inserts sybil identities into the routing tables of victims over time.
+#[derive(Debug, Clone)]
+pub struct EclipseAttack {
+    sybils: Vec<PeerId>,
+    per_period: usize,
+    period: Duration,
+}
+
+impl EclipseAttack {
+    /// Creates an attack that inserts the given identities, by default one per second.
+    #[inline]
+    pub fn new(sybils: Vec<PeerId>) -> EclipseAttack {
+        EclipseAttack {
+            sybils,
+            per_period: 1,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    /// Inserts `count` identities every `period`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `count` is zero.
+    #[inline]
+    pub fn with_rate(mut self, count: usize, period: Duration) -> Self {
+        assert_ne!(count, 0, "an eclipse attack must insert at least one identity per period");
+        self.per_period = count;
+        self.period = period;
+        self
+    }
+
+    /// Adds the attack to `scenario`, starting at `start`. `insert` is called with the context
+    /// of the scenario for each identity to insert, and is expected to add it to the routing
+    /// tables of the victims, for example with `KadSystem::update_kbuckets`.
+    pub fn schedule<C, F>(self, mut scenario: Scenario<C>, start: Duration, insert: F) -> Scenario<C>
+    where
+        C: 'static,
+        F: FnMut(&mut C, &PeerId) + Send + 'static,
+    {
+        let insert = Arc::new(Mutex::new(insert));
+        let mut time = start;
+        for batch in self.sybils.chunks(self.per_period) {
+            let batch = batch.to_vec();
+            let insert = insert.clone();
+            scenario = scenario.at(time, move |context: &mut C, _| {
+                let mut insert = insert.lock();
+                for sybil in &batch {
+                    (&mut *insert)(context, sybil);
+                }
+            });
+            time += self.period;
+        }
+        scenario
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::SimClock;
+    use parking_lot::Mutex;
+    use scenario::Scenario;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use swarm::runtime::RandomSource;
+    use swarm::{PeerId, PublicKey};
+    use super::{common_prefix_len, EclipseAttack, SybilGenerator};
+    use tokio_current_thread::CurrentThread;
+
+    #[test]
+    fn sybils_share_prefix() {
+        let target = PeerId::from_public_key(PublicKey::Ed25519(vec![5; 32]));
+        let sybils = SybilGenerator::new(RandomSource::seeded(1)).generate(&target, 6, 4);
+        assert_eq!(sybils.len(), 4);
+        assert!(sybils.iter().all(|s| common_prefix_len(&target, &s.peer_id) >= 6));
+
+        let none = SybilGenerator::new(RandomSource::seeded(1))
+            .with_max_attempts(10)
+            .generate(&target, 200, 1);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn attack_follows_rate() {
+        let sybils = SybilGenerator::new(RandomSource::seeded(2)).generate(
+            &PeerId::from_public_key(PublicKey::Ed25519(vec![0; 32])), 0, 5);
+        let ids = sybils.into_iter().map(|s| s.peer_id).collect::<Vec<_>>();
+
+        let clock = SimClock::new();
+        let inserted = Arc::new(Mutex::new(Vec::new()));
+        let inserted2 = inserted.clone();
+        let clock2 = clock.clone();
+        EclipseAttack::new(ids.clone())
+            .with_rate(2, Duration::from_secs(10))
+            .schedule(Scenario::new(), Duration::from_secs(5), move |_: &mut (), sybil| {
+                inserted2.lock().push((clock2.now(), sybil.clone()));
+            })
+            .run(&clock, &mut CurrentThread::new(), &mut ());
+
+        let inserted = inserted.lock();
+        let times = inserted.iter().map(|&(t, _)| t.as_secs()).collect::<Vec<_>>();
+        assert_eq!(times, vec![5, 5, 15, 15, 25]);
+        assert_eq!(inserted.iter().map(|&(_, ref p)| p.clone()).collect::<Vec<_>>(), ids);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Generation of network topologies.
+//!
+//! A `Topology` describes how the nodes of a simulation are connected to each other. Calling
+//! `build` on a `Topology` adds the nodes to a `SimNetwork`, makes each of them listen on a
+//! fresh address, and returns the list of addresses each node must dial in order to obtain the
+//! requested graph.
+//!
+//! For each edge of the graph, the node with the higher index dials the node with the lower
+//! index.
+
+use fnv::FnvHashSet;
+use multiaddr::Multiaddr;
+use network::SimNetwork;
+use rand::Rng;
+use swarm::Transport;
+use transport::{SimListener, SimTransport};
+
+/// Shape of the graph of connections between the nodes of a simulation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Topology {
+    /// Every node is connected to every other node.
+    Clique,
+    /// Each node is connected to the node before and the node after it.
+    Ring,
+    /// Erdős–Rényi random graph. Each possible edge exists with probability `p`.
+    Random {
+        /// Probability for each edge to exist. Must be between 0.0 and 1.0.
+        p: f64,
+    },
+    /// Watts–Strogatz small-world graph. Starts from a ring where each node is connected to its
+    /// `k` nearest neighbours on each side, then rewires each edge with probability `beta`.
+    SmallWorld {
+        /// Number of neighbours on each side of a node.
+        k: usize,
+        /// Probability for each edge to be rewired. Must be between 0.0 and 1.0.
+        beta: f64,
+    },
+    /// Barabási–Albert scale-free graph. Each node connects to `m` existing nodes, with a
+    /// probability proportional to their number of connections.
+    ScaleFree {
+        /// Number of edges added with each new node.
+        m: usize,
+    },
+}
+
+impl Topology {
+    /// Generates the edges of the graph for `num_nodes` nodes.
+    ///
+    /// Each edge is a pair `(a, b)` of node indices with `a < b`. The list is sorted and
+    /// doesn't contain any duplicate.
+    pub fn edges<R: Rng>(&self, num_nodes: usize, rng: &mut R) -> Vec<(usize, usize)> {
+        let mut edges = FnvHashSet::default();
+
+        match *self {
+            Topology::Clique => {
+                for a in 0..num_nodes {
+                    for b in (a + 1)..num_nodes {
+                        edges.insert((a, b));
+                    }
+                }
+            },
+            Topology::Ring => {
+                if num_nodes >= 2 {
+                    for a in 0..num_nodes {
+                        insert_edge(&mut edges, a, (a + 1) % num_nodes);
+                    }
+                }
+            },
+            Topology::Random { p } => {
+                for a in 0..num_nodes {
+                    for b in (a + 1)..num_nodes {
+                        if rng.gen::<f64>() < p {
+                            edges.insert((a, b));
+                        }
+                    }
+                }
+            },
+            Topology::SmallWorld { k, beta } => {
+                let k = k.min(num_nodes.saturating_sub(1) / 2);
+                for a in 0..num_nodes {
+                    for offset in 1..(k + 1) {
+                        insert_edge(&mut edges, a, (a + offset) % num_nodes);
+                    }
+                }
+
+                for a in 0..num_nodes {
+                    for offset in 1..(k + 1) {
+                        let b = (a + offset) % num_nodes;
+                        if rng.gen::<f64>() >= beta {
+                            continue;
+                        }
+                        // Don't rewire if `a` is already connected to every other node.
+                        if edges.iter().filter(|&&(x, y)| x == a || y == a).count() >= num_nodes - 1 {
+                            continue;
+                        }
+                        let new_b = loop {
+                            let candidate = rng.gen_range(0, num_nodes);
+                            if candidate != a && !has_edge(&edges, a, candidate) {
+                                break candidate;
+                            }
+                        };
+                        remove_edge(&mut edges, a, b);
+                        insert_edge(&mut edges, a, new_b);
+                    }
+                }
+            },
+            Topology::ScaleFree { m } => {
+                let m = m.max(1);
+                let initial = (m + 1).min(num_nodes);
+                // Each node appears in this list once per edge it has, so that picking a random
+                // element is the same as picking a node proportionally to its degree.
+                let mut targets = Vec::new();
+                for a in 0..initial {
+                    for b in (a + 1)..initial {
+                        edges.insert((a, b));
+                        targets.push(a);
+                        targets.push(b);
+                    }
+                }
+
+                for new_node in initial..num_nodes {
+                    let mut chosen = FnvHashSet::default();
+                    while chosen.len() < m {
+                        let target = if targets.is_empty() {
+                            rng.gen_range(0, new_node)
+                        } else {
+                            targets[rng.gen_range(0, targets.len())]
+                        };
+                        chosen.insert(target);
+                    }
+                    for target in chosen {
+                        edges.insert((target, new_node));
+                        targets.push(target);
+                        targets.push(new_node);
+                    }
+                }
+            },
+        }
+
+        let mut edges = edges.into_iter().collect::<Vec<_>>();
+        edges.sort();
+        edges
+    }
+
+    /// Adds `num_nodes` nodes to the network, makes them listen, and returns the topology.
+    #[inline]
+    pub fn build<R: Rng>(&self, network: &SimNetwork, num_nodes: usize, rng: &mut R)
+        -> SimTopology
+    {
+        self.build_with(network, num_nodes, rng, |network, _| network.add_node())
+    }
+
+    /// Same as `build`, but calls `add_node` to add each node to the network. This can be used
+    /// to place some of the nodes behind a NAT.
+    pub fn build_with<R, F>(&self, network: &SimNetwork, num_nodes: usize, rng: &mut R,
+                            mut add_node: F) -> SimTopology
+    where R: Rng,
+          F: FnMut(&SimNetwork, usize) -> SimTransport,
+    {
+        let nodes = (0..num_nodes)
+            .map(|index| {
+                let transport = add_node(network, index);
+                let (listener, listen_addr) = transport.clone()
+                    .listen_on("/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr"))
+                    .unwrap_or_else(|_| panic!("a simulated node can always listen on port 0"));
+                TopologyNode {
+                    transport: transport,
+                    listener: listener,
+                    listen_addr: listen_addr,
+                }
+            })
+            .collect();
+
+        SimTopology {
+            nodes: nodes,
+            edges: self.edges(num_nodes, rng),
+        }
+    }
+}
+
+/// Inserts an edge between `a` and `b`, unless `a == b`.
+fn insert_edge(edges: &mut FnvHashSet<(usize, usize)>, a: usize, b: usize) {
+    if a < b {
+        edges.insert((a, b));
+    } else if b < a {
+        edges.insert((b, a));
+    }
+}
+
+/// Removes the edge between `a` and `b`.
+fn remove_edge(edges: &mut FnvHashSet<(usize, usize)>, a: usize, b: usize) {
+    edges.remove(&(a.min(b), a.max(b)));
+}
+
+/// Returns true if there is an edge between `a` and `b`.
+fn has_edge(edges: &FnvHashSet<(usize, usize)>, a: usize, b: usize) -> bool {
+    edges.contains(&(a.min(b), a.max(b)))
+}
+
+/// Nodes of a simulation connected according to a `Topology`.
+pub struct SimTopology {
+    nodes: Vec<TopologyNode>,
+    edges: Vec<(usize, usize)>,
+}
+
+/// A node of a `SimTopology`.
+pub struct TopologyNode {
+    /// Transport of the node.
+    pub transport: SimTransport,
+    /// Listener of the node. Incoming connections must be processed from it.
+    pub listener: SimListener,
+    /// Address the node is listening on.
+    pub listen_addr: Multiaddr,
+}
+
+impl SimTopology {
+    /// Returns the nodes of the topology.
+    #[inline]
+    pub fn nodes(&self) -> &[TopologyNode] {
+        &self.nodes
+    }
+
+    /// Returns the edges of the topology. See `Topology::edges`.
+    #[inline]
+    pub fn edges(&self) -> &[(usize, usize)] {
+        &self.edges
+    }
+
+    /// Returns the addresses that the node with the given index must dial.
+    pub fn dials_of(&self, index: usize) -> Vec<Multiaddr> {
+        self.edges.iter()
+            .filter(|&&(_, b)| b == index)
+            .map(|&(a, _)| self.nodes[a].listen_addr.clone())
+            .collect()
+    }
+
+    /// Returns the list of all the dials to perform, as `(dialer index, address)` pairs.
+    pub fn dials(&self) -> Vec<(usize, Multiaddr)> {
+        self.edges.iter()
+            .map(|&(a, b)| (b, self.nodes[a].listen_addr.clone()))
+            .collect()
+    }
+
+    /// Destroys the topology and returns its nodes.
+    #[inline]
+    pub fn into_nodes(self) -> Vec<TopologyNode> {
+        self.nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use network::SimNetwork;
+    use rand::{SeedableRng, prng::XorShiftRng};
+    use super::Topology;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([7; 16])
+    }
+
+    fn assert_well_formed(edges: &[(usize, usize)], num_nodes: usize) {
+        for window in edges.windows(2) {
+            assert!(window[0] < window[1]);
+        }
+        for &(a, b) in edges {
+            assert!(a < b);
+            assert!(b < num_nodes);
+        }
+    }
+
+    #[test]
+    fn clique_and_ring() {
+        let edges = Topology::Clique.edges(10, &mut rng());
+        assert_eq!(edges.len(), 45);
+        assert_well_formed(&edges, 10);
+
+        let edges = Topology::Ring.edges(10, &mut rng());
+        assert_eq!(edges.len(), 10);
+        assert_well_formed(&edges, 10);
+    }
+
+    #[test]
+    fn random_extremes() {
+        assert!(Topology::Random { p: 0.0 }.edges(20, &mut rng()).is_empty());
+        assert_eq!(Topology::Random { p: 1.0 }.edges(20, &mut rng()).len(), 190);
+    }
+
+    #[test]
+    fn small_world_keeps_edge_count() {
+        let lattice = Topology::SmallWorld { k: 2, beta: 0.0 }.edges(50, &mut rng());
+        assert_eq!(lattice.len(), 100);
+        let rewired = Topology::SmallWorld { k: 2, beta: 0.3 }.edges(50, &mut rng());
+        assert_eq!(rewired.len(), 100);
+        assert_ne!(lattice, rewired);
+        assert_well_formed(&rewired, 50);
+    }
+
+    #[test]
+    fn scale_free_edge_count() {
+        let edges = Topology::ScaleFree { m: 3 }.edges(100, &mut rng());
+        assert_eq!(edges.len(), 6 + 96 * 3);
+        assert_well_formed(&edges, 100);
+    }
+
+    #[test]
+    fn build_wires_dials() {
+        let network = SimNetwork::new();
+        let topology = Topology::Ring.build(&network, 5, &mut rng());
+        assert_eq!(network.num_nodes(), 5);
+        assert_eq!(topology.dials().len(), 5);
+        assert_eq!(topology.dials_of(1), vec![topology.nodes()[0].listen_addr.clone()]);
+    }
+}
@@ -0,0 +1,414 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Recording and replay of the events of a simulation.
+//!
+//! A `Tracer` records events along with the time of the virtual clock at which they happen.
+//! Passing a `Tracer` to `SimNetwork::set_tracer` records all the events of the network, such
+//! as connections being opened and severed. Protocols can record their own events, such as
+//! handshakes, substreams being opened or messages being received, with `record_custom`.
+//!
+//! The recorded `Trace` can be serialized into a compact binary format, and turned back into a
+//! `Scenario` in order to re-drive a run.
+//!
+//! # Format
+//!
+//! The binary format starts with the magic bytes `SIMTRACE` followed by a version byte. Then
+//! comes each event, made of the number of nanoseconds elapsed since the previous event encoded
+//! as a varint, a byte indicating the type of event, and the fields of the event. Integers are
+//! encoded as varints, and byte strings are prefixed with their length.
+
+use clock::SimClock;
+use network::{NetworkEvent, SimNetwork, StopMode};
+use parking_lot::Mutex;
+use scenario::Scenario;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_current_thread::CurrentThread;
+use unsigned_varint::{decode, encode};
+
+/// Magic bytes at the start of a serialized trace.
+const MAGIC: &[u8] = b"SIMTRACE";
+/// Version of the binary format.
+const VERSION: u8 = 1;
+
+/// Event recorded by a `Tracer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// Event generated by the network.
+    Network(NetworkEvent),
+    /// Event generated by a protocol running on a node.
+    Custom {
+        /// Index of the node.
+        node: usize,
+        /// Type of the event, for example `"kad/find_node"`.
+        kind: String,
+        /// Opaque data attached to the event.
+        payload: Vec<u8>,
+    },
+}
+
+/// Records events with the time at which they happen.
+///
+/// Cloning a `Tracer` produces a handle to the same record.
+#[derive(Clone)]
+pub struct Tracer {
+    clock: SimClock,
+    events: Arc<Mutex<Vec<(Duration, TraceEvent)>>>,
+}
+
+impl Tracer {
+    /// Creates a new tracer that uses the given clock for the timestamps.
+    pub fn new(clock: SimClock) -> Tracer {
+        Tracer {
+            clock: clock,
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Records an event at the current time.
+    pub fn record(&self, event: TraceEvent) {
+        let now = self.clock.now();
+        self.events.lock().push((now, event));
+    }
+
+    /// Records an event generated by a protocol.
+    #[inline]
+    pub fn record_custom<K, P>(&self, node: usize, kind: K, payload: P)
+    where K: Into<String>,
+          P: Into<Vec<u8>>,
+    {
+        self.record(TraceEvent::Custom {
+            node: node,
+            kind: kind.into(),
+            payload: payload.into(),
+        })
+    }
+
+    /// Returns a copy of the events recorded so far.
+    pub fn trace(&self) -> Trace {
+        Trace {
+            events: self.events.lock().clone(),
+        }
+    }
+}
+
+/// List of events with their timestamps, sorted by time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Trace {
+    events: Vec<(Duration, TraceEvent)>,
+}
+
+impl Trace {
+    /// Returns the events of the trace.
+    #[inline]
+    pub fn events(&self) -> &[(Duration, TraceEvent)] {
+        &self.events
+    }
+
+    /// Serializes the trace.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MAGIC.len() + 1 + self.events.len() * 8);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        let mut previous = Duration::new(0, 0);
+        for &(time, ref event) in &self.events {
+            write_u64(&mut out, duration_nanos(time - previous));
+            previous = time;
+
+            match *event {
+                TraceEvent::Network(NetworkEvent::Partitioned { ref group_a, ref group_b }) => {
+                    out.push(0);
+                    write_list(&mut out, group_a);
+                    write_list(&mut out, group_b);
+                },
+                TraceEvent::Network(NetworkEvent::Healed) => out.push(1),
+                TraceEvent::Network(NetworkEvent::NodeStopped { node, mode }) => {
+                    out.push(2);
+                    write_u64(&mut out, node as u64);
+                    out.push(match mode { StopMode::Graceful => 0, StopMode::Crash => 1 });
+                },
+                TraceEvent::Network(NetworkEvent::NodeRestarted { node }) => {
+                    out.push(3);
+                    write_u64(&mut out, node as u64);
+                },
+                TraceEvent::Network(NetworkEvent::ConnectionOpened { dialer, listener }) => {
+                    out.push(4);
+                    write_u64(&mut out, dialer as u64);
+                    write_u64(&mut out, listener as u64);
+                },
+                TraceEvent::Network(NetworkEvent::ConnectionSevered { dialer, listener }) => {
+                    out.push(5);
+                    write_u64(&mut out, dialer as u64);
+                    write_u64(&mut out, listener as u64);
+                },
+                TraceEvent::Custom { node, ref kind, ref payload } => {
+                    out.push(6);
+                    write_u64(&mut out, node as u64);
+                    write_bytes(&mut out, kind.as_bytes());
+                    write_bytes(&mut out, payload);
+                },
+            }
+        }
+
+        out
+    }
+
+    /// Deserializes a trace produced by `to_bytes`.
+    pub fn from_bytes(mut data: &[u8]) -> Result<Trace, io::Error> {
+        if !data.starts_with(MAGIC) {
+            return Err(invalid_data("missing magic bytes"));
+        }
+        data = &data[MAGIC.len()..];
+        if data.first() != Some(&VERSION) {
+            return Err(invalid_data("unsupported version"));
+        }
+        data = &data[1..];
+
+        let mut events = Vec::new();
+        let mut time = Duration::new(0, 0);
+        while !data.is_empty() {
+            let (delta, rest) = read_u64(data)?;
+            time += Duration::from_nanos(delta);
+            let (&tag, rest) = rest.split_first().ok_or_else(|| invalid_data("truncated event"))?;
+            data = rest;
+
+            let event = match tag {
+                0 => {
+                    let (group_a, rest) = read_list(data)?;
+                    let (group_b, rest) = read_list(rest)?;
+                    data = rest;
+                    TraceEvent::Network(NetworkEvent::Partitioned {
+                        group_a: group_a,
+                        group_b: group_b,
+                    })
+                },
+                1 => TraceEvent::Network(NetworkEvent::Healed),
+                2 => {
+                    let (node, rest) = read_u64(data)?;
+                    let (&mode, rest) = rest.split_first()
+                        .ok_or_else(|| invalid_data("truncated event"))?;
+                    data = rest;
+                    let mode = match mode {
+                        0 => StopMode::Graceful,
+                        1 => StopMode::Crash,
+                        _ => return Err(invalid_data("invalid stop mode")),
+                    };
+                    let node = node as usize;
+                    TraceEvent::Network(NetworkEvent::NodeStopped { node: node, mode: mode })
+                },
+                3 => {
+                    let (node, rest) = read_u64(data)?;
+                    data = rest;
+                    TraceEvent::Network(NetworkEvent::NodeRestarted { node: node as usize })
+                },
+                4 | 5 => {
+                    let (dialer, rest) = read_u64(data)?;
+                    let (listener, rest) = read_u64(rest)?;
+                    data = rest;
+                    let (dialer, listener) = (dialer as usize, listener as usize);
+                    TraceEvent::Network(if tag == 4 {
+                        NetworkEvent::ConnectionOpened { dialer: dialer, listener: listener }
+                    } else {
+                        NetworkEvent::ConnectionSevered { dialer: dialer, listener: listener }
+                    })
+                },
+                6 => {
+                    let (node, rest) = read_u64(data)?;
+                    let (kind, rest) = read_bytes(rest)?;
+                    let (payload, rest) = read_bytes(rest)?;
+                    data = rest;
+                    let kind = String::from_utf8(kind.to_vec())
+                        .map_err(|_| invalid_data("event kind isn't valid UTF-8"))?;
+                    TraceEvent::Custom {
+                        node: node as usize,
+                        kind: kind,
+                        payload: payload.to_vec(),
+                    }
+                },
+                _ => return Err(invalid_data("unknown event type")),
+            };
+
+            events.push((time, event));
+        }
+
+        Ok(Trace { events: events })
+    }
+
+    /// Writes the serialized trace.
+    #[inline]
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Reads a serialized trace.
+    #[inline]
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Trace, io::Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Trace::from_bytes(&data)
+    }
+
+    /// Builds a scenario that calls `replay` with each event of the trace, at the time the event
+    /// was recorded.
+    pub fn into_scenario<C, F>(self, replay: F) -> Scenario<C>
+    where C: 'static,
+          F: FnMut(&TraceEvent, &mut C, &mut CurrentThread) + Clone + Send + 'static,
+    {
+        self.events.into_iter().fold(Scenario::new(), move |scenario, (time, event)| {
+            let mut replay = replay.clone();
+            scenario.at(time, move |context, executor| replay(&event, context, executor))
+        })
+    }
+
+    /// Builds a scenario that applies to `network` the partitions and the node stops and
+    /// restarts of the trace.
+    ///
+    /// The other events, such as connections being opened, are the consequence of the
+    /// behaviour of the nodes and aren't replayed.
+    pub fn into_network_scenario<C: 'static>(self, network: SimNetwork) -> Scenario<C> {
+        self.into_scenario(move |event, _, _| {
+            match *event {
+                TraceEvent::Network(NetworkEvent::Partitioned { ref group_a, ref group_b }) => {
+                    network.partition(group_a.iter().cloned(), group_b.iter().cloned())
+                },
+                TraceEvent::Network(NetworkEvent::Healed) => network.heal(),
+                TraceEvent::Network(NetworkEvent::NodeStopped { node, mode }) => {
+                    network.stop_node(node, mode)
+                },
+                TraceEvent::Network(NetworkEvent::NodeRestarted { node }) => {
+                    network.restart_node(node)
+                },
+                _ => (),
+            }
+        })
+    }
+}
+
+/// Converts a `Duration` into a number of nanoseconds.
+pub(crate) fn duration_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_u64(out: &mut Vec<u8>, value: u64) {
+    let mut buf = encode::u64_buffer();
+    out.extend_from_slice(encode::u64(value, &mut buf));
+}
+
+pub(crate) fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) fn write_list(out: &mut Vec<u8>, list: &[usize]) {
+    write_u64(out, list.len() as u64);
+    for &elem in list {
+        write_u64(out, elem as u64);
+    }
+}
+
+pub(crate) fn read_u64(data: &[u8]) -> Result<(u64, &[u8]), io::Error> {
+    decode::u64(data).map_err(|_| invalid_data("invalid varint"))
+}
+
+pub(crate) fn read_bytes(data: &[u8]) -> Result<(&[u8], &[u8]), io::Error> {
+    let (len, rest) = read_u64(data)?;
+    let len = len as usize;
+    if rest.len() < len {
+        return Err(invalid_data("truncated byte string"));
+    }
+    Ok((&rest[..len], &rest[len..]))
+}
+
+pub(crate) fn read_list(data: &[u8]) -> Result<(Vec<usize>, &[u8]), io::Error> {
+    let (len, mut rest) = read_u64(data)?;
+    let mut list = Vec::new();
+    for _ in 0..len {
+        let (elem, r) = read_u64(rest)?;
+        list.push(elem as usize);
+        rest = r;
+    }
+    Ok((list, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use network::{NetworkEvent, SimNetwork, StopMode};
+    use std::time::Duration;
+    use super::{Trace, Tracer};
+    use tokio_current_thread::CurrentThread;
+
+    #[test]
+    fn encode_decode() {
+        let network = SimNetwork::new();
+        let tracer = Tracer::new(network.clock().clone());
+        network.set_tracer(Some(tracer.clone()));
+        for _ in 0..4 {
+            network.add_node();
+        }
+
+        network.clock().advance(Duration::from_millis(1500));
+        network.partition(vec![0, 1], vec![2, 3]);
+        network.clock().advance(Duration::from_secs(3));
+        network.heal();
+        network.stop_node(2, StopMode::Crash);
+        tracer.record_custom(1, "pubsub/message", &b"hello"[..]);
+
+        let trace = tracer.trace();
+        assert_eq!(trace.events().len(), 4);
+        let decoded = Trace::from_bytes(&trace.to_bytes()).unwrap();
+        assert_eq!(decoded, trace);
+        assert!(Trace::from_bytes(b"garbage").is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let original = SimNetwork::new();
+        let tracer = Tracer::new(original.clock().clone());
+        original.set_tracer(Some(tracer.clone()));
+        for _ in 0..2 {
+            original.add_node();
+        }
+        original.clock().advance(Duration::from_secs(5));
+        original.stop_node(1, StopMode::Graceful);
+
+        let replayed = SimNetwork::new();
+        for _ in 0..2 {
+            replayed.add_node();
+        }
+        let events = replayed.events();
+        let mut executor = CurrentThread::new();
+        tracer.trace()
+            .into_network_scenario(replayed.clone())
+            .run(replayed.clock(), &mut executor, &mut ());
+
+        assert_eq!(replayed.clock().now(), Duration::from_secs(5));
+        assert!(!replayed.is_online(1));
+        let event = events.wait().next().unwrap().unwrap();
+        assert_eq!(event, NetworkEvent::NodeStopped { node: 1, mode: StopMode::Graceful });
+    }
+}
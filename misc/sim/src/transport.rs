@@ -0,0 +1,323 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use connection::SimConnection;
+use futures::{future::{self, FutureResult}, prelude::*, sync::mpsc};
+use multiaddr::{Multiaddr, Protocol};
+use network::SimNetwork;
+use std::{io, iter};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use swarm::Transport;
+
+/// Transport of a node of the simulated network.
+///
+/// Supports listening on and dialing `/ip4/<ip>/tcp/<port>` addresses. Listening on port 0
+/// allocates a free port, and listening on `0.0.0.0` is equivalent to listening on the local
+/// address of the node.
+///
+/// > **Note**: When dialing, the port of the first listener of the node, if any, is used as the
+/// >           source port of the connection. This mimics the `SO_REUSEPORT` option that is
+/// >           commonly used for NAT traversal.
+#[derive(Clone)]
+pub struct SimTransport {
+    network: SimNetwork,
+    node: usize,
+}
+
+/// Builds a transport for a node that has already been added to the network.
+pub(crate) fn new(network: SimNetwork, node: usize) -> SimTransport {
+    SimTransport {
+        network: network,
+        node: node,
+    }
+}
+
+impl SimTransport {
+    /// Returns the network this node belongs to.
+    #[inline]
+    pub fn network(&self) -> &SimNetwork {
+        &self.network
+    }
+
+    /// Returns the index of the node in the network.
+    #[inline]
+    pub fn node_index(&self) -> usize {
+        self.node
+    }
+
+    /// Returns the IP address of the node on its local network.
+    #[inline]
+    pub fn local_ip(&self) -> Ipv4Addr {
+        self.network.node_ips(self.node).0
+    }
+
+    /// Returns the IP address of the node as seen by the rest of the network. This is the
+    /// address of its NAT if the node is behind a NAT.
+    #[inline]
+    pub fn public_ip(&self) -> Ipv4Addr {
+        self.network.node_ips(self.node).1
+    }
+}
+
+impl Transport for SimTransport {
+    type Output = SimConnection;
+    type Listener = SimListener;
+    type ListenerUpgrade = FutureResult<Self::Output, io::Error>;
+    type Dial = Box<Future<Item = Self::Output, Error = io::Error> + Send>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let socket_addr = match multiaddr_to_socketaddr(&addr) {
+            Ok(a) => a,
+            Err(()) => return Err((self, addr)),
+        };
+
+        let (receiver, actual_addr, id) = match self.network.listen(self.node, socket_addr) {
+            Ok(v) => v,
+            Err(()) => return Err((self, addr)),
+        };
+
+        let listener = SimListener {
+            network: self.network,
+            addr: actual_addr,
+            id: id,
+            receiver: receiver,
+        };
+
+        Ok((listener, socket_to_multiaddr(actual_addr)))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let socket_addr = match multiaddr_to_socketaddr(&addr) {
+            Ok(a) => a,
+            Err(()) => return Err((self, addr)),
+        };
+
+        // Both the handshake and the failure of an attempt towards an unreachable node take
+        // virtual time.
+        let clock = self.network.clock();
+        let future = match self.network.connect(self.node, socket_addr) {
+            Ok((connection, handshake)) => {
+                let delay = clock.delay(handshake);
+                future::Either::A(delay.then(move |_| Ok::<_, io::Error>(connection)))
+            },
+            Err((err, after)) => {
+                let delay = clock.delay(after);
+                future::Either::B(delay.then(move |_| Err::<SimConnection, _>(err)))
+            },
+        };
+        Ok(Box::new(future))
+    }
+}
+
+/// Stream of incoming connections of a `SimTransport`.
+///
+/// Stops listening when dropped.
+pub struct SimListener {
+    network: SimNetwork,
+    addr: SocketAddrV4,
+    id: u64,
+    receiver: mpsc::UnboundedReceiver<(SimConnection, Multiaddr)>,
+}
+
+impl Stream for SimListener {
+    type Item = (FutureResult<SimConnection, io::Error>, Multiaddr);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some((connection, observed)))) => {
+                Ok(Async::Ready(Some((future::ok(connection), observed))))
+            },
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => unreachable!("an UnboundedReceiver never errors"),
+        }
+    }
+}
+
+impl Drop for SimListener {
+    fn drop(&mut self) {
+        self.network.unlisten(&self.addr, self.id);
+    }
+}
+
+/// Converts a socket address into a `/ip4/<ip>/tcp/<port>` multiaddress.
+pub(crate) fn socket_to_multiaddr(addr: SocketAddrV4) -> Multiaddr {
+    iter::once(Protocol::Ip4(*addr.ip()))
+        .chain(iter::once(Protocol::Tcp(addr.port())))
+        .collect()
+}
+
+/// Converts a `/ip4/<ip>/tcp/<port>` multiaddress into a socket address.
+pub(crate) fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddrV4, ()> {
+    let mut iter = addr.iter();
+    let proto1 = iter.next().ok_or(())?;
+    let proto2 = iter.next().ok_or(())?;
+
+    if iter.next().is_some() {
+        return Err(());
+    }
+
+    match (proto1, proto2) {
+        (Protocol::Ip4(ip), Protocol::Tcp(port)) => Ok(SocketAddrV4::new(ip, port)),
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use connection::SimConnection;
+    use futures::{future, prelude::*};
+    use link::{LinkModel, LinkParams};
+    use multiaddr::{Multiaddr, Protocol};
+    use nat::{NatConfig, NatType};
+    use network::SimNetwork;
+    use parking_lot::Mutex;
+    use scenario::Scenario;
+    use std::{io, sync::Arc, time::Duration};
+    use swarm::Transport;
+    use super::SimTransport;
+    use tokio_current_thread::{self, CurrentThread};
+    use tokio_io;
+
+    /// Listens on `listener` and dials it from `dialer`. Returns the address observed by the
+    /// listener, or the error of the dialer.
+    fn connect(listener: SimTransport, listener_addr: &str, dialer: SimTransport,
+               dialer_addr: Option<&str>) -> Result<Multiaddr, io::Error>
+    {
+        let (listener, addr) = listener.listen_on(listener_addr.parse().unwrap()).ok().unwrap();
+        let _dialer_listener = dialer_addr
+            .map(|a| dialer.clone().listen_on(a.parse().unwrap()).ok().unwrap());
+
+        let dial = dialer.dial(addr).ok().unwrap()
+            .and_then(|socket| tokio_io::io::write_all(socket, b"hello"));
+        let listen = listener.into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(incoming, _)| {
+                let (upgrade, observed) = incoming.unwrap();
+                upgrade.and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]))
+                    .map(move |(_, buf)| { assert_eq!(&buf, b"hello"); observed })
+            });
+
+        tokio_current_thread::block_on_all(dial.and_then(|_| listen))
+    }
+
+    /// Dials `addr` and advances the clock of `network` until the attempt finishes.
+    fn dial_and_wait(network: &SimNetwork, transport: SimTransport, addr: Multiaddr)
+        -> Result<SimConnection, io::Error>
+    {
+        let mut dial = transport.dial(addr).ok().unwrap();
+        let clock = network.clock().clone();
+        future::poll_fn(move || {
+            loop {
+                match dial.poll()? {
+                    Async::Ready(connection) => return Ok(Async::Ready(connection)),
+                    Async::NotReady => {
+                        let deadline = clock.next_deadline().expect("dial waits for the clock");
+                        clock.advance_to(deadline);
+                    },
+                }
+            }
+        }).wait()
+    }
+
+    #[test]
+    fn direct_connection() {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node();
+        let observed = connect(a, "/ip4/0.0.0.0/tcp/0", b.clone(), Some("/ip4/0.0.0.0/tcp/4000"))
+            .unwrap();
+        let expected = format!("/ip4/{}/tcp/4000", b.public_ip()).parse::<Multiaddr>().unwrap();
+        assert_eq!(observed, expected);
+    }
+
+    #[test]
+    fn nat_rewrites_source() {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node_behind_nat(NatConfig::new(NatType::Symmetric));
+        assert_ne!(b.local_ip(), b.public_ip());
+        let observed = connect(a, "/ip4/0.0.0.0/tcp/0", b.clone(), None).unwrap();
+        let mut iter = observed.iter();
+        assert_eq!(iter.next(), Some(Protocol::Ip4(b.public_ip())));
+    }
+
+    #[test]
+    fn nat_drops_unsolicited() {
+        let network = SimNetwork::new();
+        let a = network.add_node();
+        let b = network.add_node_behind_nat(NatConfig::new(NatType::FullCone));
+        let (_listener, addr) = b.clone().listen_on("/ip4/0.0.0.0/tcp/5000".parse().unwrap())
+            .ok().unwrap();
+        let expected = format!("/ip4/{}/tcp/5000", b.local_ip()).parse::<Multiaddr>().unwrap();
+        assert_eq!(addr, expected);
+        let public = format!("/ip4/{}/tcp/5000", b.public_ip()).parse().unwrap();
+        let err = dial_and_wait(&network, a, public).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn simultaneous_open_traverses_nats() {
+        let network = SimNetwork::new();
+        let config = NatConfig::new(NatType::PortRestrictedCone);
+        let a = network.add_node_behind_nat(config.clone());
+        let b = network.add_node_behind_nat(config);
+        let (_la, _) = a.clone().listen_on("/ip4/0.0.0.0/tcp/5000".parse().unwrap()).ok().unwrap();
+        let (_lb, _) = b.clone().listen_on("/ip4/0.0.0.0/tcp/6000".parse().unwrap()).ok().unwrap();
+        let a_public = format!("/ip4/{}/tcp/5000", a.public_ip()).parse().unwrap();
+        let b_public = format!("/ip4/{}/tcp/6000", b.public_ip()).parse().unwrap();
+
+        // The first attempt opens a mapping in the NAT of `a` but is dropped by the NAT of `b`.
+        assert!(dial_and_wait(&network, a.clone(), b_public).is_err());
+        // The attempt in the other direction then goes through.
+        assert!(b.dial(a_public).ok().unwrap().wait().is_ok());
+    }
+
+    #[test]
+    fn latency_is_applied() {
+        let network = SimNetwork::new();
+        let latency = LinkParams::with_latency(Duration::from_millis(50));
+        network.set_link_model(LinkModel::Uniform(latency));
+        let a = network.add_node();
+        let b = network.add_node();
+        let (listener, addr) = a.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).ok().unwrap();
+
+        let clock = network.clock().clone();
+        let received = Arc::new(Mutex::new(None));
+        let mut executor = CurrentThread::new();
+
+        let clock2 = clock.clone();
+        let received2 = received.clone();
+        executor.spawn(listener.into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(incoming, _)| incoming.unwrap().0)
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]))
+            .map(move |_| *received2.lock() = Some(clock2.now()))
+            .map_err(|err| panic!("{:?}", err)));
+        executor.spawn(b.dial(addr).ok().unwrap()
+            .and_then(|socket| tokio_io::io::write_all(socket, b"hello"))
+            .map(|_| ())
+            .map_err(|err| panic!("{:?}", err)));
+
+        Scenario::new().until(Duration::from_secs(1)).run(&clock, &mut executor, &mut ());
+        assert_eq!(*received.lock(), Some(Duration::from_millis(150)));
+    }
+}
@@ -18,17 +18,31 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use std::cmp;
+use std::collections::VecDeque;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::mem;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use core::Endpoint;
+use futures::prelude::*;
+use iovec::IoVec;
+use tokio_codec::FramedRead;
+use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::{Decoder, Encoder};
 use unsigned_varint::{codec, encode};
 
 // Arbitrary maximum size for a packet.
 // Since data is entirely buffered before being dispatched, we need a limit or remotes could just
 // send a 4 TB-long packet full of zeroes that we kill our process with an OOM error.
-const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+pub(crate) const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+
+/// Maximum number of bytes that we reserve at once in the read buffer when waiting for the data of
+/// a frame. A remote announcing a large frame can't make us allocate memory that it never sends.
+const MAX_EAGER_RESERVE: usize = 64 * 1024;
+
+/// Number of bytes waiting to be written above which `Framed` first tries to write them before
+/// accepting a new frame.
+const BACKPRESSURE_BOUNDARY: usize = 8 * 1024;
 
 #[derive(Debug, Clone)]
 pub enum Elem {
@@ -137,7 +151,7 @@ impl Decoder for Codec {
                 CodecDecodeState::HasHeaderAndLen(header, len) => {
                     if src.len() < len {
                         self.decoder_state = CodecDecodeState::HasHeaderAndLen(header, len);
-                        let to_reserve = len - src.len();
+                        let to_reserve = cmp::min(len - src.len(), MAX_EAGER_RESERVE);
                         src.reserve(to_reserve);
                         return Ok(None);
                     }
@@ -175,45 +189,249 @@ impl Encoder for Codec {
     type Error = IoError;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let (header, data) = match item {
-            Elem::Open { substream_id } => {
-                ((substream_id as u64) << 3, Bytes::new())
-            },
-            Elem::Data { substream_id, endpoint: Endpoint::Listener, data } => {
-                ((substream_id as u64) << 3 | 1, data)
-            },
-            Elem::Data { substream_id, endpoint: Endpoint::Dialer, data } => {
-                ((substream_id as u64) << 3 | 2, data)
-            },
-            Elem::Close { substream_id, endpoint: Endpoint::Listener } => {
-                ((substream_id as u64) << 3 | 3, Bytes::new())
-            },
-            Elem::Close { substream_id, endpoint: Endpoint::Dialer } => {
-                ((substream_id as u64) << 3 | 4, Bytes::new())
-            },
-            Elem::Reset { substream_id, endpoint: Endpoint::Listener } => {
-                ((substream_id as u64) << 3 | 5, Bytes::new())
-            },
-            Elem::Reset { substream_id, endpoint: Endpoint::Dialer } => {
-                ((substream_id as u64) << 3 | 6, Bytes::new())
+        let (header, data) = encode_frame(item)?;
+        dst.reserve(header.len() + data.len());
+        dst.put(header);
+        dst.put(data);
+        Ok(())
+    }
+}
+
+/// Encodes an element. Returns the header of the frame, which includes the length of the data,
+/// and the data itself.
+fn encode_frame(item: Elem) -> Result<(Bytes, Bytes), IoError> {
+    let (header, data) = match item {
+        Elem::Open { substream_id } => {
+            ((substream_id as u64) << 3, Bytes::new())
+        },
+        Elem::Data { substream_id, endpoint: Endpoint::Listener, data } => {
+            ((substream_id as u64) << 3 | 1, data)
+        },
+        Elem::Data { substream_id, endpoint: Endpoint::Dialer, data } => {
+            ((substream_id as u64) << 3 | 2, data)
+        },
+        Elem::Close { substream_id, endpoint: Endpoint::Listener } => {
+            ((substream_id as u64) << 3 | 3, Bytes::new())
+        },
+        Elem::Close { substream_id, endpoint: Endpoint::Dialer } => {
+            ((substream_id as u64) << 3 | 4, Bytes::new())
+        },
+        Elem::Reset { substream_id, endpoint: Endpoint::Listener } => {
+            ((substream_id as u64) << 3 | 5, Bytes::new())
+        },
+        Elem::Reset { substream_id, endpoint: Endpoint::Dialer } => {
+            ((substream_id as u64) << 3 | 6, Bytes::new())
+        },
+    };
+
+    let data_len = data.len();
+    if data_len > MAX_FRAME_SIZE {
+        return Err(IoError::new(IoErrorKind::InvalidData, "data size exceed maximum"));
+    }
+
+    let mut header_buf = encode::u64_buffer();
+    let header_bytes = encode::u64(header, &mut header_buf);
+    let mut data_buf = encode::usize_buffer();
+    let data_len_bytes = encode::usize(data_len, &mut data_buf);
+
+    let mut out = BytesMut::with_capacity(header_bytes.len() + data_len_bytes.len());
+    out.put(header_bytes);
+    out.put(data_len_bytes);
+    Ok((out.freeze(), data))
+}
+
+/// Stream of the `Elem`s read from a connection, and sink of the `Elem`s to write to it.
+///
+/// Contrary to `tokio_codec::Framed`, the frames to send aren't encoded in a single write
+/// buffer. Only the headers are encoded, and they are written along with the data of the frames
+/// using vectored writes. This avoids copying the data.
+pub struct Framed<C> {
+    /// Reading side of the connection. Also gives access to the connection for writing.
+    inner: FramedRead<C, Codec>,
+    /// Headers and data of the frames waiting to be written.
+    pending: PendingFrames,
+}
+
+impl<C> Framed<C>
+where
+    C: AsyncRead,
+{
+    /// Wraps around a connection.
+    #[inline]
+    pub fn new(io: C) -> Framed<C> {
+        Framed {
+            inner: FramedRead::new(io, Codec::new()),
+            pending: PendingFrames {
+                bufs: VecDeque::new(),
+                remaining: 0,
             },
-        };
+        }
+    }
+}
+
+impl<C> Stream for Framed<C>
+where
+    C: AsyncRead,
+{
+    type Item = Elem;
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Elem>, IoError> {
+        self.inner.poll()
+    }
+}
+
+impl<C> Sink for Framed<C>
+where
+    C: AsyncWrite,
+{
+    type SinkItem = Elem;
+    type SinkError = IoError;
 
-        let mut header_buf = encode::u64_buffer();
-        let header_bytes = encode::u64(header, &mut header_buf);
+    fn start_send(&mut self, item: Elem) -> StartSend<Elem, IoError> {
+        if self.pending.remaining >= BACKPRESSURE_BOUNDARY {
+            self.poll_complete()?;
+            if self.pending.remaining >= BACKPRESSURE_BOUNDARY {
+                return Ok(AsyncSink::NotReady(item));
+            }
+        }
 
-        let data_len = data.as_ref().len();
-        let mut data_buf = encode::usize_buffer();
-        let data_len_bytes = encode::usize(data_len, &mut data_buf);
+        let (header, data) = encode_frame(item)?;
+        self.pending.push(header);
+        self.pending.push(data);
+        Ok(AsyncSink::Ready)
+    }
 
-        if data_len > MAX_FRAME_SIZE {
-            return Err(IoError::new(IoErrorKind::InvalidData, "data size exceed maximum"));
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        while self.pending.has_remaining() {
+            let written = try_ready!(self.inner.get_mut().write_buf(&mut self.pending));
+            if written == 0 {
+                return Err(IoError::new(IoErrorKind::WriteZero, "failed to write frame to transport"));
+            }
         }
 
-        dst.reserve(header_bytes.len() + data_len_bytes.len() + data_len);
-        dst.put(header_bytes);
-        dst.put(data_len_bytes);
-        dst.put(data);
-        Ok(())
+        self.inner.get_mut().poll_flush()
+    }
+
+    fn close(&mut self) -> Poll<(), IoError> {
+        try_ready!(self.poll_complete());
+        self.inner.get_mut().shutdown()
+    }
+}
+
+/// Queue of buffers to write, in order. Implements `Buf` so that all the buffers can be passed
+/// at once to `AsyncWrite::write_buf`.
+struct PendingFrames {
+    /// The buffers. Never contains an empty buffer.
+    bufs: VecDeque<Bytes>,
+    /// Total number of bytes in `bufs`.
+    remaining: usize,
+}
+
+impl PendingFrames {
+    /// Adds a buffer at the end of the queue.
+    #[inline]
+    fn push(&mut self, buf: Bytes) {
+        if !buf.is_empty() {
+            self.remaining += buf.len();
+            self.bufs.push_back(buf);
+        }
+    }
+}
+
+impl Buf for PendingFrames {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        self.bufs.front().map(|buf| &buf[..]).unwrap_or(&[])
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(cnt <= self.remaining);
+        self.remaining -= cnt;
+
+        while cnt > 0 {
+            let front_len = self.bufs.front().map(|buf| buf.len()).expect("remaining is non-zero");
+            if cnt >= front_len {
+                self.bufs.pop_front();
+                cnt -= front_len;
+            } else {
+                let _ = self.bufs.front_mut().expect("checked above").split_to(cnt);
+                cnt = 0;
+            }
+        }
+    }
+
+    fn bytes_vec<'a>(&'a self, dst: &mut [&'a IoVec]) -> usize {
+        let mut num = 0;
+        for (buf, dst) in self.bufs.iter().zip(dst.iter_mut()) {
+            *dst = From::from(&buf[..]);
+            num += 1;
+        }
+        num
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn vectored_frames_match_codec() {
+        let elems = vec![
+            Elem::Open { substream_id: 3 },
+            Elem::Data { substream_id: 3, endpoint: Endpoint::Dialer, data: Bytes::from("hello") },
+            Elem::Close { substream_id: 3, endpoint: Endpoint::Dialer },
+        ];
+
+        let mut expected = BytesMut::new();
+        for elem in elems.iter().cloned() {
+            Codec::new().encode(elem, &mut expected).unwrap();
+        }
+
+        let mut framed = Framed::new(Cursor::new(Vec::new()));
+        for elem in elems {
+            assert!(framed.start_send(elem).unwrap().is_ready());
+        }
+        assert!(framed.poll_complete().unwrap().is_ready());
+        assert_eq!(&framed.inner.get_ref().get_ref()[..], &expected[..]);
+    }
+
+    #[test]
+    fn invalid_flag_is_error() {
+        let mut src = BytesMut::from(&[(1 << 3) | 7, 0][..]);
+        let err = Codec::new().decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_frame_is_error() {
+        let mut len_buf = encode::usize_buffer();
+        let mut src = BytesMut::from(&[2 << 3 | 2][..]);
+        src.extend_from_slice(encode::usize(MAX_FRAME_SIZE + 1, &mut len_buf));
+        let err = Codec::new().decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn announced_length_not_reserved_upfront() {
+        let mut len_buf = encode::usize_buffer();
+        let mut src = BytesMut::from(&[2 << 3 | 2][..]);
+        src.extend_from_slice(encode::usize(MAX_FRAME_SIZE, &mut len_buf));
+        assert!(Codec::new().decode(&mut src).unwrap().is_none());
+        assert!(src.capacity() <= 2 * MAX_EAGER_RESERVE);
+    }
+
+    #[test]
+    fn header_overflow_is_error() {
+        // The header doesn't fit in a `u32`.
+        let mut src = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0][..]);
+        assert!(Codec::new().decode(&mut src).is_err());
     }
 }
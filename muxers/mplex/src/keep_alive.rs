@@ -0,0 +1,285 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Keep-alive of a multiplexed connection.
+//!
+//! When enabled, we periodically send a ping on the reserved `KEEP_ALIVE_SUBSTREAM`, which the
+//! remote echoes back. This measures the round-trip time of the connection, and detects the
+//! connections that are dead even if no substream is open. The substream is never opened
+//! explicitly and never reported to the user.
+//!
+//! The pings of the remote are always answered, even if the keep-alive is disabled locally.
+
+use bytes::Bytes;
+use codec::Elem;
+use core::Endpoint;
+use core::runtime::{Delay, Runtime};
+use futures::prelude::*;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::mem;
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// Substream reserved for the keep-alive. This is the highest ID that fits in the header of a
+/// frame.
+pub(crate) const KEEP_ALIVE_SUBSTREAM: u32 = (1 << 29) - 1;
+
+/// Maximum number of answers to the pings of the remote waiting to be sent. Additional pings
+/// are ignored.
+const MAX_PENDING_PONGS: usize = 8;
+
+/// Configuration of the keep-alive.
+#[derive(Debug, Clone)]
+pub(crate) struct KeepAliveConfig {
+    /// Runtime whose timer paces the pings.
+    pub runtime: Runtime,
+    /// Time between an answer of the remote and the next ping.
+    pub interval: Duration,
+    /// Time after which a ping that hasn't been answered closes the connection.
+    pub timeout: Duration,
+}
+
+/// State of the keep-alive of a connection.
+pub(crate) struct KeepAlive {
+    /// `None` if we don't send pings.
+    config: Option<KeepAliveConfig>,
+    state: KeepAliveState,
+    /// Value of the next ping, so that late answers to previous pings are ignored.
+    next_nonce: u64,
+    /// Frames waiting to be sent on the underlying stream.
+    to_send: VecDeque<Elem>,
+    /// Round-trip time measured since the last call to `take_rtt`.
+    rtt: Option<Duration>,
+}
+
+enum KeepAliveState {
+    /// We don't send pings.
+    Disabled,
+    /// Waiting before sending the next ping.
+    Idle(Delay),
+    /// A ping has been queued and we're waiting for the answer of the remote.
+    Waiting { nonce: Bytes, sent_at: Instant, timeout: Delay },
+}
+
+impl KeepAlive {
+    /// Creates the keep-alive of a new connection.
+    pub fn new(config: Option<KeepAliveConfig>) -> KeepAlive {
+        let state = match config {
+            Some(ref config) => KeepAliveState::Idle(config.runtime.delay_for(config.interval)),
+            None => KeepAliveState::Disabled,
+        };
+
+        KeepAlive {
+            config,
+            state,
+            next_nonce: 0,
+            to_send: VecDeque::new(),
+            rtt: None,
+        }
+    }
+
+    /// Returns the round-trip time measured since the last call, if any.
+    #[inline]
+    pub fn take_rtt(&mut self) -> Option<Duration> {
+        self.rtt.take()
+    }
+
+    /// Returns the next frame to send on the underlying stream. Call `frame_sent` once it has
+    /// been accepted.
+    #[inline]
+    pub fn next_frame(&self) -> Option<Elem> {
+        self.to_send.front().cloned()
+    }
+
+    /// Removes the frame returned by `next_frame`.
+    #[inline]
+    pub fn frame_sent(&mut self) {
+        self.to_send.pop_front();
+    }
+
+    /// Processes a frame received on `KEEP_ALIVE_SUBSTREAM`.
+    ///
+    /// Returns `true` if a new ping has been scheduled, in which case `poll` must be called
+    /// again for the current task to be notified when it is due.
+    pub fn inject_frame(&mut self, elem: Elem) -> bool {
+        match elem {
+            // The remote flags the pings it sends as the initiator of the substream.
+            Elem::Data { endpoint: Endpoint::Dialer, data, .. } => {
+                if self.to_send.len() >= MAX_PENDING_PONGS {
+                    debug!("Ignored mplex keep-alive ping ; too many pending answers");
+                    return false;
+                }
+
+                self.to_send.push_back(Elem::Data {
+                    substream_id: KEEP_ALIVE_SUBSTREAM,
+                    endpoint: Endpoint::Listener,
+                    data,
+                });
+                false
+            },
+            Elem::Data { endpoint: Endpoint::Listener, data, .. } => {
+                match mem::replace(&mut self.state, KeepAliveState::Disabled) {
+                    KeepAliveState::Waiting { ref nonce, sent_at, .. } if *nonce == data => {
+                        let config = self.config.as_ref()
+                            .expect("we only wait for an answer if we have a config ; qed");
+                        let rtt = config.runtime.now().duration_since(sent_at);
+                        trace!("Mplex keep-alive round-trip time: {:?}", rtt);
+                        self.rtt = Some(rtt);
+                        self.state = KeepAliveState::Idle(config.runtime.delay_for(config.interval));
+                        true
+                    },
+                    state => {
+                        debug!("Ignored unexpected mplex keep-alive answer");
+                        self.state = state;
+                        false
+                    },
+                }
+            },
+            elem => {
+                debug!("Ignored message {:?} on the mplex keep-alive substream", elem);
+                false
+            },
+        }
+    }
+
+    /// Queues a ping if one is due. Returns an error if the remote didn't answer our last ping
+    /// in time.
+    ///
+    /// Must be called from within a task, which is notified when `poll` must be called again.
+    pub fn poll(&mut self) -> Result<(), IoError> {
+        let config = match self.config {
+            Some(ref config) => config,
+            None => return Ok(()),
+        };
+
+        loop {
+            let next_state = match self.state {
+                KeepAliveState::Disabled => return Ok(()),
+                KeepAliveState::Idle(ref mut delay) => {
+                    if let Async::NotReady = delay.poll()? {
+                        return Ok(());
+                    }
+
+                    let nonce = nonce_bytes(self.next_nonce);
+                    self.next_nonce = self.next_nonce.wrapping_add(1);
+                    self.to_send.push_back(Elem::Data {
+                        substream_id: KEEP_ALIVE_SUBSTREAM,
+                        endpoint: Endpoint::Dialer,
+                        data: nonce.clone(),
+                    });
+
+                    KeepAliveState::Waiting {
+                        nonce,
+                        sent_at: config.runtime.now(),
+                        timeout: config.runtime.delay_for(config.timeout),
+                    }
+                },
+                KeepAliveState::Waiting { ref mut timeout, .. } => {
+                    if let Async::NotReady = timeout.poll()? {
+                        return Ok(());
+                    }
+
+                    return Err(IoError::new(IoErrorKind::TimedOut, "mplex keep-alive timed out"));
+                },
+            };
+
+            self.state = next_state;
+        }
+    }
+}
+
+// Encodes the value of a ping in big endian.
+fn nonce_bytes(nonce: u64) -> Bytes {
+    let mut out = [0; 8];
+    for (n, byte) in out.iter_mut().enumerate() {
+        *byte = (nonce >> (8 * (7 - n))) as u8;
+    }
+    Bytes::from(&out[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::runtime::ManualTimer;
+    use futures::future;
+
+    fn keep_alive(timer: &ManualTimer) -> KeepAlive {
+        KeepAlive::new(Some(KeepAliveConfig {
+            runtime: Runtime::deterministic(timer.clone()),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }))
+    }
+
+    #[test]
+    fn measures_round_trip_time() {
+        future::lazy(|| {
+            let timer = ManualTimer::new();
+            let mut keep_alive = keep_alive(&timer);
+            keep_alive.poll().unwrap();
+            assert!(keep_alive.next_frame().is_none());
+
+            timer.advance(Duration::from_secs(10));
+            keep_alive.poll().unwrap();
+            let data = match keep_alive.next_frame() {
+                Some(Elem::Data { substream_id: KEEP_ALIVE_SUBSTREAM, endpoint: Endpoint::Dialer, data }) => data,
+                other => panic!("unexpected frame {:?}", other),
+            };
+            keep_alive.frame_sent();
+
+            timer.advance(Duration::from_millis(300));
+            let pong = Elem::Data { substream_id: KEEP_ALIVE_SUBSTREAM, endpoint: Endpoint::Listener, data };
+            assert!(keep_alive.inject_frame(pong));
+            assert_eq!(keep_alive.take_rtt(), Some(Duration::from_millis(300)));
+            assert_eq!(keep_alive.take_rtt(), None);
+
+            // The answer reset the timeout.
+            timer.advance(Duration::from_secs(6));
+            assert!(keep_alive.poll().is_ok());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn times_out_without_answer() {
+        future::lazy(|| {
+            let timer = ManualTimer::new();
+            let mut keep_alive = keep_alive(&timer);
+            timer.advance(Duration::from_secs(10));
+            keep_alive.poll().unwrap();
+            timer.advance(Duration::from_secs(5));
+            let err = keep_alive.poll().unwrap_err();
+            assert_eq!(err.kind(), IoErrorKind::TimedOut);
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn answers_pings_when_disabled() {
+        let mut keep_alive = KeepAlive::new(None);
+        let ping = Elem::Data { substream_id: KEEP_ALIVE_SUBSTREAM, endpoint: Endpoint::Dialer, data: nonce_bytes(7) };
+        assert!(!keep_alive.inject_frame(ping));
+        match keep_alive.next_frame() {
+            Some(Elem::Data { endpoint: Endpoint::Listener, ref data, .. }) => assert_eq!(*data, nonce_bytes(7)),
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+}
@@ -22,6 +22,7 @@ extern crate bytes;
 extern crate fnv;
 #[macro_use]
 extern crate futures;
+extern crate iovec;
 extern crate libp2p_core as core;
 #[macro_use]
 extern crate log;
@@ -29,19 +30,23 @@ extern crate parking_lot;
 extern crate tokio_codec;
 extern crate tokio_io;
 extern crate unsigned_varint;
+extern crate wasm_timer;
 
 mod codec;
+mod keep_alive;
 
 use std::{cmp, iter, mem};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::sync::{atomic::AtomicUsize, atomic::Ordering, Arc};
-use bytes::Bytes;
-use core::{ConnectionUpgrade, Endpoint, Multiaddr, StreamMuxer};
+use std::time::Duration;
+use bytes::{BufMut, Bytes, BytesMut};
+use core::{ConnectionUpgrade, Endpoint, Multiaddr, Runtime, StreamMuxer};
+use core::resource_manager::{MemoryPermit, ResourceManager};
+use keep_alive::{KeepAlive, KeepAliveConfig, KEEP_ALIVE_SUBSTREAM};
 use parking_lot::Mutex;
 use fnv::{FnvHashMap, FnvHashSet};
 use futures::prelude::*;
 use futures::{executor, future, stream::Fuse, task};
-use tokio_codec::Framed;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 /// Configuration for the multiplexer.
@@ -55,6 +60,10 @@ pub struct MplexConfig {
     max_buffer_behaviour: MaxBufferBehaviour,
     /// When sending data, split it into frames whose maximum size is this value.
     split_send_size: usize,
+    /// Configuration of the pings sent to the remote. `None` if disabled.
+    keep_alive: Option<KeepAliveConfig>,
+    /// Manager in which the memory of the buffered data is reserved, if any.
+    resource_manager: Option<ResourceManager>,
 }
 
 impl MplexConfig {
@@ -91,6 +100,50 @@ impl MplexConfig {
         self.max_buffer_behaviour = behaviour;
         self
     }
+
+    /// Sets the maximum size of the frames that carry the data written on a substream. Smaller
+    /// frames reduce the head-of-line blocking between substreams, while larger frames reduce
+    /// the overhead of the headers.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `size` is zero or larger than the maximum frame size of 32 MiB.
+    #[inline]
+    pub fn split_send_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0 && size <= codec::MAX_FRAME_SIZE, "invalid split send size {}", size);
+        self.split_send_size = size;
+        self
+    }
+
+    /// Enables the keep-alive of the connection. A ping is sent to the remote `interval` after
+    /// the previous one has been answered, and the connection is closed with a `TimedOut` error
+    /// if the remote doesn't answer within `timeout`. The pings are paced by the timer of
+    /// `runtime`.
+    ///
+    /// The pings are exchanged on a substream reserved by this implementation, independently of
+    /// the protocols that run on the connection. This detects dead connections even if no
+    /// substream is open, and the round-trip times that are measured are reported by
+    /// `StreamMuxer::take_round_trip_time`.
+    ///
+    /// > **Note**: The pings are always answered, whether or not the keep-alive is enabled
+    /// >           locally. However other implementations of mplex ignore them, and the
+    /// >           connection would then time out.
+    #[inline]
+    pub fn keep_alive(&mut self, runtime: Runtime, interval: Duration, timeout: Duration) -> &mut Self {
+        self.keep_alive = Some(KeepAliveConfig { runtime, interval, timeout });
+        self
+    }
+
+    /// Reserves the memory of the data that is buffered because its substream isn't being read
+    /// in `manager`, under the `/mplex/6.7.0` protocol.
+    ///
+    /// Data that doesn't fit in the memory budget is handled the same way as when the maximum
+    /// buffer length is reached. See `MaxBufferBehaviour`.
+    #[inline]
+    pub fn resource_manager(&mut self, manager: ResourceManager) -> &mut Self {
+        self.resource_manager = Some(manager);
+        self
+    }
 }
 
 impl Default for MplexConfig {
@@ -101,11 +154,14 @@ impl Default for MplexConfig {
             max_buffer_len: 4096,
             max_buffer_behaviour: MaxBufferBehaviour::CloseAll,
             split_send_size: 1024,
+            keep_alive: None,
+            resource_manager: None,
         }
     }
 }
 
-/// Behaviour when the maximum length of the buffer is reached.
+/// Behaviour when the maximum length of the buffer is reached, or when the memory budget of the
+/// `ResourceManager` is exhausted.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MaxBufferBehaviour {
     /// Produce an error on all the substreams.
@@ -129,13 +185,16 @@ where
     #[inline]
     fn upgrade(self, i: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
         let max_buffer_len = self.max_buffer_len;
+        let keep_alive = KeepAlive::new(self.keep_alive.clone());
 
         let out = Multiplex {
             inner: Mutex::new(MultiplexInner {
                 error: Ok(()),
-                inner: executor::spawn(Framed::new(i, codec::Codec::new()).fuse()),
+                inner: executor::spawn(codec::Framed::new(i).fuse()),
+                keep_alive,
                 config: self,
                 buffer: Vec::with_capacity(cmp::min(max_buffer_len, 512)),
+                memory_exhausted: false,
                 opened_substreams: Default::default(),
                 next_outbound_stream_id: if endpoint == Endpoint::Dialer { 0 } else { 1 },
                 notifier_read: Arc::new(Notifier {
@@ -166,11 +225,17 @@ struct MultiplexInner<C> {
     // Errored that happend earlier. Should poison any attempt to use this `MultiplexError`.
     error: Result<(), IoError>,
     // Underlying stream.
-    inner: executor::Spawn<Fuse<Framed<C, codec::Codec>>>,
+    inner: executor::Spawn<Fuse<codec::Framed<C>>>,
+    // Pings exchanged on `KEEP_ALIVE_SUBSTREAM`.
+    keep_alive: KeepAlive,
     /// The original configuration.
     config: MplexConfig,
-    // Buffer of elements pulled from the stream but not processed yet.
-    buffer: Vec<codec::Elem>,
+    // Buffer of elements pulled from the stream but not processed yet, with the memory reserved
+    // for them if the configuration has a `ResourceManager`.
+    buffer: Vec<(codec::Elem, Option<MemoryPermit>)>,
+    // True if an element has been buffered without being able to reserve its memory. No other
+    // element is buffered until the buffer shrinks.
+    memory_exhausted: bool,
     // List of Ids of opened substreams. Used to filter out messages that don't belong to any
     // substream. Note that this is handled exclusively by `next_match`.
     // The `Endpoint` value denotes who initiated the substream from our point of view
@@ -227,21 +292,23 @@ where C: AsyncRead + AsyncWrite,
         return Err(IoError::new(err.kind(), err.to_string()));
     }
 
-    if let Some((offset, out)) = inner.buffer.iter().enumerate().filter_map(|(n, v)| filter(v).map(|v| (n, v))).next() {
+    if let Some((offset, out)) = inner.buffer.iter().enumerate().filter_map(|(n, v)| filter(&v.0).map(|v| (n, v))).next() {
         // The buffer was full and no longer is, so let's notify everything.
-        if inner.buffer.len() == inner.config.max_buffer_len {
+        if inner.buffer.len() == inner.config.max_buffer_len || inner.memory_exhausted {
             executor::Notify::notify(&*inner.notifier_read, 0);
         }
 
+        // Dropping the element releases its memory.
         inner.buffer.remove(offset);
+        inner.memory_exhausted = false;
         return Ok(Async::Ready(Some(out)));
     }
 
     loop {
         // Check if we reached max buffer length first.
         debug_assert!(inner.buffer.len() <= inner.config.max_buffer_len);
-        if inner.buffer.len() == inner.config.max_buffer_len {
-            debug!("Reached mplex maximum buffer length");
+        if inner.buffer.len() == inner.config.max_buffer_len || inner.memory_exhausted {
+            debug!("Reached mplex maximum buffer length or memory budget");
             match inner.config.max_buffer_behaviour {
                 MaxBufferBehaviour::CloseAll => {
                     inner.error = Err(IoError::new(IoErrorKind::Other, "reached maximum buffer length"));
@@ -270,6 +337,16 @@ where C: AsyncRead + AsyncWrite,
 
         trace!("Received message: {:?}", elem);
 
+        // The keep-alive substream is handled internally and never reaches `filter`.
+        if elem.substream_id() == KEEP_ALIVE_SUBSTREAM {
+            if inner.keep_alive.inject_frame(elem) {
+                // A new ping has been scheduled, and its timer must be polled by `poll_inbound`.
+                task::current().notify();
+            }
+            send_keep_alive_frames(inner)?;
+            continue;
+        }
+
         // Handle substreams opening/closing.
         match elem {
             codec::Elem::Open { substream_id } => {
@@ -288,7 +365,30 @@ where C: AsyncRead + AsyncWrite,
         } else {
             let endpoint = elem.endpoint().unwrap_or(Endpoint::Dialer);
             if inner.opened_substreams.contains(&(elem.substream_id(), !endpoint)) || elem.is_open_msg() {
-                inner.buffer.push(elem);
+                let permit = match inner.config.resource_manager {
+                    Some(ref manager) => {
+                        let size = match elem {
+                            codec::Elem::Data { ref data, .. } => data.len(),
+                            _ => 0,
+                        };
+                        match manager.reserve_memory(Bytes::from("/mplex/6.7.0"), size) {
+                            Ok(permit) => Some(permit),
+                            Err(err) => {
+                                debug!("Failed to reserve memory for buffered mplex data: {}", err);
+                                if inner.config.max_buffer_behaviour == MaxBufferBehaviour::CloseAll {
+                                    inner.error = Err(err.clone().into());
+                                    return Err(err.into());
+                                }
+                                // The element has already been pulled from the stream and can't
+                                // be dropped, so we buffer it anyway but stop reading.
+                                inner.memory_exhausted = true;
+                                None
+                            },
+                        }
+                    },
+                    None => None,
+                };
+                inner.buffer.push((elem, permit));
             } else if !elem.is_close_or_reset_msg() {
                 debug!("Ignored message {:?} because the substream wasn't open", elem);
             }
@@ -312,6 +412,47 @@ where C: AsyncRead + AsyncWrite
     }
 }
 
+// Polls the timers of the keep-alive and sends the frames it produced. Poisons the muxer if the
+// remote didn't answer a ping in time.
+fn poll_keep_alive<C>(inner: &mut MultiplexInner<C>) -> Result<(), IoError>
+where C: AsyncRead + AsyncWrite
+{
+    if let Err(ref err) = inner.error {
+        return Err(IoError::new(err.kind(), err.to_string()));
+    }
+
+    if let Err(err) = inner.keep_alive.poll() {
+        debug!("Closing mplex connection: {}", err);
+        let err2 = IoError::new(err.kind(), err.to_string());
+        inner.error = Err(err);
+        return Err(err2);
+    }
+
+    send_keep_alive_frames(inner)
+}
+
+// Sends the frames of the keep-alive that are waiting, and flushes them.
+fn send_keep_alive_frames<C>(inner: &mut MultiplexInner<C>) -> Result<(), IoError>
+where C: AsyncRead + AsyncWrite
+{
+    let mut sent_any = false;
+    while let Some(elem) = inner.keep_alive.next_frame() {
+        match poll_send(inner, elem)? {
+            Async::Ready(()) => {
+                inner.keep_alive.frame_sent();
+                sent_any = true;
+            },
+            Async::NotReady => break,
+        }
+    }
+
+    if sent_any {
+        inner.inner.poll_flush_notify(&inner.notifier_write, 0)?;
+    }
+
+    Ok(())
+}
+
 impl<C> StreamMuxer for Multiplex<C>
 where C: AsyncRead + AsyncWrite
 {
@@ -320,6 +461,7 @@ where C: AsyncRead + AsyncWrite
 
     fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
         let mut inner = self.inner.lock();
+        poll_keep_alive(&mut inner)?;
 
         if inner.opened_substreams.len() >= inner.config.max_substreams {
             debug!("Refused substream ; reached maximum number of substreams {}", inner.config.max_substreams);
@@ -389,7 +531,7 @@ where C: AsyncRead + AsyncWrite
                 },
                 Err(err) => {
                     debug!("Failed to open outbound substream {}", substream.num);
-                    inner.buffer.retain(|elem| {
+                    inner.buffer.retain(|&(ref elem, _)| {
                         elem.substream_id() != substream.num || elem.endpoint() == Some(Endpoint::Dialer)
                     });
                     return Err(err)
@@ -481,6 +623,34 @@ where C: AsyncRead + AsyncWrite
         }
     }
 
+    fn write_substream_vectored(&self, substream: &mut Self::Substream, bufs: &[&[u8]]) -> Result<usize, IoError> {
+        let mut inner = self.inner.lock();
+
+        // All the buffers are sent in a single frame, up to `split_send_size` bytes.
+        let to_write = cmp::min(bufs.iter().map(|buf| buf.len()).sum::<usize>(), inner.config.split_send_size);
+        if to_write == 0 {
+            return Ok(0);
+        }
+
+        let mut data = BytesMut::with_capacity(to_write);
+        for buf in bufs {
+            let len = cmp::min(buf.len(), to_write - data.len());
+            data.put_slice(&buf[..len]);
+        }
+
+        let elem = codec::Elem::Data {
+            substream_id: substream.num,
+            data: data.freeze(),
+            endpoint: substream.endpoint,
+        };
+
+        match poll_send(&mut inner, elem) {
+            Ok(Async::Ready(())) => Ok(to_write),
+            Ok(Async::NotReady) => Err(IoErrorKind::WouldBlock.into()),
+            Err(err) => Err(err),
+        }
+    }
+
     fn flush_substream(&self, _substream: &mut Self::Substream) -> Result<(), IoError> {
         let mut inner = self.inner.lock();
         let inner = &mut *inner; // Avoids borrow errors
@@ -507,9 +677,11 @@ where C: AsyncRead + AsyncWrite
 
     fn destroy_substream(&self, mut substream: Self::Substream) {
         let _ = self.shutdown_substream(&mut substream);        // TODO: this doesn't necessarily send the close message
-        self.inner.lock().buffer.retain(|elem| {
+        let mut inner = self.inner.lock();
+        inner.buffer.retain(|&(ref elem, _)| {
             elem.substream_id() != substream.num || elem.endpoint() == Some(substream.endpoint)
-        })
+        });
+        inner.memory_exhausted = false;
     }
 
     #[inline]
@@ -519,6 +691,11 @@ where C: AsyncRead + AsyncWrite
     #[inline]
     fn close_outbound(&self) {
     }
+
+    #[inline]
+    fn take_round_trip_time(&self) -> Option<Duration> {
+        self.inner.lock().keep_alive.take_rtt()
+    }
 }
 
 /// Active attempt to open an outbound substream.
@@ -30,6 +30,7 @@ use futures::future::Future;
 use futures::{Sink, Stream};
 use std::sync::{Arc, mpsc};
 use std::thread;
+use swarm::resource_manager::{ResourceLimits, ResourceManager};
 use swarm::{muxing, Transport};
 use tcp::TcpConfig;
 use tokio_io::codec::length_delimited::Framed;
@@ -133,3 +134,54 @@ fn client_to_server_inbound() {
     tokio_current_thread::block_on_all(future).unwrap();
     bg_thread.join().unwrap();
 }
+
+#[test]
+fn buffered_data_exceeds_memory_budget() {
+    // The client sends data on a substream that the server doesn't read, while the server waits
+    // for another substream. The data is buffered, which exceeds the memory budget of the server.
+
+    let (tx, rx) = mpsc::channel();
+
+    let bg_thread = thread::spawn(move || {
+        let manager = ResourceManager::new(ResourceLimits::new().with_max_memory(512));
+        let mut config = multiplex::MplexConfig::new();
+        config.resource_manager(manager.clone());
+        let transport = TcpConfig::new().with_upgrade(config);
+
+        let (listener, addr) = transport
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        tx.send(addr).unwrap();
+
+        let future = listener
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(client, _)| client.unwrap().0)
+            .map(Arc::new)
+            .and_then(|client| {
+                muxing::inbound_from_ref_and_wrap(client.clone()).map(move |s| (client, s))
+            })
+            .and_then(|(client, substream)| {
+                // We keep the first substream open but never read from it.
+                assert!(substream.is_some());
+                muxing::inbound_from_ref_and_wrap(client).map(move |next| (substream, next))
+            });
+
+        assert!(tokio_current_thread::block_on_all(future).is_err());
+        assert_eq!(manager.stats().memory, 0);
+    });
+
+    let transport = TcpConfig::new().with_upgrade(multiplex::MplexConfig::new());
+
+    let future = transport
+        .dial(rx.recv().unwrap())
+        .unwrap()
+        .and_then(|client| muxing::outbound_from_ref_and_wrap(Arc::new(client)))
+        .map(|server| Framed::<_, bytes::BytesMut>::new(server.unwrap()))
+        .and_then(|server| server.send(vec![0u8; 2048].into()))
+        .map(|_| ());
+
+    // The server may close the connection before everything has been sent.
+    let _ = tokio_current_thread::block_on_all(future);
+    bg_thread.join().unwrap();
+}
@@ -29,47 +29,108 @@ extern crate yamux;
 
 use bytes::Bytes;
 use core::{Endpoint, Multiaddr};
+use core::resource_manager::{MemoryPermit, ResourceManager};
 use futures::{future::{self, FutureResult}, prelude::*};
 use parking_lot::Mutex;
-use std::{io, iter};
+use std::{cmp, io, iter};
 use std::io::{Read, Write, Error as IoError};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 
-pub struct Yamux<C>(Mutex<yamux::Connection<C>>);
+pub struct Yamux<C> {
+    connection: Mutex<yamux::Connection<C>>,
+    /// Maximum number of bytes written on a substream at once, if any.
+    split_send_size: Option<usize>,
+    /// Manager in which the receive buffer of each substream is reserved, with the size of the
+    /// buffers.
+    memory: Option<(ResourceManager, usize)>,
+}
+
+/// Substream of a `Yamux` connection.
+pub struct Substream<C> {
+    inner: yamux::StreamHandle<C>,
+    /// Memory reserved for the receive buffer of the substream, if any. Released when the
+    /// substream is destroyed.
+    _memory: Option<MemoryPermit>,
+}
 
 impl<C> Yamux<C>
 where
     C: AsyncRead + AsyncWrite + 'static
 {
     pub fn new(c: C, cfg: yamux::Config, mode: yamux::Mode) -> Self {
-        Yamux(Mutex::new(yamux::Connection::new(c, cfg, mode)))
+        Yamux {
+            connection: Mutex::new(yamux::Connection::new(c, cfg, mode)),
+            split_send_size: None,
+            memory: None,
+        }
+    }
+
+    /// Same as `new`, but the data written on the substreams is split into frames of at most
+    /// `split_send_size` bytes.
+    pub fn with_split_send_size(c: C, cfg: yamux::Config, mode: yamux::Mode, split_send_size: usize) -> Self {
+        Yamux {
+            connection: Mutex::new(yamux::Connection::new(c, cfg, mode)),
+            split_send_size: Some(split_send_size),
+            memory: None,
+        }
+    }
+
+    // Reserves the receive buffer of a new substream.
+    fn reserve_buffer(&self) -> Result<Option<MemoryPermit>, IoError> {
+        match self.memory {
+            Some((ref manager, size)) => {
+                let permit = manager.reserve_memory(Bytes::from("/yamux/1.0.0"), size)?;
+                Ok(Some(permit))
+            },
+            None => Ok(None),
+        }
     }
 }
 
+/// > **Note**: The yamux protocol has native ping frames, which are answered by the `yamux`
+/// >           crate, but the crate doesn't let us send our own. Contrary to mplex, `Yamux`
+/// >           therefore doesn't report any round-trip time through
+/// >           `StreamMuxer::take_round_trip_time`.
 impl<C> core::StreamMuxer for Yamux<C>
 where
     C: AsyncRead + AsyncWrite + 'static
 {
-    type Substream = yamux::StreamHandle<C>;
+    type Substream = Substream<C>;
     type OutboundSubstream = FutureResult<Option<Self::Substream>, io::Error>;
 
-    #[inline]
     fn poll_inbound(&self) -> Poll<Option<Self::Substream>, IoError> {
-        match self.0.lock().poll() {
-            Err(e) => {
-                error!("connection error: {}", e);
-                Err(io::Error::new(io::ErrorKind::Other, e))
+        loop {
+            let stream = match self.connection.lock().poll() {
+                Err(e) => {
+                    error!("connection error: {}", e);
+                    return Err(io::Error::new(io::ErrorKind::Other, e))
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::Ready(Some(stream))) => stream,
+            };
+
+            // Substreams whose receive buffer doesn't fit in the memory budget are refused, by
+            // dropping them.
+            match self.reserve_buffer() {
+                Ok(memory) => {
+                    let substream = Substream { inner: stream, _memory: memory };
+                    return Ok(Async::Ready(Some(substream)))
+                },
+                Err(err) => debug!("Refusing inbound yamux substream: {}", err),
             }
-            Ok(Async::NotReady) => Ok(Async::NotReady),
-            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
-            Ok(Async::Ready(Some(stream))) => Ok(Async::Ready(Some(stream)))
         }
     }
 
-    #[inline]
     fn open_outbound(&self) -> Self::OutboundSubstream {
-        let stream = self.0.lock().open_stream().map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        let memory = match self.reserve_buffer() {
+            Ok(memory) => memory,
+            Err(err) => return future::err(err),
+        };
+        let stream = self.connection.lock().open_stream()
+            .map(|stream| stream.map(|inner| Substream { inner, _memory: memory }))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
         future::result(stream)
     }
 
@@ -84,22 +145,28 @@ where
 
     #[inline]
     fn read_substream(&self, substream: &mut Self::Substream, buf: &mut [u8]) -> Result<usize, IoError> {
-        substream.read(buf)
+        substream.inner.read(buf)
     }
 
     #[inline]
     fn write_substream(&self, substream: &mut Self::Substream, buf: &[u8]) -> Result<usize, IoError> {
-        substream.write(buf)
+        match self.split_send_size {
+            Some(split_send_size) => {
+                let to_write = cmp::min(buf.len(), split_send_size);
+                substream.inner.write(&buf[..to_write])
+            },
+            None => substream.inner.write(buf),
+        }
     }
 
     #[inline]
     fn flush_substream(&self, substream: &mut Self::Substream) -> Result<(), IoError> {
-        substream.flush()
+        substream.inner.flush()
     }
 
     #[inline]
     fn shutdown_substream(&self, substream: &mut Self::Substream) -> Poll<(), IoError> {
-        substream.shutdown()
+        substream.inner.shutdown()
     }
 
     #[inline]
@@ -117,18 +184,97 @@ where
 
 
 
+/// Default maximum number of bytes buffered for a substream by the `yamux` crate.
+const DEFAULT_MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
 #[derive(Clone)]
-pub struct Config(yamux::Config);
+pub struct Config {
+    inner: yamux::Config,
+    /// When sending data, split it into frames whose maximum size is this value, if any.
+    split_send_size: Option<usize>,
+    /// Maximum number of bytes buffered for a substream. Mirrors the value of `inner`.
+    max_buffer_size: usize,
+    /// Manager in which the receive buffers are reserved, if any.
+    resource_manager: Option<ResourceManager>,
+}
 
 impl Config {
+    /// Builds a configuration out of the configuration of the `yamux` crate.
+    ///
+    /// > **Note**: If `cfg` has a maximum buffer size other than the default one, it must also
+    /// >           be passed to `max_buffer_size` for the memory reserved with
+    /// >           `resource_manager` to be accurate.
     pub fn new(cfg: yamux::Config) -> Self {
-        Config(cfg)
+        Config {
+            inner: cfg,
+            split_send_size: None,
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            resource_manager: None,
+        }
+    }
+
+    /// Sets the size of the receive window of the substreams, in bytes. This is the amount of
+    /// data the remote can send on a substream before waiting for us to read it, which should
+    /// be at least the bandwidth-delay product of the link for transfers not to stall.
+    ///
+    /// > **Note**: The receive window can't be lower than the initial window of 256 KiB defined
+    /// >           by the yamux specification.
+    #[inline]
+    pub fn receive_window(&mut self, size: u32) -> &mut Self {
+        self.inner.set_receive_window(size);
+        self
+    }
+
+    /// Sets the maximum number of bytes buffered for a substream that hasn't been read yet.
+    ///
+    /// A limit is necessary in order to avoid DoS attacks.
+    #[inline]
+    pub fn max_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.inner.set_max_buffer_size(size);
+        self.max_buffer_size = size;
+        self
+    }
+
+    /// Reserves the maximum buffer size of each substream in `manager`, under the `/yamux/1.0.0`
+    /// protocol, for as long as the substream is alive.
+    ///
+    /// Inbound substreams that don't fit in the memory budget are refused, and opening an
+    /// outbound substream fails with an error.
+    #[inline]
+    pub fn resource_manager(&mut self, manager: ResourceManager) -> &mut Self {
+        self.resource_manager = Some(manager);
+        self
+    }
+
+    /// Sets the maximum number of simultaneously opened substreams.
+    ///
+    /// A limit is necessary in order to avoid DoS attacks.
+    #[inline]
+    pub fn max_substreams(&mut self, max: usize) -> &mut Self {
+        self.inner.set_max_num_streams(max);
+        self
+    }
+
+    /// Sets the maximum size of the frames that carry the data written on a substream. Smaller
+    /// frames reduce the head-of-line blocking between substreams, while larger frames reduce
+    /// the overhead of the headers.
+    ///
+    /// By default, the data is written as it is passed to the substream.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `size` is zero.
+    #[inline]
+    pub fn split_send_size(&mut self, size: usize) -> &mut Self {
+        assert_ne!(size, 0, "the split send size can't be zero");
+        self.split_send_size = Some(size);
+        self
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Config(yamux::Config::default())
+        Config::new(yamux::Config::default())
     }
 }
 
@@ -152,7 +298,13 @@ where
             Endpoint::Listener => yamux::Mode::Server
         };
 
-        future::ok(Yamux::new(i, self.0, mode))
+        let max_buffer_size = self.max_buffer_size;
+        let mut yamux = match self.split_send_size {
+            Some(size) => Yamux::with_split_send_size(i, self.inner, mode, size),
+            None => Yamux::new(i, self.inner, mode),
+        };
+        yamux.memory = self.resource_manager.map(|manager| (manager, max_buffer_size));
+        future::ok(yamux)
     }
 }
 
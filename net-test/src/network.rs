@@ -32,7 +32,7 @@ use libp2p::secio::SecioKeyPair;
 use libp2p::core::muxing::StreamMuxerBox;
 use libp2p::core::nodes::handled_node::NodeHandler;
 use libp2p::core::nodes::node::Substream;
-use libp2p::core::nodes::swarm::{ConnectedPoint, Swarm, SwarmEvent};
+use libp2p::core::nodes::swarm::{CloseCause, ConnectedPoint, DuplicateConnectionPolicy, Swarm, SwarmEvent};
 use rand;
 use tokio_core::reactor::Core;
 use tokio::runtime::Runtime;
@@ -107,6 +107,11 @@ impl Network {
                 transport::build_transport(key.clone(), transport_timeout),
                 handler
             );
+            // Nodes of the simulation may dial each other at the same time ; make sure that both
+            // sides keep the same connection.
+            swarm.set_duplicate_connection_policy(DuplicateConnectionPolicy::TieBreak {
+                local_peer_id: key.to_peer_id(),
+            });
 
             let new_addr = swarm.listen_on(listen_addr).unwrap();
             init_tx.send(new_addr).expect("Network not running");
@@ -115,7 +120,7 @@ impl Network {
             let future = stream::poll_fn(move || -> Poll<_, io::Error> {
                 loop {
                     match conn_rx.poll() {
-                        Ok(Async::Ready(Some(dial_addr))) => swarm.dial(dial_addr).unwrap(),
+                        Ok(Async::Ready(Some(dial_addr))) => { swarm.dial(dial_addr).unwrap(); },
                         Ok(Async::NotReady) => break,
                         Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(None)),
                     }
@@ -130,12 +135,14 @@ impl Network {
                             SwarmEvent::IncomingConnectionError { error, .. } => {
                                 panic!("Incoming connection error: {:?}", error);
                             },
-                            SwarmEvent::Connected { .. } => {},
+                            SwarmEvent::ConnectionEstablished { .. } => {},
                             SwarmEvent::ListenerClosed { result, .. } => {
                                 panic!("Listener closed: {:?}", result);
                             },
-                            SwarmEvent::Replaced { .. } => {},
-                            SwarmEvent::NodeClosed { .. } => {
+                            SwarmEvent::NewListenAddr { .. } |
+                            SwarmEvent::ExpiredListenAddr { .. } => {},
+                            SwarmEvent::ConnectionClosed { cause: CloseCause::Replaced, .. } => {},
+                            SwarmEvent::ConnectionClosed { cause: CloseCause::Graceful, .. } => {
                                 if !swarm.has_connections_or_pending() {
                                     // We send all the dial requests before starting to poll this
                                     // future, therefore the swarm will be empty only when all
@@ -143,17 +150,27 @@ impl Network {
                                     return Ok(Async::Ready(None));
                                 }
                             },
-                            SwarmEvent::NodeError { peer_id, error, .. } => {
+                            SwarmEvent::ConnectionClosed { peer_id, cause: CloseCause::Error(error), .. } => {
                                 panic!("{:?} NodeError: {:?}", peer_id, error);
                             },
-                            SwarmEvent::DialError { peer_id, error, .. } => {
-                                panic!("{:?} DialError: {:?}", peer_id, error);
-                            },
-                            SwarmEvent::UnknownPeerDialError { error, .. } => {
-                                panic!("UnknownPeerDialError: {:?}", error);
+                            SwarmEvent::ConnectionClosed { peer_id, cause: CloseCause::HandlerPanicked(message), .. } => {
+                                panic!("{:?} handler panicked: {}", peer_id, message);
                             },
-                            _ => {
+                            SwarmEvent::DialFailure { peer_id, error, .. } => {
+                                panic!("{:?} DialError: {:?}", peer_id, error);
                             },
+                            SwarmEvent::ConnectionClosed { cause: CloseCause::Banned, .. } |
+                            SwarmEvent::ConnectionClosed { cause: CloseCause::Disconnected, .. } |
+                            SwarmEvent::ConnectionClosed { cause: CloseCause::ShutdownDeadline, .. } => {},
+                            SwarmEvent::PeerBanned { .. } | SwarmEvent::PeerUnbanned { .. } |
+                            SwarmEvent::BannedPeerRejected { .. } |
+                            SwarmEvent::ConnectionFiltered { .. } |
+                            SwarmEvent::DialBackedOff { .. } |
+                            SwarmEvent::DuplicateConnectionRejected { .. } => {},
+                            SwarmEvent::NewExternalAddress { .. } |
+                            SwarmEvent::ExpiredExternalAddress { .. } |
+                            SwarmEvent::PeerUnreachable { .. } => {},
+                            SwarmEvent::NodeEvent { .. } | SwarmEvent::Behaviour(_) => {},
                         }
                     }
                 }
@@ -20,7 +20,8 @@
 
 use std::time::Duration;
 use libp2p::{self, PeerId, Transport, mplex, secio, yamux};
-use libp2p::core::{either, upgrade, transport::boxed::Boxed, muxing::StreamMuxerBox};
+use libp2p::core::{either, upgrade, transport::boxed::Boxed};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox};
 use libp2p::transport_timeout::TransportTimeout;
 
 /// Builds the transport that serves as a common ground for all connections.
@@ -39,7 +40,7 @@ pub fn build_transport(
 			let upgrade = upgrade::map(upgrade, move |muxer| (peer_id, muxer));
 			upgrade::apply(out.stream, upgrade, endpoint, client_addr)
 		})
-		.map(|(id, muxer), _| (id, StreamMuxerBox::new(muxer)));
+		.map(|(id, muxer), _| (id, muxer.boxed()));
 
 	if let Some(timeout) = timeout {
 		TransportTimeout::new(base, timeout).boxed()
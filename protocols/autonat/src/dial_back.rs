@@ -0,0 +1,201 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{future, stream, Future, Stream};
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::{Multiaddr, PeerId, Transport};
+use protocol::{DialRequest, DialResponse, ResponseStatus};
+use std::io::Error as IoError;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio_timer::Timeout;
+
+/// Maximum number of addresses of a request that we try to dial.
+pub const MAX_DIAL_BACK_ADDRESSES: usize = 8;
+
+/// Tries to dial the addresses of a `DialRequest` one by one with the given transport, and
+/// builds the response to send back to the remote.
+///
+/// `remote` and `remote_addr` are the identity and the address of the remote that sent the
+/// request. The request is refused if it is about another peer, and we only dial the addresses
+/// whose IP is the one the remote is connected from, so that we can't be used to connect to
+/// arbitrary hosts. A dial back is only successful if the node we reach has the identity of the
+/// remote.
+///
+/// Each dial attempt is aborted after `timeout`. The connection that is established, if any, is
+/// immediately closed.
+///
+/// > **Note**: The returned future must be polled from within a tokio runtime.
+pub fn dial_back<T, M>(
+    transport: T,
+    remote: &PeerId,
+    remote_addr: &Multiaddr,
+    request: &DialRequest,
+    timeout: Duration,
+) -> Box<Future<Item = DialResponse, Error = IoError> + Send>
+where
+    T: Transport<Output = (PeerId, M)> + Clone + Send + 'static,
+    T::Dial: Send,
+    M: Send,
+{
+    if request.peer_id != *remote {
+        let response = DialResponse::Error {
+            status: ResponseStatus::BadRequest,
+            text: "can only dial back the peer that sent the request".to_owned(),
+        };
+        return Box::new(future::ok(response));
+    }
+
+    if request.addresses.is_empty() {
+        let response = DialResponse::Error {
+            status: ResponseStatus::BadRequest,
+            text: "no address to dial".to_owned(),
+        };
+        return Box::new(future::ok(response));
+    }
+
+    let observed_ip = ip_of(remote_addr);
+    let addresses = request
+        .addresses
+        .iter()
+        .filter(|addr| observed_ip.is_some() && ip_of(addr) == observed_ip)
+        .take(MAX_DIAL_BACK_ADDRESSES)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if addresses.is_empty() {
+        debug!("Refusing to dial back {:?} ; no address matches {}", remote, remote_addr);
+        let response = DialResponse::Error {
+            status: ResponseStatus::DialRefused,
+            text: "no address matches the observed IP".to_owned(),
+        };
+        return Box::new(future::ok(response));
+    }
+
+    let remote = remote.clone();
+    let future = stream::iter_ok(addresses.into_iter())
+        .filter_map(move |addr: Multiaddr| {
+            let dial = transport.clone().dial(addr.clone()).ok()?;
+            Some(Timeout::new(dial, timeout).map(move |(peer_id, _)| (peer_id, addr)))
+        })
+        .and_then(|dial| dial)
+        .then(|result| Ok(result.ok()))
+        .filter_map(move |result| match result {
+            Some((ref peer_id, _)) if *peer_id != remote => {
+                debug!("Dialed back {:?} instead of {:?}", peer_id, remote);
+                None
+            }
+            result => result,
+        })
+        .into_future()
+        .map_err(|(err, _): (IoError, _)| err)
+        .map(|(ok, _)| match ok {
+            Some((_, addr)) => {
+                debug!("Successfully dialed back {}", addr);
+                DialResponse::Ok(addr)
+            }
+            None => DialResponse::Error {
+                status: ResponseStatus::DialError,
+                text: "failed to dial any of the addresses".to_owned(),
+            },
+        });
+
+    Box::new(future)
+}
+
+// Returns the IP address at the start of a multiaddress, if any.
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => Some(ip.into()),
+        Some(Protocol::Ip6(ip)) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libp2p_tcp_transport;
+    extern crate tokio;
+
+    use self::libp2p_tcp_transport::TcpConfig;
+    use self::tokio::runtime::current_thread::Runtime;
+    use super::dial_back;
+    use futures::{Future, Stream};
+    use libp2p_core::{Multiaddr, PeerId, PublicKey, Transport};
+    use protocol::{DialRequest, DialResponse, ResponseStatus};
+    use std::time::Duration;
+
+    // Dials back `addr`, or our listener if `None`, with a transport that reaches `reached`, on
+    // behalf of a requester connected from 127.0.0.1.
+    fn run(reached: PeerId, requester: PeerId, addr: Option<Multiaddr>) -> DialResponse {
+        let mut runtime = Runtime::new().unwrap();
+
+        let (listener, listen_addr) = TcpConfig::new()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        runtime.spawn(listener
+            .for_each(|(upgrade, _)| upgrade.map(|_| ()))
+            .map_err(|_| ()));
+
+        let request = DialRequest {
+            peer_id: requester.clone(),
+            addresses: vec![addr.unwrap_or(listen_addr)],
+        };
+        let transport = TcpConfig::new().map(move |socket, _| (reached.clone(), socket));
+        let remote_addr = "/ip4/127.0.0.1/tcp/12345".parse().unwrap();
+        let future = dial_back(transport, &peer(1), &remote_addr, &request, Duration::from_secs(5));
+        runtime.block_on(future).unwrap()
+    }
+
+    fn peer(n: u8) -> PeerId {
+        PublicKey::Ed25519(vec![n; 32]).into_peer_id()
+    }
+
+    fn error_status(response: DialResponse) -> ResponseStatus {
+        match response {
+            DialResponse::Error { status, .. } => status,
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    #[test]
+    fn dial_back_success() {
+        match run(peer(1), peer(1), None) {
+            DialResponse::Ok(addr) => assert!(addr.to_string().starts_with("/ip4/127.0.0.1/tcp/")),
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    #[test]
+    fn other_peer_refused() {
+        assert_eq!(error_status(run(peer(2), peer(2), None)), ResponseStatus::BadRequest);
+    }
+
+    #[test]
+    fn other_ips_not_dialed() {
+        let addr = "/ip4/80.81.82.83/tcp/500".parse().unwrap();
+        assert_eq!(error_status(run(peer(1), peer(1), Some(addr))), ResponseStatus::DialRefused);
+    }
+
+    #[test]
+    fn wrong_peer_reached() {
+        assert_eq!(error_status(run(peer(2), peer(1), None)), ResponseStatus::DialError);
+    }
+}
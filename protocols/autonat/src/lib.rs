@@ -0,0 +1,56 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/libp2p/autonat/1.0.0` protocol.
+//!
+//! AutoNAT lets a node find out whether it is reachable from the outside, by asking the remotes
+//! it is connected to to dial it back on the addresses it thinks it is reachable at.
+//!
+//! # Usage
+//!
+//! Use the `AutoNatProtocolConfig` upgrade on a substream.
+//!
+//! - As the dialer, the upgrade produces an `AutoNatRequester`. Call `probe` with our identity
+//!   and our addresses, and pass the `DialResponse` that the remote sends back to a
+//!   `NatStatusTracker`. The tracker aggregates the responses of multiple remotes and produces
+//!   the new `NatStatus` whenever our reachability changes.
+//! - As the listener, the upgrade produces the `DialRequest` of the remote alongside with an
+//!   `AutoNatResponder`. Use `dial_back` to try dialing the addresses of the request, then send
+//!   the result with the responder. Only the addresses whose IP matches the one the remote is
+//!   connected from are dialed back.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate protobuf;
+extern crate tokio_io;
+extern crate tokio_timer;
+
+pub use self::dial_back::{dial_back, MAX_DIAL_BACK_ADDRESSES};
+pub use self::protocol::{AutoNatOutput, AutoNatProtocolConfig, AutoNatRequester, AutoNatResponder};
+pub use self::protocol::{DialRequest, DialResponse, ResponseStatus};
+pub use self::status::{NatStatus, NatStatusTracker, DEFAULT_CONFIDENCE_THRESHOLD};
+
+mod dial_back;
+mod protocol;
+mod status;
+mod structs_proto;
@@ -0,0 +1,364 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bytes::Bytes;
+use futures::{future, Future};
+use libp2p_core::upgrade::{self, MessageLimits};
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr, PeerId};
+use protobuf::Message as ProtobufMessage;
+use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
+use protobuf::RepeatedField;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use structs_proto;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/libp2p/autonat/1.0.0";
+
+/// Configuration for an upgrade to the AutoNAT protocol.
+#[derive(Debug, Clone, Default)]
+pub struct AutoNatProtocolConfig {
+    /// Bounds the size of the requests and responses of the remote.
+    limits: MessageLimits,
+}
+
+impl AutoNatProtocolConfig {
+    /// Builds a new configuration, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        AutoNatProtocolConfig::default()
+    }
+
+    /// Replaces the limits applied to the messages of the remote.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+/// Output of the connection upgrade.
+pub enum AutoNatOutput<T> {
+    /// We opened the substream and can ask the remote to dial us back. Happens when we are the
+    /// dialer.
+    Requester {
+        /// Object used to send the request.
+        requester: AutoNatRequester<T>,
+    },
+
+    /// The remote asked us to dial it back. Happens when we are the listener.
+    Request {
+        /// The addresses to dial back.
+        request: DialRequest,
+        /// Object used to send back the result of the dial.
+        responder: AutoNatResponder<T>,
+    },
+}
+
+/// Request of a remote to be dialed back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialRequest {
+    /// Identity of the remote that must be dialed back.
+    pub peer_id: PeerId,
+    /// Addresses that the remote thinks it is reachable at.
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// Result of a dial back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialResponse {
+    /// The remote successfully dialed us back on the given address.
+    Ok(Multiaddr),
+    /// The remote couldn't or wouldn't dial us back.
+    Error {
+        /// Reason of the failure.
+        status: ResponseStatus,
+        /// Human-readable explanation of the failure.
+        text: String,
+    },
+}
+
+/// Reason why a dial back failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResponseStatus {
+    /// None of the addresses could be dialed.
+    DialError,
+    /// The remote refused to dial us back.
+    DialRefused,
+    /// The request was invalid.
+    BadRequest,
+    /// The remote encountered an internal error.
+    InternalError,
+}
+
+/// Object used to ask the remote to dial us back.
+pub struct AutoNatRequester<T> {
+    socket: T,
+    /// Maximum size of the response of the remote.
+    max_message_size: usize,
+}
+
+impl<'a, T> AutoNatRequester<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'a,
+{
+    /// Asks the remote to dial us back on the given addresses. Returns a future that produces
+    /// the answer of the remote.
+    pub fn probe(
+        self,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    ) -> Box<Future<Item = DialResponse, Error = IoError> + Send + 'a> {
+        debug!("Asking remote to dial back {} addresses", addresses.len());
+
+        let mut peer = structs_proto::Message_PeerInfo::new();
+        peer.set_id(peer_id.into_bytes());
+        peer.set_addrs(RepeatedField::from_vec(
+            addresses.into_iter().map(|addr| addr.into_bytes()).collect(),
+        ));
+        let mut dial = structs_proto::Message_Dial::new();
+        dial.set_peer(peer);
+        let mut message = structs_proto::Message::new();
+        message.set_field_type(structs_proto::Message_MessageType::DIAL);
+        message.set_dial(dial);
+
+        let bytes = message
+            .write_to_bytes()
+            .expect("writing protobuf failed ; should never happen");
+
+        let max_message_size = self.max_message_size;
+        let future = upgrade::write_one(self.socket, bytes)
+            .and_then(move |socket| upgrade::read_one(socket, max_message_size).from_err())
+            .and_then(|(_, msg)| parse_response(&msg));
+
+        Box::new(future) as Box<_>
+    }
+}
+
+/// Object used to send back the result of a dial back to the remote.
+pub struct AutoNatResponder<T> {
+    socket: T,
+}
+
+impl<'a, T> AutoNatResponder<T>
+where
+    T: AsyncWrite + Send + 'a,
+{
+    /// Sends the result of the dial back to the remote. Returns a future that is signalled
+    /// once the response has been sent.
+    pub fn respond(self, response: DialResponse) -> Box<Future<Item = (), Error = IoError> + Send + 'a> {
+        trace!("Sending AutoNAT response: {:?}", response);
+
+        let mut dial_response = structs_proto::Message_DialResponse::new();
+        match response {
+            DialResponse::Ok(addr) => {
+                dial_response.set_status(structs_proto::Message_ResponseStatus::OK);
+                dial_response.set_addr(addr.into_bytes());
+            }
+            DialResponse::Error { status, text } => {
+                dial_response.set_status(match status {
+                    ResponseStatus::DialError => structs_proto::Message_ResponseStatus::E_DIAL_ERROR,
+                    ResponseStatus::DialRefused => structs_proto::Message_ResponseStatus::E_DIAL_REFUSED,
+                    ResponseStatus::BadRequest => structs_proto::Message_ResponseStatus::E_BAD_REQUEST,
+                    ResponseStatus::InternalError => structs_proto::Message_ResponseStatus::E_INTERNAL_ERROR,
+                });
+                dial_response.set_statusText(text);
+            }
+        }
+
+        let mut message = structs_proto::Message::new();
+        message.set_field_type(structs_proto::Message_MessageType::DIAL_RESPONSE);
+        message.set_dialResponse(dial_response);
+
+        let bytes = message
+            .write_to_bytes()
+            .expect("writing protobuf failed ; should never happen");
+
+        let future = upgrade::write_one(self.socket, bytes).map(|_| ());
+        Box::new(future) as Box<_>
+    }
+}
+
+impl<C> ConnectionUpgrade<C> for AutoNatProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+    type UpgradeIdentifier = ();
+    type Output = AutoNatOutput<C>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    fn upgrade(self, socket: C, _: (), ty: Endpoint, _: &Multiaddr) -> Self::Future {
+        trace!("Upgrading connection as {:?}", ty);
+
+        let max_message_size = self.limits.max_for(PROTOCOL_NAME);
+        match ty {
+            Endpoint::Dialer => {
+                let output = AutoNatOutput::Requester {
+                    requester: AutoNatRequester { socket, max_message_size },
+                };
+                Box::new(future::ok(output)) as Box<_>
+            }
+
+            Endpoint::Listener => {
+                let respond = |socket: C, msg: Vec<u8>, ()| {
+                    let request = parse_request(&msg)?;
+                    trace!("Remote asked to be dialed back: {:?}", request);
+                    Ok::<_, IoError>(AutoNatOutput::Request {
+                        request: request,
+                        responder: AutoNatResponder { socket },
+                    })
+                };
+                let future = upgrade::read_respond(socket, max_message_size, (), respond);
+
+                Box::new(future) as Box<_>
+            }
+        }
+    }
+}
+
+// Turns a protobuf message into a `DialRequest`. Addresses that fail to parse are ignored.
+fn parse_request(msg: &[u8]) -> Result<DialRequest, IoError> {
+    let mut message = protobuf_parse_from_bytes::<structs_proto::Message>(msg)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    if message.get_field_type() != structs_proto::Message_MessageType::DIAL || !message.has_dial() {
+        return Err(IoError::new(IoErrorKind::InvalidData, "expected a DIAL message"));
+    }
+
+    let mut peer = message.take_dial().take_peer();
+    let peer_id = PeerId::from_bytes(peer.take_id())
+        .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid peer id"))?;
+    let addresses = peer
+        .take_addrs()
+        .into_iter()
+        .filter_map(|addr| Multiaddr::from_bytes(addr).ok())
+        .collect();
+
+    Ok(DialRequest {
+        peer_id: peer_id,
+        addresses: addresses,
+    })
+}
+
+// Turns a protobuf message into a `DialResponse`.
+fn parse_response(msg: &[u8]) -> Result<DialResponse, IoError> {
+    let mut message = protobuf_parse_from_bytes::<structs_proto::Message>(msg)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    if message.get_field_type() != structs_proto::Message_MessageType::DIAL_RESPONSE
+        || !message.has_dialResponse()
+    {
+        return Err(IoError::new(IoErrorKind::InvalidData, "expected a DIAL_RESPONSE message"));
+    }
+
+    let mut response = message.take_dialResponse();
+    let status = match response.get_status() {
+        structs_proto::Message_ResponseStatus::OK => {
+            let addr = Multiaddr::from_bytes(response.take_addr())
+                .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+            return Ok(DialResponse::Ok(addr));
+        }
+        structs_proto::Message_ResponseStatus::E_DIAL_ERROR => ResponseStatus::DialError,
+        structs_proto::Message_ResponseStatus::E_DIAL_REFUSED => ResponseStatus::DialRefused,
+        structs_proto::Message_ResponseStatus::E_BAD_REQUEST => ResponseStatus::BadRequest,
+        structs_proto::Message_ResponseStatus::E_INTERNAL_ERROR => ResponseStatus::InternalError,
+    };
+
+    Ok(DialResponse::Error {
+        status: status,
+        text: response.take_statusText(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libp2p_tcp_transport;
+    extern crate tokio_current_thread;
+
+    use self::libp2p_tcp_transport::TcpConfig;
+    use futures::{Future, Stream};
+    use libp2p_core::{PublicKey, Transport};
+    use std::sync::mpsc;
+    use std::thread;
+    use {AutoNatOutput, AutoNatProtocolConfig, DialResponse};
+
+    #[test]
+    fn correct_transfer() {
+        // The dialer asks the listener to be dialed back, and the listener answers with the
+        // first address of the request.
+
+        let (tx, rx) = mpsc::channel();
+        let peer_id = PublicKey::Ed25519(vec![1, 2, 3, 4, 5, 7]).into_peer_id();
+        let peer_id2 = peer_id.clone();
+
+        let bg_thread = thread::spawn(move || {
+            let transport = TcpConfig::new().with_upgrade(AutoNatProtocolConfig::new());
+
+            let (listener, addr) = transport
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+            tx.send(addr).unwrap();
+
+            let future = listener
+                .into_future()
+                .map_err(|(err, _)| err)
+                .and_then(|(client, _)| client.unwrap().0)
+                .and_then(move |output| match output {
+                    AutoNatOutput::Request { request, responder } => {
+                        assert_eq!(request.peer_id, peer_id2);
+                        assert_eq!(
+                            request.addresses,
+                            &["/ip4/80.81.82.83/tcp/500".parse().unwrap()]
+                        );
+                        responder.respond(DialResponse::Ok(request.addresses[0].clone()))
+                    }
+                    _ => panic!(),
+                });
+
+            let _ = tokio_current_thread::block_on_all(future).unwrap();
+        });
+
+        let transport = TcpConfig::new().with_upgrade(AutoNatProtocolConfig::new());
+
+        let future = transport
+            .dial(rx.recv().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .and_then(|output| match output {
+                AutoNatOutput::Requester { requester } => requester.probe(
+                    peer_id,
+                    vec!["/ip4/80.81.82.83/tcp/500".parse().unwrap()],
+                ),
+                _ => panic!(),
+            })
+            .map(|response| {
+                assert_eq!(response, DialResponse::Ok("/ip4/80.81.82.83/tcp/500".parse().unwrap()));
+            });
+
+        let _ = tokio_current_thread::block_on_all(future).unwrap();
+        bg_thread.join().unwrap();
+    }
+}
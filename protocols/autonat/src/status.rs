@@ -0,0 +1,174 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use libp2p_core::Multiaddr;
+use protocol::{DialResponse, ResponseStatus};
+
+/// Default number of consecutive identical results required to change the NAT status.
+pub const DEFAULT_CONFIDENCE_THRESHOLD: usize = 3;
+
+/// Reachability of the local node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatStatus {
+    /// The local node is reachable from the outside at the given address.
+    Public(Multiaddr),
+    /// The local node is not reachable from the outside, for example because it is behind a NAT.
+    Private,
+    /// Not enough remotes have answered yet.
+    Unknown,
+}
+
+/// Aggregates the results of the dial backs made by multiple remotes into a `NatStatus`.
+///
+/// The status only changes after a number of consecutive results that agree with each other,
+/// so that a single misbehaving or badly-connected remote can't flip it.
+#[derive(Debug, Clone)]
+pub struct NatStatusTracker {
+    /// The current status.
+    status: NatStatus,
+    /// Number of consecutive identical results required to change the status.
+    threshold: usize,
+    /// The status that the latest results point to, and the number of consecutive results
+    /// pointing to it.
+    candidate: Option<(NatStatus, usize)>,
+}
+
+impl NatStatusTracker {
+    /// Creates a new tracker with the default confidence threshold.
+    #[inline]
+    pub fn new() -> NatStatusTracker {
+        NatStatusTracker::with_threshold(DEFAULT_CONFIDENCE_THRESHOLD)
+    }
+
+    /// Creates a new tracker that changes its status after `threshold` consecutive identical
+    /// results. A threshold of 0 is treated as 1.
+    pub fn with_threshold(threshold: usize) -> NatStatusTracker {
+        NatStatusTracker {
+            status: NatStatus::Unknown,
+            threshold: if threshold == 0 { 1 } else { threshold },
+            candidate: None,
+        }
+    }
+
+    /// Returns the current status.
+    #[inline]
+    pub fn status(&self) -> &NatStatus {
+        &self.status
+    }
+
+    /// Adds the result of a dial back. Returns the new status if it changed.
+    ///
+    /// Results that don't tell anything about our reachability, such as a remote refusing to
+    /// dial us back, are ignored.
+    pub fn add_response(&mut self, response: &DialResponse) -> Option<NatStatus> {
+        let pointed = match *response {
+            DialResponse::Ok(ref addr) => NatStatus::Public(addr.clone()),
+            DialResponse::Error { status: ResponseStatus::DialError, .. } => NatStatus::Private,
+            DialResponse::Error { .. } => return None,
+        };
+
+        if pointed == self.status {
+            self.candidate = None;
+            return None;
+        }
+
+        let same_candidate = match self.candidate {
+            Some((ref candidate, _)) => *candidate == pointed,
+            None => false,
+        };
+        let count = if same_candidate {
+            self.candidate.as_mut().map(|c| { c.1 += 1; c.1 }).unwrap_or(1)
+        } else {
+            self.candidate = Some((pointed.clone(), 1));
+            1
+        };
+
+        if count >= self.threshold {
+            self.candidate = None;
+            self.status = pointed.clone();
+            Some(pointed)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for NatStatusTracker {
+    #[inline]
+    fn default() -> Self {
+        NatStatusTracker::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NatStatus, NatStatusTracker};
+    use protocol::{DialResponse, ResponseStatus};
+
+    fn error(status: ResponseStatus) -> DialResponse {
+        DialResponse::Error {
+            status: status,
+            text: String::new(),
+        }
+    }
+
+    #[test]
+    fn becomes_public_then_private() {
+        let addr = "/ip4/80.81.82.83/tcp/500".parse().unwrap();
+        let mut tracker = NatStatusTracker::with_threshold(2);
+        assert_eq!(tracker.status(), &NatStatus::Unknown);
+
+        assert_eq!(tracker.add_response(&DialResponse::Ok(addr)), None);
+        let addr = "/ip4/80.81.82.83/tcp/500".parse().unwrap();
+        assert_eq!(
+            tracker.add_response(&DialResponse::Ok(addr)),
+            Some(NatStatus::Public("/ip4/80.81.82.83/tcp/500".parse().unwrap()))
+        );
+
+        assert_eq!(tracker.add_response(&error(ResponseStatus::DialError)), None);
+        assert_eq!(
+            tracker.add_response(&error(ResponseStatus::DialError)),
+            Some(NatStatus::Private)
+        );
+        assert_eq!(tracker.status(), &NatStatus::Private);
+    }
+
+    #[test]
+    fn inconclusive_results_ignored() {
+        let mut tracker = NatStatusTracker::with_threshold(2);
+        assert_eq!(tracker.add_response(&error(ResponseStatus::DialError)), None);
+        assert_eq!(tracker.add_response(&error(ResponseStatus::DialRefused)), None);
+        assert_eq!(tracker.add_response(&error(ResponseStatus::InternalError)), None);
+        assert_eq!(
+            tracker.add_response(&error(ResponseStatus::DialError)),
+            Some(NatStatus::Private)
+        );
+    }
+
+    #[test]
+    fn contradicting_results_reset_confidence() {
+        let addr: ::libp2p_core::Multiaddr = "/ip4/80.81.82.83/tcp/500".parse().unwrap();
+        let mut tracker = NatStatusTracker::with_threshold(2);
+        assert_eq!(tracker.add_response(&error(ResponseStatus::DialError)), None);
+        assert_eq!(tracker.add_response(&DialResponse::Ok(addr.clone())), None);
+        assert_eq!(tracker.add_response(&error(ResponseStatus::DialError)), None);
+        assert_eq!(tracker.status(), &NatStatus::Unknown);
+    }
+}
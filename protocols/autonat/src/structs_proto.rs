@@ -0,0 +1,1086 @@
+// This file is generated by rust-protobuf 2.0.2. Do not edit
+// @generated
+
+// https://github.com/Manishearth/rust-clippy/issues/702
+#![allow(unknown_lints)]
+#![allow(clippy)]
+
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+#![allow(box_pointers)]
+#![allow(dead_code)]
+#![allow(missing_docs)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+#![allow(trivial_casts)]
+#![allow(unsafe_code)]
+#![allow(unused_imports)]
+#![allow(unused_results)]
+
+use protobuf::Message as Message_imported_for_functions;
+use protobuf::ProtobufEnum as ProtobufEnum_imported_for_functions;
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message {
+    // message fields
+    field_type: ::std::option::Option<Message_MessageType>,
+    dial: ::protobuf::SingularPtrField<Message_Dial>,
+    dialResponse: ::protobuf::SingularPtrField<Message_DialResponse>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message {
+    pub fn new() -> Message {
+        ::std::default::Default::default()
+    }
+
+    // optional .autonat.pb.Message.MessageType type = 1;
+
+    pub fn clear_field_type(&mut self) {
+        self.field_type = ::std::option::Option::None;
+    }
+
+    pub fn has_field_type(&self) -> bool {
+        self.field_type.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_field_type(&mut self, v: Message_MessageType) {
+        self.field_type = ::std::option::Option::Some(v);
+    }
+
+    pub fn get_field_type(&self) -> Message_MessageType {
+        self.field_type.unwrap_or(Message_MessageType::DIAL)
+    }
+
+    // optional .autonat.pb.Message.Dial dial = 2;
+
+    pub fn clear_dial(&mut self) {
+        self.dial.clear();
+    }
+
+    pub fn has_dial(&self) -> bool {
+        self.dial.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_dial(&mut self, v: Message_Dial) {
+        self.dial = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_dial(&mut self) -> &mut Message_Dial {
+        if self.dial.is_none() {
+            self.dial.set_default();
+        }
+        self.dial.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_dial(&mut self) -> Message_Dial {
+        self.dial.take().unwrap_or_else(|| Message_Dial::new())
+    }
+
+    pub fn get_dial(&self) -> &Message_Dial {
+        self.dial.as_ref().unwrap_or_else(|| Message_Dial::default_instance())
+    }
+
+    // optional .autonat.pb.Message.DialResponse dialResponse = 3;
+
+    pub fn clear_dialResponse(&mut self) {
+        self.dialResponse.clear();
+    }
+
+    pub fn has_dialResponse(&self) -> bool {
+        self.dialResponse.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_dialResponse(&mut self, v: Message_DialResponse) {
+        self.dialResponse = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_dialResponse(&mut self) -> &mut Message_DialResponse {
+        if self.dialResponse.is_none() {
+            self.dialResponse.set_default();
+        }
+        self.dialResponse.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_dialResponse(&mut self) -> Message_DialResponse {
+        self.dialResponse.take().unwrap_or_else(|| Message_DialResponse::new())
+    }
+
+    pub fn get_dialResponse(&self) -> &Message_DialResponse {
+        self.dialResponse.as_ref().unwrap_or_else(|| Message_DialResponse::default_instance())
+    }
+}
+
+impl ::protobuf::Message for Message {
+    fn is_initialized(&self) -> bool {
+        for v in &self.dial {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        for v in &self.dialResponse {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_proto2_enum_with_unknown_fields_into(wire_type, is, &mut self.field_type, 1, &mut self.unknown_fields)?
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.dial)?;
+                },
+                3 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.dialResponse)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(v) = self.field_type {
+            my_size += ::protobuf::rt::enum_size(1, v);
+        }
+        if let Some(ref v) = self.dial.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        if let Some(ref v) = self.dialResponse.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(v) = self.field_type {
+            os.write_enum(1, v.value())?;
+        }
+        if let Some(ref v) = self.dial.as_ref() {
+            os.write_tag(2, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        if let Some(ref v) = self.dialResponse.as_ref() {
+            os.write_tag(3, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message {
+        Message::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeEnum<Message_MessageType>>(
+                    "type",
+                    |m: &Message| { &m.field_type },
+                    |m: &mut Message| { &mut m.field_type },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Message_Dial>>(
+                    "dial",
+                    |m: &Message| { &m.dial },
+                    |m: &mut Message| { &mut m.dial },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Message_DialResponse>>(
+                    "dialResponse",
+                    |m: &Message| { &m.dialResponse },
+                    |m: &mut Message| { &mut m.dialResponse },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message>(
+                    "Message",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message {
+        static mut instance: ::protobuf::lazy::Lazy<Message> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message,
+        };
+        unsafe {
+            instance.get(Message::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message {
+    fn clear(&mut self) {
+        self.clear_field_type();
+        self.clear_dial();
+        self.clear_dialResponse();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message_PeerInfo {
+    // message fields
+    id: ::protobuf::SingularField<::std::vec::Vec<u8>>,
+    addrs: ::protobuf::RepeatedField<::std::vec::Vec<u8>>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message_PeerInfo {
+    pub fn new() -> Message_PeerInfo {
+        ::std::default::Default::default()
+    }
+
+    // optional bytes id = 1;
+
+    pub fn clear_id(&mut self) {
+        self.id.clear();
+    }
+
+    pub fn has_id(&self) -> bool {
+        self.id.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_id(&mut self, v: ::std::vec::Vec<u8>) {
+        self.id = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_id(&mut self) -> &mut ::std::vec::Vec<u8> {
+        if self.id.is_none() {
+            self.id.set_default();
+        }
+        self.id.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_id(&mut self) -> ::std::vec::Vec<u8> {
+        self.id.take().unwrap_or_else(|| ::std::vec::Vec::new())
+    }
+
+    pub fn get_id(&self) -> &[u8] {
+        match self.id.as_ref() {
+            Some(v) => &v,
+            None => &[],
+        }
+    }
+
+    // repeated bytes addrs = 2;
+
+    pub fn clear_addrs(&mut self) {
+        self.addrs.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_addrs(&mut self, v: ::protobuf::RepeatedField<::std::vec::Vec<u8>>) {
+        self.addrs = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_addrs(&mut self) -> &mut ::protobuf::RepeatedField<::std::vec::Vec<u8>> {
+        &mut self.addrs
+    }
+
+    // Take field
+    pub fn take_addrs(&mut self) -> ::protobuf::RepeatedField<::std::vec::Vec<u8>> {
+        ::std::mem::replace(&mut self.addrs, ::protobuf::RepeatedField::new())
+    }
+
+    pub fn get_addrs(&self) -> &[::std::vec::Vec<u8>] {
+        &self.addrs
+    }
+}
+
+impl ::protobuf::Message for Message_PeerInfo {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.id)?;
+                },
+                2 => {
+                    ::protobuf::rt::read_repeated_bytes_into(wire_type, is, &mut self.addrs)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.id.as_ref() {
+            my_size += ::protobuf::rt::bytes_size(1, &v);
+        }
+        for value in &self.addrs {
+            my_size += ::protobuf::rt::bytes_size(2, &value);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.id.as_ref() {
+            os.write_bytes(1, &v)?;
+        }
+        for v in &self.addrs {
+            os.write_bytes(2, &v)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message_PeerInfo {
+        Message_PeerInfo::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "id",
+                    |m: &Message_PeerInfo| { &m.id },
+                    |m: &mut Message_PeerInfo| { &mut m.id },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "addrs",
+                    |m: &Message_PeerInfo| { &m.addrs },
+                    |m: &mut Message_PeerInfo| { &mut m.addrs },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message_PeerInfo>(
+                    "Message_PeerInfo",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message_PeerInfo {
+        static mut instance: ::protobuf::lazy::Lazy<Message_PeerInfo> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message_PeerInfo,
+        };
+        unsafe {
+            instance.get(Message_PeerInfo::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message_PeerInfo {
+    fn clear(&mut self) {
+        self.clear_id();
+        self.clear_addrs();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message_PeerInfo {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_PeerInfo {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message_Dial {
+    // message fields
+    peer: ::protobuf::SingularPtrField<Message_PeerInfo>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message_Dial {
+    pub fn new() -> Message_Dial {
+        ::std::default::Default::default()
+    }
+
+    // optional .autonat.pb.Message.PeerInfo peer = 1;
+
+    pub fn clear_peer(&mut self) {
+        self.peer.clear();
+    }
+
+    pub fn has_peer(&self) -> bool {
+        self.peer.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_peer(&mut self, v: Message_PeerInfo) {
+        self.peer = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_peer(&mut self) -> &mut Message_PeerInfo {
+        if self.peer.is_none() {
+            self.peer.set_default();
+        }
+        self.peer.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_peer(&mut self) -> Message_PeerInfo {
+        self.peer.take().unwrap_or_else(|| Message_PeerInfo::new())
+    }
+
+    pub fn get_peer(&self) -> &Message_PeerInfo {
+        self.peer.as_ref().unwrap_or_else(|| Message_PeerInfo::default_instance())
+    }
+}
+
+impl ::protobuf::Message for Message_Dial {
+    fn is_initialized(&self) -> bool {
+        for v in &self.peer {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.peer)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.peer.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.peer.as_ref() {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message_Dial {
+        Message_Dial::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Message_PeerInfo>>(
+                    "peer",
+                    |m: &Message_Dial| { &m.peer },
+                    |m: &mut Message_Dial| { &mut m.peer },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message_Dial>(
+                    "Message_Dial",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message_Dial {
+        static mut instance: ::protobuf::lazy::Lazy<Message_Dial> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message_Dial,
+        };
+        unsafe {
+            instance.get(Message_Dial::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message_Dial {
+    fn clear(&mut self) {
+        self.clear_peer();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message_Dial {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_Dial {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message_DialResponse {
+    // message fields
+    status: ::std::option::Option<Message_ResponseStatus>,
+    statusText: ::protobuf::SingularField<::std::string::String>,
+    addr: ::protobuf::SingularField<::std::vec::Vec<u8>>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message_DialResponse {
+    pub fn new() -> Message_DialResponse {
+        ::std::default::Default::default()
+    }
+
+    // optional .autonat.pb.Message.ResponseStatus status = 1;
+
+    pub fn clear_status(&mut self) {
+        self.status = ::std::option::Option::None;
+    }
+
+    pub fn has_status(&self) -> bool {
+        self.status.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_status(&mut self, v: Message_ResponseStatus) {
+        self.status = ::std::option::Option::Some(v);
+    }
+
+    pub fn get_status(&self) -> Message_ResponseStatus {
+        self.status.unwrap_or(Message_ResponseStatus::OK)
+    }
+
+    // optional string statusText = 2;
+
+    pub fn clear_statusText(&mut self) {
+        self.statusText.clear();
+    }
+
+    pub fn has_statusText(&self) -> bool {
+        self.statusText.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_statusText(&mut self, v: ::std::string::String) {
+        self.statusText = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_statusText(&mut self) -> &mut ::std::string::String {
+        if self.statusText.is_none() {
+            self.statusText.set_default();
+        }
+        self.statusText.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_statusText(&mut self) -> ::std::string::String {
+        self.statusText.take().unwrap_or_else(|| ::std::string::String::new())
+    }
+
+    pub fn get_statusText(&self) -> &str {
+        match self.statusText.as_ref() {
+            Some(v) => &v,
+            None => "",
+        }
+    }
+
+    // optional bytes addr = 3;
+
+    pub fn clear_addr(&mut self) {
+        self.addr.clear();
+    }
+
+    pub fn has_addr(&self) -> bool {
+        self.addr.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_addr(&mut self, v: ::std::vec::Vec<u8>) {
+        self.addr = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_addr(&mut self) -> &mut ::std::vec::Vec<u8> {
+        if self.addr.is_none() {
+            self.addr.set_default();
+        }
+        self.addr.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_addr(&mut self) -> ::std::vec::Vec<u8> {
+        self.addr.take().unwrap_or_else(|| ::std::vec::Vec::new())
+    }
+
+    pub fn get_addr(&self) -> &[u8] {
+        match self.addr.as_ref() {
+            Some(v) => &v,
+            None => &[],
+        }
+    }
+}
+
+impl ::protobuf::Message for Message_DialResponse {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_proto2_enum_with_unknown_fields_into(wire_type, is, &mut self.status, 1, &mut self.unknown_fields)?
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_string_into(wire_type, is, &mut self.statusText)?;
+                },
+                3 => {
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.addr)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(v) = self.status {
+            my_size += ::protobuf::rt::enum_size(1, v);
+        }
+        if let Some(ref v) = self.statusText.as_ref() {
+            my_size += ::protobuf::rt::string_size(2, &v);
+        }
+        if let Some(ref v) = self.addr.as_ref() {
+            my_size += ::protobuf::rt::bytes_size(3, &v);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(v) = self.status {
+            os.write_enum(1, v.value())?;
+        }
+        if let Some(ref v) = self.statusText.as_ref() {
+            os.write_string(2, &v)?;
+        }
+        if let Some(ref v) = self.addr.as_ref() {
+            os.write_bytes(3, &v)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message_DialResponse {
+        Message_DialResponse::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeEnum<Message_ResponseStatus>>(
+                    "status",
+                    |m: &Message_DialResponse| { &m.status },
+                    |m: &mut Message_DialResponse| { &mut m.status },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                    "statusText",
+                    |m: &Message_DialResponse| { &m.statusText },
+                    |m: &mut Message_DialResponse| { &mut m.statusText },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "addr",
+                    |m: &Message_DialResponse| { &m.addr },
+                    |m: &mut Message_DialResponse| { &mut m.addr },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message_DialResponse>(
+                    "Message_DialResponse",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message_DialResponse {
+        static mut instance: ::protobuf::lazy::Lazy<Message_DialResponse> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message_DialResponse,
+        };
+        unsafe {
+            instance.get(Message_DialResponse::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message_DialResponse {
+    fn clear(&mut self) {
+        self.clear_status();
+        self.clear_statusText();
+        self.clear_addr();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message_DialResponse {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_DialResponse {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+pub enum Message_MessageType {
+    DIAL = 0,
+    DIAL_RESPONSE = 1,
+}
+
+impl ::protobuf::ProtobufEnum for Message_MessageType {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<Message_MessageType> {
+        match value {
+            0 => ::std::option::Option::Some(Message_MessageType::DIAL),
+            1 => ::std::option::Option::Some(Message_MessageType::DIAL_RESPONSE),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [Message_MessageType] = &[
+            Message_MessageType::DIAL,
+            Message_MessageType::DIAL_RESPONSE,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::EnumDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::EnumDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                ::protobuf::reflect::EnumDescriptor::new("Message_MessageType", file_descriptor_proto())
+            })
+        }
+    }
+}
+
+impl ::std::marker::Copy for Message_MessageType {
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_MessageType {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Enum(self.descriptor())
+    }
+}
+
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+pub enum Message_ResponseStatus {
+    OK = 0,
+    E_DIAL_ERROR = 100,
+    E_DIAL_REFUSED = 101,
+    E_BAD_REQUEST = 200,
+    E_INTERNAL_ERROR = 300,
+}
+
+impl ::protobuf::ProtobufEnum for Message_ResponseStatus {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<Message_ResponseStatus> {
+        match value {
+            0 => ::std::option::Option::Some(Message_ResponseStatus::OK),
+            100 => ::std::option::Option::Some(Message_ResponseStatus::E_DIAL_ERROR),
+            101 => ::std::option::Option::Some(Message_ResponseStatus::E_DIAL_REFUSED),
+            200 => ::std::option::Option::Some(Message_ResponseStatus::E_BAD_REQUEST),
+            300 => ::std::option::Option::Some(Message_ResponseStatus::E_INTERNAL_ERROR),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [Message_ResponseStatus] = &[
+            Message_ResponseStatus::OK,
+            Message_ResponseStatus::E_DIAL_ERROR,
+            Message_ResponseStatus::E_DIAL_REFUSED,
+            Message_ResponseStatus::E_BAD_REQUEST,
+            Message_ResponseStatus::E_INTERNAL_ERROR,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::EnumDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::EnumDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                ::protobuf::reflect::EnumDescriptor::new("Message_ResponseStatus", file_descriptor_proto())
+            })
+        }
+    }
+}
+
+impl ::std::marker::Copy for Message_ResponseStatus {
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_ResponseStatus {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Enum(self.descriptor())
+    }
+}
+
+static file_descriptor_proto_data: &'static [u8] = b"\
+    \n\rstructs.proto\x12\nautonat.pb\"\xb5\x04\n\x07Message\x123\n\x04type\
+    \x18\x01\x20\x01(\x0e2\x1f.autonat.pb.Message.MessageTypeR\x04type\x12,\
+    \n\x04dial\x18\x02\x20\x01(\x0b2\x18.autonat.pb.Message.DialR\x04dial\
+    \x12D\n\x0cdialResponse\x18\x03\x20\x01(\x0b2\x20.autonat.pb.Message.Dia\
+    lResponseR\x0cdialResponse\x1a0\n\x08PeerInfo\x12\x0e\n\x02id\x18\x01\
+    \x20\x01(\x0cR\x02id\x12\x14\n\x05addrs\x18\x02\x20\x03(\x0cR\x05addrs\
+    \x1a8\n\x04Dial\x120\n\x04peer\x18\x01\x20\x01(\x0b2\x1c.autonat.pb.Mess\
+    age.PeerInfoR\x04peer\x1a~\n\x0cDialResponse\x12:\n\x06status\x18\x01\
+    \x20\x01(\x0e2\".autonat.pb.Message.ResponseStatusR\x06status\x12\x1e\n\
+    \nstatusText\x18\x02\x20\x01(\tR\nstatusText\x12\x12\n\x04addr\x18\x03\
+    \x20\x01(\x0cR\x04addr\"*\n\x0bMessageType\x12\x08\n\x04DIAL\x10\0\x12\
+    \x11\n\rDIAL_RESPONSE\x10\x01\"i\n\x0eResponseStatus\x12\x06\n\x02OK\x10\
+    \0\x12\x10\n\x0cE_DIAL_ERROR\x10d\x12\x12\n\x0eE_DIAL_REFUSED\x10e\x12\
+    \x12\n\rE_BAD_REQUEST\x10\xc8\x01\x12\x15\n\x10E_INTERNAL_ERROR\x10\xac\
+    \x02
+";
+
+static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
+    lock: ::protobuf::lazy::ONCE_INIT,
+    ptr: 0 as *const ::protobuf::descriptor::FileDescriptorProto,
+};
+
+fn parse_descriptor_proto() -> ::protobuf::descriptor::FileDescriptorProto {
+    ::protobuf::parse_from_bytes(file_descriptor_proto_data).unwrap()
+}
+
+pub fn file_descriptor_proto() -> &'static ::protobuf::descriptor::FileDescriptorProto {
+    unsafe {
+        file_descriptor_proto_lazy.get(|| {
+            parse_descriptor_proto()
+        })
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use blockstore::Blockstore;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use handler::BitswapHandler;
+use ledger::Ledger;
+use libp2p_core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::nodes::swarm::ConnectedPoint;
+use libp2p_core::resource_manager::ResourceManager;
+use libp2p_core::upgrade::MessageLimits;
+use libp2p_core::PeerId;
+use multihash::Multihash;
+use protocol::{block_id, BitswapMessage, WantlistEntry};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::fmt;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Event generated by the `Bitswap` behaviour.
+#[derive(Debug, Clone)]
+pub enum BitswapEvent {
+    /// We received a block we wanted. It has been stored in the blockstore and removed from our
+    /// want-list.
+    BlockReceived {
+        /// The peer that sent the block.
+        peer_id: PeerId,
+        /// Identifier of the block.
+        block_id: Multihash,
+        /// Data of the block.
+        data: Vec<u8>,
+    },
+    /// We sent a block to a peer that wanted it.
+    BlockSent {
+        /// The peer that received the block.
+        peer_id: PeerId,
+        /// Identifier of the block.
+        block_id: Multihash,
+    },
+}
+
+/// `NetworkBehaviour` that exchanges blocks with the nodes we are connected to.
+///
+/// We send our want-list to every node we are connected to, and send the blocks of our
+/// blockstore to the nodes that want them. The exchanges with each node are recorded in a
+/// `Ledger`.
+///
+/// > **Note**: The behaviour doesn't look for the nodes that have a block. Connect to them
+/// >           through other means, for example the DHT.
+pub struct Bitswap<TSubstream, TBlockstore> {
+    /// Storage of the blocks.
+    blockstore: Arc<TBlockstore>,
+    /// Blocks we want, with their priority.
+    wantlist: FnvHashMap<Multihash, i32>,
+    /// Ledger of each node we are connected to.
+    ledgers: FnvHashMap<PeerId, Ledger>,
+    /// Actions to return from `poll`.
+    actions: VecDeque<NetworkBehaviourAction<BitswapMessage, BitswapEvent>>,
+    /// Limits given to the handlers.
+    limits: MessageLimits,
+    /// Manager in which the memory of the received blocks is reserved, if any.
+    resource_manager: Option<ResourceManager>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream, TBlockstore> Bitswap<TSubstream, TBlockstore>
+where
+    TBlockstore: Blockstore,
+{
+    /// Creates a new `Bitswap` behaviour that stores the blocks in `blockstore`.
+    pub fn new(blockstore: Arc<TBlockstore>) -> Self {
+        Bitswap {
+            blockstore,
+            wantlist: FnvHashMap::default(),
+            ledgers: FnvHashMap::default(),
+            actions: VecDeque::new(),
+            limits: MessageLimits::new(),
+            resource_manager: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Passes `limits` to the handlers of the connections opened afterwards.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+
+    /// Reserves the memory of the blocks received from the remotes in `manager`. See
+    /// `BitswapHandler::with_resource_manager`.
+    #[inline]
+    pub fn with_resource_manager(mut self, manager: ResourceManager) -> Self {
+        self.resource_manager = Some(manager);
+        self
+    }
+
+    /// Returns the blockstore.
+    #[inline]
+    pub fn blockstore(&self) -> &Arc<TBlockstore> {
+        &self.blockstore
+    }
+
+    /// Returns the blocks we want, by decreasing priority.
+    pub fn wantlist(&self) -> Vec<(Multihash, i32)> {
+        let mut list = self.wantlist
+            .iter()
+            .map(|(id, priority)| (id.clone(), *priority))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.1.cmp(&a.1));
+        list
+    }
+
+    /// Returns the ledger of a node we are connected to.
+    #[inline]
+    pub fn ledger(&self, peer_id: &PeerId) -> Option<&Ledger> {
+        self.ledgers.get(peer_id)
+    }
+
+    /// Adds a block to our want-list and asks the nodes we are connected to for it.
+    ///
+    /// Does nothing if the block is already in the blockstore.
+    pub fn want_block(&mut self, block_id: Multihash, priority: i32) {
+        if self.blockstore.has(&block_id) {
+            return;
+        }
+
+        self.wantlist.insert(block_id.clone(), priority);
+        self.broadcast_wantlist_entry(WantlistEntry { block_id, priority, cancel: false }, None);
+    }
+
+    /// Removes a block from our want-list and notifies the nodes we are connected to.
+    pub fn cancel_block(&mut self, block_id: &Multihash) {
+        if self.wantlist.remove(block_id).is_some() {
+            let entry = WantlistEntry { block_id: block_id.clone(), priority: 0, cancel: true };
+            self.broadcast_wantlist_entry(entry, None);
+        }
+    }
+
+    /// Stores a block in the blockstore, and sends it to the nodes that want it. Returns the
+    /// identifier of the block.
+    pub fn add_block(&mut self, data: Vec<u8>) -> Multihash {
+        let id = block_id(&data);
+        self.blockstore.put(id.clone(), data.clone());
+        self.cancel_block(&id);
+
+        let peers = self.ledgers
+            .iter()
+            .filter(|&(_, ledger)| ledger.wants(&id))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect::<Vec<_>>();
+        for peer_id in peers {
+            self.send_block(peer_id, id.clone(), data.clone());
+        }
+
+        id
+    }
+
+    /// Sends a want-list entry to all the nodes we are connected to, except `except`.
+    fn broadcast_wantlist_entry(&mut self, entry: WantlistEntry, except: Option<&PeerId>) {
+        for peer_id in self.ledgers.keys() {
+            if Some(peer_id) == except {
+                continue;
+            }
+
+            self.actions.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer_id.clone(),
+                event: BitswapMessage {
+                    wantlist: vec![entry.clone()],
+                    full: false,
+                    blocks: Vec::new(),
+                },
+            });
+        }
+    }
+
+    /// Sends a block to a node and records it in its ledger.
+    fn send_block(&mut self, peer_id: PeerId, block_id: Multihash, data: Vec<u8>) {
+        if let Some(ledger) = self.ledgers.get_mut(&peer_id) {
+            ledger.block_sent(&block_id, data.len());
+        }
+
+        self.actions.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id: peer_id.clone(),
+            event: BitswapMessage {
+                wantlist: Vec::new(),
+                full: false,
+                blocks: vec![data],
+            },
+        });
+        self.actions.push_back(NetworkBehaviourAction::GenerateEvent(BitswapEvent::BlockSent {
+            peer_id,
+            block_id,
+        }));
+    }
+}
+
+impl<TSubstream, TBlockstore> fmt::Debug for Bitswap<TSubstream, TBlockstore> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Bitswap")
+            .field("wantlist", &self.wantlist.len())
+            .field("ledgers", &self.ledgers)
+            .finish()
+    }
+}
+
+impl<TSubstream, TBlockstore> NetworkBehaviour for Bitswap<TSubstream, TBlockstore>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+    TBlockstore: Blockstore,
+{
+    type ConnectionHandler = BitswapHandler<TSubstream>;
+    type OutEvent = BitswapEvent;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        let handler = BitswapHandler::new().with_message_limits(&self.limits);
+        match self.resource_manager {
+            Some(ref manager) => handler.with_resource_manager(manager.clone()),
+            None => handler,
+        }
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+        self.ledgers.insert(peer_id.clone(), Ledger::new());
+
+        if !self.wantlist.is_empty() {
+            let wantlist = self.wantlist
+                .iter()
+                .map(|(id, priority)| WantlistEntry { block_id: id.clone(), priority: *priority, cancel: false })
+                .collect();
+            self.actions.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: BitswapMessage { wantlist, full: true, blocks: Vec::new() },
+            });
+        }
+    }
+
+    #[inline]
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        self.ledgers.remove(peer_id);
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, message: BitswapMessage) {
+        // Blocks sent by the remote.
+        for data in message.blocks {
+            let id = block_id(&data);
+            if let Some(ledger) = self.ledgers.get_mut(&peer_id) {
+                ledger.block_received(data.len());
+            }
+
+            if self.wantlist.remove(&id).is_none() {
+                debug!("Ignoring unwanted block {:?} sent by {:?}", id, peer_id);
+                continue;
+            }
+
+            self.blockstore.put(id.clone(), data.clone());
+            let cancel = WantlistEntry { block_id: id.clone(), priority: 0, cancel: true };
+            self.broadcast_wantlist_entry(cancel, Some(&peer_id));
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(BitswapEvent::BlockReceived {
+                peer_id: peer_id.clone(),
+                block_id: id,
+                data,
+            }));
+        }
+
+        // Want-list of the remote. We answer with the blocks we have, by decreasing priority.
+        let blockstore = &self.blockstore;
+        let available = match self.ledgers.get_mut(&peer_id) {
+            Some(ledger) => {
+                ledger.update_wantlist(message.wantlist, message.full);
+                ledger.wantlist()
+                    .into_iter()
+                    .filter_map(|(id, _)| blockstore.get(&id).map(|data| (id, data)))
+                    .collect::<Vec<_>>()
+            },
+            None => return,
+        };
+
+        for (id, data) in available {
+            self.send_block(peer_id.clone(), id, data);
+        }
+    }
+
+    fn poll(&mut self, _: &mut PollParameters) -> Async<NetworkBehaviourAction<BitswapMessage, BitswapEvent>> {
+        match self.actions.pop_front() {
+            Some(action) => Async::Ready(action),
+            None => Async::NotReady,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockstore::MemoryBlockstore;
+    use libp2p_core::PublicKey;
+    use std::io::Cursor;
+
+    type TestBitswap = Bitswap<Cursor<Vec<u8>>, MemoryBlockstore>;
+
+    fn connected_point() -> ConnectedPoint {
+        ConnectedPoint::Dialer { address: "/ip4/1.2.3.4/tcp/5".parse().unwrap() }
+    }
+
+    #[test]
+    fn blocks_exchanged() {
+        let peer_a = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let peer_b = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+
+        let mut provider = TestBitswap::new(Arc::new(MemoryBlockstore::new()));
+        let mut requester = TestBitswap::new(Arc::new(MemoryBlockstore::new()));
+        let id = provider.add_block(b"hello world".to_vec());
+        requester.want_block(id.clone(), 1);
+
+        // The requester sends its want-list upon connecting.
+        provider.inject_connected(peer_b.clone(), connected_point());
+        requester.inject_connected(peer_a.clone(), connected_point());
+        let want = match requester.actions.pop_front() {
+            Some(NetworkBehaviourAction::SendEvent { event, .. }) => event,
+            _ => panic!(),
+        };
+        assert!(want.full);
+
+        // The provider answers with the block.
+        provider.inject_node_event(peer_b.clone(), want);
+        let block = match provider.actions.pop_front() {
+            Some(NetworkBehaviourAction::SendEvent { event, .. }) => event,
+            _ => panic!(),
+        };
+        assert_eq!(provider.ledger(&peer_b).unwrap().blocks_sent(), 1);
+
+        requester.inject_node_event(peer_a.clone(), block);
+        match requester.actions.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(BitswapEvent::BlockReceived { block_id, data, .. })) => {
+                assert_eq!(block_id, id);
+                assert_eq!(data, b"hello world".to_vec());
+            },
+            _ => panic!(),
+        }
+        assert!(requester.wantlist().is_empty());
+        assert!(requester.blockstore().has(&id));
+        assert_eq!(requester.ledger(&peer_a).unwrap().bytes_received(), 11);
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Storage of the blocks exchanged by the `Bitswap` behaviour.
+
+use fnv::FnvHashMap;
+use multihash::Multihash;
+use parking_lot::Mutex;
+
+/// Storage of blocks, indexed by their identifier.
+///
+/// The methods take `&self` so that the store can be shared between the `Bitswap` behaviour and
+/// the rest of the application.
+pub trait Blockstore {
+    /// Returns the data of a block, if it is stored.
+    fn get(&self, block_id: &Multihash) -> Option<Vec<u8>>;
+
+    /// Returns true if a block is stored.
+    #[inline]
+    fn has(&self, block_id: &Multihash) -> bool {
+        self.get(block_id).is_some()
+    }
+
+    /// Stores a block. Replaces the previous data if the block was already stored.
+    fn put(&self, block_id: Multihash, data: Vec<u8>);
+
+    /// Removes a block. Returns false if it wasn't stored.
+    fn remove(&self, block_id: &Multihash) -> bool;
+}
+
+/// Blockstore that keeps the blocks in memory.
+#[derive(Debug, Default)]
+pub struct MemoryBlockstore {
+    blocks: Mutex<FnvHashMap<Multihash, Vec<u8>>>,
+}
+
+impl MemoryBlockstore {
+    /// Creates an empty store.
+    #[inline]
+    pub fn new() -> MemoryBlockstore {
+        MemoryBlockstore::default()
+    }
+
+    /// Returns the number of blocks stored.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.blocks.lock().len()
+    }
+}
+
+impl Blockstore for MemoryBlockstore {
+    #[inline]
+    fn get(&self, block_id: &Multihash) -> Option<Vec<u8>> {
+        self.blocks.lock().get(block_id).cloned()
+    }
+
+    #[inline]
+    fn has(&self, block_id: &Multihash) -> bool {
+        self.blocks.lock().contains_key(block_id)
+    }
+
+    #[inline]
+    fn put(&self, block_id: Multihash, data: Vec<u8>) {
+        self.blocks.lock().insert(block_id, data);
+    }
+
+    #[inline]
+    fn remove(&self, block_id: &Multihash) -> bool {
+        self.blocks.lock().remove(block_id).is_some()
+    }
+}
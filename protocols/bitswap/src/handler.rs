@@ -0,0 +1,211 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use futures::prelude::*;
+use libp2p_core::nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use libp2p_core::nodes::handled_node::NodeHandlerEndpoint;
+use libp2p_core::resource_manager::{MemoryPermit, ResourceManager};
+use libp2p_core::upgrade::MessageLimits;
+use protocol::{BitswapMessage, BitswapProtocol};
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::marker::PhantomData;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Connection handler of the `Bitswap` behaviour.
+///
+/// Opens a substream for each message to send, and reports the messages sent by the remote. The
+/// connection is kept alive as long as the handler hasn't been shut down.
+pub struct BitswapHandler<TSubstream> {
+    /// Messages waiting for an outbound substream to be requested.
+    pending_send: VecDeque<BitswapMessage>,
+    /// Messages received from the remote, to report to the behaviour, with the memory reserved
+    /// for their blocks.
+    received: VecDeque<(BitswapMessage, Option<MemoryPermit>)>,
+    /// True if `shutdown()` has been called.
+    shutting_down: bool,
+    /// Limits passed to the inbound substreams.
+    limits: MessageLimits,
+    /// Manager in which the memory of the received blocks is reserved, if any.
+    resource_manager: Option<ResourceManager>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> BitswapHandler<TSubstream> {
+    /// Builds a new `BitswapHandler`, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        BitswapHandler {
+            pending_send: VecDeque::new(),
+            received: VecDeque::new(),
+            shutting_down: false,
+            limits: MessageLimits::new(),
+            resource_manager: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Reserves the memory of the blocks received from the remote in `manager`, under the
+    /// `/ipfs/bitswap/1.0.0` protocol, until they are reported to the behaviour. The messages
+    /// whose blocks don't fit in the memory budget are dropped.
+    #[inline]
+    pub fn with_resource_manager(mut self, manager: ResourceManager) -> Self {
+        self.resource_manager = Some(manager);
+        self
+    }
+
+    /// Replaces the limits applied to the messages of the remote.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+impl<TSubstream> Clone for BitswapHandler<TSubstream> {
+    #[inline]
+    fn clone(&self) -> Self {
+        let handler = BitswapHandler::new().with_message_limits(&self.limits);
+        match self.resource_manager {
+            Some(ref manager) => handler.with_resource_manager(manager.clone()),
+            None => handler,
+        }
+    }
+}
+
+impl<TSubstream> ConnectionHandler for BitswapHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type InEvent = BitswapMessage;
+    type OutEvent = BitswapMessage;
+    type Substream = TSubstream;
+    type Protocol = BitswapProtocol;
+    type OutboundOpenInfo = ();
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        BitswapProtocol::Inbound { limits: self.limits.clone() }
+    }
+
+    fn inject_fully_negotiated(&mut self, output: Option<BitswapMessage>, _: NodeHandlerEndpoint<()>) {
+        let message = match output {
+            Some(message) => message,
+            None => return,
+        };
+
+        let permit = match self.resource_manager {
+            Some(ref manager) => {
+                let size = message.blocks.iter().map(|block| block.len()).sum();
+                match manager.reserve_memory(Bytes::from("/ipfs/bitswap/1.0.0"), size) {
+                    Ok(permit) => Some(permit),
+                    Err(err) => {
+                        debug!("Dropping bitswap message: {}", err);
+                        return;
+                    },
+                }
+            },
+            None => None,
+        };
+
+        self.received.push_back((message, permit));
+    }
+
+    #[inline]
+    fn inject_event(&mut self, message: BitswapMessage) {
+        if !message.is_empty() {
+            self.pending_send.push_back(message);
+        }
+    }
+
+    #[inline]
+    fn inject_dial_upgrade_error(&mut self, _: (), error: &IoError) {
+        debug!("Failed to send bitswap message: {:?}", error);
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        KeepAlive::Forever
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, (), BitswapMessage>>, IoError> {
+        // Dropping the permit releases the memory once the message is handed to the behaviour.
+        if let Some((message, _)) = self.received.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(message))));
+        }
+
+        if self.shutting_down {
+            return Ok(Async::Ready(None));
+        }
+
+        if let Some(message) = self.pending_send.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                upgrade: BitswapProtocol::Outbound(message),
+                info: (),
+            })));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::resource_manager::ResourceLimits;
+    use std::io::Cursor;
+
+    #[test]
+    fn messages_over_memory_budget_dropped() {
+        let manager = ResourceManager::new(ResourceLimits::new().with_max_memory(100));
+        let mut handler = BitswapHandler::<Cursor<Vec<u8>>>::new()
+            .with_resource_manager(manager.clone());
+
+        let small = BitswapMessage { blocks: vec![vec![1; 60]], ..Default::default() };
+        let large = BitswapMessage { blocks: vec![vec![2; 60], vec![3; 60]], ..Default::default() };
+        handler.inject_fully_negotiated(Some(small.clone()), NodeHandlerEndpoint::Listener);
+        assert_eq!(manager.stats().memory, 60);
+        handler.inject_fully_negotiated(Some(large), NodeHandlerEndpoint::Listener);
+        assert_eq!(manager.stats().memory, 60);
+
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(message)))) => {
+                assert_eq!(message, small)
+            },
+            _ => panic!(),
+        }
+        assert_eq!(manager.stats().memory, 0);
+        match handler.poll() {
+            Ok(Async::NotReady) => {},
+            _ => panic!(),
+        }
+    }
+}
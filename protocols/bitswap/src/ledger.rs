@@ -0,0 +1,150 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Accounting of the exchanges with each peer.
+
+use fnv::FnvHashMap;
+use multihash::Multihash;
+use protocol::WantlistEntry;
+
+/// What has been exchanged with a peer, and what it wants from us.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    /// Number of bytes of blocks sent to the peer.
+    bytes_sent: u64,
+    /// Number of bytes of blocks received from the peer.
+    bytes_received: u64,
+    /// Number of blocks sent to the peer.
+    blocks_sent: u64,
+    /// Number of blocks received from the peer.
+    blocks_received: u64,
+    /// Blocks wanted by the peer, with their priority.
+    wantlist: FnvHashMap<Multihash, i32>,
+}
+
+impl Ledger {
+    /// Creates an empty ledger.
+    #[inline]
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    /// Returns the number of bytes of blocks sent to the peer.
+    #[inline]
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of bytes of blocks received from the peer.
+    #[inline]
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns the number of blocks sent to the peer.
+    #[inline]
+    pub fn blocks_sent(&self) -> u64 {
+        self.blocks_sent
+    }
+
+    /// Returns the number of blocks received from the peer.
+    #[inline]
+    pub fn blocks_received(&self) -> u64 {
+        self.blocks_received
+    }
+
+    /// Returns the ratio between the bytes we sent and the bytes we received. A high value means
+    /// that the peer owes us.
+    #[inline]
+    pub fn debt_ratio(&self) -> f64 {
+        self.bytes_sent as f64 / (self.bytes_received as f64 + 1.0)
+    }
+
+    /// Returns true if the peer wants a block.
+    #[inline]
+    pub fn wants(&self, block_id: &Multihash) -> bool {
+        self.wantlist.contains_key(block_id)
+    }
+
+    /// Returns the blocks wanted by the peer, by decreasing priority.
+    pub fn wantlist(&self) -> Vec<(Multihash, i32)> {
+        let mut list = self.wantlist
+            .iter()
+            .map(|(id, priority)| (id.clone(), *priority))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.1.cmp(&a.1));
+        list
+    }
+
+    /// Applies the want-list of a message sent by the peer.
+    pub(crate) fn update_wantlist(&mut self, entries: Vec<WantlistEntry>, full: bool) {
+        if full {
+            self.wantlist.clear();
+        }
+
+        for entry in entries {
+            if entry.cancel {
+                self.wantlist.remove(&entry.block_id);
+            } else {
+                self.wantlist.insert(entry.block_id, entry.priority);
+            }
+        }
+    }
+
+    /// Records that we sent a block to the peer. The block is removed from its want-list.
+    pub(crate) fn block_sent(&mut self, block_id: &Multihash, len: usize) {
+        self.wantlist.remove(block_id);
+        self.bytes_sent += len as u64;
+        self.blocks_sent += 1;
+    }
+
+    /// Records that we received a block from the peer.
+    pub(crate) fn block_received(&mut self, len: usize) {
+        self.bytes_received += len as u64;
+        self.blocks_received += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::block_id;
+
+    #[test]
+    fn wantlist_updates() {
+        let mut ledger = Ledger::new();
+        let entry = |data: &[u8], priority, cancel| WantlistEntry { block_id: block_id(data), priority, cancel };
+
+        ledger.update_wantlist(vec![entry(b"a", 1, false), entry(b"b", 3, false)], false);
+        assert_eq!(ledger.wantlist(), vec![(block_id(b"b"), 3), (block_id(b"a"), 1)]);
+
+        ledger.update_wantlist(vec![entry(b"a", 1, true)], false);
+        assert!(!ledger.wants(&block_id(b"a")));
+
+        ledger.update_wantlist(vec![entry(b"c", 2, false)], true);
+        assert_eq!(ledger.wantlist(), vec![(block_id(b"c"), 2)]);
+
+        ledger.block_sent(&block_id(b"c"), 10);
+        assert!(ledger.wantlist().is_empty());
+        assert_eq!(ledger.bytes_sent(), 10);
+        assert_eq!(ledger.blocks_sent(), 1);
+    }
+}
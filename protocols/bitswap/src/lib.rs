@@ -0,0 +1,61 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Implementation of a Bitswap-style block-exchange protocol.
+//!
+//! Data is split into blocks, each identified by the SHA2-256 multihash of its content. Nodes
+//! send their want-list, the blocks they are looking for, to the nodes they are connected to,
+//! and answer the want-lists of the other nodes with the blocks they have. Every exchange with a
+//! node is recorded in its `Ledger`.
+//!
+//! # Usage
+//!
+//! Create a `Bitswap` behaviour with an implementation of the `Blockstore` trait, for example
+//! `MemoryBlockstore`, and pass it to the swarm.
+//!
+//! - Call `add_block` to store a block and make it available to the other nodes.
+//! - Call `want_block` to ask the nodes we are connected to for a block. The behaviour generates
+//!   a `BitswapEvent::BlockReceived` event once a node sends it to us.
+//! - Call `cancel_block` if a block is no longer wanted.
+
+extern crate bytes;
+extern crate fnv;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate multihash;
+extern crate parking_lot;
+extern crate protobuf;
+extern crate tokio_io;
+
+pub use self::behaviour::{Bitswap, BitswapEvent};
+pub use self::blockstore::{Blockstore, MemoryBlockstore};
+pub use self::handler::BitswapHandler;
+pub use self::ledger::Ledger;
+pub use self::protocol::{block_id, BitswapMessage, BitswapProtocol, WantlistEntry};
+
+mod behaviour;
+mod blockstore;
+mod handler;
+mod ledger;
+mod protocol;
+mod structs_proto;
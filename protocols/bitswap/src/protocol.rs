@@ -0,0 +1,190 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use futures::Future;
+use libp2p_core::upgrade::{self, MessageLimits};
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr};
+use multihash::{self, Hash, Multihash};
+use protobuf::Message as ProtobufMessage;
+use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
+use protobuf::RepeatedField;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use structs_proto;
+use tokio_io::{io, AsyncRead, AsyncWrite};
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/ipfs/bitswap/1.0.0";
+
+/// Returns the identifier of a block, which is the SHA2-256 multihash of its data.
+#[inline]
+pub fn block_id(data: &[u8]) -> Multihash {
+    multihash::encode(Hash::SHA2256, data).expect("SHA2-256 is always supported ; qed")
+}
+
+/// Entry of a want-list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WantlistEntry {
+    /// The block that is wanted.
+    pub block_id: Multihash,
+    /// Priority of the block. Blocks with a higher priority are sent first.
+    pub priority: i32,
+    /// If true, the block is no longer wanted.
+    pub cancel: bool,
+}
+
+/// Message exchanged by the block-exchange protocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitswapMessage {
+    /// Modifications of the want-list of the sender.
+    pub wantlist: Vec<WantlistEntry>,
+    /// If true, `wantlist` is the full want-list of the sender and replaces the previous one.
+    pub full: bool,
+    /// Blocks sent to the receiver.
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl BitswapMessage {
+    /// Returns true if the message doesn't contain anything.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.wantlist.is_empty() && self.blocks.is_empty() && !self.full
+    }
+
+    /// Encodes the message.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut wantlist = structs_proto::Message_Wantlist::new();
+        wantlist.set_full(self.full);
+        wantlist.set_entries(RepeatedField::from_vec(
+            self.wantlist
+                .into_iter()
+                .map(|entry| {
+                    let mut proto = structs_proto::Message_Wantlist_Entry::new();
+                    proto.set_block(entry.block_id.into_bytes());
+                    proto.set_priority(entry.priority);
+                    proto.set_cancel(entry.cancel);
+                    proto
+                })
+                .collect(),
+        ));
+
+        let mut msg = structs_proto::Message::new();
+        msg.set_wantlist(wantlist);
+        msg.set_blocks(RepeatedField::from_vec(self.blocks));
+        msg.write_to_bytes().expect("writing protobuf failed ; should never happen")
+    }
+
+    /// Decodes a message. Entries with an invalid block identifier are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BitswapMessage, IoError> {
+        let mut msg: structs_proto::Message = protobuf_parse_from_bytes(bytes)
+            .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+        let mut wantlist = msg.take_wantlist();
+        let entries = wantlist
+            .take_entries()
+            .into_iter()
+            .filter_map(|mut entry| {
+                let block_id = Multihash::from_bytes(entry.take_block()).ok()?;
+                Some(WantlistEntry {
+                    block_id,
+                    priority: if entry.has_priority() { entry.get_priority() } else { 1 },
+                    cancel: entry.get_cancel(),
+                })
+            })
+            .collect();
+
+        Ok(BitswapMessage {
+            wantlist: entries,
+            full: wantlist.get_full(),
+            blocks: msg.take_blocks().into_vec(),
+        })
+    }
+}
+
+/// Upgrade of the block-exchange protocol.
+///
+/// Each message is sent on its own substream. As a listener, reads the message sent by the
+/// remote. As a dialer, writes the message and closes the substream.
+#[derive(Debug, Clone)]
+pub enum BitswapProtocol {
+    /// Receives a message from the remote.
+    Inbound {
+        /// Bounds the size of the message sent by the remote.
+        limits: MessageLimits,
+    },
+    /// Sends a message to the remote.
+    Outbound(BitswapMessage),
+}
+
+impl<TSubstream> ConnectionUpgrade<TSubstream> for BitswapProtocol
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    /// The message received from the remote, or `None` if we sent a message.
+    type Output = Option<BitswapMessage>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    fn upgrade(self, socket: TSubstream, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        match self {
+            BitswapProtocol::Inbound { limits } => {
+                let future = upgrade::read_one(socket, limits.max_for(PROTOCOL_NAME))
+                    .from_err()
+                    .and_then(|(_, msg)| BitswapMessage::from_bytes(&msg).map(Some));
+                Box::new(future)
+            },
+            BitswapProtocol::Outbound(message) => {
+                let future = upgrade::write_one(socket, message.into_bytes())
+                    .and_then(io::shutdown)
+                    .map(|_| None);
+                Box::new(future)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let message = BitswapMessage {
+            wantlist: vec![
+                WantlistEntry { block_id: block_id(b"foo"), priority: 5, cancel: false },
+                WantlistEntry { block_id: block_id(b"bar"), priority: 1, cancel: true },
+            ],
+            full: true,
+            blocks: vec![b"hello".to_vec(), b"world".to_vec()],
+        };
+
+        let bytes = message.clone().into_bytes();
+        assert_eq!(BitswapMessage::from_bytes(&bytes).unwrap(), message);
+    }
+}
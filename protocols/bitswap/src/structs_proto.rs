@@ -0,0 +1,729 @@
+// This file is generated by rust-protobuf 2.0.2. Do not edit
+// @generated
+
+// https://github.com/Manishearth/rust-clippy/issues/702
+#![allow(unknown_lints)]
+#![allow(clippy)]
+
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+#![allow(box_pointers)]
+#![allow(dead_code)]
+#![allow(missing_docs)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+#![allow(trivial_casts)]
+#![allow(unsafe_code)]
+#![allow(unused_imports)]
+#![allow(unused_results)]
+
+use protobuf::Message as Message_imported_for_functions;
+use protobuf::ProtobufEnum as ProtobufEnum_imported_for_functions;
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message {
+    // message fields
+    wantlist: ::protobuf::SingularPtrField<Message_Wantlist>,
+    blocks: ::protobuf::RepeatedField<::std::vec::Vec<u8>>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message {
+    pub fn new() -> Message {
+        ::std::default::Default::default()
+    }
+
+    // optional .bitswap.pb.Message.Wantlist wantlist = 1;
+
+    pub fn clear_wantlist(&mut self) {
+        self.wantlist.clear();
+    }
+
+    pub fn has_wantlist(&self) -> bool {
+        self.wantlist.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_wantlist(&mut self, v: Message_Wantlist) {
+        self.wantlist = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_wantlist(&mut self) -> &mut Message_Wantlist {
+        if self.wantlist.is_none() {
+            self.wantlist.set_default();
+        }
+        self.wantlist.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_wantlist(&mut self) -> Message_Wantlist {
+        self.wantlist.take().unwrap_or_else(|| Message_Wantlist::new())
+    }
+
+    pub fn get_wantlist(&self) -> &Message_Wantlist {
+        self.wantlist.as_ref().unwrap_or_else(|| Message_Wantlist::default_instance())
+    }
+
+    // repeated bytes blocks = 2;
+
+    pub fn clear_blocks(&mut self) {
+        self.blocks.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_blocks(&mut self, v: ::protobuf::RepeatedField<::std::vec::Vec<u8>>) {
+        self.blocks = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_blocks(&mut self) -> &mut ::protobuf::RepeatedField<::std::vec::Vec<u8>> {
+        &mut self.blocks
+    }
+
+    // Take field
+    pub fn take_blocks(&mut self) -> ::protobuf::RepeatedField<::std::vec::Vec<u8>> {
+        ::std::mem::replace(&mut self.blocks, ::protobuf::RepeatedField::new())
+    }
+
+    pub fn get_blocks(&self) -> &[::std::vec::Vec<u8>] {
+        &self.blocks
+    }
+}
+
+impl ::protobuf::Message for Message {
+    fn is_initialized(&self) -> bool {
+        for v in &self.wantlist {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.wantlist)?;
+                },
+                2 => {
+                    ::protobuf::rt::read_repeated_bytes_into(wire_type, is, &mut self.blocks)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.wantlist.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        for value in &self.blocks {
+            my_size += ::protobuf::rt::bytes_size(2, &value);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.wantlist.as_ref() {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        for v in &self.blocks {
+            os.write_bytes(2, &v)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message {
+        Message::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Message_Wantlist>>(
+                    "wantlist",
+                    |m: &Message| { &m.wantlist },
+                    |m: &mut Message| { &mut m.wantlist },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "blocks",
+                    |m: &Message| { &m.blocks },
+                    |m: &mut Message| { &mut m.blocks },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message>(
+                    "Message",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message {
+        static mut instance: ::protobuf::lazy::Lazy<Message> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message,
+        };
+        unsafe {
+            instance.get(Message::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message {
+    fn clear(&mut self) {
+        self.clear_wantlist();
+        self.clear_blocks();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message_Wantlist {
+    // message fields
+    entries: ::protobuf::RepeatedField<Message_Wantlist_Entry>,
+    full: ::std::option::Option<bool>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message_Wantlist {
+    pub fn new() -> Message_Wantlist {
+        ::std::default::Default::default()
+    }
+
+    // repeated .bitswap.pb.Message.Wantlist.Entry entries = 1;
+
+    pub fn clear_entries(&mut self) {
+        self.entries.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_entries(&mut self, v: ::protobuf::RepeatedField<Message_Wantlist_Entry>) {
+        self.entries = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_entries(&mut self) -> &mut ::protobuf::RepeatedField<Message_Wantlist_Entry> {
+        &mut self.entries
+    }
+
+    // Take field
+    pub fn take_entries(&mut self) -> ::protobuf::RepeatedField<Message_Wantlist_Entry> {
+        ::std::mem::replace(&mut self.entries, ::protobuf::RepeatedField::new())
+    }
+
+    pub fn get_entries(&self) -> &[Message_Wantlist_Entry] {
+        &self.entries
+    }
+
+    // optional bool full = 2;
+
+    pub fn clear_full(&mut self) {
+        self.full = ::std::option::Option::None;
+    }
+
+    pub fn has_full(&self) -> bool {
+        self.full.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_full(&mut self, v: bool) {
+        self.full = ::std::option::Option::Some(v);
+    }
+
+    pub fn get_full(&self) -> bool {
+        self.full.unwrap_or(false)
+    }
+}
+
+impl ::protobuf::Message for Message_Wantlist {
+    fn is_initialized(&self) -> bool {
+        for v in &self.entries {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.entries)?;
+                },
+                2 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.full = ::std::option::Option::Some(tmp);
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        for value in &self.entries {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        if let Some(v) = self.full {
+            my_size += 2;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        for v in &self.entries {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        if let Some(v) = self.full {
+            os.write_bool(2, v)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message_Wantlist {
+        Message_Wantlist::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Message_Wantlist_Entry>>(
+                    "entries",
+                    |m: &Message_Wantlist| { &m.entries },
+                    |m: &mut Message_Wantlist| { &mut m.entries },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                    "full",
+                    |m: &Message_Wantlist| { &m.full },
+                    |m: &mut Message_Wantlist| { &mut m.full },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message_Wantlist>(
+                    "Message_Wantlist",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message_Wantlist {
+        static mut instance: ::protobuf::lazy::Lazy<Message_Wantlist> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message_Wantlist,
+        };
+        unsafe {
+            instance.get(Message_Wantlist::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message_Wantlist {
+    fn clear(&mut self) {
+        self.clear_entries();
+        self.clear_full();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message_Wantlist {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_Wantlist {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Message_Wantlist_Entry {
+    // message fields
+    block: ::protobuf::SingularField<::std::vec::Vec<u8>>,
+    priority: ::std::option::Option<i32>,
+    cancel: ::std::option::Option<bool>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+impl Message_Wantlist_Entry {
+    pub fn new() -> Message_Wantlist_Entry {
+        ::std::default::Default::default()
+    }
+
+    // optional bytes block = 1;
+
+    pub fn clear_block(&mut self) {
+        self.block.clear();
+    }
+
+    pub fn has_block(&self) -> bool {
+        self.block.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_block(&mut self, v: ::std::vec::Vec<u8>) {
+        self.block = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_block(&mut self) -> &mut ::std::vec::Vec<u8> {
+        if self.block.is_none() {
+            self.block.set_default();
+        }
+        self.block.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_block(&mut self) -> ::std::vec::Vec<u8> {
+        self.block.take().unwrap_or_else(|| ::std::vec::Vec::new())
+    }
+
+    pub fn get_block(&self) -> &[u8] {
+        match self.block.as_ref() {
+            Some(v) => &v,
+            None => &[],
+        }
+    }
+
+    // optional int32 priority = 2;
+
+    pub fn clear_priority(&mut self) {
+        self.priority = ::std::option::Option::None;
+    }
+
+    pub fn has_priority(&self) -> bool {
+        self.priority.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_priority(&mut self, v: i32) {
+        self.priority = ::std::option::Option::Some(v);
+    }
+
+    pub fn get_priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
+    }
+
+    // optional bool cancel = 3;
+
+    pub fn clear_cancel(&mut self) {
+        self.cancel = ::std::option::Option::None;
+    }
+
+    pub fn has_cancel(&self) -> bool {
+        self.cancel.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_cancel(&mut self, v: bool) {
+        self.cancel = ::std::option::Option::Some(v);
+    }
+
+    pub fn get_cancel(&self) -> bool {
+        self.cancel.unwrap_or(false)
+    }
+}
+
+impl ::protobuf::Message for Message_Wantlist_Entry {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.block)?;
+                },
+                2 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_int32()?;
+                    self.priority = ::std::option::Option::Some(tmp);
+                },
+                3 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.cancel = ::std::option::Option::Some(tmp);
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.block.as_ref() {
+            my_size += ::protobuf::rt::bytes_size(1, &v);
+        }
+        if let Some(v) = self.priority {
+            my_size += ::protobuf::rt::value_size(2, v, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if let Some(v) = self.cancel {
+            my_size += 2;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.block.as_ref() {
+            os.write_bytes(1, &v)?;
+        }
+        if let Some(v) = self.priority {
+            os.write_int32(2, v)?;
+        }
+        if let Some(v) = self.cancel {
+            os.write_bool(3, v)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Message_Wantlist_Entry {
+        Message_Wantlist_Entry::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "block",
+                    |m: &Message_Wantlist_Entry| { &m.block },
+                    |m: &mut Message_Wantlist_Entry| { &mut m.block },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "priority",
+                    |m: &Message_Wantlist_Entry| { &m.priority },
+                    |m: &mut Message_Wantlist_Entry| { &mut m.priority },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                    "cancel",
+                    |m: &Message_Wantlist_Entry| { &m.cancel },
+                    |m: &mut Message_Wantlist_Entry| { &mut m.cancel },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Message_Wantlist_Entry>(
+                    "Message_Wantlist_Entry",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Message_Wantlist_Entry {
+        static mut instance: ::protobuf::lazy::Lazy<Message_Wantlist_Entry> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Message_Wantlist_Entry,
+        };
+        unsafe {
+            instance.get(Message_Wantlist_Entry::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Message_Wantlist_Entry {
+    fn clear(&mut self) {
+        self.clear_block();
+        self.clear_priority();
+        self.clear_cancel();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Message_Wantlist_Entry {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Message_Wantlist_Entry {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+static file_descriptor_proto_data: &'static [u8] = b"\
+    \n\rstructs.proto\x12\nbitswap.pb\"\x8d\x02\n\x07Message\x128\n\x08wantl\
+    ist\x18\x01\x20\x01(\x0b2\x1c.bitswap.pb.Message.WantlistR\x08wantlist\
+    \x12\x16\n\x06blocks\x18\x02\x20\x03(\x0cR\x06blocks\x1a\xaf\x01\n\x08Wa\
+    ntlist\x12<\n\x07entries\x18\x01\x20\x03(\x0b2\".bitswap.pb.Message.Want\
+    list.EntryR\x07entries\x12\x12\n\x04full\x18\x02\x20\x01(\x08R\x04full\
+    \x1aQ\n\x05Entry\x12\x14\n\x05block\x18\x01\x20\x01(\x0cR\x05block\x12\
+    \x1a\n\x08priority\x18\x02\x20\x01(\x05R\x08priority\x12\x16\n\x06cancel\
+    \x18\x03\x20\x01(\x08R\x06cancel
+";
+
+static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
+    lock: ::protobuf::lazy::ONCE_INIT,
+    ptr: 0 as *const ::protobuf::descriptor::FileDescriptorProto,
+};
+
+fn parse_descriptor_proto() -> ::protobuf::descriptor::FileDescriptorProto {
+    ::protobuf::parse_from_bytes(file_descriptor_proto_data).unwrap()
+}
+
+pub fn file_descriptor_proto() -> &'static ::protobuf::descriptor::FileDescriptorProto {
+    unsafe {
+        file_descriptor_proto_lazy.get(|| {
+            parse_descriptor_proto()
+        })
+    }
+}
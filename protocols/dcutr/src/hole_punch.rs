@@ -0,0 +1,60 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::future::{self, Future};
+use libp2p_core::{Multiaddr, Transport};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+/// Dials all the given addresses at the same time with the given transport, and produces the
+/// first connection that succeeds alongside with its address.
+///
+/// This is meant to be called on both sides at the same time once the coordination of the
+/// `DcutrConfig` protocol is over, so that the packets sent by each side open the NAT of the
+/// other side.
+pub fn hole_punch<T>(
+    transport: T,
+    addresses: Vec<Multiaddr>,
+) -> Box<Future<Item = (T::Output, Multiaddr), Error = IoError> + Send>
+where
+    T: Transport + Clone + Send + 'static,
+    T::Dial: Send,
+    T::Output: Send,
+{
+    let dials = addresses
+        .into_iter()
+        .filter_map(|addr| {
+            let dial = transport.clone().dial(addr.clone()).ok()?;
+            Some(dial.map(move |out| (out, addr)))
+        })
+        .collect::<Vec<_>>();
+
+    if dials.is_empty() {
+        debug!("No address to hole punch");
+        let err = IoError::new(IoErrorKind::Other, "no supported address to dial");
+        return Box::new(future::err(err));
+    }
+
+    let future = future::select_ok(dials).map(|((out, addr), _)| {
+        debug!("Hole punching succeeded with {}", addr);
+        (out, addr)
+    });
+
+    Box::new(future)
+}
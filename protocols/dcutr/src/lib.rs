@@ -0,0 +1,54 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/libp2p/dcutr` protocol, which upgrades a relayed connection into a
+//! direct one.
+//!
+//! When two nodes behind NATs are connected through a relay, they can use this protocol over the
+//! relayed connection to coordinate dialing each other at the same time. If the NATs allow it,
+//! the packets sent by each side open a mapping in the NAT of the other side, and a direct
+//! connection is established. This is known as *hole punching*.
+//!
+//! # Usage
+//!
+//! Use the `DcutrConfig` upgrade on a substream of the relayed connection.
+//!
+//! - The node that opens the substream obtains a `DcutrInitiator`. `DcutrInitiator::connect`
+//!   exchanges the addresses of both nodes and waits until the right moment to dial.
+//! - The other node obtains the addresses of the initiator and a `DcutrResponder`.
+//!   `DcutrResponder::respond` sends our addresses and waits until the right moment to dial.
+//!
+//! Both sides then call `hole_punch` with the addresses of the other side.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate protobuf;
+extern crate tokio_io;
+extern crate tokio_timer;
+
+pub use self::hole_punch::hole_punch;
+pub use self::protocol::{DcutrConfig, DcutrInitiator, DcutrOutput, DcutrResponder};
+
+mod hole_punch;
+mod protocol;
+mod structs_proto;
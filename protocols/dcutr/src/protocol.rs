@@ -0,0 +1,287 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bytes::Bytes;
+use futures::{future, Future};
+use libp2p_core::upgrade::{self, MessageLimits};
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr};
+use protobuf::Message as ProtobufMessage;
+use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
+use protobuf::RepeatedField;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::time::Instant;
+use structs_proto;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/libp2p/dcutr";
+
+/// Configuration for an upgrade to the direct connection upgrade protocol.
+#[derive(Debug, Clone, Default)]
+pub struct DcutrConfig {
+    /// Bounds the size of the `CONNECT` and `SYNC` messages of the remote.
+    limits: MessageLimits,
+}
+
+impl DcutrConfig {
+    /// Builds a new configuration, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        DcutrConfig::default()
+    }
+
+    /// Replaces the limits applied to the messages of the remote.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+/// Output of the connection upgrade.
+pub enum DcutrOutput<T> {
+    /// We opened the substream and must start the coordination. Happens when we are the dialer.
+    Initiator {
+        /// Object used to coordinate the hole punching.
+        initiator: DcutrInitiator<T>,
+    },
+
+    /// The remote started the coordination. Happens when we are the listener.
+    Responder {
+        /// Addresses that the remote is observed at, which we must dial.
+        remote_addrs: Vec<Multiaddr>,
+        /// Object used to finish the coordination.
+        responder: DcutrResponder<T>,
+    },
+}
+
+/// Object used to coordinate the hole punching on the side of the dialer of the substream.
+pub struct DcutrInitiator<T> {
+    socket: T,
+    /// Maximum size of the messages of the remote.
+    max_message_size: usize,
+}
+
+impl<'a, T> DcutrInitiator<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'a,
+{
+    /// Sends our observed addresses to the remote, and measures the round-trip time until the
+    /// remote answers with its own addresses. Then sends a synchronization message.
+    ///
+    /// The returned future produces the addresses of the remote after waiting half of the
+    /// round-trip time, which is approximately when the remote receives the synchronization
+    /// message. The caller must then immediately dial these addresses, for example with
+    /// `hole_punch`, at the same time as the remote dials ours.
+    ///
+    /// > **Note**: The returned future must be polled from within a tokio runtime.
+    pub fn connect(self, local_addrs: Vec<Multiaddr>) -> Box<Future<Item = Vec<Multiaddr>, Error = IoError> + Send + 'a> {
+        debug!("Starting hole punching coordination");
+
+        let max_message_size = self.max_message_size;
+        let future = future::lazy(move || {
+            let start = Instant::now();
+            let connect = encode(structs_proto::HolePunch_Type::CONNECT, local_addrs);
+            upgrade::write_one(self.socket, connect)
+                .and_then(move |socket| upgrade::read_one(socket, max_message_size).from_err())
+                .and_then(move |(socket, msg)| {
+                    let remote_addrs = parse(&msg, structs_proto::HolePunch_Type::CONNECT)?;
+                    let rtt = start.elapsed();
+                    trace!("Hole punching round-trip time: {:?}", rtt);
+                    Ok((socket, remote_addrs, rtt))
+                })
+        })
+        .and_then(|(socket, remote_addrs, rtt)| {
+            upgrade::write_one(socket, encode(structs_proto::HolePunch_Type::SYNC, Vec::new()))
+                .map(move |_| (remote_addrs, rtt))
+        })
+        .and_then(|(remote_addrs, rtt)| {
+            Delay::new(Instant::now() + rtt / 2)
+                .map_err(|err| IoError::new(IoErrorKind::Other, err))
+                .map(move |()| remote_addrs)
+        });
+
+        Box::new(future) as Box<_>
+    }
+}
+
+/// Object used to coordinate the hole punching on the side of the listener of the substream.
+pub struct DcutrResponder<T> {
+    socket: T,
+    /// Maximum size of the messages of the remote.
+    max_message_size: usize,
+}
+
+impl<'a, T> DcutrResponder<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'a,
+{
+    /// Sends our observed addresses to the remote, then waits for the synchronization message.
+    ///
+    /// Once the returned future finishes, the caller must immediately dial the addresses of the
+    /// remote, for example with `hole_punch`.
+    pub fn respond(self, local_addrs: Vec<Multiaddr>) -> Box<Future<Item = (), Error = IoError> + Send + 'a> {
+        let max_message_size = self.max_message_size;
+        let connect = encode(structs_proto::HolePunch_Type::CONNECT, local_addrs);
+        let future = upgrade::write_one(self.socket, connect)
+            .and_then(move |socket| upgrade::read_one(socket, max_message_size).from_err())
+            .and_then(|(_, msg)| {
+                parse(&msg, structs_proto::HolePunch_Type::SYNC)?;
+                trace!("Received hole punching synchronization message");
+                Ok(())
+            });
+
+        Box::new(future) as Box<_>
+    }
+}
+
+impl<C> ConnectionUpgrade<C> for DcutrConfig
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+    type UpgradeIdentifier = ();
+    type Output = DcutrOutput<C>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    fn upgrade(self, socket: C, _: (), ty: Endpoint, _: &Multiaddr) -> Self::Future {
+        trace!("Upgrading connection as {:?}", ty);
+
+        let max_message_size = self.limits.max_for(PROTOCOL_NAME);
+        match ty {
+            Endpoint::Dialer => {
+                let output = DcutrOutput::Initiator {
+                    initiator: DcutrInitiator { socket, max_message_size },
+                };
+                Box::new(future::ok(output)) as Box<_>
+            }
+
+            Endpoint::Listener => {
+                let respond = move |socket: C, msg: Vec<u8>, ()| {
+                    let remote_addrs = parse(&msg, structs_proto::HolePunch_Type::CONNECT)?;
+                    let responder = DcutrResponder { socket, max_message_size };
+                    Ok::<_, IoError>(DcutrOutput::Responder { remote_addrs, responder })
+                };
+                let future = upgrade::read_respond(socket, max_message_size, (), respond);
+
+                Box::new(future) as Box<_>
+            }
+        }
+    }
+}
+
+// Builds the bytes of a message to send on the substream.
+fn encode(ty: structs_proto::HolePunch_Type, addrs: Vec<Multiaddr>) -> Vec<u8> {
+    let mut message = structs_proto::HolePunch::new();
+    message.set_field_type(ty);
+    message.set_ObsAddrs(RepeatedField::from_vec(
+        addrs.into_iter().map(|addr| addr.into_bytes()).collect(),
+    ));
+    message
+        .write_to_bytes()
+        .expect("writing protobuf failed ; should never happen")
+}
+
+// Parses a message received on the substream, which must be of type `expected`. Returns the
+// addresses it contains. Addresses that fail to parse are ignored.
+fn parse(msg: &[u8], expected: structs_proto::HolePunch_Type) -> Result<Vec<Multiaddr>, IoError> {
+    let mut message = protobuf_parse_from_bytes::<structs_proto::HolePunch>(msg)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    if message.get_field_type() != expected {
+        debug!("Expected hole punching message of type {:?}, got {:?}", expected,
+               message.get_field_type());
+        return Err(IoError::new(IoErrorKind::InvalidData, "unexpected message type"));
+    }
+
+    Ok(message
+        .take_ObsAddrs()
+        .into_iter()
+        .filter_map(|addr| Multiaddr::from_bytes(addr).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libp2p_tcp_transport;
+    extern crate tokio;
+
+    use self::libp2p_tcp_transport::TcpConfig;
+    use self::tokio::runtime::current_thread::Runtime;
+    use futures::{Future, Stream};
+    use libp2p_core::{Multiaddr, Transport};
+    use std::sync::mpsc;
+    use std::thread;
+    use {DcutrConfig, DcutrOutput};
+
+    #[test]
+    fn addresses_exchanged() {
+        let initiator_addr: Multiaddr = "/ip4/80.81.82.83/tcp/500".parse().unwrap();
+        let responder_addr: Multiaddr = "/ip4/90.91.92.93/tcp/600".parse().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+
+        let expected = initiator_addr.clone();
+        let to_send = responder_addr.clone();
+        let bg_thread = thread::spawn(move || {
+            let transport = TcpConfig::new().with_upgrade(DcutrConfig::new());
+
+            let (listener, addr) = transport
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+            tx.send(addr).unwrap();
+
+            let future = listener
+                .into_future()
+                .map_err(|(err, _)| err)
+                .and_then(|(client, _)| client.unwrap().0)
+                .and_then(move |output| match output {
+                    DcutrOutput::Responder { remote_addrs, responder } => {
+                        assert_eq!(remote_addrs, vec![expected]);
+                        responder.respond(vec![to_send])
+                    }
+                    _ => panic!(),
+                });
+
+            Runtime::new().unwrap().block_on(future).unwrap();
+        });
+
+        let transport = TcpConfig::new().with_upgrade(DcutrConfig::new());
+
+        let future = transport
+            .dial(rx.recv().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .and_then(move |output| match output {
+                DcutrOutput::Initiator { initiator } => initiator.connect(vec![initiator_addr]),
+                _ => panic!(),
+            });
+
+        let remote_addrs = Runtime::new().unwrap().block_on(future).unwrap();
+        assert_eq!(remote_addrs, vec![responder_addr]);
+        bg_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use libp2p_core::upgrade::MessageLimits;
+use signing::MessageSigner;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use trace::MessageTracer;
+use validation::{MessageValidator, ValidationMode};
+
+/// Configuration of the floodsub system.
+#[derive(Clone)]
+pub struct FloodSubConfig {
+    pub(crate) signer: Option<Arc<MessageSigner>>,
+    pub(crate) validation_mode: ValidationMode,
+    pub(crate) validator: Option<Arc<MessageValidator>>,
+    pub(crate) max_message_size: usize,
+    pub(crate) seen_ttl: Duration,
+    pub(crate) seen_capacity: usize,
+    pub(crate) tracer: Option<Arc<MessageTracer>>,
+}
+
+impl FloodSubConfig {
+    /// Builds a default configuration, where messages are not signed, and where only messages
+    /// with an invalid signature are rejected. Packets are limited to 1 MiB, and the
+    /// identifiers of the messages we have seen are remembered for 2 minutes, up to 100,000 of
+    /// them.
+    #[inline]
+    pub fn new() -> FloodSubConfig {
+        FloodSubConfig {
+            signer: None,
+            validation_mode: ValidationMode::default(),
+            validator: None,
+            max_message_size: 1024 * 1024,
+            seen_ttl: Duration::from_secs(120),
+            seen_capacity: 100_000,
+            tracer: None,
+        }
+    }
+
+    /// Signs the messages we publish with the given signer.
+    #[inline]
+    pub fn with_signer<S>(mut self, signer: S) -> Self
+    where
+        S: MessageSigner + 'static,
+    {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Sets how the signatures of the received messages are checked.
+    #[inline]
+    pub fn with_validation_mode(mut self, mode: ValidationMode) -> Self {
+        self.validation_mode = mode;
+        self
+    }
+
+    /// Passes each received message to the given validator before dispatching and propagating
+    /// it.
+    #[inline]
+    pub fn with_validator<V>(mut self, validator: V) -> Self
+    where
+        V: MessageValidator + 'static,
+    {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Sets the maximum size of a packet, in bytes. Packets received from a remote that are
+    /// larger close the connection, and messages we publish that would exceed it are dropped.
+    #[inline]
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Takes the maximum size of a packet from `limits`.
+    #[inline]
+    pub fn with_message_limits(self, limits: &MessageLimits) -> Self {
+        self.with_max_message_size(limits.max_for(b"/floodsub/1.0.0"))
+    }
+
+    /// Sets for how long and up to how many message identifiers are remembered in order to
+    /// detect duplicates. A message received again after having been forgotten is dispatched and
+    /// propagated again.
+    #[inline]
+    pub fn with_seen_cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.seen_ttl = ttl;
+        self.seen_capacity = capacity;
+        self
+    }
+
+    /// Reports the messages we publish, receive and forward to the given tracer.
+    #[inline]
+    pub fn with_tracer<T>(mut self, tracer: T) -> Self
+    where
+        T: MessageTracer + 'static,
+    {
+        self.tracer = Some(Arc::new(tracer));
+        self
+    }
+}
+
+impl Default for FloodSubConfig {
+    #[inline]
+    fn default() -> Self {
+        FloodSubConfig::new()
+    }
+}
+
+impl fmt::Debug for FloodSubConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FloodSubConfig")
+            .field("signed", &self.signer.is_some())
+            .field("validation_mode", &self.validation_mode)
+            .field("validator", &self.validator.is_some())
+            .field("max_message_size", &self.max_message_size)
+            .field("seen_ttl", &self.seen_ttl)
+            .field("seen_capacity", &self.seen_capacity)
+            .field("traced", &self.tracer.is_some())
+            .finish()
+    }
+}
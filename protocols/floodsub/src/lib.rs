@@ -29,15 +29,27 @@ extern crate log;
 extern crate multiaddr;
 extern crate parking_lot;
 extern crate protobuf;
+extern crate ring;
 extern crate smallvec;
 extern crate tokio_codec;
 extern crate tokio_io;
 extern crate unsigned_varint;
+extern crate untrusted;
 
+mod config;
 mod rpc_proto;
+mod seen_cache;
+mod signing;
 mod topic;
+mod trace;
+mod validation;
 
+pub use self::config::FloodSubConfig;
+pub use self::seen_cache::SeenCache;
+pub use self::signing::{Ed25519Signer, MessageSigner};
 pub use self::topic::{Topic, TopicBuilder, TopicHash};
+pub use self::trace::{trace_id, MemoryTracer, MessageTrace, MessageTracer, TraceEvent};
+pub use self::validation::{MessageValidator, ValidationMode};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use bytes::{Bytes, BytesMut};
@@ -69,16 +81,24 @@ pub struct FloodSubUpgrade {
 impl FloodSubUpgrade {
     /// Builds a new `FloodSubUpgrade`. Also returns a `FloodSubReceiver` that will stream incoming
     /// messages for the floodsub system.
+    #[inline]
     pub fn new(my_id: PeerId) -> (FloodSubUpgrade, FloodSubReceiver) {
+        FloodSubUpgrade::with_config(my_id, FloodSubConfig::new())
+    }
+
+    /// Same as `new`, but uses the given configuration for signing and validating messages.
+    pub fn with_config(my_id: PeerId, config: FloodSubConfig) -> (FloodSubUpgrade, FloodSubReceiver) {
         let (output_tx, output_rx) = mpsc::unbounded();
 
         let inner = Arc::new(Inner {
             peer_id: my_id.into_bytes(),
+            received: Mutex::new(SeenCache::new(config.seen_ttl, config.seen_capacity)),
+            config: config,
             output_tx: output_tx,
             remote_connections: RwLock::new(FnvHashMap::default()),
             subscribed_topics: RwLock::new(Vec::new()),
+            topic_senders: Mutex::new(FnvHashMap::default()),
             seq_no: AtomicUsize::new(0),
-            received: Mutex::new(FnvHashSet::default()),
         });
 
         let upgrade = FloodSubUpgrade { inner: inner };
@@ -136,7 +156,9 @@ where
             };
 
             // Split the socket into writing and reading parts.
-            let (floodsub_sink, floodsub_stream) = Framed::new(socket, codec::UviBytes::default())
+            let mut codec = codec::UviBytes::default();
+            codec.set_max_len(self.inner.config.max_message_size);
+            let (floodsub_sink, floodsub_stream) = Framed::new(socket, codec)
                 .sink_map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
                 .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
                 .split();
@@ -181,13 +203,15 @@ where
                             match input {
                                 Some((bytes, MessageSource::FromSocket)) => {
                                     // Received a packet from the remote.
-                                    let fut = match handle_packet_received(bytes, inner, &remote_addr) {
-                                        Ok(()) => {
-                                            future::ok(future::Loop::Continue((floodsub_sink, rest)))
+                                    match handle_packet_received(bytes, inner, &remote_addr) {
+                                        Ok(fut) => {
+                                            let fut = fut.map(move |()| {
+                                                future::Loop::Continue((floodsub_sink, rest))
+                                            });
+                                            Box::new(fut) as Box<Future<Item = _, Error = _> + Send>
                                         }
-                                        Err(err) => future::err(err),
-                                    };
-                                    Box::new(fut) as Box<_>
+                                        Err(err) => Box::new(future::err(err)) as Box<_>,
+                                    }
                                 }
 
                                 Some((bytes, MessageSource::FromChannel)) => {
@@ -233,6 +257,9 @@ struct Inner {
     // Our local peer ID multihash, to pass as the source.
     peer_id: Vec<u8>,
 
+    // Signing and validation of the messages.
+    config: FloodSubConfig,
+
     // Channel where to send the messages that should be dispatched to the user.
     output_tx: mpsc::UnboundedSender<Message>,
 
@@ -243,13 +270,15 @@ struct Inner {
     // erroneously receive.
     subscribed_topics: RwLock<Vec<Topic>>,
 
+    // Channels of the `FloodSubSubscription`s of each topic.
+    topic_senders: Mutex<FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<Message>>>>,
+
     // Sequence number for the messages we send.
     seq_no: AtomicUsize,
 
     // We keep track of the messages we received (in the format `(remote ID, seq_no)`) so that we
     // don't dispatch the same message twice if we receive it twice on the network.
-    // TODO: the `HashSet` will keep growing indefinitely :-/
-    received: Mutex<FnvHashSet<u64>>,
+    received: Mutex<SeenCache<u64>>,
 }
 
 struct RemoteInfo {
@@ -263,13 +292,14 @@ impl fmt::Debug for Inner {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Inner")
             .field("peer_id", &self.peer_id)
+            .field("config", &self.config)
             .field(
                 "num_remote_connections",
                 &self.remote_connections.read().len(),
             )
             .field("subscribed_topics", &*self.subscribed_topics.read())
             .field("seq_no", &self.seq_no)
-            .field("received", &self.received)
+            .field("received", &self.received.lock().len())
             .finish()
     }
 }
@@ -287,10 +317,22 @@ impl FloodSubController {
     /// likely receive it.
     ///
     /// It is not guaranteed that we receive every single message published on the network.
-    #[inline]
-    pub fn subscribe(&self, topic: &Topic) {
-        // This function exists for convenience.
+    ///
+    /// Returns a handle that produces the messages of this topic. The messages are also produced
+    /// by the `FloodSubReceiver`, which can be dropped if only the handles are used.
+    pub fn subscribe(&self, topic: &Topic) -> FloodSubSubscription {
+        let (tx, rx) = mpsc::unbounded();
+        self.inner.topic_senders.lock()
+            .entry(topic.hash().clone())
+            .or_insert_with(Vec::new)
+            .push(tx);
         self.subscribe_many(iter::once(topic));
+
+        FloodSubSubscription {
+            topic: topic.clone(),
+            receiver: rx,
+            controller: self.clone(),
+        }
     }
 
     /// Same as `subscribe`, but subscribes to multiple topics at once.
@@ -308,7 +350,8 @@ impl FloodSubController {
         self.sub_unsub_multi(topics.into_iter().map::<_, fn(_) -> _>(|t| (t, true)))
     }
 
-    /// Unsubscribe from a topic. We will no longer receive any message for this topic.
+    /// Unsubscribe from a topic. We will no longer receive any message for this topic, and the
+    /// `FloodSubSubscription`s of this topic end.
     ///
     /// If a message was sent to us before we are able to notify that we don't want messages
     /// anymore, then the message will be filtered out locally.
@@ -362,13 +405,16 @@ impl FloodSubController {
             proto.mut_subscriptions().push(subscription);
 
             if subscribe {
-                subscribed_topics.push(topic.clone());
+                if !subscribed_topics.iter().any(|t| t.hash() == topic.hash()) {
+                    subscribed_topics.push(topic.clone());
+                }
             } else {
-                subscribed_topics.retain(|t| t.hash() != topic.hash())
+                subscribed_topics.retain(|t| t.hash() != topic.hash());
+                self.inner.topic_senders.lock().remove(topic.hash());
             }
         }
 
-        self.broadcast(proto, |_| true);
+        self.broadcast(proto, None, |_| true);
     }
 
     /// Publishes a message on the network for the specified topic
@@ -383,6 +429,9 @@ impl FloodSubController {
     /// Since this results in a single packet sent to the remotes, it is preferable to use this
     /// method when publishing multiple messages at once rather than call `publish` multiple
     /// times.
+    ///
+    /// > **Note**: The message is dropped if the packet would exceed the maximum message size of
+    /// >           the configuration.
     pub fn publish_many<'a, I>(&self, topics: I, data: Vec<u8>)
     where
         I: IntoIterator<Item = &'a Topic>,
@@ -403,8 +452,6 @@ impl FloodSubController {
             seqno_bytes
         };
 
-        // TODO: should handle encryption/authentication of the message
-
         let mut msg = rpc_proto::Message::new();
         msg.set_data(data);
         msg.set_from(self.inner.peer_id.clone());
@@ -415,9 +462,23 @@ impl FloodSubController {
                 .map(|t| t.hash().clone().into_string())
                 .collect(),
         );
+        if let Some(ref signer) = self.inner.config.signer {
+            signing::sign_message(&mut msg, &**signer);
+        }
 
         let mut proto = rpc_proto::RPC::new();
         proto.mut_publish().push(msg);
+        let size = proto.compute_size() as usize;
+        if size > self.inner.config.max_message_size {
+            warn!("Dropping published message of {} bytes ; exceeds the maximum message size", size);
+            return;
+        }
+
+        let id = trace_id(&self.inner.peer_id, &seq_no_bytes);
+        if let Some(ref tracer) = self.inner.config.tracer {
+            let hashes = topics.iter().map(|t| t.hash().clone()).collect::<Vec<_>>();
+            tracer.published(&id, &hashes);
+        }
 
         // Insert into `received` so that we ignore the message if a remote sends it back to us.
         self.inner
@@ -425,14 +486,15 @@ impl FloodSubController {
             .lock()
             .insert(hash((self.inner.peer_id.clone(), seq_no_bytes)));
 
-        self.broadcast(proto, |r_top| {
+        self.broadcast(proto, Some(&id), |r_top| {
             topics.iter().any(|t| r_top.iter().any(|to| to == t.hash()))
         });
     }
 
     // Internal function that dispatches an `RPC` protobuf struct to all the connected remotes
-    // for which `filter` returns true.
-    fn broadcast<F>(&self, message: rpc_proto::RPC, mut filter: F)
+    // for which `filter` returns true. If `trace_id` is set, the message is reported as
+    // forwarded to the tracer.
+    fn broadcast<F>(&self, message: rpc_proto::RPC, trace_id: Option<&str>, mut filter: F)
     where
         F: FnMut(&FnvHashSet<TopicHash>) -> bool,
     {
@@ -456,7 +518,11 @@ impl FloodSubController {
 
             num_dispatched += 1;
             match remote.sender.unbounded_send(bytes.clone().into()) {
-                Ok(_) => (),
+                Ok(_) => {
+                    if let (Some(id), Some(tracer)) = (trace_id, self.inner.config.tracer.as_ref()) {
+                        tracer.forwarded(id, remote_addr);
+                    }
+                },
                 Err(_) => {
                     trace!("Failed to dispatch message to {} because channel was closed",
                            remote_addr);
@@ -479,6 +545,53 @@ impl FloodSubController {
     }
 }
 
+/// Handle to a subscription to a topic, returned by `FloodSubController::subscribe`.
+///
+/// Implements `Stream` and produces the messages received for this topic. The stream ends once
+/// we unsubscribe from the topic.
+///
+/// > **Note**: Dropping the handle doesn't unsubscribe from the topic. Call `unsubscribe`
+/// >           instead.
+pub struct FloodSubSubscription {
+    topic: Topic,
+    receiver: mpsc::UnboundedReceiver<Message>,
+    controller: FloodSubController,
+}
+
+impl FloodSubSubscription {
+    /// Returns the topic of the subscription.
+    #[inline]
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    /// Unsubscribes from the topic. Equivalent to calling `FloodSubController::unsubscribe`.
+    #[inline]
+    pub fn unsubscribe(self) {
+        self.controller.unsubscribe(&self.topic);
+    }
+}
+
+impl Stream for FloodSubSubscription {
+    type Item = Message;
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.receiver
+            .poll()
+            .map_err(|_| unreachable!("UnboundedReceiver cannot err"))
+    }
+}
+
+impl fmt::Debug for FloodSubSubscription {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FloodSubSubscription")
+            .field("topic", self.topic.hash())
+            .finish()
+    }
+}
+
 /// Implementation of `Stream` that provides messages for the subscribed topics you subscribed to.
 pub struct FloodSubReceiver {
     inner: mpsc::UnboundedReceiver<Message>,
@@ -543,13 +656,22 @@ impl fmt::Debug for FloodSubFuture {
 //
 // - `bytes` contains the raw data.
 // - `remote_addr` is the address of the sender.
+//
+// Returns a future that resolves once the messages of the packet have been validated and
+// dispatched.
 fn handle_packet_received(
     bytes: BytesMut,
     inner: Arc<Inner>,
     remote_addr: &Multiaddr,
-) -> Result<(), IoError> {
+) -> Result<Box<Future<Item = (), Error = IoError> + Send>, IoError> {
     trace!("Received packet from {}", remote_addr);
 
+    if bytes.len() > inner.config.max_message_size {
+        debug!("Received packet of {} bytes from {} ; exceeds the maximum message size",
+               bytes.len(), remote_addr);
+        return Err(IoError::new(IoErrorKind::InvalidData, "packet too large"));
+    }
+
     // Parsing attempt.
     let mut input = match protobuf::parse_from_bytes::<rpc_proto::RPC>(&bytes) {
         Ok(msg) => msg,
@@ -577,23 +699,29 @@ fn handle_packet_received(
         }
     }
 
+    // Validations of the messages that are still pending.
+    let mut pending = Vec::new();
+
     // Handle the messages coming from the remote.
-    for publish in input.mut_publish().iter_mut() {
-        let from = publish.take_from();
+    for publish in input.take_publish().into_iter() {
         // We maintain a list of the messages that have already been
         // processed so that we don't process the same message twice.
         // Each message is identified by the `(from, seqno)` tuple.
-        if !inner
-            .received
-            .lock()
-            .insert(hash((from.clone(), publish.take_seqno())))
-        {
+        // The message is only inserted in that list once it has been accepted, otherwise a forged
+        // message could prevent the genuine one with the same identifier from being processed.
+        let key = hash((publish.get_from().to_vec(), publish.get_seqno().to_vec()));
+        let first_delivery = !inner.received.lock().contains(&key);
+        let id = trace_id(publish.get_from(), publish.get_seqno());
+        if let Some(ref tracer) = inner.config.tracer {
+            tracer.received(&id, remote_addr, first_delivery);
+        }
+        if !first_delivery {
             trace!("Skipping message because we had already received it ; payload = {} bytes",
                    publish.get_data().len());
             continue;
         }
 
-        let peer_id = match PeerId::from_bytes(from.clone()) {
+        let peer_id = match PeerId::from_bytes(publish.get_from().to_vec()) {
             Ok(id) => id,
             Err(err) => {
                 trace!("Parsing PeerId failed: {:?}. Skipping.", err);
@@ -601,55 +729,127 @@ fn handle_packet_received(
             }
         };
 
+        let signature_valid = match inner.config.validation_mode {
+            ValidationMode::None => true,
+            ValidationMode::Permissive => {
+                !publish.has_signature() || signing::verify_message(&publish)
+            }
+            ValidationMode::Strict => signing::verify_message(&publish),
+        };
+        if !signature_valid {
+            debug!("Rejecting message from {:?} because of a missing or invalid signature",
+                   peer_id);
+            continue;
+        }
+
         let from: Multiaddr = Protocol::P2p(peer_id.into()).into();
 
         let topics = publish
-            .take_topicIDs()
-            .into_iter()
-            .map(|h| TopicHash::from_raw(h))
+            .get_topicIDs()
+            .iter()
+            .map(|h| TopicHash::from_raw(h.clone()))
             .collect::<Vec<_>>();
 
         trace!("Processing message for topics {:?} ; payload = {} bytes",
                topics,
                publish.get_data().len());
 
-        // TODO: should check encryption/authentication of the message
+        // The packet that is propagated to the other remotes.
+        let packet: BytesMut = {
+            let mut proto = rpc_proto::RPC::new();
+            proto.mut_publish().push(publish.clone());
+            proto
+                .write_to_bytes()
+                .expect("protobuf message is always valid")
+                .into()
+        };
+
+        let message = Message {
+            source: from,
+            data: publish.get_data().to_vec(),
+            topics: topics,
+        };
 
-        // Broadcast the message to all the other remotes.
-        {
-            let remote_connections = inner.remote_connections.read();
-            for (addr, info) in remote_connections.iter() {
-                let st = info.subscribed_topics.read();
-                if !topics.iter().any(|t| st.contains(t)) {
-                    continue;
-                }
-                // TODO: don't send back to the remote that just sent it
-                trace!("Broadcasting received message to {}", addr);
-                let _ = info.sender.unbounded_send(bytes.clone());
+        match inner.config.validator {
+            Some(ref validator) => {
+                let inner = inner.clone();
+                let future = validator.validate(&message).then(move |result| {
+                    match result {
+                        Ok(true) => accept(&inner, key, &id, message, packet),
+                        Ok(false) => debug!("Message rejected by the validator"),
+                        Err(err) => debug!("Failed to validate message ; err = {:?}", err),
+                    }
+                    Ok::<_, IoError>(())
+                });
+                pending.push(future);
             }
+            None => accept(&inner, key, &id, message, packet),
         }
+    }
 
-        // Send the message locally if relevant.
-        let dispatch_locally = {
-            let subscribed_topics = inner.subscribed_topics.read();
-            topics
-                .iter()
-                .any(|t| subscribed_topics.iter().any(|topic| topic.hash() == t))
-        };
-        if dispatch_locally {
-            // Ignore if channel is closed.
-            trace!("Dispatching message locally");
-            let _ = inner.output_tx.unbounded_send(Message {
-                source: from,
-                data: publish.take_data(),
-                topics: topics,
-            });
-        } else {
-            trace!("Message not dispatched locally as we are not subscribed to any of the topics");
+    Ok(Box::new(future::join_all(pending).map(|_| ())))
+}
+
+// Marks a message that passed the validations as received, and propagates it unless another copy
+// has been accepted in the meantime.
+fn accept(inner: &Inner, key: u64, id: &str, message: Message, packet: BytesMut) {
+    if inner.received.lock().insert(key) {
+        propagate(inner, id, message, packet);
+    } else {
+        trace!("Skipping message because another copy has been accepted in the meantime");
+    }
+}
+
+// Sends a message that was received and accepted to the other remotes subscribed to its topics,
+// and dispatches it locally if we are subscribed to one of them.
+fn propagate(inner: &Inner, id: &str, message: Message, packet: BytesMut) {
+    // Broadcast the message to all the other remotes.
+    {
+        let remote_connections = inner.remote_connections.read();
+        for (addr, info) in remote_connections.iter() {
+            let st = info.subscribed_topics.read();
+            if !message.topics.iter().any(|t| st.contains(t)) {
+                continue;
+            }
+            // TODO: don't send back to the remote that just sent it
+            trace!("Broadcasting received message to {}", addr);
+            if info.sender.unbounded_send(packet.clone()).is_ok() {
+                if let Some(ref tracer) = inner.config.tracer {
+                    tracer.forwarded(id, addr);
+                }
+            }
         }
     }
 
-    Ok(())
+    // Send the message locally if relevant.
+    let dispatch_locally = {
+        let subscribed_topics = inner.subscribed_topics.read();
+        message
+            .topics
+            .iter()
+            .any(|t| subscribed_topics.iter().any(|topic| topic.hash() == t))
+    };
+    if dispatch_locally {
+        trace!("Dispatching message locally");
+        dispatch_to_subscriptions(&mut inner.topic_senders.lock(), &message);
+        // Ignore if channel is closed.
+        let _ = inner.output_tx.unbounded_send(message);
+    } else {
+        trace!("Message not dispatched locally as we are not subscribed to any of the topics");
+    }
+}
+
+// Sends a message to the `FloodSubSubscription`s of its topics, and forgets the subscriptions
+// whose handle has been dropped.
+fn dispatch_to_subscriptions(
+    senders: &mut FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<Message>>>,
+    message: &Message,
+) {
+    for topic in message.topics.iter() {
+        if let Some(list) = senders.get_mut(topic) {
+            list.retain(|tx| tx.unbounded_send(message.clone()).is_ok());
+        }
+    }
 }
 
 // Shortcut function that hashes a value.
@@ -659,3 +859,65 @@ fn hash<V: Hash>(value: V) -> u64 {
     value.hash(&mut h);
     h.finish()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+
+    #[test]
+    fn subscription_produces_topic_messages() {
+        let (upgrade, _) = FloodSubUpgrade::new(PublicKey::Ed25519(vec![1; 32]).into_peer_id());
+        let controller = FloodSubController::new(&upgrade);
+        let topic = TopicBuilder::new("chat").build();
+        let other = TopicBuilder::new("other").build();
+        let subscription = controller.subscribe(&topic);
+        controller.subscribe(&other);
+
+        let message = Message {
+            source: "/ip4/1.2.3.4/tcp/5".parse().unwrap(),
+            data: vec![1, 2, 3],
+            topics: vec![topic.hash().clone()],
+        };
+        propagate(&upgrade.inner, "a", message.clone(), BytesMut::new());
+        let other_message = Message { topics: vec![other.hash().clone()], ..message.clone() };
+        propagate(&upgrade.inner, "b", other_message, BytesMut::new());
+
+        // Unsubscribing ends the stream.
+        controller.unsubscribe(&topic);
+        assert_eq!(subscription.collect().wait().unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn forged_message_does_not_poison_seen_cache() {
+        let signer = Ed25519Signer::generated().unwrap();
+        let config = FloodSubConfig::new().with_validation_mode(ValidationMode::Strict);
+        let (upgrade, receiver) = FloodSubUpgrade::with_config(
+            PublicKey::Ed25519(vec![1; 32]).into_peer_id(),
+            config,
+        );
+        let controller = FloodSubController::new(&upgrade);
+        let topic = TopicBuilder::new("chat").build();
+        controller.subscribe(&topic);
+
+        let mut genuine = rpc_proto::Message::new();
+        genuine.set_from(signer.public_key().into_peer_id().into_bytes());
+        genuine.set_data(b"hello world".to_vec());
+        genuine.set_seqno(vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        genuine.set_topicIDs(vec![topic.hash().clone().into_string()].into());
+        signing::sign_message(&mut genuine, &signer);
+        let mut forged = genuine.clone();
+        forged.set_data(b"goodbye world".to_vec());
+
+        let remote: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        for msg in vec![forged, genuine] {
+            let mut rpc = rpc_proto::RPC::new();
+            rpc.mut_publish().push(msg);
+            let bytes = rpc.write_to_bytes().unwrap().into();
+            handle_packet_received(bytes, upgrade.inner.clone(), &remote).unwrap().wait().unwrap();
+        }
+
+        let received = receiver.take(1).collect().wait().unwrap();
+        assert_eq!(received[0].data, b"hello world".to_vec());
+    }
+}
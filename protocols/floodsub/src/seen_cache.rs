@@ -0,0 +1,142 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::FnvHashMap;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Set of the identifiers of the messages we have recently seen, used to avoid dispatching or
+/// propagating the same message twice.
+///
+/// Entries are forgotten once they are older than the time-to-live, or when the number of
+/// entries exceeds the capacity, in which case the oldest entries are forgotten first.
+#[derive(Debug, Clone)]
+pub struct SeenCache<K>
+where
+    K: Hash + Eq,
+{
+    /// When each entry has been inserted.
+    entries: FnvHashMap<K, Instant>,
+    /// The entries, in insertion order.
+    order: VecDeque<(K, Instant)>,
+    /// Duration after which an entry is forgotten.
+    ttl: Duration,
+    /// Maximum number of entries.
+    capacity: usize,
+}
+
+impl<K> SeenCache<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates an empty cache.
+    ///
+    /// A capacity of `0` is treated like a capacity of `1`.
+    pub fn new(ttl: Duration, capacity: usize) -> SeenCache<K> {
+        SeenCache {
+            entries: FnvHashMap::default(),
+            order: VecDeque::new(),
+            ttl,
+            capacity: if capacity == 0 { 1 } else { capacity },
+        }
+    }
+
+    /// Inserts an entry. Returns true if the entry wasn't in the cache.
+    pub fn insert(&mut self, key: K) -> bool {
+        self.insert_at(key, Instant::now())
+    }
+
+    /// Same as `insert`, but with the current time passed explicitly.
+    pub fn insert_at(&mut self, key: K, now: Instant) -> bool {
+        self.expire(now);
+        if self.entries.contains_key(&key) {
+            return false;
+        }
+
+        while self.order.len() >= self.capacity {
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(key.clone(), now);
+        self.order.push_back((key, now));
+        true
+    }
+
+    /// Returns true if the entry is in the cache.
+    ///
+    /// > **Note**: Entries that have expired but haven't been removed yet by a call to `insert`
+    /// >           are not reported.
+    pub fn contains(&self, key: &K) -> bool {
+        match self.entries.get(key) {
+            Some(inserted) => inserted.elapsed() < self.ttl,
+            None => false,
+        }
+    }
+
+    /// Returns the number of entries, including the ones that have expired but haven't been
+    /// removed yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Removes the entries that are older than the time-to-live.
+    fn expire(&mut self, now: Instant) {
+        while let Some(inserted) = self.order.front().map(|&(_, inserted)| inserted) {
+            if now.duration_since(inserted) < self.ttl {
+                break;
+            }
+            let (key, _) = self.order.pop_front().expect("front() returned Some ; qed");
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeenCache;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bounded_by_capacity() {
+        let mut cache = SeenCache::new(Duration::from_secs(60), 2);
+        assert!(cache.insert(1));
+        assert!(!cache.insert(1));
+        assert!(cache.insert(2));
+        assert!(cache.insert(3));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&1));
+        assert!(cache.contains(&3));
+    }
+
+    #[test]
+    fn bounded_by_ttl() {
+        let mut cache = SeenCache::new(Duration::from_secs(10), 100);
+        let now = Instant::now();
+        assert!(cache.insert_at(1, now));
+        assert!(cache.insert_at(2, now + Duration::from_secs(5)));
+        assert!(cache.insert_at(1, now + Duration::from_secs(11)));
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use libp2p_core::PublicKey;
+use protobuf::Message as ProtobufMessage;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, ED25519, RSA_PKCS1_2048_8192_SHA256};
+use rpc_proto;
+use std::error::Error;
+use std::sync::Arc;
+use untrusted::Input;
+
+/// Prefix prepended to the encoded message before it is signed, so that a signature can't be
+/// reused in another context.
+const SIGNING_PREFIX: &[u8] = b"libp2p-pubsub:";
+
+/// Signs the messages published by the local node.
+///
+/// The key used for signing must correspond to the `PeerId` passed to `FloodSubUpgrade`,
+/// otherwise the remotes will reject the messages.
+pub trait MessageSigner: Send + Sync {
+    /// Returns the public key that remotes must use to verify the signatures.
+    fn public_key(&self) -> PublicKey;
+
+    /// Signs the given data.
+    fn sign(&self, data: &[u8]) -> Vec<u8>;
+}
+
+/// Implementation of `MessageSigner` that uses an Ed25519 key pair.
+#[derive(Clone)]
+pub struct Ed25519Signer {
+    // We use an `Arc` so that we can clone the struct.
+    key_pair: Arc<Ed25519KeyPair>,
+}
+
+impl Ed25519Signer {
+    /// Builds an `Ed25519Signer` from a PKCS8 Ed25519 private key.
+    pub fn from_pkcs8<K>(key: K) -> Result<Ed25519Signer, Box<Error + Send + Sync>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key_pair = Ed25519KeyPair::from_pkcs8(Input::from(key.as_ref())).map_err(Box::new)?;
+        Ok(Ed25519Signer {
+            key_pair: Arc::new(key_pair),
+        })
+    }
+
+    /// Generates a new random Ed25519 key pair and uses it.
+    pub fn generated() -> Result<Ed25519Signer, Box<Error + Send + Sync>> {
+        let rng = SystemRandom::new();
+        let gen = Ed25519KeyPair::generate_pkcs8(&rng).map_err(Box::new)?;
+        Ok(Ed25519Signer::from_pkcs8(&gen[..]).expect("failed to parse generated Ed25519 key"))
+    }
+}
+
+impl MessageSigner for Ed25519Signer {
+    #[inline]
+    fn public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.key_pair.public_key_bytes().to_vec())
+    }
+
+    #[inline]
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.key_pair.sign(data).as_ref().to_owned()
+    }
+}
+
+// Fills the `signature` and `key` fields of the message.
+pub(crate) fn sign_message(message: &mut rpc_proto::Message, signer: &MessageSigner) {
+    let signature = signer.sign(&signed_bytes(message));
+    message.set_signature(signature);
+    message.set_key(signer.public_key().into_protobuf_encoding());
+}
+
+// Returns true if the message carries a valid signature of the peer in its `from` field.
+pub(crate) fn verify_message(message: &rpc_proto::Message) -> bool {
+    if !message.has_signature() || !message.has_key() {
+        return false;
+    }
+
+    let key = match PublicKey::from_protobuf_encoding(message.get_key()) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+
+    if key.clone().into_peer_id().into_bytes() != message.get_from() {
+        debug!("Message signed with a key that doesn't match its source");
+        return false;
+    }
+
+    let (algorithm, public): (&signature::VerificationAlgorithm, _) = match key {
+        PublicKey::Rsa(ref public) => (&RSA_PKCS1_2048_8192_SHA256, public),
+        PublicKey::Ed25519(ref public) => (&ED25519, public),
+        // TODO: support secp256k1
+        PublicKey::Secp256k1(_) => {
+            debug!("Verifying secp256k1 signatures isn't supported");
+            return false;
+        }
+    };
+
+    signature::verify(
+        algorithm,
+        Input::from(&public[..]),
+        Input::from(&signed_bytes(message)[..]),
+        Input::from(message.get_signature()),
+    ).is_ok()
+}
+
+// Returns the bytes that are signed, which are the encoded message without its `signature` and
+// `key` fields.
+fn signed_bytes(message: &rpc_proto::Message) -> Vec<u8> {
+    let mut message = message.clone();
+    message.clear_signature();
+    message.clear_key();
+
+    let mut out = SIGNING_PREFIX.to_vec();
+    out.extend(
+        message
+            .write_to_bytes()
+            .expect("protobuf message is always valid"),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign_message, verify_message, Ed25519Signer, MessageSigner};
+    use rpc_proto;
+
+    fn message(signer: &Ed25519Signer) -> rpc_proto::Message {
+        let mut msg = rpc_proto::Message::new();
+        msg.set_from(signer.public_key().into_peer_id().into_bytes());
+        msg.set_data(b"hello world".to_vec());
+        msg.set_seqno(vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        msg
+    }
+
+    #[test]
+    fn sign_then_verify() {
+        let signer = Ed25519Signer::generated().unwrap();
+        let mut msg = message(&signer);
+        assert!(!verify_message(&msg));
+        sign_message(&mut msg, &signer);
+        assert!(verify_message(&msg));
+    }
+
+    #[test]
+    fn tampered_message_rejected() {
+        let signer = Ed25519Signer::generated().unwrap();
+        let mut msg = message(&signer);
+        sign_message(&mut msg, &signer);
+        msg.set_data(b"goodbye world".to_vec());
+        assert!(!verify_message(&msg));
+    }
+
+    #[test]
+    fn wrong_source_rejected() {
+        let signer = Ed25519Signer::generated().unwrap();
+        let other = Ed25519Signer::generated().unwrap();
+        let mut msg = message(&other);
+        sign_message(&mut msg, &signer);
+        assert!(!verify_message(&msg));
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use bs58;
+use fnv::FnvHashMap;
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use TopicHash;
+
+/// Builds the identifier under which a message is reported to a `MessageTracer`.
+///
+/// This is the base58 encoding of the concatenation of the source and the sequence number of
+/// the message, which is the same on every node and in both floodsub and gossipsub.
+pub fn trace_id(from: &[u8], seqno: &[u8]) -> String {
+    let mut bytes = from.to_vec();
+    bytes.extend_from_slice(seqno);
+    bs58::encode(bytes).into_string()
+}
+
+/// Hooks called by the pubsub system for each message it handles, in order to trace the
+/// propagation of the messages through the network.
+///
+/// Messages are identified with the value returned by `trace_id`. All the methods do nothing by
+/// default.
+pub trait MessageTracer: Send + Sync {
+    /// Called when we publish a message.
+    #[inline]
+    fn published(&self, _id: &str, _topics: &[TopicHash]) {
+    }
+
+    /// Called when a remote sends us a message. `first_delivery` is true if it is the first time
+    /// we see this message.
+    #[inline]
+    fn received(&self, _id: &str, _from: &Multiaddr, _first_delivery: bool) {
+    }
+
+    /// Called when we send a message to a remote, either because we published it or because we
+    /// forward it.
+    #[inline]
+    fn forwarded(&self, _id: &str, _to: &Multiaddr) {
+    }
+}
+
+/// What happened to a message, as recorded by a `MemoryTracer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// We published the message.
+    Published,
+    /// A remote sent us the message.
+    Received {
+        /// Address of the remote.
+        from: Multiaddr,
+        /// True if it is the first time we saw the message.
+        first_delivery: bool,
+    },
+    /// We sent the message to a remote.
+    Forwarded {
+        /// Address of the remote.
+        to: Multiaddr,
+    },
+}
+
+/// Everything that happened to a message on the local node, in chronological order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTrace {
+    /// Identifier of the message, as returned by `trace_id`.
+    pub id: String,
+    /// The events, with the moment they happened.
+    pub events: Vec<(Instant, TraceEvent)>,
+}
+
+impl MessageTrace {
+    /// Returns the moment we first received the message from a remote, or published it.
+    pub fn first_seen(&self) -> Option<Instant> {
+        self.events
+            .iter()
+            .find(|&&(_, ref ev)| match *ev {
+                TraceEvent::Published => true,
+                TraceEvent::Received { first_delivery, .. } => first_delivery,
+                TraceEvent::Forwarded { .. } => false,
+            })
+            .map(|&(at, _)| at)
+    }
+}
+
+/// Implementation of `MessageTracer` that stores the events in memory.
+///
+/// Cloning a `MemoryTracer` gives access to the same storage, so a clone can be kept in order to
+/// export the traces after the tracer has been passed to the pubsub system.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTracer {
+    traces: Arc<Mutex<FnvHashMap<String, Vec<(Instant, TraceEvent)>>>>,
+}
+
+impl MemoryTracer {
+    /// Creates a tracer with no event.
+    #[inline]
+    pub fn new() -> MemoryTracer {
+        MemoryTracer::default()
+    }
+
+    /// Returns the trace of the given message, if any event has been recorded for it.
+    pub fn trace(&self, id: &str) -> Option<MessageTrace> {
+        self.traces.lock().get(id).map(|events| MessageTrace {
+            id: id.to_owned(),
+            events: events.clone(),
+        })
+    }
+
+    /// Returns the traces of all the messages.
+    pub fn traces(&self) -> Vec<MessageTrace> {
+        self.traces
+            .lock()
+            .iter()
+            .map(|(id, events)| MessageTrace { id: id.clone(), events: events.clone() })
+            .collect()
+    }
+
+    /// Forgets all the events that have been recorded.
+    #[inline]
+    pub fn clear(&self) {
+        self.traces.lock().clear();
+    }
+
+    /// Writes all the events as CSV, one event per line, in the format
+    /// `id,micros,event,remote,first_delivery`.
+    ///
+    /// `micros` is the number of microseconds elapsed since `origin`, or `0` for events that
+    /// happened before it. Passing the same `origin` to the tracers of all the nodes of a
+    /// simulation makes the times comparable.
+    pub fn export_csv<W: Write>(&self, mut out: W, origin: Instant) -> Result<(), IoError> {
+        for trace in self.traces() {
+            for &(at, ref event) in trace.events.iter() {
+                let elapsed = if at > origin { at - origin } else { Duration::new(0, 0) };
+                let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_nanos() / 1_000);
+                match *event {
+                    TraceEvent::Published => {
+                        writeln!(out, "{},{},published,,", trace.id, micros)?
+                    },
+                    TraceEvent::Received { ref from, first_delivery } => {
+                        writeln!(out, "{},{},received,{},{}", trace.id, micros, from, first_delivery)?
+                    },
+                    TraceEvent::Forwarded { ref to } => {
+                        writeln!(out, "{},{},forwarded,{},", trace.id, micros, to)?
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, id: &str, event: TraceEvent) {
+        self.traces
+            .lock()
+            .entry(id.to_owned())
+            .or_insert_with(Vec::new)
+            .push((Instant::now(), event));
+    }
+}
+
+impl MessageTracer for MemoryTracer {
+    #[inline]
+    fn published(&self, id: &str, _: &[TopicHash]) {
+        self.record(id, TraceEvent::Published);
+    }
+
+    #[inline]
+    fn received(&self, id: &str, from: &Multiaddr, first_delivery: bool) {
+        self.record(id, TraceEvent::Received { from: from.clone(), first_delivery });
+    }
+
+    #[inline]
+    fn forwarded(&self, id: &str, to: &Multiaddr) {
+        self.record(id, TraceEvent::Forwarded { to: to.clone() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multiaddr::Multiaddr;
+    use trace::{MemoryTracer, MessageTracer, TraceEvent};
+    use std::time::Instant;
+
+    #[test]
+    fn records_and_exports() {
+        let origin = Instant::now();
+        let tracer = MemoryTracer::new();
+        let remote: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        tracer.clone().received("msg", &remote, true);
+        tracer.received("msg", &remote, false);
+        tracer.forwarded("msg", &remote);
+
+        let trace = tracer.trace("msg").unwrap();
+        assert_eq!(trace.events.len(), 3);
+        assert_eq!(trace.events[2].1, TraceEvent::Forwarded { to: remote.clone() });
+        assert!(trace.first_seen().unwrap() >= origin);
+
+        let mut out = Vec::new();
+        tracer.export_csv(&mut out, origin).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.starts_with("msg,")));
+    }
+}
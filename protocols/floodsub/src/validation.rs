@@ -0,0 +1,54 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::Future;
+use std::io::Error as IoError;
+use Message;
+
+/// How the signatures of the messages received from the network are checked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Signatures are ignored.
+    None,
+    /// Messages that carry an invalid signature are rejected, but unsigned messages are accepted.
+    Permissive,
+    /// Only messages that carry a valid signature are accepted.
+    Strict,
+}
+
+impl Default for ValidationMode {
+    #[inline]
+    fn default() -> ValidationMode {
+        ValidationMode::Permissive
+    }
+}
+
+/// Application-defined validation of the messages received from the network.
+///
+/// Each message that passes the signature check is passed to the validator before being
+/// dispatched locally and propagated to the other remotes. If the future produces `false` or an
+/// error, the message is dropped.
+///
+/// > **Note**: The messages received on a connection are processed one by one, therefore a slow
+/// >           validation delays the processing of the next packets of that connection.
+pub trait MessageValidator: Send + Sync {
+    /// Validates a message. The future must produce `true` if the message is acceptable.
+    fn validate(&self, message: &Message) -> Box<Future<Item = bool, Error = IoError> + Send>;
+}
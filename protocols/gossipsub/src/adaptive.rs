@@ -0,0 +1,131 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use fnv::FnvHashMap;
+use libp2p_core::Multiaddr;
+
+/// Configuration of the adaptive dissemination mode, inspired by Epidemic Broadcast Trees.
+///
+/// In this mode, the mesh of a topic is the set of remotes to which we *eagerly push* messages,
+/// and every other remote subscribed to the topic is sent the IDs of the messages in `IHAVE`
+/// gossip at each heartbeat, from which it *lazily pulls* the messages it misses.
+///
+/// Remotes are switched between the two modes based on what we observe:
+///
+/// - A mesh remote that sends us too many messages we had already received is redundant. It is
+///   pruned from the mesh, unless the mesh is already at its minimum size.
+/// - A remote that advertises a message we haven't received yet is on a faster path. It is
+///   grafted into the mesh.
+///
+/// Over time, the meshes converge towards a spanning tree of the network, which reduces the
+/// number of duplicates at the cost of a higher latency when the tree has to be repaired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveConfig {
+    /// Number of messages received from a mesh remote over which its duplicate rate is measured.
+    pub window: u32,
+    /// Percentage of duplicates among the messages of a window above which a mesh remote is
+    /// switched to lazy pull.
+    pub max_duplicates_percent: u32,
+    /// Minimum number of remotes in the mesh of a topic. Remotes are never pruned below this
+    /// number because of duplicates, and the heartbeat grafts remotes until it is reached.
+    pub min_eager: usize,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> AdaptiveConfig {
+        AdaptiveConfig {
+            window: 16,
+            max_duplicates_percent: 50,
+            min_eager: 2,
+        }
+    }
+}
+
+/// Counts, for each remote, the messages and duplicates it sent us in the current window.
+#[derive(Debug, Default)]
+pub struct DuplicateTracker {
+    // For each remote, the number of messages and the number of duplicates.
+    stats: FnvHashMap<Multiaddr, (u32, u32)>,
+}
+
+impl DuplicateTracker {
+    /// Creates an empty tracker.
+    #[inline]
+    pub fn new() -> DuplicateTracker {
+        DuplicateTracker::default()
+    }
+
+    /// Records a message received from a remote. Returns true if the window of the remote is
+    /// complete and its duplicate rate is above the limit, in which case it should be switched
+    /// to lazy pull.
+    pub fn record(&mut self, remote: &Multiaddr, duplicate: bool, config: &AdaptiveConfig) -> bool {
+        let complete = {
+            let entry = self.stats.entry(remote.clone()).or_insert((0, 0));
+            entry.0 += 1;
+            if duplicate {
+                entry.1 += 1;
+            }
+            if entry.0 >= config.window {
+                Some(*entry)
+            } else {
+                None
+            }
+        };
+
+        match complete {
+            Some((total, duplicates)) => {
+                self.stats.remove(remote);
+                u64::from(duplicates) * 100 > u64::from(total) * u64::from(config.max_duplicates_percent)
+            },
+            None => false,
+        }
+    }
+
+    /// Forgets about a remote.
+    #[inline]
+    pub fn remove(&mut self, remote: &Multiaddr) {
+        self.stats.remove(remote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use adaptive::{AdaptiveConfig, DuplicateTracker};
+    use libp2p_core::Multiaddr;
+
+    #[test]
+    fn switch_after_window() {
+        let config = AdaptiveConfig { window: 4, max_duplicates_percent: 50, min_eager: 1 };
+        let remote: Multiaddr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let mut tracker = DuplicateTracker::new();
+
+        // Two duplicates out of four is not above the limit.
+        assert!(!tracker.record(&remote, true, &config));
+        assert!(!tracker.record(&remote, true, &config));
+        assert!(!tracker.record(&remote, false, &config));
+        assert!(!tracker.record(&remote, false, &config));
+
+        // Three out of four is.
+        assert!(!tracker.record(&remote, true, &config));
+        assert!(!tracker.record(&remote, true, &config));
+        assert!(!tracker.record(&remote, true, &config));
+        assert!(tracker.record(&remote, false, &config));
+    }
+}
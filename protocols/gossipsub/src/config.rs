@@ -0,0 +1,152 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use adaptive::AdaptiveConfig;
+use libp2p_core::upgrade::MessageLimits;
+use std::{error, fmt};
+use std::time::Duration;
+
+/// Configuration parameters of the gossipsub protocol.
+///
+/// The names of the parameters follow the gossipsub specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipSubConfig {
+    /// Target number of peers in the mesh of a topic. Often called `D`.
+    pub mesh_n: usize,
+    /// Minimum number of peers in the mesh of a topic before we try to graft new peers.
+    pub mesh_n_low: usize,
+    /// Maximum number of peers in the mesh of a topic before we prune some of them.
+    pub mesh_n_high: usize,
+    /// Number of peers outside of the mesh to which we send gossip at each heartbeat.
+    pub gossip_lazy: usize,
+    /// Number of heartbeats during which a message is kept in the message cache.
+    pub history_length: usize,
+    /// Number of the most recent heartbeats whose messages are advertised in gossip.
+    pub history_gossip: usize,
+    /// Interval between two heartbeats.
+    pub heartbeat_interval: Duration,
+    /// Duration after which we forget about the peers of a topic we publish to without being
+    /// subscribed to it.
+    pub fanout_ttl: Duration,
+    /// Maximum size in bytes of a packet, whether we send or receive it.
+    pub max_message_size: usize,
+    /// Duration during which we remember the ID of a message, in order to ignore duplicates.
+    pub seen_ttl: Duration,
+    /// Maximum number of message IDs that we remember in order to ignore duplicates.
+    pub seen_capacity: usize,
+    /// If `Some`, enables the adaptive dissemination mode, where remotes are switched between
+    /// eager push and lazy pull depending on the duplicates they send us. See `AdaptiveConfig`.
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+impl GossipSubConfig {
+    /// Checks that the parameters are consistent with each other.
+    ///
+    /// The sizes of the meshes must satisfy `mesh_n_low <= mesh_n <= mesh_n_high`, and the
+    /// message cache must keep at least one heartbeat and gossip about at most `history_length`
+    /// of them.
+    pub fn validate(&self) -> Result<(), GossipSubConfigError> {
+        if self.history_length == 0 {
+            return Err(GossipSubConfigError::ZeroHistoryLength);
+        }
+        if self.history_gossip > self.history_length {
+            return Err(GossipSubConfigError::HistoryGossipTooLarge);
+        }
+        if self.mesh_n_low > self.mesh_n || self.mesh_n > self.mesh_n_high {
+            return Err(GossipSubConfigError::InvalidMeshSizes);
+        }
+        Ok(())
+    }
+
+    /// Takes `max_message_size` from `limits`.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.max_message_size = limits.max_for(b"/meshsub/1.0.0");
+        self
+    }
+}
+
+impl Default for GossipSubConfig {
+    fn default() -> GossipSubConfig {
+        GossipSubConfig {
+            mesh_n: 6,
+            mesh_n_low: 4,
+            mesh_n_high: 12,
+            gossip_lazy: 6,
+            history_length: 5,
+            history_gossip: 3,
+            heartbeat_interval: Duration::from_secs(1),
+            fanout_ttl: Duration::from_secs(60),
+            max_message_size: 1024 * 1024,
+            seen_ttl: Duration::from_secs(120),
+            seen_capacity: 100_000,
+            adaptive: None,
+        }
+    }
+}
+
+/// Error returned by `GossipSubConfig::validate`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GossipSubConfigError {
+    /// `history_length` is zero.
+    ZeroHistoryLength,
+    /// `history_gossip` is larger than `history_length`.
+    HistoryGossipTooLarge,
+    /// The sizes of the meshes don't satisfy `mesh_n_low <= mesh_n <= mesh_n_high`.
+    InvalidMeshSizes,
+}
+
+impl fmt::Display for GossipSubConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GossipSubConfigError::ZeroHistoryLength => write!(f, "history_length can't be zero"),
+            GossipSubConfigError::HistoryGossipTooLarge => {
+                write!(f, "history_gossip can't be larger than history_length")
+            },
+            GossipSubConfigError::InvalidMeshSizes => {
+                write!(f, "the mesh sizes must satisfy mesh_n_low <= mesh_n <= mesh_n_high")
+            },
+        }
+    }
+}
+
+impl error::Error for GossipSubConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(GossipSubConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn invalid_parameters_rejected() {
+        let config = GossipSubConfig { history_length: 0, history_gossip: 0, ..Default::default() };
+        assert_eq!(config.validate(), Err(GossipSubConfigError::ZeroHistoryLength));
+        let config = GossipSubConfig { history_gossip: 6, ..Default::default() };
+        assert_eq!(config.validate(), Err(GossipSubConfigError::HistoryGossipTooLarge));
+        let config = GossipSubConfig { mesh_n: 3, ..Default::default() };
+        assert_eq!(config.validate(), Err(GossipSubConfigError::InvalidMeshSizes));
+        let config = GossipSubConfig { mesh_n_high: 5, ..Default::default() };
+        assert_eq!(config.validate(), Err(GossipSubConfigError::InvalidMeshSizes));
+    }
+}
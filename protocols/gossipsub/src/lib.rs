@@ -0,0 +1,1450 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/meshsub/1.0.0` protocol, also known as *gossipsub*.
+//!
+//! Gossipsub is a sibling of floodsub that shares the same topics and messages, but instead of
+//! forwarding every message to every remote subscribed to a topic, each node only forwards
+//! messages to a limited set of remotes called the *mesh* of the topic. The rest of the remotes
+//! are only informed about the IDs of the messages we recently saw (`IHAVE`), and can request the
+//! ones they missed (`IWANT`).
+//!
+//! # Usage
+//!
+//! Create a `GossipSubUpgrade` with `GossipSubUpgrade::new`, which also returns a
+//! `GossipSubReceiver` that produces the messages of the topics we are subscribed to. Use the
+//! upgrade on each connection, and control the system with a `GossipSubController`.
+//!
+//! `GossipSubController::subscribe` returns a `GossipSubSubscription` that produces the messages
+//! of a single topic. The `GossipSubReceiver` can be dropped if only these handles are used.
+//!
+//! The meshes are maintained by a periodic *heartbeat*. Either call
+//! `GossipSubController::heartbeat` at regular intervals, or drive the future returned by
+//! `GossipSubController::heartbeat_future` which does so with the interval of the configuration.
+//!
+//! The selection of the remotes that are part of the meshes can be influenced by passing an
+//! implementation of the `PeerScoring` trait to `GossipSubUpgrade::with_scoring`.
+//!
+//! Setting `GossipSubConfig::adaptive` enables an alternative dissemination mode where remotes
+//! are moved in and out of the meshes depending on the duplicates they send us, similar to
+//! Epidemic Broadcast Trees. See `AdaptiveConfig`.
+//!
+//! The propagation of the messages can be traced by passing a `MessageTracer` to
+//! `GossipSubController::set_tracer`.
+//!
+//! The remotes are selected randomly. Pass the `RandomSource` of a deterministic `Runtime` to
+//! `GossipSubController::set_random_source` in order to make these decisions reproducible.
+
+extern crate byteorder;
+extern crate bytes;
+extern crate fnv;
+extern crate futures;
+extern crate libp2p_core;
+extern crate libp2p_floodsub;
+#[macro_use]
+extern crate log;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate protobuf;
+extern crate smallvec;
+extern crate tokio_codec;
+extern crate tokio_io;
+extern crate tokio_timer;
+extern crate unsigned_varint;
+
+mod adaptive;
+mod config;
+mod mcache;
+mod rpc_proto;
+mod scoring;
+
+pub use self::adaptive::AdaptiveConfig;
+pub use self::config::{GossipSubConfig, GossipSubConfigError};
+pub use self::scoring::{NoScoring, PeerScoring};
+pub use libp2p_floodsub::{Message, SeenCache, Topic, TopicBuilder, TopicHash};
+pub use libp2p_floodsub::{trace_id, MemoryTracer, MessageTrace, MessageTracer, TraceEvent};
+
+use byteorder::{BigEndian, WriteBytesExt};
+use adaptive::DuplicateTracker;
+use bytes::{Bytes, BytesMut};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::sync::mpsc;
+use futures::{future, Async, Future, Poll, Sink, Stream};
+use libp2p_core::{ConnectionUpgrade, Endpoint, PeerId};
+use libp2p_core::runtime::RandomSource;
+use mcache::MessageCache;
+use multiaddr::{Protocol, Multiaddr};
+use parking_lot::{Mutex, RwLock};
+use protobuf::Message as ProtobufMessage;
+use protobuf::RepeatedField;
+use smallvec::SmallVec;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Interval;
+use unsigned_varint::codec;
+
+/// Implementation of the `ConnectionUpgrade` for the gossipsub protocol.
+#[derive(Debug, Clone)]
+pub struct GossipSubUpgrade {
+    inner: Arc<Inner>,
+}
+
+impl GossipSubUpgrade {
+    /// Builds a new `GossipSubUpgrade`. Also returns a `GossipSubReceiver` that will stream
+    /// incoming messages for the gossipsub system.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the configuration is invalid. Use `GossipSubConfig::validate` to check it
+    /// beforehand.
+    #[inline]
+    pub fn new(my_id: PeerId, config: GossipSubConfig) -> (GossipSubUpgrade, GossipSubReceiver) {
+        GossipSubUpgrade::with_scoring(my_id, config, NoScoring)
+    }
+
+    /// Same as `new`, but uses the given `PeerScoring` to decide which remotes are part of the
+    /// meshes.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the configuration is invalid. Use `GossipSubConfig::validate` to check it
+    /// beforehand.
+    pub fn with_scoring<S>(my_id: PeerId, config: GossipSubConfig, scoring: S)
+        -> (GossipSubUpgrade, GossipSubReceiver)
+    where S: PeerScoring + 'static
+    {
+        if let Err(err) = config.validate() {
+            panic!("invalid gossipsub configuration: {}", err);
+        }
+
+        let (output_tx, output_rx) = mpsc::unbounded();
+
+        let inner = Arc::new(Inner {
+            peer_id: my_id.into_bytes(),
+            mcache: Mutex::new(MessageCache::new(config.history_gossip, config.history_length)),
+            received: Mutex::new(SeenCache::new(config.seen_ttl, config.seen_capacity)),
+            duplicates: Mutex::new(DuplicateTracker::new()),
+            tracer: RwLock::new(None),
+            random: RwLock::new(RandomSource::from_entropy()),
+            config: config,
+            scoring: Box::new(scoring),
+            output_tx: output_tx,
+            remote_connections: RwLock::new(FnvHashMap::default()),
+            subscribed_topics: RwLock::new(Vec::new()),
+            topic_senders: Mutex::new(FnvHashMap::default()),
+            mesh: Mutex::new(FnvHashMap::default()),
+            fanout: Mutex::new(FnvHashMap::default()),
+            seq_no: AtomicUsize::new(0),
+        });
+
+        let upgrade = GossipSubUpgrade { inner: inner };
+
+        let receiver = GossipSubReceiver { inner: output_rx };
+
+        (upgrade, receiver)
+    }
+}
+
+impl<C> ConnectionUpgrade<C> for GossipSubUpgrade
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once(("/meshsub/1.0.0".into(), ()))
+    }
+
+    type Output = GossipSubFuture;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn upgrade(
+        self,
+        socket: C,
+        _: Self::UpgradeIdentifier,
+        _: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        debug!("Upgrading connection as gossipsub");
+
+        let remote_addr = remote_addr.clone();
+
+        // Whenever a new node connects, we send to it a message containing the topics we are
+        // already subscribed to.
+        let init_msg: Vec<u8> = {
+            let subscribed_topics = self.inner.subscribed_topics.read();
+            let mut proto = rpc_proto::RPC::new();
+
+            for topic in subscribed_topics.iter() {
+                let mut subscription = rpc_proto::RPC_SubOpts::new();
+                subscription.set_subscribe(true);
+                subscription.set_topicid(topic.hash().clone().into_string());
+                proto.mut_subscriptions().push(subscription);
+            }
+
+            proto
+                .write_to_bytes()
+                .expect("programmer error: the protobuf message should always be valid")
+        };
+
+        // Split the socket into writing and reading parts.
+        let mut codec = codec::UviBytes::default();
+        codec.set_max_len(self.inner.config.max_message_size);
+        let (gossipsub_sink, gossipsub_stream) = Framed::new(socket, codec)
+            .sink_map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
+            .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
+            .split();
+
+        // Build the channel that will be used to communicate outgoing message to this remote.
+        let (input_tx, input_rx) = mpsc::unbounded();
+        input_tx
+            .unbounded_send(init_msg.into())
+            .expect("newly-created channel should always be open");
+        self.inner.remote_connections.write().insert(
+            remote_addr.clone(),
+            RemoteInfo {
+                sender: input_tx,
+                subscribed_topics: RwLock::new(FnvHashSet::default()),
+            },
+        );
+
+        // Combine the socket read and the outgoing messages input, so that we can wake up when
+        // either happens.
+        let messages = input_rx
+            .map(|m| (m, MessageSource::FromChannel))
+            .map_err(|_| unreachable!("channel streams should never produce an error"))
+            .select(gossipsub_stream.map(|m| (m, MessageSource::FromSocket)));
+
+        #[derive(Debug)]
+        enum MessageSource {
+            FromSocket,
+            FromChannel,
+        }
+
+        let inner = self.inner.clone();
+        let future = future::loop_fn(
+            (gossipsub_sink, messages),
+            move |(gossipsub_sink, messages)| {
+                let inner = inner.clone();
+                let remote_addr = remote_addr.clone();
+
+                messages
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .and_then(move |(input, rest)| {
+                        match input {
+                            Some((bytes, MessageSource::FromSocket)) => {
+                                // Received a packet from the remote.
+                                let fut = match handle_packet_received(bytes, &inner, &remote_addr) {
+                                    Ok(()) => {
+                                        future::ok(future::Loop::Continue((gossipsub_sink, rest)))
+                                    }
+                                    Err(err) => future::err(err),
+                                };
+                                Box::new(fut) as Box<_>
+                            }
+
+                            Some((bytes, MessageSource::FromChannel)) => {
+                                // Received a packet from the channel.
+                                // Need to send a message to remote.
+                                trace!("Effectively sending message to remote");
+                                let future = gossipsub_sink.send(bytes).map(|gossipsub_sink| {
+                                    future::Loop::Continue((gossipsub_sink, rest))
+                                });
+                                Box::new(future) as Box<_>
+                            }
+
+                            None => {
+                                // Both the connection stream and `rx` are empty, so we break
+                                // the loop.
+                                trace!("Gossipsub future clean finish");
+                                inner.remove_remote(&remote_addr);
+                                let future = future::ok(future::Loop::Break(()));
+                                Box::new(future) as Box<Future<Item = _, Error = _> + Send>
+                            }
+                        }
+                    })
+            },
+        );
+
+        let future = future::ok(GossipSubFuture {
+            inner: Box::new(future) as Box<_>,
+        });
+
+        Box::new(future) as Box<_>
+    }
+}
+
+/// Allows one to control the behaviour of the gossipsub system.
+#[derive(Clone)]
+pub struct GossipSubController {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // Our local peer ID multihash, to pass as the source.
+    peer_id: Vec<u8>,
+
+    // Configuration of the system.
+    config: GossipSubConfig,
+
+    // Decides which remotes are desirable in the meshes.
+    scoring: Box<PeerScoring>,
+
+    // Channel where to send the messages that should be dispatched to the user.
+    output_tx: mpsc::UnboundedSender<Message>,
+
+    // Active connections with a remote.
+    remote_connections: RwLock<FnvHashMap<Multiaddr, RemoteInfo>>,
+
+    // List of topics we're subscribed to. Necessary in order to filter out messages that we
+    // erroneously receive.
+    subscribed_topics: RwLock<Vec<Topic>>,
+
+    // Channels of the `GossipSubSubscription`s of each topic.
+    topic_senders: Mutex<FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<Message>>>>,
+
+    // For each topic we're subscribed to, the remotes we forward messages to.
+    mesh: Mutex<FnvHashMap<TopicHash, FnvHashSet<Multiaddr>>>,
+
+    // For each topic we publish to without being subscribed, the remotes we send messages to and
+    // the last time we published.
+    fanout: Mutex<FnvHashMap<TopicHash, (FnvHashSet<Multiaddr>, Instant)>>,
+
+    // Recently-seen messages.
+    mcache: Mutex<MessageCache>,
+
+    // Sequence number for the messages we send.
+    seq_no: AtomicUsize,
+
+    // IDs of the messages we received, so that we don't dispatch the same message twice.
+    received: Mutex<SeenCache<String>>,
+
+    // Duplicates sent by each remote. Only used in adaptive mode.
+    duplicates: Mutex<DuplicateTracker>,
+
+    // Hooks that trace the propagation of the messages, if any.
+    tracer: RwLock<Option<Arc<MessageTracer>>>,
+
+    // Source of the random numbers used to select the remotes.
+    random: RwLock<RandomSource>,
+}
+
+// > **Note**: In order to avoid deadlocks, the code of this module never holds more than one of
+// >           the locks of `Inner` at the same time, with the exception of `remote_connections`
+// >           and the `subscribed_topics` of the individual remotes.
+
+struct RemoteInfo {
+    // Sender to send data over the socket to that host.
+    sender: mpsc::UnboundedSender<BytesMut>,
+    // Topics the remote is registered to.
+    subscribed_topics: RwLock<FnvHashSet<TopicHash>>,
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Inner")
+            .field("peer_id", &self.peer_id)
+            .field("config", &self.config)
+            .field(
+                "num_remote_connections",
+                &self.remote_connections.read().len(),
+            )
+            .field("subscribed_topics", &*self.subscribed_topics.read())
+            .field("mesh", &*self.mesh.lock())
+            .field("seq_no", &self.seq_no)
+            .finish()
+    }
+}
+
+impl Inner {
+    // Returns true if we are subscribed to the given topic.
+    fn is_subscribed(&self, topic: &TopicHash) -> bool {
+        self.subscribed_topics.read().iter().any(|t| t.hash() == topic)
+    }
+
+    // Sends an `RPC` to the given remote. Does nothing if we are not connected to it.
+    fn send_rpc(&self, remote: &Multiaddr, rpc: &rpc_proto::RPC) {
+        let bytes = rpc
+            .write_to_bytes()
+            .expect("protobuf message is always valid");
+
+        if let Some(info) = self.remote_connections.read().get(remote) {
+            if info.sender.unbounded_send(bytes.into()).is_err() {
+                trace!("Failed to dispatch message to {} because channel was closed", remote);
+            }
+        }
+    }
+
+    // Returns the tracer, if any. The `Arc` is cloned so that the lock isn't held while tracing.
+    fn tracer(&self) -> Option<Arc<MessageTracer>> {
+        self.tracer.read().clone()
+    }
+
+    // Returns, for each topic, the list of remotes that are subscribed to it.
+    fn topic_peers(&self) -> FnvHashMap<TopicHash, Vec<Multiaddr>> {
+        let mut out = FnvHashMap::<_, Vec<_>>::default();
+        for (addr, info) in self.remote_connections.read().iter() {
+            for topic in info.subscribed_topics.read().iter() {
+                out.entry(topic.clone()).or_insert_with(Vec::new).push(addr.clone());
+            }
+        }
+        out
+    }
+
+    // Picks up to `count` remotes among `candidates` that are not in `exclude` and that have a
+    // non-negative score. Remotes with a higher score are preferred, and ties are broken
+    // randomly.
+    fn select_peers(&self, candidates: &[Multiaddr], count: usize, exclude: &FnvHashSet<Multiaddr>)
+        -> Vec<Multiaddr>
+    {
+        let mut list = candidates
+            .iter()
+            .filter(|addr| !exclude.contains(addr))
+            .map(|addr| (self.scoring.score(addr), addr.clone()))
+            .filter(|&(score, _)| score >= 0.0)
+            .collect::<Vec<_>>();
+        self.random.read().shuffle(&mut list);
+        list.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(::std::cmp::Ordering::Equal));
+        list.into_iter().take(count).map(|(_, addr)| addr).collect()
+    }
+
+    // Removes all the information about a remote after it disconnected.
+    fn remove_remote(&self, remote: &Multiaddr) {
+        // TODO: what if multiple connections?
+        self.remote_connections.write().remove(remote);
+        for peers in self.mesh.lock().values_mut() {
+            peers.remove(remote);
+        }
+        for &mut (ref mut peers, _) in self.fanout.lock().values_mut() {
+            peers.remove(remote);
+        }
+        self.duplicates.lock().remove(remote);
+    }
+}
+
+impl GossipSubController {
+    /// Builds a new controller for gossipsub.
+    #[inline]
+    pub fn new(upgrade: &GossipSubUpgrade) -> Self {
+        GossipSubController {
+            inner: upgrade.inner.clone(),
+        }
+    }
+
+    /// Subscribe to a topic. When a node on the network sends a message for that topic, we will
+    /// likely receive it.
+    ///
+    /// It is not guaranteed that we receive every single message published on the network.
+    ///
+    /// Returns a handle that produces the messages of this topic. The messages are also produced
+    /// by the `GossipSubReceiver`, which can be dropped if only the handles are used.
+    pub fn subscribe(&self, topic: &Topic) -> GossipSubSubscription {
+        let (tx, rx) = mpsc::unbounded();
+        self.inner.topic_senders.lock()
+            .entry(topic.hash().clone())
+            .or_insert_with(Vec::new)
+            .push(tx);
+        self.subscribe_many(iter::once(topic));
+
+        GossipSubSubscription {
+            topic: topic.clone(),
+            receiver: rx,
+            controller: self.clone(),
+        }
+    }
+
+    /// Same as `subscribe`, but subscribes to multiple topics at once.
+    #[inline]
+    pub fn subscribe_many<'a, I>(&self, topics: I)
+    where
+        I: IntoIterator<Item = &'a Topic>,
+    {
+        // This function exists for convenience.
+        self.sub_unsub_multi(topics.into_iter().map::<_, fn(_) -> _>(|t| (t, true)))
+    }
+
+    /// Unsubscribe from a topic. We will no longer receive any message for this topic, and the
+    /// `GossipSubSubscription`s of this topic end.
+    #[inline]
+    pub fn unsubscribe(&self, topic: &Topic) {
+        // This function exists for convenience.
+        self.unsubscribe_many(iter::once(topic));
+    }
+
+    /// Same as `unsubscribe` but unsubscribes from multiple topics at once.
+    #[inline]
+    pub fn unsubscribe_many<'a, I>(&self, topics: I)
+    where
+        I: IntoIterator<Item = &'a Topic>,
+    {
+        // This function exists for convenience.
+        self.sub_unsub_multi(topics.into_iter().map::<_, fn(_) -> _>(|t| (t, false)));
+    }
+
+    // Inner implementation. The iterator should produce a boolean that is true if we subscribe and
+    // false if we unsubscribe.
+    //
+    // Subscribing to a topic also builds its mesh out of the fanout peers of this topic and of
+    // random remotes subscribed to it, and sends them a `GRAFT`. Unsubscribing sends a `PRUNE` to
+    // the mesh of the topic.
+    fn sub_unsub_multi<'a, I>(&self, topics: I)
+    where
+        I: IntoIterator<Item = (&'a Topic, bool)>,
+    {
+        let mut proto = rpc_proto::RPC::new();
+        let mut control = FnvHashMap::<Multiaddr, rpc_proto::ControlMessage>::default();
+        let topic_peers = self.inner.topic_peers();
+
+        for (topic, subscribe) in topics {
+            debug!("Queuing {} message for {:?}", if subscribe { "sub" } else { "unsub" },
+                   topic.hash());
+
+            let mut subscription = rpc_proto::RPC_SubOpts::new();
+            subscription.set_subscribe(subscribe);
+            subscription.set_topicid(topic.hash().clone().into_string());
+            proto.mut_subscriptions().push(subscription);
+
+            if subscribe {
+                {
+                    let mut subscribed_topics = self.inner.subscribed_topics.write();
+                    if subscribed_topics.iter().any(|t| t.hash() == topic.hash()) {
+                        continue;
+                    }
+                    subscribed_topics.push(topic.clone());
+                }
+
+                let mut peers = self.inner.fanout.lock()
+                    .remove(topic.hash())
+                    .map(|(peers, _)| peers)
+                    .unwrap_or_default();
+                let missing = self.inner.config.mesh_n.saturating_sub(peers.len());
+                if missing > 0 {
+                    let candidates = topic_peers.get(topic.hash()).map(|v| &v[..]).unwrap_or(&[]);
+                    let selected = self.inner.select_peers(candidates, missing, &peers);
+                    peers.extend(selected);
+                }
+
+                for peer in peers.iter() {
+                    let mut graft = rpc_proto::ControlGraft::new();
+                    graft.set_topicID(topic.hash().clone().into_string());
+                    control.entry(peer.clone()).or_insert_with(Default::default)
+                        .mut_graft().push(graft);
+                }
+
+                self.inner.mesh.lock().insert(topic.hash().clone(), peers);
+
+            } else {
+                self.inner.subscribed_topics.write().retain(|t| t.hash() != topic.hash());
+                self.inner.topic_senders.lock().remove(topic.hash());
+                let peers = self.inner.mesh.lock().remove(topic.hash()).unwrap_or_default();
+                for peer in peers {
+                    let mut prune = rpc_proto::ControlPrune::new();
+                    prune.set_topicID(topic.hash().clone().into_string());
+                    control.entry(peer).or_insert_with(Default::default)
+                        .mut_prune().push(prune);
+                }
+            }
+        }
+
+        let remotes = self.inner.remote_connections.read().keys().cloned().collect::<Vec<_>>();
+        for remote in remotes {
+            let mut rpc = proto.clone();
+            if let Some(control) = control.remove(&remote) {
+                rpc.set_control(control);
+            }
+            self.inner.send_rpc(&remote, &rpc);
+        }
+    }
+
+    /// Publishes a message on the network for the specified topic.
+    #[inline]
+    pub fn publish(&self, topic: &Topic, data: Vec<u8>) {
+        // This function exists for convenience.
+        self.publish_many(iter::once(topic), data)
+    }
+
+    /// Publishes a message on the network for the specified topics.
+    ///
+    /// The message is sent to the mesh of the topics we are subscribed to, and to the fanout
+    /// remotes of the other topics.
+    ///
+    /// > **Note**: The message is dropped if the packet would exceed the maximum message size of
+    /// >           the configuration.
+    pub fn publish_many<'a, I>(&self, topics: I, data: Vec<u8>)
+    where
+        I: IntoIterator<Item = &'a Topic>,
+    {
+        let topics = topics.into_iter().collect::<Vec<_>>();
+
+        debug!("Queueing publish message ; topics = {:?} ; data_len = {:?}",
+               topics.iter().map(|t| t.hash().clone().into_string()).collect::<Vec<_>>(),
+               data.len());
+
+        // Build the `Vec<u8>` containing our sequence number for this message.
+        let seq_no_bytes = {
+            let mut seqno_bytes = Vec::new();
+            let seqn = self.inner.seq_no.fetch_add(1, Ordering::Relaxed);
+            seqno_bytes
+                .write_u64::<BigEndian>(seqn as u64)
+                .expect("writing to a Vec never fails");
+            seqno_bytes
+        };
+
+        let mut msg = rpc_proto::Message::new();
+        msg.set_data(data);
+        msg.set_from(self.inner.peer_id.clone());
+        msg.set_seqno(seq_no_bytes);
+        msg.set_topicIDs(
+            topics
+                .iter()
+                .map(|t| t.hash().clone().into_string())
+                .collect(),
+        );
+
+        let size = msg.compute_size() as usize;
+        if size > self.inner.config.max_message_size {
+            warn!("Dropping published message of {} bytes ; exceeds the maximum message size", size);
+            return;
+        }
+
+        // Insert into `received` so that we ignore the message if a remote sends it back to us.
+        let id = message_id(&msg);
+        self.inner.received.lock().insert(id.clone());
+        self.inner.mcache.lock().put(id.clone(), msg.clone());
+        let tracer = self.inner.tracer();
+        if let Some(ref tracer) = tracer {
+            let hashes = topics.iter().map(|t| t.hash().clone()).collect::<Vec<_>>();
+            tracer.published(&id, &hashes);
+        }
+
+        let mut recipients = FnvHashSet::default();
+        let topic_peers = self.inner.topic_peers();
+        for topic in topics.iter() {
+            if let Some(peers) = self.inner.mesh.lock().get(topic.hash()) {
+                recipients.extend(peers.iter().cloned());
+                continue;
+            }
+
+            let mut fanout = self.inner.fanout.lock();
+            let entry = fanout
+                .entry(topic.hash().clone())
+                .or_insert_with(|| (FnvHashSet::default(), Instant::now()));
+            entry.1 = Instant::now();
+            if entry.0.is_empty() {
+                let candidates = topic_peers.get(topic.hash()).map(|v| &v[..]).unwrap_or(&[]);
+                let selected = self.inner.select_peers(candidates, self.inner.config.mesh_n, &entry.0);
+                entry.0.extend(selected);
+            }
+            recipients.extend(entry.0.iter().cloned());
+        }
+
+        let mut proto = rpc_proto::RPC::new();
+        proto.mut_publish().push(msg);
+        for recipient in recipients.iter() {
+            self.inner.send_rpc(recipient, &proto);
+            if let Some(ref tracer) = tracer {
+                tracer.forwarded(&id, recipient);
+            }
+        }
+
+        debug!("Message queued for {} remotes", recipients.len());
+    }
+
+    /// Reports the messages we publish, receive and forward to the given tracer, replacing the
+    /// previous one.
+    pub fn set_tracer<T>(&self, tracer: T)
+    where
+        T: MessageTracer + 'static,
+    {
+        *self.inner.tracer.write() = Some(Arc::new(tracer));
+    }
+
+    /// Draws the random numbers used to select the remotes of the meshes and of the gossip from
+    /// the given source, for example the one of the swarm's `Runtime`.
+    #[inline]
+    pub fn set_random_source(&self, random: RandomSource) {
+        *self.inner.random.write() = random;
+    }
+
+    /// Returns the list of remotes that are part of the mesh of the given topic.
+    ///
+    /// In adaptive mode, these are the remotes to which we eagerly push messages.
+    pub fn mesh_peers(&self, topic: &TopicHash) -> Vec<Multiaddr> {
+        self.inner.mesh.lock()
+            .get(topic)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Performs the periodic maintenance of the system.
+    ///
+    /// This prunes undesirable remotes and grafts or prunes remotes so that the size of each mesh
+    /// stays between `mesh_n_low` and `mesh_n_high`, sends `IHAVE` gossip to remotes outside of
+    /// the meshes, expires the fanout topics, and shifts the message cache.
+    ///
+    /// Should be called every `heartbeat_interval`, or use `heartbeat_future` instead.
+    pub fn heartbeat(&self) {
+        heartbeat(&self.inner)
+    }
+
+    /// Returns a future that calls `heartbeat` every `heartbeat_interval`. Must be driven in
+    /// order for the meshes to be maintained.
+    pub fn heartbeat_future(&self) -> GossipSubHeartbeat {
+        let interval = self.inner.config.heartbeat_interval;
+        GossipSubHeartbeat {
+            inner: self.inner.clone(),
+            interval: Interval::new(Instant::now() + interval, interval),
+        }
+    }
+}
+
+/// Handle to a subscription to a topic, returned by `GossipSubController::subscribe`.
+///
+/// Implements `Stream` and produces the messages received for this topic. The stream ends once
+/// we unsubscribe from the topic.
+///
+/// > **Note**: Dropping the handle doesn't unsubscribe from the topic. Call `unsubscribe`
+/// >           instead.
+pub struct GossipSubSubscription {
+    topic: Topic,
+    receiver: mpsc::UnboundedReceiver<Message>,
+    controller: GossipSubController,
+}
+
+impl GossipSubSubscription {
+    /// Returns the topic of the subscription.
+    #[inline]
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    /// Unsubscribes from the topic. Equivalent to calling `GossipSubController::unsubscribe`.
+    #[inline]
+    pub fn unsubscribe(self) {
+        self.controller.unsubscribe(&self.topic);
+    }
+}
+
+impl Stream for GossipSubSubscription {
+    type Item = Message;
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.receiver
+            .poll()
+            .map_err(|_| unreachable!("UnboundedReceiver cannot err"))
+    }
+}
+
+impl fmt::Debug for GossipSubSubscription {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GossipSubSubscription")
+            .field("topic", self.topic.hash())
+            .finish()
+    }
+}
+
+/// Implementation of `Stream` that provides messages for the subscribed topics you subscribed to.
+pub struct GossipSubReceiver {
+    inner: mpsc::UnboundedReceiver<Message>,
+}
+
+impl Stream for GossipSubReceiver {
+    type Item = Message;
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner
+            .poll()
+            .map_err(|_| unreachable!("UnboundedReceiver cannot err"))
+    }
+}
+
+impl fmt::Debug for GossipSubReceiver {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GossipSubReceiver").finish()
+    }
+}
+
+/// Implementation of `Future` that must be driven to completion in order for gossipsub to work.
+#[must_use = "futures do nothing unless polled"]
+pub struct GossipSubFuture {
+    inner: Box<Future<Item = (), Error = IoError> + Send>,
+}
+
+impl Future for GossipSubFuture {
+    type Item = ();
+    type Error = IoError;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl fmt::Debug for GossipSubFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GossipSubFuture").finish()
+    }
+}
+
+/// Future that periodically performs the heartbeat of the gossipsub system. Never finishes.
+#[must_use = "futures do nothing unless polled"]
+pub struct GossipSubHeartbeat {
+    inner: Arc<Inner>,
+    interval: Interval,
+}
+
+impl Future for GossipSubHeartbeat {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(_))) => heartbeat(&self.inner),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => return Err(IoError::new(IoErrorKind::Other, err)),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for GossipSubHeartbeat {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GossipSubHeartbeat").finish()
+    }
+}
+
+// Sends a message to the `GossipSubSubscription`s of its topics, and forgets the subscriptions
+// whose handle has been dropped.
+fn dispatch_to_subscriptions(
+    senders: &mut FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<Message>>>,
+    message: &Message,
+) {
+    for topic in message.topics.iter() {
+        if let Some(list) = senders.get_mut(topic) {
+            list.retain(|tx| tx.unbounded_send(message.clone()).is_ok());
+        }
+    }
+}
+
+// Adaptive mode. Prunes a remote that sends us too many duplicates from the meshes of the given
+// topics, unless the mesh would fall below its minimum size. The `PRUNE`s are added to
+// `response`.
+fn switch_to_lazy(
+    inner: &Inner,
+    remote_addr: &Multiaddr,
+    topics: &[String],
+    config: &AdaptiveConfig,
+    response: &mut rpc_proto::RPC,
+) {
+    let mut mesh = inner.mesh.lock();
+    for topic in topics {
+        let peers = match mesh.get_mut(&TopicHash::from_raw(topic.clone())) {
+            Some(peers) => peers,
+            None => continue,
+        };
+        if peers.len() <= config.min_eager || !peers.remove(remote_addr) {
+            continue;
+        }
+
+        debug!("Switching {} to lazy pull for {} because of duplicates", remote_addr, topic);
+        let mut prune = rpc_proto::ControlPrune::new();
+        prune.set_topicID(topic.clone());
+        response.mut_control().mut_prune().push(prune);
+    }
+}
+
+// Adaptive mode. Grafts a remote that advertised messages we are missing into the meshes of the
+// given topics. The `GRAFT`s are added to `response`.
+fn switch_to_eager(
+    inner: &Inner,
+    remote_addr: &Multiaddr,
+    topics: Vec<TopicHash>,
+    response: &mut rpc_proto::RPC,
+) {
+    if inner.scoring.score(remote_addr) < 0.0 {
+        return;
+    }
+
+    let mut mesh = inner.mesh.lock();
+    for topic in topics {
+        let inserted = match mesh.get_mut(&topic) {
+            Some(peers) => peers.insert(remote_addr.clone()),
+            None => false,
+        };
+        if !inserted {
+            continue;
+        }
+
+        debug!("Switching {} to eager push for {:?} because it is ahead of us", remote_addr, topic);
+        let mut graft = rpc_proto::ControlGraft::new();
+        graft.set_topicID(topic.into_string());
+        response.mut_control().mut_graft().push(graft);
+    }
+}
+
+// Builds the identifier of a message, which is the concatenation of its source and sequence
+// number.
+// This is also the identifier reported to the `MessageTracer`.
+fn message_id(msg: &rpc_proto::Message) -> String {
+    trace_id(msg.get_from(), msg.get_seqno())
+}
+
+// Performs a heartbeat. See `GossipSubController::heartbeat`.
+fn heartbeat(inner: &Inner) {
+    trace!("Gossipsub heartbeat");
+
+    let config = &inner.config;
+    let topic_peers = inner.topic_peers();
+    // In adaptive mode, the meshes are only kept at their minimum size by the heartbeat, and every
+    // remote outside of a mesh receives gossip.
+    let (mesh_n_low, mesh_n, gossip_lazy) = match config.adaptive {
+        Some(ref adaptive) => (adaptive.min_eager, adaptive.min_eager, usize::max_value()),
+        None => (config.mesh_n_low, config.mesh_n, config.gossip_lazy),
+    };
+    let mut control = FnvHashMap::<Multiaddr, rpc_proto::ControlMessage>::default();
+    // Topics for which we send gossip, alongside with the peers not to send gossip to.
+    let mut gossip_topics = Vec::new();
+
+    {
+        let mut mesh = inner.mesh.lock();
+        for (topic, peers) in mesh.iter_mut() {
+            let topic_str = topic.clone().into_string();
+            let mut to_prune: SmallVec<[Multiaddr; 8]> = SmallVec::new();
+
+            // Remove the remotes that are no longer subscribed or that are undesirable.
+            let subscribed = topic_peers.get(topic).map(|v| &v[..]).unwrap_or(&[]);
+            for peer in peers.iter() {
+                if !subscribed.contains(peer) || inner.scoring.score(peer) < 0.0 {
+                    to_prune.push(peer.clone());
+                }
+            }
+
+            // Prune the excess remotes, keeping the ones with the highest score.
+            if peers.len() - to_prune.len() > config.mesh_n_high {
+                let remaining = peers.iter()
+                    .filter(|p| !to_prune.contains(p))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let keep = inner.select_peers(&remaining, config.mesh_n, &FnvHashSet::default())
+                    .into_iter()
+                    .collect::<FnvHashSet<_>>();
+                to_prune.extend(remaining.into_iter().filter(|p| !keep.contains(p)));
+            }
+
+            for peer in to_prune {
+                peers.remove(&peer);
+                let mut prune = rpc_proto::ControlPrune::new();
+                prune.set_topicID(topic_str.clone());
+                control.entry(peer).or_insert_with(Default::default).mut_prune().push(prune);
+            }
+
+            // Graft new remotes if the mesh is too small.
+            if peers.len() < mesh_n_low {
+                let missing = mesh_n.saturating_sub(peers.len());
+                for peer in inner.select_peers(subscribed, missing, peers) {
+                    peers.insert(peer.clone());
+                    let mut graft = rpc_proto::ControlGraft::new();
+                    graft.set_topicID(topic_str.clone());
+                    control.entry(peer).or_insert_with(Default::default).mut_graft().push(graft);
+                }
+            }
+
+            gossip_topics.push((topic.clone(), peers.clone()));
+        }
+    }
+
+    {
+        let mut fanout = inner.fanout.lock();
+        let now = Instant::now();
+        fanout.retain(|_, &mut (_, last_pub)| now.duration_since(last_pub) < config.fanout_ttl);
+
+        for (topic, &mut (ref mut peers, _)) in fanout.iter_mut() {
+            let subscribed = topic_peers.get(topic).map(|v| &v[..]).unwrap_or(&[]);
+            peers.retain(|p| subscribed.contains(p) && inner.scoring.score(p) >= 0.0);
+            if peers.len() < config.mesh_n {
+                let missing = config.mesh_n.saturating_sub(peers.len());
+                let selected = inner.select_peers(subscribed, missing, peers);
+                peers.extend(selected);
+            }
+
+            gossip_topics.push((topic.clone(), peers.clone()));
+        }
+    }
+
+    // Send `IHAVE` to random remotes outside of the mesh or fanout.
+    {
+        let mut mcache = inner.mcache.lock();
+        for (topic, exclude) in gossip_topics {
+            let ids = mcache.get_gossip_ids(&topic);
+            if ids.is_empty() {
+                continue;
+            }
+
+            let subscribed = topic_peers.get(&topic).map(|v| &v[..]).unwrap_or(&[]);
+            for peer in inner.select_peers(subscribed, gossip_lazy, &exclude) {
+                let mut ihave = rpc_proto::ControlIHave::new();
+                ihave.set_topicID(topic.clone().into_string());
+                ihave.set_messageIDs(RepeatedField::from_vec(ids.clone()));
+                control.entry(peer).or_insert_with(Default::default).mut_ihave().push(ihave);
+            }
+        }
+
+        mcache.shift();
+    }
+
+    for (peer, control) in control {
+        let mut rpc = rpc_proto::RPC::new();
+        rpc.set_control(control);
+        inner.send_rpc(&peer, &rpc);
+    }
+}
+
+// Handles when a packet is received on a connection.
+//
+// - `bytes` contains the raw data.
+// - `remote_addr` is the address of the sender.
+fn handle_packet_received(
+    bytes: BytesMut,
+    inner: &Inner,
+    remote_addr: &Multiaddr,
+) -> Result<(), IoError> {
+    trace!("Received packet from {}", remote_addr);
+
+    if bytes.len() > inner.config.max_message_size {
+        debug!("Received packet of {} bytes from {} ; exceeds the maximum message size",
+               bytes.len(), remote_addr);
+        inner.scoring.invalid_message(remote_addr);
+        return Err(IoError::new(IoErrorKind::InvalidData, "packet too large"));
+    }
+
+    // Parsing attempt.
+    let mut input = match protobuf::parse_from_bytes::<rpc_proto::RPC>(&bytes) {
+        Ok(msg) => msg,
+        Err(err) => {
+            debug!("Failed to parse protobuf message ; err = {:?}", err);
+            inner.scoring.invalid_message(remote_addr);
+            return Err(err.into());
+        }
+    };
+
+    // Answer to send back to the remote.
+    let mut response = rpc_proto::RPC::new();
+    let tracer = inner.tracer();
+
+    // Update the topics the remote is subscribed to.
+    if !input.get_subscriptions().is_empty() {
+        let mut unsubscribed = Vec::new();
+
+        if let Some(remote) = inner.remote_connections.read().get(remote_addr) {
+            let mut topics = remote.subscribed_topics.write();
+            for subscription in input.mut_subscriptions().iter_mut() {
+                let topic = TopicHash::from_raw(subscription.take_topicid());
+                if subscription.get_subscribe() {
+                    trace!("Remote {} subscribed to {:?}", remote_addr, topic);
+                    topics.insert(topic);
+                } else {
+                    trace!("Remote {} unsubscribed from {:?}", remote_addr, topic);
+                    topics.remove(&topic);
+                    unsubscribed.push(topic);
+                }
+            }
+        }
+
+        for topic in unsubscribed {
+            if let Some(peers) = inner.mesh.lock().get_mut(&topic) {
+                peers.remove(remote_addr);
+            }
+            if let Some(&mut (ref mut peers, _)) = inner.fanout.lock().get_mut(&topic) {
+                peers.remove(remote_addr);
+            }
+        }
+    }
+
+    // Handle the messages coming from the remote.
+    for publish in input.take_publish().into_iter() {
+        let id = message_id(&publish);
+        let first_delivery = inner.received.lock().insert(id.clone());
+        inner.scoring.message_delivered(remote_addr, first_delivery);
+        if let Some(ref tracer) = tracer {
+            tracer.received(&id, remote_addr, first_delivery);
+        }
+        if let Some(ref adaptive) = inner.config.adaptive {
+            if inner.duplicates.lock().record(remote_addr, !first_delivery, adaptive) {
+                switch_to_lazy(inner, remote_addr, publish.get_topicIDs(), adaptive, &mut response);
+            }
+        }
+        if !first_delivery {
+            trace!("Skipping message because we had already received it ; payload = {} bytes",
+                   publish.get_data().len());
+            continue;
+        }
+
+        let peer_id = match PeerId::from_bytes(publish.get_from().to_vec()) {
+            Ok(id) => id,
+            Err(err) => {
+                trace!("Parsing PeerId failed: {:?}. Skipping.", err);
+                continue
+            }
+        };
+
+        let topics = publish
+            .get_topicIDs()
+            .iter()
+            .map(|h| TopicHash::from_raw(h.clone()))
+            .collect::<Vec<_>>();
+
+        trace!("Processing message for topics {:?} ; payload = {} bytes",
+               topics,
+               publish.get_data().len());
+
+        inner.mcache.lock().put(id.clone(), publish.clone());
+
+        // Forward the message to the meshes of its topics.
+        let mut recipients = FnvHashSet::default();
+        {
+            let mesh = inner.mesh.lock();
+            for topic in topics.iter() {
+                if let Some(peers) = mesh.get(topic) {
+                    recipients.extend(peers.iter().filter(|p| *p != remote_addr).cloned());
+                }
+            }
+        }
+        if !recipients.is_empty() {
+            let mut forward = rpc_proto::RPC::new();
+            forward.mut_publish().push(publish.clone());
+            for recipient in recipients {
+                trace!("Forwarding received message to {}", recipient);
+                inner.send_rpc(&recipient, &forward);
+                if let Some(ref tracer) = tracer {
+                    tracer.forwarded(&id, &recipient);
+                }
+            }
+        }
+
+        // Send the message locally if relevant.
+        if topics.iter().any(|t| inner.is_subscribed(t)) {
+            // Ignore if channel is closed.
+            trace!("Dispatching message locally");
+            let message = Message {
+                source: Protocol::P2p(peer_id.into()).into(),
+                data: publish.get_data().to_vec(),
+                topics: topics,
+            };
+            dispatch_to_subscriptions(&mut inner.topic_senders.lock(), &message);
+            let _ = inner.output_tx.unbounded_send(message);
+        } else {
+            trace!("Message not dispatched locally as we are not subscribed to any of the topics");
+        }
+    }
+
+    // Handle the control messages.
+    if input.has_control() {
+        let mut control = input.take_control();
+
+        let mut iwant = Vec::new();
+        // Topics for which the remote advertised a message we are missing.
+        let mut missing_topics = Vec::new();
+        // We check the subscriptions before locking `received`, as we only hold one lock at a time.
+        let ihaves = control.get_ihave()
+            .iter()
+            .map(|ihave| (TopicHash::from_raw(ihave.get_topicID().to_owned()), ihave))
+            .filter(|&(ref topic, _)| inner.is_subscribed(topic))
+            .collect::<Vec<_>>();
+        {
+            let received = inner.received.lock();
+            for (topic, ihave) in ihaves {
+                for id in ihave.get_messageIDs() {
+                    if !received.contains(id) && !iwant.contains(id) {
+                        iwant.push(id.clone());
+                        if !missing_topics.contains(&topic) {
+                            missing_topics.push(topic.clone());
+                        }
+                    }
+                }
+            }
+        }
+        if inner.config.adaptive.is_some() {
+            switch_to_eager(inner, remote_addr, missing_topics, &mut response);
+        }
+        if !iwant.is_empty() {
+            trace!("Requesting {} messages from {}", iwant.len(), remote_addr);
+            let mut msg = rpc_proto::ControlIWant::new();
+            msg.set_messageIDs(RepeatedField::from_vec(iwant));
+            response.mut_control().mut_iwant().push(msg);
+        }
+
+        {
+            let mcache = inner.mcache.lock();
+            for iwant in control.get_iwant() {
+                for id in iwant.get_messageIDs() {
+                    if let Some(msg) = mcache.get(id) {
+                        response.mut_publish().push(msg.clone());
+                        if let Some(ref tracer) = tracer {
+                            tracer.forwarded(id, remote_addr);
+                        }
+                    }
+                }
+            }
+        }
+
+        for mut graft in control.take_graft().into_iter() {
+            let topic = TopicHash::from_raw(graft.take_topicID());
+            let desirable = inner.scoring.score(remote_addr) >= 0.0;
+            if !desirable {
+                inner.scoring.graft_refused(remote_addr);
+            }
+
+            let accepted = desirable && inner.is_subscribed(&topic) && {
+                let mut mesh = inner.mesh.lock();
+                match mesh.get_mut(&topic) {
+                    Some(peers) => { peers.insert(remote_addr.clone()); true },
+                    None => false,
+                }
+            };
+
+            if !accepted {
+                trace!("Refusing graft from {} for {:?}", remote_addr, topic);
+                let mut prune = rpc_proto::ControlPrune::new();
+                prune.set_topicID(topic.into_string());
+                response.mut_control().mut_prune().push(prune);
+            }
+        }
+
+        for mut prune in control.take_prune().into_iter() {
+            let topic = TopicHash::from_raw(prune.take_topicID());
+            if let Some(peers) = inner.mesh.lock().get_mut(&topic) {
+                peers.remove(remote_addr);
+            }
+        }
+    }
+
+    if !response.get_publish().is_empty() || response.has_control() {
+        inner.send_rpc(remote_addr, &response);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+
+    fn new_upgrade(config: GossipSubConfig) -> (GossipSubUpgrade, GossipSubController) {
+        let local_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let (upgrade, _) = GossipSubUpgrade::new(local_id, config);
+        let controller = GossipSubController::new(&upgrade);
+        (upgrade, controller)
+    }
+
+    // Registers a fake remote subscribed to the given topic, and returns the receiving end of
+    // the messages we send to it.
+    fn add_remote(inner: &Inner, num: u32, topic: &Topic)
+        -> (Multiaddr, mpsc::UnboundedReceiver<BytesMut>)
+    {
+        let addr: Multiaddr = format!("/ip4/1.2.3.4/tcp/{}", num).parse().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut topics = FnvHashSet::default();
+        topics.insert(topic.hash().clone());
+        inner.remote_connections.write().insert(addr.clone(), RemoteInfo {
+            sender: tx,
+            subscribed_topics: RwLock::new(topics),
+        });
+        (addr, rx)
+    }
+
+    // Returns the RPCs that have been sent to a remote so far.
+    fn drain(rx: &mut mpsc::UnboundedReceiver<BytesMut>) -> Vec<rpc_proto::RPC> {
+        future::poll_fn(|| {
+            let mut out = Vec::new();
+            while let Async::Ready(Some(bytes)) = rx.poll().unwrap() {
+                out.push(protobuf::parse_from_bytes::<rpc_proto::RPC>(&bytes).unwrap());
+            }
+            Ok::<_, ()>(Async::Ready(out))
+        }).wait().unwrap()
+    }
+
+    fn control_rpc(control: rpc_proto::ControlMessage) -> BytesMut {
+        let mut rpc = rpc_proto::RPC::new();
+        rpc.set_control(control);
+        rpc.write_to_bytes().unwrap().into()
+    }
+
+    fn small_mesh_config() -> GossipSubConfig {
+        GossipSubConfig { mesh_n_low: 1, mesh_n: 2, mesh_n_high: 3, ..Default::default() }
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_config_rejected() {
+        let config = GossipSubConfig { history_length: 0, history_gossip: 0, ..Default::default() };
+        new_upgrade(config);
+    }
+
+    #[test]
+    fn heartbeat_maintains_mesh() {
+        let (upgrade, controller) = new_upgrade(small_mesh_config());
+        let topic = TopicBuilder::new("chat").build();
+        controller.subscribe(&topic);
+        assert!(controller.mesh_peers(topic.hash()).is_empty());
+
+        let mut remotes = (0 .. 5).map(|n| add_remote(&upgrade.inner, n, &topic)).collect::<Vec<_>>();
+
+        // The mesh is too small and gets filled up to `mesh_n`.
+        controller.heartbeat();
+        let mesh = controller.mesh_peers(topic.hash());
+        assert_eq!(mesh.len(), 2);
+        for &mut (ref addr, ref mut rx) in remotes.iter_mut() {
+            let grafted = drain(rx).iter().any(|rpc| !rpc.get_control().get_graft().is_empty());
+            assert_eq!(grafted, mesh.contains(addr));
+        }
+
+        // The mesh is too large and gets pruned down to `mesh_n`.
+        upgrade.inner.mesh.lock().insert(
+            topic.hash().clone(),
+            remotes.iter().map(|&(ref addr, _)| addr.clone()).collect(),
+        );
+        controller.heartbeat();
+        let mesh = controller.mesh_peers(topic.hash());
+        assert_eq!(mesh.len(), 2);
+        for &mut (ref addr, ref mut rx) in remotes.iter_mut() {
+            let pruned = drain(rx).iter().any(|rpc| !rpc.get_control().get_prune().is_empty());
+            assert_eq!(pruned, !mesh.contains(addr));
+        }
+    }
+
+    #[test]
+    fn heartbeat_gossips_outside_mesh() {
+        let (upgrade, controller) = new_upgrade(small_mesh_config());
+        let topic = TopicBuilder::new("chat").build();
+        let mut remotes = (0 .. 5).map(|n| add_remote(&upgrade.inner, n, &topic)).collect::<Vec<_>>();
+        controller.subscribe(&topic);
+        controller.publish(&topic, vec![1, 2, 3]);
+        for &mut (_, ref mut rx) in remotes.iter_mut() {
+            drain(rx);
+        }
+
+        controller.heartbeat();
+        let mesh = controller.mesh_peers(topic.hash());
+        let ids = upgrade.inner.mcache.lock().get_gossip_ids(topic.hash());
+        assert_eq!(ids.len(), 1);
+        for &mut (ref addr, ref mut rx) in remotes.iter_mut() {
+            let ihaves = drain(rx).iter()
+                .flat_map(|rpc| rpc.get_control().get_ihave().to_vec())
+                .collect::<Vec<_>>();
+            if mesh.contains(addr) {
+                assert!(ihaves.is_empty());
+            } else {
+                assert_eq!(ihaves.len(), 1);
+                assert_eq!(ihaves[0].get_messageIDs(), &ids[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn graft_and_prune() {
+        let (upgrade, controller) = new_upgrade(small_mesh_config());
+        let topic = TopicBuilder::new("chat").build();
+        let other = TopicBuilder::new("other").build();
+        controller.subscribe(&topic);
+        let (addr, mut rx) = add_remote(&upgrade.inner, 0, &topic);
+
+        // The graft for a topic we aren't subscribed to is answered with a prune.
+        let mut control = rpc_proto::ControlMessage::new();
+        for t in &[&topic, &other] {
+            let mut graft = rpc_proto::ControlGraft::new();
+            graft.set_topicID(t.hash().clone().into_string());
+            control.mut_graft().push(graft);
+        }
+        handle_packet_received(control_rpc(control), &upgrade.inner, &addr).unwrap();
+        assert_eq!(controller.mesh_peers(topic.hash()), vec![addr.clone()]);
+        let responses = drain(&mut rx);
+        assert_eq!(responses.len(), 1);
+        let prunes = responses[0].get_control().get_prune();
+        assert_eq!(prunes.len(), 1);
+        assert_eq!(prunes[0].get_topicID(), other.hash().clone().into_string());
+
+        // A prune removes the remote from the mesh.
+        let mut control = rpc_proto::ControlMessage::new();
+        let mut prune = rpc_proto::ControlPrune::new();
+        prune.set_topicID(topic.hash().clone().into_string());
+        control.mut_prune().push(prune);
+        handle_packet_received(control_rpc(control), &upgrade.inner, &addr).unwrap();
+        assert!(controller.mesh_peers(topic.hash()).is_empty());
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn ihave_and_iwant() {
+        let (upgrade, controller) = new_upgrade(small_mesh_config());
+        let topic = TopicBuilder::new("chat").build();
+        let other = TopicBuilder::new("other").build();
+        controller.subscribe(&topic);
+        controller.publish(&topic, vec![1, 2, 3]);
+        let known = upgrade.inner.mcache.lock().get_gossip_ids(topic.hash()).remove(0);
+        let (addr, mut rx) = add_remote(&upgrade.inner, 0, &topic);
+
+        // We only ask for the messages we haven't seen, and only for topics we're subscribed to.
+        let mut control = rpc_proto::ControlMessage::new();
+        let mut ihave = rpc_proto::ControlIHave::new();
+        ihave.set_topicID(topic.hash().clone().into_string());
+        ihave.set_messageIDs(RepeatedField::from_vec(vec![known.clone(), "unknown".to_owned()]));
+        control.mut_ihave().push(ihave);
+        let mut ihave = rpc_proto::ControlIHave::new();
+        ihave.set_topicID(other.hash().clone().into_string());
+        ihave.set_messageIDs(RepeatedField::from_vec(vec!["unsubscribed".to_owned()]));
+        control.mut_ihave().push(ihave);
+        handle_packet_received(control_rpc(control), &upgrade.inner, &addr).unwrap();
+        let responses = drain(&mut rx);
+        assert_eq!(responses.len(), 1);
+        let iwants = responses[0].get_control().get_iwant();
+        assert_eq!(iwants.len(), 1);
+        assert_eq!(iwants[0].get_messageIDs(), &["unknown".to_owned()]);
+
+        // We answer an IWANT with the messages we have in cache.
+        let mut control = rpc_proto::ControlMessage::new();
+        let mut iwant = rpc_proto::ControlIWant::new();
+        iwant.set_messageIDs(RepeatedField::from_vec(vec![known.clone(), "unknown".to_owned()]));
+        control.mut_iwant().push(iwant);
+        handle_packet_received(control_rpc(control), &upgrade.inner, &addr).unwrap();
+        let responses = drain(&mut rx);
+        assert_eq!(responses.len(), 1);
+        let publish = responses[0].get_publish();
+        assert_eq!(publish.len(), 1);
+        assert_eq!(message_id(&publish[0]), known);
+        assert_eq!(publish[0].get_data(), &[1, 2, 3]);
+    }
+}
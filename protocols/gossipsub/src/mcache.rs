@@ -0,0 +1,139 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use fnv::FnvHashMap;
+use libp2p_floodsub::TopicHash;
+use rpc_proto;
+use std::collections::VecDeque;
+
+/// Cache of the messages we recently saw, used to answer `IWANT` requests and to build the list
+/// of message IDs that we advertise with `IHAVE`.
+///
+/// Messages are grouped in windows. Each call to `shift` opens a new window and drops the oldest
+/// one once there are more than `history_length` windows.
+pub struct MessageCache {
+    // Messages of all the windows, indexed by message ID.
+    messages: FnvHashMap<String, rpc_proto::Message>,
+    // For each window, the list of message IDs and topics. The front is the most recent window.
+    history: VecDeque<Vec<(String, Vec<TopicHash>)>>,
+    // Number of windows to advertise in gossip.
+    gossip: usize,
+    // Total number of windows to keep.
+    history_length: usize,
+}
+
+impl MessageCache {
+    /// Creates a new empty cache.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `history_length` is zero or lower than `gossip`.
+    pub fn new(gossip: usize, history_length: usize) -> MessageCache {
+        assert!(history_length > 0, "the message cache must keep at least one window");
+        assert!(gossip <= history_length, "can't gossip about more windows than we keep");
+        let mut history = VecDeque::with_capacity(history_length);
+        history.push_front(Vec::new());
+        MessageCache {
+            messages: FnvHashMap::default(),
+            history,
+            gossip,
+            history_length,
+        }
+    }
+
+    /// Inserts a message in the current window.
+    pub fn put(&mut self, id: String, message: rpc_proto::Message) {
+        let topics = message
+            .get_topicIDs()
+            .iter()
+            .map(|t| TopicHash::from_raw(t.clone()))
+            .collect();
+        self.history
+            .front_mut()
+            .expect("history always contains at least one window ; qed")
+            .push((id.clone(), topics));
+        self.messages.insert(id, message);
+    }
+
+    /// Returns the message with the given ID, if it is still in the cache.
+    #[inline]
+    pub fn get(&self, id: &str) -> Option<&rpc_proto::Message> {
+        self.messages.get(id)
+    }
+
+    /// Returns the IDs of the messages of the gossip windows that belong to the given topic.
+    pub fn get_gossip_ids(&self, topic: &TopicHash) -> Vec<String> {
+        self.history
+            .iter()
+            .take(self.gossip)
+            .flat_map(|window| window.iter())
+            .filter(|&&(_, ref topics)| topics.iter().any(|t| t == topic))
+            .map(|&(ref id, _)| id.clone())
+            .collect()
+    }
+
+    /// Opens a new window, and drops the oldest one if necessary.
+    pub fn shift(&mut self) {
+        while self.history.len() >= self.history_length {
+            if let Some(window) = self.history.pop_back() {
+                for (id, _) in window {
+                    self.messages.remove(&id);
+                }
+            }
+        }
+
+        self.history.push_front(Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_floodsub::TopicHash;
+    use mcache::MessageCache;
+    use rpc_proto;
+    use protobuf::RepeatedField;
+
+    fn message(topic: &str) -> rpc_proto::Message {
+        let mut msg = rpc_proto::Message::new();
+        msg.set_topicIDs(RepeatedField::from_vec(vec![topic.to_owned()]));
+        msg
+    }
+
+    #[test]
+    fn gossip_ids_per_topic() {
+        let mut cache = MessageCache::new(3, 5);
+        cache.put("a".to_owned(), message("t1"));
+        cache.put("b".to_owned(), message("t2"));
+        assert_eq!(cache.get_gossip_ids(&TopicHash::from_raw("t1".to_owned())), vec!["a".to_owned()]);
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn shift_expires() {
+        let mut cache = MessageCache::new(1, 2);
+        cache.put("a".to_owned(), message("t"));
+        cache.shift();
+        // Still in the cache, but no longer gossiped.
+        assert!(cache.get("a").is_some());
+        assert!(cache.get_gossip_ids(&TopicHash::from_raw("t".to_owned())).is_empty());
+        cache.shift();
+        assert!(cache.get("a").is_none());
+    }
+}
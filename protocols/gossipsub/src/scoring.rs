@@ -0,0 +1,61 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use libp2p_core::Multiaddr;
+
+/// Hooks that allow the user to influence which remotes end up in the mesh.
+///
+/// The gossipsub system never removes remotes from the mesh or refuses a graft on its own based
+/// on their behaviour. Instead it calls `score` and treats remotes with a negative score as
+/// undesirable: they are pruned from the meshes at the next heartbeat, their grafts are refused,
+/// and remotes with higher scores are preferred when filling a mesh.
+///
+/// The other methods are notifications that the implementation can use to compute the score.
+pub trait PeerScoring: Send + Sync {
+    /// Returns the current score of the given remote.
+    fn score(&self, remote: &Multiaddr) -> f64;
+
+    /// Called when a remote sends us a message. `first_delivery` is true if it is the first time
+    /// we see this message.
+    #[inline]
+    fn message_delivered(&self, _remote: &Multiaddr, _first_delivery: bool) {
+    }
+
+    /// Called when a remote sends us a message that we failed to decode.
+    #[inline]
+    fn invalid_message(&self, _remote: &Multiaddr) {
+    }
+
+    /// Called when a remote sends a graft for a topic in which we consider it undesirable.
+    #[inline]
+    fn graft_refused(&self, _remote: &Multiaddr) {
+    }
+}
+
+/// Implementation of `PeerScoring` that gives the same score to every remote.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoScoring;
+
+impl PeerScoring for NoScoring {
+    #[inline]
+    fn score(&self, _: &Multiaddr) -> f64 {
+        0.0
+    }
+}
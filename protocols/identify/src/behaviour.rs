@@ -0,0 +1,335 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use handler::{IdentifyHandler, IdentifyHandlerEvent};
+use libp2p_core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::nodes::swarm::ConnectedPoint;
+use libp2p_core::upgrade::MessageLimits;
+use libp2p_core::{Multiaddr, PeerId, PublicKey};
+use libp2p_peerstore::{PeerAccess, Peerstore, TTL};
+use protocol::{IdentifyInfo, IdentifyProtocolConfig, IdentifySender};
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use std::fmt;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Event generated by the `Identify` behaviour.
+#[derive(Debug)]
+pub enum IdentifyEvent {
+    /// We obtained the information of a remote.
+    Identified {
+        /// The remote.
+        peer_id: PeerId,
+        /// Information sent by the remote: agent version, supported protocols and listen
+        /// addresses.
+        info: IdentifyInfo,
+        /// Address the remote sees for us.
+        observed_addr: Multiaddr,
+    },
+    /// We sent our information to a remote that asked for it.
+    Sent {
+        /// The remote.
+        peer_id: PeerId,
+    },
+    /// Failed to obtain the information of a remote or to send it ours.
+    Error {
+        /// The remote.
+        peer_id: PeerId,
+        /// The error that happened.
+        error: IoError,
+    },
+}
+
+/// `NetworkBehaviour` that exchanges information with every node we are connected to.
+///
+/// Our listen addresses are taken from the swarm every time we send our information, while our
+/// agent version and supported protocols can be modified at any time with `set_agent_version`
+/// and `set_protocols`.
+pub struct Identify<TSubstream> {
+    /// Public key of the local node.
+    public_key: PublicKey,
+    /// Version of the "global" protocol.
+    protocol_version: String,
+    /// Name and version of the local software.
+    agent_version: String,
+    /// Protocols we support.
+    protocols: Vec<String>,
+    /// Stores the information of the remotes, if any.
+    store: Option<Box<Fn(&PeerId, &IdentifyInfo) + Send>>,
+    /// Modifies the information we send to a remote and the address we report to it, if any.
+    rewrite: Option<Box<Fn(&PeerId, &mut IdentifyInfo, &mut Multiaddr) + Send>>,
+    /// Address of each node we are connected to. This is what we report as observed address.
+    connected: FnvHashMap<PeerId, Multiaddr>,
+    /// Requests for our information, answered during the next `poll`.
+    requests: Vec<(PeerId, IdentifySender<TSubstream>)>,
+    /// Our information being sent.
+    sending: Vec<(PeerId, Box<Future<Item = (), Error = IoError> + Send>)>,
+    /// Actions to return from `poll`.
+    actions: VecDeque<NetworkBehaviourAction<(), IdentifyEvent>>,
+    /// Configuration of the substreams of the handlers.
+    protocol_config: IdentifyProtocolConfig,
+}
+
+impl<TSubstream> Identify<TSubstream> {
+    /// Creates a new `Identify` behaviour. We don't advertise any protocol until `set_protocols`
+    /// is called.
+    pub fn new(public_key: PublicKey, protocol_version: String, agent_version: String) -> Self {
+        Identify {
+            public_key,
+            protocol_version,
+            agent_version,
+            protocols: Vec::new(),
+            store: None,
+            rewrite: None,
+            connected: FnvHashMap::default(),
+            requests: Vec::new(),
+            sending: Vec::new(),
+            actions: VecDeque::new(),
+            protocol_config: IdentifyProtocolConfig::new(),
+        }
+    }
+
+    /// Applies `limits` to the information the remotes send us.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.protocol_config = self.protocol_config.with_message_limits(limits);
+        self
+    }
+
+    /// Stores the information obtained from remotes in `peerstore`. The listen addresses are
+    /// stored with the given time-to-live. See `store_identify_info`.
+    pub fn with_peerstore<TPeerstore>(mut self, peerstore: Arc<TPeerstore>, ttl: TTL) -> Self
+    where
+        for<'a> &'a TPeerstore: Peerstore,
+        TPeerstore: Send + Sync + 'static,
+    {
+        self.store = Some(Box::new(move |peer_id, info| {
+            store_identify_info(&*peerstore, peer_id, info, ttl)
+        }));
+        self
+    }
+
+    /// Calls `rewrite` on the information we are about to send to a remote and on the address
+    /// we report having observed for it.
+    ///
+    /// > **Note**: This makes it possible to send different information to each remote, and is
+    /// >           mostly useful to simulate nodes that lie about themselves.
+    pub fn with_info_rewriter<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&PeerId, &mut IdentifyInfo, &mut Multiaddr) + Send + 'static,
+    {
+        self.rewrite = Some(Box::new(rewrite));
+        self
+    }
+
+    /// Returns the agent version we send to remotes.
+    #[inline]
+    pub fn agent_version(&self) -> &str {
+        &self.agent_version
+    }
+
+    /// Modifies the agent version we send to remotes. Only affects the information sent
+    /// afterwards.
+    #[inline]
+    pub fn set_agent_version(&mut self, agent_version: String) {
+        self.agent_version = agent_version;
+    }
+
+    /// Returns the protocols we report supporting.
+    #[inline]
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Modifies the protocols we report supporting. Only affects the information sent
+    /// afterwards.
+    #[inline]
+    pub fn set_protocols(&mut self, protocols: Vec<String>) {
+        self.protocols = protocols;
+    }
+}
+
+/// Stores the information sent by a remote in a peer store: its listen addresses with the given
+/// time-to-live, its agent version and its supported protocols.
+pub fn store_identify_info<P>(peerstore: P, peer_id: &PeerId, info: &IdentifyInfo, ttl: TTL)
+where
+    P: Peerstore,
+{
+    let mut peer = peerstore.peer_or_create(peer_id);
+    peer.add_addrs(info.listen_addrs.iter().cloned(), ttl);
+    peer.set_agent_version(info.agent_version.clone());
+    peer.set_protocols(info.protocols.clone());
+}
+
+impl<TSubstream> fmt::Debug for Identify<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Identify")
+            .field("agent_version", &self.agent_version)
+            .field("protocols", &self.protocols)
+            .field("connected", &self.connected.len())
+            .finish()
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Identify<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type ConnectionHandler = IdentifyHandler<TSubstream>;
+    type OutEvent = IdentifyEvent;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        IdentifyHandler::with_config(self.protocol_config.clone())
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        let remote_addr = match endpoint {
+            ConnectedPoint::Dialer { address } => address,
+            ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr,
+        };
+        self.connected.insert(peer_id, remote_addr);
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        self.connected.remove(peer_id);
+        self.requests.retain(|&(ref p, _)| p != peer_id);
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, event: IdentifyHandlerEvent<TSubstream>) {
+        match event {
+            IdentifyHandlerEvent::Identified { info, observed_addr } => {
+                if let Some(ref store) = self.store {
+                    store(&peer_id, &info);
+                }
+                self.actions.push_back(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Identified {
+                    peer_id,
+                    info,
+                    observed_addr,
+                }));
+            },
+            IdentifyHandlerEvent::InfoRequested { sender } => {
+                self.requests.push((peer_id, sender));
+            },
+            IdentifyHandlerEvent::IdentificationError(error) => {
+                self.actions.push_back(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Error {
+                    peer_id,
+                    error,
+                }));
+            },
+        }
+    }
+
+    fn poll(&mut self, params: &mut PollParameters) -> Async<NetworkBehaviourAction<(), IdentifyEvent>> {
+        if let Some(action) = self.actions.pop_front() {
+            return Async::Ready(action);
+        }
+
+        // Answer the requests for our information.
+        if !self.requests.is_empty() {
+            let listen_addrs = params.advertised_addresses();
+            for (peer_id, sender) in self.requests.drain(..) {
+                let mut observed_addr = match self.connected.get(&peer_id) {
+                    Some(addr) => addr.clone(),
+                    None => continue,
+                };
+
+                let mut info = IdentifyInfo {
+                    public_key: self.public_key.clone(),
+                    protocol_version: self.protocol_version.clone(),
+                    agent_version: self.agent_version.clone(),
+                    listen_addrs: listen_addrs.clone(),
+                    protocols: self.protocols.clone(),
+                };
+                if let Some(ref rewrite) = self.rewrite {
+                    rewrite(&peer_id, &mut info, &mut observed_addr);
+                }
+                self.sending.push((peer_id, sender.send(info, &observed_addr)));
+            }
+        }
+
+        // Drive the information being sent.
+        for n in (0 .. self.sending.len()).rev() {
+            let (peer_id, mut fut) = self.sending.swap_remove(n);
+            match fut.poll() {
+                Ok(Async::Ready(())) => {
+                    self.actions.push_back(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Sent { peer_id }));
+                },
+                Ok(Async::NotReady) => self.sending.push((peer_id, fut)),
+                Err(error) => {
+                    self.actions.push_back(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Error { peer_id, error }));
+                },
+            }
+        }
+
+        match self.actions.pop_front() {
+            Some(action) => Async::Ready(action),
+            None => Async::NotReady,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_peerstore::memory_peerstore::MemoryPeerstore;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn identified_updates_peerstore() {
+        let peerstore = Arc::new(MemoryPeerstore::empty());
+        let mut behaviour = Identify::<Cursor<Vec<u8>>>::new(
+            PublicKey::Ed25519(vec![1; 32]),
+            "proto_version".to_owned(),
+            "agent/1.0".to_owned(),
+        ).with_peerstore(peerstore.clone(), Duration::from_secs(60));
+
+        let peer_id = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+        let listen_addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+        behaviour.inject_node_event(peer_id.clone(), IdentifyHandlerEvent::Identified {
+            info: IdentifyInfo {
+                public_key: PublicKey::Ed25519(vec![2; 32]),
+                protocol_version: "proto_version".to_owned(),
+                agent_version: "agent/2.0".to_owned(),
+                listen_addrs: vec![listen_addr.clone()],
+                protocols: vec!["/ipfs/ping/1.0.0".to_owned()],
+            },
+            observed_addr: "/ip4/5.6.7.8/tcp/9".parse().unwrap(),
+        });
+
+        match behaviour.actions.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Identified { peer_id: p, info, .. })) => {
+                assert_eq!(p, peer_id);
+                assert_eq!(info.agent_version, "agent/2.0");
+            },
+            _ => panic!(),
+        }
+
+        let peer = (&*peerstore).peer(&peer_id).unwrap();
+        assert_eq!(peer.addrs().collect::<Vec<_>>(), vec![listen_addr]);
+        assert_eq!(peer.agent_version(), Some("agent/2.0".to_owned()));
+        assert_eq!(peer.protocols(), vec!["/ipfs/ping/1.0.0".to_owned()]);
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use futures::prelude::*;
+use libp2p_core::Multiaddr;
+use libp2p_core::nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use libp2p_core::nodes::handled_node::NodeHandlerEndpoint;
+use protocol::{IdentifyInfo, IdentifyOutput, IdentifyProtocolConfig, IdentifySender};
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::marker::PhantomData;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Event produced by the handler of the `Identify` behaviour.
+pub enum IdentifyHandlerEvent<TSubstream> {
+    /// The remote sent us its information.
+    Identified {
+        /// Information about the remote.
+        info: IdentifyInfo,
+        /// Address the remote sees for us.
+        observed_addr: Multiaddr,
+    },
+    /// The remote asks for our information. It must be sent through `sender`.
+    InfoRequested {
+        /// Object used to send our information to the remote.
+        sender: IdentifySender<TSubstream>,
+    },
+    /// Failed to obtain the information of the remote.
+    IdentificationError(IoError),
+}
+
+/// Connection handler of the `Identify` behaviour.
+///
+/// Asks the remote for its information once, right after the connection is opened, and reports
+/// the requests of the remote for our information.
+pub struct IdentifyHandler<TSubstream> {
+    /// Configuration of the substreams.
+    config: IdentifyProtocolConfig,
+    /// True if we have opened the substream that asks the remote for its information.
+    requested: bool,
+    /// True if we have obtained the information of the remote, or failed to.
+    finished: bool,
+    /// Events to report to the behaviour.
+    events: VecDeque<IdentifyHandlerEvent<TSubstream>>,
+    /// True if `shutdown()` has been called.
+    shutting_down: bool,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> IdentifyHandler<TSubstream> {
+    /// Builds a new `IdentifyHandler`.
+    #[inline]
+    pub fn new() -> Self {
+        IdentifyHandler::with_config(IdentifyProtocolConfig::new())
+    }
+
+    /// Builds a new `IdentifyHandler` that opens and accepts substreams with the given
+    /// configuration.
+    #[inline]
+    pub fn with_config(config: IdentifyProtocolConfig) -> Self {
+        IdentifyHandler {
+            config,
+            requested: false,
+            finished: false,
+            events: VecDeque::new(),
+            shutting_down: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> Clone for IdentifyHandler<TSubstream> {
+    #[inline]
+    fn clone(&self) -> Self {
+        IdentifyHandler::with_config(self.config.clone())
+    }
+}
+
+impl<TSubstream> ConnectionHandler for IdentifyHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type InEvent = ();
+    type OutEvent = IdentifyHandlerEvent<TSubstream>;
+    type Substream = TSubstream;
+    type Protocol = IdentifyProtocolConfig;
+    type OutboundOpenInfo = ();
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        self.config.clone()
+    }
+
+    fn inject_fully_negotiated(
+        &mut self,
+        output: IdentifyOutput<TSubstream>,
+        _: NodeHandlerEndpoint<()>,
+    ) {
+        match output {
+            IdentifyOutput::RemoteInfo { info, observed_addr } => {
+                self.finished = true;
+                self.events.push_back(IdentifyHandlerEvent::Identified { info, observed_addr });
+            },
+            IdentifyOutput::Sender { sender } => {
+                self.events.push_back(IdentifyHandlerEvent::InfoRequested { sender });
+            },
+        }
+    }
+
+    #[inline]
+    fn inject_event(&mut self, _: ()) {
+    }
+
+    fn inject_dial_upgrade_error(&mut self, _: (), error: &IoError) {
+        self.finished = true;
+        let error = IoError::new(error.kind(), error.to_string());
+        self.events.push_back(IdentifyHandlerEvent::IdentificationError(error));
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    #[inline]
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.finished {
+            KeepAlive::Now
+        } else {
+            KeepAlive::Forever
+        }
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, (), Self::OutEvent>>, IoError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(event))));
+        }
+
+        if self.shutting_down {
+            return Ok(Async::Ready(None));
+        }
+
+        if !self.requested {
+            self.requested = true;
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                upgrade: self.config.clone(),
+                info: (),
+            })));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind as IoErrorKind};
+
+    #[test]
+    fn requests_info_once() {
+        let mut handler = IdentifyHandler::<Cursor<Vec<u8>>>::new();
+        assert_eq!(handler.connection_keep_alive(), KeepAlive::Forever);
+
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest { .. }))) => (),
+            _ => panic!(),
+        }
+        match handler.poll() {
+            Ok(Async::NotReady) => (),
+            _ => panic!(),
+        }
+
+        handler.inject_dial_upgrade_error((), &IoErrorKind::ConnectionRefused.into());
+        assert_eq!(handler.connection_keep_alive(), KeepAlive::Now);
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(IdentifyHandlerEvent::IdentificationError(err))))) => {
+                assert_eq!(err.kind(), IoErrorKind::ConnectionRefused);
+            },
+            _ => panic!(),
+        }
+    }
+}
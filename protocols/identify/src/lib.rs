@@ -64,6 +64,32 @@
 //! will contain the information sent by the remote. If we are the listener, then it will contain
 //! a `IdentifySender` struct that can be used to transmit back to the remote the information about
 //! it.
+//!
+//! ## Usage through the `Identify` behaviour
+//!
+//! The `Identify` struct implements `NetworkBehaviour`. It exchanges information with every node
+//! we connect to and generates an `IdentifyEvent::Identified` event containing the agent version,
+//! the supported protocols and the listen addresses of the remote, plus the address it observes
+//! us on. Our own agent version and supported protocols can be changed at any time, and the
+//! information of the remotes can be stored automatically in a peer store with
+//! `Identify::with_peerstore`.
+//!
+//! ## Pushing updated information
+//!
+//! The `IdentifyPushProtocolConfig` struct negotiates the `/ipfs/id/push/1.0.0` protocol, where
+//! the roles are reversed: the dialer obtains an `IdentifySender` and pushes its own information
+//! to the listener. This should be done towards every connected node whenever the local listen
+//! addresses or supported protocols change.
+//!
+//! ## Aggregating observed addresses
+//!
+//! The address a remote reports observing us on is not reliable on its own. The `ObservedAddrs`
+//! struct collects these reports and only yields the addresses that have been confirmed by enough
+//! distinct remotes. These addresses should be passed to `Swarm::report_observed_address` of
+//! `libp2p-core`, which translates them into external addresses, and the `listen_addrs` of the `IdentifyInfo` we send should be filled with
+//! `Swarm::advertised_addresses()`, which contains the external addresses followed by the listen
+//! addresses. Behaviours can report an observed address with the
+//! `NetworkBehaviourAction::ReportObservedAddr` action instead.
 
 extern crate bytes;
 extern crate fnv;
@@ -75,12 +101,18 @@ extern crate log;
 extern crate multiaddr;
 extern crate parking_lot;
 extern crate protobuf;
-extern crate tokio_codec;
 extern crate tokio_io;
-extern crate unsigned_varint;
 
+pub use self::behaviour::{store_identify_info, Identify, IdentifyEvent};
+pub use self::handler::{IdentifyHandler, IdentifyHandlerEvent};
 pub use self::protocol::{IdentifyInfo, IdentifyOutput};
 pub use self::protocol::{IdentifyProtocolConfig, IdentifySender};
+pub use self::push::{IdentifyPushOutput, IdentifyPushProtocolConfig};
+pub use self::observed_addrs::ObservedAddrs;
 
+mod behaviour;
+mod handler;
+mod observed_addrs;
 mod protocol;
+mod push;
 mod structs_proto;
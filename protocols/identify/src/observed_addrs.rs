@@ -0,0 +1,154 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Aggregation of the addresses that remotes report observing us on.
+//!
+//! A single remote can lie or be mistaken about our address, and a remote on the same local
+//! network will report a local address. Therefore we only consider an observed address as one of
+//! our external addresses once enough distinct remotes have reported it.
+
+use fnv::{FnvHashMap, FnvHashSet};
+use libp2p_core::{Multiaddr, PeerId};
+
+/// Default number of distinct remotes that must report an address before it is advertised.
+pub const DEFAULT_CONFIDENCE_THRESHOLD: usize = 3;
+
+/// Collection of addresses that remotes report observing us on.
+#[derive(Debug, Clone)]
+pub struct ObservedAddrs {
+    // For each observed address, the list of remotes that have reported it.
+    addrs: FnvHashMap<Multiaddr, FnvHashSet<PeerId>>,
+    // Number of distinct reporters required before an address is considered confirmed.
+    threshold: usize,
+}
+
+impl ObservedAddrs {
+    /// Creates a new empty collection with the default confidence threshold.
+    #[inline]
+    pub fn new() -> ObservedAddrs {
+        ObservedAddrs::with_threshold(DEFAULT_CONFIDENCE_THRESHOLD)
+    }
+
+    /// Creates a new empty collection. An address will only be returned by `addresses()` once
+    /// `threshold` distinct remotes have reported it.
+    ///
+    /// A threshold of `0` is treated like a threshold of `1`.
+    #[inline]
+    pub fn with_threshold(threshold: usize) -> ObservedAddrs {
+        ObservedAddrs {
+            addrs: FnvHashMap::default(),
+            threshold: if threshold == 0 { 1 } else { threshold },
+        }
+    }
+
+    /// Records that `reporter` observes us on `observed`.
+    ///
+    /// Returns `true` if this report makes the address reach the confidence threshold for the
+    /// first time, in which case it should be advertised to the rest of the network (for example
+    /// through the identify push protocol).
+    pub fn add(&mut self, observed: Multiaddr, reporter: PeerId) -> bool {
+        let reporters = self.addrs.entry(observed).or_insert_with(Default::default);
+        let was_confirmed = reporters.len() >= self.threshold;
+        reporters.insert(reporter);
+        !was_confirmed && reporters.len() >= self.threshold
+    }
+
+    /// Removes all the reports made by `reporter`, for example because we disconnected from it.
+    ///
+    /// Addresses that are no longer reported by anyone are forgotten.
+    pub fn remove_reporter(&mut self, reporter: &PeerId) {
+        for reporters in self.addrs.values_mut() {
+            reporters.remove(reporter);
+        }
+
+        self.addrs.retain(|_, reporters| !reporters.is_empty());
+    }
+
+    /// Returns the number of distinct remotes that reported the given address.
+    #[inline]
+    pub fn confidence(&self, addr: &Multiaddr) -> usize {
+        self.addrs.get(addr).map(|r| r.len()).unwrap_or(0)
+    }
+
+    /// Returns the addresses that have reached the confidence threshold, ordered by decreasing
+    /// confidence.
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        let mut list = self.addrs
+            .iter()
+            .filter(|(_, reporters)| reporters.len() >= self.threshold)
+            .map(|(addr, reporters)| (addr.clone(), reporters.len()))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.1.cmp(&a.1));
+        list.into_iter().map(|(addr, _)| addr).collect()
+    }
+
+    /// Returns all the observed addresses, including the ones that haven't reached the
+    /// threshold, alongside with their confidence.
+    pub fn all_addresses(&self) -> impl Iterator<Item = (&Multiaddr, usize)> {
+        self.addrs.iter().map(|(addr, reporters)| (addr, reporters.len()))
+    }
+}
+
+impl Default for ObservedAddrs {
+    #[inline]
+    fn default() -> Self {
+        ObservedAddrs::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{PeerId, PublicKey};
+    use observed_addrs::ObservedAddrs;
+
+    fn peer(n: u8) -> PeerId {
+        PublicKey::Rsa(vec![n]).into_peer_id()
+    }
+
+    #[test]
+    fn threshold_reached() {
+        let mut addrs = ObservedAddrs::with_threshold(2);
+        let addr = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+
+        assert!(!addrs.add(addr.clone(), peer(1)));
+        assert!(addrs.addresses().is_empty());
+        // Same reporter twice doesn't increase the confidence.
+        assert!(!addrs.add(addr.clone(), peer(1)));
+        assert_eq!(addrs.confidence(&addr), 1);
+        assert!(addrs.add(addr.clone(), peer(2)));
+        assert_eq!(addrs.addresses(), vec![addr.clone()]);
+        // Only the first time the threshold is reached returns `true`.
+        assert!(!addrs.add(addr.clone(), peer(3)));
+    }
+
+    #[test]
+    fn remove_reporter() {
+        let mut addrs = ObservedAddrs::with_threshold(1);
+        let addr1 = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+        let addr2 = "/ip4/1.2.3.4/tcp/6000".parse().unwrap();
+
+        addrs.add(addr1, peer(1));
+        addrs.add(addr2.clone(), peer(1));
+        addrs.add(addr2.clone(), peer(2));
+        addrs.remove_reporter(&peer(1));
+        assert_eq!(addrs.addresses(), vec![addr2.clone()]);
+        assert_eq!(addrs.confidence(&addr2), 1);
+    }
+}
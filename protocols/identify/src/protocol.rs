@@ -18,22 +18,42 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use bytes::{Bytes, BytesMut};
-use futures::{future, Future, Sink, Stream};
+use bytes::Bytes;
+use futures::{future, Future};
 use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr, PublicKey};
+use libp2p_core::upgrade::{self, MessageLimits};
 use protobuf::Message as ProtobufMessage;
 use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
 use protobuf::RepeatedField;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::iter;
 use structs_proto;
-use tokio_codec::Framed;
 use tokio_io::{AsyncRead, AsyncWrite};
-use unsigned_varint::codec;
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/ipfs/id/1.0.0";
 
 /// Configuration for an upgrade to the identity protocol.
-#[derive(Debug, Clone)]
-pub struct IdentifyProtocolConfig;
+#[derive(Debug, Clone, Default)]
+pub struct IdentifyProtocolConfig {
+    /// Bounds the size of the information sent by the remote.
+    limits: MessageLimits,
+}
+
+impl IdentifyProtocolConfig {
+    /// Builds a new configuration, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        IdentifyProtocolConfig::default()
+    }
+
+    /// Replaces the limits applied to the identify message of the remote.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
 
 /// Output of the connection upgrade.
 pub enum IdentifyOutput<T> {
@@ -55,7 +75,20 @@ pub enum IdentifyOutput<T> {
 
 /// Object used to send back information to the client.
 pub struct IdentifySender<T> {
-    inner: Framed<T, codec::UviBytes<Vec<u8>>>,
+    inner: T,
+}
+
+impl<T> IdentifySender<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    /// Wraps around a raw socket that has been negotiated for one of the identify protocols.
+    #[inline]
+    pub(crate) fn new(socket: T) -> IdentifySender<T> {
+        IdentifySender {
+            inner: socket,
+        }
+    }
 }
 
 impl<'a, T> IdentifySender<T>
@@ -89,7 +122,7 @@ where
             .write_to_bytes()
             .expect("writing protobuf failed ; should never happen");
 
-        let future = self.inner.send(bytes).map(|_| ());
+        let future = upgrade::write_one(self.inner, bytes).map(|_| ());
         Box::new(future) as Box<_>
     }
 }
@@ -121,43 +154,34 @@ where
 
     #[inline]
     fn protocol_names(&self) -> Self::NamesIter {
-        iter::once((Bytes::from("/ipfs/id/1.0.0"), ()))
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
     }
 
     fn upgrade(self, socket: C, _: (), ty: Endpoint, _: &Multiaddr) -> Self::Future {
         trace!("Upgrading connection as {:?}", ty);
 
-        let socket = Framed::new(socket, codec::UviBytes::default());
-
         match ty {
             Endpoint::Dialer => {
-                let future = socket
-                    .into_future()
-                    .map(|(msg, _)| msg)
-                    .map_err(|(err, _)| err)
-                    .and_then(|msg| {
+                let future = upgrade::read_one(socket, self.limits.max_for(PROTOCOL_NAME))
+                    .from_err()
+                    .and_then(|(_, msg)| {
                         debug!("Received identify message");
 
-                        if let Some(msg) = msg {
-                            let (info, observed_addr) = match parse_proto_msg(msg) {
-                                Ok(v) => v,
-                                Err(err) => {
-                                    debug!("Failed to parse protobuf message ; error = {:?}", err);
-                                    return Err(err.into());
-                                }
-                            };
-
-                            trace!("Remote observes us as {:?}", observed_addr);
-                            trace!("Information received: {:?}", info);
-
-                            Ok(IdentifyOutput::RemoteInfo {
-                                info,
-                                observed_addr: observed_addr.clone(),
-                            })
-                        } else {
-                            debug!("Identify protocol stream closed before receiving info");
-                            Err(IoErrorKind::InvalidData.into())
-                        }
+                        let (info, observed_addr) = match parse_proto_msg(&msg) {
+                            Ok(v) => v,
+                            Err(err) => {
+                                debug!("Failed to parse protobuf message ; error = {:?}", err);
+                                return Err(err);
+                            }
+                        };
+
+                        trace!("Remote observes us as {:?}", observed_addr);
+                        trace!("Information received: {:?}", info);
+
+                        Ok(IdentifyOutput::RemoteInfo {
+                            info,
+                            observed_addr: observed_addr.clone(),
+                        })
                     });
 
                 Box::new(future) as Box<_>
@@ -174,8 +198,8 @@ where
 
 // Turns a protobuf message into an `IdentifyInfo` and an observed address. If something bad
 // happens, turn it into an `IoError`.
-fn parse_proto_msg(msg: BytesMut) -> Result<(IdentifyInfo, Multiaddr), IoError> {
-    match protobuf_parse_from_bytes::<structs_proto::Identify>(&msg) {
+pub(crate) fn parse_proto_msg(msg: &[u8]) -> Result<(IdentifyInfo, Multiaddr), IoError> {
+    match protobuf_parse_from_bytes::<structs_proto::Identify>(msg) {
         Ok(mut msg) => {
             // Turn a `Vec<u8>` into a `Multiaddr`. If something bad happens, turn it into
             // an `IoError`.
@@ -218,6 +242,7 @@ mod tests {
     use libp2p_core::{PublicKey, Transport};
     use std::sync::mpsc;
     use std::thread;
+    use libp2p_core::upgrade::MessageLimits;
     use {IdentifyInfo, IdentifyOutput, IdentifyProtocolConfig};
 
     #[test]
@@ -228,7 +253,7 @@ mod tests {
         let (tx, rx) = mpsc::channel();
 
         let bg_thread = thread::spawn(move || {
-            let transport = TcpConfig::new().with_upgrade(IdentifyProtocolConfig);
+            let transport = TcpConfig::new().with_upgrade(IdentifyProtocolConfig::new());
 
             let (listener, addr) = transport
                 .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
@@ -242,7 +267,7 @@ mod tests {
                 .and_then(|identify| match identify {
                     IdentifyOutput::Sender { sender, .. } => sender.send(
                         IdentifyInfo {
-                            public_key: PublicKey::Ed25519(vec![1, 2, 3, 4, 5, 7]),
+                            public_key: PublicKey::Ed25519(vec![7; 32]),
                             protocol_version: "proto_version".to_owned(),
                             agent_version: "agent_version".to_owned(),
                             listen_addrs: vec![
@@ -259,7 +284,7 @@ mod tests {
             let _ = tokio_current_thread::block_on_all(future).unwrap();
         });
 
-        let transport = TcpConfig::new().with_upgrade(IdentifyProtocolConfig);
+        let transport = TcpConfig::new().with_upgrade(IdentifyProtocolConfig::new());
 
         let future = transport
             .dial(rx.recv().unwrap())
@@ -273,7 +298,7 @@ mod tests {
                         observed_addr,
                         "/ip4/100.101.102.103/tcp/5000".parse().unwrap()
                     );
-                    assert_eq!(info.public_key, PublicKey::Ed25519(vec![1, 2, 3, 4, 5, 7]));
+                    assert_eq!(info.public_key, PublicKey::Ed25519(vec![7; 32]));
                     assert_eq!(info.protocol_version, "proto_version");
                     assert_eq!(info.agent_version, "agent_version");
                     assert_eq!(
@@ -295,4 +320,49 @@ mod tests {
         let _ = tokio_current_thread::block_on_all(future).unwrap();
         bg_thread.join().unwrap();
     }
+
+    #[test]
+    fn oversized_info_rejected() {
+        let (tx, rx) = mpsc::channel();
+
+        let bg_thread = thread::spawn(move || {
+            let transport = TcpConfig::new().with_upgrade(IdentifyProtocolConfig::new());
+
+            let (listener, addr) = transport
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+            tx.send(addr).unwrap();
+
+            let future = listener
+                .into_future()
+                .map_err(|(err, _)| err)
+                .and_then(|(client, _)| client.unwrap().0)
+                .and_then(|identify| match identify {
+                    IdentifyOutput::Sender { sender, .. } => sender.send(
+                        IdentifyInfo {
+                            public_key: PublicKey::Ed25519(vec![7; 32]),
+                            protocol_version: "proto_version".to_owned(),
+                            agent_version: "a".repeat(1024),
+                            listen_addrs: Vec::new(),
+                            protocols: Vec::new(),
+                        },
+                        &"/ip4/100.101.102.103/tcp/5000".parse().unwrap(),
+                    ),
+                    _ => panic!(),
+                });
+
+            let _ = tokio_current_thread::block_on_all(future);
+        });
+
+        let limits = MessageLimits::new().with_protocol_max("/ipfs/id/1.0.0", 512);
+        let transport = TcpConfig::new()
+            .with_upgrade(IdentifyProtocolConfig::new().with_message_limits(&limits));
+
+        let future = transport
+            .dial(rx.recv().unwrap())
+            .unwrap_or_else(|_| panic!());
+
+        assert!(tokio_current_thread::block_on_all(future).is_err());
+        bg_thread.join().unwrap();
+    }
 }
@@ -0,0 +1,201 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/ipfs/id/push/1.0.0` protocol.
+//!
+//! Contrary to `/ipfs/id/1.0.0` where the listener sends its information to the dialer, here the
+//! dialer pushes its information to the listener. A node is supposed to open a substream with
+//! this protocol towards all the nodes it is connected to whenever its listen addresses or its
+//! supported protocols change.
+
+use bytes::Bytes;
+use futures::{future, Future};
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr};
+use libp2p_core::upgrade::{self, MessageLimits};
+use protocol::{parse_proto_msg, IdentifyInfo, IdentifySender};
+use std::io::Error as IoError;
+use std::iter;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/ipfs/id/push/1.0.0";
+
+/// Configuration for an upgrade to the identify push protocol.
+#[derive(Debug, Clone, Default)]
+pub struct IdentifyPushProtocolConfig {
+    /// Bounds the size of the information pushed by the remote.
+    limits: MessageLimits,
+}
+
+impl IdentifyPushProtocolConfig {
+    /// Builds a new configuration, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        IdentifyPushProtocolConfig::default()
+    }
+
+    /// Replaces the limits applied to the information pushed by the remote.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+/// Output of the push connection upgrade.
+pub enum IdentifyPushOutput<T> {
+    /// We opened a substream in order to push our information to the remote. Happens when we
+    /// are the dialer.
+    Pusher {
+        /// Object used to push our information to the remote.
+        sender: IdentifySender<T>,
+    },
+
+    /// The remote pushed updated information about itself. Happens when we are the listener.
+    Received {
+        /// Updated information about the remote.
+        info: IdentifyInfo,
+        /// Address the remote sees for us, if it sent one.
+        observed_addr: Option<Multiaddr>,
+    },
+}
+
+impl<C> ConnectionUpgrade<C> for IdentifyPushProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+    type UpgradeIdentifier = ();
+    type Output = IdentifyPushOutput<C>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    fn upgrade(self, socket: C, _: (), ty: Endpoint, _: &Multiaddr) -> Self::Future {
+        trace!("Upgrading identify push connection as {:?}", ty);
+
+        match ty {
+            Endpoint::Dialer => {
+                let sender = IdentifySender::new(socket);
+                let future = future::ok(IdentifyPushOutput::Pusher { sender });
+                Box::new(future) as Box<_>
+            }
+
+            Endpoint::Listener => {
+                let future = upgrade::read_one(socket, self.limits.max_for(PROTOCOL_NAME))
+                    .from_err()
+                    .and_then(|(_, msg)| {
+                        let (info, observed_addr) = parse_proto_msg(&msg)?;
+                        trace!("Information pushed by remote: {:?}", info);
+
+                        // The observed address is optional in push messages, in which case it
+                        // is transmitted as an empty multiaddr.
+                        let observed_addr = if observed_addr.iter().next().is_some() {
+                            Some(observed_addr)
+                        } else {
+                            None
+                        };
+
+                        Ok(IdentifyPushOutput::Received { info, observed_addr })
+                    });
+
+                Box::new(future) as Box<_>
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libp2p_tcp_transport;
+    extern crate tokio_current_thread;
+
+    use self::libp2p_tcp_transport::TcpConfig;
+    use futures::{Future, Stream};
+    use libp2p_core::{PublicKey, Transport};
+    use std::sync::mpsc;
+    use std::thread;
+    use {IdentifyInfo, IdentifyPushOutput, IdentifyPushProtocolConfig};
+
+    #[test]
+    fn correct_push() {
+        // The dialer pushes its information to the listener, which checks that it was
+        // successfully received.
+
+        let (tx, rx) = mpsc::channel();
+
+        let bg_thread = thread::spawn(move || {
+            let transport = TcpConfig::new().with_upgrade(IdentifyPushProtocolConfig::new());
+
+            let (listener, addr) = transport
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+            tx.send(addr).unwrap();
+
+            let future = listener
+                .into_future()
+                .map_err(|(err, _)| err)
+                .and_then(|(client, _)| client.unwrap().0)
+                .map(|push| match push {
+                    IdentifyPushOutput::Received { info, observed_addr } => {
+                        assert_eq!(info.public_key, PublicKey::Ed25519(vec![1, 2, 3, 4, 5, 7]));
+                        assert_eq!(info.agent_version, "agent_version");
+                        assert_eq!(
+                            info.listen_addrs,
+                            &["/ip4/80.81.82.83/tcp/500".parse().unwrap()]
+                        );
+                        assert_eq!(info.protocols, &["proto1".to_string()]);
+                        assert_eq!(
+                            observed_addr,
+                            Some("/ip4/100.101.102.103/tcp/5000".parse().unwrap())
+                        );
+                    }
+                    _ => panic!(),
+                });
+
+            let _ = tokio_current_thread::block_on_all(future).unwrap();
+        });
+
+        let transport = TcpConfig::new().with_upgrade(IdentifyPushProtocolConfig::new());
+
+        let future = transport
+            .dial(rx.recv().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .and_then(|push| match push {
+                IdentifyPushOutput::Pusher { sender } => sender.send(
+                    IdentifyInfo {
+                        public_key: PublicKey::Ed25519(vec![1, 2, 3, 4, 5, 7]),
+                        protocol_version: "proto_version".to_owned(),
+                        agent_version: "agent_version".to_owned(),
+                        listen_addrs: vec!["/ip4/80.81.82.83/tcp/500".parse().unwrap()],
+                        protocols: vec!["proto1".to_string()],
+                    },
+                    &"/ip4/100.101.102.103/tcp/5000".parse().unwrap(),
+                ),
+                _ => panic!(),
+            });
+
+        let _ = tokio_current_thread::block_on_all(future).unwrap();
+        bg_thread.join().unwrap();
+    }
+}
@@ -18,20 +18,33 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use fnv::FnvHashSet;
-use futures::{future, Future, IntoFuture, stream, Stream};
-use kad_server::KadConnecController;
-use kbucket::{KBucketsTable, KBucketsPeerId};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{future, Async, Future, IntoFuture, Poll, stream, Stream};
+use kad_server::{KadConnecConfig, KadConnecController, KadIncomingRequest, KadMode, KadModeHandle};
+use kbucket::{key_distance, KBucketEntry, KBucketEvent, KBucketKey, KBucketsTable, UpdateOutcome};
+use kbucket::MAX_NODES_PER_BUCKET;
 use libp2p_core::PeerId;
-use protocol;
-use rand;
-use smallvec::SmallVec;
-use std::cmp::Ordering;
+use libp2p_core::runtime::{RandomSource, Runtime};
+use libp2p_core::upgrade::MessageLimits;
+use multiaddr::Multiaddr;
+use multihash::Multihash;
+use parking_lot::Mutex;
+use protocol::{self, KadConnectionType, KadPeer};
+use record_store::{KadRecordEvent, KadRecordStore, KadRecordStoreConfig, KadRecordWorker};
+use std::cmp::{self, Ordering};
+use std::collections::VecDeque;
+use std::fmt;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
 use std::mem;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio_timer::Timeout;
 
+/// Maximum number of records or provider records published at the same time by the stream of
+/// `KadSystem::republish_records`.
+const MAX_PUBLICATIONS: usize = 16;
+
 /// Prototype for a future Kademlia protocol running on a socket.
 #[derive(Debug, Clone)]
 pub struct KadSystemConfig<I> {
@@ -47,16 +60,37 @@ pub struct KadSystemConfig<I> {
     pub kbuckets_timeout: Duration,
     /// When contacting a node, duration after which we consider it unresponsive.
     pub request_timeout: Duration,
+    /// Mode of the node when the system starts. Can be changed later with `set_mode`.
+    pub mode: KadMode,
+    /// Source of the random IDs looked up in order to refresh the k-buckets. Usually the one of
+    /// the swarm's `Runtime`.
+    pub random_source: RandomSource,
+    /// Limits passed to the `KadConnecConfig`s built by `connec_config`.
+    pub message_limits: MessageLimits,
+    /// Expiration and republication of the records of the `KadRecordStore` of the system.
+    pub record_store: KadRecordStoreConfig,
+    /// Runtime whose clock determines when the records expire and get republished.
+    pub runtime: Runtime,
 }
 
 /// System that drives the whole Kademlia process.
 pub struct KadSystem {
-    // The actual DHT.
-    kbuckets: KBucketsTable<PeerId, ()>,
+    // The actual DHT. Shared with the publications started by `republish_records`.
+    kbuckets: Arc<KBucketsTable<PeerId, ()>>,
     // Same as in the config.
     parallelism: u32,
     // Same as in the config.
     request_timeout: Duration,
+    // Current mode of the node. Shared with the `KadConnecConfig`s built by `connec_config`.
+    mode: KadModeHandle,
+    // Same as in the config.
+    random_source: RandomSource,
+    // Same as in the config.
+    message_limits: MessageLimits,
+    // Value and provider records that we serve to the remotes.
+    record_store: KadRecordStore,
+    // Worker of `record_store`. Taken by `republish_records`.
+    record_worker: Mutex<Option<KadRecordWorker>>,
 }
 
 /// Event that happens during a query.
@@ -68,6 +102,160 @@ pub enum KadQueryEvent<TOut> {
     Finished(TOut),
 }
 
+/// Options of a query performed with `get_closest_peers`, `get_record` or `get_providers`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KadQueryConfig {
+    /// Number of disjoint paths.
+    disjoint_paths: usize,
+}
+
+impl KadQueryConfig {
+    /// Builds the default options: the lookup follows a single path.
+    #[inline]
+    pub fn new() -> KadQueryConfig {
+        KadQueryConfig {
+            disjoint_paths: 1,
+        }
+    }
+
+    /// Runs the lookup over `paths` disjoint paths, as described in the S/Kademlia paper.
+    ///
+    /// The initial peers are split between the paths, and each path performs its own iterative
+    /// lookup. A peer is never contacted by more than one path, so that an adversary has to be
+    /// present on every path in order to control the outcome of the query. The results of all
+    /// the paths are merged at the end.
+    ///
+    /// A value of `0` is treated as `1`.
+    #[inline]
+    pub fn with_disjoint_paths(mut self, paths: usize) -> Self {
+        self.disjoint_paths = if paths == 0 { 1 } else { paths };
+        self
+    }
+
+    /// Returns the number of disjoint paths.
+    #[inline]
+    pub fn disjoint_paths(&self) -> usize {
+        self.disjoint_paths
+    }
+}
+
+impl Default for KadQueryConfig {
+    #[inline]
+    fn default() -> Self {
+        KadQueryConfig::new()
+    }
+}
+
+/// Step-by-step progress of a query. Produced by `get_closest_peers`, `get_record` and
+/// `get_providers`.
+///
+/// An event is produced each time a request is sent to a peer and each time a request finishes,
+/// which makes it possible to observe the fan-out and the convergence of the query. The last
+/// event is always `Finished`.
+#[derive(Debug)]
+pub enum KadQueryProgress<TResponse, TOut> {
+    /// A request has been sent to a peer.
+    RequestSent {
+        /// The peer the request has been sent to.
+        peer_id: PeerId,
+        /// Index of the disjoint path the request belongs to. Always `0` without disjoint paths.
+        path: usize,
+        /// Number of hops between us and the peer: `1` for the peers of our routing table, and
+        /// one more than the peer that reported it otherwise.
+        hops: usize,
+    },
+    /// A peer has answered a request.
+    ResponseReceived {
+        /// The peer that answered.
+        peer_id: PeerId,
+        /// Index of the disjoint path the request belongs to.
+        path: usize,
+        /// Number of hops between us and the peer.
+        hops: usize,
+        /// Peers closer to the target that the remote has reported.
+        closer_peers: Vec<KadPeer>,
+        /// Content of the response specific to the query, for example the record.
+        response: TResponse,
+        /// Time between sending the request and receiving the response.
+        elapsed: Duration,
+    },
+    /// A request has failed or timed out. The peer won't be contacted again by this query.
+    RequestFailed {
+        /// The peer the request has been sent to.
+        peer_id: PeerId,
+        /// Index of the disjoint path the request belongs to.
+        path: usize,
+        /// Number of hops between us and the peer.
+        hops: usize,
+        /// The error that happened.
+        error: IoError,
+        /// Time between sending the request and the failure.
+        elapsed: Duration,
+    },
+    /// The query is finished. Contains the result.
+    Finished(TOut),
+}
+
+impl<TResponse, TOut> KadQueryProgress<TResponse, TOut> {
+    /// Applies a function to the result of the query, if this is the `Finished` event.
+    pub fn map_finished<TOut2, F>(self, map: F) -> KadQueryProgress<TResponse, TOut2>
+    where F: FnOnce(TOut) -> TOut2
+    {
+        match self {
+            KadQueryProgress::RequestSent { peer_id, path, hops } => {
+                KadQueryProgress::RequestSent { peer_id, path, hops }
+            },
+            KadQueryProgress::ResponseReceived { peer_id, path, hops, closer_peers, response, elapsed } => {
+                KadQueryProgress::ResponseReceived { peer_id, path, hops, closer_peers, response, elapsed }
+            },
+            KadQueryProgress::RequestFailed { peer_id, path, hops, error, elapsed } => {
+                KadQueryProgress::RequestFailed { peer_id, path, hops, error, elapsed }
+            },
+            KadQueryProgress::Finished(out) => KadQueryProgress::Finished(map(out)),
+        }
+    }
+}
+
+/// Summary of a query, built by passing each of its `KadQueryProgress` events to `observe`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KadQueryStats {
+    /// Number of peers a request has been sent to.
+    pub peers_contacted: usize,
+    /// Number of requests that succeeded.
+    pub responses: usize,
+    /// Number of requests that failed or timed out.
+    pub failures: usize,
+    /// Largest number of hops of a peer that answered. `0` if no peer answered.
+    pub max_hops: usize,
+    /// Total time spent waiting for the responses and failures.
+    pub total_request_time: Duration,
+}
+
+impl KadQueryStats {
+    /// Builds empty statistics.
+    #[inline]
+    pub fn new() -> KadQueryStats {
+        KadQueryStats::default()
+    }
+
+    /// Updates the statistics with an event of the query.
+    pub fn observe<TResponse, TOut>(&mut self, event: &KadQueryProgress<TResponse, TOut>) {
+        match *event {
+            KadQueryProgress::RequestSent { .. } => self.peers_contacted += 1,
+            KadQueryProgress::ResponseReceived { hops, elapsed, .. } => {
+                self.responses += 1;
+                self.max_hops = cmp::max(self.max_hops, hops);
+                self.total_request_time += elapsed;
+            },
+            KadQueryProgress::RequestFailed { elapsed, .. } => {
+                self.failures += 1;
+                self.total_request_time += elapsed;
+            },
+            KadQueryProgress::Finished(_) => (),
+        }
+    }
+}
+
 impl KadSystem {
     /// Starts a new Kademlia system.
     ///
@@ -91,10 +279,19 @@ impl KadSystem {
             let _ = kbuckets.update(peer, ());
         }
 
+        let record_store = KadRecordStore::new(config.record_store, config.runtime,
+                                               config.local_peer_id);
+        let record_worker = record_store.worker();
+
         let system = KadSystem {
-            kbuckets: kbuckets,
+            kbuckets: Arc::new(kbuckets),
             parallelism: config.parallelism,
             request_timeout: config.request_timeout,
+            mode: KadModeHandle::new(config.mode),
+            random_source: config.random_source,
+            message_limits: config.message_limits,
+            record_store,
+            record_worker: Mutex::new(Some(record_worker)),
         };
 
         system
@@ -108,7 +305,7 @@ impl KadSystem {
     {
         let futures: Vec<_> = (0..256)      // TODO: 256 is arbitrary
             .map(|n| {
-                refresh(n, access.clone(), &self.kbuckets,
+                refresh(n, access.clone(), &self.kbuckets, &self.random_source,
                         self.parallelism as usize, self.request_timeout)
             })
             .map(|stream| stream.for_each(|_| Ok(())))
@@ -130,9 +327,43 @@ impl KadSystem {
     /// Updates the k-buckets with the specific peer.
     ///
     /// Should be called whenever we receive a message from a peer.
-    pub fn update_kbuckets(&self, peer: PeerId) {
-        // TODO: ping system
-        let _ = self.kbuckets.update(peer, ());
+    ///
+    /// If the bucket of the peer is full, returns the least recently seen peer of that bucket,
+    /// which must be pinged. Call `update_kbuckets` again with that peer if it answers, or
+    /// `report_ping_failure` if it doesn't. If neither happens before `kbuckets_timeout`, the
+    /// peer is evicted.
+    pub fn update_kbuckets(&self, peer: PeerId) -> Option<PeerId> {
+        match self.kbuckets.update(peer, ()) {
+            UpdateOutcome::NeedPing(to_ping) => Some(to_ping),
+            _ => None,
+        }
+    }
+
+    /// Reports that a peer returned by `update_kbuckets` didn't answer our ping. The peer is
+    /// evicted and replaced with the peer that was waiting for a slot.
+    ///
+    /// Returns true if the peer has been evicted.
+    pub fn report_ping_failure(&self, peer: &PeerId) -> bool {
+        self.kbuckets.report_ping_failure(peer)
+    }
+
+    /// Returns the content of the k-buckets, including the peers waiting for a slot.
+    pub fn routing_table(&self) -> Vec<KBucketEntry<PeerId>> {
+        self.kbuckets.entries()
+    }
+
+    /// Returns the number of peers in each k-bucket, excluding the peers waiting for a slot.
+    ///
+    /// The bucket at index `i` contains the peers whose distance to the local node is between
+    /// `2^i` included and `2^(i + 1)` excluded. Sampling this periodically shows how the routing
+    /// table fills up.
+    pub fn bucket_occupancy(&self) -> Vec<usize> {
+        self.kbuckets.buckets().map(|bucket| bucket.num_entries()).collect()
+    }
+
+    /// Returns the changes in the k-buckets since the last call.
+    pub fn routing_table_events(&self) -> Vec<KBucketEvent<PeerId>> {
+        self.kbuckets.drain_events()
     }
 
     /// Returns the local peer ID, as passed in the configuration.
@@ -140,9 +371,38 @@ impl KadSystem {
         self.kbuckets.my_id()
     }
 
-    /// Finds the known nodes closest to `id`, ordered by distance.
-    pub fn known_closest_peers(&self, id: &PeerId) -> impl Iterator<Item = PeerId> {
-        self.kbuckets.find_closest_with_self(id)
+    /// Finds the known nodes closest to `key`, ordered by distance. The key can be a `PeerId`, or
+    /// any other value of the keyspace such as the key of a record.
+    ///
+    /// The local node is included only in server mode.
+    pub fn known_closest_peers<K>(&self, key: &K) -> impl Iterator<Item = PeerId>
+    where K: KBucketKey + ?Sized,
+    {
+        match self.mode.get() {
+            KadMode::Server => self.kbuckets.find_closest_to_key_with_self(key),
+            KadMode::Client => self.kbuckets.find_closest_to_key(key),
+        }
+    }
+
+    /// Returns the current mode of the node.
+    #[inline]
+    pub fn mode(&self) -> KadMode {
+        self.mode.get()
+    }
+
+    /// Switches the node between client and server mode, for example when we learn that the
+    /// node isn't reachable from the outside. Affects all the connections upgraded with a
+    /// `KadConnecConfig` returned by `connec_config`.
+    #[inline]
+    pub fn set_mode(&self, mode: KadMode) {
+        self.mode.set(mode)
+    }
+
+    /// Returns a `KadConnecConfig` whose behaviour follows the mode of this system.
+    #[inline]
+    pub fn connec_config(&self) -> KadConnecConfig {
+        KadConnecConfig::with_mode(self.mode.clone())
+            .with_message_limits(&self.message_limits)
     }
 
     /// Starts a query for an iterative `FIND_NODE` request.
@@ -155,6 +415,336 @@ impl KadSystem {
         query(access, &self.kbuckets, searched_key, self.parallelism as usize,
               20, self.request_timeout)  // TODO: arbitrary const
     }
+
+    /// Same as `find_node`, but reports the progress of the query step by step. The query
+    /// finishes with the peers closest to `target` that have answered, ordered by distance.
+    pub fn get_closest_peers<'a, F, Fut>(&self, target: PeerId, config: KadQueryConfig, access: F)
+        -> impl Stream<Item = KadQueryProgress<(), Vec<PeerId>>, Error = IoError> + 'a
+    where F: FnMut(&PeerId) -> Fut + Send + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError>  + 'a,
+        Fut::Future: Send,
+    {
+        KadQuery::new(access, &self.kbuckets, target, config, self.parallelism as usize, 20,  // TODO: arbitrary const
+                      self.request_timeout, find_node_rpc, never_stop)
+    }
+
+    /// Looks for the record whose identifier is `key` by sending `GET_VALUE` requests to the
+    /// peers closest to `key`, and reports the progress of the query step by step.
+    ///
+    /// The query finishes as soon as a peer returns the record, and produces the value of the
+    /// record, or `None` if no peer had it.
+    ///
+    /// The key can use any hash function. Its digest is its position in the keyspace, see
+    /// `KBucketKey`.
+    pub fn get_record<'a, F, Fut>(&self, key: Multihash, config: KadQueryConfig, access: F)
+        -> Box<Stream<Item = KadQueryProgress<Option<Vec<u8>>, Option<Vec<u8>>>, Error = IoError> + Send + 'a>
+    where F: FnMut(&PeerId) -> Fut + Send + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError>  + 'a,
+        Fut::Future: Send,
+    {
+        let mut record = None;
+        let stream = KadQuery::new(access, &self.kbuckets, key, config, self.parallelism as usize, 20,  // TODO: arbitrary const
+                                   self.request_timeout, get_value_rpc, stop_on_record)
+            .map(move |event| {
+                if let KadQueryProgress::ResponseReceived { response: Some(ref value), .. } = event {
+                    if record.is_none() {
+                        record = Some(value.clone());
+                    }
+                }
+                event.map_finished(|_| record.take())
+            });
+        Box::new(stream)
+    }
+
+    /// Looks for the providers of `key` by sending `GET_PROVIDERS` requests to the peers closest
+    /// to `key`, and reports the progress of the query step by step.
+    ///
+    /// The query finishes with all the providers that have been reported, without duplicates.
+    pub fn get_providers<'a, F, Fut>(&self, key: Multihash, config: KadQueryConfig, access: F)
+        -> Box<Stream<Item = KadQueryProgress<Vec<KadPeer>, Vec<KadPeer>>, Error = IoError> + Send + 'a>
+    where F: FnMut(&PeerId) -> Fut + Send + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError>  + 'a,
+        Fut::Future: Send,
+    {
+        let mut providers: Vec<KadPeer> = Vec::new();
+        let stream = KadQuery::new(access, &self.kbuckets, key, config, self.parallelism as usize, 20,  // TODO: arbitrary const
+                                   self.request_timeout, get_providers_rpc, never_stop)
+            .map(move |event| {
+                if let KadQueryProgress::ResponseReceived { ref response, .. } = event {
+                    for provider in response {
+                        if !providers.iter().any(|p| p.node_id == provider.node_id) {
+                            providers.push(provider.clone());
+                        }
+                    }
+                }
+                event.map_finished(|_| mem::replace(&mut providers, Vec::new()))
+            });
+        Box::new(stream)
+    }
+
+    /// Performs an iterative `FIND_NODE` request for `peer_id` and produces the addresses of
+    /// this peer that have been reported by the remotes during the query.
+    ///
+    /// The future can be used as the `lookup` of an `AddressResolver`, so that the swarm can
+    /// dial peers whose addresses are not known locally.
+    pub fn find_peer_addresses<'a, F, Fut>(&self, peer_id: PeerId, access: F)
+        -> impl Future<Item = Vec<Multiaddr>, Error = IoError> + 'a
+    where F: FnMut(&PeerId) -> Fut + Send + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError>  + 'a,
+        Fut::Future: Send,
+    {
+        self.find_node(peer_id.clone(), access)
+            .fold(Vec::new(), move |mut addrs, event| {
+                if let KadQueryEvent::PeersReported(peers) = event {
+                    for peer in peers.into_iter().filter(|p| p.node_id == peer_id) {
+                        for addr in peer.multiaddrs {
+                            if !addrs.contains(&addr) {
+                                addrs.push(addr);
+                            }
+                        }
+                    }
+                }
+                Ok::<_, IoError>(addrs)
+            })
+    }
+
+    /// Returns the store of the value and provider records that the system serves to the
+    /// remotes.
+    #[inline]
+    pub fn record_store(&self) -> &KadRecordStore {
+        &self.record_store
+    }
+
+    /// Answers a request received from a remote with the content of the k-buckets and of the
+    /// record store. The records and provider records sent by the remote are stored.
+    ///
+    /// `peer_info` builds the `KadPeer` of each peer included in a response, for example with the
+    /// addresses known by the swarm.
+    ///
+    /// > **Note**: A `KadIncomingRequest` doesn't identify its sender, therefore the provider
+    /// >           announced by an `ADD_PROVIDER` request isn't checked against the remote.
+    pub fn serve_request<P>(&self, request: KadIncomingRequest, mut peer_info: P)
+    where P: FnMut(&PeerId) -> KadPeer,
+    {
+        match request {
+            KadIncomingRequest::FindNode { searched, responder } => {
+                responder.respond(self.closer_peers(&searched, &mut peer_info));
+            },
+            KadIncomingRequest::GetValue { key, responder } => {
+                match Multihash::from_bytes(key) {
+                    Ok(key) => {
+                        let record = self.record_store.get(&key);
+                        responder.respond(record, self.closer_peers(&key, &mut peer_info));
+                    },
+                    Err(_) => {
+                        debug!("Received GET_VALUE request with an invalid key");
+                        responder.respond(None, iter::empty());
+                    },
+                }
+            },
+            KadIncomingRequest::GetProviders { key, responder } => {
+                match Multihash::from_bytes(key) {
+                    Ok(key) => {
+                        let providers = self.record_store.providers(&key)
+                            .iter()
+                            .map(&mut peer_info)
+                            .collect::<Vec<_>>();
+                        responder.respond(providers, self.closer_peers(&key, &mut peer_info));
+                    },
+                    Err(_) => {
+                        debug!("Received GET_PROVIDERS request with an invalid key");
+                        responder.respond(iter::empty(), iter::empty());
+                    },
+                }
+            },
+            KadIncomingRequest::PutValue { key, value } => {
+                match Multihash::from_bytes(key) {
+                    Ok(key) => self.record_store.store(key, value),
+                    Err(_) => debug!("Ignoring PUT_VALUE request with an invalid key"),
+                }
+            },
+            KadIncomingRequest::AddProvider { key, provider } => {
+                match Multihash::from_bytes(key) {
+                    Ok(key) => self.record_store.add_provider(key, provider.node_id),
+                    Err(_) => debug!("Ignoring ADD_PROVIDER request with an invalid key"),
+                }
+            },
+            KadIncomingRequest::PingPong => (),
+        }
+    }
+
+    /// Stores a record in the record store, and sends it with `PUT_VALUE` to the peers closest
+    /// to `key`. The record is then published again periodically by the stream returned by
+    /// `republish_records`.
+    ///
+    /// Produces the peers the record has been sent to.
+    pub fn put_record<'a, F, Fut>(&self, key: Multihash, value: Vec<u8>, access: F)
+        -> Box<Future<Item = Vec<PeerId>, Error = IoError> + Send + 'a>
+    where F: FnMut(&PeerId) -> Fut + Send + Clone + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+        Fut::Future: Send,
+    {
+        self.record_store.put_local(key.clone(), value.clone());
+        publish(&self.kbuckets, key, Publication::Record(value), self.parallelism as usize,
+                self.request_timeout, access)
+    }
+
+    /// Records in the record store that we provide `key`, and announces it with `ADD_PROVIDER`
+    /// to the peers closest to `key`. The announcement is then made again periodically by the
+    /// stream returned by `republish_records`.
+    ///
+    /// Produces the peers the announcement has been sent to.
+    ///
+    /// > **Note**: The announcement doesn't contain our addresses. The remotes are expected to
+    /// >           find them with a `FIND_NODE` query.
+    pub fn provide<'a, F, Fut>(&self, key: Multihash, access: F)
+        -> Box<Future<Item = Vec<PeerId>, Error = IoError> + Send + 'a>
+    where F: FnMut(&PeerId) -> Fut + Send + Clone + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+        Fut::Future: Send,
+    {
+        self.record_store.add_local_provider(key.clone());
+        publish(&self.kbuckets, key, Publication::Provider(self.local_provider()),
+                self.parallelism as usize, self.request_timeout, access)
+    }
+
+    /// Drives the `KadRecordWorker` of the record store. The expired records are removed, and
+    /// our own records and provider records are sent again with `PUT_VALUE` and `ADD_PROVIDER`
+    /// to the peers closest to their key when they must be republished.
+    ///
+    /// The stream must be polled for the records to expire and to be republished. It produces
+    /// each `KadRecordEvent` once the corresponding publication has finished, and never ends.
+    ///
+    /// # Panic
+    ///
+    /// Panics if called more than once.
+    pub fn republish_records<'a, F, Fut>(&self, access: F)
+        -> Box<Stream<Item = KadRecordEvent, Error = IoError> + Send + 'a>
+    where F: FnMut(&PeerId) -> Fut + Send + Clone + 'a,
+        Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+        Fut::Future: Send,
+    {
+        let worker = self.record_worker.lock().take()
+            .expect("republish_records can only be called once");
+        let kbuckets = self.kbuckets.clone();
+        let parallelism = self.parallelism as usize;
+        let request_timeout = self.request_timeout;
+        let provider = self.local_provider();
+
+        let stream = worker
+            .map(move |event| {
+                let publication = match event {
+                    KadRecordEvent::RepublishRecord { ref key, ref value } => {
+                        Some((key.clone(), Publication::Record(value.clone())))
+                    },
+                    KadRecordEvent::RepublishProvider { ref key } => {
+                        Some((key.clone(), Publication::Provider(provider.clone())))
+                    },
+                    KadRecordEvent::RecordExpired { .. } => None,
+                    KadRecordEvent::ProviderExpired { .. } => None,
+                };
+
+                match publication {
+                    Some((key, publication)) => {
+                        let future = publish(&kbuckets, key, publication, parallelism,
+                                             request_timeout, access.clone())
+                            .then(move |result| -> Result<_, IoError> {
+                                if let Err(err) = result {
+                                    debug!("Failed to republish {:?}: {:?}", event, err);
+                                }
+                                Ok(event)
+                            });
+                        future::Either::A(future)
+                    },
+                    None => future::Either::B(future::ok(event)),
+                }
+            })
+            .buffer_unordered(MAX_PUBLICATIONS);
+        Box::new(stream)
+    }
+
+    // Builds the `KadPeer`s of the known peers closest to `key`, for the response to a request.
+    fn closer_peers<K, P>(&self, key: &K, peer_info: &mut P) -> Vec<KadPeer>
+    where K: KBucketKey + ?Sized,
+        P: FnMut(&PeerId) -> KadPeer,
+    {
+        self.known_closest_peers(key)
+            .take(MAX_NODES_PER_BUCKET)
+            .map(|peer| peer_info(&peer))
+            .collect()
+    }
+
+    // Builds the `KadPeer` that announces us as a provider.
+    fn local_provider(&self) -> KadPeer {
+        KadPeer {
+            node_id: self.local_peer_id().clone(),
+            multiaddrs: Vec::new(),
+            connection_ty: KadConnectionType::Connected,
+        }
+    }
+}
+
+// Record or provider record sent by `publish`.
+#[derive(Debug, Clone)]
+enum Publication {
+    // Value of a record, sent with `PUT_VALUE`.
+    Record(Vec<u8>),
+    // Provider of the key, sent with `ADD_PROVIDER`.
+    Provider(KadPeer),
+}
+
+// Looks for the peers closest to `key`, then sends them the publication.
+//
+// Produces the peers the publication has been sent to.
+fn publish<'a, F, Fut>(kbuckets: &KBucketsTable<PeerId, ()>, key: Multihash,
+                       publication: Publication, parallelism: usize, request_timeout: Duration,
+                       access: F)
+    -> Box<Future<Item = Vec<PeerId>, Error = IoError> + Send + 'a>
+where F: FnMut(&PeerId) -> Fut + Send + Clone + 'a,
+    Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+    Fut::Future: Send,
+{
+    let mut send_access = access.clone();
+    // The `GET_PROVIDERS` requests are only used to find the closest peers.
+    let future = KadQuery::new(access, kbuckets, key.clone(), KadQueryConfig::new(), parallelism,
+                               20, request_timeout, get_providers_rpc, never_stop)  // TODO: arbitrary const
+        .fold(Vec::new(), |closest, event| -> Result<_, IoError> {
+            match event {
+                KadQueryProgress::Finished(result) => Ok(result),
+                _ => Ok(closest),
+            }
+        })
+        .and_then(move |closest| {
+            let sends = closest.into_iter()
+                .map(|peer| {
+                    let key = key.clone();
+                    let publication = publication.clone();
+                    send_access(&peer)
+                        .into_future()
+                        .and_then(move |controller| match publication {
+                            Publication::Record(value) => {
+                                controller.put_value(key.as_bytes(), value)
+                            },
+                            Publication::Provider(provider) => {
+                                controller.add_provider(key.as_bytes(), provider)
+                            },
+                        })
+                        .then(move |result| -> Result<_, IoError> {
+                            match result {
+                                Ok(()) => Ok(Some(peer)),
+                                Err(err) => {
+                                    debug!("Failed to publish to {:?}: {:?}", peer, err);
+                                    Ok(None)
+                                },
+                            }
+                        })
+                })
+                .collect::<Vec<_>>();
+            future::join_all(sends)
+                .map(|peers| peers.into_iter().flatten().collect())
+        });
+
+    Box::new(future)
 }
 
 // Refreshes a specific bucket by performing an iterative `FIND_NODE` on a random ID of this
@@ -162,13 +752,13 @@ impl KadSystem {
 //
 // Returns a dummy no-op future if `bucket_num` is out of range.
 fn refresh<'a, F, Fut>(bucket_num: usize, access: F, kbuckets: &KBucketsTable<PeerId, ()>,
-                        parallelism: usize, request_timeout: Duration)
+                        random_source: &RandomSource, parallelism: usize, request_timeout: Duration)
     -> impl Stream<Item = KadQueryEvent<()>, Error = IoError> + 'a
 where F: FnMut(&PeerId) -> Fut + Send + 'a,
     Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
     Fut::Future: Send,
 {
-    let peer_id = match gen_random_id(kbuckets.my_id(), bucket_num) {
+    let peer_id = match gen_random_id(kbuckets.my_id(), bucket_num, random_source) {
         Ok(p) => p,
         Err(()) => {
             let stream = stream::once(Ok(KadQueryEvent::Finished(())));
@@ -189,7 +779,9 @@ where F: FnMut(&PeerId) -> Fut + Send + 'a,
 // Generates a random `PeerId` that belongs to the given bucket.
 //
 // Returns an error if `bucket_num` is out of range.
-fn gen_random_id(my_id: &PeerId, bucket_num: usize) -> Result<PeerId, ()> {
+fn gen_random_id(my_id: &PeerId, bucket_num: usize, random_source: &RandomSource)
+    -> Result<PeerId, ()>
+{
     let my_id_len = my_id.as_bytes().len();
 
     // TODO: this 2 is magic here ; it is the length of the hash of the multihash
@@ -198,6 +790,9 @@ fn gen_random_id(my_id: &PeerId, bucket_num: usize) -> Result<PeerId, ()> {
         return Err(());
     }
 
+    let mut random_bytes = [0; 64];
+    random_source.fill_bytes(&mut random_bytes[..my_id_len]);
+
     let mut random_id = [0; 64];
     for byte in 0..my_id_len {
         match byte.cmp(&(my_id_len - bits_diff / 8 - 1)) {
@@ -206,10 +801,10 @@ fn gen_random_id(my_id: &PeerId, bucket_num: usize) -> Result<PeerId, ()> {
             }
             Ordering::Equal => {
                 let mask: u8 = (1 << (bits_diff % 8)) - 1;
-                random_id[byte] = (my_id.as_bytes()[byte] & !mask) | (rand::random::<u8>() & mask);
+                random_id[byte] = (my_id.as_bytes()[byte] & !mask) | (random_bytes[byte] & mask);
             }
             Ordering::Greater => {
-                random_id[byte] = rand::random();
+                random_id[byte] = random_bytes[byte];
             }
         }
     }
@@ -232,110 +827,196 @@ where F: FnMut(&PeerId) -> Fut + 'a,
       Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
       Fut::Future: Send,
 {
-    debug!("Start query for {:?} ; num results = {}", searched_key, num_results);
+    let stream = KadQuery::new(access, kbuckets, searched_key, KadQueryConfig::new(), parallelism,
+                               num_results, request_timeout, find_node_rpc, never_stop)
+        .filter_map(|event| match event {
+            KadQueryProgress::ResponseReceived { closer_peers, .. } => {
+                Some(KadQueryEvent::PeersReported(closer_peers))
+            },
+            KadQueryProgress::Finished(result) => Some(KadQueryEvent::Finished(result)),
+            KadQueryProgress::RequestSent { .. } | KadQueryProgress::RequestFailed { .. } => None,
+        });
 
-    // State of the current iterative process.
-    struct State<'a, F> {
-        // At which stage we are.
-        stage: Stage,
-        // The `access` parameter.
+    // Boxing the stream is not necessary, but we do it in order to improve compilation time.
+    Box::new(stream) as Box<_>
+}
+
+// Future that performs the RPC request of a query on a remote.
+type RpcFuture<TResponse> = Box<Future<Item = (Vec<KadPeer>, TResponse), Error = IoError> + Send>;
+
+// Sends a `FIND_NODE` request.
+fn find_node_rpc(controller: &KadConnecController, target: &PeerId) -> RpcFuture<()> {
+    Box::new(controller.find_node(target).map(|closer_peers| (closer_peers, ())))
+}
+
+// Sends a `GET_VALUE` request.
+fn get_value_rpc(controller: &KadConnecController, target: &Multihash) -> RpcFuture<Option<Vec<u8>>> {
+    Box::new(controller.get_value(target.as_bytes()).map(|(record, closer_peers)| (closer_peers, record)))
+}
+
+// Sends a `GET_PROVIDERS` request.
+fn get_providers_rpc(controller: &KadConnecController, target: &Multihash) -> RpcFuture<Vec<KadPeer>> {
+    Box::new(controller.get_providers(target.as_bytes()))
+}
+
+// Stop condition of the queries that always run until they converge.
+fn never_stop<T>(_: &T) -> bool {
+    false
+}
+
+// Stop condition of the queries that stop as soon as a record has been found.
+fn stop_on_record(record: &Option<Vec<u8>>) -> bool {
+    record.is_some()
+}
+
+// Stage of a path of a query.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PathStage {
+    // We are still in the first step of the algorithm where we try to find the closest node.
+    FirstStep,
+    // We are contacting the k closest nodes in order to fill the list with enough results.
+    SecondStep,
+    // The path has converged, or has no more node to contact.
+    Done,
+}
+
+// General state of a query.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum QueryState {
+    // At least one path is still running.
+    Running,
+    // The results are complete, and the next stream iteration will produce the outcome.
+    FinishingNextIter,
+    // We are finished and the stream shouldn't return anything anymore.
+    Finished,
+}
+
+// One of the paths of a query. Without disjoint paths, a query has a single path.
+struct QueryPath<'a, TResponse> {
+    // At which stage this path is.
+    stage: PathStage,
+    // Peers that answered, ordered by distance to the target.
+    result: Vec<PeerId>,
+    // Requests in progress, with the peer and the moment the request has been sent.
+    in_progress: Vec<(PeerId, Instant, Box<Future<Item = (Vec<KadPeer>, TResponse), Error = IoError> + Send + 'a>)>,
+    // Nodes that need to be attempted, ordered by distance to the target.
+    pending_nodes: Vec<PeerId>,
+}
+
+// Iterative query on the network. Sends a request to the peers closest to the target, then to
+// the peers closer to the target that they report, until the closest peers have been reached.
+//
+// With disjoint paths, the initial peers are split between the paths, and each path runs its own
+// iterative lookup. A peer is contacted by at most one path, so that a malicious peer can only
+// influence the path it belongs to.
+//
+// Produces a `KadQueryProgress` each time a request is sent or finishes.
+struct KadQuery<'a, F, TTarget, TResponse> {
+    // General state of the query.
+    state: QueryState,
+    // Provides the Kademlia controller of a peer.
+    access: F,
+    // Sends the request of the query to a peer.
+    rpc: fn(&KadConnecController, &TTarget) -> RpcFuture<TResponse>,
+    // If this returns true for a response, the query finishes immediately.
+    stop: fn(&TResponse) -> bool,
+    // Target of the query. Can be a `PeerId` or the key of a record.
+    target: TTarget,
+    // Maximum number of requests in parallel in each path during the first step.
+    parallelism: usize,
+    // Number of closest peers to find.
+    num_results: usize,
+    // Duration after which a request is considered failed.
+    request_timeout: Duration,
+    // The paths of the query.
+    paths: Vec<QueryPath<'a, TResponse>>,
+    // Peers that have been contacted by any of the paths, whether they answered or not.
+    contacted: FnvHashSet<PeerId>,
+    // Number of hops of the peers that have been discovered. The peers of the routing table are
+    // at one hop, and the peers reported by a peer at `n` hops are at `n + 1` hops.
+    hops: FnvHashMap<PeerId, usize>,
+    // Events to produce before doing anything else.
+    queued_events: VecDeque<KadQueryProgress<TResponse, Vec<PeerId>>>,
+}
+
+impl<'a, F, Fut, TTarget, TResponse> KadQuery<'a, F, TTarget, TResponse>
+where F: FnMut(&PeerId) -> Fut + 'a,
+      Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+      Fut::Future: Send,
+      TTarget: KBucketKey + Clone + fmt::Debug + Send + 'a,
+      TResponse: 'a,
+{
+    fn new(
         access: F,
-        // Final output of the iteration.
-        result: Vec<PeerId>,
-        // For each open connection, a future with the response of the remote.
-        // Note that don't use a `SmallVec` here because `select_all` produces a `Vec`.
-        current_attempts_fut: Vec<Box<Future<Item = Vec<protocol::KadPeer>, Error = IoError> + Send + 'a>>,
-        // For each open connection, the peer ID that we are connected to.
-        // Must always have the same length as `current_attempts_fut`.
-        current_attempts_addrs: SmallVec<[PeerId; 32]>,
-        // Nodes that need to be attempted.
-        pending_nodes: Vec<PeerId>,
-        // Peers that we tried to contact but failed.
-        failed_to_contact: FnvHashSet<PeerId>,
-    }
-
-    // General stage of the state.
-    #[derive(Copy, Clone, PartialEq, Eq)]
-    enum Stage {
-        // We are still in the first step of the algorithm where we try to find the closest node.
-        FirstStep,
-        // We are contacting the k closest nodes in order to fill the list with enough results.
-        SecondStep,
-        // The results are complete, and the next stream iteration will produce the outcome.
-        FinishingNextIter,
-        // We are finished and the stream shouldn't return anything anymore.
-        Finished,
-    }
-
-    let initial_state = State {
-        stage: Stage::FirstStep,
-        access: access,
-        result: Vec::with_capacity(num_results),
-        current_attempts_fut: Vec::new(),
-        current_attempts_addrs: SmallVec::new(),
-        pending_nodes: kbuckets.find_closest(&searched_key).collect(),
-        failed_to_contact: Default::default(),
-    };
+        kbuckets: &KBucketsTable<PeerId, ()>,
+        target: TTarget,
+        config: KadQueryConfig,
+        parallelism: usize,
+        num_results: usize,
+        request_timeout: Duration,
+        rpc: fn(&KadConnecController, &TTarget) -> RpcFuture<TResponse>,
+        stop: fn(&TResponse) -> bool,
+    ) -> Self {
+        debug!("Start query for {:?} ; num results = {} ; paths = {}", target, num_results,
+               config.disjoint_paths);
 
-    // Start of the iterative process.
-    let stream = stream::unfold(initial_state, move |mut state| -> Option<_> {
-        match state.stage {
-            Stage::FinishingNextIter => {
-                let result = mem::replace(&mut state.result, Vec::new());
-                debug!("Query finished with {} results", result.len());
-                state.stage = Stage::Finished;
-                let future = future::ok((Some(KadQueryEvent::Finished(result)), state));
-                return Some(future::Either::A(future));
-            },
-            Stage::Finished => {
-                return None;
-            },
-            _ => ()
-        };
+        let mut paths = (0 .. config.disjoint_paths)
+            .map(|_| QueryPath {
+                stage: PathStage::FirstStep,
+                result: Vec::with_capacity(num_results),
+                in_progress: Vec::new(),
+                pending_nodes: Vec::new(),
+            })
+            .collect::<Vec<_>>();
 
-        let searched_key = searched_key.clone();
+        // The initial peers are distributed between the paths, the closest ones first.
+        let num_paths = paths.len();
+        let mut hops = FnvHashMap::default();
+        for (n, peer) in kbuckets.find_closest_to_key(&target).enumerate() {
+            hops.insert(peer.clone(), 1);
+            paths[n % num_paths].pending_nodes.push(peer);
+        }
 
-        // Find out which nodes to contact at this iteration.
-        let to_contact = {
-            let wanted_len = if state.stage == Stage::FirstStep {
-                parallelism.saturating_sub(state.current_attempts_fut.len())
-            } else {
-                num_results.saturating_sub(state.current_attempts_fut.len())
-            };
-            let mut to_contact = SmallVec::<[_; 16]>::new();
-            while to_contact.len() < wanted_len && !state.pending_nodes.is_empty() {
-                // Move the first element of `pending_nodes` to `to_contact`, but ignore nodes that
-                // are already part of the results or of a current attempt or if we failed to
-                // contact it before.
-                let peer = state.pending_nodes.remove(0);
-                if state.result.iter().any(|p| p == &peer) {
-                    continue;
-                }
-                if state.current_attempts_addrs.iter().any(|p| p == &peer) {
-                    continue;
-                }
-                if state.failed_to_contact.iter().any(|p| p == &peer) {
-                    continue;
-                }
-                to_contact.push(peer);
-            }
-            to_contact
+        KadQuery {
+            state: QueryState::Running,
+            access,
+            rpc,
+            stop,
+            target,
+            parallelism,
+            num_results,
+            request_timeout,
+            paths,
+            contacted: Default::default(),
+            hops,
+            queued_events: VecDeque::new(),
+        }
+    }
+
+    // Sends requests to the next pending nodes of a path, up to the number of requests allowed
+    // in parallel at the current stage of the path.
+    fn start_requests(&mut self, path_index: usize) {
+        let path = &mut self.paths[path_index];
+        let wanted_len = if path.stage == PathStage::FirstStep {
+            self.parallelism.saturating_sub(path.in_progress.len())
+        } else {
+            self.num_results.saturating_sub(path.in_progress.len())
         };
 
-        debug!("New query round ; {} queries in progress ; contacting {} new peers",
-               state.current_attempts_fut.len(),
-               to_contact.len());
+        let mut started = 0;
+        while started < wanted_len && !path.pending_nodes.is_empty() {
+            // Ignore nodes that have already been contacted by any path.
+            let peer = path.pending_nodes.remove(0);
+            if !self.contacted.insert(peer.clone()) {
+                continue;
+            }
 
-        // For each node in `to_contact`, start an RPC query and a corresponding entry in the two
-        // `state.current_attempts_*` fields.
-        for peer in to_contact {
-            let searched_key2 = searched_key.clone();
-            let current_attempt = (state.access)(&peer)
+            let rpc = self.rpc;
+            let target = self.target.clone();
+            let request = (self.access)(&peer)
                 .into_future()
-                .and_then(move |controller| {
-                    controller.find_node(&searched_key2)
-                });
-            let with_deadline = Timeout::new(current_attempt, request_timeout)
+                .and_then(move |controller| rpc(&controller, &target));
+            let with_deadline = Timeout::new(request, self.request_timeout)
                 .map_err(|err| {
                     if let Some(err) = err.into_inner() {
                         err
@@ -343,123 +1024,324 @@ where F: FnMut(&PeerId) -> Fut + 'a,
                         IoError::new(IoErrorKind::ConnectionAborted, "kademlia request timeout")
                     }
                 });
-            state.current_attempts_addrs.push(peer.clone());
-            state
-                .current_attempts_fut
-                .push(Box::new(with_deadline) as Box<_>);
-        }
-        debug_assert_eq!(
-            state.current_attempts_addrs.len(),
-            state.current_attempts_fut.len()
-        );
-
-        // Extract `current_attempts_fut` so that we can pass it to `select_all`. We will push the
-        // values back when inside the loop.
-        let current_attempts_fut = mem::replace(&mut state.current_attempts_fut, Vec::new());
-        if current_attempts_fut.is_empty() {
-            // If `current_attempts_fut` is empty, then `select_all` would panic. It happens
-            // when we have no additional node to query.
-            debug!("Finishing query early because no additional node available");
-            state.stage = Stage::FinishingNextIter;
-            let future = future::ok((None, state));
-            return Some(future::Either::A(future));
+
+            path.in_progress.push((peer.clone(), Instant::now(), Box::new(with_deadline)));
+            self.queued_events.push_back(KadQueryProgress::RequestSent {
+                hops: self.hops.get(&peer).cloned().unwrap_or(1),
+                peer_id: peer,
+                path: path_index,
+            });
+            started += 1;
         }
 
-        // This is the future that continues or breaks the `loop_fn`.
-        let future = future::select_all(current_attempts_fut.into_iter()).then(move |result| {
-            let (message, trigger_idx, other_current_attempts) = match result {
-                Err((err, trigger_idx, other_current_attempts)) => {
-                    (Err(err), trigger_idx, other_current_attempts)
-                }
-                Ok((message, trigger_idx, other_current_attempts)) => {
-                    (Ok(message), trigger_idx, other_current_attempts)
-                }
-            };
+        if started != 0 {
+            debug!("New query round on path #{} ; {} queries in progress ; contacted {} new peers",
+                   path_index, path.in_progress.len(), started);
+        }
+    }
 
-            // Putting back the extracted elements in `state`.
-            let remote_id = state.current_attempts_addrs.remove(trigger_idx);
-            debug_assert!(state.current_attempts_fut.is_empty());
-            state.current_attempts_fut = other_current_attempts;
-
-            // `message` contains the reason why the current future was woken up.
-            let closer_peers = match message {
-                Ok(msg) => msg,
-                Err(err) => {
-                    trace!("RPC query failed for {:?}: {:?}", remote_id, err);
-                    state.failed_to_contact.insert(remote_id);
-                    return future::ok((None, state));
-                }
-            };
+    // Processes the response of a remote.
+    fn inject_response(&mut self, path_index: usize, remote_id: PeerId, elapsed: Duration,
+                       closer_peers: Vec<KadPeer>, response: TResponse)
+    {
+        let remote_hops = self.hops.get(&remote_id).cloned().unwrap_or(1);
+        {
+            let target = &self.target;
+            let hops = &mut self.hops;
+            let num_results = self.num_results;
+            let contacted = &self.contacted;
+            let path = &mut self.paths[path_index];
 
-            // Inserting the node we received a response from into `state.result`.
-            // The code is non-trivial because `state.result` is ordered by distance and is limited
-            // by `num_results` elements.
-            if let Some(insert_pos) = state.result.iter().position(|e| {
-                e.distance_with(&searched_key) >= remote_id.distance_with(&searched_key)
+            // Inserting the node we received a response from into `path.result`.
+            // The code is non-trivial because `path.result` is ordered by distance and is
+            // limited by `num_results` elements.
+            if let Some(insert_pos) = path.result.iter().position(|e| {
+                key_distance(e, target) >= key_distance(&remote_id, target)
             }) {
-                if state.result[insert_pos] != remote_id {
-                    if state.result.len() >= num_results {
-                        state.result.pop();
+                if path.result[insert_pos] != remote_id {
+                    if path.result.len() >= num_results {
+                        path.result.pop();
                     }
-                    state.result.insert(insert_pos, remote_id);
+                    path.result.insert(insert_pos, remote_id.clone());
                 }
-            } else if state.result.len() < num_results {
-                state.result.push(remote_id);
+            } else if path.result.len() < num_results {
+                path.result.push(remote_id.clone());
             }
 
             // The loop below will set this variable to `true` if we find a new element to put at
             // the top of the result. This would mean that we have to continue looping.
             let mut local_nearest_node_updated = false;
 
-            // Update `state` with the actual content of the message.
-            let mut peers_reported = Vec::with_capacity(closer_peers.len());
-            for mut peer in closer_peers {
-                // Update the peerstore with the information sent by
-                // the remote.
+            for peer in closer_peers.iter() {
                 trace!("Reporting multiaddresses for {:?}: {:?}", peer.node_id, peer.multiaddrs);
-                peers_reported.push(peer.clone());
 
-                if peer.node_id.distance_with(&searched_key)
-                    <= state.result[0].distance_with(&searched_key)
-                {
+                if key_distance(&peer.node_id, target) <= key_distance(&path.result[0], target) {
                     local_nearest_node_updated = true;
                 }
 
-                if state.result.iter().any(|ma| ma == &peer.node_id) {
+                // Peers that have already been contacted, possibly by another path, are ignored.
+                if contacted.contains(&peer.node_id) {
                     continue;
                 }
 
+                let peer_hops = hops.entry(peer.node_id.clone()).or_insert(remote_hops + 1);
+                *peer_hops = cmp::min(*peer_hops, remote_hops + 1);
+
                 // Insert the node into `pending_nodes` at the right position, or do not
                 // insert it if it is already in there.
-                if let Some(insert_pos) = state.pending_nodes.iter().position(|e| {
-                    e.distance_with(&searched_key) >= peer.node_id.distance_with(&searched_key)
+                if let Some(insert_pos) = path.pending_nodes.iter().position(|e| {
+                    key_distance(e, target) >= key_distance(&peer.node_id, target)
                 }) {
-                    if state.pending_nodes[insert_pos] != peer.node_id {
-                        state.pending_nodes.insert(insert_pos, peer.node_id.clone());
+                    if path.pending_nodes[insert_pos] != peer.node_id {
+                        path.pending_nodes.insert(insert_pos, peer.node_id.clone());
                     }
                 } else {
-                    state.pending_nodes.push(peer.node_id.clone());
+                    path.pending_nodes.push(peer.node_id.clone());
                 }
             }
 
-            if state.result.len() >= num_results
-                || (state.stage != Stage::FirstStep && state.current_attempts_fut.is_empty())
+            if path.result.len() >= num_results
+                || (path.stage != PathStage::FirstStep && path.in_progress.is_empty())
             {
-                state.stage = Stage::FinishingNextIter;
+                trace!("Path #{} of the query has converged", path_index);
+                path.stage = PathStage::Done;
+                path.in_progress.clear();
+            } else if !local_nearest_node_updated {
+                trace!("Loop didn't update closer node ; jumping to step 2");
+                path.stage = PathStage::SecondStep;
+            }
+        }
+
+        if (self.stop)(&response) {
+            debug!("Query for {:?} satisfied by the response of {:?}", self.target, remote_id);
+            self.state = QueryState::FinishingNextIter;
+        }
+
+        self.queued_events.push_back(KadQueryProgress::ResponseReceived {
+            peer_id: remote_id,
+            path: path_index,
+            hops: remote_hops,
+            closer_peers,
+            response,
+            elapsed,
+        });
+    }
+
+    // Merges the results of all the paths.
+    fn take_result(&mut self) -> Vec<PeerId> {
+        let mut result = Vec::with_capacity(self.num_results);
+        for path in self.paths.iter_mut() {
+            result.extend(mem::replace(&mut path.result, Vec::new()));
+        }
+        let target = &self.target;
+        result.sort_by(|a, b| key_distance(a, target).cmp(&key_distance(b, target)));
+        result.dedup();
+        result.truncate(self.num_results);
+        result
+    }
+}
+
+impl<'a, F, Fut, TTarget, TResponse> Stream for KadQuery<'a, F, TTarget, TResponse>
+where F: FnMut(&PeerId) -> Fut + 'a,
+      Fut: IntoFuture<Item = KadConnecController, Error = IoError> + 'a,
+      Fut::Future: Send,
+      TTarget: KBucketKey + Clone + fmt::Debug + Send + 'a,
+      TResponse: 'a,
+{
+    type Item = KadQueryProgress<TResponse, Vec<PeerId>>;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+        loop {
+            if let Some(event) = self.queued_events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            match self.state {
+                QueryState::FinishingNextIter => {
+                    let result = self.take_result();
+                    debug!("Query finished with {} results", result.len());
+                    self.state = QueryState::Finished;
+                    self.paths.clear();
+                    return Ok(Async::Ready(Some(KadQueryProgress::Finished(result))));
+                },
+                QueryState::Finished => return Ok(Async::Ready(None)),
+                QueryState::Running => (),
+            }
 
-            } else {
-                if !local_nearest_node_updated {
-                    trace!("Loop didn't update closer node ; jumping to step 2");
-                    state.stage = Stage::SecondStep;
+            for path_index in 0 .. self.paths.len() {
+                if self.paths[path_index].stage == PathStage::Done {
+                    continue;
+                }
+                self.start_requests(path_index);
+                if self.paths[path_index].in_progress.is_empty() {
+                    debug!("Path #{} finished early because no additional node available",
+                           path_index);
+                    self.paths[path_index].stage = PathStage::Done;
                 }
             }
 
-            future::ok((Some(KadQueryEvent::PeersReported(peers_reported)), state))
+            if self.paths.iter().all(|path| path.stage == PathStage::Done) {
+                self.state = QueryState::FinishingNextIter;
+                continue;
+            }
+            if !self.queued_events.is_empty() {
+                continue;
+            }
+
+            // Process at most one finished request, then start again from the beginning, as the
+            // response can change the stage of the path.
+            let mut finished = None;
+            'paths: for (path_index, path) in self.paths.iter_mut().enumerate() {
+                for (n, &mut (_, _, ref mut request)) in path.in_progress.iter_mut().enumerate() {
+                    match request.poll() {
+                        Ok(Async::NotReady) => (),
+                        Ok(Async::Ready(response)) => {
+                            finished = Some((path_index, n, Ok(response)));
+                            break 'paths;
+                        },
+                        Err(err) => {
+                            finished = Some((path_index, n, Err(err)));
+                            break 'paths;
+                        },
+                    }
+                }
+            }
+
+            let (path_index, n, outcome) = match finished {
+                Some(f) => f,
+                None => return Ok(Async::NotReady),
+            };
+
+            let (remote_id, started, _) = self.paths[path_index].in_progress.remove(n);
+            let elapsed = started.elapsed();
+            match outcome {
+                Ok((closer_peers, response)) => {
+                    self.inject_response(path_index, remote_id, elapsed, closer_peers, response);
+                },
+                Err(error) => {
+                    trace!("RPC query failed for {:?}: {:?}", remote_id, error);
+                    self.queued_events.push_back(KadQueryProgress::RequestFailed {
+                        hops: self.hops.get(&remote_id).cloned().unwrap_or(1),
+                        peer_id: remote_id,
+                        path: path_index,
+                        error,
+                        elapsed,
+                    });
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tokio;
+
+    use self::tokio::runtime::current_thread::Runtime as TokioRuntime;
+    use super::*;
+    use kad_server;
+    use libp2p_core::PublicKey;
+    use libp2p_core::runtime::ManualTimer;
+    use multihash::{self, Hash};
+
+    #[test]
+    fn disjoint_paths_contact_each_peer_once() {
+        let peers = (1 .. 9u8)
+            .map(|n| PublicKey::Ed25519(vec![n; 32]).into_peer_id())
+            .collect::<Vec<_>>();
+        let system = KadSystem::without_init(KadSystemConfig {
+            parallelism: 3,
+            local_peer_id: PublicKey::Ed25519(vec![0; 32]).into_peer_id(),
+            known_initial_peers: peers.clone().into_iter(),
+            kbuckets_timeout: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(10),
+            mode: KadMode::Server,
+            random_source: RandomSource::seeded(0),
+            message_limits: MessageLimits::new(),
+            record_store: KadRecordStoreConfig::new(),
+            runtime: Runtime::default(),
+        });
+
+        let target = PublicKey::Ed25519(vec![42; 32]).into_peer_id();
+        let config = KadQueryConfig::new().with_disjoint_paths(2);
+        let events = system
+            .get_closest_peers(target, config, |_| -> Result<KadConnecController, IoError> {
+                Err(IoError::new(IoErrorKind::Other, "unreachable"))
+            })
+            .collect()
+            .wait()
+            .unwrap();
+
+        let mut contacted = Vec::new();
+        let mut paths = FnvHashSet::default();
+        for event in &events {
+            if let KadQueryProgress::RequestSent { ref peer_id, path, hops } = *event {
+                assert_eq!(hops, 1);
+                assert!(!contacted.contains(peer_id));
+                contacted.push(peer_id.clone());
+                paths.insert(path);
+            }
+        }
+
+        assert_eq!(contacted.len(), peers.len());
+        assert_eq!(paths.len(), 2);
+        match events.last() {
+            Some(KadQueryProgress::Finished(result)) => assert!(result.is_empty()),
+            _ => panic!("the last event must be Finished"),
+        }
+    }
+
+    #[test]
+    fn records_republished_to_closest_peer() {
+        let (controller_a, requests_a, _controller_b, requests_b) = kad_server::tests::build_test();
+        let peer_b = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let timer = ManualTimer::new();
+        let system = KadSystem::without_init(KadSystemConfig {
+            parallelism: 3,
+            local_peer_id: PublicKey::Ed25519(vec![0; 32]).into_peer_id(),
+            known_initial_peers: iter::once(peer_b.clone()),
+            kbuckets_timeout: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(10),
+            mode: KadMode::Server,
+            random_source: RandomSource::seeded(0),
+            message_limits: MessageLimits::new(),
+            record_store: KadRecordStoreConfig::new()
+                .with_republish_interval(Duration::from_secs(60)),
+            runtime: Runtime::deterministic(timer.clone()),
         });
 
-        Some(future::Either::B(future))
-    }).filter_map(|val| val);
+        let key = multihash::encode(Hash::SHA2256, b"hello").unwrap();
+        system.record_store().put_local(key.clone(), b"world".to_vec());
+        let republish = system
+            .republish_records(move |_| Ok::<_, IoError>(controller_a.clone()));
+        timer.advance(Duration::from_secs(60));
 
-    // Boxing the stream is not necessary, but we do it in order to improve compilation time.
-    Box::new(stream) as Box<_>
+        // The remote only reports itself as the closest peer, then receives the record.
+        let received = requests_b
+            .filter_map(move |request| match request {
+                KadIncomingRequest::GetProviders { responder, .. } => {
+                    responder.respond(iter::empty(), iter::once(KadPeer {
+                        node_id: peer_b.clone(),
+                        multiaddrs: Vec::new(),
+                        connection_ty: KadConnectionType::Connected,
+                    }));
+                    None
+                },
+                KadIncomingRequest::PutValue { key, value } => Some((key, value)),
+                _ => None,
+            })
+            .into_future()
+            .map(|(put, _)| put)
+            .map_err(|(err, _)| err);
+        let background = requests_a.for_each(|_| Ok(()))
+            .select(republish.for_each(|_| Ok(())))
+            .map(|_| None)
+            .map_err(|(err, _)| err);
+
+        let put = TokioRuntime::new().unwrap()
+            .block_on(received.select(background).map(|(put, _)| put).map_err(|(err, _)| err))
+            .unwrap();
+        assert_eq!(put, Some((key.into_bytes(), b"world".to_vec())));
+    }
 }
@@ -32,17 +32,77 @@
 //!
 //! This `KadConnecController` is usually extracted and stored in some sort of hash map in an
 //! `Arc` in order to be available whenever we need to request something from a node.
+//!
+//! # Client mode
+//!
+//! A node can be switched at runtime between server mode, the default, and client mode through a
+//! `KadModeHandle`. In client mode the node can still perform requests, but substreams opened by
+//! remotes are refused and requests sent by remotes on our own substreams close them. Since they
+//! never answer, nodes in client mode don't end up in the routing tables of other nodes.
 
 use bytes::Bytes;
 use futures::sync::{mpsc, oneshot};
 use futures::{future, Future, Sink, stream, Stream};
 use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr, PeerId};
+use libp2p_core::upgrade::MessageLimits;
 use protocol::{self, KadMsg, KademliaProtocolConfig, KadPeer};
 use std::collections::VecDeque;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::iter;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio_io::{AsyncRead, AsyncWrite};
 
+/// Mode of a Kademlia node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KadMode {
+    /// The node performs requests, but doesn't answer the requests of remotes.
+    Client,
+    /// The node performs requests and answers the requests of remotes.
+    Server,
+}
+
+/// Shared handle to the mode of a Kademlia node. Cloning the handle gives access to the same
+/// mode, which can be switched at runtime, for example when we learn that the node isn't
+/// reachable from the outside.
+#[derive(Debug, Clone)]
+pub struct KadModeHandle {
+    client: Arc<AtomicBool>,
+}
+
+impl KadModeHandle {
+    /// Creates a new handle initialized with the given mode.
+    #[inline]
+    pub fn new(mode: KadMode) -> Self {
+        KadModeHandle {
+            client: Arc::new(AtomicBool::new(mode == KadMode::Client)),
+        }
+    }
+
+    /// Returns the current mode.
+    #[inline]
+    pub fn get(&self) -> KadMode {
+        if self.client.load(Ordering::Relaxed) {
+            KadMode::Client
+        } else {
+            KadMode::Server
+        }
+    }
+
+    /// Switches to another mode. Only affects the substreams and requests received afterwards.
+    #[inline]
+    pub fn set(&self, mode: KadMode) {
+        self.client.store(mode == KadMode::Client, Ordering::Relaxed);
+    }
+}
+
+impl Default for KadModeHandle {
+    #[inline]
+    fn default() -> Self {
+        KadModeHandle::new(KadMode::Server)
+    }
+}
+
 /// Configuration for a Kademlia server.
 ///
 /// Implements `ConnectionUpgrade`. On a successful upgrade, produces a `KadConnecController`
@@ -51,16 +111,37 @@ use tokio_io::{AsyncRead, AsyncWrite};
 #[derive(Debug, Clone)]
 pub struct KadConnecConfig {
     raw_proto: KademliaProtocolConfig,
+    mode: KadModeHandle,
 }
 
 impl KadConnecConfig {
     /// Builds a configuration object for an upcoming Kademlia server.
     #[inline]
     pub fn new() -> Self {
+        KadConnecConfig::with_mode(KadModeHandle::default())
+    }
+
+    /// Builds a configuration object whose behaviour follows the mode of `mode`.
+    #[inline]
+    pub fn with_mode(mode: KadModeHandle) -> Self {
         KadConnecConfig {
-            raw_proto: KademliaProtocolConfig,
+            raw_proto: KademliaProtocolConfig::new(),
+            mode,
         }
     }
+
+    /// Bounds the size of the requests and responses of the remote with `limits`.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.raw_proto = self.raw_proto.with_message_limits(limits);
+        self
+    }
+
+    /// Returns the handle to the mode used by this configuration.
+    #[inline]
+    pub fn mode(&self) -> &KadModeHandle {
+        &self.mode
+    }
 }
 
 impl<C> ConnectionUpgrade<C> for KadConnecConfig
@@ -71,7 +152,7 @@ where
         KadConnecController,
         Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send>,
     );
-    type Future = future::Map<<KademliaProtocolConfig as ConnectionUpgrade<C>>::Future, fn((<KademliaProtocolConfig as ConnectionUpgrade<C>>::Output)) -> Self::Output>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
     type NamesIter = iter::Once<(Bytes, ())>;
     type UpgradeIdentifier = ();
 
@@ -82,9 +163,19 @@ where
 
     #[inline]
     fn upgrade(self, incoming: C, id: (), endpoint: Endpoint, addr: &Multiaddr) -> Self::Future {
-        self.raw_proto
+        if endpoint == Endpoint::Listener && self.mode.get() == KadMode::Client {
+            debug!("Refusing incoming Kademlia substream from {} in client mode", addr);
+            return Box::new(future::err(IoError::new(
+                IoErrorKind::ConnectionRefused,
+                "Kademlia node is in client mode",
+            )));
+        }
+
+        let mode = self.mode;
+        let future = self.raw_proto
             .upgrade(incoming, id, endpoint, addr)
-            .map::<fn(_) -> _, _>(build_from_sink_stream)
+            .map(move |connec| build_from_sink_stream(connec, mode));
+        Box::new(future)
     }
 }
 
@@ -109,6 +200,75 @@ impl KadConnecController {
             key: searched_key.clone().into_bytes(),
         };
 
+        self.request(message).and_then(|msg| match msg {
+            KadMsg::FindNodeRes { closer_peers, .. } => Ok(closer_peers),
+            _ => Err(IoError::new(
+                IoErrorKind::InvalidData,
+                "invalid response type received from the remote",
+            )),
+        })
+    }
+
+    /// Sends a `GET_VALUE` query to the node and provides a future that will contain the value
+    /// of the record if the remote has it, and the nodes closer to the key.
+    pub fn get_value(
+        &self,
+        key: &[u8],
+    ) -> impl Future<Item = (Option<Vec<u8>>, Vec<KadPeer>), Error = IoError> {
+        let message = protocol::KadMsg::GetValueReq {
+            key: key.to_owned(),
+        };
+
+        self.request(message).and_then(|msg| match msg {
+            KadMsg::GetValueRes { record, closer_peers, .. } => Ok((record, closer_peers)),
+            _ => Err(IoError::new(
+                IoErrorKind::InvalidData,
+                "invalid response type received from the remote",
+            )),
+        })
+    }
+
+    /// Sends a `GET_PROVIDERS` query to the node and provides a future that will contain the
+    /// providers of the key known by the remote, and the nodes closer to the key.
+    pub fn get_providers(
+        &self,
+        key: &[u8],
+    ) -> impl Future<Item = (Vec<KadPeer>, Vec<KadPeer>), Error = IoError> {
+        let message = protocol::KadMsg::GetProvidersReq {
+            key: key.to_owned(),
+        };
+
+        self.request(message).and_then(|msg| match msg {
+            KadMsg::GetProvidersRes { provider_peers, closer_peers } => {
+                Ok((provider_peers, closer_peers))
+            },
+            _ => Err(IoError::new(
+                IoErrorKind::InvalidData,
+                "invalid response type received from the remote",
+            )),
+        })
+    }
+
+    /// Sends a `PUT_VALUE` message to the node, asking it to store the record. The remote doesn't
+    /// answer, therefore the only error is if the connection has aborted.
+    pub fn put_value(&self, key: &[u8], value: Vec<u8>) -> Result<(), IoError> {
+        self.send(protocol::KadMsg::PutValue {
+            key: key.to_owned(),
+            record: value,
+        })
+    }
+
+    /// Sends an `ADD_PROVIDER` message to the node, announcing that `provider` provides `key`.
+    /// The remote doesn't answer, therefore the only error is if the connection has aborted.
+    pub fn add_provider(&self, key: &[u8], provider: KadPeer) -> Result<(), IoError> {
+        self.send(protocol::KadMsg::AddProvider {
+            key: key.to_owned(),
+            provider_peer: provider,
+        })
+    }
+
+    // Sends a request to the node and provides a future that will contain the response.
+    fn request(&self, message: KadMsg) -> impl Future<Item = KadMsg, Error = IoError> {
         let (tx, rx) = oneshot::channel();
 
         match self.inner.unbounded_send((message, tx)) {
@@ -128,12 +288,6 @@ impl KadConnecController {
                 IoErrorKind::ConnectionAborted,
                 "connection to remote has aborted",
             )
-        }).and_then(|msg| match msg {
-            KadMsg::FindNodeRes { closer_peers, .. } => Ok(closer_peers),
-            _ => Err(IoError::new(
-                IoErrorKind::InvalidData,
-                "invalid response type received from the remote",
-            )),
         });
 
         future::Either::A(future)
@@ -143,9 +297,14 @@ impl KadConnecController {
     /// no way to differentiate between a ping and a pong. Therefore this function doesn't return a
     /// future, and the only way to be notified of the result is through the stream.
     pub fn ping(&self) -> Result<(), IoError> {
+        self.send(protocol::KadMsg::Ping)
+    }
+
+    // Sends a message that doesn't expect a response.
+    fn send(&self, message: KadMsg) -> Result<(), IoError> {
         // Dummy channel, as the `tx` is going to be dropped anyway.
         let (tx, _rx) = oneshot::channel();
-        match self.inner.unbounded_send((protocol::KadMsg::Ping, tx)) {
+        match self.inner.unbounded_send((message, tx)) {
             Ok(()) => Ok(()),
             Err(_) => Err(IoError::new(
                 IoErrorKind::ConnectionAborted,
@@ -165,7 +324,37 @@ pub enum KadIncomingRequest {
         responder: KadFindNodeRespond,
     },
 
-    // TODO: PutValue and FindValue
+    /// Get the value of the record whose identifier is `key`.
+    GetValue {
+        /// Identifier of the record.
+        key: Vec<u8>,
+        /// Object to use to respond to the request.
+        responder: KadGetValueRespond,
+    },
+
+    /// Find the peers that provide `key`.
+    GetProviders {
+        /// Identifier being searched.
+        key: Vec<u8>,
+        /// Object to use to respond to the request.
+        responder: KadGetProvidersRespond,
+    },
+
+    /// Store the record whose identifier is `key`. The remote doesn't expect a response.
+    PutValue {
+        /// Identifier of the record.
+        key: Vec<u8>,
+        /// Value of the record.
+        value: Vec<u8>,
+    },
+
+    /// Remember that `provider` provides `key`. The remote doesn't expect a response.
+    AddProvider {
+        /// Identifier being provided.
+        key: Vec<u8>,
+        /// The provider, as announced by the remote.
+        provider: KadPeer,
+    },
 
     /// Received either a ping or a pong.
     PingPong,
@@ -187,12 +376,50 @@ impl KadFindNodeRespond {
     }
 }
 
+/// Object used to respond to `GetValue` queries from remotes.
+pub struct KadGetValueRespond {
+    key: Vec<u8>,
+    inner: oneshot::Sender<KadMsg>,
+}
+
+impl KadGetValueRespond {
+    /// Respond to the `GetValue` request with the value of the record if we have it, and with
+    /// the nodes closest to the key otherwise.
+    pub fn respond<I>(self, record: Option<Vec<u8>>, closer_peers: I)
+        where I: IntoIterator<Item = protocol::KadPeer>
+    {
+        let _ = self.inner.send(KadMsg::GetValueRes {
+            key: self.key,
+            record,
+            closer_peers: closer_peers.into_iter().collect(),
+        });
+    }
+}
+
+/// Object used to respond to `GetProviders` queries from remotes.
+pub struct KadGetProvidersRespond {
+    inner: oneshot::Sender<KadMsg>,
+}
+
+impl KadGetProvidersRespond {
+    /// Respond to the `GetProviders` request.
+    pub fn respond<I, J>(self, provider_peers: I, closer_peers: J)
+        where I: IntoIterator<Item = protocol::KadPeer>,
+              J: IntoIterator<Item = protocol::KadPeer>,
+    {
+        let _ = self.inner.send(KadMsg::GetProvidersRes {
+            closer_peers: closer_peers.into_iter().collect(),
+            provider_peers: provider_peers.into_iter().collect(),
+        });
+    }
+}
+
 // Builds a controller and stream from a stream/sink of raw messages.
-fn build_from_sink_stream<'a, S>(connec: S) -> (KadConnecController, Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send + 'a>)
+fn build_from_sink_stream<'a, S>(connec: S, mode: KadModeHandle) -> (KadConnecController, Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send + 'a>)
 where S: Sink<SinkItem = KadMsg, SinkError = IoError> + Stream<Item = KadMsg, Error = IoError> + Send + 'a
 {
     let (tx, rx) = mpsc::unbounded();
-    let future = kademlia_handler(connec, rx);
+    let future = kademlia_handler(connec, rx, mode);
     let controller = KadConnecController { inner: tx };
     (controller, future)
 }
@@ -205,9 +432,13 @@ where S: Sink<SinkItem = KadMsg, SinkError = IoError> + Stream<Item = KadMsg, Er
 // Returns a `Stream` that must be resolved in order for progress to work. The `Stream` will
 // produce objects that represent the requests sent by the remote. These requests must be answered
 // immediately before the stream continues to produce items.
+//
+// If `mode` is in client mode when a request is received from the remote, the stream produces an
+// error instead.
 fn kademlia_handler<'a, S>(
     kad_bistream: S,
     rq_rx: mpsc::UnboundedReceiver<(KadMsg, oneshot::Sender<KadMsg>)>,
+    mode: KadModeHandle,
 ) -> Box<Stream<Item = KadIncomingRequest, Error = IoError> + Send + 'a>
 where
     S: Stream<Item = KadMsg, Error = IoError> + Sink<SinkItem = KadMsg, SinkError = IoError> + Send + 'a,
@@ -249,6 +480,7 @@ where
                 return None;
             }
 
+            let mode = mode.clone();
             Some(events
                 .into_future()
                 .map_err(|(err, _)| err)
@@ -278,9 +510,11 @@ where
                                 });
                             Box::new(future)
                         },
-                        Some(EventSource::LocalRequest(message @ KadMsg::PutValue { .. }, _)) => {
-                            // A `PutValue` request. Contrary to other types of messages, this one
-                            // doesn't expect any answer and therefore we ignore the sender.
+                        Some(EventSource::LocalRequest(message @ KadMsg::PutValue { .. }, _))
+                        | Some(EventSource::LocalRequest(message @ KadMsg::AddProvider { .. }, _)) => {
+                            // A `PutValue` or `AddProvider` request. Contrary to other types of
+                            // messages, these don't expect any answer and therefore we ignore the
+                            // sender.
                             let future = kad_sink
                                 .send(message)
                                 .map(move |kad_sink| {
@@ -302,7 +536,7 @@ where
                             Box::new(future) as Box<_>
                         }
                         Some(EventSource::LocalRequest(message, send_back)) => {
-                            // Any local request other than `PutValue` or `Ping`.
+                            // Any local request other than `PutValue`, `AddProvider` or `Ping`.
                             send_back_queue.push_back(send_back);
                             let future = kad_sink
                                 .send(message)
@@ -312,6 +546,17 @@ where
                                 });
                             Box::new(future) as Box<_>
                         }
+                        Some(EventSource::Remote(KadMsg::FindNodeReq { .. }))
+                        | Some(EventSource::Remote(KadMsg::GetValueReq { .. }))
+                        | Some(EventSource::Remote(KadMsg::GetProvidersReq { .. }))
+                        | Some(EventSource::Remote(KadMsg::PutValue { .. }))
+                        | Some(EventSource::Remote(KadMsg::AddProvider { .. }))
+                            if mode.get() == KadMode::Client =>
+                        {
+                            debug!("Closing Kademlia substream after receiving a request in client mode");
+                            let future = future::err(IoError::new(IoErrorKind::ConnectionRefused, "Kademlia node is in client mode"));
+                            Box::new(future)
+                        }
                         Some(EventSource::Remote(KadMsg::Ping)) => {
                             // The way the protocol was designed, there is no way to differentiate
                             // between a ping and a pong.
@@ -337,8 +582,9 @@ where
                             }
                         }
                         Some(EventSource::Remote(message @ KadMsg::FindNodeRes { .. }))
-                        | Some(EventSource::Remote(message @ KadMsg::GetValueRes { .. })) => {
-                            // `FindNodeRes` or `GetValueRes` received on the socket.
+                        | Some(EventSource::Remote(message @ KadMsg::GetValueRes { .. }))
+                        | Some(EventSource::Remote(message @ KadMsg::GetProvidersRes { .. })) => {
+                            // A response received on the socket.
                             // Send it back through `send_back_queue`.
                             if let Some(send_back) = send_back_queue.pop_front() {
                                 let _ = send_back.send(message);
@@ -378,17 +624,62 @@ where
 
                             Box::new(future)
                         }
-                        Some(EventSource::Remote(KadMsg::GetValueReq { .. })) => {
-                            warn!("GET_VALUE requests are not implemented yet");
-                            let future = future::err(IoError::new(IoErrorKind::Other,
-                                "GET_VALUE requests are not implemented yet"));
-                            return Box::new(future);
+                        Some(EventSource::Remote(KadMsg::GetValueReq { key })) => {
+                            let (tx, rx) = oneshot::channel();
+                            let _ = responders_tx.unbounded_send(rx);
+                            let future = future::ok({
+                                let state = (events, kad_sink, responders_tx, send_back_queue, expected_pongs, finished);
+                                let rq = KadIncomingRequest::GetValue {
+                                    key: key.clone(),
+                                    responder: KadGetValueRespond {
+                                        key,
+                                        inner: tx
+                                    }
+                                };
+                                (Some(rq), state)
+                            });
+
+                            Box::new(future)
+                        }
+                        Some(EventSource::Remote(KadMsg::GetProvidersReq { key })) => {
+                            let (tx, rx) = oneshot::channel();
+                            let _ = responders_tx.unbounded_send(rx);
+                            let future = future::ok({
+                                let state = (events, kad_sink, responders_tx, send_back_queue, expected_pongs, finished);
+                                let rq = KadIncomingRequest::GetProviders {
+                                    key,
+                                    responder: KadGetProvidersRespond {
+                                        inner: tx
+                                    }
+                                };
+                                (Some(rq), state)
+                            });
+
+                            Box::new(future)
+                        }
+                        Some(EventSource::Remote(KadMsg::PutValue { key, record })) => {
+                            let future = future::ok({
+                                let state = (events, kad_sink, responders_tx, send_back_queue, expected_pongs, finished);
+                                let rq = KadIncomingRequest::PutValue {
+                                    key,
+                                    value: record,
+                                };
+                                (Some(rq), state)
+                            });
+
+                            Box::new(future)
                         }
-                        Some(EventSource::Remote(KadMsg::PutValue { .. })) => {
-                            warn!("PUT_VALUE requests are not implemented yet");
-                            let state = (events, kad_sink, responders_tx, send_back_queue, expected_pongs, finished);
-                            let future = future::ok((None, state));
-                            return Box::new(future);
+                        Some(EventSource::Remote(KadMsg::AddProvider { key, provider_peer })) => {
+                            let future = future::ok({
+                                let state = (events, kad_sink, responders_tx, send_back_queue, expected_pongs, finished);
+                                let rq = KadIncomingRequest::AddProvider {
+                                    key,
+                                    provider: provider_peer,
+                                };
+                                (Some(rq), state)
+                            });
+
+                            Box::new(future)
                         }
                     }
                 }))
@@ -398,12 +689,12 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::io::Error as IoError;
     use std::iter;
     use futures::{Future, Poll, Sink, StartSend, Stream};
     use futures::sync::mpsc;
-    use kad_server::{self, KadIncomingRequest, KadConnecController};
+    use kad_server::{self, KadIncomingRequest, KadConnecController, KadMode, KadModeHandle};
     use libp2p_core::PublicKey;
     use protocol::{KadConnectionType, KadPeer};
     use rand;
@@ -440,7 +731,11 @@ mod tests {
         }
     }
 
-    fn build_test() -> (KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>, KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>) {
+    pub(crate) fn build_test() -> (KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>, KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>) {
+        build_test_with_modes(KadModeHandle::default(), KadModeHandle::default())
+    }
+
+    fn build_test_with_modes(mode_a: KadModeHandle, mode_b: KadModeHandle) -> (KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>, KadConnecController, impl Stream<Item = KadIncomingRequest, Error = IoError>) {
         let (a_to_b, b_from_a) = mpsc::unbounded();
         let (b_to_a, a_from_b) = mpsc::unbounded();
 
@@ -449,8 +744,8 @@ mod tests {
         let sink_stream_b = Wrapper(b_from_a, b_to_a)
             .map_err(|_| panic!()).sink_map_err(|_| panic!());
 
-        let (controller_a, stream_events_a) = kad_server::build_from_sink_stream(sink_stream_a);
-        let (controller_b, stream_events_b) = kad_server::build_from_sink_stream(sink_stream_b);
+        let (controller_a, stream_events_a) = kad_server::build_from_sink_stream(sink_stream_a, mode_a);
+        let (controller_b, stream_events_b) = kad_server::build_from_sink_stream(sink_stream_b, mode_b);
         (controller_a, stream_events_a, controller_b, stream_events_b)
     }
 
@@ -505,4 +800,74 @@ mod tests {
             .map_err(|_| -> IoError { panic!() });
         assert_eq!(resp.wait().unwrap().0, vec![example_response]);
     }
+
+    #[test]
+    fn get_value_response() {
+        let (controller_a, stream_events_a, _controller_b, stream_events_b) = build_test();
+
+        let get_value_fut = controller_a.get_value(&[1, 2, 3]);
+
+        let streams = stream_events_a.map(|ev| (ev, "a"))
+            .select(stream_events_b.map(|ev| (ev, "b")));
+
+        let streams = match streams.into_future().map_err(|(err, _)| err).wait().unwrap() {
+            (Some((KadIncomingRequest::GetValue { key, responder }, "b")), streams) => {
+                assert_eq!(key, vec![1, 2, 3]);
+                responder.respond(Some(vec![4, 5, 6]), iter::empty());
+                streams
+            },
+            _ => panic!()
+        };
+
+        let resp = streams.into_future().map_err(|(err, _)| err).map(|_| unreachable!())
+            .select(get_value_fut)
+            .map_err(|_| -> IoError { panic!() });
+        assert_eq!(resp.wait().unwrap().0, (Some(vec![4, 5, 6]), Vec::new()));
+    }
+
+    #[test]
+    fn put_value_and_add_provider_reach_remote() {
+        let (controller_a, stream_events_a, _controller_b, stream_events_b) = build_test();
+
+        let provider = KadPeer {
+            node_id: PublicKey::Ed25519(vec![1; 32]).into_peer_id(),
+            multiaddrs: Vec::new(),
+            connection_ty: KadConnectionType::Connected,
+        };
+        controller_a.put_value(&[1, 2, 3], vec![4, 5, 6]).unwrap();
+        controller_a.add_provider(&[1, 2, 3], provider.clone()).unwrap();
+
+        let streams = stream_events_a.map(|ev| (ev, "a"))
+            .select(stream_events_b.map(|ev| (ev, "b")));
+
+        let streams = match streams.into_future().map_err(|(err, _)| err).wait().unwrap() {
+            (Some((KadIncomingRequest::PutValue { key, value }, "b")), streams) => {
+                assert_eq!(key, vec![1, 2, 3]);
+                assert_eq!(value, vec![4, 5, 6]);
+                streams
+            },
+            _ => panic!()
+        };
+
+        match streams.into_future().map_err(|(err, _)| err).wait().unwrap() {
+            (Some((KadIncomingRequest::AddProvider { key, provider: received }, "b")), _) => {
+                assert_eq!(key, vec![1, 2, 3]);
+                assert_eq!(received, provider);
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn client_mode_refuses_requests() {
+        let mode_b = KadModeHandle::new(KadMode::Client);
+        let (controller_a, _stream_events_a, _controller_b, stream_events_b) =
+            build_test_with_modes(KadModeHandle::default(), mode_b.clone());
+
+        let _get_value_fut = controller_a.get_value(&[1, 2, 3]);
+        assert!(stream_events_b.into_future().map_err(|(err, _)| err).wait().is_err());
+
+        mode_b.set(KadMode::Server);
+        assert_eq!(mode_b.get(), KadMode::Server);
+    }
 }
@@ -26,10 +26,22 @@
 //! If the local ID has `N` bits, then the k-buckets table contains `N` *buckets* each containing
 //! a constant number of entries. Storing a key in the k-buckets table adds it to the bucket
 //! corresponding to its distance with the reference key.
+//!
+//! When a bucket is full, a new node is put in a single pending slot and the least-recently-seen
+//! node of the bucket must be pinged. If it answers, the pending node is dropped. If it doesn't
+//! answer in time, or if the ping is reported as failed with `report_ping_failure`, it is evicted
+//! and replaced with the pending node. Each of these changes is recorded as a `KBucketEvent`,
+//! which can be retrieved with `drain_events`, and the content of the table can be inspected with
+//! `entries`.
+//!
+//! Identifiers that implement `KBucketKey` share their keyspace with other types of keys, such as
+//! the hashes of the records. The nodes closest to any of these keys can be found with
+//! `find_closest_to_key`.
 
 use arrayvec::ArrayVec;
 use bigint::U512;
 use libp2p_core::PeerId;
+use multihash::{self, Hash, Multihash, MultihashRef};
 use parking_lot::{Mutex, MutexGuard};
 use std::mem;
 use std::slice::Iter as SliceIter;
@@ -46,6 +58,8 @@ pub struct KBucketsTable<Id, Val> {
     tables: Vec<Mutex<KBucket<Id, Val>>>,
     // The timeout when pinging the first node after which we consider that it no longer responds.
     ping_timeout: Duration,
+    // Changes in the table that haven't been retrieved with `drain_events` yet.
+    events: Mutex<Vec<KBucketEvent<Id>>>,
 }
 
 impl<Id, Val> Clone for KBucketsTable<Id, Val>
@@ -63,6 +77,7 @@ where
                 .map(Mutex::new)
                 .collect(),
             ping_timeout: self.ping_timeout.clone(),
+            events: Mutex::new(self.events.lock().clone()),
         }
     }
 }
@@ -84,26 +99,96 @@ struct KBucket<Id, Val> {
     last_update: Instant,
 }
 
-impl<Id, Val> KBucket<Id, Val> {
+impl<Id, Val> KBucket<Id, Val>
+where
+    Id: Clone,
+{
     // Puts the kbucket into a coherent state.
     // If a node is pending and the timeout has expired, removes the first element of `nodes`
-    // and pushes back the node in `pending_node`.
-    fn flush(&mut self, timeout: Duration) {
+    // and pushes back the node in `pending_node`. Returns the corresponding event, if any.
+    fn flush(&mut self, timeout: Duration) -> Option<KBucketEvent<Id>> {
         if let Some((pending_node, instant)) = self.pending_node.take() {
             if instant.elapsed() >= timeout {
-                let _ = self.nodes.remove(0);
-                self.nodes.push(pending_node);
+                Some(self.replace_first(pending_node))
             } else {
                 self.pending_node = Some((pending_node, instant));
+                None
             }
+        } else {
+            None
         }
     }
+
+    // Evicts the first node of the bucket and inserts `replacement` at the end.
+    fn replace_first(&mut self, mut replacement: Node<Id, Val>) -> KBucketEvent<Id> {
+        let evicted = self.nodes.remove(0).id;
+        replacement.last_seen = Instant::now();
+        let inserted = replacement.id.clone();
+        self.nodes.push(replacement);
+        self.last_update = Instant::now();
+        KBucketEvent::Evicted { evicted, inserted }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Node<Id, Val> {
     id: Id,
     value: Val,
+    // Last time we received a communication from this node. For the pending node, the time when
+    // it has been put in the pending slot.
+    last_seen: Instant,
+}
+
+/// Change in a `KBucketsTable`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KBucketEvent<Id> {
+    /// A node has been inserted in a bucket that had free space.
+    Inserted(Id),
+    /// A node has been put in the pending slot of a full bucket. The first node of the bucket,
+    /// which is the least recently seen, must be pinged.
+    PendingPing {
+        /// The node waiting for a slot.
+        pending: Id,
+        /// The node that must be pinged.
+        to_ping: Id,
+    },
+    /// The node that has been pinged didn't answer, and has been replaced with the pending node.
+    Evicted {
+        /// The node that has been removed from the table.
+        evicted: Id,
+        /// The pending node that took its place.
+        inserted: Id,
+    },
+    /// The node that has been pinged answered, and the pending node has been dropped.
+    PendingDropped(Id),
+    /// A node has been discarded because its bucket was full and a node was already pending.
+    Discarded(Id),
+}
+
+/// Status of an entry of a `KBucketsTable`, as reported by `entries`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KBucketEntryStatus {
+    /// The node is in the table.
+    Alive,
+    /// The node is in the table, but is the least recently seen node of a full bucket and is
+    /// being pinged. It will be evicted if it doesn't answer.
+    AwaitingPing,
+    /// The node is waiting in the pending slot of its bucket.
+    Pending,
+}
+
+/// Entry of a `KBucketsTable`, as reported by `entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KBucketEntry<Id> {
+    /// Identifier of the node.
+    pub id: Id,
+    /// Index of the bucket, which is the number of bits of the distance to the local node.
+    pub bucket: usize,
+    /// Last time we received a communication from this node. For a pending node, the time when
+    /// it has been put in the pending slot.
+    pub last_seen: Instant,
+    /// Status of the node.
+    pub status: KBucketEntryStatus,
 }
 
 /// Trait that must be implemented on types that can be used as an identifier in a k-bucket.
@@ -134,11 +219,7 @@ impl KBucketsPeerId for PeerId {
 
     #[inline]
     fn distance_with(&self, other: &Self) -> Self::Distance {
-        // Note that we don't compare the hash functions because there's no chance of collision
-        // of the same value hashed with two different hash functions.
-        let my_hash = U512::from(self.digest());
-        let other_hash = U512::from(other.digest());
-        my_hash ^ other_hash
+        key_distance(self, other)
     }
 
     #[inline]
@@ -147,6 +228,69 @@ impl KBucketsPeerId for PeerId {
     }
 }
 
+/// Trait implemented on the values that have a position in the Kademlia keyspace.
+///
+/// Peer IDs and the keys of the records live in the same keyspace, which makes it possible to
+/// compute the distance between a peer and a record, and therefore to find the peers responsible
+/// for storing a record.
+pub trait KBucketKey {
+    /// Returns the position of the value in the keyspace, as a big-endian integer of at most 64
+    /// bytes. Values whose position is shorter are padded with zeroes on the left.
+    fn kbucket_key(&self) -> Vec<u8>;
+}
+
+impl KBucketKey for PeerId {
+    #[inline]
+    fn kbucket_key(&self) -> Vec<u8> {
+        multihash_key(self.as_bytes())
+    }
+}
+
+impl KBucketKey for Multihash {
+    #[inline]
+    fn kbucket_key(&self) -> Vec<u8> {
+        multihash_key(self.as_bytes())
+    }
+}
+
+/// A raw 256-bit key, for example the SHA-256 hash of some content, is its own position.
+impl KBucketKey for [u8; 32] {
+    #[inline]
+    fn kbucket_key(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+/// Computes the XOR distance between two values of the keyspace.
+///
+/// Note that we don't compare the hash functions because there's no chance of collision of the
+/// same value hashed with two different hash functions.
+pub fn key_distance<A, B>(a: &A, b: &B) -> U512
+where
+    A: KBucketKey + ?Sized,
+    B: KBucketKey + ?Sized,
+{
+    U512::from(&a.kbucket_key()[..]) ^ U512::from(&b.kbucket_key()[..])
+}
+
+/// Returns the value that positions the bytes of a multihash in the XOR metric.
+///
+/// `PeerId`s that embed a public key aren't uniformly distributed, therefore we hash them. The
+/// same goes for the digests that are too long to fit in the keyspace.
+fn multihash_key(bytes: &[u8]) -> Vec<u8> {
+    match MultihashRef::from_slice(bytes) {
+        Ok(ref hash) if hash.algorithm() != Hash::Identity && hash.digest().len() <= 64 => {
+            hash.digest().to_vec()
+        },
+        _ => {
+            multihash::encode(Hash::SHA2256, bytes)
+                .expect("sha2-256 is always supported")
+                .digest()
+                .to_vec()
+        },
+    }
+}
+
 impl<Id, Val> KBucketsTable<Id, Val>
 where
     Id: KBucketsPeerId,
@@ -164,6 +308,81 @@ where
                 .map(Mutex::new)
                 .collect(),
             ping_timeout: ping_timeout,
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Locks a bucket and flushes it, recording the eviction that happens, if any.
+    fn lock_bucket(&self, num: usize) -> MutexGuard<KBucket<Id, Val>> {
+        let mut table = self.tables[num].lock();
+        if let Some(event) = table.flush(self.ping_timeout) {
+            self.events.lock().push(event);
+        }
+        table
+    }
+
+    /// Returns the changes that happened in the table since the last call.
+    pub fn drain_events(&self) -> Vec<KBucketEvent<Id>> {
+        for num in 0 .. self.tables.len() {
+            let _ = self.lock_bucket(num);
+        }
+        mem::replace(&mut *self.events.lock(), Vec::new())
+    }
+
+    /// Returns all the entries of the table, including the pending nodes, ordered by bucket.
+    pub fn entries(&self) -> Vec<KBucketEntry<Id>> {
+        let mut out = Vec::new();
+        for num in 0 .. self.tables.len() {
+            let table = self.lock_bucket(num);
+            for (pos, node) in table.nodes.iter().enumerate() {
+                let status = if pos == 0 && table.pending_node.is_some() {
+                    KBucketEntryStatus::AwaitingPing
+                } else {
+                    KBucketEntryStatus::Alive
+                };
+                out.push(KBucketEntry {
+                    id: node.id.clone(),
+                    bucket: num,
+                    last_seen: node.last_seen,
+                    status,
+                });
+            }
+            if let Some((ref node, _)) = table.pending_node {
+                out.push(KBucketEntry {
+                    id: node.id.clone(),
+                    bucket: num,
+                    last_seen: node.last_seen,
+                    status: KBucketEntryStatus::Pending,
+                });
+            }
+        }
+        out
+    }
+
+    /// Reports that the given node didn't answer a ping. If it is the node that must be pinged
+    /// before a pending node can be inserted, it is evicted and replaced with the pending node
+    /// immediately, instead of waiting for the ping timeout.
+    ///
+    /// Returns true if the node has been evicted.
+    pub fn report_ping_failure(&self, id: &Id) -> bool {
+        let num = match self.bucket_num(id) {
+            Some(n) => n,
+            None => return false,
+        };
+
+        let mut table = self.lock_bucket(num);
+        let is_first = table.nodes.first().map(|n| n.id == *id).unwrap_or(false);
+        if !is_first {
+            return false;
+        }
+
+        match table.pending_node.take() {
+            Some((pending, _)) => {
+                let event = table.replace_first(pending);
+                self.events.lock().push(event);
+                true
+            },
+            None => false,
         }
     }
 
@@ -181,7 +400,7 @@ where
     /// first.
     #[inline]
     pub fn buckets(&self) -> BucketsIter<Id, Val> {
-        BucketsIter(self.tables.iter(), self.ping_timeout)
+        BucketsIter(self.tables.iter(), self.ping_timeout, &self.events)
     }
 
     /// Returns the ID of the local node.
@@ -197,9 +416,8 @@ where
     {
         // TODO: optimize
         let mut out = Vec::new();
-        for table in self.tables.iter() {
-            let mut table = table.lock();
-            table.flush(self.ping_timeout);
+        for num in 0 .. self.tables.len() {
+            let table = self.lock_bucket(num);
             if table.last_update.elapsed() > self.ping_timeout {
                 continue // ignore bucket with expired nodes
             }
@@ -211,6 +429,48 @@ where
         out.into_iter()
     }
 
+    /// Finds the nodes closest to `key`, ordered by distance. Contrary to `find_closest`, `key`
+    /// doesn't need to be a node identifier.
+    pub fn find_closest_to_key<K>(&self, key: &K) -> VecIntoIter<Id>
+    where
+        Id: KBucketKey + Clone,
+        K: KBucketKey + ?Sized,
+    {
+        // TODO: optimize
+        let mut out = Vec::new();
+        for num in 0 .. self.tables.len() {
+            let table = self.lock_bucket(num);
+            if table.last_update.elapsed() > self.ping_timeout {
+                continue // ignore bucket with expired nodes
+            }
+            for node in table.nodes.iter() {
+                out.push(node.id.clone());
+            }
+        }
+        out.sort_by(|a, b| key_distance(a, key).cmp(&key_distance(b, key)));
+        out.into_iter()
+    }
+
+    /// Same as `find_closest_to_key`, but includes the local peer as well.
+    pub fn find_closest_to_key_with_self<K>(&self, key: &K) -> VecIntoIter<Id>
+    where
+        Id: KBucketKey + Clone,
+        K: KBucketKey + ?Sized,
+    {
+        let mut intermediate: Vec<_> = self.find_closest_to_key(key).collect();
+        if let Some(pos) = intermediate
+            .iter()
+            .position(|e| key_distance(e, key) >= key_distance(&self.my_id, key))
+        {
+            if intermediate[pos] != self.my_id {
+                intermediate.insert(pos, self.my_id.clone());
+            }
+        } else {
+            intermediate.push(self.my_id.clone());
+        }
+        intermediate.into_iter()
+    }
+
     /// Same as `find_closest`, but includes the local peer as well.
     pub fn find_closest_with_self(&self, id: &Id) -> VecIntoIter<Id>
     where
@@ -234,49 +494,66 @@ where
     /// Marks the node as "most recent" in its bucket and modifies the value associated to it.
     /// This function should be called whenever we receive a communication from a node.
     pub fn update(&self, id: Id, value: Val) -> UpdateOutcome<Id, Val> {
-        let table = match self.bucket_num(&id) {
-            Some(n) => &self.tables[n],
+        let num = match self.bucket_num(&id) {
+            Some(n) => n,
             None => return UpdateOutcome::FailSelfUpdate,
         };
 
-        let mut table = table.lock();
-        table.flush(self.ping_timeout);
+        let mut table = self.lock_bucket(num);
 
         if let Some(pos) = table.nodes.iter().position(|n| n.id == id) {
             // Node is already in the bucket.
             let mut existing = table.nodes.remove(pos);
             let old_val = mem::replace(&mut existing.value, value);
+            existing.last_seen = Instant::now();
             if pos == 0 {
-                // If it's the first node of the bucket that we update, then we drop the node that
-                // was waiting for a ping.
+                // If it's the first node of the bucket that we update, then it answered the ping
+                // and we drop the node that was waiting.
                 table.nodes.truncate(MAX_NODES_PER_BUCKET - 1);
-                table.pending_node = None;
+                if let Some((pending, _)) = table.pending_node.take() {
+                    self.events.lock().push(KBucketEvent::PendingDropped(pending.id));
+                }
             }
             table.nodes.push(existing);
             table.last_update = Instant::now();
             UpdateOutcome::Refreshed(old_val)
         } else if table.nodes.len() < MAX_NODES_PER_BUCKET {
             // Node not yet in the bucket, but there's plenty of space.
+            self.events.lock().push(KBucketEvent::Inserted(id.clone()));
             table.nodes.push(Node {
                 id: id,
                 value: value,
+                last_seen: Instant::now(),
             });
             table.last_update = Instant::now();
             UpdateOutcome::Added
+        } else if table.pending_node.as_ref().map(|p| p.0.id == id).unwrap_or(false) {
+            // The node is already pending ; only update its value.
+            if let Some((ref mut pending, _)) = table.pending_node {
+                pending.value = value;
+            }
+            UpdateOutcome::Discarded
         } else {
             // Not enough space to put the node, but we can add it to the end as "pending". We
             // then need to tell the caller that we want it to ping the node at the top of the
             // list.
             if table.pending_node.is_none() {
+                let to_ping = table.nodes[0].id.clone();
+                self.events.lock().push(KBucketEvent::PendingPing {
+                    pending: id.clone(),
+                    to_ping: to_ping.clone(),
+                });
                 table.pending_node = Some((
                     Node {
                         id: id,
                         value: value,
+                        last_seen: Instant::now(),
                     },
                     Instant::now(),
                 ));
-                UpdateOutcome::NeedPing(table.nodes[0].id.clone())
+                UpdateOutcome::NeedPing(to_ping)
             } else {
+                self.events.lock().push(KBucketEvent::Discarded(id));
                 UpdateOutcome::Discarded
             }
         }
@@ -301,16 +578,25 @@ pub enum UpdateOutcome<Id, Val> {
 }
 
 /// Iterator giving access to a bucket.
-pub struct BucketsIter<'a, Id: 'a, Val: 'a>(SliceIter<'a, Mutex<KBucket<Id, Val>>>, Duration);
+pub struct BucketsIter<'a, Id: 'a, Val: 'a>(
+    SliceIter<'a, Mutex<KBucket<Id, Val>>>,
+    Duration,
+    &'a Mutex<Vec<KBucketEvent<Id>>>,
+);
 
-impl<'a, Id: 'a, Val: 'a> Iterator for BucketsIter<'a, Id, Val> {
+impl<'a, Id: 'a, Val: 'a> Iterator for BucketsIter<'a, Id, Val>
+where
+    Id: Clone,
+{
     type Item = Bucket<'a, Id, Val>;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next().map(|bucket| {
             let mut bucket = bucket.lock();
-            bucket.flush(self.1);
+            if let Some(event) = bucket.flush(self.1) {
+                self.2.lock().push(event);
+            }
             Bucket(bucket)
         })
     }
@@ -321,7 +607,7 @@ impl<'a, Id: 'a, Val: 'a> Iterator for BucketsIter<'a, Id, Val> {
     }
 }
 
-impl<'a, Id: 'a, Val: 'a> ExactSizeIterator for BucketsIter<'a, Id, Val> {}
+impl<'a, Id: 'a, Val: 'a> ExactSizeIterator for BucketsIter<'a, Id, Val> where Id: Clone {}
 
 /// Access to a bucket.
 pub struct Bucket<'a, Id: 'a, Val: 'a>(MutexGuard<'a, KBucket<Id, Val>>);
@@ -356,8 +642,10 @@ impl<'a, Id: 'a, Val: 'a> Bucket<'a, Id, Val> {
 mod tests {
     extern crate rand;
     use self::rand::random;
-    use kbucket::{KBucketsTable, UpdateOutcome, MAX_NODES_PER_BUCKET};
+    use kbucket::{key_distance, KBucketEntryStatus, KBucketEvent, KBucketsTable, UpdateOutcome};
+    use kbucket::MAX_NODES_PER_BUCKET;
     use libp2p_core::PeerId;
+use multihash::{self, Hash, MultihashRef};
     use std::thread;
     use std::time::Duration;
 
@@ -385,6 +673,33 @@ mod tests {
         assert_eq!(res[0], other_id);
     }
 
+    #[test]
+    fn closest_to_record_key() {
+        let random_id = || {
+            let mut bytes = (0 .. 34).map(|_| random()).collect::<Vec<u8>>();
+            bytes[0] = 18;
+            bytes[1] = 32;
+            PeerId::from_bytes(bytes).unwrap()
+        };
+
+        let my_id = random_id();
+        let table = KBucketsTable::new(my_id.clone(), Duration::from_secs(5));
+        for _ in 0 .. 10 {
+            let _ = table.update(random_id(), ());
+        }
+
+        let key = multihash::encode(Hash::SHA2256, b"some content").unwrap();
+        let res = table.find_closest_to_key(&key).collect::<Vec<_>>();
+        assert_eq!(res.len(), 10);
+        for pair in res.windows(2) {
+            assert!(key_distance(&pair[0], &key) <= key_distance(&pair[1], &key));
+        }
+
+        let with_self = table.find_closest_to_key_with_self(&key).collect::<Vec<_>>();
+        assert_eq!(with_self.len(), 11);
+        assert!(with_self.contains(&my_id));
+    }
+
     #[test]
     fn update_local_id_fails() {
         let my_id = {
@@ -495,4 +810,65 @@ mod tests {
             UpdateOutcome::NeedPing(second_node)
         );
     }
+
+    #[test]
+    fn ping_failure_evicts_and_reports() {
+        let my_id = {
+            let mut bytes = vec![random(); 34];
+            bytes[0] = 18;
+            bytes[1] = 32;
+            PeerId::from_bytes(bytes).unwrap()
+        };
+
+        let mut fill_ids = (0..MAX_NODES_PER_BUCKET + 2)
+            .map(|n| {
+                let mut id = my_id.clone().into_bytes();
+                id[2] ^= 0x80;
+                id[33] = id[33].wrapping_add(n as u8);
+                PeerId::from_bytes(id).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let first_node = fill_ids[0].clone();
+        let second_node = fill_ids[1].clone();
+        let table = KBucketsTable::new(my_id, Duration::from_secs(60));
+        for id in fill_ids.drain(..MAX_NODES_PER_BUCKET) {
+            assert_eq!(table.update(id, ()), UpdateOutcome::Added);
+        }
+        assert_eq!(table.drain_events().len(), MAX_NODES_PER_BUCKET);
+
+        // The first node answers the ping ; the pending node is dropped.
+        let pending1 = fill_ids.remove(0);
+        assert_eq!(table.update(pending1.clone(), ()), UpdateOutcome::NeedPing(first_node.clone()));
+        {
+            let entries = table.entries();
+            assert_eq!(entries.len(), MAX_NODES_PER_BUCKET + 1);
+            assert!(entries.iter().all(|e| e.bucket == 255));
+            assert_eq!(entries[0].id, first_node);
+            assert_eq!(entries[0].status, KBucketEntryStatus::AwaitingPing);
+            assert_eq!(entries[MAX_NODES_PER_BUCKET].id, pending1);
+            assert_eq!(entries[MAX_NODES_PER_BUCKET].status, KBucketEntryStatus::Pending);
+        }
+        assert_eq!(table.update(first_node.clone(), ()), UpdateOutcome::Refreshed(()));
+        assert_eq!(table.drain_events(), vec![
+            KBucketEvent::PendingPing { pending: pending1.clone(), to_ping: first_node.clone() },
+            KBucketEvent::PendingDropped(pending1),
+        ]);
+
+        // The second node, now the least recently seen, fails to answer.
+        let pending2 = fill_ids.remove(0);
+        assert_eq!(table.update(pending2.clone(), ()), UpdateOutcome::NeedPing(second_node.clone()));
+        assert!(!table.report_ping_failure(&first_node));
+        assert!(table.report_ping_failure(&second_node));
+        assert_eq!(table.drain_events(), vec![
+            KBucketEvent::PendingPing { pending: pending2.clone(), to_ping: second_node.clone() },
+            KBucketEvent::Evicted { evicted: second_node.clone(), inserted: pending2.clone() },
+        ]);
+
+        let entries = table.entries();
+        assert_eq!(entries.len(), MAX_NODES_PER_BUCKET);
+        assert!(entries.iter().all(|e| e.status == KBucketEntryStatus::Alive));
+        assert!(entries.iter().all(|e| e.id != second_node));
+        assert_eq!(entries.last().unwrap().id, pending2);
+    }
 }
@@ -34,6 +34,28 @@
 //!
 //! - You can perform queries using the `KadSystem`.
 //!
+//! The `get_closest_peers`, `get_record` and `get_providers` queries of the `KadSystem` produce a
+//! stream of `KadQueryProgress` events, which report each request sent to a peer and its outcome
+//! before the final result. Each event carries the number of hops between the local node and the
+//! peer, and a `KadQueryStats` can be fed with the events to summarize the query. Together with
+//! `KadSystem::bucket_occupancy`, this is enough to follow the health of the DHT.
+//!
+//! Peers and records share the same keyspace. Any type that implements `KBucketKey`, such as
+//! `PeerId`, `Multihash` or a raw 256-bit hash, can be positioned in it, and
+//! `KadSystem::known_closest_peers` finds the peers closest to any of them.
+//!
+//! A node can be switched at runtime to client mode with `KadSystem::set_mode`. In client mode the
+//! node keeps performing queries but doesn't answer the requests of remotes, so that it doesn't
+//! end up in their routing tables. Use `KadSystem::connec_config` to build a `KadConnecConfig`
+//! that follows the mode of the system.
+//!
+//! The `KadRecordStore` of the `KadSystem` holds the value and provider records that the node
+//! serves to the remotes. Pass the requests of the remotes to `KadSystem::serve_request` in order
+//! to answer them from the store, and publish records with `put_record` and `provide`. The stream
+//! returned by `republish_records` removes the records once they expire, and sends our own
+//! records again to the closest peers when they must be republished. It uses the timer of the
+//! `Runtime` of the configuration, and therefore follows the virtual time of a simulation.
+//!
 
 // TODO: we allow dead_code for now because this library contains a lot of unused code that will
 //       be useful later for record store
@@ -69,6 +91,7 @@ extern crate libp2p_core;
 #[macro_use]
 extern crate log;
 extern crate multiaddr;
+extern crate multihash;
 extern crate parking_lot;
 extern crate protobuf;
 extern crate rand;
@@ -77,13 +100,18 @@ extern crate tokio_codec;
 extern crate tokio_io;
 extern crate tokio_timer;
 extern crate unsigned_varint;
+extern crate wasm_timer;
 
-pub use self::high_level::{KadSystemConfig, KadSystem, KadQueryEvent};
+pub use self::high_level::{KadSystemConfig, KadSystem, KadQueryConfig, KadQueryEvent, KadQueryProgress, KadQueryStats};
 pub use self::kad_server::{KadConnecController, KadConnecConfig, KadIncomingRequest, KadFindNodeRespond};
+pub use self::kad_server::{KadGetProvidersRespond, KadGetValueRespond, KadMode, KadModeHandle};
+pub use self::kbucket::{KBucketEntry, KBucketEntryStatus, KBucketEvent, KBucketKey, KBucketsPeerId};
 pub use self::protocol::{KadConnectionType, KadPeer};
+pub use self::record_store::{KadRecordEvent, KadRecordStore, KadRecordStoreConfig, KadRecordWorker};
 
 mod high_level;
 mod kad_server;
 mod kbucket;
 mod protobuf_structs;
+mod record_store;
 mod protocol;
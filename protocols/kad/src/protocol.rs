@@ -28,6 +28,7 @@
 use bytes::{Bytes, BytesMut};
 use futures::{future, sink, Sink, stream, Stream};
 use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr, PeerId};
+use libp2p_core::upgrade::MessageLimits;
 use protobuf::{self, Message};
 use protobuf_structs;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
@@ -122,10 +123,32 @@ impl Into<protobuf_structs::dht::Message_Peer> for KadPeer {
     }
 }
 
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/ipfs/kad/1.0.0";
+
 /// Configuration for a Kademlia connection upgrade. When applied to a connection, turns this
 /// connection into a `Stream + Sink` whose items are of type `KadMsg`.
-#[derive(Debug, Default, Copy, Clone)]
-pub struct KademliaProtocolConfig;
+#[derive(Debug, Clone, Default)]
+pub struct KademliaProtocolConfig {
+    /// Bounds the size of each message received from the remote.
+    limits: MessageLimits,
+}
+
+impl KademliaProtocolConfig {
+    /// Builds a new configuration, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        KademliaProtocolConfig::default()
+    }
+
+    /// Replaces the limits applied to the messages of the remote. A message over the limit
+    /// produces an error on the stream.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
 
 impl<C> ConnectionUpgrade<C> for KademliaProtocolConfig
 where
@@ -138,12 +161,12 @@ where
 
     #[inline]
     fn protocol_names(&self) -> Self::NamesIter {
-        iter::once(("/ipfs/kad/1.0.0".into(), ()))
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
     }
 
     #[inline]
     fn upgrade(self, incoming: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
-        future::ok(kademlia_protocol(incoming))
+        future::ok(kademlia_protocol(incoming, self.limits.max_for(PROTOCOL_NAME)))
     }
 }
 
@@ -152,11 +175,14 @@ type KadStreamSink<S> = stream::AndThen<sink::With<stream::FromErr<Framed<S, cod
 // Upgrades a socket to use the Kademlia protocol.
 fn kademlia_protocol<S>(
     socket: S,
+    max_message_size: usize,
 ) -> KadStreamSink<S>
 where
     S: AsyncRead + AsyncWrite,
 {
-    Framed::new(socket, codec::UviBytes::default())
+    let mut codec = codec::UviBytes::default();
+    codec.set_max_len(max_message_size);
+    Framed::new(socket, codec)
         .from_err::<IoError>()
         .with::<_, fn(_) -> _, _>(|request| -> Result<_, IoError> {
             let proto_struct = msg_to_proto(request);
@@ -178,8 +204,8 @@ pub enum KadMsg {
     PutValue {
         /// Identifier of the record.
         key: Vec<u8>,
-        /// The record itself.
-        record: (), //record: protobuf_structs::record::Record, // TODO: no
+        /// Value of the record.
+        record: Vec<u8>,
     },
     GetValueReq {
         /// Identifier of the record.
@@ -188,7 +214,9 @@ pub enum KadMsg {
     GetValueRes {
         /// Identifier of the returned record.
         key: Vec<u8>,
-        record: (), //record: Option<protobuf_structs::record::Record>, // TODO: no
+        /// Value of the record, if the remote has it.
+        record: Option<Vec<u8>>,
+        /// Peers closer to the key, if the remote doesn't have the record.
         closer_peers: Vec<KadPeer>,
     },
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
@@ -202,6 +230,26 @@ pub enum KadMsg {
         /// Results of the request.
         closer_peers: Vec<KadPeer>,
     },
+    /// Request for the list of peers that provide `key`, and for the nodes closest to `key`.
+    GetProvidersReq {
+        /// Identifier being searched.
+        key: Vec<u8>,
+    },
+    /// Response to a `GetProvidersReq`.
+    GetProvidersRes {
+        /// Nodes closest to the key.
+        closer_peers: Vec<KadPeer>,
+        /// Known providers for this key.
+        provider_peers: Vec<KadPeer>,
+    },
+    /// Target must remember that `provider_peer` provides `key`, and can return it later in a
+    /// `GetProvidersRes`.
+    AddProvider {
+        /// Identifier being provided.
+        key: Vec<u8>,
+        /// The provider.
+        provider_peer: KadPeer,
+    },
 }
 
 // Turns a type-safe kadmelia message into the corresponding row protobuf message.
@@ -212,11 +260,14 @@ fn msg_to_proto(kad_msg: KadMsg) -> protobuf_structs::dht::Message {
             msg.set_field_type(protobuf_structs::dht::Message_MessageType::PING);
             msg
         }
-        KadMsg::PutValue { key, .. } => {
+        KadMsg::PutValue { key, record } => {
             let mut msg = protobuf_structs::dht::Message::new();
             msg.set_field_type(protobuf_structs::dht::Message_MessageType::PUT_VALUE);
+            let mut raw_record = protobuf_structs::record::Record::new();
+            raw_record.set_key(bs58::encode(&key).into_string());
+            raw_record.set_value(record);
+            msg.set_record(raw_record);
             msg.set_key(key);
-            //msg.set_record(record);		// TODO:
             msg
         }
         KadMsg::GetValueReq { key } => {
@@ -226,7 +277,24 @@ fn msg_to_proto(kad_msg: KadMsg) -> protobuf_structs::dht::Message {
             msg.set_clusterLevelRaw(10);
             msg
         }
-        KadMsg::GetValueRes { .. } => unimplemented!(),     // TODO:
+        KadMsg::GetValueRes { key, record, closer_peers } => {
+            // TODO: if both `record` and `closer_peers` are empty, the remote will think it's a
+            //       request
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::GET_VALUE);
+            msg.set_clusterLevelRaw(9);
+            if let Some(value) = record {
+                let mut raw_record = protobuf_structs::record::Record::new();
+                raw_record.set_key(bs58::encode(&key).into_string());
+                raw_record.set_value(value);
+                msg.set_record(raw_record);
+            }
+            msg.set_key(key);
+            for peer in closer_peers {
+                msg.mut_closerPeers().push(peer.into());
+            }
+            msg
+        }
         KadMsg::FindNodeReq { key } => {
             let mut msg = protobuf_structs::dht::Message::new();
             msg.set_field_type(protobuf_structs::dht::Message_MessageType::FIND_NODE);
@@ -246,6 +314,33 @@ fn msg_to_proto(kad_msg: KadMsg) -> protobuf_structs::dht::Message {
             }
             msg
         }
+        KadMsg::GetProvidersReq { key } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::GET_PROVIDERS);
+            msg.set_key(key);
+            msg.set_clusterLevelRaw(10);
+            msg
+        }
+        KadMsg::GetProvidersRes { closer_peers, provider_peers } => {
+            // TODO: if both lists are empty, the remote will think it's a request
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::GET_PROVIDERS);
+            msg.set_clusterLevelRaw(9);
+            for peer in closer_peers {
+                msg.mut_closerPeers().push(peer.into());
+            }
+            for peer in provider_peers {
+                msg.mut_providerPeers().push(peer.into());
+            }
+            msg
+        }
+        KadMsg::AddProvider { key, provider_peer } => {
+            let mut msg = protobuf_structs::dht::Message::new();
+            msg.set_field_type(protobuf_structs::dht::Message_MessageType::ADD_PROVIDER);
+            msg.set_key(key);
+            msg.mut_providerPeers().push(provider_peer.into());
+            msg
+        }
     }
 }
 
@@ -256,16 +351,29 @@ fn proto_to_msg(mut message: protobuf_structs::dht::Message) -> Result<KadMsg, I
 
         protobuf_structs::dht::Message_MessageType::PUT_VALUE => {
             let key = message.take_key();
-            let _record = message.take_record();
+            let record = message.take_record().take_value();
             Ok(KadMsg::PutValue {
                 key: key,
-                record: (),
+                record: record,
             })
         }
 
         protobuf_structs::dht::Message_MessageType::GET_VALUE => {
             let key = message.take_key();
-            Ok(KadMsg::GetValueReq { key: key })
+            if !message.has_record() && message.get_closerPeers().is_empty() {
+                Ok(KadMsg::GetValueReq { key: key })
+            } else {
+                let record = if message.has_record() {
+                    Some(message.take_record().take_value())
+                } else {
+                    None
+                };
+                Ok(KadMsg::GetValueRes {
+                    key,
+                    record,
+                    closer_peers: parse_peers(message.mut_closerPeers()),
+                })
+            }
         }
 
         protobuf_structs::dht::Message_MessageType::FIND_NODE => {
@@ -278,29 +386,49 @@ fn proto_to_msg(mut message: protobuf_structs::dht::Message) -> Result<KadMsg, I
                 // TODO: for now we don't parse the peer properly, so it is possible that we get
                 //       parsing errors for peers even when they are valid ; we ignore these
                 //       errors for now, but ultimately we should just error altogether
-                let closer_peers = message.mut_closerPeers()
-                    .iter_mut()
-                    .filter_map(|peer| KadPeer::from_peer(peer).ok())
-                    .collect::<Vec<_>>();
-
                 Ok(KadMsg::FindNodeRes {
-                    closer_peers,
+                    closer_peers: parse_peers(message.mut_closerPeers()),
+                })
+            }
+        }
+
+        protobuf_structs::dht::Message_MessageType::GET_PROVIDERS => {
+            if message.get_closerPeers().is_empty() && message.get_providerPeers().is_empty() {
+                Ok(KadMsg::GetProvidersReq {
+                    key: message.take_key(),
+                })
+            } else {
+                Ok(KadMsg::GetProvidersRes {
+                    closer_peers: parse_peers(message.mut_closerPeers()),
+                    provider_peers: parse_peers(message.mut_providerPeers()),
                 })
             }
         }
 
-        protobuf_structs::dht::Message_MessageType::GET_PROVIDERS
-        | protobuf_structs::dht::Message_MessageType::ADD_PROVIDER => {
-            // These messages don't seem to be used in the protocol in practice, so if we receive
-            // them we suppose that it's a mistake in the protocol usage.
-            Err(IoError::new(
-                IoErrorKind::InvalidData,
-                "received an unsupported kad message type",
-            ))
+        protobuf_structs::dht::Message_MessageType::ADD_PROVIDER => {
+            let key = message.take_key();
+            let provider_peer = parse_peers(message.mut_providerPeers())
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    IoError::new(IoErrorKind::InvalidData, "ADD_PROVIDER without provider")
+                })?;
+            Ok(KadMsg::AddProvider {
+                key,
+                provider_peer,
+            })
         }
     }
 }
 
+// Parses a list of raw peers, ignoring the ones that are invalid.
+fn parse_peers(peers: &mut [protobuf_structs::dht::Message_Peer]) -> Vec<KadPeer> {
+    peers
+        .iter_mut()
+        .filter_map(|peer| KadPeer::from_peer(peer).ok())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     extern crate libp2p_tcp_transport;
@@ -321,7 +449,7 @@ mod tests {
         test_one(KadMsg::Ping);
         test_one(KadMsg::PutValue {
             key: vec![1, 2, 3, 4],
-            record: (),
+            record: vec![5, 6, 7],
         });
         test_one(KadMsg::GetValueReq {
             key: vec![10, 11, 12],
@@ -329,6 +457,24 @@ mod tests {
         test_one(KadMsg::FindNodeReq {
             key: vec![9, 12, 0, 245, 245, 201, 28, 95],
         });
+        test_one(KadMsg::GetValueRes {
+            key: vec![10, 11, 12],
+            record: Some(vec![5, 6, 7]),
+            closer_peers: Vec::new(),
+        });
+        test_one(KadMsg::GetProvidersReq {
+            key: vec![1, 2, 3],
+        });
+        test_one(KadMsg::GetProvidersRes {
+            closer_peers: Vec::new(),
+            provider_peers: vec![
+                KadPeer {
+                    node_id: PeerId::from_public_key(PublicKey::Rsa(vec![93, 80, 12, 250])),
+                    multiaddrs: vec!["/ip4/100.101.102.103/tcp/20105".parse().unwrap()],
+                    connection_ty: KadConnectionType::Connected,
+                },
+            ],
+        });
         test_one(KadMsg::FindNodeRes {
             closer_peers: vec![
                 KadPeer {
@@ -338,14 +484,21 @@ mod tests {
                 },
             ],
         });
-        // TODO: all messages
+        test_one(KadMsg::AddProvider {
+            key: vec![1, 2, 3],
+            provider_peer: KadPeer {
+                node_id: PeerId::from_public_key(PublicKey::Rsa(vec![93, 80, 12, 250])),
+                multiaddrs: vec!["/ip4/100.101.102.103/tcp/20105".parse().unwrap()],
+                connection_ty: KadConnectionType::Connected,
+            },
+        });
 
         fn test_one(msg_server: KadMsg) {
             let msg_client = msg_server.clone();
             let (tx, rx) = mpsc::channel();
 
             let bg_thread = thread::spawn(move || {
-                let transport = TcpConfig::new().with_upgrade(KademliaProtocolConfig);
+                let transport = TcpConfig::new().with_upgrade(KademliaProtocolConfig::new());
 
                 let (listener, addr) = transport
                     .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
@@ -365,7 +518,7 @@ mod tests {
                 let _ = tokio_current_thread::block_on_all(future).unwrap();
             });
 
-            let transport = TcpConfig::new().with_upgrade(KademliaProtocolConfig);
+            let transport = TcpConfig::new().with_upgrade(KademliaProtocolConfig::new());
 
             let future = transport
                 .dial(rx.recv().unwrap())
@@ -0,0 +1,572 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Storage of the value and provider records, with their expiration and republication.
+//!
+//! Records stored on behalf of remotes expire after a configurable time to live, unless they are
+//! stored again. Records that we published ourselves with `put_local` and `add_local_provider`
+//! must instead be published again on the network periodically, before they expire on the
+//! remotes that store them.
+//!
+//! Both tasks are performed by the `KadRecordWorker` returned by `KadRecordStore::worker`, which
+//! must be polled. It waits with the timer of the `Runtime` given to the store, so that records
+//! expire and get republished according to the virtual time when the node runs in a simulation.
+//! The worker removes the expired records by itself, but only reports the records to republish.
+//! The `KadSystem` owns a store and its worker: the stream of `KadSystem::republish_records`
+//! polls the worker and sends the reported records to the peers closest to their key.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate futures;
+//! extern crate libp2p_core;
+//! extern crate libp2p_kad;
+//! extern crate multihash;
+//!
+//! use futures::prelude::*;
+//! use libp2p_core::{PublicKey, runtime::{ManualTimer, Runtime}};
+//! use libp2p_kad::{KadRecordEvent, KadRecordStore, KadRecordStoreConfig};
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! let timer = ManualTimer::new();
+//! let runtime = Runtime::deterministic(timer.clone());
+//! let local_peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+//! let config = KadRecordStoreConfig::new().with_republish_interval(Duration::from_secs(3600));
+//! let store = KadRecordStore::new(config, runtime, local_peer_id);
+//!
+//! let key = multihash::encode(multihash::Hash::SHA2256, b"hello").unwrap();
+//! store.put_local(key.clone(), b"world".to_vec());
+//! let mut worker = store.worker();
+//!
+//! futures::future::poll_fn(move || {
+//!     assert!(worker.poll().unwrap().is_not_ready());
+//!     timer.advance(Duration::from_secs(3600));
+//!     match worker.poll().unwrap() {
+//!         Async::Ready(Some(KadRecordEvent::RepublishRecord { key: k, .. })) => assert_eq!(k, key),
+//!         _ => panic!("the record must be republished"),
+//!     }
+//!     Ok::<_, ()>(Async::Ready(()))
+//! }).wait().unwrap();
+//! # }
+//! ```
+
+use fnv::FnvHashMap;
+use futures::{prelude::*, task};
+use libp2p_core::PeerId;
+use libp2p_core::runtime::{Delay, Runtime};
+use multihash::Multihash;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// Configuration of a `KadRecordStore`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KadRecordStoreConfig {
+    /// Time after which a value record expires.
+    record_ttl: Duration,
+    /// Time after which a provider record expires.
+    provider_ttl: Duration,
+    /// Interval between two publications of our own value records.
+    republish_interval: Duration,
+    /// Interval between two publications of our own provider records.
+    provider_republish_interval: Duration,
+}
+
+impl KadRecordStoreConfig {
+    /// Builds the default configuration: value records expire after 36 hours and are
+    /// republished every hour, provider records expire after 24 hours and are republished every
+    /// 12 hours.
+    #[inline]
+    pub fn new() -> KadRecordStoreConfig {
+        KadRecordStoreConfig {
+            record_ttl: Duration::from_secs(36 * 60 * 60),
+            provider_ttl: Duration::from_secs(24 * 60 * 60),
+            republish_interval: Duration::from_secs(60 * 60),
+            provider_republish_interval: Duration::from_secs(12 * 60 * 60),
+        }
+    }
+
+    /// Sets the time after which a value record expires.
+    #[inline]
+    pub fn with_record_ttl(mut self, ttl: Duration) -> Self {
+        self.record_ttl = ttl;
+        self
+    }
+
+    /// Sets the time after which a provider record expires.
+    #[inline]
+    pub fn with_provider_ttl(mut self, ttl: Duration) -> Self {
+        self.provider_ttl = ttl;
+        self
+    }
+
+    /// Sets the interval between two publications of our own value records. Should be shorter
+    /// than the time to live of the records, so that they don't expire on the remotes.
+    #[inline]
+    pub fn with_republish_interval(mut self, interval: Duration) -> Self {
+        self.republish_interval = interval;
+        self
+    }
+
+    /// Sets the interval between two publications of our own provider records.
+    #[inline]
+    pub fn with_provider_republish_interval(mut self, interval: Duration) -> Self {
+        self.provider_republish_interval = interval;
+        self
+    }
+
+    /// Returns the time after which a value record expires.
+    #[inline]
+    pub fn record_ttl(&self) -> Duration {
+        self.record_ttl
+    }
+
+    /// Returns the time after which a provider record expires.
+    #[inline]
+    pub fn provider_ttl(&self) -> Duration {
+        self.provider_ttl
+    }
+
+    /// Returns the interval between two publications of our own value records.
+    #[inline]
+    pub fn republish_interval(&self) -> Duration {
+        self.republish_interval
+    }
+
+    /// Returns the interval between two publications of our own provider records.
+    #[inline]
+    pub fn provider_republish_interval(&self) -> Duration {
+        self.provider_republish_interval
+    }
+}
+
+impl Default for KadRecordStoreConfig {
+    #[inline]
+    fn default() -> Self {
+        KadRecordStoreConfig::new()
+    }
+}
+
+/// Event produced by a `KadRecordWorker`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KadRecordEvent {
+    /// One of our value records must be published again on the network. Its local expiration
+    /// has been pushed back already.
+    RepublishRecord {
+        /// Key of the record.
+        key: Multihash,
+        /// Value of the record.
+        value: Vec<u8>,
+    },
+    /// We must announce again on the network that we provide this key.
+    RepublishProvider {
+        /// The key we provide.
+        key: Multihash,
+    },
+    /// A value record has expired and has been removed.
+    RecordExpired {
+        /// Key of the record.
+        key: Multihash,
+    },
+    /// A provider record has expired and has been removed.
+    ProviderExpired {
+        /// The key that was provided.
+        key: Multihash,
+        /// The provider.
+        provider: PeerId,
+    },
+}
+
+/// Value and provider records of a node.
+///
+/// Cloning a `KadRecordStore` produces a handle to the same records.
+#[derive(Clone)]
+pub struct KadRecordStore {
+    inner: Arc<Mutex<Inner>>,
+    config: KadRecordStoreConfig,
+    runtime: Runtime,
+    local_peer_id: PeerId,
+}
+
+struct Inner {
+    /// Value records, by key.
+    records: FnvHashMap<Multihash, Stored<Vec<u8>>>,
+    /// Provider records, by key.
+    providers: FnvHashMap<Multihash, Vec<Stored<PeerId>>>,
+    /// Task of the worker, to wake up when a record with an earlier deadline is inserted.
+    worker_task: Option<task::Task>,
+}
+
+/// A record and its deadlines.
+struct Stored<T> {
+    value: T,
+    /// When the record expires.
+    expires: Instant,
+    /// When to publish the record again. `Some` only for our own records.
+    republish: Option<Instant>,
+}
+
+impl<T> Stored<T> {
+    /// Returns the earliest deadline of the record.
+    fn deadline(&self) -> Instant {
+        match self.republish {
+            Some(republish) if republish < self.expires => republish,
+            _ => self.expires,
+        }
+    }
+}
+
+impl KadRecordStore {
+    /// Creates an empty store. `runtime` provides the clock used for the deadlines of the records,
+    /// and `local_peer_id` is the identity used for our own provider records.
+    pub fn new(config: KadRecordStoreConfig, runtime: Runtime, local_peer_id: PeerId) -> KadRecordStore {
+        KadRecordStore {
+            inner: Arc::new(Mutex::new(Inner {
+                records: Default::default(),
+                providers: Default::default(),
+                worker_task: None,
+            })),
+            config,
+            runtime,
+            local_peer_id,
+        }
+    }
+
+    /// Returns the configuration of the store.
+    #[inline]
+    pub fn config(&self) -> &KadRecordStoreConfig {
+        &self.config
+    }
+
+    /// Stores a value record that we publish, and that must be republished periodically.
+    pub fn put_local(&self, key: Multihash, value: Vec<u8>) {
+        let now = self.runtime.now();
+        self.insert_record(key, Stored {
+            value,
+            expires: now + self.config.record_ttl,
+            republish: Some(now + self.config.republish_interval),
+        });
+    }
+
+    /// Stores a value record received from a remote. It expires after the time to live of the
+    /// configuration, unless it is stored again.
+    ///
+    /// Does nothing if we have published a record with the same key ourselves.
+    pub fn store(&self, key: Multihash, value: Vec<u8>) {
+        if self.inner.lock().records.get(&key).map(|r| r.republish.is_some()).unwrap_or(false) {
+            return;
+        }
+
+        let now = self.runtime.now();
+        self.insert_record(key, Stored {
+            value,
+            expires: now + self.config.record_ttl,
+            republish: None,
+        });
+    }
+
+    /// Returns the value of a record, if we have it and it hasn't expired.
+    pub fn get(&self, key: &Multihash) -> Option<Vec<u8>> {
+        let now = self.runtime.now();
+        self.inner.lock().records.get(key)
+            .filter(|record| record.expires > now)
+            .map(|record| record.value.clone())
+    }
+
+    /// Removes a value record. Returns true if it was present.
+    pub fn remove(&self, key: &Multihash) -> bool {
+        self.inner.lock().records.remove(key).is_some()
+    }
+
+    /// Records that we provide `key`. The provider record must be republished periodically.
+    pub fn add_local_provider(&self, key: Multihash) {
+        let now = self.runtime.now();
+        let provider = Stored {
+            value: self.local_peer_id.clone(),
+            expires: now + self.config.provider_ttl,
+            republish: Some(now + self.config.provider_republish_interval),
+        };
+        self.insert_provider(key, provider);
+    }
+
+    /// Stores a provider record received from a remote. It expires after the time to live of the
+    /// configuration, unless it is stored again.
+    pub fn add_provider(&self, key: Multihash, provider: PeerId) {
+        if provider == self.local_peer_id {
+            return self.add_local_provider(key);
+        }
+
+        let now = self.runtime.now();
+        let provider = Stored {
+            value: provider,
+            expires: now + self.config.provider_ttl,
+            republish: None,
+        };
+        self.insert_provider(key, provider);
+    }
+
+    /// Stops providing `key`. Returns true if we were providing it.
+    pub fn remove_local_provider(&self, key: &Multihash) -> bool {
+        let mut inner = self.inner.lock();
+        let (removed, now_empty) = match inner.providers.get_mut(key) {
+            Some(providers) => {
+                let before = providers.len();
+                providers.retain(|p| p.value != self.local_peer_id);
+                (providers.len() != before, providers.is_empty())
+            },
+            None => (false, false),
+        };
+        if now_empty {
+            inner.providers.remove(key);
+        }
+        removed
+    }
+
+    /// Returns the providers of `key` whose record hasn't expired.
+    pub fn providers(&self, key: &Multihash) -> Vec<PeerId> {
+        let now = self.runtime.now();
+        self.inner.lock().providers.get(key)
+            .map(|providers| {
+                providers.iter()
+                    .filter(|provider| provider.expires > now)
+                    .map(|provider| provider.value.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of value records, including the ones that have expired but haven't
+    /// been removed by the worker yet.
+    #[inline]
+    pub fn num_records(&self) -> usize {
+        self.inner.lock().records.len()
+    }
+
+    /// Builds the worker that removes the expired records and reports the records to republish.
+    ///
+    /// Only one worker should exist for a store.
+    #[inline]
+    pub fn worker(&self) -> KadRecordWorker {
+        KadRecordWorker {
+            store: self.clone(),
+            events: VecDeque::new(),
+            delay: None,
+        }
+    }
+
+    /// Inserts or replaces a value record, and wakes up the worker.
+    fn insert_record(&self, key: Multihash, record: Stored<Vec<u8>>) {
+        let mut inner = self.inner.lock();
+        inner.records.insert(key, record);
+        if let Some(task) = inner.worker_task.take() {
+            task.notify();
+        }
+    }
+
+    /// Inserts or refreshes a provider record, and wakes up the worker.
+    fn insert_provider(&self, key: Multihash, provider: Stored<PeerId>) {
+        let mut inner = self.inner.lock();
+        {
+            let providers = inner.providers.entry(key).or_insert_with(Vec::new);
+            providers.retain(|p| p.value != provider.value);
+            providers.push(provider);
+        }
+        if let Some(task) = inner.worker_task.take() {
+            task.notify();
+        }
+    }
+}
+
+/// Removes the expired records of a `KadRecordStore` and reports the records to republish.
+///
+/// Implements `Stream` and never ends. The events that happen at the same time are produced in
+/// the order of their keys.
+pub struct KadRecordWorker {
+    store: KadRecordStore,
+    /// Events that have happened but haven't been produced yet.
+    events: VecDeque<KadRecordEvent>,
+    /// Delay until the next deadline, and that deadline.
+    delay: Option<(Instant, Delay)>,
+}
+
+impl KadRecordWorker {
+    /// Processes the records whose deadline is `now` or earlier, and returns the next deadline.
+    fn process(&mut self, now: Instant) -> Option<Instant> {
+        let config = self.store.config;
+        let mut inner = self.store.inner.lock();
+        let mut events = Vec::new();
+
+        inner.records.retain(|key, record| {
+            if let Some(republish) = record.republish {
+                if republish <= now {
+                    record.expires = now + config.record_ttl;
+                    record.republish = Some(now + config.republish_interval);
+                    events.push(KadRecordEvent::RepublishRecord { key: key.clone(), value: record.value.clone() });
+                    return true;
+                }
+            }
+            if record.expires <= now {
+                events.push(KadRecordEvent::RecordExpired { key: key.clone() });
+                return false;
+            }
+            true
+        });
+
+        inner.providers.retain(|key, providers| {
+            providers.retain(|provider| {
+                if let Some(republish) = provider.republish {
+                    if republish <= now {
+                        events.push(KadRecordEvent::RepublishProvider { key: key.clone() });
+                        return true;
+                    }
+                }
+                if provider.expires <= now {
+                    events.push(KadRecordEvent::ProviderExpired { key: key.clone(), provider: provider.value.clone() });
+                    return false;
+                }
+                true
+            });
+            for provider in providers.iter_mut() {
+                if provider.republish.map(|r| r <= now).unwrap_or(false) {
+                    provider.expires = now + config.provider_ttl;
+                    provider.republish = Some(now + config.provider_republish_interval);
+                }
+            }
+            !providers.is_empty()
+        });
+
+        // The iteration order of the maps depends on their history. Sorting makes the order of
+        // the events reproducible.
+        events.sort_by(|a, b| event_key(a).cmp(event_key(b)));
+        self.events.extend(events);
+
+        let records = inner.records.values().map(Stored::deadline);
+        let providers = inner.providers.values().flat_map(|p| p.iter()).map(Stored::deadline);
+        records.chain(providers).min()
+    }
+}
+
+/// Returns the bytes of the key of an event.
+fn event_key(event: &KadRecordEvent) -> &[u8] {
+    match *event {
+        KadRecordEvent::RepublishRecord { ref key, .. } => key.as_bytes(),
+        KadRecordEvent::RepublishProvider { ref key } => key.as_bytes(),
+        KadRecordEvent::RecordExpired { ref key } => key.as_bytes(),
+        KadRecordEvent::ProviderExpired { ref key, .. } => key.as_bytes(),
+    }
+}
+
+impl Stream for KadRecordWorker {
+    type Item = KadRecordEvent;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<KadRecordEvent>, IoError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            // Registering the task before processing guarantees that a record inserted in the
+            // meantime wakes us up.
+            self.store.inner.lock().worker_task = Some(task::current());
+            let now = self.store.runtime.now();
+            let deadline = match self.process(now) {
+                Some(deadline) => deadline,
+                None => {
+                    self.delay = None;
+                    return Ok(Async::NotReady);
+                },
+            };
+
+            if !self.events.is_empty() {
+                continue;
+            }
+
+            let same_deadline = self.delay.as_ref().map(|d| d.0 == deadline).unwrap_or(false);
+            if !same_deadline {
+                self.delay = Some((deadline, self.store.runtime.delay(deadline)));
+            }
+
+            match self.delay.as_mut().expect("delay was set above").1.poll()? {
+                Async::Ready(()) => self.delay = None,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, prelude::*};
+    use libp2p_core::PublicKey;
+    use libp2p_core::runtime::{ManualTimer, Runtime};
+    use multihash::{self, Hash};
+    use record_store::{KadRecordEvent, KadRecordStore, KadRecordStoreConfig};
+    use std::time::Duration;
+
+    #[test]
+    fn expiry_and_republish_follow_the_timer() {
+        let timer = ManualTimer::new();
+        let runtime = Runtime::deterministic(timer.clone());
+        let local = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let remote = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+        let config = KadRecordStoreConfig::new()
+            .with_record_ttl(Duration::from_secs(100))
+            .with_republish_interval(Duration::from_secs(60))
+            .with_provider_ttl(Duration::from_secs(50));
+        let store = KadRecordStore::new(config, runtime, local);
+
+        let ours = multihash::encode(Hash::SHA2256, b"ours").unwrap();
+        let theirs = multihash::encode(Hash::SHA2256, b"theirs").unwrap();
+        store.put_local(ours.clone(), b"1".to_vec());
+        store.store(theirs.clone(), b"2".to_vec());
+        store.add_provider(theirs.clone(), remote.clone());
+
+        let mut worker = store.worker();
+        future::poll_fn(move || -> Poll<(), ()> {
+            assert!(worker.poll().unwrap().is_not_ready());
+
+            timer.advance(Duration::from_secs(50));
+            assert_eq!(worker.poll().unwrap(),
+                       Async::Ready(Some(KadRecordEvent::ProviderExpired { key: theirs.clone(), provider: remote.clone() })));
+            assert!(worker.poll().unwrap().is_not_ready());
+            assert!(store.providers(&theirs).is_empty());
+
+            timer.advance(Duration::from_secs(10));
+            assert_eq!(worker.poll().unwrap(),
+                       Async::Ready(Some(KadRecordEvent::RepublishRecord { key: ours.clone(), value: b"1".to_vec() })));
+            assert!(worker.poll().unwrap().is_not_ready());
+
+            timer.advance(Duration::from_secs(40));
+            assert_eq!(worker.poll().unwrap(),
+                       Async::Ready(Some(KadRecordEvent::RecordExpired { key: theirs.clone() })));
+            assert!(worker.poll().unwrap().is_not_ready());
+            assert_eq!(store.get(&theirs), None);
+            // Republishing pushed back the expiration of our own record.
+            assert_eq!(store.get(&ours), Some(b"1".to_vec()));
+            Ok(Async::Ready(()))
+        }).wait().unwrap();
+    }
+}
@@ -31,9 +31,16 @@
 //! is a future that will process the data received on the socket and will be signalled only when
 //! the connection closes.
 //!
+//! # Periodic pinging
+//!
+//! The `PeriodicPinger` struct wraps around a `PingDialer` and pings the remote at a configurable
+//! interval. It reports the round-trip time of each ping, times out pings that aren't answered,
+//! and can produce an error after a number of consecutive failures so that the connection gets
+//! closed. See `PingConfig` for the available options.
+//!
 //! # About timeouts
 //!
-//! For technical reasons, this crate doesn't handle timeouts. The action of pinging returns a
+//! For technical reasons, the `PingDialer` itself doesn't handle timeouts. The action of pinging returns a
 //! future that is signalled only when the remote answers. If the remote is not responsive, the
 //! future will never be signalled.
 //!
@@ -90,6 +97,11 @@ extern crate parking_lot;
 extern crate rand;
 extern crate tokio_codec;
 extern crate tokio_io;
+extern crate tokio_timer;
+
+pub use self::periodic::{PeriodicPinger, PingConfig, PingEvent};
+
+mod periodic;
 
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::{prelude::*, future::{FutureResult, IntoFuture}, task};
@@ -0,0 +1,358 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Periodic liveness checking on top of a `PingDialer`.
+//!
+//! The `PeriodicPinger` sends a ping to the remote every `interval`, waits up to `timeout` for the
+//! matching pong, and produces a `PingEvent` for each outcome. After `max_failures` consecutive
+//! timeouts, the remote is considered unresponsive.
+
+use futures::prelude::*;
+use libp2p_core::rtt::SharedRtt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::{Duration, Instant};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+use PingDialer;
+
+/// Configuration of a `PeriodicPinger`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PingConfig {
+    /// Delay between the end of a ping (either a pong or a timeout) and the next ping.
+    interval: Duration,
+    /// Duration after which a ping without a pong is considered as failed.
+    timeout: Duration,
+    /// Number of consecutive failures after which the remote is considered unresponsive.
+    max_failures: u32,
+    /// If true, the pinger produces an error when the remote becomes unresponsive, which is
+    /// supposed to close the connection.
+    close_on_failure: bool,
+}
+
+impl PingConfig {
+    /// Builds the default configuration. Pings every 15 seconds with a 20 seconds timeout, and
+    /// closes the connection after a single failure.
+    #[inline]
+    pub fn new() -> PingConfig {
+        PingConfig {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(20),
+            max_failures: 1,
+            close_on_failure: true,
+        }
+    }
+
+    /// Sets the delay between two pings.
+    #[inline]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the duration after which a ping is considered as failed.
+    #[inline]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the number of consecutive failures after which the remote is unresponsive.
+    ///
+    /// A value of `0` is treated as `1`.
+    #[inline]
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = if max_failures == 0 { 1 } else { max_failures };
+        self
+    }
+
+    /// If `true`, the `PeriodicPinger` produces an error once the remote is unresponsive.
+    /// Otherwise it keeps pinging and only reports `PingEvent::Unresponsive`.
+    #[inline]
+    pub fn with_close_on_failure(mut self, close: bool) -> Self {
+        self.close_on_failure = close;
+        self
+    }
+}
+
+impl Default for PingConfig {
+    #[inline]
+    fn default() -> Self {
+        PingConfig::new()
+    }
+}
+
+/// Event produced by a `PeriodicPinger`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PingEvent {
+    /// The remote answered a ping.
+    Success {
+        /// Round-trip time between sending the ping and receiving the pong.
+        rtt: Duration,
+    },
+    /// The remote didn't answer a ping in time.
+    Timeout {
+        /// Number of consecutive failed pings, including this one.
+        consecutive_failures: u32,
+    },
+    /// The number of consecutive failures reached the configured maximum. Produced instead of
+    /// `Timeout` for that failure, and only if the configuration doesn't close the connection on
+    /// failure.
+    Unresponsive,
+}
+
+/// Pings the remote at regular intervals.
+///
+/// Implements `Stream`. The stream produces an error if the connection fails, or if the remote is
+/// unresponsive and `close_on_failure` is enabled. The stream ends when the remote closes the
+/// connection.
+pub struct PeriodicPinger<TSocket> {
+    /// The underlying dialer. The user data is a ping identifier.
+    dialer: PingDialer<TSocket, u64>,
+    /// Configuration.
+    config: PingConfig,
+    /// Fires when it is time to send the next ping.
+    next_ping: Delay,
+    /// The ping currently waiting for an answer, with the moment it was sent and its deadline.
+    in_flight: Option<(u64, Instant, Delay)>,
+    /// Identifier to use for the next ping.
+    next_id: u64,
+    /// Number of consecutive pings that timed out.
+    consecutive_failures: u32,
+    /// If `Some`, the round-trip time of every successful ping is reported to it.
+    rtt: Option<SharedRtt>,
+}
+
+impl<TSocket> PeriodicPinger<TSocket> {
+    /// Wraps around a `PingDialer`. The first ping is sent immediately.
+    pub fn new(dialer: PingDialer<TSocket, u64>, config: PingConfig) -> Self {
+        PeriodicPinger {
+            dialer,
+            config,
+            next_ping: Delay::new(Instant::now()),
+            in_flight: None,
+            next_id: 0,
+            consecutive_failures: 0,
+            rtt: None,
+        }
+    }
+
+    /// Reports the round-trip time of every successful ping to `rtt`, for example the estimator
+    /// returned by `Connection::rtt`.
+    #[inline]
+    pub fn with_rtt_estimator(mut self, rtt: SharedRtt) -> Self {
+        self.rtt = Some(rtt);
+        self
+    }
+
+    /// Returns the configuration of this pinger.
+    #[inline]
+    pub fn config(&self) -> &PingConfig {
+        &self.config
+    }
+
+    /// Returns the number of consecutive pings that timed out.
+    #[inline]
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+}
+
+impl<TSocket> Stream for PeriodicPinger<TSocket>
+where TSocket: AsyncRead + AsyncWrite,
+{
+    type Item = PingEvent;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            // Process the pongs. Pongs of pings that already timed out are ignored.
+            loop {
+                match self.dialer.poll()? {
+                    Async::Ready(Some(id)) => {
+                        let matches = self.in_flight.as_ref().map(|f| f.0 == id).unwrap_or(false);
+                        if !matches {
+                            trace!("Ignoring late pong for ping #{}", id);
+                            continue;
+                        }
+
+                        let (_, sent_at, _) = self.in_flight.take()
+                            .expect("in_flight is Some because matches is true ; qed");
+                        let now = Instant::now();
+                        self.consecutive_failures = 0;
+                        self.next_ping.reset(now + self.config.interval);
+                        let rtt = now - sent_at;
+                        if let Some(ref estimator) = self.rtt {
+                            estimator.add_sample(rtt);
+                        }
+                        return Ok(Async::Ready(Some(PingEvent::Success { rtt })));
+                    },
+                    Async::Ready(None) => return Ok(Async::Ready(None)),
+                    Async::NotReady => break,
+                }
+            }
+
+            if let Some((id, _, ref mut deadline)) = self.in_flight {
+                match deadline.poll() {
+                    Ok(Async::Ready(())) => (),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => return Err(IoError::new(IoErrorKind::Other, err)),
+                }
+
+                debug!("Ping #{} timed out", id);
+            } else {
+                match self.next_ping.poll() {
+                    Ok(Async::Ready(())) => (),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => return Err(IoError::new(IoErrorKind::Other, err)),
+                }
+
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+                let now = Instant::now();
+                self.dialer.ping(id);
+                self.in_flight = Some((id, now, Delay::new(now + self.config.timeout)));
+                // Loop again so that the dialer actually sends the ping.
+                continue;
+            }
+
+            // If we reach here, the ping in flight has timed out.
+            self.in_flight = None;
+            self.consecutive_failures += 1;
+            self.next_ping.reset(Instant::now() + self.config.interval);
+
+            if self.consecutive_failures >= self.config.max_failures {
+                if self.config.close_on_failure {
+                    return Err(IoError::new(IoErrorKind::TimedOut, "remote is unresponsive to pings"));
+                }
+
+                if self.consecutive_failures == self.config.max_failures {
+                    return Ok(Async::Ready(Some(PingEvent::Unresponsive)));
+                }
+            }
+
+            let consecutive_failures = self.consecutive_failures;
+            return Ok(Async::Ready(Some(PingEvent::Timeout { consecutive_failures })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tokio;
+    extern crate tokio_tcp;
+
+    use self::tokio::runtime::current_thread::Runtime;
+    use self::tokio_tcp::{TcpListener, TcpStream};
+    use futures::{future, Future, Stream};
+    use libp2p_core::{ConnectionUpgrade, Endpoint};
+    use std::io::ErrorKind as IoErrorKind;
+    use std::time::Duration;
+    use {PeriodicPinger, Ping, PingConfig, PingEvent, PingOutput};
+
+    #[test]
+    fn periodic_success() {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(c, _)| {
+                Ping::<u64>::default().upgrade(
+                    c.unwrap(),
+                    (),
+                    Endpoint::Listener,
+                    &"/ip4/127.0.0.1/tcp/10000".parse().unwrap(),
+                )
+            })
+            .and_then(|out| match out {
+                PingOutput::Ponger(service) => service,
+                _ => unreachable!(),
+            });
+
+        let client = TcpStream::connect(&listener_addr)
+            .and_then(|c| {
+                Ping::<u64>::default().upgrade(
+                    c,
+                    (),
+                    Endpoint::Dialer,
+                    &"/ip4/127.0.0.1/tcp/10000".parse().unwrap(),
+                )
+            })
+            .and_then(|out| match out {
+                PingOutput::Pinger(pinger) => {
+                    let config = PingConfig::new().with_interval(Duration::from_millis(10));
+                    PeriodicPinger::new(pinger, config).take(3).collect()
+                },
+                _ => unreachable!(),
+            })
+            .map(|events| {
+                assert_eq!(events.len(), 3);
+                for event in events {
+                    match event {
+                        PingEvent::Success { .. } => (),
+                        ev => panic!("unexpected event: {:?}", ev),
+                    }
+                }
+            });
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(server.select(client).map_err(|_| panic!())).unwrap();
+    }
+
+    #[test]
+    fn unresponsive_remote() {
+        // The listener accepts the connection but never answers.
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(c, _)| future::empty::<(), _>().map(move |_| drop(c)));
+
+        let client = TcpStream::connect(&listener_addr)
+            .and_then(|c| {
+                Ping::<u64>::default().upgrade(
+                    c,
+                    (),
+                    Endpoint::Dialer,
+                    &"/ip4/127.0.0.1/tcp/10000".parse().unwrap(),
+                )
+            })
+            .and_then(|out| match out {
+                PingOutput::Pinger(pinger) => {
+                    let config = PingConfig::new()
+                        .with_timeout(Duration::from_millis(20))
+                        .with_interval(Duration::from_millis(5))
+                        .with_max_failures(2);
+                    PeriodicPinger::new(pinger, config).collect()
+                },
+                _ => unreachable!(),
+            });
+
+        let mut runtime = Runtime::new().unwrap();
+        match runtime.block_on(server.select(client.map(|_| ()))) {
+            Err((err, _)) => assert_eq!(err.kind(), IoErrorKind::TimedOut),
+            Ok(_) => panic!(),
+        }
+    }
+}
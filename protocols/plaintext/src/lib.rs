@@ -0,0 +1,345 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/plaintext/2.0.0` protocol.
+//!
+//! Similarly to `secio`, each side sends its identity to the other side during the upgrade.
+//! Contrary to `secio`, the communications are **not** encrypted afterwards, and the remote
+//! doesn't have to prove that it owns the private key corresponding to the public key it sends.
+//!
+//! This makes it possible to measure the overhead of the multiplexing and of the protocols on
+//! top of a connection without the cost of the cryptography.
+//!
+//! > **Note**: Never use this protocol outside of tests and benchmarks. Anyone on the path of
+//! >           the connection can read and modify the data, and any node can pretend to be any
+//! >           other node.
+//!
+//! # Usage
+//!
+//! The `PlainText2Config` struct implements the `ConnectionUpgrade` trait, and is used the same
+//! way as `SecioConfig`. The output of the upgrade is a `PlainTextOutput` that contains the
+//! socket and the identity of the remote.
+//!
+//! ```no_run
+//! extern crate libp2p_core;
+//! extern crate libp2p_plaintext;
+//! extern crate libp2p_tcp_transport;
+//!
+//! # fn main() {
+//! use libp2p_core::{PublicKey, Transport, upgrade};
+//! use libp2p_plaintext::{PlainText2Config, PlainTextOutput};
+//! use libp2p_tcp_transport::TcpConfig;
+//!
+//! # let local_public_key = PublicKey::Ed25519(vec![1; 32]);
+//! let transport = TcpConfig::new()
+//!     .with_upgrade({
+//!         let upgrade = PlainText2Config::new(local_public_key);
+//!         upgrade::map(upgrade, |out: PlainTextOutput<_>| out.stream)
+//!     });
+//! # }
+//! ```
+//!
+//! Since `PlainTextOutput` implements `AuthenticatedOutput`, `PlainText2Config` can also be passed
+//! to `Transport::upgrade().authenticate()` in place of `SecioConfig`.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate protobuf;
+extern crate tokio_io;
+
+#[cfg(test)]
+extern crate libp2p_mplex;
+#[cfg(test)]
+extern crate libp2p_tcp_transport;
+#[cfg(test)]
+extern crate tokio_current_thread;
+#[cfg(test)]
+extern crate tokio_tcp;
+
+use bytes::Bytes;
+use futures::Future;
+use libp2p_core::{upgrade, Endpoint, Multiaddr, PeerId, PublicKey};
+use libp2p_core::transport::AuthenticatedOutput;
+use protobuf::{parse_from_bytes as protobuf_parse_from_bytes, Message as ProtobufMessage};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use structs_proto::Exchange;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+mod structs_proto;
+
+/// Maximum size of the exchange message sent by the remote. Large enough for a RSA public key.
+const MAX_EXCHANGE_LEN: usize = 4096;
+
+/// Implementation of the `ConnectionUpgrade` that negotiates `/plaintext/2.0.0`.
+///
+/// Exchanges the public keys of both sides, then passes communications through without doing
+/// anything more.
+#[derive(Debug, Clone)]
+pub struct PlainText2Config {
+    /// Public key sent to the remote.
+    local_public_key: PublicKey,
+}
+
+impl PlainText2Config {
+    /// Builds a new configuration that will send `local_public_key` to the remote.
+    #[inline]
+    pub fn new(local_public_key: PublicKey) -> PlainText2Config {
+        PlainText2Config { local_public_key }
+    }
+}
+
+/// Output of the plaintext protocol.
+pub struct PlainTextOutput<S> {
+    /// The socket, left untouched.
+    pub stream: S,
+    /// The public key sent by the remote.
+    pub remote_key: PublicKey,
+    /// The `PeerId` sent by the remote. Always matches `remote_key`.
+    pub remote_peer_id: PeerId,
+}
+
+impl<S> AuthenticatedOutput for PlainTextOutput<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Stream = S;
+
+    #[inline]
+    fn into_authenticated(self) -> (PeerId, S) {
+        (self.remote_peer_id, self.stream)
+    }
+}
+
+impl<C> libp2p_core::ConnectionUpgrade<C> for PlainText2Config
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type Output = PlainTextOutput<C>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+    type UpgradeIdentifier = ();
+    type NamesIter = iter::Once<(Bytes, ())>;
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from("/plaintext/2.0.0"), ()))
+    }
+
+    fn upgrade(self, socket: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        debug!("Starting plaintext upgrade");
+
+        let local_exchange = encode_exchange(self.local_public_key);
+        let future = upgrade::write_one(socket, local_exchange)
+            .and_then(|socket| upgrade::read_one(socket, MAX_EXCHANGE_LEN).from_err())
+            .and_then(|(socket, remote_exchange)| {
+                let (remote_key, remote_peer_id) = decode_exchange(&remote_exchange)?;
+                trace!("Plaintext upgrade with {:?} succeeded", remote_peer_id);
+                Ok(PlainTextOutput {
+                    stream: socket,
+                    remote_key,
+                    remote_peer_id,
+                })
+            });
+
+        Box::new(future)
+    }
+}
+
+/// Builds the message to send to the remote.
+fn encode_exchange(public_key: PublicKey) -> Vec<u8> {
+    let mut exchange = Exchange::new();
+    exchange.set_id(public_key.clone().into_peer_id().into_bytes());
+    exchange.set_pubkey(public_key.into_protobuf_encoding());
+    exchange
+        .write_to_bytes()
+        .expect("writing a protobuf message to a Vec never fails")
+}
+
+/// Decodes the message sent by the remote, and checks that its `PeerId` matches its public key.
+fn decode_exchange(bytes: &[u8]) -> Result<(PublicKey, PeerId), IoError> {
+    let mut exchange: Exchange = protobuf_parse_from_bytes(bytes)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    let public_key = PublicKey::from_protobuf_encoding(exchange.get_pubkey())?;
+    let peer_id = PeerId::from_bytes(exchange.take_id())
+        .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid remote peer id"))?;
+
+    if peer_id.is_public_key(&public_key) != Some(true) {
+        debug!("Remote peer id {:?} doesn't match its public key", peer_id);
+        return Err(IoError::new(IoErrorKind::InvalidData, "peer id doesn't match the public key"));
+    }
+
+    Ok((public_key, peer_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_exchange, encode_exchange, PlainText2Config};
+    use futures::{Future, Stream};
+    use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr, PublicKey, Transport};
+    use libp2p_mplex::MplexConfig;
+    use libp2p_tcp_transport::TcpConfig;
+    use libp2p_core::transport::Connection;
+    use std::io::ErrorKind as IoErrorKind;
+    use std::sync::Arc;
+    use structs_proto::Exchange;
+    use protobuf::Message;
+    use tokio_current_thread;
+    use tokio_tcp::{TcpListener, TcpStream};
+
+    #[test]
+    fn exchange_round_trip() {
+        let key = PublicKey::Ed25519(vec![1; 32]);
+        let (decoded_key, peer_id) = decode_exchange(&encode_exchange(key.clone())).unwrap();
+        assert_eq!(decoded_key, key);
+        assert_eq!(peer_id, key.into_peer_id());
+    }
+
+    #[test]
+    fn mismatching_peer_id_rejected() {
+        let mut exchange = Exchange::new();
+        exchange.set_id(PublicKey::Ed25519(vec![2; 32]).into_peer_id().into_bytes());
+        exchange.set_pubkey(PublicKey::Ed25519(vec![1; 32]).into_protobuf_encoding());
+        let bytes = exchange.write_to_bytes().unwrap();
+        let err = decode_exchange(&bytes).unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn upgrade_exchanges_keys() {
+        let key1 = PublicKey::Ed25519(vec![1; 32]);
+        let key2 = PublicKey::Ed25519(vec![2; 32]);
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+
+        let server = {
+            let config = PlainText2Config::new(key1.clone());
+            let addr = addr.clone();
+            listener
+                .incoming()
+                .into_future()
+                .map_err(|(e, _)| e)
+                .and_then(move |(connec, _)| {
+                    config.upgrade(connec.unwrap(), (), Endpoint::Listener, &addr)
+                })
+        };
+
+        let client = {
+            let config = PlainText2Config::new(key2.clone());
+            TcpStream::connect(&listener_addr)
+                .and_then(move |stream| config.upgrade(stream, (), Endpoint::Dialer, &addr))
+        };
+
+        let (server_out, client_out) =
+            tokio_current_thread::block_on_all(server.join(client)).unwrap();
+        assert_eq!(server_out.remote_key, key2);
+        assert_eq!(client_out.remote_key, key1);
+        assert_eq!(client_out.remote_peer_id, key1.into_peer_id());
+    }
+
+    #[test]
+    fn builder_produces_connection_info() {
+        let key1 = PublicKey::Ed25519(vec![1; 32]);
+        let key2 = PublicKey::Ed25519(vec![2; 32]);
+
+        let listener = TcpConfig::new()
+            .upgrade()
+            .authenticate(PlainText2Config::new(key1.clone()))
+            .multiplex(MplexConfig::new());
+        let (listener, listen_addr) = listener
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap_or_else(|_| panic!());
+        let server = listener
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(connec, _)| connec.unwrap().0);
+
+        let client = TcpConfig::new()
+            .upgrade()
+            .authenticate(PlainText2Config::new(key2.clone()))
+            .multiplex(MplexConfig::new())
+            .dial(listen_addr.clone())
+            .unwrap_or_else(|_| panic!());
+
+        let ((server_peer, server_connec), (client_peer, client_connec)) =
+            tokio_current_thread::block_on_all(server.join(client)).unwrap();
+        assert_eq!(server_peer, key2.into_peer_id());
+        assert_eq!(client_peer, key1.into_peer_id());
+
+        let server_info = server_connec.info();
+        assert_eq!(server_info.peer_id, server_peer);
+        assert_eq!(server_info.endpoint, Endpoint::Listener);
+        assert_eq!(server_info.local_addr, Some(listen_addr));
+        assert_eq!(&server_info.security_protocol[..], &b"/plaintext/2.0.0"[..]);
+        assert_eq!(&server_info.muxer_protocol[..], &b"/mplex/6.7.0"[..]);
+        assert!(server_info.timings.handshake_start <= server_info.timings.security_established);
+        assert!(server_info.timings.security_established <= server_info.timings.established);
+        assert_eq!(server_connec.rtt().smoothed(), None);
+
+        let client_info = client_connec.info();
+        assert_eq!(client_info.endpoint, Endpoint::Dialer);
+        assert_eq!(client_info.local_addr, None);
+    }
+
+    #[test]
+    fn re_upgrade_keeps_peer_id() {
+        let key1 = PublicKey::Ed25519(vec![1; 32]);
+        let key2 = PublicKey::Ed25519(vec![2; 32]);
+
+        let listener = TcpConfig::new()
+            .upgrade()
+            .authenticate(PlainText2Config::new(key1.clone()))
+            .multiplex(MplexConfig::new());
+        let (listener, listen_addr) = listener
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap_or_else(|_| panic!());
+        let server = listener
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(connec, _)| connec.unwrap().0)
+            .and_then(move |(_, connec)| {
+                let upgrade = PlainText2Config::new(key1);
+                Connection::re_upgrade(Arc::new(connec), Endpoint::Listener, upgrade, MplexConfig::new())
+            });
+
+        let client = TcpConfig::new()
+            .upgrade()
+            .authenticate(PlainText2Config::new(key2.clone()))
+            .multiplex(MplexConfig::new())
+            .dial(listen_addr.clone())
+            .unwrap_or_else(|_| panic!())
+            .and_then(move |(_, connec)| {
+                let upgrade = PlainText2Config::new(key2);
+                Connection::re_upgrade(Arc::new(connec), Endpoint::Dialer, upgrade, MplexConfig::new())
+            });
+
+        let ((server_peer, server_connec), (client_peer, client_connec)) =
+            tokio_current_thread::block_on_all(server.join(client)).unwrap();
+        assert_eq!(server_peer, PublicKey::Ed25519(vec![2; 32]).into_peer_id());
+        assert_eq!(client_peer, PublicKey::Ed25519(vec![1; 32]).into_peer_id());
+        assert_eq!(server_connec.info().local_addr, Some(listen_addr));
+        assert_eq!(client_connec.info().endpoint, Endpoint::Dialer);
+    }
+}
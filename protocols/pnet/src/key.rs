@@ -0,0 +1,168 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// First line of a key file.
+const KEY_TYPE: &str = "/key/swarm/psk/1.0.0/";
+/// Second line of a key file, for a key encoded in hexadecimal.
+const KEY_ENCODING_BASE16: &str = "/base16/";
+
+/// A 256 bits key shared by all the members of a private network.
+///
+/// Can be parsed from and formatted to the `/key/swarm/psk/1.0.0/` key file format used by the
+/// other libp2p implementations:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// 0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PreSharedKey([u8; 32]);
+
+impl PreSharedKey {
+    /// Builds a key from its raw bytes.
+    #[inline]
+    pub fn new(key: [u8; 32]) -> PreSharedKey {
+        PreSharedKey(key)
+    }
+
+    /// Returns the raw bytes of the key.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl FromStr for PreSharedKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(|l| l.trim());
+        let (key_type, encoding, key) = match (lines.next(), lines.next(), lines.next()) {
+            (Some(t), Some(e), Some(k)) => (t, e, k),
+            _ => return Err(KeyParseError::InvalidKeyFile),
+        };
+
+        if key_type != KEY_TYPE {
+            return Err(KeyParseError::InvalidKeyType);
+        }
+        if encoding != KEY_ENCODING_BASE16 {
+            return Err(KeyParseError::InvalidKeyEncoding);
+        }
+        if key.len() != 64 {
+            return Err(KeyParseError::InvalidKeyLength);
+        }
+
+        let mut out = [0; 32];
+        for (byte, chunk) in out.iter_mut().zip(key.as_bytes().chunks(2)) {
+            *byte = (hex_digit(chunk[0])? << 4) | hex_digit(chunk[1])?;
+        }
+        Ok(PreSharedKey(out))
+    }
+}
+
+impl fmt::Display for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", KEY_TYPE)?;
+        writeln!(f, "{}", KEY_ENCODING_BASE16)?;
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the key in the logs.
+        f.debug_tuple("PreSharedKey").field(&"<hidden>").finish()
+    }
+}
+
+// Decodes a hexadecimal digit.
+fn hex_digit(c: u8) -> Result<u8, KeyParseError> {
+    match c {
+        b'0' ..= b'9' => Ok(c - b'0'),
+        b'a' ..= b'f' => Ok(c - b'a' + 10),
+        b'A' ..= b'F' => Ok(c - b'A' + 10),
+        _ => Err(KeyParseError::InvalidKey),
+    }
+}
+
+/// Error while parsing a `PreSharedKey`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyParseError {
+    /// The key file doesn't contain three lines.
+    InvalidKeyFile,
+    /// The first line isn't `/key/swarm/psk/1.0.0/`.
+    InvalidKeyType,
+    /// The second line isn't `/base16/`, which is the only supported encoding.
+    InvalidKeyEncoding,
+    /// The key doesn't contain 64 hexadecimal digits.
+    InvalidKeyLength,
+    /// The key contains a character that isn't an hexadecimal digit.
+    InvalidKey,
+}
+
+impl fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for KeyParseError {
+    fn description(&self) -> &str {
+        match *self {
+            KeyParseError::InvalidKeyFile => "the key file must contain three lines",
+            KeyParseError::InvalidKeyType => "unsupported key type",
+            KeyParseError::InvalidKeyEncoding => "unsupported key encoding",
+            KeyParseError::InvalidKeyLength => "the key must contain 64 hexadecimal digits",
+            KeyParseError::InvalidKey => "the key contains an invalid hexadecimal digit",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use key::{KeyParseError, PreSharedKey};
+
+    #[test]
+    fn key_file_roundtrip() {
+        let key = PreSharedKey::new([0xab; 32]);
+        let file = key.to_string();
+        assert!(file.starts_with("/key/swarm/psk/1.0.0/\n/base16/\nabab"));
+        assert_eq!(file.parse::<PreSharedKey>(), Ok(key));
+    }
+
+    #[test]
+    fn invalid_key_files() {
+        let parse = |s: &str| s.parse::<PreSharedKey>();
+        assert_eq!(parse("/key/swarm/psk/1.0.0/\n/base16/"), Err(KeyParseError::InvalidKeyFile));
+        assert_eq!(parse("/key/swarm/psk/2.0.0/\n/base16/\n00"), Err(KeyParseError::InvalidKeyType));
+        assert_eq!(parse("/key/swarm/psk/1.0.0/\n/base64/\n00"), Err(KeyParseError::InvalidKeyEncoding));
+        assert_eq!(parse("/key/swarm/psk/1.0.0/\n/base16/\n00"), Err(KeyParseError::InvalidKeyLength));
+        let bad = format!("/key/swarm/psk/1.0.0/\n/base16/\n{}", "zz".repeat(32));
+        assert_eq!(parse(&bad), Err(KeyParseError::InvalidKey));
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the libp2p private networks specification (*pnet*).
+//!
+//! In a private network, all the nodes share a 256 bits key. Every connection is protected by an
+//! outer XSalsa20 layer keyed with this key, which is applied before any other upgrade and in
+//! particular before the security protocol is negotiated. Nodes that don't know the key are
+//! unable to establish a connection with the members of the network.
+//!
+//! # Usage
+//!
+//! Parse the key with `PreSharedKey::from_str`, usually from a `swarm.key` file in the
+//! `/key/swarm/psk/1.0.0/` format, and apply `PnetConfig::handshake` on each connection with
+//! `Transport::and_then`, before applying the other upgrades. The nonces are drawn from the
+//! `RandomSource` passed to `with_random_source`, usually the one of the swarm's `Runtime`:
+//!
+//! ```ignore
+//! let psk: PreSharedKey = fs::read_to_string("swarm.key")?.parse()?;
+//! let pnet = PnetConfig::new(psk).with_random_source(runtime.random_source().clone());
+//! let transport = TcpConfig::new()
+//!     .and_then(move |socket, _, _| pnet.clone().handshake(socket))
+//!     .with_upgrade(secio);
+//! ```
+//!
+//! > **Note**: The XSalsa20 layer only provides confidentiality against the nodes that don't
+//! >           know the key. It doesn't authenticate the remote and doesn't protect the integrity
+//! >           of the data, which is still the job of the security protocol.
+
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate salsa20;
+extern crate tokio_io;
+
+mod key;
+
+pub use self::key::{KeyParseError, PreSharedKey};
+
+use futures::{Async, Future, Poll};
+use libp2p_core::runtime::RandomSource;
+use salsa20::XSalsa20;
+use salsa20::stream_cipher::generic_array::GenericArray;
+use salsa20::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use std::cmp;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Size of the nonce that each side sends at the start of the connection.
+const NONCE_SIZE: usize = 24;
+/// Maximum number of bytes that are encrypted by a single call to `write`.
+const WRITE_BUFFER_SIZE: usize = 4096;
+
+/// Configuration of the private network protection.
+#[derive(Debug, Clone)]
+pub struct PnetConfig {
+    key: PreSharedKey,
+    /// Source of the nonces.
+    random_source: RandomSource,
+}
+
+impl PnetConfig {
+    /// Builds a configuration that uses the given key.
+    #[inline]
+    pub fn new(key: PreSharedKey) -> PnetConfig {
+        PnetConfig {
+            key,
+            random_source: RandomSource::from_entropy(),
+        }
+    }
+
+    /// Uses the given source of random numbers to generate the nonces, for example the one of
+    /// the swarm's `Runtime`.
+    #[inline]
+    pub fn with_random_source(mut self, random_source: RandomSource) -> Self {
+        self.random_source = random_source;
+        self
+    }
+
+    /// Exchanges the nonces with the remote, and produces the socket protected with the key.
+    ///
+    /// Each side sends a random nonce and encrypts the data it sends with the key and its own
+    /// nonce. The handshake is symmetric, therefore the same method is used by the dialer and by
+    /// the listener.
+    pub fn handshake<S>(self, socket: S) -> Box<Future<Item = PnetOutput<S>, Error = IoError> + Send>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        trace!("Starting the private network handshake");
+        let mut local_nonce = [0; NONCE_SIZE];
+        self.random_source.fill_bytes(&mut local_nonce);
+        let key = self.key;
+
+        let future = tokio_io::io::write_all(socket, local_nonce)
+            .and_then(|(socket, _)| tokio_io::io::flush(socket))
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; NONCE_SIZE]))
+            .map(move |(socket, remote_nonce)| {
+                trace!("Private network handshake finished");
+                PnetOutput::new(socket, &key, &local_nonce, &remote_nonce)
+            });
+        Box::new(future)
+    }
+}
+
+/// Socket protected with the key of the private network.
+///
+/// Implements `AsyncRead` and `AsyncWrite`.
+pub struct PnetOutput<S> {
+    /// The underlying socket.
+    inner: S,
+    /// Decrypts the data we receive.
+    read_cipher: XSalsa20,
+    /// Encrypts the data we send.
+    write_cipher: XSalsa20,
+    /// Encrypted data that hasn't been written to the socket yet.
+    write_buffer: Vec<u8>,
+    /// Number of bytes at the start of `write_buffer` that have already been written.
+    write_pos: usize,
+}
+
+impl<S> PnetOutput<S> {
+    // Builds the output. `write_nonce` is our nonce, and `read_nonce` the one of the remote.
+    fn new(inner: S, key: &PreSharedKey, write_nonce: &[u8; NONCE_SIZE], read_nonce: &[u8; NONCE_SIZE])
+        -> PnetOutput<S>
+    {
+        let key = GenericArray::from_slice(key.as_bytes());
+        PnetOutput {
+            inner,
+            read_cipher: XSalsa20::new(key, GenericArray::from_slice(read_nonce)),
+            write_cipher: XSalsa20::new(key, GenericArray::from_slice(write_nonce)),
+            write_buffer: Vec::with_capacity(WRITE_BUFFER_SIZE),
+            write_pos: 0,
+        }
+    }
+
+    /// Returns the underlying socket.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    // Writes the content of `write_buffer` to the socket.
+    fn flush_buffer(&mut self) -> Result<(), IoError>
+    where
+        S: Write,
+    {
+        while self.write_pos < self.write_buffer.len() {
+            let written = self.inner.write(&self.write_buffer[self.write_pos..])?;
+            if written == 0 {
+                return Err(IoErrorKind::WriteZero.into());
+            }
+            self.write_pos += written;
+        }
+
+        self.write_buffer.clear();
+        self.write_pos = 0;
+        Ok(())
+    }
+}
+
+impl<S> Read for PnetOutput<S>
+where
+    S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let read = self.inner.read(buf)?;
+        self.read_cipher.apply_keystream(&mut buf[.. read]);
+        Ok(read)
+    }
+}
+
+impl<S> AsyncRead for PnetOutput<S>
+where
+    S: AsyncRead,
+{
+}
+
+impl<S> Write for PnetOutput<S>
+where
+    S: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        // The data is encrypted as soon as it is accepted, therefore we only accept new data
+        // once the previous one has been entirely written.
+        self.flush_buffer()?;
+
+        let len = cmp::min(buf.len(), WRITE_BUFFER_SIZE);
+        self.write_buffer.extend_from_slice(&buf[.. len]);
+        self.write_cipher.apply_keystream(&mut self.write_buffer);
+
+        match self.flush_buffer() {
+            Ok(()) => Ok(len),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => Ok(len),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.flush_buffer()?;
+        self.inner.flush()
+    }
+}
+
+impl<S> AsyncWrite for PnetOutput<S>
+where
+    S: AsyncWrite,
+{
+    fn shutdown(&mut self) -> Poll<(), IoError> {
+        match self.flush_buffer() {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => return Ok(Async::NotReady),
+            Err(err) => return Err(err),
+        }
+        self.inner.shutdown()
+    }
+}
+
+impl<S> fmt::Debug for PnetOutput<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PnetOutput")
+            .field("inner", &self.inner)
+            .field("buffered", &(self.write_buffer.len() - self.write_pos))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+    use libp2p_core::transport::memory;
+    use libp2p_core::{Multiaddr, Transport};
+    use std::io::Cursor;
+
+    /// Opens an in-memory connection, and returns the dialing and the listening ends of it.
+    fn connection() -> (memory::Channel<Vec<u8>>, memory::Channel<Vec<u8>>) {
+        let (dialer, listener) = memory::connector_custom_type();
+        let addr: Multiaddr = "/memory".parse().unwrap();
+        let (incoming, _) = listener.listen_on(addr.clone()).unwrap_or_else(|_| panic!());
+        let outgoing = dialer.dial(addr).unwrap_or_else(|_| panic!());
+        outgoing
+            .join(incoming.into_future().map_err(|(err, _)| err).and_then(|(incoming, _)| incoming.unwrap().0))
+            .wait()
+            .unwrap()
+    }
+
+    /// Performs the handshake between a dialer using `dialer_key` and a listener using
+    /// `listener_key`, sends a message from the dialer, and returns what the listener received.
+    fn exchange(dialer_key: PreSharedKey, listener_key: PreSharedKey) -> Vec<u8> {
+        let (dialer, listener) = connection();
+        let dialer = PnetConfig::new(dialer_key)
+            .with_random_source(RandomSource::seeded(1))
+            .handshake(dialer)
+            .and_then(|socket| tokio_io::io::write_all(socket, b"hello private network"))
+            .and_then(|(socket, _)| tokio_io::io::flush(socket));
+        let listener = PnetConfig::new(listener_key)
+            .with_random_source(RandomSource::seeded(2))
+            .handshake(listener)
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 21]));
+        let (_, (_, received)) = dialer.join(listener).wait().unwrap();
+        received.to_vec()
+    }
+
+    #[test]
+    fn handshake_with_same_key() {
+        let key = PreSharedKey::new([7; 32]);
+        assert_eq!(exchange(key, key), b"hello private network".to_vec());
+    }
+
+    #[test]
+    fn handshake_with_mismatched_keys() {
+        // The handshake itself succeeds, but the remote can't decrypt anything we send.
+        let received = exchange(PreSharedKey::new([7; 32]), PreSharedKey::new([8; 32]));
+        assert_ne!(received, b"hello private network".to_vec());
+    }
+
+    #[test]
+    fn nonce_drawn_from_random_source() {
+        let (dialer, listener) = connection();
+        let dialer = PnetConfig::new(PreSharedKey::new([7; 32]))
+            .with_random_source(RandomSource::seeded(1))
+            .handshake(dialer);
+        let listener = tokio_io::io::read_exact(listener, [0; NONCE_SIZE])
+            .and_then(|(socket, nonce)| tokio_io::io::write_all(socket, [0; NONCE_SIZE]).map(move |_| nonce));
+        let (_, nonce) = dialer.join(listener).wait().unwrap();
+
+        let mut expected = [0; NONCE_SIZE];
+        RandomSource::seeded(1).fill_bytes(&mut expected);
+        assert_eq!(nonce, expected);
+    }
+
+    #[test]
+    fn encrypted_roundtrip() {
+        let key = PreSharedKey::new([7; 32]);
+        let (nonce_a, nonce_b) = ([1; NONCE_SIZE], [2; NONCE_SIZE]);
+
+        let mut writer = PnetOutput::new(Cursor::new(Vec::new()), &key, &nonce_a, &nonce_b);
+        writer.write_all(b"hello private network").unwrap();
+        writer.flush().unwrap();
+        let encrypted = writer.get_ref().get_ref().clone();
+        assert_ne!(&encrypted[..], &b"hello private network"[..]);
+
+        let mut reader = PnetOutput::new(Cursor::new(encrypted), &key, &nonce_b, &nonce_a);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(&out[..], &b"hello private network"[..]);
+
+        // A node with another key can't read the data.
+        let other = PreSharedKey::new([8; 32]);
+        let encrypted = writer.get_ref().get_ref().clone();
+        let mut reader = PnetOutput::new(Cursor::new(encrypted), &other, &nonce_b, &nonce_a);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_ne!(&out[..], &b"hello private network"[..]);
+    }
+}
@@ -0,0 +1,432 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use libp2p_core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::nodes::swarm::ConnectedPoint;
+use libp2p_core::upgrade::MessageLimits;
+use libp2p_core::{Multiaddr, PeerId};
+use libp2p_request_response::{OutboundFailure, RequestId, RequestResponse, RequestResponseConfig};
+use libp2p_request_response::{RequestResponseEvent, RequestResponseHandler, RequestResponseHandlerEvent};
+use libp2p_request_response::RequestResponseHandlerIn;
+use protocol::{Cookie, ErrorCode, Registration, RendezvousCodec, RendezvousRequest, RendezvousResponse};
+use registry::{RegistryConfig, RendezvousRegistry};
+use std::collections::VecDeque;
+use std::time::Instant;
+use std::{error, fmt};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Event generated by the `Rendezvous` behaviour.
+#[derive(Debug)]
+pub enum RendezvousEvent {
+    /// A rendezvous node accepted our registration.
+    Registered {
+        rendezvous_node: PeerId,
+        namespace: String,
+        /// Time-to-live of the registration granted by the rendezvous node, in seconds.
+        ttl: u64,
+    },
+    /// Failed to register with a rendezvous node.
+    RegisterFailed {
+        rendezvous_node: PeerId,
+        namespace: String,
+        error: RendezvousError,
+    },
+    /// Our unregistration has been sent to a rendezvous node.
+    Unregistered {
+        rendezvous_node: PeerId,
+        namespace: String,
+    },
+    /// A rendezvous node answered our discovery.
+    Discovered {
+        rendezvous_node: PeerId,
+        registrations: Vec<Registration>,
+        /// Pass this cookie to the next `discover` in order to only obtain the new
+        /// registrations.
+        cookie: Cookie,
+    },
+    /// Failed to discover peers through a rendezvous node.
+    DiscoverFailed {
+        rendezvous_node: PeerId,
+        namespace: Option<String>,
+        error: RendezvousError,
+    },
+    /// A peer registered with us. Only generated when acting as a rendezvous node.
+    PeerRegistered {
+        peer_id: PeerId,
+        namespace: String,
+        addresses: Vec<Multiaddr>,
+        ttl: u64,
+    },
+    /// A peer unregistered from us. Only generated when acting as a rendezvous node.
+    PeerUnregistered {
+        peer_id: PeerId,
+        namespace: String,
+    },
+}
+
+/// Error while communicating with a rendezvous node.
+#[derive(Debug)]
+pub enum RendezvousError {
+    /// The rendezvous node rejected the request.
+    Rejected {
+        status: ErrorCode,
+        text: String,
+    },
+    /// Failed to obtain a response.
+    Failed(OutboundFailure),
+}
+
+impl fmt::Display for RendezvousError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendezvousError::Rejected { status, ref text } => {
+                write!(f, "Request rejected with status {:?}: {}", status, text)
+            },
+            RendezvousError::Failed(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for RendezvousError {
+    fn description(&self) -> &str {
+        match *self {
+            RendezvousError::Rejected { .. } => "request rejected by the rendezvous node",
+            RendezvousError::Failed(_) => "failed to obtain a response",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RendezvousError::Rejected { .. } => None,
+            RendezvousError::Failed(ref err) => Some(err),
+        }
+    }
+}
+
+/// Kind of request we sent to a rendezvous node.
+#[derive(Debug, Clone)]
+enum OutboundKind {
+    Register(String),
+    Unregister(String),
+    Discover(Option<String>),
+}
+
+/// `NetworkBehaviour` implementing the rendezvous protocol.
+///
+/// Peers register their addresses under namespaces with a rendezvous node, and other peers
+/// discover them by querying the same rendezvous node. Every node acts as a client, and acts as a
+/// rendezvous node as well if created with `with_server`.
+///
+/// > **Note**: The behaviour doesn't dial the rendezvous nodes. Requests sent to a node we are
+/// >           not connected to fail with `OutboundFailure::NotConnected`.
+pub struct Rendezvous<TSubstream> {
+    /// Underlying request/response behaviour.
+    inner: RequestResponse<TSubstream, RendezvousCodec>,
+    /// Id of the local node.
+    local_peer_id: PeerId,
+    /// Registrations of the other peers, if we act as a rendezvous node.
+    registry: Option<RendezvousRegistry>,
+    /// Registrations to send during the next `poll`, once we know our addresses.
+    pending_registrations: Vec<(PeerId, String, Option<u64>)>,
+    /// Requests we sent and haven't been answered yet.
+    outbound: FnvHashMap<RequestId, (PeerId, OutboundKind)>,
+    /// Events to return from `poll`.
+    events: VecDeque<RendezvousEvent>,
+}
+
+impl<TSubstream> Rendezvous<TSubstream> {
+    /// Creates a new `Rendezvous` behaviour that only acts as a client.
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Rendezvous {
+            inner: RequestResponse::new(RendezvousCodec::new(), RequestResponseConfig::new()),
+            local_peer_id,
+            registry: None,
+            pending_registrations: Vec::new(),
+            outbound: FnvHashMap::default(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Reads the registrations and discovery answers of the remotes with `limits`.
+    ///
+    /// Must be called before the behaviour is used.
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        let codec = RendezvousCodec::new().with_message_limits(limits);
+        self.inner = RequestResponse::new(codec, RequestResponseConfig::new());
+        self
+    }
+
+    /// Makes the local node act as a rendezvous node and accept the registrations of the other
+    /// peers.
+    pub fn with_server(mut self, config: RegistryConfig) -> Self {
+        self.registry = Some(RendezvousRegistry::new(config));
+        self
+    }
+
+    /// Returns the registrations we store, if we act as a rendezvous node.
+    #[inline]
+    pub fn registry(&self) -> Option<&RendezvousRegistry> {
+        self.registry.as_ref()
+    }
+
+    /// Registers our advertised addresses with a rendezvous node under `namespace`. If `ttl` is
+    /// `None`, the rendezvous node uses `DEFAULT_TTL`.
+    ///
+    /// The registration has to be renewed before it expires.
+    pub fn register(&mut self, rendezvous_node: PeerId, namespace: String, ttl: Option<u64>) {
+        self.pending_registrations.push((rendezvous_node, namespace, ttl));
+    }
+
+    /// Removes our registration under `namespace` from a rendezvous node.
+    pub fn unregister(&mut self, rendezvous_node: PeerId, namespace: String) {
+        let request = RendezvousRequest::Unregister {
+            namespace: namespace.clone(),
+            peer_id: self.local_peer_id.clone(),
+        };
+        let request_id = self.inner.send_request(&rendezvous_node, request);
+        self.outbound.insert(request_id, (rendezvous_node, OutboundKind::Unregister(namespace)));
+    }
+
+    /// Asks a rendezvous node for the peers registered under `namespace`, or under any
+    /// namespace if `None`.
+    ///
+    /// Pass the cookie of a previous `Discovered` event in order to only obtain the
+    /// registrations that happened since then.
+    pub fn discover(&mut self, rendezvous_node: PeerId, namespace: Option<String>, limit: Option<u64>, cookie: Option<Cookie>) {
+        let request = RendezvousRequest::Discover {
+            namespace: namespace.clone(),
+            limit,
+            cookie,
+        };
+        let request_id = self.inner.send_request(&rendezvous_node, request);
+        self.outbound.insert(request_id, (rendezvous_node, OutboundKind::Discover(namespace)));
+    }
+
+    /// Processes an event of the underlying behaviour.
+    fn handle_inner_event(&mut self, event: RequestResponseEvent<RendezvousRequest, RendezvousResponse>) {
+        match event {
+            RequestResponseEvent::Request { peer_id, request_id, request } => {
+                let response = match self.registry {
+                    Some(ref mut registry) => {
+                        let response = registry.handle_request(&peer_id, request.clone(), Instant::now());
+                        match (request, &response) {
+                            (RendezvousRequest::Register { namespace, addresses, .. }, &RendezvousResponse::Registered { ttl }) => {
+                                self.events.push_back(RendezvousEvent::PeerRegistered {
+                                    peer_id,
+                                    namespace,
+                                    addresses,
+                                    ttl,
+                                });
+                            },
+                            (RendezvousRequest::Unregister { namespace, peer_id: ref p }, _) if *p == peer_id => {
+                                self.events.push_back(RendezvousEvent::PeerUnregistered { peer_id, namespace });
+                            },
+                            _ => (),
+                        }
+                        response
+                    },
+                    None => RendezvousResponse::Error {
+                        status: ErrorCode::Unavailable,
+                        text: "not a rendezvous node".to_owned(),
+                    },
+                };
+
+                if self.inner.send_response(request_id, response).is_err() {
+                    debug!("Rendezvous request {} closed before being answered", request_id);
+                }
+            },
+
+            RequestResponseEvent::Response { peer_id, request_id, response } => {
+                let kind = match self.outbound.remove(&request_id) {
+                    Some((_, kind)) => kind,
+                    None => return,
+                };
+
+                let event = match (kind, response) {
+                    (OutboundKind::Register(namespace), RendezvousResponse::Registered { ttl }) => {
+                        RendezvousEvent::Registered { rendezvous_node: peer_id, namespace, ttl }
+                    },
+                    (OutboundKind::Unregister(namespace), _) => {
+                        RendezvousEvent::Unregistered { rendezvous_node: peer_id, namespace }
+                    },
+                    (OutboundKind::Discover(_), RendezvousResponse::Discovered { registrations, cookie }) => {
+                        RendezvousEvent::Discovered { rendezvous_node: peer_id, registrations, cookie }
+                    },
+                    (kind, response) => {
+                        let error = match response {
+                            RendezvousResponse::Error { status, text } => RendezvousError::Rejected { status, text },
+                            _ => RendezvousError::Rejected {
+                                status: ErrorCode::InternalError,
+                                text: "unexpected response".to_owned(),
+                            },
+                        };
+                        failure_event(peer_id, kind, error)
+                    },
+                };
+                self.events.push_back(event);
+            },
+
+            RequestResponseEvent::OutboundFailure { peer_id, request_id, error } => {
+                if let Some((_, kind)) = self.outbound.remove(&request_id) {
+                    self.events.push_back(failure_event(peer_id, kind, RendezvousError::Failed(error)));
+                }
+            },
+
+            RequestResponseEvent::InboundFailure { peer_id, request_id, error } => {
+                debug!("Failed to answer rendezvous request {} of {:?}: {}", request_id, peer_id, error);
+            },
+
+            RequestResponseEvent::ResponseSent { .. } => (),
+        }
+    }
+}
+
+// Builds the event reporting that a request failed.
+fn failure_event(rendezvous_node: PeerId, kind: OutboundKind, error: RendezvousError) -> RendezvousEvent {
+    match kind {
+        OutboundKind::Register(namespace) => {
+            RendezvousEvent::RegisterFailed { rendezvous_node, namespace, error }
+        },
+        // Unregistrations aren't answered, so there is nothing to report but the fact that the
+        // request has been processed.
+        OutboundKind::Unregister(namespace) => {
+            RendezvousEvent::Unregistered { rendezvous_node, namespace }
+        },
+        OutboundKind::Discover(namespace) => {
+            RendezvousEvent::DiscoverFailed { rendezvous_node, namespace, error }
+        },
+    }
+}
+
+impl<TSubstream> fmt::Debug for Rendezvous<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Rendezvous")
+            .field("local_peer_id", &self.local_peer_id)
+            .field("registry", &self.registry)
+            .field("outbound", &self.outbound.len())
+            .finish()
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Rendezvous<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type ConnectionHandler = RequestResponseHandler<TSubstream, RendezvousCodec>;
+    type OutEvent = RendezvousEvent;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        self.inner.new_handler()
+    }
+
+    #[inline]
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        self.inner.inject_connected(peer_id, endpoint)
+    }
+
+    #[inline]
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+        self.inner.inject_disconnected(peer_id, endpoint)
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, event: RequestResponseHandlerEvent<RendezvousCodec>) {
+        self.inner.inject_node_event(peer_id, event)
+    }
+
+    fn poll(&mut self, params: &mut PollParameters)
+        -> Async<NetworkBehaviourAction<RequestResponseHandlerIn<RendezvousCodec>, RendezvousEvent>>
+    {
+        if !self.pending_registrations.is_empty() {
+            let addresses = params.advertised_addresses();
+            for (rendezvous_node, namespace, ttl) in self.pending_registrations.drain(..) {
+                let request = RendezvousRequest::Register {
+                    namespace: namespace.clone(),
+                    peer_id: self.local_peer_id.clone(),
+                    addresses: addresses.clone(),
+                    ttl,
+                };
+                let request_id = self.inner.send_request(&rendezvous_node, request);
+                self.outbound.insert(request_id, (rendezvous_node, OutboundKind::Register(namespace)));
+            }
+        }
+
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
+            }
+
+            match self.inner.poll(params) {
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
+                    self.handle_inner_event(event);
+                },
+                Async::Ready(action) => {
+                    return Async::Ready(action.map_out_event(|_| unreachable!()));
+                },
+                Async::NotReady => return Async::NotReady,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+    use std::io::Cursor;
+
+    #[test]
+    fn discovery_response_reported() {
+        let local = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let node = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+        let mut behaviour = Rendezvous::<Cursor<Vec<u8>>>::new(local);
+
+        behaviour.discover(node.clone(), Some("chat".to_owned()), None, None);
+        let request_id = *behaviour.outbound.keys().next().unwrap();
+
+        let registration = Registration {
+            namespace: "chat".to_owned(),
+            peer_id: PublicKey::Ed25519(vec![3; 32]).into_peer_id(),
+            addresses: vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()],
+            ttl: 60,
+        };
+        behaviour.handle_inner_event(RequestResponseEvent::Response {
+            peer_id: node.clone(),
+            request_id,
+            response: RendezvousResponse::Discovered {
+                registrations: vec![registration.clone()],
+                cookie: Cookie::from_bytes(vec![1, 2, 3]),
+            },
+        });
+
+        match behaviour.events.pop_front() {
+            Some(RendezvousEvent::Discovered { rendezvous_node, registrations, cookie }) => {
+                assert_eq!(rendezvous_node, node);
+                assert_eq!(registrations, vec![registration]);
+                assert_eq!(cookie, Cookie::from_bytes(vec![1, 2, 3]));
+            },
+            _ => panic!(),
+        }
+        assert!(behaviour.outbound.is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Implementation of the rendezvous protocol.
+//!
+//! The rendezvous protocol is a lightweight alternative to the DHT for discovering peers. Peers
+//! register their addresses under a namespace with a rendezvous node, for a limited time. Other
+//! peers then ask the same rendezvous node for the peers registered under a namespace.
+//!
+//! # Usage
+//!
+//! Create a `Rendezvous` behaviour and pass it to the swarm. Call `with_server` to also act as
+//! a rendezvous node.
+//!
+//! - Call `register` to register the addresses of the local node with a rendezvous node we are
+//!   connected to. Registrations expire after their TTL and must be renewed.
+//! - Call `discover` to obtain the peers registered with a rendezvous node. The `Discovered`
+//!   event contains a cookie that can be passed to the next `discover` in order to only obtain
+//!   the new registrations.
+//! - Call `unregister` to remove a registration before it expires.
+
+extern crate bytes;
+extern crate fnv;
+extern crate futures;
+extern crate libp2p_core;
+extern crate libp2p_request_response;
+#[macro_use]
+extern crate log;
+extern crate protobuf;
+extern crate tokio_io;
+
+pub use self::behaviour::{Rendezvous, RendezvousError, RendezvousEvent};
+pub use self::protocol::{Cookie, ErrorCode, Registration, RendezvousCodec, DEFAULT_TTL};
+pub use self::protocol::{RendezvousRequest, RendezvousResponse};
+pub use self::registry::{RegistryConfig, RendezvousRegistry};
+
+mod behaviour;
+mod protocol;
+mod registry;
+mod structs_proto;
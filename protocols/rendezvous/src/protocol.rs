@@ -0,0 +1,540 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use futures::{future, Future};
+use libp2p_core::upgrade::{self, MessageLimits, ReadOneError};
+use libp2p_core::{Multiaddr, PeerId};
+use libp2p_request_response::RequestResponseCodec;
+use protobuf::Message as ProtobufMessage;
+use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
+use protobuf::RepeatedField;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use structs_proto;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Time-to-live of a registration when none is specified, in seconds.
+pub const DEFAULT_TTL: u64 = 2 * 60 * 60;
+
+/// Registration of a peer under a namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    /// Namespace the peer is registered under.
+    pub namespace: String,
+    /// The registered peer.
+    pub peer_id: PeerId,
+    /// Addresses of the registered peer.
+    pub addresses: Vec<Multiaddr>,
+    /// Time-to-live of the registration, in seconds.
+    pub ttl: u64,
+}
+
+/// Opaque value returned by a discovery. Passing it to the next discovery of the same namespace
+/// only returns the registrations that have been made since.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cookie(Vec<u8>);
+
+impl Cookie {
+    /// Builds a cookie from its raw bytes.
+    #[inline]
+    pub fn from_bytes(bytes: Vec<u8>) -> Cookie {
+        Cookie(bytes)
+    }
+
+    /// Returns the raw bytes of the cookie.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Turns the cookie into its raw bytes.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Request sent to a rendezvous node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RendezvousRequest {
+    /// Register a peer under a namespace.
+    Register {
+        /// Namespace to register under.
+        namespace: String,
+        /// The peer to register. Must be the peer sending the request.
+        peer_id: PeerId,
+        /// Addresses of the peer.
+        addresses: Vec<Multiaddr>,
+        /// Time-to-live of the registration in seconds, or `None` for the default.
+        ttl: Option<u64>,
+    },
+    /// Remove the registration of a peer under a namespace.
+    Unregister {
+        /// Namespace to unregister from.
+        namespace: String,
+        /// The peer to unregister. Must be the peer sending the request.
+        peer_id: PeerId,
+    },
+    /// Ask for the peers registered under a namespace.
+    Discover {
+        /// Namespace to discover, or `None` for all the namespaces.
+        namespace: Option<String>,
+        /// Maximum number of registrations to return.
+        limit: Option<u64>,
+        /// Cookie returned by a previous discovery.
+        cookie: Option<Cookie>,
+    },
+}
+
+/// Response sent back by a rendezvous node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RendezvousResponse {
+    /// The registration has been accepted with the given time-to-live, in seconds.
+    Registered {
+        /// Time-to-live of the registration.
+        ttl: u64,
+    },
+    /// The peer has been unregistered. Nothing is sent on the substream in that case.
+    Unregistered,
+    /// Result of a discovery.
+    Discovered {
+        /// The registrations.
+        registrations: Vec<Registration>,
+        /// Cookie to pass to the next discovery.
+        cookie: Cookie,
+    },
+    /// The request has been refused.
+    Error {
+        /// Reason of the failure.
+        status: ErrorCode,
+        /// Human-readable explanation of the failure.
+        text: String,
+    },
+}
+
+/// Reason why a rendezvous node refused a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The namespace is empty or too long.
+    InvalidNamespace,
+    /// The peer information is invalid.
+    InvalidPeerInfo,
+    /// The time-to-live is out of the accepted range.
+    InvalidTtl,
+    /// The cookie is invalid or doesn't match the namespace.
+    InvalidCookie,
+    /// The peer isn't allowed to perform this request.
+    NotAuthorized,
+    /// The rendezvous node encountered an internal error.
+    InternalError,
+    /// The rendezvous node doesn't accept this kind of request.
+    Unavailable,
+}
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/rendezvous/1.0.0";
+
+/// Implementation of `RequestResponseCodec` for the `/rendezvous/1.0.0` protocol.
+///
+/// Each message is a protobuf message prefixed with its length.
+#[derive(Debug, Clone, Default)]
+pub struct RendezvousCodec {
+    /// Bounds the size of the requests and responses we read.
+    limits: MessageLimits,
+}
+
+impl RendezvousCodec {
+    /// Builds a new codec, with the default `MessageLimits`.
+    #[inline]
+    pub fn new() -> Self {
+        RendezvousCodec::default()
+    }
+
+    /// Replaces the limits applied to the messages we read.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+impl RequestResponseCodec for RendezvousCodec {
+    type Request = RendezvousRequest;
+    type Response = RendezvousResponse;
+
+    #[inline]
+    fn protocol_name(&self) -> Bytes {
+        Bytes::from(PROTOCOL_NAME)
+    }
+
+    fn read_request<T>(&self, io: T) -> Box<Future<Item = (RendezvousRequest, T), Error = IoError> + Send>
+    where
+        T: AsyncRead + Send + 'static,
+    {
+        let future = upgrade::read_respond(io, self.limits.max_for(PROTOCOL_NAME), (), |io, msg, ()| {
+            Ok::<_, IoError>((decode_request(&msg)?, io))
+        });
+        Box::new(future)
+    }
+
+    fn read_response<T>(&self, io: T) -> Box<Future<Item = RendezvousResponse, Error = IoError> + Send>
+    where
+        T: AsyncRead + Send + 'static,
+    {
+        // Unregistrations aren't answered, in which case the substream is closed without any
+        // message.
+        let future = upgrade::read_one(io, self.limits.max_for(PROTOCOL_NAME))
+            .then(|result| match result {
+                Ok((_, msg)) => decode_response(&msg),
+                Err(ReadOneError::Io(ref err)) if err.kind() == IoErrorKind::UnexpectedEof => {
+                    Ok(RendezvousResponse::Unregistered)
+                },
+                Err(err) => Err(err.into()),
+            });
+        Box::new(future)
+    }
+
+    fn write_request<T>(&self, io: T, request: RendezvousRequest) -> Box<Future<Item = T, Error = IoError> + Send>
+    where
+        T: AsyncWrite + Send + 'static,
+    {
+        Box::new(upgrade::write_one(io, encode_request(request)))
+    }
+
+    fn write_response<T>(&self, io: T, response: RendezvousResponse) -> Box<Future<Item = T, Error = IoError> + Send>
+    where
+        T: AsyncWrite + Send + 'static,
+    {
+        match encode_response(response) {
+            Some(bytes) => Box::new(upgrade::write_one(io, bytes)),
+            None => Box::new(future::ok(io)),
+        }
+    }
+}
+
+// Builds the protobuf version of a registration.
+fn registration_to_proto(namespace: String, peer_id: PeerId, addresses: Vec<Multiaddr>, ttl: Option<u64>)
+    -> structs_proto::Message_Register
+{
+    let mut peer = structs_proto::Message_PeerInfo::new();
+    peer.set_id(peer_id.into_bytes());
+    peer.set_addrs(RepeatedField::from_vec(
+        addresses.into_iter().map(|addr| addr.into_bytes()).collect(),
+    ));
+
+    let mut register = structs_proto::Message_Register::new();
+    register.set_ns(namespace);
+    register.set_peer(peer);
+    if let Some(ttl) = ttl {
+        register.set_ttl(ttl as i64);
+    }
+    register
+}
+
+// Turns the protobuf version of a registration into its parts. Addresses that fail to parse are
+// ignored.
+fn registration_from_proto(mut register: structs_proto::Message_Register)
+    -> Result<(String, PeerId, Vec<Multiaddr>, Option<u64>), IoError>
+{
+    let mut peer = register.take_peer();
+    let peer_id = PeerId::from_bytes(peer.take_id())
+        .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid peer id"))?;
+    let addresses = peer
+        .take_addrs()
+        .into_iter()
+        .filter_map(|addr| Multiaddr::from_bytes(addr).ok())
+        .collect();
+    let ttl = if register.has_ttl() {
+        if register.get_ttl() < 0 {
+            return Err(IoError::new(IoErrorKind::InvalidData, "negative TTL"));
+        }
+        Some(register.get_ttl() as u64)
+    } else {
+        None
+    };
+    Ok((register.take_ns(), peer_id, addresses, ttl))
+}
+
+// Encodes a request into bytes.
+pub(crate) fn encode_request(request: RendezvousRequest) -> Vec<u8> {
+    let mut message = structs_proto::Message::new();
+    match request {
+        RendezvousRequest::Register { namespace, peer_id, addresses, ttl } => {
+            message.set_field_type(structs_proto::Message_MessageType::REGISTER);
+            message.set_register(registration_to_proto(namespace, peer_id, addresses, ttl));
+        },
+        RendezvousRequest::Unregister { namespace, peer_id } => {
+            let mut unregister = structs_proto::Message_Unregister::new();
+            unregister.set_ns(namespace);
+            unregister.set_id(peer_id.into_bytes());
+            message.set_field_type(structs_proto::Message_MessageType::UNREGISTER);
+            message.set_unregister(unregister);
+        },
+        RendezvousRequest::Discover { namespace, limit, cookie } => {
+            let mut discover = structs_proto::Message_Discover::new();
+            if let Some(namespace) = namespace {
+                discover.set_ns(namespace);
+            }
+            if let Some(limit) = limit {
+                discover.set_limit(limit as i64);
+            }
+            if let Some(cookie) = cookie {
+                discover.set_cookie(cookie.into_bytes());
+            }
+            message.set_field_type(structs_proto::Message_MessageType::DISCOVER);
+            message.set_discover(discover);
+        },
+    }
+
+    message
+        .write_to_bytes()
+        .expect("writing protobuf failed ; should never happen")
+}
+
+// Decodes a request from bytes.
+pub(crate) fn decode_request(msg: &[u8]) -> Result<RendezvousRequest, IoError> {
+    let mut message = protobuf_parse_from_bytes::<structs_proto::Message>(msg)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    match message.get_field_type() {
+        structs_proto::Message_MessageType::REGISTER if message.has_register() => {
+            let (namespace, peer_id, addresses, ttl) = registration_from_proto(message.take_register())?;
+            Ok(RendezvousRequest::Register { namespace, peer_id, addresses, ttl })
+        },
+        structs_proto::Message_MessageType::UNREGISTER if message.has_unregister() => {
+            let mut unregister = message.take_unregister();
+            let peer_id = PeerId::from_bytes(unregister.take_id())
+                .map_err(|_| IoError::new(IoErrorKind::InvalidData, "invalid peer id"))?;
+            Ok(RendezvousRequest::Unregister { namespace: unregister.take_ns(), peer_id })
+        },
+        structs_proto::Message_MessageType::DISCOVER if message.has_discover() => {
+            let mut discover = message.take_discover();
+            let namespace = if discover.has_ns() { Some(discover.take_ns()) } else { None };
+            let limit = if discover.has_limit() && discover.get_limit() > 0 {
+                Some(discover.get_limit() as u64)
+            } else {
+                None
+            };
+            let cookie = if discover.has_cookie() {
+                Some(Cookie(discover.take_cookie()))
+            } else {
+                None
+            };
+            Ok(RendezvousRequest::Discover { namespace, limit, cookie })
+        },
+        _ => Err(IoError::new(IoErrorKind::InvalidData, "expected a rendezvous request")),
+    }
+}
+
+// Encodes a response into bytes. Returns `None` if nothing must be sent.
+pub(crate) fn encode_response(response: RendezvousResponse) -> Option<Vec<u8>> {
+    fn status_to_proto(status: ErrorCode) -> structs_proto::Message_ResponseStatus {
+        match status {
+            ErrorCode::InvalidNamespace => structs_proto::Message_ResponseStatus::E_INVALID_NAMESPACE,
+            ErrorCode::InvalidPeerInfo => structs_proto::Message_ResponseStatus::E_INVALID_PEER_INFO,
+            ErrorCode::InvalidTtl => structs_proto::Message_ResponseStatus::E_INVALID_TTL,
+            ErrorCode::InvalidCookie => structs_proto::Message_ResponseStatus::E_INVALID_COOKIE,
+            ErrorCode::NotAuthorized => structs_proto::Message_ResponseStatus::E_NOT_AUTHORIZED,
+            ErrorCode::InternalError => structs_proto::Message_ResponseStatus::E_INTERNAL_ERROR,
+            ErrorCode::Unavailable => structs_proto::Message_ResponseStatus::E_UNAVAILABLE,
+        }
+    }
+
+    let mut message = structs_proto::Message::new();
+    match response {
+        RendezvousResponse::Registered { ttl } => {
+            let mut register_response = structs_proto::Message_RegisterResponse::new();
+            register_response.set_status(structs_proto::Message_ResponseStatus::OK);
+            register_response.set_ttl(ttl as i64);
+            message.set_field_type(structs_proto::Message_MessageType::REGISTER_RESPONSE);
+            message.set_registerResponse(register_response);
+        },
+        RendezvousResponse::Unregistered => return None,
+        RendezvousResponse::Discovered { registrations, cookie } => {
+            let registrations = registrations
+                .into_iter()
+                .map(|r| registration_to_proto(r.namespace, r.peer_id, r.addresses, Some(r.ttl)))
+                .collect();
+            let mut discover_response = structs_proto::Message_DiscoverResponse::new();
+            discover_response.set_status(structs_proto::Message_ResponseStatus::OK);
+            discover_response.set_registrations(RepeatedField::from_vec(registrations));
+            discover_response.set_cookie(cookie.into_bytes());
+            message.set_field_type(structs_proto::Message_MessageType::DISCOVER_RESPONSE);
+            message.set_discoverResponse(discover_response);
+        },
+        RendezvousResponse::Error { status, text } => {
+            // Errors are transmitted as a response of the same type as the request. As we don't
+            // know the type of the request here, we send a `DISCOVER_RESPONSE` if the error can
+            // only be caused by a discovery, and a `REGISTER_RESPONSE` otherwise.
+            if status == ErrorCode::InvalidCookie {
+                let mut discover_response = structs_proto::Message_DiscoverResponse::new();
+                discover_response.set_status(status_to_proto(status));
+                discover_response.set_statusText(text);
+                message.set_field_type(structs_proto::Message_MessageType::DISCOVER_RESPONSE);
+                message.set_discoverResponse(discover_response);
+            } else {
+                let mut register_response = structs_proto::Message_RegisterResponse::new();
+                register_response.set_status(status_to_proto(status));
+                register_response.set_statusText(text);
+                message.set_field_type(structs_proto::Message_MessageType::REGISTER_RESPONSE);
+                message.set_registerResponse(register_response);
+            }
+        },
+    }
+
+    Some(message
+        .write_to_bytes()
+        .expect("writing protobuf failed ; should never happen"))
+}
+
+// Decodes a response from bytes.
+pub(crate) fn decode_response(msg: &[u8]) -> Result<RendezvousResponse, IoError> {
+    fn status_from_proto(status: structs_proto::Message_ResponseStatus) -> Option<ErrorCode> {
+        match status {
+            structs_proto::Message_ResponseStatus::OK => None,
+            structs_proto::Message_ResponseStatus::E_INVALID_NAMESPACE => Some(ErrorCode::InvalidNamespace),
+            structs_proto::Message_ResponseStatus::E_INVALID_PEER_INFO => Some(ErrorCode::InvalidPeerInfo),
+            structs_proto::Message_ResponseStatus::E_INVALID_TTL => Some(ErrorCode::InvalidTtl),
+            structs_proto::Message_ResponseStatus::E_INVALID_COOKIE => Some(ErrorCode::InvalidCookie),
+            structs_proto::Message_ResponseStatus::E_NOT_AUTHORIZED => Some(ErrorCode::NotAuthorized),
+            structs_proto::Message_ResponseStatus::E_INTERNAL_ERROR => Some(ErrorCode::InternalError),
+            structs_proto::Message_ResponseStatus::E_UNAVAILABLE => Some(ErrorCode::Unavailable),
+        }
+    }
+
+    let mut message = protobuf_parse_from_bytes::<structs_proto::Message>(msg)
+        .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+    match message.get_field_type() {
+        structs_proto::Message_MessageType::REGISTER_RESPONSE if message.has_registerResponse() => {
+            let mut response = message.take_registerResponse();
+            match status_from_proto(response.get_status()) {
+                None => Ok(RendezvousResponse::Registered { ttl: response.get_ttl().max(0) as u64 }),
+                Some(status) => Ok(RendezvousResponse::Error { status, text: response.take_statusText() }),
+            }
+        },
+        structs_proto::Message_MessageType::DISCOVER_RESPONSE if message.has_discoverResponse() => {
+            let mut response = message.take_discoverResponse();
+            if let Some(status) = status_from_proto(response.get_status()) {
+                return Ok(RendezvousResponse::Error { status, text: response.take_statusText() });
+            }
+
+            let mut registrations = Vec::new();
+            for register in response.take_registrations().into_iter() {
+                let (namespace, peer_id, addresses, ttl) = registration_from_proto(register)?;
+                registrations.push(Registration {
+                    namespace,
+                    peer_id,
+                    addresses,
+                    ttl: ttl.unwrap_or(DEFAULT_TTL),
+                });
+            }
+
+            Ok(RendezvousResponse::Discovered {
+                registrations,
+                cookie: Cookie(response.take_cookie()),
+            })
+        },
+        _ => Err(IoError::new(IoErrorKind::InvalidData, "expected a rendezvous response")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+    use std::io::Cursor;
+
+    #[test]
+    fn requests_round_trip() {
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let requests = vec![
+            RendezvousRequest::Register {
+                namespace: "chat".to_owned(),
+                peer_id: peer_id.clone(),
+                addresses: vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()],
+                ttl: Some(60),
+            },
+            RendezvousRequest::Unregister { namespace: "chat".to_owned(), peer_id },
+            RendezvousRequest::Discover {
+                namespace: None,
+                limit: Some(10),
+                cookie: Some(Cookie::from_bytes(vec![1, 2, 3])),
+            },
+        ];
+
+        for request in requests {
+            let bytes = encode_request(request.clone());
+            assert_eq!(decode_request(&bytes).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let responses = vec![
+            RendezvousResponse::Registered { ttl: 60 },
+            RendezvousResponse::Discovered {
+                registrations: vec![Registration {
+                    namespace: "chat".to_owned(),
+                    peer_id,
+                    addresses: vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()],
+                    ttl: 60,
+                }],
+                cookie: Cookie::from_bytes(vec![4, 5]),
+            },
+            RendezvousResponse::Error { status: ErrorCode::InvalidTtl, text: "too long".to_owned() },
+            RendezvousResponse::Error { status: ErrorCode::InvalidCookie, text: String::new() },
+        ];
+
+        for response in responses {
+            let bytes = encode_response(response.clone()).unwrap();
+            assert_eq!(decode_response(&bytes).unwrap(), response);
+        }
+
+        assert!(encode_response(RendezvousResponse::Unregistered).is_none());
+    }
+
+    #[test]
+    fn oversized_message_rejected() {
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let request = RendezvousRequest::Unregister { namespace: "chat".to_owned(), peer_id };
+        let encoded = encode_request(request.clone());
+        assert!(encoded.len() < 128);
+        let mut bytes = vec![encoded.len() as u8];
+        bytes.extend_from_slice(&encoded);
+
+        let codec = RendezvousCodec::new();
+        let (decoded, _) = codec.read_request(Cursor::new(bytes.clone())).wait().unwrap();
+        assert_eq!(decoded, request);
+
+        let limits = MessageLimits::new().with_default_max(encoded.len() - 1);
+        let codec = RendezvousCodec::new().with_message_limits(&limits);
+        assert!(codec.read_request(Cursor::new(bytes)).wait().is_err());
+    }
+
+    #[test]
+    fn closed_substream_means_unregistered() {
+        let codec = RendezvousCodec::new();
+        let response = codec.read_response(Cursor::new(Vec::new())).wait().unwrap();
+        assert_eq!(response, RendezvousResponse::Unregistered);
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::FnvHashMap;
+use libp2p_core::PeerId;
+use protocol::{Cookie, ErrorCode, Registration, RendezvousRequest, RendezvousResponse, DEFAULT_TTL};
+use std::time::{Duration, Instant};
+
+/// Configuration of the registrations accepted by a rendezvous node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryConfig {
+    /// Minimum time-to-live of a registration, in seconds.
+    pub(crate) min_ttl: u64,
+    /// Maximum time-to-live of a registration, in seconds.
+    pub(crate) max_ttl: u64,
+    /// Maximum number of namespaces a single peer can be registered under.
+    pub(crate) max_registrations_per_peer: usize,
+    /// Maximum number of registrations returned by a discovery.
+    pub(crate) max_discover_limit: u64,
+}
+
+impl RegistryConfig {
+    /// Builds the default configuration. Registrations last between 2 minutes and 72 hours, a
+    /// peer can be registered under up to 1000 namespaces, and discoveries return up to 1000
+    /// registrations.
+    #[inline]
+    pub fn new() -> RegistryConfig {
+        RegistryConfig {
+            min_ttl: 2 * 60,
+            max_ttl: 72 * 60 * 60,
+            max_registrations_per_peer: 1000,
+            max_discover_limit: 1000,
+        }
+    }
+
+    /// Sets the range of accepted time-to-live, in seconds.
+    #[inline]
+    pub fn with_ttl_range(mut self, min_ttl: u64, max_ttl: u64) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Sets the maximum number of namespaces a single peer can be registered under.
+    #[inline]
+    pub fn with_max_registrations_per_peer(mut self, max: usize) -> Self {
+        self.max_registrations_per_peer = max;
+        self
+    }
+
+    /// Sets the maximum number of registrations returned by a discovery.
+    ///
+    /// A value of `0` is treated as `1`.
+    #[inline]
+    pub fn with_max_discover_limit(mut self, max: u64) -> Self {
+        self.max_discover_limit = if max == 0 { 1 } else { max };
+        self
+    }
+}
+
+impl Default for RegistryConfig {
+    #[inline]
+    fn default() -> Self {
+        RegistryConfig::new()
+    }
+}
+
+/// Maximum length of a namespace, in bytes.
+const MAX_NAMESPACE_LEN: usize = 255;
+
+/// A registration stored by the rendezvous node.
+#[derive(Debug, Clone)]
+struct StoredRegistration {
+    /// Increasing number identifying the registration. Used for the cookies.
+    id: u64,
+    registration: Registration,
+    /// When the registration expires.
+    expires: Instant,
+}
+
+/// Registrations stored by a rendezvous node.
+///
+/// The registry only manipulates data and is driven by the `Rendezvous` behaviour, but can also
+/// be used directly.
+#[derive(Debug, Clone)]
+pub struct RendezvousRegistry {
+    config: RegistryConfig,
+    /// The registrations, by namespace and peer.
+    registrations: FnvHashMap<(String, PeerId), StoredRegistration>,
+    /// Identifier of the next registration.
+    next_id: u64,
+}
+
+impl RendezvousRegistry {
+    /// Creates an empty registry.
+    #[inline]
+    pub fn new(config: RegistryConfig) -> RendezvousRegistry {
+        RendezvousRegistry {
+            config,
+            registrations: FnvHashMap::default(),
+            next_id: 1,
+        }
+    }
+
+    /// Processes a request sent by `sender` and returns the response to send back.
+    pub fn handle_request(&mut self, sender: &PeerId, request: RendezvousRequest, now: Instant)
+        -> RendezvousResponse
+    {
+        self.remove_expired(now);
+
+        match request {
+            RendezvousRequest::Register { namespace, peer_id, addresses, ttl } => {
+                if &peer_id != sender {
+                    return error(ErrorCode::NotAuthorized, "can only register the sender");
+                }
+                if !valid_namespace(&namespace) {
+                    return error(ErrorCode::InvalidNamespace, "invalid namespace");
+                }
+                if addresses.is_empty() {
+                    return error(ErrorCode::InvalidPeerInfo, "no address");
+                }
+                let ttl = ttl.unwrap_or(DEFAULT_TTL);
+                if ttl < self.config.min_ttl || ttl > self.config.max_ttl {
+                    return error(ErrorCode::InvalidTtl, "TTL out of range");
+                }
+
+                let key = (namespace.clone(), peer_id.clone());
+                if !self.registrations.contains_key(&key) &&
+                    self.registrations_of(&peer_id) >= self.config.max_registrations_per_peer
+                {
+                    return error(ErrorCode::NotAuthorized, "too many registrations");
+                }
+
+                let id = self.next_id;
+                self.next_id += 1;
+                self.registrations.insert(key, StoredRegistration {
+                    id,
+                    registration: Registration { namespace, peer_id, addresses, ttl },
+                    expires: now + Duration::from_secs(ttl),
+                });
+                RendezvousResponse::Registered { ttl }
+            },
+
+            RendezvousRequest::Unregister { namespace, peer_id } => {
+                if &peer_id == sender {
+                    self.registrations.remove(&(namespace, peer_id));
+                }
+                RendezvousResponse::Unregistered
+            },
+
+            RendezvousRequest::Discover { namespace, limit, cookie } => {
+                if let Some(ref namespace) = namespace {
+                    if !valid_namespace(namespace) {
+                        return error(ErrorCode::InvalidNamespace, "invalid namespace");
+                    }
+                }
+
+                let after = match cookie {
+                    Some(cookie) => match decode_cookie(&cookie) {
+                        Some((id, ref ns)) if *ns == namespace => id,
+                        _ => return error(ErrorCode::InvalidCookie, "invalid cookie"),
+                    },
+                    None => 0,
+                };
+
+                let limit = limit
+                    .unwrap_or(self.config.max_discover_limit)
+                    .min(self.config.max_discover_limit) as usize;
+
+                let mut matching = self.registrations
+                    .values()
+                    .filter(|r| r.id > after)
+                    .filter(|r| namespace.as_ref().map(|ns| *ns == r.registration.namespace).unwrap_or(true))
+                    .collect::<Vec<_>>();
+                matching.sort_by_key(|r| r.id);
+                matching.truncate(limit);
+
+                let last_id = matching.last().map(|r| r.id).unwrap_or(after);
+                let registrations = matching
+                    .into_iter()
+                    .map(|r| {
+                        let mut registration = r.registration.clone();
+                        // Report the remaining time-to-live.
+                        registration.ttl = r.expires.duration_since(now).as_secs();
+                        registration
+                    })
+                    .collect();
+
+                RendezvousResponse::Discovered {
+                    registrations,
+                    cookie: encode_cookie(last_id, namespace.as_ref().map(|ns| &ns[..])),
+                }
+            },
+        }
+    }
+
+    /// Removes the registrations that have expired.
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.registrations.retain(|_, r| r.expires > now);
+    }
+
+    /// Returns the registrations that haven't expired, in the order they have been made.
+    pub fn registrations(&self, now: Instant) -> Vec<Registration> {
+        let mut list = self.registrations
+            .values()
+            .filter(|r| r.expires > now)
+            .collect::<Vec<_>>();
+        list.sort_by_key(|r| r.id);
+        list.into_iter().map(|r| r.registration.clone()).collect()
+    }
+
+    /// Returns the number of namespaces the peer is registered under.
+    fn registrations_of(&self, peer_id: &PeerId) -> usize {
+        self.registrations.keys().filter(|&&(_, ref p)| p == peer_id).count()
+    }
+}
+
+// Builds an error response.
+fn error(status: ErrorCode, text: &str) -> RendezvousResponse {
+    RendezvousResponse::Error { status, text: text.to_owned() }
+}
+
+// Returns true if the namespace can be registered under.
+fn valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty() && namespace.len() <= MAX_NAMESPACE_LEN
+}
+
+// A cookie is the identifier of the last registration returned, in big endian, followed by the
+// namespace of the discovery if any.
+fn encode_cookie(id: u64, namespace: Option<&str>) -> Cookie {
+    let mut bytes = (0 .. 8).map(|n| (id >> (56 - 8 * n)) as u8).collect::<Vec<_>>();
+    if let Some(namespace) = namespace {
+        bytes.extend_from_slice(namespace.as_bytes());
+    }
+    Cookie::from_bytes(bytes)
+}
+
+// Decodes a cookie produced by `encode_cookie`.
+fn decode_cookie(cookie: &Cookie) -> Option<(u64, Option<String>)> {
+    let bytes = cookie.as_bytes();
+    if bytes.len() < 8 {
+        return None;
+    }
+
+    let id = bytes[.. 8].iter().fold(0u64, |id, &b| (id << 8) | u64::from(b));
+    let namespace = if bytes.len() > 8 {
+        Some(String::from_utf8(bytes[8 ..].to_vec()).ok()?)
+    } else {
+        None
+    };
+    Some((id, namespace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+
+    fn register(peer_id: &PeerId, namespace: &str, ttl: Option<u64>) -> RendezvousRequest {
+        RendezvousRequest::Register {
+            namespace: namespace.to_owned(),
+            peer_id: peer_id.clone(),
+            addresses: vec!["/ip4/1.2.3.4/tcp/5".parse().unwrap()],
+            ttl,
+        }
+    }
+
+    fn discover(registry: &mut RendezvousRegistry, namespace: Option<&str>, cookie: Option<Cookie>, now: Instant)
+        -> (Vec<PeerId>, Cookie)
+    {
+        let request = RendezvousRequest::Discover {
+            namespace: namespace.map(|ns| ns.to_owned()),
+            limit: None,
+            cookie,
+        };
+        let sender = PublicKey::Ed25519(vec![9; 32]).into_peer_id();
+        match registry.handle_request(&sender, request, now) {
+            RendezvousResponse::Discovered { registrations, cookie } => {
+                (registrations.into_iter().map(|r| r.peer_id).collect(), cookie)
+            },
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn register_and_discover_with_cookie() {
+        let mut registry = RendezvousRegistry::new(RegistryConfig::new());
+        let now = Instant::now();
+        let a = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let b = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+
+        assert_eq!(registry.handle_request(&a, register(&a, "chat", None), now),
+                   RendezvousResponse::Registered { ttl: DEFAULT_TTL });
+        assert_eq!(registry.handle_request(&b, register(&b, "files", Some(600)), now),
+                   RendezvousResponse::Registered { ttl: 600 });
+
+        let (peers, cookie) = discover(&mut registry, Some("chat"), None, now);
+        assert_eq!(peers, vec![a.clone()]);
+        let (peers, _) = discover(&mut registry, None, None, now);
+        assert_eq!(peers, vec![a.clone(), b.clone()]);
+
+        // The cookie only returns the new registrations, and is bound to its namespace.
+        let (peers, cookie) = discover(&mut registry, Some("chat"), Some(cookie), now);
+        assert!(peers.is_empty());
+        assert_eq!(registry.handle_request(&b, register(&b, "chat", None), now),
+                   RendezvousResponse::Registered { ttl: DEFAULT_TTL });
+        let (peers, _) = discover(&mut registry, Some("chat"), Some(cookie.clone()), now);
+        assert_eq!(peers, vec![b.clone()]);
+        match registry.handle_request(&a, RendezvousRequest::Discover {
+            namespace: Some("files".to_owned()), limit: None, cookie: Some(cookie),
+        }, now) {
+            RendezvousResponse::Error { status: ErrorCode::InvalidCookie, .. } => (),
+            _ => panic!(),
+        }
+
+        // Unregistering and expiration.
+        let request = RendezvousRequest::Unregister { namespace: "chat".to_owned(), peer_id: a.clone() };
+        assert_eq!(registry.handle_request(&a, request, now), RendezvousResponse::Unregistered);
+        let (peers, _) = discover(&mut registry, Some("chat"), None, now);
+        assert_eq!(peers, vec![b.clone()]);
+        let (peers, _) = discover(&mut registry, None, None, now + Duration::from_secs(601));
+        assert_eq!(peers, vec![b]);
+    }
+
+    #[test]
+    fn invalid_registrations() {
+        let mut registry = RendezvousRegistry::new(RegistryConfig::new().with_ttl_range(60, 120));
+        let now = Instant::now();
+        let a = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let b = PublicKey::Ed25519(vec![2; 32]).into_peer_id();
+
+        let cases = vec![
+            (register(&b, "chat", Some(60)), ErrorCode::NotAuthorized),
+            (register(&a, "", Some(60)), ErrorCode::InvalidNamespace),
+            (register(&a, "chat", Some(10)), ErrorCode::InvalidTtl),
+            (register(&a, "chat", None), ErrorCode::InvalidTtl),
+        ];
+        for (request, expected) in cases {
+            match registry.handle_request(&a, request, now) {
+                RendezvousResponse::Error { status, .. } => assert_eq!(status, expected),
+                _ => panic!(),
+            }
+        }
+        assert!(registry.registrations(now).is_empty());
+    }
+}
@@ -0,0 +1,540 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use codec::RequestResponseCodec;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use handler::{RequestResponseHandler, RequestResponseHandlerEvent, RequestResponseHandlerIn};
+use libp2p_core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::nodes::swarm::ConnectedPoint;
+use libp2p_core::PeerId;
+use std::collections::{HashSet, VecDeque};
+use std::io::Error as IoError;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{error, fmt, time::Duration};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Identifier of a request. Unique among all the requests, inbound and outbound, of a
+/// `RequestResponse` behaviour.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(pub(crate) usize);
+
+impl fmt::Display for RequestId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Configuration of a `RequestResponse` behaviour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestResponseConfig {
+    /// Maximum duration of a request, from the opening of the substream until the response has
+    /// been received. Also applies to the time we have to answer a request of the remote.
+    pub(crate) request_timeout: Duration,
+    /// Duration after which a connection without any request in progress is closed.
+    pub(crate) idle_timeout: Duration,
+    /// Maximum number of requests in progress at the same time, per peer and per direction.
+    pub(crate) max_in_flight: usize,
+}
+
+impl RequestResponseConfig {
+    /// Builds the default configuration. Requests time out after 10 seconds, idle connections
+    /// are closed after 10 seconds, and up to 16 requests can be in progress per peer.
+    #[inline]
+    pub fn new() -> RequestResponseConfig {
+        RequestResponseConfig {
+            request_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(10),
+            max_in_flight: 16,
+        }
+    }
+
+    /// Sets the maximum duration of a request.
+    #[inline]
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets the duration after which a connection without any request in progress is closed.
+    #[inline]
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of requests in progress per peer.
+    ///
+    /// Outbound requests above this limit are queued until a previous request finishes. Inbound
+    /// requests above this limit are refused by closing their substream.
+    ///
+    /// A value of `0` is treated as `1`.
+    #[inline]
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = if max == 0 { 1 } else { max };
+        self
+    }
+}
+
+impl Default for RequestResponseConfig {
+    #[inline]
+    fn default() -> Self {
+        RequestResponseConfig::new()
+    }
+}
+
+/// Reason why one of our requests failed.
+#[derive(Debug)]
+pub enum OutboundFailure {
+    /// We are not connected to the peer.
+    NotConnected,
+    /// The connection closed before the response was received.
+    ConnectionClosed,
+    /// The response wasn't received in time.
+    Timeout,
+    /// Error while opening the substream or exchanging the messages.
+    Io(IoError),
+}
+
+impl fmt::Display for OutboundFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            OutboundFailure::NotConnected => write!(f, "not connected to the peer"),
+            OutboundFailure::ConnectionClosed => write!(f, "connection closed before the response"),
+            OutboundFailure::Timeout => write!(f, "request timed out"),
+            OutboundFailure::Io(ref err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl error::Error for OutboundFailure {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            OutboundFailure::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Reason why we failed to answer a request.
+#[derive(Debug)]
+pub enum InboundFailure {
+    /// The connection closed before the response was sent.
+    ConnectionClosed,
+    /// No response was provided in time, or writing it took too long.
+    Timeout,
+    /// Error while writing the response.
+    Io(IoError),
+}
+
+impl fmt::Display for InboundFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            InboundFailure::ConnectionClosed => write!(f, "connection closed before the response"),
+            InboundFailure::Timeout => write!(f, "response timed out"),
+            InboundFailure::Io(ref err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl error::Error for InboundFailure {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            InboundFailure::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Event generated by the `RequestResponse` behaviour.
+pub enum RequestResponseEvent<TRequest, TResponse> {
+    /// A peer sent us a request. Answer it with `send_response`.
+    Request {
+        peer_id: PeerId,
+        request_id: RequestId,
+        request: TRequest,
+    },
+    /// A peer answered one of our requests.
+    Response {
+        peer_id: PeerId,
+        request_id: RequestId,
+        response: TResponse,
+    },
+    /// One of our requests failed. No response will be received for it.
+    OutboundFailure {
+        peer_id: PeerId,
+        request_id: RequestId,
+        error: OutboundFailure,
+    },
+    /// We failed to answer a request of a peer.
+    InboundFailure {
+        peer_id: PeerId,
+        request_id: RequestId,
+        error: InboundFailure,
+    },
+    /// A response has been sent to a peer.
+    ResponseSent {
+        peer_id: PeerId,
+        request_id: RequestId,
+    },
+}
+
+/// Requests in progress with a peer.
+struct PeerState<TRequest> {
+    /// Outbound requests that have been passed to the handler.
+    in_flight: HashSet<RequestId>,
+    /// Outbound requests waiting for a slot because of the in-flight limit.
+    queued: VecDeque<(RequestId, TRequest)>,
+}
+
+/// `NetworkBehaviour` that sends requests to peers and answers their requests, with the messages
+/// encoded by a `RequestResponseCodec`.
+pub struct RequestResponse<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    codec: TCodec,
+    config: RequestResponseConfig,
+    /// Generator of request ids. Shared with the handlers, which assign the ids of the inbound
+    /// requests.
+    next_request_id: Arc<AtomicUsize>,
+    /// Peers we are connected to, with the outbound requests in progress.
+    connected: FnvHashMap<PeerId, PeerState<TCodec::Request>>,
+    /// Peer that sent each inbound request we haven't answered yet.
+    inbound: FnvHashMap<RequestId, PeerId>,
+    /// Actions to return from `poll`.
+    actions: VecDeque<NetworkBehaviourAction<RequestResponseHandlerIn<TCodec>, RequestResponseEvent<TCodec::Request, TCodec::Response>>>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream, TCodec> RequestResponse<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Creates a new `RequestResponse` behaviour that uses `codec` for its messages.
+    pub fn new(codec: TCodec, config: RequestResponseConfig) -> Self {
+        RequestResponse {
+            codec,
+            config,
+            next_request_id: Arc::new(AtomicUsize::new(0)),
+            connected: FnvHashMap::default(),
+            inbound: FnvHashMap::default(),
+            actions: VecDeque::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Sends a request to a peer. The outcome is reported as either a `Response` or an
+    /// `OutboundFailure` event with the returned id.
+    ///
+    /// > **Note**: This method doesn't dial the peer. If we are not connected to it, the request
+    /// >           fails with `OutboundFailure::NotConnected`.
+    pub fn send_request(&mut self, peer_id: &PeerId, request: TCodec::Request) -> RequestId {
+        let request_id = RequestId(self.next_request_id.fetch_add(1, Ordering::Relaxed));
+
+        let state = match self.connected.get_mut(peer_id) {
+            Some(state) => state,
+            None => {
+                self.actions.push_back(NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundFailure {
+                    peer_id: peer_id.clone(),
+                    request_id,
+                    error: OutboundFailure::NotConnected,
+                }));
+                return request_id;
+            },
+        };
+
+        if state.in_flight.len() < self.config.max_in_flight {
+            state.in_flight.insert(request_id);
+            self.actions.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id: peer_id.clone(),
+                event: RequestResponseHandlerIn::Request { request_id, request },
+            });
+        } else {
+            state.queued.push_back((request_id, request));
+        }
+
+        request_id
+    }
+
+    /// Answers a request received from a peer.
+    ///
+    /// Returns back the response if the request is unknown, which happens if it has already been
+    /// answered, if it timed out or if the connection closed.
+    pub fn send_response(&mut self, request_id: RequestId, response: TCodec::Response)
+        -> Result<(), TCodec::Response>
+    {
+        let peer_id = match self.inbound.remove(&request_id) {
+            Some(peer_id) => peer_id,
+            None => return Err(response),
+        };
+
+        self.actions.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id,
+            event: RequestResponseHandlerIn::Response { request_id, response },
+        });
+        Ok(())
+    }
+
+    /// Returns the number of outbound requests in progress with a peer, including the queued
+    /// ones.
+    pub fn pending_requests(&self, peer_id: &PeerId) -> usize {
+        self.connected.get(peer_id)
+            .map(|state| state.in_flight.len() + state.queued.len())
+            .unwrap_or(0)
+    }
+
+    /// Marks an outbound request as finished and sends the next queued request to the peer.
+    fn outbound_finished(&mut self, peer_id: &PeerId, request_id: RequestId) {
+        let state = match self.connected.get_mut(peer_id) {
+            Some(state) => state,
+            None => return,
+        };
+
+        state.in_flight.remove(&request_id);
+        if state.in_flight.len() < self.config.max_in_flight {
+            if let Some((request_id, request)) = state.queued.pop_front() {
+                state.in_flight.insert(request_id);
+                self.actions.push_back(NetworkBehaviourAction::SendEvent {
+                    peer_id: peer_id.clone(),
+                    event: RequestResponseHandlerIn::Request { request_id, request },
+                });
+            }
+        }
+    }
+}
+
+impl<TSubstream, TCodec> fmt::Debug for RequestResponse<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RequestResponse")
+            .field("config", &self.config)
+            .field("connected", &self.connected.len())
+            .field("inbound", &self.inbound.len())
+            .finish()
+    }
+}
+
+impl<TSubstream, TCodec> NetworkBehaviour for RequestResponse<TSubstream, TCodec>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+    TCodec: RequestResponseCodec,
+{
+    type ConnectionHandler = RequestResponseHandler<TSubstream, TCodec>;
+    type OutEvent = RequestResponseEvent<TCodec::Request, TCodec::Response>;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        RequestResponseHandler::new(self.codec.clone(), self.config.clone(), self.next_request_id.clone())
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+        self.connected.entry(peer_id).or_insert_with(|| PeerState {
+            in_flight: HashSet::new(),
+            queued: VecDeque::new(),
+        });
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        if let Some(state) = self.connected.remove(peer_id) {
+            let failed = state.in_flight.into_iter()
+                .chain(state.queued.into_iter().map(|(id, _)| id));
+            for request_id in failed {
+                self.actions.push_back(NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundFailure {
+                    peer_id: peer_id.clone(),
+                    request_id,
+                    error: OutboundFailure::ConnectionClosed,
+                }));
+            }
+        }
+
+        let unanswered = self.inbound.iter()
+            .filter(|&(_, p)| p == peer_id)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for request_id in unanswered {
+            self.inbound.remove(&request_id);
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::InboundFailure {
+                peer_id: peer_id.clone(),
+                request_id,
+                error: InboundFailure::ConnectionClosed,
+            }));
+        }
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, event: RequestResponseHandlerEvent<TCodec>) {
+        let event = match event {
+            RequestResponseHandlerEvent::Request { request_id, request } => {
+                self.inbound.insert(request_id, peer_id.clone());
+                RequestResponseEvent::Request { peer_id, request_id, request }
+            },
+            RequestResponseHandlerEvent::Response { request_id, response } => {
+                self.outbound_finished(&peer_id, request_id);
+                RequestResponseEvent::Response { peer_id, request_id, response }
+            },
+            RequestResponseHandlerEvent::OutboundFailure { request_id, error } => {
+                self.outbound_finished(&peer_id, request_id);
+                RequestResponseEvent::OutboundFailure { peer_id, request_id, error }
+            },
+            RequestResponseHandlerEvent::InboundFailure { request_id, error } => {
+                self.inbound.remove(&request_id);
+                RequestResponseEvent::InboundFailure { peer_id, request_id, error }
+            },
+            RequestResponseHandlerEvent::ResponseSent { request_id } => {
+                RequestResponseEvent::ResponseSent { peer_id, request_id }
+            },
+        };
+
+        self.actions.push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(&mut self, _: &mut PollParameters)
+        -> Async<NetworkBehaviourAction<RequestResponseHandlerIn<TCodec>, Self::OutEvent>>
+    {
+        match self.actions.pop_front() {
+            Some(action) => Async::Ready(action),
+            None => Async::NotReady,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use libp2p_core::{Multiaddr, PublicKey};
+    use std::io::Cursor;
+    use tokio_io::io;
+
+    /// Codec whose messages are a single byte.
+    #[derive(Debug, Clone)]
+    struct ByteCodec;
+
+    impl RequestResponseCodec for ByteCodec {
+        type Request = u8;
+        type Response = u8;
+
+        fn protocol_name(&self) -> Bytes {
+            Bytes::from("/test/1.0.0")
+        }
+
+        fn read_request<T>(&self, io: T) -> Box<Future<Item = (u8, T), Error = IoError> + Send>
+        where T: AsyncRead + Send + 'static {
+            Box::new(io::read_exact(io, [0]).map(|(io, buf)| (buf[0], io)))
+        }
+
+        fn read_response<T>(&self, io: T) -> Box<Future<Item = u8, Error = IoError> + Send>
+        where T: AsyncRead + Send + 'static {
+            Box::new(io::read_exact(io, [0]).map(|(_, buf)| buf[0]))
+        }
+
+        fn write_request<T>(&self, io: T, request: u8) -> Box<Future<Item = T, Error = IoError> + Send>
+        where T: AsyncWrite + Send + 'static {
+            Box::new(io::write_all(io, [request]).map(|(io, _)| io))
+        }
+
+        fn write_response<T>(&self, io: T, response: u8) -> Box<Future<Item = T, Error = IoError> + Send>
+        where T: AsyncWrite + Send + 'static {
+            Box::new(io::write_all(io, [response]).map(|(io, _)| io))
+        }
+    }
+
+    type TestBehaviour = RequestResponse<Cursor<Vec<u8>>, ByteCodec>;
+
+    fn dialer() -> ConnectedPoint {
+        ConnectedPoint::Dialer { address: "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap() }
+    }
+
+    fn sent_requests(behaviour: &mut TestBehaviour) -> Vec<RequestId> {
+        behaviour.actions.drain(..).filter_map(|action| match action {
+            NetworkBehaviourAction::SendEvent { event: RequestResponseHandlerIn::Request { request_id, .. }, .. } => Some(request_id),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn not_connected() {
+        let mut behaviour = TestBehaviour::new(ByteCodec, RequestResponseConfig::new());
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let request_id = behaviour.send_request(&peer_id, 5);
+        match behaviour.actions.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundFailure {
+                request_id: id, error: OutboundFailure::NotConnected, ..
+            })) => assert_eq!(id, request_id),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn in_flight_limit() {
+        let config = RequestResponseConfig::new().with_max_in_flight(2);
+        let mut behaviour = TestBehaviour::new(ByteCodec, config);
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        behaviour.inject_connected(peer_id.clone(), dialer());
+
+        let ids = (0 .. 3).map(|n| behaviour.send_request(&peer_id, n)).collect::<Vec<_>>();
+        assert_eq!(sent_requests(&mut behaviour), vec![ids[0], ids[1]]);
+        assert_eq!(behaviour.pending_requests(&peer_id), 3);
+
+        // The queued request is sent as soon as a slot is free.
+        behaviour.inject_node_event(peer_id.clone(), RequestResponseHandlerEvent::Response {
+            request_id: ids[0],
+            response: 0,
+        });
+        assert_eq!(sent_requests(&mut behaviour), vec![ids[2]]);
+        assert_eq!(behaviour.pending_requests(&peer_id), 2);
+    }
+
+    #[test]
+    fn responses_and_disconnection() {
+        let mut behaviour = TestBehaviour::new(ByteCodec, RequestResponseConfig::new());
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        behaviour.inject_connected(peer_id.clone(), dialer());
+
+        let inbound = RequestId(behaviour.next_request_id.fetch_add(1, Ordering::Relaxed));
+        behaviour.inject_node_event(peer_id.clone(), RequestResponseHandlerEvent::Request {
+            request_id: inbound,
+            request: 7,
+        });
+        let outbound = behaviour.send_request(&peer_id, 8);
+        assert_ne!(inbound, outbound);
+        behaviour.actions.clear();
+
+        assert!(behaviour.send_response(inbound, 9).is_ok());
+        assert_eq!(behaviour.send_response(inbound, 9), Err(9));
+
+        // Closing the connection fails the outbound request.
+        behaviour.actions.clear();
+        behaviour.inject_disconnected(&peer_id, dialer());
+        match behaviour.actions.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundFailure {
+                request_id, error: OutboundFailure::ConnectionClosed, ..
+            })) => assert_eq!(request_id, outbound),
+            _ => panic!(),
+        }
+        assert!(behaviour.actions.is_empty());
+    }
+}
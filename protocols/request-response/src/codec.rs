@@ -0,0 +1,66 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use futures::Future;
+use std::io::Error as IoError;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Encodes and decodes the requests and responses of a request/response protocol.
+///
+/// Each request is sent on a new substream. The dialer writes the request then reads the
+/// response, while the listener reads the request then writes the response. The codec is only
+/// responsible for the format of the messages on the substream ; in particular, it must be able
+/// to determine where a message ends without relying on the substream being closed.
+///
+/// > **Note**: The substreams are controlled by the remote. Implementations should put an upper
+/// >           bound on the size of the messages they read.
+pub trait RequestResponseCodec: Clone + Send + 'static {
+    /// Request sent by the dialer of a substream.
+    type Request: Send + 'static;
+    /// Response sent back by the listener.
+    type Response: Send + 'static;
+
+    /// Name of the protocol to negotiate on the substreams.
+    fn protocol_name(&self) -> Bytes;
+
+    /// Reads a request from the substream. Returns the substream alongside with the request, so
+    /// that the response can be written on it afterwards.
+    fn read_request<T>(&self, io: T) -> Box<Future<Item = (Self::Request, T), Error = IoError> + Send>
+    where
+        T: AsyncRead + Send + 'static;
+
+    /// Reads a response from the substream.
+    fn read_response<T>(&self, io: T) -> Box<Future<Item = Self::Response, Error = IoError> + Send>
+    where
+        T: AsyncRead + Send + 'static;
+
+    /// Writes a request on the substream. Flushing the substream is handled by the caller.
+    fn write_request<T>(&self, io: T, request: Self::Request) -> Box<Future<Item = T, Error = IoError> + Send>
+    where
+        T: AsyncWrite + Send + 'static;
+
+    /// Writes a response on the substream. Flushing and closing the substream is handled by the
+    /// caller.
+    fn write_response<T>(&self, io: T, response: Self::Response) -> Box<Future<Item = T, Error = IoError> + Send>
+    where
+        T: AsyncWrite + Send + 'static;
+}
@@ -0,0 +1,316 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use codec::RequestResponseCodec;
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use libp2p_core::nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use libp2p_core::nodes::handled_node::NodeHandlerEndpoint;
+use protocol::{map_timeout_err, RequestResponseOutput, RequestResponseProtocol};
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio_io::{io, AsyncRead, AsyncWrite};
+use tokio_timer::{Delay, Timeout};
+use {InboundFailure, OutboundFailure, RequestId, RequestResponseConfig};
+
+/// Event sent from the `RequestResponse` behaviour to its handler.
+pub enum RequestResponseHandlerIn<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Send a request to the remote.
+    Request {
+        request_id: RequestId,
+        request: TCodec::Request,
+    },
+    /// Answer a request previously received from the remote.
+    Response {
+        request_id: RequestId,
+        response: TCodec::Response,
+    },
+}
+
+/// Event produced by the handler of the `RequestResponse` behaviour.
+pub enum RequestResponseHandlerEvent<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// The remote sent a request.
+    Request {
+        request_id: RequestId,
+        request: TCodec::Request,
+    },
+    /// The remote answered one of our requests.
+    Response {
+        request_id: RequestId,
+        response: TCodec::Response,
+    },
+    /// A response has been fully written on its substream.
+    ResponseSent {
+        request_id: RequestId,
+    },
+    /// One of our requests failed.
+    OutboundFailure {
+        request_id: RequestId,
+        error: OutboundFailure,
+    },
+    /// We failed to answer a request of the remote.
+    InboundFailure {
+        request_id: RequestId,
+        error: InboundFailure,
+    },
+}
+
+/// Connection handler of the `RequestResponse` behaviour.
+pub struct RequestResponseHandler<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    codec: TCodec,
+    config: RequestResponseConfig,
+    /// Generator of the ids of the inbound requests. Shared with the behaviour.
+    next_request_id: Arc<AtomicUsize>,
+    /// Requests waiting for an outbound substream to be opened.
+    pending_outbound: VecDeque<(RequestId, TCodec::Request)>,
+    /// Number of outbound substreams being opened or negotiated.
+    outbound_in_progress: usize,
+    /// Inbound requests waiting for the behaviour to provide a response, with their deadline.
+    pending_inbound: FnvHashMap<RequestId, (TSubstream, Delay)>,
+    /// Responses being written.
+    sending: Vec<(RequestId, Timeout<Box<Future<Item = (), Error = IoError> + Send>>)>,
+    /// Events to report to the behaviour.
+    events: VecDeque<RequestResponseHandlerEvent<TCodec>>,
+    /// Last moment when a request was in progress.
+    last_active: Instant,
+    /// True if `shutdown()` has been called.
+    shutting_down: bool,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream, TCodec> RequestResponseHandler<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    pub(crate) fn new(codec: TCodec, config: RequestResponseConfig, next_request_id: Arc<AtomicUsize>) -> Self {
+        RequestResponseHandler {
+            codec,
+            config,
+            next_request_id,
+            pending_outbound: VecDeque::new(),
+            outbound_in_progress: 0,
+            pending_inbound: FnvHashMap::default(),
+            sending: Vec::new(),
+            events: VecDeque::new(),
+            last_active: Instant::now(),
+            shutting_down: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns true if there is no request in progress in either direction.
+    fn is_idle(&self) -> bool {
+        self.pending_outbound.is_empty() && self.outbound_in_progress == 0 &&
+            self.pending_inbound.is_empty() && self.sending.is_empty()
+    }
+}
+
+impl<TSubstream, TCodec> Clone for RequestResponseHandler<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        RequestResponseHandler::new(self.codec.clone(), self.config.clone(), self.next_request_id.clone())
+    }
+}
+
+impl<TSubstream, TCodec> ConnectionHandler for RequestResponseHandler<TSubstream, TCodec>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+    TCodec: RequestResponseCodec,
+{
+    type InEvent = RequestResponseHandlerIn<TCodec>;
+    type OutEvent = RequestResponseHandlerEvent<TCodec>;
+    type Substream = TSubstream;
+    type Protocol = RequestResponseProtocol<TCodec>;
+    type OutboundOpenInfo = RequestId;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        RequestResponseProtocol::Inbound {
+            codec: self.codec.clone(),
+            timeout: self.config.request_timeout,
+        }
+    }
+
+    fn inject_fully_negotiated(
+        &mut self,
+        output: RequestResponseOutput<TCodec::Request, TCodec::Response, TSubstream>,
+        endpoint: NodeHandlerEndpoint<RequestId>,
+    ) {
+        match (output, endpoint) {
+            (RequestResponseOutput::Request(request, substream), NodeHandlerEndpoint::Listener) => {
+                if self.pending_inbound.len() + self.sending.len() >= self.config.max_in_flight {
+                    warn!("Dropping inbound request ; too many requests in progress");
+                    return;
+                }
+
+                let request_id = RequestId(self.next_request_id.fetch_add(1, Ordering::Relaxed));
+                let deadline = Delay::new(Instant::now() + self.config.request_timeout);
+                self.pending_inbound.insert(request_id, (substream, deadline));
+                self.events.push_back(RequestResponseHandlerEvent::Request { request_id, request });
+            },
+            (RequestResponseOutput::Response(response), NodeHandlerEndpoint::Dialer(request_id)) => {
+                self.outbound_in_progress -= 1;
+                self.events.push_back(RequestResponseHandlerEvent::Response { request_id, response });
+            },
+            (RequestResponseOutput::Request(..), NodeHandlerEndpoint::Dialer(_)) |
+            (RequestResponseOutput::Response(_), NodeHandlerEndpoint::Listener) => {
+                unreachable!("The inbound upgrade only produces requests and the outbound upgrade \
+                              only produces responses")
+            },
+        }
+    }
+
+    fn inject_event(&mut self, event: RequestResponseHandlerIn<TCodec>) {
+        match event {
+            RequestResponseHandlerIn::Request { request_id, request } => {
+                self.pending_outbound.push_back((request_id, request));
+            },
+            RequestResponseHandlerIn::Response { request_id, response } => {
+                let substream = match self.pending_inbound.remove(&request_id) {
+                    Some((substream, _)) => substream,
+                    None => {
+                        debug!("Response to unknown or expired request {:?}", request_id);
+                        return;
+                    },
+                };
+
+                let fut = self.codec.write_response(substream, response)
+                    .and_then(|substream| io::flush(substream))
+                    .and_then(|substream| io::shutdown(substream))
+                    .map(|_| ());
+                let fut = Box::new(fut) as Box<Future<Item = _, Error = _> + Send>;
+                self.sending.push((request_id, Timeout::new(fut, self.config.request_timeout)));
+            },
+        }
+    }
+
+    fn inject_dial_upgrade_error(&mut self, request_id: RequestId, error: &IoError) {
+        self.outbound_in_progress -= 1;
+        let error = if error.kind() == IoErrorKind::TimedOut {
+            OutboundFailure::Timeout
+        } else {
+            OutboundFailure::Io(IoError::new(error.kind(), error.to_string()))
+        };
+        self.events.push_back(RequestResponseHandlerEvent::OutboundFailure { request_id, error });
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.is_idle() {
+            KeepAlive::Until(self.last_active + self.config.idle_timeout)
+        } else {
+            KeepAlive::Forever
+        }
+    }
+
+    #[inline]
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+        for (request_id, _) in self.pending_outbound.drain(..) {
+            let error = OutboundFailure::ConnectionClosed;
+            self.events.push_back(RequestResponseHandlerEvent::OutboundFailure { request_id, error });
+        }
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, RequestId, Self::OutEvent>>, IoError> {
+        // Drive the responses being written.
+        for n in (0 .. self.sending.len()).rev() {
+            let (request_id, mut fut) = self.sending.swap_remove(n);
+            match fut.poll().map_err(map_timeout_err) {
+                Ok(Async::Ready(())) => {
+                    self.events.push_back(RequestResponseHandlerEvent::ResponseSent { request_id });
+                },
+                Ok(Async::NotReady) => self.sending.push((request_id, fut)),
+                Err(err) => {
+                    let error = if err.kind() == IoErrorKind::TimedOut {
+                        InboundFailure::Timeout
+                    } else {
+                        InboundFailure::Io(err)
+                    };
+                    self.events.push_back(RequestResponseHandlerEvent::InboundFailure { request_id, error });
+                },
+            }
+        }
+
+        // Expire the inbound requests that haven't been answered in time.
+        let mut expired = Vec::new();
+        for (request_id, &mut (_, ref mut deadline)) in self.pending_inbound.iter_mut() {
+            match deadline.poll() {
+                Ok(Async::NotReady) => (),
+                Ok(Async::Ready(())) => expired.push((*request_id, InboundFailure::Timeout)),
+                Err(err) => {
+                    let err = IoError::new(IoErrorKind::Other, err);
+                    expired.push((*request_id, InboundFailure::Io(err)));
+                },
+            }
+        }
+        for (request_id, error) in expired {
+            self.pending_inbound.remove(&request_id);
+            self.events.push_back(RequestResponseHandlerEvent::InboundFailure { request_id, error });
+        }
+
+        if !self.is_idle() {
+            self.last_active = Instant::now();
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(event))));
+        }
+
+        if !self.shutting_down {
+            if let Some((request_id, request)) = self.pending_outbound.pop_front() {
+                self.outbound_in_progress += 1;
+                let upgrade = RequestResponseProtocol::outbound(self.codec.clone(),
+                                                                self.config.request_timeout,
+                                                                request);
+                return Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                    upgrade,
+                    info: request_id,
+                })));
+            }
+        }
+
+        if self.shutting_down && self.is_idle() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
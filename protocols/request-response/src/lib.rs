@@ -0,0 +1,63 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Generic request/response protocol.
+//!
+//! Many protocols consist in sending a request to a peer and waiting for its response. This
+//! crate provides the `RequestResponse` behaviour, which takes care of opening a substream for
+//! each request, of matching the responses with the requests, of timing out the requests, and of
+//! limiting the number of requests in progress with each peer. The format of the messages is
+//! provided by the user through the `RequestResponseCodec` trait.
+//!
+//! # Usage
+//!
+//! Implement `RequestResponseCodec` for your protocol, then create a `RequestResponse` behaviour
+//! and pass it to the swarm.
+//!
+//! - Call `send_request` to send a request to a peer we are connected to. The behaviour then
+//!   generates either a `RequestResponseEvent::Response` or a
+//!   `RequestResponseEvent::OutboundFailure` with the id returned by `send_request`.
+//! - When a peer sends us a request, the behaviour generates a `RequestResponseEvent::Request`.
+//!   Answer it with `send_response` before the request times out.
+//!
+//! Requests that exceed the in-flight limit of `RequestResponseConfig` are queued and sent once
+//! a previous request to the same peer has finished.
+
+extern crate bytes;
+extern crate fnv;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate parking_lot;
+extern crate tokio_io;
+extern crate tokio_timer;
+
+pub use self::behaviour::{InboundFailure, OutboundFailure, RequestId, RequestResponse};
+pub use self::behaviour::{RequestResponseConfig, RequestResponseEvent};
+pub use self::codec::RequestResponseCodec;
+pub use self::handler::{RequestResponseHandler, RequestResponseHandlerEvent, RequestResponseHandlerIn};
+pub use self::protocol::{RequestResponseOutput, RequestResponseProtocol};
+
+mod behaviour;
+mod codec;
+mod handler;
+mod protocol;
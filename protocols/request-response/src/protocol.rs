@@ -0,0 +1,178 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use codec::RequestResponseCodec;
+use futures::{future, prelude::*};
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr};
+use parking_lot::Mutex;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use std::{fmt, iter, time::Duration};
+use tokio_io::{io, AsyncRead, AsyncWrite};
+use tokio_timer::{timeout::Error as TimeoutError, Timeout};
+
+/// Upgrade used by the request/response handler.
+///
+/// As a listener, reads the request of the remote. As a dialer, writes a request and reads the
+/// response. In both cases, the exchange fails with an error of kind `TimedOut` if it doesn't
+/// finish within the timeout.
+pub enum RequestResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Accepts a request from the remote.
+    Inbound {
+        codec: TCodec,
+        timeout: Duration,
+    },
+    /// Sends a request to the remote.
+    Outbound {
+        codec: TCodec,
+        timeout: Duration,
+        /// The request to send. Shared between the clones of the upgrade so that `TCodec::Request`
+        /// doesn't need to implement `Clone`. Only the first upgrade to be applied sends it.
+        request: Arc<Mutex<Option<TCodec::Request>>>,
+    },
+}
+
+impl<TCodec> RequestResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Builds an upgrade that sends `request` to the remote.
+    pub fn outbound(codec: TCodec, timeout: Duration, request: TCodec::Request) -> Self {
+        RequestResponseProtocol::Outbound {
+            codec,
+            timeout,
+            request: Arc::new(Mutex::new(Some(request))),
+        }
+    }
+
+    /// Returns the codec of the upgrade.
+    fn codec(&self) -> &TCodec {
+        match *self {
+            RequestResponseProtocol::Inbound { ref codec, .. } => codec,
+            RequestResponseProtocol::Outbound { ref codec, .. } => codec,
+        }
+    }
+}
+
+impl<TCodec> Clone for RequestResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    fn clone(&self) -> Self {
+        match *self {
+            RequestResponseProtocol::Inbound { ref codec, timeout } => {
+                RequestResponseProtocol::Inbound { codec: codec.clone(), timeout }
+            },
+            RequestResponseProtocol::Outbound { ref codec, timeout, ref request } => {
+                RequestResponseProtocol::Outbound {
+                    codec: codec.clone(),
+                    timeout,
+                    request: request.clone(),
+                }
+            },
+        }
+    }
+}
+
+impl<TCodec> fmt::Debug for RequestResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RequestResponseProtocol::Inbound { timeout, .. } => {
+                f.debug_struct("Inbound").field("timeout", &timeout).finish()
+            },
+            RequestResponseProtocol::Outbound { timeout, .. } => {
+                f.debug_struct("Outbound").field("timeout", &timeout).finish()
+            },
+        }
+    }
+}
+
+/// Output of the `RequestResponseProtocol`.
+pub enum RequestResponseOutput<TRequest, TResponse, TSubstream> {
+    /// We are the listener and received a request. The response must be written on the
+    /// substream.
+    Request(TRequest, TSubstream),
+    /// We are the dialer and received the response to our request.
+    Response(TResponse),
+}
+
+impl<TCodec, TSubstream> ConnectionUpgrade<TSubstream> for RequestResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((self.codec().protocol_name(), ()))
+    }
+
+    type Output = RequestResponseOutput<TCodec::Request, TCodec::Response, TSubstream>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    fn upgrade(self, socket: TSubstream, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        let (exchange, timeout) = match self {
+            RequestResponseProtocol::Inbound { codec, timeout } => {
+                let fut = codec.read_request(socket)
+                    .map(|(request, socket)| RequestResponseOutput::Request(request, socket));
+                (Box::new(fut) as Box<Future<Item = _, Error = _> + Send>, timeout)
+            },
+            RequestResponseProtocol::Outbound { codec, timeout, request } => {
+                let request = match request.lock().take() {
+                    Some(request) => request,
+                    None => {
+                        let err = IoError::new(IoErrorKind::Other, "request already sent");
+                        return Box::new(future::err(err));
+                    },
+                };
+
+                let fut = codec.write_request(socket, request)
+                    .and_then(|socket| io::flush(socket))
+                    .and_then(move |socket| codec.read_response(socket))
+                    .map(RequestResponseOutput::Response);
+                (Box::new(fut) as Box<Future<Item = _, Error = _> + Send>, timeout)
+            },
+        };
+
+        Box::new(Timeout::new(exchange, timeout).map_err(map_timeout_err))
+    }
+}
+
+/// Converts an error of a `Timeout` into an `IoError`.
+pub(crate) fn map_timeout_err(err: TimeoutError<IoError>) -> IoError {
+    if err.is_inner() {
+        err.into_inner().expect("ensured by is_inner()")
+    } else if err.is_elapsed() {
+        IoErrorKind::TimedOut.into()
+    } else {
+        let err = err.into_timer().expect("ensured by is_timer()");
+        IoError::new(IoErrorKind::Other, err)
+    }
+}
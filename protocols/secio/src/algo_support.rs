@@ -30,17 +30,22 @@ use stream_cipher::Cipher;
 
 const ECDH_P256: &str = "P-256";
 const ECDH_P384: &str = "P-384";
+const X25519: &str = "X25519";
 
 const AES_128: &str = "AES-128";
 const AES_256: &str = "AES-256";
 const TWOFISH_CTR: &str = "TwofishCTR";
+const AES_128_GCM: &str = "AES-128-GCM";
+const AES_256_GCM: &str = "AES-256-GCM";
+const CHACHA20_POLY1305: &str = "ChaCha20-Poly1305";
 const NULL: &str = "NULL";
 
 const SHA_256: &str = "SHA256";
 const SHA_512: &str = "SHA512";
 
-pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "P-256,P-384";
-pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str = "AES-128,AES-256,TwofishCTR";
+pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "P-256,P-384,X25519";
+pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str =
+    "AES-128,AES-256,TwofishCTR,AES-128-GCM,AES-256-GCM,ChaCha20-Poly1305";
 pub(crate) const DEFAULT_DIGESTS_PROPOSITION: &str = "SHA256,SHA512";
 
 
@@ -48,7 +53,8 @@ pub(crate) const DEFAULT_DIGESTS_PROPOSITION: &str = "SHA256,SHA512";
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KeyAgreement {
     EcdhP256,
-    EcdhP384
+    EcdhP384,
+    X25519,
 }
 
 /// Return a proposition string from the given sequence of `KeyAgreement` values.
@@ -67,6 +73,10 @@ where
                 s.push_str(ECDH_P384);
                 s.push(',')
             }
+            KeyAgreement::X25519 => {
+                s.push_str(X25519);
+                s.push(',')
+            }
         }
     }
     s.pop(); // remove trailing comma if any
@@ -87,6 +97,7 @@ pub fn select_agreement<'a>(r: Ordering, ours: &str, theirs: &str) -> Result<&'a
             match x {
                 ECDH_P256 => return Ok(&agreement::ECDH_P256),
                 ECDH_P384 => return Ok(&agreement::ECDH_P384),
+                X25519 => return Ok(&agreement::X25519),
                 _ => continue
             }
         }
@@ -115,6 +126,18 @@ where
                 s.push_str(TWOFISH_CTR);
                 s.push(',')
             }
+            Cipher::Aes128Gcm => {
+                s.push_str(AES_128_GCM);
+                s.push(',')
+            }
+            Cipher::Aes256Gcm => {
+                s.push_str(AES_256_GCM);
+                s.push(',')
+            }
+            Cipher::ChaCha20Poly1305 => {
+                s.push_str(CHACHA20_POLY1305);
+                s.push(',')
+            }
             Cipher::Null => {
                 s.push_str(NULL);
                 s.push(',')
@@ -140,6 +163,9 @@ pub fn select_cipher(r: Ordering, ours: &str, theirs: &str) -> Result<Cipher, Se
                 AES_128 => return Ok(Cipher::Aes128),
                 AES_256 => return Ok(Cipher::Aes256),
                 TWOFISH_CTR => return Ok(Cipher::TwofishCtr),
+                AES_128_GCM => return Ok(Cipher::Aes128Gcm),
+                AES_256_GCM => return Ok(Cipher::Aes256Gcm),
+                CHACHA20_POLY1305 => return Ok(Cipher::ChaCha20Poly1305),
                 NULL => return Ok(Cipher::Null),
                 _ => continue
             }
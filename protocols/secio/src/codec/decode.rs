@@ -21,7 +21,7 @@
 //! Individual messages decoding.
 
 use bytes::BytesMut;
-use super::StreamCipher;
+use super::{NonceSequence, StreamCipher};
 
 use error::SecioError;
 use futures::sink::Sink;
@@ -29,7 +29,7 @@ use futures::stream::Stream;
 use futures::Async;
 use futures::Poll;
 use futures::StartSend;
-use ring::hmac;
+use ring::{aead, hmac};
 
 /// Wraps around a `Stream<Item = BytesMut>`. The buffers produced by the underlying stream
 /// are decoded using the cipher and hmac.
@@ -40,13 +40,26 @@ use ring::hmac;
 ///
 /// Also implements `Sink` for convenience.
 pub struct DecoderMiddleware<S> {
-    cipher_state: StreamCipher,
-    hmac_key: hmac::VerificationKey,
-    // TODO: when a new version of ring is released, we can use `hmac_key.digest_algorithm().output_len` instead
-    hmac_num_bytes: usize,
+    decryption: Decryption,
     raw_stream: S,
 }
 
+/// How the frames are decrypted.
+enum Decryption {
+    /// Stream cipher, with the frames followed with an HMAC of the encrypted data.
+    Stream {
+        cipher_state: StreamCipher,
+        hmac_key: hmac::VerificationKey,
+        // TODO: when a new version of ring is released, we can use `hmac_key.digest_algorithm().output_len` instead
+        hmac_num_bytes: usize,
+    },
+    /// Authenticated cipher.
+    Aead {
+        key: aead::OpeningKey,
+        nonces: NonceSequence,
+    },
+}
+
 impl<S> DecoderMiddleware<S> {
     #[inline]
     pub fn new(
@@ -56,10 +69,20 @@ impl<S> DecoderMiddleware<S> {
         hmac_num_bytes: usize, // TODO: remove this parameter
     ) -> DecoderMiddleware<S> {
         DecoderMiddleware {
-            cipher_state: cipher,
-            hmac_key,
+            decryption: Decryption::Stream {
+                cipher_state: cipher,
+                hmac_key,
+                hmac_num_bytes,
+            },
+            raw_stream,
+        }
+    }
+
+    #[inline]
+    pub fn new_aead(raw_stream: S, key: aead::OpeningKey, nonces: NonceSequence) -> DecoderMiddleware<S> {
+        DecoderMiddleware {
+            decryption: Decryption::Aead { key, nonces },
             raw_stream,
-            hmac_num_bytes,
         }
     }
 }
@@ -81,8 +104,27 @@ where
             Err(err) => return Err(err.into()),
         };
 
-        // TODO: when a new version of ring is released, we can use `hmac_key.digest_algorithm().output_len` instead
-        let hmac_num_bytes = self.hmac_num_bytes;
+        let (cipher_state, hmac_key, hmac_num_bytes) = match self.decryption {
+            Decryption::Stream { ref mut cipher_state, ref hmac_key, hmac_num_bytes } => {
+                (cipher_state, hmac_key, hmac_num_bytes)
+            },
+            Decryption::Aead { ref key, ref mut nonces } => {
+                if frame.len() < key.algorithm().tag_len() {
+                    debug!("frame too short when decoding secio frame");
+                    return Err(SecioError::FrameTooShort);
+                }
+                let mut data_buf = frame.to_vec();
+                let len = match aead::open_in_place(key, &nonces.next(), &[], 0, &mut data_buf) {
+                    Ok(plain) => plain.len(),
+                    Err(_) => {
+                        debug!("authentication failure when decoding secio frame");
+                        return Err(SecioError::HmacNotMatching);
+                    },
+                };
+                data_buf.truncate(len);
+                return Ok(Async::Ready(Some(data_buf)));
+            },
+        };
 
         if frame.len() < hmac_num_bytes {
             debug!("frame too short when decoding secio frame");
@@ -93,7 +135,7 @@ where
             let (crypted_data, expected_hash) = frame.split_at(content_length);
             debug_assert_eq!(expected_hash.len(), hmac_num_bytes);
 
-            if hmac::verify(&self.hmac_key, crypted_data, expected_hash).is_err() {
+            if hmac::verify(hmac_key, crypted_data, expected_hash).is_err() {
                 debug!("hmac mismatch when decoding secio frame");
                 return Err(SecioError::HmacNotMatching);
             }
@@ -101,7 +143,7 @@ where
 
         let mut data_buf = frame.to_vec();
         data_buf.truncate(content_length);
-        self.cipher_state
+        cipher_state
             .try_apply_keystream(&mut data_buf)
             .map_err::<SecioError,_>(|e|e.into())?;
 
@@ -21,9 +21,9 @@
 //! Individual messages encoding.
 
 use bytes::BytesMut;
-use super::StreamCipher;
+use super::{NonceSequence, StreamCipher};
 use futures::prelude::*;
-use ring::hmac;
+use ring::{aead, hmac};
 
 /// Wraps around a `Sink`. Encodes the buffers passed to it and passes it to the underlying sink.
 ///
@@ -33,17 +33,40 @@ use ring::hmac;
 ///
 /// Also implements `Stream` for convenience.
 pub struct EncoderMiddleware<S> {
-    cipher_state: StreamCipher,
-    hmac_key: hmac::SigningKey,
+    encryption: Encryption,
     raw_sink: S,
     pending: Option<BytesMut> // buffer encrypted data which can not be sent right away
 }
 
+/// How the frames are encrypted.
+enum Encryption {
+    /// Stream cipher, followed with an HMAC of the encrypted data.
+    Stream {
+        cipher_state: StreamCipher,
+        hmac_key: hmac::SigningKey,
+    },
+    /// Authenticated cipher.
+    Aead {
+        key: aead::SealingKey,
+        nonces: NonceSequence,
+    },
+}
+
 impl<S> EncoderMiddleware<S> {
     pub fn new(raw: S, cipher: StreamCipher, key: hmac::SigningKey) -> EncoderMiddleware<S> {
         EncoderMiddleware {
-            cipher_state: cipher,
-            hmac_key: key,
+            encryption: Encryption::Stream {
+                cipher_state: cipher,
+                hmac_key: key,
+            },
+            raw_sink: raw,
+            pending: None
+        }
+    }
+
+    pub fn new_aead(raw: S, key: aead::SealingKey, nonces: NonceSequence) -> EncoderMiddleware<S> {
+        EncoderMiddleware {
+            encryption: Encryption::Aead { key, nonces },
             raw_sink: raw,
             pending: None
         }
@@ -65,10 +88,21 @@ where
             }
         }
         debug_assert!(self.pending.is_none());
-        // TODO if SinkError gets refactor to SecioError, then use try_apply_keystream
-        self.cipher_state.apply_keystream(&mut data_buf[..]);
-        let signature = hmac::sign(&self.hmac_key, &data_buf[..]);
-        data_buf.extend_from_slice(signature.as_ref());
+        match self.encryption {
+            Encryption::Stream { ref mut cipher_state, ref hmac_key } => {
+                // TODO if SinkError gets refactor to SecioError, then use try_apply_keystream
+                cipher_state.apply_keystream(&mut data_buf[..]);
+                let signature = hmac::sign(hmac_key, &data_buf[..]);
+                data_buf.extend_from_slice(signature.as_ref());
+            },
+            Encryption::Aead { ref key, ref mut nonces } => {
+                let tag_len = key.algorithm().tag_len();
+                data_buf.extend_from_slice(&vec![0; tag_len]);
+                let out_len = aead::seal_in_place(key, &nonces.next(), &[], &mut data_buf[..], tag_len)
+                    .expect("the buffer has enough room for the tag");
+                data_buf.truncate(out_len);
+            },
+        }
         if let AsyncSink::NotReady(data) = self.raw_sink.start_send(data_buf)? {
             self.pending = Some(data)
         }
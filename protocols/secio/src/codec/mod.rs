@@ -25,7 +25,7 @@ use self::decode::DecoderMiddleware;
 use self::encode::EncoderMiddleware;
 
 use aes_ctr::stream_cipher::StreamCipherCore;
-use ring::hmac;
+use ring::{aead, hmac};
 use tokio_io::codec::length_delimited;
 use tokio_io::{AsyncRead, AsyncWrite};
 
@@ -37,6 +37,39 @@ pub type FullCodec<S> = DecoderMiddleware<EncoderMiddleware<length_delimited::Fr
 
 pub type StreamCipher = Box<dyn StreamCipherCore + Send>;
 
+/// Length of the nonces of the authenticated ciphers.
+const NONCE_LEN: usize = 12;
+
+/// Generates the nonces of an authenticated cipher. Each nonce is the XOR of the IV and of the
+/// number of frames that have been processed, so that a nonce is never reused with the same key.
+pub struct NonceSequence {
+    iv: [u8; NONCE_LEN],
+    counter: u64,
+}
+
+impl NonceSequence {
+    /// Builds a sequence from the IV derived during the handshake.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `iv` isn't 12 bytes long.
+    pub fn new(iv: &[u8]) -> NonceSequence {
+        let mut nonce_iv = [0; NONCE_LEN];
+        nonce_iv.copy_from_slice(iv);
+        NonceSequence { iv: nonce_iv, counter: 0 }
+    }
+
+    /// Returns the nonce of the next frame.
+    pub fn next(&mut self) -> [u8; NONCE_LEN] {
+        let mut nonce = self.iv;
+        for (n, byte) in nonce[NONCE_LEN - 8 ..].iter_mut().enumerate() {
+            *byte ^= (self.counter >> (8 * (7 - n))) as u8;
+        }
+        self.counter = self.counter.checked_add(1).expect("nonce counter overflow");
+        nonce
+    }
+}
+
 
 /// Takes control of `socket`. Returns an object that implements `future::Sink` and
 /// `future::Stream`. The `Stream` and `Sink` produce and accept `BytesMut` objects.
@@ -58,6 +91,22 @@ where
     DecoderMiddleware::new(encoder, cipher_decoder, decoding_hmac, hmac_num_bytes)
 }
 
+/// Same as `full_codec`, but for an authenticated cipher. The frames are not followed with an
+/// HMAC, as the cipher already authenticates them.
+pub fn full_codec_aead<S>(
+    socket: length_delimited::Framed<S>,
+    encoding_key: aead::SealingKey,
+    encoding_iv: &[u8],
+    decoding_key: aead::OpeningKey,
+    decoding_iv: &[u8],
+) -> FullCodec<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let encoder = EncoderMiddleware::new_aead(socket, encoding_key, NonceSequence::new(encoding_iv));
+    DecoderMiddleware::new_aead(encoder, decoding_key, NonceSequence::new(decoding_iv))
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tokio_current_thread;
@@ -68,11 +117,13 @@ mod tests {
     use super::full_codec;
     use super::DecoderMiddleware;
     use super::EncoderMiddleware;
+    use super::NonceSequence;
     use bytes::BytesMut;
     use error::SecioError;
     use futures::sync::mpsc::channel;
     use futures::{Future, Sink, Stream};
     use rand;
+    use ring::aead::{OpeningKey, SealingKey};
     use ring::digest::SHA256;
     use ring::hmac::SigningKey;
     use ring::hmac::VerificationKey;
@@ -114,6 +165,51 @@ mod tests {
         assert_eq!(&decoded.unwrap()[..], &data[..]);
     }
 
+    #[test]
+    fn raw_aead_encode_then_decode() {
+        for cipher in &[Cipher::Aes128Gcm, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let (data_tx, data_rx) = channel::<BytesMut>(256);
+            let data_tx = data_tx.sink_map_err::<_, IoError>(|_| panic!());
+            let data_rx = data_rx.map_err::<IoError, _>(|_| panic!());
+
+            let algorithm = cipher.aead_algorithm().unwrap();
+            let cipher_key: [u8; 32] = rand::random();
+            let iv: [u8; 12] = rand::random();
+
+            let encoder = EncoderMiddleware::new_aead(
+                data_tx,
+                SealingKey::new(algorithm, &cipher_key[..cipher.key_size()]).unwrap(),
+                NonceSequence::new(&iv),
+            );
+            let decoder = DecoderMiddleware::new_aead(
+                data_rx,
+                OpeningKey::new(algorithm, &cipher_key[..cipher.key_size()]).unwrap(),
+                NonceSequence::new(&iv),
+            );
+
+            let data_sent = encoder.send(BytesMut::from(&b"hello"[..]))
+                .and_then(|encoder| encoder.send(BytesMut::from(&b"world"[..])))
+                .from_err();
+            let data_received = decoder.take(2).collect();
+
+            let (_, decoded) = tokio_current_thread::block_on_all(data_sent.join(data_received))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(decoded, vec![b"hello".to_vec(), b"world".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn nonces_are_unique() {
+        let mut nonces = NonceSequence::new(&[7; 12]);
+        let first = nonces.next();
+        let second = nonces.next();
+        assert_eq!(first, [7; 12]);
+        assert_ne!(first, second);
+        assert_eq!(&second[..11], &[7; 11][..]);
+        assert_eq!(second[11], 6);
+    }
+
     fn full_codec_encode_then_decode(cipher: Cipher) {
         let cipher_key: [u8; 32] = rand::random();
         let cipher_key_clone = cipher_key.clone();
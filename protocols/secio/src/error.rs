@@ -18,7 +18,8 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-//! Defines the `SecioError` enum that groups all possible errors in SECIO.
+//! Defines the `SecioError` enum that groups all possible errors in SECIO, and the
+//! `HandshakeError` enum that details why a handshake transcript has been rejected.
 
 use aes_ctr::stream_cipher::LoopError;
 use std::error;
@@ -66,12 +67,68 @@ pub enum SecioError {
 
     /// We received an invalid proposition from remote.
     InvalidProposition(&'static str),
+
+    /// The transcript of the handshake is malformed or has been tampered with.
+    Handshake(HandshakeError),
+}
+
+/// Reason why the transcript of a handshake has been rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// A required field of a handshake message is missing.
+    MissingField(&'static str),
+
+    /// The nonce of the remote doesn't have the expected length.
+    InvalidNonceLength(usize),
+
+    /// The remote sent back our own nonce in its proposition.
+    NonceReflected,
+
+    /// The remote uses the same public key as us, which means that we are talking to ourselves
+    /// or that our proposition has been replayed.
+    ConnectedToSelf,
+
+    /// A list of algorithms of the remote's proposition is empty or contains duplicates. Such a
+    /// list could be used to influence the algorithms that are chosen.
+    MalformedProposition(&'static str),
+
+    /// The key confirmation of the remote doesn't match our transcript of the handshake. Either
+    /// a message has been modified, or the remote derived different keys.
+    TranscriptMismatch,
+
+    /// The remote answered our offer to resume a session with an invalid proof.
+    InvalidResumption,
+}
+
+impl error::Error for HandshakeError {
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            HandshakeError::MissingField(field) =>
+                write!(f, "Handshake message is missing the {} field", field),
+            HandshakeError::InvalidNonceLength(len) =>
+                write!(f, "Remote nonce has an invalid length of {} bytes", len),
+            HandshakeError::NonceReflected =>
+                f.write_str("Remote sent back our own nonce"),
+            HandshakeError::ConnectedToSelf =>
+                f.write_str("Remote uses the same public key as us"),
+            HandshakeError::MalformedProposition(field) =>
+                write!(f, "Remote proposition has an empty or duplicate entry in {}", field),
+            HandshakeError::TranscriptMismatch =>
+                f.write_str("Key confirmation doesn't match the transcript of the handshake"),
+            HandshakeError::InvalidResumption =>
+                f.write_str("Remote sent an invalid proof of session resumption"),
+        }
+    }
 }
 
 impl error::Error for SecioError {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             SecioError::IoError(ref err) => Some(err),
+            SecioError::Handshake(ref err) => Some(err),
             // TODO: The type doesn't implement `Error`
             /*SecioError::CipherError(ref err) => {
                 Some(err)
@@ -110,7 +167,9 @@ impl fmt::Display for SecioError {
             SecioError::HmacNotMatching =>
                 f.write_str("The hashes of the message didn't match"),
             SecioError::InvalidProposition(msg) =>
-                write!(f, "invalid proposition: {}", msg)
+                write!(f, "invalid proposition: {}", msg),
+            SecioError::Handshake(e) =>
+                write!(f, "Invalid handshake: {}", e),
         }
     }
 }
@@ -122,6 +181,13 @@ impl From<LoopError> for SecioError {
     }
 }
 
+impl From<HandshakeError> for SecioError {
+    #[inline]
+    fn from(err: HandshakeError) -> SecioError {
+        SecioError::Handshake(err)
+    }
+}
+
 impl From<IoError> for SecioError {
     #[inline]
     fn from(err: IoError) -> SecioError {
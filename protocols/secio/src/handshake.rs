@@ -20,16 +20,17 @@
 
 use algo_support;
 use bytes::BytesMut;
-use codec::{full_codec, FullCodec};
+use codec::{full_codec, full_codec_aead, FullCodec};
 use stream_cipher::{Cipher, ctr};
-use error::SecioError;
+use error::{HandshakeError, SecioError};
 use futures::future;
 use futures::sink::Sink;
 use futures::stream::Stream;
 use futures::Future;
-use libp2p_core::PublicKey;
+use libp2p_core::{Multiaddr, PublicKey};
 use protobuf::parse_from_bytes as protobuf_parse_from_bytes;
 use protobuf::Message as ProtobufMessage;
+use ring::aead::{OpeningKey, SealingKey};
 use ring::agreement::EphemeralPrivateKey;
 use ring::hmac::{SigningContext, SigningKey, VerificationKey};
 use ring::rand::SecureRandom;
@@ -38,6 +39,7 @@ use ring::signature::{ED25519, RSASigningState, RSA_PKCS1_2048_8192_SHA256, RSA_
 use ring::{agreement, digest, rand};
 #[cfg(feature = "secp256k1")]
 use secp256k1;
+use session::{self, Session};
 use std::cmp::{self, Ordering};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::mem;
@@ -47,78 +49,110 @@ use tokio_io::{AsyncRead, AsyncWrite};
 use untrusted::Input as UntrustedInput;
 use {SecioConfig, SecioKeyPairInner};
 
+/// Role of the local node in the resumption of sessions.
+#[derive(Debug, Clone)]
+pub enum Resumption {
+    /// Sessions are neither offered nor accepted.
+    Disabled,
+    /// We are dialing the given address, and offer to resume the session we have with it.
+    Dialer(Multiaddr),
+    /// We are listening, and accept to resume the sessions that are offered.
+    Listener,
+}
+
+// This struct contains the whole context of a handshake, and is filled progressively
+// throughout the various parts of the handshake.
+struct HandshakeContext {
+    // Filled with this function's parameter.
+    config: SecioConfig,
+    resumption: Resumption,
+
+    rng: rand::SystemRandom,
+    // Locally-generated random number. The array size can be changed without any repercussion.
+    local_nonce: [u8; 16],
+
+    // Our local proposition's raw bytes.
+    local_public_key_in_protobuf_bytes: Vec<u8>,
+    local_proposition_bytes: Vec<u8>,
+    // Our local exchange's raw bytes.
+    local_exchange_bytes: Vec<u8>,
+
+    // The remote proposition's raw bytes.
+    remote_proposition_bytes: BytesMut,
+    remote_public_key_in_protobuf_bytes: Vec<u8>,
+    remote_public_key: Option<PublicKey>,
+    // The remote exchange's raw bytes.
+    remote_exchange_bytes: BytesMut,
+
+    // The remote peer's version of `local_nonce`.
+    // If the NONCE size is actually part of the protocol, we can change this to a fixed-size
+    // array instead of a `Vec`.
+    remote_nonce: Vec<u8>,
+
+    // Set to `ordering(
+    //             hash(concat(remote-pubkey, local-none)),
+    //             hash(concat(local-pubkey, remote-none))
+    //         )`.
+    // `Ordering::Equal` is an invalid value (as it would mean we're talking to ourselves).
+    //
+    // Since everything is symmetrical, this value is used to determine what should be ours
+    // and what should be the remote's.
+    hashes_ordering: Ordering,
+
+    // Crypto algorithms chosen for the communication.
+    chosen_exchange: Option<&'static agreement::Algorithm>,
+    // We only support AES for now, so store just a key size.
+    chosen_cipher: Option<Cipher>,
+    chosen_hash: Option<&'static digest::Algorithm>,
+
+    // Ephemeral key generated for the handshake and then thrown away.
+    local_tmp_priv_key: Option<EphemeralPrivateKey>,
+    local_tmp_pub_key: Vec<u8>,
+
+    // Session that we offered to resume, as a dialer.
+    offered_session: Option<Session>,
+    // Session offered by the remote that we accept to resume, as a listener.
+    accepted_session: Option<Session>,
+    // Secret of the session to store in the cache once the handshake succeeds.
+    next_session_secret: Option<Vec<u8>>,
+}
+
 /// Performs a handshake on the given socket.
 ///
 /// This function expects that the remote is identified with `remote_public_key`, and the remote
 /// will expect that we are identified with `local_key`.Any mismatch somewhere will produce a
 /// `SecioError`.
 ///
+/// If the configuration has a `SessionCache`, `resumption` determines whether we offer or accept
+/// to resume a previous session instead of performing the key exchange.
+///
 /// On success, returns an object that implements the `Sink` and `Stream` trait whose items are
 /// buffers of data, plus the public key of the remote, plus the ephemeral public key used during
-/// negotiation.
+/// negotiation. The ephemeral public key is empty if a session has been resumed.
 pub fn handshake<'a, S: 'a>(
     socket: S,
-    config: SecioConfig
+    config: SecioConfig,
+    resumption: Resumption,
 ) -> Box<Future<Item = (FullCodec<S>, PublicKey, Vec<u8>), Error = SecioError> + Send + 'a>
 where
     S: AsyncRead + AsyncWrite + Send,
 {
     // TODO: could be rewritten as a coroutine once coroutines land in stable Rust
 
-    // This struct contains the whole context of a handshake, and is filled progressively
-    // throughout the various parts of the handshake.
-    struct HandshakeContext {
-        // Filled with this function's parameter.
-        config: SecioConfig,
-
-        rng: rand::SystemRandom,
-        // Locally-generated random number. The array size can be changed without any repercussion.
-        local_nonce: [u8; 16],
-
-        // Our local proposition's raw bytes.
-        local_public_key_in_protobuf_bytes: Vec<u8>,
-        local_proposition_bytes: Vec<u8>,
-
-        // The remote proposition's raw bytes.
-        remote_proposition_bytes: BytesMut,
-        remote_public_key_in_protobuf_bytes: Vec<u8>,
-        remote_public_key: Option<PublicKey>,
-
-        // The remote peer's version of `local_nonce`.
-        // If the NONCE size is actually part of the protocol, we can change this to a fixed-size
-        // array instead of a `Vec`.
-        remote_nonce: Vec<u8>,
-
-        // Set to `ordering(
-        //             hash(concat(remote-pubkey, local-none)),
-        //             hash(concat(local-pubkey, remote-none))
-        //         )`.
-        // `Ordering::Equal` is an invalid value (as it would mean we're talking to ourselves).
-        //
-        // Since everything is symmetrical, this value is used to determine what should be ours
-        // and what should be the remote's.
-        hashes_ordering: Ordering,
-
-        // Crypto algorithms chosen for the communication.
-        chosen_exchange: Option<&'static agreement::Algorithm>,
-        // We only support AES for now, so store just a key size.
-        chosen_cipher: Option<Cipher>,
-        chosen_hash: Option<&'static digest::Algorithm>,
-
-        // Ephemeral key generated for the handshake and then thrown away.
-        local_tmp_priv_key: Option<EphemeralPrivateKey>,
-        local_tmp_pub_key: Vec<u8>,
-    }
-
+    let resumption = if config.session_cache.is_some() { resumption } else { Resumption::Disabled };
+    let max_frame_length = config.max_frame_length;
     let context = HandshakeContext {
         config,
+        resumption,
         rng: rand::SystemRandom::new(),
         local_nonce: Default::default(),
         local_public_key_in_protobuf_bytes: Vec::new(),
         local_proposition_bytes: Vec::new(),
+        local_exchange_bytes: Vec::new(),
         remote_proposition_bytes: BytesMut::new(),
         remote_public_key_in_protobuf_bytes: Vec::new(),
         remote_public_key: None,
+        remote_exchange_bytes: BytesMut::new(),
         remote_nonce: Vec::new(),
         hashes_ordering: Ordering::Equal,
         chosen_exchange: None,
@@ -126,12 +160,16 @@ where
         chosen_hash: None,
         local_tmp_priv_key: None,
         local_tmp_pub_key: Vec::new(),
+        offered_session: None,
+        accepted_session: None,
+        next_session_secret: None,
     };
 
     // The handshake messages all start with a 4-bytes message length prefix.
     let socket = length_delimited::Builder::new()
         .big_endian()
         .length_field_length(4)
+        .max_frame_length(max_frame_length)
         .new_framed(socket);
 
     let future = future::ok::<_, SecioError>(context)
@@ -175,6 +213,17 @@ where
                 proposition.set_hashes(algo_support::DEFAULT_DIGESTS_PROPOSITION.into())
             }
 
+            if let Resumption::Dialer(ref addr) = context.resumption {
+                let cache = context.config.session_cache.as_ref()
+                    .expect("resumption is disabled without a session cache");
+                if let Some(session) = cache.take_for_addr(addr) {
+                    trace!("offering to resume a session with {}", addr);
+                    let offer = session::offer(&session, &context.local_nonce);
+                    proposition.mut_unknown_fields().add_length_delimited(session::OFFER_FIELD, offer);
+                    context.offered_session = Some(session);
+                }
+            }
+
             let proposition_bytes = proposition.write_to_bytes().unwrap();
             context.local_proposition_bytes = proposition_bytes.clone();
 
@@ -208,7 +257,13 @@ where
                             return Err(SecioError::HandshakeParsingFailure);
                         }
                     };
+                    check_proposition(&prop)?;
                     context.remote_public_key_in_protobuf_bytes = prop.take_pubkey();
+                    if context.remote_public_key_in_protobuf_bytes == context.local_public_key_in_protobuf_bytes {
+                        debug!("remote uses the same public key as us");
+                        return Err(HandshakeError::ConnectedToSelf.into());
+                    }
+
                     let pubkey = match PublicKey::from_protobuf_encoding(&context.remote_public_key_in_protobuf_bytes) {
                         Ok(p) => p,
                         Err(_) => {
@@ -218,9 +273,30 @@ where
                     };
 
                     context.remote_nonce = prop.take_rand();
+                    if context.remote_nonce.len() != context.local_nonce.len() {
+                        debug!("remote's nonce has an invalid length");
+                        return Err(HandshakeError::InvalidNonceLength(context.remote_nonce.len()).into());
+                    }
+                    if context.remote_nonce[..] == context.local_nonce[..] {
+                        debug!("remote sent back our own nonce");
+                        return Err(HandshakeError::NonceReflected.into());
+                    }
                     context.remote_public_key = Some(pubkey);
                     trace!("received proposition from remote ; pubkey = {:?} ; nonce = {:?}",
                            context.remote_public_key, context.remote_nonce);
+
+                    // A session that we offered can only be resumed with the same remote.
+                    if context.offered_session.as_ref()
+                        .map_or(false, |s| s.remote_key != context.remote_public_key_in_protobuf_bytes)
+                    {
+                        debug!("remote doesn't match the session we offered");
+                        context.offered_session = None;
+                    }
+
+                    if let Resumption::Listener = context.resumption {
+                        context.accepted_session = accept_offer(&prop, &context);
+                    }
+
                     Ok((prop, socket, context))
                 })
         })
@@ -296,278 +372,588 @@ where
             Ok((socket, context))
         })
 
-        // Generate an ephemeral key for the negotiation.
+        // Perform the key exchange, or resume a session, and build the encoder/decoder.
         .and_then(|(socket, context)| {
-            match EphemeralPrivateKey::generate(context.chosen_exchange.as_ref().unwrap(), &context.rng) {
-                Ok(tmp_priv_key) => Ok((socket, context, tmp_priv_key)),
-                Err(_) => {
-                    debug!("failed to generate ECDH key");
-                    Err(SecioError::EphemeralKeyGenerationFailed)
-                },
+            if context.accepted_session.is_some() {
+                accept_resumption(socket, context)
+            } else if context.offered_session.is_some() {
+                offer_resumption(socket, context)
+            } else {
+                key_exchange(socket, context)
             }
         })
 
-        // Send the ephemeral pub key to the remote in an `Exchange` struct. The `Exchange` also
-        // contains a signature of the two propositions encoded with our static public key.
-        .and_then(|(socket, mut context, tmp_priv)| {
-            let exchange = {
-                let mut local_tmp_pub_key: Vec<u8> = (0 .. tmp_priv.public_key_len()).map(|_| 0).collect();
-                tmp_priv.compute_public_key(&mut local_tmp_pub_key).unwrap();
-                context.local_tmp_priv_key = Some(tmp_priv);
-
-                let mut data_to_sign = context.local_proposition_bytes.clone();
-                data_to_sign.extend_from_slice(&context.remote_proposition_bytes);
-                data_to_sign.extend_from_slice(&local_tmp_pub_key);
-
-                let mut exchange = Exchange::new();
-                exchange.set_epubkey(local_tmp_pub_key.clone());
-                exchange.set_signature({
-                    match context.config.key.inner {
-                        SecioKeyPairInner::Rsa { ref private, .. } => {
-                            let mut state = match RSASigningState::new(private.clone()) {
-                                Ok(s) => s,
-                                Err(_) => {
-                                    debug!("failed to sign local exchange");
-                                    return Err(SecioError::SigningFailure);
-                                },
-                            };
-                            let mut signature = vec![0; private.public_modulus_len()];
-                            match state.sign(&RSA_PKCS1_SHA256, &context.rng, &data_to_sign,
-                                             &mut signature)
-                            {
-                                Ok(_) => (),
-                                Err(_) => {
-                                    debug!("failed to sign local exchange");
-                                    return Err(SecioError::SigningFailure);
-                                },
-                            };
-
-                            signature
-                        },
-                        SecioKeyPairInner::Ed25519 { ref key_pair } => {
-                            let signature = key_pair.sign(&data_to_sign);
-                            signature.as_ref().to_owned()
+        // We send back their nonce to check if the connection works.
+        .and_then(|(codec, mut context)| {
+            let remote_nonce = mem::replace(&mut context.remote_nonce, Vec::new());
+            trace!("checking encryption by sending back remote's nonce");
+            codec.send(BytesMut::from(remote_nonce))
+                .map(|s| (s, context))
+                .from_err()
+        })
+
+        // Check that the received nonce is correct.
+        .and_then(|(codec, context)| {
+            codec.into_future()
+                .map_err(|(e, _)| e)
+                .and_then(move |(nonce, rest)| {
+                    match nonce {
+                        Some(ref n) if n == &context.local_nonce => {
+                            trace!("nonce verification succeeded");
+                            Ok((rest, context))
                         },
-                        #[cfg(feature = "secp256k1")]
-                        SecioKeyPairInner::Secp256k1 { ref private } => {
-                            let data_to_sign = digest::digest(&digest::SHA256, &data_to_sign);
-                            let message = secp256k1::Message::from_slice(data_to_sign.as_ref())
-                                .expect("digest output length doesn't match secp256k1 input length");
-                            let secp256k1 = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::SignOnly);
-                            secp256k1
-                                .sign(&message, private)
-                                .expect("failed to sign message")
-                                .serialize_der(&secp256k1)
+                        None => {
+                            debug!("unexpected eof during nonce check");
+                            Err(IoError::new(IoErrorKind::BrokenPipe, "unexpected eof").into())
                         },
+                        _ => {
+                            debug!("failed nonce verification with remote");
+                            Err(SecioError::NonceVerificationFailed)
+                        }
                     }
-                });
-                exchange
+                })
+        })
+
+        // If enabled, send the hash of our transcript of the handshake, and check that the remote
+        // sends the same one.
+        .and_then(|(codec, context)| {
+            let future = if context.config.key_confirmation {
+                let transcript = transcript_hash(&context.local_proposition_bytes,
+                                                 &context.remote_proposition_bytes,
+                                                 &context.local_exchange_bytes,
+                                                 &context.remote_exchange_bytes,
+                                                 context.hashes_ordering);
+                trace!("sending key confirmation to remote");
+                let future = codec.send(BytesMut::from(transcript.clone()))
+                    .from_err()
+                    .and_then(|codec| codec.into_future().map_err(|(e, _)| e))
+                    .and_then(move |(confirmation, rest)| {
+                        match confirmation {
+                            Some(ref c) if c[..] == transcript[..] => {
+                                trace!("key confirmation succeeded");
+                                Ok(rest)
+                            },
+                            None => {
+                                debug!("unexpected eof during key confirmation");
+                                Err(IoError::new(IoErrorKind::BrokenPipe, "unexpected eof").into())
+                            },
+                            _ => {
+                                debug!("remote's key confirmation doesn't match our transcript");
+                                Err(HandshakeError::TranscriptMismatch.into())
+                            }
+                        }
+                    });
+                future::Either::A(future)
+            } else {
+                future::Either::B(future::ok::<_, SecioError>(codec))
             };
 
-            let local_exch = exchange.write_to_bytes()
-                .expect("can only fail if the protobuf msg is malformed, which can't happen for \
-                         this message in particular");
-            Ok((BytesMut::from(local_exch), socket, context))
+            future.map(move |codec| (codec, context))
         })
 
-        // Send our local `Exchange`.
-        .and_then(|(local_exch, socket, context)| {
+        // Store the session so that it can be resumed later.
+        .map(|(codec, mut context)| {
+            if let Some(secret) = context.next_session_secret.take() {
+                let addr = match context.resumption {
+                    Resumption::Dialer(ref addr) => Some(addr.clone()),
+                    _ => None,
+                };
+                let cache = context.config.session_cache.as_ref()
+                    .expect("a secret is only derived with a session cache");
+                cache.insert(addr, secret, context.remote_public_key_in_protobuf_bytes.clone());
+            }
+
+            trace!("secio handshake success");
+            (codec, context.remote_public_key.expect("we stored a Some earlier"), context.local_tmp_pub_key)
+        });
+
+    Box::new(future)
+}
+
+// Future that produces the encoder/decoder of the connection, and the context of the handshake.
+type CodecFuture<'a, S> = Box<Future<Item = (FullCodec<S>, HandshakeContext), Error = SecioError> + Send + 'a>;
+
+// Performs a full key exchange: sends our `Exchange`, receives the remote's, checks it, and
+// derives the keys of the connection from the ephemeral keys.
+fn key_exchange<'a, S: 'a>(socket: length_delimited::Framed<S>, mut context: HandshakeContext)
+    -> CodecFuture<'a, S>
+where
+    S: AsyncRead + AsyncWrite + Send,
+{
+    let local_exch = match local_exchange(&mut context) {
+        Ok(e) => e,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    trace!("sending exchange to remote");
+    let future = socket.send(local_exch)
+        .from_err()
+        .and_then(|socket| receive_exchange(socket, context))
+        .and_then(|(remote_exch, socket, mut context)| {
+            check_exchange(&remote_exch)?;
+            verify_exchange(&remote_exch, &context)?;
+            let codec = agree(socket, &remote_exch, &mut context)?;
+            Ok((codec, context))
+        });
+    Box::new(future)
+}
+
+// Answers to the offer of the remote with a proof that we know the secret of the session, and
+// derives the keys of the connection from this secret.
+fn accept_resumption<'a, S: 'a>(socket: length_delimited::Framed<S>, mut context: HandshakeContext)
+    -> CodecFuture<'a, S>
+where
+    S: AsyncRead + AsyncWrite + Send,
+{
+    let session = context.accepted_session.take().expect("checked by the caller");
+    let mut accept = Exchange::new();
+    accept.set_signature(session::accept_proof(&session, &context.remote_proposition_bytes,
+                                               &context.local_proposition_bytes));
+    let accept = accept.write_to_bytes()
+        .expect("can only fail if the protobuf msg is malformed, which can't happen for \
+                 this message in particular");
+    context.local_exchange_bytes = accept.clone();
+
+    trace!("accepting to resume the session offered by the remote");
+    let future = socket.send(BytesMut::from(accept))
+        .from_err()
+        .and_then(move |socket| {
+            let codec = resume(socket, &session, &mut context)?;
+            Ok((codec, context))
+        });
+    Box::new(future)
+}
+
+// Waits for the remote to answer our offer. The remote either accepts to resume the session, or
+// sends a regular `Exchange`, in which case we continue with a full key exchange.
+fn offer_resumption<'a, S: 'a>(socket: length_delimited::Framed<S>, context: HandshakeContext)
+    -> CodecFuture<'a, S>
+where
+    S: AsyncRead + AsyncWrite + Send,
+{
+    let future = receive_exchange(socket, context)
+        .and_then(|(remote_exch, socket, mut context)| {
+            let session = context.offered_session.take().expect("checked by the caller");
+
+            if !remote_exch.has_epubkey() {
+                if !session::verify_accept(&session, &context.local_proposition_bytes,
+                                           &context.remote_proposition_bytes,
+                                           remote_exch.get_signature())
+                {
+                    debug!("remote's proof of resumption is invalid");
+                    let err = SecioError::from(HandshakeError::InvalidResumption);
+                    return future::Either::A(future::err(err));
+                }
+                trace!("remote accepted to resume the session");
+                let codec = resume(socket, &session, &mut context).map(|codec| (codec, context));
+                return future::Either::A(future::result(codec));
+            }
+
+            trace!("remote refused to resume the session ; performing a full key exchange");
+            if let Err(err) = check_exchange(&remote_exch) {
+                return future::Either::A(future::err(err));
+            }
+            let local_exch = match local_exchange(&mut context) {
+                Ok(e) => e,
+                Err(err) => return future::Either::A(future::err(err)),
+            };
+
             trace!("sending exchange to remote");
-            socket.send(local_exch)
+            let future = socket.send(local_exch)
                 .from_err()
-                .map(|s| (s, context))
-        })
+                .and_then(move |socket| {
+                    verify_exchange(&remote_exch, &context)?;
+                    let codec = agree(socket, &remote_exch, &mut context)?;
+                    Ok((codec, context))
+                });
+            future::Either::B(future)
+        });
+    Box::new(future)
+}
 
-        // Receive the remote's `Exchange`.
-        .and_then(move |(socket, context)| {
-            socket.into_future()
-                .map_err(|(e, _)| e.into())
-                .and_then(move |(raw, socket)| {
-                    let raw = match raw {
-                        Some(r) => r,
-                        None => {
-                            let err = IoError::new(IoErrorKind::BrokenPipe, "unexpected eof");
-                            debug!("unexpected eof while waiting for remote's exchange");
-                            return Err(err.into())
-                        },
-                    };
+// Checks the offer that the remote put in its proposition, and returns the session to resume if
+// we accept it.
+fn accept_offer(remote_prop: &Propose, context: &HandshakeContext) -> Option<Session> {
+    let offer = remote_prop.get_unknown_fields()
+        .get(session::OFFER_FIELD)
+        .and_then(|values| values.length_delimited.first())?;
+    let (ticket, proof) = match session::parse_offer(offer) {
+        Some(offer) => offer,
+        None => {
+            debug!("remote's offer of resumption is malformed");
+            return None;
+        },
+    };
 
-                    let remote_exch = match protobuf_parse_from_bytes::<Exchange>(&raw) {
-                        Ok(e) => e,
-                        Err(err) => {
-                            debug!("failed to parse remote's exchange protobuf ; {:?}", err);
-                            return Err(SecioError::HandshakeParsingFailure);
-                        }
-                    };
+    let cache = context.config.session_cache.as_ref()?;
+    let session = cache.get(ticket)?;
+    if session.remote_key != context.remote_public_key_in_protobuf_bytes {
+        debug!("remote offered to resume a session that belongs to another peer");
+        return None;
+    }
+    if !session::verify_offer(&session, &context.remote_nonce, proof) {
+        debug!("remote's proof of resumption is invalid");
+        return None;
+    }
 
-                    trace!("received and decoded the remote's exchange");
-                    Ok((remote_exch, socket, context))
-                })
-        })
+    // Removing the ticket ensures that it can't be used a second time, including by another
+    // connection that is being negotiated at the same time.
+    if !cache.remove(ticket) {
+        return None;
+    }
 
-        // Check the validity of the remote's `Exchange`. This verifies that the remote was really
-        // the sender of its proposition, and that it is the owner of both its global and ephemeral
-        // keys.
-        .and_then(|(remote_exch, socket, context)| {
-            let mut data_to_verify = context.remote_proposition_bytes.clone();
-            data_to_verify.extend_from_slice(&context.local_proposition_bytes);
-            data_to_verify.extend_from_slice(remote_exch.get_epubkey());
-
-            match context.remote_public_key {
-                Some(PublicKey::Rsa(ref remote_public_key)) => {
-                    // TODO: The ring library doesn't like some stuff in our DER public key,
-                    //       therefore we scrap the first 24 bytes of the key. A proper fix would
-                    //       be to write a DER parser, but that's not trivial.
-                    match signature_verify(&RSA_PKCS1_2048_8192_SHA256,
-                                           UntrustedInput::from(&remote_public_key[24..]),
-                                           UntrustedInput::from(&data_to_verify),
-                                           UntrustedInput::from(remote_exch.get_signature()))
-                    {
-                        Ok(()) => (),
+    Some(session)
+}
+
+// Generates an ephemeral key and builds our `Exchange` message, which contains the ephemeral
+// public key and a signature of the two propositions encoded with our static public key.
+fn local_exchange(context: &mut HandshakeContext) -> Result<BytesMut, SecioError> {
+    let tmp_priv = match EphemeralPrivateKey::generate(context.chosen_exchange.as_ref().unwrap(), &context.rng) {
+        Ok(tmp_priv_key) => tmp_priv_key,
+        Err(_) => {
+            debug!("failed to generate ECDH key");
+            return Err(SecioError::EphemeralKeyGenerationFailed);
+        },
+    };
+
+    let exchange = {
+        let mut local_tmp_pub_key: Vec<u8> = (0 .. tmp_priv.public_key_len()).map(|_| 0).collect();
+        tmp_priv.compute_public_key(&mut local_tmp_pub_key).unwrap();
+        context.local_tmp_priv_key = Some(tmp_priv);
+
+        let mut data_to_sign = context.local_proposition_bytes.clone();
+        data_to_sign.extend_from_slice(&context.remote_proposition_bytes);
+        data_to_sign.extend_from_slice(&local_tmp_pub_key);
+
+        let mut exchange = Exchange::new();
+        exchange.set_epubkey(local_tmp_pub_key.clone());
+        exchange.set_signature({
+            match context.config.key.inner {
+                SecioKeyPairInner::Rsa { ref private, .. } => {
+                    let mut state = match RSASigningState::new(private.clone()) {
+                        Ok(s) => s,
                         Err(_) => {
-                            debug!("failed to verify the remote's signature");
-                            return Err(SecioError::SignatureVerificationFailed)
+                            debug!("failed to sign local exchange");
+                            return Err(SecioError::SigningFailure);
                         },
-                    }
-                },
-                Some(PublicKey::Ed25519(ref remote_public_key)) => {
-                    match signature_verify(&ED25519,
-                                           UntrustedInput::from(remote_public_key),
-                                           UntrustedInput::from(&data_to_verify),
-                                           UntrustedInput::from(remote_exch.get_signature()))
+                    };
+                    let mut signature = vec![0; private.public_modulus_len()];
+                    match state.sign(&RSA_PKCS1_SHA256, &context.rng, &data_to_sign,
+                                     &mut signature)
                     {
-                        Ok(()) => (),
+                        Ok(_) => (),
                         Err(_) => {
-                            debug!("failed to verify the remote's signature");
-                            return Err(SecioError::SignatureVerificationFailed)
+                            debug!("failed to sign local exchange");
+                            return Err(SecioError::SigningFailure);
                         },
-                    }
+                    };
+
+                    signature
+                },
+                SecioKeyPairInner::Ed25519 { ref key_pair } => {
+                    let signature = key_pair.sign(&data_to_sign);
+                    signature.as_ref().to_owned()
                 },
                 #[cfg(feature = "secp256k1")]
-                Some(PublicKey::Secp256k1(ref remote_public_key)) => {
-                    let data_to_verify = digest::digest(&digest::SHA256, &data_to_verify);
-                    let message = secp256k1::Message::from_slice(data_to_verify.as_ref())
+                SecioKeyPairInner::Secp256k1 { ref private } => {
+                    let data_to_sign = digest::digest(&digest::SHA256, &data_to_sign);
+                    let message = secp256k1::Message::from_slice(data_to_sign.as_ref())
                         .expect("digest output length doesn't match secp256k1 input length");
-                    let secp256k1 = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::VerifyOnly);
-                    let signature = secp256k1::Signature::from_der(&secp256k1, remote_exch.get_signature());
-                    let remote_public_key = secp256k1::key::PublicKey::from_slice(&secp256k1, remote_public_key);
-                    if let (Ok(signature), Ok(remote_public_key)) = (signature, remote_public_key) {
-                        match secp256k1.verify(&message, &signature, &remote_public_key) {
-                            Ok(()) => (),
-                            Err(_) => {
-                                debug!("failed to verify the remote's signature");
-                                return Err(SecioError::SignatureVerificationFailed)
-                            },
-                        }
-                    } else {
-                        debug!("remote's secp256k1 signature has wrong format");
-                        return Err(SecioError::SignatureVerificationFailed)
-                    }
+                    let secp256k1 = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::SignOnly);
+                    secp256k1
+                        .sign(&message, private)
+                        .expect("failed to sign message")
+                        .serialize_der(&secp256k1)
                 },
-                #[cfg(not(feature = "secp256k1"))]
-                Some(PublicKey::Secp256k1(_)) => {
-                    debug!("support for secp256k1 was disabled at compile-time");
-                    return Err(SecioError::SignatureVerificationFailed);
+            }
+        });
+        context.local_tmp_pub_key = local_tmp_pub_key;
+        exchange
+    };
+
+    let local_exch = exchange.write_to_bytes()
+        .expect("can only fail if the protobuf msg is malformed, which can't happen for \
+                 this message in particular");
+    context.local_exchange_bytes = local_exch.clone();
+    Ok(BytesMut::from(local_exch))
+}
+
+// Receives the remote's `Exchange`.
+fn receive_exchange<'a, S: 'a>(socket: length_delimited::Framed<S>, mut context: HandshakeContext)
+    -> impl Future<Item = (Exchange, length_delimited::Framed<S>, HandshakeContext), Error = SecioError> + 'a
+where
+    S: AsyncRead + AsyncWrite + Send,
+{
+    socket.into_future()
+        .map_err(|(e, _)| e.into())
+        .and_then(move |(raw, socket)| {
+            let raw = match raw {
+                Some(r) => r,
+                None => {
+                    let err = IoError::new(IoErrorKind::BrokenPipe, "unexpected eof");
+                    debug!("unexpected eof while waiting for remote's exchange");
+                    return Err(err.into())
                 },
-                None => unreachable!("we store a Some in the remote public key before reaching \
-                                      this point")
             };
 
-            trace!("successfully verified the remote's signature");
+            let remote_exch = match protobuf_parse_from_bytes::<Exchange>(&raw) {
+                Ok(e) => e,
+                Err(err) => {
+                    debug!("failed to parse remote's exchange protobuf ; {:?}", err);
+                    return Err(SecioError::HandshakeParsingFailure);
+                }
+            };
+
+            context.remote_exchange_bytes = raw;
+            trace!("received and decoded the remote's exchange");
             Ok((remote_exch, socket, context))
         })
+}
 
-        // Generate a key from the local ephemeral private key and the remote ephemeral public key,
-        // derive from it a ciper key, an iv, and a hmac key, and build the encoder/decoder.
-        .and_then(|(remote_exch, socket, mut context)| {
-            let local_priv_key = context.local_tmp_priv_key.take()
-                .expect("we filled this Option earlier, and extract it now");
-            let codec = agreement::agree_ephemeral(local_priv_key,
-                                                   &context.chosen_exchange.unwrap(),
-                                                   UntrustedInput::from(remote_exch.get_epubkey()),
-                                                   SecioError::SecretGenerationFailed,
-                                                   |key_material| {
-                let key = SigningKey::new(context.chosen_hash.unwrap(), key_material);
-
-                let chosen_cipher = context.chosen_cipher.unwrap();
-                let cipher_key_size = chosen_cipher.key_size();
-                let iv_size = chosen_cipher.iv_size();
-
-                let mut longer_key = vec![0u8; 2 * (iv_size + cipher_key_size + 20)];
-                stretch_key(&key, &mut longer_key);
-
-                let (local_infos, remote_infos) = {
-                    let (first_half, second_half) = longer_key.split_at(longer_key.len() / 2);
-                    match context.hashes_ordering {
-                        Ordering::Equal => {
-                            let msg = "equal digest of public key and nonce for local and remote";
-                            return Err(SecioError::InvalidProposition(msg))
-                        }
-                        Ordering::Less => (second_half, first_half),
-                        Ordering::Greater => (first_half, second_half),
-                    }
-                };
+// Checks that the remote's `Exchange` has all its fields.
+fn check_exchange(remote_exch: &Exchange) -> Result<(), SecioError> {
+    if !remote_exch.has_epubkey() || remote_exch.get_epubkey().is_empty() {
+        debug!("remote's exchange has no ephemeral public key");
+        return Err(HandshakeError::MissingField("epubkey").into());
+    }
+    if !remote_exch.has_signature() || remote_exch.get_signature().is_empty() {
+        debug!("remote's exchange has no signature");
+        return Err(HandshakeError::MissingField("signature").into());
+    }
+    Ok(())
+}
 
-                let (encoding_cipher, encoding_hmac) = {
-                    let (iv, rest) = local_infos.split_at(iv_size);
-                    let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
-                    let hmac = SigningKey::new(&context.chosen_hash.unwrap(), mac_key);
-                    let cipher = ctr(chosen_cipher, cipher_key, iv);
-                    (cipher, hmac)
-                };
+// Check the validity of the remote's `Exchange`. This verifies that the remote was really
+// the sender of its proposition, and that it is the owner of both its global and ephemeral
+// keys.
+fn verify_exchange(remote_exch: &Exchange, context: &HandshakeContext) -> Result<(), SecioError> {
+    let mut data_to_verify = context.remote_proposition_bytes.clone();
+    data_to_verify.extend_from_slice(&context.local_proposition_bytes);
+    data_to_verify.extend_from_slice(remote_exch.get_epubkey());
+
+    match context.remote_public_key {
+        Some(PublicKey::Rsa(ref remote_public_key)) => {
+            // TODO: The ring library doesn't like some stuff in our DER public key,
+            //       therefore we scrap the first 24 bytes of the key. A proper fix would
+            //       be to write a DER parser, but that's not trivial.
+            match signature_verify(&RSA_PKCS1_2048_8192_SHA256,
+                                   UntrustedInput::from(&remote_public_key[24..]),
+                                   UntrustedInput::from(&data_to_verify),
+                                   UntrustedInput::from(remote_exch.get_signature()))
+            {
+                Ok(()) => (),
+                Err(_) => {
+                    debug!("failed to verify the remote's signature");
+                    return Err(SecioError::SignatureVerificationFailed)
+                },
+            }
+        },
+        Some(PublicKey::Ed25519(ref remote_public_key)) => {
+            match signature_verify(&ED25519,
+                                   UntrustedInput::from(remote_public_key),
+                                   UntrustedInput::from(&data_to_verify),
+                                   UntrustedInput::from(remote_exch.get_signature()))
+            {
+                Ok(()) => (),
+                Err(_) => {
+                    debug!("failed to verify the remote's signature");
+                    return Err(SecioError::SignatureVerificationFailed)
+                },
+            }
+        },
+        #[cfg(feature = "secp256k1")]
+        Some(PublicKey::Secp256k1(ref remote_public_key)) => {
+            let data_to_verify = digest::digest(&digest::SHA256, &data_to_verify);
+            let message = secp256k1::Message::from_slice(data_to_verify.as_ref())
+                .expect("digest output length doesn't match secp256k1 input length");
+            let secp256k1 = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::VerifyOnly);
+            let signature = secp256k1::Signature::from_der(&secp256k1, remote_exch.get_signature());
+            let remote_public_key = secp256k1::key::PublicKey::from_slice(&secp256k1, remote_public_key);
+            if let (Ok(signature), Ok(remote_public_key)) = (signature, remote_public_key) {
+                match secp256k1.verify(&message, &signature, &remote_public_key) {
+                    Ok(()) => (),
+                    Err(_) => {
+                        debug!("failed to verify the remote's signature");
+                        return Err(SecioError::SignatureVerificationFailed)
+                    },
+                }
+            } else {
+                debug!("remote's secp256k1 signature has wrong format");
+                return Err(SecioError::SignatureVerificationFailed)
+            }
+        },
+        #[cfg(not(feature = "secp256k1"))]
+        Some(PublicKey::Secp256k1(_)) => {
+            debug!("support for secp256k1 was disabled at compile-time");
+            return Err(SecioError::SignatureVerificationFailed);
+        },
+        None => unreachable!("we store a Some in the remote public key before reaching \
+                              this point")
+    };
 
-                let (decoding_cipher, decoding_hmac) = {
-                    let (iv, rest) = remote_infos.split_at(iv_size);
-                    let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
-                    let hmac = VerificationKey::new(&context.chosen_hash.unwrap(), mac_key);
-                    let cipher = ctr(chosen_cipher, cipher_key, iv);
-                    (cipher, hmac)
-                };
+    trace!("successfully verified the remote's signature");
+    Ok(())
+}
+
+// Generate a key from the local ephemeral private key and the remote ephemeral public key,
+// and build the encoder/decoder from it.
+fn agree<S>(socket: length_delimited::Framed<S>, remote_exch: &Exchange, context: &mut HandshakeContext)
+    -> Result<FullCodec<S>, SecioError>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let local_priv_key = context.local_tmp_priv_key.take()
+        .expect("we filled this Option earlier, and extract it now");
+    let with_cache = context.config.session_cache.is_some();
+    let result = agreement::agree_ephemeral(local_priv_key,
+                                            &context.chosen_exchange.unwrap(),
+                                            UntrustedInput::from(remote_exch.get_epubkey()),
+                                            SecioError::SecretGenerationFailed,
+                                            |key_material| {
+        let secret = if with_cache { Some(session::session_secret(key_material)) } else { None };
+        build_codec(socket, key_material, context).map(|codec| (codec, secret))
+    });
+
+    match result {
+        Ok((codec, secret)) => {
+            context.next_session_secret = secret;
+            Ok(codec)
+        },
+        Err(err) => {
+            debug!("failed to generate shared secret with remote");
+            Err(err)
+        },
+    }
+}
 
-                Ok(full_codec(socket, encoding_cipher, encoding_hmac, decoding_cipher, decoding_hmac))
-            });
+// Derives the keys of the connection from the secret of a resumed session and from the nonces
+// of both sides, and builds the encoder/decoder.
+fn resume<S>(socket: length_delimited::Framed<S>, session: &Session, context: &mut HandshakeContext)
+    -> Result<FullCodec<S>, SecioError>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (first_nonce, second_nonce) = match context.hashes_ordering {
+        Ordering::Less | Ordering::Equal => (&context.remote_nonce[..], &context.local_nonce[..]),
+        Ordering::Greater => (&context.local_nonce[..], &context.remote_nonce[..]),
+    };
 
-            match codec {
-                Ok(c) => Ok((c, context)),
-                Err(err) => {
-                    debug!("failed to generate shared secret with remote");
-                    Err(err)
-                },
+    let key_material = session::resumed_key_material(session, first_nonce, second_nonce);
+    let next_secret = session::next_secret(session, first_nonce, second_nonce);
+    let codec = build_codec(socket, &key_material, context)?;
+    context.next_session_secret = Some(next_secret);
+    Ok(codec)
+}
+
+// Derives a ciper key, an iv, and a hmac key from the shared key material, and builds the
+// encoder/decoder.
+fn build_codec<S>(socket: length_delimited::Framed<S>, key_material: &[u8], context: &HandshakeContext)
+    -> Result<FullCodec<S>, SecioError>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let key = SigningKey::new(context.chosen_hash.unwrap(), key_material);
+
+    let chosen_cipher = context.chosen_cipher.unwrap();
+    let cipher_key_size = chosen_cipher.key_size();
+    let iv_size = chosen_cipher.iv_size();
+
+    let mut longer_key = vec![0u8; 2 * (iv_size + cipher_key_size + 20)];
+    stretch_key(&key, &mut longer_key);
+
+    let (local_infos, remote_infos) = {
+        let (first_half, second_half) = longer_key.split_at(longer_key.len() / 2);
+        match context.hashes_ordering {
+            Ordering::Equal => {
+                let msg = "equal digest of public key and nonce for local and remote";
+                return Err(SecioError::InvalidProposition(msg))
             }
-        })
+            Ordering::Less => (second_half, first_half),
+            Ordering::Greater => (first_half, second_half),
+        }
+    };
 
-        // We send back their nonce to check if the connection works.
-        .and_then(|(codec, mut context)| {
-            let remote_nonce = mem::replace(&mut context.remote_nonce, Vec::new());
-            trace!("checking encryption by sending back remote's nonce");
-            codec.send(BytesMut::from(remote_nonce))
-                .map(|s| (s, context))
-                .from_err()
-        })
+    if let Some(algorithm) = chosen_cipher.aead_algorithm() {
+        // Authenticated ciphers don't need the HMAC key.
+        let (encoding_iv, rest) = local_infos.split_at(iv_size);
+        let encoding_key = SealingKey::new(algorithm, &rest[.. cipher_key_size])
+            .map_err(|_| SecioError::SecretGenerationFailed)?;
+        let (decoding_iv, rest) = remote_infos.split_at(iv_size);
+        let decoding_key = OpeningKey::new(algorithm, &rest[.. cipher_key_size])
+            .map_err(|_| SecioError::SecretGenerationFailed)?;
+        return Ok(full_codec_aead(socket, encoding_key, encoding_iv, decoding_key, decoding_iv));
+    }
 
-        // Check that the received nonce is correct.
-        .and_then(|(codec, context)| {
-            codec.into_future()
-                .map_err(|(e, _)| e)
-                .and_then(move |(nonce, rest)| {
-                    match nonce {
-                        Some(ref n) if n == &context.local_nonce => {
-                            trace!("secio handshake success");
-                            Ok((rest, context.remote_public_key.expect("we stored a Some earlier"), context.local_tmp_pub_key))
-                        },
-                        None => {
-                            debug!("unexpected eof during nonce check");
-                            Err(IoError::new(IoErrorKind::BrokenPipe, "unexpected eof").into())
-                        },
-                        _ => {
-                            debug!("failed nonce verification with remote");
-                            Err(SecioError::NonceVerificationFailed)
-                        }
-                    }
-                })
-        });
+    let (encoding_cipher, encoding_hmac) = {
+        let (iv, rest) = local_infos.split_at(iv_size);
+        let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
+        let hmac = SigningKey::new(&context.chosen_hash.unwrap(), mac_key);
+        let cipher = ctr(chosen_cipher, cipher_key, iv);
+        (cipher, hmac)
+    };
 
-    Box::new(future)
+    let (decoding_cipher, decoding_hmac) = {
+        let (iv, rest) = remote_infos.split_at(iv_size);
+        let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
+        let hmac = VerificationKey::new(&context.chosen_hash.unwrap(), mac_key);
+        let cipher = ctr(chosen_cipher, cipher_key, iv);
+        (cipher, hmac)
+    };
+
+    Ok(full_codec(socket, encoding_cipher, encoding_hmac, decoding_cipher, decoding_hmac))
+}
+
+// Checks that a proposition of the remote has all its fields, and that its lists of algorithms
+// contain neither empty entries nor duplicates.
+fn check_proposition(prop: &Propose) -> Result<(), HandshakeError> {
+    if !prop.has_rand() {
+        return Err(HandshakeError::MissingField("rand"));
+    }
+    if !prop.has_pubkey() {
+        return Err(HandshakeError::MissingField("pubkey"));
+    }
+
+    let lists = [
+        (prop.has_exchanges(), prop.get_exchanges(), "exchanges"),
+        (prop.has_ciphers(), prop.get_ciphers(), "ciphers"),
+        (prop.has_hashes(), prop.get_hashes(), "hashes"),
+    ];
+
+    for &(present, list, field) in lists.iter() {
+        if !present {
+            return Err(HandshakeError::MissingField(field));
+        }
+        let entries = list.split(',').collect::<Vec<_>>();
+        if entries.iter().any(|e| e.is_empty()) {
+            return Err(HandshakeError::MalformedProposition(field));
+        }
+        if entries.iter().enumerate().any(|(n, e)| entries[.. n].contains(e)) {
+            return Err(HandshakeError::MalformedProposition(field));
+        }
+    }
+
+    Ok(())
+}
+
+// Hashes the messages of the handshake. The messages of the side whose hashes ordering is
+// `Greater` come first, so that both sides compute the same hash.
+fn transcript_hash(local_prop: &[u8], remote_prop: &[u8], local_exch: &[u8], remote_exch: &[u8],
+                   ordering: Ordering) -> Vec<u8>
+{
+    let (first_prop, second_prop, first_exch, second_exch) = match ordering {
+        Ordering::Less | Ordering::Equal => (remote_prop, local_prop, remote_exch, local_exch),
+        Ordering::Greater => (local_prop, remote_prop, local_exch, remote_exch),
+    };
+
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    for message in &[first_prop, second_prop, first_exch, second_exch] {
+        // Each message is prefixed with its length, so that bytes can't be moved from one
+        // message to the next.
+        let len = message.len() as u32;
+        ctx.update(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        ctx.update(message);
+    }
+    ctx.finish().as_ref().to_vec()
 }
 
 // Custom algorithm translated from reference implementations. Needs to be the same algorithm
@@ -604,13 +990,18 @@ mod tests {
     extern crate tokio_tcp;
     use self::tokio_tcp::TcpListener;
     use self::tokio_tcp::TcpStream;
-    use super::handshake;
-    use super::stretch_key;
+    use super::{handshake, Resumption};
+    use super::{check_proposition, stretch_key, transcript_hash};
+    use error::HandshakeError;
     use futures::Future;
     use futures::Stream;
+    use libp2p_core::Runtime;
     use ring::digest::SHA256;
     use ring::hmac::SigningKey;
-    use {SecioConfig, SecioKeyPair};
+    use std::cmp::Ordering;
+    use std::time::Duration;
+    use structs_proto::Propose;
+    use {Cipher, KeyAgreement, SecioConfig, SecioKeyPair, SessionCache};
 
     #[test]
     fn handshake_with_self_succeeds_rsa() {
@@ -636,6 +1027,22 @@ mod tests {
         handshake_with_self_succeeds(SecioConfig::new(key1), SecioConfig::new(key2));
     }
 
+    #[test]
+    fn handshake_with_self_succeeds_aead() {
+        for &(agreement, cipher) in &[(KeyAgreement::X25519, Cipher::ChaCha20Poly1305),
+                                      (KeyAgreement::EcdhP256, Cipher::Aes128Gcm),
+                                      (KeyAgreement::EcdhP384, Cipher::Aes256Gcm)] {
+            let config = |key| {
+                SecioConfig::new(key)
+                    .key_agreements(&[agreement])
+                    .ciphers(&[cipher])
+            };
+            let key1 = SecioKeyPair::ed25519_generated().unwrap();
+            let key2 = SecioKeyPair::ed25519_generated().unwrap();
+            handshake_with_self_succeeds(config(key1), config(key2));
+        }
+    }
+
     #[test]
     #[cfg(feature = "secp256k1")]
     fn handshake_with_self_succeeds_secp256k1() {
@@ -652,7 +1059,74 @@ mod tests {
         handshake_with_self_succeeds(SecioConfig::new(key1), SecioConfig::new(key2));
     }
 
+    #[test]
+    fn handshake_with_key_confirmation_succeeds() {
+        let key1 = SecioKeyPair::ed25519_generated().unwrap();
+        let key2 = SecioKeyPair::ed25519_generated().unwrap();
+        handshake_with_self_succeeds(SecioConfig::new(key1).key_confirmation(true),
+                                     SecioConfig::new(key2).key_confirmation(true));
+    }
+
+    #[test]
+    fn malformed_propositions_rejected() {
+        let proposition = |exchanges: &str| {
+            let mut prop = Propose::new();
+            prop.set_rand(vec![0; 16]);
+            prop.set_pubkey(vec![1; 32]);
+            prop.set_exchanges(exchanges.to_owned());
+            prop.set_ciphers("AES-128".to_owned());
+            prop.set_hashes("SHA256".to_owned());
+            prop
+        };
+
+        assert_eq!(check_proposition(&proposition("P-256,X25519")), Ok(()));
+        assert_eq!(check_proposition(&proposition("P-256,X25519,P-256")),
+                   Err(HandshakeError::MalformedProposition("exchanges")));
+        assert_eq!(check_proposition(&proposition("P-256,,X25519")),
+                   Err(HandshakeError::MalformedProposition("exchanges")));
+
+        let mut prop = proposition("P-256");
+        prop.clear_rand();
+        assert_eq!(check_proposition(&prop), Err(HandshakeError::MissingField("rand")));
+    }
+
+    #[test]
+    fn transcript_hash_symmetric() {
+        let local = transcript_hash(b"prop-a", b"prop-b", b"exch-a", b"exch-b", Ordering::Greater);
+        let remote = transcript_hash(b"prop-b", b"prop-a", b"exch-b", b"exch-a", Ordering::Less);
+        assert_eq!(local, remote);
+
+        let modified = transcript_hash(b"prop-a", b"prop-b", b"exch-a", b"exch-", Ordering::Greater);
+        assert_ne!(local, modified);
+    }
+
+    #[test]
+    fn session_resumed_on_second_connection() {
+        let listener_config = SecioConfig::new(SecioKeyPair::ed25519_generated().unwrap())
+            .session_cache(SessionCache::new(Duration::from_secs(60), Runtime::default()));
+        let dialer_cache = SessionCache::new(Duration::from_secs(60), Runtime::default());
+        let dialer_config = SecioConfig::new(SecioKeyPair::ed25519_generated().unwrap())
+            .session_cache(dialer_cache.clone());
+
+        for &resumed in &[false, true, true] {
+            let (listener_ephemeral, dialer_ephemeral) = handshake_pair(
+                listener_config.clone(), Resumption::Listener,
+                dialer_config.clone(), Resumption::Dialer("/ip4/127.0.0.1/tcp/1".parse().unwrap())
+            );
+            assert_eq!(listener_ephemeral.is_empty(), resumed);
+            assert_eq!(dialer_ephemeral.is_empty(), resumed);
+            assert_eq!(dialer_cache.len(), 1);
+        }
+    }
+
     fn handshake_with_self_succeeds(key1: SecioConfig, key2: SecioConfig) {
+        handshake_pair(key1, Resumption::Disabled, key2, Resumption::Disabled);
+    }
+
+    // Performs a handshake between a listener and a dialer, and returns their ephemeral public
+    // keys.
+    fn handshake_pair(key1: SecioConfig, resumption1: Resumption,
+                      key2: SecioConfig, resumption2: Resumption) -> (Vec<u8>, Vec<u8>) {
         let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
         let listener_addr = listener.local_addr().unwrap();
 
@@ -660,13 +1134,15 @@ mod tests {
             .incoming()
             .into_future()
             .map_err(|(e, _)| e.into())
-            .and_then(move |(connec, _)| handshake(connec.unwrap(), key1));
+            .and_then(move |(connec, _)| handshake(connec.unwrap(), key1, resumption1));
 
         let client = TcpStream::connect(&listener_addr)
             .map_err(|e| e.into())
-            .and_then(move |stream| handshake(stream, key2));
+            .and_then(move |stream| handshake(stream, key2, resumption2));
 
-        tokio_current_thread::block_on_all(server.join(client)).unwrap();
+        let ((_, _, ephemeral1), (_, _, ephemeral2)) =
+            tokio_current_thread::block_on_all(server.join(client)).unwrap();
+        (ephemeral1, ephemeral2)
     }
 
     #[test]
@@ -0,0 +1,557 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Persistence of identity key pairs.
+//!
+//! A `SecioPrivateKey` holds the private part of an identity, and can be converted to a
+//! `SecioKeyPair` to be used by the secio handshake. Contrary to `SecioKeyPair`, it can be
+//! exported and imported in the protobuf encoding of libp2p or in PEM format.
+//!
+//! The `Keystore` stores private keys in a directory, one file per key, encrypted with a
+//! passphrase. The encryption key is derived from the passphrase with PBKDF2-HMAC-SHA256, and the
+//! private key is encrypted with AES-256-GCM.
+//!
+//! > **Note**: Only Ed25519 and secp256k1 keys are supported. RSA keys can't be generated and
+//! >           can't be exported by the cryptographic library that we use.
+
+use base64;
+use protobuf::{CodedInputStream, CodedOutputStream};
+use protobuf::wire_format::WireType;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::Ed25519KeyPair;
+use ring::{aead, digest, pbkdf2};
+use std::{error, fmt, fs};
+use std::io::Error as IoError;
+use std::path::PathBuf;
+use std::sync::Arc;
+use untrusted::Input;
+use {SecioKeyPair, SecioKeyPairInner};
+#[cfg(feature = "secp256k1")]
+use secp256k1;
+
+/// Length of an Ed25519 seed or public key.
+const ED25519_LEN: usize = 32;
+/// Length of a raw secp256k1 private key.
+#[cfg(feature = "secp256k1")]
+const SECP256K1_LEN: usize = 32;
+/// Beginning of the PKCS#8 v2 document of an Ed25519 key, up to the seed.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+/// Bytes between the seed and the public key in the PKCS#8 v2 document of an Ed25519 key.
+const ED25519_PKCS8_MIDDLE: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+/// Values of the `KeyType` enum of the libp2p protobuf encoding.
+const KEY_TYPE_ED25519: i32 = 1;
+#[cfg(feature = "secp256k1")]
+const KEY_TYPE_SECP256K1: i32 = 2;
+
+/// Version of the format of the encrypted key files.
+const FILE_FORMAT_VERSION: u8 = 1;
+/// Length of the salt of the key derivation.
+const SALT_LEN: usize = 16;
+/// Length of the nonce of AES-256-GCM.
+const NONCE_LEN: usize = 12;
+/// Number of iterations of PBKDF2.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// Extension of the key files in a `Keystore`.
+const KEY_FILE_EXTENSION: &str = "key";
+
+/// Private key of an identity, which can be exported and persisted.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecioPrivateKey {
+    inner: PrivateKeyInner,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum PrivateKeyInner {
+    Ed25519 {
+        seed: Vec<u8>,
+        public: Vec<u8>,
+    },
+    #[cfg(feature = "secp256k1")]
+    Secp256k1 {
+        secret: Vec<u8>,
+    },
+}
+
+impl SecioPrivateKey {
+    /// Generates a new Ed25519 private key.
+    pub fn ed25519_generated() -> Result<SecioPrivateKey, KeystoreError> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| KeystoreError::KeyGenerationFailed)?;
+        SecioPrivateKey::ed25519_from_pkcs8(&pkcs8[..])
+    }
+
+    /// Generates a new secp256k1 private key.
+    #[cfg(feature = "secp256k1")]
+    pub fn secp256k1_generated() -> Result<SecioPrivateKey, KeystoreError> {
+        let secp = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::Full);
+        let (private, _) = secp.generate_keypair(&mut ::rand::thread_rng())
+            .map_err(|_| KeystoreError::KeyGenerationFailed)?;
+        Ok(SecioPrivateKey {
+            inner: PrivateKeyInner::Secp256k1 { secret: private[..].to_vec() },
+        })
+    }
+
+    /// Builds a private key from the PKCS#8 v2 document of an Ed25519 key.
+    ///
+    /// PKCS#8 v1 documents are not supported, as they don't contain the public key.
+    pub fn ed25519_from_pkcs8(pkcs8: &[u8]) -> Result<SecioPrivateKey, KeystoreError> {
+        let seed_end = ED25519_PKCS8_PREFIX.len() + ED25519_LEN;
+        let public_start = seed_end + ED25519_PKCS8_MIDDLE.len();
+        if pkcs8.len() != public_start + ED25519_LEN ||
+            pkcs8[.. ED25519_PKCS8_PREFIX.len()] != ED25519_PKCS8_PREFIX[..] ||
+            pkcs8[seed_end .. public_start] != ED25519_PKCS8_MIDDLE[..]
+        {
+            return Err(KeystoreError::InvalidKey);
+        }
+
+        SecioPrivateKey::ed25519_from_seed_and_public_key(&pkcs8[ED25519_PKCS8_PREFIX.len() .. seed_end],
+                                                          &pkcs8[public_start ..])
+    }
+
+    /// Builds a private key from an Ed25519 seed and the corresponding public key.
+    pub fn ed25519_from_seed_and_public_key(seed: &[u8], public: &[u8]) -> Result<SecioPrivateKey, KeystoreError> {
+        // Makes sure that the seed and the public key match.
+        Ed25519KeyPair::from_seed_and_public_key(Input::from(seed), Input::from(public))
+            .map_err(|_| KeystoreError::InvalidKey)?;
+        Ok(SecioPrivateKey {
+            inner: PrivateKeyInner::Ed25519 { seed: seed.to_vec(), public: public.to_vec() },
+        })
+    }
+
+    /// Builds a private key from a raw secp256k1 32 bytes private key.
+    #[cfg(feature = "secp256k1")]
+    pub fn secp256k1_raw_key(key: &[u8]) -> Result<SecioPrivateKey, KeystoreError> {
+        let secp = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::None);
+        secp256k1::key::SecretKey::from_slice(&secp, key).map_err(|_| KeystoreError::InvalidKey)?;
+        Ok(SecioPrivateKey {
+            inner: PrivateKeyInner::Secp256k1 { secret: key.to_vec() },
+        })
+    }
+
+    /// Decodes a private key from the protobuf encoding of libp2p, as produced by go-libp2p.
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<SecioPrivateKey, KeystoreError> {
+        let mut key_type = None;
+        let mut data = None;
+        {
+            let mut stream = CodedInputStream::from_bytes(bytes);
+            while !stream.eof().map_err(|_| KeystoreError::InvalidKey)? {
+                let (field, wire_type) = stream.read_tag_unpack().map_err(|_| KeystoreError::InvalidKey)?;
+                match (field, wire_type) {
+                    (1, WireType::WireTypeVarint) => {
+                        key_type = Some(stream.read_int32().map_err(|_| KeystoreError::InvalidKey)?);
+                    },
+                    (2, WireType::WireTypeLengthDelimited) => {
+                        data = Some(stream.read_bytes().map_err(|_| KeystoreError::InvalidKey)?);
+                    },
+                    (_, wire_type) => {
+                        stream.skip_field(wire_type).map_err(|_| KeystoreError::InvalidKey)?;
+                    },
+                }
+            }
+        }
+
+        let data = data.ok_or(KeystoreError::InvalidKey)?;
+        match key_type {
+            // The data is the seed followed with the public key.
+            Some(KEY_TYPE_ED25519) if data.len() == 2 * ED25519_LEN => {
+                SecioPrivateKey::ed25519_from_seed_and_public_key(&data[.. ED25519_LEN], &data[ED25519_LEN ..])
+            },
+            #[cfg(feature = "secp256k1")]
+            Some(KEY_TYPE_SECP256K1) => SecioPrivateKey::secp256k1_raw_key(&data),
+            Some(KEY_TYPE_ED25519) => Err(KeystoreError::InvalidKey),
+            _ => Err(KeystoreError::UnsupportedKeyType),
+        }
+    }
+
+    /// Encodes the private key with the protobuf encoding of libp2p, as used by go-libp2p.
+    pub fn to_protobuf_encoding(&self) -> Vec<u8> {
+        let (key_type, data) = match self.inner {
+            PrivateKeyInner::Ed25519 { ref seed, ref public } => {
+                let mut data = seed.clone();
+                data.extend_from_slice(public);
+                (KEY_TYPE_ED25519, data)
+            },
+            #[cfg(feature = "secp256k1")]
+            PrivateKeyInner::Secp256k1 { ref secret } => (KEY_TYPE_SECP256K1, secret.clone()),
+        };
+
+        let mut out = Vec::new();
+        {
+            let mut stream = CodedOutputStream::vec(&mut out);
+            stream.write_int32(1, key_type).expect("writing to a Vec never fails");
+            stream.write_bytes(2, &data).expect("writing to a Vec never fails");
+            stream.flush().expect("writing to a Vec never fails");
+        }
+        out
+    }
+
+    /// Decodes a private key in PEM format.
+    ///
+    /// Supports Ed25519 keys in PKCS#8 v2 documents (`PRIVATE KEY`) and secp256k1 keys in the
+    /// format of RFC 5915 (`EC PRIVATE KEY`).
+    pub fn from_pem(pem: &str) -> Result<SecioPrivateKey, KeystoreError> {
+        let mut lines = pem.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+        let label = match lines.next() {
+            Some(l) if l.starts_with("-----BEGIN ") && l.ends_with("-----") => {
+                l["-----BEGIN ".len() .. l.len() - "-----".len()].to_owned()
+            },
+            _ => return Err(KeystoreError::InvalidPem),
+        };
+        let end = format!("-----END {}-----", label);
+
+        let mut body = String::new();
+        let mut found_end = false;
+        for line in lines {
+            if line == end {
+                found_end = true;
+                break;
+            }
+            body.push_str(line);
+        }
+        if !found_end {
+            return Err(KeystoreError::InvalidPem);
+        }
+
+        let der = base64::decode(&body).map_err(|_| KeystoreError::InvalidPem)?;
+        match &label[..] {
+            "PRIVATE KEY" => SecioPrivateKey::ed25519_from_pkcs8(&der),
+            #[cfg(feature = "secp256k1")]
+            "EC PRIVATE KEY" => {
+                let secret = ::secp256k1_der_to_raw(&der).map_err(|_| KeystoreError::InvalidKey)?;
+                SecioPrivateKey::secp256k1_raw_key(&secret)
+            },
+            _ => Err(KeystoreError::UnsupportedKeyType),
+        }
+    }
+
+    /// Encodes the private key in PEM format. The output can be decoded with `from_pem`.
+    pub fn to_pem(&self) -> String {
+        let (label, der) = match self.inner {
+            PrivateKeyInner::Ed25519 { ref seed, ref public } => {
+                let mut der = ED25519_PKCS8_PREFIX.to_vec();
+                der.extend_from_slice(seed);
+                der.extend_from_slice(&ED25519_PKCS8_MIDDLE);
+                der.extend_from_slice(public);
+                ("PRIVATE KEY", der)
+            },
+            #[cfg(feature = "secp256k1")]
+            PrivateKeyInner::Secp256k1 { ref secret } => {
+                // ECPrivateKey ::= SEQUENCE { version 1, privateKey, [0] secp256k1 }
+                let mut der = vec![0x30, 0x2e, 0x02, 0x01, 0x01, 0x04, SECP256K1_LEN as u8];
+                der.extend_from_slice(secret);
+                der.extend_from_slice(&[0xa0, 0x07, 0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a]);
+                ("EC PRIVATE KEY", der)
+            },
+        };
+
+        let mut pem = format!("-----BEGIN {}-----\n", label);
+        for chunk in base64::encode(&der).as_bytes().chunks(64) {
+            pem.push_str(&String::from_utf8_lossy(chunk));
+            pem.push('\n');
+        }
+        pem.push_str(&format!("-----END {}-----\n", label));
+        pem
+    }
+
+    /// Builds the `SecioKeyPair` corresponding to this private key.
+    pub fn to_key_pair(&self) -> SecioKeyPair {
+        let inner = match self.inner {
+            PrivateKeyInner::Ed25519 { ref seed, ref public } => {
+                let key_pair = Ed25519KeyPair::from_seed_and_public_key(Input::from(seed), Input::from(public))
+                    .expect("seed and public key are checked when building a SecioPrivateKey");
+                SecioKeyPairInner::Ed25519 { key_pair: Arc::new(key_pair) }
+            },
+            #[cfg(feature = "secp256k1")]
+            PrivateKeyInner::Secp256k1 { ref secret } => {
+                let secp = secp256k1::Secp256k1::with_caps(secp256k1::ContextFlag::None);
+                let private = secp256k1::key::SecretKey::from_slice(&secp, secret)
+                    .expect("secret key is checked when building a SecioPrivateKey");
+                SecioKeyPairInner::Secp256k1 { private }
+            },
+        };
+
+        SecioKeyPair { inner }
+    }
+
+    /// Encrypts the private key with a passphrase. The output can be decrypted with
+    /// `from_encrypted`.
+    pub fn to_encrypted(&self, passphrase: &str) -> Result<Vec<u8>, KeystoreError> {
+        let rng = SystemRandom::new();
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| KeystoreError::EncryptionFailed)?;
+        rng.fill(&mut nonce).map_err(|_| KeystoreError::EncryptionFailed)?;
+
+        let key = derive_key(passphrase, &salt);
+        let key = aead::SealingKey::new(&aead::AES_256_GCM, &key)
+            .map_err(|_| KeystoreError::EncryptionFailed)?;
+
+        let tag_len = aead::AES_256_GCM.tag_len();
+        let mut in_out = self.to_protobuf_encoding();
+        in_out.extend((0 .. tag_len).map(|_| 0));
+        let len = aead::seal_in_place(&key, &nonce, &[FILE_FORMAT_VERSION], &mut in_out, tag_len)
+            .map_err(|_| KeystoreError::EncryptionFailed)?;
+        in_out.truncate(len);
+
+        let mut out = Vec::with_capacity(1 + SALT_LEN + NONCE_LEN + in_out.len());
+        out.push(FILE_FORMAT_VERSION);
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&in_out);
+        Ok(out)
+    }
+
+    /// Decrypts a private key produced by `to_encrypted`.
+    pub fn from_encrypted(data: &[u8], passphrase: &str) -> Result<SecioPrivateKey, KeystoreError> {
+        if data.len() < 1 + SALT_LEN + NONCE_LEN {
+            return Err(KeystoreError::InvalidKeyFile);
+        }
+        if data[0] != FILE_FORMAT_VERSION {
+            return Err(KeystoreError::InvalidKeyFile);
+        }
+
+        let salt = &data[1 .. 1 + SALT_LEN];
+        let nonce = &data[1 + SALT_LEN .. 1 + SALT_LEN + NONCE_LEN];
+        let key = derive_key(passphrase, salt);
+        let key = aead::OpeningKey::new(&aead::AES_256_GCM, &key)
+            .map_err(|_| KeystoreError::DecryptionFailed)?;
+
+        let mut in_out = data[1 + SALT_LEN + NONCE_LEN ..].to_vec();
+        let plain = aead::open_in_place(&key, nonce, &[FILE_FORMAT_VERSION], 0, &mut in_out)
+            .map_err(|_| KeystoreError::DecryptionFailed)?;
+        SecioPrivateKey::from_protobuf_encoding(plain)
+    }
+}
+
+impl fmt::Debug for SecioPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // Don't print the private key.
+        match self.inner {
+            PrivateKeyInner::Ed25519 { .. } => f.debug_tuple("SecioPrivateKey::Ed25519").finish(),
+            #[cfg(feature = "secp256k1")]
+            PrivateKeyInner::Secp256k1 { .. } => f.debug_tuple("SecioPrivateKey::Secp256k1").finish(),
+        }
+    }
+}
+
+/// Derives the encryption key of a key file from the passphrase.
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::derive(&digest::SHA256, PBKDF2_ITERATIONS, salt, passphrase.as_bytes(), &mut key);
+    key
+}
+
+/// Directory containing private keys encrypted with a passphrase.
+///
+/// Each key is stored in a file named after the key, so that the identities of nodes can be
+/// reused between runs.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    path: PathBuf,
+}
+
+impl Keystore {
+    /// Opens the keystore in the given directory. The directory is created if it doesn't exist.
+    pub fn open<P>(path: P) -> Result<Keystore, IoError>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        Ok(Keystore { path })
+    }
+
+    /// Stores a key under the given name, encrypted with `passphrase`. Overwrites the key that
+    /// was previously stored under this name, if any.
+    pub fn store(&self, name: &str, key: &SecioPrivateKey, passphrase: &str) -> Result<(), KeystoreError> {
+        let path = self.key_path(name)?;
+        let data = key.to_encrypted(passphrase)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Loads the key stored under the given name.
+    pub fn load(&self, name: &str, passphrase: &str) -> Result<SecioPrivateKey, KeystoreError> {
+        let data = fs::read(self.key_path(name)?)?;
+        SecioPrivateKey::from_encrypted(&data, passphrase)
+    }
+
+    /// Loads the key stored under the given name, or generates a new Ed25519 key and stores it if
+    /// there is none.
+    pub fn load_or_generate(&self, name: &str, passphrase: &str) -> Result<SecioPrivateKey, KeystoreError> {
+        if self.key_path(name)?.exists() {
+            return self.load(name, passphrase);
+        }
+
+        let key = SecioPrivateKey::ed25519_generated()?;
+        self.store(name, &key, passphrase)?;
+        Ok(key)
+    }
+
+    /// Removes the key stored under the given name. Returns false if there was no such key.
+    pub fn remove(&self, name: &str) -> Result<bool, KeystoreError> {
+        let path = self.key_path(name)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(path)?;
+        Ok(true)
+    }
+
+    /// Returns the names of the keys in the keystore.
+    pub fn names(&self) -> Result<Vec<String>, IoError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == KEY_FILE_EXTENSION).unwrap_or(false) {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Returns the path of the file of a key.
+    fn key_path(&self, name: &str) -> Result<PathBuf, KeystoreError> {
+        let valid = !name.is_empty() &&
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(KeystoreError::InvalidName);
+        }
+        Ok(self.path.join(format!("{}.{}", name, KEY_FILE_EXTENSION)))
+    }
+}
+
+/// Error that can happen when manipulating private keys.
+#[derive(Debug)]
+pub enum KeystoreError {
+    /// I/O error while accessing the keystore.
+    IoError(IoError),
+    /// Failed to generate a key.
+    KeyGenerationFailed,
+    /// The key material is invalid.
+    InvalidKey,
+    /// The type of the key is not supported.
+    UnsupportedKeyType,
+    /// The PEM document is malformed.
+    InvalidPem,
+    /// The name of the key contains characters other than ASCII letters, digits, `-` and `_`.
+    InvalidName,
+    /// The content of the key file is malformed.
+    InvalidKeyFile,
+    /// Failed to encrypt the key.
+    EncryptionFailed,
+    /// Failed to decrypt the key. Most likely the passphrase is wrong.
+    DecryptionFailed,
+}
+
+impl error::Error for KeystoreError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KeystoreError::IoError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            KeystoreError::IoError(ref err) => write!(f, "I/O error: {}", err),
+            KeystoreError::KeyGenerationFailed => write!(f, "failed to generate a key"),
+            KeystoreError::InvalidKey => write!(f, "invalid key material"),
+            KeystoreError::UnsupportedKeyType => write!(f, "unsupported key type"),
+            KeystoreError::InvalidPem => write!(f, "malformed PEM document"),
+            KeystoreError::InvalidName => write!(f, "invalid key name"),
+            KeystoreError::InvalidKeyFile => write!(f, "malformed key file"),
+            KeystoreError::EncryptionFailed => write!(f, "failed to encrypt the key"),
+            KeystoreError::DecryptionFailed => write!(f, "failed to decrypt the key"),
+        }
+    }
+}
+
+impl From<IoError> for KeystoreError {
+    #[inline]
+    fn from(err: IoError) -> KeystoreError {
+        KeystoreError::IoError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn protobuf_and_pem_round_trip() {
+        let key = SecioPrivateKey::ed25519_generated().unwrap();
+        let decoded = SecioPrivateKey::from_protobuf_encoding(&key.to_protobuf_encoding()).unwrap();
+        assert!(decoded == key);
+        let decoded = SecioPrivateKey::from_pem(&key.to_pem()).unwrap();
+        assert!(decoded == key);
+        assert_eq!(decoded.to_key_pair().to_peer_id(), key.to_key_pair().to_peer_id());
+    }
+
+    #[test]
+    #[cfg(feature = "secp256k1")]
+    fn secp256k1_round_trip() {
+        let key = SecioPrivateKey::secp256k1_generated().unwrap();
+        let decoded = SecioPrivateKey::from_protobuf_encoding(&key.to_protobuf_encoding()).unwrap();
+        assert!(decoded == key);
+        let decoded = SecioPrivateKey::from_pem(&key.to_pem()).unwrap();
+        assert!(decoded == key);
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let key = SecioPrivateKey::ed25519_generated().unwrap();
+        let encrypted = key.to_encrypted("passphrase").unwrap();
+        assert!(SecioPrivateKey::from_encrypted(&encrypted, "passphrase").unwrap() == key);
+        match SecioPrivateKey::from_encrypted(&encrypted, "wrong") {
+            Err(KeystoreError::DecryptionFailed) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn keystore_persistence() {
+        let dir = env::temp_dir().join(format!("libp2p-secio-keystore-test-{}", ::rand::random::<u64>()));
+        let keystore = Keystore::open(&dir).unwrap();
+        assert!(keystore.names().unwrap().is_empty());
+
+        let key = keystore.load_or_generate("node-1", "pass").unwrap();
+        assert!(Keystore::open(&dir).unwrap().load_or_generate("node-1", "pass").unwrap() == key);
+        assert_eq!(keystore.names().unwrap(), vec!["node-1".to_owned()]);
+
+        match keystore.store("../escape", &key, "pass") {
+            Err(KeystoreError::InvalidName) => (),
+            _ => panic!(),
+        }
+
+        assert!(keystore.remove("node-1").unwrap());
+        assert!(!keystore.remove("node-1").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -76,10 +76,37 @@
 //! moment when the handshake succeeds or errored. On success, the future produces a
 //! `SecioMiddleware` that implements `Sink` and `Stream` and can be used to send packets of data.
 //!
+//! # Handshake checks
+//!
+//! The handshake rejects the transcripts that are malformed: missing fields, nonces of the wrong
+//! length or reflected back at us, a remote that uses our own public key, and algorithm lists
+//! that are empty or contain duplicates. These produce a `SecioError::Handshake` that contains a
+//! `HandshakeError` describing the problem.
+//!
+//! With `SecioConfig::key_confirmation`, both sides additionally send a hash of the whole
+//! transcript over the encrypted channel once the keys have been derived. This confirms that
+//! both sides saw the same propositions and exchanges, in the same order, and derived the same
+//! keys.
+//!
+//! # Session resumption
+//!
+//! With `SecioConfig::session_cache`, the sessions established by the handshake are stored in a
+//! `SessionCache`. When dialing an address again within the lifetime of the cache, the dialer
+//! offers to resume the previous session, and if the listener still knows it, both sides derive
+//! the keys of the new connection from the session instead of performing the key exchange. Each
+//! session can be resumed only once, which protects against replays.
+//!
+//! # Persisting keys
+//!
+//! A `SecioKeyPair` can't be exported. In order to reuse the same identity between runs, use a
+//! `SecioPrivateKey` instead, which can be converted to and from the protobuf and PEM formats,
+//! and can be stored encrypted on disk with a `Keystore`.
+//!
 
 extern crate aes_ctr;
 #[cfg(feature = "secp256k1")]
 extern crate asn1_der;
+extern crate base64;
 extern crate bytes;
 extern crate ctr;
 extern crate futures;
@@ -95,18 +122,24 @@ extern crate secp256k1;
 extern crate tokio_io;
 extern crate twofish;
 extern crate untrusted;
+extern crate wasm_timer;
 
 #[cfg(feature = "aes-all")]
 #[macro_use]
 extern crate lazy_static;
-pub use self::error::SecioError;
+pub use self::error::{HandshakeError, SecioError};
+pub use self::keystore::{Keystore, KeystoreError, SecioPrivateKey};
+pub use self::session::SessionCache;
 
 #[cfg(feature = "secp256k1")]
 use asn1_der::{traits::FromDerEncoded, traits::FromDerObject, DerObject};
 use bytes::{Bytes, BytesMut};
 use futures::stream::MapErr as StreamMapErr;
 use futures::{Future, Poll, Sink, StartSend, Stream};
+use handshake::Resumption;
 use libp2p_core::{Multiaddr, PeerId, PublicKey};
+use libp2p_core::transport::AuthenticatedOutput;
+use libp2p_core::upgrade::MessageLimits;
 use ring::rand::SystemRandom;
 use ring::signature::{Ed25519KeyPair, RSAKeyPair};
 use rw_stream_sink::RwStreamSink;
@@ -121,6 +154,8 @@ mod algo_support;
 mod codec;
 mod error;
 mod handshake;
+mod keystore;
+mod session;
 mod structs_proto;
 mod stream_cipher;
 
@@ -135,7 +170,10 @@ pub struct SecioConfig {
     pub(crate) key: SecioKeyPair,
     pub(crate) agreements_prop: Option<String>,
     pub(crate) ciphers_prop: Option<String>,
-    pub(crate) digests_prop: Option<String>
+    pub(crate) digests_prop: Option<String>,
+    pub(crate) max_frame_length: usize,
+    pub(crate) key_confirmation: bool,
+    pub(crate) session_cache: Option<SessionCache>,
 }
 
 impl SecioConfig {
@@ -145,10 +183,43 @@ impl SecioConfig {
             key: kp,
             agreements_prop: None,
             ciphers_prop: None,
-            digests_prop: None
+            digests_prop: None,
+            max_frame_length: 8 * 1024 * 1024,
+            key_confirmation: false,
+            session_cache: None,
         }
     }
 
+    /// Override the maximum length of a frame, both during the handshake and afterwards. A remote
+    /// that announces a larger frame produces an error. Defaults to 8 MiB.
+    pub fn max_frame_length(mut self, len: usize) -> Self {
+        self.max_frame_length = len;
+        self
+    }
+
+    /// Enables or disables the exchange of key-confirmation messages at the end of the handshake.
+    /// Disabled by default.
+    ///
+    /// > **Note**: Key confirmation is an extension of the secio protocol. It must be enabled
+    /// >           either on both sides or on neither.
+    pub fn key_confirmation(mut self, enabled: bool) -> Self {
+        self.key_confirmation = enabled;
+        self
+    }
+
+    /// Stores the sessions in `cache`, so that a later connection to the same address can resume
+    /// them without performing the key exchange. The same cache should be shared by all the
+    /// connections of the node.
+    pub fn session_cache(mut self, cache: SessionCache) -> Self {
+        self.session_cache = Some(cache);
+        self
+    }
+
+    /// Take the maximum length of a frame from `limits`.
+    pub fn with_message_limits(self, limits: &MessageLimits) -> Self {
+        self.max_frame_length(limits.max_for(b"/secio/1.0.0"))
+    }
+
     /// Override the default set of supported key agreement algorithms.
     pub fn key_agreements<'a, I>(mut self, xs: I) -> Self
     where
@@ -277,14 +348,7 @@ impl SecioKeyPair {
     where
         K: AsRef<[u8]>,
     {
-        // See ECPrivateKey in https://tools.ietf.org/html/rfc5915
-        let obj: Vec<DerObject> =
-            FromDerEncoded::with_der_encoded(key.as_ref()).map_err(|err| err.to_string())?;
-        let priv_key_obj = obj.into_iter()
-            .nth(1)
-            .ok_or_else(|| "Not enough elements in DER".to_string())?;
-        let private_key: Vec<u8> =
-            FromDerObject::from_der_object(priv_key_obj).map_err(|err| err.to_string())?;
+        let private_key = secp256k1_der_to_raw(key.as_ref())?;
         SecioKeyPair::secp256k1_raw_key(&private_key)
     }
 
@@ -311,7 +375,20 @@ impl SecioKeyPair {
         self.to_public_key().into_peer_id()
     }
 
-    // TODO: method to save generated key on disk?
+}
+
+/// Extracts the raw private key from a secp256k1 private key in DER format.
+#[cfg(feature = "secp256k1")]
+fn secp256k1_der_to_raw(key: &[u8]) -> Result<Vec<u8>, Box<Error + Send + Sync>> {
+    // See ECPrivateKey in https://tools.ietf.org/html/rfc5915
+    let obj: Vec<DerObject> =
+        FromDerEncoded::with_der_encoded(key).map_err(|err| err.to_string())?;
+    let priv_key_obj = obj.into_iter()
+        .nth(1)
+        .ok_or_else(|| "Not enough elements in DER".to_string())?;
+    let private_key: Vec<u8> =
+        FromDerObject::from_der_object(priv_key_obj).map_err(|err| err.to_string())?;
+    Ok(private_key)
 }
 
 // Inner content of `SecioKeyPair`.
@@ -339,10 +416,23 @@ where
     pub stream: RwStreamSink<StreamMapErr<SecioMiddleware<S>, fn(SecioError) -> IoError>>,
     /// The public key of the remote.
     pub remote_key: PublicKey,
-    /// Ephemeral public key used during the negotiation.
+    /// Ephemeral public key used during the negotiation. Empty if a previous session has been
+    /// resumed.
     pub ephemeral_public_key: Vec<u8>,
 }
 
+impl<S> AuthenticatedOutput for SecioOutput<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Stream = RwStreamSink<StreamMapErr<SecioMiddleware<S>, fn(SecioError) -> IoError>>;
+
+    #[inline]
+    fn into_authenticated(self) -> (PeerId, Self::Stream) {
+        (self.remote_key.into_peer_id(), self.stream)
+    }
+}
+
 impl<S> libp2p_core::ConnectionUpgrade<S> for SecioConfig
 where
     S: AsyncRead + AsyncWrite + Send + 'static, // TODO: 'static :(
@@ -362,13 +452,19 @@ where
         self,
         incoming: S,
         _: (),
-        _: libp2p_core::Endpoint,
-        _: &Multiaddr,
+        endpoint: libp2p_core::Endpoint,
+        remote_addr: &Multiaddr,
     ) -> Self::Future {
         debug!("Starting secio upgrade");
 
-        let fut = SecioMiddleware::handshake(incoming, self);
-        let wrapped = fut.map(|(stream_sink, pubkey, ephemeral)| {
+        let resumption = match endpoint {
+            libp2p_core::Endpoint::Dialer => Resumption::Dialer(remote_addr.clone()),
+            libp2p_core::Endpoint::Listener => Resumption::Listener,
+        };
+
+        let fut = handshake::handshake(incoming, self, resumption);
+        let wrapped = fut.map(|(inner, pubkey, ephemeral)| {
+            let stream_sink = SecioMiddleware { inner };
             let mapped = stream_sink.map_err(map_err as fn(_) -> _);
             SecioOutput {
                 stream: RwStreamSink::new(mapped),
@@ -409,7 +505,7 @@ where
     where
         S: 'a,
     {
-        let fut = handshake::handshake(socket, config).map(|(inner, pubkey, ephemeral)| {
+        let fut = handshake::handshake(socket, config, Resumption::Disabled).map(|(inner, pubkey, ephemeral)| {
             let inner = SecioMiddleware { inner };
             (inner, pubkey, ephemeral)
         });
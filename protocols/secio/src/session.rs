@@ -0,0 +1,293 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Resumption of the sessions established with secio.
+//!
+//! After a full handshake, both sides derive a session secret from the shared key material and
+//! store it in their `SessionCache`, indexed by a ticket that is itself derived from the secret.
+//! The dialer also remembers which ticket belongs to the address it dialed.
+//!
+//! When dialing the same address again, the dialer includes the ticket in its proposition,
+//! together with a proof that it knows the secret. If the listener still has the ticket, it
+//! answers with a proof of its own instead of an exchange, and both sides derive the keys of the
+//! connection from the secret and from the fresh nonces of the propositions. The ephemeral key
+//! exchange and the signatures are skipped. Otherwise the listener answers with a regular
+//! exchange and the handshake continues as usual.
+//!
+//! A ticket can only be used once: both sides remove it as soon as it is offered or accepted, and
+//! replace it with a ticket derived from the new nonces once the handshake succeeds. A replayed
+//! proposition therefore finds no ticket to resume.
+
+use libp2p_core::{Multiaddr, Runtime};
+use ring::{digest, hmac};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// Number of the field of the proposition that carries the offer of the dialer. Other
+/// implementations ignore the fields that they don't know, and proceed with a full handshake.
+pub(crate) const OFFER_FIELD: u32 = 100;
+
+/// Length of a ticket.
+const TICKET_LEN: usize = 16;
+
+/// Cache of the sessions established by secio, shared between all the connections that use the
+/// same `SecioConfig`.
+///
+/// Cloning a `SessionCache` produces a handle to the same cache.
+#[derive(Clone)]
+pub struct SessionCache {
+    inner: Arc<Mutex<SessionCacheInner>>,
+    lifetime: Duration,
+    // Runtime whose clock determines when the sessions expire.
+    runtime: Runtime,
+}
+
+struct SessionCacheInner {
+    // Ticket to offer when dialing each address.
+    by_addr: HashMap<Multiaddr, Vec<u8>>,
+    // Sessions that can be resumed, indexed by ticket.
+    sessions: HashMap<Vec<u8>, Session>,
+}
+
+/// A session that can be resumed.
+#[derive(Clone)]
+pub(crate) struct Session {
+    /// Ticket of the session.
+    pub ticket: Vec<u8>,
+    /// Secret shared with the remote.
+    pub secret: Vec<u8>,
+    /// Protobuf encoding of the public key of the remote.
+    pub remote_key: Vec<u8>,
+    // Moment after which the session can't be resumed anymore.
+    expires: Instant,
+}
+
+impl SessionCache {
+    /// Creates an empty cache. A session can be resumed during `lifetime` after the handshake
+    /// that established it, as measured by the clock of `runtime`.
+    pub fn new(lifetime: Duration, runtime: Runtime) -> SessionCache {
+        SessionCache {
+            inner: Arc::new(Mutex::new(SessionCacheInner {
+                by_addr: HashMap::new(),
+                sessions: HashMap::new(),
+            })),
+            lifetime,
+            runtime,
+        }
+    }
+
+    /// Returns the duration during which a session can be resumed.
+    #[inline]
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Returns the number of sessions that can currently be resumed.
+    pub fn len(&self) -> usize {
+        let now = self.runtime.now();
+        let inner = self.inner.lock().expect("the lock is never poisoned");
+        inner.sessions.values().filter(|s| s.expires > now).count()
+    }
+
+    /// Returns true if no session can currently be resumed.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets all the sessions.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().expect("the lock is never poisoned");
+        inner.by_addr.clear();
+        inner.sessions.clear();
+    }
+
+    /// Removes and returns the session to offer when dialing `addr`.
+    pub(crate) fn take_for_addr(&self, addr: &Multiaddr) -> Option<Session> {
+        let mut inner = self.inner.lock().expect("the lock is never poisoned");
+        let ticket = inner.by_addr.remove(addr)?;
+        inner.sessions.remove(&ticket).filter(|s| s.expires > self.runtime.now())
+    }
+
+    /// Returns the session of `ticket`, without removing it.
+    pub(crate) fn get(&self, ticket: &[u8]) -> Option<Session> {
+        let inner = self.inner.lock().expect("the lock is never poisoned");
+        inner.sessions.get(ticket).filter(|s| s.expires > self.runtime.now()).cloned()
+    }
+
+    /// Removes the session of `ticket`. Returns false if it had already been removed, for
+    /// example by another connection that resumed it.
+    pub(crate) fn remove(&self, ticket: &[u8]) -> bool {
+        let mut inner = self.inner.lock().expect("the lock is never poisoned");
+        inner.sessions.remove(ticket).is_some()
+    }
+
+    /// Stores a session established with the remote whose public key is `remote_key`. If `addr`
+    /// is set, the session will be offered when dialing this address.
+    pub(crate) fn insert(&self, addr: Option<Multiaddr>, secret: Vec<u8>, remote_key: Vec<u8>) {
+        let now = self.runtime.now();
+        let ticket = ticket(&secret);
+        let mut guard = self.inner.lock().expect("the lock is never poisoned");
+        let inner = &mut *guard;
+        inner.sessions.retain(|_, s| s.expires > now);
+        {
+            let sessions = &inner.sessions;
+            inner.by_addr.retain(|_, ticket| sessions.contains_key(ticket));
+        }
+        if let Some(addr) = addr {
+            inner.by_addr.insert(addr, ticket.clone());
+        }
+        inner.sessions.insert(ticket.clone(), Session {
+            ticket,
+            secret,
+            remote_key,
+            expires: now + self.lifetime,
+        });
+    }
+}
+
+impl fmt::Debug for SessionCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SessionCache")
+            .field("lifetime", &self.lifetime)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Computes the ticket of a session secret.
+fn ticket(secret: &[u8]) -> Vec<u8> {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(b"secio ticket");
+    ctx.update(secret);
+    ctx.finish().as_ref()[.. TICKET_LEN].to_vec()
+}
+
+// Computes the HMAC of the concatenation of `parts` with `secret` as key.
+fn sign(secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret);
+    let mut ctx = hmac::SigningContext::with_key(&key);
+    for part in parts {
+        ctx.update(part);
+    }
+    ctx.sign().as_ref().to_vec()
+}
+
+/// Builds the offer that the dialer puts in its proposition.
+pub(crate) fn offer(session: &Session, local_nonce: &[u8]) -> Vec<u8> {
+    let mut out = session.ticket.clone();
+    out.extend(sign(&session.secret, &[b"secio offer", local_nonce]));
+    out
+}
+
+/// Splits an offer into its ticket and its proof.
+pub(crate) fn parse_offer(offer: &[u8]) -> Option<(&[u8], &[u8])> {
+    if offer.len() != TICKET_LEN + digest::SHA256.output_len {
+        return None;
+    }
+    Some(offer.split_at(TICKET_LEN))
+}
+
+/// Checks the proof of an offer sent by a remote.
+pub(crate) fn verify_offer(session: &Session, remote_nonce: &[u8], proof: &[u8]) -> bool {
+    let key = hmac::SigningKey::new(&digest::SHA256, &session.secret);
+    let mut data = b"secio offer".to_vec();
+    data.extend_from_slice(remote_nonce);
+    hmac::verify_with_own_key(&key, &data, proof).is_ok()
+}
+
+/// Builds the proof with which the listener accepts an offer. It covers both propositions.
+pub(crate) fn accept_proof(session: &Session, dialer_prop: &[u8], listener_prop: &[u8]) -> Vec<u8> {
+    sign(&session.secret, &[b"secio accept", dialer_prop, listener_prop])
+}
+
+/// Checks the proof with which a remote listener accepted our offer.
+pub(crate) fn verify_accept(session: &Session, dialer_prop: &[u8], listener_prop: &[u8], proof: &[u8])
+    -> bool
+{
+    let key = hmac::SigningKey::new(&digest::SHA256, &session.secret);
+    let mut data = b"secio accept".to_vec();
+    data.extend_from_slice(dialer_prop);
+    data.extend_from_slice(listener_prop);
+    hmac::verify_with_own_key(&key, &data, proof).is_ok()
+}
+
+/// Derives the secret of a session from the key material of a full handshake.
+pub(crate) fn session_secret(key_material: &[u8]) -> Vec<u8> {
+    sign(key_material, &[b"secio session"])
+}
+
+/// Derives the key material of a resumed connection. The nonces must be in the same order on
+/// both sides.
+pub(crate) fn resumed_key_material(session: &Session, first_nonce: &[u8], second_nonce: &[u8]) -> Vec<u8> {
+    sign(&session.secret, &[b"secio resume", first_nonce, second_nonce])
+}
+
+/// Derives the secret that replaces the one of a session once it has been resumed.
+pub(crate) fn next_secret(session: &Session, first_nonce: &[u8], second_nonce: &[u8]) -> Vec<u8> {
+    sign(&session.secret, &[b"secio next", first_nonce, second_nonce])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{offer, parse_offer, verify_offer, SessionCache};
+    use libp2p_core::Runtime;
+    use libp2p_core::runtime::ManualTimer;
+    use std::time::Duration;
+
+    #[test]
+    fn tickets_are_single_use() {
+        let cache = SessionCache::new(Duration::from_secs(60), Runtime::default());
+        let addr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        cache.insert(Some(addr), vec![7; 32], vec![1, 2, 3]);
+        assert_eq!(cache.len(), 1);
+
+        let addr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        let session = cache.take_for_addr(&addr).unwrap();
+        assert!(cache.take_for_addr(&addr).is_none());
+        assert!(cache.is_empty());
+
+        let offer = offer(&session, &[9; 16]);
+        let (ticket, proof) = parse_offer(&offer).unwrap();
+        assert_eq!(ticket, &session.ticket[..]);
+        assert!(verify_offer(&session, &[9; 16], proof));
+        assert!(!verify_offer(&session, &[8; 16], proof));
+    }
+
+    #[test]
+    fn expired_sessions_not_resumed() {
+        let timer = ManualTimer::new();
+        let runtime = Runtime::deterministic(timer.clone());
+        let cache = SessionCache::new(Duration::from_secs(60), runtime);
+        let addr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        cache.insert(Some(addr), vec![7; 32], vec![1, 2, 3]);
+
+        timer.advance(Duration::from_secs(59));
+        assert_eq!(cache.len(), 1);
+
+        timer.advance(Duration::from_secs(1));
+        assert!(cache.is_empty());
+        let addr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+        assert!(cache.take_for_addr(&addr).is_none());
+    }
+}
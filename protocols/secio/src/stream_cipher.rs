@@ -23,14 +23,22 @@ use aes_ctr::stream_cipher::generic_array::GenericArray;
 use aes_ctr::stream_cipher::{NewFixStreamCipher, LoopError, StreamCipherCore};
 use aes_ctr::{Aes128Ctr, Aes256Ctr};
 use ctr::Ctr128;
+use ring::aead;
 use twofish::Twofish;
 
 /// Possible encryption ciphers.
+///
+/// `Aes128Gcm`, `Aes256Gcm` and `ChaCha20Poly1305` are authenticated ciphers. When one of them is
+/// selected, the frames are authenticated by the cipher itself and the negotiated digest isn't
+/// used to compute an HMAC.
 #[derive(Clone, Copy, Debug)]
 pub enum Cipher {
     Aes128,
     Aes256,
     TwofishCtr,
+    Aes128Gcm,
+    Aes256Gcm,
+    ChaCha20Poly1305,
     Null,
 }
 
@@ -41,6 +49,9 @@ impl Cipher {
             Cipher::Aes128 => 16,
             Cipher::Aes256 => 32,
             Cipher::TwofishCtr => 32,
+            Cipher::Aes128Gcm => 16,
+            Cipher::Aes256Gcm => 32,
+            Cipher::ChaCha20Poly1305 => 32,
             Cipher::Null => 0,
         }
     }
@@ -50,9 +61,22 @@ impl Cipher {
     pub fn iv_size(&self) -> usize {
         match self {
             Cipher::Aes128 | Cipher::Aes256 | Cipher::TwofishCtr => 16,
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::ChaCha20Poly1305 => 12,
             Cipher::Null => 0
         }
     }
+
+    /// Returns the AEAD algorithm if this is an authenticated cipher, or `None` if this is a
+    /// stream cipher.
+    #[inline]
+    pub(crate) fn aead_algorithm(&self) -> Option<&'static aead::Algorithm> {
+        match self {
+            Cipher::Aes128Gcm => Some(&aead::AES_128_GCM),
+            Cipher::Aes256Gcm => Some(&aead::AES_256_GCM),
+            Cipher::ChaCha20Poly1305 => Some(&aead::CHACHA20_POLY1305),
+            Cipher::Aes128 | Cipher::Aes256 | Cipher::TwofishCtr | Cipher::Null => None,
+        }
+    }
 }
 
 /// A no-op cipher which does not encrypt or decrypt at all.
@@ -67,12 +91,20 @@ impl StreamCipherCore for NullCipher {
 }
 
 /// Returns your stream cipher depending on `Cipher`.
+///
+/// # Panic
+///
+/// Panics if `key_size` is an authenticated cipher.
 #[cfg(not(all(feature = "aes-all", any(target_arch = "x86_64", target_arch = "x86"))))]
 pub fn ctr(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
     ctr_int(key_size, key, iv)
 }
  
 /// Returns your stream cipher depending on `Cipher`.
+///
+/// # Panic
+///
+/// Panics if `key_size` is an authenticated cipher.
 #[cfg(all(feature = "aes-all", any(target_arch = "x86_64", target_arch = "x86")))]
 pub fn ctr(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
     if *aes_alt::AES_NI {
@@ -122,6 +154,9 @@ mod aes_alt {
                 GenericArray::from_slice(iv),
             )),
             Cipher::Null => Box::new(NullCipher),
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::ChaCha20Poly1305 => {
+                unreachable!("authenticated ciphers are not stream ciphers")
+            },
         }
     }
 
@@ -143,6 +178,9 @@ fn ctr_int(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
             GenericArray::from_slice(iv),
         )),
         Cipher::Null => Box::new(NullCipher),
+        Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::ChaCha20Poly1305 => {
+            unreachable!("authenticated ciphers are not stream ciphers")
+        },
     }
 }
 
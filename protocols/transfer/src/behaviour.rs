@@ -0,0 +1,482 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::prelude::*;
+use handler::{TransferHandler, TransferHandlerEvent, TransferHandlerIn};
+use libp2p_core::nodes::behaviour::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::nodes::swarm::ConnectedPoint;
+use libp2p_core::upgrade::MessageLimits;
+use libp2p_core::PeerId;
+use parking_lot::Mutex;
+use protocol::{ResumeTable, TransferSource};
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::fmt;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Identifier of an outbound transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TransferId(pub(crate) usize);
+
+impl fmt::Display for TransferId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Configuration of a `Transfer` behaviour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferConfig {
+    /// Size of the chunks the data is split into, in bytes.
+    pub(crate) chunk_size: usize,
+    /// Duration after which a connection without any transfer in progress is closed.
+    pub(crate) idle_timeout: Duration,
+    /// Limits on the frames sent by the remotes.
+    pub(crate) limits: MessageLimits,
+}
+
+impl TransferConfig {
+    /// Builds the default configuration. Data is sent in chunks of 64 kiB, the frames of the
+    /// remotes are bounded by the default `MessageLimits`, and idle connections are closed after
+    /// 10 seconds.
+    #[inline]
+    pub fn new() -> TransferConfig {
+        TransferConfig {
+            chunk_size: 64 * 1024,
+            idle_timeout: Duration::from_secs(10),
+            limits: MessageLimits::new(),
+        }
+    }
+
+    /// Sets the size of the chunks the data is split into.
+    ///
+    /// A value of `0` is treated as `1`.
+    #[inline]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = if chunk_size == 0 { 1 } else { chunk_size };
+        self
+    }
+
+    /// Sets the duration after which a connection without any transfer in progress is closed.
+    #[inline]
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Replaces the limits applied to the frames of the remotes.
+    ///
+    /// > **Note**: The limit must be larger than the chunk size of the remotes, otherwise their
+    /// >           transfers fail.
+    #[inline]
+    pub fn with_message_limits(mut self, limits: &MessageLimits) -> Self {
+        self.limits = limits.clone();
+        self
+    }
+}
+
+impl Default for TransferConfig {
+    #[inline]
+    fn default() -> Self {
+        TransferConfig::new()
+    }
+}
+
+/// Direction of a transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferDirection {
+    /// The remote sends us data.
+    Inbound,
+    /// We send data to the remote with the given transfer.
+    Outbound(TransferId),
+}
+
+/// Event generated by the `Transfer` behaviour.
+#[derive(Debug)]
+pub enum TransferEvent {
+    /// A transfer has been accepted by the receiver. `offset` is the number of bytes that had
+    /// already been received by a previous transfer with the same name, and that are skipped.
+    Started {
+        peer_id: PeerId,
+        name: String,
+        direction: TransferDirection,
+        total_len: u64,
+        offset: u64,
+    },
+    /// We received a chunk of data. `offset` is the position of the chunk in the data.
+    Received {
+        peer_id: PeerId,
+        name: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Progress of a transfer. Generated after each chunk, in both directions.
+    Progress {
+        peer_id: PeerId,
+        name: String,
+        direction: TransferDirection,
+        /// Offset of the end of the data transferred so far.
+        transferred: u64,
+        total_len: u64,
+    },
+    /// A transfer has finished.
+    Finished {
+        peer_id: PeerId,
+        name: String,
+        direction: TransferDirection,
+        /// Number of bytes transferred, excluding the ones skipped because of a resume.
+        bytes: u64,
+        /// Time between the start and the end of the transfer.
+        duration: Duration,
+    },
+    /// A transfer has failed. Inbound transfers can be resumed by sending the data again with
+    /// the same name.
+    Failed {
+        peer_id: PeerId,
+        name: String,
+        direction: TransferDirection,
+        error: IoError,
+    },
+}
+
+/// State of a transfer in progress.
+#[derive(Debug, Clone)]
+struct TransferState {
+    name: String,
+    total_len: u64,
+    /// Offset the transfer started from.
+    offset: u64,
+    /// Offset of the end of the data transferred so far.
+    transferred: u64,
+    /// When the receiver accepted the transfer, if it did.
+    started: Option<Instant>,
+}
+
+/// `NetworkBehaviour` that transfers large amounts of data over dedicated substreams.
+///
+/// The data is sent in chunks, each with a checksum verified by the receiver. The receiver
+/// remembers how many bytes it has received for each transfer name, so that a transfer that has
+/// been interrupted resumes where it stopped when the data is sent again with the same name.
+pub struct Transfer<TSubstream> {
+    config: TransferConfig,
+    /// Number of bytes received for each transfer name. Shared with the handlers.
+    resume: ResumeTable,
+    /// Id of the next outbound transfer.
+    next_id: usize,
+    /// Peers we are connected to.
+    connected: FnvHashSet<PeerId>,
+    /// Outbound transfers in progress.
+    outbound: FnvHashMap<TransferId, (PeerId, TransferState)>,
+    /// Inbound transfers in progress, by peer and name.
+    inbound: FnvHashMap<(PeerId, String), TransferState>,
+    /// Actions to return from `poll`.
+    actions: VecDeque<NetworkBehaviourAction<TransferHandlerIn, TransferEvent>>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> Transfer<TSubstream> {
+    /// Creates a new `Transfer` behaviour.
+    pub fn new(config: TransferConfig) -> Self {
+        Transfer {
+            config,
+            resume: Arc::new(Mutex::new(FnvHashMap::default())),
+            next_id: 0,
+            connected: FnvHashSet::default(),
+            outbound: FnvHashMap::default(),
+            inbound: FnvHashMap::default(),
+            actions: VecDeque::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Sends the data of `source` to a peer. The outcome is reported with events whose
+    /// direction is `TransferDirection::Outbound` with the returned id.
+    ///
+    /// > **Note**: This method doesn't dial the peer. If we are not connected to it, the
+    /// >           transfer fails immediately.
+    pub fn send(&mut self, peer_id: &PeerId, name: String, source: Box<TransferSource>) -> TransferId {
+        let id = TransferId(self.next_id);
+        self.next_id += 1;
+
+        if !self.connected.contains(peer_id) {
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(TransferEvent::Failed {
+                peer_id: peer_id.clone(),
+                name,
+                direction: TransferDirection::Outbound(id),
+                error: IoError::new(IoErrorKind::NotConnected, "not connected to the peer"),
+            }));
+            return id;
+        }
+
+        let state = TransferState {
+            name: name.clone(),
+            total_len: source.len(),
+            offset: 0,
+            transferred: 0,
+            started: None,
+        };
+        self.outbound.insert(id, (peer_id.clone(), state));
+        self.actions.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id: peer_id.clone(),
+            event: TransferHandlerIn::Send { id, name, source },
+        });
+        id
+    }
+
+    /// Returns the number of bytes received so far for a transfer name. A transfer with this
+    /// name resumes from there.
+    #[inline]
+    pub fn resume_offset(&self, name: &str) -> Option<u64> {
+        self.resume.lock().get(name).cloned()
+    }
+
+    /// Sets the number of bytes already received for a transfer name, for example because they
+    /// have been stored on disk by a previous run. `None` makes the next transfer with this name
+    /// start from the beginning.
+    pub fn set_resume_offset(&mut self, name: String, offset: Option<u64>) {
+        let mut resume = self.resume.lock();
+        match offset {
+            Some(offset) => { resume.insert(name, offset); },
+            None => { resume.remove(&name); },
+        }
+    }
+
+    /// Processes an event of a handler.
+    fn handle_event(&mut self, peer_id: PeerId, event: TransferHandlerEvent) -> Option<TransferEvent> {
+        match event {
+            TransferHandlerEvent::InboundStarted { name, total_len, offset } => {
+                self.inbound.insert((peer_id.clone(), name.clone()), TransferState {
+                    name: name.clone(),
+                    total_len,
+                    offset,
+                    transferred: offset,
+                    started: Some(Instant::now()),
+                });
+                Some(TransferEvent::Started { peer_id, name, direction: TransferDirection::Inbound, total_len, offset })
+            },
+            TransferHandlerEvent::InboundChunk { name, offset, data } => {
+                let state = self.inbound.get_mut(&(peer_id.clone(), name.clone()))?;
+                state.transferred = offset + data.len() as u64;
+                self.resume.lock().insert(name.clone(), state.transferred);
+                self.actions.push_back(NetworkBehaviourAction::GenerateEvent(TransferEvent::Progress {
+                    peer_id: peer_id.clone(),
+                    name: name.clone(),
+                    direction: TransferDirection::Inbound,
+                    transferred: state.transferred,
+                    total_len: state.total_len,
+                }));
+                Some(TransferEvent::Received { peer_id, name, offset, data })
+            },
+            TransferHandlerEvent::InboundFinished { name } => {
+                let state = self.inbound.remove(&(peer_id.clone(), name.clone()))?;
+                self.resume.lock().remove(&name);
+                Some(finished_event(peer_id, TransferDirection::Inbound, state))
+            },
+            TransferHandlerEvent::InboundFailed { name, error } => {
+                self.inbound.remove(&(peer_id.clone(), name.clone()));
+                Some(TransferEvent::Failed { peer_id, name, direction: TransferDirection::Inbound, error })
+            },
+            TransferHandlerEvent::OutboundStarted { id, offset } => {
+                let &mut (_, ref mut state) = self.outbound.get_mut(&id)?;
+                state.offset = offset;
+                state.transferred = offset;
+                state.started = Some(Instant::now());
+                Some(TransferEvent::Started {
+                    peer_id,
+                    name: state.name.clone(),
+                    direction: TransferDirection::Outbound(id),
+                    total_len: state.total_len,
+                    offset,
+                })
+            },
+            TransferHandlerEvent::OutboundProgress { id, sent } => {
+                let &mut (_, ref mut state) = self.outbound.get_mut(&id)?;
+                state.transferred = sent;
+                Some(TransferEvent::Progress {
+                    peer_id,
+                    name: state.name.clone(),
+                    direction: TransferDirection::Outbound(id),
+                    transferred: sent,
+                    total_len: state.total_len,
+                })
+            },
+            TransferHandlerEvent::OutboundFinished { id } => {
+                let (_, state) = self.outbound.remove(&id)?;
+                Some(finished_event(peer_id, TransferDirection::Outbound(id), state))
+            },
+            TransferHandlerEvent::OutboundFailed { id, error } => {
+                let (_, state) = self.outbound.remove(&id)?;
+                Some(TransferEvent::Failed { peer_id, name: state.name, direction: TransferDirection::Outbound(id), error })
+            },
+        }
+    }
+}
+
+// Builds the event reporting that a transfer has finished.
+fn finished_event(peer_id: PeerId, direction: TransferDirection, state: TransferState) -> TransferEvent {
+    TransferEvent::Finished {
+        peer_id,
+        name: state.name,
+        direction,
+        bytes: state.transferred - state.offset,
+        duration: state.started.map(|started| started.elapsed()).unwrap_or(Duration::new(0, 0)),
+    }
+}
+
+impl<TSubstream> fmt::Debug for Transfer<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Transfer")
+            .field("config", &self.config)
+            .field("outbound", &self.outbound.len())
+            .field("inbound", &self.inbound.len())
+            .finish()
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Transfer<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type ConnectionHandler = TransferHandler<TSubstream>;
+    type OutEvent = TransferEvent;
+
+    #[inline]
+    fn new_handler(&self) -> Self::ConnectionHandler {
+        TransferHandler::new(self.config.clone(), self.resume.clone())
+    }
+
+    #[inline]
+    fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+        self.connected.insert(peer_id);
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        self.connected.remove(peer_id);
+
+        let failed = self.outbound.iter()
+            .filter(|&(_, &(ref p, _))| p == peer_id)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in failed {
+            let (_, state) = self.outbound.remove(&id).expect("the id comes from the map ; qed");
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(TransferEvent::Failed {
+                peer_id: peer_id.clone(),
+                name: state.name,
+                direction: TransferDirection::Outbound(id),
+                error: IoError::new(IoErrorKind::ConnectionAborted, "connection closed"),
+            }));
+        }
+
+        let failed = self.inbound.keys()
+            .filter(|&&(ref p, _)| p == peer_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in failed {
+            self.inbound.remove(&key);
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(TransferEvent::Failed {
+                peer_id: peer_id.clone(),
+                name: key.1,
+                direction: TransferDirection::Inbound,
+                error: IoError::new(IoErrorKind::ConnectionAborted, "connection closed"),
+            }));
+        }
+    }
+
+    fn inject_node_event(&mut self, peer_id: PeerId, event: TransferHandlerEvent) {
+        if let Some(event) = self.handle_event(peer_id, event) {
+            self.actions.push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+    }
+
+    fn poll(&mut self, _: &mut PollParameters) -> Async<NetworkBehaviourAction<TransferHandlerIn, TransferEvent>> {
+        match self.actions.pop_front() {
+            Some(action) => Async::Ready(action),
+            None => Async::NotReady,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+    use std::io::Cursor;
+
+    #[test]
+    fn inbound_progress_and_resume() {
+        let mut behaviour = Transfer::<Cursor<Vec<u8>>>::new(TransferConfig::new());
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let name = "file.bin".to_owned();
+
+        behaviour.inject_node_event(peer_id.clone(), TransferHandlerEvent::InboundStarted {
+            name: name.clone(), total_len: 10, offset: 0,
+        });
+        behaviour.inject_node_event(peer_id.clone(), TransferHandlerEvent::InboundChunk {
+            name: name.clone(), offset: 0, data: vec![0; 4],
+        });
+        assert_eq!(behaviour.resume_offset(&name), Some(4));
+
+        // The connection closes, and the transfer can later resume from the 4th byte.
+        behaviour.inject_disconnected(&peer_id, ConnectedPoint::Dialer {
+            address: "/ip4/1.2.3.4/tcp/5".parse().unwrap(),
+        });
+        let events = behaviour.actions.drain(..).collect::<Vec<_>>();
+        match (&events[0], &events[1], &events[2], &events[3]) {
+            (&NetworkBehaviourAction::GenerateEvent(TransferEvent::Started { .. }),
+             &NetworkBehaviourAction::GenerateEvent(TransferEvent::Progress { transferred: 4, total_len: 10, .. }),
+             &NetworkBehaviourAction::GenerateEvent(TransferEvent::Received { offset: 0, .. }),
+             &NetworkBehaviourAction::GenerateEvent(TransferEvent::Failed { .. })) => (),
+            _ => panic!(),
+        }
+        assert_eq!(behaviour.resume_offset(&name), Some(4));
+
+        behaviour.inject_node_event(peer_id.clone(), TransferHandlerEvent::InboundStarted {
+            name: name.clone(), total_len: 10, offset: 4,
+        });
+        behaviour.inject_node_event(peer_id.clone(), TransferHandlerEvent::InboundChunk {
+            name: name.clone(), offset: 4, data: vec![0; 6],
+        });
+        behaviour.inject_node_event(peer_id.clone(), TransferHandlerEvent::InboundFinished { name: name.clone() });
+        match behaviour.actions.pop_back() {
+            Some(NetworkBehaviourAction::GenerateEvent(TransferEvent::Finished { bytes, .. })) => assert_eq!(bytes, 6),
+            _ => panic!(),
+        }
+        assert_eq!(behaviour.resume_offset(&name), None);
+    }
+
+    #[test]
+    fn send_when_not_connected_fails() {
+        let mut behaviour = Transfer::<Cursor<Vec<u8>>>::new(TransferConfig::new());
+        let peer_id = PublicKey::Ed25519(vec![1; 32]).into_peer_id();
+        let id = behaviour.send(&peer_id, "data".to_owned(), Box::new(vec![1, 2, 3]));
+        match behaviour.actions.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(TransferEvent::Failed { direction, .. })) => {
+                assert_eq!(direction, TransferDirection::Outbound(id));
+            },
+            _ => panic!(),
+        }
+    }
+}
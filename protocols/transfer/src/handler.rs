@@ -0,0 +1,326 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use libp2p_core::nodes::connection_handler::{ConnectionHandler, ConnectionHandlerEvent, KeepAlive};
+use libp2p_core::nodes::handled_node::NodeHandlerEndpoint;
+use protocol::{InboundTransfer, OutboundTransfer, ResumeTable, TransferOutput, TransferProtocol, TransferSource};
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::Instant;
+use tokio_io::{AsyncRead, AsyncWrite};
+use {TransferConfig, TransferId};
+
+/// Event sent from the `Transfer` behaviour to its handler.
+pub enum TransferHandlerIn {
+    /// Send data to the remote.
+    Send {
+        id: TransferId,
+        name: String,
+        source: Box<TransferSource>,
+    },
+}
+
+/// Event produced by the handler of the `Transfer` behaviour.
+#[derive(Debug)]
+pub enum TransferHandlerEvent {
+    /// The remote started sending us data.
+    InboundStarted {
+        name: String,
+        total_len: u64,
+        offset: u64,
+    },
+    /// We received a chunk of data.
+    InboundChunk {
+        name: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// All the data of an inbound transfer has been received.
+    InboundFinished {
+        name: String,
+    },
+    /// An inbound transfer failed.
+    InboundFailed {
+        name: String,
+        error: IoError,
+    },
+    /// The remote accepted one of our transfers.
+    OutboundStarted {
+        id: TransferId,
+        offset: u64,
+    },
+    /// Some data of an outbound transfer has been sent.
+    OutboundProgress {
+        id: TransferId,
+        /// Offset of the end of the data sent so far.
+        sent: u64,
+    },
+    /// All the data of an outbound transfer has been sent.
+    OutboundFinished {
+        id: TransferId,
+    },
+    /// An outbound transfer failed.
+    OutboundFailed {
+        id: TransferId,
+        error: IoError,
+    },
+}
+
+/// Connection handler of the `Transfer` behaviour.
+///
+/// Opens a substream for each outbound transfer, and accepts the transfers of the remote.
+pub struct TransferHandler<TSubstream> {
+    config: TransferConfig,
+    /// Number of bytes already received for each transfer name. Shared with the behaviour.
+    resume: ResumeTable,
+    /// Transfers waiting for an outbound substream to be requested.
+    pending_outbound: VecDeque<(TransferId, String, Box<TransferSource>)>,
+    /// Transfers whose outbound substream is being opened or negotiated.
+    negotiating: FnvHashMap<TransferId, Box<TransferSource>>,
+    /// Transfers in progress in each direction.
+    outbound: Vec<(TransferId, OutboundTransfer<TSubstream>)>,
+    inbound: Vec<(String, InboundTransfer<TSubstream>)>,
+    /// Events to report to the behaviour.
+    events: VecDeque<TransferHandlerEvent>,
+    /// Last moment when a transfer was in progress.
+    last_active: Instant,
+    /// True if `shutdown()` has been called.
+    shutting_down: bool,
+}
+
+impl<TSubstream> TransferHandler<TSubstream> {
+    pub(crate) fn new(config: TransferConfig, resume: ResumeTable) -> Self {
+        TransferHandler {
+            config,
+            resume,
+            pending_outbound: VecDeque::new(),
+            negotiating: FnvHashMap::default(),
+            outbound: Vec::new(),
+            inbound: Vec::new(),
+            events: VecDeque::new(),
+            last_active: Instant::now(),
+            shutting_down: false,
+        }
+    }
+
+    /// Returns true if there is no transfer in progress in either direction.
+    fn is_idle(&self) -> bool {
+        self.pending_outbound.is_empty() && self.negotiating.is_empty() &&
+            self.outbound.is_empty() && self.inbound.is_empty()
+    }
+}
+
+impl<TSubstream> Clone for TransferHandler<TSubstream> {
+    #[inline]
+    fn clone(&self) -> Self {
+        TransferHandler::new(self.config.clone(), self.resume.clone())
+    }
+}
+
+impl<TSubstream> ConnectionHandler for TransferHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type InEvent = TransferHandlerIn;
+    type OutEvent = TransferHandlerEvent;
+    type Substream = TSubstream;
+    type Protocol = TransferProtocol;
+    type OutboundOpenInfo = TransferId;
+
+    #[inline]
+    fn listen_protocol(&self) -> Self::Protocol {
+        TransferProtocol::Inbound {
+            resume: self.resume.clone(),
+            limits: self.config.limits.clone(),
+        }
+    }
+
+    fn inject_fully_negotiated(&mut self, output: TransferOutput<TSubstream>, endpoint: NodeHandlerEndpoint<TransferId>) {
+        match (output, endpoint) {
+            (TransferOutput::Inbound { name, total_len, offset, substream }, NodeHandlerEndpoint::Listener) => {
+                let transfer = InboundTransfer::new(substream, offset, total_len);
+                self.inbound.push((name.clone(), transfer));
+                self.events.push_back(TransferHandlerEvent::InboundStarted { name, total_len, offset });
+            },
+            (TransferOutput::Outbound { offset, substream }, NodeHandlerEndpoint::Dialer(id)) => {
+                let source = match self.negotiating.remove(&id) {
+                    Some(source) => source,
+                    None => return,
+                };
+                let transfer = OutboundTransfer::new(substream, source, offset, self.config.chunk_size);
+                self.outbound.push((id, transfer));
+                self.events.push_back(TransferHandlerEvent::OutboundStarted { id, offset });
+            },
+            (TransferOutput::Inbound { .. }, NodeHandlerEndpoint::Dialer(_)) |
+            (TransferOutput::Outbound { .. }, NodeHandlerEndpoint::Listener) => {
+                unreachable!("The inbound upgrade only produces inbound transfers and the outbound \
+                              upgrade only produces outbound transfers")
+            },
+        }
+    }
+
+    #[inline]
+    fn inject_event(&mut self, event: TransferHandlerIn) {
+        match event {
+            TransferHandlerIn::Send { id, name, source } => {
+                self.pending_outbound.push_back((id, name, source));
+            },
+        }
+    }
+
+    fn inject_dial_upgrade_error(&mut self, id: TransferId, error: &IoError) {
+        self.negotiating.remove(&id);
+        let error = IoError::new(error.kind(), error.to_string());
+        self.events.push_back(TransferHandlerEvent::OutboundFailed { id, error });
+    }
+
+    #[inline]
+    fn inject_inbound_closed(&mut self) {
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.is_idle() {
+            KeepAlive::Until(self.last_active + self.config.idle_timeout)
+        } else {
+            KeepAlive::Forever
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.shutting_down = true;
+        for (id, _, _) in self.pending_outbound.drain(..) {
+            let error = IoError::new(IoErrorKind::ConnectionAborted, "connection closed");
+            self.events.push_back(TransferHandlerEvent::OutboundFailed { id, error });
+        }
+    }
+
+    fn poll(&mut self) -> Poll<Option<ConnectionHandlerEvent<Self::Protocol, TransferId, Self::OutEvent>>, IoError> {
+        // Every transfer is polled at most once per call, so that a fast transfer doesn't
+        // prevent the others from progressing.
+        for n in (0 .. self.outbound.len()).rev() {
+            let (id, mut transfer) = self.outbound.swap_remove(n);
+            match transfer.poll() {
+                Ok(Async::Ready(Some(sent))) => {
+                    self.events.push_back(TransferHandlerEvent::OutboundProgress { id, sent });
+                    self.outbound.push((id, transfer));
+                },
+                Ok(Async::Ready(None)) => {
+                    self.events.push_back(TransferHandlerEvent::OutboundFinished { id });
+                },
+                Ok(Async::NotReady) => self.outbound.push((id, transfer)),
+                Err(error) => {
+                    self.events.push_back(TransferHandlerEvent::OutboundFailed { id, error });
+                },
+            }
+        }
+
+        for n in (0 .. self.inbound.len()).rev() {
+            let (name, mut transfer) = self.inbound.swap_remove(n);
+            match transfer.poll() {
+                Ok(Async::Ready(Some((offset, data)))) => {
+                    self.events.push_back(TransferHandlerEvent::InboundChunk { name: name.clone(), offset, data });
+                    self.inbound.push((name, transfer));
+                },
+                Ok(Async::Ready(None)) => {
+                    self.events.push_back(TransferHandlerEvent::InboundFinished { name });
+                },
+                Ok(Async::NotReady) => self.inbound.push((name, transfer)),
+                Err(error) => {
+                    self.events.push_back(TransferHandlerEvent::InboundFailed { name, error });
+                },
+            }
+        }
+
+        if !self.is_idle() {
+            self.last_active = Instant::now();
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(event))));
+        }
+
+        if !self.shutting_down {
+            if let Some((id, name, source)) = self.pending_outbound.pop_front() {
+                let upgrade = TransferProtocol::Outbound {
+                    name,
+                    total_len: source.len(),
+                    limits: self.config.limits.clone(),
+                };
+                self.negotiating.insert(id, source);
+                return Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                    upgrade,
+                    info: id,
+                })));
+            }
+        }
+
+        if self.shutting_down && self.is_idle() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    #[test]
+    fn outbound_transfer_requested() {
+        let resume = Arc::new(Mutex::new(FnvHashMap::default()));
+        let mut handler = TransferHandler::<Cursor<Vec<u8>>>::new(TransferConfig::new(), resume);
+        assert!(handler.is_idle());
+
+        handler.inject_event(TransferHandlerIn::Send {
+            id: TransferId(3),
+            name: "data".to_owned(),
+            source: Box::new(vec![0u8; 100]),
+        });
+        assert_eq!(handler.connection_keep_alive(), KeepAlive::Forever);
+
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                upgrade: TransferProtocol::Outbound { name, total_len, .. },
+                info,
+            }))) => {
+                assert_eq!(name, "data");
+                assert_eq!(total_len, 100);
+                assert_eq!(info, TransferId(3));
+            },
+            _ => panic!(),
+        }
+
+        handler.inject_dial_upgrade_error(TransferId(3), &IoErrorKind::ConnectionRefused.into());
+        match handler.poll() {
+            Ok(Async::Ready(Some(ConnectionHandlerEvent::Custom(TransferHandlerEvent::OutboundFailed { id, .. })))) => {
+                assert_eq!(id, TransferId(3));
+            },
+            _ => panic!(),
+        }
+        assert!(handler.is_idle());
+    }
+}
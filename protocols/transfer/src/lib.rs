@@ -0,0 +1,61 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Bulk data transfer protocol.
+//!
+//! Transfers large amounts of data over a dedicated substream, which makes it a convenient
+//! workload for measuring the goodput of a transport stack. The data is split into chunks, each
+//! with an Adler-32 checksum that the receiver verifies.
+//!
+//! # Usage
+//!
+//! Create a `Transfer` behaviour and pass it to the swarm.
+//!
+//! - Call `send` with a `TransferSource`, for example a `Vec<u8>` or a `File`, to send data to a
+//!   peer we are connected to.
+//! - Both sides receive a `TransferEvent::Started` event once the transfer has been accepted,
+//!   then `TransferEvent::Progress` events after each chunk, and finally either
+//!   `TransferEvent::Finished` or `TransferEvent::Failed`. The receiver obtains the data through
+//!   `TransferEvent::Received` events.
+//!
+//! Transfers are identified by a name. If a transfer is interrupted, sending the data again with
+//! the same name resumes it from the last byte the receiver has received.
+
+extern crate bytes;
+extern crate fnv;
+#[macro_use]
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate parking_lot;
+extern crate tokio_codec;
+extern crate tokio_io;
+extern crate unsigned_varint;
+
+pub use self::behaviour::{Transfer, TransferConfig, TransferDirection, TransferEvent, TransferId};
+pub use self::handler::{TransferHandler, TransferHandlerEvent, TransferHandlerIn};
+pub use self::protocol::{adler32, InboundTransfer, OutboundTransfer, TransferOutput};
+pub use self::protocol::{TransferProtocol, TransferSource, TransferSubstream};
+
+mod behaviour;
+mod handler;
+mod protocol;
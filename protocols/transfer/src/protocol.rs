@@ -0,0 +1,432 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::{future, prelude::*};
+use libp2p_core::upgrade::MessageLimits;
+use libp2p_core::{ConnectionUpgrade, Endpoint, Multiaddr};
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::{cmp, iter};
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use unsigned_varint::codec;
+
+/// Name of the protocol, as negotiated with multistream-select.
+const PROTOCOL_NAME: &[u8] = b"/libp2p-sim/transfer/1.0.0";
+
+/// Number of bytes already received for each transfer name. Used to resume interrupted
+/// transfers. Shared between the behaviour and its handlers.
+pub type ResumeTable = Arc<Mutex<FnvHashMap<String, u64>>>;
+
+/// Data to send with a transfer.
+pub trait TransferSource: Send {
+    /// Returns the total length of the data, in bytes.
+    fn len(&self) -> u64;
+
+    /// Reads the data starting at `offset` into `buf`. Returns the number of bytes read, which
+    /// must only be `0` if `offset` is the end of the data.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, IoError>;
+}
+
+impl TransferSource for Vec<u8> {
+    #[inline]
+    fn len(&self) -> u64 {
+        Vec::len(self) as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, IoError> {
+        let offset = cmp::min(offset, self.len() as u64) as usize;
+        let len = cmp::min(buf.len(), Vec::len(self) - offset);
+        buf[.. len].copy_from_slice(&self[offset .. offset + len]);
+        Ok(len)
+    }
+}
+
+impl TransferSource for File {
+    #[inline]
+    fn len(&self) -> u64 {
+        self.metadata().map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, IoError> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+}
+
+/// Computes the Adler-32 checksum of some data.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest number of bytes that can be processed before `b` overflows.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Frame sent on a transfer substream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Frame {
+    /// Sent by the dialer to start a transfer.
+    Offer { name: String, total_len: u64 },
+    /// Sent by the listener. The dialer must send the data starting at `offset`.
+    Accept { offset: u64 },
+    /// Chunk of data with its checksum.
+    Chunk { checksum: u32, data: Vec<u8> },
+}
+
+impl Frame {
+    /// Encodes the frame.
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Frame::Offer { name, total_len } => {
+                let mut out = vec![0];
+                out.extend_from_slice(&u64_to_bytes(total_len));
+                out.extend_from_slice(name.as_bytes());
+                out
+            },
+            Frame::Accept { offset } => {
+                let mut out = vec![1];
+                out.extend_from_slice(&u64_to_bytes(offset));
+                out
+            },
+            Frame::Chunk { checksum, data } => {
+                let mut out = Vec::with_capacity(5 + data.len());
+                out.push(2);
+                out.extend_from_slice(&u64_to_bytes(u64::from(checksum))[4 ..]);
+                out.extend_from_slice(&data);
+                out
+            },
+        }
+    }
+
+    /// Decodes a frame.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Frame, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidData, "invalid transfer frame");
+        match bytes.first() {
+            Some(&0) if bytes.len() >= 9 => {
+                let name = String::from_utf8(bytes[9 ..].to_vec()).map_err(|_| invalid())?;
+                Ok(Frame::Offer { name, total_len: u64_from_bytes(&bytes[1 .. 9]) })
+            },
+            Some(&1) if bytes.len() == 9 => {
+                Ok(Frame::Accept { offset: u64_from_bytes(&bytes[1 .. 9]) })
+            },
+            Some(&2) if bytes.len() >= 5 => {
+                Ok(Frame::Chunk {
+                    checksum: u64_from_bytes(&bytes[1 .. 5]) as u32,
+                    data: bytes[5 ..].to_vec(),
+                })
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Encodes a number in big endian.
+fn u64_to_bytes(value: u64) -> [u8; 8] {
+    let mut out = [0; 8];
+    for (n, byte) in out.iter_mut().enumerate() {
+        *byte = (value >> (56 - 8 * n)) as u8;
+    }
+    out
+}
+
+// Decodes a big endian number of at most 8 bytes.
+fn u64_from_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &b| (value << 8) | u64::from(b))
+}
+
+/// Substream of a transfer, once negotiated.
+pub type TransferSubstream<TSubstream> = Framed<TSubstream, codec::UviBytes<Vec<u8>>>;
+
+/// Upgrade used by the transfer handler.
+///
+/// As a dialer, offers a transfer to the remote and obtains the offset to resume from. As a
+/// listener, receives the offer and answers with the number of bytes already received for this
+/// transfer name.
+#[derive(Debug, Clone)]
+pub enum TransferProtocol {
+    /// Accepts a transfer from the remote.
+    Inbound {
+        resume: ResumeTable,
+        /// Bounds the size of the frames sent by the remote.
+        limits: MessageLimits,
+    },
+    /// Offers a transfer to the remote.
+    Outbound {
+        name: String,
+        total_len: u64,
+        /// Bounds the size of the frames sent by the remote.
+        limits: MessageLimits,
+    },
+}
+
+/// Output of the `TransferProtocol`.
+pub enum TransferOutput<TSubstream> {
+    /// We are the listener and accepted a transfer.
+    Inbound {
+        name: String,
+        total_len: u64,
+        /// Offset from which the remote sends the data.
+        offset: u64,
+        substream: TransferSubstream<TSubstream>,
+    },
+    /// We are the dialer and the remote accepted our transfer.
+    Outbound {
+        /// Offset from which we must send the data.
+        offset: u64,
+        substream: TransferSubstream<TSubstream>,
+    },
+}
+
+impl<TSubstream> ConnectionUpgrade<TSubstream> for TransferProtocol
+where
+    TSubstream: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    type Output = TransferOutput<TSubstream>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    fn upgrade(self, socket: TSubstream, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        let max_message_size = match self {
+            TransferProtocol::Inbound { ref limits, .. } |
+            TransferProtocol::Outbound { ref limits, .. } => limits.max_for(PROTOCOL_NAME),
+        };
+        let mut codec = codec::UviBytes::<Vec<u8>>::default();
+        codec.set_max_len(max_message_size);
+        let framed = Framed::new(socket, codec);
+
+        match self {
+            TransferProtocol::Inbound { resume, .. } => {
+                let future = framed
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .and_then(move |(msg, framed)| {
+                        let (name, total_len) = match msg.map(|msg| Frame::from_bytes(&msg)) {
+                            Some(Ok(Frame::Offer { name, total_len })) => (name, total_len),
+                            Some(Err(err)) => return future::Either::A(future::err(err)),
+                            _ => {
+                                debug!("Transfer substream closed before receiving an offer");
+                                return future::Either::A(future::err(IoErrorKind::InvalidData.into()));
+                            },
+                        };
+
+                        // Restart from the beginning if what we have doesn't fit in the offer.
+                        let offset = match resume.lock().get(&name) {
+                            Some(&offset) if offset <= total_len => offset,
+                            _ => 0,
+                        };
+
+                        let future = framed
+                            .send(Frame::Accept { offset }.into_bytes())
+                            .map(move |substream| TransferOutput::Inbound { name, total_len, offset, substream });
+                        future::Either::B(future)
+                    });
+                Box::new(future)
+            },
+            TransferProtocol::Outbound { name, total_len, .. } => {
+                let future = framed
+                    .send(Frame::Offer { name, total_len }.into_bytes())
+                    .and_then(|framed| framed.into_future().map_err(|(err, _)| err))
+                    .and_then(move |(msg, substream)| {
+                        match msg.map(|msg| Frame::from_bytes(&msg)) {
+                            Some(Ok(Frame::Accept { offset })) if offset <= total_len => {
+                                Ok(TransferOutput::Outbound { offset, substream })
+                            },
+                            Some(Err(err)) => Err(err),
+                            _ => Err(IoError::new(IoErrorKind::ConnectionRefused, "transfer refused")),
+                        }
+                    });
+                Box::new(future)
+            },
+        }
+    }
+}
+
+/// Sends the data of a transfer on a negotiated substream, one chunk at a time.
+///
+/// Produces the number of bytes sent so far after each chunk, and finishes once all the data has
+/// been sent and the substream closed.
+pub struct OutboundTransfer<TSubstream> {
+    substream: TransferSubstream<TSubstream>,
+    source: Box<TransferSource>,
+    /// Offset of the next byte to read from the source.
+    offset: u64,
+    total_len: u64,
+    chunk_size: usize,
+    /// Chunk that hasn't been accepted by the substream yet.
+    pending: Option<Vec<u8>>,
+}
+
+impl<TSubstream> OutboundTransfer<TSubstream> {
+    /// Sends the data of `source` starting at `offset`, in chunks of `chunk_size` bytes.
+    pub fn new(substream: TransferSubstream<TSubstream>, source: Box<TransferSource>, offset: u64, chunk_size: usize) -> Self {
+        let total_len = source.len();
+        OutboundTransfer {
+            substream,
+            source,
+            offset,
+            total_len,
+            chunk_size: cmp::max(chunk_size, 1),
+            pending: None,
+        }
+    }
+}
+
+impl<TSubstream> Stream for OutboundTransfer<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type Item = u64;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<u64>, IoError> {
+        loop {
+            if let Some(frame) = self.pending.take() {
+                match self.substream.start_send(frame)? {
+                    AsyncSink::Ready => return Ok(Async::Ready(Some(self.offset))),
+                    AsyncSink::NotReady(frame) => {
+                        self.pending = Some(frame);
+                        return Ok(Async::NotReady);
+                    },
+                }
+            }
+
+            if self.offset >= self.total_len {
+                try_ready!(self.substream.close());
+                return Ok(Async::Ready(None));
+            }
+
+            let len = cmp::min(self.chunk_size as u64, self.total_len - self.offset) as usize;
+            let mut data = vec![0; len];
+            let read = self.source.read_at(self.offset, &mut data)?;
+            if read == 0 {
+                return Err(IoErrorKind::UnexpectedEof.into());
+            }
+            data.truncate(read);
+            self.offset += read as u64;
+            self.pending = Some(Frame::Chunk { checksum: adler32(&data), data }.into_bytes());
+        }
+    }
+}
+
+/// Receives the data of a transfer on a negotiated substream.
+///
+/// Produces each chunk with its offset after verifying its checksum, and finishes once all the
+/// data has been received.
+pub struct InboundTransfer<TSubstream> {
+    substream: TransferSubstream<TSubstream>,
+    /// Offset of the next byte to receive.
+    offset: u64,
+    total_len: u64,
+}
+
+impl<TSubstream> InboundTransfer<TSubstream> {
+    /// Receives the data starting at `offset`.
+    #[inline]
+    pub fn new(substream: TransferSubstream<TSubstream>, offset: u64, total_len: u64) -> Self {
+        InboundTransfer { substream, offset, total_len }
+    }
+}
+
+impl<TSubstream> Stream for InboundTransfer<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type Item = (u64, Vec<u8>);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<(u64, Vec<u8>)>, IoError> {
+        if self.offset >= self.total_len {
+            return Ok(Async::Ready(None));
+        }
+
+        let frame = match try_ready!(self.substream.poll()) {
+            Some(frame) => Frame::from_bytes(&frame)?,
+            None => return Err(IoErrorKind::UnexpectedEof.into()),
+        };
+
+        match frame {
+            Frame::Chunk { checksum, data } => {
+                if adler32(&data) != checksum {
+                    return Err(IoError::new(IoErrorKind::InvalidData, "checksum mismatch"));
+                }
+                if data.is_empty() || self.offset + data.len() as u64 > self.total_len {
+                    return Err(IoError::new(IoErrorKind::InvalidData, "invalid chunk length"));
+                }
+                let offset = self.offset;
+                self.offset += data.len() as u64;
+                Ok(Async::Ready(Some((offset, data))))
+            },
+            _ => Err(IoError::new(IoErrorKind::InvalidData, "expected a chunk")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = vec![
+            Frame::Offer { name: "file.bin".to_owned(), total_len: 1 << 40 },
+            Frame::Accept { offset: 12345 },
+            Frame::Chunk { checksum: 0xdeadbeef, data: vec![1, 2, 3] },
+        ];
+        for frame in frames {
+            assert_eq!(Frame::from_bytes(&frame.clone().into_bytes()).unwrap(), frame);
+        }
+        assert!(Frame::from_bytes(&[7]).is_err());
+    }
+
+    #[test]
+    fn adler32_known_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn vec_source() {
+        let mut source = vec![1u8, 2, 3, 4, 5];
+        let mut buf = [0; 3];
+        assert_eq!(source.read_at(3, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[.. 2], &[4, 5]);
+        assert_eq!(source.read_at(5, &mut buf).unwrap(), 0);
+    }
+}
@@ -89,7 +89,7 @@
 //! Example:
 //!
 //! ```rust
-//! # #[cfg(all(not(target_os = "emscripten"), feature = "libp2p-secio"))] {
+//! # #[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))] {
 //! use libp2p::{Transport, tcp::TcpConfig, secio::{SecioConfig, SecioKeyPair}};
 //! let tcp_transport = TcpConfig::new();
 //! let secio_upgrade = SecioConfig::new(SecioKeyPair::ed25519_generated().unwrap());
@@ -100,6 +100,22 @@
 //! # }
 //! ```
 //!
+//! When adding both a security layer and multiplexing, prefer the `Transport::upgrade` builder.
+//! It enforces that the connection is authenticated before being multiplexed, and produces the
+//! `PeerId` of the remote alongside with the multiplexer:
+//!
+//! ```rust
+//! # #[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))] {
+//! use libp2p::{Transport, mplex, tcp::TcpConfig, secio::{SecioConfig, SecioKeyPair}};
+//! let transport = TcpConfig::new()
+//!     .upgrade()
+//!     .authenticate(SecioConfig::new(SecioKeyPair::ed25519_generated().unwrap()))
+//!     .multiplex(mplex::MplexConfig::new());
+//! // Each connection opened through `transport` produces a `(PeerId, Connection)`. The
+//! // `Connection` implements `StreamMuxer`, and its `info()` method describes the connection.
+//! # }
+//! ```
+//!
 //! See the documentation of the `libp2p-core` crate for more details about upgrades.
 //!
 //! ## Swarm
@@ -111,6 +127,9 @@
 //!
 //! See the documentation of the `libp2p-core` crate for more details about creating a swarm.
 //!
+//! The `SwarmBuilder` provides preset stacks of transport, security and multiplexing, and builds
+//! a swarm driven by a `NetworkBehaviour` out of them. See the `swarm_builder` module.
+//!
 //! # Using libp2p
 //!
 //! This section contains details about how to use libp2p in practice.
@@ -132,37 +151,61 @@
 
 pub extern crate bytes;
 pub extern crate futures;
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub extern crate tokio_current_thread;
 pub extern crate multiaddr;
 pub extern crate tokio_io;
 pub extern crate tokio_codec;
 
+pub extern crate libp2p_autonat as autonat;
+pub extern crate libp2p_bitswap as bitswap;
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-control"))]
+pub extern crate libp2p_control as control;
 pub extern crate libp2p_core as core;
-#[cfg(not(target_os = "emscripten"))]
+pub extern crate libp2p_core_derive as core_derive;
+pub extern crate libp2p_dcutr as dcutr;
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub extern crate libp2p_dns as dns;
 pub extern crate libp2p_identify as identify;
 pub extern crate libp2p_kad as kad;
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
+pub extern crate libp2p_mdns as mdns;
 pub extern crate libp2p_floodsub as floodsub;
+pub extern crate libp2p_gossipsub as gossipsub;
+pub extern crate libp2p_metrics as metrics;
 pub extern crate libp2p_mplex as mplex;
 pub extern crate libp2p_peerstore as peerstore;
 pub extern crate libp2p_ping as ping;
+pub extern crate libp2p_plaintext as plaintext;
+pub extern crate libp2p_pnet as pnet;
+pub extern crate libp2p_proxy as proxy;
 pub extern crate libp2p_ratelimit as ratelimit;
 pub extern crate libp2p_relay as relay;
-#[cfg(all(not(target_os = "emscripten"), feature = "libp2p-secio"))]
+pub extern crate libp2p_rendezvous as rendezvous;
+pub extern crate libp2p_request_response as request_response;
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
 pub extern crate libp2p_secio as secio;
-#[cfg(not(target_os = "emscripten"))]
+pub extern crate libp2p_sim as sim;
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub extern crate libp2p_tcp_transport as tcp;
+pub extern crate libp2p_transfer as transfer;
 pub extern crate libp2p_transport_timeout as transport_timeout;
 pub extern crate libp2p_uds as uds;
 pub extern crate libp2p_websocket as websocket;
 pub extern crate libp2p_yamux as yamux;
 
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+pub mod node_config;
 pub mod simple;
+pub mod swarm_builder;
 
 pub use self::core::{Transport, ConnectionUpgrade, PeerId, swarm};
 pub use self::multiaddr::Multiaddr;
 pub use self::simple::SimpleProtocol;
+pub use self::swarm_builder::SwarmBuilder;
 pub use self::transport_timeout::TransportTimeout;
 
 /// Implementation of `Transport` that supports the most common protocols.
@@ -175,9 +218,9 @@ pub struct CommonTransport {
     inner: CommonTransportInner
 }
 
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub type InnerImplementation = core::transport::OrTransport<dns::DnsConfig<tcp::TcpConfig>, websocket::WsConfig<dns::DnsConfig<tcp::TcpConfig>>>;
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 pub type InnerImplementation = websocket::BrowserWsConfig;
 
 #[derive(Debug, Clone)]
@@ -188,7 +231,7 @@ struct CommonTransportInner {
 impl CommonTransport {
     /// Initializes the `CommonTransport`.
     #[inline]
-    #[cfg(not(target_os = "emscripten"))]
+    #[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
     pub fn new() -> CommonTransport {
         let tcp = tcp::TcpConfig::new();
         let with_dns = dns::DnsConfig::new(tcp);
@@ -202,7 +245,7 @@ impl CommonTransport {
 
     /// Initializes the `CommonTransport`.
     #[inline]
-    #[cfg(target_os = "emscripten")]
+    #[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
     pub fn new() -> CommonTransport {
         let inner = websocket::BrowserWsConfig::new();
         CommonTransport {
@@ -238,11 +281,6 @@ impl Transport for CommonTransport {
             }
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.inner.inner.nat_traversal(server, observed)
-    }
 }
 
 /// The `multiaddr!` macro is an easy way for a user to create a `Multiaddr`.
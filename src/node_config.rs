@@ -0,0 +1,660 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Configuration of a node, loaded from TOML or JSON.
+//!
+//! A `NodeConfig` describes the transport stack of a node, the addresses it listens on, the
+//! peers it connects to when starting, the parameters of its protocols and its resource limits.
+//! It is built with a `NodeConfigLoader` from one or more *layers*, each of them a TOML or JSON
+//! document. The layers are merged in the order in which they are added: tables are merged
+//! recursively, while any other value of a later layer replaces the one of the earlier layers.
+//! This makes it possible to describe the variants of a simulation campaign as small files on
+//! top of a shared base, instead of recompiling.
+//!
+//! All the fields are optional. The layout, in TOML, is the following:
+//!
+//! ```toml
+//! [transport]
+//! stack = "tcp+secio+mplex"     # or "tcp+secio+yamux", or "sim+plaintext+mplex"
+//! listen = ["/ip4/0.0.0.0/tcp/4001"]
+//!
+//! [[bootstrap]]
+//! peer_id = "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ"
+//! address = "/ip4/104.131.131.82/tcp/4001"
+//!
+//! [protocols.identify]
+//! protocol_version = "/my-app/1.0.0"
+//! agent_version = "my-app/0.1"
+//!
+//! [protocols.ping]
+//! interval_ms = 15000
+//! timeout_ms = 20000
+//!
+//! [protocols.mplex]
+//! max_substreams = 128
+//! max_buffer_len = 4096
+//! split_send_size = 8192
+//!
+//! [protocols.yamux]
+//! receive_window = 262144
+//! max_buffer_size = 16777216
+//! max_substreams = 1024
+//!
+//! [limits]
+//! max_connections = 256
+//! max_connections_per_peer = 2
+//! max_substreams_per_connection = 64
+//! max_substreams_per_peer = 128
+//! max_memory = 67108864
+//! max_memory_per_protocol = 16777216
+//! ```
+//!
+//! Unknown fields and invalid values are reported as a `ConfigError` that references the
+//! offending field, for example `protocols.ping.interval_ms` or `transport.listen[1]`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use libp2p::node_config::{NodeConfigLoader, TransportStack};
+//!
+//! let base = r#"
+//!     [transport]
+//!     stack = "sim+plaintext+mplex"
+//!     listen = ["/ip4/0.0.0.0/tcp/4001"]
+//! "#;
+//! let variant = r#"{ "limits": { "max_connections": 8 } }"#;
+//!
+//! let config = NodeConfigLoader::new()
+//!     .add_toml(base).unwrap()
+//!     .add_json(variant).unwrap()
+//!     .load()
+//!     .unwrap();
+//! assert_eq!(config.transport(), TransportStack::SimPlainTextMplex);
+//! assert_eq!(config.limits().max_connections(), Some(8));
+//! ```
+
+use core::resource_manager::ResourceLimits;
+use core::PeerId;
+use mplex::MplexConfig;
+use multiaddr::Multiaddr;
+use ping::PingConfig;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml;
+
+/// Maximum size of an mplex frame. Higher values of `split_send_size` are rejected by mplex.
+const MPLEX_MAX_FRAME_SIZE: u64 = 32 * 1024 * 1024;
+/// Initial window defined by the yamux specification, which is the minimum receive window.
+const YAMUX_MIN_RECEIVE_WINDOW: u64 = 256 * 1024;
+
+/// Stack of protocols used to reach the other nodes. Each stack corresponds to a preset of the
+/// `SwarmBuilder`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransportStack {
+    /// TCP, secio and mplex. Written `"tcp+secio+mplex"`. See `SwarmBuilder::tcp_secio_mplex`.
+    TcpSecioMplex,
+    /// TCP, secio and yamux. Written `"tcp+secio+yamux"`. See `SwarmBuilder::tcp_secio_yamux`.
+    TcpSecioYamux,
+    /// A node of a simulated network, with the plaintext handshake and mplex. Written
+    /// `"sim+plaintext+mplex"`. See `SwarmBuilder::sim_plaintext_mplex`.
+    SimPlainTextMplex,
+}
+
+impl TransportStack {
+    /// Returns the name of the stack in the configuration files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TransportStack::TcpSecioMplex => "tcp+secio+mplex",
+            TransportStack::TcpSecioYamux => "tcp+secio+yamux",
+            TransportStack::SimPlainTextMplex => "sim+plaintext+mplex",
+        }
+    }
+
+    /// Returns the stack with the given name, if any.
+    pub fn from_name(name: &str) -> Option<TransportStack> {
+        [TransportStack::TcpSecioMplex, TransportStack::TcpSecioYamux, TransportStack::SimPlainTextMplex]
+            .iter()
+            .find(|stack| stack.name() == name)
+            .cloned()
+    }
+}
+
+impl Default for TransportStack {
+    #[inline]
+    fn default() -> Self {
+        TransportStack::TcpSecioMplex
+    }
+}
+
+/// Parameters of the protocols of a node. The fields that aren't set keep the defaults of the
+/// protocols.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolParams {
+    /// Protocol version sent by identify.
+    pub identify_protocol_version: Option<String>,
+    /// Agent version sent by identify.
+    pub identify_agent_version: Option<String>,
+    /// Delay between two pings.
+    pub ping_interval: Option<Duration>,
+    /// Duration after which a ping is considered as failed.
+    pub ping_timeout: Option<Duration>,
+    /// Maximum number of simultaneous mplex substreams.
+    pub mplex_max_substreams: Option<usize>,
+    /// Maximum number of buffered mplex frames per substream.
+    pub mplex_max_buffer_len: Option<usize>,
+    /// Maximum size of the mplex frames that are sent.
+    pub mplex_split_send_size: Option<usize>,
+    /// Receive window of the yamux substreams, in bytes.
+    pub yamux_receive_window: Option<u32>,
+    /// Maximum number of bytes buffered for a yamux substream.
+    pub yamux_max_buffer_size: Option<usize>,
+    /// Maximum number of simultaneous yamux substreams.
+    pub yamux_max_substreams: Option<usize>,
+}
+
+/// Configuration of a node. Built with a `NodeConfigLoader`.
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    transport: TransportStack,
+    listen_addresses: Vec<Multiaddr>,
+    bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+    protocols: ProtocolParams,
+    limits: ResourceLimits,
+}
+
+impl NodeConfig {
+    /// Returns the transport stack of the node.
+    #[inline]
+    pub fn transport(&self) -> TransportStack {
+        self.transport
+    }
+
+    /// Returns the addresses to listen on.
+    #[inline]
+    pub fn listen_addresses(&self) -> &[Multiaddr] {
+        &self.listen_addresses
+    }
+
+    /// Returns the peers to connect to when starting, with their address.
+    #[inline]
+    pub fn bootstrap_peers(&self) -> &[(PeerId, Multiaddr)] {
+        &self.bootstrap_peers
+    }
+
+    /// Returns the parameters of the protocols.
+    #[inline]
+    pub fn protocols(&self) -> &ProtocolParams {
+        &self.protocols
+    }
+
+    /// Returns the resource limits, to pass to a `ResourceManager`.
+    #[inline]
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    /// Builds the mplex configuration of the node.
+    pub fn mplex_config(&self) -> MplexConfig {
+        let mut config = MplexConfig::new();
+        if let Some(max) = self.protocols.mplex_max_substreams {
+            config.max_substreams(max);
+        }
+        if let Some(max) = self.protocols.mplex_max_buffer_len {
+            config.max_buffer_len(max);
+        }
+        if let Some(size) = self.protocols.mplex_split_send_size {
+            config.split_send_size(size);
+        }
+        config
+    }
+
+    /// Builds the yamux configuration of the node.
+    pub fn yamux_config(&self) -> ::yamux::Config {
+        let mut config = ::yamux::Config::default();
+        if let Some(size) = self.protocols.yamux_receive_window {
+            config.receive_window(size);
+        }
+        if let Some(size) = self.protocols.yamux_max_buffer_size {
+            config.max_buffer_size(size);
+        }
+        if let Some(max) = self.protocols.yamux_max_substreams {
+            config.max_substreams(max);
+        }
+        config
+    }
+
+    /// Builds the configuration of the periodic pings of the node.
+    pub fn ping_config(&self) -> PingConfig {
+        let mut config = PingConfig::new();
+        if let Some(interval) = self.protocols.ping_interval {
+            config = config.with_interval(interval);
+        }
+        if let Some(timeout) = self.protocols.ping_timeout {
+            config = config.with_timeout(timeout);
+        }
+        config
+    }
+}
+
+/// Loads a `NodeConfig` from layers of TOML or JSON.
+///
+/// See the module-level documentation.
+#[derive(Debug, Clone)]
+pub struct NodeConfigLoader {
+    /// Result of merging the layers added so far. Always an object.
+    merged: Value,
+}
+
+impl NodeConfigLoader {
+    /// Builds a loader without any layer. Loading it produces the default `NodeConfig`.
+    #[inline]
+    pub fn new() -> NodeConfigLoader {
+        NodeConfigLoader {
+            merged: Value::Object(Map::new()),
+        }
+    }
+
+    /// Adds a layer written in TOML.
+    pub fn add_toml(self, document: &str) -> Result<Self, ConfigError> {
+        let layer = toml::from_str::<Value>(document)
+            .map_err(|err| ConfigError::Syntax { origin: "TOML document".to_owned(), message: err.to_string() })?;
+        Ok(self.add_layer(layer))
+    }
+
+    /// Adds a layer written in JSON.
+    pub fn add_json(self, document: &str) -> Result<Self, ConfigError> {
+        let layer = serde_json::from_str::<Value>(document)
+            .map_err(|err| ConfigError::Syntax { origin: "JSON document".to_owned(), message: err.to_string() })?;
+        Ok(self.add_layer(layer))
+    }
+
+    /// Adds a layer read from a file. The file is parsed as JSON if its extension is `json`, and
+    /// as TOML otherwise.
+    pub fn add_file<P: AsRef<Path>>(self, path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let document = fs::read_to_string(path)
+            .map_err(|error| ConfigError::Io { path: path.to_owned(), error })?;
+
+        let is_json = path.extension().map(|ext| ext == "json").unwrap_or(false);
+        let layer = if is_json {
+            serde_json::from_str::<Value>(&document).map_err(|err| err.to_string())
+        } else {
+            toml::from_str::<Value>(&document).map_err(|err| err.to_string())
+        };
+
+        match layer {
+            Ok(layer) => Ok(self.add_layer(layer)),
+            Err(message) => Err(ConfigError::Syntax { origin: path.display().to_string(), message }),
+        }
+    }
+
+    /// Merges a layer on top of the previous ones.
+    fn add_layer(mut self, layer: Value) -> Self {
+        merge(&mut self.merged, layer);
+        self
+    }
+
+    /// Validates the merged layers and builds the `NodeConfig`.
+    pub fn load(&self) -> Result<NodeConfig, ConfigError> {
+        let root = table(&self.merged, "")?;
+        check_keys(root, "", &["transport", "bootstrap", "protocols", "limits"])?;
+        let mut config = NodeConfig::default();
+
+        if let Some(transport) = root.get("transport") {
+            let transport = table(transport, "transport")?;
+            check_keys(transport, "transport", &["stack", "listen"])?;
+            if let Some(name) = field::<String>(transport, "transport", "stack")? {
+                config.transport = TransportStack::from_name(&name)
+                    .ok_or_else(|| invalid("transport.stack", format!("unknown stack {:?}", name)))?;
+            }
+            if let Some(listen) = field::<Vec<String>>(transport, "transport", "listen")? {
+                for (n, addr) in listen.iter().enumerate() {
+                    let path = format!("transport.listen[{}]", n);
+                    config.listen_addresses.push(parse_multiaddr(addr, &path)?);
+                }
+            }
+        }
+
+        if let Some(bootstrap) = root.get("bootstrap") {
+            let peers = bootstrap.as_array()
+                .ok_or_else(|| invalid("bootstrap", "expected an array".to_owned()))?;
+            for (n, peer) in peers.iter().enumerate() {
+                let path = format!("bootstrap[{}]", n);
+                let peer = table(peer, &path)?;
+                check_keys(peer, &path, &["peer_id", "address"])?;
+                let peer_id = required::<String>(peer, &path, "peer_id")?;
+                let peer_id = peer_id.parse::<PeerId>()
+                    .map_err(|_| invalid(&join(&path, "peer_id"), format!("invalid peer id {:?}", peer_id)))?;
+                let address = required::<String>(peer, &path, "address")?;
+                let address = parse_multiaddr(&address, &join(&path, "address"))?;
+                config.bootstrap_peers.push((peer_id, address));
+            }
+        }
+
+        if let Some(protocols) = root.get("protocols") {
+            config.protocols = load_protocols(table(protocols, "protocols")?)?;
+        }
+
+        if let Some(limits) = root.get("limits") {
+            config.limits = load_limits(table(limits, "limits")?)?;
+        }
+
+        Ok(config)
+    }
+}
+
+impl Default for NodeConfigLoader {
+    #[inline]
+    fn default() -> Self {
+        NodeConfigLoader::new()
+    }
+}
+
+/// Loads the `protocols` table.
+fn load_protocols(protocols: &Map<String, Value>) -> Result<ProtocolParams, ConfigError> {
+    check_keys(protocols, "protocols", &["identify", "ping", "mplex", "yamux"])?;
+    let mut params = ProtocolParams::default();
+
+    if let Some(identify) = protocols.get("identify") {
+        let path = "protocols.identify";
+        let identify = table(identify, path)?;
+        check_keys(identify, path, &["protocol_version", "agent_version"])?;
+        params.identify_protocol_version = field(identify, path, "protocol_version")?;
+        params.identify_agent_version = field(identify, path, "agent_version")?;
+    }
+
+    if let Some(ping) = protocols.get("ping") {
+        let path = "protocols.ping";
+        let ping = table(ping, path)?;
+        check_keys(ping, path, &["interval_ms", "timeout_ms"])?;
+        params.ping_interval = non_zero(ping, path, "interval_ms")?.map(Duration::from_millis);
+        params.ping_timeout = non_zero(ping, path, "timeout_ms")?.map(Duration::from_millis);
+    }
+
+    if let Some(mplex) = protocols.get("mplex") {
+        let path = "protocols.mplex";
+        let mplex = table(mplex, path)?;
+        check_keys(mplex, path, &["max_substreams", "max_buffer_len", "split_send_size"])?;
+        params.mplex_max_substreams = non_zero(mplex, path, "max_substreams")?.map(|v| v as usize);
+        params.mplex_max_buffer_len = non_zero(mplex, path, "max_buffer_len")?.map(|v| v as usize);
+        if let Some(size) = non_zero(mplex, path, "split_send_size")? {
+            if size > MPLEX_MAX_FRAME_SIZE {
+                let message = format!("must be at most {}", MPLEX_MAX_FRAME_SIZE);
+                return Err(invalid(&join(path, "split_send_size"), message));
+            }
+            params.mplex_split_send_size = Some(size as usize);
+        }
+    }
+
+    if let Some(yamux) = protocols.get("yamux") {
+        let path = "protocols.yamux";
+        let yamux = table(yamux, path)?;
+        check_keys(yamux, path, &["receive_window", "max_buffer_size", "max_substreams"])?;
+        if let Some(window) = field::<u32>(yamux, path, "receive_window")? {
+            if u64::from(window) < YAMUX_MIN_RECEIVE_WINDOW {
+                let message = format!("must be at least {}", YAMUX_MIN_RECEIVE_WINDOW);
+                return Err(invalid(&join(path, "receive_window"), message));
+            }
+            params.yamux_receive_window = Some(window);
+        }
+        params.yamux_max_buffer_size = non_zero(yamux, path, "max_buffer_size")?.map(|v| v as usize);
+        params.yamux_max_substreams = non_zero(yamux, path, "max_substreams")?.map(|v| v as usize);
+    }
+
+    Ok(params)
+}
+
+/// Loads the `limits` table.
+fn load_limits(limits: &Map<String, Value>) -> Result<ResourceLimits, ConfigError> {
+    let path = "limits";
+    check_keys(limits, path, &["max_connections", "max_connections_per_peer",
+        "max_substreams_per_connection", "max_substreams_per_peer", "max_memory",
+        "max_memory_per_protocol"])?;
+
+    let mut result = ResourceLimits::new();
+    if let Some(max) = non_zero(limits, path, "max_connections")? {
+        result = result.with_max_connections(max as usize);
+    }
+    if let Some(max) = non_zero(limits, path, "max_connections_per_peer")? {
+        result = result.with_max_connections_per_peer(max as usize);
+    }
+    if let Some(max) = non_zero(limits, path, "max_substreams_per_connection")? {
+        result = result.with_max_substreams_per_connection(max as usize);
+    }
+    if let Some(max) = non_zero(limits, path, "max_substreams_per_peer")? {
+        result = result.with_max_substreams_per_peer(max as usize);
+    }
+    if let Some(max) = non_zero(limits, path, "max_memory")? {
+        result = result.with_max_memory(max as usize);
+    }
+    if let Some(max) = non_zero(limits, path, "max_memory_per_protocol")? {
+        result = result.with_max_memory_per_protocol(max as usize);
+    }
+    Ok(result)
+}
+
+/// Merges `layer` into `base`. Objects are merged recursively, other values are replaced.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (&mut Value::Object(ref mut base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        merge(existing, value);
+                        continue;
+                    },
+                    None => (),
+                }
+                base.insert(key, value);
+            }
+        },
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Joins a path and the name of a field.
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Builds an `InvalidField` error.
+fn invalid(field: &str, message: String) -> ConfigError {
+    ConfigError::InvalidField { field: field.to_owned(), message }
+}
+
+/// Returns `value` as a table, or an error referencing `path`.
+fn table<'a>(value: &'a Value, path: &str) -> Result<&'a Map<String, Value>, ConfigError> {
+    value.as_object().ok_or_else(|| {
+        let field = if path.is_empty() { "<root>" } else { path };
+        invalid(field, "expected a table".to_owned())
+    })
+}
+
+/// Returns an error if `table` contains a key that isn't in `known`.
+fn check_keys(table: &Map<String, Value>, path: &str, known: &[&str]) -> Result<(), ConfigError> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(invalid(&join(path, key), "unknown field".to_owned())),
+        None => Ok(()),
+    }
+}
+
+/// Deserializes the field `key` of `table`, if present.
+fn field<T>(table: &Map<String, Value>, path: &str, key: &str) -> Result<Option<T>, ConfigError>
+where
+    T: DeserializeOwned,
+{
+    match table.get(key) {
+        Some(value) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(|err| invalid(&join(path, key), err.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Same as `field`, but the field is mandatory.
+fn required<T>(table: &Map<String, Value>, path: &str, key: &str) -> Result<T, ConfigError>
+where
+    T: DeserializeOwned,
+{
+    field(table, path, key)?
+        .ok_or_else(|| invalid(&join(path, key), "missing field".to_owned()))
+}
+
+/// Same as `field` for an integer that must be strictly positive.
+fn non_zero(table: &Map<String, Value>, path: &str, key: &str) -> Result<Option<u64>, ConfigError> {
+    match field::<u64>(table, path, key)? {
+        Some(0) => Err(invalid(&join(path, key), "must be greater than 0".to_owned())),
+        value => Ok(value),
+    }
+}
+
+/// Parses a multiaddress, or returns an error referencing `path`.
+fn parse_multiaddr(addr: &str, path: &str) -> Result<Multiaddr, ConfigError> {
+    addr.parse::<Multiaddr>()
+        .map_err(|err| invalid(path, format!("invalid multiaddress {:?}: {}", addr, err)))
+}
+
+/// Error while loading a `NodeConfig`.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Failed to read a configuration file.
+    Io {
+        /// The file.
+        path: PathBuf,
+        /// The error that happened.
+        error: IoError,
+    },
+    /// A layer isn't valid TOML or JSON.
+    Syntax {
+        /// Where the layer comes from.
+        origin: String,
+        /// Description of the error.
+        message: String,
+    },
+    /// A field is unknown or has an invalid value.
+    InvalidField {
+        /// Path of the field, such as `protocols.ping.interval_ms` or `transport.listen[1]`.
+        field: String,
+        /// Why the value is invalid.
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io { ref path, ref error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            },
+            ConfigError::Syntax { ref origin, ref message } => {
+                write!(f, "failed to parse {}: {}", origin, message)
+            },
+            ConfigError::InvalidField { ref field, ref message } => {
+                write!(f, "invalid field `{}`: {}", field, message)
+            },
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ConfigError::Io { ref error, .. } => Some(error),
+            ConfigError::Syntax { .. } | ConfigError::InvalidField { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_field(result: Result<NodeConfig, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::InvalidField { field, .. }) => field,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn layers_are_merged() {
+        let base = r#"
+            [transport]
+            stack = "tcp+secio+yamux"
+            listen = ["/ip4/0.0.0.0/tcp/4001"]
+
+            [protocols.ping]
+            interval_ms = 1000
+            timeout_ms = 2000
+
+            [limits]
+            max_connections = 16
+        "#;
+        let variant = r#"{
+            "protocols": { "ping": { "timeout_ms": 500 } },
+            "bootstrap": [{
+                "peer_id": "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+                "address": "/ip4/10.0.0.1/tcp/4001"
+            }]
+        }"#;
+
+        let config = NodeConfigLoader::new()
+            .add_toml(base).unwrap()
+            .add_json(variant).unwrap()
+            .load().unwrap();
+
+        assert_eq!(config.transport(), TransportStack::TcpSecioYamux);
+        assert_eq!(config.listen_addresses(), &["/ip4/0.0.0.0/tcp/4001".parse::<Multiaddr>().unwrap()]);
+        assert_eq!(config.bootstrap_peers().len(), 1);
+        assert_eq!(config.protocols().ping_interval, Some(Duration::from_millis(1000)));
+        assert_eq!(config.protocols().ping_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.limits().max_connections(), Some(16));
+    }
+
+    #[test]
+    fn errors_reference_the_field() {
+        let unknown = NodeConfigLoader::new().add_toml("[limits]\nmax_conections = 3").unwrap().load();
+        assert_eq!(invalid_field(unknown), "limits.max_conections");
+
+        let wrong_type = NodeConfigLoader::new()
+            .add_json(r#"{ "protocols": { "ping": { "interval_ms": "soon" } } }"#).unwrap()
+            .load();
+        assert_eq!(invalid_field(wrong_type), "protocols.ping.interval_ms");
+
+        let bad_addr = NodeConfigLoader::new()
+            .add_json(r#"{ "transport": { "listen": ["/ip4/0.0.0.0/tcp/1", "/ip4/nope"] } }"#).unwrap()
+            .load();
+        assert_eq!(invalid_field(bad_addr), "transport.listen[1]");
+
+        let zero = NodeConfigLoader::new().add_toml("[protocols.mplex]\nmax_substreams = 0").unwrap().load();
+        assert_eq!(invalid_field(zero), "protocols.mplex.max_substreams");
+    }
+}
@@ -0,0 +1,332 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Builds a ready-to-use swarm out of a preset stack of protocols.
+//!
+//! Composing a transport, a security layer and a multiplexer by hand, then putting the result
+//! in a `BehaviourSwarm` and configuring it, takes a lot of boilerplate. The `SwarmBuilder`
+//! provides preset stacks that cover the common cases:
+//!
+//! - `SwarmBuilder::tcp_secio_mplex`: TCP, secured with secio and multiplexed with mplex.
+//! - `SwarmBuilder::tcp_secio_yamux`: TCP, secured with secio and multiplexed with yamux.
+//! - `SwarmBuilder::sim_plaintext_mplex`: a node of a `SimNetwork`, with the plaintext
+//!   handshake and mplex. Meant for simulations and tests.
+//!
+//! Each layer of a preset can be replaced or modified with the override hooks
+//! (`map_transport`, `with_security`, `with_muxer`), and the swarm itself can be configured
+//! with the `with_*` methods before calling `build`.
+//!
+//! > **Note**: There is no implementation of the *noise* handshake yet. The yamux preset uses
+//! >           secio instead, and `with_security` can be used to swap it later.
+//!
+//! # Example
+//!
+//! ```rust
+//! # #[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))] {
+//! use libp2p::SwarmBuilder;
+//! use libp2p::identify::Identify;
+//! use libp2p::secio::SecioKeyPair;
+//!
+//! let key = SecioKeyPair::ed25519_generated().unwrap();
+//! let behaviour = Identify::new(key.to_public_key(), "/my-app/1.0.0".to_owned(), "my-app".to_owned());
+//! let mut swarm = SwarmBuilder::tcp_secio_mplex(key).build(behaviour);
+//! swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+//! // `swarm` is a `Stream` of `SwarmEvent`s, and can be used to dial.
+//! # }
+//! ```
+
+use core::nodes::address_filter::AddressFilter;
+use core::nodes::behaviour::{BehaviourSwarm, NetworkBehaviour};
+use core::nodes::connection_handler::{ConnectionHandler, NodeHandlerWrapper};
+use core::nodes::dial_backoff::DialBackoffConfig;
+use core::nodes::handled_node::NodeHandler;
+use core::nodes::node::{OutboundLimits, Substream};
+use core::nodes::outbound_scheduler::SchedulerConfig;
+use core::nodes::raw_swarm::DuplicateConnectionPolicy;
+use core::muxing::StreamMuxer;
+use core::transport::AuthenticatedOutput;
+use core::transport::builder::{AuthenticateThenMultiplex, Multiplexed};
+use core::{ConnectionUpgrade, PeerId, PublicKey, Runtime, Transport};
+use mplex::MplexConfig;
+use plaintext::PlainText2Config;
+use sim::SimTransport;
+use tokio_io::{AsyncRead, AsyncWrite};
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+use secio::{SecioConfig, SecioKeyPair};
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+use tcp::TcpConfig;
+
+/// Transport produced by a `SwarmBuilder` whose layers are `TTrans`, `TSec` and `TMux`.
+pub type BuiltTransport<TTrans, TSec, TMux> =
+    Multiplexed<TTrans, AuthenticateThenMultiplex<TSec, TMux>>;
+
+/// Input event of the handlers of a behaviour.
+type InEvent<TBehaviour> =
+    <<TBehaviour as NetworkBehaviour>::ConnectionHandler as ConnectionHandler>::InEvent;
+/// Output event of the handlers of a behaviour.
+type OutEvent<TBehaviour> =
+    <<TBehaviour as NetworkBehaviour>::ConnectionHandler as ConnectionHandler>::OutEvent;
+
+/// Builds a `BehaviourSwarm` from a base transport, a security upgrade and a multiplexing
+/// upgrade.
+///
+/// See the module-level documentation for the presets.
+pub struct SwarmBuilder<TTrans, TSec, TMux> {
+    /// The base transport.
+    transport: TTrans,
+    /// Security upgrade applied on the connections opened by the transport.
+    security: TSec,
+    /// Multiplexing upgrade applied on the authenticated connections.
+    muxer: TMux,
+    /// Runtime to build the swarm with, or `None` for the default one.
+    runtime: Option<Runtime>,
+    /// Address filter to set on the swarm, if any.
+    address_filter: Option<AddressFilter>,
+    /// Dial back-off configuration to set on the swarm, if any.
+    dial_backoff: Option<DialBackoffConfig>,
+    /// Policy for duplicate connections to set on the swarm, if any.
+    duplicate_policy: Option<DuplicateConnectionPolicy>,
+    /// Configuration of the outbound schedulers to set on the swarm, if any.
+    scheduler_config: Option<SchedulerConfig>,
+    /// Limits on the outbound substreams to set on the swarm, if any.
+    outbound_limits: Option<OutboundLimits>,
+}
+
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+impl SwarmBuilder<TcpConfig, SecioConfig, MplexConfig> {
+    /// Preset that uses TCP, secio and mplex.
+    #[inline]
+    pub fn tcp_secio_mplex(key: SecioKeyPair) -> Self {
+        SwarmBuilder::new(TcpConfig::new(), SecioConfig::new(key), MplexConfig::new())
+    }
+}
+
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+impl SwarmBuilder<TcpConfig, SecioConfig, yamux::Config> {
+    /// Preset that uses TCP, secio and yamux.
+    #[inline]
+    pub fn tcp_secio_yamux(key: SecioKeyPair) -> Self {
+        SwarmBuilder::new(TcpConfig::new(), SecioConfig::new(key), yamux::Config::default())
+    }
+}
+
+impl SwarmBuilder<SimTransport, PlainText2Config, MplexConfig> {
+    /// Preset for a node of a `SimNetwork`, that uses the plaintext handshake and mplex.
+    ///
+    /// `public_key` is the key that the node sends to the remotes during the handshake, and
+    /// therefore determines its `PeerId`.
+    #[inline]
+    pub fn sim_plaintext_mplex(node: SimTransport, public_key: PublicKey) -> Self {
+        SwarmBuilder::new(node, PlainText2Config::new(public_key), MplexConfig::new())
+    }
+}
+
+impl<TTrans, TSec, TMux> SwarmBuilder<TTrans, TSec, TMux> {
+    /// Creates a builder from the individual layers of the stack.
+    #[inline]
+    pub fn new(transport: TTrans, security: TSec, muxer: TMux) -> Self {
+        SwarmBuilder {
+            transport,
+            security,
+            muxer,
+            runtime: None,
+            address_filter: None,
+            dial_backoff: None,
+            duplicate_policy: None,
+            scheduler_config: None,
+            outbound_limits: None,
+        }
+    }
+
+    /// Replaces the base transport with the result of `map`, which receives the current one.
+    ///
+    /// This can be used to wrap the transport, for example with a timeout or with DNS
+    /// resolution.
+    pub fn map_transport<TNewTrans, TMap>(self, map: TMap) -> SwarmBuilder<TNewTrans, TSec, TMux>
+    where
+        TMap: FnOnce(TTrans) -> TNewTrans,
+    {
+        SwarmBuilder {
+            transport: map(self.transport),
+            security: self.security,
+            muxer: self.muxer,
+            runtime: self.runtime,
+            address_filter: self.address_filter,
+            dial_backoff: self.dial_backoff,
+            duplicate_policy: self.duplicate_policy,
+            scheduler_config: self.scheduler_config,
+            outbound_limits: self.outbound_limits,
+        }
+    }
+
+    /// Replaces the security upgrade.
+    pub fn with_security<TNewSec>(self, security: TNewSec) -> SwarmBuilder<TTrans, TNewSec, TMux> {
+        SwarmBuilder {
+            transport: self.transport,
+            security,
+            muxer: self.muxer,
+            runtime: self.runtime,
+            address_filter: self.address_filter,
+            dial_backoff: self.dial_backoff,
+            duplicate_policy: self.duplicate_policy,
+            scheduler_config: self.scheduler_config,
+            outbound_limits: self.outbound_limits,
+        }
+    }
+
+    /// Replaces the multiplexing upgrade. Use this to pass a tuned `MplexConfig` or
+    /// `yamux::Config`.
+    pub fn with_muxer<TNewMux>(self, muxer: TNewMux) -> SwarmBuilder<TTrans, TSec, TNewMux> {
+        SwarmBuilder {
+            transport: self.transport,
+            security: self.security,
+            muxer,
+            runtime: self.runtime,
+            address_filter: self.address_filter,
+            dial_backoff: self.dial_backoff,
+            duplicate_policy: self.duplicate_policy,
+            scheduler_config: self.scheduler_config,
+            outbound_limits: self.outbound_limits,
+        }
+    }
+
+    /// Builds the swarm on the given `Runtime` instead of the default one.
+    #[inline]
+    pub fn with_runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Sets the `AddressFilter` of the swarm. See `Swarm::set_address_filter`.
+    #[inline]
+    pub fn with_address_filter(mut self, filter: AddressFilter) -> Self {
+        self.address_filter = Some(filter);
+        self
+    }
+
+    /// Sets the dial back-off configuration of the swarm. See `Swarm::set_dial_backoff_config`.
+    #[inline]
+    pub fn with_dial_backoff(mut self, config: DialBackoffConfig) -> Self {
+        self.dial_backoff = Some(config);
+        self
+    }
+
+    /// Sets what the swarm does with duplicate connections. See
+    /// `RawSwarm::set_duplicate_connection_policy`.
+    #[inline]
+    pub fn with_duplicate_policy(mut self, policy: DuplicateConnectionPolicy) -> Self {
+        self.duplicate_policy = Some(policy);
+        self
+    }
+
+    /// Enables the outbound scheduler on the connections of the swarm. See
+    /// `RawSwarm::set_scheduler_config`.
+    #[inline]
+    pub fn with_scheduler_config(mut self, config: SchedulerConfig) -> Self {
+        self.scheduler_config = Some(config);
+        self
+    }
+
+    /// Sets the limits on the outbound substreams being opened on the connections of the swarm.
+    /// See `RawSwarm::set_outbound_limits`.
+    #[inline]
+    pub fn with_outbound_limits(mut self, limits: OutboundLimits) -> Self {
+        self.outbound_limits = Some(limits);
+        self
+    }
+}
+
+impl<TTrans, TSec, TMux> SwarmBuilder<TTrans, TSec, TMux>
+where
+    TTrans: Transport,
+    TTrans::Output: AsyncRead + AsyncWrite,
+    TSec: ConnectionUpgrade<TTrans::Output>,
+    TSec::Output: AuthenticatedOutput,
+    TMux: ConnectionUpgrade<<TSec::Output as AuthenticatedOutput>::Stream>,
+    TMux::Output: StreamMuxer,
+{
+    /// Composes the layers and returns the resulting transport, without building a swarm.
+    #[inline]
+    pub fn transport(self) -> BuiltTransport<TTrans, TSec, TMux> {
+        self.transport
+            .upgrade()
+            .authenticate(self.security)
+            .multiplex(self.muxer)
+    }
+
+    /// Composes the layers and builds a `BehaviourSwarm` driven by `behaviour`.
+    pub fn build<TBehaviour, TMuxer>(self, behaviour: TBehaviour)
+        -> BehaviourSwarm<BuiltTransport<TTrans, TSec, TMux>, TBehaviour>
+    where
+        BuiltTransport<TTrans, TSec, TMux>: Transport<Output = (PeerId, TMuxer)> + Clone,
+        <BuiltTransport<TTrans, TSec, TMux> as Transport>::Dial: Send + 'static,
+        <BuiltTransport<TTrans, TSec, TMux> as Transport>::ListenerUpgrade: Send + 'static,
+        TMuxer: StreamMuxer + Send + Sync + 'static,
+        TMuxer::OutboundSubstream: Send,
+        TMuxer::Substream: Send,
+        TBehaviour: NetworkBehaviour,
+        TBehaviour::ConnectionHandler: ConnectionHandler<Substream = Substream<TMuxer>> + Clone,
+        InEvent<TBehaviour>: Send + 'static,
+        OutEvent<TBehaviour>: Send + 'static,
+        NodeHandlerWrapper<TBehaviour::ConnectionHandler>: NodeHandler<
+            Substream<TMuxer>,
+            InEvent = InEvent<TBehaviour>,
+            OutEvent = OutEvent<TBehaviour>,
+            OutboundOpenInfo = u64,
+        > + Send + 'static,
+    {
+        let SwarmBuilder {
+            transport,
+            security,
+            muxer,
+            runtime,
+            address_filter,
+            dial_backoff,
+            duplicate_policy,
+            scheduler_config,
+            outbound_limits,
+        } = self;
+
+        let transport = transport.upgrade().authenticate(security).multiplex(muxer);
+        let mut swarm = match runtime {
+            Some(runtime) => BehaviourSwarm::with_runtime(transport, behaviour, runtime),
+            None => BehaviourSwarm::new(transport, behaviour),
+        };
+
+        if let Some(filter) = address_filter {
+            swarm.set_address_filter(filter);
+        }
+        if let Some(config) = dial_backoff {
+            swarm.set_dial_backoff_config(config);
+        }
+        if let Some(policy) = duplicate_policy {
+            swarm.set_duplicate_connection_policy(policy);
+        }
+        if scheduler_config.is_some() {
+            swarm.set_scheduler_config(scheduler_config);
+        }
+        if let Some(limits) = outbound_limits {
+            swarm.set_outbound_limits(limits);
+        }
+
+        swarm
+    }
+}
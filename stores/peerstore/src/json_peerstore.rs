@@ -25,7 +25,7 @@ use bs58;
 use datastore::{Datastore, JsonFileDatastore, JsonFileDatastoreEntry, Query};
 use futures::{Future, Stream};
 use multiaddr::Multiaddr;
-use peer_info::{AddAddrBehaviour, PeerInfo};
+use peer_info::{AddAddrBehaviour, AddrStats, PeerInfo};
 use peerstore::{PeerAccess, Peerstore};
 use std::io::Error as IoError;
 use std::iter;
@@ -136,6 +136,41 @@ impl<'a> PeerAccess for JsonPeerstoreAccess<'a> {
     fn clear_addrs(&mut self) {
         self.0.set_addrs(iter::empty());
     }
+
+    #[inline]
+    fn report_dial_success(&mut self, addr: &Multiaddr) {
+        self.0.report_success(addr);
+    }
+
+    #[inline]
+    fn report_dial_failure(&mut self, addr: &Multiaddr) {
+        self.0.report_failure(addr);
+    }
+
+    #[inline]
+    fn addr_stats(&self, addr: &Multiaddr) -> Option<AddrStats> {
+        self.0.addr_stats(addr).cloned()
+    }
+
+    #[inline]
+    fn agent_version(&self) -> Option<String> {
+        self.0.agent_version().map(|v| v.to_owned())
+    }
+
+    #[inline]
+    fn set_agent_version(&mut self, version: String) {
+        self.0.set_agent_version(version);
+    }
+
+    #[inline]
+    fn protocols(&self) -> Vec<String> {
+        self.0.protocols().to_vec()
+    }
+
+    #[inline]
+    fn set_protocols(&mut self, protocols: Vec<String>) {
+        self.0.set_protocols(protocols);
+    }
 }
 
 #[cfg(test)]
@@ -25,6 +25,10 @@
 //! values are the public key and a list of multiaddresses. Additionally, the multiaddresses stored
 //! by the `peerstore` have a time-to-live after which they disappear.
 //!
+//! The peerstore also remembers which addresses could be successfully dialed. Components that
+//! dial peers should report the outcome with `report_dial_success` and `report_dial_failure`,
+//! and use `addrs_by_preference` to decide which address to try first.
+//!
 //! This crate consists of a generic `Peerstore` trait and the follow implementations:
 //!
 //! - `JsonPeerstore`: Stores the information in a single JSON file.
@@ -81,7 +85,9 @@ extern crate serde_derive;
 
 // TODO: remove
 pub use self::libp2p_core::PeerId;
+pub use self::peer_info::AddrStats;
 pub use self::peerstore::{PeerAccess, Peerstore};
+pub use self::resolver::PeerstoreResolver;
 
 #[macro_use]
 mod peerstore_tests;
@@ -90,5 +96,6 @@ pub mod json_peerstore;
 pub mod memory_peerstore;
 mod peer_info;
 mod peerstore;
+mod resolver;
 
 pub type TTL = std::time::Duration;
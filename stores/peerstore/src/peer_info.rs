@@ -36,13 +36,48 @@ use TTL;
 pub struct PeerInfo {
     // Adresses, and the time at which they will be considered expired.
     addrs: Vec<(Multiaddr, SystemTime)>,
+    // History of the connection attempts to each address. Missing from the files written by
+    // older versions.
+    #[serde(default)]
+    stats: Vec<(Multiaddr, AddrStats)>,
+    // Name and version of the software of the peer, as reported by the identify protocol.
+    #[serde(default)]
+    agent_version: Option<String>,
+    // Protocols supported by the peer, as reported by the identify protocol.
+    #[serde(default)]
+    protocols: Vec<String>,
+}
+
+/// History of the connection attempts to an address of a peer.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddrStats {
+    /// Number of successful connection attempts.
+    pub successes: u32,
+    /// Number of failed connection attempts.
+    pub failures: u32,
+    /// Time of the last successful connection attempt.
+    pub last_success: Option<SystemTime>,
+    /// Time of the last failed connection attempt.
+    pub last_failure: Option<SystemTime>,
+}
+
+impl AddrStats {
+    /// Returns true if the last connection attempt to this address has succeeded.
+    #[inline]
+    pub fn last_attempt_succeeded(&self) -> bool {
+        match (self.last_success, self.last_failure) {
+            (Some(success), Some(failure)) => success >= failure,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
 }
 
 impl PeerInfo {
     /// Builds a new empty `PeerInfo`.
     #[inline]
     pub fn new() -> PeerInfo {
-        PeerInfo { addrs: vec![] }
+        PeerInfo { addrs: vec![], stats: vec![], agent_version: None, protocols: vec![] }
     }
 
     /// Returns the list of the non-expired addresses stored in this `PeerInfo`.
@@ -96,6 +131,63 @@ impl PeerInfo {
     }
 }
 
+impl PeerInfo {
+    /// Returns the history of the connection attempts to an address, if any.
+    #[inline]
+    pub fn addr_stats(&self, addr: &Multiaddr) -> Option<&AddrStats> {
+        self.stats.iter().find(|&&(ref a, _)| a == addr).map(|&(_, ref stats)| stats)
+    }
+
+    /// Records a successful connection attempt to an address.
+    pub fn report_success(&mut self, addr: &Multiaddr) {
+        let stats = self.stats_mut(addr);
+        stats.successes = stats.successes.saturating_add(1);
+        stats.last_success = Some(SystemTime::now());
+    }
+
+    /// Records a failed connection attempt to an address.
+    pub fn report_failure(&mut self, addr: &Multiaddr) {
+        let stats = self.stats_mut(addr);
+        stats.failures = stats.failures.saturating_add(1);
+        stats.last_failure = Some(SystemTime::now());
+    }
+
+    /// Returns the agent version of the peer, if known.
+    #[inline]
+    pub fn agent_version(&self) -> Option<&str> {
+        self.agent_version.as_ref().map(|v| &v[..])
+    }
+
+    /// Sets the agent version of the peer.
+    #[inline]
+    pub fn set_agent_version(&mut self, version: String) {
+        self.agent_version = Some(version);
+    }
+
+    /// Returns the protocols supported by the peer.
+    #[inline]
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Replaces the list of protocols supported by the peer.
+    #[inline]
+    pub fn set_protocols(&mut self, protocols: Vec<String>) {
+        self.protocols = protocols;
+    }
+
+    fn stats_mut(&mut self, addr: &Multiaddr) -> &mut AddrStats {
+        let pos = match self.stats.iter().position(|&(ref a, _)| a == addr) {
+            Some(pos) => pos,
+            None => {
+                self.stats.push((addr.clone(), AddrStats::default()));
+                self.stats.len() - 1
+            }
+        };
+        &mut self.stats[pos].1
+    }
+}
+
 /// Behaviour of the `add_addr` function.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AddAddrBehaviour {
@@ -131,9 +223,38 @@ mod tests {
                 "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap(),
                 UNIX_EPOCH,
             )],
+            stats: vec![(
+                "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap(),
+                AddrStats {
+                    successes: 1,
+                    failures: 2,
+                    last_success: Some(UNIX_EPOCH),
+                    last_failure: None,
+                },
+            )],
         };
         let serialized = serde_json::to_string(&peer_info).unwrap();
         let deserialized: PeerInfo = serde_json::from_str(&serialized).unwrap();
         assert_eq!(peer_info, deserialized);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn deser_without_stats() {
+        let serialized = r#"{"addrs":[]}"#;
+        let deserialized: PeerInfo = serde_json::from_str(serialized).unwrap();
+        assert_eq!(deserialized, PeerInfo::new());
+    }
+
+    #[test]
+    fn report_attempts() {
+        let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+        let mut peer_info = PeerInfo::new();
+        assert!(peer_info.addr_stats(&addr).is_none());
+        peer_info.report_failure(&addr);
+        assert!(!peer_info.addr_stats(&addr).unwrap().last_attempt_succeeded());
+        peer_info.report_success(&addr);
+        let stats = peer_info.addr_stats(&addr).unwrap();
+        assert_eq!((stats.successes, stats.failures), (1, 1));
+        assert!(stats.last_attempt_succeeded());
+    }
+}
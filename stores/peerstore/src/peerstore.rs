@@ -19,6 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use multiaddr::Multiaddr;
+use peer_info::AddrStats;
 use std::time::Duration;
 use {PeerId, TTL};
 
@@ -114,4 +115,49 @@ pub trait PeerAccess {
 
     /// Removes all previously stored addresses.
     fn clear_addrs(&mut self);
+
+    /// Records that dialing the given address of the peer has succeeded.
+    fn report_dial_success(&mut self, addr: &Multiaddr);
+
+    /// Records that dialing the given address of the peer has failed.
+    fn report_dial_failure(&mut self, addr: &Multiaddr);
+
+    /// Returns the history of the dialing attempts to the given address of the peer, or `None`
+    /// if the address has never been dialed.
+    fn addr_stats(&self, addr: &Multiaddr) -> Option<AddrStats>;
+
+    /// Returns the name and version of the software of the peer, as reported by the identify
+    /// protocol, if known.
+    fn agent_version(&self) -> Option<String>;
+
+    /// Sets the name and version of the software of the peer.
+    fn set_agent_version(&mut self, version: String);
+
+    /// Returns the protocols supported by the peer, as reported by the identify protocol.
+    fn protocols(&self) -> Vec<String>;
+
+    /// Replaces the list of protocols supported by the peer.
+    fn set_protocols(&mut self, protocols: Vec<String>);
+
+    /// Returns the same addresses as `addrs`, ordered by the order in which they should be
+    /// dialed.
+    ///
+    /// Addresses whose last dialing attempt succeeded come first, followed by the addresses that
+    /// have never been dialed, followed by the addresses whose last dialing attempt failed
+    /// (fewest failures first).
+    fn addrs_by_preference(&self) -> Vec<Multiaddr> {
+        let mut addrs = self.addrs()
+            .map(|addr| {
+                let rank = match self.addr_stats(&addr) {
+                    Some(ref stats) if stats.last_attempt_succeeded() => (0, 0),
+                    Some(ref stats) => (2, stats.failures),
+                    None => (1, 0),
+                };
+                (rank, addr)
+            })
+            .collect::<Vec<_>>();
+        // `sort_by_key` is stable, which preserves the insertion order for equal ranks.
+        addrs.sort_by_key(|&(rank, _)| rank);
+        addrs.into_iter().map(|(_, addr)| addr).collect()
+    }
 }
@@ -129,5 +129,68 @@ macro_rules! peerstore_tests {
             thread::sleep(Duration::from_millis(2));
             assert_eq!(peer_store.peer(&peer_id).unwrap().addrs().count(), 1);
         }
+    
+        #[test]
+        fn dial_history() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(PublicKey::Ed25519(vec![1, 2, 3]));
+            let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+
+            peer_store.peer_or_create(&peer_id).add_addr(addr.clone(), Duration::from_millis(5000));
+            assert!(peer_store.peer(&peer_id).unwrap().addr_stats(&addr).is_none());
+
+            peer_store.peer_or_create(&peer_id).report_dial_failure(&addr);
+            peer_store.peer_or_create(&peer_id).report_dial_failure(&addr);
+            let stats = peer_store.peer(&peer_id).unwrap().addr_stats(&addr).unwrap();
+            assert_eq!((stats.successes, stats.failures), (0, 2));
+        }
+
+        #[test]
+        fn addrs_by_preference() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(PublicKey::Ed25519(vec![1, 2, 3]));
+
+            let failing_twice = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+            let failing_once = "/ip4/0.0.0.1/tcp/0".parse::<Multiaddr>().unwrap();
+            let untried = "/ip4/0.0.0.2/tcp/0".parse::<Multiaddr>().unwrap();
+            let working = "/ip4/0.0.0.3/tcp/0".parse::<Multiaddr>().unwrap();
+
+            {
+                let mut peer = peer_store.peer_or_create(&peer_id);
+                for addr in &[&failing_twice, &failing_once, &untried, &working] {
+                    peer.add_addr((*addr).clone(), Duration::from_millis(5000));
+                }
+                peer.report_dial_failure(&failing_twice);
+                peer.report_dial_failure(&failing_twice);
+                peer.report_dial_failure(&failing_once);
+                peer.report_dial_success(&working);
+            }
+
+            let addrs = peer_store.peer(&peer_id).unwrap().addrs_by_preference();
+            assert_eq!(addrs, &[working, untried, failing_once, failing_twice]);
+        }
+    
+
+        #[test]
+        fn identify_info() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(PublicKey::Ed25519(vec![1, 2, 3]));
+
+            assert!(peer_store.peer_or_create(&peer_id).agent_version().is_none());
+            assert!(peer_store.peer_or_create(&peer_id).protocols().is_empty());
+
+            {
+                let mut peer = peer_store.peer_or_create(&peer_id);
+                peer.set_agent_version("agent/1.0".to_owned());
+                peer.set_protocols(vec!["/ipfs/id/1.0.0".to_owned()]);
+            }
+
+            let peer = peer_store.peer(&peer_id).unwrap();
+            assert_eq!(peer.agent_version(), Some("agent/1.0".to_owned()));
+            assert_eq!(peer.protocols(), vec!["/ipfs/id/1.0.0".to_owned()]);
+        }
     };
 }
@@ -0,0 +1,110 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Use of a peer store as the `AddressResolver` of a swarm.
+
+use libp2p_core::nodes::address_resolver::{AddressLookup, AddressResolver};
+use multiaddr::Multiaddr;
+use peerstore::{PeerAccess, Peerstore};
+use std::fmt;
+use std::sync::Arc;
+use PeerId;
+
+/// Implementation of `AddressResolver` that returns the addresses stored in a peer store, by
+/// order of preference. See `PeerAccess::addrs_by_preference`.
+///
+/// Can optionally be combined with a lookup on the network, for example a Kademlia query, which
+/// is performed when the peer store doesn't know any working address.
+pub struct PeerstoreResolver<T> {
+    store: Arc<T>,
+    lookup: Option<Box<FnMut(&PeerId) -> AddressLookup + Send>>,
+}
+
+impl<T> PeerstoreResolver<T> {
+    /// Builds a resolver that uses the given peer store, and never performs lookups.
+    #[inline]
+    pub fn new(store: Arc<T>) -> PeerstoreResolver<T> {
+        PeerstoreResolver {
+            store,
+            lookup: None,
+        }
+    }
+
+    /// Sets the function that looks for the addresses of a peer on the network.
+    #[inline]
+    pub fn with_lookup<F>(mut self, lookup: F) -> Self
+    where
+        F: FnMut(&PeerId) -> AddressLookup + Send + 'static,
+    {
+        self.lookup = Some(Box::new(lookup));
+        self
+    }
+}
+
+impl<T> AddressResolver for PeerstoreResolver<T>
+where
+    for<'a> &'a T: Peerstore,
+{
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        match (&*self.store).peer(peer_id) {
+            Some(peer) => peer.addrs_by_preference(),
+            None => Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn lookup(&mut self, peer_id: &PeerId) -> Option<AddressLookup> {
+        self.lookup.as_mut().map(|lookup| lookup(peer_id))
+    }
+}
+
+impl<T> fmt::Debug for PeerstoreResolver<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("PeerstoreResolver")
+            .field("store", &self.store)
+            .field("lookup", &self.lookup.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PublicKey;
+    use memory_peerstore::MemoryPeerstore;
+    use std::time::Duration;
+
+    #[test]
+    fn returns_stored_addresses() {
+        let store = Arc::new(MemoryPeerstore::empty());
+        let peer_id = PeerId::from_public_key(PublicKey::Rsa(vec![1, 2, 3, 4]));
+        let addr: Multiaddr = "/ip4/10.11.12.13/tcp/20000".parse().unwrap();
+        (&*store).peer_or_create(&peer_id).add_addr(addr.clone(), Duration::from_secs(3600));
+
+        let mut resolver = PeerstoreResolver::new(store);
+        assert_eq!(resolver.known_addresses(&peer_id), vec![addr]);
+        let other = PeerId::from_public_key(PublicKey::Rsa(vec![5, 6, 7, 8]));
+        assert!(resolver.known_addresses(&other).is_empty());
+        assert!(resolver.lookup(&peer_id).is_none());
+    }
+}
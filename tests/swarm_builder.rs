@@ -0,0 +1,128 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Checks that each preset of the `SwarmBuilder` produces a working node in a few lines.
+
+extern crate futures;
+extern crate libp2p;
+extern crate tokio_current_thread;
+
+use futures::{future, prelude::*};
+use libp2p::core::PublicKey;
+use libp2p::core::nodes::swarm::SwarmEvent;
+use libp2p::identify::{Identify, IdentifyEvent};
+use libp2p::sim::SimNetwork;
+use libp2p::SwarmBuilder;
+
+/// Polls `$listener` and `$dialer` until the dialer has identified the listener, and returns
+/// the `PeerId` and the agent version that it received.
+macro_rules! identify_listener {
+    ($listener:ident, $dialer:ident) => {{
+        let identified = future::poll_fn(move || -> Poll<_, ()> {
+            while let Async::Ready(Some(_)) = $listener.poll() {}
+            loop {
+                match $dialer.poll() {
+                    Async::Ready(Some(SwarmEvent::Behaviour(IdentifyEvent::Identified { peer_id, info, .. }))) => {
+                        return Ok(Async::Ready((peer_id, info.agent_version)));
+                    },
+                    Async::Ready(Some(_)) => (),
+                    Async::Ready(None) | Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+        });
+
+        tokio_current_thread::block_on_all(identified).unwrap()
+    }};
+}
+
+#[test]
+fn sim_plaintext_mplex() {
+    let network = SimNetwork::new();
+    let listener_key = PublicKey::Ed25519(vec![1; 32]);
+    let dialer_key = PublicKey::Ed25519(vec![2; 32]);
+
+    let mut listener = SwarmBuilder::sim_plaintext_mplex(network.add_node(), listener_key.clone())
+        .build(Identify::new(listener_key.clone(), "/test/1.0.0".to_owned(), "listener".to_owned()));
+    let mut dialer = SwarmBuilder::sim_plaintext_mplex(network.add_node(), dialer_key.clone())
+        .build(Identify::new(dialer_key, "/test/1.0.0".to_owned(), "dialer".to_owned()));
+
+    let addr = listener.listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap()).unwrap();
+    dialer.dial(addr).unwrap();
+
+    let (peer_id, agent_version) = identify_listener!(listener, dialer);
+    assert_eq!(peer_id, listener_key.into_peer_id());
+    assert_eq!(agent_version, "listener");
+}
+
+#[test]
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+fn tcp_secio_mplex() {
+    use libp2p::secio::SecioKeyPair;
+
+    let listener_key = SecioKeyPair::ed25519_generated().unwrap();
+    let listener_id = listener_key.to_peer_id();
+    let dialer_key = SecioKeyPair::ed25519_generated().unwrap();
+
+    let listener_behaviour = Identify::new(listener_key.to_public_key(), "/test/1.0.0".to_owned(), "listener".to_owned());
+    let mut listener = SwarmBuilder::tcp_secio_mplex(listener_key).build(listener_behaviour);
+    let dialer_behaviour = Identify::new(dialer_key.to_public_key(), "/test/1.0.0".to_owned(), "dialer".to_owned());
+    let mut dialer = SwarmBuilder::tcp_secio_mplex(dialer_key).build(dialer_behaviour);
+
+    let addr = listener.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    dialer.dial(addr).unwrap();
+
+    let (peer_id, agent_version) = identify_listener!(listener, dialer);
+    assert_eq!(peer_id, listener_id);
+    assert_eq!(agent_version, "listener");
+}
+
+#[test]
+#[cfg(all(not(any(target_os = "emscripten", target_arch = "wasm32")), feature = "libp2p-secio"))]
+fn tcp_secio_yamux_with_overrides() {
+    use libp2p::core::nodes::raw_swarm::DuplicateConnectionPolicy;
+    use libp2p::secio::SecioKeyPair;
+    use libp2p::yamux;
+
+    let listener_key = SecioKeyPair::ed25519_generated().unwrap();
+    let listener_id = listener_key.to_peer_id();
+    let dialer_key = SecioKeyPair::ed25519_generated().unwrap();
+
+    let mut muxer = yamux::Config::default();
+    muxer.split_send_size(1024);
+
+    let listener_behaviour = Identify::new(listener_key.to_public_key(), "/test/1.0.0".to_owned(), "listener".to_owned());
+    let mut listener = SwarmBuilder::tcp_secio_yamux(listener_key)
+        .with_muxer(muxer.clone())
+        .with_duplicate_policy(DuplicateConnectionPolicy::KeepExisting)
+        .build(listener_behaviour);
+    let dialer_behaviour = Identify::new(dialer_key.to_public_key(), "/test/1.0.0".to_owned(), "dialer".to_owned());
+    let mut dialer = SwarmBuilder::tcp_secio_yamux(dialer_key)
+        .with_muxer(muxer)
+        .map_transport(|tcp| tcp.nodelay(true))
+        .build(dialer_behaviour);
+
+    let addr = listener.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    dialer.dial(addr).unwrap();
+
+    let (peer_id, agent_version) = identify_listener!(listener, dialer);
+    assert_eq!(peer_id, listener_id);
+    assert_eq!(agent_version, "listener");
+}
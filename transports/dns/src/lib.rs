@@ -20,8 +20,8 @@
 
 //! # libp2p-dns
 //!
-//! This crate provides the type `DnsConfig` that allows one to resolve the `/dns4/` and `/dns6/`
-//! components of multiaddresses.
+//! This crate provides the type `DnsConfig` that allows one to resolve the `/dns4/`, `/dns6/` and
+//! `/dnsaddr/` components of multiaddresses.
 //!
 //! ## Usage
 //!
@@ -30,7 +30,14 @@
 //!
 //! Whenever we want to dial an address through the `DnsConfig` and that address contains a
 //! `/dns4/` or `/dns6/` component, a DNS resolve will be performed and the component will be
-//! replaced with respectively an `/ip4/` or an `/ip6/` component.
+//! replaced with respectively an `/ip4/` or an `/ip6/` component. If the name resolves to
+//! multiple IP addresses, they are dialed one after the other until one of them succeeds.
+//!
+//! A `/dnsaddr/<name>` component is resolved by looking up the TXT records of `_dnsaddr.<name>`,
+//! each of which contains a `dnsaddr=<multiaddr>` entry, as described in the
+//! [multiaddr specifications](https://github.com/multiformats/multiaddr/blob/master/protocols/DNSADDR.md).
+//! Looking up TXT records requires a resolver that supports them; see the `Resolver` trait and
+//! `DnsConfig::with_resolver`.
 //!
 
 extern crate futures;
@@ -41,26 +48,92 @@ extern crate multiaddr;
 extern crate tokio_dns;
 extern crate tokio_io;
 
-use futures::future::{self, Future};
+use futures::{future::{self, Future}, Async, Poll};
 use log::Level;
 use multiaddr::{Protocol, Multiaddr};
 use std::fmt;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::net::IpAddr;
+use std::vec::IntoIter as VecIntoIter;
 use swarm::Transport;
-use tokio_dns::{CpuPoolResolver, Resolver};
+use tokio_dns::{CpuPoolResolver, Resolver as TokioResolver};
+
+/// Maximum number of nested `/dnsaddr/` lookups performed when resolving an address.
+const MAX_DNSADDR_DEPTH: u32 = 4;
+
+/// Maximum number of addresses that a single multiaddress can resolve to.
+const MAX_RESOLVED_ADDRS: usize = 32;
+
+/// Performs DNS lookups on behalf of a `DnsConfig`.
+pub trait Resolver {
+    /// Future that resolves a name to IP addresses.
+    type IpFuture: Future<Item = Vec<IpAddr>, Error = IoError> + Send + 'static;
+    /// Future that resolves a name to the content of its TXT records.
+    type TxtFuture: Future<Item = Vec<String>, Error = IoError> + Send + 'static;
+
+    /// Looks up the A and AAAA records of `name`.
+    fn resolve_ip(&self, name: &str) -> Self::IpFuture;
+
+    /// Looks up the TXT records of `name`.
+    fn resolve_txt(&self, name: &str) -> Self::TxtFuture;
+}
+
+/// Resolver that uses the resolution mechanism of the operating system on a thread pool.
+///
+/// > **Note**: The operating system doesn't give access to TXT records, therefore this resolver
+/// >           can't resolve `/dnsaddr/` components.
+#[derive(Clone)]
+pub struct SystemResolver {
+    inner: CpuPoolResolver,
+}
+
+impl SystemResolver {
+    /// Creates a new resolver that uses the given number of threads.
+    #[inline]
+    pub fn new(num_threads: usize) -> SystemResolver {
+        trace!("Created a CpuPoolResolver");
+        SystemResolver {
+            inner: CpuPoolResolver::new(num_threads),
+        }
+    }
+}
+
+impl fmt::Debug for SystemResolver {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("SystemResolver").finish()
+    }
+}
+
+impl Resolver for SystemResolver {
+    type IpFuture = Box<Future<Item = Vec<IpAddr>, Error = IoError> + Send>;
+    type TxtFuture = future::FutureResult<Vec<String>, IoError>;
+
+    #[inline]
+    fn resolve_ip(&self, name: &str) -> Self::IpFuture {
+        self.inner.resolve(name)
+    }
+
+    #[inline]
+    fn resolve_txt(&self, _: &str) -> Self::TxtFuture {
+        future::err(IoError::new(
+            IoErrorKind::Other,
+            "the system resolver doesn't support TXT lookups",
+        ))
+    }
+}
 
 /// Represents the configuration for a DNS transport capability of libp2p.
 ///
 /// This struct implements the `Transport` trait and holds an underlying transport. Any call to
-/// `dial` with a multiaddr that contains `/dns4/` or `/dns6/` will be first be resolved, then
-/// passed to the underlying transport.
+/// `dial` with a multiaddr that contains `/dns4/`, `/dns6/` or `/dnsaddr/` will be first be
+/// resolved, then passed to the underlying transport.
 ///
 /// Listening is unaffected.
 #[derive(Clone)]
-pub struct DnsConfig<T> {
+pub struct DnsConfig<T, R = SystemResolver> {
     inner: T,
-    resolver: CpuPoolResolver,
+    resolver: R,
 }
 
 impl<T> DnsConfig<T> {
@@ -73,16 +146,22 @@ impl<T> DnsConfig<T> {
     /// Same as `new`, but allows specifying a number of threads for the resolving.
     #[inline]
     pub fn with_resolve_threads(inner: T, num_threads: usize) -> DnsConfig<T> {
-        trace!("Created a CpuPoolResolver");
+        DnsConfig::with_resolver(inner, SystemResolver::new(num_threads))
+    }
+}
 
+impl<T, R> DnsConfig<T, R> {
+    /// Creates a new configuration object that uses the given resolver.
+    #[inline]
+    pub fn with_resolver(inner: T, resolver: R) -> DnsConfig<T, R> {
         DnsConfig {
             inner,
-            resolver: CpuPoolResolver::new(num_threads),
+            resolver,
         }
     }
 }
 
-impl<T> fmt::Debug for DnsConfig<T>
+impl<T, R> fmt::Debug for DnsConfig<T, R>
 where
     T: fmt::Debug,
 {
@@ -92,10 +171,11 @@ where
     }
 }
 
-impl<T> Transport for DnsConfig<T>
+impl<T, R> Transport for DnsConfig<T, R>
 where
-    T: Transport + Send + 'static, // TODO: 'static :-/
+    T: Transport + Clone + Send + 'static, // TODO: 'static :-/
     T::Dial: Send,
+    R: Resolver + Clone + Send + 'static,
 {
     type Output = T::Output;
     type Listener = T::Listener;
@@ -117,13 +197,7 @@ where
     }
 
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
-        let contains_dns = addr.iter().any(|cmp| match cmp {
-            Protocol::Dns4(_) => true,
-            Protocol::Dns6(_) => true,
-            _ => false,
-        });
-
-        if !contains_dns {
+        if !contains_dns(&addr) {
             trace!("Pass-through address without DNS: {}", addr);
             return match self.inner.dial(addr) {
                 Ok(d) => Ok(Box::new(d) as Box<_>),
@@ -137,46 +211,222 @@ where
             };
         }
 
-        let resolver = self.resolver;
-
         trace!("Dialing address with DNS: {}", addr);
-        let resolve_iters = addr.iter()
-            .map(move |cmp| match cmp {
-                Protocol::Dns4(ref name) => {
-                    future::Either::A(resolve_dns(name, &resolver, ResolveTy::Dns4))
+        let inner = self.inner;
+        let future = resolve_addr(addr, self.resolver, MAX_DNSADDR_DEPTH)
+            .and_then(move |addrs| DialFallback {
+                transport: inner,
+                addrs: addrs.into_iter(),
+                current: None,
+                last_error: None,
+            });
+
+        Ok(Box::new(future) as Box<_>)
+    }
+}
+
+/// Future that dials a list of addresses one after the other, and produces the first connection
+/// that succeeds.
+struct DialFallback<T>
+where
+    T: Transport,
+{
+    transport: T,
+    addrs: VecIntoIter<Multiaddr>,
+    current: Option<T::Dial>,
+    last_error: Option<IoError>,
+}
+
+impl<T> Future for DialFallback<T>
+where
+    T: Transport + Clone,
+{
+    type Item = T::Output;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if let Some(mut current) = self.current.take() {
+                match current.poll() {
+                    Ok(Async::Ready(output)) => return Ok(Async::Ready(output)),
+                    Ok(Async::NotReady) => {
+                        self.current = Some(current);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        debug!("Dialing resolved address failed: {:?}", err);
+                        self.last_error = Some(err);
+                    }
                 }
-                Protocol::Dns6(ref name) => {
-                    future::Either::A(resolve_dns(name, &resolver, ResolveTy::Dns6))
+            }
+
+            let addr = match self.addrs.next() {
+                Some(addr) => addr,
+                None => {
+                    return Err(self.last_error.take().unwrap_or_else(|| {
+                        IoError::new(IoErrorKind::Other, "couldn't find any relevant IP address")
+                    }))
                 }
-                cmp => future::Either::B(future::ok(cmp.acquire())),
-            })
-            .collect::<Vec<_>>()
-            .into_iter();
+            };
 
-        let new_addr = future::join_all(resolve_iters).map(move |outcome| {
-            let outcome: Multiaddr = outcome.into_iter().collect();
-            debug!("DNS resolution outcome: {} => {}", addr, outcome);
-            outcome
-        });
+            trace!("Dialing resolved address {}", addr);
+            match self.transport.clone().dial(addr) {
+                Ok(dial) => self.current = Some(dial),
+                Err((_, addr)) => {
+                    debug!("Resolved address not supported: {}", addr);
+                    let err = IoError::new(IoErrorKind::Other, "multiaddr not supported");
+                    self.last_error = Some(err);
+                }
+            }
+        }
+    }
+}
 
-        let inner = self.inner;
-        let future = new_addr
-            .and_then(move |addr| {
-                inner
-                    .dial(addr)
-                    .map_err(|_| IoError::new(IoErrorKind::Other, "multiaddr not supported"))
+// Returns true if the address contains a component that needs to be resolved.
+fn contains_dns(addr: &Multiaddr) -> bool {
+    addr.iter().any(|cmp| match cmp {
+        Protocol::Dns4(_) => true,
+        Protocol::Dns6(_) => true,
+        Protocol::Dnsaddr(_) => true,
+        _ => false,
+    })
+}
+
+// Resolves all the DNS components of `addr`, and produces the list of addresses to dial.
+fn resolve_addr<R>(
+    addr: Multiaddr,
+    resolver: R,
+    depth: u32,
+) -> Box<Future<Item = Vec<Multiaddr>, Error = IoError> + Send>
+where
+    R: Resolver + Clone + Send + 'static,
+{
+    let components = addr.iter().map(|cmp| cmp.acquire()).collect::<Vec<Protocol<'static>>>();
+
+    let dnsaddr_pos = components.iter().position(|cmp| match cmp {
+        Protocol::Dnsaddr(_) => true,
+        _ => false,
+    });
+
+    if let Some(pos) = dnsaddr_pos {
+        if depth == 0 {
+            return Box::new(future::err(IoError::new(
+                IoErrorKind::Other,
+                "too many nested /dnsaddr/ lookups",
+            )));
+        }
+
+        let name = match components[pos] {
+            Protocol::Dnsaddr(ref name) => name.clone().into_owned(),
+            _ => unreachable!("we checked that pos points to a dnsaddr component"),
+        };
+        let prefix = components[..pos].to_vec();
+        let suffix = components[pos + 1..].to_vec();
+
+        let future = resolver
+            .resolve_txt(&format!("_dnsaddr.{}", name))
+            .and_then(move |records| {
+                let addrs = records
+                    .iter()
+                    .filter_map(|record| parse_dnsaddr_record(record))
+                    .filter(|addr| ends_with(addr, &suffix))
+                    .map(|addr| {
+                        prefix.iter().cloned().chain(addr.iter().map(|cmp| cmp.acquire())).collect()
+                    })
+                    .collect::<Vec<Multiaddr>>();
+                debug!("DNS resolution outcome: /dnsaddr/{} => {:?}", name, addrs);
+
+                let resolves = addrs
+                    .into_iter()
+                    .map(move |addr| {
+                        // Ignore the entries that fail to resolve, as long as one succeeds.
+                        resolve_addr(addr, resolver.clone(), depth - 1).then(|result| {
+                            Ok::<_, IoError>(result.ok())
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                future::join_all(resolves)
             })
-            .flatten();
+            .and_then(|outcomes| {
+                let addrs = outcomes
+                    .into_iter()
+                    .filter_map(|addrs| addrs)
+                    .flat_map(|addrs| addrs)
+                    .take(MAX_RESOLVED_ADDRS)
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() {
+                    Err(IoError::new(IoErrorKind::Other, "no usable /dnsaddr/ record"))
+                } else {
+                    Ok(addrs)
+                }
+            });
 
-        Ok(Box::new(future) as Box<_>)
+        return Box::new(future);
     }
 
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        // Since `listen_on` doesn't perform any resolution, we just pass through `nat_traversal`
-        // as well.
-        self.inner.nat_traversal(server, observed)
+    let resolve_iters = components
+        .into_iter()
+        .map(move |cmp| match cmp {
+            Protocol::Dns4(ref name) => {
+                future::Either::A(resolve_dns(name, &resolver, ResolveTy::Dns4))
+            }
+            Protocol::Dns6(ref name) => {
+                future::Either::A(resolve_dns(name, &resolver, ResolveTy::Dns6))
+            }
+            cmp => future::Either::B(future::ok(vec![cmp])),
+        })
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    let future = future::join_all(resolve_iters).map(move |outcome| {
+        // Build every combination of the resolved components.
+        let mut addrs = vec![Vec::new()];
+        for alternatives in outcome {
+            let mut next = Vec::with_capacity(addrs.len() * alternatives.len());
+            for prefix in &addrs {
+                for cmp in &alternatives {
+                    if next.len() >= MAX_RESOLVED_ADDRS {
+                        break;
+                    }
+                    let mut prefix: Vec<Protocol<'static>> = prefix.clone();
+                    prefix.push(cmp.clone());
+                    next.push(prefix);
+                }
+            }
+            addrs = next;
+        }
+
+        let addrs = addrs
+            .into_iter()
+            .map(|cmps| cmps.into_iter().collect::<Multiaddr>())
+            .collect::<Vec<_>>();
+        debug!("DNS resolution outcome: {} => {:?}", addr, addrs);
+        addrs
+    });
+
+    Box::new(future)
+}
+
+// Parses the content of a `_dnsaddr` TXT record. Returns `None` if the record is not a valid
+// `dnsaddr=<multiaddr>` entry.
+fn parse_dnsaddr_record(record: &str) -> Option<Multiaddr> {
+    if !record.starts_with("dnsaddr=") {
+        return None;
     }
+
+    match record["dnsaddr=".len()..].parse() {
+        Ok(addr) => Some(addr),
+        Err(err) => {
+            debug!("Invalid /dnsaddr/ record {:?}: {:?}", record, err);
+            None
+        }
+    }
+}
+
+// Returns true if the last components of `addr` are `suffix`.
+fn ends_with<'a>(addr: &'a Multiaddr, suffix: &[Protocol<'a>]) -> bool {
+    let cmps = addr.iter().collect::<Vec<_>>();
+    cmps.len() >= suffix.len() && &cmps[cmps.len() - suffix.len()..] == suffix
 }
 
 // How to resolve ; to an IPv4 address or an IPv6 address?
@@ -186,19 +436,22 @@ enum ResolveTy {
     Dns6,
 }
 
-// Resolve a DNS name and returns a future with the result.
-fn resolve_dns<'a>(
+// Resolve a DNS name and returns a future with all the matching addresses.
+fn resolve_dns<R>(
     name: &str,
-    resolver: &CpuPoolResolver,
+    resolver: &R,
     ty: ResolveTy,
-) -> impl Future<Item = Protocol<'a>, Error = IoError> {
+) -> impl Future<Item = Vec<Protocol<'static>>, Error = IoError>
+where
+    R: Resolver,
+{
     let debug_name = if log_enabled!(Level::Trace) {
         Some(name.to_owned())
     } else {
         None
     };
 
-    resolver.resolve(name).and_then(move |addrs| {
+    resolver.resolve_ip(name).and_then(move |addrs| {
         if log_enabled!(Level::Trace) {
             trace!(
                 "DNS component resolution: {} => {:?}",
@@ -207,17 +460,20 @@ fn resolve_dns<'a>(
             );
         }
 
-        addrs
+        let protocols = addrs
             .into_iter()
             .filter_map(move |addr| match (addr, ty) {
                 (IpAddr::V4(addr), ResolveTy::Dns4) => Some(Protocol::Ip4(addr)),
                 (IpAddr::V6(addr), ResolveTy::Dns6) => Some(Protocol::Ip6(addr)),
                 _ => None,
             })
-            .next()
-            .ok_or_else(|| {
-                IoError::new(IoErrorKind::Other, "couldn't find any relevant IP address")
-            })
+            .collect::<Vec<_>>();
+
+        if protocols.is_empty() {
+            Err(IoError::new(IoErrorKind::Other, "couldn't find any relevant IP address"))
+        } else {
+            Ok(protocols)
+        }
     })
 }
 
@@ -226,10 +482,38 @@ mod tests {
     extern crate libp2p_tcp_transport;
     use self::libp2p_tcp_transport::TcpConfig;
     use futures::future;
+    use futures::{future::FutureResult, stream, Future};
     use multiaddr::{Protocol, Multiaddr};
-    use std::io::Error as IoError;
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use std::net::IpAddr;
     use swarm::Transport;
-    use DnsConfig;
+    use {resolve_addr, DnsConfig, Resolver};
+
+    // Resolver that always returns the same records, whatever the name.
+    #[derive(Clone)]
+    struct StaticResolver {
+        ips: Vec<IpAddr>,
+        txt: Vec<(String, Vec<String>)>,
+    }
+
+    impl Resolver for StaticResolver {
+        type IpFuture = FutureResult<Vec<IpAddr>, IoError>;
+        type TxtFuture = FutureResult<Vec<String>, IoError>;
+
+        fn resolve_ip(&self, _: &str) -> Self::IpFuture {
+            future::ok(self.ips.clone())
+        }
+
+        fn resolve_txt(&self, name: &str) -> Self::TxtFuture {
+            let records = self.txt.iter()
+                .find(|&&(ref n, _)| n == name)
+                .map(|&(_, ref records)| records.clone());
+            match records {
+                Some(records) => future::ok(records),
+                None => future::err(IoError::new(IoErrorKind::Other, "no such name")),
+            }
+        }
+    }
 
     #[test]
     fn basic_resolve() {
@@ -263,11 +547,6 @@ mod tests {
                 };
                 Ok(future::empty())
             }
-
-            #[inline]
-            fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
-                panic!()
-            }
         }
 
         let transport = DnsConfig::new(CustomTransport);
@@ -280,4 +559,123 @@ mod tests {
             .dial("/dns6/example.com/tcp/20000".parse().unwrap())
             .unwrap_or_else(|_| panic!());
     }
+
+    #[test]
+    fn multiple_records() {
+        let resolver = StaticResolver {
+            ips: vec![
+                "1.2.3.4".parse().unwrap(),
+                "::1".parse().unwrap(),
+                "5.6.7.8".parse().unwrap(),
+            ],
+            txt: Vec::new(),
+        };
+
+        let addr = "/dns4/example.com/tcp/20000".parse().unwrap();
+        let addrs = resolve_addr(addr, resolver.clone(), 1)
+            .wait()
+            .unwrap();
+        assert_eq!(addrs, vec![
+            "/ip4/1.2.3.4/tcp/20000".parse::<Multiaddr>().unwrap(),
+            "/ip4/5.6.7.8/tcp/20000".parse().unwrap(),
+        ]);
+
+        let addrs = resolve_addr("/dns6/example.com/tcp/20000".parse().unwrap(), resolver, 1)
+            .wait()
+            .unwrap();
+        assert_eq!(addrs, vec!["/ip6/::1/tcp/20000".parse::<Multiaddr>().unwrap()]);
+    }
+
+    #[test]
+    fn dnsaddr() {
+        let resolver = StaticResolver {
+            ips: vec!["1.2.3.4".parse().unwrap()],
+            txt: vec![
+                ("_dnsaddr.bootstrap.example.com".to_owned(), vec![
+                    "dnsaddr=/dns4/a.example.com/tcp/4001/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC".to_owned(),
+                    "dnsaddr=/ip4/9.9.9.9/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN".to_owned(),
+                    "dnsaddr=/dnsaddr/nested.example.com".to_owned(),
+                    "unrelated record".to_owned(),
+                ]),
+                ("_dnsaddr.nested.example.com".to_owned(), vec![
+                    "dnsaddr=/ip4/8.8.8.8/tcp/4001/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC".to_owned(),
+                ]),
+            ],
+        };
+
+        let addr = "/dnsaddr/bootstrap.example.com".parse().unwrap();
+        let addrs = resolve_addr(addr, resolver.clone(), 2)
+            .wait()
+            .unwrap();
+        assert_eq!(addrs, vec![
+            "/ip4/1.2.3.4/tcp/4001/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC".parse::<Multiaddr>().unwrap(),
+            "/ip4/9.9.9.9/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN".parse().unwrap(),
+            "/ip4/8.8.8.8/tcp/4001/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC".parse().unwrap(),
+        ]);
+
+        // Only the records that match the peer ID are kept.
+        let addr =
+            "/dnsaddr/bootstrap.example.com/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN";
+        let addrs = resolve_addr(addr.parse().unwrap(), resolver.clone(), 2).wait().unwrap();
+        assert_eq!(addrs, vec![
+            "/ip4/9.9.9.9/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN".parse::<Multiaddr>().unwrap(),
+        ]);
+
+        // The nested lookup isn't performed if the depth is exhausted.
+        let addrs = resolve_addr("/dnsaddr/bootstrap.example.com".parse().unwrap(), resolver, 1)
+            .wait()
+            .unwrap();
+        assert_eq!(addrs.len(), 2);
+    }
+
+    #[test]
+    fn dial_fallback() {
+        // Transport whose dials only succeed for one specific address.
+        #[derive(Clone)]
+        struct OnlyOne(Multiaddr);
+        impl Transport for OnlyOne {
+            type Output = Multiaddr;
+            type Listener = stream::Empty<(Self::ListenerUpgrade, Multiaddr), IoError>;
+            type ListenerUpgrade = FutureResult<Multiaddr, IoError>;
+            type Dial = FutureResult<Multiaddr, IoError>;
+
+            fn listen_on(
+                self,
+                addr: Multiaddr,
+            ) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+                Err((self, addr))
+            }
+
+            fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+                if addr == self.0 {
+                    Ok(future::ok(addr))
+                } else {
+                    Ok(future::err(IoError::new(IoErrorKind::ConnectionRefused, "refused")))
+                }
+            }
+        }
+
+        let resolver = StaticResolver {
+            ips: vec!["1.2.3.4".parse().unwrap(), "5.6.7.8".parse().unwrap()],
+            txt: Vec::new(),
+        };
+
+        let working = "/ip4/5.6.7.8/tcp/20000".parse::<Multiaddr>().unwrap();
+        let transport = DnsConfig::with_resolver(OnlyOne(working.clone()), resolver.clone());
+        let output = transport
+            .dial("/dns4/example.com/tcp/20000".parse().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .wait()
+            .unwrap();
+        assert_eq!(output, working);
+
+        let unreachable = "/ip4/9.9.9.9/tcp/20000".parse::<Multiaddr>().unwrap();
+        let transport = DnsConfig::with_resolver(OnlyOne(unreachable), resolver);
+        let err = transport
+            .dial("/dns4/example.com/tcp/20000".parse().unwrap())
+            .unwrap_or_else(|_| panic!())
+            .wait()
+            .unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::ConnectionRefused);
+    }
 }
@@ -0,0 +1,136 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Client side of the HTTP `CONNECT` method.
+
+use base64;
+use futures::future::{self, Future, Loop};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use tokio_io::io::{read_exact, write_all};
+use tokio_io::{AsyncRead, AsyncWrite};
+use {Credentials, TargetAddr};
+
+/// Maximum size of the response headers of the proxy.
+const MAX_RESPONSE_LEN: usize = 8192;
+
+/// Asks the HTTP proxy at the other side of `socket` to connect to `target`. Produces the
+/// socket, which is then connected to `target`, once the proxy has accepted.
+pub fn connect<S>(
+    socket: S,
+    target: TargetAddr,
+    credentials: Option<Credentials>,
+) -> Box<Future<Item = S, Error = IoError> + Send>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let request = connect_request(&target, credentials.as_ref());
+
+    let future = write_all(socket, request.into_bytes())
+        .and_then(|(socket, _)| read_response(socket))
+        .and_then(move |(socket, response)| {
+            let status = parse_status(&response)?;
+            if status >= 200 && status < 300 {
+                Ok(socket)
+            } else {
+                debug!("HTTP proxy failed to connect to {}: status {}", target, status);
+                let kind = match status {
+                    403 | 407 => IoErrorKind::PermissionDenied,
+                    _ => IoErrorKind::Other,
+                };
+                Err(IoError::new(kind, format!("HTTP proxy responded with status {}", status)))
+            }
+        });
+
+    Box::new(future)
+}
+
+/// Builds the `CONNECT` request for `target`.
+fn connect_request(target: &TargetAddr, credentials: Option<&Credentials>) -> String {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(credentials) = credentials {
+        let token = base64::encode(&format!("{}:{}", credentials.username, credentials.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+/// Reads the response headers of the proxy, up to and including the empty line that ends them.
+///
+/// The response is read byte by byte, so that we don't consume the data that follows it and
+/// that belongs to the tunnel.
+fn read_response<S>(socket: S) -> impl Future<Item = (S, Vec<u8>), Error = IoError>
+where
+    S: AsyncRead,
+{
+    future::loop_fn((socket, Vec::new()), |(socket, mut response)| {
+        read_exact(socket, [0; 1]).and_then(move |(socket, byte)| {
+            response.push(byte[0]);
+            if response.ends_with(b"\r\n\r\n") {
+                Ok(Loop::Break((socket, response)))
+            } else if response.len() >= MAX_RESPONSE_LEN {
+                Err(IoError::new(IoErrorKind::InvalidData, "HTTP proxy response too long"))
+            } else {
+                Ok(Loop::Continue((socket, response)))
+            }
+        })
+    })
+}
+
+/// Extracts the status code from the response of the proxy.
+fn parse_status(response: &[u8]) -> Result<u16, IoError> {
+    let invalid = || IoError::new(IoErrorKind::InvalidData, "invalid HTTP proxy response");
+    let line_end = response.windows(2).position(|w| w == b"\r\n").ok_or_else(invalid)?;
+    let line = String::from_utf8_lossy(&response[..line_end]);
+    let mut parts = line.split(' ');
+    match parts.next() {
+        Some(version) if version.starts_with("HTTP/1.") => (),
+        _ => return Err(invalid()),
+    }
+    parts.next().and_then(|code| code.parse().ok()).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connect_request, parse_status};
+    use {Credentials, TargetAddr};
+
+    #[test]
+    fn request_format() {
+        let target = TargetAddr::Ip("[::1]:80".parse().unwrap());
+        assert_eq!(connect_request(&target, None), "CONNECT [::1]:80 HTTP/1.1\r\nHost: [::1]:80\r\n\r\n");
+
+        let credentials = Credentials { username: "a".to_owned(), password: "b".to_owned() };
+        let target = TargetAddr::Domain("example.com".to_owned(), 443);
+        assert_eq!(
+            connect_request(&target, Some(&credentials)),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\
+             Proxy-Authorization: Basic YTpi\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn status_parsing() {
+        assert_eq!(parse_status(b"HTTP/1.1 200 OK\r\n\r\n").unwrap(), 200);
+        assert_eq!(parse_status(b"HTTP/1.0 407 Auth\r\n\r\n").unwrap(), 407);
+        assert!(parse_status(b"SSH-2.0\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Wraps around a `Transport` and routes the outgoing connections through a proxy.
+//!
+//! Nodes in restricted environments sometimes can only reach the outside through a proxy. The
+//! `ProxyTransport` dials the proxy with the underlying transport, then asks it to open a tunnel
+//! to the actual destination. Both SOCKS5 and HTTP `CONNECT` proxies are supported, with an
+//! optional username and password.
+//!
+//! Only the addresses of the form `/ip4/.../tcp/...`, `/ip6/.../tcp/...`, `/dns4/.../tcp/...`
+//! and `/dns6/.../tcp/...` can be dialed through a proxy. The names of the `/dns4/` and `/dns6/`
+//! addresses are resolved by the proxy. Listening is not affected and is passed through to the
+//! underlying transport.
+//!
+//! # Usage
+//!
+//! The proxy is configured per transport. Since the WebSocket transport dials the TCP/IP part of
+//! its addresses with the transport it wraps, wrapping a `ProxyTransport` in it routes the
+//! WebSocket connections through the proxy as well.
+//!
+//! ```
+//! extern crate libp2p_proxy;
+//! extern crate libp2p_tcp_transport;
+//!
+//! use libp2p_proxy::{ProxyConfig, ProxyTransport};
+//! use libp2p_tcp_transport::TcpConfig;
+//!
+//! # fn main() {
+//! let proxy = ProxyConfig::socks5("/ip4/127.0.0.1/tcp/1080".parse().unwrap())
+//!     .with_credentials("user", "password");
+//! let transport = ProxyTransport::new(TcpConfig::new(), proxy);
+//! # }
+//! ```
+
+extern crate base64;
+extern crate futures;
+extern crate libp2p_core;
+#[macro_use]
+extern crate log;
+extern crate multiaddr;
+extern crate tokio_io;
+
+#[cfg(test)]
+extern crate libp2p_tcp_transport;
+#[cfg(test)]
+extern crate tokio_current_thread;
+
+mod http;
+mod socks5;
+
+use futures::prelude::*;
+use libp2p_core::{MuxedTransport, Transport};
+use multiaddr::{Multiaddr, Protocol};
+use std::fmt;
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Protocol spoken by a proxy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// SOCKS version 5, as described in RFC 1928. Authentication uses the username/password
+    /// method of RFC 1929.
+    Socks5,
+    /// HTTP `CONNECT` method. Authentication uses the `Basic` scheme.
+    HttpConnect,
+}
+
+/// Username and password used to authenticate with a proxy.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// The username.
+    pub username: String,
+    /// The password.
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
+/// Configuration of the proxy to dial through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Address of the proxy, dialed with the underlying transport.
+    address: Multiaddr,
+    /// Protocol spoken by the proxy.
+    protocol: ProxyProtocol,
+    /// Credentials to authenticate with, if any.
+    credentials: Option<Credentials>,
+}
+
+impl ProxyConfig {
+    /// Creates a configuration for a SOCKS5 proxy reachable at the given address.
+    #[inline]
+    pub fn socks5(address: Multiaddr) -> ProxyConfig {
+        ProxyConfig::new(address, ProxyProtocol::Socks5)
+    }
+
+    /// Creates a configuration for an HTTP proxy reachable at the given address.
+    #[inline]
+    pub fn http_connect(address: Multiaddr) -> ProxyConfig {
+        ProxyConfig::new(address, ProxyProtocol::HttpConnect)
+    }
+
+    /// Creates a configuration for a proxy reachable at the given address.
+    #[inline]
+    pub fn new(address: Multiaddr, protocol: ProxyProtocol) -> ProxyConfig {
+        ProxyConfig {
+            address,
+            protocol,
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy using the given username and password.
+    ///
+    /// > **Note**: SOCKS5 limits the username and the password to 255 bytes each. Dialing fails
+    /// >           if they are longer.
+    #[inline]
+    pub fn with_credentials<U, P>(mut self, username: U, password: P) -> ProxyConfig
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.credentials = Some(Credentials {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Returns the address of the proxy.
+    #[inline]
+    pub fn address(&self) -> &Multiaddr {
+        &self.address
+    }
+
+    /// Returns the protocol spoken by the proxy.
+    #[inline]
+    pub fn protocol(&self) -> ProxyProtocol {
+        self.protocol
+    }
+
+    /// Returns the credentials used to authenticate with the proxy, if any.
+    #[inline]
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+}
+
+/// Wraps around a `Transport` and dials all the outgoing connections through a proxy.
+#[derive(Debug, Clone)]
+pub struct ProxyTransport<T> {
+    /// The underlying transport, used to reach the proxy.
+    inner: T,
+    /// The proxy to use.
+    config: ProxyConfig,
+}
+
+impl<T> ProxyTransport<T> {
+    /// Wraps around `inner` and dials through the proxy described by `config`.
+    #[inline]
+    pub fn new(inner: T, config: ProxyConfig) -> ProxyTransport<T> {
+        ProxyTransport { inner, config }
+    }
+
+    /// Returns the configuration of the proxy.
+    #[inline]
+    pub fn config(&self) -> &ProxyConfig {
+        &self.config
+    }
+}
+
+impl<T> Transport for ProxyTransport<T>
+where
+    T: Transport,
+    T::Dial: Send + 'static,
+    T::Output: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type Output = T::Output;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+    #[inline]
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let config = self.config;
+        self.inner
+            .listen_on(addr)
+            .map_err(|(inner, addr)| (ProxyTransport { inner, config }, addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let target = match TargetAddr::from_multiaddr(&addr) {
+            Some(target) => target,
+            None => {
+                trace!("Ignoring dial attempt for {} because it can't be proxied", addr);
+                return Err((self, addr));
+            }
+        };
+
+        let config = self.config;
+        let dial = match self.inner.dial(config.address.clone()) {
+            Ok(dial) => dial,
+            Err((inner, _)) => {
+                debug!("Proxy address {} is not supported by the underlying transport",
+                       config.address);
+                return Err((ProxyTransport { inner, config }, addr));
+            }
+        };
+
+        debug!("Dialing {} through proxy {}", addr, config.address);
+        let credentials = config.credentials;
+        let future: Self::Dial = match config.protocol {
+            ProxyProtocol::Socks5 => Box::new(dial.and_then(move |socket| {
+                socks5::connect(socket, target, credentials)
+            })),
+            ProxyProtocol::HttpConnect => Box::new(dial.and_then(move |socket| {
+                http::connect(socket, target, credentials)
+            })),
+        };
+        Ok(future)
+    }
+}
+
+impl<T> MuxedTransport for ProxyTransport<T>
+where
+    T: MuxedTransport,
+    T::Dial: Send + 'static,
+    T::Output: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type Incoming = T::Incoming;
+    type IncomingUpgrade = T::IncomingUpgrade;
+
+    #[inline]
+    fn next_incoming(self) -> Self::Incoming {
+        self.inner.next_incoming()
+    }
+}
+
+/// Destination of a connection, as sent to the proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TargetAddr {
+    /// An IP address and a port.
+    Ip(SocketAddr),
+    /// A domain name, resolved by the proxy, and a port.
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    /// Extracts the destination from an address of the form `/<ip or dns>/.../tcp/<port>`.
+    fn from_multiaddr(addr: &Multiaddr) -> Option<TargetAddr> {
+        let mut iter = addr.iter();
+        let proto1 = iter.next()?;
+        let proto2 = iter.next()?;
+        if iter.next().is_some() {
+            return None;
+        }
+
+        match (proto1, proto2) {
+            (Protocol::Ip4(ip), Protocol::Tcp(port)) => {
+                Some(TargetAddr::Ip(SocketAddr::new(ip.into(), port)))
+            }
+            (Protocol::Ip6(ip), Protocol::Tcp(port)) => {
+                Some(TargetAddr::Ip(SocketAddr::new(ip.into(), port)))
+            }
+            (Protocol::Dns4(name), Protocol::Tcp(port)) |
+            (Protocol::Dns6(name), Protocol::Tcp(port)) => {
+                Some(TargetAddr::Domain(name.into_owned(), port))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TargetAddr::Ip(ref addr) => write!(f, "{}", addr),
+            TargetAddr::Domain(ref name, port) => write!(f, "{}:{}", name, port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_tcp_transport::TcpConfig;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio_current_thread;
+    use tokio_io;
+
+    #[test]
+    fn target_addresses() {
+        let target = |s: &str| TargetAddr::from_multiaddr(&s.parse().unwrap());
+        assert_eq!(
+            target("/ip4/1.2.3.4/tcp/80"),
+            Some(TargetAddr::Ip("1.2.3.4:80".parse().unwrap()))
+        );
+        assert_eq!(
+            target("/ip6/::1/tcp/80"),
+            Some(TargetAddr::Ip("[::1]:80".parse().unwrap()))
+        );
+        assert_eq!(
+            target("/dns4/example.com/tcp/443"),
+            Some(TargetAddr::Domain("example.com".to_owned(), 443))
+        );
+        assert_eq!(target("/ip4/1.2.3.4/udp/80"), None);
+        assert_eq!(target("/ip4/1.2.3.4/tcp/80/ws"), None);
+    }
+
+    #[test]
+    fn unsupported_address_refused() {
+        let proxy = ProxyConfig::socks5("/ip4/127.0.0.1/tcp/1080".parse().unwrap());
+        let transport = ProxyTransport::new(TcpConfig::new(), proxy);
+        assert!(transport.dial("/ip4/1.2.3.4/udp/80".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn dial_through_socks5() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = server.local_addr().unwrap().port();
+
+        let proxy = thread::spawn(move || {
+            let (mut socket, _) = server.accept().unwrap();
+
+            let mut greeting = [0; 4];
+            socket.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            socket.write_all(&[5, 2]).unwrap();
+
+            let mut auth = [0; 11];
+            socket.read_exact(&mut auth).unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            socket.write_all(&[1, 0]).unwrap();
+
+            let mut request = [0; 18];
+            socket.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..5], &[5, 1, 0, 3, 11]);
+            assert_eq!(&request[5..16], b"example.com");
+            assert_eq!(&request[16..], &[0x01, 0xbb]);
+            socket.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0x30, 0x39]).unwrap();
+
+            socket.write_all(b"hello").unwrap();
+        });
+
+        let config = ProxyConfig::socks5(format!("/ip4/127.0.0.1/tcp/{}", proxy_port).parse().unwrap())
+            .with_credentials("user", "pass");
+        let dial = ProxyTransport::new(TcpConfig::new(), config)
+            .dial("/dns4/example.com/tcp/443".parse().unwrap())
+            .unwrap()
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]));
+
+        let (_, data) = tokio_current_thread::block_on_all(dial).unwrap();
+        assert_eq!(&data, b"hello");
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn dial_through_http_connect() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = server.local_addr().unwrap().port();
+
+        let proxy = thread::spawn(move || {
+            let (mut socket, _) = server.accept().unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                socket.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("CONNECT 1.2.3.4:80 HTTP/1.1\r\n"));
+            assert!(request.contains("\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+
+            socket.write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello").unwrap();
+        });
+
+        let config = ProxyConfig::http_connect(format!("/ip4/127.0.0.1/tcp/{}", proxy_port).parse().unwrap())
+            .with_credentials("user", "pass");
+        let dial = ProxyTransport::new(TcpConfig::new(), config)
+            .dial("/ip4/1.2.3.4/tcp/80".parse().unwrap())
+            .unwrap()
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]));
+
+        let (_, data) = tokio_current_thread::block_on_all(dial).unwrap();
+        assert_eq!(&data, b"hello");
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn http_connect_refused() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = server.local_addr().unwrap().port();
+
+        let proxy = thread::spawn(move || {
+            let (mut socket, _) = server.accept().unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                socket.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            socket.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").unwrap();
+        });
+
+        let config = ProxyConfig::http_connect(format!("/ip4/127.0.0.1/tcp/{}", proxy_port).parse().unwrap());
+        let dial = ProxyTransport::new(TcpConfig::new(), config)
+            .dial("/ip4/1.2.3.4/tcp/80".parse().unwrap())
+            .unwrap();
+
+        assert!(tokio_current_thread::block_on_all(dial).is_err());
+        proxy.join().unwrap();
+    }
+}
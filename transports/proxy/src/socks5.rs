@@ -0,0 +1,225 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Client side of the SOCKS5 protocol, as described in RFC 1928 and RFC 1929.
+
+use futures::future::{self, Future};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::IpAddr;
+use tokio_io::io::{read_exact, write_all};
+use tokio_io::{AsyncRead, AsyncWrite};
+use {Credentials, TargetAddr};
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USER_PASS: u8 = 2;
+const USER_PASS_VERSION: u8 = 1;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Asks the SOCKS5 proxy at the other side of `socket` to connect to `target`. Produces the
+/// socket, which is then connected to `target`, once the proxy has accepted.
+pub fn connect<S>(
+    socket: S,
+    target: TargetAddr,
+    credentials: Option<Credentials>,
+) -> Box<Future<Item = S, Error = IoError> + Send>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let request = match connect_request(&target) {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let auth = match credentials.map(|c| auth_request(&c)) {
+        Some(Ok(auth)) => Some(auth),
+        Some(Err(err)) => return Box::new(future::err(err)),
+        None => None,
+    };
+
+    let greeting = if auth.is_some() {
+        vec![VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS]
+    } else {
+        vec![VERSION, 1, METHOD_NO_AUTH]
+    };
+
+    let future = write_all(socket, greeting)
+        .and_then(|(socket, _)| read_exact(socket, [0; 2]))
+        .and_then(move |(socket, reply)| -> Box<Future<Item = S, Error = IoError> + Send> {
+            if reply[0] != VERSION {
+                return Box::new(future::err(invalid_data("invalid SOCKS version in reply")));
+            }
+
+            match (reply[1], auth) {
+                (METHOD_NO_AUTH, _) => Box::new(future::ok(socket)),
+                (METHOD_USER_PASS, Some(auth)) => {
+                    let future = write_all(socket, auth)
+                        .and_then(|(socket, _)| read_exact(socket, [0; 2]))
+                        .and_then(|(socket, reply)| {
+                            if reply[1] == 0 {
+                                Ok(socket)
+                            } else {
+                                Err(IoError::new(IoErrorKind::PermissionDenied,
+                                                 "SOCKS5 proxy rejected the credentials"))
+                            }
+                        });
+                    Box::new(future)
+                }
+                _ => Box::new(future::err(IoError::new(
+                    IoErrorKind::PermissionDenied,
+                    "SOCKS5 proxy requires an unsupported authentication method",
+                ))),
+            }
+        })
+        .and_then(move |socket| write_all(socket, request))
+        .and_then(|(socket, _)| read_exact(socket, [0; 4]))
+        .and_then(move |(socket, header)| -> Box<Future<Item = (S, usize), Error = IoError> + Send> {
+            if header[0] != VERSION {
+                return Box::new(future::err(invalid_data("invalid SOCKS version in reply")));
+            }
+            if header[1] != 0 {
+                debug!("SOCKS5 proxy failed to connect to {}: {}", target, reply_message(header[1]));
+                return Box::new(future::err(reply_error(header[1])));
+            }
+
+            // The reply ends with the address the proxy has bound, which we don't need but have
+            // to read before the tunnel starts.
+            match header[3] {
+                ATYP_IPV4 => Box::new(future::ok((socket, 4 + 2))),
+                ATYP_IPV6 => Box::new(future::ok((socket, 16 + 2))),
+                ATYP_DOMAIN => Box::new(read_exact(socket, [0; 1])
+                    .map(|(socket, len)| (socket, len[0] as usize + 2))),
+                _ => Box::new(future::err(invalid_data("invalid address type in SOCKS5 reply"))),
+            }
+        })
+        .and_then(|(socket, len)| read_exact(socket, vec![0; len]))
+        .map(|(socket, _)| socket);
+
+    Box::new(future)
+}
+
+/// Builds the message that asks the proxy to connect to `target`.
+fn connect_request(target: &TargetAddr) -> Result<Vec<u8>, IoError> {
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    let port = match *target {
+        TargetAddr::Ip(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        TargetAddr::Domain(ref name, port) => {
+            if name.len() > 255 {
+                return Err(IoError::new(IoErrorKind::InvalidInput, "domain name too long for SOCKS5"));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name.as_bytes());
+            port
+        }
+    };
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+/// Builds the username/password authentication message.
+fn auth_request(credentials: &Credentials) -> Result<Vec<u8>, IoError> {
+    let username = credentials.username.as_bytes();
+    let password = credentials.password.as_bytes();
+    if username.len() > 255 || password.len() > 255 {
+        return Err(IoError::new(IoErrorKind::InvalidInput, "SOCKS5 credentials too long"));
+    }
+
+    let mut request = Vec::with_capacity(3 + username.len() + password.len());
+    request.push(USER_PASS_VERSION);
+    request.push(username.len() as u8);
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+    Ok(request)
+}
+
+/// Turns the failure code of a reply into an error.
+fn reply_error(code: u8) -> IoError {
+    let kind = match code {
+        2 => IoErrorKind::PermissionDenied,
+        5 => IoErrorKind::ConnectionRefused,
+        _ => IoErrorKind::Other,
+    };
+    IoError::new(kind, format!("SOCKS5 proxy error: {}", reply_message(code)))
+}
+
+/// Returns the meaning of the failure code of a reply.
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[inline]
+fn invalid_data(msg: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{auth_request, connect_request};
+    use {Credentials, TargetAddr};
+
+    #[test]
+    fn connect_requests() {
+        let request = connect_request(&TargetAddr::Ip("1.2.3.4:80".parse().unwrap())).unwrap();
+        assert_eq!(request, vec![5, 1, 0, 1, 1, 2, 3, 4, 0, 80]);
+
+        let request = connect_request(&TargetAddr::Domain("a.b".to_owned(), 443)).unwrap();
+        assert_eq!(request, vec![5, 1, 0, 3, 3, b'a', b'.', b'b', 1, 187]);
+
+        let long_name = TargetAddr::Domain(String::from_utf8(vec![b'a'; 256]).unwrap(), 1);
+        assert!(connect_request(&long_name).is_err());
+    }
+
+    #[test]
+    fn long_credentials_rejected() {
+        let credentials = Credentials {
+            username: String::from_utf8(vec![b'a'; 256]).unwrap(),
+            password: String::new(),
+        };
+        assert!(auth_request(&credentials).is_err());
+    }
+}
@@ -200,8 +200,4 @@ where
             })
             .map_err(|(transport, a)| (RateLimited::from_parts(transport, r2, w2), a))
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.value.nat_traversal(server, observed)
-    }
 }
@@ -18,6 +18,17 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+//! Implementation of the `/libp2p/relay/circuit/0.1.0` protocol.
+//!
+//! A relay forwards the traffic between two nodes that can't connect to each other directly,
+//! for example because one of them is behind a NAT.
+//!
+//! - The `RelayTransport` dials `/p2p-circuit` addresses by asking a relay to open a circuit to
+//!   the destination.
+//! - The `RelayConfig` upgrade must be used by the relays and by the destinations. Depending on
+//!   the request of the remote, it either relays the circuit or produces the stream of a circuit
+//!   of which we are the destination.
+
 extern crate bytes;
 #[macro_use]
 extern crate futures;
@@ -27,7 +38,6 @@ extern crate libp2p_core as core;
 extern crate log;
 extern crate multiaddr;
 extern crate protobuf;
-extern crate rand;
 extern crate tokio_codec;
 extern crate tokio_io;
 extern crate unsigned_varint;
@@ -28,6 +28,10 @@ use std::{io, iter, ops::Deref};
 use tokio_io::{AsyncRead, AsyncWrite};
 use utility::{io_err, is_success, status, Io, Peer};
 
+/// Implementation of `ConnectionUpgrade` for the relay protocol, on the side of the relay and of
+/// the destination.
+///
+/// Dialing through a relay is done with the `RelayTransport`.
 #[derive(Debug, Clone)]
 pub struct RelayConfig<T, P> {
     my_id: PeerId,
@@ -44,8 +48,11 @@ pub struct RelayConfig<T, P> {
 // we pipe data from source to destination and do not want to use the stream in any other way.
 // Therefore, in the latter case we simply return a future that can be driven to completion
 // but otherwise the stream is not programmatically accessible.
+/// Output of the `RelayConfig` upgrade.
 pub enum Output<C> {
+    /// We are the destination of a circuit. The stream is connected to the source.
     Stream(C),
+    /// We are relaying a circuit or answering a query. The future must be driven to completion.
     Sealed(Box<Future<Item=(), Error=io::Error> + Send>)
 }
 
@@ -82,13 +89,27 @@ where
                 CircuitRelay_Type::HOP if self.allow_relays => { // act as relay
                     B(A(self.on_hop(msg, io).map(|fut| Output::Sealed(Box::new(fut)))))
                 }
+                CircuitRelay_Type::HOP => {
+                    debug!("refusing to act as relay");
+                    let resp = status(CircuitRelay_Status::HOP_CANT_SPEAK_RELAY);
+                    A(B(A(io.send(resp).and_then(|_| Err(io_err("relaying not allowed"))))))
+                }
+                CircuitRelay_Type::CAN_HOP => { // report whether we can act as relay
+                    let resp = if self.allow_relays {
+                        status(CircuitRelay_Status::SUCCESS)
+                    } else {
+                        status(CircuitRelay_Status::HOP_CANT_SPEAK_RELAY)
+                    };
+                    let future = io.send(resp).map(|_| Output::Sealed(Box::new(future::ok(()))));
+                    B(B(B(future)))
+                }
                 CircuitRelay_Type::STOP => { // act as destination
-                    B(B(self.on_stop(msg, io).map(Output::Stream)))
+                    B(B(A(self.on_stop(msg, io).map(Output::Stream))))
                 }
                 other => {
                     debug!("invalid message type: {:?}", other);
                     let resp = status(CircuitRelay_Status::MALFORMED_MESSAGE);
-                    A(B(io.send(resp).and_then(|_| Err(io_err("invalid message type")))))
+                    A(B(B(io.send(resp).and_then(|_| Err(io_err("invalid message type"))))))
                 }
             }
         });
@@ -106,10 +127,16 @@ where
     P: Deref<Target = S> + Clone + 'static,
     for<'a> &'a S: Peerstore,
 {
+    /// Creates a new relay upgrade.
+    ///
+    /// `transport` is used to dial the destinations of the circuits we relay, and `peers` is used
+    /// to look up their addresses if the source didn't provide any.
     pub fn new(my_id: PeerId, transport: T, peers: P) -> RelayConfig<T, P> {
         RelayConfig { my_id, transport, peers, allow_relays: true }
     }
 
+    /// Sets whether we accept to relay circuits to other nodes. If false, we can only be the
+    /// destination of circuits. The default is true.
     pub fn allow_relays(&mut self, val: bool) {
         self.allow_relays = val
     }
@@ -133,10 +160,15 @@ where
             return B(A(io.send(msg).and_then(|_| Err(io_err("invalid dest address")))))
         };
 
+        if dest.id == self.my_id {
+            let msg = status(CircuitRelay_Status::HOP_CANT_RELAY_TO_SELF);
+            return B(B(A(io.send(msg).and_then(|_| Err(io_err("cannot relay to self"))))))
+        }
+
         if dest.addrs.is_empty() {
             // Add locally know addresses of destination
             if let Some(peer) = self.peers.peer(&dest.id) {
-                dest.addrs.extend(peer.addrs())
+                dest.addrs.extend(peer.addrs_by_preference())
             }
         }
 
@@ -199,7 +231,7 @@ where
                 Ok(future)
             });
 
-        B(B(future))
+        B(B(B(future)))
     }
 
     // STOP message handling (destination mode)
@@ -266,6 +298,35 @@ where
     }
 }
 
+// Upgrade that asks a node whether it can act as a relay. Produces true if it can.
+#[derive(Debug, Clone)]
+pub(crate) struct CanHop;
+
+impl<C> ConnectionUpgrade<C> for CanHop
+where
+    C: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+    type UpgradeIdentifier = ();
+
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from("/libp2p/relay/circuit/0.1.0"), ()))
+    }
+
+    type Output = bool;
+    type Future = Box<Future<Item=Self::Output, Error=io::Error> + Send>;
+
+    fn upgrade(self, conn: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+        let mut msg = CircuitRelay::new();
+        msg.set_field_type(CircuitRelay_Type::CAN_HOP);
+        let future = Io::new(conn)
+            .send(msg)
+            .and_then(Io::recv)
+            .map(|(response, _io)| response.map(|rsp| is_success(&rsp)).unwrap_or(false));
+        Box::new(future)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Source(pub(crate) CircuitRelay);
 
@@ -302,3 +363,104 @@ where
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{PublicKey, transport::memory};
+    use peerstore::memory_peerstore::MemoryPeerstore;
+    use std::sync::Arc;
+
+    fn peer_id(n: u8) -> PeerId {
+        PublicKey::Ed25519(vec![n; 32]).into_peer_id()
+    }
+
+    fn relay(allow_relays: bool) -> RelayConfig<memory::Dialer, Arc<MemoryPeerstore>> {
+        let (dialer, _) = memory::connector();
+        let mut config = RelayConfig::new(peer_id(1), dialer, Arc::new(MemoryPeerstore::empty()));
+        config.allow_relays(allow_relays);
+        config
+    }
+
+    /// Opens an in-memory connection, and returns the dialing and the listening ends of it.
+    fn connection() -> (memory::Channel<Bytes>, memory::Channel<Bytes>) {
+        let (dialer, listener) = memory::connector();
+        let addr: Multiaddr = "/memory".parse().unwrap();
+        let (incoming, _) = listener.listen_on(addr.clone()).unwrap_or_else(|_| panic!());
+        let outgoing = dialer.dial(addr).unwrap_or_else(|_| panic!());
+        outgoing
+            .join(incoming.into_future().map_err(|(err, _)| err).and_then(|(incoming, _)| incoming.unwrap().0))
+            .wait()
+            .unwrap()
+    }
+
+    fn hop_message(src: &PeerId, dest: &PeerId) -> CircuitRelay {
+        let mut msg = CircuitRelay::new();
+        msg.set_field_type(CircuitRelay_Type::HOP);
+        let mut from = CircuitRelay_Peer::new();
+        from.set_id(src.as_bytes().to_vec());
+        msg.set_srcPeer(from);
+        let mut to = CircuitRelay_Peer::new();
+        to.set_id(dest.as_bytes().to_vec());
+        msg.set_dstPeer(to);
+        msg
+    }
+
+    /// Sends `msg` to the `relay` through its upgrade, and returns the response of the relay and
+    /// the result of the upgrade.
+    fn request(relay: RelayConfig<memory::Dialer, Arc<MemoryPeerstore>>, msg: CircuitRelay)
+        -> (CircuitRelay, Result<Output<memory::Channel<Bytes>>, io::Error>)
+    {
+        let (client, server) = connection();
+        let addr = "/memory".parse().unwrap();
+        let server = relay.upgrade(server, (), Endpoint::Listener, &addr).then(Ok::<_, io::Error>);
+        let client = Io::new(client).send(msg).and_then(Io::recv);
+        let ((response, _io), result) = client.join(server).wait().unwrap();
+        (response.expect("no response from the relay"), result)
+    }
+
+    #[test]
+    fn can_hop_query() {
+        for &allow_relays in &[true, false] {
+            let (client, server) = connection();
+            let addr = "/memory".parse().unwrap();
+            let server = relay(allow_relays).upgrade(server, (), Endpoint::Listener, &addr)
+                .and_then(|output| match output {
+                    Output::Sealed(future) => future,
+                    Output::Stream(_) => panic!("a query doesn't produce a stream"),
+                });
+            let client = CanHop.upgrade(client, (), Endpoint::Dialer, &addr);
+            let (can_hop, ()) = client.join(server).wait().unwrap();
+            assert_eq!(can_hop, allow_relays);
+        }
+    }
+
+    #[test]
+    fn hop_refused_when_relays_not_allowed() {
+        let (response, result) = request(relay(false), hop_message(&peer_id(2), &peer_id(3)));
+        assert_eq!(response.get_field_type(), CircuitRelay_Type::STATUS);
+        assert_eq!(response.get_code(), CircuitRelay_Status::HOP_CANT_SPEAK_RELAY);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn hop_to_self_refused() {
+        let (response, result) = request(relay(true), hop_message(&peer_id(2), &peer_id(1)));
+        assert_eq!(response.get_field_type(), CircuitRelay_Type::STATUS);
+        assert_eq!(response.get_code(), CircuitRelay_Status::HOP_CANT_RELAY_TO_SELF);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn source_fails_on_refusal() {
+        let (client, server) = connection();
+        let addr = "/memory".parse().unwrap();
+        let server = relay(false).upgrade(server, (), Endpoint::Listener, &addr).then(Ok::<_, io::Error>);
+        let client = Source(hop_message(&peer_id(2), &peer_id(3)))
+            .upgrade(client, (), Endpoint::Dialer, &addr)
+            .then(Ok::<_, io::Error>);
+        let (client, server) = client.join(server).wait().unwrap();
+        assert!(client.is_err());
+        assert!(server.is_err());
+    }
+}
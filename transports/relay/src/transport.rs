@@ -19,22 +19,28 @@
 // DEALINGS IN THE SOFTWARE.
 
 use core::Transport;
+use core::runtime::RandomSource;
 use futures::{stream, prelude::*};
 use message::{CircuitRelay, CircuitRelay_Peer, CircuitRelay_Type};
 use multiaddr::Multiaddr;
 use peerstore::{PeerAccess, PeerId, Peerstore};
 use protocol;
-use rand::{self, Rng};
 use std::{io, iter::FromIterator, ops::Deref, sync::Arc};
 use tokio_io::{AsyncRead, AsyncWrite};
 use utility::{io_err, Peer, RelayAddr};
 
+/// Transport that dials `/p2p-circuit` addresses through a relay.
+///
+/// Addresses of the form `<relay>/p2p-circuit/p2p/<destination>` are dialed through the given
+/// relay, while addresses of the form `/p2p-circuit/p2p/<destination>` are dialed through any of
+/// the relays passed to `new`.
 #[derive(Debug, Clone)]
 pub struct RelayTransport<T, P> {
     my_id: PeerId,
     transport: T,
     peers: P,
-    relays: Arc<Vec<PeerId>>
+    relays: Arc<Vec<PeerId>>,
+    random_source: RandomSource,
 }
 
 impl<T, P, S> Transport for RelayTransport<T, P>
@@ -78,10 +84,6 @@ where
             }
         }
     }
-
-    fn nat_traversal(&self, a: &Multiaddr, b: &Multiaddr) -> Option<Multiaddr> {
-        self.transport.nat_traversal(a, b)
-    }
 }
 
 impl<T, P, S> RelayTransport<T, P>
@@ -107,7 +109,37 @@ where
             transport,
             peers,
             relays: Arc::new(Vec::from_iter(relays)),
+            random_source: RandomSource::from_entropy(),
+        }
+    }
+
+    /// Uses the given source of random numbers to decide in which order the relays are tried,
+    /// for example the one of the swarm's `Runtime`.
+    #[inline]
+    pub fn with_random_source(mut self, random_source: RandomSource) -> Self {
+        self.random_source = random_source;
+        self
+    }
+
+    /// Asks the given relay whether it accepts to relay circuits to other nodes.
+    ///
+    /// The addresses of the relay are looked up in the peerstore, best ones first. Produces
+    /// false if none of them can be reached.
+    pub fn can_hop(&self, relay: &PeerId) -> impl Future<Item=bool, Error=io::Error> {
+        let mut addresses = Vec::new();
+        if let Some(peer) = self.peers.peer(relay) {
+            addresses.extend(peer.addrs_by_preference())
         }
+
+        let transport = self.transport.clone().with_upgrade(protocol::CanHop);
+        stream::iter_ok(addresses.into_iter())
+            .filter_map(move |addr| transport.clone().dial(addr).ok())
+            .and_then(|dial| dial)
+            .then(|result| Ok(result.ok()))
+            .filter_map(|result| result)
+            .into_future()
+            .map_err(|(err, _stream)| err)
+            .map(|(ok, _stream)| ok.unwrap_or(false))
     }
 
     // Relay to destination over any available relay node.
@@ -130,7 +162,7 @@ where
         }
 
         // Try one relay after another and stick to the first working one.
-        rand::thread_rng().shuffle(&mut dials); // randomise to spread load
+        self.random_source.shuffle(&mut dials); // randomise to spread load
         let dest_peer = destination.id.clone();
         let future = stream::iter_ok(dials.into_iter())
             .and_then(|dial| dial)
@@ -156,7 +188,7 @@ where
         if relay.addrs.is_empty() {
             // try all known relay addresses
             if let Some(peer) = self.peers.peer(&relay.id) {
-                addresses.extend(peer.addrs())
+                addresses.extend(peer.addrs_by_preference())
             }
         } else {
             // use only specific relay addresses
@@ -180,3 +180,48 @@ where
 {
     io::Error::new(io::ErrorKind::Other, e)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RelayAddr;
+
+    const RELAY: &str = "QmXnxVaQoP8cPm2J5uN73GPEu3pCkJdYDNDCMZS8dMxTXL";
+    const DEST: &str = "QmcwnUP8cM2U4EeMW6g6nbFUQRyE6xXh65TPaZD9bqkhdK";
+
+    #[test]
+    fn parse_with_relay() {
+        let addr = format!("/ip4/127.0.0.1/tcp/10002/p2p/{}/p2p-circuit/p2p/{}", RELAY, DEST);
+        match RelayAddr::parse(&addr.parse().unwrap()) {
+            RelayAddr::Address { relay: Some(relay), dest } => {
+                assert_eq!(relay.id.to_base58(), RELAY);
+                assert_eq!(relay.addrs, vec!["/ip4/127.0.0.1/tcp/10002".parse().unwrap()]);
+                assert_eq!(dest.id.to_base58(), DEST);
+                assert!(dest.addrs.is_empty());
+            }
+            _ => panic!("expected an address with a relay"),
+        }
+    }
+
+    #[test]
+    fn parse_without_relay() {
+        let addr = format!("/p2p-circuit/p2p/{}", DEST);
+        match RelayAddr::parse(&addr.parse().unwrap()) {
+            RelayAddr::Address { relay: None, dest } => assert_eq!(dest.id.to_base58(), DEST),
+            _ => panic!("expected an address without a relay"),
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let multihop = format!("/p2p-circuit/p2p/{}/p2p-circuit/p2p/{}", RELAY, DEST);
+        match RelayAddr::parse(&multihop.parse().unwrap()) {
+            RelayAddr::Multihop => (),
+            _ => panic!("expected a multihop address"),
+        }
+
+        match RelayAddr::parse(&"/ip4/127.0.0.1/tcp/10002".parse().unwrap()) {
+            RelayAddr::Malformed => (),
+            _ => panic!("expected a malformed address"),
+        }
+    }
+}
@@ -37,14 +37,49 @@
 //!
 //! The `TcpConfig` structs implements the `Transport` trait of the `swarm` library. See the
 //! documentation of `swarm` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! # Listening on all the interfaces
+//!
+//! Listening on `/ip4/0.0.0.0/tcp/<port>` or `/ip6/::/tcp/<port>` accepts connections on all the
+//! network interfaces. The address returned by `listen_on` contains the unspecified IP address,
+//! which remotes can't dial; the swarm replaces it with the address of each network interface
+//! when reporting the addresses it listens on.
+//!
+//! The IPv4 connections accepted by an IPv6 listener are reported with their IPv4 address
+//! instead of an IPv4-mapped IPv6 address.
+//!
+//! # Socket options
+//!
+//! The `TcpConfig` has builder methods to set the options of the sockets it creates, such as
+//! `nodelay`, `keepalive`, `ttl` or `listen_backlog`.
+//!
+//! With `port_reuse`, the outgoing connections are opened from the port of one of our listeners
+//! instead of an ephemeral port. The remote then sees the same address whether we dial it or it
+//! dials us, which is required for TCP simultaneous open, and therefore for hole punching.
+//!
+//! ```
+//! extern crate libp2p_tcp_transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! let tcp = TcpConfig::new()
+//!     .nodelay(true)
+//!     .keepalive(Some(Duration::from_secs(30)))
+//!     .listen_backlog(128)
+//!     .port_reuse(true);
+//! # }
+//! ```
 
 extern crate futures;
 extern crate libp2p_core as swarm;
 #[macro_use]
 extern crate log;
 extern crate multiaddr;
+extern crate net2;
 extern crate tk_listen;
 extern crate tokio_io;
+extern crate tokio_reactor;
 extern crate tokio_tcp;
 
 #[cfg(test)]
@@ -52,16 +87,23 @@ extern crate tokio_current_thread;
 
 use futures::{future, future::FutureResult, prelude::*, Async, Poll};
 use multiaddr::{Protocol, Multiaddr, ToMultiaddr};
+use net2::TcpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixTcpBuilderExt;
 use std::fmt;
 use std::io::{Error as IoError, Read, Write};
-use std::iter;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream as StdTcpStream};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use swarm::Transport;
 use tk_listen::{ListenExt, SleepOnError};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_reactor::Handle;
 use tokio_tcp::{ConnectFuture, Incoming, TcpListener, TcpStream};
 
+/// Backlog of the listening sockets if none has been configured.
+const DEFAULT_LISTEN_BACKLOG: i32 = 1024;
+
 /// Represents the configuration for a TCP/IP transport capability for libp2p.
 ///
 /// The TCP sockets created by libp2p will need to be progressed by running the futures and streams
@@ -80,6 +122,17 @@ pub struct TcpConfig {
     keepalive: Option<Option<Duration>>,
     /// `TCP_NODELAY` to set for opened sockets, or `None` to keep default.
     nodelay: Option<bool>,
+    /// Size of the backlog of the listening sockets, or `None` for `DEFAULT_LISTEN_BACKLOG`.
+    listen_backlog: Option<i32>,
+    /// `SO_REUSEADDR` to set for listening sockets, or `None` to set it on Unix platforms only.
+    reuse_address: Option<bool>,
+    /// If true, `SO_REUSEPORT` is set for listening sockets.
+    reuse_port: bool,
+    /// If true, outgoing connections are opened from the port of one of the listeners.
+    port_reuse: bool,
+    /// Local addresses of the listeners that have been created with `port_reuse` enabled.
+    /// Shared between all the clones of this configuration.
+    listen_addrs: Arc<Mutex<Vec<SocketAddr>>>,
 }
 
 impl TcpConfig {
@@ -93,6 +146,11 @@ impl TcpConfig {
             ttl: None,
             keepalive: None,
             nodelay: None,
+            listen_backlog: None,
+            reuse_address: None,
+            reuse_port: false,
+            port_reuse: false,
+            listen_addrs: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -130,6 +188,119 @@ impl TcpConfig {
         self.nodelay = Some(value);
         self
     }
+
+    /// Sets the maximum number of pending incoming connections of the listening sockets.
+    /// Defaults to 1024.
+    #[inline]
+    pub fn listen_backlog(mut self, value: u32) -> Self {
+        self.listen_backlog = Some(if value > i32::max_value() as u32 {
+            i32::max_value()
+        } else {
+            value as i32
+        });
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` on the listening sockets, which allows listening again on an
+    /// address whose previous connections are still in the `TIME_WAIT` state. Enabled by
+    /// default on Unix platforms, and disabled on the others.
+    #[inline]
+    pub fn reuse_address(mut self, value: bool) -> Self {
+        self.reuse_address = Some(value);
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` on the listening sockets, which allows several sockets to listen on
+    /// the same port. Disabled by default.
+    ///
+    /// > **Note**: Has no effect on platforms other than Unix.
+    #[inline]
+    pub fn reuse_port(mut self, value: bool) -> Self {
+        self.reuse_port = value;
+        self
+    }
+
+    /// If true, outgoing connections are opened from the port of one of the listeners of this
+    /// configuration, instead of an ephemeral port. Implies `reuse_address` and `reuse_port`.
+    /// Disabled by default.
+    ///
+    /// This is required for TCP simultaneous open. If there is no listener whose address is
+    /// suitable for dialing a given address, an ephemeral port is used.
+    ///
+    /// > **Note**: Only the listeners created after port reuse has been enabled are taken into
+    /// >           account.
+    #[inline]
+    pub fn port_reuse(mut self, value: bool) -> Self {
+        self.port_reuse = value;
+        self
+    }
+
+    /// Creates a listening socket bound to the given address.
+    fn create_listener(&self, socket_addr: &SocketAddr) -> Result<TcpListener, IoError> {
+        let builder = new_builder(socket_addr)?;
+        let reuse_address = self.reuse_address.unwrap_or(cfg!(unix)) || self.port_reuse;
+        builder.reuse_address(reuse_address)?;
+        #[cfg(unix)]
+        {
+            if self.reuse_port || self.port_reuse {
+                builder.reuse_port(true)?;
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            builder.ttl(ttl)?;
+        }
+        builder.bind(socket_addr)?;
+        let listener = builder.listen(self.listen_backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG))?;
+        TcpListener::from_std(listener, &Handle::default())
+    }
+
+    /// Starts connecting to the given address. If port reuse is enabled, the socket is bound to
+    /// the address of one of our listeners first.
+    fn connect(&self, socket_addr: &SocketAddr) -> Result<ConnectFuture, IoError> {
+        let local_addr = if self.port_reuse {
+            self.port_reuse_addr(socket_addr)
+        } else {
+            None
+        };
+
+        let local_addr = match local_addr {
+            Some(addr) => addr,
+            None => return Ok(TcpStream::connect(socket_addr)),
+        };
+
+        debug!("Dialing {} from {}", socket_addr, local_addr);
+        let builder = new_builder(socket_addr)?;
+        builder.reuse_address(true)?;
+        #[cfg(unix)]
+        {
+            builder.reuse_port(true)?;
+        }
+        builder.bind(&local_addr)?;
+        let socket: StdTcpStream = builder.to_tcp_stream()?;
+        Ok(TcpStream::connect_std(socket, socket_addr, &Handle::default()))
+    }
+
+    /// Returns the address of a listener from which we can dial the given address, if any.
+    fn port_reuse_addr(&self, remote_addr: &SocketAddr) -> Option<SocketAddr> {
+        let listen_addrs = self.listen_addrs.lock().unwrap_or_else(|err| err.into_inner());
+        listen_addrs
+            .iter()
+            .find(|addr| {
+                addr.is_ipv4() == remote_addr.is_ipv4() &&
+                    (addr.ip().is_unspecified() ||
+                        addr.ip().is_loopback() == remote_addr.ip().is_loopback())
+            })
+            .cloned()
+    }
+}
+
+/// Creates a socket builder of the same IP version as `addr`.
+fn new_builder(addr: &SocketAddr) -> Result<TcpBuilder, IoError> {
+    if addr.is_ipv4() {
+        TcpBuilder::new_v4()
+    } else {
+        TcpBuilder::new_v6()
+    }
 }
 
 impl Transport for TcpConfig {
@@ -140,7 +311,7 @@ impl Transport for TcpConfig {
 
     fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
         if let Ok(socket_addr) = multiaddr_to_socketaddr(&addr) {
-            let listener = TcpListener::bind(&socket_addr);
+            let listener = self.create_listener(&socket_addr);
             // We need to build the `Multiaddr` to return from this function. If an error happened,
             // just return the original multiaddr.
             let new_addr = match listener {
@@ -156,6 +327,17 @@ impl Transport for TcpConfig {
             };
 
             debug!("Now listening on {}", new_addr);
+            let registered = match listener {
+                Ok(ref l) if self.port_reuse => l.local_addr().ok().map(|addr| {
+                    self.listen_addrs
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .push(addr);
+                    addr
+                }),
+                _ => None,
+            };
+
             let sleep_on_error = self.sleep_on_error;
             let inner = listener
                 .map_err(Some)
@@ -163,6 +345,7 @@ impl Transport for TcpConfig {
             Ok((
                 TcpListenStream {
                     inner,
+                    registered,
                     config: self,
                 },
                 new_addr,
@@ -179,7 +362,7 @@ impl Transport for TcpConfig {
             if socket_addr.port() != 0 && !socket_addr.ip().is_unspecified() {
                 debug!("Dialing {}", addr);
                 Ok(TcpDialFut {
-                    inner: TcpStream::connect(&socket_addr),
+                    inner: self.connect(&socket_addr).map_err(Some),
                     config: self,
                 })
             } else {
@@ -190,43 +373,6 @@ impl Transport for TcpConfig {
             Err((self, addr))
         }
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        // Check that `server` only has two components and retreive them.
-        let mut server_protocols_iter = server.iter();
-        let server_proto1 = server_protocols_iter.next()?;
-        let server_proto2 = server_protocols_iter.next()?;
-        if server_protocols_iter.next().is_some() {
-            return None;
-        }
-
-        // Check that `observed` only has two components and retreive them.
-        let mut observed_protocols_iter = observed.iter();
-        let observed_proto1 = observed_protocols_iter.next()?;
-        let observed_proto2 = observed_protocols_iter.next()?;
-        if observed_protocols_iter.next().is_some() {
-            return None;
-        }
-
-        // Check that `server` is a valid TCP/IP address.
-        match (&server_proto1, &server_proto2) {
-            (&Protocol::Ip4(_), &Protocol::Tcp(_))
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_)) => {}
-            _ => return None,
-        }
-
-        // Check that `observed` is a valid TCP/IP address.
-        match (&observed_proto1, &observed_proto2) {
-            (&Protocol::Ip4(_), &Protocol::Tcp(_))
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_)) => {}
-            _ => return None,
-        }
-
-        let result = iter::once(observed_proto1.clone())
-            .chain(iter::once(server_proto2.clone()))
-            .collect();
-        Some(result)
-    }
 }
 
 // This type of logic should probably be moved into the multiaddr package
@@ -246,6 +392,28 @@ fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     }
 }
 
+/// Turns an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`), which is how an IPv6 socket reports
+/// IPv4 peers, into the corresponding IPv4 address.
+fn unmap_ipv4(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(ref v6) => {
+            let segments = v6.ip().segments();
+            if segments[..5].iter().all(|s| *s == 0) && segments[5] == 0xffff {
+                let ip = Ipv4Addr::new(
+                    (segments[6] >> 8) as u8,
+                    segments[6] as u8,
+                    (segments[7] >> 8) as u8,
+                    segments[7] as u8,
+                );
+                return SocketAddr::new(IpAddr::V4(ip), v6.port());
+            }
+        }
+        SocketAddr::V4(_) => {}
+    }
+
+    addr
+}
+
 /// Applies the socket configuration parameters to a socket.
 fn apply_config(config: &TcpConfig, socket: &TcpStream) -> Result<(), IoError> {
     if let Some(recv_buffer_size) = config.recv_buffer_size {
@@ -275,7 +443,7 @@ fn apply_config(config: &TcpConfig, socket: &TcpStream) -> Result<(), IoError> {
 #[derive(Debug)]
 #[must_use = "futures do nothing unless polled"]
 pub struct TcpDialFut {
-    inner: ConnectFuture,
+    inner: Result<ConnectFuture, Option<IoError>>,
     /// Original configuration.
     config: TcpConfig,
 }
@@ -285,7 +453,16 @@ impl Future for TcpDialFut {
     type Error = IoError;
 
     fn poll(&mut self) -> Poll<TcpTransStream, IoError> {
-        match self.inner.poll() {
+        let inner = match self.inner {
+            Ok(ref mut inner) => inner,
+            Err(ref mut err) => {
+                let err = err.take().expect("poll called again after error");
+                debug!("Error while dialing => {:?}", err);
+                return Err(err);
+            }
+        };
+
+        match inner.poll() {
             Ok(Async::Ready(stream)) => {
                 apply_config(&self.config, &stream)?;
                 Ok(Async::Ready(TcpTransStream { inner: stream }))
@@ -302,6 +479,8 @@ impl Future for TcpDialFut {
 /// Stream that listens on an TCP/IP address.
 pub struct TcpListenStream {
     inner: Result<SleepOnError<Incoming>, Option<IoError>>,
+    /// Local address that has been registered for port reuse, if any.
+    registered: Option<SocketAddr>,
     /// Original configuration.
     config: TcpConfig,
 }
@@ -326,7 +505,7 @@ impl Stream for TcpListenStream {
         loop {
             match inner.poll() {
                 Ok(Async::Ready(Some(sock))) => {
-                    let addr = match sock.peer_addr() {
+                    let addr = match sock.peer_addr().map(unmap_ipv4) {
                         // TODO: remove this expect()
                         Ok(addr) => addr
                             .to_multiaddr()
@@ -357,6 +536,19 @@ impl Stream for TcpListenStream {
     }
 }
 
+impl Drop for TcpListenStream {
+    fn drop(&mut self) {
+        if let Some(addr) = self.registered.take() {
+            let mut listen_addrs = self.config.listen_addrs
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            if let Some(pos) = listen_addrs.iter().position(|a| *a == addr) {
+                listen_addrs.remove(pos);
+            }
+        }
+    }
+}
+
 impl fmt::Debug for TcpListenStream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.inner {
@@ -414,7 +606,7 @@ impl Drop for TcpTransStream {
 
 #[cfg(test)]
 mod tests {
-    use super::{multiaddr_to_socketaddr, TcpConfig};
+    use super::{multiaddr_to_socketaddr, unmap_ipv4, TcpConfig};
     use futures::stream::Stream;
     use futures::Future;
     use multiaddr::Multiaddr;
@@ -473,6 +665,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ipv4_mapped_addresses_unmapped() {
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:4000".parse().unwrap();
+        assert_eq!(unmap_ipv4(mapped), "10.0.0.1:4000".parse().unwrap());
+
+        let loopback: SocketAddr = "[::1]:4000".parse().unwrap();
+        assert_eq!(unmap_ipv4(loopback), loopback);
+        let v4: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        assert_eq!(unmap_ipv4(v4), v4);
+    }
+
     #[test]
     fn communicating_between_dialer_and_listener() {
         use std::io::Write;
@@ -535,26 +738,48 @@ mod tests {
     }
 
     #[test]
-    fn larger_addr_denied() {
-        let tcp = TcpConfig::new();
+    fn listen_with_socket_options() {
+        let tcp = TcpConfig::new()
+            .listen_backlog(16)
+            .reuse_address(true)
+            .reuse_port(true)
+            .ttl(32)
+            .nodelay(true);
 
-        let addr = "/ip4/127.0.0.1/tcp/12345/tcp/12345"
-            .parse::<Multiaddr>()
+        let addr = "/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>().unwrap();
+        let (_, new_addr) = tcp.listen_on(addr).unwrap();
+        assert!(!new_addr.to_string().contains("tcp/0"));
+    }
+
+    #[test]
+    fn port_reuse_dials_from_listen_port() {
+        let dialer = TcpConfig::new().port_reuse(true);
+        let (_dialer_listener, dialer_addr) = dialer
+            .clone()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
             .unwrap();
-        assert!(tcp.listen_on(addr).is_err());
+
+        let (listener, listener_addr) = TcpConfig::new()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+
+        let incoming = listener
+            .into_future()
+            .map(|(incoming, _)| incoming.expect("listener closed").1)
+            .map_err(|(err, _)| err);
+        let dial = dialer.dial(listener_addr).unwrap();
+
+        let (remote_addr, _socket) = tokio_current_thread::block_on_all(incoming.join(dial)).unwrap();
+        assert_eq!(remote_addr, dialer_addr);
     }
 
     #[test]
-    fn nat_traversal() {
+    fn larger_addr_denied() {
         let tcp = TcpConfig::new();
 
-        let server = "/ip4/127.0.0.1/tcp/10000".parse::<Multiaddr>().unwrap();
-        let observed = "/ip4/80.81.82.83/tcp/25000".parse::<Multiaddr>().unwrap();
-
-        let out = tcp.nat_traversal(&server, &observed);
-        assert_eq!(
-            out.unwrap(),
-            "/ip4/80.81.82.83/tcp/10000".parse::<Multiaddr>().unwrap()
-        );
+        let addr = "/ip4/127.0.0.1/tcp/12345/tcp/12345"
+            .parse::<Multiaddr>()
+            .unwrap();
+        assert!(tcp.listen_on(addr).is_err());
     }
 }
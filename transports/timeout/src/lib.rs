@@ -127,11 +127,6 @@ where
             }
         }
     }
-
-    #[inline]
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        self.inner.nat_traversal(server, observed)
-    }
 }
 
 impl<InnerTrans> MuxedTransport for TransportTimeout<InnerTrans>
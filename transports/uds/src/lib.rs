@@ -127,14 +127,6 @@ impl Transport for UdsConfig {
             Err((self, addr))
         }
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        if server == observed {
-            Some(observed.clone())
-        } else {
-            None
-        }
-    }
 }
 
 // This type of logic should probably be moved into the multiaddr package
@@ -25,7 +25,6 @@ use multiaddr::{Protocol, Multiaddr};
 use rw_stream_sink::RwStreamSink;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::io::{Read, Write};
-use std::iter;
 use std::sync::{Arc, Mutex};
 use stdweb::web::TypedArray;
 use stdweb::{self, Reference};
@@ -171,7 +170,7 @@ impl Transport for BrowserWsConfig {
             )));
         };
 
-        js! {
+        js! { @(no_return)
             var socket = @{websocket};
             var open_cb = @{open_cb};
             var message_cb = @{message_cb};
@@ -205,52 +204,6 @@ impl Transport for BrowserWsConfig {
             }
         })) as Box<_>)
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        let mut server_protocols = server.iter();
-        let server_proto0 = server_protocols.next()?;
-        let server_proto1 = server_protocols.next()?;
-        let server_proto2 = server_protocols.next()?;
-        if server_protocols.next().is_some() {
-            return None;
-        }
-
-        let mut observed_protocols = observed.iter();
-        let obs_proto0 = observed_protocols.next()?;
-        let obs_proto1 = observed_protocols.next()?;
-        let obs_proto2 = observed_protocols.next()?;
-        if observed_protocols.next().is_some() {
-            return None;
-        }
-
-        // Check that `server` is a valid TCP/IP address.
-        match (&server_proto0, &server_proto1, &server_proto2) {
-            (&Protocol::Ip4(_), &Protocol::Tcp(_), &Protocol::Ws)
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_), &Protocol::Ws)
-            | (&Protocol::Ip4(_), &Protocol::Tcp(_), &Protocol::Wss)
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_), &Protocol::Wss) => {}
-            _ => return None,
-        }
-
-        // Check that `observed` is a valid TCP/IP address.
-        match (&obs_proto0, &obs_proto1, &obs_proto2) {
-            (&Protocol::Ip4(_), &Protocol::Tcp(_), &Protocol::Ws)
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_), &Protocol::Ws)
-            | (&Protocol::Ip4(_), &Protocol::Tcp(_), &Protocol::Wss)
-            | (&Protocol::Ip6(_), &Protocol::Tcp(_), &Protocol::Wss) => {}
-            _ => return None,
-        }
-
-        // Note that it will still work if the server uses WSS while the client uses WS,
-        // or vice-versa.
-
-        let result = iter::once(obs_proto0)
-            .chain(iter::once(server_proto1))
-            .chain(iter::once(server_proto2))
-            .collect();
-
-        Some(result)
-    }
 }
 
 pub struct BrowserWsConn {
@@ -269,7 +222,7 @@ impl Drop for BrowserWsConn {
     #[inline]
     fn drop(&mut self) {
         // TODO: apparently there's a memory leak related to callbacks?
-        js! { @{&self.websocket}.close(); }
+        js! { @(no_return) @{&self.websocket}.close(); }
     }
 }
 
@@ -218,28 +218,6 @@ where
 
         Ok(Box::new(dial) as Box<_>)
     }
-
-    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
-        let mut server = server.clone();
-        let last_proto = match server.pop() {
-            Some(v @ Protocol::Ws) | Some(v @ Protocol::Wss) => v,
-            _ => return None,
-        };
-
-        let mut observed = observed.clone();
-        match observed.pop() {
-            Some(Protocol::Ws) => false,
-            Some(Protocol::Wss) => true,
-            _ => return None,
-        };
-
-        self.transport
-            .nat_traversal(&server, &observed)
-            .map(move |mut result| {
-                result.append(last_proto);
-                result
-            })
-    }
 }
 
 fn client_addr_to_ws(client_addr: &Multiaddr, is_wss: bool) -> String {
@@ -328,61 +306,4 @@ mod tests {
             .and_then(|(_, n)| n);
         tokio_current_thread::block_on_all(future).unwrap();
     }
-
-    #[test]
-    fn nat_traversal() {
-        let ws_config = WsConfig::new(tcp::TcpConfig::new());
-
-        {
-            let server = "/ip4/127.0.0.1/tcp/10000/ws".parse::<Multiaddr>().unwrap();
-            let observed = "/ip4/80.81.82.83/tcp/25000/ws"
-                .parse::<Multiaddr>()
-                .unwrap();
-            assert_eq!(
-                ws_config.nat_traversal(&server, &observed).unwrap(),
-                "/ip4/80.81.82.83/tcp/10000/ws"
-                    .parse::<Multiaddr>()
-                    .unwrap()
-            );
-        }
-
-        {
-            let server = "/ip4/127.0.0.1/tcp/10000/wss".parse::<Multiaddr>().unwrap();
-            let observed = "/ip4/80.81.82.83/tcp/25000/wss"
-                .parse::<Multiaddr>()
-                .unwrap();
-            assert_eq!(
-                ws_config.nat_traversal(&server, &observed).unwrap(),
-                "/ip4/80.81.82.83/tcp/10000/wss"
-                    .parse::<Multiaddr>()
-                    .unwrap()
-            );
-        }
-
-        {
-            let server = "/ip4/127.0.0.1/tcp/10000/ws".parse::<Multiaddr>().unwrap();
-            let observed = "/ip4/80.81.82.83/tcp/25000/wss"
-                .parse::<Multiaddr>()
-                .unwrap();
-            assert_eq!(
-                ws_config.nat_traversal(&server, &observed).unwrap(),
-                "/ip4/80.81.82.83/tcp/10000/ws"
-                    .parse::<Multiaddr>()
-                    .unwrap()
-            );
-        }
-
-        {
-            let server = "/ip4/127.0.0.1/tcp/10000/wss".parse::<Multiaddr>().unwrap();
-            let observed = "/ip4/80.81.82.83/tcp/25000/ws"
-                .parse::<Multiaddr>()
-                .unwrap();
-            assert_eq!(
-                ws_config.nat_traversal(&server, &observed).unwrap(),
-                "/ip4/80.81.82.83/tcp/10000/wss"
-                    .parse::<Multiaddr>()
-                    .unwrap()
-            );
-        }
-    }
 }
@@ -25,16 +25,17 @@
 //! See the documentation of `swarm` and of libp2p in general to learn how to use the `Transport`
 //! trait.
 //!
-//! This library is used in a different way depending on whether you are compiling for emscripten
-//! or for a different operating system.
+//! This library is used in a different way depending on whether you are compiling for the
+//! browser (emscripten or `wasm32-unknown-unknown`) or for a different operating system.
 //!
-//! # Emscripten
+//! # Browser
 //!
-//! On emscripten, you can create a `BrowserWsConfig` object with `BrowserWsConfig::new()`. It can
-//! then be used as a transport.
+//! On emscripten and `wasm32-unknown-unknown`, you can create a `BrowserWsConfig` object with
+//! `BrowserWsConfig::new()`. It can then be used as a transport. The JavaScript glue is provided
+//! by `stdweb`, which means that `wasm32-unknown-unknown` builds must go through `cargo-web`.
 //!
-//! Listening on a websockets multiaddress isn't supported on emscripten. Dialing a multiaddress
-//! which uses `ws` on top of TCP/IP will automatically use the `XMLHttpRequest` Javascript object.
+//! Listening on a websockets multiaddress isn't supported in the browser. Dialing a multiaddress
+//! which uses `ws` on top of TCP/IP will automatically use the `WebSocket` Javascript object.
 //!
 //! ```ignore
 //! use libp2p_websocket::BrowserWsConfig;
@@ -76,18 +77,18 @@ extern crate multiaddr;
 extern crate rw_stream_sink;
 extern crate tokio_io;
 
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 #[macro_use]
 extern crate stdweb;
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 extern crate websocket;
 
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 mod browser;
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 mod desktop;
 
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 pub use self::browser::{BrowserWsConfig, BrowserWsConn};
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub use self::desktop::WsConfig;